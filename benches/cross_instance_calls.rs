@@ -0,0 +1,89 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use wasmer::*;
+
+static EXPORTING_WAT: &str = r#"(module
+    (func (export "add_one") (param i32) (result i32)
+       (i32.add (local.get 0) (i32.const 1)))
+)"#;
+
+static IMPORTING_WAT: &str = r#"(module
+    (func $add_one (import "a" "add_one") (param i32) (result i32))
+    (func (export "count") (param $n i32) (result i32)
+       (local $count i32)
+       (block $done
+          (loop $next
+             (br_if $done (i32.eq (local.get $count) (local.get $n)))
+             (local.set $count (call $add_one (local.get $count)))
+             (br $next)))
+       (local.get $count))
+)"#;
+
+/// Forwards the calls of the importing instance to the exporting one through the host.
+#[derive(Clone)]
+struct Forward {
+    add_one: Function,
+}
+
+impl WasmerEnv for Forward {}
+
+pub fn run_cross_instance_calls(store: &Store, compiler_name: &str, c: &mut Criterion) {
+    let exporting = Module::new(&store, EXPORTING_WAT).unwrap();
+    let exporting = Instance::new(&exporting, &imports! {}).unwrap();
+    let importing = Module::new(&store, IMPORTING_WAT).unwrap();
+
+    let direct = Instance::new(
+        &importing,
+        &ImportObject::from_instance_namespace("a", &exporting),
+    )
+    .unwrap();
+    let count: NativeFunc<i32, i32> = direct.get_native_function("count").unwrap();
+    c.bench_function(
+        &format!("direct cross-instance calls {}", compiler_name),
+        |b| {
+            b.iter(|| {
+                let result = black_box(count.call(black_box(1000)).unwrap());
+                assert_eq!(result, 1000);
+            })
+        },
+    );
+
+    let forward = Forward {
+        add_one: exporting.lookup_function("add_one").unwrap(),
+    };
+    let import_object = imports! {
+        "a" => {
+            "add_one" => Function::new_native_with_env(
+                &store,
+                forward,
+                |env: &Forward, x: i32| -> Result<i32, RuntimeError> {
+                    Ok(env.add_one.call(&[Val::I32(x)])?[0].unwrap_i32())
+                },
+            ),
+        },
+    };
+    let through_host = Instance::new(&importing, &import_object).unwrap();
+    let count: NativeFunc<i32, i32> = through_host.get_native_function("count").unwrap();
+    c.bench_function(
+        &format!("cross-instance calls through the host {}", compiler_name),
+        |b| {
+            b.iter(|| {
+                let result = black_box(count.call(black_box(1000)).unwrap());
+                assert_eq!(result, 1000);
+            })
+        },
+    );
+}
+
+fn run_cross_instance_benchmarks(_c: &mut Criterion) {
+    #[cfg(feature = "singlepass")]
+    {
+        let store =
+            Store::new(&Universal::new(wasmer_compiler_singlepass::Singlepass::new()).engine());
+        run_cross_instance_calls(&store, "singlepass", _c);
+    }
+}
+
+criterion_group!(benches, run_cross_instance_benchmarks);
+
+criterion_main!(benches);
@@ -0,0 +1,49 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use wasmer::*;
+
+static FLOAT_LOOP_WAT: &str = r#"(module
+    (func (export "sum_series") (param $n i32) (result f64)
+        (local $i i32) (local $acc f64) (local $x f64) (local $step f64)
+        (local.set $step (f64.const 0.5))
+        (block $done
+            (loop $next
+                (br_if $done (i32.ge_u (local.get $i) (local.get $n)))
+                (local.set $x (f64.add (local.get $x) (local.get $step)))
+                (local.set $acc
+                    (f64.add (local.get $acc)
+                             (f64.div (f64.const 1) (f64.mul (local.get $x) (local.get $x)))))
+                (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                (br $next)))
+        (local.get $acc))
+)"#;
+
+pub fn run_float_loop(store: &Store, compiler_name: &str, c: &mut Criterion) {
+    let module = Module::new(&store, FLOAT_LOOP_WAT).unwrap();
+    let instance = Instance::new(&module, &imports! {}).unwrap();
+    let f: NativeFunc<i32, f64> = instance
+        .lookup_function("sum_series")
+        .unwrap()
+        .native()
+        .unwrap();
+
+    c.bench_function(&format!("f64 locals loop {}", compiler_name), |b| {
+        b.iter(|| {
+            let result = black_box(f.call(black_box(10_000)).unwrap());
+            assert!(result > 0.0);
+        })
+    });
+}
+
+fn run_float_benchmarks(_c: &mut Criterion) {
+    #[cfg(feature = "singlepass")]
+    {
+        let store =
+            Store::new(&Universal::new(wasmer_compiler_singlepass::Singlepass::new()).engine());
+        run_float_loop(&store, "singlepass", _c);
+    }
+}
+
+criterion_group!(benches, run_float_benchmarks);
+
+criterion_main!(benches);
@@ -0,0 +1,55 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use wasmer::*;
+
+/// A loop bumping a mutable global counter, defined by the module or imported from the host,
+/// on each iteration.
+static WAT: &str = r#"(module
+    (global $imported (import "env" "counter") (mut i64))
+    (global $defined (mut i64) (i64.const 0))
+    (func (export "count_defined") (param $n i32) (result i64)
+       (local $i i32)
+       (loop $next
+          (global.set $defined (i64.add (global.get $defined) (i64.const 1)))
+          (local.set $i (i32.add (local.get $i) (i32.const 1)))
+          (br_if $next (i32.lt_u (local.get $i) (local.get $n))))
+       (global.get $defined))
+    (func (export "count_imported") (param $n i32) (result i64)
+       (local $i i32)
+       (loop $next
+          (global.set $imported (i64.add (global.get $imported) (i64.const 1)))
+          (local.set $i (i32.add (local.get $i) (i32.const 1)))
+          (br_if $next (i32.lt_u (local.get $i) (local.get $n))))
+       (global.get $imported))
+)"#;
+
+pub fn run_global_counter(store: &Store, name: &str, c: &mut Criterion) {
+    let module = Module::new(&store, WAT).unwrap();
+    let imports = imports! {
+        "env" => {
+            "counter" => Global::new_mut(&store, Value::I64(0)),
+        },
+    };
+    let instance = Instance::new(&module, &imports).unwrap();
+    for counter in ["defined", "imported"] {
+        let count: NativeFunc<i32, i64> = instance
+            .get_native_function(&format!("count_{}", counter))
+            .unwrap();
+        c.bench_function(&format!("{} {} global counter", name, counter), |b| {
+            b.iter(|| black_box(count.call(black_box(10_000)).unwrap()))
+        });
+    }
+}
+
+fn run_global_counter_benchmarks(_c: &mut Criterion) {
+    #[cfg(feature = "singlepass")]
+    {
+        let compiler = wasmer_compiler_singlepass::Singlepass::new();
+        let store = Store::new(&Universal::new(compiler).engine());
+        run_global_counter(&store, "singlepass", _c);
+    }
+}
+
+criterion_group!(benches, run_global_counter_benchmarks);
+
+criterion_main!(benches);
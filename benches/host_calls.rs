@@ -0,0 +1,81 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Arc;
+
+use wasmer::*;
+
+static WAT: &str = r#"(module
+    (func $add (import "env" "add") (param i32 i32) (result i32))
+    (func (export "count") (param $n i32) (result i32)
+       (local $count i32)
+       (block $done
+          (loop $next
+             (br_if $done (i32.eq (local.get $count) (local.get $n)))
+             (local.set $count (call $add (local.get $count) (i32.const 1)))
+             (br $next)))
+       (local.get $count))
+)"#;
+
+fn bench_host_calls(c: &mut Criterion, name: &str, module: &Module, add: Function) {
+    let import_object = imports! {
+        "env" => {
+            "add" => add,
+        },
+    };
+    let instance = Instance::new(&module, &import_object).unwrap();
+    let count: NativeFunc<i32, i32> = instance.get_native_function("count").unwrap();
+    c.bench_function(name, |b| {
+        b.iter(|| {
+            let result = black_box(count.call(black_box(1000)).unwrap());
+            assert_eq!(result, 1000);
+        })
+    });
+}
+
+pub fn run_host_calls(store: &Store, compiler_name: &str, c: &mut Criterion) {
+    let module = Module::new(&store, WAT).unwrap();
+
+    let ty = FunctionType::new(vec![Type::I32, Type::I32], vec![Type::I32]);
+    let dynamic = Function::new(&store, &ty, |args| {
+        Ok(vec![Val::I32(args[0].unwrap_i32() + args[1].unwrap_i32())])
+    });
+    bench_host_calls(
+        c,
+        &format!("dynamic host calls {}", compiler_name),
+        &module,
+        dynamic,
+    );
+
+    let static_ = Function::new_native(&store, |a: i32, b: i32| a + b);
+    bench_host_calls(
+        c,
+        &format!("static host calls {}", compiler_name),
+        &module,
+        static_,
+    );
+
+    let calls = Arc::new(AtomicI32::new(0));
+    let closure = Function::new_native(&store, move |a: i32, b: i32| {
+        calls.fetch_add(1, Ordering::Relaxed);
+        a + b
+    });
+    bench_host_calls(
+        c,
+        &format!("static host closure calls {}", compiler_name),
+        &module,
+        closure,
+    );
+}
+
+fn run_host_call_benchmarks(_c: &mut Criterion) {
+    #[cfg(feature = "singlepass")]
+    {
+        let store =
+            Store::new(&Universal::new(wasmer_compiler_singlepass::Singlepass::new()).engine());
+        run_host_calls(&store, "singlepass", _c);
+    }
+}
+
+criterion_group!(benches, run_host_call_benchmarks);
+
+criterion_main!(benches);
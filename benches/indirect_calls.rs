@@ -0,0 +1,72 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use wasmer::*;
+
+/// The number of calls each run of the loop makes.
+const CALLS: i32 = 10_000_000;
+
+/// Dispatches through a table as virtual calls do, alternating between two implementations of
+/// the same signature, next to the same loop calling the implementations directly.
+static WAT: &str = r#"(module
+    (type $method (func (param i32) (result i32)))
+    (table 2 funcref)
+    (elem (i32.const 0) $add_one $add_two)
+    (func $add_one (type $method)
+       (i32.add (local.get 0) (i32.const 1)))
+    (func $add_two (type $method)
+       (i32.add (local.get 0) (i32.const 2)))
+    (func (export "indirect") (param $n i32) (result i32)
+       (local $i i32) (local $sum i32)
+       (block $done
+          (loop $next
+             (br_if $done (i32.eq (local.get $i) (local.get $n)))
+             (local.set $sum
+                (call_indirect (type $method)
+                   (local.get $sum)
+                   (i32.and (local.get $i) (i32.const 1))))
+             (local.set $i (i32.add (local.get $i) (i32.const 1)))
+             (br $next)))
+       (local.get $sum))
+    (func (export "direct") (param $n i32) (result i32)
+       (local $i i32) (local $sum i32)
+       (block $done
+          (loop $next
+             (br_if $done (i32.eq (local.get $i) (local.get $n)))
+             (local.set $sum
+                (if (result i32) (i32.and (local.get $i) (i32.const 1))
+                   (then (call $add_two (local.get $sum)))
+                   (else (call $add_one (local.get $sum)))))
+             (local.set $i (i32.add (local.get $i) (i32.const 1)))
+             (br $next)))
+       (local.get $sum))
+)"#;
+
+pub fn run_indirect_calls(store: &Store, compiler_name: &str, c: &mut Criterion) {
+    let module = Module::new(&store, WAT).unwrap();
+    let instance = Instance::new(&module, &imports! {}).unwrap();
+    let mut group = c.benchmark_group(format!("10M calls {}", compiler_name));
+    group.sample_size(10);
+    for name in ["indirect", "direct"] {
+        let calls: NativeFunc<i32, i32> = instance.get_native_function(name).unwrap();
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let result = black_box(calls.call(black_box(CALLS)).unwrap());
+                assert_eq!(result, CALLS / 2 * 3);
+            })
+        });
+    }
+    group.finish();
+}
+
+fn run_indirect_call_benchmarks(_c: &mut Criterion) {
+    #[cfg(feature = "singlepass")]
+    {
+        let store =
+            Store::new(&Universal::new(wasmer_compiler_singlepass::Singlepass::new()).engine());
+        run_indirect_calls(&store, "singlepass", _c);
+    }
+}
+
+criterion_group!(benches, run_indirect_call_benchmarks);
+
+criterion_main!(benches);
@@ -0,0 +1,66 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use wasmer::*;
+
+/// A module with 4 MiB of data segments, of 64 KiB of non-zero bytes each.
+fn large_data_wat() -> String {
+    let segment = "\\2a".repeat(0x1_0000);
+    let mut wat = String::from("(module (memory (export \"memory\") 64)");
+    for page in 0..64 {
+        wat.push_str(&format!(
+            "(data (i32.const {}) \"{}\")",
+            page * 0x1_0000,
+            segment
+        ));
+    }
+    wat.push_str(
+        r#"(func (export "touch") (param $address i32) (result i32)
+            (i32.store8 (local.get $address) (i32.const 1))
+            (i32.load8_u (i32.add (local.get $address) (i32.const 1))))
+        )"#,
+    );
+    wat
+}
+
+pub fn run_instantiation(store: &Store, compiler_name: &str, c: &mut Criterion) {
+    let module = Module::new(&store, large_data_wat()).unwrap();
+
+    c.bench_function(
+        &format!("instantiate with large data segments {}", compiler_name),
+        |b| {
+            b.iter(|| {
+                let instance = Instance::new(&module, &imports! {}).unwrap();
+                let touch: NativeFunc<i32, i32> = instance.get_native_function("touch").unwrap();
+                assert_eq!(black_box(touch.call(black_box(0x20_0000)).unwrap()), 0x2a);
+            })
+        },
+    );
+
+    let pre = InstancePre::new(&module, &imports! {}).unwrap();
+    c.bench_function(
+        &format!(
+            "instantiate with large data segments from an image {}",
+            compiler_name
+        ),
+        |b| {
+            b.iter(|| {
+                let instance = pre.instantiate().unwrap();
+                let touch: NativeFunc<i32, i32> = instance.get_native_function("touch").unwrap();
+                assert_eq!(black_box(touch.call(black_box(0x20_0000)).unwrap()), 0x2a);
+            })
+        },
+    );
+}
+
+fn run_instantiation_benchmarks(_c: &mut Criterion) {
+    #[cfg(feature = "singlepass")]
+    {
+        let store =
+            Store::new(&Universal::new(wasmer_compiler_singlepass::Singlepass::new()).engine());
+        run_instantiation(&store, "singlepass", _c);
+    }
+}
+
+criterion_group!(benches, run_instantiation_benchmarks);
+
+criterion_main!(benches);
@@ -0,0 +1,46 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use wasmer::*;
+
+/// A loop calling tiny leaf functions, which spend as much time in their prologue and epilogue
+/// as in their body.
+static WAT: &str = r#"(module
+    (func $mix (param i64 i64) (result i64)
+       (i64.xor (i64.mul (local.get 0) (i64.const 0x9e3779b97f4a7c15)) (local.get 1)))
+    (func $rotate (param i64) (result i64)
+       (i64.rotl (local.get 0) (i64.const 17)))
+    (func (export "hash") (param $n i32) (result i64)
+       (local $i i32) (local $h i64)
+       (loop $next
+          (local.set $h (call $rotate (call $mix (local.get $h) (i64.extend_i32_u (local.get $i)))))
+          (local.set $i (i32.add (local.get $i) (i32.const 1)))
+          (br_if $next (i32.lt_u (local.get $i) (local.get $n))))
+       (local.get $h))
+)"#;
+
+pub fn run_leaf_calls(store: &Store, name: &str, c: &mut Criterion) {
+    let module = Module::new(&store, WAT).unwrap();
+    let instance = Instance::new(&module, &imports! {}).unwrap();
+    let hash: NativeFunc<i32, i64> = instance.get_native_function("hash").unwrap();
+    c.bench_function(name, |b| {
+        b.iter(|| black_box(hash.call(black_box(10_000)).unwrap()))
+    });
+}
+
+fn run_leaf_calls_benchmarks(_c: &mut Criterion) {
+    #[cfg(feature = "singlepass")]
+    {
+        let compiler = wasmer_compiler_singlepass::Singlepass::new();
+        let store = Store::new(&Universal::new(compiler).engine());
+        run_leaf_calls(&store, "leaf calls singlepass", _c);
+
+        let mut compiler = wasmer_compiler_singlepass::Singlepass::new();
+        compiler.omit_leaf_frame_pointers(true);
+        let store = Store::new(&Universal::new(compiler).engine());
+        run_leaf_calls(&store, "leaf calls without frame pointers singlepass", _c);
+    }
+}
+
+criterion_group!(benches, run_leaf_calls_benchmarks);
+
+criterion_main!(benches);
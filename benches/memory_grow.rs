@@ -0,0 +1,57 @@
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use std::sync::Arc;
+
+use wasmer::*;
+
+/// A guest growing its memory a page at a time, like an allocator asking for more heap.
+static WAT: &str = r#"(module
+    (memory 1)
+    (func (export "grow_by_pages") (param $n i32) (result i32)
+       (local $grown i32)
+       (block $done
+          (loop $next
+             (br_if $done (i32.eq (local.get $grown) (local.get $n)))
+             (br_if $done (i32.eq (memory.grow (i32.const 1)) (i32.const -1)))
+             (local.set $grown (i32.add (local.get $grown) (i32.const 1)))
+             (br $next)))
+       (local.get $grown))
+)"#;
+
+pub fn run_memory_grow(store: &Store, name: &str, c: &mut Criterion) {
+    let module = Module::new(&store, WAT).unwrap();
+    c.bench_function(name, |b| {
+        b.iter_batched(
+            || {
+                let instance = Instance::new(&module, &imports! {}).unwrap();
+                let grow: NativeFunc<i32, i32> =
+                    instance.get_native_function("grow_by_pages").unwrap();
+                (instance, grow)
+            },
+            |(_instance, grow)| {
+                let grown = black_box(grow.call(black_box(1000)).unwrap());
+                assert_eq!(grown, 1000);
+            },
+            BatchSize::PerIteration,
+        )
+    });
+}
+
+fn run_memory_grow_benchmarks(_c: &mut Criterion) {
+    #[cfg(feature = "singlepass")]
+    {
+        let engine = Universal::new(wasmer_compiler_singlepass::Singlepass::new()).engine();
+        let store = Store::new(&engine);
+        run_memory_grow(&store, "memory grows singlepass", _c);
+
+        // A grow callback makes every grow call into the runtime, as they all did before
+        // compiled code could grow the memories by itself.
+        let mut tunables = BaseTunables::for_target(engine.target());
+        tunables.memory_grow_callback = Some(Arc::new(|_: &MemoryGrow| Ok(())));
+        let store = Store::new_with_tunables(&engine, tunables);
+        run_memory_grow(&store, "memory grows through the runtime singlepass", _c);
+    }
+}
+
+criterion_group!(benches, run_memory_grow_benchmarks);
+
+criterion_main!(benches);
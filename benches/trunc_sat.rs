@@ -0,0 +1,51 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use wasmer::*;
+
+static TRUNC_SAT_LOOP_WAT: &str = r#"(module
+    (func (export "sum_truncated") (param $n i32) (result i64)
+        (local $i i32) (local $acc i64) (local $x f64)
+        (local.set $x (f64.const -1e10))
+        (block $done
+            (loop $next
+                (br_if $done (i32.ge_u (local.get $i) (local.get $n)))
+                (local.set $acc
+                    (i64.add
+                        (i64.add (local.get $acc) (i64.trunc_sat_f64_s (local.get $x)))
+                        (i64.extend_i32_u
+                            (i32.add (i32.trunc_sat_f32_u (f32.demote_f64 (local.get $x)))
+                                     (i32.trunc_sat_f64_s (local.get $x))))))
+                (local.set $x (f64.add (local.get $x) (f64.const 2.5e6)))
+                (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                (br $next)))
+        (local.get $acc))
+)"#;
+
+pub fn run_trunc_sat_loop(store: &Store, compiler_name: &str, c: &mut Criterion) {
+    let module = Module::new(&store, TRUNC_SAT_LOOP_WAT).unwrap();
+    let instance = Instance::new(&module, &imports! {}).unwrap();
+    let f: NativeFunc<i32, i64> = instance
+        .lookup_function("sum_truncated")
+        .unwrap()
+        .native()
+        .unwrap();
+
+    c.bench_function(&format!("trunc_sat loop {}", compiler_name), |b| {
+        b.iter(|| {
+            black_box(f.call(black_box(10_000)).unwrap());
+        })
+    });
+}
+
+fn run_trunc_sat_benchmarks(_c: &mut Criterion) {
+    #[cfg(feature = "singlepass")]
+    {
+        let store =
+            Store::new(&Universal::new(wasmer_compiler_singlepass::Singlepass::new()).engine());
+        run_trunc_sat_loop(&store, "singlepass", _c);
+    }
+}
+
+criterion_group!(benches, run_trunc_sat_benchmarks);
+
+criterion_main!(benches);
@@ -0,0 +1,46 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use wasmer::*;
+
+static ADD_WAT: &str = r#"(module
+    (func (export "add") (param i32 i32) (result i32)
+        (i32.add (local.get 0) (local.get 1)))
+)"#;
+
+/// Compares the overhead of calling a trivial function with `Value`s and with a typed function.
+pub fn run_add_calls(store: &Store, compiler_name: &str, c: &mut Criterion) {
+    let module = Module::new(&store, ADD_WAT).unwrap();
+    let instance = Instance::new(&module, &imports! {}).unwrap();
+    let add = instance.lookup_function("add").unwrap();
+    let typed_add: TypedFunction<(i32, i32), i32> = add.native().unwrap();
+
+    let mut group = c.benchmark_group(format!("add calls {}", compiler_name));
+    group.bench_function("values", |b| {
+        b.iter(|| {
+            let results = add
+                .call(&[Val::I32(black_box(4)), Val::I32(black_box(6))])
+                .unwrap();
+            assert_eq!(black_box(results)[0], Val::I32(10));
+        })
+    });
+    group.bench_function("typed", |b| {
+        b.iter(|| {
+            let result = typed_add.call(black_box(4), black_box(6)).unwrap();
+            assert_eq!(black_box(result), 10);
+        })
+    });
+    group.finish();
+}
+
+fn run_typed_function_benchmarks(_c: &mut Criterion) {
+    #[cfg(feature = "singlepass")]
+    {
+        let store =
+            Store::new(&Universal::new(wasmer_compiler_singlepass::Singlepass::new()).engine());
+        run_add_calls(&store, "singlepass", _c);
+    }
+}
+
+criterion_group!(benches, run_typed_function_benchmarks);
+
+criterion_main!(benches);
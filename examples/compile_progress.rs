@@ -0,0 +1,95 @@
+//! Compiling a large module takes a while, so embedders may want to show
+//! how far along the compilation is, and to give up on it early.
+//!
+//! This example illustrates how to report the progress of the compilation
+//! of a module, function by function, and how to cancel it.
+//!
+//! You can run the example directly by executing in Wasmer root:
+//!
+//! ```shell
+//! cargo run --example compile-progress --release --features "singlepass"
+//! ```
+//!
+//! Ready?
+
+use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use wasmer::{wat2wasm, CancellationToken, CompileError, CompileProgress, Module, Store};
+use wasmer_compiler_singlepass::Singlepass;
+use wasmer_engine_universal::Universal;
+
+const FUNCTIONS: usize = 500;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Let's generate a module with many functions, so that its
+    // compilation is worth reporting.
+    let mut wat = String::from("(module\n");
+    for i in 0..FUNCTIONS {
+        wat.push_str(&format!(
+            "  (func (export \"f{}\") (param i64) (result i64)\n    \
+             (i64.add (i64.mul (local.get 0) (i64.const {})) (i64.const 1)))\n",
+            i, i
+        ));
+    }
+    wat.push(')');
+    let wasm_bytes = wat2wasm(wat.as_bytes())?;
+
+    let store = Store::new(&Universal::new(Singlepass::default()).engine());
+
+    // The callback may be called from several threads at once, so the count
+    // of compiled functions is shared through an atomic.
+    let compiled = Arc::new(AtomicUsize::new(0));
+    let progress = CompileProgress::new().on_function_compiled({
+        let compiled = compiled.clone();
+        move |_index, _code_size, _elapsed| {
+            let done = compiled.fetch_add(1, Ordering::Relaxed) + 1;
+            let width = 40 * done / FUNCTIONS;
+            print!(
+                "\r[{:<40}] {}/{} functions",
+                "=".repeat(width),
+                done,
+                FUNCTIONS
+            );
+            let _ = std::io::stdout().flush();
+        }
+    });
+
+    println!("Compiling module...");
+    // Let's compile the Wasm module, reporting each compiled function.
+    let _module = Module::new_with_progress(&store, &wasm_bytes, &progress)?;
+    println!();
+    assert_eq!(compiled.load(Ordering::Relaxed), FUNCTIONS);
+
+    println!("Compiling module again, cancelling it halfway...");
+    // The token is checked between functions, so the compilation stops
+    // shortly after it is cancelled.
+    let token = CancellationToken::new();
+    let compiled = Arc::new(AtomicUsize::new(0));
+    let progress = CompileProgress::new()
+        .cancellation_token(token.clone())
+        .on_function_compiled({
+            let compiled = compiled.clone();
+            move |_index, _code_size, _elapsed| {
+                if compiled.fetch_add(1, Ordering::Relaxed) + 1 == FUNCTIONS / 2 {
+                    token.cancel();
+                }
+            }
+        });
+    match Module::new_with_progress(&store, &wasm_bytes, &progress) {
+        Err(CompileError::Cancelled) => println!(
+            "Cancelled after {} functions",
+            compiled.load(Ordering::Relaxed)
+        ),
+        Err(e) => return Err(e.into()),
+        Ok(_) => panic!("The compilation should have been cancelled"),
+    }
+
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "singlepass")]
+fn test_compile_progress() -> Result<(), Box<dyn std::error::Error>> {
+    main()
+}
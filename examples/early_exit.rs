@@ -82,10 +82,7 @@ fn main() -> anyhow::Result<()> {
     //
     // Get the `run` function which we'll use as our entrypoint.
     println!("Calling `run` function...");
-    let run_func: NativeFunc<(i32, i32), i32> = instance
-        .lookup_function("run")
-        .ok_or(anyhow::anyhow!("could not find `run` export"))?
-        .native()?;
+    let run_func: NativeFunc<(i32, i32), i32> = instance.lookup_function("run")?.native()?;
 
     // When we call a function it can either succeed or fail. We expect it to fail.
     match run_func.call(1, 7) {
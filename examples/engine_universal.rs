@@ -76,9 +76,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     println!("Calling `sum` function...");
     // The Wasm module exports a function called `sum`.
-    let sum = instance
-        .lookup_function("sum")
-        .ok_or("could not find `sum` export")?;
+    let sum = instance.lookup_function("sum")?;
     let results = sum.call(&[Value::I32(1), Value::I32(2)])?;
 
     println!("Results: {:?}", results);
@@ -60,8 +60,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     //
     // Let's get it.
     let div_by_zero = instance
-        .lookup_function("div_by_zero")
-        .ok_or("could not find `div_by_zero` export")?
+        .lookup_function("div_by_zero")?
         .native::<(), i32>()?;
 
     println!("Calling `div_by_zero` function...");
@@ -67,9 +67,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     //     ```
     //     get::<Function>(name)`.
     //     ```
-    let sum = instance
-        .lookup_function("sum")
-        .ok_or("could not find `sum` export")?;
+    let sum = instance.lookup_function("sum")?;
 
     println!("Calling `sum` function...");
     // Let's call the `sum` exported function. The parameters are a
@@ -94,10 +94,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     //
     // We will use an exported function for the `one` global
     // and the Global API for `some`.
-    let get_one = instance
-        .lookup_function("get_one")
-        .ok_or("could not find `get_one` export")?
-        .native::<(), f32>()?;
+    let get_one = instance.lookup_function("get_one")?.native::<(), f32>()?;
 
     let one_value = get_one.call()?;
     let some_value = some.from.get(&store);
@@ -126,10 +123,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     //   2. Using the Global API directly.
     //
     // We will use both for the `some` global.
-    let set_some = instance
-        .lookup_function("set_some")
-        .ok_or("could not find `set_some` export")?
-        .native::<f32, ()>()?;
+    let set_some = instance.lookup_function("set_some")?.native::<f32, ()>()?;
     set_some.call(21.0)?;
     let some_result = some.from.get(&store);
     println!("`some` value after `set_some`: {:?}", some_result);
@@ -52,12 +52,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let instance = Instance::new(&module, &import_object)?;
 
     let load_offset = instance
-        .lookup_function("load_offset")
-        .ok_or("could not find `load_offset` export")?
+        .lookup_function("load_offset")?
         .native::<(), WasmPtr<u8, Array>>()?;
     let load_length = instance
-        .lookup_function("load_length")
-        .ok_or("could not find `load_length` export")?
+        .lookup_function("load_length")?
         .native::<(), i32>()?;
 
     // Here we go.
@@ -46,9 +46,7 @@ fn main() -> anyhow::Result<()> {
     // :-).
     let import_object = imports! {};
     let instance = Instance::new(&module, &import_object)?;
-    let swap = instance
-        .lookup_function("swap")
-        .ok_or(anyhow::anyhow!("could not find `swap` export"))?;
+    let swap = instance.lookup_function("swap")?;
 
     let results = swap.call(&[Value::I32(1), Value::I64(2)])?;
 
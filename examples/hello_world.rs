@@ -77,10 +77,7 @@ fn main() -> anyhow::Result<()> {
     //
     // Recall that the Wasm module exported a function named "run", this is getting
     // that exported function from the `Instance`.
-    let run_func: NativeFunc<(), ()> = instance
-        .lookup_function("run")
-        .ok_or(anyhow::anyhow!("could not find `run` export"))?
-        .native()?;
+    let run_func: NativeFunc<(), ()> = instance.lookup_function("run")?.native()?;
 
     // Finally, we call our exported Wasm function which will call our "say_hello"
     // function and return.
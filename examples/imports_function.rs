@@ -86,8 +86,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     //
     // The Wasm module exports a function called `sum`. Let's get it.
     let sum = instance
-        .lookup_function("sum")
-        .ok_or("could not find `sum` export")?
+        .lookup_function("sum")?
         .native::<(i32, i32), i32>()?;
 
     println!("Calling `sum` function...");
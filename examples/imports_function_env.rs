@@ -102,8 +102,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     //
     // The Wasm module exports a function called `increment_counter_loop`. Let's get it.
     let increment_counter_loop = instance
-        .lookup_function("increment_counter_loop")
-        .ok_or("could not find `increment_counter_loop` export")?
+        .lookup_function("increment_counter_loop")?
         .native::<i32, i32>()?;
 
     let counter_value: i32 = *shared_counter.lock().unwrap();
@@ -73,18 +73,9 @@ fn main() -> anyhow::Result<()> {
     // The module exports some utility functions, let's get them.
     //
     // These function will be used later in this example.
-    let mem_size: NativeFunc<(), i32> = instance
-        .lookup_function("mem_size")
-        .ok_or(anyhow::anyhow!("could not find `mem_size` export"))?
-        .native()?;
-    let get_at: NativeFunc<i32, i32> = instance
-        .lookup_function("get_at")
-        .ok_or(anyhow::anyhow!("could not find `get_at` export"))?
-        .native()?;
-    let set_at: NativeFunc<(i32, i32), ()> = instance
-        .lookup_function("set_at")
-        .ok_or(anyhow::anyhow!("could not find `set_at` export"))?
-        .native()?;
+    let mem_size: NativeFunc<(), i32> = instance.lookup_function("mem_size")?.native()?;
+    let get_at: NativeFunc<i32, i32> = instance.lookup_function("get_at")?.native()?;
+    let set_at: NativeFunc<(i32, i32), ()> = instance.lookup_function("set_at")?.native()?;
     let memory = match instance.lookup("memory") {
         Some(wasmer::Export::Memory(m)) => m,
         _ => anyhow::bail!("could not find `memory` as an exported memory"),
@@ -0,0 +1,207 @@
+//! Async calls: calling into Wasm from a future, with host functions awaiting futures of
+//! their own.
+//!
+//! Each async call runs on a fiber. When a host function created with
+//! `Function::new_async` is called and its future is pending, the fiber is suspended, Wasm
+//! frames included, and the future of the call is pending as well. Polling the call again
+//! resumes the fiber, which polls the future of the host function again.
+//!
+//! The calls are `Send`: polled from another thread, a call resumes its fiber there.
+
+use crate::sys::{RuntimeError, Store};
+use std::cell::Cell;
+use std::future::Future;
+use std::pin::Pin;
+use std::ptr;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use wasmer_vm::{Fiber, Suspend, WeakOrStrongInstanceRef};
+
+/// The state of an async call shared with the host functions it runs.
+struct AsyncContext {
+    /// The handle suspending the fiber of the call, once it started.
+    suspend: Cell<*const Suspend>,
+    /// The context of the poll running the call, null while it isn't polled.
+    cx: Cell<*mut Context<'static>>,
+    /// Whether the future of the call was dropped before the call returned.
+    cancelled: Cell<bool>,
+}
+
+// The context is used by the fiber of the call and by the code resuming it, which never run
+// at the same time.
+unsafe impl Send for AsyncContext {}
+
+thread_local! {
+    /// The async call running on this thread, if any.
+    static CURRENT: Cell<*const AsyncContext> = Cell::new(ptr::null());
+}
+
+/// The async call running on this thread, if any.
+// Not inlined, so that the address of the thread-local isn't kept across a suspension,
+// after which the fiber may run on another thread.
+#[inline(never)]
+fn current() -> *const AsyncContext {
+    CURRENT.with(Cell::get)
+}
+
+/// Set the async call running on this thread, returning the previous one.
+#[inline(never)]
+fn replace_current(context: *const AsyncContext) -> *const AsyncContext {
+    CURRENT.with(|current| current.replace(context))
+}
+
+/// The context of an async call, moved to its fiber.
+struct ContextPtr(*const AsyncContext);
+
+unsafe impl Send for ContextPtr {}
+
+/// The future of an async call into a function, created with `Function::call_async` or
+/// `NativeFunc::call_async`.
+///
+/// The call doesn't start before the future is polled. The future may be polled from any
+/// thread, for example by a multi-threaded executor, and the call then resumes on that
+/// thread. Host functions calling into Wasm that suspends go along with it, so they mustn't
+/// hold values bound to their thread across the call. Dropping the future before it is ready
+/// cancels the call: the async host functions it is running return an error, which unwinds
+/// the Wasm frames as a trap.
+#[must_use = "futures do nothing unless polled"]
+pub struct AsyncCall<R> {
+    fiber: Option<Fiber<'static>>,
+    context: Box<AsyncContext>,
+    result: Arc<Mutex<Option<Result<R, RuntimeError>>>>,
+    /// The instance the call is into, marked as suspended while the call is.
+    instance_ref: Option<WeakOrStrongInstanceRef>,
+    suspended: bool,
+}
+
+impl<R: Send + 'static> AsyncCall<R> {
+    pub(crate) fn new(
+        store: &Store,
+        instance_ref: Option<WeakOrStrongInstanceRef>,
+        call: impl FnOnce() -> Result<R, RuntimeError> + Send + 'static,
+    ) -> Self {
+        let context = Box::new(AsyncContext {
+            suspend: Cell::new(ptr::null()),
+            cx: Cell::new(ptr::null_mut()),
+            cancelled: Cell::new(false),
+        });
+        let result = Arc::new(Mutex::new(None));
+        let fiber = Fiber::new(store.async_stack_size, {
+            let context = ContextPtr(&*context);
+            let result = result.clone();
+            move |suspend| {
+                unsafe { (*context.0).suspend.set(suspend) };
+                let output = call();
+                *result.lock().unwrap() = Some(output);
+            }
+        });
+        let fiber = match fiber {
+            Ok(fiber) => Some(fiber),
+            Err(error) => {
+                *result.lock().unwrap() = Some(Err(RuntimeError::new(format!(
+                    "failed to allocate the stack of an async call: {}",
+                    error
+                ))));
+                None
+            }
+        };
+        Self {
+            fiber,
+            context,
+            result,
+            instance_ref,
+            suspended: false,
+        }
+    }
+}
+
+impl<R> AsyncCall<R> {
+    /// Run the call until it returns or awaits a pending future, and return whether it
+    /// returned.
+    fn resume(&mut self, cx: *mut Context<'static>) -> bool {
+        struct Restore(*const AsyncContext);
+
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                replace_current(self.0);
+            }
+        }
+
+        let fiber = self
+            .fiber
+            .as_mut()
+            .expect("the async call already returned");
+        if self.suspended {
+            if let Some(instance_ref) = &self.instance_ref {
+                instance_ref.set_suspended(false);
+            }
+            self.suspended = false;
+        }
+        self.context.cx.set(cx);
+        let context: *const AsyncContext = &*self.context;
+        let done = {
+            let _restore = Restore(replace_current(context));
+            fiber.resume()
+        };
+        self.context.cx.set(ptr::null_mut());
+        if done {
+            self.fiber = None;
+        } else {
+            if let Some(instance_ref) = &self.instance_ref {
+                instance_ref.set_suspended(true);
+            }
+            self.suspended = true;
+        }
+        done
+    }
+}
+
+impl<R> Future for AsyncCall<R> {
+    type Output = Result<R, RuntimeError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        // The context is only used while it is borrowed, during `resume`.
+        let cx = (cx as *mut Context<'_>).cast::<Context<'static>>();
+        if this.fiber.is_some() && !this.resume(cx) {
+            return Poll::Pending;
+        }
+        let result = this.result.lock().unwrap().take();
+        Poll::Ready(result.expect("`AsyncCall` polled after completion"))
+    }
+}
+
+impl<R> Drop for AsyncCall<R> {
+    fn drop(&mut self) {
+        if self.fiber.as_ref().map_or(false, |fiber| !fiber.is_done()) {
+            // Run the call to its end, so that the values on its stack are dropped.
+            self.context.cancelled.set(true);
+            while !self.resume(ptr::null_mut()) {}
+        }
+    }
+}
+
+/// Run `future` to completion from a host function, suspending the async call running it
+/// while the future is pending.
+pub(crate) fn block_on<F: Future>(future: F) -> Result<F::Output, RuntimeError> {
+    let mut future = future;
+    // The future stays on the stack of the fiber, which never moves.
+    let mut future = unsafe { Pin::new_unchecked(&mut future) };
+    loop {
+        let context = current();
+        if context.is_null() {
+            return Err(RuntimeError::new(
+                "async host functions can only be called from an async call",
+            ));
+        }
+        let context = unsafe { &*context };
+        if context.cancelled.get() {
+            return Err(RuntimeError::new("the async call was cancelled"));
+        }
+        let cx = unsafe { &mut *context.cx.get() };
+        if let Poll::Ready(output) = future.as_mut().poll(cx) {
+            return Ok(output);
+        }
+        unsafe { (*context.suspend.get()).suspend() };
+    }
+}
@@ -0,0 +1,228 @@
+//! Caches of compiled modules, used by [`Module::new_cached`](crate::Module::new_cached).
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use wasmer_engine_universal::Fingerprint;
+
+/// Identifies a module compiled from some wasm bytes, by an engine with some fingerprint.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ModuleCacheKey([u8; blake3::OUT_LEN]);
+
+impl ModuleCacheKey {
+    /// The key of the module compiled from `wasm` by an engine with `fingerprint`.
+    pub fn new(wasm: &[u8], fingerprint: &Fingerprint) -> Self {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(format!("{:?}", fingerprint).as_bytes());
+        // The debug output never contains a null byte, so it can't run into the wasm.
+        hasher.update(&[0]);
+        hasher.update(wasm);
+        Self(*hasher.finalize().as_bytes())
+    }
+
+    fn parse(hex: &str) -> Option<Self> {
+        let mut key = [0; blake3::OUT_LEN];
+        if hex.len() != 2 * key.len() {
+            return None;
+        }
+        for (i, byte) in key.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(hex.get(2 * i..2 * i + 2)?, 16).ok()?;
+        }
+        Some(Self(key))
+    }
+}
+
+impl fmt::Display for ModuleCacheKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in &self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// A store of serialized modules.
+///
+/// The entries are checked against the hash embedded in serialized executables before they
+/// are loaded, and entries that fail the check are replaced. This detects corrupted entries,
+/// but not forged ones, so the storage of the cache must be trusted.
+pub trait ModuleCache {
+    /// Returns the serialized module stored under `key`, if any.
+    fn load(&self, key: &ModuleCacheKey) -> Option<Vec<u8>>;
+
+    /// Stores the serialized module `bytes` under `key`, replacing any previous entry.
+    fn store(&self, key: &ModuleCacheKey, bytes: &[u8]) -> io::Result<()>;
+}
+
+/// A [`ModuleCache`] storing each module in a file of a directory.
+///
+/// Once the files take more than the given size, the least recently used
+/// ones are removed. The cache only tracks the uses made through it, so
+/// several processes can share a directory, but each one evicts entries
+/// according to its own uses.
+pub struct FileSystemCache {
+    dir: PathBuf,
+    max_size: u64,
+    /// The entries and their sizes, from the least to the most recently used.
+    entries: Mutex<Vec<(ModuleCacheKey, u64)>>,
+}
+
+impl FileSystemCache {
+    /// Opens the cache in `dir`, creating the directory if needed, which
+    /// keeps the size of the stored modules under `max_size` bytes.
+    ///
+    /// The modules already in the directory are considered used in the
+    /// order they were last modified.
+    pub fn new(dir: impl Into<PathBuf>, max_size: u64) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        let mut entries = vec![];
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let key = match entry.file_name().to_str().and_then(ModuleCacheKey::parse) {
+                Some(key) => key,
+                None => continue,
+            };
+            let metadata = entry.metadata()?;
+            entries.push((metadata.modified()?, key, metadata.len()));
+        }
+        entries.sort_by_key(|&(modified, _, _)| modified);
+        Ok(Self {
+            dir,
+            max_size,
+            entries: Mutex::new(
+                entries
+                    .into_iter()
+                    .map(|(_, key, size)| (key, size))
+                    .collect(),
+            ),
+        })
+    }
+
+    /// The directory holding the modules.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn path(&self, key: &ModuleCacheKey) -> PathBuf {
+        self.dir.join(key.to_string())
+    }
+}
+
+impl ModuleCache for FileSystemCache {
+    fn load(&self, key: &ModuleCacheKey) -> Option<Vec<u8>> {
+        let bytes = fs::read(self.path(key));
+        let mut entries = self.entries.lock().unwrap();
+        let position = entries.iter().position(|(k, _)| k == key);
+        match (bytes, position) {
+            (Ok(bytes), Some(position)) => {
+                let entry = entries.remove(position);
+                entries.push(entry);
+                Some(bytes)
+            }
+            // Stored by another process since the cache was opened.
+            (Ok(bytes), None) => {
+                entries.push((*key, bytes.len() as u64));
+                Some(bytes)
+            }
+            (Err(_), Some(position)) => {
+                entries.remove(position);
+                None
+            }
+            (Err(_), None) => None,
+        }
+    }
+
+    fn store(&self, key: &ModuleCacheKey, bytes: &[u8]) -> io::Result<()> {
+        static TEMPORARY_FILES: AtomicUsize = AtomicUsize::new(0);
+        let size = bytes.len() as u64;
+        if size > self.max_size {
+            return Ok(());
+        }
+        // Write the whole file before it can be seen under its key, so that concurrent loads
+        // never observe part of it.
+        let temporary = self.dir.join(format!(
+            "{}.{}.{}.tmp",
+            key,
+            std::process::id(),
+            TEMPORARY_FILES.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&temporary, bytes)?;
+        if let Err(e) = fs::rename(&temporary, self.path(key)) {
+            let _ = fs::remove_file(&temporary);
+            return Err(e);
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|(k, _)| k != key);
+        entries.push((*key, size));
+        let mut total = entries.iter().map(|&(_, size)| size).sum::<u64>();
+        let mut evicted = 0;
+        while total > self.max_size {
+            let (key, size) = entries[evicted];
+            match fs::remove_file(self.path(&key)) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+            total -= size;
+            evicted += 1;
+        }
+        entries.drain(..evicted);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasmer_compiler::CpuFeature;
+    use wasmer_types::Features;
+
+    fn key(n: u8) -> ModuleCacheKey {
+        let fingerprint = Fingerprint {
+            compiler: None,
+            features: Features::default(),
+            cpu_features: CpuFeature::set(),
+            calling_convention: "SystemV".to_string(),
+            triple: "x86_64-unknown-linux-gnu".to_string(),
+        };
+        ModuleCacheKey::new(&[n], &fingerprint)
+    }
+
+    #[test]
+    fn keys_round_trip_through_file_names() {
+        let key = key(0);
+        assert_eq!(ModuleCacheKey::parse(&key.to_string()), Some(key));
+        assert_eq!(ModuleCacheKey::parse("module.wasm"), None);
+    }
+
+    #[test]
+    fn least_recently_used_modules_are_evicted() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = FileSystemCache::new(dir.path(), 30).unwrap();
+        cache.store(&key(0), &[0; 10]).unwrap();
+        cache.store(&key(1), &[1; 10]).unwrap();
+        cache.store(&key(2), &[2; 10]).unwrap();
+        // Using the oldest module makes the next one the least recently used.
+        assert_eq!(cache.load(&key(0)), Some(vec![0; 10]));
+        cache.store(&key(3), &[3; 10]).unwrap();
+        assert_eq!(cache.load(&key(1)), None);
+        assert_eq!(cache.load(&key(0)), Some(vec![0; 10]));
+        assert_eq!(cache.load(&key(2)), Some(vec![2; 10]));
+        assert_eq!(cache.load(&key(3)), Some(vec![3; 10]));
+
+        // Modules larger than the whole cache are not stored.
+        cache.store(&key(4), &[4; 31]).unwrap();
+        assert_eq!(cache.load(&key(4)), None);
+        assert_eq!(cache.load(&key(3)), Some(vec![3; 10]));
+
+        // A reopened cache finds the modules stored before.
+        let reopened = FileSystemCache::new(dir.path(), 30).unwrap();
+        assert_eq!(reopened.load(&key(0)), Some(vec![0; 10]));
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 3);
+    }
+}
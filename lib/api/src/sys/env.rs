@@ -27,9 +27,10 @@ impl From<ExportError> for HostEnvInitError {
 ///
 /// # Examples
 ///
-/// This trait may also be implemented manually:
+/// An environment giving its host functions access to the memory of the instance
+/// they are imported into:
 /// ```
-/// # use wasmer::{WasmerEnv, LazyInit, Memory, Instance, HostEnvInitError};
+/// # use wasmer::{Exportable, WasmerEnv, LazyInit, Memory, Instance, HostEnvInitError};
 /// #[derive(Clone)]
 /// pub struct MyEnv {
 ///    memory: LazyInit<Memory>,
@@ -37,14 +38,19 @@ impl From<ExportError> for HostEnvInitError {
 ///
 /// impl WasmerEnv for MyEnv {
 ///     fn init_with_instance(&mut self, instance: &Instance) -> Result<(), HostEnvInitError> {
-///         todo!()
+///         let mut memory = instance.lookup_memory("memory")?;
+///         memory.into_weak_instance_ref();
+///         self.memory.initialize(memory);
+///         Ok(())
 ///     }
 /// }
 /// ```
 ///
-/// When implementing the trait manually, it's important to get a "weak" export to
-/// prevent a cyclic reference leaking memory. You can access a "weak" export with
-/// a method like `get_with_generics_weak`.
+/// It's important to only keep a "weak" reference to the instance in the exports
+/// stored in the environment, with [`Exportable::into_weak_instance_ref`], to
+/// prevent a cyclic reference leaking memory.
+///
+/// [`Exportable::into_weak_instance_ref`]: crate::Exportable::into_weak_instance_ref
 pub trait WasmerEnv: Clone + Send + Sync {
     /// The function that Wasmer will call on your type to let it finish
     /// setting up the environment with data from the `Instance`.
@@ -0,0 +1,107 @@
+use crate::sys::externals::Tag;
+use crate::sys::store::{Store, StoreObject};
+use crate::sys::types::Val;
+use crate::sys::RuntimeError;
+use std::fmt;
+use wasmer_vm::VMException;
+
+/// A WebAssembly exception, of the exception handling proposal.
+///
+/// An exception is an instance of a [`Tag`] carrying values of the param types of the tag. The
+/// host throws one into the guest by returning [`Exception::into_runtime_error`] as the error
+/// of a host function, and gets the exceptions the guest doesn't catch back with
+/// [`Exception::from_runtime_error`].
+#[derive(Clone)]
+pub struct Exception {
+    store: Store,
+    vm_exception: VMException,
+}
+
+impl Exception {
+    /// Create a new `Exception` of `tag`, carrying `payload`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use wasmer::{Exception, Store, Tag, TagType, Type, Value};
+    /// # let store = Store::default();
+    /// #
+    /// let t = Tag::new(&store, TagType::new(vec![Type::I32]));
+    /// let e = Exception::new(&t, &[Value::I32(42)]).unwrap();
+    ///
+    /// assert!(e.tag().same(&t));
+    /// assert_eq!(e.payload(), vec![Value::I32(42)]);
+    /// ```
+    pub fn new(tag: &Tag, payload: &[Val]) -> Result<Self, RuntimeError> {
+        let params = tag.ty().params();
+        if payload.len() != params.len()
+            || payload
+                .iter()
+                .zip(params.iter())
+                .any(|(v, ty)| v.ty() != *ty)
+        {
+            return Err(RuntimeError::new(format!(
+                "exception payload does not match the tag type {}",
+                tag.ty()
+            )));
+        }
+        if payload
+            .iter()
+            .any(|v| !v.comes_from_same_store(tag.store()))
+        {
+            return Err(RuntimeError::new("cross-`Store` values are not supported"));
+        }
+        let mut values = vec![0i128; payload.len()].into_boxed_slice();
+        for (value, slot) in payload.iter().zip(values.iter_mut()) {
+            // SAFETY: the slot is an aligned 16-byte slot, and the `externref`s written to it hold
+            // a count for the exception.
+            unsafe { value.write_value_to(slot) };
+        }
+        Ok(Self {
+            store: tag.store().clone(),
+            // SAFETY: the values were checked against the tag type.
+            vm_exception: unsafe { VMException::new(tag.vm_tag().clone(), values) },
+        })
+    }
+
+    /// Returns the [`Tag`] of the `Exception`.
+    pub fn tag(&self) -> Tag {
+        Tag::from_vm_export(&self.store, self.vm_exception.tag().clone())
+    }
+
+    /// Returns the values the `Exception` carries.
+    pub fn payload(&self) -> Vec<Val> {
+        let params = self.vm_exception.tag().ty().params();
+        self.vm_exception
+            .payload()
+            .iter()
+            .zip(params.iter())
+            // SAFETY: the exception holds a value of each param type of its tag.
+            .map(|(value, ty)| unsafe { Val::read_value_from(&self.store, value, *ty) })
+            .collect()
+    }
+
+    /// Returns the exception a [`RuntimeError`] reports as uncaught, if it is one.
+    pub fn from_runtime_error(store: &Store, error: &RuntimeError) -> Option<Self> {
+        error.exception().map(|vm_exception| Self {
+            store: store.clone(),
+            vm_exception: vm_exception.clone(),
+        })
+    }
+
+    /// Turns the `Exception` into a [`RuntimeError`], which throws the exception into the guest
+    /// when a host function returns it.
+    pub fn into_runtime_error(self) -> RuntimeError {
+        RuntimeError::uncaught_exception(self.vm_exception)
+    }
+}
+
+impl fmt::Debug for Exception {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter
+            .debug_struct("Exception")
+            .field("tag", &self.tag())
+            .field("payload", &self.payload())
+            .finish()
+    }
+}
@@ -1,5 +1,6 @@
 use crate::sys::externals::Extern;
 use crate::sys::import_object::LikeNamespace;
+use crate::sys::types::ExternType;
 use indexmap::IndexMap;
 use std::sync::Arc;
 use thiserror::Error;
@@ -46,8 +47,15 @@ use wasmer_vm::Export;
 pub enum ExportError {
     /// An error than occurs when the exported type and the expected type
     /// are incompatible.
-    #[error("Incompatible Export Type")]
-    IncompatibleType,
+    #[error("Incompatible type for export `{name}`: expected {expected}, found {found}")]
+    IncompatibleType {
+        /// The name of the export.
+        name: String,
+        /// A description of the expected type.
+        expected: String,
+        /// The type of the export.
+        found: ExternType,
+    },
     /// This error arises when an export is missing
     #[error("Missing export {0}")]
     Missing(String),
@@ -102,4 +110,14 @@ pub trait Exportable<'a>: Sized {
     ///
     /// [`Module`]: crate::Module
     fn to_export(&self) -> Export;
+
+    /// Makes this export only keep a weak reference to the instance it comes from.
+    ///
+    /// Host function environments that store exports of the instance they are imported into
+    /// should do this in [`WasmerEnv::init_with_instance`], so that the instance and the
+    /// environment don't keep each other alive. Cloning the export makes its reference
+    /// strong again, and panics if the instance was dropped.
+    ///
+    /// [`WasmerEnv::init_with_instance`]: crate::WasmerEnv::init_with_instance
+    fn into_weak_instance_ref(&mut self);
 }
@@ -1,3 +1,5 @@
+#[cfg(unix)]
+use crate::sys::async_call::{block_on, AsyncCall};
 use crate::sys::exports::Exportable;
 use crate::sys::store::Store;
 use crate::sys::types::{Val, ValFuncRef};
@@ -5,17 +7,21 @@ use crate::sys::FunctionType;
 use crate::sys::NativeFunc;
 use crate::sys::RuntimeError;
 use crate::sys::WasmerEnv;
+use inner::ClosureEnv;
 pub use inner::{FromToNativeWasmType, HostFunction, WasmTypeList, WithEnv, WithoutEnv};
 
 use std::cmp::max;
 use std::ffi::c_void;
 use std::fmt;
+#[cfg(unix)]
+use std::future::Future;
 use std::sync::Arc;
+use wasmer_types::ExternRef;
 use wasmer_vm::{
-    raise_user_trap, resume_panic, wasmer_call_trampoline, Export, ExportFunction,
-    ExportFunctionMetadata, ImportInitializerFuncPtr, TableElement, VMCallerCheckedAnyfunc,
-    VMDynamicFunctionContext, VMFuncRef, VMFunction, VMFunctionBody, VMFunctionEnvironment,
-    VMFunctionKind, VMTrampoline,
+    catch_traps_with_result, defer_externref_drop, raise_exception, raise_user_trap, resume_panic,
+    wasmer_call_trampoline, Export, ExportFunction, ExportFunctionMetadata,
+    ImportInitializerFuncPtr, TableElement, VMCallerCheckedAnyfunc, VMDynamicFunctionContext,
+    VMFuncRef, VMFunction, VMFunctionBody, VMFunctionEnvironment, VMFunctionKind, VMTrampoline,
 };
 
 /// A WebAssembly `function` instance.
@@ -29,12 +35,6 @@ use wasmer_vm::{
 /// during execution of the function.
 ///
 /// Spec: <https://webassembly.github.io/spec/core/exec/runtime.html#function-instances>
-///
-/// # Panics
-/// - Closures (functions with captured environments) are not currently supported
-///   with native functions. Attempting to create a native `Function` with one will
-///   result in a panic.
-///   [Closures as host functions tracking issue](https://github.com/wasmerio/wasmer/issues/1840)
 #[derive(PartialEq)]
 pub struct Function {
     pub(crate) store: Store,
@@ -65,6 +65,34 @@ impl wasmer_types::WasmValueType for Function {
     }
 }
 
+/// Raise the error a host function returned: an uncaught wasm exception is thrown into the
+/// guest, which may catch it, and any other error traps.
+///
+/// # Safety
+///
+/// Same as `raise_user_trap`.
+pub(crate) unsafe fn raise_host_error(error: Box<dyn std::error::Error + Send + Sync>) -> ! {
+    let exception = error
+        .downcast_ref::<RuntimeError>()
+        .and_then(|error| error.exception().cloned());
+    match exception {
+        Some(exception) => {
+            drop(error);
+            raise_exception(exception)
+        }
+        None => raise_user_trap(error),
+    }
+}
+
+/// Write `value` to `p` for the current call into Wasm, which keeps the reference of an
+/// `externref` alive until it returns.
+pub(crate) unsafe fn write_value_for_call(value: &Val, p: *mut i128) {
+    value.write_value_to(p);
+    if let Val::ExternRef(_) = value {
+        defer_externref_drop(std::ptr::read(p as *const ExternRef));
+    }
+}
+
 impl Function {
     /// Convert a `VMFuncRef` into a `Function`.
     ///
@@ -195,6 +223,36 @@ impl Function {
         Self::new_with_env(store, ty, WithoutEnv, wrapped_func)
     }
 
+    /// Creates a new host `Function` (dynamic) with the provided signature, whose results are
+    /// given by the future `func` returns.
+    ///
+    /// The function can only be called from an async call (see [`Function::call_async`]),
+    /// which is suspended, Wasm frames included, while the future is pending, and resumed
+    /// on the same stack once the future is ready. Calling it otherwise fails with a
+    /// [`RuntimeError`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use wasmer::{Function, FunctionType, Type, Store, Value};
+    /// # let store = Store::default();
+    /// #
+    /// let signature = FunctionType::new(vec![Type::I32], vec![Type::I32]);
+    ///
+    /// let f = Function::new_async(&store, &signature, |args| async move {
+    ///     Ok(vec![Value::I32(args[0].unwrap_i32() + 1)])
+    /// });
+    /// ```
+    #[cfg(unix)]
+    pub fn new_async<FT, F, Fut>(store: &Store, ty: FT, func: F) -> Self
+    where
+        FT: Into<FunctionType>,
+        F: Fn(Vec<Val>) -> Fut + 'static + Send + Sync,
+        Fut: Future<Output = Result<Vec<Val>, RuntimeError>> + Send,
+    {
+        Self::new(store, ty, move |args| block_on(func(args.to_vec()))?)
+    }
+
     /// Creates a new host `Function` (dynamic) with the provided signature and environment.
     ///
     /// If you know the signature of the host function at compile time,
@@ -298,6 +356,11 @@ impl Function {
     /// The function signature is automatically retrieved using the
     /// Rust typing system.
     ///
+    /// Wasm calls the function with its arguments as they are, without
+    /// converting them to [`Val`]s. A closure capturing its environment is
+    /// called through its environment, which is shared by the instances
+    /// importing the function.
+    ///
     /// # Example
     ///
     /// ```
@@ -309,16 +372,29 @@ impl Function {
     /// }
     ///
     /// let f = Function::new_native(&store, sum);
+    ///
+    /// let offset = 10;
+    /// let g = Function::new_native(&store, move |a: i32| a + offset);
     /// ```
     pub fn new_native<F, Args, Rets, Env>(store: &Store, func: F) -> Self
     where
-        F: HostFunction<Args, Rets, WithoutEnv, Env>,
+        F: HostFunction<Args, Rets, WithoutEnv, Env> + Send + Sync + 'static,
         Args: WasmTypeList,
         Rets: WasmTypeList,
         Env: Sized + 'static,
     {
         if std::mem::size_of::<F>() != 0 {
-            Self::closures_unsupported_panic();
+            let address = func.closure_body_ptr();
+            let env = ClosureEnv {
+                env: (),
+                func: Arc::new(func),
+            };
+            return Self::new_native_closure::<Args, Rets, _, _>(
+                store,
+                address,
+                env,
+                |_, _| Ok(()),
+            );
         }
         let function = inner::Function::<Args, Rets>::new(func);
         let address = function.address() as *const VMFunctionBody;
@@ -374,13 +450,23 @@ impl Function {
     /// ```
     pub fn new_native_with_env<F, Args, Rets, Env>(store: &Store, env: Env, func: F) -> Self
     where
-        F: HostFunction<Args, Rets, WithEnv, Env>,
+        F: HostFunction<Args, Rets, WithEnv, Env> + Send + Sync + 'static,
         Args: WasmTypeList,
         Rets: WasmTypeList,
         Env: Sized + WasmerEnv + 'static,
     {
         if std::mem::size_of::<F>() != 0 {
-            Self::closures_unsupported_panic();
+            let address = func.closure_body_ptr();
+            let env = ClosureEnv {
+                env,
+                func: Arc::new(func),
+            };
+            return Self::new_native_closure::<Args, Rets, _, _>(
+                store,
+                address,
+                env,
+                |env, instance| Env::init_with_instance(&mut env.env, instance),
+            );
         }
         let function = inner::Function::<Args, Rets>::new(func);
         let address = function.address();
@@ -406,6 +492,43 @@ impl Function {
         }
     }
 
+    /// Creates a new host `Function` from the `closure_wrapper` of a closure, at `address`,
+    /// which Wasm calls with `env` as its environment.
+    fn new_native_closure<Args, Rets, Env, F>(
+        store: &Store,
+        address: *const VMFunctionBody,
+        env: ClosureEnv<Env, F>,
+        import_init_function_ptr: for<'a> fn(
+            &'a mut ClosureEnv<Env, F>,
+            &'a crate::Instance,
+        ) -> Result<(), crate::HostEnvInitError>,
+    ) -> Self
+    where
+        Args: WasmTypeList,
+        Rets: WasmTypeList,
+        Env: Clone + Send + Sync + 'static,
+        F: Send + Sync + 'static,
+    {
+        let (host_env, metadata) = build_export_function_metadata(env, import_init_function_ptr);
+        let vmctx = VMFunctionEnvironment { host_env };
+        let ty = FunctionType::new(Args::wasm_types(), Rets::wasm_types());
+        let signature = store.engine().register_signature((&ty).into());
+        Self {
+            store: store.clone(),
+            exported: ExportFunction {
+                metadata: Some(Arc::new(metadata)),
+                vm_function: VMFunction {
+                    address,
+                    kind: VMFunctionKind::Static,
+                    vmctx,
+                    signature,
+                    call_trampoline: None,
+                    instance_ref: None,
+                },
+            },
+        }
+    }
+
     /// Returns the [`FunctionType`] of the `Function`.
     ///
     /// # Example
@@ -464,11 +587,7 @@ impl Function {
             )));
         }
 
-        let mut values_vec = vec![0; max(params.len(), results.len())];
-
-        // Store the argument values into `values_vec`.
-        let param_tys = signature.params().iter();
-        for ((arg, slot), ty) in params.iter().zip(&mut values_vec).zip(param_tys) {
+        for (arg, ty) in params.iter().zip(signature.params()) {
             if arg.ty() != *ty {
                 let param_types = format_types_for_error_message(params);
                 return Err(RuntimeError::new(format!(
@@ -476,32 +595,39 @@ impl Function {
                     param_types, &signature,
                 )));
             }
-            unsafe {
-                arg.write_value_to(slot);
-            }
         }
 
-        // Call the trampoline.
-        if let Err(error) = unsafe {
-            wasmer_call_trampoline(
-                self.exported.vm_function.vmctx,
-                trampoline,
-                self.exported.vm_function.address,
-                values_vec.as_mut_ptr() as *mut u8,
-            )
-        } {
-            return Err(RuntimeError::from_trap(error));
-        }
+        let mut values_vec = vec![0; max(params.len(), results.len())];
 
-        // Load the return values out of `values_vec`.
-        for (index, &value_type) in signature.results().iter().enumerate() {
-            unsafe {
-                let ptr = values_vec.as_ptr().add(index);
-                results[index] = Val::read_value_from(&self.store, ptr, value_type);
-            }
-        }
+        // The call only keeps the references it is passed and returns alive until it returns,
+        // so the return values are read within it.
+        let outcome = unsafe {
+            catch_traps_with_result(|| {
+                // Store the argument values into `values_vec`.
+                for (arg, slot) in params.iter().zip(&mut values_vec) {
+                    write_value_for_call(arg, slot);
+                }
 
-        Ok(())
+                // Call the trampoline.
+                wasmer_call_trampoline(
+                    self.exported.vm_function.vmctx,
+                    trampoline,
+                    self.exported.vm_function.address,
+                    values_vec.as_mut_ptr() as *mut u8,
+                )?;
+
+                // Load the return values out of `values_vec`.
+                for (index, &value_type) in signature.results().iter().enumerate() {
+                    let ptr = values_vec.as_ptr().add(index);
+                    results[index] = Val::read_value_from(&self.store, ptr, value_type);
+                }
+                Ok(())
+            })
+        };
+        outcome.and_then(|result| result).map_err(|error| {
+            let instance = self.exported.vm_function.instance_id();
+            self.store.runtime_error(error, instance)
+        })
     }
 
     /// Returns the number of parameters that this function takes.
@@ -574,8 +700,20 @@ impl Function {
     /// assert_eq!(sum.call(&[Value::I32(1), Value::I32(2)]).unwrap().to_vec(), vec![Value::I32(3)]);
     /// ```
     pub fn call(&self, params: &[Val]) -> Result<Box<[Val]>, RuntimeError> {
+        if self.exported.vm_function.is_instance_closed() {
+            return Err(RuntimeError::instance_closed());
+        }
+        if self.exported.vm_function.is_instance_suspended() {
+            return Err(RuntimeError::instance_suspended());
+        }
+        let _entry = self
+            .exported
+            .vm_function
+            .enter_instance()
+            .ok_or_else(RuntimeError::concurrent_reentry)?;
         // If it's a function defined in the Wasm, it will always have a call_trampoline
         if let Some(trampoline) = self.exported.vm_function.call_trampoline {
+            self.exported.vm_function.record_host_call();
             let mut results = vec![Val::null(); self.result_arity()];
             self.call_wasm(trampoline, params, &mut results)?;
             return Ok(results.into_boxed_slice());
@@ -596,6 +734,25 @@ impl Function {
         }
     }
 
+    /// Call the `Function` from a future, so that the host functions created with
+    /// [`Function::new_async`] can await futures of their own.
+    ///
+    /// The call runs on a stack of its own (see `Store::async_stack_size`). When a host
+    /// function awaits a pending future, the call is suspended and the returned future is
+    /// pending; polling it again resumes the call where it stopped. While the call is
+    /// suspended, calling the functions of its instance from the host fails with
+    /// [`RuntimeError::instance_suspended`].
+    #[cfg(unix)]
+    pub fn call_async(&self, params: &[Val]) -> AsyncCall<Box<[Val]>> {
+        let function = self.clone();
+        let params = params.to_vec();
+        AsyncCall::new(
+            &self.store,
+            self.exported.vm_function.instance_ref.clone(),
+            move || function.call(&params),
+        )
+    }
+
     pub(crate) fn from_vm_export(store: &Store, wasmer_export: ExportFunction) -> Self {
         Self {
             store: store.clone(),
@@ -697,32 +854,17 @@ impl Function {
             .lookup_signature(self.exported.vm_function.signature)
             .expect("Could not resolve VMSharedSignatureIndex! Wrong engine?");
         // type check
-        let expected = signature.params();
-        let given = Args::wasm_types();
-        if expected != given {
+        if signature.params() != Args::wasm_types() || signature.results() != Rets::wasm_types() {
             return Err(RuntimeError::new(format!(
-                "types (`{:?}`) for the function arguments don't match the actual types (`{:?}`)",
-                given, expected,
-            )));
-        }
-        let expected = signature.results();
-        let given = Rets::wasm_types();
-        if expected != given {
-            // todo: error result types don't match
-            return Err(RuntimeError::new(format!(
-                "types (`{:?}`) for the function results don't match the actual types (`{:?}`)",
-                given, expected,
+                "the requested signature `{}` doesn't match the signature `{}` of the function",
+                FunctionType::new(Args::wasm_types(), Rets::wasm_types()),
+                signature,
             )));
         }
 
         Ok(NativeFunc::new(self.store.clone(), self.exported.clone()))
     }
 
-    #[track_caller]
-    fn closures_unsupported_panic() -> ! {
-        unimplemented!("Closures (functions with captured environments) are currently unsupported with native functions. See: https://github.com/wasmerio/wasmer/issues/1840")
-    }
-
     /// Get access to the backing VM value for this extern. This function is for
     /// tests it should not be called by users of the Wasmer API.
     ///
@@ -740,6 +882,10 @@ impl<'a> Exportable<'a> for Function {
     fn to_export(&self) -> Export {
         self.exported.clone().into()
     }
+
+    fn into_weak_instance_ref(&mut self) {
+        self.exported.vm_function.downgrade_instance_ref();
+    }
 }
 
 impl Clone for Function {
@@ -854,16 +1000,14 @@ impl<T: VMDynamicFunction> VMDynamicFunctionCall<T> for VMDynamicFunctionContext
                 )));
             }
             for (i, ret) in returns.iter().enumerate() {
-                ret.write_value_to(values_vec.add(i));
+                write_value_for_call(ret, values_vec.add(i));
             }
             Ok(())
-        })); // We get extern ref drops at the end of this block that we don't need.
-             // By preventing extern ref incs in the code above we can save the work of
-             // incrementing and decrementing. However the logic as-is is correct.
+        }));
 
         match result {
             Ok(Ok(())) => {}
-            Ok(Err(trap)) => raise_user_trap(Box::new(trap)),
+            Ok(Err(trap)) => raise_host_error(Box::new(trap)),
             Err(panic) => resume_panic(panic),
         }
     }
@@ -877,8 +1021,9 @@ mod inner {
     use std::error::Error;
     use std::marker::PhantomData;
     use std::panic::{self, AssertUnwindSafe};
-    use wasmer_types::{FunctionType, NativeWasmType, Type};
-    use wasmer_vm::{raise_user_trap, resume_panic, VMFunctionBody};
+    use std::sync::Arc;
+    use wasmer_types::{ExternRef, FunctionType, NativeWasmType, Type, VMExternRef};
+    use wasmer_vm::{resume_panic, VMFunctionBody};
 
     /// A trait to convert a Rust value to a `WasmNativeType` value,
     /// or to convert `WasmNativeType` value to a Rust value.
@@ -969,6 +1114,30 @@ mod inner {
         f64 => f64
     );
 
+    /// Wasm code doesn't hold counts of the references it uses: the
+    /// reference read from Wasm is counted again, and the count of the
+    /// reference passed to Wasm is held by the current call into Wasm (see
+    /// [`wasmer_vm::defer_externref_drop`]) until it returns.
+    unsafe impl FromToNativeWasmType for Option<ExternRef> {
+        type Native = VMExternRef;
+
+        #[inline]
+        fn from_native(native: Self::Native) -> Self {
+            if native.is_null() {
+                None
+            } else {
+                Some(native.ref_clone().into())
+            }
+        }
+
+        #[inline]
+        fn to_native(self) -> Self::Native {
+            let native = self.map_or_else(VMExternRef::null, Into::into);
+            wasmer_vm::defer_externref_drop(native.into());
+            native
+        }
+    }
+
     #[cfg(test)]
     mod test_from_to_native_wasm_type {
         use super::*;
@@ -991,6 +1160,15 @@ mod inner {
             assert_eq!(7f32.to_native(), 7f32);
             assert_eq!(7f64.to_native(), 7f64);
         }
+
+        #[test]
+        fn test_extern_ref_round_trip() {
+            assert_eq!(None::<ExternRef>.to_native(), VMExternRef::null());
+            assert_eq!(Option::<ExternRef>::from_native(VMExternRef::null()), None);
+            let extern_ref = ExternRef::new(42u32);
+            let native = Some(extern_ref.clone()).to_native();
+            assert_eq!(Option::<ExternRef>::from_native(native), Some(extern_ref));
+        }
     }
 
     /// The `WasmTypeList` trait represents a tuple (list) of Wasm
@@ -1141,6 +1319,30 @@ mod inner {
     {
         /// Get the pointer to the function body.
         fn function_body_ptr(self) -> *const VMFunctionBody;
+
+        /// Get the pointer to the body of a function calling the host function kept in the
+        /// [`ClosureEnv`] it is passed, for the host functions capturing their environment.
+        #[doc(hidden)]
+        fn closure_body_ptr(&self) -> *const VMFunctionBody;
+    }
+
+    /// The environment of a host function capturing its environment: the function itself,
+    /// along with the environment it is given, if any.
+    ///
+    /// The function is shared by the clones of the environment made for each instance.
+    #[doc(hidden)]
+    pub struct ClosureEnv<Env, Func> {
+        pub(crate) env: Env,
+        pub(crate) func: Arc<Func>,
+    }
+
+    impl<Env: Clone, Func> Clone for ClosureEnv<Env, Func> {
+        fn clone(&self) -> Self {
+            Self {
+                env: self.env.clone(),
+                func: self.func.clone(),
+            }
+        }
     }
 
     /// Empty trait to specify the kind of `HostFunction`: With or
@@ -1331,13 +1533,40 @@ mod inner {
 
                         match result {
                             Ok(Ok(result)) => return result.into_c_struct(),
-                            Ok(Err(trap)) => unsafe { raise_user_trap(Box::new(trap)) },
+                            Ok(Err(trap)) => unsafe { super::raise_host_error(Box::new(trap)) },
                             Err(panic) => unsafe { resume_panic(panic) },
                         }
                     }
 
                     func_wrapper::< $( $x, )* Rets, RetsAsResult, Self > as *const VMFunctionBody
                 }
+
+                #[allow(non_snake_case)]
+                fn closure_body_ptr(&self) -> *const VMFunctionBody {
+                    /// This is a function that wraps the real host
+                    /// function, found in its environment. Its address
+                    /// will be used inside the runtime.
+                    extern fn closure_wrapper<$( $x, )* Rets, RetsAsResult, Func>( env: &ClosureEnv<(), Func>, $( $x: $x::Native, )* ) -> Rets::CStruct
+                    where
+                        $( $x: FromToNativeWasmType, )*
+                        Rets: WasmTypeList,
+                        RetsAsResult: IntoResult<Rets>,
+                        Func: Fn( $( $x ),* ) -> RetsAsResult + 'static
+                    {
+                        let func: &Func = &env.func;
+                        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                            func( $( FromToNativeWasmType::from_native($x) ),* ).into_result()
+                        }));
+
+                        match result {
+                            Ok(Ok(result)) => return result.into_c_struct(),
+                            Ok(Err(trap)) => unsafe { super::raise_host_error(Box::new(trap)) },
+                            Err(panic) => unsafe { resume_panic(panic) },
+                        }
+                    }
+
+                    closure_wrapper::< $( $x, )* Rets, RetsAsResult, Self > as *const VMFunctionBody
+                }
             }
 
             // Implement `HostFunction` for a function that has the same arity than the tuple.
@@ -1375,13 +1604,42 @@ mod inner {
 
                         match result {
                             Ok(Ok(result)) => return result.into_c_struct(),
-                            Ok(Err(trap)) => unsafe { raise_user_trap(Box::new(trap)) },
+                            Ok(Err(trap)) => unsafe { super::raise_host_error(Box::new(trap)) },
                             Err(panic) => unsafe { resume_panic(panic) },
                         }
                     }
 
                     func_wrapper::< $( $x, )* Rets, RetsAsResult, Env, Self > as *const VMFunctionBody
                 }
+
+                #[allow(non_snake_case)]
+                fn closure_body_ptr(&self) -> *const VMFunctionBody {
+                    /// This is a function that wraps the real host
+                    /// function, found in its environment along with
+                    /// the environment it is given. Its address will be
+                    /// used inside the runtime.
+                    extern fn closure_wrapper<$( $x, )* Rets, RetsAsResult, Env, Func>( env: &ClosureEnv<Env, Func>, $( $x: $x::Native, )* ) -> Rets::CStruct
+                    where
+                        $( $x: FromToNativeWasmType, )*
+                        Rets: WasmTypeList,
+                        RetsAsResult: IntoResult<Rets>,
+                        Env: Sized,
+                        Func: Fn(&Env, $( $x ),* ) -> RetsAsResult + 'static
+                    {
+                        let func: &Func = &env.func;
+                        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                            func(&env.env, $( FromToNativeWasmType::from_native($x) ),* ).into_result()
+                        }));
+
+                        match result {
+                            Ok(Ok(result)) => return result.into_c_struct(),
+                            Ok(Err(trap)) => unsafe { super::raise_host_error(Box::new(trap)) },
+                            Err(panic) => unsafe { resume_panic(panic) },
+                        }
+                    }
+
+                    closure_wrapper::< $( $x, )* Rets, RetsAsResult, Env, Self > as *const VMFunctionBody
+                }
             }
         };
     }
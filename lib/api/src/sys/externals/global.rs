@@ -169,10 +169,16 @@ impl Global {
     /// // This results in an error: `RuntimeError`.
     /// g.set(Value::I64(2)).unwrap();
     /// ```
+    ///
+    /// Setting the global of an instance fails too while another thread runs a call into it.
     pub fn set(&self, val: Val) -> Result<(), RuntimeError> {
         if !val.comes_from_same_store(&self.store) {
             return Err(RuntimeError::new("cross-`Store` values are not supported"));
         }
+        let _entry = self
+            .vm_global
+            .enter_instance()
+            .ok_or_else(RuntimeError::concurrent_reentry)?;
         unsafe {
             self.vm_global
                 .from
@@ -216,4 +222,8 @@ impl<'a> Exportable<'a> for Global {
     fn to_export(&self) -> Export {
         self.vm_global.clone().into()
     }
+
+    fn into_weak_instance_ref(&mut self) {
+        self.vm_global.downgrade_instance_ref();
+    }
 }
@@ -1,10 +1,33 @@
 use crate::sys::exports::Exportable;
+use crate::sys::mem_access::{end_of, MemoryAccessError};
 use crate::sys::store::Store;
 use crate::sys::{MemoryType, MemoryView};
-use std::convert::TryInto;
-use std::slice;
+use std::convert::{TryFrom, TryInto};
+use std::ops::Range;
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::{mem, ptr, slice};
 use wasmer_types::{Pages, ValueType};
-use wasmer_vm::{Export, MemoryError, VMMemory};
+use wasmer_vm::{Export, LimitedMemory, MemoryError, MemoryGrow, VMMemory};
+
+/// Implements `Memory` methods reading and writing numbers in the little-endian order of Wasm.
+macro_rules! little_endian_accessors {
+    ($($read:ident, $write:ident: $ty:ty;)*) => {
+        $(
+            #[doc = concat!("Reads the `", stringify!($ty), "` at `offset`.")]
+            pub fn $read(&self, offset: u64) -> Result<$ty, MemoryAccessError> {
+                let mut bytes = [0; mem::size_of::<$ty>()];
+                self.read(offset, &mut bytes)?;
+                Ok(<$ty>::from_le_bytes(bytes))
+            }
+
+            #[doc = concat!("Writes `value`, a `", stringify!($ty), "`, at `offset`.")]
+            pub fn $write(&self, offset: u64, value: $ty) -> Result<(), MemoryAccessError> {
+                self.write(offset, &value.to_le_bytes())
+            }
+        )*
+    };
+}
 
 /// A WebAssembly `memory` instance.
 ///
@@ -43,7 +66,10 @@ impl Memory {
     pub fn new(store: &Store, ty: MemoryType) -> Result<Self, MemoryError> {
         let tunables = store.tunables();
         let style = tunables.memory_style(&ty);
-        let memory = tunables.create_host_memory(&ty, &style)?;
+        let mut memory = tunables.create_host_memory(&ty, &style)?;
+        if let Some(limiter) = tunables.resource_limiter() {
+            memory = Arc::new(LimitedMemory::new(memory, limiter)?);
+        }
 
         Ok(Self {
             store: store.clone(),
@@ -154,6 +180,53 @@ impl Memory {
         self.vm_memory.from.size()
     }
 
+    /// Grow memory by the specified amount of WebAssembly [`Pages`] and return the previous
+    /// memory size.
+    ///
+    /// This fails as `memory.grow` would, when the memory would grow past its maximum, or when
+    /// the memory grow callback of the store's tunables vetoes it. It also fails with
+    /// [`MemoryError::ConcurrentReentry`] when the memory isn't shared and another thread runs
+    /// a call into the instance defining it.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use wasmer::{Memory, MemoryType, Pages, Store, Type, Value};
+    /// # let store = Store::default();
+    /// #
+    /// let m = Memory::new(&store, MemoryType::new(1, Some(3), false)).unwrap();
+    /// let p = m.grow(Pages(2)).unwrap();
+    ///
+    /// assert_eq!(p, Pages(1));
+    /// assert_eq!(m.size(), Pages(3));
+    /// assert!(m.grow(Pages(1)).is_err());
+    /// ```
+    pub fn grow(&self, delta: Pages) -> Result<Pages, MemoryError> {
+        let _entry = self
+            .vm_memory
+            .enter_instance()
+            .ok_or(MemoryError::ConcurrentReentry)?;
+        let callback = match self.store.tunables().memory_grow_callback() {
+            Some(callback) => callback,
+            None => return self.vm_memory.from.grow(delta),
+        };
+        let instance = self
+            .vm_memory
+            .instance_ref
+            .as_ref()
+            .and_then(|instance_ref| instance_ref.id());
+        self.vm_memory
+            .from
+            .grow_checked(delta, &mut |previous, new, result| {
+                callback(&MemoryGrow {
+                    instance,
+                    previous,
+                    new,
+                    result,
+                })
+            })
+    }
+
     /// Return a "view" of the currently accessible memory. By
     /// default, the view is unsynchronized, using regular memory
     /// accesses. You can force a memory view to use atomic accesses
@@ -166,6 +239,13 @@ impl Memory {
     /// Therefore, if this memory is shared between multiple threads, a single memory
     /// location can be mutated concurrently without synchronization.
     ///
+    /// The view covers the memory as it is when the view is created, and growing the memory
+    /// may move it elsewhere, so a view must not be used after the memory grows, including
+    /// from a call into Wasm. [`Memory::read`], [`Memory::write`] and [`WasmSlice`] look up
+    /// the memory on each access instead.
+    ///
+    /// [`WasmSlice`]: crate::WasmSlice
+    ///
     /// # Usage:
     ///
     /// ```
@@ -193,6 +273,194 @@ impl Memory {
         unsafe { MemoryView::new(base as _, length as u32) }
     }
 
+    /// Runs `f` on a pointer to the `len` bytes at `offset`, checking that they are within the
+    /// current size of the memory and keeping the memory from growing meanwhile.
+    fn access<R>(
+        &self,
+        offset: u64,
+        len: usize,
+        f: impl FnOnce(*mut u8) -> R,
+    ) -> Result<R, MemoryAccessError> {
+        self.access_ranges(&[(offset, len)], |base| {
+            f(unsafe { base.add(offset as usize) })
+        })
+    }
+
+    /// Runs `f` on a pointer to the start of the memory, checking that the `ranges`, offsets
+    /// and lengths in bytes, are within its current size and keeping it from growing meanwhile.
+    fn access_ranges<R>(
+        &self,
+        ranges: &[(u64, usize)],
+        f: impl FnOnce(*mut u8) -> R,
+    ) -> Result<R, MemoryAccessError> {
+        let mut end = 0;
+        for &(offset, len) in ranges {
+            end = end.max(end_of::<u8>(offset, len as u64)?);
+        }
+        let mut f = Some(f);
+        let mut result = Err(MemoryAccessError::HeapOutOfBounds);
+        self.vm_memory.from.with_definition(&mut |definition| {
+            if end <= definition.current_length as u64 {
+                let f = f.take().unwrap();
+                result = Ok(f(definition.base));
+            }
+        });
+        result
+    }
+
+    /// Whether the memory is shared, and so may be accessed by the threads running Wasm
+    /// while the host accesses it.
+    fn is_shared(&self) -> bool {
+        self.vm_memory.from.ty().shared
+    }
+
+    /// Copies the bytes at `offset` in the memory into `buf`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use wasmer::{Memory, MemoryAccessError, MemoryType, Store};
+    /// # let store = Store::default();
+    /// let m = Memory::new(&store, MemoryType::new(1, None, false)).unwrap();
+    /// m.write(0x100, b"bytes").unwrap();
+    ///
+    /// let mut buf = [0; 5];
+    /// m.read(0x100, &mut buf).unwrap();
+    /// assert_eq!(&buf, b"bytes");
+    /// assert_eq!(m.read(0xfffe, &mut buf), Err(MemoryAccessError::HeapOutOfBounds));
+    /// ```
+    pub fn read(&self, offset: u64, buf: &mut [u8]) -> Result<(), MemoryAccessError> {
+        let shared = self.is_shared();
+        self.access(offset, buf.len(), |src| unsafe {
+            copy_bytes(shared, src, buf.as_mut_ptr(), buf.len())
+        })
+    }
+
+    /// Copies `data` into the memory at `offset`.
+    pub fn write(&self, offset: u64, data: &[u8]) -> Result<(), MemoryAccessError> {
+        self.copy_from_slice(offset, data)
+    }
+
+    /// Copies the bytes of `range` in the memory into a new `Vec`.
+    ///
+    /// Nothing is allocated unless the whole range is within the memory. A range ending
+    /// before it starts is out of bounds.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use wasmer::{Memory, MemoryAccessError, MemoryType, Store};
+    /// # let store = Store::default();
+    /// let m = Memory::new(&store, MemoryType::new(1, None, false)).unwrap();
+    /// m.write(0x100, b"bytes").unwrap();
+    ///
+    /// assert_eq!(m.copy_to_vec(0x100..0x105).unwrap(), b"bytes");
+    /// assert_eq!(m.copy_to_vec(0xfffe..0x10003), Err(MemoryAccessError::HeapOutOfBounds));
+    /// ```
+    pub fn copy_to_vec(&self, range: Range<u64>) -> Result<Vec<u8>, MemoryAccessError> {
+        let len = range
+            .end
+            .checked_sub(range.start)
+            .ok_or(MemoryAccessError::HeapOutOfBounds)?;
+        let len = usize::try_from(len).map_err(|_| MemoryAccessError::Overflow)?;
+        let shared = self.is_shared();
+        self.access(range.start, len, |src| unsafe {
+            let mut bytes = Vec::with_capacity(len);
+            copy_bytes(shared, src, bytes.as_mut_ptr(), len);
+            bytes.set_len(len);
+            bytes
+        })
+    }
+
+    /// Copies `data` into the memory at `offset`, writing nothing unless it all fits.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use wasmer::{Memory, MemoryAccessError, MemoryType, Store};
+    /// # let store = Store::default();
+    /// let m = Memory::new(&store, MemoryType::new(1, None, false)).unwrap();
+    /// m.copy_from_slice(0x100, b"bytes").unwrap();
+    ///
+    /// assert_eq!(m.read_u8(0x100).unwrap(), b'b');
+    /// assert_eq!(m.copy_from_slice(0xfffe, b"bytes"), Err(MemoryAccessError::HeapOutOfBounds));
+    /// ```
+    pub fn copy_from_slice(&self, offset: u64, data: &[u8]) -> Result<(), MemoryAccessError> {
+        let shared = self.is_shared();
+        self.access(offset, data.len(), |dst| unsafe {
+            copy_bytes(shared, data.as_ptr(), dst, data.len())
+        })
+    }
+
+    /// Copies the `len` bytes at `src` in the memory to `dst`, as `memory.copy` does: the
+    /// ranges may overlap, and nothing is copied unless both are within the memory.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use wasmer::{Memory, MemoryAccessError, MemoryType, Store};
+    /// # let store = Store::default();
+    /// let m = Memory::new(&store, MemoryType::new(1, None, false)).unwrap();
+    /// m.write(0x100, b"bytes").unwrap();
+    ///
+    /// m.copy_within(0x100, 0x102, 5).unwrap();
+    /// assert_eq!(m.copy_to_vec(0x100..0x107).unwrap(), b"bybytes");
+    /// assert_eq!(m.copy_within(0x100, 0xfffe, 5), Err(MemoryAccessError::HeapOutOfBounds));
+    /// ```
+    pub fn copy_within(&self, src: u64, dst: u64, len: u64) -> Result<(), MemoryAccessError> {
+        let len = usize::try_from(len).map_err(|_| MemoryAccessError::Overflow)?;
+        let shared = self.is_shared();
+        self.access_ranges(&[(src, len), (dst, len)], |base| unsafe {
+            copy_bytes(shared, base.add(src as usize), base.add(dst as usize), len)
+        })
+    }
+
+    /// Reads the value at `offset`, in the representation of the host.
+    pub(crate) fn read_value<T: ValueType>(&self, offset: u64) -> Result<T, MemoryAccessError> {
+        // Any bit pattern is a valid `T`.
+        let mut value: T = unsafe { mem::zeroed() };
+        let shared = self.is_shared();
+        self.access(offset, mem::size_of::<T>(), |src| unsafe {
+            copy_bytes(
+                shared,
+                src,
+                &mut value as *mut T as *mut u8,
+                mem::size_of::<T>(),
+            )
+        })?;
+        Ok(value)
+    }
+
+    /// Writes `value` at `offset`, in the representation of the host.
+    pub(crate) fn write_value<T: ValueType>(
+        &self,
+        offset: u64,
+        value: T,
+    ) -> Result<(), MemoryAccessError> {
+        let shared = self.is_shared();
+        self.access(offset, mem::size_of::<T>(), |dst| unsafe {
+            copy_bytes(
+                shared,
+                &value as *const T as *const u8,
+                dst,
+                mem::size_of::<T>(),
+            )
+        })
+    }
+
+    little_endian_accessors! {
+        read_u8, write_u8: u8;
+        read_i8, write_i8: i8;
+        read_u16, write_u16: u16;
+        read_i16, write_i16: i16;
+        read_u32, write_u32: u32;
+        read_i32, write_i32: i32;
+        read_u64, write_u64: u64;
+        read_i64, write_i64: i64;
+        read_f32, write_f32: f32;
+        read_f64, write_f64: f64;
+    }
+
     pub(crate) fn from_vm_export(store: &Store, vm_memory: VMMemory) -> Self {
         Self {
             store: store.clone(),
@@ -229,4 +497,58 @@ impl<'a> Exportable<'a> for Memory {
     fn to_export(&self) -> Export {
         self.vm_memory.clone().into()
     }
+
+    fn into_weak_instance_ref(&mut self) {
+        self.vm_memory.downgrade_instance_ref();
+    }
+}
+
+/// Copies `len` bytes from `src` to `dst`, which may overlap. The copy from or to a `shared`
+/// memory is made of atomic accesses, as the threads running Wasm may access it meanwhile.
+///
+/// # Safety
+///
+/// Both ranges must be valid for accesses.
+unsafe fn copy_bytes(shared: bool, src: *const u8, dst: *mut u8, len: usize) {
+    if shared {
+        atomic_copy(src, dst, len)
+    } else {
+        ptr::copy(src, dst, len)
+    }
+}
+
+/// Copies `len` bytes from `src` to `dst`, which may overlap, with relaxed atomic accesses,
+/// a word at a time where both are aligned alike.
+///
+/// # Safety
+///
+/// Both ranges must be valid for accesses, atomic ones included.
+unsafe fn atomic_copy(src: *const u8, dst: *mut u8, len: usize) {
+    const WORD: usize = mem::size_of::<usize>();
+    let copy_byte = |i: usize| {
+        let byte = (*(src.add(i) as *const AtomicU8)).load(Ordering::Relaxed);
+        (*(dst.add(i) as *const AtomicU8)).store(byte, Ordering::Relaxed);
+    };
+    let copy_word = |i: usize| {
+        let word = (*(src.add(i) as *const AtomicUsize)).load(Ordering::Relaxed);
+        (*(dst.add(i) as *const AtomicUsize)).store(word, Ordering::Relaxed);
+    };
+    // The bytes before the first aligned word, and the start of the bytes after the last one.
+    let (head, tail) = if src as usize % WORD == dst as usize % WORD {
+        let head = ((WORD - src as usize % WORD) % WORD).min(len);
+        (head, head + (len - head) / WORD * WORD)
+    } else {
+        (len, len)
+    };
+    // Copying backwards when the destination is after the source keeps overlapping bytes
+    // from being overwritten before they are copied.
+    if (dst as usize) <= (src as usize) {
+        (0..head).for_each(copy_byte);
+        (head..tail).step_by(WORD).for_each(copy_word);
+        (tail..len).for_each(copy_byte);
+    } else {
+        (tail..len).rev().for_each(copy_byte);
+        (head..tail).step_by(WORD).rev().for_each(copy_word);
+        (0..head).rev().for_each(copy_byte);
+    }
 }
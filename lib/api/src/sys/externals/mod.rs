@@ -2,6 +2,7 @@ pub(crate) mod function;
 mod global;
 mod memory;
 mod table;
+mod tag;
 
 pub use self::function::{
     FromToNativeWasmType, Function, HostFunction, WasmTypeList, WithEnv, WithoutEnv,
@@ -10,9 +11,11 @@ pub use self::function::{
 pub use self::global::Global;
 pub use self::memory::Memory;
 pub use self::table::Table;
+pub use self::tag::Tag;
 
 use crate::sys::exports::Exportable;
 use crate::sys::store::{Store, StoreObject};
+use crate::sys::types::ExternType;
 use std::fmt;
 use wasmer_vm::Export;
 
@@ -30,6 +33,8 @@ pub enum Extern {
     Table(Table),
     /// A external [`Memory`].
     Memory(Memory),
+    /// A external [`Tag`].
+    Tag(Tag),
 }
 
 impl Extern {
@@ -40,6 +45,18 @@ impl Extern {
             Export::Memory(m) => Self::Memory(Memory::from_vm_export(store, m)),
             Export::Global(g) => Self::Global(Global::from_vm_export(store, g)),
             Export::Table(t) => Self::Table(Table::from_vm_export(store, t)),
+            Export::Tag(t) => Self::Tag(Tag::from_vm_export(store, t)),
+        }
+    }
+
+    /// Return the type of this `Extern`.
+    pub fn ty(&self) -> ExternType {
+        match self {
+            Self::Function(f) => ExternType::Function(f.ty()),
+            Self::Global(g) => ExternType::Global(*g.ty()),
+            Self::Table(t) => ExternType::Table(*t.ty()),
+            Self::Memory(m) => ExternType::Memory(m.ty()),
+            Self::Tag(t) => ExternType::Tag(t.ty().clone()),
         }
     }
 }
@@ -51,6 +68,17 @@ impl<'a> Exportable<'a> for Extern {
             Self::Global(g) => g.to_export(),
             Self::Memory(m) => m.to_export(),
             Self::Table(t) => t.to_export(),
+            Self::Tag(t) => t.to_export(),
+        }
+    }
+
+    fn into_weak_instance_ref(&mut self) {
+        match self {
+            Self::Function(f) => f.into_weak_instance_ref(),
+            Self::Global(g) => g.into_weak_instance_ref(),
+            Self::Memory(m) => m.into_weak_instance_ref(),
+            Self::Table(t) => t.into_weak_instance_ref(),
+            Self::Tag(t) => t.into_weak_instance_ref(),
         }
     }
 }
@@ -62,6 +90,7 @@ impl StoreObject for Extern {
             Self::Global(g) => g.store(),
             Self::Memory(m) => m.store(),
             Self::Table(t) => t.store(),
+            Self::Tag(t) => t.store(),
         };
         Store::same(my_store, store)
     }
@@ -77,6 +106,7 @@ impl fmt::Debug for Extern {
                 Self::Global(_) => "Global(...)",
                 Self::Memory(_) => "Memory(...)",
                 Self::Table(_) => "Table(...)",
+                Self::Tag(_) => "Tag(...)",
             }
         )
     }
@@ -105,3 +135,9 @@ impl From<Table> for Extern {
         Self::Table(r)
     }
 }
+
+impl From<Tag> for Extern {
+    fn from(r: Tag) -> Self {
+        Self::Tag(r)
+    }
+}
@@ -4,7 +4,10 @@ use crate::sys::types::{Val, ValFuncRef};
 use crate::sys::RuntimeError;
 use crate::sys::TableType;
 use std::sync::Arc;
-use wasmer_vm::{Export, Table as RuntimeTable, TableElement, VMTable};
+use wasmer_vm::{
+    Export, InstanceEntry, LimitedTable, Table as RuntimeTable, TableElement, Trap, TrapCode,
+    VMTable,
+};
 
 /// A WebAssembly `table` instance.
 ///
@@ -20,6 +23,19 @@ pub struct Table {
     vm_table: VMTable,
 }
 
+/// Convert `val` into an element of a table of type `ty`, checking that it is of the table's
+/// element type.
+fn table_element(store: &Store, ty: &TableType, val: &Val) -> Result<TableElement, RuntimeError> {
+    if val.ty() != ty.ty {
+        return Err(RuntimeError::new(format!(
+            "cannot store a value of type {} in a table of {}",
+            val.ty(),
+            ty.ty
+        )));
+    }
+    val.into_table_reference(store)
+}
+
 fn set_table_item(
     table: &dyn RuntimeTable,
     item_index: u32,
@@ -36,12 +52,15 @@ impl Table {
     /// This function will construct the `Table` using the store
     /// [`BaseTunables`][crate::sys::BaseTunables].
     pub fn new(store: &Store, ty: TableType, init: Val) -> Result<Self, RuntimeError> {
-        let item = init.into_table_reference(store)?;
+        let item = table_element(store, &ty, &init)?;
         let tunables = store.tunables();
         let style = tunables.table_style(&ty);
-        let table = tunables
+        let mut table = tunables
             .create_host_table(&ty, &style)
             .map_err(RuntimeError::new)?;
+        if let Some(limiter) = tunables.resource_limiter() {
+            table = Arc::new(LimitedTable::new(table, limiter).map_err(RuntimeError::new)?);
+        }
 
         let num_elements = table.size();
         for i in 0..num_elements {
@@ -72,6 +91,72 @@ impl Table {
         self.vm_table.from.size()
     }
 
+    /// Retrieves the element at the given index, or `None` if the index is out of bounds.
+    pub fn get(&self, index: u32) -> Option<Val> {
+        let item = self.vm_table.from.get(index)?;
+        Some(unsafe { ValFuncRef::from_table_reference(item, &self.store) })
+    }
+
+    /// Sets the element at the given index.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the index is out of bounds, if `val` is not of the table's element
+    /// type, or if another thread runs a call into the instance defining the table.
+    pub fn set(&self, index: u32, val: Val) -> Result<(), RuntimeError> {
+        let item = table_element(&self.store, self.ty(), &val)?;
+        let _entry = self.enter_instance()?;
+        set_table_item(self.vm_table.from.as_ref(), index, item)
+    }
+
+    /// Grows the size of the `Table` by `delta`, initializating the new elements to `init`, and
+    /// returns the previous size.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the table would grow past its maximum, if `init` is not of the
+    /// table's element type, or if another thread runs a call into the instance defining the
+    /// table.
+    pub fn grow(&self, delta: u32, init: Val) -> Result<u32, RuntimeError> {
+        let item = table_element(&self.store, self.ty(), &init)?;
+        let _entry = self.enter_instance()?;
+        self.vm_table
+            .from
+            .grow(delta, item)
+            .ok_or_else(|| RuntimeError::new(format!("failed to grow table by `{}`", delta)))
+    }
+
+    /// Sets the `len` elements starting at `start` to `val`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error, without setting any element, if the range is out of bounds, if `val`
+    /// is not of the table's element type, or if another thread runs a call into the instance
+    /// defining the table.
+    pub fn fill(&self, start: u32, len: u32, val: Val) -> Result<(), RuntimeError> {
+        let item = table_element(&self.store, self.ty(), &val)?;
+        let _entry = self.enter_instance()?;
+        let table = self.vm_table.from.as_ref();
+        if start
+            .checked_add(len)
+            .map_or(true, |end| end > table.size())
+        {
+            return Err(Trap::lib(TrapCode::TableAccessOutOfBounds).into());
+        }
+        for index in start..start + len {
+            set_table_item(table, index, item.clone())?;
+        }
+        Ok(())
+    }
+
+    /// Enter the instance defining the table for a change from the host, failing if another
+    /// thread runs a call into it.
+    fn enter_instance(&self) -> Result<InstanceEntry, RuntimeError> {
+        self.vm_table
+            .enter_instance()
+            .ok_or_else(RuntimeError::concurrent_reentry)
+    }
+
     pub(crate) fn from_vm_export(store: &Store, vm_table: VMTable) -> Self {
         Self {
             store: store.clone(),
@@ -113,4 +198,8 @@ impl<'a> Exportable<'a> for Table {
     fn to_export(&self) -> Export {
         self.vm_table.clone().into()
     }
+
+    fn into_weak_instance_ref(&mut self) {
+        self.vm_table.downgrade_instance_ref();
+    }
 }
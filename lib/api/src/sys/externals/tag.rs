@@ -0,0 +1,83 @@
+use crate::sys::exports::Exportable;
+use crate::sys::store::Store;
+use crate::sys::TagType;
+use std::fmt;
+use wasmer_vm::{Export, VMTag};
+
+/// A WebAssembly `tag` instance, of the exception handling proposal.
+///
+/// A tag identifies a kind of exception, and the types of the values its exceptions carry.
+/// Two tags of the same type are still different tags.
+///
+/// Spec: <https://webassembly.github.io/exception-handling/core/exec/runtime.html#tag-instances>
+#[derive(Clone)]
+pub struct Tag {
+    store: Store,
+    vm_tag: VMTag,
+}
+
+impl Tag {
+    /// Create a new `Tag` of the given [`TagType`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use wasmer::{Store, Tag, TagType, Type};
+    /// # let store = Store::default();
+    /// #
+    /// let t = Tag::new(&store, TagType::new(vec![Type::I32]));
+    ///
+    /// assert_eq!(t.ty().params(), &[Type::I32]);
+    /// ```
+    pub fn new(store: &Store, ty: TagType) -> Self {
+        Self {
+            store: store.clone(),
+            vm_tag: VMTag::new(ty),
+        }
+    }
+
+    /// Returns the [`TagType`] of the `Tag`.
+    pub fn ty(&self) -> &TagType {
+        self.vm_tag.ty()
+    }
+
+    /// Returns the [`Store`] where the `Tag` belongs.
+    pub fn store(&self) -> &Store {
+        &self.store
+    }
+
+    /// Returns whether or not these two tags are the same tag.
+    pub fn same(&self, other: &Self) -> bool {
+        self.vm_tag.same(&other.vm_tag)
+    }
+
+    pub(crate) fn from_vm_export(store: &Store, vm_tag: VMTag) -> Self {
+        Self {
+            store: store.clone(),
+            vm_tag,
+        }
+    }
+
+    pub(crate) fn vm_tag(&self) -> &VMTag {
+        &self.vm_tag
+    }
+}
+
+impl fmt::Debug for Tag {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter
+            .debug_struct("Tag")
+            .field("ty", &self.ty())
+            .finish()
+    }
+}
+
+impl<'a> Exportable<'a> for Tag {
+    fn to_export(&self) -> Export {
+        self.vm_tag.clone().into()
+    }
+
+    fn into_weak_instance_ref(&mut self) {
+        // Tags hold no reference to an instance.
+    }
+}
@@ -1,6 +1,7 @@
 //! The import module contains the implementation data structures and helper functions used to
 //! manipulate and access a wasm module's imports including memories, tables, globals, and
 //! functions.
+use crate::sys::Instance;
 use std::borrow::{Borrow, BorrowMut};
 use std::collections::VecDeque;
 use std::collections::{hash_map::Entry, HashMap};
@@ -20,24 +21,43 @@ pub trait LikeNamespace {
 
 /// All of the import data used when instantiating.
 ///
-/// It's suggested that you use the [`imports!`] macro
-/// instead of creating an `ImportObject` by hand.
+/// The [`imports!`] macro is the shortest way to build an `ImportObject`, but one can be built
+/// at runtime too, namespace by namespace. Import objects can be layered with
+/// [`ChainableNamedResolver`], the first resolver providing an import wins.
+///
+/// If imports are missing, instantiation fails with a [`LinkError::UnknownImports`] listing
+/// all of them.
 ///
 /// [`imports!`]: macro.imports.html
+/// [`ChainableNamedResolver`]: crate::ChainableNamedResolver
+/// [`LinkError::UnknownImports`]: crate::LinkError::UnknownImports
 ///
 /// # Usage:
-/// ```ignore
-/// use wasmer::{Exports, ImportObject, Function};
+/// ```
+/// use wasmer::{ChainableNamedResolver, Exports, Function, ImportObject, Store};
+/// # let store = Store::default();
 ///
 /// let mut import_object = ImportObject::new();
 /// let mut env = Exports::new();
 ///
-/// env.insert("foo", Function::new_native(foo));
+/// env.insert("foo", Function::new_native(&store, foo));
+/// env.insert("bar", Function::new_native(&store, foo));
 /// import_object.register("env", env);
 ///
+/// // Override `env.bar`, keeping `env.foo`.
+/// let mut overrides = ImportObject::new();
+/// let mut env = Exports::new();
+/// env.insert("bar", Function::new_native(&store, bar));
+/// overrides.register("env", env);
+/// let resolver = import_object.chain_front(overrides);
+///
 /// fn foo(n: i32) -> i32 {
 ///     n
 /// }
+///
+/// fn bar(n: i32) -> i32 {
+///     n + 1
+/// }
 /// ```
 #[derive(Clone, Default)]
 pub struct ImportObject {
@@ -50,13 +70,24 @@ impl ImportObject {
         Default::default()
     }
 
+    /// Create an `ImportObject` providing the exports of `instance` as the namespace `name`.
+    ///
+    /// The exports are imported as they are: memories, tables and globals are shared, and
+    /// functions are called directly, without going through the host. Instances importing
+    /// them keep `instance` alive.
+    pub fn from_instance_namespace<S: Into<String>>(name: S, instance: &Instance) -> Self {
+        let mut import_object = Self::new();
+        import_object.register(name, instance.clone());
+        import_object
+    }
+
     /// Gets an export given a module and a name
     ///
     /// # Usage
-    /// ```ignore
-    /// # use wasmer_vm::{ImportObject, Instance, Namespace};
-    /// let mut import_object = ImportObject::new();
-    /// import_object.get_export("module", "name");
+    /// ```
+    /// # use wasmer::ImportObject;
+    /// let import_object = ImportObject::new();
+    /// assert!(import_object.get_export("module", "name").is_none());
     /// ```
     pub fn get_export(&self, module: &str, name: &str) -> Option<Export> {
         let guard = self.map.lock().unwrap();
@@ -75,14 +106,21 @@ impl ImportObject {
 
     /// Register anything that implements `LikeNamespace` as a namespace.
     ///
+    /// Registering a namespace under a name that is already taken replaces it, and returns
+    /// the replaced namespace.
+    ///
     /// # Usage:
-    /// ```ignore
-    /// # use wasmer_vm::{ImportObject, Instance, Namespace};
+    /// ```
+    /// # use wasmer::{Exports, Global, ImportObject, Store, Value};
+    /// # let store = Store::default();
     /// let mut import_object = ImportObject::new();
+    /// let mut namespace = Exports::new();
+    /// namespace.insert("answer", Global::new(&store, Value::I32(42)));
     ///
-    /// import_object.register("namespace0", instance);
-    /// import_object.register("namespace1", namespace);
-    /// // ...
+    /// assert!(import_object.register("namespace", namespace).is_none());
+    /// assert!(import_object.get_export("namespace", "answer").is_some());
+    /// assert!(import_object.register("namespace", Exports::new()).is_some());
+    /// assert!(import_object.get_export("namespace", "answer").is_none());
     /// ```
     pub fn register<S, N>(&mut self, name: S, namespace: N) -> Option<Box<dyn LikeNamespace>>
     where
@@ -1,10 +1,18 @@
+use crate::sys::import_object::LikeNamespace;
 use crate::sys::module::Module;
 use crate::sys::{HostEnvInitError, LinkError, RuntimeError};
-use crate::{ExportError, NativeFunc, WasmTypeList};
+use crate::{
+    Export, ExportError, Extern, ExternType, Function, FunctionType, Global, Memory, Mutability,
+    NativeFunc, Table, Tag, WasmTypeList,
+};
+use std::fmt;
 use std::sync::{Arc, Mutex};
 use thiserror::Error;
-use wasmer_types::InstanceConfig;
-use wasmer_vm::{InstanceHandle, Resolver};
+use wasmer_types::{ExportIndex, InstanceConfig};
+use wasmer_vm::{
+    Artifact, EpochDeadlineAction, InstanceHandle, InstanceId, InstanceMetrics, InstancePreImage,
+    InstanceSnapshot, Resolver, WeakInstanceRef,
+};
 
 /// A WebAssembly Instance is a stateful, executable
 /// instance of a WebAssembly [`Module`].
@@ -13,6 +21,27 @@ use wasmer_vm::{InstanceHandle, Resolver};
 /// functions, memories, tables and globals that allow
 /// interacting with WebAssembly.
 ///
+/// Instances and their exports can be sent to and shared between threads, but a single
+/// thread at a time may run code in a given instance: calling one of its functions while
+/// another thread runs a call into it fails with [`RuntimeError::is_concurrent_reentry`].
+/// Growing its memories and tables, and setting its tables and globals, fail the same way.
+/// A call also runs in the instances that this one imports functions, tables, mutable
+/// globals and non-shared memories from, so it is refused while another thread runs in one
+/// of them too. Memories declared `shared` may still be accessed from several threads at
+/// once.
+///
+/// This exclusivity is checked when the calls are made rather than by the types: the
+/// handles are cheap to clone, and calls take `&self`. Some accesses aren't checked:
+///
+/// - the functions of an instance that this one neither defines nor imports, which its code
+///   may still call through a table set by the host or by another instance, run without
+///   entering their instance;
+/// - memories, tables and globals created by the host belong to no instance, so changing
+///   them is never refused;
+/// - reading or writing the bytes of a memory, and reading a table or a global, isn't
+///   refused either, and races with the thread running in the instance as the accesses to
+///   a shared memory would.
+///
 /// Spec: <https://webassembly.github.io/spec/core/exec/runtime.html#module-instances>
 #[derive(Clone)]
 pub struct Instance {
@@ -28,10 +57,63 @@ mod send_test {
         true
     }
 
+    fn is_send_sync<T: Send + Sync>() -> bool {
+        true
+    }
+
     #[test]
     fn instance_is_send() {
         assert!(is_send::<Instance>());
     }
+
+    #[test]
+    fn handles_are_send_and_sync() {
+        assert!(is_send_sync::<crate::Store>());
+        assert!(is_send_sync::<Module>());
+        assert!(is_send_sync::<Instance>());
+        assert!(is_send_sync::<Function>());
+        assert!(is_send_sync::<Memory>());
+        assert!(is_send_sync::<Table>());
+        assert!(is_send_sync::<Global>());
+        assert!(is_send::<NativeFunc<i32, i32>>());
+    }
+}
+
+/// A handle to the fuel of an instance, see [`Instance::fuel`].
+#[derive(Clone, Debug)]
+pub struct Fuel {
+    instance: WeakInstanceRef,
+}
+
+impl Fuel {
+    /// Return the fuel left, or 0 once the instance was freed.
+    pub fn remaining(&self) -> u64 {
+        self.instance
+            .upgrade()
+            .map_or(0, |instance| instance.fuel_remaining())
+    }
+
+    /// Set the fuel left, doing nothing once the instance was freed.
+    pub fn set(&self, fuel: u64) {
+        if let Some(instance) = self.instance.upgrade() {
+            instance.set_fuel(fuel);
+        }
+    }
+
+    /// Consume `amount` of fuel.
+    ///
+    /// When less is left, no fuel is consumed and this returns a [`RuntimeError`] with the
+    /// `TrapCode::OutOfFuel` trap code, which a host function can return to stop the
+    /// execution as metered code running out of fuel would.
+    pub fn consume(&self, amount: u64) -> Result<(), RuntimeError> {
+        let instance = self
+            .instance
+            .upgrade()
+            .ok_or_else(|| RuntimeError::new("the instance was freed"))?;
+        instance
+            .consume_fuel(amount)
+            .map_err(RuntimeError::from_trap)
+    }
 }
 
 /// An error while instantiating a module.
@@ -57,6 +139,10 @@ pub enum InstantiationError {
     #[error("missing requires CPU features: {0:?}")]
     CpuFeature(String),
 
+    /// The module was compiled for a target the current host can't run.
+    #[error("module compiled for another target: {0}")]
+    IncompatibleTarget(String),
+
     /// Error occurred when initializing the host environment.
     #[error(transparent)]
     HostEnvInitialization(HostEnvInitError),
@@ -68,6 +154,7 @@ impl From<wasmer_engine::InstantiationError> for InstantiationError {
             wasmer_engine::InstantiationError::Link(e) => Self::Link(e),
             wasmer_engine::InstantiationError::Start(e) => Self::Start(e),
             wasmer_engine::InstantiationError::CpuFeature(e) => Self::CpuFeature(e),
+            wasmer_engine::InstantiationError::IncompatibleTarget(e) => Self::IncompatibleTarget(e),
         }
     }
 }
@@ -78,13 +165,94 @@ impl From<HostEnvInitError> for InstantiationError {
     }
 }
 
+/// An error while creating an instance adopting the state of another one, see
+/// [`Instance::reinstantiate_with_config`].
+#[derive(Error, Debug)]
+pub enum ReinstantiateError {
+    /// Exports of the new module can't adopt the state of those of the instance. All of them
+    /// are listed, in the order the new module declares them.
+    #[error("incompatible exports: {}", display_incompatible_exports(.0))]
+    Incompatible(Vec<IncompatibleExport>),
+
+    /// Instantiating the new module failed.
+    #[error(transparent)]
+    Instantiation(InstantiationError),
+}
+
+impl From<InstantiationError> for ReinstantiateError {
+    fn from(other: InstantiationError) -> Self {
+        Self::Instantiation(other)
+    }
+}
+
+/// An export of a new module which can't adopt the state of the export of the same name of
+/// an instance.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IncompatibleExport {
+    /// The name of the export.
+    pub name: String,
+    /// The type of the export of the instance.
+    pub current: ExternType,
+    /// The type of the export of the new module.
+    pub new: ExternType,
+    /// Why the export of the new module can't adopt the state of the current one.
+    pub reason: String,
+}
+
+impl fmt::Display for IncompatibleExport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:?} ({} becoming {}): {}",
+            self.name, self.current, self.new, self.reason
+        )
+    }
+}
+
+fn display_incompatible_exports(exports: &[IncompatibleExport]) -> String {
+    exports
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// An error while taking a snapshot of an instance, or restoring it, see
+/// [`Instance::snapshot`].
+#[derive(Error, Debug)]
+pub enum SnapshotError {
+    /// The state of the instance holds references which can't be captured.
+    #[error("the state of the instance can't be captured: {0}")]
+    Capture(String),
+
+    /// The snapshot is of an instance of another module.
+    #[error("the snapshot is of an instance of another module")]
+    OtherModule,
+
+    /// The snapshot doesn't fit the definitions of the module.
+    #[error("the snapshot can't be restored: {0}")]
+    Restore(String),
+
+    /// Instantiating the module failed.
+    #[error(transparent)]
+    Instantiation(InstantiationError),
+}
+
+impl From<InstantiationError> for SnapshotError {
+    fn from(other: InstantiationError) -> Self {
+        Self::Instantiation(other)
+    }
+}
+
 impl Instance {
     /// Creates a new `Instance` from a WebAssembly [`Module`] and a
     /// set of imports resolved by the [`Resolver`].
     ///
     /// The resolver can be anything that implements the [`Resolver`] trait,
     /// so you can plug custom resolution for the imports, if you wish not
-    /// to use [`ImportObject`].
+    /// to use [`ImportObject`]. It is only asked for the imports the module
+    /// declares, along with their expected type, so it can create them on
+    /// demand.
     ///
     /// The [`ImportObject`] is the easiest way to provide imports to the instance.
     ///
@@ -123,15 +291,31 @@ impl Instance {
         config: InstanceConfig,
         resolver: &dyn Resolver,
     ) -> Result<Self, InstantiationError> {
-        unsafe {
-            if (*config.gas_counter).opcode_cost > i32::MAX as u64 {
-                // Fast gas counter logic assumes that individual opcode cost is not too big.
-                return Err(InstantiationError::HostEnvInitialization(
-                    HostEnvInitError::IncorrectGasMeteringConfig,
-                ));
-            }
-        }
-        let handle = module.instantiate(resolver, config)?;
+        Self::new_with_image(module, config, resolver, None)
+    }
+
+    /// New instance with config, starting from `image` instead of running the initializers of
+    /// the module if given.
+    pub(crate) fn new_with_image(
+        module: &Module,
+        config: InstanceConfig,
+        resolver: &dyn Resolver,
+        image: Option<&InstancePreImage>,
+    ) -> Result<Self, InstantiationError> {
+        check_config(&config)?;
+        let handle = module.instantiate(resolver, config, image)?;
+        // The image was captured after the start function ran.
+        Self::from_handle(module, handle, image.is_none())
+    }
+
+    /// Wrap the `handle` of a new instance of `module`, once its state is initialized, and
+    /// run its start function if `start`.
+    fn from_handle(
+        module: &Module,
+        handle: InstanceHandle,
+        start: bool,
+    ) -> Result<Self, InstantiationError> {
+        let instance_ref = handle.downgrade();
         let instance = Self {
             handle: Arc::new(Mutex::new(handle)),
             module: module.clone(),
@@ -152,21 +336,407 @@ impl Instance {
             )?;
         }
 
+        // The start function runs once the host environments are initialized, as the host
+        // functions it calls may use them, and without holding the lock on the handle, as
+        // they may use the instance too.
+        if start {
+            let instance_ref = instance_ref.upgrade().expect("the instance is alive");
+            let result = unsafe { instance_ref.invoke_start_function() };
+            drop(instance_ref);
+            if let Err(trap) = result {
+                // The host functions may have kept references to the instance, its functions
+                // can't be called from them anymore. Otherwise, it is freed before returning.
+                let error = module.store().runtime_error(trap, Some(instance.id()));
+                instance.close();
+                return Err(InstantiationError::Start(error));
+            }
+        }
+
+        Ok(instance)
+    }
+
+    /// Create an instance of `new_module` which adopts the state of this instance, and close
+    /// this instance.
+    ///
+    /// This is [`Instance::reinstantiate_with_config`] with the default configuration,
+    /// keeping the contents of the adopted memories verbatim.
+    pub fn reinstantiate_with(
+        &self,
+        new_module: &Module,
+        resolver: &dyn Resolver,
+    ) -> Result<Self, ReinstantiateError> {
+        self.reinstantiate_with_config(new_module, InstanceConfig::default(), resolver, false)
+    }
+
+    /// Create an instance of `new_module` which adopts the state of this instance, and close
+    /// this instance, to upgrade the code of a module without losing its state.
+    ///
+    /// The memories and the mutable globals `new_module` defines and exports adopt the
+    /// contents of the exports of this instance of the same name, the others start as they
+    /// would in a new instance. The adopted memories grow to the current size of those of this
+    /// instance, of which they get a copy, and the active data segments of `new_module` are
+    /// only applied over the copy if `initialize_active_data`. The start function of
+    /// `new_module` runs once the state is adopted.
+    ///
+    /// The exports adopted must be compatible: a memory must be able to have the current
+    /// size of the memory of this instance, and be shared if it is, and a global must have
+    /// the same type as the global of this instance. Otherwise, this fails with
+    /// [`ReinstantiateError::Incompatible`] listing all the exports which aren't, and this
+    /// instance is left as it is, like when instantiating `new_module` fails. The functions of
+    /// this instance must not run while its state is adopted.
+    ///
+    /// Once this instance is closed, the memories and the globals looked up from it are
+    /// distinct from those of the new instance, and the writes to them aren't seen by the
+    /// new instance.
+    pub fn reinstantiate_with_config(
+        &self,
+        new_module: &Module,
+        config: InstanceConfig,
+        resolver: &dyn Resolver,
+        initialize_active_data: bool,
+    ) -> Result<Self, ReinstantiateError> {
+        check_config(&config)?;
+        let adopted = self.adopted_exports(new_module)?;
+        let handle = new_module.instantiate_uninitialized(resolver, config)?;
+        let store = new_module.store();
+        for (name, current) in adopted {
+            let export = handle
+                .lookup(&name)
+                .expect("instances have every export of their module");
+            match (current, Extern::from_vm_export(store, export.into())) {
+                (Extern::Memory(current), Extern::Memory(memory)) => {
+                    adopt_memory(&current, &memory)?
+                }
+                (Extern::Global(current), Extern::Global(global)) => global
+                    .set(current.get())
+                    .expect("the globals adopted are mutable and of the same type"),
+                _ => unreachable!("the exports adopted are of the same kind"),
+            }
+        }
+        // As in `Module::instantiate`, the instance is freed along with its handle if this
+        // traps.
+        let result = unsafe {
+            if initialize_active_data {
+                handle.finish_instantiation()
+            } else {
+                handle.finish_instantiation_without_data()
+            }
+        };
+        result.map_err(|trap| {
+            InstantiationError::Start(store.runtime_error(trap, Some(handle.id())))
+        })?;
+        let instance = Self::from_handle(new_module, handle, true)?;
+        self.handle.lock().unwrap().close();
         Ok(instance)
     }
 
+    /// The exports of this instance whose state an instance of `new_module` adopts, with their
+    /// name, or the exports which aren't compatible.
+    fn adopted_exports(
+        &self,
+        new_module: &Module,
+    ) -> Result<Vec<(String, Extern)>, ReinstantiateError> {
+        let artifact = new_module.artifact();
+        let import_counts = artifact.import_counts();
+        let mut adopted = vec![];
+        let mut incompatible = vec![];
+        for (name, index) in artifact.exports() {
+            let defined = match index {
+                ExportIndex::Memory(index) => import_counts.local_memory_index(index).is_ok(),
+                ExportIndex::Global(index) => import_counts.local_global_index(index).is_ok(),
+                _ => false,
+            };
+            let current = match self.lookup(name) {
+                Some(export) if defined => Extern::from_vm_export(self.module.store(), export),
+                _ => continue,
+            };
+            let new = artifact.export_type(&index);
+            let reason = match (&current, &new) {
+                (Extern::Memory(memory), ExternType::Memory(ty)) => {
+                    let size = memory.size();
+                    if ty.minimum > size {
+                        Some(format!(
+                            "the new minimum of {} pages is above the {} pages of the memory",
+                            ty.minimum.0, size.0
+                        ))
+                    } else if ty.maximum.map_or(false, |maximum| maximum < size) {
+                        Some(format!(
+                            "the new maximum of {} pages is below the {} pages of the memory",
+                            ty.maximum.unwrap().0,
+                            size.0
+                        ))
+                    } else if ty.shared != memory.ty().shared {
+                        Some("only one of the memories is shared".to_string())
+                    } else if ty.memory64 != memory.ty().memory64 {
+                        Some("only one of the memories is 64-bit".to_string())
+                    } else {
+                        None
+                    }
+                }
+                (Extern::Global(global), ExternType::Global(ty)) => {
+                    if ty.mutability == Mutability::Const {
+                        continue;
+                    } else if global.ty() != ty {
+                        Some("the types of the globals differ".to_string())
+                    } else if ty.ty.is_ref() {
+                        Some("references can't be adopted".to_string())
+                    } else {
+                        None
+                    }
+                }
+                (_, ExternType::Global(ty)) if ty.mutability == Mutability::Const => continue,
+                _ => Some("the kinds of the exports differ".to_string()),
+            };
+            match reason {
+                Some(reason) => incompatible.push(IncompatibleExport {
+                    name: name.to_string(),
+                    current: current.ty(),
+                    new,
+                    reason,
+                }),
+                None => adopted.push((name.to_string(), current)),
+            }
+        }
+        if incompatible.is_empty() {
+            Ok(adopted)
+        } else {
+            Err(ReinstantiateError::Incompatible(incompatible))
+        }
+    }
+
+    /// Capture the state of this instance, for instances of its module to start from it.
+    pub(crate) fn capture_image(&self) -> Result<InstancePreImage, String> {
+        self.handle.lock().unwrap().capture_image()
+    }
+
+    /// Take a snapshot of the state of this instance, to restore it into a new instance of its
+    /// module with [`Instance::restore`], possibly in another process.
+    ///
+    /// The snapshot holds the contents of the memories, tables and globals the instance
+    /// defines, without the chunks of the memories only holding zeros, and the passive
+    /// segments it didn't drop. The imported memories, tables and globals are left out. Its
+    /// tables and globals may only hold null references, and references to the functions it
+    /// defines or imports, otherwise this fails with [`SnapshotError::Capture`].
+    ///
+    /// The functions of the instance must not run while the snapshot is taken.
+    pub fn snapshot(&self) -> Result<InstanceSnapshot, SnapshotError> {
+        self.snapshot_with(true)
+    }
+
+    /// Take a snapshot of the state of this instance like [`Instance::snapshot`], but keeping
+    /// the whole contents of the memories, which is faster when they are mostly written to.
+    pub fn snapshot_with_zero_pages(&self) -> Result<InstanceSnapshot, SnapshotError> {
+        self.snapshot_with(false)
+    }
+
+    fn snapshot_with(&self, elide_zero_pages: bool) -> Result<InstanceSnapshot, SnapshotError> {
+        let module_hash = *self.module.hash();
+        self.handle
+            .lock()
+            .unwrap()
+            .snapshot(module_hash, elide_zero_pages)
+            .map_err(SnapshotError::Capture)
+    }
+
+    /// Create an instance of `module` with the imports resolved by `resolver`, restoring the
+    /// state captured in `snapshot` instead of running the initializers of the module and
+    /// its start function.
+    ///
+    /// The snapshot must be of an instance of the same module, compiled from the same wasm
+    /// binary, otherwise this fails with [`SnapshotError::OtherModule`]. As the state may
+    /// depend on the imports, the instance only continues where the instance the snapshot was
+    /// taken of was if the imports are the same too.
+    pub fn restore(
+        module: &Module,
+        resolver: &dyn Resolver,
+        snapshot: &InstanceSnapshot,
+    ) -> Result<Self, SnapshotError> {
+        if snapshot.module_hash() != module.hash() {
+            return Err(SnapshotError::OtherModule);
+        }
+        let handle = module.instantiate_uninitialized(resolver, InstanceConfig::default())?;
+        // As in `Module::instantiate`, the instance is freed along with its handle if this
+        // fails.
+        unsafe { handle.finish_instantiation_from_snapshot(snapshot) }
+            .map_err(SnapshotError::Restore)?;
+        Ok(Self::from_handle(module, handle, false)?)
+    }
+
+    /// Return the identifier of this instance, as given to the memory grow callback.
+    pub fn id(&self) -> InstanceId {
+        self.handle.lock().unwrap().id()
+    }
+
+    /// Close the instance, and drop this handle to it.
+    ///
+    /// An instance is freed when the last reference to it is dropped: its memories are
+    /// unmapped along with their guard regions, its tables and globals are freed, and it
+    /// releases its reference on the artifact of its module. The references are the clones of this
+    /// `Instance`, the externs looked up from it, and the instances importing its
+    /// functions.
+    ///
+    /// Closing the instance doesn't wait for them to be dropped: calling its functions
+    /// from the host through any of them, including the weak references of host
+    /// environments, fails with [`RuntimeError::instance_closed`]. Instances importing its
+    /// functions keep calling them, and keep it alive.
+    pub fn close(self) {
+        self.handle.lock().unwrap().close();
+    }
+
+    /// Set the lowest address the native stack pointer may reach while running functions of
+    /// this instance, or `0` to remove the limit.
+    ///
+    /// Only code compiled with stack limit checks enabled (see
+    /// `Singlepass::enable_stack_limit_checks`) honours this limit: its functions trap with
+    /// `TrapCode::StackOverflow` on entry when their frame would extend below it.
+    pub fn set_native_stack_limit(&self, limit: usize) {
+        self.handle.lock().unwrap().set_native_stack_limit(limit);
+    }
+
+    /// Set the fuel left to the functions of this instance compiled with metering (see
+    /// `Singlepass::enable_metering`).
+    ///
+    /// Each instance has fuel of its own, `u64::MAX` when it is created, which is consumed by
+    /// its functions and by the host functions using its [`Fuel`] handle. Once they ran out of
+    /// fuel, setting more lets them run again.
+    pub fn set_fuel(&self, fuel: u64) {
+        self.handle.lock().unwrap().set_fuel(fuel);
+    }
+
+    /// Return the fuel left to the functions of this instance compiled with metering.
+    pub fn fuel_remaining(&self) -> u64 {
+        self.handle.lock().unwrap().fuel_remaining()
+    }
+
+    /// Get a handle to the fuel of this instance, for host functions to consume it.
+    ///
+    /// The handle only keeps a weak reference to the instance, so it can be stored in the
+    /// environments of the host functions the instance imports.
+    pub fn fuel(&self) -> Fuel {
+        Fuel {
+            instance: self.handle.lock().unwrap().downgrade(),
+        }
+    }
+
+    /// Sample the metrics of this instance, if it was created with
+    /// `InstanceConfig::with_metrics`, or `None`.
+    ///
+    /// The metrics can be sampled at any time, from any thread, including while the functions
+    /// of the instance run. The calls from the instance to the functions it imports are direct
+    /// ones, so they aren't counted.
+    pub fn metrics(&self) -> Option<InstanceMetrics> {
+        self.handle.lock().unwrap().metrics()
+    }
+
+    /// Set the epoch of the store at which the functions of this instance compiled with epoch
+    /// interruption (see `Singlepass::enable_epoch_interruption`) stop running.
+    ///
+    /// The deadline is absolute: they stop once [`Store::epoch`](crate::Store::epoch) is at
+    /// least `deadline`. It is `u64::MAX` when the instance is created, so they never stop
+    /// unless a deadline is set.
+    pub fn set_epoch_deadline(&self, deadline: u64) {
+        self.handle.lock().unwrap().set_epoch_deadline(deadline);
+    }
+
+    /// Set the callback called with the current epoch when the functions of this instance
+    /// reach the epoch deadline.
+    ///
+    /// Returning [`EpochDeadlineAction::Continue`] sets a new deadline and keeps them running,
+    /// returning [`EpochDeadlineAction::Trap`] makes them trap, as they do without a callback,
+    /// with a [`RuntimeError`] for which `is_epoch_deadline_exceeded` is true.
+    pub fn set_epoch_deadline_callback(
+        &self,
+        callback: impl Fn(u64) -> EpochDeadlineAction + Send + Sync + 'static,
+    ) {
+        self.handle
+            .lock()
+            .unwrap()
+            .set_epoch_deadline_callback(Some(Arc::new(callback)));
+    }
+
     /// Lookup an exported entity by its name.
     pub fn lookup(&self, field: &str) -> Option<crate::Export> {
         let vmextern = self.handle.lock().unwrap().lookup(field)?;
         Some(vmextern.into())
     }
 
+    /// Iterate over the names and types of the exports, in the order the module declares them.
+    pub fn exports(&self) -> impl Iterator<Item = (&str, ExternType)> + '_ {
+        self.module.artifact().exports().map(move |(name, _)| {
+            let export = self
+                .lookup(name)
+                .expect("instances have every export of their module");
+            (
+                name,
+                Extern::from_vm_export(self.module.store(), export).ty(),
+            )
+        })
+    }
+
+    fn lookup_extern(&self, name: &str) -> Result<Extern, ExportError> {
+        let export = self
+            .lookup(name)
+            .ok_or_else(|| ExportError::Missing(name.to_string()))?;
+        Ok(Extern::from_vm_export(self.module.store(), export))
+    }
+
     /// Lookup an exported function by its name.
-    pub fn lookup_function(&self, field: &str) -> Option<crate::Function> {
-        if let crate::Export::Function(f) = self.lookup(field)? {
-            Some(crate::Function::from_vm_export(self.module.store(), f))
-        } else {
-            None
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use wasmer::{imports, wat2wasm, Instance, Module, Store};
+    /// # fn main() -> anyhow::Result<()> {
+    /// # let store = Store::default();
+    /// # let wasm_bytes = wat2wasm(br#"
+    /// # (module
+    /// #   (func (export "extend") (param i32) (result i64)
+    /// #     local.get 0
+    /// #     i64.extend_i32_s))
+    /// # "#)?;
+    /// # let module = Module::new(&store, wasm_bytes)?;
+    /// # let instance = Instance::new(&module, &imports! {})?;
+    /// let extend = instance.lookup_function("extend")?.native::<i32, i64>()?;
+    /// assert_eq!(extend.call(-1)?, -1);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn lookup_function(&self, name: &str) -> Result<Function, ExportError> {
+        match self.lookup_extern(name)? {
+            Extern::Function(function) => Ok(function),
+            other => Err(incompatible_type(name, "a function", &other)),
+        }
+    }
+
+    /// Lookup an exported memory by its name.
+    pub fn lookup_memory(&self, name: &str) -> Result<Memory, ExportError> {
+        match self.lookup_extern(name)? {
+            Extern::Memory(memory) => Ok(memory),
+            other => Err(incompatible_type(name, "a memory", &other)),
+        }
+    }
+
+    /// Lookup an exported global by its name.
+    pub fn lookup_global(&self, name: &str) -> Result<Global, ExportError> {
+        match self.lookup_extern(name)? {
+            Extern::Global(global) => Ok(global),
+            other => Err(incompatible_type(name, "a global", &other)),
+        }
+    }
+
+    /// Lookup an exported table by its name.
+    pub fn lookup_table(&self, name: &str) -> Result<Table, ExportError> {
+        match self.lookup_extern(name)? {
+            Extern::Table(table) => Ok(table),
+            other => Err(incompatible_type(name, "a table", &other)),
+        }
+    }
+
+    /// Lookup an exported tag by its name.
+    pub fn lookup_tag(&self, name: &str) -> Result<Tag, ExportError> {
+        match self.lookup_extern(name)? {
+            Extern::Tag(tag) => Ok(tag),
+            other => Err(incompatible_type(name, "a tag", &other)),
         }
     }
 
@@ -179,14 +749,77 @@ impl Instance {
         Args: WasmTypeList,
         Rets: WasmTypeList,
     {
-        match self.lookup(name) {
-            Some(crate::Export::Function(f)) => {
-                crate::Function::from_vm_export(self.module.store(), f)
-                    .native()
-                    .map_err(|_| ExportError::IncompatibleType)
-            }
-            Some(_) => Err(ExportError::IncompatibleType),
-            None => Err(ExportError::Missing("not found".into())),
+        let function = self.lookup_function(name)?;
+        function
+            .native()
+            .map_err(|_| ExportError::IncompatibleType {
+                name: name.to_string(),
+                expected: format!(
+                    "a function of type {}",
+                    FunctionType::new(Args::wasm_types(), Rets::wasm_types())
+                ),
+                found: ExternType::Function(function.ty()),
+            })
+    }
+}
+
+impl LikeNamespace for Instance {
+    fn get_namespace_export(&self, name: &str) -> Option<Export> {
+        self.lookup(name)
+    }
+
+    fn get_namespace_exports(&self) -> Vec<(String, Export)> {
+        self.module
+            .artifact()
+            .exports()
+            .map(|(name, _)| {
+                let export = self
+                    .lookup(name)
+                    .expect("instances have every export of their module");
+                (name.to_string(), export)
+            })
+            .collect()
+    }
+}
+
+fn incompatible_type(name: &str, expected: &str, found: &Extern) -> ExportError {
+    ExportError::IncompatibleType {
+        name: name.to_string(),
+        expected: expected.to_string(),
+        found: found.ty(),
+    }
+}
+
+fn check_config(config: &InstanceConfig) -> Result<(), InstantiationError> {
+    unsafe {
+        if (*config.gas_counter).opcode_cost > i32::MAX as u64 {
+            // Fast gas counter logic assumes that individual opcode cost is not too big.
+            return Err(InstantiationError::HostEnvInitialization(
+                HostEnvInitError::IncorrectGasMeteringConfig,
+            ));
         }
     }
+    Ok(())
+}
+
+/// Make `memory` a copy of `current`, growing it to its size.
+fn adopt_memory(current: &Memory, memory: &Memory) -> Result<(), InstantiationError> {
+    let resource_error = |e: String| {
+        InstantiationError::Link(LinkError::Resource(format!(
+            "could not adopt a memory: {}",
+            e
+        )))
+    };
+    let size = current.size();
+    if memory.size() < size {
+        memory
+            .grow(size - memory.size())
+            .map_err(|e| resource_error(e.to_string()))?;
+    }
+    // The new memory can't be accessed from anywhere else until its instance is returned.
+    let len = current.data_size().min(memory.data_size()) as usize;
+    let data = unsafe { &mut memory.data_unchecked_mut()[..len] };
+    current
+        .read(0, data)
+        .map_err(|e| resource_error(e.to_string()))
 }
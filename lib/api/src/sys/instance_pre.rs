@@ -0,0 +1,161 @@
+use crate::sys::instance::{Instance, InstantiationError};
+use crate::sys::module::Module;
+use crate::sys::{ExternType, LinkError};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use wasmer_types::InstanceConfig;
+use wasmer_vm::{Export, InstancePreImage, Resolver};
+
+/// A [`Module`] instantiated once, whose instances start from the state of that first instance
+/// instead of being initialized.
+///
+/// Creating the `InstancePre` instantiates the module, running its data and element segments
+/// and its start function, and captures the state of the instance: the contents of its memories,
+/// tables and globals. The instances it creates then start with that state, and their
+/// memories map the captured contents copy-on-write where the platform allows it, so the
+/// instantiation doesn't depend on the size of the data segments, and the pages an instance
+/// writes are its own.
+///
+/// The imports are resolved once, and the instances use the same imports. The initializers
+/// don't run again, so their effects on the imports, like the calls the start function makes,
+/// only happen for the first instance.
+///
+/// ```
+/// # use wasmer::{imports, InstancePre, Module, Store};
+/// # fn main() -> anyhow::Result<()> {
+/// let store = Store::default();
+/// let module = Module::new(&store, r#"
+///     (memory (export "memory") 1)
+///     (data (i32.const 0) "hello")
+/// "#)?;
+/// let pre = InstancePre::new(&module, &imports! {})?;
+/// let instance = pre.instantiate()?;
+/// let mut hello = [0; 5];
+/// instance.lookup_memory("memory")?.read(0, &mut hello)?;
+/// assert_eq!(&hello, b"hello");
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct InstancePre {
+    module: Module,
+    /// The exports the imports of the module resolved to, by import index.
+    imports: Arc<ResolvedImports>,
+    image: Arc<InstancePreImage>,
+}
+
+impl InstancePre {
+    /// Instantiate `module` with the imports resolved by `resolver`, and capture the state of the
+    /// instance for the next ones.
+    ///
+    /// ## Errors
+    ///
+    /// The errors of [`Instance::new`], and a [`LinkError::Resource`] if the state of the
+    /// instance can't be captured: its tables and globals may only hold null references, and
+    /// references to the functions it defines or imports.
+    pub fn new(module: &Module, resolver: &dyn Resolver) -> Result<Self, InstantiationError> {
+        let imports = ResolvedImports::new(module, resolver);
+        let template = Instance::new(module, &imports)?;
+        let image = template.capture_image().map_err(|e| {
+            InstantiationError::Link(LinkError::Resource(format!(
+                "the state of the instance can't be captured: {}",
+                e
+            )))
+        })?;
+        Ok(Self {
+            module: module.clone(),
+            imports: Arc::new(imports),
+            image: Arc::new(image),
+        })
+    }
+
+    /// The module this creates instances of.
+    pub fn module(&self) -> &Module {
+        &self.module
+    }
+
+    /// Create an instance of the module, starting from the captured state.
+    pub fn instantiate(&self) -> Result<Instance, InstantiationError> {
+        self.instantiate_with_config(InstanceConfig::default())
+    }
+
+    /// Create an instance of the module with `config`, starting from the captured state.
+    pub fn instantiate_with_config(
+        &self,
+        config: InstanceConfig,
+    ) -> Result<Instance, InstantiationError> {
+        Instance::new_with_image(&self.module, config, &*self.imports, Some(&self.image))
+    }
+
+    /// Create an instance of the module with the imports resolved by `resolver`.
+    ///
+    /// The instance only starts from the captured state if the imports resolve to the same
+    /// externs as those of the first instance. Otherwise, as the state may depend on the
+    /// imports, the instance is initialized by running the initializers of the module, as
+    /// [`Instance::new`] does.
+    pub fn instantiate_with_imports(
+        &self,
+        resolver: &dyn Resolver,
+    ) -> Result<Instance, InstantiationError> {
+        let imports = ResolvedImports::new(&self.module, resolver);
+        let image = if imports.same(&self.imports) {
+            Some(&*self.image)
+        } else {
+            None
+        };
+        Instance::new_with_image(&self.module, InstanceConfig::default(), &imports, image)
+    }
+}
+
+/// The exports the imports of a module resolved to, by import index.
+struct ResolvedImports(BTreeMap<u32, Export>);
+
+impl ResolvedImports {
+    /// Resolve the imports of `module` with `resolver`, leaving out those it doesn't resolve.
+    fn new(module: &Module, resolver: &dyn Resolver) -> Self {
+        Self(
+            module
+                .imports()
+                .filter_map(|import| {
+                    let export = resolver.resolve(
+                        import.index(),
+                        import.module(),
+                        import.name(),
+                        import.ty(),
+                    )?;
+                    Some((import.index(), export))
+                })
+                .collect(),
+        )
+    }
+
+    /// Whether the imports resolved to the same externs.
+    fn same(&self, other: &Self) -> bool {
+        self.0.len() == other.0.len()
+            && self.0.iter().zip(other.0.iter()).all(
+                |((index, export), (other_index, other_export))| {
+                    index == other_index && same_export(export, other_export)
+                },
+            )
+    }
+}
+
+impl Resolver for ResolvedImports {
+    fn resolve(&self, index: u32, _module: &str, _field: &str, _: &ExternType) -> Option<Export> {
+        self.0.get(&index).cloned()
+    }
+}
+
+fn same_export(export: &Export, other: &Export) -> bool {
+    match (export, other) {
+        (Export::Function(function), Export::Function(other)) => {
+            function.vm_function.address == other.vm_function.address
+                && function.vm_function.vmctx == other.vm_function.vmctx
+        }
+        (Export::Table(table), Export::Table(other)) => table.same(other),
+        (Export::Memory(memory), Export::Memory(other)) => memory.same(other),
+        (Export::Global(global), Export::Global(other)) => global.same(other),
+        (Export::Tag(tag), Export::Tag(other)) => tag.same(other),
+        _ => false,
+    }
+}
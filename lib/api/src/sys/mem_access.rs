@@ -0,0 +1,117 @@
+//! Bounds-checked accesses to Wasm linear memory.
+//!
+//! Every access is checked against the size of the memory at the time it happens, and the
+//! memory can't grow while the access is in progress, even from another thread. Memories
+//! never shrink, so a range that was in bounds stays in bounds.
+
+use crate::sys::externals::Memory;
+use crate::sys::RuntimeError;
+use std::convert::TryFrom;
+use std::marker::PhantomData;
+use std::mem;
+use thiserror::Error;
+use wasmer_types::ValueType;
+
+/// An error while accessing Wasm linear memory from the host.
+#[derive(Clone, Copy, Debug, Error, PartialEq, Eq)]
+pub enum MemoryAccessError {
+    /// The access goes past the end of the memory.
+    #[error("out of bounds memory access")]
+    HeapOutOfBounds,
+    /// The address of the end of the access doesn't fit in 64 bits.
+    #[error("address calculation overflow")]
+    Overflow,
+    /// The bytes read are not a valid UTF-8 string.
+    #[error("invalid UTF-8 string")]
+    NonUtf8String,
+}
+
+impl From<MemoryAccessError> for RuntimeError {
+    fn from(err: MemoryAccessError) -> Self {
+        Self::new(err.to_string())
+    }
+}
+
+/// The offset of the end of `len` values of type `T` starting at `offset`.
+pub(crate) fn end_of<T>(offset: u64, len: u64) -> Result<u64, MemoryAccessError> {
+    let size = u64::try_from(mem::size_of::<T>()).map_err(|_| MemoryAccessError::Overflow)?;
+    len.checked_mul(size)
+        .and_then(|bytes| offset.checked_add(bytes))
+        .ok_or(MemoryAccessError::Overflow)
+}
+
+/// A range of values of type `T` in Wasm linear memory, checked to be in bounds.
+///
+/// The values are copied in and out of the memory, so they don't need to be aligned.
+pub struct WasmSlice<'a, T: ValueType> {
+    memory: &'a Memory,
+    offset: u64,
+    len: u64,
+    _phantom: PhantomData<T>,
+}
+
+impl<'a, T: ValueType> WasmSlice<'a, T> {
+    /// Creates a slice of `len` values at `offset` in `memory`, checking that it ends within
+    /// the memory.
+    pub fn new(memory: &'a Memory, offset: u64, len: u64) -> Result<Self, MemoryAccessError> {
+        if end_of::<T>(offset, len)? > memory.data_size() {
+            return Err(MemoryAccessError::HeapOutOfBounds);
+        }
+        Ok(Self {
+            memory,
+            offset,
+            len,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// The offset of the first value in the memory.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// The number of values in the slice.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Whether the slice holds no values.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn offset_of(&self, index: u64) -> Result<u64, MemoryAccessError> {
+        if index >= self.len {
+            return Err(MemoryAccessError::HeapOutOfBounds);
+        }
+        // Can't overflow once the whole slice was checked.
+        Ok(self.offset + index * mem::size_of::<T>() as u64)
+    }
+
+    /// Reads the value at `index`.
+    pub fn read(&self, index: u64) -> Result<T, MemoryAccessError> {
+        self.memory.read_value(self.offset_of(index)?)
+    }
+
+    /// Writes `value` at `index`.
+    pub fn write(&self, index: u64, value: T) -> Result<(), MemoryAccessError> {
+        self.memory.write_value(self.offset_of(index)?, value)
+    }
+
+    /// Reads every value of the slice.
+    pub fn read_to_vec(&self) -> Result<Vec<T>, MemoryAccessError> {
+        (0..self.len).map(|index| self.read(index)).collect()
+    }
+
+    /// Writes `values` at the start of the slice, failing without writing anything if they
+    /// don't fit in it.
+    pub fn write_slice(&self, values: &[T]) -> Result<(), MemoryAccessError> {
+        if values.len() as u64 > self.len {
+            return Err(MemoryAccessError::HeapOutOfBounds);
+        }
+        for (index, value) in values.iter().enumerate() {
+            self.write(index as u64, *value)?;
+        }
+        Ok(())
+    }
+}
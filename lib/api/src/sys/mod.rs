@@ -1,13 +1,21 @@
+#[cfg(unix)]
+mod async_call;
+mod cache;
 mod cell;
 mod env;
+mod exception;
 mod exports;
 mod externals;
 mod import_object;
 mod instance;
+mod instance_pre;
+mod mem_access;
 mod module;
 mod native;
 mod ptr;
 mod store;
+#[cfg(feature = "wasmprinter")]
+mod text;
 mod tunables;
 mod types;
 
@@ -21,37 +29,55 @@ pub mod internals {
     pub use crate::sys::externals::{WithEnv, WithoutEnv};
 }
 
+#[cfg(unix)]
+pub use crate::sys::async_call::AsyncCall;
+pub use crate::sys::cache::{FileSystemCache, ModuleCache, ModuleCacheKey};
 pub use crate::sys::cell::WasmCell;
 pub use crate::sys::env::{HostEnvInitError, LazyInit, WasmerEnv};
+pub use crate::sys::exception::Exception;
 pub use crate::sys::exports::{ExportError, Exportable, Exports};
 pub use crate::sys::externals::{
-    Extern, FromToNativeWasmType, Function, Global, HostFunction, Memory, Table, WasmTypeList,
+    Extern, FromToNativeWasmType, Function, Global, HostFunction, Memory, Table, Tag, WasmTypeList,
 };
 pub use crate::sys::import_object::{ImportObject, ImportObjectIterator, LikeNamespace};
-pub use crate::sys::instance::{Instance, InstantiationError};
+pub use crate::sys::instance::{
+    Fuel, IncompatibleExport, Instance, InstantiationError, ReinstantiateError, SnapshotError,
+};
+pub use crate::sys::instance_pre::InstancePre;
+pub use crate::sys::mem_access::{MemoryAccessError, WasmSlice};
 pub use crate::sys::module::Module;
-pub use crate::sys::native::NativeFunc;
+pub use crate::sys::native::{NativeFunc, TypedFunction};
 pub use crate::sys::ptr::{Array, Item, WasmPtr};
-pub use crate::sys::store::{Store, StoreObject};
-pub use crate::sys::tunables::BaseTunables;
+pub use crate::sys::store::{Store, StoreObject, TrapHook};
+pub use crate::sys::tunables::{BaseTunables, LimitingTunables, MemoryStyleOverride};
 pub use crate::sys::types::{
-    ExportType, ExternType, FunctionType, GlobalType, MemoryType, Mutability, TableType, Val,
-    ValType,
+    ExportType, ExternType, FunctionType, GlobalType, ImportType, MemoryType, Mutability,
+    TableType, TagType, Val, ValType,
 };
 pub use crate::sys::types::{Val as Value, ValType as Type};
 pub use target_lexicon::{Architecture, CallingConvention, OperatingSystem, Triple, HOST};
 #[cfg(feature = "compiler")]
-pub use wasmer_compiler::{wasmparser, CompilerConfig};
 pub use wasmer_compiler::{
-    CompileError, CpuFeature, Features, ParseCpuFeatureError, Target, WasmError, WasmResult,
+    wasmparser, CompilerConfig, FunctionMiddleware, MiddlewareReaderState, ModuleMiddleware,
+};
+pub use wasmer_compiler::{
+    CancellationToken, CompileError, CompileProgress, CompileTiming, CpuFeature, Features,
+    FunctionLocation, MiddlewareError, ParseCpuFeatureError, Target, UnsupportedItem,
+    UnsupportedUse, WasmError, WasmResult,
+};
+pub use wasmer_engine::{
+    CacheStats, CodeMemoryUsage, DeserializeError, Engine, FrameInfo, ImportError, LinkError,
+    OobDetails, RuntimeError, TrapInfo, UnknownImport,
 };
-pub use wasmer_engine::{DeserializeError, Engine, FrameInfo, LinkError, RuntimeError};
 pub use wasmer_types::{
-    Atomically, Bytes, ExportIndex, ExternRef, GlobalInit, LocalFunctionIndex, MemoryView, Pages,
-    ValueType, WASM_MAX_PAGES, WASM_MIN_PAGES, WASM_PAGE_SIZE,
+    Atomically, Bytes, ConstExpr, ConstOp, ExportIndex, ExternRef, FunctionIndex, GlobalInit,
+    LocalFunctionIndex, MemoryView, Pages, ValueType, WASM64_MAX_PAGES, WASM_MAX_PAGES,
+    WASM_MIN_PAGES, WASM_PAGE_SIZE,
 };
 pub use wasmer_vm::{
-    ChainableNamedResolver, Export, NamedResolver, NamedResolverChain, Resolver, Tunables,
+    ChainableNamedResolver, EpochDeadlineAction, Export, InstanceId, InstanceMetrics,
+    InstanceSnapshot, MemoryGrow, MemoryGrowCallback, NamedResolver, NamedResolverChain, Resolver,
+    ResourceLimiter, StaticLimiter, Tunables,
 };
 
 // TODO: should those be moved into wasmer::vm as well?
@@ -66,13 +92,19 @@ pub mod vm {
 }
 
 #[cfg(feature = "wat")]
-pub use wat::parse_bytes as wat2wasm;
+pub use crate::sys::text::{wat2wasm, wat2wasm_with_features, WatParseError};
+
+#[cfg(feature = "wasmprinter")]
+pub use crate::sys::text::{wasm2wat, ToWatError};
 
 #[cfg(feature = "singlepass")]
 pub use wasmer_compiler_singlepass::Singlepass;
 
 #[cfg(feature = "universal")]
-pub use wasmer_engine_universal::{Universal, UniversalArtifact, UniversalEngine};
+pub use wasmer_engine_universal::{
+    Fingerprint, ModuleCompileMode, ProfilingStrategy, Universal, UniversalArtifact,
+    UniversalEngine,
+};
 
 #[cfg(feature = "dylib")]
 pub use wasmer_engine_dylib::{Dylib, DylibArtifact, DylibEngine};
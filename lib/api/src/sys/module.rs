@@ -1,16 +1,18 @@
+use crate::sys::cache::{ModuleCache, ModuleCacheKey};
 use crate::sys::store::Store;
-use crate::sys::InstantiationError;
+use crate::sys::{ExportType, ExternType, ImportType, InstantiationError, LinkError};
 use std::fmt;
 use std::io;
+use std::path::Path;
 use std::sync::Arc;
 use thiserror::Error;
-use wasmer_compiler::CompileError;
 #[cfg(feature = "wat")]
 use wasmer_compiler::WasmError;
-use wasmer_engine::RuntimeError;
-use wasmer_engine_universal::UniversalArtifact;
-use wasmer_types::InstanceConfig;
-use wasmer_vm::{InstanceHandle, Instantiatable, Resolver};
+use wasmer_compiler::{CompileError, CompileProgress, CompileTiming};
+use wasmer_engine::{DeserializeError, Engine, Executable};
+use wasmer_engine_universal::{UniversalArtifact, UniversalEngine, UniversalExecutableFile};
+use wasmer_types::{FunctionIndex, InstanceConfig};
+use wasmer_vm::{InstanceHandle, InstancePreImage, Instantiatable, Resolver, VMImportType};
 
 #[derive(Error, Debug)]
 pub enum IoCompileError {
@@ -45,6 +47,10 @@ impl Module {
     /// to convert the bytes assuming they correspond to the WebAssembly text
     /// format.
     ///
+    /// Modules created from the same bytes by the same engine share their
+    /// compiled code for as long as one of them is alive, see
+    /// [`Engine::cache_stats`].
+    ///
     /// ## Security
     ///
     /// Before the code is compiled, it will be validated using the store
@@ -100,7 +106,7 @@ impl Module {
     #[tracing::instrument(skip_all)]
     pub fn new(store: &Store, bytes: impl AsRef<[u8]>) -> Result<Self, CompileError> {
         #[cfg(feature = "wat")]
-        let bytes = wat::parse_bytes(bytes.as_ref()).map_err(|e| {
+        let bytes = crate::wat2wasm(bytes.as_ref()).map_err(|e| {
             CompileError::Wasm(WasmError::Generic(format!(
                 "Error when converting wat: {}",
                 e
@@ -110,57 +116,443 @@ impl Module {
         Self::from_binary(store, bytes.as_ref())
     }
 
+    /// Validates a WebAssembly module given the configuration
+    /// in the store, without compiling it.
+    ///
+    /// The module is checked against the [`Features`](crate::Features) of the engine of
+    /// `store`, and the error of an invalid module gives the offset in `binary` of what
+    /// made it invalid. Unlike with [`Module::new`], `binary` can't be in the text format.
+    ///
+    /// ```
+    /// use wasmer::*;
+    /// # fn main() -> anyhow::Result<()> {
+    /// # let store = Store::default();
+    /// assert!(Module::validate(&store, b"\0asm\x01\0\0\0").is_ok());
+    /// assert!(Module::validate(&store, b"\0asm\x01\0\0\0\x01").is_err());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn validate(store: &Store, binary: &[u8]) -> Result<(), CompileError> {
+        store.engine().validate(binary)
+    }
+
+    /// Creates a new WebAssembly Module like [`Module::new`], reusing the
+    /// module compiled earlier from the same bytes if `cache` holds it.
+    ///
+    /// The key of the module in the cache is computed from the bytes of
+    /// the module and the [fingerprint](UniversalEngine::fingerprint) of the
+    /// engine of `store`. When the cache doesn't hold the module, or holds
+    /// a corrupted entry for it, the module is compiled and stored in the
+    /// cache. Failing to store it doesn't fail the creation of the module.
+    #[allow(unreachable_code)]
+    #[tracing::instrument(skip_all)]
+    pub fn new_cached(
+        store: &Store,
+        bytes: impl AsRef<[u8]>,
+        cache: &dyn ModuleCache,
+    ) -> Result<Self, CompileError> {
+        #[cfg(feature = "wat")]
+        let bytes = crate::wat2wasm(bytes.as_ref()).map_err(|e| {
+            CompileError::Wasm(WasmError::Generic(format!(
+                "Error when converting wat: {}",
+                e
+            )))
+        })?;
+        let binary = bytes.as_ref();
+
+        let engine: &dyn Engine = &**store.engine();
+        let engine = engine
+            .downcast_ref::<UniversalEngine>()
+            .ok_or(CompileError::EngineDowncast)?;
+        let key = ModuleCacheKey::new(binary, &engine.fingerprint());
+        if let Some(serialized) = cache.load(&key) {
+            // SAFETY: the entries of the cache are trusted to come from `serialize`, and are
+            // checked against their hash.
+            match unsafe { engine.deserialize_universal(&serialized) } {
+                Ok(artifact) => {
+                    return Ok(Self {
+                        store: store.clone(),
+                        artifact: Arc::new(artifact),
+                    })
+                }
+                Err(e) => tracing::warn!("recompiling the cached module {}: {}", key, e),
+            }
+        }
+
+        store.engine().validate(binary)?;
+        let executable = store.engine().compile(binary, store.tunables())?;
+        match executable.serialize() {
+            Ok(serialized) => {
+                if let Err(e) = cache.store(&key, &serialized) {
+                    tracing::warn!("could not cache the module {}: {}", key, e);
+                }
+            }
+            Err(e) => tracing::warn!("could not serialize the module {}: {}", key, e),
+        }
+        Self::from_executable(store, &*executable)
+    }
+
+    /// Creates a new WebAssembly Module like [`Module::new`], reporting the
+    /// progress of the compilation to `progress`.
+    ///
+    /// The callbacks of `progress` are invoked as each function is compiled,
+    /// possibly from several threads at once. Once its cancellation token is
+    /// cancelled, no more functions are compiled and this returns
+    /// [`CompileError::Cancelled`].
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use std::sync::Arc;
+    /// use wasmer::*;
+    /// # fn main() -> anyhow::Result<()> {
+    /// # let store = Store::default();
+    /// let compiled = Arc::new(AtomicUsize::new(0));
+    /// let progress = CompileProgress::new().on_function_compiled({
+    ///     let compiled = compiled.clone();
+    ///     move |_index, _code_size, _elapsed| {
+    ///         compiled.fetch_add(1, Ordering::Relaxed);
+    ///     }
+    /// });
+    /// let module = Module::new_with_progress(&store, "(module (func) (func))", &progress)?;
+    /// assert_eq!(compiled.load(Ordering::Relaxed), 2);
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[allow(unreachable_code)]
+    #[tracing::instrument(skip_all)]
+    pub fn new_with_progress(
+        store: &Store,
+        bytes: impl AsRef<[u8]>,
+        progress: &CompileProgress,
+    ) -> Result<Self, CompileError> {
+        #[cfg(feature = "wat")]
+        let bytes = crate::wat2wasm(bytes.as_ref()).map_err(|e| {
+            CompileError::Wasm(WasmError::Generic(format!(
+                "Error when converting wat: {}",
+                e
+            )))
+        })?;
+
+        Self::from_binary_with_progress(store, bytes.as_ref(), progress)
+    }
+
+    /// Creates a new WebAssembly Module like [`Module::new`], along with the
+    /// time each phase of its compilation took.
+    ///
+    /// A module sharing the compiled code of a module created earlier from
+    /// the same bytes reports the timing of the earlier module. With a
+    /// `tracing` subscriber, each phase is also a span.
+    ///
+    /// ```
+    /// use wasmer::*;
+    /// # fn main() -> anyhow::Result<()> {
+    /// # let store = Store::default();
+    /// let (module, timing) = Module::new_with_timing(&store, "(module (func))")?;
+    /// assert!(timing.total() >= timing.codegen);
+    /// assert_eq!(module.compile_timing(), timing);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new_with_timing(
+        store: &Store,
+        bytes: impl AsRef<[u8]>,
+    ) -> Result<(Self, CompileTiming), CompileError> {
+        let module = Self::new(store, bytes)?;
+        let timing = module.compile_timing();
+        Ok((module, timing))
+    }
+
     /// Creates a new WebAssembly module from a binary.
     ///
     /// Opposed to [`Module::new`], this function is not compatible with
     /// the WebAssembly text format (if the "wat" feature is enabled for
     /// this crate).
+    ///
+    /// The module shares the compiled code of the other modules the engine
+    /// of `store` compiled from the same binary and which are still alive,
+    /// see [`UniversalEngine::compile_deduplicated`].
+    #[allow(unreachable_code)]
     #[tracing::instrument(skip_all)]
     pub(crate) fn from_binary(store: &Store, binary: &[u8]) -> Result<Self, CompileError> {
+        #[cfg(feature = "compiler")]
+        {
+            let engine: &dyn Engine = &**store.engine();
+            let engine = engine
+                .downcast_ref::<UniversalEngine>()
+                .ok_or(CompileError::EngineDowncast)?;
+            return Ok(Self {
+                store: store.clone(),
+                artifact: engine.compile_deduplicated(binary, store.tunables())?,
+            });
+        }
+        Self::from_binary_with_progress(store, binary, &CompileProgress::default())
+    }
+
+    fn from_binary_with_progress(
+        store: &Store,
+        binary: &[u8],
+        progress: &CompileProgress,
+    ) -> Result<Self, CompileError> {
         store.engine().validate(binary)?;
-        let module = {
-            let executable = store.engine().compile(binary, store.tunables())?;
-            let artifact = store.engine().load(&*executable)?;
-            match artifact.downcast_arc::<UniversalArtifact>() {
-                Ok(universal) => Self {
-                    store: store.clone(),
-                    artifact: universal,
-                },
-                // We're are probably given an externally defined artifact type
-                // which I imagine we don't care about for now since this entire crate
-                // is only used for tests and this crate only defines universal engine.
-                Err(_) => panic!("unhandled artifact type"),
-            }
-        };
-        Ok(module)
+        let executable =
+            store
+                .engine()
+                .compile_with_progress(binary, store.tunables(), progress)?;
+        Self::from_executable(store, &*executable)
     }
 
+    /// Loads a WebAssembly module from a file written by
+    /// [`UniversalExecutable::serialize_to_file`](wasmer_engine_universal::UniversalExecutable::serialize_to_file).
+    ///
+    /// The file is mapped in memory rather than read, and stays mapped as
+    /// long as the module: only the code is copied, to be made executable,
+    /// while the data segments and custom sections are borrowed from the
+    /// mapping.
+    ///
+    /// The executable must have been compiled with the
+    /// [fingerprint](UniversalEngine::fingerprint) of the engine of `store`.
+    ///
+    /// ## Safety
+    ///
+    /// The file must not be modified while the module is loaded. Files that
+    /// were modified or truncated since they were written are rejected.
+    pub unsafe fn deserialize_from_file_mmap(
+        store: &Store,
+        path: impl AsRef<Path>,
+    ) -> Result<Self, DeserializeError> {
+        let engine: &dyn Engine = &**store.engine();
+        let engine = engine
+            .downcast_ref::<UniversalEngine>()
+            .ok_or(DeserializeError::Compiler(CompileError::EngineDowncast))?;
+        let file = UniversalExecutableFile::open(path)?;
+        let artifact = engine.load_universal_executable_file(file)?;
+        Ok(Self {
+            store: store.clone(),
+            artifact: Arc::new(artifact),
+        })
+    }
+
+    /// Loads a WebAssembly module from a shared object linked from the object
+    /// file written by
+    /// [`UniversalExecutable::write_object`](wasmer_engine_universal::UniversalExecutable::write_object).
+    ///
+    /// The code of the module is the code of the shared object, which stays
+    /// loaded as long as the module. The executable must have been compiled
+    /// with the [fingerprint](UniversalEngine::fingerprint) of the engine of
+    /// `store`, for the host.
+    ///
+    /// ## Safety
+    ///
+    /// The shared object is loaded in the process, which runs its
+    /// initializers, and must have been linked from an object file written by
+    /// `write_object` and nothing else.
+    pub unsafe fn from_shared_object(
+        store: &Store,
+        path: impl AsRef<Path>,
+    ) -> Result<Self, DeserializeError> {
+        let engine: &dyn Engine = &**store.engine();
+        let engine = engine
+            .downcast_ref::<UniversalEngine>()
+            .ok_or(DeserializeError::Compiler(CompileError::EngineDowncast))?;
+        let artifact = engine.load_shared_object(path)?;
+        Ok(Self {
+            store: store.clone(),
+            artifact: Arc::new(artifact),
+        })
+    }
+
+    fn from_executable(store: &Store, executable: &dyn Executable) -> Result<Self, CompileError> {
+        let artifact = store.engine().load(executable)?;
+        match artifact.downcast_arc::<UniversalArtifact>() {
+            Ok(universal) => Ok(Self {
+                store: store.clone(),
+                artifact: universal,
+            }),
+            // We're are probably given an externally defined artifact type
+            // which I imagine we don't care about for now since this entire crate
+            // is only used for tests and this crate only defines universal engine.
+            Err(_) => panic!("unhandled artifact type"),
+        }
+    }
+
+    /// Instantiate the module, restoring `image` instead of running its initializers if given.
+    ///
+    /// The start function isn't called yet, the host environments of the imports must be
+    /// initialized first.
     pub(crate) fn instantiate(
         &self,
         resolver: &dyn Resolver,
         config: InstanceConfig,
+        image: Option<&InstancePreImage>,
     ) -> Result<InstanceHandle, InstantiationError> {
+        let instance_handle = self.instantiate_uninitialized(resolver, config)?;
         unsafe {
-            let instance_handle = Arc::clone(&self.artifact).instantiate(
+            // After the instance handle is created, we need to initialize
+            // the tables and memories. If this traps, the instance is freed
+            // along with its handle.
+            match image {
+                Some(image) => instance_handle
+                    .finish_instantiation_from_image(image)
+                    .map_err(|e| InstantiationError::Link(LinkError::Resource(e)))?,
+                None => instance_handle.finish_instantiation().map_err(|t| {
+                    let id = instance_handle.id();
+                    InstantiationError::Start(self.store.runtime_error(t, Some(id)))
+                })?,
+            }
+        }
+        Ok(instance_handle)
+    }
+
+    /// Instantiate the module, leaving its tables and memories to initialize with one of the
+    /// `finish_instantiation` methods of the handle before anything else.
+    pub(crate) fn instantiate_uninitialized(
+        &self,
+        resolver: &dyn Resolver,
+        config: InstanceConfig,
+    ) -> Result<InstanceHandle, InstantiationError> {
+        unsafe {
+            Ok(Arc::clone(&self.artifact).instantiate(
                 self.store.tunables(),
                 resolver,
                 Box::new((self.store.clone(), Arc::clone(&self.artifact))),
-                config,
-            )?;
-
-            // After the instance handle is created, we need to initialize
-            // the data, call the start function and so. However, if any
-            // of this steps traps, we still need to keep the instance alive
-            // as some of the Instance elements may have placed in other
-            // instance tables.
-            instance_handle
-                .finish_instantiation()
-                .map_err(|t| InstantiationError::Start(RuntimeError::from_trap(t)))?;
-
-            Ok(instance_handle)
+                config.with_epoch_counter(Arc::clone(self.store.epoch_counter())),
+            )?)
         }
     }
 
+    pub(crate) fn artifact(&self) -> &UniversalArtifact {
+        &self.artifact
+    }
+
+    /// Returns the imports of the module, in the order the module declares them.
+    pub fn imports(&self) -> impl Iterator<Item = ImportType> + '_ {
+        let engine = self.artifact.engine();
+        self.artifact.imports().iter().map(move |import| {
+            let ty = match &import.ty {
+                VMImportType::Function { sig, .. } => ExternType::Function(
+                    engine
+                        .lookup_signature(*sig)
+                        .expect("the signatures of the imports are registered"),
+                ),
+                VMImportType::Global(ty) => ExternType::Global(*ty),
+                VMImportType::Table(ty) => ExternType::Table(*ty),
+                VMImportType::Memory(ty, _) => ExternType::Memory(*ty),
+                VMImportType::Tag(ty) => ExternType::Tag(ty.clone()),
+            };
+            ImportType::new(
+                import.module.clone(),
+                import.field.clone(),
+                import.import_no,
+                ty,
+            )
+        })
+    }
+
+    /// Returns the exports of the module, in the order the module declares them.
+    pub fn exports(&self) -> impl Iterator<Item = ExportType> + '_ {
+        self.artifact
+            .exports()
+            .map(move |(name, index)| ExportType::new(name, self.artifact.export_type(&index)))
+    }
+
+    /// Returns the contents of the custom sections named `name`, in the order the module has
+    /// them. A module can have several custom sections with the same name.
+    ///
+    /// The name section isn't one of them, its contents are in [`Module::name`] and
+    /// [`Module::function_names`].
+    pub fn custom_sections<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a [u8]> + 'a {
+        self.artifact.custom_sections(name)
+    }
+
+    /// Returns the BLAKE3 hash of the wasm binary the module was compiled from, which
+    /// identifies the module in the snapshots of its instances.
+    pub fn hash(&self) -> &[u8; 32] {
+        self.artifact.module_hash()
+    }
+
+    /// Returns the linked code of the module: its functions, trampolines and executable
+    /// sections.
+    pub fn code(&self) -> &[u8] {
+        self.artifact.code()
+    }
+
+    /// Returns whether the [code](Self::code) of the module is the same wherever it is loaded,
+    /// so that processes can share its pages at different addresses.
+    pub fn is_position_independent(&self) -> bool {
+        self.artifact.is_position_independent()
+    }
+
+    /// Returns the time each phase of the compilation and loading of the
+    /// module took, see [`UniversalArtifact::compile_timing`].
+    pub fn compile_timing(&self) -> CompileTiming {
+        self.artifact.compile_timing()
+    }
+
+    /// Returns the name of the module, from its name section.
+    ///
+    /// ```
+    /// # use wasmer::*;
+    /// # fn main() -> anyhow::Result<()> {
+    /// # let store = Store::default();
+    /// let module = Module::new(&store, "(module $moduleName)")?;
+    /// assert_eq!(module.name(), Some("moduleName"));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn name(&self) -> Option<&str> {
+        self.artifact.name()
+    }
+
+    /// Returns the names of the functions of the module, from its name section, in the order
+    /// of their indices. The imported functions come first, as in the index space of the
+    /// functions, and those without a name are skipped.
+    pub fn function_names(&self) -> impl Iterator<Item = (FunctionIndex, &str)> + '_ {
+        self.artifact.function_names()
+    }
+
+    /// Renders the module in the WebAssembly text format, for debugging.
+    ///
+    /// The functions are named as in the name section of the module. The
+    /// globals and exports the middlewares of the compiler appended to the
+    /// module are rendered after its other fields, but the bodies of the
+    /// functions are those of the binary: the middlewares transform their
+    /// operators as they are compiled.
+    ///
+    /// The text isn't byte for byte the binary of the module, but parsing it
+    /// with [`wat2wasm`](crate::wat2wasm) gives an equivalent module.
+    ///
+    /// ```
+    /// # use wasmer::*;
+    /// # fn main() -> anyhow::Result<()> {
+    /// # let store = Store::default();
+    /// let module = Module::new(&store, "(func $answer (export \"answer\") (result i32) i32.const 42)")?;
+    /// let wat = module.to_wat()?;
+    /// assert!(wat.contains("(func $answer"));
+    /// assert_eq!(Module::new(&store, wat)?.exports().count(), 1);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`ToWatError::NoBinary`](crate::ToWatError::NoBinary) if the
+    /// module was deserialized: its executable doesn't hold its binary.
+    #[cfg(all(feature = "compiler", feature = "wasmprinter"))]
+    pub fn to_wat(&self) -> Result<String, crate::ToWatError> {
+        crate::sys::text::artifact_to_wat(&self.artifact)
+    }
+
+    /// Returns how many of the functions the module defines are compiled.
+    ///
+    /// All of them are unless the engine compiles the modules in
+    /// [`ModuleCompileMode::Lazy`](crate::ModuleCompileMode::Lazy), where each function is
+    /// compiled the first time an instance of the module calls it.
+    pub fn compiled_function_count(&self) -> usize {
+        self.artifact.compiled_function_count()
+    }
+
     /// Returns the [`Store`] where the `Instance` belongs.
     pub fn store(&self) -> &Store {
         &self.store
@@ -169,6 +561,8 @@ impl Module {
 
 impl fmt::Debug for Module {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("Module").finish()
+        f.debug_struct("Module")
+            .field("name", &self.name())
+            .finish()
     }
 }
@@ -9,7 +9,9 @@
 //! ```
 use std::marker::PhantomData;
 
-use crate::sys::externals::function::{DynamicFunction, VMDynamicFunction};
+use crate::sys::externals::function::{write_value_for_call, DynamicFunction, VMDynamicFunction};
+#[cfg(unix)]
+use crate::sys::AsyncCall;
 use crate::sys::{FromToNativeWasmType, Function, RuntimeError, Store, WasmTypeList};
 use std::panic::{catch_unwind, AssertUnwindSafe};
 use wasmer_types::NativeWasmType;
@@ -27,6 +29,9 @@ pub struct NativeFunc<Args = (), Rets = ()> {
 
 unsafe impl<Args, Rets> Send for NativeFunc<Args, Rets> {}
 
+/// A [`NativeFunc`], under the name used by newer versions of the Wasmer API.
+pub type TypedFunction<Args = (), Rets = ()> = NativeFunc<Args, Rets>;
+
 impl<Args, Rets> NativeFunc<Args, Rets>
 where
     Args: WasmTypeList,
@@ -122,7 +127,32 @@ macro_rules! impl_native_traits {
         {
             /// Call the typed func and return results.
             pub fn call(&self, $( $x: $x, )* ) -> Result<Rets, RuntimeError> {
+                if self.exported.vm_function.is_instance_closed() {
+                    return Err(RuntimeError::instance_closed());
+                }
+                if self.exported.vm_function.is_instance_suspended() {
+                    return Err(RuntimeError::instance_suspended());
+                }
+                let _entry = self
+                    .exported
+                    .vm_function
+                    .enter_instance()
+                    .ok_or_else(RuntimeError::concurrent_reentry)?;
+                let mut args = Some(( $( $x, )* ));
+                // The call only keeps the references it is passed and returns alive until it
+                // returns, so the return values are read within it.
+                let outcome = unsafe {
+                    wasmer_vm::catch_traps_with_result(|| {
+                        let ( $( $x, )* ) = args.take().unwrap();
+                        self.call_in_activation($( $x, )*)
+                    })
+                };
+                outcome.map_err(|trap| self.store.runtime_error(trap, self.exported.vm_function.instance_id()))?
+            }
+
+            fn call_in_activation(&self, $( $x: $x, )* ) -> Result<Rets, RuntimeError> {
                 if !self.is_host() {
+                    self.exported.vm_function.record_host_call();
                     // We assume the trampoline is always going to be present for
                     // Wasm functions
                     let trampoline = self.exported.vm_function.call_trampoline.expect("Call trampoline not found in wasm function");
@@ -149,7 +179,7 @@ macro_rules! impl_native_traits {
                             self.address(),
                             args_rets.as_mut_ptr() as *mut u8,
                         )
-                    }?;
+                    }.map_err(|trap| self.store.runtime_error(trap, self.exported.vm_function.instance_id()))?;
                     let num_rets = rets_list.len();
                     if !using_rets_array && num_rets > 0 {
                         let src_pointer = params_list.as_ptr();
@@ -180,9 +210,9 @@ macro_rules! impl_native_traits {
                     match self.arg_kind() {
                         VMFunctionKind::Static => {
                             let results = catch_unwind(AssertUnwindSafe(|| unsafe {
-                                let f = std::mem::transmute::<_, unsafe extern "C" fn( VMFunctionEnvironment, $( $x, )*) -> Rets::CStruct>(self.address());
+                                let f = std::mem::transmute::<_, unsafe extern "C" fn( VMFunctionEnvironment, $( $x::Native, )*) -> Rets::CStruct>(self.address());
                                 // We always pass the vmctx
-                                f( self.vmctx(), $( $x, )* )
+                                f( self.vmctx(), $( $x.to_native(), )* )
                             })).map_err(|e| RuntimeError::new(format!("{:?}", e)))?;
                             Ok(Rets::from_c_struct(results))
                         },
@@ -199,7 +229,7 @@ macro_rules! impl_native_traits {
                             let mut_rets = rets_list_array.as_mut() as *mut [i128] as *mut i128;
                             for (i, ret) in results.iter().enumerate() {
                                 unsafe {
-                                    ret.write_value_to(mut_rets.add(i));
+                                    write_value_for_call(ret, mut_rets.add(i));
                                 }
                             }
                             Ok(Rets::from_array(rets_list_array))
@@ -208,6 +238,20 @@ macro_rules! impl_native_traits {
                 }
             }
 
+            /// Call the typed func from a future, see [`Function::call_async`].
+            #[cfg(unix)]
+            pub fn call_async(&self, $( $x: $x, )* ) -> AsyncCall<Rets>
+            where
+                $( $x: Send + 'static, )*
+                Rets: Send + 'static,
+            {
+                let function = self.clone();
+                AsyncCall::new(
+                    &self.store,
+                    self.exported.vm_function.instance_ref.clone(),
+                    move || function.call($( $x, )*),
+                )
+            }
         }
     };
 }
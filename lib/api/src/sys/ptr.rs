@@ -7,6 +7,7 @@
 //! related bugs when implementing an ABI.
 
 use crate::sys::cell::WasmCell;
+use crate::sys::mem_access::{MemoryAccessError, WasmSlice};
 use crate::sys::{externals::Memory, FromToNativeWasmType};
 use std::{cell::Cell, marker::PhantomData, mem};
 use wasmer_types::ValueType;
@@ -44,6 +45,22 @@ impl<T: Copy, Ty> WasmPtr<T, Ty> {
     }
 }
 
+/// Methods for `WasmPtr`s to data that can be copied in and out of memory, namely to types
+/// that implement [`ValueType`].
+impl<T: Copy + ValueType> WasmPtr<T, Item> {
+    /// Read the value pointed to, checking that it is within the memory.
+    #[inline]
+    pub fn read(self, memory: &Memory) -> Result<T, MemoryAccessError> {
+        memory.read_value(self.offset.into())
+    }
+
+    /// Write `value` where this `WasmPtr` points, checking that it is within the memory.
+    #[inline]
+    pub fn write(self, memory: &Memory, value: T) -> Result<(), MemoryAccessError> {
+        memory.write_value(self.offset.into(), value)
+    }
+}
+
 #[inline(always)]
 fn align_pointer(ptr: usize, align: usize) -> usize {
     // clears bits below aligment amount (assumes power of 2) to align pointer
@@ -121,6 +138,35 @@ impl<T: Copy + ValueType> WasmPtr<T, Array> {
     }
 }
 
+impl<T: Copy + ValueType> WasmPtr<T, Array> {
+    /// Get the `length` values starting where this `WasmPtr` points, checking that they are
+    /// within the memory.
+    ///
+    /// Unlike [`WasmPtr::deref`], the slice doesn't need to be aligned, and remains usable
+    /// after the memory grows.
+    #[inline]
+    pub fn slice(
+        self,
+        memory: &Memory,
+        length: u32,
+    ) -> Result<WasmSlice<'_, T>, MemoryAccessError> {
+        WasmSlice::new(memory, self.offset.into(), length.into())
+    }
+}
+
+impl WasmPtr<u8, Array> {
+    /// Read the UTF-8 string of `str_len` bytes starting where this `WasmPtr` points.
+    pub fn read_utf8_string(
+        self,
+        memory: &Memory,
+        str_len: u32,
+    ) -> Result<String, MemoryAccessError> {
+        let mut bytes = vec![0; str_len as usize];
+        memory.read(self.offset.into(), &mut bytes)?;
+        String::from_utf8(bytes).map_err(|_| MemoryAccessError::NonUtf8String)
+    }
+}
+
 unsafe impl<T: Copy, Ty> FromToNativeWasmType for WasmPtr<T, Ty> {
     type Native = i32;
 
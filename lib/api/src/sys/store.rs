@@ -1,10 +1,17 @@
 use crate::sys::tunables::BaseTunables;
+use crate::sys::{RuntimeError, TrapInfo};
 use std::fmt;
+use std::panic;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 #[cfg(all(feature = "compiler", feature = "engine"))]
 use wasmer_compiler::CompilerConfig;
 use wasmer_engine::Engine;
-use wasmer_vm::Tunables;
+use wasmer_vm::{InstanceId, Trap, Tunables};
+
+/// A hook called with the traps raised while calling into Wasm, see
+/// [`Store::set_trap_hook`].
+pub type TrapHook = dyn Fn(&TrapInfo) + Send + Sync;
 
 /// The store represents all global state that can be manipulated by
 /// WebAssembly programs. It consists of the runtime representation
@@ -20,6 +27,12 @@ use wasmer_vm::Tunables;
 pub struct Store {
     engine: Arc<dyn Engine + Send + Sync>,
     tunables: Arc<dyn Tunables + Send + Sync>,
+    epoch: Arc<AtomicU64>,
+    resume_host_panics: bool,
+    trap_hook: Option<Arc<TrapHook>>,
+    /// The size of the stacks the async calls run on.
+    #[cfg(unix)]
+    pub(crate) async_stack_size: usize,
 }
 
 impl Store {
@@ -39,6 +52,11 @@ impl Store {
         Self {
             engine: engine.cloned(),
             tunables: Arc::new(tunables),
+            epoch: Arc::new(AtomicU64::new(0)),
+            resume_host_panics: false,
+            trap_hook: None,
+            #[cfg(unix)]
+            async_stack_size: 2 << 20,
         }
     }
 
@@ -52,6 +70,81 @@ impl Store {
         &self.engine
     }
 
+    /// Advance the epoch of the store by one.
+    ///
+    /// This can be called from any thread, to stop the code compiled with epoch interruption
+    /// (see `Singlepass::enable_epoch_interruption`) that runs in the instances of this store
+    /// once the epoch reaches their deadline.
+    pub fn increment_epoch(&self) {
+        self.epoch.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Returns the current epoch of the store.
+    pub fn epoch(&self) -> u64 {
+        self.epoch.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn epoch_counter(&self) -> &Arc<AtomicU64> {
+        &self.epoch
+    }
+
+    /// Resume the panics of host functions called from Wasm instead of returning them as
+    /// errors.
+    ///
+    /// A host function panicking never unwinds through the Wasm frames: the panic is caught
+    /// at the boundary, the values of the host function are dropped, and the Wasm frames are
+    /// left as a trap leaves them. By default, the call from the host then returns a
+    /// [`RuntimeError`] for which `is_host_panic` is true, with the panic message as its
+    /// message. When enabled, the call resumes the panic with its original payload instead.
+    ///
+    /// This applies to the modules created with this store after it is set.
+    pub fn resume_host_panics(&mut self, enable: bool) -> &mut Self {
+        self.resume_host_panics = enable;
+        self
+    }
+
+    /// Set a hook called with each trap raised while calling into Wasm, before the trap is
+    /// converted into the [`RuntimeError`] the call returns.
+    ///
+    /// The hook is not called from the signal handler: it is called on the thread that made
+    /// the call, once the Wasm frames were left and the call is back on the host stack, so
+    /// it may lock, allocate and log. It is only called for the traps with a trap code, not
+    /// for the errors and panics of host functions nor for uncaught exceptions.
+    ///
+    /// This applies to the modules created with this store after it is set.
+    pub fn set_trap_hook(&mut self, hook: Box<TrapHook>) -> &mut Self {
+        self.trap_hook = Some(Arc::from(hook));
+        self
+    }
+
+    /// Set the size of the stacks the async calls run on, 2 MiB by default.
+    ///
+    /// Each async call (see `Function::call_async`) runs on a stack of its own, allocated
+    /// when the call is made, on which the Wasm frames and the host functions they call
+    /// must fit.
+    #[cfg(unix)]
+    pub fn async_stack_size(&mut self, size: usize) -> &mut Self {
+        self.async_stack_size = size;
+        self
+    }
+
+    /// Convert a trap raised while calling into `instance` into an error, after telling the
+    /// trap hook about it, resuming the panic of a host function if the store resumes host
+    /// panics.
+    pub(crate) fn runtime_error(&self, trap: Trap, instance: Option<InstanceId>) -> RuntimeError {
+        if let Some(hook) = &self.trap_hook {
+            if let Some(info) = TrapInfo::from_trap(&trap, instance) {
+                hook(&info);
+            }
+        }
+        match trap {
+            Trap::HostPanic { payload, .. } if self.resume_host_panics => {
+                panic::resume_unwind(payload.into_inner())
+            }
+            trap => RuntimeError::from_trap(trap),
+        }
+    }
+
     /// Checks whether two stores are identical. A store is considered
     /// equal to another store if both have the same engine. The
     /// tunables are excluded from the logic.
@@ -0,0 +1,308 @@
+//! Converting modules from and into the WebAssembly text format.
+
+#[cfg(feature = "wat")]
+use std::borrow::Cow;
+#[cfg(feature = "compiler")]
+use std::fmt::Write;
+use thiserror::Error;
+#[cfg(feature = "compiler")]
+use wasmer_engine_universal::UniversalArtifact;
+#[cfg(feature = "wat")]
+use wasmer_types::Features;
+#[cfg(feature = "compiler")]
+use wasmer_types::{ConstOp, ExportIndex, GlobalInit, GlobalType, Mutability, Type};
+#[cfg(feature = "compiler")]
+use wasmer_vm::VMImportType;
+
+/// An error rendering a module into the WebAssembly text format.
+#[derive(Error, Debug)]
+pub enum ToWatError {
+    /// The module was loaded from an executable, which doesn't hold its binary.
+    #[error("the module was loaded from an executable, without its binary")]
+    NoBinary,
+    /// The binary couldn't be parsed.
+    #[error("failed to print the module: {0}")]
+    Print(String),
+}
+
+/// Render a WebAssembly binary in the text format, naming the functions and locals as its
+/// name section does.
+pub fn wasm2wat(bytes: &[u8]) -> Result<String, ToWatError> {
+    wasmprinter::print_bytes(bytes).map_err(|error| ToWatError::Print(format!("{:#}", error)))
+}
+
+/// An error parsing the WebAssembly text format, located in the text.
+#[cfg(feature = "wat")]
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("{message}\n --> {line}:{column}\n{snippet}")]
+pub struct WatParseError {
+    /// What is wrong with the text.
+    pub message: String,
+    /// The line of the error, from 1.
+    pub line: usize,
+    /// The column of the error, in characters from 1.
+    pub column: usize,
+    /// The line of the error, followed by a line with a caret under the column.
+    pub snippet: String,
+}
+
+#[cfg(feature = "wat")]
+impl WatParseError {
+    /// The error `message`, at the byte `offset` of `text`.
+    fn at(text: &str, offset: usize, message: String) -> Self {
+        let start = text[..offset].rfind('\n').map_or(0, |newline| newline + 1);
+        let end = text[offset..]
+            .find('\n')
+            .map_or(text.len(), |newline| offset + newline);
+        // The caret is indented with the tabs of the line, to stay under the column.
+        let indent = text[start..offset]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect::<String>();
+        Self {
+            message,
+            line: text[..offset].matches('\n').count() + 1,
+            column: text[start..offset].chars().count() + 1,
+            snippet: format!("{}\n{}^", text[start..end].trim_end_matches('\r'), indent),
+        }
+    }
+}
+
+/// Convert the WebAssembly text format to a binary, or return `bytes` if they already are a
+/// binary.
+///
+/// The text may hold custom sections as `(@custom "name" "data")` annotations. Errors are
+/// located in the text.
+#[cfg(feature = "wat")]
+pub fn wat2wasm(bytes: &[u8]) -> Result<Cow<'_, [u8]>, WatParseError> {
+    if bytes.starts_with(b"\0asm") {
+        return Ok(Cow::Borrowed(bytes));
+    }
+    let text = std::str::from_utf8(bytes).map_err(|error| {
+        // The text is located up to the invalid bytes.
+        let valid = &bytes[..error.valid_up_to()];
+        let valid = std::str::from_utf8(valid).expect("the bytes are valid up to there");
+        WatParseError::at(
+            valid,
+            valid.len(),
+            "the text is not valid UTF-8".to_string(),
+        )
+    })?;
+    parse_wat(text).map(Cow::Owned)
+}
+
+/// Like [`wat2wasm`], for `text` whose syntax can only use the proposals enabled in
+/// `features`, the way an engine with these features validates the binary.
+///
+/// The SIMD, threads and floating-point instructions and types are refused where they are used
+/// when their feature is disabled.
+#[cfg(feature = "wat")]
+pub fn wat2wasm_with_features(text: &str, features: &Features) -> Result<Vec<u8>, WatParseError> {
+    let binary = parse_wat(text)?;
+    // The text was parsed, so the lexer has no error left to find.
+    for token in wast::lexer::Lexer::new(text).flatten() {
+        if let wast::lexer::Token::Keyword(keyword) = token {
+            if let Some(feature) = disabled_feature(keyword, features) {
+                let offset = keyword.as_ptr() as usize - text.as_ptr() as usize;
+                let message = format!("{} support is not enabled", feature);
+                return Err(WatParseError::at(text, offset, message));
+            }
+        }
+    }
+    Ok(binary)
+}
+
+#[cfg(feature = "wat")]
+fn parse_wat(text: &str) -> Result<Vec<u8>, WatParseError> {
+    let error = |error: wast::Error| {
+        WatParseError::at(text, span_offset(text, error.span()), error.message())
+    };
+    let buffer = wast::parser::ParseBuffer::new(text).map_err(error)?;
+    let mut wat = wast::parser::parse::<wast::Wat>(&buffer).map_err(error)?;
+    wat.module.encode().map_err(error)
+}
+
+/// The byte offset of `span` in `text`, which `wast` only gives as a line and column.
+#[cfg(feature = "wat")]
+fn span_offset(text: &str, span: wast::Span) -> usize {
+    let (line, column) = span.linecol_in(text);
+    let line_start: usize = text
+        .split_terminator('\n')
+        .take(line)
+        .map(|line| line.len() + 1)
+        .sum();
+    (line_start + column).min(text.len())
+}
+
+/// The proposal `keyword` belongs to, if `features` doesn't enable it.
+#[cfg(feature = "wat")]
+fn disabled_feature(keyword: &str, features: &Features) -> Option<&'static str> {
+    const SIMD_SHAPES: [&str; 7] = ["v128", "i8x16", "i16x8", "i32x4", "i64x2", "f32x4", "f64x2"];
+    let prefix = keyword.split('.').next().unwrap_or(keyword);
+    if SIMD_SHAPES.contains(&prefix) {
+        (!features.simd).then(|| "SIMD")
+    } else if keyword == "shared" || keyword == "atomic.fence" || keyword.contains(".atomic.") {
+        (!features.threads).then(|| "threads")
+    } else if prefix == "f32" || prefix == "f64" {
+        (!features.floats).then(|| "floating-point")
+    } else {
+        None
+    }
+}
+
+/// Render the module of `artifact` in the text format, with the globals and exports the
+/// middlewares of the compiler appended to it.
+#[cfg(feature = "compiler")]
+pub(crate) fn artifact_to_wat(artifact: &UniversalArtifact) -> Result<String, ToWatError> {
+    use wasmer_compiler::wasmparser::{Parser, Payload};
+
+    let binary = artifact.binary().ok_or(ToWatError::NoBinary)?;
+    let mut wat = wasm2wat(binary)?;
+
+    let print_error = |error: wasmer_compiler::wasmparser::BinaryReaderError| {
+        ToWatError::Print(error.to_string())
+    };
+    let mut binary_globals = 0;
+    let mut binary_exports = Vec::new();
+    for payload in Parser::new(0).parse_all(binary) {
+        match payload.map_err(print_error)? {
+            Payload::GlobalSection(reader) => binary_globals = reader.get_count() as usize,
+            Payload::ExportSection(reader) => {
+                for export in reader {
+                    binary_exports.push(export.map_err(print_error)?.field.to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut appended = String::new();
+    let imported_globals = artifact
+        .imports()
+        .iter()
+        .filter(|import| matches!(import.ty, VMImportType::Global(_)))
+        .count();
+    let globals = artifact.local_globals().iter().enumerate();
+    for (index, (ty, init)) in globals.skip(binary_globals) {
+        let index = imported_globals + index;
+        writeln!(
+            appended,
+            "  (global (;{};) {} ({}))",
+            index,
+            global_type(ty),
+            const_expr(ty, init)
+        )
+        .unwrap();
+    }
+    for (name, index) in artifact.exports() {
+        if binary_exports.iter().any(|export| export == name) {
+            continue;
+        }
+        let (kind, index) = match index {
+            ExportIndex::Function(index) => ("func", index.as_u32()),
+            ExportIndex::Table(index) => ("table", index.as_u32()),
+            ExportIndex::Memory(index) => ("memory", index.as_u32()),
+            ExportIndex::Global(index) => ("global", index.as_u32()),
+            ExportIndex::Tag(index) => ("tag", index.as_u32()),
+        };
+        writeln!(appended, "  (export {} ({} {}))", string(name), kind, index).unwrap();
+    }
+
+    // The fields are appended at the end of the module, before its closing parenthesis.
+    let end = wat.trim_end().len() - 1;
+    wat.insert_str(end, &appended);
+    Ok(wat)
+}
+
+#[cfg(feature = "compiler")]
+fn value_type(ty: Type) -> &'static str {
+    match ty {
+        Type::I32 => "i32",
+        Type::I64 => "i64",
+        Type::F32 => "f32",
+        Type::F64 => "f64",
+        Type::V128 => "v128",
+        Type::ExternRef => "externref",
+        Type::FuncRef => "funcref",
+    }
+}
+
+#[cfg(feature = "compiler")]
+fn global_type(ty: &GlobalType) -> String {
+    match ty.mutability {
+        Mutability::Const => value_type(ty.ty).to_string(),
+        Mutability::Var => format!("(mut {})", value_type(ty.ty)),
+    }
+}
+
+#[cfg(feature = "compiler")]
+fn const_expr(ty: &GlobalType, init: &GlobalInit) -> String {
+    match init {
+        GlobalInit::I32Const(value) => format!("i32.const {}", value),
+        GlobalInit::I64Const(value) => format!("i64.const {}", value),
+        GlobalInit::F32Const(value) => format!(
+            "f32.const {}",
+            float(*value as f64, (value.to_bits() & 0x7f_ffff).into())
+        ),
+        GlobalInit::F64Const(value) => format!(
+            "f64.const {}",
+            float(*value, value.to_bits() & 0xf_ffff_ffff_ffff)
+        ),
+        GlobalInit::V128Const(value) => {
+            let mut expr = "v128.const i8x16".to_string();
+            for byte in value.iter() {
+                write!(expr, " {}", byte).unwrap();
+            }
+            expr
+        }
+        GlobalInit::GetGlobal(index) => format!("global.get {}", index.as_u32()),
+        GlobalInit::RefNullConst if ty.ty == Type::ExternRef => "ref.null extern".to_string(),
+        GlobalInit::RefNullConst => "ref.null func".to_string(),
+        GlobalInit::RefFunc(index) => format!("ref.func {}", index.as_u32()),
+        GlobalInit::Expr(expr) => {
+            let ops = expr.ops.iter().map(|op| match op {
+                ConstOp::I32Const(value) => format!("i32.const {}", value),
+                ConstOp::I64Const(value) => format!("i64.const {}", value),
+                ConstOp::GlobalGet(index) => format!("global.get {}", index.as_u32()),
+                ConstOp::I32Add => "i32.add".to_string(),
+                ConstOp::I32Sub => "i32.sub".to_string(),
+                ConstOp::I32Mul => "i32.mul".to_string(),
+                ConstOp::I64Add => "i64.add".to_string(),
+                ConstOp::I64Sub => "i64.sub".to_string(),
+                ConstOp::I64Mul => "i64.mul".to_string(),
+            });
+            ops.collect::<Vec<_>>().join(" ")
+        }
+    }
+}
+
+/// A float literal for `value`, whose mantissa is `mantissa` if it is a NaN.
+///
+/// The shortest decimal representation the float is printed as rounds back to it.
+#[cfg(feature = "compiler")]
+fn float(value: f64, mantissa: u64) -> String {
+    let sign = if value.is_sign_negative() { "-" } else { "" };
+    if value.is_nan() {
+        format!("{}nan:{:#x}", sign, mantissa)
+    } else if value.is_infinite() {
+        format!("{}inf", sign)
+    } else {
+        value.to_string()
+    }
+}
+
+/// A string literal for `name`.
+#[cfg(feature = "compiler")]
+fn string(name: &str) -> String {
+    let mut literal = "\"".to_string();
+    for byte in name.bytes() {
+        match byte {
+            b'"' | b'\\' => write!(literal, "\\{}", byte as char),
+            0x20..=0x7e => write!(literal, "{}", byte as char),
+            _ => write!(literal, "\\{:02x}", byte),
+        }
+        .unwrap();
+    }
+    literal.push('"');
+    literal
+}
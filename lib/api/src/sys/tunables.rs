@@ -3,11 +3,15 @@ use std::ptr::NonNull;
 use std::sync::Arc;
 use target_lexicon::PointerWidth;
 use wasmer_compiler::Target;
-use wasmer_vm::MemoryError;
 use wasmer_vm::{
     LinearMemory, LinearTable, Memory, MemoryStyle, Table, TableStyle, Tunables,
     VMMemoryDefinition, VMTableDefinition,
 };
+use wasmer_vm::{MemoryError, MemoryGrowCallback, ResourceLimiter};
+
+/// A function choosing the style of some memories, returning `None` for the memories whose
+/// style the tunables choose as usual.
+pub type MemoryStyleOverride = dyn Fn(&MemoryType) -> Option<MemoryStyle> + Send + Sync;
 
 /// Tunable parameters for WebAssembly compilation.
 /// This is the reference implementation of the `Tunables` trait,
@@ -27,6 +31,20 @@ pub struct BaseTunables {
 
     /// The size in bytes of the offset guard for dynamic heaps.
     pub dynamic_memory_offset_guard_size: u64,
+
+    /// The callback to call before the memories grow, for example to account for them or to
+    /// limit them further than their maximum.
+    pub memory_grow_callback: Option<Arc<MemoryGrowCallback>>,
+
+    /// The limiter of the memories, tables and instances, for example a [`StaticLimiter`]
+    /// capping their totals.
+    ///
+    /// [`StaticLimiter`]: crate::StaticLimiter
+    pub resource_limiter: Option<Arc<dyn ResourceLimiter>>,
+
+    /// The override of the style of the memories, for example to make them all dynamic with
+    /// [`BaseTunables::dynamic_memory_style`] so that they don't reserve their static bound.
+    pub memory_style_override: Option<Arc<MemoryStyleOverride>>,
 }
 
 impl BaseTunables {
@@ -61,6 +79,27 @@ impl BaseTunables {
             static_memory_bound,
             static_memory_offset_guard_size,
             dynamic_memory_offset_guard_size,
+            memory_grow_callback: None,
+            resource_limiter: None,
+            memory_style_override: None,
+        }
+    }
+
+    /// The static style: the memory reserves `static_memory_bound` pages, followed by the
+    /// static offset guard, and never moves.
+    pub fn static_memory_style(&self) -> MemoryStyle {
+        MemoryStyle::Static {
+            // Bound can be larger than the maximum for performance reasons
+            bound: self.static_memory_bound,
+            offset_guard_size: self.static_memory_offset_guard_size,
+        }
+    }
+
+    /// The dynamic style: the memory only reserves its current size, followed by the dynamic
+    /// offset guard, and is moved when it grows past its allocation.
+    pub fn dynamic_memory_style(&self) -> MemoryStyle {
+        MemoryStyle::Dynamic {
+            offset_guard_size: self.dynamic_memory_offset_guard_size,
         }
     }
 }
@@ -68,21 +107,22 @@ impl BaseTunables {
 impl Tunables for BaseTunables {
     /// Get a `MemoryStyle` for the provided `MemoryType`
     fn memory_style(&self, memory: &MemoryType) -> MemoryStyle {
+        if let Some(style) = self
+            .memory_style_override
+            .as_ref()
+            .and_then(|style_override| style_override(memory))
+        {
+            return style;
+        }
         // A heap with a maximum that doesn't exceed the static memory bound specified by the
         // tunables make it static.
         //
-        // If the module doesn't declare an explicit maximum treat it as 4GiB.
-        let maximum = memory.maximum.unwrap_or_else(Pages::max_value);
+        // If the module doesn't declare an explicit maximum treat it as its whole index space.
+        let maximum = memory.maximum.unwrap_or_else(|| memory.max_pages());
         if maximum <= self.static_memory_bound {
-            MemoryStyle::Static {
-                // Bound can be larger than the maximum for performance reasons
-                bound: self.static_memory_bound,
-                offset_guard_size: self.static_memory_offset_guard_size,
-            }
+            self.static_memory_style()
         } else {
-            MemoryStyle::Dynamic {
-                offset_guard_size: self.dynamic_memory_offset_guard_size,
-            }
+            self.dynamic_memory_style()
         }
     }
 
@@ -144,6 +184,112 @@ impl Tunables for BaseTunables {
             vm_definition_location,
         )?))
     }
+
+    fn memory_grow_callback(&self) -> Option<Arc<MemoryGrowCallback>> {
+        self.memory_grow_callback.clone()
+    }
+
+    fn resource_limiter(&self) -> Option<Arc<dyn ResourceLimiter>> {
+        self.resource_limiter.clone()
+    }
+}
+
+/// Tunables clamping the maximum of the memories to a limit, and otherwise delegating to the
+/// tunables they wrap.
+///
+/// The memories without a maximum get the limit as their maximum, so that they never grow
+/// past it, and their style is chosen from that maximum: under [`BaseTunables`], a small
+/// enough limit makes all the memories static even if they don't declare a maximum. Memories
+/// whose minimum exceeds the limit can't be created.
+#[derive(Clone)]
+pub struct LimitingTunables<T: Tunables> {
+    /// The maximum size of the memories.
+    limit: Pages,
+    /// The tunables choosing the styles of the memories and creating them.
+    base: T,
+}
+
+impl<T: Tunables> LimitingTunables<T> {
+    /// Wrap `base`, clamping the maximum of the memories to `limit`.
+    pub fn new(base: T, limit: Pages) -> Self {
+        Self { limit, base }
+    }
+
+    /// The memory type `requested`, with its maximum clamped to the limit.
+    fn adjust_memory(&self, requested: &MemoryType) -> MemoryType {
+        let mut adjusted = *requested;
+        adjusted.maximum = Some(match requested.maximum {
+            Some(maximum) if maximum < self.limit => maximum,
+            _ => self.limit,
+        });
+        adjusted
+    }
+
+    /// Check that the memories of type `ty` fit in the limit.
+    fn validate_memory(&self, ty: &MemoryType) -> Result<(), MemoryError> {
+        if ty.minimum > self.limit {
+            return Err(MemoryError::MinimumMemoryTooLarge {
+                min_requested: ty.minimum,
+                max_allowed: self.limit,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl<T: Tunables> Tunables for LimitingTunables<T> {
+    fn memory_style(&self, memory: &MemoryType) -> MemoryStyle {
+        self.base.memory_style(&self.adjust_memory(memory))
+    }
+
+    fn table_style(&self, table: &TableType) -> TableStyle {
+        self.base.table_style(table)
+    }
+
+    fn create_host_memory(
+        &self,
+        ty: &MemoryType,
+        style: &MemoryStyle,
+    ) -> Result<Arc<dyn Memory>, MemoryError> {
+        self.validate_memory(ty)?;
+        self.base.create_host_memory(&self.adjust_memory(ty), style)
+    }
+
+    unsafe fn create_vm_memory(
+        &self,
+        ty: &MemoryType,
+        style: &MemoryStyle,
+        vm_definition_location: NonNull<VMMemoryDefinition>,
+    ) -> Result<Arc<dyn Memory>, MemoryError> {
+        self.validate_memory(ty)?;
+        self.base
+            .create_vm_memory(&self.adjust_memory(ty), style, vm_definition_location)
+    }
+
+    fn create_host_table(
+        &self,
+        ty: &TableType,
+        style: &TableStyle,
+    ) -> Result<Arc<dyn Table>, String> {
+        self.base.create_host_table(ty, style)
+    }
+
+    unsafe fn create_vm_table(
+        &self,
+        ty: &TableType,
+        style: &TableStyle,
+        vm_definition_location: NonNull<VMTableDefinition>,
+    ) -> Result<Arc<dyn Table>, String> {
+        self.base.create_vm_table(ty, style, vm_definition_location)
+    }
+
+    fn memory_grow_callback(&self) -> Option<Arc<MemoryGrowCallback>> {
+        self.base.memory_grow_callback()
+    }
+
+    fn resource_limiter(&self) -> Option<Arc<dyn ResourceLimiter>> {
+        self.base.resource_limiter()
+    }
 }
 
 #[cfg(test)]
@@ -156,6 +302,9 @@ mod tests {
             static_memory_bound: Pages(2048),
             static_memory_offset_guard_size: 128,
             dynamic_memory_offset_guard_size: 256,
+            memory_grow_callback: None,
+            resource_limiter: None,
+            memory_style_override: None,
         };
 
         // No maximum
@@ -188,4 +337,83 @@ mod tests {
             s => panic!("Unexpected memory style: {:?}", s),
         }
     }
+
+    #[test]
+    fn memory_style_override() {
+        let mut tunables = BaseTunables {
+            static_memory_bound: Pages(2048),
+            static_memory_offset_guard_size: 128,
+            dynamic_memory_offset_guard_size: 256,
+            memory_grow_callback: None,
+            resource_limiter: None,
+            memory_style_override: None,
+        };
+        let dynamic = tunables.dynamic_memory_style();
+        tunables.memory_style_override = Some(Arc::new(move |memory: &MemoryType| {
+            if memory.shared {
+                None
+            } else {
+                Some(dynamic.clone())
+            }
+        }));
+
+        // Overridden
+        let requested = MemoryType::new(3, Some(16), false);
+        assert_eq!(
+            tunables.memory_style(&requested),
+            MemoryStyle::Dynamic {
+                offset_guard_size: 256
+            }
+        );
+
+        // Left to the tunables
+        let requested = MemoryType::new(3, Some(16), true);
+        assert_eq!(
+            tunables.memory_style(&requested),
+            MemoryStyle::Static {
+                bound: Pages(2048),
+                offset_guard_size: 128,
+            }
+        );
+    }
+
+    #[test]
+    fn limiting_tunables() {
+        let base = BaseTunables {
+            static_memory_bound: Pages(2048),
+            static_memory_offset_guard_size: 0x1_0000,
+            dynamic_memory_offset_guard_size: 0x2_0000,
+            memory_grow_callback: None,
+            resource_limiter: None,
+            memory_style_override: None,
+        };
+        let tunables = LimitingTunables::new(base, Pages(16));
+
+        // The maximum of the memories without one is the limit, which makes them static.
+        let style = MemoryStyle::Static {
+            bound: Pages(2048),
+            offset_guard_size: 0x1_0000,
+        };
+        let requested = MemoryType::new(3, None, false);
+        assert_eq!(tunables.memory_style(&requested), style);
+        let memory = tunables.create_host_memory(&requested, &style).unwrap();
+        assert_eq!(memory.ty().maximum, Some(Pages(16)));
+
+        // Larger maximums are clamped, smaller ones are kept.
+        let requested = MemoryType::new(3, Some(5_000_000), false);
+        let memory = tunables.create_host_memory(&requested, &style).unwrap();
+        assert_eq!(memory.ty().maximum, Some(Pages(16)));
+        let requested = MemoryType::new(3, Some(8), false);
+        let memory = tunables.create_host_memory(&requested, &style).unwrap();
+        assert_eq!(memory.ty().maximum, Some(Pages(8)));
+
+        let requested = MemoryType::new(17, None, false);
+        assert_eq!(
+            tunables.create_host_memory(&requested, &style).unwrap_err(),
+            MemoryError::MinimumMemoryTooLarge {
+                min_requested: Pages(17),
+                max_allowed: Pages(16),
+            }
+        );
+    }
 }
@@ -3,8 +3,8 @@ use crate::sys::store::{Store, StoreObject};
 use crate::sys::RuntimeError;
 use wasmer_types::Value;
 pub use wasmer_types::{
-    ExportType, ExternType, FunctionType, GlobalType, MemoryType, Mutability, TableType,
-    Type as ValType,
+    ExportType, ExternType, FunctionType, GlobalType, Import as ImportType, MemoryType, Mutability,
+    TableType, TagType, Type as ValType,
 };
 use wasmer_vm::VMFuncRef;
 
@@ -75,6 +75,12 @@ impl ValFuncRef for Val {
                 wasmer_vm::TableElement::ExternRef(extern_ref.clone().into())
             }
             Self::FuncRef(None) => wasmer_vm::TableElement::FuncRef(VMFuncRef::null()),
+            // Dynamic host functions only get an address with the Wasm ABI when imported.
+            Self::FuncRef(Some(f)) if f.exported.vm_function.address.is_null() => {
+                return Err(RuntimeError::new(
+                    "dynamic host functions can't be stored in tables, use `Function::new_native`",
+                ))
+            }
             Self::FuncRef(Some(f)) => wasmer_vm::TableElement::FuncRef(f.vm_funcref()),
             _ => return Err(RuntimeError::new("val is not reference")),
         })
@@ -1,19 +1,33 @@
 use crate::address_map::get_function_address_map;
 use crate::config::IntrinsicKind;
-use crate::{config::Singlepass, emitter_x64::*, machine::Machine, x64_decl::*};
+#[cfg(feature = "debug-asm")]
+use crate::debug_asm::DebugAsmEmitter;
+use crate::{
+    config::Singlepass,
+    const_fold::{constant_log2, fold_binop, fold_unop},
+    emitter_x64::*,
+    machine::{CallFrame, Machine, DEFAULT_PROBE_STRIDE},
+    peephole::PeepholeEmitter,
+    unwind::{UnwindFrame, UnwindOp},
+    unwind_winx64::create_unwind_info,
+    x64_decl::*,
+};
 use dynasmrt::{x64::X64Relocation, AssemblyOffset, DynamicLabel, DynasmApi, VecAssembler};
 use memoffset::offset_of;
 use smallvec::{smallvec, SmallVec};
 use std::cmp::max;
 use std::iter;
+use std::mem;
+use std::ops::Range;
 use wasmer_compiler::wasmparser::{
     MemoryImmediate, Operator, Type as WpType, TypeOrFuncType as WpTypeOrFuncType,
 };
 use wasmer_compiler::{
-    CallingConvention, CompiledFunction, CompiledFunctionFrameInfo, CustomSection,
-    CustomSectionProtection, FunctionBody, FunctionBodyData, InstructionAddressMap,
+    operator_name, CallingConvention, CompileError, CompiledFunction, CompiledFunctionFrameInfo,
+    CompiledFunctionUnwindInfo, CpuFeature, CustomSection, CustomSectionProtection, FrameLayout,
+    FunctionBody, FunctionBodyData, InstructionAddressMap, LandingPad, MachineStats, MemoryAccess,
     ModuleTranslationState, Relocation, RelocationKind, RelocationTarget, SectionBody,
-    SectionIndex, SourceLoc,
+    SectionIndex, SourceLoc, Target, TrapInformation,
 };
 use wasmer_types::{
     entity::{EntityRef, PrimaryMap, SecondaryMap},
@@ -21,11 +35,19 @@ use wasmer_types::{
 };
 use wasmer_types::{
     FunctionIndex, GlobalIndex, LocalFunctionIndex, LocalMemoryIndex, MemoryIndex, ModuleInfo,
-    SignatureIndex, TableIndex, Type,
+    SignatureIndex, TableIndex, TagIndex, Type,
 };
-use wasmer_vm::{TableStyle, TrapCode, VMBuiltinFunctionIndex, VMOffsets};
+use wasmer_vm::{MemoryStyle, TableStyle, TrapCode, VMBuiltinFunctionIndex, VMOffsets};
+
+#[cfg(not(feature = "debug-asm"))]
+type Assembler = PeepholeEmitter<VecAssembler<X64Relocation>>;
+#[cfg(feature = "debug-asm")]
+type Assembler = PeepholeEmitter<DebugAsmEmitter<VecAssembler<X64Relocation>>>;
 
-type Assembler = VecAssembler<X64Relocation>;
+/// A scalar AVX operation, with its two sources and destination.
+type AvxOp = fn(&mut Assembler, XMM, XMMOrMemory, XMM);
+/// A scalar AVX blend, with its two sources, mask and destination.
+type AvxBlend = fn(&mut Assembler, XMM, XMMOrMemory, XMM, XMM);
 
 /// The singlepass per-function code generator.
 pub(crate) struct FuncGen<'a> {
@@ -42,8 +64,12 @@ pub(crate) struct FuncGen<'a> {
     /// Offsets of vmctx fields.
     vmoffsets: &'a VMOffsets,
 
-    // // Memory plans.
-    // memory_styles: &'a PrimaryMap<MemoryIndex, MemoryStyle>,
+    /// The target the code is compiled for, whose CPU features decide which instructions can be
+    /// used.
+    target: &'a Target,
+
+    /// The styles of the memories, which decide how their accesses are checked.
+    memory_styles: &'a PrimaryMap<MemoryIndex, MemoryStyle>,
 
     // // Table plans.
     // table_styles: &'a PrimaryMap<TableIndex, TableStyle>,
@@ -60,6 +86,9 @@ pub(crate) struct FuncGen<'a> {
     /// Types of the local variables, including arguments.
     local_types: wasmer_types::partial_sum_map::PartialSumMap<u32, WpType>,
 
+    /// Indices of the 16-byte locals, in runs of consecutive locals.
+    v128_locals: Vec<Range<u32>>,
+
     /// Value stack.
     value_stack: Vec<Location>,
 
@@ -69,6 +98,9 @@ pub(crate) struct FuncGen<'a> {
     /// Location to patch when we know the max stack depth.
     stack_check_offset: AssemblyOffset,
 
+    /// Locations to patch with the max stack depth as well, in the exits of the tail calls.
+    tail_call_stack_checks: Vec<AssemblyOffset>,
+
     /// Metadata about floating point values on the stack.
     fp_stack: Vec<FloatValue>,
 
@@ -84,31 +116,73 @@ pub(crate) struct FuncGen<'a> {
     /// Relocation information.
     relocations: Vec<Relocation>,
 
-    /// A set of special labels for trapping.
-    special_labels: SpecialLabelSet,
+    /// The label trapping with a stack overflow from the prologue.
+    stack_overflow: DynamicLabel,
+
+    /// The traps of the instructions, emitted after the function body.
+    trap_stubs: Vec<TrapStub>,
 
     /// The source location for the current operator.
     src_loc: u32,
 
     /// Map from byte offset into wasm function to range of native instructions.
     ///
-    // Ordered by increasing InstructionAddressMap::srcloc.
+    // Ordered by increasing InstructionAddressMap::code_offset.
     instructions_address_map: Vec<InstructionAddressMap>,
 
     /// Calling convention to use.
     calling_convention: CallingConvention,
+
+    /// Layout of the stack frame, known once the epilogue is emitted.
+    frame_layout: Option<FrameLayout>,
+
+    /// The fuel to charge before each operator, empty without metering.
+    fuel_costs: Vec<u64>,
+
+    /// The index of the next operator to feed.
+    operator_index: usize,
+
+    /// Whether the function has `try` blocks, see `enable_exception_handlers`.
+    exception_handlers: bool,
+
+    /// The landing pads of the calls in the bodies of the `try` blocks, emitted after the
+    /// function body.
+    landing_pads: Vec<LandingPadStub>,
 }
 
-struct SpecialLabelSet {
-    integer_division_by_zero: DynamicLabel,
-    integer_overflow: DynamicLabel,
-    bad_conversion_to_integer: DynamicLabel,
-    heap_access_oob: DynamicLabel,
-    table_access_oob: DynamicLabel,
-    indirect_call_null: DynamicLabel,
-    bad_signature: DynamicLabel,
-    gas_limit_exceeded: DynamicLabel,
-    stack_overflow: DynamicLabel,
+/// The landing pad of a call made in the body of a `try` block, where the exceptions thrown by
+/// the callee arrive, with the exception in RAX.
+///
+/// It tears the frame of the call down as the code following the call does, and jumps to the
+/// dispatch of the block.
+struct LandingPadStub {
+    /// The offset of the return address of the call.
+    return_offset: usize,
+    /// The frame set up around the call.
+    frame: CallFrame,
+    /// The distance from RBP down to RSP at the call.
+    rsp: usize,
+    /// The dispatch of the `try` block, see `TryFrame`.
+    dispatch: DynamicLabel,
+}
+
+/// A trap raised by the instruction at `srcloc`, jumped to from its code.
+///
+/// Each instruction gets traps of its own so that the address map attributes them to it.
+struct TrapStub {
+    label: DynamicLabel,
+    code: TrapCode,
+    srcloc: u32,
+    /// The access out of bounds, for the traps of memory accesses.
+    memory_access: Option<MemoryAccessStub>,
+}
+
+/// The memory access a trap stub raises the out-of-bounds trap of.
+struct MemoryAccessStub {
+    /// Where the wasm address of the access is, which is passed to the trap handler.
+    address: Location,
+    /// What the stub records in its trap information.
+    access: MemoryAccess,
 }
 
 /// Metadata about a floating-point value.
@@ -142,28 +216,6 @@ impl FloatValue {
             depth,
         }
     }
-
-    fn promote(self, depth: usize) -> FloatValue {
-        FloatValue {
-            canonicalization: match self.canonicalization {
-                Some(CanonicalizeType::F32) => Some(CanonicalizeType::F64),
-                Some(CanonicalizeType::F64) => panic!("cannot promote F64"),
-                None => None,
-            },
-            depth,
-        }
-    }
-
-    fn demote(self, depth: usize) -> FloatValue {
-        FloatValue {
-            canonicalization: match self.canonicalization {
-                Some(CanonicalizeType::F64) => Some(CanonicalizeType::F32),
-                Some(CanonicalizeType::F32) => panic!("cannot demote F32"),
-                None => None,
-            },
-            depth,
-        }
-    }
 }
 
 /// Type of a pending canonicalization floating point value.
@@ -238,9 +290,68 @@ pub(crate) struct ControlFrame {
 
     pub(crate) loop_like: bool,
     pub(crate) if_else: IfElseState,
+    pub(crate) params: SmallVec<[WpType; 8]>,
     pub(crate) returns: SmallVec<[WpType; 1]>,
+    /// Where branches leave the results, if there are several of them
+    ///
+    /// For blocks, these are stack slots right below the block on the value stack, which become
+    /// the results at the end of the block. For functions, these are the return locations. A
+    /// single result is passed in RAX instead, or in XMM0 if it is a 16-byte value.
+    pub(crate) return_slots: SmallVec<[Location; 8]>,
+    /// Copies of the params of `loop` and `if` blocks, on the value stack above `return_slots`
+    ///
+    /// Branches back to a loop header leave the new params there, and `else` reloads the params
+    /// from there.
+    pub(crate) param_slots: SmallVec<[Location; 8]>,
     pub(crate) value_stack_depth: usize,
     pub(crate) fp_stack_depth: usize,
+    /// The state of `try` blocks, `None` for all other frames.
+    pub(crate) try_frame: Option<TryFrame>,
+}
+
+impl ControlFrame {
+    /// The types of the values passed by branches to this frame, or by falling through its end
+    /// if `end`, and where they go.
+    fn branch_values(&self, end: bool) -> (SmallVec<[WpType; 8]>, SmallVec<[Location; 8]>) {
+        if self.loop_like && !end {
+            (self.params.clone(), self.param_slots.clone())
+        } else if self.returns.len() == 1 {
+            let dest = match self.returns[0] {
+                WpType::V128 => Location::XMM(XMM::XMM0),
+                _ => Location::GPR(GPR::RAX),
+            };
+            (self.returns.iter().cloned().collect(), smallvec![dest])
+        } else {
+            (
+                self.returns.iter().cloned().collect(),
+                self.return_slots.clone(),
+            )
+        }
+    }
+}
+
+/// The state of a `try` block.
+///
+/// The exceptions thrown in the body of the block, by its calls, `throw`s and nested blocks,
+/// arrive at the dispatch of the block with the exception in RAX. The dispatch follows the body,
+/// and stores the exception in its slot, right below the block on the value stack, where the
+/// handlers load it from. Each `catch` tests the tag of the exception, and jumps to the next
+/// handler if it doesn't match; the exceptions no handler catches are thrown again from the
+/// end of the block.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct TryFrame {
+    pub(crate) dispatch: DynamicLabel,
+    pub(crate) exception: Location,
+    pub(crate) state: TryState,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub(crate) enum TryState {
+    /// The body of the block is running, and its exceptions go to the dispatch.
+    Body,
+    /// A handler is running, with the label of the next one to try if the current `catch`
+    /// doesn't match.
+    Catch(Option<DynamicLabel>),
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -270,7 +381,23 @@ impl<'a> FuncGen<'a> {
     }
 
     fn get_location_released(&mut self, loc: Location) -> Location {
-        self.machine.release_locations(&mut self.assembler, &[loc]);
+        self.machine.release_locations(&[loc]);
+        loc
+    }
+
+    /// Acquires the `preferred` register for a new value of type `ty`.
+    ///
+    /// Values evicted from the register are updated on the value stack. See
+    /// `Machine::acquire_locations_in`.
+    fn acquire_location_in(&mut self, ty: WpType, preferred: Location) -> Location {
+        let loc = self
+            .machine
+            .acquire_locations_in(&mut self.assembler, ty, preferred);
+        for (from, to) in self.machine.take_evictions() {
+            for value in self.value_stack.iter_mut().filter(|value| **value == from) {
+                *value = to;
+            }
+        }
         loc
     }
 
@@ -301,6 +428,23 @@ impl<'a> FuncGen<'a> {
         I2O1 { loc_a, loc_b, ret }
     }
 
+    /// Pops the constant operand of `op`, a multiplication or an unsigned division or remainder,
+    /// if it is a power of two, and returns its base-2 logarithm.
+    ///
+    /// The operands of a multiplication are swapped if only the first one is such a constant.
+    fn pop_power_of_two_operand(&mut self, op: &Operator) -> Option<u32> {
+        let len = self.value_stack.len();
+        if matches!(op, Operator::I32Mul | Operator::I64Mul)
+            && constant_log2(op, self.value_stack[len - 1]).is_none()
+            && constant_log2(op, self.value_stack[len - 2]).is_some()
+        {
+            self.value_stack.swap(len - 2, len - 1);
+        }
+        let log2 = constant_log2(op, self.value_stack[len - 1])?;
+        self.pop_value_released();
+        Some(log2)
+    }
+
     fn emit_call(&mut self, function: FunctionIndex) -> Result<(), CodegenError> {
         let sig_index = *self.module.functions.get(function).unwrap();
         let sig = self.module.signatures.get(sig_index).unwrap();
@@ -309,10 +453,41 @@ impl<'a> FuncGen<'a> {
         let return_types: SmallVec<[WpType; 1]> =
             sig.results().iter().cloned().map(type_to_wp_type).collect();
 
-        let params: SmallVec<[_; 8]> = self
+        let params = self.pop_call_params(&param_types);
+
+        if self.try_intrinsic(function, &params) {
+            // This was genereated as an intrinsic, we're done.
+            return Ok(());
+        }
+
+        let stack_results = self.acquire_call_stack_results(&return_types);
+
+        // RAX is preserved on entry to `emit_call_sysv` callback.
+        self.emit_load_function_address(function);
+
+        self.emit_call_native_with_results(
+            |this| {
+                this.assembler.emit_call_location(Location::GPR(GPR::RAX));
+            },
+            value_words(&param_types, &params).into_iter(),
+            &value_words(&stack_result_types(&return_types), &stack_results),
+            true,
+        )?;
+
+        self.machine.release_locations_only_stack(&params);
+
+        self.push_call_results(&return_types, &stack_results);
+        Ok(())
+    }
+
+    /// Pops the `param_types` params of a call off the value stack, releasing their registers,
+    /// with the 16-byte ones spilled to the stack and the floats canonicalized if needed.
+    fn pop_call_params(&mut self, param_types: &[WpType]) -> SmallVec<[Location; 8]> {
+        let mut params: SmallVec<[_; 8]> = self
             .value_stack
             .drain(self.value_stack.len() - param_types.len()..)
             .collect();
+        self.spill_v128_params(&mut params);
         self.machine.release_locations_only_regs(&params);
 
         // Pop arguments off the FP stack and canonicalize them if needed.
@@ -335,59 +510,350 @@ impl<'a> FuncGen<'a> {
                 break;
             }
         }
+        params
+    }
 
-        if self.try_intrinsic(function, &params) {
-            // This was genereated as an intrinsic, we're done.
-            return Ok(());
-        }
-
-        let reloc_at = self.assembler.get_offset().0 + self.assembler.arch_mov64_imm_offset();
+    /// Loads the address of `function` into RAX, with a relocation.
+    ///
+    /// The address is relative to the code, which keeps it independent of where the code is
+    /// loaded.
+    fn emit_load_function_address(&mut self, function: FunctionIndex) {
         // Imported functions are called through trampolines placed as custom sections.
         let reloc_target = match self.module.import_counts.local_function_index(function) {
             Ok(local) => RelocationTarget::LocalFunc(local),
             Err(imp) => RelocationTarget::CustomSection(SectionIndex::from_u32(imp.as_u32())),
         };
+        self.assembler.emit_lea_pc_relative(GPR::RAX);
+        // The displacement is relative to the end of the instruction, right after it.
         self.relocations.push(Relocation {
-            kind: RelocationKind::Abs8,
+            kind: RelocationKind::X86PCRel4,
             reloc_target,
-            offset: reloc_at as u32,
-            addend: 0,
+            offset: (self.assembler.get_offset().0 - 4) as u32,
+            addend: -4,
         });
+    }
 
-        // RAX is preserved on entry to `emit_call_sysv` callback.
-        // The Imm64 value is relocated by the JIT linker.
+    /// Loads the `VMCallerCheckedAnyfunc` at `func_index` in `table_index` into RAX, trapping if
+    /// it is out of bounds, null, or not of the signature `index`.
+    fn emit_load_indirect_callee(
+        &mut self,
+        table_index: TableIndex,
+        index: SignatureIndex,
+        func_index: Location,
+    ) {
+        let table_base = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
+        let table_count = self
+            .machine
+            .steal_temp_gpr(&mut self.assembler, &[Location::GPR(table_base)]);
+
+        self.emit_load_table_definition(table_index, table_base, table_count);
+
+        self.assembler
+            .emit_cmp(Size::S32, func_index, Location::GPR(table_count));
+        let trap = self.trap_label(TrapCode::TableAccessOutOfBounds);
+        self.assembler.emit_jmp(Condition::BelowEqual, trap);
+        self.assembler
+            .emit_mov(Size::S32, func_index, Location::GPR(table_count));
+        self.assembler
+            .emit_imul_imm32_gpr64(self.vmoffsets.size_of_vm_funcref() as u32, table_count);
+        self.assembler.emit_add(
+            Size::S64,
+            Location::GPR(table_base),
+            Location::GPR(table_count),
+        );
+
+        // deref the table to get a VMFuncRef
         self.assembler.emit_mov(
             Size::S64,
-            Location::Imm64(std::u64::MAX),
-            Location::GPR(GPR::RAX),
+            Location::Memory(table_count, self.vmoffsets.vm_funcref_anyfunc_ptr() as i32),
+            Location::GPR(table_count),
         );
+        // Trap if the FuncRef is null
+        self.assembler
+            .emit_cmp(Size::S64, Location::Imm32(0), Location::GPR(table_count));
+        let trap = self.trap_label(TrapCode::IndirectCallToNull);
+        self.assembler.emit_jmp(Condition::Equal, trap);
 
-        self.emit_call_native(
-            |this| {
-                this.assembler.emit_call_location(Location::GPR(GPR::RAX));
-            },
-            params.iter().copied(),
-        )?;
+        // Trap if signature mismatches. The engine-wide id of the expected signature is read
+        // from the vmctx rather than patched in, to keep the code the same in every process.
+        self.assembler.emit_mov(
+            Size::S32,
+            Location::Memory(
+                Machine::get_vmctx_reg(),
+                self.vmoffsets.vmctx_vmshared_signature_id(index) as i32,
+            ),
+            Location::GPR(table_base),
+        );
+        self.assembler.emit_cmp(
+            Size::S32,
+            Location::GPR(table_base),
+            Location::Memory(
+                table_count,
+                (self.vmoffsets.vmcaller_checked_anyfunc_type_index() as usize) as i32,
+            ),
+        );
+        let trap = self.trap_label(TrapCode::BadSignature);
+        self.assembler.emit_jmp(Condition::NotEqual, trap);
 
         self.machine
-            .release_locations_only_stack(&mut self.assembler, &params);
+            .restore_stolen_gpr(&mut self.assembler, table_count);
+        self.machine
+            .restore_stolen_gpr(&mut self.assembler, table_base);
 
-        if !return_types.is_empty() {
-            let ret =
-                self.machine
-                    .acquire_locations(&mut self.assembler, &[(return_types[0])], false)[0];
+        if table_count != GPR::RAX {
+            self.assembler.emit_mov(
+                Size::S64,
+                Location::GPR(table_count),
+                Location::GPR(GPR::RAX),
+            );
+        }
+    }
+
+    /// The number of 8-byte slots of the area a function taking `param_types` and returning
+    /// `return_types` reads its stack arguments from, and writes its stack results to.
+    fn stack_arg_area_words(&self, param_types: &[WpType], return_types: &[WpType]) -> usize {
+        let (_, n_param_words) = param_words(param_types);
+        let stack_params = (0..n_param_words)
+            .filter(|i| {
+                matches!(
+                    Machine::get_param_location(1 + i, self.calling_convention),
+                    Location::Memory(_, _)
+                )
+            })
+            .count();
+        let stack_results = stack_result_types(return_types)
+            .iter()
+            .map(|ty| if *ty == WpType::V128 { 2 } else { 1 })
+            .sum::<usize>();
+        max(stack_params, stack_results)
+    }
+
+    /// Emits a tail call of `callee` with `params`: the frame of the function is torn down, the
+    /// arguments are moved to where the callee reads them, and the code jumps to the callee,
+    /// which returns to the caller of the function.
+    ///
+    /// The arguments are first pushed below the frame, so that moving them to their registers
+    /// and to the area the function got its own stack arguments in never overwrites the ones
+    /// left to move. The area is resized to the stack arguments of the callee, growing down into
+    /// the frame or shrinking back up, and the return address moved along: the caller then gets
+    /// RSP back elsewhere than before the call, which `emit_call_native_with_results` and
+    /// `gen_std_trampoline` account for. As every function finds the area sized after its own
+    /// signature, the area never grows further than that of the largest callee over a chain of
+    /// tail calls, and never shrinks past the area the caller reserved.
+    fn emit_return_call(
+        &mut self,
+        callee: TailCallee,
+        param_types: &[WpType],
+        return_types: &[WpType],
+        params: &[Location],
+    ) {
+        let calling_convention = self.calling_convention;
+        self.machine.require_frame();
+        self.machine.flush_stack_adjustment(&mut self.assembler);
+        self.emit_tail_call_stack_check();
+
+        // The area keeps a multiple of 16 bytes, for RSP to be aligned at the entry of the
+        // callee as after a call. Its offsets are relative to RBP.
+        let own_params: SmallVec<[WpType; 8]> = self
+            .signature
+            .params()
+            .iter()
+            .cloned()
+            .map(type_to_wp_type)
+            .collect();
+        let own_results: SmallVec<[WpType; 1]> = self
+            .signature
+            .results()
+            .iter()
+            .cloned()
+            .map(type_to_wp_type)
+            .collect();
+        let area_words = |words: usize| ((words + 1) & !1) as i32;
+        let grow = area_words(self.stack_arg_area_words(param_types, return_types))
+            - area_words(self.stack_arg_area_words(&own_params, &own_results));
+        let return_address = 8 - 8 * grow;
+        let shadow_space = match calling_convention {
+            CallingConvention::WindowsFastcall => 32,
+            _ => 0,
+        };
+        let first_stack_arg = return_address + 8 + shadow_space;
+
+        // The arguments are pushed below the area, which may grow below RSP. The pages skipped
+        // are probed from the top down, as in `Machine::init_locals`.
+        let mut rsp = -((self.machine.get_stack_offset() as u32 + RED_ZONE_SIZE) as i32);
+        if return_address < rsp {
+            self.assembler.emit_lea(
+                Size::S64,
+                Location::Memory(GPR::RBP, return_address),
+                Location::GPR(GPR::RSP),
+            );
+            let probe_stride = self.target.page_size().unwrap_or(DEFAULT_PROBE_STRIDE) as i32;
+            let mut probe = rsp - probe_stride;
+            while probe > return_address {
+                self.assembler.emit_mov(
+                    Size::S64,
+                    Location::Imm32(0),
+                    Location::Memory(GPR::RBP, probe),
+                );
+                probe -= probe_stride;
+            }
+            rsp = return_address;
+        }
+
+        // The saved RBP and the return address may be overwritten by the stack arguments, so
+        // they are pushed as well, and the callee of an indirect call, read from RAX.
+        let mut staged: SmallVec<[Location; 16]> =
+            smallvec![Location::Memory(GPR::RBP, 0), Location::Memory(GPR::RBP, 8)];
+        if let TailCallee::Indirect = callee {
+            staged.push(Location::Memory(
+                GPR::RAX,
+                self.vmoffsets.vmcaller_checked_anyfunc_vmctx() as i32,
+            ));
+            staged.push(Location::Memory(
+                GPR::RAX,
+                self.vmoffsets.vmcaller_checked_anyfunc_func_ptr() as i32,
+            ));
+        }
+        let first_param = staged.len();
+        staged.extend(value_words(param_types, params));
+        for loc in &staged {
+            match *loc {
+                Location::Imm64(_) => {
+                    self.assembler
+                        .emit_mov(Size::S64, *loc, Location::GPR(GPR::RAX));
+                    self.assembler.emit_push(Size::S64, Location::GPR(GPR::RAX));
+                }
+                Location::XMM(_) => {
+                    self.assembler
+                        .emit_sub(Size::S64, Location::Imm32(8), Location::GPR(GPR::RSP));
+                    self.assembler
+                        .emit_mov(Size::S64, *loc, Location::Memory(GPR::RSP, 0));
+                }
+                _ => self.assembler.emit_push(Size::S64, *loc),
+            }
+        }
+        rsp -= 8 * staged.len() as i32;
+        let n_staged = staged.len();
+        let pushed = |i: usize| Location::Memory(GPR::RSP, 8 * (n_staged - 1 - i) as i32);
+
+        let mut stack_args = 0;
+        for i in first_param..n_staged {
+            match Machine::get_param_location(1 + i - first_param, calling_convention) {
+                Location::GPR(gpr) => {
+                    self.assembler
+                        .emit_mov(Size::S64, pushed(i), Location::GPR(gpr));
+                }
+                _ => stack_args += 1,
+            }
+        }
+        let vmctx = match callee {
+            TailCallee::Direct(_) => Location::GPR(Machine::get_vmctx_reg()),
+            TailCallee::Indirect => pushed(2),
+        };
+        self.assembler.emit_mov(
+            Size::S64,
+            vmctx,
+            Machine::get_param_location(0, calling_convention),
+        );
+
+        // The stack arguments are the last ones, and the callee-saved registers must be read
+        // from the frame before they overwrite it.
+        self.machine.reload_callee_saved(&mut self.assembler);
+        for (slot, i) in (n_staged - stack_args..n_staged).enumerate() {
+            self.assembler
+                .emit_mov(Size::S64, pushed(i), Location::GPR(GPR::RAX));
+            self.assembler.emit_mov(
+                Size::S64,
+                Location::GPR(GPR::RAX),
+                Location::Memory(GPR::RBP, first_stack_arg + 8 * slot as i32),
+            );
+        }
+        if grow != 0 {
+            self.assembler
+                .emit_mov(Size::S64, pushed(1), Location::GPR(GPR::RAX));
+            self.assembler.emit_mov(
+                Size::S64,
+                Location::GPR(GPR::RAX),
+                Location::Memory(GPR::RBP, return_address),
+            );
+        }
+
+        match callee {
+            TailCallee::Direct(function) => self.emit_load_function_address(function),
+            TailCallee::Indirect => {
+                self.assembler
+                    .emit_mov(Size::S64, pushed(3), Location::GPR(GPR::RAX))
+            }
+        }
+        self.assembler
+            .emit_mov(Size::S64, pushed(0), Location::GPR(GPR::RBP));
+        self.assembler.emit_lea(
+            Size::S64,
+            Location::Memory(GPR::RSP, return_address - rsp),
+            Location::GPR(GPR::RSP),
+        );
+        self.machine
+            .record_unwind_op(&mut self.assembler, UnwindOp::PopFramePointer);
+        self.assembler.emit_jmp_location(Location::GPR(GPR::RAX));
+        self.machine
+            .record_unwind_op(&mut self.assembler, UnwindOp::Return);
+    }
+
+    /// Acquires the stack locations receiving the results of a call that are not returned in
+    /// registers, see `Machine::get_return_location`.
+    fn acquire_call_stack_results(&mut self, return_types: &[WpType]) -> SmallVec<[Location; 8]> {
+        let tys = stack_result_types(return_types);
+        if tys.is_empty() {
+            return smallvec![];
+        }
+        self.machine
+            .acquire_stack_locations(&mut self.assembler, &tys)
+    }
+
+    /// Pushes the results of a call onto the value stack, right after the call.
+    ///
+    /// `stack_results` are the results already copied by `emit_call_native_with_results`.
+    fn push_call_results(&mut self, return_types: &[WpType], stack_results: &[Location]) {
+        if let [ty] = return_types {
+            let ret = self
+                .machine
+                .acquire_locations(&mut self.assembler, &[*ty], false)[0];
             self.value_stack.push(ret);
-            if return_types[0].is_float() {
+            if ty.is_float() {
                 self.assembler
                     .emit_mov(Size::S64, Location::XMM(XMM::XMM0), ret);
                 self.fp_stack
                     .push(FloatValue::new(self.value_stack.len() - 1));
+            } else if *ty == WpType::V128 {
+                self.emit_move_v128(Location::XMM(XMM::XMM0), ret);
             } else {
                 self.assembler
                     .emit_mov(Size::S64, Location::GPR(GPR::RAX), ret);
             }
+            return;
+        }
+        let (words, _) = result_words(return_types);
+        let mut stack_results = stack_results.iter();
+        for (ty, word) in return_types.iter().zip(words) {
+            let ret = if word >= 2 {
+                *stack_results.next().unwrap()
+            } else {
+                let loc = self
+                    .machine
+                    .acquire_locations(&mut self.assembler, &[*ty], false)[0];
+                self.assembler.emit_mov(
+                    Size::S64,
+                    Machine::get_return_location(word, self.calling_convention),
+                    loc,
+                );
+                loc
+            };
+            self.value_stack.push(ret);
+            if ty.is_float() {
+                self.fp_stack
+                    .push(FloatValue::new(self.value_stack.len() - 1));
+            }
         }
-        Ok(())
     }
 
     /// Try emitting an intrinsic for a function call of function at index.
@@ -417,7 +883,7 @@ impl<'a> FuncGen<'a> {
         assert_eq!(counter_offset, 0);
         assert_eq!(gas_limit_offset, 8);
         assert_eq!(opcode_cost_offset, 16);
-        let base_reg = self.machine.acquire_temp_gpr().unwrap();
+        let base_reg = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
         // Load gas counter base.
         self.assembler.emit_mov(
             Size::S64,
@@ -427,7 +893,9 @@ impl<'a> FuncGen<'a> {
             ),
             Location::GPR(base_reg),
         );
-        let current_burnt_reg = self.machine.acquire_temp_gpr().unwrap();
+        let current_burnt_reg = self
+            .machine
+            .steal_temp_gpr(&mut self.assembler, &[Location::GPR(base_reg)]);
         // Read current gas counter.
         self.assembler.emit_mov(
             Size::S64,
@@ -435,7 +903,10 @@ impl<'a> FuncGen<'a> {
             Location::GPR(current_burnt_reg),
         );
         // Read opcode cost.
-        let count_reg = self.machine.acquire_temp_gpr().unwrap();
+        let count_reg = self.machine.steal_temp_gpr(
+            &mut self.assembler,
+            &[Location::GPR(base_reg), Location::GPR(current_burnt_reg)],
+        );
         self.assembler.emit_mov(
             Size::S64,
             Location::Memory(base_reg, opcode_cost_offset),
@@ -452,8 +923,8 @@ impl<'a> FuncGen<'a> {
             Location::GPR(count_reg),
             Location::GPR(current_burnt_reg),
         );
-        self.assembler
-            .emit_jmp(Condition::Overflow, self.special_labels.integer_overflow);
+        let trap = self.trap_label(TrapCode::IntegerOverflow);
+        self.assembler.emit_jmp(Condition::Overflow, trap);
         // Compare with the limit.
         self.assembler.emit_cmp(
             Size::S64,
@@ -466,48 +937,176 @@ impl<'a> FuncGen<'a> {
             Location::GPR(current_burnt_reg),
             Location::Memory(base_reg, counter_offset),
         );
-        self.assembler.emit_jmp(
-            Condition::BelowEqual,
-            self.special_labels.gas_limit_exceeded,
-        );
-        self.machine.release_temp_gpr(base_reg);
-        self.machine.release_temp_gpr(current_burnt_reg);
-        self.machine.release_temp_gpr(count_reg);
+        let trap = self.trap_label(TrapCode::GasExceeded);
+        self.assembler.emit_jmp(Condition::BelowEqual, trap);
+        self.machine
+            .restore_stolen_gpr(&mut self.assembler, base_reg);
+        self.machine
+            .restore_stolen_gpr(&mut self.assembler, current_burnt_reg);
+        self.machine
+            .restore_stolen_gpr(&mut self.assembler, count_reg);
     }
 
-    fn emit_trap(&mut self, code: TrapCode) {
-        let label = self.assembler.get_label();
-        self.assembler.emit_label(label);
-        self.assembler.emit_lea_label(
-            label,
-            Machine::get_param_location(0, self.calling_convention),
-        );
+    /// Take `cost` from the fuel of the instance, trapping without taking any when less is
+    /// left.
+    fn emit_fuel_charge(&mut self, cost: u64) {
+        let fuel = Location::Memory(Machine::get_vmctx_reg(), self.vmoffsets.vmctx_fuel() as i32);
+        let tmp = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
+        self.assembler.emit_mov(Size::S64, fuel, Location::GPR(tmp));
+        self.assembler
+            .emit_sub(Size::S64, Location::Imm64(cost), Location::GPR(tmp));
+        let trap = self.trap_label(TrapCode::OutOfFuel);
+        self.assembler.emit_jmp(Condition::Below, trap);
+        self.assembler.emit_mov(Size::S64, Location::GPR(tmp), fuel);
+        self.machine.restore_stolen_gpr(&mut self.assembler, tmp);
+    }
+
+    /// Call into the runtime when the epoch counter has reached the epoch deadline of the
+    /// instance, which either sets a new deadline or traps.
+    fn emit_epoch_check(&mut self) -> Result<(), CodegenError> {
+        let vmctx = Machine::get_vmctx_reg();
+        let skip = self.assembler.get_label();
+        self.machine.flush_stack_adjustment(&mut self.assembler);
+        let tmp = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
         self.assembler.emit_mov(
-            Size::S32,
-            Location::Imm32(code as u32),
-            Machine::get_param_location(1, self.calling_convention),
-        );
-        // Align stack.
-        self.assembler.emit_and(
             Size::S64,
-            Location::Imm32(0xfffffff0),
-            Location::GPR(GPR::RSP),
+            Location::Memory(vmctx, self.vmoffsets.vmctx_epoch_pointer() as i32),
+            Location::GPR(tmp),
         );
-        let offset = self.vmoffsets.vmctx_trap_handler();
         self.assembler
-            .emit_call_location(Location::Memory(Machine::get_vmctx_reg(), offset as i32));
+            .emit_mov(Size::S64, Location::Memory(tmp, 0), Location::GPR(tmp));
+        self.assembler.emit_cmp(
+            Size::S64,
+            Location::Memory(vmctx, self.vmoffsets.vmctx_epoch_deadline() as i32),
+            Location::GPR(tmp),
+        );
+        self.machine.restore_stolen_gpr(&mut self.assembler, tmp);
+        self.assembler.emit_jmp(Condition::Below, skip);
+        self.assembler.emit_mov(
+            Size::S64,
+            Location::Memory(
+                vmctx,
+                self.vmoffsets.vmctx_builtin_function(
+                    VMBuiltinFunctionIndex::get_epoch_deadline_reached_index(),
+                ) as i32,
+            ),
+            Location::GPR(GPR::RAX),
+        );
+        self.emit_call_native(
+            |this| {
+                this.assembler.emit_call_register(GPR::RAX);
+            },
+            // [vmctx]
+            iter::empty(),
+        )?;
+        self.assembler.emit_label(skip);
+        Ok(())
     }
 
-    /// Canonicalizes the floating point value at `input` into `output`.
-    fn canonicalize_nan(&mut self, sz: Size, input: Location, output: Location) {
-        let tmp1 = self.machine.acquire_temp_xmm().unwrap();
-        let tmp2 = self.machine.acquire_temp_xmm().unwrap();
-        let tmp3 = self.machine.acquire_temp_xmm().unwrap();
-
-        self.emit_relaxed_binop(Assembler::emit_mov, sz, input, Location::XMM(tmp1));
-        let tmpg1 = self.machine.acquire_temp_gpr().unwrap();
-
-        match sz {
+    /// Return the label to jump to in order to trap with `code` from the current instruction.
+    fn trap_label(&mut self, code: TrapCode) -> DynamicLabel {
+        let srcloc = self.src_loc;
+        let existing = self
+            .trap_stubs
+            .iter()
+            .rev()
+            .take_while(|stub| stub.srcloc == srcloc)
+            .find(|stub| stub.code == code && stub.memory_access.is_none());
+        if let Some(stub) = existing {
+            return stub.label;
+        }
+        let label = self.assembler.get_label();
+        self.trap_stubs.push(TrapStub {
+            label,
+            code,
+            srcloc,
+            memory_access: None,
+        });
+        label
+    }
+
+    /// Whether the memory the accesses go to has 64-bit addresses.
+    fn memory64(&self) -> bool {
+        self.module.memories[MemoryIndex::new(0)].memory64
+    }
+
+    /// Fails the compilation of `operator`, which the runtime only implements on 32-bit
+    /// memories, if memory `mem` is 64-bit.
+    fn check_memory32(&self, mem: u32, operator: &str) -> Result<(), CodegenError> {
+        if self.module.memories[MemoryIndex::from_u32(mem)].memory64 {
+            return Err(CodegenError {
+                message: format!("{} on 64-bit memories is not supported yet", operator),
+            });
+        }
+        Ok(())
+    }
+
+    /// Return the label to jump to in order to trap with `HeapAccessOutOfBounds` from the
+    /// current instruction, which accesses `size` bytes at the wasm address `address` plus
+    /// `offset` in the memory.
+    ///
+    /// `address` must still be valid where the label is jumped to from.
+    fn memory_access_trap_label(
+        &mut self,
+        address: Location,
+        offset: u32,
+        size: usize,
+    ) -> DynamicLabel {
+        let label = self.assembler.get_label();
+        self.trap_stubs.push(TrapStub {
+            label,
+            code: TrapCode::HeapAccessOutOfBounds,
+            srcloc: self.src_loc,
+            memory_access: Some(MemoryAccessStub {
+                address,
+                access: MemoryAccess {
+                    memory_index: MemoryIndex::new(0),
+                    offset: u64::from(offset),
+                    size: size as u32,
+                },
+            }),
+        });
+        label
+    }
+
+    fn emit_trap(&mut self, code: TrapCode) {
+        let label = self.assembler.get_label();
+        self.assembler.emit_label(label);
+        self.assembler.emit_lea_label(
+            label,
+            Machine::get_param_location(0, self.calling_convention),
+        );
+        self.assembler.emit_mov(
+            Size::S32,
+            Location::Imm32(code as u32),
+            Machine::get_param_location(1, self.calling_convention),
+        );
+        // Align stack. The stack of a frameless function is aligned already, and moving RSP
+        // would hide its frame from the unwinder.
+        if !self.machine.is_frameless() {
+            self.assembler.emit_and(
+                Size::S64,
+                Location::Imm32(0xfffffff0),
+                Location::GPR(GPR::RSP),
+            );
+        }
+        let offset = self.vmoffsets.vmctx_trap_handler();
+        self.assembler
+            .emit_call_location(Location::Memory(Machine::get_vmctx_reg(), offset as i32));
+    }
+
+    /// Canonicalizes the floating point value at `input` into `output`.
+    fn canonicalize_nan(&mut self, sz: Size, input: Location, output: Location) {
+        let tmp1 = self.machine.acquire_temp_xmm().unwrap();
+        let tmp2 = self.machine.acquire_temp_xmm().unwrap();
+        let tmp3 = self.machine.acquire_temp_xmm().unwrap();
+
+        self.emit_relaxed_binop(Assembler::emit_mov, sz, input, Location::XMM(tmp1));
+        let tmpg1 = self
+            .machine
+            .steal_temp_gpr(&mut self.assembler, &[input, output]);
+
+        match sz {
             Size::S32 => {
                 self.assembler
                     .emit_vcmpunordss(tmp1, XMMOrMemory::XMM(tmp1), tmp2);
@@ -539,57 +1138,527 @@ impl<'a> FuncGen<'a> {
 
         self.emit_relaxed_binop(Assembler::emit_mov, sz, Location::XMM(tmp1), output);
 
-        self.machine.release_temp_gpr(tmpg1);
+        self.machine.restore_stolen_gpr(&mut self.assembler, tmpg1);
         self.machine.release_temp_xmm(tmp3);
         self.machine.release_temp_xmm(tmp2);
         self.machine.release_temp_xmm(tmp1);
     }
 
-    /// Moves `loc` to a valid location for `div`/`idiv`.
-    fn emit_relaxed_xdiv(&mut self, signed: bool, sz: Size, loc: Location) {
-        self.assembler.emit_cmp(sz, Location::Imm32(0), loc);
-        self.assembler.emit_jmp(
-            Condition::Equal,
-            self.special_labels.integer_division_by_zero,
-        );
+    /// The canonicalization pending on the value at `depth` in the value stack, if NaN
+    /// canonicalization is enabled.
+    fn pending_canonicalization(&self, depth: usize) -> Option<CanonicalizeType> {
+        if !self.assembler.arch_supports_canonicalize_nan()
+            || !self.config.enable_nan_canonicalization
+        {
+            return None;
+        }
+        self.fp_stack
+            .iter()
+            .rev()
+            .find(|fp| fp.depth == depth)
+            .and_then(|fp| fp.canonicalization)
+    }
 
-        // Boundary checks for integer overflow. It clearly doesn't make sense for
-        // unsigned division, as numerator is of same size as the actual result, and divisor is
-        // always >= 1.
-        // For signed division we can get out of bound only when divide MIN_INT / -1,
-        // as result will be MAX_INT+1 so test for that case explicitly.
-        if signed {
-            let end = self.assembler.get_label();
-            // Check if divisor is -1.
-            self.assembler
-                .emit_cmp(sz, Location::Imm32(0xffffffff), loc);
-            self.assembler.emit_jmp(Condition::NotEqual, end);
-            // Check if numerator is MIN_INT.
-            match sz {
-                Size::S32 => {
-                    self.assembler.emit_cmp(
-                        sz,
-                        Location::Imm32(0x80000000u32),
-                        Location::GPR(GPR::RAX),
-                    );
+    /// The param and result types of a block of type `ty`.
+    fn block_signature(
+        &self,
+        ty: WpTypeOrFuncType,
+    ) -> (SmallVec<[WpType; 8]>, SmallVec<[WpType; 1]>) {
+        match ty {
+            WpTypeOrFuncType::Type(WpType::EmptyBlockType) => (smallvec![], smallvec![]),
+            WpTypeOrFuncType::Type(inner_ty) => (smallvec![], smallvec![inner_ty]),
+            WpTypeOrFuncType::FuncType(index) => {
+                let sig = &self.module.signatures[SignatureIndex::from_u32(index)];
+                (
+                    sig.params().iter().cloned().map(type_to_wp_type).collect(),
+                    sig.results().iter().cloned().map(type_to_wp_type).collect(),
+                )
+            }
+        }
+    }
+
+    /// Pushes the frame of a block of type `ty`, whose params are at the top of the value stack.
+    ///
+    /// Blocks with several results get their return slots below the params. With `copy_params`,
+    /// the params are also moved to param slots above those and taken off the value stack, to
+    /// be loaded back with `load_block_params` once all the paths into the block agree on the
+    /// machine state.
+    fn push_block_frame(
+        &mut self,
+        br_label: DynamicLabel,
+        loop_like: bool,
+        if_else: IfElseState,
+        ty: WpTypeOrFuncType,
+        copy_params: bool,
+    ) {
+        let (params, returns) = self.block_signature(ty);
+        let base = self.value_stack.len() - params.len();
+
+        let return_slots = if returns.len() > 1 {
+            self.machine
+                .acquire_stack_locations(&mut self.assembler, &returns)
+        } else {
+            smallvec![]
+        };
+        let param_slots = if copy_params && !params.is_empty() {
+            let slots = self
+                .machine
+                .acquire_stack_locations(&mut self.assembler, &params);
+            for (i, slot) in slots.iter().enumerate() {
+                let loc = self.value_stack[base + i];
+                match self.pending_canonicalization(base + i) {
+                    Some(cncl) => self.canonicalize_nan(cncl.to_size(), loc, *slot),
+                    None => self.emit_typed_move(params[i], loc, *slot),
                 }
-                Size::S64 => {
+            }
+            let released: SmallVec<[Location; 8]> = self.value_stack.drain(base..).collect();
+            self.machine.release_locations(&released);
+            while self.fp_stack.last().map_or(false, |fp| fp.depth >= base) {
+                self.fp_stack.pop();
+            }
+            slots
+        } else {
+            smallvec![]
+        };
+
+        let n_slots = return_slots.len() + param_slots.len();
+        for fp in self.fp_stack.iter_mut().filter(|fp| fp.depth >= base) {
+            fp.depth += n_slots;
+        }
+        let block_params = self.value_stack.split_off(base);
+        self.value_stack
+            .extend(return_slots.iter().chain(param_slots.iter()).cloned());
+        self.value_stack.extend(block_params);
+        let fp_stack_depth = self
+            .fp_stack
+            .iter()
+            .take_while(|fp| fp.depth < base)
+            .count();
+
+        self.control_stack.push(ControlFrame {
+            br_label,
+            loop_like,
+            if_else,
+            params,
+            returns,
+            return_slots,
+            param_slots,
+            value_stack_depth: base + n_slots,
+            fp_stack_depth,
+            try_frame: None,
+        });
+    }
+
+    /// Loads the params of the innermost block from its param slots onto the value stack.
+    fn load_block_params(&mut self) {
+        let frame = self.control_stack.last().unwrap();
+        if frame.param_slots.is_empty() {
+            return;
+        }
+        let params = frame.params.clone();
+        let slots = frame.param_slots.clone();
+        let locs = self
+            .machine
+            .acquire_locations(&mut self.assembler, &params, false);
+        for ((ty, slot), loc) in params.iter().zip(slots).zip(locs) {
+            self.emit_typed_move(*ty, slot, loc);
+            self.value_stack.push(loc);
+            if ty.is_float() {
+                // Canonicalized when stored in the slot.
+                self.fp_stack
+                    .push(FloatValue::new(self.value_stack.len() - 1));
+            }
+        }
+    }
+
+    /// Moves the values at the top of the value stack, of types `tys`, to `dests`,
+    /// canonicalizing floats on the way if needed.
+    fn emit_branch_moves(&mut self, tys: &[WpType], dests: &[Location]) {
+        let depth = self.value_stack.len() - dests.len();
+        if let [dest] = *dests {
+            let loc = self.value_stack[depth];
+            match self.pending_canonicalization(depth) {
+                Some(cncl) if tys[0].is_float() => self.canonicalize_nan(cncl.to_size(), loc, dest),
+                _ => self.emit_typed_move(tys[0], loc, dest),
+            }
+            return;
+        }
+
+        // Going through temporary registers could clobber the register destinations, so the
+        // floats are canonicalized in place first, and the memory destinations written next.
+        for (i, ty) in tys.iter().enumerate() {
+            if !ty.is_float() {
+                continue;
+            }
+            if let Some(cncl) = self.pending_canonicalization(depth + i) {
+                let loc = self.value_stack[depth + i];
+                self.canonicalize_nan(cncl.to_size(), loc, loc);
+            }
+        }
+        for (i, dest) in dests.iter().enumerate() {
+            if let Location::Memory(_, _) = dest {
+                let loc = self.value_stack[depth + i];
+                self.emit_typed_move(tys[i], loc, *dest);
+            }
+        }
+        for (i, dest) in dests.iter().enumerate() {
+            if let Location::GPR(_) = dest {
+                let loc = self.value_stack[depth + i];
+                self.assembler.emit_mov(Size::S64, loc, *dest);
+            }
+        }
+    }
+
+    /// Emits a branch to the frame at `frame_index` in the control stack, passing it the values
+    /// at the top of the value stack.
+    fn emit_br(&mut self, frame_index: usize) {
+        let (tys, dests) = self.control_stack[frame_index].branch_values(false);
+        self.emit_branch_moves(&tys, &dests);
+        let frame = &self.control_stack[frame_index];
+        let released = &self.value_stack[frame.value_stack_depth..];
+        self.machine
+            .release_locations_keep_state(&mut self.assembler, released);
+        self.assembler.emit_jmp(Condition::None, frame.br_label);
+    }
+
+    /// Ends the `then` branch of the innermost `if` block and starts its `else` branch.
+    fn emit_else(&mut self, was_unreachable: bool) -> Result<(), CodegenError> {
+        if !was_unreachable {
+            let (tys, dests) = self.control_stack.last().unwrap().branch_values(true);
+            self.emit_branch_moves(&tys, &dests);
+        }
+
+        self.update_max_stack_depth();
+
+        let frame = self.control_stack.last_mut().unwrap();
+
+        let released: &[Location] = &self.value_stack[frame.value_stack_depth..];
+        self.machine.release_locations(released);
+        self.machine.flush_stack_adjustment(&mut self.assembler);
+        self.value_stack.truncate(frame.value_stack_depth);
+        self.fp_stack.truncate(frame.fp_stack_depth);
+
+        match frame.if_else {
+            IfElseState::If(label) => {
+                self.assembler.emit_jmp(Condition::None, frame.br_label);
+                self.assembler.emit_label(label);
+                frame.if_else = IfElseState::Else;
+            }
+            _ => {
+                return Err(CodegenError {
+                    message: "Else: frame.if_else unreachable code".to_string(),
+                })
+            }
+        }
+
+        self.load_block_params();
+        Ok(())
+    }
+
+    /// Ends the innermost block, whose `try` handlers, if any, already ended.
+    fn emit_end(&mut self, mut was_unreachable: bool) -> Result<(), CodegenError> {
+        // Without an `else`, the params of the `if` are its results when the condition
+        // is false.
+        let frame = self.control_stack.last().unwrap();
+        if let IfElseState::If(_) = frame.if_else {
+            if !frame.params.is_empty() {
+                self.emit_else(was_unreachable)?;
+                was_unreachable = false;
+            }
+        }
+
+        let frame = self.control_stack.pop().unwrap();
+
+        if !was_unreachable {
+            let (tys, dests) = frame.branch_values(true);
+            self.emit_branch_moves(&tys, &dests);
+        }
+
+        if self.control_stack.is_empty() {
+            self.assembler.emit_label(frame.br_label);
+            self.update_max_stack_depth();
+            self.emit_function_stack_check(false);
+            #[cfg(feature = "debug-machine-checks")]
+            self.machine.forget_locations(&self.value_stack);
+            if self.machine.is_frameless() {
+                self.machine.finalize_frameless_locals(&mut self.assembler);
+            } else {
+                self.frame_layout = Some(
+                    self.machine
+                        .finalize_locals(&mut self.assembler, self.calling_convention),
+                );
+                self.assembler.emit_mov(
+                    Size::S64,
+                    Location::GPR(GPR::RBP),
+                    Location::GPR(GPR::RSP),
+                );
+                self.assembler.emit_pop(Size::S64, Location::GPR(GPR::RBP));
+                self.machine
+                    .record_unwind_op(&mut self.assembler, UnwindOp::PopFramePointer);
+            }
+
+            // Make a copy of the return value in XMM0, as required by the SysV CC.
+            match self.signature.results() {
+                [x] if *x == Type::F32 || *x == Type::F64 => {
                     self.assembler.emit_mov(
-                        sz,
-                        Location::Imm64(0x8000000000000000u64),
-                        Location::GPR(GPR::RCX),
+                        Size::S64,
+                        Location::GPR(GPR::RAX),
+                        Location::XMM(XMM::XMM0),
                     );
+                }
+                _ => {}
+            }
+            self.assembler.emit_ret();
+            self.machine
+                .record_unwind_op(&mut self.assembler, UnwindOp::Return);
+        } else {
+            let released = &self.value_stack[frame.value_stack_depth..];
+            self.machine.release_locations(released);
+            self.machine.flush_stack_adjustment(&mut self.assembler);
+            self.update_max_stack_depth();
+            self.value_stack.truncate(frame.value_stack_depth);
+            self.fp_stack.truncate(frame.fp_stack_depth);
+
+            if !frame.loop_like {
+                self.assembler.emit_label(frame.br_label);
+            }
+
+            if let IfElseState::If(label) = frame.if_else {
+                self.assembler.emit_label(label);
+            }
+
+            let param_slots = self
+                .value_stack
+                .split_off(self.value_stack.len() - frame.param_slots.len());
+            self.machine.release_locations(&param_slots);
+
+            if frame.returns.len() > 1 {
+                // The return slots are at the top of the value stack now.
+                let depth = self.value_stack.len() - frame.returns.len();
+                for (i, ty) in frame.returns.iter().enumerate() {
+                    if ty.is_float() {
+                        self.fp_stack.push(FloatValue::new(depth + i));
+                    }
+                }
+            } else if !frame.returns.is_empty() {
+                let loc = self.machine.acquire_locations(
+                    &mut self.assembler,
+                    &[(frame.returns[0])],
+                    false,
+                )[0];
+                if frame.returns[0] == WpType::V128 {
+                    self.emit_move_v128(Location::XMM(XMM::XMM0), loc);
+                } else {
                     self.assembler
-                        .emit_cmp(sz, Location::GPR(GPR::RCX), Location::GPR(GPR::RAX));
+                        .emit_mov(Size::S64, Location::GPR(GPR::RAX), loc);
+                }
+                self.value_stack.push(loc);
+                if frame.returns[0].is_float() {
+                    self.fp_stack
+                        .push(FloatValue::new(self.value_stack.len() - 1));
+                    // we already canonicalized at the `Br*` instruction or here previously.
                 }
-                _ => assert!(false),
             }
-            self.assembler.emit_jmp(Condition::NotEqual, end);
-            self.assembler
-                .emit_jmp(Condition::None, self.special_labels.integer_overflow);
-            self.assembler.emit_label(end);
         }
+        Ok(())
+    }
+
+    /// The dispatch of the innermost `try` block, at or outside the frame at `frame_index` in the
+    /// control stack, whose body is running: the handlers of the function for the exceptions
+    /// thrown there, if any.
+    fn exception_handler(&self, frame_index: usize) -> Option<DynamicLabel> {
+        self.control_stack[..=frame_index]
+            .iter()
+            .rev()
+            .find_map(|frame| match frame.try_frame {
+                Some(TryFrame {
+                    dispatch,
+                    state: TryState::Body,
+                    ..
+                }) => Some(dispatch),
+                _ => None,
+            })
+    }
+
+    /// Throws the exception in RAX from within the frame at `frame_index` in the control stack:
+    /// jumps to the handlers of the function catching it, or else throws it again to the calling
+    /// functions.
+    fn emit_throw_from(&mut self, frame_index: usize) -> Result<(), CodegenError> {
+        if let Some(dispatch) = self.exception_handler(frame_index) {
+            self.assembler.emit_jmp(Condition::None, dispatch);
+            return Ok(());
+        }
+        let rethrow = self
+            .vmoffsets
+            .vmctx_builtin_function(VMBuiltinFunctionIndex::get_rethrow_index());
+        self.emit_call_native(
+            |this| {
+                this.assembler.emit_mov(
+                    Size::S64,
+                    Location::Memory(Machine::get_vmctx_reg(), rethrow as i32),
+                    Location::GPR(GPR::RAX),
+                );
+                this.assembler.emit_call_register(GPR::RAX);
+            },
+            // [vmctx, exception]
+            iter::once(Location::GPR(GPR::RAX)),
+        )
+    }
+
+    /// Ends the current clause of the innermost `try` block, its body or one of its handlers,
+    /// as `emit_else` does the `then` branch of an `if` block.
+    fn emit_try_clause_end(&mut self, was_unreachable: bool) {
+        if !was_unreachable {
+            let (tys, dests) = self.control_stack.last().unwrap().branch_values(true);
+            self.emit_branch_moves(&tys, &dests);
+        }
+
+        self.update_max_stack_depth();
+
+        let frame = self.control_stack.last().unwrap();
+        let released: &[Location] = &self.value_stack[frame.value_stack_depth..];
+        self.machine.release_locations(released);
+        self.machine.flush_stack_adjustment(&mut self.assembler);
+        self.value_stack.truncate(frame.value_stack_depth);
+        self.fp_stack.truncate(frame.fp_stack_depth);
+        self.assembler.emit_jmp(Condition::None, frame.br_label);
+    }
+
+    /// Emits the dispatch of the innermost `try` block, whose body just ended, and starts its
+    /// handlers. The exception is left in RAX.
+    fn emit_try_dispatch(&mut self) {
+        let try_frame = self
+            .control_stack
+            .last_mut()
+            .unwrap()
+            .try_frame
+            .as_mut()
+            .unwrap();
+        try_frame.state = TryState::Catch(None);
+        let (dispatch, exception) = (try_frame.dispatch, try_frame.exception);
+
+        // The exceptions arrive from deeper in the stack, or from the landing pads.
+        self.assembler.emit_label(dispatch);
+        let rsp = self.machine.get_stack_offset() + RED_ZONE_SIZE as usize;
+        self.assembler.emit_lea(
+            Size::S64,
+            Location::Memory(GPR::RBP, -(rsp as i32)),
+            Location::GPR(GPR::RSP),
+        );
+        self.assembler
+            .emit_mov(Size::S64, Location::GPR(GPR::RAX), exception);
+    }
+
+    /// Starts the handler of the innermost `try` block for the exceptions of `tag`, or for all
+    /// of them, pushing the values of the exception caught on the value stack.
+    fn emit_catch(
+        &mut self,
+        tag: Option<TagIndex>,
+        was_unreachable: bool,
+    ) -> Result<(), CodegenError> {
+        self.emit_try_clause_end(was_unreachable);
+        let try_frame = self.control_stack.last().unwrap().try_frame.unwrap();
+        match try_frame.state {
+            TryState::Body => self.emit_try_dispatch(),
+            TryState::Catch(next) => {
+                // Only a `catch_all` has no next handler, and it comes last.
+                if let Some(next) = next {
+                    self.assembler.emit_label(next);
+                }
+            }
+        }
+
+        let next = match tag {
+            Some(tag) => {
+                let next = self.assembler.get_label();
+                self.assembler.emit_mov(
+                    Size::S64,
+                    Location::Memory(
+                        Machine::get_vmctx_reg(),
+                        self.vmoffsets.vmctx_builtin_function(
+                            VMBuiltinFunctionIndex::get_exception_catch_index(),
+                        ) as i32,
+                    ),
+                    Location::GPR(GPR::RAX),
+                );
+                self.emit_call_native(
+                    |this| {
+                        this.assembler.emit_call_register(GPR::RAX);
+                    },
+                    // [vmctx, exception, tag_index] -> values
+                    [try_frame.exception, Location::Imm32(tag.as_u32())]
+                        .iter()
+                        .cloned(),
+                )?;
+                self.assembler.emit_test_gpr_64(GPR::RAX);
+                self.assembler.emit_jmp(Condition::Equal, next);
+
+                // The values are in consecutive 16-byte slots at RAX, which the moves keep.
+                let sig = self.module.tags[tag];
+                let tys: SmallVec<[WpType; 8]> = self.module.signatures[sig]
+                    .params()
+                    .iter()
+                    .cloned()
+                    .map(type_to_wp_type)
+                    .collect();
+                let values_ptr = self.machine.reserve_unused_temp_gpr(GPR::RAX);
+                let values = self
+                    .machine
+                    .acquire_locations(&mut self.assembler, &tys, false);
+                for (i, (ty, loc)) in tys.iter().zip(values).enumerate() {
+                    self.emit_typed_move(*ty, Location::Memory(values_ptr, 16 * i as i32), loc);
+                    self.value_stack.push(loc);
+                    if ty.is_float() {
+                        self.fp_stack
+                            .push(FloatValue::new(self.value_stack.len() - 1));
+                    }
+                }
+                self.machine.release_temp_gpr(values_ptr);
+                Some(next)
+            }
+            None => None,
+        };
+        if let Some(try_frame) = &mut self.control_stack.last_mut().unwrap().try_frame {
+            try_frame.state = TryState::Catch(next);
+        }
+        Ok(())
+    }
+
+    /// Ends the handlers of the innermost `try` block, throwing the exceptions none of them
+    /// caught from the frame at `frame_index` in the control stack.
+    ///
+    /// A `try` block without any handler ends with its dispatch.
+    fn emit_try_end(
+        &mut self,
+        was_unreachable: bool,
+        frame_index: usize,
+    ) -> Result<(), CodegenError> {
+        self.emit_try_clause_end(was_unreachable);
+        let try_frame = self.control_stack.last().unwrap().try_frame.unwrap();
+        match try_frame.state {
+            TryState::Body => self.emit_try_dispatch(),
+            TryState::Catch(Some(next)) => {
+                self.assembler.emit_label(next);
+                self.assembler
+                    .emit_mov(Size::S64, try_frame.exception, Location::GPR(GPR::RAX));
+            }
+            TryState::Catch(None) => return Ok(()),
+        }
+        self.emit_throw_from(frame_index)
+    }
+
+    /// Branches to an `IntegerDivisionByZero` trap if the divisor `loc` is zero.
+    fn emit_divisor_zero_check(&mut self, sz: Size, loc: Location) {
+        self.assembler.emit_cmp(sz, Location::Imm32(0), loc);
+        let trap = self.trap_label(TrapCode::IntegerDivisionByZero);
+        self.assembler.emit_jmp(Condition::Equal, trap);
+    }
 
+    /// Moves `loc` to a valid location for `div`/`idiv`.
+    ///
+    /// The divisor must already be checked: see `emit_divisor_zero_check` and
+    /// `emit_signed_xdiv`.
+    fn emit_relaxed_xdiv(&mut self, signed: bool, sz: Size, loc: Location) {
         match loc {
             Location::Imm64(_) | Location::Imm32(_) => {
                 self.assembler.emit_mov(sz, loc, Location::GPR(GPR::RCX)); // must not be used during div (rax, rdx)
@@ -609,6 +1678,58 @@ impl<'a> FuncGen<'a> {
         }
     }
 
+    /// Emits the `div_s` of `loc_a` by `loc_b` into `ret`, or their `rem_s` if `rem`, for
+    /// both 32 and 64-bit operands.
+    ///
+    /// Nothing is left to the fault of `idiv`: a zero divisor branches to an
+    /// `IntegerDivisionByZero` trap and `MIN / -1` to an `IntegerOverflow` one. Any remainder
+    /// by -1 is 0, so `MIN % -1` skips the `idiv` instead. Past the zero check, the only extra
+    /// compare and branch of the common path is the one against -1.
+    fn emit_signed_xdiv(
+        &mut self,
+        sz: Size,
+        rem: bool,
+        loc_a: Location,
+        loc_b: Location,
+        ret: Location,
+    ) {
+        // We assume that RAX and RDX are temporary registers here.
+        self.assembler.emit_mov(sz, loc_a, Location::GPR(GPR::RAX));
+        match sz {
+            Size::S32 => self.assembler.emit_cdq(),
+            Size::S64 => self.assembler.emit_cqo(),
+            _ => unreachable!(),
+        }
+        self.emit_divisor_zero_check(sz, loc_b);
+
+        let divide = self.assembler.get_label();
+        let end = self.assembler.get_label();
+        // A 32-bit immediate is sign-extended to -1 for 64-bit compares too.
+        self.assembler
+            .emit_cmp(sz, Location::Imm32(0xffffffff), loc_b);
+        self.assembler.emit_jmp(Condition::NotEqual, divide);
+        if rem {
+            self.assembler
+                .emit_xor(Size::S64, Location::GPR(GPR::RDX), Location::GPR(GPR::RDX));
+            self.assembler.emit_jmp(Condition::None, end);
+        } else {
+            // MIN is the only dividend whose quotient by -1 doesn't fit.
+            let min = match sz {
+                Size::S32 => Location::Imm32(0x80000000),
+                _ => Location::Imm64(0x8000000000000000),
+            };
+            self.assembler.emit_cmp(sz, min, Location::GPR(GPR::RAX));
+            let trap = self.trap_label(TrapCode::IntegerOverflow);
+            self.assembler.emit_jmp(Condition::Equal, trap);
+        }
+
+        self.assembler.emit_label(divide);
+        self.emit_relaxed_xdiv(true, sz, loc_b);
+        self.assembler.emit_label(end);
+        let result = if rem { GPR::RDX } else { GPR::RAX };
+        self.assembler.emit_mov(sz, Location::GPR(result), ret);
+    }
+
     /// Moves `src` and `dst` to valid locations for `movzx`/`movsx`.
     fn emit_relaxed_zx_sx(
         &mut self,
@@ -625,11 +1746,11 @@ impl<'a> FuncGen<'a> {
                 })
             }
             Location::Memory(_, _) => {
-                let tmp_dst = m.acquire_temp_gpr().unwrap();
+                let tmp_dst = m.steal_temp_gpr(a, &[src, dst]);
                 op(a, sz_src, src, sz_dst, Location::GPR(tmp_dst));
                 a.emit_mov(Size::S64, Location::GPR(tmp_dst), dst);
 
-                m.release_temp_gpr(tmp_dst);
+                m.restore_stolen_gpr(a, tmp_dst);
                 Ok(())
             }
             Location::GPR(_) => {
@@ -645,14 +1766,15 @@ impl<'a> FuncGen<'a> {
 
         match src {
             Location::Imm32(_) | Location::Imm64(_) => {
-                let tmp_src = self.machine.acquire_temp_gpr().unwrap();
+                let tmp_src = self.machine.steal_temp_gpr(&mut self.assembler, &[dst]);
                 self.assembler
                     .emit_mov(Size::S64, src, Location::GPR(tmp_src));
                 src = Location::GPR(tmp_src);
 
                 inner(&mut self.machine, &mut self.assembler, src)?;
 
-                self.machine.release_temp_gpr(tmp_src);
+                self.machine
+                    .restore_stolen_gpr(&mut self.assembler, tmp_src);
             }
             Location::GPR(_) | Location::Memory(_, _) => {
                 inner(&mut self.machine, &mut self.assembler, src)?
@@ -666,6 +1788,23 @@ impl<'a> FuncGen<'a> {
         Ok(())
     }
 
+    /// Sign-extends the low `sz_src` bits of the value on top of the stack into a value of type
+    /// `ty`, with a single `movsx` from its location, register or memory. The constants are
+    /// extended at compile time instead, see `fold_unop`.
+    fn emit_sign_extension(&mut self, sz_src: Size, ty: WpType) -> Result<(), CodegenError> {
+        let loc = self.pop_value_released();
+        let ret = self
+            .machine
+            .acquire_locations(&mut self.assembler, &[(ty)], false)[0];
+        self.value_stack.push(ret);
+        let sz_dst = if ty == WpType::I32 {
+            Size::S32
+        } else {
+            Size::S64
+        };
+        self.emit_relaxed_zx_sx(Assembler::emit_movsx, sz_src, loc, sz_dst, ret)
+    }
+
     /// Moves `src` and `dst` to valid locations for generic instructions.
     fn emit_relaxed_binop(
         &mut self,
@@ -705,20 +1844,28 @@ impl<'a> FuncGen<'a> {
 
         match mode {
             RelaxMode::SrcToGPR => {
-                let temp = self.machine.acquire_temp_gpr().unwrap();
+                let temp = self
+                    .machine
+                    .steal_temp_gpr(&mut self.assembler, &[src, dst]);
                 self.assembler.emit_mov(sz, src, Location::GPR(temp));
                 op(&mut self.assembler, sz, Location::GPR(temp), dst);
-                self.machine.release_temp_gpr(temp);
+                self.machine.restore_stolen_gpr(&mut self.assembler, temp);
             }
             RelaxMode::DstToGPR => {
-                let temp = self.machine.acquire_temp_gpr().unwrap();
+                let temp = self
+                    .machine
+                    .steal_temp_gpr(&mut self.assembler, &[src, dst]);
                 self.assembler.emit_mov(sz, dst, Location::GPR(temp));
                 op(&mut self.assembler, sz, src, Location::GPR(temp));
-                self.machine.release_temp_gpr(temp);
+                self.machine.restore_stolen_gpr(&mut self.assembler, temp);
             }
             RelaxMode::BothToGPR => {
-                let temp_src = self.machine.acquire_temp_gpr().unwrap();
-                let temp_dst = self.machine.acquire_temp_gpr().unwrap();
+                let temp_src = self
+                    .machine
+                    .steal_temp_gpr(&mut self.assembler, &[src, dst]);
+                let temp_dst = self
+                    .machine
+                    .steal_temp_gpr(&mut self.assembler, &[src, dst, Location::GPR(temp_src)]);
                 self.assembler.emit_mov(sz, src, Location::GPR(temp_src));
                 self.assembler.emit_mov(sz, dst, Location::GPR(temp_dst));
                 op(
@@ -733,8 +1880,10 @@ impl<'a> FuncGen<'a> {
                     }
                     _ => {}
                 }
-                self.machine.release_temp_gpr(temp_dst);
-                self.machine.release_temp_gpr(temp_src);
+                self.machine
+                    .restore_stolen_gpr(&mut self.assembler, temp_dst);
+                self.machine
+                    .restore_stolen_gpr(&mut self.assembler, temp_src);
             }
             RelaxMode::Direct => {
                 op(&mut self.assembler, sz, src, dst);
@@ -770,7 +1919,9 @@ impl<'a> FuncGen<'a> {
         let tmp1 = self.machine.acquire_temp_xmm().unwrap();
         let tmp2 = self.machine.acquire_temp_xmm().unwrap();
         let tmp3 = self.machine.acquire_temp_xmm().unwrap();
-        let tmpg = self.machine.acquire_temp_gpr().unwrap();
+        let tmpg = self
+            .machine
+            .steal_temp_gpr(&mut self.assembler, &[src1, src2, dst]);
 
         let src1 = match src1 {
             Location::XMM(x) => x,
@@ -844,7 +1995,7 @@ impl<'a> FuncGen<'a> {
             }
         }
 
-        self.machine.release_temp_gpr(tmpg);
+        self.machine.restore_stolen_gpr(&mut self.assembler, tmpg);
         self.machine.release_temp_xmm(tmp3);
         self.machine.release_temp_xmm(tmp2);
         self.machine.release_temp_xmm(tmp1);
@@ -856,11 +2007,11 @@ impl<'a> FuncGen<'a> {
         // Using Red Zone here.
         let I2O1 { loc_a, loc_b, ret } = self.i2o1_prepare(WpType::I32);
         if loc_a != ret {
-            let tmp = self.machine.acquire_temp_gpr().unwrap();
+            let tmp = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
             self.emit_relaxed_binop(Assembler::emit_mov, Size::S32, loc_a, Location::GPR(tmp));
             self.emit_relaxed_binop(f, Size::S32, loc_b, Location::GPR(tmp));
             self.emit_relaxed_binop(Assembler::emit_mov, Size::S32, Location::GPR(tmp), ret);
-            self.machine.release_temp_gpr(tmp);
+            self.machine.restore_stolen_gpr(&mut self.assembler, tmp);
         } else {
             self.emit_relaxed_binop(f, Size::S32, loc_b, ret);
         }
@@ -872,11 +2023,11 @@ impl<'a> FuncGen<'a> {
         let I2O1 { loc_a, loc_b, ret } = self.i2o1_prepare(WpType::I64);
 
         if loc_a != ret {
-            let tmp = self.machine.acquire_temp_gpr().unwrap();
+            let tmp = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
             self.emit_relaxed_binop(Assembler::emit_mov, Size::S64, loc_a, Location::GPR(tmp));
             self.emit_relaxed_binop(f, Size::S64, loc_b, Location::GPR(tmp));
             self.emit_relaxed_binop(Assembler::emit_mov, Size::S64, Location::GPR(tmp), ret);
-            self.machine.release_temp_gpr(tmp);
+            self.machine.restore_stolen_gpr(&mut self.assembler, tmp);
         } else {
             self.emit_relaxed_binop(f, Size::S64, loc_b, ret);
         }
@@ -902,13 +2053,13 @@ impl<'a> FuncGen<'a> {
                     .emit_and(Size::S32, Location::Imm32(0xff), Location::GPR(x));
             }
             Location::Memory(_, _) => {
-                let tmp = self.machine.acquire_temp_gpr().unwrap();
+                let tmp = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                 self.emit_relaxed_binop(Assembler::emit_cmp, Size::S32, loc_b, loc_a);
                 self.assembler.emit_set(c, tmp);
                 self.assembler
                     .emit_and(Size::S32, Location::Imm32(0xff), Location::GPR(tmp));
                 self.assembler.emit_mov(Size::S32, Location::GPR(tmp), ret);
-                self.machine.release_temp_gpr(tmp);
+                self.machine.restore_stolen_gpr(&mut self.assembler, tmp);
             }
             _ => {
                 return Err(CodegenError {
@@ -947,13 +2098,13 @@ impl<'a> FuncGen<'a> {
                     .emit_and(Size::S32, Location::Imm32(0xff), Location::GPR(x));
             }
             Location::Memory(_, _) => {
-                let tmp = self.machine.acquire_temp_gpr().unwrap();
+                let tmp = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                 self.emit_relaxed_binop(Assembler::emit_cmp, Size::S64, loc_b, loc_a);
                 self.assembler.emit_set(c, tmp);
                 self.assembler
                     .emit_and(Size::S32, Location::Imm32(0xff), Location::GPR(tmp));
                 self.assembler.emit_mov(Size::S32, Location::GPR(tmp), ret);
-                self.machine.release_temp_gpr(tmp);
+                self.machine.restore_stolen_gpr(&mut self.assembler, tmp);
             }
             _ => {
                 return Err(CodegenError {
@@ -972,10 +2123,57 @@ impl<'a> FuncGen<'a> {
         Ok(())
     }
 
+    fn has_cpu_feature(&self, feature: CpuFeature) -> bool {
+        self.target.cpu_features().contains(feature)
+    }
+
+    /// Counts the bits set in `src` into the GPR `dst`.
+    ///
+    /// Without POPCNT, the bits are summed in pairs, then nibbles, then bytes, and the bytes are
+    /// added up by a multiplication.
+    fn emit_popcnt(&mut self, sz: Size, src: Location, dst: Location) {
+        if self.has_cpu_feature(CpuFeature::POPCNT) {
+            self.assembler.emit_popcnt(sz, src, dst);
+            return;
+        }
+        let (splat, top_byte): (fn(u8) -> Location, u8) = match sz {
+            Size::S32 => (|b| Location::Imm32(u32::from_ne_bytes([b; 4])), 24),
+            _ => (|b| Location::Imm64(u64::from_ne_bytes([b; 8])), 56),
+        };
+        let tmp_gpr = self
+            .machine
+            .steal_temp_gpr(&mut self.assembler, &[src, dst]);
+        let tmp = Location::GPR(tmp_gpr);
+        self.assembler.emit_mov(sz, src, dst);
+
+        self.assembler.emit_mov(sz, dst, tmp);
+        self.assembler.emit_shr(sz, Location::Imm8(1), tmp);
+        self.emit_relaxed_binop(Assembler::emit_and, sz, splat(0x55), tmp);
+        self.assembler.emit_sub(sz, tmp, dst);
+
+        self.assembler.emit_mov(sz, dst, tmp);
+        self.assembler.emit_shr(sz, Location::Imm8(2), tmp);
+        self.emit_relaxed_binop(Assembler::emit_and, sz, splat(0x33), tmp);
+        self.emit_relaxed_binop(Assembler::emit_and, sz, splat(0x33), dst);
+        self.assembler.emit_add(sz, tmp, dst);
+
+        self.assembler.emit_mov(sz, dst, tmp);
+        self.assembler.emit_shr(sz, Location::Imm8(4), tmp);
+        self.assembler.emit_add(sz, tmp, dst);
+        self.emit_relaxed_binop(Assembler::emit_and, sz, splat(0x0f), dst);
+
+        // The top byte of the product is the sum of all the bytes.
+        self.emit_relaxed_binop(Assembler::emit_imul, sz, splat(0x01), dst);
+        self.assembler.emit_shr(sz, Location::Imm8(top_byte), dst);
+
+        self.machine
+            .restore_stolen_gpr(&mut self.assembler, tmp_gpr);
+    }
+
     /// I32 `lzcnt`/`tzcnt`/`popcnt` with operand popped from the virtual stack.
     fn emit_xcnt_i32(
         &mut self,
-        f: fn(&mut Assembler, Size, Location, Location),
+        f: fn(&mut Self, Size, Location, Location),
     ) -> Result<(), CodegenError> {
         let loc = self.pop_value_released();
         let ret = self
@@ -984,33 +2182,32 @@ impl<'a> FuncGen<'a> {
 
         match loc {
             Location::Imm32(_) => {
-                let tmp = self.machine.acquire_temp_gpr().unwrap();
+                let tmp = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                 self.assembler.emit_mov(Size::S32, loc, Location::GPR(tmp));
                 if let Location::Memory(_, _) = ret {
-                    let out_tmp = self.machine.acquire_temp_gpr().unwrap();
-                    f(
-                        &mut self.assembler,
-                        Size::S32,
-                        Location::GPR(tmp),
-                        Location::GPR(out_tmp),
-                    );
+                    let out_tmp = self
+                        .machine
+                        .steal_temp_gpr(&mut self.assembler, &[Location::GPR(tmp)]);
+                    f(self, Size::S32, Location::GPR(tmp), Location::GPR(out_tmp));
                     self.assembler
                         .emit_mov(Size::S32, Location::GPR(out_tmp), ret);
-                    self.machine.release_temp_gpr(out_tmp);
+                    self.machine
+                        .restore_stolen_gpr(&mut self.assembler, out_tmp);
                 } else {
-                    f(&mut self.assembler, Size::S32, Location::GPR(tmp), ret);
+                    f(self, Size::S32, Location::GPR(tmp), ret);
                 }
-                self.machine.release_temp_gpr(tmp);
+                self.machine.restore_stolen_gpr(&mut self.assembler, tmp);
             }
             Location::Memory(_, _) | Location::GPR(_) => {
                 if let Location::Memory(_, _) = ret {
-                    let out_tmp = self.machine.acquire_temp_gpr().unwrap();
-                    f(&mut self.assembler, Size::S32, loc, Location::GPR(out_tmp));
+                    let out_tmp = self.machine.steal_temp_gpr(&mut self.assembler, &[loc]);
+                    f(self, Size::S32, loc, Location::GPR(out_tmp));
                     self.assembler
                         .emit_mov(Size::S32, Location::GPR(out_tmp), ret);
-                    self.machine.release_temp_gpr(out_tmp);
+                    self.machine
+                        .restore_stolen_gpr(&mut self.assembler, out_tmp);
                 } else {
-                    f(&mut self.assembler, Size::S32, loc, ret);
+                    f(self, Size::S32, loc, ret);
                 }
             }
             _ => {
@@ -1026,7 +2223,7 @@ impl<'a> FuncGen<'a> {
     /// I64 `lzcnt`/`tzcnt`/`popcnt` with operand popped from the virtual stack.
     fn emit_xcnt_i64(
         &mut self,
-        f: fn(&mut Assembler, Size, Location, Location),
+        f: fn(&mut Self, Size, Location, Location),
     ) -> Result<(), CodegenError> {
         let loc = self.pop_value_released();
         let ret = self
@@ -1035,189 +2232,752 @@ impl<'a> FuncGen<'a> {
 
         match loc {
             Location::Imm64(_) | Location::Imm32(_) => {
-                let tmp = self.machine.acquire_temp_gpr().unwrap();
+                let tmp = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                 self.assembler.emit_mov(Size::S64, loc, Location::GPR(tmp));
                 if let Location::Memory(_, _) = ret {
-                    let out_tmp = self.machine.acquire_temp_gpr().unwrap();
-                    f(
-                        &mut self.assembler,
-                        Size::S64,
-                        Location::GPR(tmp),
-                        Location::GPR(out_tmp),
-                    );
+                    let out_tmp = self
+                        .machine
+                        .steal_temp_gpr(&mut self.assembler, &[Location::GPR(tmp)]);
+                    f(self, Size::S64, Location::GPR(tmp), Location::GPR(out_tmp));
                     self.assembler
                         .emit_mov(Size::S64, Location::GPR(out_tmp), ret);
-                    self.machine.release_temp_gpr(out_tmp);
+                    self.machine
+                        .restore_stolen_gpr(&mut self.assembler, out_tmp);
                 } else {
-                    f(&mut self.assembler, Size::S64, Location::GPR(tmp), ret);
+                    f(self, Size::S64, Location::GPR(tmp), ret);
                 }
-                self.machine.release_temp_gpr(tmp);
+                self.machine.restore_stolen_gpr(&mut self.assembler, tmp);
             }
             Location::Memory(_, _) | Location::GPR(_) => {
                 if let Location::Memory(_, _) = ret {
-                    let out_tmp = self.machine.acquire_temp_gpr().unwrap();
-                    f(&mut self.assembler, Size::S64, loc, Location::GPR(out_tmp));
+                    let out_tmp = self.machine.steal_temp_gpr(&mut self.assembler, &[loc]);
+                    f(self, Size::S64, loc, Location::GPR(out_tmp));
                     self.assembler
                         .emit_mov(Size::S64, Location::GPR(out_tmp), ret);
-                    self.machine.release_temp_gpr(out_tmp);
+                    self.machine
+                        .restore_stolen_gpr(&mut self.assembler, out_tmp);
                 } else {
-                    f(&mut self.assembler, Size::S64, loc, ret);
+                    f(self, Size::S64, loc, ret);
                 }
             }
             _ => {
-                return Err(CodegenError {
-                    message: "emit_xcnt_i64 loc: unreachable code".to_string(),
-                })
+                return Err(CodegenError {
+                    message: "emit_xcnt_i64 loc: unreachable code".to_string(),
+                })
+            }
+        }
+        self.value_stack.push(ret);
+        Ok(())
+    }
+
+    /// I32 shift with both operands popped from the virtual stack.
+    fn emit_shift_i32(&mut self, f: fn(&mut Assembler, Size, Location, Location)) {
+        let I2O1 { loc_a, loc_b, ret } = self.i2o1_prepare(WpType::I32);
+
+        let count = match loc_b {
+            Location::Imm32(count) => Location::Imm8(count as u8 & 31),
+            _ => {
+                self.assembler
+                    .emit_mov(Size::S32, loc_b, Location::GPR(GPR::RCX));
+                Location::GPR(GPR::RCX)
+            }
+        };
+
+        if loc_a != ret {
+            self.emit_relaxed_binop(Assembler::emit_mov, Size::S32, loc_a, ret);
+        }
+
+        f(&mut self.assembler, Size::S32, count, ret);
+    }
+
+    /// I64 shift with both operands popped from the virtual stack.
+    fn emit_shift_i64(&mut self, f: fn(&mut Assembler, Size, Location, Location)) {
+        let I2O1 { loc_a, loc_b, ret } = self.i2o1_prepare(WpType::I64);
+
+        let count = match loc_b {
+            Location::Imm64(count) => Location::Imm8(count as u8 & 63),
+            _ => {
+                self.assembler
+                    .emit_mov(Size::S64, loc_b, Location::GPR(GPR::RCX));
+                Location::GPR(GPR::RCX)
+            }
+        };
+
+        if loc_a != ret {
+            self.emit_relaxed_binop(Assembler::emit_mov, Size::S64, loc_a, ret);
+        }
+
+        f(&mut self.assembler, Size::S64, count, ret);
+    }
+
+    /// Floating point (AVX) binary operation with both operands popped from the virtual stack.
+    fn emit_fp_binop_avx(
+        &mut self,
+        f: fn(&mut Assembler, XMM, XMMOrMemory, XMM),
+    ) -> Result<(), CodegenError> {
+        let I2O1 { loc_a, loc_b, ret } = self.i2o1_prepare(WpType::F64);
+
+        self.emit_relaxed_avx(f, loc_a, loc_b, ret)?;
+        Ok(())
+    }
+
+    /// Floating point (AVX) minimum or maximum with both operands popped from the virtual stack.
+    ///
+    /// `min_max` returns its second operand when the operands are unordered or both zeros, so
+    /// the result is patched up with blends rather than branches: equal operands are combined
+    /// with `fix_zeros` (`or` for the minimum and `and` for the maximum, which gives the right
+    /// sign for zeros), and unordered operands produce the canonical NaN.
+    fn emit_fp_min_max(
+        &mut self,
+        sz: Size,
+        min_max: AvxOp,
+        fix_zeros: AvxOp,
+    ) -> Result<(), CodegenError> {
+        let (ty, cmpeq, cmpunord, blendv, nan): (_, AvxOp, AvxOp, AvxBlend, _) = match sz {
+            Size::S32 => (
+                WpType::F32,
+                Assembler::emit_vcmpeqss,
+                Assembler::emit_vcmpunordss,
+                Assembler::emit_vblendvps,
+                Location::Imm32(0x7FC0_0000),
+            ),
+            Size::S64 => (
+                WpType::F64,
+                Assembler::emit_vcmpeqsd,
+                Assembler::emit_vcmpunordsd,
+                Assembler::emit_vblendvpd,
+                Location::Imm64(0x7FF8_0000_0000_0000),
+            ),
+            _ => {
+                return Err(CodegenError {
+                    message: format!("emit_fp_min_max: unsupported size {:?}", sz),
+                })
+            }
+        };
+        let I2O1 { loc_a, loc_b, ret } = self.i2o1_prepare(ty);
+
+        let tmp1 = self.machine.acquire_temp_xmm().unwrap();
+        let tmp2 = self.machine.acquire_temp_xmm().unwrap();
+        let tmpg = self
+            .machine
+            .steal_temp_gpr(&mut self.assembler, &[loc_a, loc_b, ret]);
+
+        let to_xmm = |this: &mut Self, loc: Location, tmp: XMM| match loc {
+            Location::XMM(x) => Ok(x),
+            Location::GPR(_) | Location::Memory(_, _) => {
+                this.assembler.emit_mov(Size::S64, loc, Location::XMM(tmp));
+                Ok(tmp)
+            }
+            Location::Imm32(_) | Location::Imm64(_) => {
+                let imm_sz = if let Location::Imm32(_) = loc {
+                    Size::S32
+                } else {
+                    Size::S64
+                };
+                this.assembler.emit_mov(imm_sz, loc, Location::GPR(tmpg));
+                this.assembler
+                    .emit_mov(imm_sz, Location::GPR(tmpg), Location::XMM(tmp));
+                Ok(tmp)
+            }
+            _ => Err(CodegenError {
+                message: format!("emit_fp_min_max: unreachable operand {:?}", loc),
+            }),
+        };
+        let src1 = to_xmm(self, loc_a, tmp1)?;
+        let src2 = to_xmm(self, loc_b, tmp2)?;
+
+        let result = XMM::XMM8;
+        let fixup = XMM::XMM9;
+        let mask = XMM::XMM10;
+
+        min_max(&mut self.assembler, src1, XMMOrMemory::XMM(src2), result);
+        fix_zeros(&mut self.assembler, src1, XMMOrMemory::XMM(src2), fixup);
+        cmpeq(&mut self.assembler, src1, XMMOrMemory::XMM(src2), mask);
+        blendv(
+            &mut self.assembler,
+            mask,
+            XMMOrMemory::XMM(fixup),
+            result,
+            result,
+        );
+        cmpunord(&mut self.assembler, src1, XMMOrMemory::XMM(src2), mask);
+        self.assembler.emit_mov(sz, nan, Location::GPR(tmpg));
+        self.assembler
+            .emit_mov(sz, Location::GPR(tmpg), Location::XMM(fixup));
+        blendv(
+            &mut self.assembler,
+            mask,
+            XMMOrMemory::XMM(fixup),
+            result,
+            result,
+        );
+        match ret {
+            Location::XMM(x) => {
+                self.assembler
+                    .emit_vmovaps(XMMOrMemory::XMM(result), XMMOrMemory::XMM(x));
+            }
+            Location::Memory(_, _) | Location::GPR(_) => {
+                self.assembler
+                    .emit_mov(Size::S64, Location::XMM(result), ret);
+            }
+            _ => {
+                return Err(CodegenError {
+                    message: format!("emit_fp_min_max: unreachable return location {:?}", ret),
+                })
+            }
+        }
+
+        self.machine.restore_stolen_gpr(&mut self.assembler, tmpg);
+        self.machine.release_temp_xmm(tmp2);
+        self.machine.release_temp_xmm(tmp1);
+        Ok(())
+    }
+
+    /// Floating point (AVX) comparison with both operands popped from the virtual stack.
+    fn emit_fp_cmpop_avx(
+        &mut self,
+        f: fn(&mut Assembler, XMM, XMMOrMemory, XMM),
+    ) -> Result<(), CodegenError> {
+        let I2O1 { loc_a, loc_b, ret } = self.i2o1_prepare(WpType::I32);
+
+        self.emit_relaxed_avx(f, loc_a, loc_b, ret)?;
+
+        // Workaround for behavior inconsistency among different backing implementations.
+        // (all bits or only the least significant bit are set to one?)
+        self.assembler.emit_and(Size::S32, Location::Imm32(1), ret);
+        Ok(())
+    }
+
+    /// Floating point (AVX) unop with both operands popped from the virtual stack.
+    fn emit_fp_unop_avx(
+        &mut self,
+        f: fn(&mut Assembler, XMM, XMMOrMemory, XMM),
+    ) -> Result<(), CodegenError> {
+        let loc = self.pop_value_released();
+        let ret = self
+            .machine
+            .acquire_locations(&mut self.assembler, &[(WpType::F64)], false)[0];
+        self.value_stack.push(ret);
+        self.emit_relaxed_avx(f, loc, loc, ret)?;
+        Ok(())
+    }
+
+    /// Moves the value of type `ty` at `src` to `dst`.
+    fn emit_typed_move(&mut self, ty: WpType, src: Location, dst: Location) {
+        if ty == WpType::V128 {
+            self.emit_move_v128(src, dst);
+        } else {
+            self.emit_relaxed_binop(Assembler::emit_mov, Size::S64, src, dst);
+        }
+    }
+
+    /// Moves `v_a` to `ret` if `cond` is not zero and `v_b` otherwise, without branching.
+    ///
+    /// Scalar values of all types are held in GPRs or memory, so this works for floats too as long
+    /// as they do not need to be canonicalized.
+    fn emit_select_cmov(&mut self, cond: Location, v_a: Location, v_b: Location, ret: Location) {
+        // `ret` may have been allocated where one of the inputs is, so they are all read first.
+        let avoid = [cond, v_a, v_b, ret];
+        let tmp = self.machine.steal_temp_gpr(&mut self.assembler, &avoid);
+        self.assembler.emit_mov(Size::S64, v_a, Location::GPR(tmp));
+        // `cmov` has no immediate form. This is done before the comparison since immediate moves
+        // may be emitted as a flag-clobbering `xor`.
+        let (v_b, tmp_b) = match v_b {
+            Location::GPR(_) | Location::Memory(_, _) => (v_b, None),
+            _ => {
+                let tmp_b = self
+                    .machine
+                    .steal_temp_gpr(&mut self.assembler, &[Location::GPR(tmp)]);
+                self.assembler
+                    .emit_mov(Size::S64, v_b, Location::GPR(tmp_b));
+                (Location::GPR(tmp_b), Some(tmp_b))
+            }
+        };
+        self.emit_relaxed_binop(Assembler::emit_cmp, Size::S32, Location::Imm32(0), cond);
+        self.assembler
+            .emit_cmovcc(Size::S64, Condition::Equal, v_b, Location::GPR(tmp));
+        self.assembler.emit_mov(Size::S64, Location::GPR(tmp), ret);
+        if let Some(tmp_b) = tmp_b {
+            self.machine.restore_stolen_gpr(&mut self.assembler, tmp_b);
+        }
+        self.machine.restore_stolen_gpr(&mut self.assembler, tmp);
+    }
+
+    /// Moves the 16-byte value at `src` to `dst`, each an XMM register or memory.
+    fn emit_move_v128(&mut self, src: Location, dst: Location) {
+        if src == dst {
+            return;
+        }
+        match (src, dst) {
+            (Location::Memory(_, _), Location::Memory(_, _)) => {
+                let tmp = self.machine.acquire_temp_xmm().unwrap();
+                self.assembler
+                    .emit_movdqu(v128_operand(src), XMMOrMemory::XMM(tmp));
+                self.assembler
+                    .emit_movdqu(XMMOrMemory::XMM(tmp), v128_operand(dst));
+                self.machine.release_temp_xmm(tmp);
+            }
+            _ => self
+                .assembler
+                .emit_movdqu(v128_operand(src), v128_operand(dst)),
+        }
+    }
+
+    /// Writes the 16-byte constant `bytes` to `dst`.
+    fn emit_v128_const(&mut self, bytes: [u8; 16], dst: Location) {
+        let mut lo = [0; 8];
+        let mut hi = [0; 8];
+        lo.copy_from_slice(&bytes[..8]);
+        hi.copy_from_slice(&bytes[8..]);
+        let (lo, hi) = (u64::from_le_bytes(lo), u64::from_le_bytes(hi));
+        if let (Location::XMM(x), 0, 0) = (dst, lo, hi) {
+            self.assembler.emit_vpxor(x, XMMOrMemory::XMM(x), x);
+            return;
+        }
+        let tmp = self.machine.steal_temp_gpr(&mut self.assembler, &[dst]);
+        self.assembler
+            .emit_mov(Size::S64, Location::Imm64(lo), Location::GPR(tmp));
+        match dst {
+            Location::XMM(x) => {
+                self.assembler
+                    .emit_mov(Size::S64, Location::GPR(tmp), Location::XMM(x));
+                self.assembler
+                    .emit_mov(Size::S64, Location::Imm64(hi), Location::GPR(tmp));
+                self.assembler.emit_pinsr(Size::S64, tmp, 1, x);
+            }
+            Location::Memory(base, disp) => {
+                self.assembler.emit_mov(Size::S64, Location::GPR(tmp), dst);
+                self.assembler
+                    .emit_mov(Size::S64, Location::Imm64(hi), Location::GPR(tmp));
+                self.assembler.emit_mov(
+                    Size::S64,
+                    Location::GPR(tmp),
+                    Location::Memory(base, disp + 8),
+                );
+            }
+            _ => unreachable!(),
+        }
+        self.machine.restore_stolen_gpr(&mut self.assembler, tmp);
+    }
+
+    /// Moves the 16-byte value at `loc` to `tmp`, unless it is in an XMM register already.
+    fn v128_to_xmm(&mut self, loc: Location, tmp: XMM) -> XMM {
+        match loc {
+            Location::XMM(x) => x,
+            _ => {
+                self.emit_move_v128(loc, Location::XMM(tmp));
+                tmp
+            }
+        }
+    }
+
+    /// Like `emit_relaxed_avx_base`, for 16-byte operands and results.
+    fn emit_relaxed_avx_v128<F: FnOnce(&mut Self, XMM, XMMOrMemory, XMM)>(
+        &mut self,
+        op: F,
+        src1: Location,
+        src2: Location,
+        dst: Location,
+    ) {
+        let tmp_src = self.machine.acquire_temp_xmm().unwrap();
+        let tmp_dst = self.machine.acquire_temp_xmm().unwrap();
+        let src1 = self.v128_to_xmm(src1, tmp_src);
+        match dst {
+            Location::XMM(x) => op(self, src1, v128_operand(src2), x),
+            _ => {
+                op(self, src1, v128_operand(src2), tmp_dst);
+                self.emit_move_v128(Location::XMM(tmp_dst), dst);
             }
         }
-        self.value_stack.push(ret);
-        Ok(())
+        self.machine.release_temp_xmm(tmp_dst);
+        self.machine.release_temp_xmm(tmp_src);
     }
 
-    /// I32 shift with both operands popped from the virtual stack.
-    fn emit_shift_i32(&mut self, f: fn(&mut Assembler, Size, Location, Location)) {
-        let I2O1 { loc_a, loc_b, ret } = self.i2o1_prepare(WpType::I32);
+    /// 16-byte (AVX) binary operation with both operands popped from the virtual stack.
+    fn emit_v128_binop_avx(&mut self, f: fn(&mut Assembler, XMM, XMMOrMemory, XMM)) {
+        let I2O1 { loc_a, loc_b, ret } = self.i2o1_prepare(WpType::V128);
+        self.emit_relaxed_avx_v128(
+            |this, src1, src2, dst| f(&mut this.assembler, src1, src2, dst),
+            loc_a,
+            loc_b,
+            ret,
+        );
+    }
 
+    /// Negates the integer lanes of the 16-byte value at the top of the value stack, by
+    /// subtracting them from zero with `sub`.
+    fn emit_v128_neg(&mut self, sub: fn(&mut Assembler, XMM, XMMOrMemory, XMM)) {
+        let loc = self.pop_value_released();
+        let ret = self
+            .machine
+            .acquire_locations(&mut self.assembler, &[WpType::V128], false)[0];
+        self.value_stack.push(ret);
+        let zero = self.machine.acquire_temp_xmm().unwrap();
         self.assembler
-            .emit_mov(Size::S32, loc_b, Location::GPR(GPR::RCX));
+            .emit_vpxor(zero, XMMOrMemory::XMM(zero), zero);
+        self.emit_relaxed_avx_v128(
+            |this, src1, src2, dst| sub(&mut this.assembler, src1, src2, dst),
+            Location::XMM(zero),
+            loc,
+            ret,
+        );
+        self.machine.release_temp_xmm(zero);
+    }
 
-        if loc_a != ret {
-            self.emit_relaxed_binop(Assembler::emit_mov, Size::S32, loc_a, ret);
+    /// Pops a scalar of type `ty` to be put in the lanes of a 16-byte value, and moves its low
+    /// `sz` bits to `tmp`.
+    fn pop_lane_value(&mut self, ty: WpType, sz: Size, tmp: GPR) -> Result<(), CodegenError> {
+        let loc = self.pop_value_released();
+        if ty.is_float() {
+            let fp = self.fp_stack.pop1()?;
+            if self.assembler.arch_supports_canonicalize_nan()
+                && self.config.enable_nan_canonicalization
+                && fp.canonicalization.is_some()
+            {
+                self.canonicalize_nan(sz, loc, loc);
+            }
         }
-
-        f(&mut self.assembler, Size::S32, Location::GPR(GPR::RCX), ret);
+        self.assembler.emit_mov(sz, loc, Location::GPR(tmp));
+        Ok(())
     }
 
-    /// I64 shift with both operands popped from the virtual stack.
-    fn emit_shift_i64(&mut self, f: fn(&mut Assembler, Size, Location, Location)) {
-        let I2O1 { loc_a, loc_b, ret } = self.i2o1_prepare(WpType::I64);
+    /// Puts the scalar of type `ty` at the top of the value stack in all the lanes of a 16-byte
+    /// value.
+    ///
+    /// The scalar is moved to the low lane with a `sz` move, and `shuffle` copies it to the
+    /// others.
+    fn emit_v128_splat<F: FnOnce(&mut Self, XMM)>(
+        &mut self,
+        ty: WpType,
+        sz: Size,
+        shuffle: F,
+    ) -> Result<(), CodegenError> {
+        let tmp = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
+        self.pop_lane_value(ty, sz, tmp)?;
+        let ret = self
+            .machine
+            .acquire_locations(&mut self.assembler, &[WpType::V128], false)[0];
+        self.value_stack.push(ret);
+        let tmp_xmm = self.machine.acquire_temp_xmm().unwrap();
         self.assembler
-            .emit_mov(Size::S64, loc_b, Location::GPR(GPR::RCX));
+            .emit_mov(sz, Location::GPR(tmp), Location::XMM(tmp_xmm));
+        self.machine.restore_stolen_gpr(&mut self.assembler, tmp);
+        shuffle(self, tmp_xmm);
+        self.emit_move_v128(Location::XMM(tmp_xmm), ret);
+        self.machine.release_temp_xmm(tmp_xmm);
+        Ok(())
+    }
 
-        if loc_a != ret {
-            self.emit_relaxed_binop(Assembler::emit_mov, Size::S64, loc_a, ret);
+    /// Copies the low `sz` lane of `x` to its other lanes.
+    fn emit_v128_broadcast(&mut self, sz: Size, x: XMM) {
+        match sz {
+            Size::S8 => {
+                // Shuffling with an all-zero mask copies the lowest byte everywhere.
+                let mask = self.machine.acquire_temp_xmm().unwrap();
+                self.assembler
+                    .emit_vpxor(mask, XMMOrMemory::XMM(mask), mask);
+                self.assembler.emit_vpshufb(x, XMMOrMemory::XMM(mask), x);
+                self.machine.release_temp_xmm(mask);
+            }
+            Size::S16 => {
+                self.assembler.emit_pshuflw(x, 0, x);
+                self.assembler.emit_pshufd(x, 0, x);
+            }
+            Size::S32 => self.assembler.emit_pshufd(x, 0, x),
+            Size::S64 => self.assembler.emit_pshufd(x, 0x44, x),
         }
-
-        f(&mut self.assembler, Size::S64, Location::GPR(GPR::RCX), ret);
     }
 
-    /// Floating point (AVX) binary operation with both operands popped from the virtual stack.
-    fn emit_fp_binop_avx(
+    /// Pops an address and pushes the 16-byte value `build` makes out of the `sz` bits loaded
+    /// from it, which it is given in the low lane of an XMM register, the other lanes being
+    /// zeroed.
+    fn emit_v128_load_scalar<F: FnOnce(&mut Self, XMM)>(
         &mut self,
-        f: fn(&mut Assembler, XMM, XMMOrMemory, XMM),
+        memarg: &MemoryImmediate,
+        sz: Size,
+        build: F,
     ) -> Result<(), CodegenError> {
-        let I2O1 { loc_a, loc_b, ret } = self.i2o1_prepare(WpType::F64);
-
-        self.emit_relaxed_avx(f, loc_a, loc_b, ret)?;
+        let target = self.pop_value_released();
+        let ret = self
+            .machine
+            .acquire_locations(&mut self.assembler, &[WpType::V128], false)[0];
+        self.value_stack.push(ret);
+        let tmp_xmm = self.machine.acquire_temp_xmm().unwrap();
+        self.emit_memory_op(target, memarg, false, size_in_bytes(sz), |this, addr| {
+            match sz {
+                // `movd` and `movq` zero the other lanes.
+                Size::S32 | Size::S64 => {
+                    this.assembler
+                        .emit_mov(sz, Location::Memory(addr, 0), Location::XMM(tmp_xmm))
+                }
+                Size::S8 | Size::S16 => {
+                    let tmp = this
+                        .machine
+                        .steal_temp_gpr(&mut this.assembler, &[Location::GPR(addr)]);
+                    this.assembler.emit_movzx(
+                        sz,
+                        Location::Memory(addr, 0),
+                        Size::S32,
+                        Location::GPR(tmp),
+                    );
+                    this.assembler
+                        .emit_mov(Size::S32, Location::GPR(tmp), Location::XMM(tmp_xmm));
+                    this.machine.restore_stolen_gpr(&mut this.assembler, tmp);
+                }
+            }
+            Ok(())
+        })?;
+        build(self, tmp_xmm);
+        self.emit_move_v128(Location::XMM(tmp_xmm), ret);
+        self.machine.release_temp_xmm(tmp_xmm);
         Ok(())
     }
 
-    /// Floating point (AVX) comparison with both operands popped from the virtual stack.
-    fn emit_fp_cmpop_avx(
+    /// Pops a 16-byte value and an address, and pushes the former with its `sz` lane `lane`
+    /// replaced by the `sz` bits loaded from the latter.
+    fn emit_v128_load_lane(
         &mut self,
-        f: fn(&mut Assembler, XMM, XMMOrMemory, XMM),
+        memarg: &MemoryImmediate,
+        sz: Size,
+        lane: u8,
     ) -> Result<(), CodegenError> {
-        let I2O1 { loc_a, loc_b, ret } = self.i2o1_prepare(WpType::I32);
-
-        self.emit_relaxed_avx(f, loc_a, loc_b, ret)?;
+        let loc = self.pop_value_released();
+        let target = self.pop_value_released();
+        let ret = self
+            .machine
+            .acquire_locations(&mut self.assembler, &[WpType::V128], false)[0];
+        self.value_stack.push(ret);
+        let tmp_xmm = self.machine.acquire_temp_xmm().unwrap();
+        self.emit_move_v128(loc, Location::XMM(tmp_xmm));
+        self.emit_memory_op(target, memarg, false, size_in_bytes(sz), |this, addr| {
+            let tmp = this
+                .machine
+                .steal_temp_gpr(&mut this.assembler, &[Location::GPR(addr)]);
+            match sz {
+                Size::S8 | Size::S16 => this.assembler.emit_movzx(
+                    sz,
+                    Location::Memory(addr, 0),
+                    Size::S32,
+                    Location::GPR(tmp),
+                ),
+                Size::S32 | Size::S64 => {
+                    this.assembler
+                        .emit_mov(sz, Location::Memory(addr, 0), Location::GPR(tmp))
+                }
+            }
+            this.assembler.emit_pinsr(sz, tmp, lane, tmp_xmm);
+            this.machine.restore_stolen_gpr(&mut this.assembler, tmp);
+            Ok(())
+        })?;
+        self.emit_move_v128(Location::XMM(tmp_xmm), ret);
+        self.machine.release_temp_xmm(tmp_xmm);
+        Ok(())
+    }
 
-        // Workaround for behavior inconsistency among different backing implementations.
-        // (all bits or only the least significant bit are set to one?)
-        self.assembler.emit_and(Size::S32, Location::Imm32(1), ret);
+    /// Pops a 16-byte value and an address, and stores the `sz` lane `lane` of the former at
+    /// the latter.
+    fn emit_v128_store_lane(
+        &mut self,
+        memarg: &MemoryImmediate,
+        sz: Size,
+        lane: u8,
+    ) -> Result<(), CodegenError> {
+        let loc = self.pop_value_released();
+        let target = self.pop_value_released();
+        let tmp_xmm = self.machine.acquire_temp_xmm().unwrap();
+        let src = self.v128_to_xmm(loc, tmp_xmm);
+        self.emit_memory_op(target, memarg, false, size_in_bytes(sz), |this, addr| {
+            let tmp = this
+                .machine
+                .steal_temp_gpr(&mut this.assembler, &[Location::GPR(addr)]);
+            this.assembler.emit_pextr(sz, src, lane, tmp);
+            this.assembler
+                .emit_mov(sz, Location::GPR(tmp), Location::Memory(addr, 0));
+            this.machine.restore_stolen_gpr(&mut this.assembler, tmp);
+            Ok(())
+        })?;
+        self.machine.release_temp_xmm(tmp_xmm);
         Ok(())
     }
 
-    /// Floating point (AVX) unop with both operands popped from the virtual stack.
-    fn emit_fp_unop_avx(
+    /// Pops a 16-byte value and pushes its `sz` lane `lane` as a scalar of type `ty`.
+    fn emit_v128_extract_lane(&mut self, ty: WpType, sz: Size, lane: u8, sign_extend: bool) {
+        let loc = self.pop_value_released();
+        let ret = self
+            .machine
+            .acquire_locations(&mut self.assembler, &[ty], false)[0];
+        self.value_stack.push(ret);
+        if ty.is_float() {
+            self.fp_stack
+                .push(FloatValue::new(self.value_stack.len() - 1));
+        }
+        let tmp_xmm = self.machine.acquire_temp_xmm().unwrap();
+        let src = self.v128_to_xmm(loc, tmp_xmm);
+        let tmp = self.machine.steal_temp_gpr(&mut self.assembler, &[ret]);
+        self.assembler.emit_pextr(sz, src, lane, tmp);
+        if sign_extend {
+            self.assembler
+                .emit_movsx(sz, Location::GPR(tmp), Size::S32, Location::GPR(tmp));
+        }
+        let ret_sz = if sz == Size::S64 {
+            Size::S64
+        } else {
+            Size::S32
+        };
+        self.assembler.emit_mov(ret_sz, Location::GPR(tmp), ret);
+        self.machine.restore_stolen_gpr(&mut self.assembler, tmp);
+        self.machine.release_temp_xmm(tmp_xmm);
+    }
+
+    /// Pops a scalar of type `ty` and a 16-byte value, and pushes the latter with its `sz` lane
+    /// `lane` replaced by the former.
+    fn emit_v128_replace_lane(
         &mut self,
-        f: fn(&mut Assembler, XMM, XMMOrMemory, XMM),
+        ty: WpType,
+        sz: Size,
+        lane: u8,
     ) -> Result<(), CodegenError> {
+        let tmp = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
+        let mov_sz = if sz == Size::S64 {
+            Size::S64
+        } else {
+            Size::S32
+        };
+        self.pop_lane_value(ty, mov_sz, tmp)?;
         let loc = self.pop_value_released();
         let ret = self
             .machine
-            .acquire_locations(&mut self.assembler, &[(WpType::F64)], false)[0];
+            .acquire_locations(&mut self.assembler, &[WpType::V128], false)[0];
         self.value_stack.push(ret);
-        self.emit_relaxed_avx(f, loc, loc, ret)?;
+        let tmp_xmm = self.machine.acquire_temp_xmm().unwrap();
+        self.emit_move_v128(loc, Location::XMM(tmp_xmm));
+        self.assembler.emit_pinsr(sz, tmp, lane, tmp_xmm);
+        self.machine.restore_stolen_gpr(&mut self.assembler, tmp);
+        self.emit_move_v128(Location::XMM(tmp_xmm), ret);
+        self.machine.release_temp_xmm(tmp_xmm);
         Ok(())
     }
 
+    /// Pops a 16-byte value and pushes whether `test` sets the zero flag, or does not if
+    /// `zero` is false.
+    ///
+    /// `test` is given the value in an XMM register and a temporary XMM register.
+    fn emit_v128_test<F: FnOnce(&mut Self, XMM, XMM)>(&mut self, zero: bool, test: F) {
+        let loc = self.pop_value_released();
+        let ret = self
+            .machine
+            .acquire_locations(&mut self.assembler, &[WpType::I32], false)[0];
+        self.value_stack.push(ret);
+        let tmp_src = self.machine.acquire_temp_xmm().unwrap();
+        let tmp = self.machine.acquire_temp_xmm().unwrap();
+        let src = self.v128_to_xmm(loc, tmp_src);
+        test(self, src, tmp);
+        let gpr = self.machine.steal_temp_gpr(&mut self.assembler, &[ret]);
+        let condition = if zero {
+            Condition::Equal
+        } else {
+            Condition::NotEqual
+        };
+        self.assembler.emit_set(condition, gpr);
+        self.assembler
+            .emit_and(Size::S32, Location::Imm32(0xff), Location::GPR(gpr));
+        self.assembler.emit_mov(Size::S32, Location::GPR(gpr), ret);
+        self.machine.restore_stolen_gpr(&mut self.assembler, gpr);
+        self.machine.release_temp_xmm(tmp);
+        self.machine.release_temp_xmm(tmp_src);
+    }
+
+    /// Moves the 16-byte values among the params of a call that are in registers to the stack,
+    /// as they are passed as two 64-bit words.
+    fn spill_v128_params(&mut self, params: &mut [Location]) {
+        for param in params.iter_mut() {
+            if let Location::XMM(_) = *param {
+                if self.machine.is_v128(*param) {
+                    let slot = self
+                        .machine
+                        .acquire_stack_locations(&mut self.assembler, &[WpType::V128])[0];
+                    self.emit_move_v128(*param, slot);
+                    self.machine.release_locations(&[*param]);
+                    *param = slot;
+                }
+            }
+        }
+    }
+
     /// Emits a System V / Windows call sequence.
     ///
     /// This function will not use RAX before `cb` is called.
     ///
-    /// The caller MUST NOT hold any temporary registers allocated by `acquire_temp_gpr` when calling
+    /// The caller MUST NOT hold any temporary registers allocated by `steal_temp_gpr` when calling
     /// this function.
     fn emit_call_native<I: Iterator<Item = Location>, F: FnOnce(&mut Self)>(
         &mut self,
         cb: F,
         params: I,
     ) -> Result<(), CodegenError> {
-        let params: Vec<_> = params.collect();
-
-        // Save used GPRs.
-        let used_gprs = self.machine.get_used_gprs();
-        for r in used_gprs.iter() {
-            self.assembler.emit_push(Size::S64, Location::GPR(*r));
-        }
-
-        // Save used XMM registers.
-        let used_xmms = self.machine.get_used_xmms();
-        if used_xmms.len() > 0 {
-            self.assembler.emit_sub(
-                Size::S64,
-                Location::Imm32((used_xmms.len() * 8) as u32),
-                Location::GPR(GPR::RSP),
-            );
+        self.emit_call_native_with_results(cb, params, &[], false)
+    }
 
-            for (i, r) in used_xmms.iter().enumerate() {
-                self.assembler.emit_mov(
-                    Size::S64,
-                    Location::XMM(*r),
-                    Location::Memory(GPR::RSP, (i * 8) as i32),
-                );
-            }
-        }
+    /// Like `emit_call_native`, for callees returning more than two 64-bit words.
+    ///
+    /// The words beyond the first two are copied from the return area to `stack_results`, which
+    /// must be in stack slots acquired before the call.
+    ///
+    /// A `wasm_callee` may return with RSP elsewhere than before the call, after a tail call, see
+    /// `emit_return_call`. RSP is then reset from RBP.
+    fn emit_call_native_with_results<I: Iterator<Item = Location>, F: FnOnce(&mut Self)>(
+        &mut self,
+        cb: F,
+        params: I,
+        stack_results: &[Location],
+        wasm_callee: bool,
+    ) -> Result<(), CodegenError> {
+        let params: Vec<_> = params.collect();
         let calling_convention = self.calling_convention;
 
-        let stack_padding: usize = match calling_convention {
-            CallingConvention::WindowsFastcall => 32,
-            _ => 0,
-        };
-
-        let mut stack_offset: usize = 0;
-
-        // Calculate stack offset.
-        for (i, _param) in params.iter().enumerate() {
-            if let Location::Memory(_, _) = Machine::get_param_location(1 + i, calling_convention) {
-                stack_offset += 8;
-            }
+        // Move the values on the value stack out of the argument registers once and for all,
+        // rather than saving and restoring them around the call. The arguments themselves are
+        // left where they are, as well as the values the caller still holds, which aren't
+        // tracked on the value stack.
+        let arg_regs: SmallVec<[Location; 8]> = (0..=params.len())
+            .map(|i| Machine::get_param_location(i, calling_convention))
+            .filter(|loc| self.value_stack.contains(loc) && !params.contains(loc))
+            .collect();
+        for &reg in &arg_regs {
+            self.acquire_location_in(WpType::I64, reg);
         }
+        self.machine.release_locations_only_regs(&arg_regs);
 
-        // Align stack to 16 bytes.
-        if (self.machine.get_stack_offset()
-            + used_gprs.len() * 8
-            + used_xmms.len() * 8
-            + stack_offset)
-            % 16
-            != 0
-        {
-            self.assembler
-                .emit_sub(Size::S64, Location::Imm32(8), Location::GPR(GPR::RSP));
-            stack_offset += 8;
-        }
+        // The call frame is aligned based on the stack offset.
+        self.machine.flush_stack_adjustment(&mut self.assembler);
+
+        let n_stack_args = (0..params.len())
+            .filter(|i| {
+                matches!(
+                    Machine::get_param_location(1 + i, calling_convention),
+                    Location::Memory(_, _)
+                )
+            })
+            .count();
+        // The return area overlaps the stack arguments.
+        let n_stack_args = max(n_stack_args, stack_results.len());
+        let frame =
+            self.machine
+                .prepare_call_frame(&mut self.assembler, n_stack_args, calling_convention);
 
         let mut call_movs: Vec<(Location, GPR)> = vec![];
+        let mut stack_args = 0;
         // Prepare register & stack parameters.
-        for (i, param) in params.iter().enumerate().rev() {
+        for (i, param) in params.iter().enumerate() {
             let loc = Machine::get_param_location(1 + i, calling_convention);
             match loc {
                 Location::GPR(x) => {
                     call_movs.push((*param, x));
                 }
                 Location::Memory(_, _) => {
+                    let arg = frame.stack_arg_location(stack_args);
+                    stack_args += 1;
                     match *param {
-                        Location::GPR(_) => {}
-                        Location::XMM(_) => {}
                         Location::Memory(reg, _) => {
                             if reg != GPR::RBP {
                                 return Err(CodegenError {
@@ -1225,7 +2985,6 @@ impl<'a> FuncGen<'a> {
                                         .to_string(),
                                 });
                             }
-                            // TODO: Read value at this offset
                         }
                         _ => {}
                     }
@@ -1235,34 +2994,32 @@ impl<'a> FuncGen<'a> {
                             // immdiate value into a register and then write the register to the
                             // memory. Now the problem is that there might not be any registers
                             // available to clobber. In order to make this work out we spill a
-                            // register thus retaining both the original value of the
-                            // register and producing the required data at the top of the stack.
+                            // register, retaining the original value of the register.
                             //
                             // FIXME(#2723): figure out how to not require spilling a register
                             // here. It should definitely be possible to `pick_gpr`/`pick_temp_gpr`
                             // to grab an otherwise unused register and just clobber its value
                             // here.
+                            let arg = match arg {
+                                Location::Memory(base, disp) => Location::Memory(base, disp + 8),
+                                _ => unreachable!(),
+                            };
                             self.assembler.emit_push(Size::S64, Location::GPR(GPR::R9));
                             self.assembler
                                 .emit_mov(Size::S64, *param, Location::GPR(GPR::R9));
-                            self.assembler.emit_xchg(
-                                Size::S64,
-                                Location::GPR(GPR::R9),
-                                Location::Memory(GPR::RSP, 0),
-                            );
+                            self.assembler
+                                .emit_mov(Size::S64, Location::GPR(GPR::R9), arg);
+                            self.assembler.emit_pop(Size::S64, Location::GPR(GPR::R9));
                         }
-                        Location::XMM(_) => {
-                            // Dummy value slot to be filled with `mov`.
-                            self.assembler.emit_push(Size::S64, Location::GPR(GPR::RAX));
-
-                            // XMM registers can be directly stored to memory.
-                            self.assembler.emit_mov(
-                                Size::S64,
-                                *param,
-                                Location::Memory(GPR::RSP, 0),
-                            );
+                        Location::GPR(_) | Location::XMM(_) => {
+                            self.assembler.emit_mov(Size::S64, *param, arg);
+                        }
+                        _ => {
+                            // `pop` computes the address of its operand after incrementing RSP,
+                            // so this copies the value right into `arg`.
+                            self.assembler.emit_push(Size::S64, *param);
+                            self.assembler.emit_pop(Size::S64, arg);
                         }
-                        _ => self.assembler.emit_push(Size::S64, *param),
                     }
                 }
                 _ => {
@@ -1290,62 +3047,215 @@ impl<'a> FuncGen<'a> {
             Machine::get_param_location(0, calling_convention),
         ); // vmctx
 
-        if stack_padding > 0 {
-            self.assembler.emit_sub(
+        cb(self);
+
+        if wasm_callee && self.exception_handlers {
+            if let Some(dispatch) = self.exception_handler(self.control_stack.len() - 1) {
+                let rsp = self.machine.get_stack_offset() + RED_ZONE_SIZE as usize + frame.depth();
+                self.landing_pads.push(LandingPadStub {
+                    return_offset: self.assembler.get_offset().0,
+                    frame: frame.clone(),
+                    rsp,
+                    dispatch,
+                });
+            }
+        }
+
+        // RAX and RDX may hold the other results, but RCX is free.
+        for (i, result) in stack_results.iter().enumerate() {
+            self.assembler.emit_mov(
                 Size::S64,
-                Location::Imm32(stack_padding as u32),
-                Location::GPR(GPR::RSP),
+                frame.stack_arg_location(i),
+                Location::GPR(GPR::RCX),
             );
+            self.assembler
+                .emit_mov(Size::S64, Location::GPR(GPR::RCX), *result);
         }
 
-        cb(self);
-
-        // Restore stack.
-        if stack_offset + stack_padding > 0 {
-            self.assembler.emit_add(
+        if wasm_callee {
+            let depth = self.machine.get_stack_offset() + RED_ZONE_SIZE as usize + frame.depth();
+            self.assembler.emit_lea(
                 Size::S64,
-                Location::Imm32((stack_offset + stack_padding) as u32),
+                Location::Memory(GPR::RBP, -(depth as i32)),
                 Location::GPR(GPR::RSP),
             );
-            if (stack_offset % 8) != 0 {
-                return Err(CodegenError {
-                    message: "emit_call_native: Bad restoring stack alignement".to_string(),
-                });
-            }
         }
+        self.machine.restore_call_frame(&mut self.assembler, frame);
 
-        // Restore XMMs.
-        if !used_xmms.is_empty() {
-            for (i, r) in used_xmms.iter().enumerate() {
-                self.assembler.emit_mov(
-                    Size::S64,
-                    Location::Memory(GPR::RSP, (i * 8) as i32),
-                    Location::XMM(*r),
-                );
-            }
-            self.assembler.emit_add(
+        Ok(())
+    }
+
+    /// Emits a System V call sequence, specialized for labels as the call target.
+    fn _emit_call_native_label<I: Iterator<Item = Location>>(
+        &mut self,
+        label: DynamicLabel,
+        params: I,
+    ) -> Result<(), CodegenError> {
+        self.emit_call_native(|this| this.assembler.emit_call_label(label), params)?;
+        Ok(())
+    }
+
+    /// Emits the `memory.grow` of `pages` pages that compiled code does by itself, within the
+    /// `reserved_length` of the memory, leaving the previous size of the memory in RAX.
+    ///
+    /// Jumps to `runtime_grow` instead when the runtime must do the grow: when it doesn't fit
+    /// in the reservation, when it's a `memory.grow 0` the runtime may have to report, and when
+    /// another thread grew the shared memory meanwhile.
+    fn emit_inline_memory_grow(
+        &mut self,
+        memory_index: MemoryIndex,
+        pages: Location,
+        runtime_grow: DynamicLabel,
+    ) {
+        let vmctx = Machine::get_vmctx_reg();
+        let shared = self.module.memories[memory_index].shared;
+        // RAX is the operand `cmpxchg` compares with.
+        let current = self.machine.reserve_unused_temp_gpr(GPR::RAX);
+        let definition = self
+            .machine
+            .steal_temp_gpr(&mut self.assembler, &[pages, Location::GPR(current)]);
+        match self.module.local_memory_index(memory_index) {
+            Some(local_memory_index) => self.assembler.emit_lea(
+                Size::S64,
+                Location::Memory(
+                    vmctx,
+                    self.vmoffsets.vmctx_vmmemory_definition(local_memory_index) as i32,
+                ),
+                Location::GPR(definition),
+            ),
+            None => self.assembler.emit_mov(
+                Size::S64,
+                Location::Memory(
+                    vmctx,
+                    self.vmoffsets
+                        .vmctx_vmmemory_import_definition(memory_index) as i32,
+                ),
+                Location::GPR(definition),
+            ),
+        }
+        let current_length = Location::Memory(
+            definition,
+            self.vmoffsets.vmmemory_definition_current_length() as i32,
+        );
+        let reserved_length = Location::Memory(
+            definition,
+            self.vmoffsets.vmmemory_definition_reserved_length() as i32,
+        );
+        let new_length = self.machine.steal_temp_gpr(
+            &mut self.assembler,
+            &[pages, Location::GPR(current), Location::GPR(definition)],
+        );
+
+        let failed = self.assembler.get_label();
+        let done = self.assembler.get_label();
+        self.assembler
+            .emit_mov(Size::S32, pages, Location::GPR(new_length));
+        self.assembler
+            .emit_cmp(Size::S32, Location::Imm32(0), Location::GPR(new_length));
+        self.assembler.emit_jmp(Condition::Equal, failed);
+        self.assembler
+            .emit_shl(Size::S64, Location::Imm8(16), Location::GPR(new_length));
+        self.assembler
+            .emit_mov(Size::S64, current_length, Location::GPR(current));
+        self.assembler
+            .emit_add(Size::S64, Location::GPR(current), Location::GPR(new_length));
+        self.assembler
+            .emit_cmp(Size::S64, reserved_length, Location::GPR(new_length));
+        self.assembler.emit_jmp(Condition::Above, failed);
+        if shared {
+            // Fails, and reloads the current length, when another thread grew the memory.
+            self.assembler
+                .emit_lock_cmpxchg(Size::S64, Location::GPR(new_length), current_length);
+            self.assembler.emit_jmp(Condition::NotEqual, failed);
+        } else {
+            self.assembler
+                .emit_mov(Size::S64, Location::GPR(new_length), current_length);
+        }
+        self.assembler.emit_jmp(Condition::None, done);
+
+        // No memory is `u64::MAX` bytes long, so this tells the paths apart once the stolen
+        // registers are restored.
+        self.assembler.emit_label(failed);
+        self.assembler
+            .emit_mov(Size::S64, Location::Imm32(u32::MAX), Location::GPR(current));
+        self.assembler.emit_label(done);
+        self.machine
+            .restore_stolen_gpr(&mut self.assembler, new_length);
+        self.machine
+            .restore_stolen_gpr(&mut self.assembler, definition);
+        self.machine.release_temp_gpr(current);
+        self.assembler
+            .emit_cmp(Size::S64, Location::Imm32(u32::MAX), Location::GPR(current));
+        self.assembler.emit_jmp(Condition::Equal, runtime_grow);
+        self.assembler
+            .emit_shr(Size::S64, Location::Imm8(16), Location::GPR(current));
+    }
+
+    /// Loads the base of the memory into `base`, and the address right past its current length
+    /// into `end` if given.
+    fn emit_load_memory(&mut self, base: GPR, end: Option<GPR>) {
+        let vmctx = Machine::get_vmctx_reg();
+        let (definition, offset) = if self.module.import_counts.memories != 0 {
+            // Imported memories require one level of indirection.
+            let offset = self
+                .vmoffsets
+                .vmctx_vmmemory_import_definition(MemoryIndex::new(0));
+            self.assembler.emit_mov(
                 Size::S64,
-                Location::Imm32((used_xmms.len() * 8) as u32),
-                Location::GPR(GPR::RSP),
+                Location::Memory(vmctx, offset as i32),
+                Location::GPR(base),
             );
+            (base, 0)
+        } else {
+            let offset = self
+                .vmoffsets
+                .vmctx_vmmemory_definition(LocalMemoryIndex::new(0));
+            (vmctx, offset as i32)
+        };
+        let field = |offset_in_definition: u8| {
+            Location::Memory(definition, offset + i32::from(offset_in_definition))
+        };
+        let current_length = field(self.vmoffsets.vmmemory_definition_current_length());
+        let memory_base = field(self.vmoffsets.vmmemory_definition_base());
+        // The length is loaded first, as `base` may hold the address of the definition.
+        if let Some(end) = end {
+            self.assembler
+                .emit_mov(Size::S64, current_length, Location::GPR(end));
         }
-
-        // Restore GPRs.
-        for r in used_gprs.iter().rev() {
-            self.assembler.emit_pop(Size::S64, Location::GPR(*r));
+        self.assembler
+            .emit_mov(Size::S64, memory_base, Location::GPR(base));
+        if let Some(end) = end {
+            self.assembler
+                .emit_add(Size::S64, Location::GPR(base), Location::GPR(end));
         }
-
-        Ok(())
     }
 
-    /// Emits a System V call sequence, specialized for labels as the call target.
-    fn _emit_call_native_label<I: Iterator<Item = Location>>(
-        &mut self,
-        label: DynamicLabel,
-        params: I,
-    ) -> Result<(), CodegenError> {
-        self.emit_call_native(|this| this.assembler.emit_call_label(label), params)?;
-        Ok(())
+    /// The location of the value of the global `global_index`, and the temporary register to
+    /// give back once done with it, holding the address of its definition.
+    ///
+    /// The defined globals living in the vmctx are accessed right there, the others through the
+    /// pointer to their definition.
+    fn global_location(&mut self, global_index: GlobalIndex) -> (Location, Option<GPR>) {
+        let vmctx = Machine::get_vmctx_reg();
+        let offset = match self.module.local_global_index(global_index) {
+            Some(local_global_index) => {
+                if let Some(offset) = self.vmoffsets.vmctx_vmglobal_cell(local_global_index) {
+                    return (Location::Memory(vmctx, offset as i32), None);
+                }
+                self.vmoffsets.vmctx_vmglobal_definition(local_global_index)
+            }
+            // Imported globals require one level of indirection.
+            None => self
+                .vmoffsets
+                .vmctx_vmglobal_import_definition(global_index),
+        };
+        let tmp = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
+        self.assembler.emit_mov(
+            Size::S64,
+            Location::Memory(vmctx, offset as i32),
+            Location::GPR(tmp),
+        );
+        (Location::Memory(tmp, 0), Some(tmp))
     }
 
     /// Emits a memory operation.
@@ -1357,98 +3267,142 @@ impl<'a> FuncGen<'a> {
         value_size: usize,
         cb: F,
     ) -> Result<(), CodegenError> {
-        let need_check = true;
-        let tmp_addr = self.machine.acquire_temp_gpr().unwrap();
-
-        // Reusing `tmp_addr` for temporary indirection here, since it's not used before the last reference to `{base,bound}_loc`.
-        let (base_loc, bound_loc) = if self.module.import_counts.memories != 0 {
-            // Imported memories require one level of indirection.
-            let offset = self
-                .vmoffsets
-                .vmctx_vmmemory_import_definition(MemoryIndex::new(0));
-            self.emit_relaxed_binop(
-                Assembler::emit_mov,
-                Size::S64,
-                Location::Memory(Machine::get_vmctx_reg(), offset as i32),
-                Location::GPR(tmp_addr),
-            );
-            (Location::Memory(tmp_addr, 0), Location::Memory(tmp_addr, 8))
-        } else {
-            let offset = self
-                .vmoffsets
-                .vmctx_vmmemory_definition(LocalMemoryIndex::new(0));
-            (
-                Location::Memory(Machine::get_vmctx_reg(), offset as i32),
-                Location::Memory(Machine::get_vmctx_reg(), (offset + 8) as i32),
-            )
+        // Both styles check the accesses against the current size of the memory. The accesses
+        // past the end of a static memory would land in its guard pages, but singlepass doesn't
+        // turn those faults into traps, and the guard of a dynamic memory is too small to catch
+        // the accesses far out of bounds.
+        //
+        // The guard pages of no style catch the accesses of 64-bit memories either.
+        let memory64 = self.memory64();
+        let need_check = match self.memory_styles[MemoryIndex::new(0)] {
+            _ if memory64 => true,
+            MemoryStyle::Static { .. } => true,
+            MemoryStyle::Dynamic { .. } => true,
         };
 
-        let tmp_base = self.machine.acquire_temp_gpr().unwrap();
-        let tmp_bound = self.machine.acquire_temp_gpr().unwrap();
-
-        // Load base into temporary register.
-        self.assembler
-            .emit_mov(Size::S64, base_loc, Location::GPR(tmp_base));
-
-        // Load bound into temporary register, if needed.
-        if need_check {
-            self.assembler
-                .emit_mov(Size::S64, bound_loc, Location::GPR(tmp_bound));
-
-            // Wasm -> Effective.
-            // Assuming we never underflow - should always be true on Linux/macOS and Windows >=8,
-            // since the first page from 0x0 to 0x1000 is not accepted by mmap.
-
-            // This `lea` calculates the upper bound allowed for the beginning of the word.
-            // Since the upper bound of the memory is (exclusively) `tmp_bound + tmp_base`,
-            // the maximum allowed beginning of word is (inclusively)
-            // `tmp_bound + tmp_base - value_size`.
-            self.assembler.emit_lea(
-                Size::S64,
-                Location::MemoryAddTriple(tmp_bound, tmp_base, -(value_size as i32)),
-                Location::GPR(tmp_bound),
-            );
-        }
+        // The base and the end of the memory are loaded by the first access of a basic block,
+        // and kept in registers for the next ones. The base of a dynamic memory moves when it
+        // grows past its allocation, and only a call or a `memory.grow` can grow it, which
+        // forget the cache. Without registers to spare, they are loaded for this access only.
+        let cache = match self.machine.memory_cache() {
+            Some(cache) => Some(cache),
+            None => {
+                let cache = self.machine.acquire_memory_cache(need_check);
+                if let Some(cache) = cache {
+                    self.emit_load_memory(cache.base, cache.end);
+                }
+                cache
+            }
+        };
+        let tmp_addr = self.machine.steal_temp_gpr(&mut self.assembler, &[addr]);
+        let (base, end, stolen) = match cache {
+            Some(cache) => (cache.base, cache.end, false),
+            None => {
+                let tmp_base = self
+                    .machine
+                    .steal_temp_gpr(&mut self.assembler, &[addr, Location::GPR(tmp_addr)]);
+                let tmp_end = if need_check {
+                    Some(self.machine.steal_temp_gpr(
+                        &mut self.assembler,
+                        &[addr, Location::GPR(tmp_addr), Location::GPR(tmp_base)],
+                    ))
+                } else {
+                    None
+                };
+                self.emit_load_memory(tmp_base, tmp_end);
+                (tmp_base, tmp_end, true)
+            }
+        };
 
         // Load effective address.
-        // `base_loc` and `bound_loc` becomes INVALID after this line, because `tmp_addr`
-        // might be reused.
+        let address_size = if memory64 { Size::S64 } else { Size::S32 };
         self.assembler
-            .emit_mov(Size::S32, addr, Location::GPR(tmp_addr));
+            .emit_mov(address_size, addr, Location::GPR(tmp_addr));
+
+        // Both checks jump to the same trap, which finds the address of the access in `addr`.
+        let trap = if memarg.offset != 0 || need_check {
+            Some(self.memory_access_trap_label(addr, memarg.offset, value_size))
+        } else {
+            None
+        };
 
         // Add offset to memory address.
         if memarg.offset != 0 {
-            self.assembler.emit_add(
-                Size::S32,
-                Location::Imm32(memarg.offset),
-                Location::GPR(tmp_addr),
-            );
+            if memory64 && memarg.offset > i32::MAX as u32 {
+                // A 64-bit addition would sign-extend the offset as an immediate.
+                let tmp_offset = self
+                    .machine
+                    .steal_temp_gpr(&mut self.assembler, &[addr, Location::GPR(tmp_addr)]);
+                self.assembler.emit_mov(
+                    Size::S32,
+                    Location::Imm32(memarg.offset),
+                    Location::GPR(tmp_offset),
+                );
+                self.assembler.emit_add(
+                    Size::S64,
+                    Location::GPR(tmp_offset),
+                    Location::GPR(tmp_addr),
+                );
+                self.machine
+                    .restore_stolen_gpr(&mut self.assembler, tmp_offset);
+            } else {
+                self.assembler.emit_add(
+                    address_size,
+                    Location::Imm32(memarg.offset),
+                    Location::GPR(tmp_addr),
+                );
+            }
 
             // Trap if offset calculation overflowed.
-            self.assembler
-                .emit_jmp(Condition::Carry, self.special_labels.heap_access_oob);
+            self.assembler.emit_jmp(Condition::Carry, trap.unwrap());
         }
 
         // Wasm linear memory -> real memory
         self.assembler
-            .emit_add(Size::S64, Location::GPR(tmp_base), Location::GPR(tmp_addr));
+            .emit_add(Size::S64, Location::GPR(base), Location::GPR(tmp_addr));
+        if memory64 {
+            // A 64-bit address may wrap around the address space past the base, back below the
+            // end of the memory.
+            self.assembler.emit_jmp(Condition::Carry, trap.unwrap());
+        }
+
+        if let Some(end) = end {
+            // Assuming we never underflow - should always be true on Linux/macOS and Windows >=8,
+            // since the first page from 0x0 to 0x1000 is not accepted by mmap.
+
+            // The maximum allowed beginning of the word is (inclusively) `end - value_size`,
+            // computed in place if `end` is only loaded for this access.
+            let tmp_bound = if stolen {
+                end
+            } else {
+                self.machine
+                    .steal_temp_gpr(&mut self.assembler, &[addr, Location::GPR(tmp_addr)])
+            };
+            self.assembler.emit_lea(
+                Size::S64,
+                Location::Memory(end, -(value_size as i32)),
+                Location::GPR(tmp_bound),
+            );
 
-        if need_check {
             // Trap if the end address of the requested area is above that of the linear memory.
             self.assembler
                 .emit_cmp(Size::S64, Location::GPR(tmp_bound), Location::GPR(tmp_addr));
 
             // `tmp_bound` is inclusive. So trap only if `tmp_addr > tmp_bound`.
-            self.assembler
-                .emit_jmp(Condition::Above, self.special_labels.heap_access_oob);
-        }
+            self.assembler.emit_jmp(Condition::Above, trap.unwrap());
 
-        self.machine.release_temp_gpr(tmp_bound);
-        self.machine.release_temp_gpr(tmp_base);
+            self.machine
+                .restore_stolen_gpr(&mut self.assembler, tmp_bound);
+        }
+        if stolen {
+            self.machine.restore_stolen_gpr(&mut self.assembler, base);
+        }
 
-        let align = memarg.align;
-        if check_alignment && align != 1 {
-            let tmp_aligncheck = self.machine.acquire_temp_gpr().unwrap();
+        // Atomic accesses must be aligned to their size, whatever the alignment hint.
+        if check_alignment && value_size != 1 {
+            let tmp_aligncheck = self
+                .machine
+                .steal_temp_gpr(&mut self.assembler, &[Location::GPR(tmp_addr)]);
             self.assembler.emit_mov(
                 Size::S32,
                 Location::GPR(tmp_addr),
@@ -1456,17 +3410,77 @@ impl<'a> FuncGen<'a> {
             );
             self.assembler.emit_and(
                 Size::S64,
-                Location::Imm32((align - 1).into()),
+                Location::Imm32(value_size as u32 - 1),
                 Location::GPR(tmp_aligncheck),
             );
-            self.assembler
-                .emit_jmp(Condition::NotEqual, self.special_labels.heap_access_oob);
-            self.machine.release_temp_gpr(tmp_aligncheck);
+            let trap = self.trap_label(TrapCode::UnalignedAtomic);
+            self.assembler.emit_jmp(Condition::NotEqual, trap);
+            self.machine
+                .restore_stolen_gpr(&mut self.assembler, tmp_aligncheck);
         }
 
         cb(self, tmp_addr).unwrap();
 
-        self.machine.release_temp_gpr(tmp_addr);
+        self.machine
+            .restore_stolen_gpr(&mut self.assembler, tmp_addr);
+        Ok(())
+    }
+
+    /// Emits `memory.atomic.wait32` or `memory.atomic.wait64`, for a `value_size`-byte value.
+    fn emit_atomic_wait(
+        &mut self,
+        memarg: &MemoryImmediate,
+        value_size: usize,
+    ) -> Result<(), CodegenError> {
+        let timeout = self.value_stack.pop().unwrap();
+        let expected = self.value_stack.pop().unwrap();
+        let target = self.value_stack.pop().unwrap();
+
+        // The runtime gets the host address of the value, which also identifies it for the
+        // other instances sharing the memory.
+        let addr = self
+            .machine
+            .acquire_locations(&mut self.assembler, &[WpType::I64], false)[0];
+        self.emit_memory_op(target, memarg, true, value_size, |this, host_addr| {
+            this.assembler
+                .emit_mov(Size::S64, Location::GPR(host_addr), addr);
+            Ok(())
+        })?;
+        // No other thread could ever notify a waiter on an unshared memory.
+        if !self.module.memories[MemoryIndex::new(0)].shared {
+            self.emit_trap(TrapCode::AtomicWaitNonSharedMemory);
+        }
+
+        let builtin = match value_size {
+            4 => VMBuiltinFunctionIndex::get_memory_atomic_wait32_index(),
+            _ => VMBuiltinFunctionIndex::get_memory_atomic_wait64_index(),
+        };
+        self.machine
+            .release_locations_only_regs(&[target, expected, timeout, addr]);
+        self.assembler.emit_mov(
+            Size::S64,
+            Location::Memory(
+                Machine::get_vmctx_reg(),
+                self.vmoffsets.vmctx_builtin_function(builtin) as i32,
+            ),
+            Location::GPR(GPR::RAX),
+        );
+        self.emit_call_native(
+            |this| {
+                this.assembler.emit_call_register(GPR::RAX);
+            },
+            // [vmctx, addr, expected, timeout] -> i32
+            [addr, expected, timeout].iter().cloned(),
+        )?;
+        self.machine
+            .release_locations_only_stack(&[target, expected, timeout, addr]);
+
+        let ret = self
+            .machine
+            .acquire_locations(&mut self.assembler, &[WpType::I32], false)[0];
+        self.value_stack.push(ret);
+        self.assembler
+            .emit_mov(Size::S32, Location::GPR(GPR::RAX), ret);
         Ok(())
     }
 
@@ -1488,6 +3502,8 @@ impl<'a> FuncGen<'a> {
             });
         }
 
+        // The register pushed below moves RSP, which only the frame pointer keeps track of.
+        self.machine.require_frame();
         let compare = self.machine.reserve_unused_temp_gpr(GPR::RAX);
         let value = if loc == Location::GPR(GPR::R14) {
             GPR::R13
@@ -1523,7 +3539,8 @@ impl<'a> FuncGen<'a> {
         self.assembler.emit_jmp(Condition::NotEqual, retry);
 
         self.assembler.emit_pop(Size::S64, Location::GPR(value));
-        self.machine.release_temp_gpr(compare);
+        self.machine
+            .restore_stolen_gpr(&mut self.assembler, compare);
         Ok(())
     }
 
@@ -1541,7 +3558,7 @@ impl<'a> FuncGen<'a> {
         let lower_bound = f32::to_bits(lower_bound);
         let upper_bound = f32::to_bits(upper_bound);
 
-        let tmp = self.machine.acquire_temp_gpr().unwrap();
+        let tmp = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
         let tmp_x = self.machine.acquire_temp_xmm().unwrap();
 
         // Underflow.
@@ -1583,13 +3600,13 @@ impl<'a> FuncGen<'a> {
         self.assembler.emit_jmp(Condition::None, succeed_label);
 
         self.machine.release_temp_xmm(tmp_x);
-        self.machine.release_temp_gpr(tmp);
+        self.machine.restore_stolen_gpr(&mut self.assembler, tmp);
     }
 
     // Checks for underflow/overflow/nan before IxxTrunc{U/S}F32.
     fn emit_f32_int_conv_check_trap(&mut self, reg: XMM, lower_bound: f32, upper_bound: f32) {
-        let trap_overflow = self.special_labels.integer_overflow;
-        let trap_badconv = self.special_labels.bad_conversion_to_integer;
+        let trap_overflow = self.trap_label(TrapCode::IntegerOverflow);
+        let trap_badconv = self.trap_label(TrapCode::BadConversionToInteger);
         let end = self.assembler.get_label();
 
         self.emit_f32_int_conv_check(
@@ -1605,64 +3622,6 @@ impl<'a> FuncGen<'a> {
         self.assembler.emit_label(end);
     }
 
-    fn emit_f32_int_conv_check_sat<
-        F1: FnOnce(&mut Self),
-        F2: FnOnce(&mut Self),
-        F3: FnOnce(&mut Self),
-        F4: FnOnce(&mut Self),
-    >(
-        &mut self,
-        reg: XMM,
-        lower_bound: f32,
-        upper_bound: f32,
-        underflow_cb: F1,
-        overflow_cb: F2,
-        nan_cb: Option<F3>,
-        convert_cb: F4,
-    ) {
-        // As an optimization nan_cb is optional, and when set to None we turn
-        // use 'underflow' as the 'nan' label. This is useful for callers who
-        // set the return value to zero for both underflow and nan.
-
-        let underflow = self.assembler.get_label();
-        let overflow = self.assembler.get_label();
-        let nan = if nan_cb.is_some() {
-            self.assembler.get_label()
-        } else {
-            underflow
-        };
-        let convert = self.assembler.get_label();
-        let end = self.assembler.get_label();
-
-        self.emit_f32_int_conv_check(
-            reg,
-            lower_bound,
-            upper_bound,
-            underflow,
-            overflow,
-            nan,
-            convert,
-        );
-
-        self.assembler.emit_label(underflow);
-        underflow_cb(self);
-        self.assembler.emit_jmp(Condition::None, end);
-
-        self.assembler.emit_label(overflow);
-        overflow_cb(self);
-        self.assembler.emit_jmp(Condition::None, end);
-
-        if let Some(cb) = nan_cb {
-            self.assembler.emit_label(nan);
-            cb(self);
-            self.assembler.emit_jmp(Condition::None, end);
-        }
-
-        self.assembler.emit_label(convert);
-        convert_cb(self);
-        self.assembler.emit_label(end);
-    }
-
     // Checks for underflow/overflow/nan.
     fn emit_f64_int_conv_check(
         &mut self,
@@ -1677,7 +3636,7 @@ impl<'a> FuncGen<'a> {
         let lower_bound = f64::to_bits(lower_bound);
         let upper_bound = f64::to_bits(upper_bound);
 
-        let tmp = self.machine.acquire_temp_gpr().unwrap();
+        let tmp = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
         let tmp_x = self.machine.acquire_temp_xmm().unwrap();
 
         // Underflow.
@@ -1719,13 +3678,13 @@ impl<'a> FuncGen<'a> {
         self.assembler.emit_jmp(Condition::None, succeed_label);
 
         self.machine.release_temp_xmm(tmp_x);
-        self.machine.release_temp_gpr(tmp);
+        self.machine.restore_stolen_gpr(&mut self.assembler, tmp);
     }
 
     // Checks for underflow/overflow/nan before IxxTrunc{U/S}F64.
     fn emit_f64_int_conv_check_trap(&mut self, reg: XMM, lower_bound: f64, upper_bound: f64) {
-        let trap_overflow = self.special_labels.integer_overflow;
-        let trap_badconv = self.special_labels.bad_conversion_to_integer;
+        let trap_overflow = self.trap_label(TrapCode::IntegerOverflow);
+        let trap_badconv = self.trap_label(TrapCode::BadConversionToInteger);
         let end = self.assembler.get_label();
 
         self.emit_f64_int_conv_check(
@@ -1741,62 +3700,265 @@ impl<'a> FuncGen<'a> {
         self.assembler.emit_label(end);
     }
 
-    fn emit_f64_int_conv_check_sat<
-        F1: FnOnce(&mut Self),
-        F2: FnOnce(&mut Self),
-        F3: FnOnce(&mut Self),
-        F4: FnOnce(&mut Self),
-    >(
+    /// Emits `iNN.trunc_sat_fMM_{s,u}` for an `int_sz` result from a `float_sz` float.
+    ///
+    /// The input is truncated right away: `cvtts{s,d}2si` gets every in-range input right and
+    /// returns its "integer indefinite" value (the minimum of the signed type) for NaN and
+    /// out-of-range inputs. Only then is the input compared to find which saturated value it
+    /// gets, so the common case costs one conversion and a well-predicted branch.
+    fn emit_trunc_sat(
         &mut self,
-        reg: XMM,
-        lower_bound: f64,
-        upper_bound: f64,
-        underflow_cb: F1,
-        overflow_cb: F2,
-        nan_cb: Option<F3>,
-        convert_cb: F4,
-    ) {
-        // As an optimization nan_cb is optional, and when set to None we turn
-        // use 'underflow' as the 'nan' label. This is useful for callers who
-        // set the return value to zero for both underflow and nan.
-
-        let underflow = self.assembler.get_label();
-        let overflow = self.assembler.get_label();
-        let nan = if nan_cb.is_some() {
-            self.assembler.get_label()
-        } else {
-            underflow
+        float_sz: Size,
+        int_sz: Size,
+        signed: bool,
+    ) -> Result<(), CodegenError> {
+        let loc = self.pop_value_released();
+        let ty = match int_sz {
+            Size::S32 => WpType::I32,
+            _ => WpType::I64,
         };
-        let convert = self.assembler.get_label();
+        let ret = self
+            .machine
+            .acquire_locations(&mut self.assembler, &[ty], false)[0];
+        self.value_stack.push(ret);
+        self.fp_stack.pop1()?;
+
+        let tmp_out = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
+        let tmp = self
+            .machine
+            .steal_temp_gpr(&mut self.assembler, &[Location::GPR(tmp_out)]);
+        let tmp_in = self.machine.acquire_temp_xmm().unwrap();
+        let tmp_x = self.machine.acquire_temp_xmm().unwrap();
         let end = self.assembler.get_label();
 
-        self.emit_f64_int_conv_check(
-            reg,
-            lower_bound,
-            upper_bound,
-            underflow,
-            overflow,
-            nan,
-            convert,
-        );
+        self.emit_relaxed_binop(Assembler::emit_mov, float_sz, loc, Location::XMM(tmp_in));
+        let truncate =
+            |a: &mut Assembler, int_sz: Size, src: XMM, dst: GPR| match (float_sz, int_sz) {
+                (Size::S32, Size::S32) => a.emit_cvttss2si_32(XMMOrMemory::XMM(src), dst),
+                (Size::S32, _) => a.emit_cvttss2si_64(XMMOrMemory::XMM(src), dst),
+                (_, Size::S32) => a.emit_cvttsd2si_32(XMMOrMemory::XMM(src), dst),
+                _ => a.emit_cvttsd2si_64(XMMOrMemory::XMM(src), dst),
+            };
+        let compare = |a: &mut Assembler, src: XMM, dst: XMM| match float_sz {
+            Size::S32 => a.emit_ucomiss(XMMOrMemory::XMM(src), dst),
+            _ => a.emit_ucomisd(XMMOrMemory::XMM(src), dst),
+        };
+
+        match (int_sz, signed) {
+            (_, true) => {
+                truncate(&mut self.assembler, int_sz, tmp_in, tmp_out);
+                // Only the minimum overflows when decremented.
+                self.assembler
+                    .emit_cmp(int_sz, Location::Imm32(1), Location::GPR(tmp_out));
+                self.assembler.emit_jmp(Condition::NotOverflow, end);
+
+                // The minimum is right for negative inputs, positive ones get the maximum and
+                // NaN (unordered, so ZF is set) gets zero.
+                self.assembler
+                    .emit_vxorps(tmp_x, XMMOrMemory::XMM(tmp_x), tmp_x);
+                compare(&mut self.assembler, tmp_x, tmp_in);
+                match int_sz {
+                    Size::S32 => {
+                        self.assembler.emit_mov(
+                            Size::S32,
+                            Location::Imm32(std::i32::MAX as u32),
+                            Location::GPR(tmp),
+                        );
+                        self.assembler.emit_cmovcc(
+                            Size::S32,
+                            Condition::AboveEqual,
+                            Location::GPR(tmp),
+                            Location::GPR(tmp_out),
+                        );
+                    }
+                    _ => {
+                        self.assembler.emit_mov(
+                            Size::S64,
+                            Location::Imm64(std::i64::MAX as u64),
+                            Location::GPR(tmp),
+                        );
+                        self.assembler.emit_cmovcc(
+                            Size::S64,
+                            Condition::AboveEqual,
+                            Location::GPR(tmp),
+                            Location::GPR(tmp_out),
+                        );
+                    }
+                }
+                self.assembler.emit_jmp(Condition::NotEqual, end);
+                self.assembler
+                    .emit_mov(Size::S32, Location::Imm32(0), Location::GPR(tmp_out));
+            }
+            (Size::S32, false) => {
+                // All of the u32 range fits in the result of a 64-bit truncation.
+                truncate(&mut self.assembler, Size::S64, tmp_in, tmp_out);
+                self.assembler.emit_mov(
+                    Size::S32,
+                    Location::Imm32(std::u32::MAX),
+                    Location::GPR(tmp),
+                );
+                self.assembler
+                    .emit_cmp(Size::S64, Location::GPR(tmp), Location::GPR(tmp_out));
+                self.assembler.emit_jmp(Condition::BelowEqual, end);
+
+                // Positive inputs get the maximum, negative ones and NaN (unordered, so CF is
+                // set) get zero. Moving zero is a flag-setting `xor`, so it goes first.
+                self.assembler
+                    .emit_mov(Size::S32, Location::Imm32(0), Location::GPR(tmp_out));
+                self.assembler
+                    .emit_vxorps(tmp_x, XMMOrMemory::XMM(tmp_x), tmp_x);
+                compare(&mut self.assembler, tmp_x, tmp_in);
+                self.assembler.emit_cmovcc(
+                    Size::S32,
+                    Condition::AboveEqual,
+                    Location::GPR(tmp),
+                    Location::GPR(tmp_out),
+                );
+            }
+            (_, false) => {
+                truncate(&mut self.assembler, Size::S64, tmp_in, tmp_out);
+                self.assembler
+                    .emit_cmp(Size::S64, Location::Imm32(0), Location::GPR(tmp_out));
+                self.assembler.emit_jmp(Condition::GreaterEqual, end);
+
+                // Inputs below 2^63 and NaN (unordered, so CF is set) get zero.
+                let upper_half = self.assembler.get_label();
+                match float_sz {
+                    Size::S32 => self.assembler.emit_mov(
+                        Size::S32,
+                        Location::Imm32(f32::to_bits(9223372036854775808.0)),
+                        Location::GPR(tmp),
+                    ),
+                    _ => self.assembler.emit_mov(
+                        Size::S64,
+                        Location::Imm64(f64::to_bits(9223372036854775808.0)),
+                        Location::GPR(tmp),
+                    ),
+                }
+                self.assembler
+                    .emit_mov(float_sz, Location::GPR(tmp), Location::XMM(tmp_x));
+                compare(&mut self.assembler, tmp_x, tmp_in);
+                self.assembler.emit_jmp(Condition::AboveEqual, upper_half);
+                self.assembler
+                    .emit_mov(Size::S32, Location::Imm32(0), Location::GPR(tmp_out));
+                self.assembler.emit_jmp(Condition::None, end);
+
+                // Other inputs are truncated with 2^63 taken off, which is added back by
+                // setting the top bit. Inputs of 2^64 and above are still out of range, and
+                // setting the top bit of the indefinite value clears it instead.
+                self.assembler.emit_label(upper_half);
+                match float_sz {
+                    Size::S32 => self
+                        .assembler
+                        .emit_vsubss(tmp_in, XMMOrMemory::XMM(tmp_x), tmp_x),
+                    _ => self
+                        .assembler
+                        .emit_vsubsd(tmp_in, XMMOrMemory::XMM(tmp_x), tmp_x),
+                }
+                truncate(&mut self.assembler, Size::S64, tmp_x, tmp_out);
+                self.assembler.emit_mov(
+                    Size::S64,
+                    Location::Imm64(0x8000000000000000u64),
+                    Location::GPR(tmp),
+                );
+                self.assembler
+                    .emit_xor(Size::S64, Location::GPR(tmp), Location::GPR(tmp_out));
+                self.assembler.emit_jmp(Condition::NotEqual, end);
+                self.assembler.emit_mov(
+                    Size::S64,
+                    Location::Imm64(std::u64::MAX),
+                    Location::GPR(tmp_out),
+                );
+            }
+        }
+
+        self.assembler.emit_label(end);
+        self.assembler.emit_mov(int_sz, Location::GPR(tmp_out), ret);
+        self.machine.release_temp_xmm(tmp_x);
+        self.machine.release_temp_xmm(tmp_in);
+        self.machine.restore_stolen_gpr(&mut self.assembler, tmp);
+        self.machine
+            .restore_stolen_gpr(&mut self.assembler, tmp_out);
+        Ok(())
+    }
 
-        self.assembler.emit_label(underflow);
-        underflow_cb(self);
-        self.assembler.emit_jmp(Condition::None, end);
+    /// Loads the base and the current number of elements of the table `table_index`.
+    fn emit_load_table_definition(&mut self, table_index: TableIndex, base: GPR, len: GPR) {
+        if let Some(local_table_index) = self.module.local_table_index(table_index) {
+            let (vmctx_offset_base, vmctx_offset_len) = (
+                self.vmoffsets.vmctx_vmtable_definition(local_table_index),
+                self.vmoffsets
+                    .vmctx_vmtable_definition_current_elements(local_table_index),
+            );
+            self.assembler.emit_mov(
+                Size::S64,
+                Location::Memory(Machine::get_vmctx_reg(), vmctx_offset_base as i32),
+                Location::GPR(base),
+            );
+            self.assembler.emit_mov(
+                Size::S32,
+                Location::Memory(Machine::get_vmctx_reg(), vmctx_offset_len as i32),
+                Location::GPR(len),
+            );
+        } else {
+            // Do an indirection.
+            let import_offset = self.vmoffsets.vmctx_vmtable_import(table_index);
+            self.assembler.emit_mov(
+                Size::S64,
+                Location::Memory(Machine::get_vmctx_reg(), import_offset as i32),
+                Location::GPR(base),
+            );
 
-        self.assembler.emit_label(overflow);
-        overflow_cb(self);
-        self.assembler.emit_jmp(Condition::None, end);
+            // Load len.
+            self.assembler.emit_mov(
+                Size::S32,
+                Location::Memory(
+                    base,
+                    self.vmoffsets.vmtable_definition_current_elements() as _,
+                ),
+                Location::GPR(len),
+            );
 
-        if let Some(cb) = nan_cb {
-            self.assembler.emit_label(nan);
-            cb(self);
-            self.assembler.emit_jmp(Condition::None, end);
+            // Load base.
+            self.assembler.emit_mov(
+                Size::S64,
+                Location::Memory(base, self.vmoffsets.vmtable_definition_base() as _),
+                Location::GPR(base),
+            );
         }
+    }
 
-        self.assembler.emit_label(convert);
-        convert_cb(self);
-        self.assembler.emit_label(end);
+    /// Computes the address of the element at `index` in the funcref table `table_index`,
+    /// trapping if it is out of bounds.
+    ///
+    /// The address is returned in a stolen temporary GPR, which is not one used by `index` or
+    /// `avoid`. Give it back with `restore_stolen_gpr`.
+    fn emit_funcref_table_element_address(
+        &mut self,
+        table_index: TableIndex,
+        index: Location,
+        avoid: &[Location],
+    ) -> GPR {
+        let mut avoid: SmallVec<[Location; 4]> = avoid.iter().cloned().collect();
+        avoid.push(index);
+        let base = self.machine.steal_temp_gpr(&mut self.assembler, &avoid);
+        avoid.push(Location::GPR(base));
+        let elem = self.machine.steal_temp_gpr(&mut self.assembler, &avoid);
+
+        self.emit_load_table_definition(table_index, base, elem);
+        self.assembler
+            .emit_cmp(Size::S32, index, Location::GPR(elem));
+        let trap = self.trap_label(TrapCode::TableAccessOutOfBounds);
+        self.assembler.emit_jmp(Condition::BelowEqual, trap);
+        self.assembler
+            .emit_mov(Size::S32, index, Location::GPR(elem));
+        self.assembler
+            .emit_imul_imm32_gpr64(self.vmoffsets.size_of_vm_funcref() as u32, elem);
+        self.assembler
+            .emit_add(Size::S64, Location::GPR(base), Location::GPR(elem));
+
+        self.machine.restore_stolen_gpr(&mut self.assembler, base);
+        elem
     }
 
     fn emit_stack_check(&mut self, enter: bool, depth: usize) {
@@ -1812,17 +3974,24 @@ impl<'a> FuncGen<'a> {
             );
             // TODO: make it cleaner, now we assume instruction with 32-bit immediate at the end.
             // Recheck offsets, if change above instruction to anything else.
-            self.stack_check_offset = AssemblyOffset(self.assembler.offset().0 - 4);
+            self.stack_check_offset = AssemblyOffset(self.assembler.get_offset().0 - 4);
             self.assembler
-                .emit_jmp(Condition::Signed, self.special_labels.stack_overflow);
+                .emit_jmp(Condition::Signed, self.stack_overflow);
         } else {
             {
                 // Patch earlier stack checker with now known max stack depth.
                 assert!(self.stack_check_offset.0 > 0);
-                let mut alter = self.assembler.alter();
+                let assembler = self.assembler.flushed();
+                #[cfg(feature = "debug-asm")]
+                let assembler = assembler.inner_mut();
+                let mut alter = assembler.alter();
                 alter.goto(self.stack_check_offset);
                 // TODO: check that the value before was 0x7fff_ffff
                 alter.push_u32(depth as u32);
+                for offset in &self.tail_call_stack_checks {
+                    alter.goto(*offset);
+                    alter.push_u32(depth as u32);
+                }
             }
             self.assembler.emit_add(
                 Size::S32,
@@ -1835,6 +4004,21 @@ impl<'a> FuncGen<'a> {
         }
     }
 
+    /// Gives the stack taken by the function back before a tail call leaves it, like the end of
+    /// the function does in `emit_function_stack_check`, which patches the depth in.
+    fn emit_tail_call_stack_check(&mut self) {
+        self.assembler.emit_add(
+            Size::S32,
+            Location::Imm32(0x7fff_ffff),
+            Location::Memory(
+                Machine::get_vmctx_reg(),
+                self.vmoffsets.vmctx_stack_limit_begin() as i32,
+            ),
+        );
+        self.tail_call_stack_checks
+            .push(AssemblyOffset(self.assembler.get_offset().0 - 4));
+    }
+
     fn emit_function_stack_check(&mut self, enter: bool) {
         // `local_types` include parameters as well.
         let depth = self.local_count() as usize
@@ -1849,37 +4033,84 @@ impl<'a> FuncGen<'a> {
     pub(crate) fn emit_head(&mut self) -> Result<(), CodegenError> {
         // TODO: Patchpoint is not emitted for now, and ARM trampoline is not prepended.
 
-        // Normal x86 entry prologue.
-        self.assembler.emit_push(Size::S64, Location::GPR(GPR::RBP));
-        self.assembler
-            .emit_mov(Size::S64, Location::GPR(GPR::RSP), Location::GPR(GPR::RBP));
-
-        // Initialize locals.
         let local_count = self.local_count();
-        self.machine.init_locals(
-            &mut self.assembler,
-            local_count,
-            self.signature.params().len() as u32,
-            self.calling_convention,
-        );
+        let register_candidates =
+            std::cmp::min(local_count, self.machine.max_register_locals() as u32);
+        let local_types = (0..register_candidates)
+            .map(|i| self.local_type(i))
+            .collect::<SmallVec<[WpType; 8]>>();
+        let frameless = self.machine.is_frameless();
+        if frameless {
+            self.machine.init_frameless_locals(
+                &mut self.assembler,
+                local_count,
+                self.signature.params().len() as u32,
+                &local_types,
+                self.calling_convention,
+            )?;
+        } else {
+            // Normal x86 entry prologue.
+            self.assembler.emit_push(Size::S64, Location::GPR(GPR::RBP));
+            self.machine
+                .record_unwind_op(&mut self.assembler, UnwindOp::PushFramePointer);
+            self.assembler
+                .emit_mov(Size::S64, Location::GPR(GPR::RSP), Location::GPR(GPR::RBP));
+            self.machine
+                .record_unwind_op(&mut self.assembler, UnwindOp::DefineFramePointer);
+
+            // Initialize locals.
+            self.machine.init_locals(
+                &mut self.assembler,
+                local_count,
+                self.signature.params().len() as u32,
+                &local_types,
+                &self.v128_locals,
+                self.calling_convention,
+                self.target.page_size().unwrap_or(DEFAULT_PROBE_STRIDE),
+            )?;
+        }
 
         self.emit_function_stack_check(true);
 
-        self.assembler
-            .emit_sub(Size::S64, Location::Imm32(32), Location::GPR(GPR::RSP)); // simulate "red zone" if not supported by the platform
+        // simulate "red zone" if not supported by the platform
+        if !frameless {
+            self.assembler.emit_sub(
+                Size::S64,
+                Location::Imm32(RED_ZONE_SIZE),
+                Location::GPR(GPR::RSP),
+            );
+        }
+
+        if self.config.enable_epoch_interruption {
+            self.emit_epoch_check()?;
+        }
 
+        let returns: SmallVec<[WpType; 1]> = self
+            .signature
+            .results()
+            .iter()
+            .map(|&x| type_to_wp_type(x))
+            .collect();
+        let return_slots = if returns.len() > 1 {
+            let (words, _) = result_words(&returns);
+            words
+                .into_iter()
+                .map(|word| Machine::get_return_location(word, self.calling_convention))
+                .collect()
+        } else {
+            smallvec![]
+        };
         self.control_stack.push(ControlFrame {
             br_label: self.assembler.get_label(),
             loop_like: false,
             if_else: IfElseState::None,
-            returns: self
-                .signature
-                .results()
-                .iter()
-                .map(|&x| type_to_wp_type(x))
-                .collect(),
+            params: smallvec![],
+            returns,
+            return_slots,
+            param_slots: smallvec![],
             value_stack_depth: 0,
             fp_stack_depth: 0,
+            try_frame: None,
         });
 
         Ok(())
@@ -1887,12 +4118,17 @@ impl<'a> FuncGen<'a> {
 
     /// Pushes the instruction to the address map, calculating the offset from a
     /// provided beginning address.
+    ///
+    /// Nothing is pushed when the instruction emitted no code.
     fn mark_instruction_address_end(&mut self, begin: usize) {
-        self.instructions_address_map.push(InstructionAddressMap {
-            srcloc: SourceLoc::new(self.src_loc),
-            code_offset: begin,
-            code_len: self.assembler.get_offset().0 - begin,
-        });
+        let end = self.assembler.unflushed_offset().0;
+        if end > begin {
+            self.instructions_address_map.push(InstructionAddressMap {
+                srcloc: SourceLoc::new(self.src_loc),
+                code_offset: begin,
+                code_len: end - begin,
+            });
+        }
     }
 
     #[tracing::instrument(skip_all)]
@@ -1901,50 +4137,60 @@ impl<'a> FuncGen<'a> {
         module_translation_state: &'a ModuleTranslationState,
         config: &'a Singlepass,
         vmoffsets: &'a VMOffsets,
+        target: &'a Target,
+        memory_styles: &'a PrimaryMap<MemoryIndex, MemoryStyle>,
         _table_styles: &'a PrimaryMap<TableIndex, TableStyle>,
         local_func_index: LocalFunctionIndex,
         calling_convention: CallingConvention,
-    ) -> Result<FuncGen<'a>, CodegenError> {
+    ) -> Result<FuncGen<'a>, CompileError> {
         let func_index = module.func_index(local_func_index);
         let sig_index = module.functions[func_index];
         let signature = module.signatures[sig_index].clone();
 
-        let mut assembler = Assembler::new(0);
-        let special_labels = SpecialLabelSet {
-            integer_division_by_zero: assembler.get_label(),
-            integer_overflow: assembler.get_label(),
-            bad_conversion_to_integer: assembler.get_label(),
-            heap_access_oob: assembler.get_label(),
-            table_access_oob: assembler.get_label(),
-            indirect_call_null: assembler.get_label(),
-            bad_signature: assembler.get_label(),
-            gas_limit_exceeded: assembler.get_label(),
-            stack_overflow: assembler.get_label(),
-        };
+        let inner = VecAssembler::new(0);
+        #[cfg(feature = "debug-asm")]
+        let inner = DebugAsmEmitter::new(inner, config.enable_debug_asm);
+        let mut assembler = Assembler::new(inner, config.enable_peephole);
+        let stack_overflow = assembler.get_label();
+
+        let mut machine = Machine::new(&config.reserved_gprs);
+        if config.enable_stack_limit_checks {
+            machine.enable_stack_limit_check(vmoffsets.vmctx_native_stack_limit(), stack_overflow);
+        }
 
         let mut fg = FuncGen {
             module,
             module_translation_state,
             config,
             vmoffsets,
+            target,
+            memory_styles,
             local_types: wasmer_types::partial_sum_map::PartialSumMap::new(),
+            v128_locals: vec![],
             assembler,
             value_stack: vec![],
             max_stack_depth: 0,
             stack_check_offset: AssemblyOffset(0),
+            tail_call_stack_checks: vec![],
             fp_stack: vec![],
             control_stack: vec![],
-            machine: Machine::new(),
+            machine,
             unreachable_depth: 0,
             relocations: vec![],
-            special_labels,
+            stack_overflow,
+            trap_stubs: vec![],
             src_loc: 0,
             instructions_address_map: vec![],
             calling_convention,
             signature,
+            frame_layout: None,
+            fuel_costs: vec![],
+            operator_index: 0,
+            exception_handlers: false,
+            landing_pads: vec![],
         };
         for param in module.signatures[sig_index].params() {
-            fg.feed_local(1, type_to_wp_type(*param));
+            fg.feed_local(1, type_to_wp_type(*param))?;
         }
         Ok(fg)
     }
@@ -1953,15 +4199,113 @@ impl<'a> FuncGen<'a> {
         !self.control_stack.is_empty()
     }
 
+    /// Collect register allocation statistics for this function.
+    ///
+    /// The statistics are returned from [`finalize`](Self::finalize).
+    pub(crate) fn enable_stats(&mut self) {
+        self.machine.enable_stats();
+    }
+
+    /// Compile this function with handlers for the exceptions thrown in its `try` blocks.
+    ///
+    /// The exceptions unwind the stack up to the landing pads of the calls in these blocks,
+    /// which only restore RBP: the locals then all live in the stack, and the function keeps
+    /// the vmctx in its frame. Must be called before [`emit_head`](Self::emit_head).
+    pub(crate) fn enable_exception_handlers(&mut self) -> Result<(), CompileError> {
+        if self.calling_convention == CallingConvention::WindowsFastcall {
+            return Err(CompileError::UnsupportedFeature(
+                "exception handling with the Windows calling convention".to_string(),
+            ));
+        }
+        self.exception_handlers = true;
+        self.machine.enable_exception_handlers();
+        Ok(())
+    }
+
+    /// Whether this function can be compiled without a frame pointer, with `operators` as its
+    /// body.
+    ///
+    /// This is the case of the System V leaf functions whose locals all fit in registers once
+    /// RBP holds one of them, and whose params and results are all passed in registers. Only
+    /// the code generator knows whether the function needs stack space for its values, so it
+    /// may still turn out to need a frame, see [`needs_frame`](Self::needs_frame).
+    pub(crate) fn can_omit_frame_pointer<'o, 'b: 'o>(
+        &self,
+        operators: impl IntoIterator<Item = &'o Operator<'b>>,
+    ) -> bool {
+        if self.calling_convention == CallingConvention::WindowsFastcall
+            || self.config.enable_epoch_interruption
+            || self.exception_handlers
+        {
+            return false;
+        }
+        let params = self.signature.params();
+        let results = self.signature.results();
+        let in_registers = |ty: &Type| *ty != Type::V128;
+        if !matches!(
+            Machine::get_param_location(params.len(), self.calling_convention),
+            Location::GPR(_)
+        ) || !params.iter().all(in_registers)
+            || results.len() > 2
+            || !results.iter().all(in_registers)
+        {
+            return false;
+        }
+        let local_count = self.local_count() as usize;
+        if local_count > self.machine.max_register_locals() + 1 {
+            return false;
+        }
+        let local_types = (0..local_count as u32)
+            .map(|i| self.local_type(i))
+            .collect::<SmallVec<[WpType; 8]>>();
+        self.machine.locals_fit_without_frame(&local_types) && !operators.into_iter().any(calls_out)
+    }
+
+    /// Compile this function without a frame pointer, as checked by
+    /// [`can_omit_frame_pointer`](Self::can_omit_frame_pointer).
+    pub(crate) fn omit_frame_pointer(&mut self) {
+        self.machine.omit_frame_pointer();
+    }
+
+    /// Whether the code of this function, compiled without a frame pointer, needed one after
+    /// all. The function must then be compiled again, with a frame pointer.
+    pub(crate) fn needs_frame(&self) -> bool {
+        self.machine.needs_frame()
+    }
+
+    /// Charge `fuel_costs[i]`, the fuel computed by `metering::fuel_costs` for the `i`th
+    /// operator, before it runs.
+    pub(crate) fn enable_metering(&mut self, fuel_costs: Vec<u64>) {
+        self.fuel_costs = fuel_costs;
+    }
+
     /// Introduce additional local variables to this function.
     ///
     /// Calling this after [`emit_head`](Self::emit_head) has been invoked is non-sensical.
-    pub(crate) fn feed_local(&mut self, local_count: u32, local_type: WpType) {
+    ///
+    /// Fails once the function has more locals, params included, than `max_locals` in the
+    /// config.
+    pub(crate) fn feed_local(
+        &mut self,
+        local_count: u32,
+        local_type: WpType,
+    ) -> Result<(), CompileError> {
         // FIXME: somehow verify that we haven't invoked `emit_head` yet? Doing so could lead us to
         // generate code that accesses the stack buffer out of bounds.
-        self.local_types
-            .push(local_count, local_type)
-            .expect("module cannot have more than u32::MAX locals");
+        let count = self.local_count() as u64 + local_count as u64;
+        if count > self.config.max_locals as u64 {
+            return Err(CompileError::CodegenTooManyLocals {
+                count,
+                max: self.config.max_locals,
+            });
+        }
+        if local_type == WpType::V128 {
+            let start = self.local_count();
+            self.v128_locals.push(start..start + local_count);
+        }
+        // `count` fits in a `u32` because `max_locals` does.
+        self.local_types.push(local_count, local_type).unwrap();
+        Ok(())
     }
 
     /// Total number of locals and arguments so far.
@@ -1986,8 +4330,28 @@ impl<'a> FuncGen<'a> {
     }
 
     #[tracing::instrument(skip(self))]
+    /// Translate `op`, mapping the code emitted for it to its source location in the address
+    /// map.
     pub(crate) fn feed_operator(&mut self, op: Operator) -> Result<(), CodegenError> {
+        let begin = self.assembler.unflushed_offset().0;
+        self.translate_operator(op)?;
+        self.mark_instruction_address_end(begin);
+        Ok(())
+    }
+
+    fn translate_operator(&mut self, op: Operator) -> Result<(), CodegenError> {
         assert!(self.fp_stack.len() <= self.value_stack.len());
+        // A `mov` held back by the peephole optimizer is recorded after this marker, even though
+        // it belongs to the previous operator.
+        #[cfg(feature = "debug-asm")]
+        self.assembler.inner_mut().mark_operator(&op);
+
+        let fuel = self
+            .fuel_costs
+            .get(self.operator_index)
+            .copied()
+            .unwrap_or(0);
+        self.operator_index += 1;
 
         let was_unreachable;
 
@@ -1995,12 +4359,23 @@ impl<'a> FuncGen<'a> {
             was_unreachable = true;
 
             match op {
-                Operator::Block { .. } | Operator::Loop { .. } | Operator::If { .. } => {
+                Operator::Block { .. }
+                | Operator::Loop { .. }
+                | Operator::If { .. }
+                | Operator::Try { .. } => {
                     self.unreachable_depth += 1;
                 }
-                Operator::End => {
+                Operator::End | Operator::Delegate { .. } => {
                     self.unreachable_depth -= 1;
                 }
+                Operator::Catch { .. } | Operator::CatchAll => {
+                    // The handlers of a `try` block are reachable from its body.
+                    if self.unreachable_depth == 1
+                        && self.control_stack.last().unwrap().try_frame.is_some()
+                    {
+                        self.unreachable_depth -= 1;
+                    }
+                }
                 Operator::Else => {
                     // We are in a reachable true branch
                     if self.unreachable_depth == 1 {
@@ -2020,6 +4395,30 @@ impl<'a> FuncGen<'a> {
             was_unreachable = false;
         }
 
+        if forgets_memory_cache(&op) {
+            self.machine.forget_memory_cache();
+        }
+
+        if fuel > 0 {
+            self.emit_fuel_charge(fuel);
+        }
+
+        if let [.., a, b] = *self.value_stack {
+            if let Some(folded) = fold_binop(&op, a, b) {
+                self.pop_value_released();
+                self.pop_value_released();
+                self.value_stack.push(folded);
+                return Ok(());
+            }
+        }
+        if let [.., a] = *self.value_stack {
+            if let Some(folded) = fold_unop(&op, a) {
+                self.pop_value_released();
+                self.value_stack.push(folded);
+                return Ok(());
+            }
+        }
+
         match op {
             Operator::GlobalGet { global_index } => {
                 let global_index = GlobalIndex::from_u32(global_index);
@@ -2033,64 +4432,47 @@ impl<'a> FuncGen<'a> {
                     .acquire_locations(&mut self.assembler, &[(ty)], false)[0];
                 self.value_stack.push(loc);
 
-                let tmp = self.machine.acquire_temp_gpr().unwrap();
+                let (src, tmp) = self.global_location(global_index);
+                self.emit_typed_move(ty, src, loc);
+                if let Some(tmp) = tmp {
+                    self.machine.restore_stolen_gpr(&mut self.assembler, tmp);
+                }
+            }
+            Operator::GlobalSet { global_index }
+                if self.module.globals[GlobalIndex::from_u32(global_index)].ty
+                    == Type::ExternRef =>
+            {
+                // The global holds a count of the reference, which the VM updates.
+                let global_index = GlobalIndex::from_u32(global_index);
+                let value = self.value_stack.pop().unwrap();
+                self.machine.release_locations_only_regs(&[value]);
 
-                let src = if let Some(local_global_index) =
-                    self.module.local_global_index(global_index)
-                {
-                    let offset = self.vmoffsets.vmctx_vmglobal_definition(local_global_index);
-                    self.emit_relaxed_binop(
-                        Assembler::emit_mov,
-                        Size::S64,
-                        Location::Memory(Machine::get_vmctx_reg(), offset as i32),
-                        Location::GPR(tmp),
-                    );
-                    Location::Memory(tmp, 0)
-                } else {
-                    // Imported globals require one level of indirection.
-                    let offset = self
-                        .vmoffsets
-                        .vmctx_vmglobal_import_definition(global_index);
-                    self.emit_relaxed_binop(
-                        Assembler::emit_mov,
-                        Size::S64,
-                        Location::Memory(Machine::get_vmctx_reg(), offset as i32),
-                        Location::GPR(tmp),
-                    );
-                    Location::Memory(tmp, 0)
-                };
+                self.assembler.emit_mov(
+                    Size::S64,
+                    Location::Memory(
+                        Machine::get_vmctx_reg(),
+                        self.vmoffsets.vmctx_builtin_function(
+                            VMBuiltinFunctionIndex::get_externref_global_set_index(),
+                        ) as i32,
+                    ),
+                    Location::GPR(GPR::RAX),
+                );
 
-                self.emit_relaxed_binop(Assembler::emit_mov, Size::S64, src, loc);
+                self.emit_call_native(
+                    |this| {
+                        this.assembler.emit_call_register(GPR::RAX);
+                    },
+                    // [vmctx, global_index, value]
+                    [Location::Imm32(global_index.as_u32()), value]
+                        .iter()
+                        .cloned(),
+                )?;
 
-                self.machine.release_temp_gpr(tmp);
+                self.machine.release_locations_only_stack(&[value]);
             }
             Operator::GlobalSet { global_index } => {
                 let global_index = GlobalIndex::from_u32(global_index);
-                let tmp = self.machine.acquire_temp_gpr().unwrap();
-                let dst = if let Some(local_global_index) =
-                    self.module.local_global_index(global_index)
-                {
-                    let offset = self.vmoffsets.vmctx_vmglobal_definition(local_global_index);
-                    self.emit_relaxed_binop(
-                        Assembler::emit_mov,
-                        Size::S64,
-                        Location::Memory(Machine::get_vmctx_reg(), offset as i32),
-                        Location::GPR(tmp),
-                    );
-                    Location::Memory(tmp, 0)
-                } else {
-                    // Imported globals require one level of indirection.
-                    let offset = self
-                        .vmoffsets
-                        .vmctx_vmglobal_import_definition(global_index);
-                    self.emit_relaxed_binop(
-                        Assembler::emit_mov,
-                        Size::S64,
-                        Location::Memory(Machine::get_vmctx_reg(), offset as i32),
-                        Location::GPR(tmp),
-                    );
-                    Location::Memory(tmp, 0)
-                };
+                let (dst, tmp) = self.global_location(global_index);
                 let ty = type_to_wp_type(self.module.globals[global_index].ty);
                 let loc = self.pop_value_released();
                 if ty.is_float() {
@@ -2112,21 +4494,22 @@ impl<'a> FuncGen<'a> {
                         self.emit_relaxed_binop(Assembler::emit_mov, Size::S64, loc, dst);
                     }
                 } else {
-                    self.emit_relaxed_binop(Assembler::emit_mov, Size::S64, loc, dst);
+                    self.emit_typed_move(ty, loc, dst);
+                }
+                if let Some(tmp) = tmp {
+                    self.machine.restore_stolen_gpr(&mut self.assembler, tmp);
                 }
-                self.machine.release_temp_gpr(tmp);
             }
             Operator::LocalGet { local_index } => {
                 let local_type = self.local_type(local_index);
-                let ret =
-                    self.machine
-                        .acquire_locations(&mut self.assembler, &[(WpType::I64)], false)[0];
-                self.emit_relaxed_binop(
-                    Assembler::emit_mov,
-                    Size::S64,
-                    self.machine.get_local_location(local_index),
-                    ret,
-                );
+                let ty = match local_type {
+                    WpType::V128 => WpType::V128,
+                    _ => WpType::I64,
+                };
+                let ret = self
+                    .machine
+                    .acquire_locations(&mut self.assembler, &[ty], false)[0];
+                self.emit_typed_move(ty, self.machine.get_local_location(local_index), ret);
                 self.value_stack.push(ret);
                 if local_type.is_float() {
                     self.fp_stack
@@ -2161,9 +4544,8 @@ impl<'a> FuncGen<'a> {
                         );
                     }
                 } else {
-                    self.emit_relaxed_binop(
-                        Assembler::emit_mov,
-                        Size::S64,
+                    self.emit_typed_move(
+                        local_type,
                         loc,
                         self.machine.get_local_location(local_index),
                     );
@@ -2196,9 +4578,8 @@ impl<'a> FuncGen<'a> {
                         );
                     }
                 } else {
-                    self.emit_relaxed_binop(
-                        Assembler::emit_mov,
-                        Size::S64,
+                    self.emit_typed_move(
+                        local_type,
                         loc,
                         self.machine.get_local_location(local_index),
                     );
@@ -2209,78 +4590,60 @@ impl<'a> FuncGen<'a> {
             }
             Operator::I32Add => self.emit_binop_i32(Assembler::emit_add),
             Operator::I32Sub => self.emit_binop_i32(Assembler::emit_sub),
-            Operator::I32Mul => self.emit_binop_i32(Assembler::emit_imul),
+            Operator::I32Mul => match self.pop_power_of_two_operand(&op) {
+                Some(shift) => {
+                    self.value_stack.push(Location::Imm32(shift));
+                    self.emit_shift_i32(Assembler::emit_shl);
+                }
+                None => self.emit_binop_i32(Assembler::emit_imul),
+            },
             Operator::I32DivU => {
-                // We assume that RAX and RDX are temporary registers here.
-                let I2O1 { loc_a, loc_b, ret } = self.i2o1_prepare(WpType::I32);
-                self.assembler
-                    .emit_mov(Size::S32, loc_a, Location::GPR(GPR::RAX));
-                self.assembler.emit_xor(
-                    Size::S32,
-                    Location::GPR(GPR::RDX),
-                    Location::GPR(GPR::RDX),
-                );
-                self.emit_relaxed_xdiv(false, Size::S32, loc_b);
-                self.assembler
-                    .emit_mov(Size::S32, Location::GPR(GPR::RAX), ret);
+                if let Some(shift) = self.pop_power_of_two_operand(&op) {
+                    self.value_stack.push(Location::Imm32(shift));
+                    self.emit_shift_i32(Assembler::emit_shr);
+                } else {
+                    // We assume that RAX and RDX are temporary registers here.
+                    let I2O1 { loc_a, loc_b, ret } = self.i2o1_prepare(WpType::I32);
+                    self.assembler
+                        .emit_mov(Size::S32, loc_a, Location::GPR(GPR::RAX));
+                    self.assembler.emit_xor(
+                        Size::S32,
+                        Location::GPR(GPR::RDX),
+                        Location::GPR(GPR::RDX),
+                    );
+                    self.emit_divisor_zero_check(Size::S32, loc_b);
+                    self.emit_relaxed_xdiv(false, Size::S32, loc_b);
+                    self.assembler
+                        .emit_mov(Size::S32, Location::GPR(GPR::RAX), ret);
+                }
             }
             Operator::I32DivS => {
-                // We assume that RAX and RDX are temporary registers here.
                 let I2O1 { loc_a, loc_b, ret } = self.i2o1_prepare(WpType::I32);
-                self.assembler
-                    .emit_mov(Size::S32, loc_a, Location::GPR(GPR::RAX));
-                self.assembler.emit_cdq();
-                self.emit_relaxed_xdiv(true, Size::S32, loc_b);
-                self.assembler
-                    .emit_mov(Size::S32, Location::GPR(GPR::RAX), ret);
+                self.emit_signed_xdiv(Size::S32, false, loc_a, loc_b, ret);
             }
             Operator::I32RemU => {
-                // We assume that RAX and RDX are temporary registers here.
-                let I2O1 { loc_a, loc_b, ret } = self.i2o1_prepare(WpType::I32);
-                self.assembler
-                    .emit_mov(Size::S32, loc_a, Location::GPR(GPR::RAX));
-                self.assembler.emit_xor(
-                    Size::S32,
-                    Location::GPR(GPR::RDX),
-                    Location::GPR(GPR::RDX),
-                );
-                self.emit_relaxed_xdiv(false, Size::S32, loc_b);
-                self.assembler
-                    .emit_mov(Size::S32, Location::GPR(GPR::RDX), ret);
+                if let Some(shift) = self.pop_power_of_two_operand(&op) {
+                    self.value_stack.push(Location::Imm32((1 << shift) - 1));
+                    self.emit_binop_i32(Assembler::emit_and);
+                } else {
+                    // We assume that RAX and RDX are temporary registers here.
+                    let I2O1 { loc_a, loc_b, ret } = self.i2o1_prepare(WpType::I32);
+                    self.assembler
+                        .emit_mov(Size::S32, loc_a, Location::GPR(GPR::RAX));
+                    self.assembler.emit_xor(
+                        Size::S32,
+                        Location::GPR(GPR::RDX),
+                        Location::GPR(GPR::RDX),
+                    );
+                    self.emit_divisor_zero_check(Size::S32, loc_b);
+                    self.emit_relaxed_xdiv(false, Size::S32, loc_b);
+                    self.assembler
+                        .emit_mov(Size::S32, Location::GPR(GPR::RDX), ret);
+                }
             }
             Operator::I32RemS => {
-                // We assume that RAX and RDX are temporary registers here.
                 let I2O1 { loc_a, loc_b, ret } = self.i2o1_prepare(WpType::I32);
-
-                let normal_path = self.assembler.get_label();
-                let end = self.assembler.get_label();
-
-                self.emit_relaxed_binop(
-                    Assembler::emit_cmp,
-                    Size::S32,
-                    Location::Imm32(0x80000000),
-                    loc_a,
-                );
-                self.assembler.emit_jmp(Condition::NotEqual, normal_path);
-                self.emit_relaxed_binop(
-                    Assembler::emit_cmp,
-                    Size::S32,
-                    Location::Imm32(0xffffffff),
-                    loc_b,
-                );
-                self.assembler.emit_jmp(Condition::NotEqual, normal_path);
-                self.assembler.emit_mov(Size::S32, Location::Imm32(0), ret);
-                self.assembler.emit_jmp(Condition::None, end);
-
-                self.assembler.emit_label(normal_path);
-                self.assembler
-                    .emit_mov(Size::S32, loc_a, Location::GPR(GPR::RAX));
-                self.assembler.emit_cdq();
-                self.emit_relaxed_xdiv(true, Size::S32, loc_b);
-                self.assembler
-                    .emit_mov(Size::S32, Location::GPR(GPR::RDX), ret);
-
-                self.assembler.emit_label(end);
+                self.emit_signed_xdiv(Size::S32, true, loc_a, loc_b, ret);
             }
             Operator::I32And => self.emit_binop_i32(Assembler::emit_and),
             Operator::I32Or => self.emit_binop_i32(Assembler::emit_or),
@@ -2294,7 +4657,7 @@ impl<'a> FuncGen<'a> {
                 let loc = self.pop_value_released();
                 let src = match loc {
                     Location::Imm32(_) | Location::Memory(_, _) => {
-                        let tmp = self.machine.acquire_temp_gpr().unwrap();
+                        let tmp = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                         self.assembler.emit_mov(Size::S32, loc, Location::GPR(tmp));
                         tmp
                     }
@@ -2312,7 +4675,9 @@ impl<'a> FuncGen<'a> {
                 self.value_stack.push(ret);
 
                 let dst = match ret {
-                    Location::Memory(_, _) => self.machine.acquire_temp_gpr().unwrap(),
+                    Location::Memory(_, _) => self
+                        .machine
+                        .steal_temp_gpr(&mut self.assembler, &[Location::GPR(src)]),
                     Location::GPR(reg) => reg,
                     _ => {
                         return Err(CodegenError {
@@ -2321,12 +4686,9 @@ impl<'a> FuncGen<'a> {
                     }
                 };
 
-                if self.assembler.arch_has_xzcnt() {
-                    self.assembler.arch_emit_lzcnt(
-                        Size::S32,
-                        Location::GPR(src),
-                        Location::GPR(dst),
-                    );
+                if self.has_cpu_feature(CpuFeature::LZCNT) {
+                    self.assembler
+                        .emit_lzcnt(Size::S32, Location::GPR(src), Location::GPR(dst));
                 } else {
                     let zero_path = self.assembler.get_label();
                     let end = self.assembler.get_label();
@@ -2346,20 +4708,20 @@ impl<'a> FuncGen<'a> {
 
                 match loc {
                     Location::Imm32(_) | Location::Memory(_, _) => {
-                        self.machine.release_temp_gpr(src);
+                        self.machine.restore_stolen_gpr(&mut self.assembler, src);
                     }
                     _ => {}
                 };
                 if let Location::Memory(_, _) = ret {
                     self.assembler.emit_mov(Size::S32, Location::GPR(dst), ret);
-                    self.machine.release_temp_gpr(dst);
+                    self.machine.restore_stolen_gpr(&mut self.assembler, dst);
                 };
             }
             Operator::I32Ctz => {
                 let loc = self.pop_value_released();
                 let src = match loc {
                     Location::Imm32(_) | Location::Memory(_, _) => {
-                        let tmp = self.machine.acquire_temp_gpr().unwrap();
+                        let tmp = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                         self.assembler.emit_mov(Size::S32, loc, Location::GPR(tmp));
                         tmp
                     }
@@ -2377,7 +4739,9 @@ impl<'a> FuncGen<'a> {
                 self.value_stack.push(ret);
 
                 let dst = match ret {
-                    Location::Memory(_, _) => self.machine.acquire_temp_gpr().unwrap(),
+                    Location::Memory(_, _) => self
+                        .machine
+                        .steal_temp_gpr(&mut self.assembler, &[Location::GPR(src)]),
                     Location::GPR(reg) => reg,
                     _ => {
                         return Err(CodegenError {
@@ -2386,12 +4750,9 @@ impl<'a> FuncGen<'a> {
                     }
                 };
 
-                if self.assembler.arch_has_xzcnt() {
-                    self.assembler.arch_emit_tzcnt(
-                        Size::S32,
-                        Location::GPR(src),
-                        Location::GPR(dst),
-                    );
+                if self.has_cpu_feature(CpuFeature::BMI1) {
+                    self.assembler
+                        .emit_tzcnt(Size::S32, Location::GPR(src), Location::GPR(dst));
                 } else {
                     let zero_path = self.assembler.get_label();
                     let end = self.assembler.get_label();
@@ -2409,16 +4770,16 @@ impl<'a> FuncGen<'a> {
 
                 match loc {
                     Location::Imm32(_) | Location::Memory(_, _) => {
-                        self.machine.release_temp_gpr(src);
+                        self.machine.restore_stolen_gpr(&mut self.assembler, src);
                     }
                     _ => {}
                 };
                 if let Location::Memory(_, _) = ret {
                     self.assembler.emit_mov(Size::S32, Location::GPR(dst), ret);
-                    self.machine.release_temp_gpr(dst);
+                    self.machine.restore_stolen_gpr(&mut self.assembler, dst);
                 };
             }
-            Operator::I32Popcnt => self.emit_xcnt_i32(Assembler::emit_popcnt)?,
+            Operator::I32Popcnt => self.emit_xcnt_i32(Self::emit_popcnt)?,
             Operator::I32Shl => self.emit_shift_i32(Assembler::emit_shl),
             Operator::I32ShrU => self.emit_shift_i32(Assembler::emit_shr),
             Operator::I32ShrS => self.emit_shift_i32(Assembler::emit_sar),
@@ -2440,78 +4801,66 @@ impl<'a> FuncGen<'a> {
             }
             Operator::I64Add => self.emit_binop_i64(Assembler::emit_add),
             Operator::I64Sub => self.emit_binop_i64(Assembler::emit_sub),
-            Operator::I64Mul => self.emit_binop_i64(Assembler::emit_imul),
+            Operator::I64Mul => match self.pop_power_of_two_operand(&op) {
+                Some(shift) => {
+                    self.value_stack.push(Location::Imm64(shift as u64));
+                    self.emit_shift_i64(Assembler::emit_shl);
+                }
+                None => self.emit_binop_i64(Assembler::emit_imul),
+            },
             Operator::I64DivU => {
-                // We assume that RAX and RDX are temporary registers here.
-                let I2O1 { loc_a, loc_b, ret } = self.i2o1_prepare(WpType::I64);
-                self.assembler
-                    .emit_mov(Size::S64, loc_a, Location::GPR(GPR::RAX));
-                self.assembler.emit_xor(
-                    Size::S64,
-                    Location::GPR(GPR::RDX),
-                    Location::GPR(GPR::RDX),
-                );
-                self.emit_relaxed_xdiv(false, Size::S64, loc_b);
-                self.assembler
-                    .emit_mov(Size::S64, Location::GPR(GPR::RAX), ret);
+                if let Some(shift) = self.pop_power_of_two_operand(&op) {
+                    self.value_stack.push(Location::Imm64(shift as u64));
+                    self.emit_shift_i64(Assembler::emit_shr);
+                } else {
+                    // We assume that RAX and RDX are temporary registers here.
+                    let I2O1 { loc_a, loc_b, ret } = self.i2o1_prepare(WpType::I64);
+                    self.assembler
+                        .emit_mov(Size::S64, loc_a, Location::GPR(GPR::RAX));
+                    self.assembler.emit_xor(
+                        Size::S64,
+                        Location::GPR(GPR::RDX),
+                        Location::GPR(GPR::RDX),
+                    );
+                    self.emit_divisor_zero_check(Size::S64, loc_b);
+                    self.emit_relaxed_xdiv(false, Size::S64, loc_b);
+                    self.assembler
+                        .emit_mov(Size::S64, Location::GPR(GPR::RAX), ret);
+                }
             }
             Operator::I64DivS => {
-                // We assume that RAX and RDX are temporary registers here.
                 let I2O1 { loc_a, loc_b, ret } = self.i2o1_prepare(WpType::I64);
-                self.assembler
-                    .emit_mov(Size::S64, loc_a, Location::GPR(GPR::RAX));
-                self.assembler.emit_cqo();
-                self.emit_relaxed_xdiv(true, Size::S64, loc_b);
-                self.assembler
-                    .emit_mov(Size::S64, Location::GPR(GPR::RAX), ret);
+                self.emit_signed_xdiv(Size::S64, false, loc_a, loc_b, ret);
             }
             Operator::I64RemU => {
-                // We assume that RAX and RDX are temporary registers here.
-                let I2O1 { loc_a, loc_b, ret } = self.i2o1_prepare(WpType::I64);
-                self.assembler
-                    .emit_mov(Size::S64, loc_a, Location::GPR(GPR::RAX));
-                self.assembler.emit_xor(
-                    Size::S64,
-                    Location::GPR(GPR::RDX),
-                    Location::GPR(GPR::RDX),
-                );
-                self.emit_relaxed_xdiv(false, Size::S64, loc_b);
-                self.assembler
-                    .emit_mov(Size::S64, Location::GPR(GPR::RDX), ret);
+                if let Some(shift) = self.pop_power_of_two_operand(&op) {
+                    // Masks of up to 31 bits are unchanged by the sign extension of an `Imm32`.
+                    let mask = (1u64 << shift) - 1;
+                    self.value_stack.push(if shift < 32 {
+                        Location::Imm32(mask as u32)
+                    } else {
+                        Location::Imm64(mask)
+                    });
+                    self.emit_binop_i64(Assembler::emit_and);
+                } else {
+                    // We assume that RAX and RDX are temporary registers here.
+                    let I2O1 { loc_a, loc_b, ret } = self.i2o1_prepare(WpType::I64);
+                    self.assembler
+                        .emit_mov(Size::S64, loc_a, Location::GPR(GPR::RAX));
+                    self.assembler.emit_xor(
+                        Size::S64,
+                        Location::GPR(GPR::RDX),
+                        Location::GPR(GPR::RDX),
+                    );
+                    self.emit_divisor_zero_check(Size::S64, loc_b);
+                    self.emit_relaxed_xdiv(false, Size::S64, loc_b);
+                    self.assembler
+                        .emit_mov(Size::S64, Location::GPR(GPR::RDX), ret);
+                }
             }
             Operator::I64RemS => {
-                // We assume that RAX and RDX are temporary registers here.
                 let I2O1 { loc_a, loc_b, ret } = self.i2o1_prepare(WpType::I64);
-
-                let normal_path = self.assembler.get_label();
-                let end = self.assembler.get_label();
-
-                self.emit_relaxed_binop(
-                    Assembler::emit_cmp,
-                    Size::S64,
-                    Location::Imm64(0x8000000000000000u64),
-                    loc_a,
-                );
-                self.assembler.emit_jmp(Condition::NotEqual, normal_path);
-                self.emit_relaxed_binop(
-                    Assembler::emit_cmp,
-                    Size::S64,
-                    Location::Imm64(0xffffffffffffffffu64),
-                    loc_b,
-                );
-                self.assembler.emit_jmp(Condition::NotEqual, normal_path);
-                self.emit_relaxed_binop(Assembler::emit_mov, Size::S64, Location::Imm64(0), ret);
-                self.assembler.emit_jmp(Condition::None, end);
-
-                self.assembler.emit_label(normal_path);
-
-                self.assembler
-                    .emit_mov(Size::S64, loc_a, Location::GPR(GPR::RAX));
-                self.assembler.emit_cqo();
-                self.emit_relaxed_xdiv(true, Size::S64, loc_b);
-                self.assembler
-                    .emit_mov(Size::S64, Location::GPR(GPR::RDX), ret);
-                self.assembler.emit_label(end);
+                self.emit_signed_xdiv(Size::S64, true, loc_a, loc_b, ret);
             }
             Operator::I64And => self.emit_binop_i64(Assembler::emit_and),
             Operator::I64Or => self.emit_binop_i64(Assembler::emit_or),
@@ -2525,7 +4874,7 @@ impl<'a> FuncGen<'a> {
                 let loc = self.pop_value_released();
                 let src = match loc {
                     Location::Imm64(_) | Location::Imm32(_) | Location::Memory(_, _) => {
-                        let tmp = self.machine.acquire_temp_gpr().unwrap();
+                        let tmp = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                         self.assembler.emit_mov(Size::S64, loc, Location::GPR(tmp));
                         tmp
                     }
@@ -2543,7 +4892,9 @@ impl<'a> FuncGen<'a> {
                 self.value_stack.push(ret);
 
                 let dst = match ret {
-                    Location::Memory(_, _) => self.machine.acquire_temp_gpr().unwrap(),
+                    Location::Memory(_, _) => self
+                        .machine
+                        .steal_temp_gpr(&mut self.assembler, &[Location::GPR(src)]),
                     Location::GPR(reg) => reg,
                     _ => {
                         return Err(CodegenError {
@@ -2552,12 +4903,9 @@ impl<'a> FuncGen<'a> {
                     }
                 };
 
-                if self.assembler.arch_has_xzcnt() {
-                    self.assembler.arch_emit_lzcnt(
-                        Size::S64,
-                        Location::GPR(src),
-                        Location::GPR(dst),
-                    );
+                if self.has_cpu_feature(CpuFeature::LZCNT) {
+                    self.assembler
+                        .emit_lzcnt(Size::S64, Location::GPR(src), Location::GPR(dst));
                 } else {
                     let zero_path = self.assembler.get_label();
                     let end = self.assembler.get_label();
@@ -2577,20 +4925,20 @@ impl<'a> FuncGen<'a> {
 
                 match loc {
                     Location::Imm64(_) | Location::Imm32(_) | Location::Memory(_, _) => {
-                        self.machine.release_temp_gpr(src);
+                        self.machine.restore_stolen_gpr(&mut self.assembler, src);
                     }
                     _ => {}
                 };
                 if let Location::Memory(_, _) = ret {
                     self.assembler.emit_mov(Size::S64, Location::GPR(dst), ret);
-                    self.machine.release_temp_gpr(dst);
+                    self.machine.restore_stolen_gpr(&mut self.assembler, dst);
                 };
             }
             Operator::I64Ctz => {
                 let loc = self.pop_value_released();
                 let src = match loc {
                     Location::Imm64(_) | Location::Imm32(_) | Location::Memory(_, _) => {
-                        let tmp = self.machine.acquire_temp_gpr().unwrap();
+                        let tmp = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                         self.assembler.emit_mov(Size::S64, loc, Location::GPR(tmp));
                         tmp
                     }
@@ -2608,7 +4956,9 @@ impl<'a> FuncGen<'a> {
                 self.value_stack.push(ret);
 
                 let dst = match ret {
-                    Location::Memory(_, _) => self.machine.acquire_temp_gpr().unwrap(),
+                    Location::Memory(_, _) => self
+                        .machine
+                        .steal_temp_gpr(&mut self.assembler, &[Location::GPR(src)]),
                     Location::GPR(reg) => reg,
                     _ => {
                         return Err(CodegenError {
@@ -2617,12 +4967,9 @@ impl<'a> FuncGen<'a> {
                     }
                 };
 
-                if self.assembler.arch_has_xzcnt() {
-                    self.assembler.arch_emit_tzcnt(
-                        Size::S64,
-                        Location::GPR(src),
-                        Location::GPR(dst),
-                    );
+                if self.has_cpu_feature(CpuFeature::BMI1) {
+                    self.assembler
+                        .emit_tzcnt(Size::S64, Location::GPR(src), Location::GPR(dst));
                 } else {
                     let zero_path = self.assembler.get_label();
                     let end = self.assembler.get_label();
@@ -2640,16 +4987,16 @@ impl<'a> FuncGen<'a> {
 
                 match loc {
                     Location::Imm64(_) | Location::Imm32(_) | Location::Memory(_, _) => {
-                        self.machine.release_temp_gpr(src);
+                        self.machine.restore_stolen_gpr(&mut self.assembler, src);
                     }
                     _ => {}
                 };
                 if let Location::Memory(_, _) = ret {
                     self.assembler.emit_mov(Size::S64, Location::GPR(dst), ret);
-                    self.machine.release_temp_gpr(dst);
+                    self.machine.restore_stolen_gpr(&mut self.assembler, dst);
                 };
             }
-            Operator::I64Popcnt => self.emit_xcnt_i64(Assembler::emit_popcnt)?,
+            Operator::I64Popcnt => self.emit_xcnt_i64(Self::emit_popcnt)?,
             Operator::I64Shl => self.emit_shift_i64(Assembler::emit_shl),
             Operator::I64ShrU => self.emit_shift_i64(Assembler::emit_shr),
             Operator::I64ShrS => self.emit_shift_i64(Assembler::emit_sar),
@@ -2692,384 +5039,71 @@ impl<'a> FuncGen<'a> {
                 self.value_stack.push(ret);
                 self.emit_relaxed_zx_sx(Assembler::emit_movsx, Size::S32, loc, Size::S64, ret)?;
             }
-            Operator::I32Extend8S => {
-                let loc = self.pop_value_released();
-                let ret =
-                    self.machine
-                        .acquire_locations(&mut self.assembler, &[(WpType::I32)], false)[0];
-                self.value_stack.push(ret);
-
-                self.emit_relaxed_zx_sx(Assembler::emit_movsx, Size::S8, loc, Size::S32, ret)?;
-            }
-            Operator::I32Extend16S => {
-                let loc = self.pop_value_released();
-                let ret =
-                    self.machine
-                        .acquire_locations(&mut self.assembler, &[(WpType::I32)], false)[0];
-                self.value_stack.push(ret);
-
-                self.emit_relaxed_zx_sx(Assembler::emit_movsx, Size::S16, loc, Size::S32, ret)?;
-            }
-            Operator::I64Extend8S => {
-                let loc = self.pop_value_released();
-                let ret =
-                    self.machine
-                        .acquire_locations(&mut self.assembler, &[(WpType::I64)], false)[0];
-                self.value_stack.push(ret);
-
-                self.emit_relaxed_zx_sx(Assembler::emit_movsx, Size::S8, loc, Size::S64, ret)?;
-            }
-            Operator::I64Extend16S => {
-                let loc = self.pop_value_released();
-                let ret =
-                    self.machine
-                        .acquire_locations(&mut self.assembler, &[(WpType::I64)], false)[0];
-                self.value_stack.push(ret);
-
-                self.emit_relaxed_zx_sx(Assembler::emit_movsx, Size::S16, loc, Size::S64, ret)?;
-            }
-            Operator::I64Extend32S => {
-                let loc = self.pop_value_released();
-                let ret =
-                    self.machine
-                        .acquire_locations(&mut self.assembler, &[(WpType::I64)], false)[0];
-                self.value_stack.push(ret);
-
-                self.emit_relaxed_zx_sx(Assembler::emit_movsx, Size::S32, loc, Size::S64, ret)?;
-            }
+            Operator::I32Extend8S => self.emit_sign_extension(Size::S8, WpType::I32)?,
+            Operator::I32Extend16S => self.emit_sign_extension(Size::S16, WpType::I32)?,
+            Operator::I64Extend8S => self.emit_sign_extension(Size::S8, WpType::I64)?,
+            Operator::I64Extend16S => self.emit_sign_extension(Size::S16, WpType::I64)?,
+            Operator::I64Extend32S => self.emit_sign_extension(Size::S32, WpType::I64)?,
             Operator::I32WrapI64 => {
-                let loc = self.pop_value_released();
-                let ret =
-                    self.machine
-                        .acquire_locations(&mut self.assembler, &[(WpType::I32)], false)[0];
-                self.value_stack.push(ret);
-                self.emit_relaxed_binop(Assembler::emit_mov, Size::S32, loc, ret);
-            }
-
-            Operator::F32Const { value } => {
-                self.value_stack.push(Location::Imm32(value.bits()));
-                self.fp_stack
-                    .push(FloatValue::new(self.value_stack.len() - 1));
-            }
-            Operator::F32Add => {
-                self.fp_stack.pop2()?;
-                self.fp_stack
-                    .push(FloatValue::cncl_f32(self.value_stack.len() - 2));
-                self.emit_fp_binop_avx(Assembler::emit_vaddss)?;
-            }
-            Operator::F32Sub => {
-                self.fp_stack.pop2()?;
-                self.fp_stack
-                    .push(FloatValue::cncl_f32(self.value_stack.len() - 2));
-                self.emit_fp_binop_avx(Assembler::emit_vsubss)?
-            }
-            Operator::F32Mul => {
-                self.fp_stack.pop2()?;
-                self.fp_stack
-                    .push(FloatValue::cncl_f32(self.value_stack.len() - 2));
-                self.emit_fp_binop_avx(Assembler::emit_vmulss)?
-            }
-            Operator::F32Div => {
-                self.fp_stack.pop2()?;
-                self.fp_stack
-                    .push(FloatValue::cncl_f32(self.value_stack.len() - 2));
-                self.emit_fp_binop_avx(Assembler::emit_vdivss)?
-            }
-            Operator::F32Max => {
-                self.fp_stack.pop2()?;
-                self.fp_stack
-                    .push(FloatValue::new(self.value_stack.len() - 2));
-                if !self.assembler.arch_supports_canonicalize_nan() {
-                    self.emit_fp_binop_avx(Assembler::emit_vmaxss)?;
-                } else {
-                    let I2O1 { loc_a, loc_b, ret } = self.i2o1_prepare(WpType::F64);
-
-                    let tmp1 = self.machine.acquire_temp_xmm().unwrap();
-                    let tmp2 = self.machine.acquire_temp_xmm().unwrap();
-                    let tmpg1 = self.machine.acquire_temp_gpr().unwrap();
-                    let tmpg2 = self.machine.acquire_temp_gpr().unwrap();
-
-                    let src1 = match loc_a {
-                        Location::XMM(x) => x,
-                        Location::GPR(_) | Location::Memory(_, _) => {
-                            self.assembler
-                                .emit_mov(Size::S64, loc_a, Location::XMM(tmp1));
-                            tmp1
-                        }
-                        Location::Imm32(_) => {
-                            self.assembler
-                                .emit_mov(Size::S32, loc_a, Location::GPR(tmpg1));
-                            self.assembler.emit_mov(
-                                Size::S32,
-                                Location::GPR(tmpg1),
-                                Location::XMM(tmp1),
-                            );
-                            tmp1
-                        }
-                        Location::Imm64(_) => {
-                            self.assembler
-                                .emit_mov(Size::S64, loc_a, Location::GPR(tmpg1));
-                            self.assembler.emit_mov(
-                                Size::S64,
-                                Location::GPR(tmpg1),
-                                Location::XMM(tmp1),
-                            );
-                            tmp1
-                        }
-                        _ => {
-                            return Err(CodegenError {
-                                message: "F32Max src1: unreachable code".to_string(),
-                            })
-                        }
-                    };
-                    let src2 = match loc_b {
-                        Location::XMM(x) => x,
-                        Location::GPR(_) | Location::Memory(_, _) => {
-                            self.assembler
-                                .emit_mov(Size::S64, loc_b, Location::XMM(tmp2));
-                            tmp2
-                        }
-                        Location::Imm32(_) => {
-                            self.assembler
-                                .emit_mov(Size::S32, loc_b, Location::GPR(tmpg1));
-                            self.assembler.emit_mov(
-                                Size::S32,
-                                Location::GPR(tmpg1),
-                                Location::XMM(tmp2),
-                            );
-                            tmp2
-                        }
-                        Location::Imm64(_) => {
-                            self.assembler
-                                .emit_mov(Size::S64, loc_b, Location::GPR(tmpg1));
-                            self.assembler.emit_mov(
-                                Size::S64,
-                                Location::GPR(tmpg1),
-                                Location::XMM(tmp2),
-                            );
-                            tmp2
-                        }
-                        _ => {
-                            return Err(CodegenError {
-                                message: "F32Max src2: unreachable code".to_string(),
-                            })
-                        }
-                    };
-
-                    let tmp_xmm1 = XMM::XMM8;
-                    let tmp_xmm2 = XMM::XMM9;
-                    let tmp_xmm3 = XMM::XMM10;
-
-                    self.assembler
-                        .emit_mov(Size::S32, Location::XMM(src1), Location::GPR(tmpg1));
-                    self.assembler
-                        .emit_mov(Size::S32, Location::XMM(src2), Location::GPR(tmpg2));
-                    self.assembler
-                        .emit_cmp(Size::S32, Location::GPR(tmpg2), Location::GPR(tmpg1));
-                    self.assembler
-                        .emit_vmaxss(src1, XMMOrMemory::XMM(src2), tmp_xmm1);
-                    let label1 = self.assembler.get_label();
-                    let label2 = self.assembler.get_label();
-                    self.assembler.emit_jmp(Condition::NotEqual, label1);
-                    self.assembler
-                        .emit_vmovaps(XMMOrMemory::XMM(tmp_xmm1), XMMOrMemory::XMM(tmp_xmm2));
-                    self.assembler.emit_jmp(Condition::None, label2);
-                    self.assembler.emit_label(label1);
-                    self.assembler
-                        .emit_vxorps(tmp_xmm2, XMMOrMemory::XMM(tmp_xmm2), tmp_xmm2);
-                    self.assembler.emit_label(label2);
-                    self.assembler
-                        .emit_vcmpeqss(src1, XMMOrMemory::XMM(src2), tmp_xmm3);
-                    self.assembler.emit_vblendvps(
-                        tmp_xmm3,
-                        XMMOrMemory::XMM(tmp_xmm2),
-                        tmp_xmm1,
-                        tmp_xmm1,
-                    );
-                    self.assembler
-                        .emit_vcmpunordss(src1, XMMOrMemory::XMM(src2), src1);
-                    // load float canonical nan
-                    self.assembler.emit_mov(
-                        Size::S64,
-                        Location::Imm32(0x7FC0_0000), // Canonical NaN
-                        Location::GPR(tmpg1),
-                    );
-                    self.assembler
-                        .emit_mov(Size::S64, Location::GPR(tmpg1), Location::XMM(src2));
-                    self.assembler
-                        .emit_vblendvps(src1, XMMOrMemory::XMM(src2), tmp_xmm1, src1);
-                    match ret {
-                        Location::XMM(x) => {
-                            self.assembler
-                                .emit_vmovaps(XMMOrMemory::XMM(src1), XMMOrMemory::XMM(x));
-                        }
-                        Location::Memory(_, _) | Location::GPR(_) => {
-                            self.assembler.emit_mov(Size::S64, Location::XMM(src1), ret);
-                        }
-                        _ => {
-                            return Err(CodegenError {
-                                message: "F32Max ret: unreachable code".to_string(),
-                            })
-                        }
-                    }
-
-                    self.machine.release_temp_gpr(tmpg2);
-                    self.machine.release_temp_gpr(tmpg1);
-                    self.machine.release_temp_xmm(tmp2);
-                    self.machine.release_temp_xmm(tmp1);
-                }
-            }
-            Operator::F32Min => {
-                self.fp_stack.pop2()?;
-                self.fp_stack
-                    .push(FloatValue::new(self.value_stack.len() - 2));
-                if !self.assembler.arch_supports_canonicalize_nan() {
-                    self.emit_fp_binop_avx(Assembler::emit_vminss)?;
-                } else {
-                    let I2O1 { loc_a, loc_b, ret } = self.i2o1_prepare(WpType::F64);
-
-                    let tmp1 = self.machine.acquire_temp_xmm().unwrap();
-                    let tmp2 = self.machine.acquire_temp_xmm().unwrap();
-                    let tmpg1 = self.machine.acquire_temp_gpr().unwrap();
-                    let tmpg2 = self.machine.acquire_temp_gpr().unwrap();
-
-                    let src1 = match loc_a {
-                        Location::XMM(x) => x,
-                        Location::GPR(_) | Location::Memory(_, _) => {
-                            self.assembler
-                                .emit_mov(Size::S64, loc_a, Location::XMM(tmp1));
-                            tmp1
-                        }
-                        Location::Imm32(_) => {
-                            self.assembler
-                                .emit_mov(Size::S32, loc_a, Location::GPR(tmpg1));
-                            self.assembler.emit_mov(
-                                Size::S32,
-                                Location::GPR(tmpg1),
-                                Location::XMM(tmp1),
-                            );
-                            tmp1
-                        }
-                        Location::Imm64(_) => {
-                            self.assembler
-                                .emit_mov(Size::S64, loc_a, Location::GPR(tmpg1));
-                            self.assembler.emit_mov(
-                                Size::S64,
-                                Location::GPR(tmpg1),
-                                Location::XMM(tmp1),
-                            );
-                            tmp1
-                        }
-                        _ => {
-                            return Err(CodegenError {
-                                message: "F32Min src1: unreachable code".to_string(),
-                            })
-                        }
-                    };
-                    let src2 = match loc_b {
-                        Location::XMM(x) => x,
-                        Location::GPR(_) | Location::Memory(_, _) => {
-                            self.assembler
-                                .emit_mov(Size::S64, loc_b, Location::XMM(tmp2));
-                            tmp2
-                        }
-                        Location::Imm32(_) => {
-                            self.assembler
-                                .emit_mov(Size::S32, loc_b, Location::GPR(tmpg1));
-                            self.assembler.emit_mov(
-                                Size::S32,
-                                Location::GPR(tmpg1),
-                                Location::XMM(tmp2),
-                            );
-                            tmp2
-                        }
-                        Location::Imm64(_) => {
-                            self.assembler
-                                .emit_mov(Size::S64, loc_b, Location::GPR(tmpg1));
-                            self.assembler.emit_mov(
-                                Size::S64,
-                                Location::GPR(tmpg1),
-                                Location::XMM(tmp2),
-                            );
-                            tmp2
-                        }
-                        _ => {
-                            return Err(CodegenError {
-                                message: "F32Min src2: unreachable code".to_string(),
-                            })
-                        }
-                    };
-
-                    let tmp_xmm1 = XMM::XMM8;
-                    let tmp_xmm2 = XMM::XMM9;
-                    let tmp_xmm3 = XMM::XMM10;
-
-                    self.assembler
-                        .emit_mov(Size::S32, Location::XMM(src1), Location::GPR(tmpg1));
-                    self.assembler
-                        .emit_mov(Size::S32, Location::XMM(src2), Location::GPR(tmpg2));
-                    self.assembler
-                        .emit_cmp(Size::S32, Location::GPR(tmpg2), Location::GPR(tmpg1));
-                    self.assembler
-                        .emit_vminss(src1, XMMOrMemory::XMM(src2), tmp_xmm1);
-                    let label1 = self.assembler.get_label();
-                    let label2 = self.assembler.get_label();
-                    self.assembler.emit_jmp(Condition::NotEqual, label1);
-                    self.assembler
-                        .emit_vmovaps(XMMOrMemory::XMM(tmp_xmm1), XMMOrMemory::XMM(tmp_xmm2));
-                    self.assembler.emit_jmp(Condition::None, label2);
-                    self.assembler.emit_label(label1);
-                    // load float -0.0
-                    self.assembler.emit_mov(
-                        Size::S64,
-                        Location::Imm32(0x8000_0000), // Negative zero
-                        Location::GPR(tmpg1),
-                    );
-                    self.assembler.emit_mov(
-                        Size::S64,
-                        Location::GPR(tmpg1),
-                        Location::XMM(tmp_xmm2),
-                    );
-                    self.assembler.emit_label(label2);
-                    self.assembler
-                        .emit_vcmpeqss(src1, XMMOrMemory::XMM(src2), tmp_xmm3);
-                    self.assembler.emit_vblendvps(
-                        tmp_xmm3,
-                        XMMOrMemory::XMM(tmp_xmm2),
-                        tmp_xmm1,
-                        tmp_xmm1,
-                    );
-                    self.assembler
-                        .emit_vcmpunordss(src1, XMMOrMemory::XMM(src2), src1);
-                    // load float canonical nan
-                    self.assembler.emit_mov(
-                        Size::S64,
-                        Location::Imm32(0x7FC0_0000), // Canonical NaN
-                        Location::GPR(tmpg1),
-                    );
-                    self.assembler
-                        .emit_mov(Size::S64, Location::GPR(tmpg1), Location::XMM(src2));
-                    self.assembler
-                        .emit_vblendvps(src1, XMMOrMemory::XMM(src2), tmp_xmm1, src1);
-                    match ret {
-                        Location::XMM(x) => {
-                            self.assembler
-                                .emit_vmovaps(XMMOrMemory::XMM(src1), XMMOrMemory::XMM(x));
-                        }
-                        Location::Memory(_, _) | Location::GPR(_) => {
-                            self.assembler.emit_mov(Size::S64, Location::XMM(src1), ret);
-                        }
-                        _ => {
-                            return Err(CodegenError {
-                                message: "F32Min ret: unreachable code".to_string(),
-                            })
-                        }
-                    }
+                let loc = self.pop_value_released();
+                let ret =
+                    self.machine
+                        .acquire_locations(&mut self.assembler, &[(WpType::I32)], false)[0];
+                self.value_stack.push(ret);
+                self.emit_relaxed_binop(Assembler::emit_mov, Size::S32, loc, ret);
+            }
 
-                    self.machine.release_temp_gpr(tmpg2);
-                    self.machine.release_temp_gpr(tmpg1);
-                    self.machine.release_temp_xmm(tmp2);
-                    self.machine.release_temp_xmm(tmp1);
+            Operator::F32Const { value } => {
+                self.value_stack.push(Location::Imm32(value.bits()));
+                self.fp_stack
+                    .push(FloatValue::new(self.value_stack.len() - 1));
+            }
+            Operator::F32Add => {
+                self.fp_stack.pop2()?;
+                self.fp_stack
+                    .push(FloatValue::cncl_f32(self.value_stack.len() - 2));
+                self.emit_fp_binop_avx(Assembler::emit_vaddss)?;
+            }
+            Operator::F32Sub => {
+                self.fp_stack.pop2()?;
+                self.fp_stack
+                    .push(FloatValue::cncl_f32(self.value_stack.len() - 2));
+                self.emit_fp_binop_avx(Assembler::emit_vsubss)?
+            }
+            Operator::F32Mul => {
+                self.fp_stack.pop2()?;
+                self.fp_stack
+                    .push(FloatValue::cncl_f32(self.value_stack.len() - 2));
+                self.emit_fp_binop_avx(Assembler::emit_vmulss)?
+            }
+            Operator::F32Div => {
+                self.fp_stack.pop2()?;
+                self.fp_stack
+                    .push(FloatValue::cncl_f32(self.value_stack.len() - 2));
+                self.emit_fp_binop_avx(Assembler::emit_vdivss)?
+            }
+            Operator::F32Max => {
+                self.fp_stack.pop2()?;
+                self.fp_stack
+                    .push(FloatValue::new(self.value_stack.len() - 2));
+                if !self.assembler.arch_supports_canonicalize_nan() {
+                    self.emit_fp_binop_avx(Assembler::emit_vmaxss)?;
+                } else {
+                    self.emit_fp_min_max(
+                        Size::S32,
+                        Assembler::emit_vmaxss,
+                        Assembler::emit_vandps,
+                    )?;
+                }
+            }
+            Operator::F32Min => {
+                self.fp_stack.pop2()?;
+                self.fp_stack
+                    .push(FloatValue::new(self.value_stack.len() - 2));
+                if !self.assembler.arch_supports_canonicalize_nan() {
+                    self.emit_fp_binop_avx(Assembler::emit_vminss)?;
+                } else {
+                    self.emit_fp_min_max(Size::S32, Assembler::emit_vminss, Assembler::emit_vorps)?;
                 }
             }
             Operator::F32Eq => {
@@ -3134,8 +5168,10 @@ impl<'a> FuncGen<'a> {
                 self.fp_stack
                     .push(FloatValue::new(self.value_stack.len() - 1));
 
-                let tmp1 = self.machine.acquire_temp_gpr().unwrap();
-                let tmp2 = self.machine.acquire_temp_gpr().unwrap();
+                let tmp1 = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
+                let tmp2 = self
+                    .machine
+                    .steal_temp_gpr(&mut self.assembler, &[Location::GPR(tmp1)]);
 
                 if self.assembler.arch_supports_canonicalize_nan()
                     && self.config.enable_nan_canonicalization
@@ -3170,8 +5206,8 @@ impl<'a> FuncGen<'a> {
                 self.assembler
                     .emit_or(Size::S32, Location::GPR(tmp2), Location::GPR(tmp1));
                 self.assembler.emit_mov(Size::S32, Location::GPR(tmp1), ret);
-                self.machine.release_temp_gpr(tmp2);
-                self.machine.release_temp_gpr(tmp1);
+                self.machine.restore_stolen_gpr(&mut self.assembler, tmp2);
+                self.machine.restore_stolen_gpr(&mut self.assembler, tmp1);
             }
 
             Operator::F32Abs => {
@@ -3182,7 +5218,7 @@ impl<'a> FuncGen<'a> {
                     self.machine
                         .acquire_locations(&mut self.assembler, &[(WpType::F32)], false)[0];
                 self.value_stack.push(ret);
-                let tmp = self.machine.acquire_temp_gpr().unwrap();
+                let tmp = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                 self.assembler.emit_mov(Size::S32, loc, Location::GPR(tmp));
                 self.assembler.emit_and(
                     Size::S32,
@@ -3190,7 +5226,7 @@ impl<'a> FuncGen<'a> {
                     Location::GPR(tmp),
                 );
                 self.assembler.emit_mov(Size::S32, Location::GPR(tmp), ret);
-                self.machine.release_temp_gpr(tmp);
+                self.machine.restore_stolen_gpr(&mut self.assembler, tmp);
             }
 
             Operator::F32Neg => {
@@ -3207,352 +5243,77 @@ impl<'a> FuncGen<'a> {
                     self.emit_relaxed_binop(
                         Assembler::emit_mov,
                         Size::S32,
-                        loc,
-                        Location::XMM(tmp),
-                    );
-                    self.assembler.arch_emit_f32_neg(tmp, tmp);
-                    self.emit_relaxed_binop(
-                        Assembler::emit_mov,
-                        Size::S32,
-                        Location::XMM(tmp),
-                        ret,
-                    );
-                    self.machine.release_temp_xmm(tmp);
-                } else {
-                    let tmp = self.machine.acquire_temp_gpr().unwrap();
-                    self.assembler.emit_mov(Size::S32, loc, Location::GPR(tmp));
-                    self.assembler.emit_btc_gpr_imm8_32(31, tmp);
-                    self.assembler.emit_mov(Size::S32, Location::GPR(tmp), ret);
-                    self.machine.release_temp_gpr(tmp);
-                }
-            }
-
-            Operator::F64Const { value } => {
-                self.value_stack.push(Location::Imm64(value.bits()));
-                self.fp_stack
-                    .push(FloatValue::new(self.value_stack.len() - 1));
-            }
-            Operator::F64Add => {
-                self.fp_stack.pop2()?;
-                self.fp_stack
-                    .push(FloatValue::cncl_f64(self.value_stack.len() - 2));
-                self.emit_fp_binop_avx(Assembler::emit_vaddsd)?
-            }
-            Operator::F64Sub => {
-                self.fp_stack.pop2()?;
-                self.fp_stack
-                    .push(FloatValue::cncl_f64(self.value_stack.len() - 2));
-                self.emit_fp_binop_avx(Assembler::emit_vsubsd)?
-            }
-            Operator::F64Mul => {
-                self.fp_stack.pop2()?;
-                self.fp_stack
-                    .push(FloatValue::cncl_f64(self.value_stack.len() - 2));
-                self.emit_fp_binop_avx(Assembler::emit_vmulsd)?
-            }
-            Operator::F64Div => {
-                self.fp_stack.pop2()?;
-                self.fp_stack
-                    .push(FloatValue::cncl_f64(self.value_stack.len() - 2));
-                self.emit_fp_binop_avx(Assembler::emit_vdivsd)?
-            }
-            Operator::F64Max => {
-                self.fp_stack.pop2()?;
-                self.fp_stack
-                    .push(FloatValue::new(self.value_stack.len() - 2));
-
-                if !self.assembler.arch_supports_canonicalize_nan() {
-                    self.emit_fp_binop_avx(Assembler::emit_vmaxsd)?;
-                } else {
-                    let I2O1 { loc_a, loc_b, ret } = self.i2o1_prepare(WpType::F64);
-
-                    let tmp1 = self.machine.acquire_temp_xmm().unwrap();
-                    let tmp2 = self.machine.acquire_temp_xmm().unwrap();
-                    let tmpg1 = self.machine.acquire_temp_gpr().unwrap();
-                    let tmpg2 = self.machine.acquire_temp_gpr().unwrap();
-
-                    let src1 = match loc_a {
-                        Location::XMM(x) => x,
-                        Location::GPR(_) | Location::Memory(_, _) => {
-                            self.assembler
-                                .emit_mov(Size::S64, loc_a, Location::XMM(tmp1));
-                            tmp1
-                        }
-                        Location::Imm32(_) => {
-                            self.assembler
-                                .emit_mov(Size::S32, loc_a, Location::GPR(tmpg1));
-                            self.assembler.emit_mov(
-                                Size::S32,
-                                Location::GPR(tmpg1),
-                                Location::XMM(tmp1),
-                            );
-                            tmp1
-                        }
-                        Location::Imm64(_) => {
-                            self.assembler
-                                .emit_mov(Size::S64, loc_a, Location::GPR(tmpg1));
-                            self.assembler.emit_mov(
-                                Size::S64,
-                                Location::GPR(tmpg1),
-                                Location::XMM(tmp1),
-                            );
-                            tmp1
-                        }
-                        _ => {
-                            return Err(CodegenError {
-                                message: "F64Max src1: unreachable code".to_string(),
-                            })
-                        }
-                    };
-                    let src2 = match loc_b {
-                        Location::XMM(x) => x,
-                        Location::GPR(_) | Location::Memory(_, _) => {
-                            self.assembler
-                                .emit_mov(Size::S64, loc_b, Location::XMM(tmp2));
-                            tmp2
-                        }
-                        Location::Imm32(_) => {
-                            self.assembler
-                                .emit_mov(Size::S32, loc_b, Location::GPR(tmpg1));
-                            self.assembler.emit_mov(
-                                Size::S32,
-                                Location::GPR(tmpg1),
-                                Location::XMM(tmp2),
-                            );
-                            tmp2
-                        }
-                        Location::Imm64(_) => {
-                            self.assembler
-                                .emit_mov(Size::S64, loc_b, Location::GPR(tmpg1));
-                            self.assembler.emit_mov(
-                                Size::S64,
-                                Location::GPR(tmpg1),
-                                Location::XMM(tmp2),
-                            );
-                            tmp2
-                        }
-                        _ => {
-                            return Err(CodegenError {
-                                message: "F64Max src2: unreachable code".to_string(),
-                            })
-                        }
-                    };
-
-                    let tmp_xmm1 = XMM::XMM8;
-                    let tmp_xmm2 = XMM::XMM9;
-                    let tmp_xmm3 = XMM::XMM10;
-
-                    self.assembler
-                        .emit_mov(Size::S64, Location::XMM(src1), Location::GPR(tmpg1));
-                    self.assembler
-                        .emit_mov(Size::S64, Location::XMM(src2), Location::GPR(tmpg2));
-                    self.assembler
-                        .emit_cmp(Size::S64, Location::GPR(tmpg2), Location::GPR(tmpg1));
-                    self.assembler
-                        .emit_vmaxsd(src1, XMMOrMemory::XMM(src2), tmp_xmm1);
-                    let label1 = self.assembler.get_label();
-                    let label2 = self.assembler.get_label();
-                    self.assembler.emit_jmp(Condition::NotEqual, label1);
-                    self.assembler
-                        .emit_vmovapd(XMMOrMemory::XMM(tmp_xmm1), XMMOrMemory::XMM(tmp_xmm2));
-                    self.assembler.emit_jmp(Condition::None, label2);
-                    self.assembler.emit_label(label1);
-                    self.assembler
-                        .emit_vxorpd(tmp_xmm2, XMMOrMemory::XMM(tmp_xmm2), tmp_xmm2);
-                    self.assembler.emit_label(label2);
-                    self.assembler
-                        .emit_vcmpeqsd(src1, XMMOrMemory::XMM(src2), tmp_xmm3);
-                    self.assembler.emit_vblendvpd(
-                        tmp_xmm3,
-                        XMMOrMemory::XMM(tmp_xmm2),
-                        tmp_xmm1,
-                        tmp_xmm1,
-                    );
-                    self.assembler
-                        .emit_vcmpunordsd(src1, XMMOrMemory::XMM(src2), src1);
-                    // load float canonical nan
-                    self.assembler.emit_mov(
-                        Size::S64,
-                        Location::Imm64(0x7FF8_0000_0000_0000), // Canonical NaN
-                        Location::GPR(tmpg1),
-                    );
-                    self.assembler
-                        .emit_mov(Size::S64, Location::GPR(tmpg1), Location::XMM(src2));
-                    self.assembler
-                        .emit_vblendvpd(src1, XMMOrMemory::XMM(src2), tmp_xmm1, src1);
-                    match ret {
-                        Location::XMM(x) => {
-                            self.assembler
-                                .emit_vmovapd(XMMOrMemory::XMM(src1), XMMOrMemory::XMM(x));
-                        }
-                        Location::Memory(_, _) | Location::GPR(_) => {
-                            self.assembler.emit_mov(Size::S64, Location::XMM(src1), ret);
-                        }
-                        _ => {
-                            return Err(CodegenError {
-                                message: "F64Max ret: unreachable code".to_string(),
-                            })
-                        }
-                    }
-
-                    self.machine.release_temp_gpr(tmpg2);
-                    self.machine.release_temp_gpr(tmpg1);
-                    self.machine.release_temp_xmm(tmp2);
-                    self.machine.release_temp_xmm(tmp1);
-                }
-            }
-            Operator::F64Min => {
-                self.fp_stack.pop2()?;
-                self.fp_stack
-                    .push(FloatValue::new(self.value_stack.len() - 2));
-
-                if !self.assembler.arch_supports_canonicalize_nan() {
-                    self.emit_fp_binop_avx(Assembler::emit_vminsd)?;
-                } else {
-                    let I2O1 { loc_a, loc_b, ret } = self.i2o1_prepare(WpType::F64);
-
-                    let tmp1 = self.machine.acquire_temp_xmm().unwrap();
-                    let tmp2 = self.machine.acquire_temp_xmm().unwrap();
-                    let tmpg1 = self.machine.acquire_temp_gpr().unwrap();
-                    let tmpg2 = self.machine.acquire_temp_gpr().unwrap();
-
-                    let src1 = match loc_a {
-                        Location::XMM(x) => x,
-                        Location::GPR(_) | Location::Memory(_, _) => {
-                            self.assembler
-                                .emit_mov(Size::S64, loc_a, Location::XMM(tmp1));
-                            tmp1
-                        }
-                        Location::Imm32(_) => {
-                            self.assembler
-                                .emit_mov(Size::S32, loc_a, Location::GPR(tmpg1));
-                            self.assembler.emit_mov(
-                                Size::S32,
-                                Location::GPR(tmpg1),
-                                Location::XMM(tmp1),
-                            );
-                            tmp1
-                        }
-                        Location::Imm64(_) => {
-                            self.assembler
-                                .emit_mov(Size::S64, loc_a, Location::GPR(tmpg1));
-                            self.assembler.emit_mov(
-                                Size::S64,
-                                Location::GPR(tmpg1),
-                                Location::XMM(tmp1),
-                            );
-                            tmp1
-                        }
-                        _ => {
-                            return Err(CodegenError {
-                                message: "F64Min src1: unreachable code".to_string(),
-                            })
-                        }
-                    };
-                    let src2 = match loc_b {
-                        Location::XMM(x) => x,
-                        Location::GPR(_) | Location::Memory(_, _) => {
-                            self.assembler
-                                .emit_mov(Size::S64, loc_b, Location::XMM(tmp2));
-                            tmp2
-                        }
-                        Location::Imm32(_) => {
-                            self.assembler
-                                .emit_mov(Size::S32, loc_b, Location::GPR(tmpg1));
-                            self.assembler.emit_mov(
-                                Size::S32,
-                                Location::GPR(tmpg1),
-                                Location::XMM(tmp2),
-                            );
-                            tmp2
-                        }
-                        Location::Imm64(_) => {
-                            self.assembler
-                                .emit_mov(Size::S64, loc_b, Location::GPR(tmpg1));
-                            self.assembler.emit_mov(
-                                Size::S64,
-                                Location::GPR(tmpg1),
-                                Location::XMM(tmp2),
-                            );
-                            tmp2
-                        }
-                        _ => {
-                            return Err(CodegenError {
-                                message: "F64Min src2: unreachable code".to_string(),
-                            })
-                        }
-                    };
-
-                    let tmp_xmm1 = XMM::XMM8;
-                    let tmp_xmm2 = XMM::XMM9;
-                    let tmp_xmm3 = XMM::XMM10;
-
-                    self.assembler
-                        .emit_mov(Size::S64, Location::XMM(src1), Location::GPR(tmpg1));
-                    self.assembler
-                        .emit_mov(Size::S64, Location::XMM(src2), Location::GPR(tmpg2));
-                    self.assembler
-                        .emit_cmp(Size::S64, Location::GPR(tmpg2), Location::GPR(tmpg1));
-                    self.assembler
-                        .emit_vminsd(src1, XMMOrMemory::XMM(src2), tmp_xmm1);
-                    let label1 = self.assembler.get_label();
-                    let label2 = self.assembler.get_label();
-                    self.assembler.emit_jmp(Condition::NotEqual, label1);
-                    self.assembler
-                        .emit_vmovapd(XMMOrMemory::XMM(tmp_xmm1), XMMOrMemory::XMM(tmp_xmm2));
-                    self.assembler.emit_jmp(Condition::None, label2);
-                    self.assembler.emit_label(label1);
-                    // load float -0.0
-                    self.assembler.emit_mov(
-                        Size::S64,
-                        Location::Imm64(0x8000_0000_0000_0000), // Negative zero
-                        Location::GPR(tmpg1),
-                    );
-                    self.assembler.emit_mov(
-                        Size::S64,
-                        Location::GPR(tmpg1),
-                        Location::XMM(tmp_xmm2),
-                    );
-                    self.assembler.emit_label(label2);
-                    self.assembler
-                        .emit_vcmpeqsd(src1, XMMOrMemory::XMM(src2), tmp_xmm3);
-                    self.assembler.emit_vblendvpd(
-                        tmp_xmm3,
-                        XMMOrMemory::XMM(tmp_xmm2),
-                        tmp_xmm1,
-                        tmp_xmm1,
-                    );
-                    self.assembler
-                        .emit_vcmpunordsd(src1, XMMOrMemory::XMM(src2), src1);
-                    // load float canonical nan
-                    self.assembler.emit_mov(
-                        Size::S64,
-                        Location::Imm64(0x7FF8_0000_0000_0000), // Canonical NaN
-                        Location::GPR(tmpg1),
-                    );
-                    self.assembler
-                        .emit_mov(Size::S64, Location::GPR(tmpg1), Location::XMM(src2));
-                    self.assembler
-                        .emit_vblendvpd(src1, XMMOrMemory::XMM(src2), tmp_xmm1, src1);
-                    match ret {
-                        Location::XMM(x) => {
-                            self.assembler
-                                .emit_vmovaps(XMMOrMemory::XMM(src1), XMMOrMemory::XMM(x));
-                        }
-                        Location::Memory(_, _) | Location::GPR(_) => {
-                            self.assembler.emit_mov(Size::S64, Location::XMM(src1), ret);
-                        }
-                        _ => {
-                            return Err(CodegenError {
-                                message: "F64Min ret: unreachable code".to_string(),
-                            })
-                        }
-                    }
+                        loc,
+                        Location::XMM(tmp),
+                    );
+                    self.assembler.arch_emit_f32_neg(tmp, tmp);
+                    self.emit_relaxed_binop(
+                        Assembler::emit_mov,
+                        Size::S32,
+                        Location::XMM(tmp),
+                        ret,
+                    );
+                    self.machine.release_temp_xmm(tmp);
+                } else {
+                    let tmp = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
+                    self.assembler.emit_mov(Size::S32, loc, Location::GPR(tmp));
+                    self.assembler.emit_btc_gpr_imm8_32(31, tmp);
+                    self.assembler.emit_mov(Size::S32, Location::GPR(tmp), ret);
+                    self.machine.restore_stolen_gpr(&mut self.assembler, tmp);
+                }
+            }
 
-                    self.machine.release_temp_gpr(tmpg2);
-                    self.machine.release_temp_gpr(tmpg1);
-                    self.machine.release_temp_xmm(tmp2);
-                    self.machine.release_temp_xmm(tmp1);
+            Operator::F64Const { value } => {
+                self.value_stack.push(Location::Imm64(value.bits()));
+                self.fp_stack
+                    .push(FloatValue::new(self.value_stack.len() - 1));
+            }
+            Operator::F64Add => {
+                self.fp_stack.pop2()?;
+                self.fp_stack
+                    .push(FloatValue::cncl_f64(self.value_stack.len() - 2));
+                self.emit_fp_binop_avx(Assembler::emit_vaddsd)?
+            }
+            Operator::F64Sub => {
+                self.fp_stack.pop2()?;
+                self.fp_stack
+                    .push(FloatValue::cncl_f64(self.value_stack.len() - 2));
+                self.emit_fp_binop_avx(Assembler::emit_vsubsd)?
+            }
+            Operator::F64Mul => {
+                self.fp_stack.pop2()?;
+                self.fp_stack
+                    .push(FloatValue::cncl_f64(self.value_stack.len() - 2));
+                self.emit_fp_binop_avx(Assembler::emit_vmulsd)?
+            }
+            Operator::F64Div => {
+                self.fp_stack.pop2()?;
+                self.fp_stack
+                    .push(FloatValue::cncl_f64(self.value_stack.len() - 2));
+                self.emit_fp_binop_avx(Assembler::emit_vdivsd)?
+            }
+            Operator::F64Max => {
+                self.fp_stack.pop2()?;
+                self.fp_stack
+                    .push(FloatValue::new(self.value_stack.len() - 2));
+                if !self.assembler.arch_supports_canonicalize_nan() {
+                    self.emit_fp_binop_avx(Assembler::emit_vmaxsd)?;
+                } else {
+                    self.emit_fp_min_max(
+                        Size::S64,
+                        Assembler::emit_vmaxsd,
+                        Assembler::emit_vandpd,
+                    )?;
+                }
+            }
+            Operator::F64Min => {
+                self.fp_stack.pop2()?;
+                self.fp_stack
+                    .push(FloatValue::new(self.value_stack.len() - 2));
+                if !self.assembler.arch_supports_canonicalize_nan() {
+                    self.emit_fp_binop_avx(Assembler::emit_vminsd)?;
+                } else {
+                    self.emit_fp_min_max(Size::S64, Assembler::emit_vminsd, Assembler::emit_vorpd)?;
                 }
             }
             Operator::F64Eq => {
@@ -3617,8 +5378,10 @@ impl<'a> FuncGen<'a> {
                 self.fp_stack
                     .push(FloatValue::new(self.value_stack.len() - 1));
 
-                let tmp1 = self.machine.acquire_temp_gpr().unwrap();
-                let tmp2 = self.machine.acquire_temp_gpr().unwrap();
+                let tmp1 = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
+                let tmp2 = self
+                    .machine
+                    .steal_temp_gpr(&mut self.assembler, &[Location::GPR(tmp1)]);
 
                 if self.assembler.arch_supports_canonicalize_nan()
                     && self.config.enable_nan_canonicalization
@@ -3641,7 +5404,10 @@ impl<'a> FuncGen<'a> {
                         .emit_mov(Size::S64, loc_b, Location::GPR(tmp2));
                 }
 
-                let c = self.machine.acquire_temp_gpr().unwrap();
+                let c = self.machine.steal_temp_gpr(
+                    &mut self.assembler,
+                    &[Location::GPR(tmp1), Location::GPR(tmp2)],
+                );
 
                 self.assembler.emit_mov(
                     Size::S64,
@@ -3663,9 +5429,9 @@ impl<'a> FuncGen<'a> {
                     .emit_or(Size::S64, Location::GPR(tmp2), Location::GPR(tmp1));
                 self.assembler.emit_mov(Size::S64, Location::GPR(tmp1), ret);
 
-                self.machine.release_temp_gpr(c);
-                self.machine.release_temp_gpr(tmp2);
-                self.machine.release_temp_gpr(tmp1);
+                self.machine.restore_stolen_gpr(&mut self.assembler, c);
+                self.machine.restore_stolen_gpr(&mut self.assembler, tmp2);
+                self.machine.restore_stolen_gpr(&mut self.assembler, tmp1);
             }
 
             Operator::F64Abs => {
@@ -3677,8 +5443,10 @@ impl<'a> FuncGen<'a> {
                         .acquire_locations(&mut self.assembler, &[(WpType::F64)], false)[0];
                 self.value_stack.push(ret);
 
-                let tmp = self.machine.acquire_temp_gpr().unwrap();
-                let c = self.machine.acquire_temp_gpr().unwrap();
+                let tmp = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
+                let c = self
+                    .machine
+                    .steal_temp_gpr(&mut self.assembler, &[Location::GPR(tmp)]);
 
                 self.assembler.emit_mov(Size::S64, loc, Location::GPR(tmp));
                 self.assembler.emit_mov(
@@ -3690,8 +5458,8 @@ impl<'a> FuncGen<'a> {
                     .emit_and(Size::S64, Location::GPR(c), Location::GPR(tmp));
                 self.assembler.emit_mov(Size::S64, Location::GPR(tmp), ret);
 
-                self.machine.release_temp_gpr(c);
-                self.machine.release_temp_gpr(tmp);
+                self.machine.restore_stolen_gpr(&mut self.assembler, c);
+                self.machine.restore_stolen_gpr(&mut self.assembler, tmp);
             }
 
             Operator::F64Neg => {
@@ -3719,22 +5487,25 @@ impl<'a> FuncGen<'a> {
                     );
                     self.machine.release_temp_xmm(tmp);
                 } else {
-                    let tmp = self.machine.acquire_temp_gpr().unwrap();
+                    let tmp = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                     self.assembler.emit_mov(Size::S64, loc, Location::GPR(tmp));
                     self.assembler.emit_btc_gpr_imm8_64(63, tmp);
                     self.assembler.emit_mov(Size::S64, Location::GPR(tmp), ret);
-                    self.machine.release_temp_gpr(tmp);
+                    self.machine.restore_stolen_gpr(&mut self.assembler, tmp);
                 }
             }
 
             Operator::F64PromoteF32 => {
-                let fp = self.fp_stack.pop1()?;
-                self.fp_stack.push(fp.promote(self.value_stack.len() - 1));
+                // The conversion quiets signaling NaNs but keeps their payload.
+                self.fp_stack.pop1()?;
+                self.fp_stack
+                    .push(FloatValue::cncl_f64(self.value_stack.len() - 1));
                 self.emit_fp_unop_avx(Assembler::emit_vcvtss2sd)?
             }
             Operator::F32DemoteF64 => {
-                let fp = self.fp_stack.pop1()?;
-                self.fp_stack.push(fp.demote(self.value_stack.len() - 1));
+                self.fp_stack.pop1()?;
+                self.fp_stack
+                    .push(FloatValue::cncl_f32(self.value_stack.len() - 1));
                 self.emit_fp_unop_avx(Assembler::emit_vcvtsd2ss)?
             }
 
@@ -3813,7 +5584,7 @@ impl<'a> FuncGen<'a> {
                 self.fp_stack.pop1()?;
 
                 if self.assembler.arch_has_itruncf() {
-                    let tmp_out = self.machine.acquire_temp_gpr().unwrap();
+                    let tmp_out = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                     let tmp_in = self.machine.acquire_temp_xmm().unwrap();
                     self.emit_relaxed_binop(
                         Assembler::emit_mov,
@@ -3829,9 +5600,10 @@ impl<'a> FuncGen<'a> {
                         ret,
                     );
                     self.machine.release_temp_xmm(tmp_in);
-                    self.machine.release_temp_gpr(tmp_out);
+                    self.machine
+                        .restore_stolen_gpr(&mut self.assembler, tmp_out);
                 } else {
-                    let tmp_out = self.machine.acquire_temp_gpr().unwrap();
+                    let tmp_out = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                     let tmp_in = self.machine.acquire_temp_xmm().unwrap();
                     self.emit_relaxed_binop(
                         Assembler::emit_mov,
@@ -3847,55 +5619,12 @@ impl<'a> FuncGen<'a> {
                         .emit_mov(Size::S32, Location::GPR(tmp_out), ret);
 
                     self.machine.release_temp_xmm(tmp_in);
-                    self.machine.release_temp_gpr(tmp_out);
+                    self.machine
+                        .restore_stolen_gpr(&mut self.assembler, tmp_out);
                 }
             }
 
-            Operator::I32TruncSatF32U => {
-                let loc = self.pop_value_released();
-                let ret =
-                    self.machine
-                        .acquire_locations(&mut self.assembler, &[(WpType::I32)], false)[0];
-                self.value_stack.push(ret);
-                self.fp_stack.pop1()?;
-
-                let tmp_out = self.machine.acquire_temp_gpr().unwrap();
-                let tmp_in = self.machine.acquire_temp_xmm().unwrap();
-                self.emit_relaxed_binop(Assembler::emit_mov, Size::S32, loc, Location::XMM(tmp_in));
-                self.emit_f32_int_conv_check_sat(
-                    tmp_in,
-                    GEF32_LT_U32_MIN,
-                    LEF32_GT_U32_MAX,
-                    |this| {
-                        this.assembler.emit_mov(
-                            Size::S32,
-                            Location::Imm32(0),
-                            Location::GPR(tmp_out),
-                        );
-                    },
-                    |this| {
-                        this.assembler.emit_mov(
-                            Size::S32,
-                            Location::Imm32(std::u32::MAX),
-                            Location::GPR(tmp_out),
-                        );
-                    },
-                    None::<fn(this: &mut Self)>,
-                    |this| {
-                        if this.assembler.arch_has_itruncf() {
-                            this.assembler.arch_emit_i32_trunc_uf32(tmp_in, tmp_out);
-                        } else {
-                            this.assembler
-                                .emit_cvttss2si_64(XMMOrMemory::XMM(tmp_in), tmp_out);
-                        }
-                    },
-                );
-
-                self.assembler
-                    .emit_mov(Size::S32, Location::GPR(tmp_out), ret);
-                self.machine.release_temp_xmm(tmp_in);
-                self.machine.release_temp_gpr(tmp_out);
-            }
+            Operator::I32TruncSatF32U => self.emit_trunc_sat(Size::S32, Size::S32, false)?,
 
             Operator::I32TruncF32S => {
                 let loc = self.pop_value_released();
@@ -3906,7 +5635,7 @@ impl<'a> FuncGen<'a> {
                 self.fp_stack.pop1()?;
 
                 if self.assembler.arch_has_itruncf() {
-                    let tmp_out = self.machine.acquire_temp_gpr().unwrap();
+                    let tmp_out = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                     let tmp_in = self.machine.acquire_temp_xmm().unwrap();
                     self.emit_relaxed_binop(
                         Assembler::emit_mov,
@@ -3922,9 +5651,10 @@ impl<'a> FuncGen<'a> {
                         ret,
                     );
                     self.machine.release_temp_xmm(tmp_in);
-                    self.machine.release_temp_gpr(tmp_out);
+                    self.machine
+                        .restore_stolen_gpr(&mut self.assembler, tmp_out);
                 } else {
-                    let tmp_out = self.machine.acquire_temp_gpr().unwrap();
+                    let tmp_out = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                     let tmp_in = self.machine.acquire_temp_xmm().unwrap();
 
                     self.emit_relaxed_binop(
@@ -3941,61 +5671,11 @@ impl<'a> FuncGen<'a> {
                         .emit_mov(Size::S32, Location::GPR(tmp_out), ret);
 
                     self.machine.release_temp_xmm(tmp_in);
-                    self.machine.release_temp_gpr(tmp_out);
-                }
-            }
-            Operator::I32TruncSatF32S => {
-                let loc = self.pop_value_released();
-                let ret =
                     self.machine
-                        .acquire_locations(&mut self.assembler, &[(WpType::I32)], false)[0];
-                self.value_stack.push(ret);
-                self.fp_stack.pop1()?;
-
-                let tmp_out = self.machine.acquire_temp_gpr().unwrap();
-                let tmp_in = self.machine.acquire_temp_xmm().unwrap();
-
-                self.emit_relaxed_binop(Assembler::emit_mov, Size::S32, loc, Location::XMM(tmp_in));
-                self.emit_f32_int_conv_check_sat(
-                    tmp_in,
-                    GEF32_LT_I32_MIN,
-                    LEF32_GT_I32_MAX,
-                    |this| {
-                        this.assembler.emit_mov(
-                            Size::S32,
-                            Location::Imm32(std::i32::MIN as u32),
-                            Location::GPR(tmp_out),
-                        );
-                    },
-                    |this| {
-                        this.assembler.emit_mov(
-                            Size::S32,
-                            Location::Imm32(std::i32::MAX as u32),
-                            Location::GPR(tmp_out),
-                        );
-                    },
-                    Some(|this: &mut Self| {
-                        this.assembler.emit_mov(
-                            Size::S32,
-                            Location::Imm32(0),
-                            Location::GPR(tmp_out),
-                        );
-                    }),
-                    |this| {
-                        if this.assembler.arch_has_itruncf() {
-                            this.assembler.arch_emit_i32_trunc_sf32(tmp_in, tmp_out);
-                        } else {
-                            this.assembler
-                                .emit_cvttss2si_32(XMMOrMemory::XMM(tmp_in), tmp_out);
-                        }
-                    },
-                );
-
-                self.assembler
-                    .emit_mov(Size::S32, Location::GPR(tmp_out), ret);
-                self.machine.release_temp_xmm(tmp_in);
-                self.machine.release_temp_gpr(tmp_out);
+                        .restore_stolen_gpr(&mut self.assembler, tmp_out);
+                }
             }
+            Operator::I32TruncSatF32S => self.emit_trunc_sat(Size::S32, Size::S32, true)?,
 
             Operator::I64TruncF32S => {
                 let loc = self.pop_value_released();
@@ -4006,7 +5686,7 @@ impl<'a> FuncGen<'a> {
                 self.fp_stack.pop1()?;
 
                 if self.assembler.arch_has_itruncf() {
-                    let tmp_out = self.machine.acquire_temp_gpr().unwrap();
+                    let tmp_out = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                     let tmp_in = self.machine.acquire_temp_xmm().unwrap();
                     self.emit_relaxed_binop(
                         Assembler::emit_mov,
@@ -4022,9 +5702,10 @@ impl<'a> FuncGen<'a> {
                         ret,
                     );
                     self.machine.release_temp_xmm(tmp_in);
-                    self.machine.release_temp_gpr(tmp_out);
+                    self.machine
+                        .restore_stolen_gpr(&mut self.assembler, tmp_out);
                 } else {
-                    let tmp_out = self.machine.acquire_temp_gpr().unwrap();
+                    let tmp_out = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                     let tmp_in = self.machine.acquire_temp_xmm().unwrap();
 
                     self.emit_relaxed_binop(
@@ -4040,62 +5721,12 @@ impl<'a> FuncGen<'a> {
                         .emit_mov(Size::S64, Location::GPR(tmp_out), ret);
 
                     self.machine.release_temp_xmm(tmp_in);
-                    self.machine.release_temp_gpr(tmp_out);
+                    self.machine
+                        .restore_stolen_gpr(&mut self.assembler, tmp_out);
                 }
             }
 
-            Operator::I64TruncSatF32S => {
-                let loc = self.pop_value_released();
-                let ret =
-                    self.machine
-                        .acquire_locations(&mut self.assembler, &[(WpType::I64)], false)[0];
-                self.value_stack.push(ret);
-                self.fp_stack.pop1()?;
-
-                let tmp_out = self.machine.acquire_temp_gpr().unwrap();
-                let tmp_in = self.machine.acquire_temp_xmm().unwrap();
-
-                self.emit_relaxed_binop(Assembler::emit_mov, Size::S32, loc, Location::XMM(tmp_in));
-                self.emit_f32_int_conv_check_sat(
-                    tmp_in,
-                    GEF32_LT_I64_MIN,
-                    LEF32_GT_I64_MAX,
-                    |this| {
-                        this.assembler.emit_mov(
-                            Size::S64,
-                            Location::Imm64(std::i64::MIN as u64),
-                            Location::GPR(tmp_out),
-                        );
-                    },
-                    |this| {
-                        this.assembler.emit_mov(
-                            Size::S64,
-                            Location::Imm64(std::i64::MAX as u64),
-                            Location::GPR(tmp_out),
-                        );
-                    },
-                    Some(|this: &mut Self| {
-                        this.assembler.emit_mov(
-                            Size::S64,
-                            Location::Imm64(0),
-                            Location::GPR(tmp_out),
-                        );
-                    }),
-                    |this| {
-                        if this.assembler.arch_has_itruncf() {
-                            this.assembler.arch_emit_i64_trunc_sf32(tmp_in, tmp_out);
-                        } else {
-                            this.assembler
-                                .emit_cvttss2si_64(XMMOrMemory::XMM(tmp_in), tmp_out);
-                        }
-                    },
-                );
-
-                self.assembler
-                    .emit_mov(Size::S64, Location::GPR(tmp_out), ret);
-                self.machine.release_temp_xmm(tmp_in);
-                self.machine.release_temp_gpr(tmp_out);
-            }
+            Operator::I64TruncSatF32S => self.emit_trunc_sat(Size::S32, Size::S64, true)?,
 
             Operator::I64TruncF32U => {
                 let loc = self.pop_value_released();
@@ -4106,7 +5737,7 @@ impl<'a> FuncGen<'a> {
                 self.fp_stack.pop1()?;
 
                 if self.assembler.arch_has_itruncf() {
-                    let tmp_out = self.machine.acquire_temp_gpr().unwrap();
+                    let tmp_out = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                     let tmp_in = self.machine.acquire_temp_xmm().unwrap();
                     self.emit_relaxed_binop(
                         Assembler::emit_mov,
@@ -4122,9 +5753,10 @@ impl<'a> FuncGen<'a> {
                         ret,
                     );
                     self.machine.release_temp_xmm(tmp_in);
-                    self.machine.release_temp_gpr(tmp_out);
+                    self.machine
+                        .restore_stolen_gpr(&mut self.assembler, tmp_out);
                 } else {
-                    let tmp_out = self.machine.acquire_temp_gpr().unwrap();
+                    let tmp_out = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                     let tmp_in = self.machine.acquire_temp_xmm().unwrap(); // xmm2
 
                     self.emit_relaxed_binop(
@@ -4135,7 +5767,9 @@ impl<'a> FuncGen<'a> {
                     );
                     self.emit_f32_int_conv_check_trap(tmp_in, GEF32_LT_U64_MIN, LEF32_GT_U64_MAX);
 
-                    let tmp = self.machine.acquire_temp_gpr().unwrap(); // r15
+                    let tmp = self
+                        .machine
+                        .steal_temp_gpr(&mut self.assembler, &[Location::GPR(tmp_out)]); // r15
                     let tmp_x1 = self.machine.acquire_temp_xmm().unwrap(); // xmm1
                     let tmp_x2 = self.machine.acquire_temp_xmm().unwrap(); // xmm3
 
@@ -4166,103 +5800,24 @@ impl<'a> FuncGen<'a> {
                         .emit_cvttss2si_64(XMMOrMemory::XMM(tmp_x2), tmp_out);
                     self.assembler
                         .emit_ucomiss(XMMOrMemory::XMM(tmp_x1), tmp_x2);
-                    self.assembler.emit_cmovae_gpr_64(tmp, tmp_out);
+                    self.assembler.emit_cmovcc(
+                        Size::S64,
+                        Condition::AboveEqual,
+                        Location::GPR(tmp),
+                        Location::GPR(tmp_out),
+                    );
                     self.assembler
                         .emit_mov(Size::S64, Location::GPR(tmp_out), ret);
 
                     self.machine.release_temp_xmm(tmp_x2);
                     self.machine.release_temp_xmm(tmp_x1);
-                    self.machine.release_temp_gpr(tmp);
+                    self.machine.restore_stolen_gpr(&mut self.assembler, tmp);
                     self.machine.release_temp_xmm(tmp_in);
-                    self.machine.release_temp_gpr(tmp_out);
-                }
-            }
-            Operator::I64TruncSatF32U => {
-                let loc = self.pop_value_released();
-                let ret =
                     self.machine
-                        .acquire_locations(&mut self.assembler, &[(WpType::I64)], false)[0];
-                self.value_stack.push(ret);
-                self.fp_stack.pop1()?;
-
-                let tmp_out = self.machine.acquire_temp_gpr().unwrap();
-                let tmp_in = self.machine.acquire_temp_xmm().unwrap();
-
-                self.emit_relaxed_binop(Assembler::emit_mov, Size::S32, loc, Location::XMM(tmp_in));
-                self.emit_f32_int_conv_check_sat(
-                    tmp_in,
-                    GEF32_LT_U64_MIN,
-                    LEF32_GT_U64_MAX,
-                    |this| {
-                        this.assembler.emit_mov(
-                            Size::S64,
-                            Location::Imm64(0),
-                            Location::GPR(tmp_out),
-                        );
-                    },
-                    |this| {
-                        this.assembler.emit_mov(
-                            Size::S64,
-                            Location::Imm64(std::u64::MAX),
-                            Location::GPR(tmp_out),
-                        );
-                    },
-                    None::<fn(this: &mut Self)>,
-                    |this| {
-                        if this.assembler.arch_has_itruncf() {
-                            this.assembler.arch_emit_i64_trunc_uf32(tmp_in, tmp_out);
-                        } else {
-                            let tmp = this.machine.acquire_temp_gpr().unwrap();
-                            let tmp_x1 = this.machine.acquire_temp_xmm().unwrap();
-                            let tmp_x2 = this.machine.acquire_temp_xmm().unwrap();
-
-                            this.assembler.emit_mov(
-                                Size::S32,
-                                Location::Imm32(1593835520u32),
-                                Location::GPR(tmp),
-                            ); //float 9.22337203E+18
-                            this.assembler.emit_mov(
-                                Size::S32,
-                                Location::GPR(tmp),
-                                Location::XMM(tmp_x1),
-                            );
-                            this.assembler.emit_mov(
-                                Size::S32,
-                                Location::XMM(tmp_in),
-                                Location::XMM(tmp_x2),
-                            );
-                            this.assembler
-                                .emit_vsubss(tmp_in, XMMOrMemory::XMM(tmp_x1), tmp_in);
-                            this.assembler
-                                .emit_cvttss2si_64(XMMOrMemory::XMM(tmp_in), tmp_out);
-                            this.assembler.emit_mov(
-                                Size::S64,
-                                Location::Imm64(0x8000000000000000u64),
-                                Location::GPR(tmp),
-                            );
-                            this.assembler.emit_xor(
-                                Size::S64,
-                                Location::GPR(tmp_out),
-                                Location::GPR(tmp),
-                            );
-                            this.assembler
-                                .emit_cvttss2si_64(XMMOrMemory::XMM(tmp_x2), tmp_out);
-                            this.assembler
-                                .emit_ucomiss(XMMOrMemory::XMM(tmp_x1), tmp_x2);
-                            this.assembler.emit_cmovae_gpr_64(tmp, tmp_out);
-
-                            this.machine.release_temp_xmm(tmp_x2);
-                            this.machine.release_temp_xmm(tmp_x1);
-                            this.machine.release_temp_gpr(tmp);
-                        }
-                    },
-                );
-
-                self.assembler
-                    .emit_mov(Size::S64, Location::GPR(tmp_out), ret);
-                self.machine.release_temp_xmm(tmp_in);
-                self.machine.release_temp_gpr(tmp_out);
+                        .restore_stolen_gpr(&mut self.assembler, tmp_out);
+                }
             }
+            Operator::I64TruncSatF32U => self.emit_trunc_sat(Size::S32, Size::S64, false)?,
 
             Operator::I32TruncF64U => {
                 let loc = self.pop_value_released();
@@ -4273,7 +5828,7 @@ impl<'a> FuncGen<'a> {
                 self.fp_stack.pop1()?;
 
                 if self.assembler.arch_has_itruncf() {
-                    let tmp_out = self.machine.acquire_temp_gpr().unwrap();
+                    let tmp_out = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                     let tmp_in = self.machine.acquire_temp_xmm().unwrap();
                     self.emit_relaxed_binop(
                         Assembler::emit_mov,
@@ -4289,76 +5844,33 @@ impl<'a> FuncGen<'a> {
                         ret,
                     );
                     self.machine.release_temp_xmm(tmp_in);
-                    self.machine.release_temp_gpr(tmp_out);
+                    self.machine
+                        .restore_stolen_gpr(&mut self.assembler, tmp_out);
                 } else {
-                    let tmp_out = self.machine.acquire_temp_gpr().unwrap();
+                    let tmp_out = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                     let tmp_in = self.machine.acquire_temp_xmm().unwrap();
 
-                    self.emit_relaxed_binop(
-                        Assembler::emit_mov,
-                        Size::S64,
-                        loc,
-                        Location::XMM(tmp_in),
-                    );
-                    self.emit_f64_int_conv_check_trap(tmp_in, GEF64_LT_U32_MIN, LEF64_GT_U32_MAX);
-
-                    self.assembler
-                        .emit_cvttsd2si_64(XMMOrMemory::XMM(tmp_in), tmp_out);
-                    self.assembler
-                        .emit_mov(Size::S32, Location::GPR(tmp_out), ret);
-
-                    self.machine.release_temp_xmm(tmp_in);
-                    self.machine.release_temp_gpr(tmp_out);
-                }
-            }
-
-            Operator::I32TruncSatF64U => {
-                let loc = self.pop_value_released();
-                let ret =
-                    self.machine
-                        .acquire_locations(&mut self.assembler, &[(WpType::I32)], false)[0];
-                self.value_stack.push(ret);
-                self.fp_stack.pop1()?;
-
-                let tmp_out = self.machine.acquire_temp_gpr().unwrap();
-                let tmp_in = self.machine.acquire_temp_xmm().unwrap();
-
-                self.emit_relaxed_binop(Assembler::emit_mov, Size::S64, loc, Location::XMM(tmp_in));
-                self.emit_f64_int_conv_check_sat(
-                    tmp_in,
-                    GEF64_LT_U32_MIN,
-                    LEF64_GT_U32_MAX,
-                    |this| {
-                        this.assembler.emit_mov(
-                            Size::S32,
-                            Location::Imm32(0),
-                            Location::GPR(tmp_out),
-                        );
-                    },
-                    |this| {
-                        this.assembler.emit_mov(
-                            Size::S32,
-                            Location::Imm32(std::u32::MAX),
-                            Location::GPR(tmp_out),
-                        );
-                    },
-                    None::<fn(this: &mut Self)>,
-                    |this| {
-                        if this.assembler.arch_has_itruncf() {
-                            this.assembler.arch_emit_i32_trunc_uf64(tmp_in, tmp_out);
-                        } else {
-                            this.assembler
-                                .emit_cvttsd2si_64(XMMOrMemory::XMM(tmp_in), tmp_out);
-                        }
-                    },
-                );
+                    self.emit_relaxed_binop(
+                        Assembler::emit_mov,
+                        Size::S64,
+                        loc,
+                        Location::XMM(tmp_in),
+                    );
+                    self.emit_f64_int_conv_check_trap(tmp_in, GEF64_LT_U32_MIN, LEF64_GT_U32_MAX);
 
-                self.assembler
-                    .emit_mov(Size::S32, Location::GPR(tmp_out), ret);
-                self.machine.release_temp_xmm(tmp_in);
-                self.machine.release_temp_gpr(tmp_out);
+                    self.assembler
+                        .emit_cvttsd2si_64(XMMOrMemory::XMM(tmp_in), tmp_out);
+                    self.assembler
+                        .emit_mov(Size::S32, Location::GPR(tmp_out), ret);
+
+                    self.machine.release_temp_xmm(tmp_in);
+                    self.machine
+                        .restore_stolen_gpr(&mut self.assembler, tmp_out);
+                }
             }
 
+            Operator::I32TruncSatF64U => self.emit_trunc_sat(Size::S64, Size::S32, false)?,
+
             Operator::I32TruncF64S => {
                 let loc = self.pop_value_released();
                 let ret =
@@ -4368,7 +5880,7 @@ impl<'a> FuncGen<'a> {
                 self.fp_stack.pop1()?;
 
                 if self.assembler.arch_has_itruncf() {
-                    let tmp_out = self.machine.acquire_temp_gpr().unwrap();
+                    let tmp_out = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                     let tmp_in = self.machine.acquire_temp_xmm().unwrap();
                     self.emit_relaxed_binop(
                         Assembler::emit_mov,
@@ -4384,9 +5896,10 @@ impl<'a> FuncGen<'a> {
                         ret,
                     );
                     self.machine.release_temp_xmm(tmp_in);
-                    self.machine.release_temp_gpr(tmp_out);
+                    self.machine
+                        .restore_stolen_gpr(&mut self.assembler, tmp_out);
                 } else {
-                    let tmp_out = self.machine.acquire_temp_gpr().unwrap();
+                    let tmp_out = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                     let tmp_in = self.machine.acquire_temp_xmm().unwrap();
 
                     let real_in = match loc {
@@ -4416,80 +5929,12 @@ impl<'a> FuncGen<'a> {
                         .emit_mov(Size::S32, Location::GPR(tmp_out), ret);
 
                     self.machine.release_temp_xmm(tmp_in);
-                    self.machine.release_temp_gpr(tmp_out);
+                    self.machine
+                        .restore_stolen_gpr(&mut self.assembler, tmp_out);
                 }
             }
 
-            Operator::I32TruncSatF64S => {
-                let loc = self.pop_value_released();
-                let ret =
-                    self.machine
-                        .acquire_locations(&mut self.assembler, &[(WpType::I32)], false)[0];
-                self.value_stack.push(ret);
-                self.fp_stack.pop1()?;
-
-                let tmp_out = self.machine.acquire_temp_gpr().unwrap();
-                let tmp_in = self.machine.acquire_temp_xmm().unwrap();
-
-                let real_in = match loc {
-                    Location::Imm32(_) | Location::Imm64(_) => {
-                        self.assembler
-                            .emit_mov(Size::S64, loc, Location::GPR(tmp_out));
-                        self.assembler.emit_mov(
-                            Size::S64,
-                            Location::GPR(tmp_out),
-                            Location::XMM(tmp_in),
-                        );
-                        tmp_in
-                    }
-                    Location::XMM(x) => x,
-                    _ => {
-                        self.assembler
-                            .emit_mov(Size::S64, loc, Location::XMM(tmp_in));
-                        tmp_in
-                    }
-                };
-
-                self.emit_f64_int_conv_check_sat(
-                    real_in,
-                    GEF64_LT_I32_MIN,
-                    LEF64_GT_I32_MAX,
-                    |this| {
-                        this.assembler.emit_mov(
-                            Size::S32,
-                            Location::Imm32(std::i32::MIN as u32),
-                            Location::GPR(tmp_out),
-                        );
-                    },
-                    |this| {
-                        this.assembler.emit_mov(
-                            Size::S32,
-                            Location::Imm32(std::i32::MAX as u32),
-                            Location::GPR(tmp_out),
-                        );
-                    },
-                    Some(|this: &mut Self| {
-                        this.assembler.emit_mov(
-                            Size::S32,
-                            Location::Imm32(0),
-                            Location::GPR(tmp_out),
-                        );
-                    }),
-                    |this| {
-                        if this.assembler.arch_has_itruncf() {
-                            this.assembler.arch_emit_i32_trunc_sf64(tmp_in, tmp_out);
-                        } else {
-                            this.assembler
-                                .emit_cvttsd2si_32(XMMOrMemory::XMM(real_in), tmp_out);
-                        }
-                    },
-                );
-
-                self.assembler
-                    .emit_mov(Size::S32, Location::GPR(tmp_out), ret);
-                self.machine.release_temp_xmm(tmp_in);
-                self.machine.release_temp_gpr(tmp_out);
-            }
+            Operator::I32TruncSatF64S => self.emit_trunc_sat(Size::S64, Size::S32, true)?,
 
             Operator::I64TruncF64S => {
                 let loc = self.pop_value_released();
@@ -4500,7 +5945,7 @@ impl<'a> FuncGen<'a> {
                 self.fp_stack.pop1()?;
 
                 if self.assembler.arch_has_itruncf() {
-                    let tmp_out = self.machine.acquire_temp_gpr().unwrap();
+                    let tmp_out = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                     let tmp_in = self.machine.acquire_temp_xmm().unwrap();
                     self.emit_relaxed_binop(
                         Assembler::emit_mov,
@@ -4516,9 +5961,10 @@ impl<'a> FuncGen<'a> {
                         ret,
                     );
                     self.machine.release_temp_xmm(tmp_in);
-                    self.machine.release_temp_gpr(tmp_out);
+                    self.machine
+                        .restore_stolen_gpr(&mut self.assembler, tmp_out);
                 } else {
-                    let tmp_out = self.machine.acquire_temp_gpr().unwrap();
+                    let tmp_out = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                     let tmp_in = self.machine.acquire_temp_xmm().unwrap();
 
                     self.emit_relaxed_binop(
@@ -4535,62 +5981,12 @@ impl<'a> FuncGen<'a> {
                         .emit_mov(Size::S64, Location::GPR(tmp_out), ret);
 
                     self.machine.release_temp_xmm(tmp_in);
-                    self.machine.release_temp_gpr(tmp_out);
+                    self.machine
+                        .restore_stolen_gpr(&mut self.assembler, tmp_out);
                 }
             }
 
-            Operator::I64TruncSatF64S => {
-                let loc = self.pop_value_released();
-                let ret =
-                    self.machine
-                        .acquire_locations(&mut self.assembler, &[(WpType::I64)], false)[0];
-                self.value_stack.push(ret);
-                self.fp_stack.pop1()?;
-
-                let tmp_out = self.machine.acquire_temp_gpr().unwrap();
-                let tmp_in = self.machine.acquire_temp_xmm().unwrap();
-
-                self.emit_relaxed_binop(Assembler::emit_mov, Size::S64, loc, Location::XMM(tmp_in));
-                self.emit_f64_int_conv_check_sat(
-                    tmp_in,
-                    GEF64_LT_I64_MIN,
-                    LEF64_GT_I64_MAX,
-                    |this| {
-                        this.assembler.emit_mov(
-                            Size::S64,
-                            Location::Imm64(std::i64::MIN as u64),
-                            Location::GPR(tmp_out),
-                        );
-                    },
-                    |this| {
-                        this.assembler.emit_mov(
-                            Size::S64,
-                            Location::Imm64(std::i64::MAX as u64),
-                            Location::GPR(tmp_out),
-                        );
-                    },
-                    Some(|this: &mut Self| {
-                        this.assembler.emit_mov(
-                            Size::S64,
-                            Location::Imm64(0),
-                            Location::GPR(tmp_out),
-                        );
-                    }),
-                    |this| {
-                        if this.assembler.arch_has_itruncf() {
-                            this.assembler.arch_emit_i64_trunc_sf64(tmp_in, tmp_out);
-                        } else {
-                            this.assembler
-                                .emit_cvttsd2si_64(XMMOrMemory::XMM(tmp_in), tmp_out);
-                        }
-                    },
-                );
-
-                self.assembler
-                    .emit_mov(Size::S64, Location::GPR(tmp_out), ret);
-                self.machine.release_temp_xmm(tmp_in);
-                self.machine.release_temp_gpr(tmp_out);
-            }
+            Operator::I64TruncSatF64S => self.emit_trunc_sat(Size::S64, Size::S64, true)?,
 
             Operator::I64TruncF64U => {
                 let loc = self.pop_value_released();
@@ -4601,7 +5997,7 @@ impl<'a> FuncGen<'a> {
                 self.fp_stack.pop1()?;
 
                 if self.assembler.arch_has_itruncf() {
-                    let tmp_out = self.machine.acquire_temp_gpr().unwrap();
+                    let tmp_out = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                     let tmp_in = self.machine.acquire_temp_xmm().unwrap();
                     self.emit_relaxed_binop(
                         Assembler::emit_mov,
@@ -4617,9 +6013,10 @@ impl<'a> FuncGen<'a> {
                         ret,
                     );
                     self.machine.release_temp_xmm(tmp_in);
-                    self.machine.release_temp_gpr(tmp_out);
+                    self.machine
+                        .restore_stolen_gpr(&mut self.assembler, tmp_out);
                 } else {
-                    let tmp_out = self.machine.acquire_temp_gpr().unwrap();
+                    let tmp_out = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                     let tmp_in = self.machine.acquire_temp_xmm().unwrap(); // xmm2
 
                     self.emit_relaxed_binop(
@@ -4630,7 +6027,9 @@ impl<'a> FuncGen<'a> {
                     );
                     self.emit_f64_int_conv_check_trap(tmp_in, GEF64_LT_U64_MIN, LEF64_GT_U64_MAX);
 
-                    let tmp = self.machine.acquire_temp_gpr().unwrap(); // r15
+                    let tmp = self
+                        .machine
+                        .steal_temp_gpr(&mut self.assembler, &[Location::GPR(tmp_out)]); // r15
                     let tmp_x1 = self.machine.acquire_temp_xmm().unwrap(); // xmm1
                     let tmp_x2 = self.machine.acquire_temp_xmm().unwrap(); // xmm3
 
@@ -4661,104 +6060,25 @@ impl<'a> FuncGen<'a> {
                         .emit_cvttsd2si_64(XMMOrMemory::XMM(tmp_x2), tmp_out);
                     self.assembler
                         .emit_ucomisd(XMMOrMemory::XMM(tmp_x1), tmp_x2);
-                    self.assembler.emit_cmovae_gpr_64(tmp, tmp_out);
+                    self.assembler.emit_cmovcc(
+                        Size::S64,
+                        Condition::AboveEqual,
+                        Location::GPR(tmp),
+                        Location::GPR(tmp_out),
+                    );
                     self.assembler
                         .emit_mov(Size::S64, Location::GPR(tmp_out), ret);
 
                     self.machine.release_temp_xmm(tmp_x2);
                     self.machine.release_temp_xmm(tmp_x1);
-                    self.machine.release_temp_gpr(tmp);
+                    self.machine.restore_stolen_gpr(&mut self.assembler, tmp);
                     self.machine.release_temp_xmm(tmp_in);
-                    self.machine.release_temp_gpr(tmp_out);
+                    self.machine
+                        .restore_stolen_gpr(&mut self.assembler, tmp_out);
                 }
             }
 
-            Operator::I64TruncSatF64U => {
-                let loc = self.pop_value_released();
-                let ret =
-                    self.machine
-                        .acquire_locations(&mut self.assembler, &[(WpType::I64)], false)[0];
-                self.value_stack.push(ret);
-                self.fp_stack.pop1()?;
-
-                let tmp_out = self.machine.acquire_temp_gpr().unwrap();
-                let tmp_in = self.machine.acquire_temp_xmm().unwrap();
-
-                self.emit_relaxed_binop(Assembler::emit_mov, Size::S64, loc, Location::XMM(tmp_in));
-                self.emit_f64_int_conv_check_sat(
-                    tmp_in,
-                    GEF64_LT_U64_MIN,
-                    LEF64_GT_U64_MAX,
-                    |this| {
-                        this.assembler.emit_mov(
-                            Size::S64,
-                            Location::Imm64(0),
-                            Location::GPR(tmp_out),
-                        );
-                    },
-                    |this| {
-                        this.assembler.emit_mov(
-                            Size::S64,
-                            Location::Imm64(std::u64::MAX),
-                            Location::GPR(tmp_out),
-                        );
-                    },
-                    None::<fn(this: &mut Self)>,
-                    |this| {
-                        if this.assembler.arch_has_itruncf() {
-                            this.assembler.arch_emit_i64_trunc_uf64(tmp_in, tmp_out);
-                        } else {
-                            let tmp = this.machine.acquire_temp_gpr().unwrap();
-                            let tmp_x1 = this.machine.acquire_temp_xmm().unwrap();
-                            let tmp_x2 = this.machine.acquire_temp_xmm().unwrap();
-
-                            this.assembler.emit_mov(
-                                Size::S64,
-                                Location::Imm64(4890909195324358656u64),
-                                Location::GPR(tmp),
-                            ); //double 9.2233720368547758E+18
-                            this.assembler.emit_mov(
-                                Size::S64,
-                                Location::GPR(tmp),
-                                Location::XMM(tmp_x1),
-                            );
-                            this.assembler.emit_mov(
-                                Size::S64,
-                                Location::XMM(tmp_in),
-                                Location::XMM(tmp_x2),
-                            );
-                            this.assembler
-                                .emit_vsubsd(tmp_in, XMMOrMemory::XMM(tmp_x1), tmp_in);
-                            this.assembler
-                                .emit_cvttsd2si_64(XMMOrMemory::XMM(tmp_in), tmp_out);
-                            this.assembler.emit_mov(
-                                Size::S64,
-                                Location::Imm64(0x8000000000000000u64),
-                                Location::GPR(tmp),
-                            );
-                            this.assembler.emit_xor(
-                                Size::S64,
-                                Location::GPR(tmp_out),
-                                Location::GPR(tmp),
-                            );
-                            this.assembler
-                                .emit_cvttsd2si_64(XMMOrMemory::XMM(tmp_x2), tmp_out);
-                            this.assembler
-                                .emit_ucomisd(XMMOrMemory::XMM(tmp_x1), tmp_x2);
-                            this.assembler.emit_cmovae_gpr_64(tmp, tmp_out);
-
-                            this.machine.release_temp_xmm(tmp_x2);
-                            this.machine.release_temp_xmm(tmp_x1);
-                            this.machine.release_temp_gpr(tmp);
-                        }
-                    },
-                );
-
-                self.assembler
-                    .emit_mov(Size::S64, Location::GPR(tmp_out), ret);
-                self.machine.release_temp_xmm(tmp_in);
-                self.machine.release_temp_gpr(tmp_out);
-            }
+            Operator::I64TruncSatF64U => self.emit_trunc_sat(Size::S64, Size::S64, false)?,
 
             Operator::F32ConvertI32S => {
                 let loc = self.pop_value_released();
@@ -4771,7 +6091,7 @@ impl<'a> FuncGen<'a> {
 
                 if self.assembler.arch_has_fconverti() {
                     let tmp_out = self.machine.acquire_temp_xmm().unwrap();
-                    let tmp_in = self.machine.acquire_temp_gpr().unwrap();
+                    let tmp_in = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                     self.emit_relaxed_binop(
                         Assembler::emit_mov,
                         Size::S32,
@@ -4785,11 +6105,11 @@ impl<'a> FuncGen<'a> {
                         Location::XMM(tmp_out),
                         ret,
                     );
-                    self.machine.release_temp_gpr(tmp_in);
+                    self.machine.restore_stolen_gpr(&mut self.assembler, tmp_in);
                     self.machine.release_temp_xmm(tmp_out);
                 } else {
                     let tmp_out = self.machine.acquire_temp_xmm().unwrap();
-                    let tmp_in = self.machine.acquire_temp_gpr().unwrap();
+                    let tmp_in = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
 
                     self.assembler
                         .emit_mov(Size::S32, loc, Location::GPR(tmp_in));
@@ -4798,7 +6118,7 @@ impl<'a> FuncGen<'a> {
                     self.assembler
                         .emit_mov(Size::S32, Location::XMM(tmp_out), ret);
 
-                    self.machine.release_temp_gpr(tmp_in);
+                    self.machine.restore_stolen_gpr(&mut self.assembler, tmp_in);
                     self.machine.release_temp_xmm(tmp_out);
                 }
             }
@@ -4813,7 +6133,7 @@ impl<'a> FuncGen<'a> {
 
                 if self.assembler.arch_has_fconverti() {
                     let tmp_out = self.machine.acquire_temp_xmm().unwrap();
-                    let tmp_in = self.machine.acquire_temp_gpr().unwrap();
+                    let tmp_in = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                     self.emit_relaxed_binop(
                         Assembler::emit_mov,
                         Size::S32,
@@ -4827,11 +6147,11 @@ impl<'a> FuncGen<'a> {
                         Location::XMM(tmp_out),
                         ret,
                     );
-                    self.machine.release_temp_gpr(tmp_in);
+                    self.machine.restore_stolen_gpr(&mut self.assembler, tmp_in);
                     self.machine.release_temp_xmm(tmp_out);
                 } else {
                     let tmp_out = self.machine.acquire_temp_xmm().unwrap();
-                    let tmp_in = self.machine.acquire_temp_gpr().unwrap();
+                    let tmp_in = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
 
                     self.assembler
                         .emit_mov(Size::S32, loc, Location::GPR(tmp_in));
@@ -4840,7 +6160,7 @@ impl<'a> FuncGen<'a> {
                     self.assembler
                         .emit_mov(Size::S32, Location::XMM(tmp_out), ret);
 
-                    self.machine.release_temp_gpr(tmp_in);
+                    self.machine.restore_stolen_gpr(&mut self.assembler, tmp_in);
                     self.machine.release_temp_xmm(tmp_out);
                 }
             }
@@ -4855,7 +6175,7 @@ impl<'a> FuncGen<'a> {
 
                 if self.assembler.arch_has_fconverti() {
                     let tmp_out = self.machine.acquire_temp_xmm().unwrap();
-                    let tmp_in = self.machine.acquire_temp_gpr().unwrap();
+                    let tmp_in = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                     self.emit_relaxed_binop(
                         Assembler::emit_mov,
                         Size::S64,
@@ -4869,11 +6189,11 @@ impl<'a> FuncGen<'a> {
                         Location::XMM(tmp_out),
                         ret,
                     );
-                    self.machine.release_temp_gpr(tmp_in);
+                    self.machine.restore_stolen_gpr(&mut self.assembler, tmp_in);
                     self.machine.release_temp_xmm(tmp_out);
                 } else {
                     let tmp_out = self.machine.acquire_temp_xmm().unwrap();
-                    let tmp_in = self.machine.acquire_temp_gpr().unwrap();
+                    let tmp_in = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
 
                     self.assembler
                         .emit_mov(Size::S64, loc, Location::GPR(tmp_in));
@@ -4882,7 +6202,7 @@ impl<'a> FuncGen<'a> {
                     self.assembler
                         .emit_mov(Size::S32, Location::XMM(tmp_out), ret);
 
-                    self.machine.release_temp_gpr(tmp_in);
+                    self.machine.restore_stolen_gpr(&mut self.assembler, tmp_in);
                     self.machine.release_temp_xmm(tmp_out);
                 }
             }
@@ -4897,7 +6217,7 @@ impl<'a> FuncGen<'a> {
 
                 if self.assembler.arch_has_fconverti() {
                     let tmp_out = self.machine.acquire_temp_xmm().unwrap();
-                    let tmp_in = self.machine.acquire_temp_gpr().unwrap();
+                    let tmp_in = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                     self.emit_relaxed_binop(
                         Assembler::emit_mov,
                         Size::S64,
@@ -4911,12 +6231,14 @@ impl<'a> FuncGen<'a> {
                         Location::XMM(tmp_out),
                         ret,
                     );
-                    self.machine.release_temp_gpr(tmp_in);
+                    self.machine.restore_stolen_gpr(&mut self.assembler, tmp_in);
                     self.machine.release_temp_xmm(tmp_out);
                 } else {
                     let tmp_out = self.machine.acquire_temp_xmm().unwrap();
-                    let tmp_in = self.machine.acquire_temp_gpr().unwrap();
-                    let tmp = self.machine.acquire_temp_gpr().unwrap();
+                    let tmp_in = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
+                    let tmp = self
+                        .machine
+                        .steal_temp_gpr(&mut self.assembler, &[Location::GPR(tmp_in)]);
 
                     let do_convert = self.assembler.get_label();
                     let end_convert = self.assembler.get_label();
@@ -4945,8 +6267,8 @@ impl<'a> FuncGen<'a> {
                     self.assembler
                         .emit_mov(Size::S32, Location::XMM(tmp_out), ret);
 
-                    self.machine.release_temp_gpr(tmp);
-                    self.machine.release_temp_gpr(tmp_in);
+                    self.machine.restore_stolen_gpr(&mut self.assembler, tmp);
+                    self.machine.restore_stolen_gpr(&mut self.assembler, tmp_in);
                     self.machine.release_temp_xmm(tmp_out);
                 }
             }
@@ -4962,7 +6284,7 @@ impl<'a> FuncGen<'a> {
 
                 if self.assembler.arch_has_fconverti() {
                     let tmp_out = self.machine.acquire_temp_xmm().unwrap();
-                    let tmp_in = self.machine.acquire_temp_gpr().unwrap();
+                    let tmp_in = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                     self.emit_relaxed_binop(
                         Assembler::emit_mov,
                         Size::S32,
@@ -4976,11 +6298,11 @@ impl<'a> FuncGen<'a> {
                         Location::XMM(tmp_out),
                         ret,
                     );
-                    self.machine.release_temp_gpr(tmp_in);
+                    self.machine.restore_stolen_gpr(&mut self.assembler, tmp_in);
                     self.machine.release_temp_xmm(tmp_out);
                 } else {
                     let tmp_out = self.machine.acquire_temp_xmm().unwrap();
-                    let tmp_in = self.machine.acquire_temp_gpr().unwrap();
+                    let tmp_in = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
 
                     self.assembler
                         .emit_mov(Size::S32, loc, Location::GPR(tmp_in));
@@ -4989,7 +6311,7 @@ impl<'a> FuncGen<'a> {
                     self.assembler
                         .emit_mov(Size::S64, Location::XMM(tmp_out), ret);
 
-                    self.machine.release_temp_gpr(tmp_in);
+                    self.machine.restore_stolen_gpr(&mut self.assembler, tmp_in);
                     self.machine.release_temp_xmm(tmp_out);
                 }
             }
@@ -5004,7 +6326,7 @@ impl<'a> FuncGen<'a> {
 
                 if self.assembler.arch_has_fconverti() {
                     let tmp_out = self.machine.acquire_temp_xmm().unwrap();
-                    let tmp_in = self.machine.acquire_temp_gpr().unwrap();
+                    let tmp_in = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                     self.emit_relaxed_binop(
                         Assembler::emit_mov,
                         Size::S32,
@@ -5018,11 +6340,11 @@ impl<'a> FuncGen<'a> {
                         Location::XMM(tmp_out),
                         ret,
                     );
-                    self.machine.release_temp_gpr(tmp_in);
+                    self.machine.restore_stolen_gpr(&mut self.assembler, tmp_in);
                     self.machine.release_temp_xmm(tmp_out);
                 } else {
                     let tmp_out = self.machine.acquire_temp_xmm().unwrap();
-                    let tmp_in = self.machine.acquire_temp_gpr().unwrap();
+                    let tmp_in = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
 
                     self.assembler
                         .emit_mov(Size::S32, loc, Location::GPR(tmp_in));
@@ -5031,7 +6353,7 @@ impl<'a> FuncGen<'a> {
                     self.assembler
                         .emit_mov(Size::S64, Location::XMM(tmp_out), ret);
 
-                    self.machine.release_temp_gpr(tmp_in);
+                    self.machine.restore_stolen_gpr(&mut self.assembler, tmp_in);
                     self.machine.release_temp_xmm(tmp_out);
                 }
             }
@@ -5046,7 +6368,7 @@ impl<'a> FuncGen<'a> {
 
                 if self.assembler.arch_has_fconverti() {
                     let tmp_out = self.machine.acquire_temp_xmm().unwrap();
-                    let tmp_in = self.machine.acquire_temp_gpr().unwrap();
+                    let tmp_in = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                     self.emit_relaxed_binop(
                         Assembler::emit_mov,
                         Size::S64,
@@ -5060,11 +6382,11 @@ impl<'a> FuncGen<'a> {
                         Location::XMM(tmp_out),
                         ret,
                     );
-                    self.machine.release_temp_gpr(tmp_in);
+                    self.machine.restore_stolen_gpr(&mut self.assembler, tmp_in);
                     self.machine.release_temp_xmm(tmp_out);
                 } else {
                     let tmp_out = self.machine.acquire_temp_xmm().unwrap();
-                    let tmp_in = self.machine.acquire_temp_gpr().unwrap();
+                    let tmp_in = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
 
                     self.assembler
                         .emit_mov(Size::S64, loc, Location::GPR(tmp_in));
@@ -5073,7 +6395,7 @@ impl<'a> FuncGen<'a> {
                     self.assembler
                         .emit_mov(Size::S64, Location::XMM(tmp_out), ret);
 
-                    self.machine.release_temp_gpr(tmp_in);
+                    self.machine.restore_stolen_gpr(&mut self.assembler, tmp_in);
                     self.machine.release_temp_xmm(tmp_out);
                 }
             }
@@ -5088,7 +6410,7 @@ impl<'a> FuncGen<'a> {
 
                 if self.assembler.arch_has_fconverti() {
                     let tmp_out = self.machine.acquire_temp_xmm().unwrap();
-                    let tmp_in = self.machine.acquire_temp_gpr().unwrap();
+                    let tmp_in = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                     self.emit_relaxed_binop(
                         Assembler::emit_mov,
                         Size::S64,
@@ -5102,12 +6424,14 @@ impl<'a> FuncGen<'a> {
                         Location::XMM(tmp_out),
                         ret,
                     );
-                    self.machine.release_temp_gpr(tmp_in);
+                    self.machine.restore_stolen_gpr(&mut self.assembler, tmp_in);
                     self.machine.release_temp_xmm(tmp_out);
                 } else {
                     let tmp_out = self.machine.acquire_temp_xmm().unwrap();
-                    let tmp_in = self.machine.acquire_temp_gpr().unwrap();
-                    let tmp = self.machine.acquire_temp_gpr().unwrap();
+                    let tmp_in = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
+                    let tmp = self
+                        .machine
+                        .steal_temp_gpr(&mut self.assembler, &[Location::GPR(tmp_in)]);
 
                     let do_convert = self.assembler.get_label();
                     let end_convert = self.assembler.get_label();
@@ -5136,8 +6460,8 @@ impl<'a> FuncGen<'a> {
                     self.assembler
                         .emit_mov(Size::S64, Location::XMM(tmp_out), ret);
 
-                    self.machine.release_temp_gpr(tmp);
-                    self.machine.release_temp_gpr(tmp_in);
+                    self.machine.restore_stolen_gpr(&mut self.assembler, tmp);
+                    self.machine.restore_stolen_gpr(&mut self.assembler, tmp_in);
                     self.machine.release_temp_xmm(tmp_out);
                 }
             }
@@ -5157,137 +6481,11 @@ impl<'a> FuncGen<'a> {
                     sig.results().iter().cloned().map(type_to_wp_type).collect();
 
                 let func_index = self.pop_value_released();
+                let params = self.pop_call_params(&param_types);
 
-                let params: SmallVec<[_; 8]> = self
-                    .value_stack
-                    .drain(self.value_stack.len() - param_types.len()..)
-                    .collect();
-                self.machine.release_locations_only_regs(&params);
-
-                // Pop arguments off the FP stack and canonicalize them if needed.
-                //
-                // Canonicalization state will be lost across function calls, so early canonicalization
-                // is necessary here.
-                while let Some(fp) = self.fp_stack.last() {
-                    if fp.depth >= self.value_stack.len() {
-                        let index = fp.depth - self.value_stack.len();
-                        if self.assembler.arch_supports_canonicalize_nan()
-                            && self.config.enable_nan_canonicalization
-                            && fp.canonicalization.is_some()
-                        {
-                            let size = fp.canonicalization.unwrap().to_size();
-                            self.canonicalize_nan(size, params[index], params[index]);
-                        }
-                        self.fp_stack.pop().unwrap();
-                    } else {
-                        break;
-                    }
-                }
-
-                let table_base = self.machine.acquire_temp_gpr().unwrap();
-                let table_count = self.machine.acquire_temp_gpr().unwrap();
-                let sigidx = self.machine.acquire_temp_gpr().unwrap();
-
-                if let Some(local_table_index) = self.module.local_table_index(table_index) {
-                    let (vmctx_offset_base, vmctx_offset_len) = (
-                        self.vmoffsets.vmctx_vmtable_definition(local_table_index),
-                        self.vmoffsets
-                            .vmctx_vmtable_definition_current_elements(local_table_index),
-                    );
-                    self.assembler.emit_mov(
-                        Size::S64,
-                        Location::Memory(Machine::get_vmctx_reg(), vmctx_offset_base as i32),
-                        Location::GPR(table_base),
-                    );
-                    self.assembler.emit_mov(
-                        Size::S32,
-                        Location::Memory(Machine::get_vmctx_reg(), vmctx_offset_len as i32),
-                        Location::GPR(table_count),
-                    );
-                } else {
-                    // Do an indirection.
-                    let import_offset = self.vmoffsets.vmctx_vmtable_import(table_index);
-                    self.assembler.emit_mov(
-                        Size::S64,
-                        Location::Memory(Machine::get_vmctx_reg(), import_offset as i32),
-                        Location::GPR(table_base),
-                    );
-
-                    // Load len.
-                    self.assembler.emit_mov(
-                        Size::S32,
-                        Location::Memory(
-                            table_base,
-                            self.vmoffsets.vmtable_definition_current_elements() as _,
-                        ),
-                        Location::GPR(table_count),
-                    );
-
-                    // Load base.
-                    self.assembler.emit_mov(
-                        Size::S64,
-                        Location::Memory(table_base, self.vmoffsets.vmtable_definition_base() as _),
-                        Location::GPR(table_base),
-                    );
-                }
-
-                self.assembler
-                    .emit_cmp(Size::S32, func_index, Location::GPR(table_count));
-                self.assembler
-                    .emit_jmp(Condition::BelowEqual, self.special_labels.table_access_oob);
-                self.assembler
-                    .emit_mov(Size::S32, func_index, Location::GPR(table_count));
-                self.assembler
-                    .emit_imul_imm32_gpr64(self.vmoffsets.size_of_vm_funcref() as u32, table_count);
-                self.assembler.emit_add(
-                    Size::S64,
-                    Location::GPR(table_base),
-                    Location::GPR(table_count),
-                );
-
-                // deref the table to get a VMFuncRef
-                self.assembler.emit_mov(
-                    Size::S64,
-                    Location::Memory(table_count, self.vmoffsets.vm_funcref_anyfunc_ptr() as i32),
-                    Location::GPR(table_count),
-                );
-                // Trap if the FuncRef is null
-                self.assembler
-                    .emit_cmp(Size::S64, Location::Imm32(0), Location::GPR(table_count));
-                self.assembler
-                    .emit_jmp(Condition::Equal, self.special_labels.indirect_call_null);
-                self.assembler.emit_mov(
-                    Size::S64,
-                    Location::Memory(
-                        Machine::get_vmctx_reg(),
-                        self.vmoffsets.vmctx_vmshared_signature_id(index) as i32,
-                    ),
-                    Location::GPR(sigidx),
-                );
-
-                // Trap if signature mismatches.
-                self.assembler.emit_cmp(
-                    Size::S32,
-                    Location::GPR(sigidx),
-                    Location::Memory(
-                        table_count,
-                        (self.vmoffsets.vmcaller_checked_anyfunc_type_index() as usize) as i32,
-                    ),
-                );
-                self.assembler
-                    .emit_jmp(Condition::NotEqual, self.special_labels.bad_signature);
-
-                self.machine.release_temp_gpr(sigidx);
-                self.machine.release_temp_gpr(table_count);
-                self.machine.release_temp_gpr(table_base);
+                let stack_results = self.acquire_call_stack_results(&return_types);
 
-                if table_count != GPR::RAX {
-                    self.assembler.emit_mov(
-                        Size::S64,
-                        Location::GPR(table_count),
-                        Location::GPR(GPR::RAX),
-                    );
-                }
+                self.emit_load_indirect_callee(table_index, index, func_index);
 
                 let vmcaller_checked_anyfunc_func_ptr =
                     self.vmoffsets.vmcaller_checked_anyfunc_func_ptr() as usize;
@@ -5295,7 +6493,7 @@ impl<'a> FuncGen<'a> {
                     self.vmoffsets.vmcaller_checked_anyfunc_vmctx() as usize;
                 let calling_convention = self.calling_convention;
 
-                self.emit_call_native(
+                self.emit_call_native_with_results(
                     |this| {
                         if this.assembler.arch_requires_indirect_call_trampoline() {
                             this.assembler.arch_emit_indirect_call_with_trampoline(
@@ -5318,121 +6516,79 @@ impl<'a> FuncGen<'a> {
                             ));
                         }
                     },
-                    params.iter().copied(),
+                    value_words(&param_types, &params).into_iter(),
+                    &value_words(&stack_result_types(&return_types), &stack_results),
+                    true,
                 )?;
 
-                self.machine
-                    .release_locations_only_stack(&mut self.assembler, &params);
-
-                if !return_types.is_empty() {
-                    let ret = self.machine.acquire_locations(
-                        &mut self.assembler,
-                        &[return_types[0]],
-                        false,
-                    )[0];
-                    self.value_stack.push(ret);
-                    if return_types[0].is_float() {
-                        self.assembler
-                            .emit_mov(Size::S64, Location::XMM(XMM::XMM0), ret);
-                        self.fp_stack
-                            .push(FloatValue::new(self.value_stack.len() - 1));
-                    } else {
-                        self.assembler
-                            .emit_mov(Size::S64, Location::GPR(GPR::RAX), ret);
-                    }
-                }
-            }
-            Operator::If { ty } => {
-                let label_end = self.assembler.get_label();
-                let label_else = self.assembler.get_label();
-
-                let cond = self.pop_value_released();
+                self.machine.release_locations_only_stack(&params);
 
-                let frame = ControlFrame {
-                    br_label: label_end,
-                    loop_like: false,
-                    if_else: IfElseState::If(label_else),
-                    returns: match ty {
-                        WpTypeOrFuncType::Type(WpType::EmptyBlockType) => smallvec![],
-                        WpTypeOrFuncType::Type(inner_ty) => smallvec![inner_ty],
-                        _ => {
-                            return Err(CodegenError {
-                                message: "If: multi-value returns not yet implemented".to_string(),
-                            })
-                        }
-                    },
-                    value_stack_depth: self.value_stack.len(),
-                    fp_stack_depth: self.fp_stack.len(),
-                };
-                self.control_stack.push(frame);
-                self.emit_relaxed_binop(Assembler::emit_cmp, Size::S32, Location::Imm32(0), cond);
-                self.assembler.emit_jmp(Condition::Equal, label_else);
+                self.push_call_results(&return_types, &stack_results);
             }
-            Operator::Else => {
-                let frame = self.control_stack.last_mut().unwrap();
-
-                if !was_unreachable && !frame.returns.is_empty() {
-                    let first_return = frame.returns[0];
-                    let loc = *self.value_stack.last().unwrap();
-                    if first_return.is_float() {
-                        let fp = self.fp_stack.peek1()?;
-                        if self.assembler.arch_supports_canonicalize_nan()
-                            && self.config.enable_nan_canonicalization
-                            && fp.canonicalization.is_some()
-                        {
-                            self.canonicalize_nan(
-                                match first_return {
-                                    WpType::F32 => Size::S32,
-                                    WpType::F64 => Size::S64,
-                                    _ => unreachable!(),
-                                },
-                                loc,
-                                Location::GPR(GPR::RAX),
-                            );
-                        } else {
-                            self.emit_relaxed_binop(
-                                Assembler::emit_mov,
-                                Size::S64,
-                                loc,
-                                Location::GPR(GPR::RAX),
-                            );
-                        }
-                    } else {
-                        self.emit_relaxed_binop(
-                            Assembler::emit_mov,
-                            Size::S64,
-                            loc,
-                            Location::GPR(GPR::RAX),
-                        );
-                    }
+            Operator::ReturnCall { function_index } => {
+                let function = FunctionIndex::from_u32(function_index);
+                let sig = &self.module.signatures[self.module.functions[function]];
+                let param_types: SmallVec<[WpType; 8]> =
+                    sig.params().iter().cloned().map(type_to_wp_type).collect();
+                let return_types: SmallVec<[WpType; 1]> =
+                    sig.results().iter().cloned().map(type_to_wp_type).collect();
+
+                let params = self.pop_call_params(&param_types);
+                if self.try_intrinsic(function, &params) {
+                    self.emit_br(0);
+                } else {
+                    self.emit_return_call(
+                        TailCallee::Direct(function),
+                        &param_types,
+                        &return_types,
+                        &params,
+                    );
+                    self.machine.release_locations_only_stack(&params);
                 }
+                self.unreachable_depth = 1;
+            }
+            Operator::ReturnCallIndirect { index, table_index } => {
+                let table_index = TableIndex::new(table_index as _);
+                let index = SignatureIndex::new(index as usize);
+                let sig = self.module.signatures.get(index).unwrap();
+                let param_types: SmallVec<[WpType; 8]> =
+                    sig.params().iter().cloned().map(type_to_wp_type).collect();
+                let return_types: SmallVec<[WpType; 1]> =
+                    sig.results().iter().cloned().map(type_to_wp_type).collect();
 
-                self.update_max_stack_depth();
+                let func_index = self.pop_value_released();
+                let params = self.pop_call_params(&param_types);
+                self.emit_load_indirect_callee(table_index, index, func_index);
+                self.emit_return_call(TailCallee::Indirect, &param_types, &return_types, &params);
+                self.machine.release_locations_only_stack(&params);
+                self.unreachable_depth = 1;
+            }
+            Operator::If { ty } => {
+                let label_end = self.assembler.get_label();
+                let label_else = self.assembler.get_label();
 
-                let mut frame = self.control_stack.last_mut().unwrap();
+                // The condition is released only once the block's slots are set up, so that
+                // they cannot overwrite it.
+                self.update_max_stack_depth();
+                let cond = self.value_stack.pop().unwrap();
+                self.push_block_frame(label_end, false, IfElseState::If(label_else), ty, true);
+                self.machine.release_locations(&[cond]);
 
-                let released: &[Location] = &self.value_stack[frame.value_stack_depth..];
-                self.machine
-                    .release_locations(&mut self.assembler, released);
-                self.value_stack.truncate(frame.value_stack_depth);
-                self.fp_stack.truncate(frame.fp_stack_depth);
-
-                match frame.if_else {
-                    IfElseState::If(label) => {
-                        self.assembler.emit_jmp(Condition::None, frame.br_label);
-                        self.assembler.emit_label(label);
-                        frame.if_else = IfElseState::Else;
-                    }
-                    _ => {
-                        return Err(CodegenError {
-                            message: "Else: frame.if_else unreachable code".to_string(),
-                        })
-                    }
-                }
+                self.emit_relaxed_binop(Assembler::emit_cmp, Size::S32, Location::Imm32(0), cond);
+                self.machine.flush_stack_adjustment(&mut self.assembler);
+                self.assembler.emit_jmp(Condition::Equal, label_else);
+                self.load_block_params();
+            }
+            Operator::Else => {
+                self.emit_else(was_unreachable)?;
             }
             // `TypedSelect` must be used for extern refs so ref counting should
             // be done with TypedSelect. But otherwise they're the same.
             Operator::TypedSelect { .. } | Operator::Select => {
+                let ty = match self.value_stack[self.value_stack.len() - 2] {
+                    loc if self.machine.is_v128(loc) => WpType::V128,
+                    _ => WpType::I64,
+                };
                 let cond = self.pop_value_released();
                 let v_b = self.pop_value_released();
                 let v_a = self.pop_value_released();
@@ -5448,67 +6604,181 @@ impl<'a> FuncGen<'a> {
                     } else {
                         None
                     };
-                let ret =
-                    self.machine
-                        .acquire_locations(&mut self.assembler, &[(WpType::I64)], false)[0];
+                let ret = self
+                    .machine
+                    .acquire_locations(&mut self.assembler, &[ty], false)[0];
                 self.value_stack.push(ret);
 
-                let end_label = self.assembler.get_label();
-                let zero_label = self.assembler.get_label();
+                let canonicalize = self.assembler.arch_supports_canonicalize_nan()
+                    && self.config.enable_nan_canonicalization;
+                let needs_canonicalization = match cncl {
+                    Some((a, b)) => canonicalize && (a.is_some() || b.is_some()),
+                    None => false,
+                };
+                if ty != WpType::V128 && !needs_canonicalization {
+                    self.emit_select_cmov(cond, v_a, v_b, ret);
+                } else {
+                    let end_label = self.assembler.get_label();
+                    let zero_label = self.assembler.get_label();
 
-                self.emit_relaxed_binop(Assembler::emit_cmp, Size::S32, Location::Imm32(0), cond);
-                self.assembler.emit_jmp(Condition::Equal, zero_label);
-                match cncl {
-                    Some((Some(fp), _))
-                        if self.assembler.arch_supports_canonicalize_nan()
-                            && self.config.enable_nan_canonicalization =>
-                    {
-                        self.canonicalize_nan(fp.to_size(), v_a, ret);
-                    }
-                    _ => {
-                        if v_a != ret {
-                            self.emit_relaxed_binop(Assembler::emit_mov, Size::S64, v_a, ret);
+                    self.emit_relaxed_binop(
+                        Assembler::emit_cmp,
+                        Size::S32,
+                        Location::Imm32(0),
+                        cond,
+                    );
+                    self.assembler.emit_jmp(Condition::Equal, zero_label);
+                    match cncl {
+                        Some((Some(fp), _)) if canonicalize => {
+                            self.canonicalize_nan(fp.to_size(), v_a, ret);
+                        }
+                        _ => {
+                            if v_a != ret {
+                                self.emit_typed_move(ty, v_a, ret);
+                            }
                         }
                     }
-                }
-                self.assembler.emit_jmp(Condition::None, end_label);
-                self.assembler.emit_label(zero_label);
-                match cncl {
-                    Some((_, Some(fp)))
-                        if self.assembler.arch_supports_canonicalize_nan()
-                            && self.config.enable_nan_canonicalization =>
-                    {
-                        self.canonicalize_nan(fp.to_size(), v_b, ret);
-                    }
-                    _ => {
-                        if v_b != ret {
-                            self.emit_relaxed_binop(Assembler::emit_mov, Size::S64, v_b, ret);
+                    self.assembler.emit_jmp(Condition::None, end_label);
+                    self.assembler.emit_label(zero_label);
+                    match cncl {
+                        Some((_, Some(fp))) if canonicalize => {
+                            self.canonicalize_nan(fp.to_size(), v_b, ret);
+                        }
+                        _ => {
+                            if v_b != ret {
+                                self.emit_typed_move(ty, v_b, ret);
+                            }
                         }
                     }
+                    self.assembler.emit_label(end_label);
                 }
-                self.assembler.emit_label(end_label);
             }
             Operator::Block { ty } => {
-                let frame = ControlFrame {
-                    br_label: self.assembler.get_label(),
-                    loop_like: false,
-                    if_else: IfElseState::None,
-                    returns: match ty {
-                        WpTypeOrFuncType::Type(WpType::EmptyBlockType) => smallvec![],
-                        WpTypeOrFuncType::Type(inner_ty) => smallvec![inner_ty],
-                        _ => {
-                            return Err(CodegenError {
-                                message: "Block: multi-value returns not yet implemented"
-                                    .to_string(),
-                            })
-                        }
-                    },
-                    value_stack_depth: self.value_stack.len(),
-                    fp_stack_depth: self.fp_stack.len(),
+                self.machine.flush_stack_adjustment(&mut self.assembler);
+                let br_label = self.assembler.get_label();
+                self.push_block_frame(br_label, false, IfElseState::None, ty, false);
+            }
+            Operator::Try { ty } => {
+                self.machine.flush_stack_adjustment(&mut self.assembler);
+                let br_label = self.assembler.get_label();
+                self.push_block_frame(br_label, false, IfElseState::None, ty, false);
+
+                // The slot of the exception goes below the params of the block, so that it
+                // outlives the body.
+                let exception = self
+                    .machine
+                    .acquire_stack_locations(&mut self.assembler, &[WpType::I64])[0];
+                let frame = self.control_stack.last_mut().unwrap();
+                let depth = frame.value_stack_depth;
+                self.value_stack.insert(depth, exception);
+                for fp in self.fp_stack.iter_mut().filter(|fp| fp.depth >= depth) {
+                    fp.depth += 1;
+                }
+                frame.value_stack_depth += 1;
+                frame.try_frame = Some(TryFrame {
+                    dispatch: self.assembler.get_label(),
+                    exception,
+                    state: TryState::Body,
+                });
+            }
+            Operator::Catch { index } => {
+                self.emit_catch(Some(TagIndex::from_u32(index)), was_unreachable)?;
+            }
+            Operator::CatchAll => {
+                self.emit_catch(None, was_unreachable)?;
+            }
+            Operator::Delegate { relative_depth } => {
+                // The exceptions of the body are thrown from the outer block at `relative_depth`
+                // instead, where the try blocks in between don't catch them. Delegating to the
+                // function body throws them to the caller.
+                let target = self.control_stack.len() - 2 - relative_depth as usize;
+                self.emit_try_clause_end(was_unreachable);
+                self.emit_try_dispatch();
+                self.emit_throw_from(target)?;
+                self.emit_end(true)?;
+            }
+            Operator::Throw { index } => {
+                let tag = TagIndex::from_u32(index);
+                let sig = self.module.tags[tag];
+                let tys: SmallVec<[WpType; 8]> = self.module.signatures[sig]
+                    .params()
+                    .iter()
+                    .cloned()
+                    .map(type_to_wp_type)
+                    .collect();
+                let params = self.pop_call_params(&tys);
+
+                // The runtime reads the values from consecutive 16-byte slots.
+                let buffer = self
+                    .machine
+                    .acquire_stack_buffer(&mut self.assembler, tys.len());
+                for ((ty, param), slot) in tys.iter().zip(params.iter()).zip(buffer.iter()) {
+                    self.emit_typed_move(*ty, *param, *slot);
+                }
+                self.machine.release_locations_only_stack(&params);
+
+                let values = self.machine.reserve_unused_temp_gpr(GPR::RCX);
+                match buffer.first() {
+                    Some(first) => {
+                        self.assembler
+                            .emit_lea(Size::S64, *first, Location::GPR(values))
+                    }
+                    None => self.assembler.emit_mov(
+                        Size::S64,
+                        Location::Imm32(0),
+                        Location::GPR(values),
+                    ),
+                }
+
+                // With a handler in this function, the exception is only created, and jumped
+                // to.
+                let handler = self.exception_handler(self.control_stack.len() - 1);
+                let builtin = match handler {
+                    Some(_) => VMBuiltinFunctionIndex::get_exception_new_index(),
+                    None => VMBuiltinFunctionIndex::get_throw_index(),
                 };
-                self.control_stack.push(frame);
+                self.assembler.emit_mov(
+                    Size::S64,
+                    Location::Memory(
+                        Machine::get_vmctx_reg(),
+                        self.vmoffsets.vmctx_builtin_function(builtin) as i32,
+                    ),
+                    Location::GPR(GPR::RAX),
+                );
+                self.emit_call_native(
+                    |this| {
+                        this.assembler.emit_call_register(GPR::RAX);
+                    },
+                    // [vmctx, tag_index, values] -> exception
+                    [Location::Imm32(index), Location::GPR(values)]
+                        .iter()
+                        .cloned(),
+                )?;
+                if let Some(dispatch) = handler {
+                    self.assembler.emit_jmp(Condition::None, dispatch);
+                }
+                self.machine.release_temp_gpr(values);
+                self.machine.release_locations(&buffer);
+                self.unreachable_depth = 1;
+            }
+            Operator::Rethrow { relative_depth } => {
+                let frame_index = self.control_stack.len() - 1;
+                let exception = self.control_stack[frame_index - relative_depth as usize]
+                    .try_frame
+                    .unwrap()
+                    .exception;
+                self.assembler
+                    .emit_mov(Size::S64, exception, Location::GPR(GPR::RAX));
+                self.emit_throw_from(frame_index)?;
+                self.unreachable_depth = 1;
             }
             Operator::Loop { ty } => {
+                let br_label = self.assembler.get_label();
+                self.push_block_frame(br_label, true, IfElseState::None, ty, true);
+
+                // Branching back to the loop header restores the exact stack depth.
+                self.machine.flush_stack_adjustment(&mut self.assembler);
+
                 // Pad with NOPs to the next 16-byte boundary.
                 // Here we don't use the dynasm `.align 16` attribute because it pads the alignment with single-byte nops
                 // which may lead to efficiency problems.
@@ -5520,29 +6790,14 @@ impl<'a> FuncGen<'a> {
                 }
                 assert_eq!(self.assembler.get_offset().0 % 16, 0);
 
-                let br_label = self.assembler.get_label();
                 let _activate_offset = self.assembler.get_offset().0;
 
-                self.control_stack.push(ControlFrame {
-                    br_label,
-                    loop_like: true,
-                    if_else: IfElseState::None,
-                    returns: match ty {
-                        WpTypeOrFuncType::Type(WpType::EmptyBlockType) => smallvec![],
-                        WpTypeOrFuncType::Type(inner_ty) => smallvec![inner_ty],
-                        _ => {
-                            return Err(CodegenError {
-                                message: "Loop: multi-value returns not yet implemented"
-                                    .to_string(),
-                            })
-                        }
-                    },
-                    value_stack_depth: self.value_stack.len(),
-                    fp_stack_depth: self.fp_stack.len(),
-                });
                 self.assembler.emit_label(br_label);
+                self.load_block_params();
 
-                // TODO: Re-enable interrupt signal check without branching
+                if self.config.enable_epoch_interruption {
+                    self.emit_epoch_check()?;
+                }
             }
             Operator::Nop => {}
             Operator::MemorySize { mem, mem_byte: _ } => {
@@ -5552,10 +6807,18 @@ impl<'a> FuncGen<'a> {
                     Location::Memory(
                         Machine::get_vmctx_reg(),
                         self.vmoffsets.vmctx_builtin_function(
-                            if self.module.local_memory_index(memory_index).is_some() {
-                                VMBuiltinFunctionIndex::get_memory32_size_index()
-                            } else {
-                                VMBuiltinFunctionIndex::get_imported_memory32_size_index()
+                            match (
+                                self.module.local_memory_index(memory_index).is_some(),
+                                self.module.memories[memory_index].memory64,
+                            ) {
+                                (true, false) => VMBuiltinFunctionIndex::get_memory32_size_index(),
+                                (false, false) => {
+                                    VMBuiltinFunctionIndex::get_imported_memory32_size_index()
+                                }
+                                (true, true) => VMBuiltinFunctionIndex::get_memory64_size_index(),
+                                (false, true) => {
+                                    VMBuiltinFunctionIndex::get_imported_memory64_size_index()
+                                }
                             },
                         ) as i32,
                     ),
@@ -5576,6 +6839,7 @@ impl<'a> FuncGen<'a> {
                     .emit_mov(Size::S64, Location::GPR(GPR::RAX), ret);
             }
             Operator::MemoryInit { segment, mem } => {
+                self.check_memory32(mem, "memory.init")?;
                 let len = self.value_stack.pop().unwrap();
                 let src = self.value_stack.pop().unwrap();
                 let dst = self.value_stack.pop().unwrap();
@@ -5607,8 +6871,7 @@ impl<'a> FuncGen<'a> {
                     .iter()
                     .cloned(),
                 )?;
-                self.machine
-                    .release_locations_only_stack(&mut self.assembler, &[dst, src, len]);
+                self.machine.release_locations_only_stack(&[dst, src, len]);
             }
             Operator::DataDrop { segment } => {
                 self.assembler.emit_mov(
@@ -5631,6 +6894,7 @@ impl<'a> FuncGen<'a> {
                 )?;
             }
             Operator::MemoryCopy { src, dst } => {
+                self.check_memory32(src, "memory.copy")?;
                 // ignore until we support multiple memories
                 let _dst = dst;
                 let len = self.value_stack.pop().unwrap();
@@ -5677,9 +6941,10 @@ impl<'a> FuncGen<'a> {
                     .cloned(),
                 )?;
                 self.machine
-                    .release_locations_only_stack(&mut self.assembler, &[dst_pos, src_pos, len]);
+                    .release_locations_only_stack(&[dst_pos, src_pos, len]);
             }
             Operator::MemoryFill { mem } => {
+                self.check_memory32(mem, "memory.fill")?;
                 let len = self.value_stack.pop().unwrap();
                 let val = self.value_stack.pop().unwrap();
                 let dst = self.value_stack.pop().unwrap();
@@ -5717,24 +6982,44 @@ impl<'a> FuncGen<'a> {
                         .iter()
                         .cloned(),
                 )?;
-                self.machine
-                    .release_locations_only_stack(&mut self.assembler, &[dst, val, len]);
+                self.machine.release_locations_only_stack(&[dst, val, len]);
             }
             Operator::MemoryGrow { mem, mem_byte: _ } => {
                 let memory_index = MemoryIndex::new(mem as usize);
                 let param_pages = self.value_stack.pop().unwrap();
 
+                let memory64 = self.module.memories[memory_index].memory64;
+
                 self.machine.release_locations_only_regs(&[param_pages]);
 
+                // The runtime is only called for the grows that don't fit in what it reserved.
+                // Those of 64-bit memories, counted in 64-bit pages, are all left to it.
+                let runtime_grow = self.assembler.get_label();
+                let grown = self.assembler.get_label();
+                self.machine.flush_stack_adjustment(&mut self.assembler);
+                if !memory64 {
+                    self.emit_inline_memory_grow(memory_index, param_pages, runtime_grow);
+                    self.assembler.emit_jmp(Condition::None, grown);
+                }
+                self.assembler.emit_label(runtime_grow);
+
                 self.assembler.emit_mov(
                     Size::S64,
                     Location::Memory(
                         Machine::get_vmctx_reg(),
                         self.vmoffsets.vmctx_builtin_function(
-                            if self.module.local_memory_index(memory_index).is_some() {
-                                VMBuiltinFunctionIndex::get_memory32_grow_index()
-                            } else {
-                                VMBuiltinFunctionIndex::get_imported_memory32_grow_index()
+                            match (
+                                self.module.local_memory_index(memory_index).is_some(),
+                                memory64,
+                            ) {
+                                (true, false) => VMBuiltinFunctionIndex::get_memory32_grow_index(),
+                                (false, false) => {
+                                    VMBuiltinFunctionIndex::get_imported_memory32_grow_index()
+                                }
+                                (true, true) => VMBuiltinFunctionIndex::get_memory64_grow_index(),
+                                (false, true) => {
+                                    VMBuiltinFunctionIndex::get_imported_memory64_grow_index()
+                                }
                             },
                         ) as i32,
                     ),
@@ -5750,8 +7035,8 @@ impl<'a> FuncGen<'a> {
                         .chain(iter::once(Location::Imm32(memory_index.index() as u32))),
                 )?;
 
-                self.machine
-                    .release_locations_only_stack(&mut self.assembler, &[param_pages]);
+                self.assembler.emit_label(grown);
+                self.machine.release_locations_only_stack(&[param_pages]);
 
                 let ret =
                     self.machine
@@ -6172,107 +7457,15 @@ impl<'a> FuncGen<'a> {
                 })?;
             }
             Operator::Unreachable => {
-                let offset = self.assembler.get_offset().0;
                 self.emit_trap(TrapCode::UnreachableCodeReached);
-                self.mark_instruction_address_end(offset);
                 self.unreachable_depth = 1;
             }
             Operator::Return => {
-                let frame = &self.control_stack[0];
-                if !frame.returns.is_empty() {
-                    if frame.returns.len() != 1 {
-                        return Err(CodegenError {
-                            message: "Return: incorrect frame.returns".to_string(),
-                        });
-                    }
-                    let first_return = frame.returns[0];
-                    let loc = *self.value_stack.last().unwrap();
-                    if first_return.is_float() {
-                        let fp = self.fp_stack.peek1()?;
-                        if self.assembler.arch_supports_canonicalize_nan()
-                            && self.config.enable_nan_canonicalization
-                            && fp.canonicalization.is_some()
-                        {
-                            self.canonicalize_nan(
-                                match first_return {
-                                    WpType::F32 => Size::S32,
-                                    WpType::F64 => Size::S64,
-                                    _ => unreachable!(),
-                                },
-                                loc,
-                                Location::GPR(GPR::RAX),
-                            );
-                        } else {
-                            self.emit_relaxed_binop(
-                                Assembler::emit_mov,
-                                Size::S64,
-                                loc,
-                                Location::GPR(GPR::RAX),
-                            );
-                        }
-                    } else {
-                        self.emit_relaxed_binop(
-                            Assembler::emit_mov,
-                            Size::S64,
-                            loc,
-                            Location::GPR(GPR::RAX),
-                        );
-                    }
-                }
-                let frame = &self.control_stack[0];
-                let released = &self.value_stack[frame.value_stack_depth..];
-                self.machine
-                    .release_locations_keep_state(&mut self.assembler, released);
-                self.assembler.emit_jmp(Condition::None, frame.br_label);
+                self.emit_br(0);
                 self.unreachable_depth = 1;
             }
             Operator::Br { relative_depth } => {
-                let frame =
-                    &self.control_stack[self.control_stack.len() - 1 - (relative_depth as usize)];
-                if !frame.loop_like && !frame.returns.is_empty() {
-                    if frame.returns.len() != 1 {
-                        return Err(CodegenError {
-                            message: "Br: incorrect frame.returns".to_string(),
-                        });
-                    }
-                    let first_return = frame.returns[0];
-                    let loc = *self.value_stack.last().unwrap();
-
-                    if first_return.is_float() {
-                        let fp = self.fp_stack.peek1()?;
-                        if self.assembler.arch_supports_canonicalize_nan()
-                            && self.config.enable_nan_canonicalization
-                            && fp.canonicalization.is_some()
-                        {
-                            self.canonicalize_nan(
-                                match first_return {
-                                    WpType::F32 => Size::S32,
-                                    WpType::F64 => Size::S64,
-                                    _ => unreachable!(),
-                                },
-                                loc,
-                                Location::GPR(GPR::RAX),
-                            );
-                        } else {
-                            self.emit_relaxed_binop(
-                                Assembler::emit_mov,
-                                Size::S64,
-                                loc,
-                                Location::GPR(GPR::RAX),
-                            );
-                        }
-                    } else {
-                        self.assembler
-                            .emit_mov(Size::S64, loc, Location::GPR(GPR::RAX));
-                    }
-                }
-                let frame =
-                    &self.control_stack[self.control_stack.len() - 1 - (relative_depth as usize)];
-
-                let released = &self.value_stack[frame.value_stack_depth..];
-                self.machine
-                    .release_locations_keep_state(&mut self.assembler, released);
-                self.assembler.emit_jmp(Condition::None, frame.br_label);
+                self.emit_br(self.control_stack.len() - 1 - (relative_depth as usize));
                 self.unreachable_depth = 1;
             }
             Operator::BrIf { relative_depth } => {
@@ -6281,51 +7474,7 @@ impl<'a> FuncGen<'a> {
                 self.emit_relaxed_binop(Assembler::emit_cmp, Size::S32, Location::Imm32(0), cond);
                 self.assembler.emit_jmp(Condition::Equal, after);
 
-                let frame =
-                    &self.control_stack[self.control_stack.len() - 1 - (relative_depth as usize)];
-                if !frame.loop_like && !frame.returns.is_empty() {
-                    if frame.returns.len() != 1 {
-                        return Err(CodegenError {
-                            message: "BrIf: incorrect frame.returns".to_string(),
-                        });
-                    }
-
-                    let first_return = frame.returns[0];
-                    let loc = *self.value_stack.last().unwrap();
-                    if first_return.is_float() {
-                        let fp = self.fp_stack.peek1()?;
-                        if self.assembler.arch_supports_canonicalize_nan()
-                            && self.config.enable_nan_canonicalization
-                            && fp.canonicalization.is_some()
-                        {
-                            self.canonicalize_nan(
-                                match first_return {
-                                    WpType::F32 => Size::S32,
-                                    WpType::F64 => Size::S64,
-                                    _ => unreachable!(),
-                                },
-                                loc,
-                                Location::GPR(GPR::RAX),
-                            );
-                        } else {
-                            self.emit_relaxed_binop(
-                                Assembler::emit_mov,
-                                Size::S64,
-                                loc,
-                                Location::GPR(GPR::RAX),
-                            );
-                        }
-                    } else {
-                        self.assembler
-                            .emit_mov(Size::S64, loc, Location::GPR(GPR::RAX));
-                    }
-                }
-                let frame =
-                    &self.control_stack[self.control_stack.len() - 1 - (relative_depth as usize)];
-                let released = &self.value_stack[frame.value_stack_depth..];
-                self.machine
-                    .release_locations_keep_state(&mut self.assembler, released);
-                self.assembler.emit_jmp(Condition::None, frame.br_label);
+                self.emit_br(self.control_stack.len() - 1 - (relative_depth as usize));
 
                 self.assembler.emit_label(after);
             }
@@ -6368,104 +7517,10 @@ impl<'a> FuncGen<'a> {
                     let label = self.assembler.get_label();
                     self.assembler.emit_label(label);
                     table.push(label);
-                    let frame =
-                        &self.control_stack[self.control_stack.len() - 1 - (*target as usize)];
-                    if !frame.loop_like && !frame.returns.is_empty() {
-                        if frame.returns.len() != 1 {
-                            return Err(CodegenError {
-                                message: format!(
-                                    "BrTable: incorrect frame.returns for {:?}",
-                                    target
-                                ),
-                            });
-                        }
-
-                        let first_return = frame.returns[0];
-                        let loc = *self.value_stack.last().unwrap();
-                        if first_return.is_float() {
-                            let fp = self.fp_stack.peek1()?;
-                            if self.assembler.arch_supports_canonicalize_nan()
-                                && self.config.enable_nan_canonicalization
-                                && fp.canonicalization.is_some()
-                            {
-                                self.canonicalize_nan(
-                                    match first_return {
-                                        WpType::F32 => Size::S32,
-                                        WpType::F64 => Size::S64,
-                                        _ => unreachable!(),
-                                    },
-                                    loc,
-                                    Location::GPR(GPR::RAX),
-                                );
-                            } else {
-                                self.emit_relaxed_binop(
-                                    Assembler::emit_mov,
-                                    Size::S64,
-                                    loc,
-                                    Location::GPR(GPR::RAX),
-                                );
-                            }
-                        } else {
-                            self.assembler
-                                .emit_mov(Size::S64, loc, Location::GPR(GPR::RAX));
-                        }
-                    }
-                    let frame =
-                        &self.control_stack[self.control_stack.len() - 1 - (*target as usize)];
-                    let released = &self.value_stack[frame.value_stack_depth..];
-                    self.machine
-                        .release_locations_keep_state(&mut self.assembler, released);
-                    self.assembler.emit_jmp(Condition::None, frame.br_label);
+                    self.emit_br(self.control_stack.len() - 1 - (*target as usize));
                 }
                 self.assembler.emit_label(default_br);
-
-                {
-                    let frame = &self.control_stack
-                        [self.control_stack.len() - 1 - (default_target as usize)];
-                    if !frame.loop_like && !frame.returns.is_empty() {
-                        if frame.returns.len() != 1 {
-                            return Err(CodegenError {
-                                message: "BrTable: incorrect frame.returns".to_string(),
-                            });
-                        }
-
-                        let first_return = frame.returns[0];
-                        let loc = *self.value_stack.last().unwrap();
-                        if first_return.is_float() {
-                            let fp = self.fp_stack.peek1()?;
-                            if self.assembler.arch_supports_canonicalize_nan()
-                                && self.config.enable_nan_canonicalization
-                                && fp.canonicalization.is_some()
-                            {
-                                self.canonicalize_nan(
-                                    match first_return {
-                                        WpType::F32 => Size::S32,
-                                        WpType::F64 => Size::S64,
-                                        _ => unreachable!(),
-                                    },
-                                    loc,
-                                    Location::GPR(GPR::RAX),
-                                );
-                            } else {
-                                self.emit_relaxed_binop(
-                                    Assembler::emit_mov,
-                                    Size::S64,
-                                    loc,
-                                    Location::GPR(GPR::RAX),
-                                );
-                            }
-                        } else {
-                            self.assembler
-                                .emit_mov(Size::S64, loc, Location::GPR(GPR::RAX));
-                        }
-                    }
-                    let frame = &self.control_stack
-                        [self.control_stack.len() - 1 - (default_target as usize)];
-                    let released = &self.value_stack[frame.value_stack_depth..];
-                    self.machine
-                        .release_locations_keep_state(&mut self.assembler, released);
-                    self.assembler.emit_jmp(Condition::None, frame.br_label);
-                }
+                self.emit_br(self.control_stack.len() - 1 - (default_target as usize));
 
                 self.assembler.emit_label(table_label);
                 for x in table {
@@ -6482,109 +7537,12 @@ impl<'a> FuncGen<'a> {
                 }
             }
             Operator::End => {
-                let frame = self.control_stack.pop().unwrap();
-
-                if !was_unreachable && !frame.returns.is_empty() {
-                    let loc = *self.value_stack.last().unwrap();
-                    if frame.returns[0].is_float() {
-                        let fp = self.fp_stack.peek1()?;
-                        if self.assembler.arch_supports_canonicalize_nan()
-                            && self.config.enable_nan_canonicalization
-                            && fp.canonicalization.is_some()
-                        {
-                            self.canonicalize_nan(
-                                match frame.returns[0] {
-                                    WpType::F32 => Size::S32,
-                                    WpType::F64 => Size::S64,
-                                    _ => unreachable!(),
-                                },
-                                loc,
-                                Location::GPR(GPR::RAX),
-                            );
-                        } else {
-                            self.emit_relaxed_binop(
-                                Assembler::emit_mov,
-                                Size::S64,
-                                loc,
-                                Location::GPR(GPR::RAX),
-                            );
-                        }
-                    } else {
-                        self.emit_relaxed_binop(
-                            Assembler::emit_mov,
-                            Size::S64,
-                            loc,
-                            Location::GPR(GPR::RAX),
-                        );
-                    }
-                }
-
-                if self.control_stack.is_empty() {
-                    self.assembler.emit_label(frame.br_label);
-                    self.update_max_stack_depth();
-                    self.emit_function_stack_check(false);
-                    let local_count = self.local_count();
-                    self.machine.finalize_locals(
-                        &mut self.assembler,
-                        self.calling_convention,
-                        local_count,
-                    );
-                    self.assembler.emit_mov(
-                        Size::S64,
-                        Location::GPR(GPR::RBP),
-                        Location::GPR(GPR::RSP),
-                    );
-                    self.assembler.emit_pop(Size::S64, Location::GPR(GPR::RBP));
-
-                    // Make a copy of the return value in XMM0, as required by the SysV CC.
-                    match self.signature.results() {
-                        [x] if *x == Type::F32 || *x == Type::F64 => {
-                            self.assembler.emit_mov(
-                                Size::S64,
-                                Location::GPR(GPR::RAX),
-                                Location::XMM(XMM::XMM0),
-                            );
-                        }
-                        _ => {}
-                    }
-                    self.assembler.emit_ret();
-                } else {
-                    let released = &self.value_stack[frame.value_stack_depth..];
-                    self.machine
-                        .release_locations(&mut self.assembler, released);
-                    self.update_max_stack_depth();
-                    self.value_stack.truncate(frame.value_stack_depth);
-                    self.fp_stack.truncate(frame.fp_stack_depth);
-
-                    if !frame.loop_like {
-                        self.assembler.emit_label(frame.br_label);
-                    }
-
-                    if let IfElseState::If(label) = frame.if_else {
-                        self.assembler.emit_label(label);
-                    }
-
-                    if !frame.returns.is_empty() {
-                        if frame.returns.len() != 1 {
-                            return Err(CodegenError {
-                                message: "End: incorrect frame.returns".to_string(),
-                            });
-                        }
-                        let loc = self.machine.acquire_locations(
-                            &mut self.assembler,
-                            &[(frame.returns[0])],
-                            false,
-                        )[0];
-                        self.assembler
-                            .emit_mov(Size::S64, Location::GPR(GPR::RAX), loc);
-                        self.value_stack.push(loc);
-                        if frame.returns[0].is_float() {
-                            self.fp_stack
-                                .push(FloatValue::new(self.value_stack.len() - 1));
-                            // we already canonicalized at the `Br*` instruction or here previously.
-                        }
-                    }
+                let mut was_unreachable = was_unreachable;
+                if self.control_stack.last().unwrap().try_frame.is_some() {
+                    self.emit_try_end(was_unreachable, self.control_stack.len() - 1)?;
+                    was_unreachable = true;
                 }
+                self.emit_end(was_unreachable)?;
             }
             Operator::AtomicFence { flags: _ } => {
                 // Fence is a nop.
@@ -6595,6 +7553,50 @@ impl<'a> FuncGen<'a> {
                 // it would lead to data races that weren't present in the
                 // original source language.
             }
+            Operator::MemoryAtomicWait32 { ref memarg } => self.emit_atomic_wait(memarg, 4)?,
+            Operator::MemoryAtomicWait64 { ref memarg } => self.emit_atomic_wait(memarg, 8)?,
+            Operator::MemoryAtomicNotify { ref memarg } => {
+                let count = self.value_stack.pop().unwrap();
+                let target = self.value_stack.pop().unwrap();
+
+                let addr =
+                    self.machine
+                        .acquire_locations(&mut self.assembler, &[(WpType::I64)], false)[0];
+                self.emit_memory_op(target, memarg, true, 4, |this, host_addr| {
+                    this.assembler
+                        .emit_mov(Size::S64, Location::GPR(host_addr), addr);
+                    Ok(())
+                })?;
+
+                self.machine
+                    .release_locations_only_regs(&[target, count, addr]);
+                self.assembler.emit_mov(
+                    Size::S64,
+                    Location::Memory(
+                        Machine::get_vmctx_reg(),
+                        self.vmoffsets.vmctx_builtin_function(
+                            VMBuiltinFunctionIndex::get_memory_atomic_notify_index(),
+                        ) as i32,
+                    ),
+                    Location::GPR(GPR::RAX),
+                );
+                self.emit_call_native(
+                    |this| {
+                        this.assembler.emit_call_register(GPR::RAX);
+                    },
+                    // [vmctx, addr, count] -> i32
+                    [addr, count].iter().cloned(),
+                )?;
+                self.machine
+                    .release_locations_only_stack(&[target, count, addr]);
+
+                let ret =
+                    self.machine
+                        .acquire_locations(&mut self.assembler, &[(WpType::I32)], false)[0];
+                self.value_stack.push(ret);
+                self.assembler
+                    .emit_mov(Size::S32, Location::GPR(GPR::RAX), ret);
+            }
             Operator::I32AtomicLoad { ref memarg } => {
                 let target = self.pop_value_released();
                 let ret =
@@ -6839,7 +7841,7 @@ impl<'a> FuncGen<'a> {
                         .acquire_locations(&mut self.assembler, &[(WpType::I32)], false)[0];
                 self.value_stack.push(ret);
 
-                let value = self.machine.acquire_temp_gpr().unwrap();
+                let value = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                 self.assembler
                     .emit_mov(Size::S32, loc, Location::GPR(value));
                 self.emit_memory_op(target, memarg, true, 4, |this, addr| {
@@ -6852,7 +7854,7 @@ impl<'a> FuncGen<'a> {
                 })?;
                 self.assembler
                     .emit_mov(Size::S32, Location::GPR(value), ret);
-                self.machine.release_temp_gpr(value);
+                self.machine.restore_stolen_gpr(&mut self.assembler, value);
             }
             Operator::I64AtomicRmwAdd { ref memarg } => {
                 let loc = self.pop_value_released();
@@ -6862,7 +7864,7 @@ impl<'a> FuncGen<'a> {
                         .acquire_locations(&mut self.assembler, &[(WpType::I64)], false)[0];
                 self.value_stack.push(ret);
 
-                let value = self.machine.acquire_temp_gpr().unwrap();
+                let value = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                 self.assembler
                     .emit_mov(Size::S64, loc, Location::GPR(value));
                 self.emit_memory_op(target, memarg, true, 8, |this, addr| {
@@ -6875,7 +7877,7 @@ impl<'a> FuncGen<'a> {
                 })?;
                 self.assembler
                     .emit_mov(Size::S64, Location::GPR(value), ret);
-                self.machine.release_temp_gpr(value);
+                self.machine.restore_stolen_gpr(&mut self.assembler, value);
             }
             Operator::I32AtomicRmw8AddU { ref memarg } => {
                 let loc = self.pop_value_released();
@@ -6885,7 +7887,7 @@ impl<'a> FuncGen<'a> {
                         .acquire_locations(&mut self.assembler, &[(WpType::I32)], false)[0];
                 self.value_stack.push(ret);
 
-                let value = self.machine.acquire_temp_gpr().unwrap();
+                let value = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                 self.assembler
                     .emit_movzx(Size::S8, loc, Size::S32, Location::GPR(value));
                 self.emit_memory_op(target, memarg, true, 1, |this, addr| {
@@ -6898,7 +7900,7 @@ impl<'a> FuncGen<'a> {
                 })?;
                 self.assembler
                     .emit_mov(Size::S32, Location::GPR(value), ret);
-                self.machine.release_temp_gpr(value);
+                self.machine.restore_stolen_gpr(&mut self.assembler, value);
             }
             Operator::I32AtomicRmw16AddU { ref memarg } => {
                 let loc = self.pop_value_released();
@@ -6908,7 +7910,7 @@ impl<'a> FuncGen<'a> {
                         .acquire_locations(&mut self.assembler, &[(WpType::I32)], false)[0];
                 self.value_stack.push(ret);
 
-                let value = self.machine.acquire_temp_gpr().unwrap();
+                let value = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                 self.assembler
                     .emit_movzx(Size::S16, loc, Size::S32, Location::GPR(value));
                 self.emit_memory_op(target, memarg, true, 2, |this, addr| {
@@ -6921,7 +7923,7 @@ impl<'a> FuncGen<'a> {
                 })?;
                 self.assembler
                     .emit_mov(Size::S32, Location::GPR(value), ret);
-                self.machine.release_temp_gpr(value);
+                self.machine.restore_stolen_gpr(&mut self.assembler, value);
             }
             Operator::I64AtomicRmw8AddU { ref memarg } => {
                 let loc = self.pop_value_released();
@@ -6931,7 +7933,7 @@ impl<'a> FuncGen<'a> {
                         .acquire_locations(&mut self.assembler, &[(WpType::I64)], false)[0];
                 self.value_stack.push(ret);
 
-                let value = self.machine.acquire_temp_gpr().unwrap();
+                let value = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                 self.assembler
                     .emit_movzx(Size::S8, loc, Size::S64, Location::GPR(value));
                 self.emit_memory_op(target, memarg, true, 1, |this, addr| {
@@ -6944,7 +7946,7 @@ impl<'a> FuncGen<'a> {
                 })?;
                 self.assembler
                     .emit_mov(Size::S64, Location::GPR(value), ret);
-                self.machine.release_temp_gpr(value);
+                self.machine.restore_stolen_gpr(&mut self.assembler, value);
             }
             Operator::I64AtomicRmw16AddU { ref memarg } => {
                 let loc = self.pop_value_released();
@@ -6954,7 +7956,7 @@ impl<'a> FuncGen<'a> {
                         .acquire_locations(&mut self.assembler, &[(WpType::I64)], false)[0];
                 self.value_stack.push(ret);
 
-                let value = self.machine.acquire_temp_gpr().unwrap();
+                let value = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                 self.assembler
                     .emit_movzx(Size::S16, loc, Size::S64, Location::GPR(value));
                 self.emit_memory_op(target, memarg, true, 2, |this, addr| {
@@ -6967,7 +7969,7 @@ impl<'a> FuncGen<'a> {
                 })?;
                 self.assembler
                     .emit_mov(Size::S64, Location::GPR(value), ret);
-                self.machine.release_temp_gpr(value);
+                self.machine.restore_stolen_gpr(&mut self.assembler, value);
             }
             Operator::I64AtomicRmw32AddU { ref memarg } => {
                 let loc = self.pop_value_released();
@@ -6977,7 +7979,7 @@ impl<'a> FuncGen<'a> {
                         .acquire_locations(&mut self.assembler, &[(WpType::I64)], false)[0];
                 self.value_stack.push(ret);
 
-                let value = self.machine.acquire_temp_gpr().unwrap();
+                let value = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                 self.assembler
                     .emit_mov(Size::S32, loc, Location::GPR(value));
                 self.emit_memory_op(target, memarg, true, 4, |this, addr| {
@@ -6990,7 +7992,7 @@ impl<'a> FuncGen<'a> {
                 })?;
                 self.assembler
                     .emit_mov(Size::S64, Location::GPR(value), ret);
-                self.machine.release_temp_gpr(value);
+                self.machine.restore_stolen_gpr(&mut self.assembler, value);
             }
             Operator::I32AtomicRmwSub { ref memarg } => {
                 let loc = self.pop_value_released();
@@ -7000,7 +8002,7 @@ impl<'a> FuncGen<'a> {
                         .acquire_locations(&mut self.assembler, &[(WpType::I32)], false)[0];
                 self.value_stack.push(ret);
 
-                let value = self.machine.acquire_temp_gpr().unwrap();
+                let value = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                 self.assembler
                     .emit_mov(Size::S32, loc, Location::GPR(value));
                 self.assembler.emit_neg(Size::S32, Location::GPR(value));
@@ -7014,7 +8016,7 @@ impl<'a> FuncGen<'a> {
                 })?;
                 self.assembler
                     .emit_mov(Size::S32, Location::GPR(value), ret);
-                self.machine.release_temp_gpr(value);
+                self.machine.restore_stolen_gpr(&mut self.assembler, value);
             }
             Operator::I64AtomicRmwSub { ref memarg } => {
                 let loc = self.pop_value_released();
@@ -7024,7 +8026,7 @@ impl<'a> FuncGen<'a> {
                         .acquire_locations(&mut self.assembler, &[(WpType::I64)], false)[0];
                 self.value_stack.push(ret);
 
-                let value = self.machine.acquire_temp_gpr().unwrap();
+                let value = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                 self.assembler
                     .emit_mov(Size::S64, loc, Location::GPR(value));
                 self.assembler.emit_neg(Size::S64, Location::GPR(value));
@@ -7038,7 +8040,7 @@ impl<'a> FuncGen<'a> {
                 })?;
                 self.assembler
                     .emit_mov(Size::S64, Location::GPR(value), ret);
-                self.machine.release_temp_gpr(value);
+                self.machine.restore_stolen_gpr(&mut self.assembler, value);
             }
             Operator::I32AtomicRmw8SubU { ref memarg } => {
                 let loc = self.pop_value_released();
@@ -7048,7 +8050,7 @@ impl<'a> FuncGen<'a> {
                         .acquire_locations(&mut self.assembler, &[(WpType::I32)], false)[0];
                 self.value_stack.push(ret);
 
-                let value = self.machine.acquire_temp_gpr().unwrap();
+                let value = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                 self.assembler
                     .emit_movzx(Size::S8, loc, Size::S32, Location::GPR(value));
                 self.assembler.emit_neg(Size::S8, Location::GPR(value));
@@ -7062,7 +8064,7 @@ impl<'a> FuncGen<'a> {
                 })?;
                 self.assembler
                     .emit_mov(Size::S32, Location::GPR(value), ret);
-                self.machine.release_temp_gpr(value);
+                self.machine.restore_stolen_gpr(&mut self.assembler, value);
             }
             Operator::I32AtomicRmw16SubU { ref memarg } => {
                 let loc = self.pop_value_released();
@@ -7072,7 +8074,7 @@ impl<'a> FuncGen<'a> {
                         .acquire_locations(&mut self.assembler, &[(WpType::I32)], false)[0];
                 self.value_stack.push(ret);
 
-                let value = self.machine.acquire_temp_gpr().unwrap();
+                let value = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                 self.assembler
                     .emit_movzx(Size::S16, loc, Size::S32, Location::GPR(value));
                 self.assembler.emit_neg(Size::S16, Location::GPR(value));
@@ -7086,7 +8088,7 @@ impl<'a> FuncGen<'a> {
                 })?;
                 self.assembler
                     .emit_mov(Size::S32, Location::GPR(value), ret);
-                self.machine.release_temp_gpr(value);
+                self.machine.restore_stolen_gpr(&mut self.assembler, value);
             }
             Operator::I64AtomicRmw8SubU { ref memarg } => {
                 let loc = self.pop_value_released();
@@ -7096,7 +8098,7 @@ impl<'a> FuncGen<'a> {
                         .acquire_locations(&mut self.assembler, &[(WpType::I64)], false)[0];
                 self.value_stack.push(ret);
 
-                let value = self.machine.acquire_temp_gpr().unwrap();
+                let value = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                 self.assembler
                     .emit_movzx(Size::S8, loc, Size::S64, Location::GPR(value));
                 self.assembler.emit_neg(Size::S8, Location::GPR(value));
@@ -7110,7 +8112,7 @@ impl<'a> FuncGen<'a> {
                 })?;
                 self.assembler
                     .emit_mov(Size::S64, Location::GPR(value), ret);
-                self.machine.release_temp_gpr(value);
+                self.machine.restore_stolen_gpr(&mut self.assembler, value);
             }
             Operator::I64AtomicRmw16SubU { ref memarg } => {
                 let loc = self.pop_value_released();
@@ -7120,7 +8122,7 @@ impl<'a> FuncGen<'a> {
                         .acquire_locations(&mut self.assembler, &[(WpType::I64)], false)[0];
                 self.value_stack.push(ret);
 
-                let value = self.machine.acquire_temp_gpr().unwrap();
+                let value = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                 self.assembler
                     .emit_movzx(Size::S16, loc, Size::S64, Location::GPR(value));
                 self.assembler.emit_neg(Size::S16, Location::GPR(value));
@@ -7134,7 +8136,7 @@ impl<'a> FuncGen<'a> {
                 })?;
                 self.assembler
                     .emit_mov(Size::S64, Location::GPR(value), ret);
-                self.machine.release_temp_gpr(value);
+                self.machine.restore_stolen_gpr(&mut self.assembler, value);
             }
             Operator::I64AtomicRmw32SubU { ref memarg } => {
                 let loc = self.pop_value_released();
@@ -7144,7 +8146,7 @@ impl<'a> FuncGen<'a> {
                         .acquire_locations(&mut self.assembler, &[(WpType::I64)], false)[0];
                 self.value_stack.push(ret);
 
-                let value = self.machine.acquire_temp_gpr().unwrap();
+                let value = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                 self.assembler
                     .emit_mov(Size::S32, loc, Location::GPR(value));
                 self.assembler.emit_neg(Size::S32, Location::GPR(value));
@@ -7158,7 +8160,7 @@ impl<'a> FuncGen<'a> {
                 })?;
                 self.assembler
                     .emit_mov(Size::S64, Location::GPR(value), ret);
-                self.machine.release_temp_gpr(value);
+                self.machine.restore_stolen_gpr(&mut self.assembler, value);
             }
             Operator::I32AtomicRmwAnd { ref memarg } => {
                 let loc = self.pop_value_released();
@@ -7630,7 +8632,7 @@ impl<'a> FuncGen<'a> {
                         .acquire_locations(&mut self.assembler, &[(WpType::I32)], false)[0];
                 self.value_stack.push(ret);
 
-                let value = self.machine.acquire_temp_gpr().unwrap();
+                let value = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                 self.assembler
                     .emit_mov(Size::S32, loc, Location::GPR(value));
                 self.emit_memory_op(target, memarg, true, 4, |this, addr| {
@@ -7643,7 +8645,7 @@ impl<'a> FuncGen<'a> {
                 })?;
                 self.assembler
                     .emit_mov(Size::S32, Location::GPR(value), ret);
-                self.machine.release_temp_gpr(value);
+                self.machine.restore_stolen_gpr(&mut self.assembler, value);
             }
             Operator::I64AtomicRmwXchg { ref memarg } => {
                 let loc = self.pop_value_released();
@@ -7653,7 +8655,7 @@ impl<'a> FuncGen<'a> {
                         .acquire_locations(&mut self.assembler, &[(WpType::I64)], false)[0];
                 self.value_stack.push(ret);
 
-                let value = self.machine.acquire_temp_gpr().unwrap();
+                let value = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                 self.assembler
                     .emit_mov(Size::S64, loc, Location::GPR(value));
                 self.emit_memory_op(target, memarg, true, 8, |this, addr| {
@@ -7666,7 +8668,7 @@ impl<'a> FuncGen<'a> {
                 })?;
                 self.assembler
                     .emit_mov(Size::S64, Location::GPR(value), ret);
-                self.machine.release_temp_gpr(value);
+                self.machine.restore_stolen_gpr(&mut self.assembler, value);
             }
             Operator::I32AtomicRmw8XchgU { ref memarg } => {
                 let loc = self.pop_value_released();
@@ -7676,7 +8678,7 @@ impl<'a> FuncGen<'a> {
                         .acquire_locations(&mut self.assembler, &[(WpType::I32)], false)[0];
                 self.value_stack.push(ret);
 
-                let value = self.machine.acquire_temp_gpr().unwrap();
+                let value = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                 self.assembler
                     .emit_movzx(Size::S8, loc, Size::S32, Location::GPR(value));
                 self.emit_memory_op(target, memarg, true, 1, |this, addr| {
@@ -7689,7 +8691,7 @@ impl<'a> FuncGen<'a> {
                 })?;
                 self.assembler
                     .emit_mov(Size::S32, Location::GPR(value), ret);
-                self.machine.release_temp_gpr(value);
+                self.machine.restore_stolen_gpr(&mut self.assembler, value);
             }
             Operator::I32AtomicRmw16XchgU { ref memarg } => {
                 let loc = self.pop_value_released();
@@ -7699,7 +8701,7 @@ impl<'a> FuncGen<'a> {
                         .acquire_locations(&mut self.assembler, &[(WpType::I32)], false)[0];
                 self.value_stack.push(ret);
 
-                let value = self.machine.acquire_temp_gpr().unwrap();
+                let value = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                 self.assembler
                     .emit_movzx(Size::S16, loc, Size::S32, Location::GPR(value));
                 self.emit_memory_op(target, memarg, true, 2, |this, addr| {
@@ -7712,7 +8714,7 @@ impl<'a> FuncGen<'a> {
                 })?;
                 self.assembler
                     .emit_mov(Size::S32, Location::GPR(value), ret);
-                self.machine.release_temp_gpr(value);
+                self.machine.restore_stolen_gpr(&mut self.assembler, value);
             }
             Operator::I64AtomicRmw8XchgU { ref memarg } => {
                 let loc = self.pop_value_released();
@@ -7722,7 +8724,7 @@ impl<'a> FuncGen<'a> {
                         .acquire_locations(&mut self.assembler, &[(WpType::I64)], false)[0];
                 self.value_stack.push(ret);
 
-                let value = self.machine.acquire_temp_gpr().unwrap();
+                let value = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                 self.assembler
                     .emit_movzx(Size::S8, loc, Size::S64, Location::GPR(value));
                 self.emit_memory_op(target, memarg, true, 1, |this, addr| {
@@ -7735,7 +8737,7 @@ impl<'a> FuncGen<'a> {
                 })?;
                 self.assembler
                     .emit_mov(Size::S64, Location::GPR(value), ret);
-                self.machine.release_temp_gpr(value);
+                self.machine.restore_stolen_gpr(&mut self.assembler, value);
             }
             Operator::I64AtomicRmw16XchgU { ref memarg } => {
                 let loc = self.pop_value_released();
@@ -7745,7 +8747,7 @@ impl<'a> FuncGen<'a> {
                         .acquire_locations(&mut self.assembler, &[(WpType::I64)], false)[0];
                 self.value_stack.push(ret);
 
-                let value = self.machine.acquire_temp_gpr().unwrap();
+                let value = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                 self.assembler
                     .emit_movzx(Size::S16, loc, Size::S64, Location::GPR(value));
                 self.emit_memory_op(target, memarg, true, 2, |this, addr| {
@@ -7758,7 +8760,7 @@ impl<'a> FuncGen<'a> {
                 })?;
                 self.assembler
                     .emit_mov(Size::S64, Location::GPR(value), ret);
-                self.machine.release_temp_gpr(value);
+                self.machine.restore_stolen_gpr(&mut self.assembler, value);
             }
             Operator::I64AtomicRmw32XchgU { ref memarg } => {
                 let loc = self.pop_value_released();
@@ -7768,7 +8770,7 @@ impl<'a> FuncGen<'a> {
                         .acquire_locations(&mut self.assembler, &[(WpType::I64)], false)[0];
                 self.value_stack.push(ret);
 
-                let value = self.machine.acquire_temp_gpr().unwrap();
+                let value = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
                 self.assembler
                     .emit_mov(Size::S32, loc, Location::GPR(value));
                 self.emit_memory_op(target, memarg, true, 4, |this, addr| {
@@ -7781,7 +8783,7 @@ impl<'a> FuncGen<'a> {
                 })?;
                 self.assembler
                     .emit_mov(Size::S64, Location::GPR(value), ret);
-                self.machine.release_temp_gpr(value);
+                self.machine.restore_stolen_gpr(&mut self.assembler, value);
             }
             Operator::I32AtomicRmwCmpxchg { ref memarg } => {
                 let new = self.pop_value_released();
@@ -7819,7 +8821,8 @@ impl<'a> FuncGen<'a> {
                     Ok(())
                 })?;
                 self.assembler.emit_pop(Size::S64, Location::GPR(value));
-                self.machine.release_temp_gpr(compare);
+                self.machine
+                    .restore_stolen_gpr(&mut self.assembler, compare);
             }
             Operator::I64AtomicRmwCmpxchg { ref memarg } => {
                 let new = self.pop_value_released();
@@ -7857,7 +8860,8 @@ impl<'a> FuncGen<'a> {
                     Ok(())
                 })?;
                 self.assembler.emit_pop(Size::S64, Location::GPR(value));
-                self.machine.release_temp_gpr(compare);
+                self.machine
+                    .restore_stolen_gpr(&mut self.assembler, compare);
             }
             Operator::I32AtomicRmw8CmpxchgU { ref memarg } => {
                 let new = self.pop_value_released();
@@ -7895,7 +8899,8 @@ impl<'a> FuncGen<'a> {
                     Ok(())
                 })?;
                 self.assembler.emit_pop(Size::S64, Location::GPR(value));
-                self.machine.release_temp_gpr(compare);
+                self.machine
+                    .restore_stolen_gpr(&mut self.assembler, compare);
             }
             Operator::I32AtomicRmw16CmpxchgU { ref memarg } => {
                 let new = self.pop_value_released();
@@ -7933,7 +8938,8 @@ impl<'a> FuncGen<'a> {
                     Ok(())
                 })?;
                 self.assembler.emit_pop(Size::S64, Location::GPR(value));
-                self.machine.release_temp_gpr(compare);
+                self.machine
+                    .restore_stolen_gpr(&mut self.assembler, compare);
             }
             Operator::I64AtomicRmw8CmpxchgU { ref memarg } => {
                 let new = self.pop_value_released();
@@ -7971,7 +8977,8 @@ impl<'a> FuncGen<'a> {
                     Ok(())
                 })?;
                 self.assembler.emit_pop(Size::S64, Location::GPR(value));
-                self.machine.release_temp_gpr(compare);
+                self.machine
+                    .restore_stolen_gpr(&mut self.assembler, compare);
             }
             Operator::I64AtomicRmw16CmpxchgU { ref memarg } => {
                 let new = self.pop_value_released();
@@ -8009,7 +9016,8 @@ impl<'a> FuncGen<'a> {
                     Ok(())
                 })?;
                 self.assembler.emit_pop(Size::S64, Location::GPR(value));
-                self.machine.release_temp_gpr(compare);
+                self.machine
+                    .restore_stolen_gpr(&mut self.assembler, compare);
             }
             Operator::I64AtomicRmw32CmpxchgU { ref memarg } => {
                 let new = self.pop_value_released();
@@ -8047,7 +9055,8 @@ impl<'a> FuncGen<'a> {
                     Ok(())
                 })?;
                 self.assembler.emit_pop(Size::S64, Location::GPR(value));
-                self.machine.release_temp_gpr(compare);
+                self.machine
+                    .restore_stolen_gpr(&mut self.assembler, compare);
             }
 
             Operator::RefNull { .. } => {
@@ -8085,6 +9094,22 @@ impl<'a> FuncGen<'a> {
             Operator::RefIsNull => {
                 self.emit_cmpop_i64_dynamic_b(Condition::Equal, Location::Imm64(0))?;
             }
+            Operator::TableSet { table: index }
+                if self.module.tables[TableIndex::new(index as _)].ty == Type::FuncRef =>
+            {
+                // Funcrefs are not reference counted, so they are written in place.
+                let table_index = TableIndex::new(index as _);
+                let value = self.pop_value_released();
+                let index = self.pop_value_released();
+                let elem = self.emit_funcref_table_element_address(table_index, index, &[value]);
+                self.emit_relaxed_binop(
+                    Assembler::emit_mov,
+                    Size::S64,
+                    value,
+                    Location::Memory(elem, 0),
+                );
+                self.machine.restore_stolen_gpr(&mut self.assembler, elem);
+            }
             Operator::TableSet { table: index } => {
                 let table_index = TableIndex::new(index as _);
                 let value = self.value_stack.pop().unwrap();
@@ -8117,8 +9142,24 @@ impl<'a> FuncGen<'a> {
                         .cloned(),
                 )?;
 
-                self.machine
-                    .release_locations_only_stack(&mut self.assembler, &[index, value]);
+                self.machine.release_locations_only_stack(&[index, value]);
+            }
+            Operator::TableGet { table: index }
+                if self.module.tables[TableIndex::new(index as _)].ty == Type::FuncRef =>
+            {
+                let table_index = TableIndex::new(index as _);
+                let index = self.pop_value_released();
+                let ret = self.machine.acquire_locations(
+                    &mut self.assembler,
+                    &[(WpType::FuncRef)],
+                    false,
+                )[0];
+                self.value_stack.push(ret);
+                let elem = self.emit_funcref_table_element_address(table_index, index, &[]);
+                self.assembler
+                    .emit_mov(Size::S64, Location::Memory(elem, 0), Location::GPR(elem));
+                self.assembler.emit_mov(Size::S64, Location::GPR(elem), ret);
+                self.machine.restore_stolen_gpr(&mut self.assembler, elem);
             }
             Operator::TableGet { table: index } => {
                 let table_index = TableIndex::new(index as _);
@@ -8150,8 +9191,7 @@ impl<'a> FuncGen<'a> {
                         .cloned(),
                 )?;
 
-                self.machine
-                    .release_locations_only_stack(&mut self.assembler, &[index]);
+                self.machine.release_locations_only_stack(&[index]);
 
                 let ret = self.machine.acquire_locations(
                     &mut self.assembler,
@@ -8164,36 +9204,41 @@ impl<'a> FuncGen<'a> {
             }
             Operator::TableSize { table: index } => {
                 let table_index = TableIndex::new(index as _);
-
-                self.assembler.emit_mov(
-                    Size::S64,
-                    Location::Memory(
-                        Machine::get_vmctx_reg(),
-                        self.vmoffsets.vmctx_builtin_function(
-                            if self.module.local_table_index(table_index).is_some() {
-                                VMBuiltinFunctionIndex::get_table_size_index()
-                            } else {
-                                VMBuiltinFunctionIndex::get_imported_table_size_index()
-                            },
-                        ) as i32,
-                    ),
-                    Location::GPR(GPR::RAX),
-                );
-
-                self.emit_call_native(
-                    |this| {
-                        this.assembler.emit_call_register(GPR::RAX);
-                    },
-                    // [vmctx, table_index] -> i32
-                    iter::once(Location::Imm32(table_index.index() as u32)),
-                )?;
-
                 let ret =
                     self.machine
                         .acquire_locations(&mut self.assembler, &[(WpType::I32)], false)[0];
                 self.value_stack.push(ret);
-                self.assembler
-                    .emit_mov(Size::S32, Location::GPR(GPR::RAX), ret);
+
+                let len = self.machine.steal_temp_gpr(&mut self.assembler, &[]);
+                if let Some(local_table_index) = self.module.local_table_index(table_index) {
+                    self.assembler.emit_mov(
+                        Size::S32,
+                        Location::Memory(
+                            Machine::get_vmctx_reg(),
+                            self.vmoffsets
+                                .vmctx_vmtable_definition_current_elements(local_table_index)
+                                as i32,
+                        ),
+                        Location::GPR(len),
+                    );
+                } else {
+                    let import_offset = self.vmoffsets.vmctx_vmtable_import(table_index);
+                    self.assembler.emit_mov(
+                        Size::S64,
+                        Location::Memory(Machine::get_vmctx_reg(), import_offset as i32),
+                        Location::GPR(len),
+                    );
+                    self.assembler.emit_mov(
+                        Size::S32,
+                        Location::Memory(
+                            len,
+                            self.vmoffsets.vmtable_definition_current_elements() as _,
+                        ),
+                        Location::GPR(len),
+                    );
+                }
+                self.assembler.emit_mov(Size::S32, Location::GPR(len), ret);
+                self.machine.restore_stolen_gpr(&mut self.assembler, len);
             }
             Operator::TableGrow { table: index } => {
                 let table_index = TableIndex::new(index as _);
@@ -8233,7 +9278,7 @@ impl<'a> FuncGen<'a> {
                 )?;
 
                 self.machine
-                    .release_locations_only_stack(&mut self.assembler, &[init_value, delta]);
+                    .release_locations_only_stack(&[init_value, delta]);
 
                 let ret =
                     self.machine
@@ -8279,8 +9324,7 @@ impl<'a> FuncGen<'a> {
                     .cloned(),
                 )?;
 
-                self.machine
-                    .release_locations_only_stack(&mut self.assembler, &[dest, src, len]);
+                self.machine.release_locations_only_stack(&[dest, src, len]);
             }
 
             Operator::TableFill { table } => {
@@ -8309,8 +9353,7 @@ impl<'a> FuncGen<'a> {
                     [Location::Imm32(table), dest, val, len].iter().cloned(),
                 )?;
 
-                self.machine
-                    .release_locations_only_stack(&mut self.assembler, &[dest, val, len]);
+                self.machine.release_locations_only_stack(&[dest, val, len]);
             }
             Operator::TableInit { segment, table } => {
                 let len = self.value_stack.pop().unwrap();
@@ -8346,8 +9389,7 @@ impl<'a> FuncGen<'a> {
                     .cloned(),
                 )?;
 
-                self.machine
-                    .release_locations_only_stack(&mut self.assembler, &[dest, src, len]);
+                self.machine.release_locations_only_stack(&[dest, src, len]);
             }
             Operator::ElemDrop { segment } => {
                 self.assembler.emit_mov(
@@ -8369,9 +9411,323 @@ impl<'a> FuncGen<'a> {
                     [Location::Imm32(segment)].iter().cloned(),
                 )?;
             }
+            Operator::V128Const { value } => {
+                let ret =
+                    self.machine
+                        .acquire_locations(&mut self.assembler, &[WpType::V128], false)[0];
+                self.value_stack.push(ret);
+                self.emit_v128_const(*value.bytes(), ret);
+            }
+            Operator::V128Load { ref memarg } => {
+                let target = self.pop_value_released();
+                let ret =
+                    self.machine
+                        .acquire_locations(&mut self.assembler, &[WpType::V128], false)[0];
+                self.value_stack.push(ret);
+
+                self.emit_memory_op(target, memarg, false, 16, |this, addr| {
+                    this.emit_move_v128(Location::Memory(addr, 0), ret);
+                    Ok(())
+                })?;
+            }
+            Operator::V128Store { ref memarg } => {
+                let value = self.pop_value_released();
+                let target = self.pop_value_released();
+
+                self.emit_memory_op(target, memarg, false, 16, |this, addr| {
+                    this.emit_move_v128(value, Location::Memory(addr, 0));
+                    Ok(())
+                })?;
+            }
+            Operator::V128Load8x8S { ref memarg } => {
+                self.emit_v128_load_scalar(memarg, Size::S64, |this, x| {
+                    this.assembler.emit_pmovsx(Size::S8, x, x)
+                })?
+            }
+            Operator::V128Load8x8U { ref memarg } => {
+                self.emit_v128_load_scalar(memarg, Size::S64, |this, x| {
+                    this.assembler.emit_pmovzx(Size::S8, x, x)
+                })?
+            }
+            Operator::V128Load16x4S { ref memarg } => {
+                self.emit_v128_load_scalar(memarg, Size::S64, |this, x| {
+                    this.assembler.emit_pmovsx(Size::S16, x, x)
+                })?
+            }
+            Operator::V128Load16x4U { ref memarg } => {
+                self.emit_v128_load_scalar(memarg, Size::S64, |this, x| {
+                    this.assembler.emit_pmovzx(Size::S16, x, x)
+                })?
+            }
+            Operator::V128Load32x2S { ref memarg } => {
+                self.emit_v128_load_scalar(memarg, Size::S64, |this, x| {
+                    this.assembler.emit_pmovsx(Size::S32, x, x)
+                })?
+            }
+            Operator::V128Load32x2U { ref memarg } => {
+                self.emit_v128_load_scalar(memarg, Size::S64, |this, x| {
+                    this.assembler.emit_pmovzx(Size::S32, x, x)
+                })?
+            }
+            Operator::V128Load8Splat { ref memarg } => {
+                self.emit_v128_load_scalar(memarg, Size::S8, |this, x| {
+                    this.emit_v128_broadcast(Size::S8, x)
+                })?
+            }
+            Operator::V128Load16Splat { ref memarg } => {
+                self.emit_v128_load_scalar(memarg, Size::S16, |this, x| {
+                    this.emit_v128_broadcast(Size::S16, x)
+                })?
+            }
+            Operator::V128Load32Splat { ref memarg } => {
+                self.emit_v128_load_scalar(memarg, Size::S32, |this, x| {
+                    this.emit_v128_broadcast(Size::S32, x)
+                })?
+            }
+            Operator::V128Load64Splat { ref memarg } => {
+                self.emit_v128_load_scalar(memarg, Size::S64, |this, x| {
+                    this.emit_v128_broadcast(Size::S64, x)
+                })?
+            }
+            Operator::V128Load32Zero { ref memarg } => {
+                self.emit_v128_load_scalar(memarg, Size::S32, |_, _| {})?
+            }
+            Operator::V128Load64Zero { ref memarg } => {
+                self.emit_v128_load_scalar(memarg, Size::S64, |_, _| {})?
+            }
+            Operator::V128Load8Lane { ref memarg, lane } => {
+                self.emit_v128_load_lane(memarg, Size::S8, lane)?
+            }
+            Operator::V128Load16Lane { ref memarg, lane } => {
+                self.emit_v128_load_lane(memarg, Size::S16, lane)?
+            }
+            Operator::V128Load32Lane { ref memarg, lane } => {
+                self.emit_v128_load_lane(memarg, Size::S32, lane)?
+            }
+            Operator::V128Load64Lane { ref memarg, lane } => {
+                self.emit_v128_load_lane(memarg, Size::S64, lane)?
+            }
+            Operator::V128Store8Lane { ref memarg, lane } => {
+                self.emit_v128_store_lane(memarg, Size::S8, lane)?
+            }
+            Operator::V128Store16Lane { ref memarg, lane } => {
+                self.emit_v128_store_lane(memarg, Size::S16, lane)?
+            }
+            Operator::V128Store32Lane { ref memarg, lane } => {
+                self.emit_v128_store_lane(memarg, Size::S32, lane)?
+            }
+            Operator::V128Store64Lane { ref memarg, lane } => {
+                self.emit_v128_store_lane(memarg, Size::S64, lane)?
+            }
+            Operator::I8x16Splat => self.emit_v128_splat(WpType::I32, Size::S32, |this, x| {
+                this.emit_v128_broadcast(Size::S8, x)
+            })?,
+            Operator::I16x8Splat => self.emit_v128_splat(WpType::I32, Size::S32, |this, x| {
+                this.emit_v128_broadcast(Size::S16, x)
+            })?,
+            Operator::I32x4Splat => self.emit_v128_splat(WpType::I32, Size::S32, |this, x| {
+                this.emit_v128_broadcast(Size::S32, x)
+            })?,
+            Operator::F32x4Splat => self.emit_v128_splat(WpType::F32, Size::S32, |this, x| {
+                this.emit_v128_broadcast(Size::S32, x)
+            })?,
+            Operator::I64x2Splat => self.emit_v128_splat(WpType::I64, Size::S64, |this, x| {
+                this.emit_v128_broadcast(Size::S64, x)
+            })?,
+            Operator::F64x2Splat => self.emit_v128_splat(WpType::F64, Size::S64, |this, x| {
+                this.emit_v128_broadcast(Size::S64, x)
+            })?,
+            Operator::I8x16ExtractLaneS { lane } => {
+                self.emit_v128_extract_lane(WpType::I32, Size::S8, lane, true)
+            }
+            Operator::I8x16ExtractLaneU { lane } => {
+                self.emit_v128_extract_lane(WpType::I32, Size::S8, lane, false)
+            }
+            Operator::I16x8ExtractLaneS { lane } => {
+                self.emit_v128_extract_lane(WpType::I32, Size::S16, lane, true)
+            }
+            Operator::I16x8ExtractLaneU { lane } => {
+                self.emit_v128_extract_lane(WpType::I32, Size::S16, lane, false)
+            }
+            Operator::I32x4ExtractLane { lane } => {
+                self.emit_v128_extract_lane(WpType::I32, Size::S32, lane, false)
+            }
+            Operator::I64x2ExtractLane { lane } => {
+                self.emit_v128_extract_lane(WpType::I64, Size::S64, lane, false)
+            }
+            Operator::F32x4ExtractLane { lane } => {
+                self.emit_v128_extract_lane(WpType::F32, Size::S32, lane, false)
+            }
+            Operator::F64x2ExtractLane { lane } => {
+                self.emit_v128_extract_lane(WpType::F64, Size::S64, lane, false)
+            }
+            Operator::I8x16ReplaceLane { lane } => {
+                self.emit_v128_replace_lane(WpType::I32, Size::S8, lane)?
+            }
+            Operator::I16x8ReplaceLane { lane } => {
+                self.emit_v128_replace_lane(WpType::I32, Size::S16, lane)?
+            }
+            Operator::I32x4ReplaceLane { lane } => {
+                self.emit_v128_replace_lane(WpType::I32, Size::S32, lane)?
+            }
+            Operator::I64x2ReplaceLane { lane } => {
+                self.emit_v128_replace_lane(WpType::I64, Size::S64, lane)?
+            }
+            Operator::F32x4ReplaceLane { lane } => {
+                self.emit_v128_replace_lane(WpType::F32, Size::S32, lane)?
+            }
+            Operator::F64x2ReplaceLane { lane } => {
+                self.emit_v128_replace_lane(WpType::F64, Size::S64, lane)?
+            }
+            Operator::I8x16Shuffle { lanes } => {
+                // Each input is shuffled with a mask selecting its lanes, the other lanes being
+                // zeroed by an index with the top bit set, and the results are combined.
+                let mut mask_a = [0x80; 16];
+                let mut mask_b = [0x80; 16];
+                for (i, lane) in lanes.iter().enumerate() {
+                    if *lane < 16 {
+                        mask_a[i] = *lane;
+                    } else {
+                        mask_b[i] = *lane - 16;
+                    }
+                }
+                let I2O1 { loc_a, loc_b, ret } = self.i2o1_prepare(WpType::V128);
+                let tmp_a = self.machine.acquire_temp_xmm().unwrap();
+                let tmp_b = self.machine.acquire_temp_xmm().unwrap();
+                let mask = self.machine.acquire_temp_xmm().unwrap();
+                self.emit_move_v128(loc_a, Location::XMM(tmp_a));
+                self.emit_move_v128(loc_b, Location::XMM(tmp_b));
+                self.emit_v128_const(mask_a, Location::XMM(mask));
+                self.assembler
+                    .emit_vpshufb(tmp_a, XMMOrMemory::XMM(mask), tmp_a);
+                self.emit_v128_const(mask_b, Location::XMM(mask));
+                self.assembler
+                    .emit_vpshufb(tmp_b, XMMOrMemory::XMM(mask), tmp_b);
+                self.assembler
+                    .emit_vpor(tmp_a, XMMOrMemory::XMM(tmp_b), tmp_a);
+                self.emit_move_v128(Location::XMM(tmp_a), ret);
+                self.machine.release_temp_xmm(mask);
+                self.machine.release_temp_xmm(tmp_b);
+                self.machine.release_temp_xmm(tmp_a);
+            }
+            Operator::I8x16Swizzle => {
+                // Indices past the last lane select zero in wasm, but only those with the top bit
+                // set do with `pshufb`, so 0x70 is added to them with unsigned saturation.
+                let I2O1 { loc_a, loc_b, ret } = self.i2o1_prepare(WpType::V128);
+                let mask = self.machine.acquire_temp_xmm().unwrap();
+                self.emit_v128_const([0x70; 16], Location::XMM(mask));
+                self.assembler
+                    .emit_vpaddusb(mask, v128_operand(loc_b), mask);
+                self.emit_relaxed_avx_v128(
+                    |this, src1, _, dst| {
+                        this.assembler
+                            .emit_vpshufb(src1, XMMOrMemory::XMM(mask), dst)
+                    },
+                    loc_a,
+                    loc_a,
+                    ret,
+                );
+                self.machine.release_temp_xmm(mask);
+            }
+            Operator::V128Not => {
+                let loc = self.pop_value_released();
+                let ret =
+                    self.machine
+                        .acquire_locations(&mut self.assembler, &[WpType::V128], false)[0];
+                self.value_stack.push(ret);
+                let ones = self.machine.acquire_temp_xmm().unwrap();
+                self.assembler
+                    .emit_vpcmpeqd(ones, XMMOrMemory::XMM(ones), ones);
+                self.emit_relaxed_avx_v128(
+                    |this, src1, src2, dst| this.assembler.emit_vpxor(src1, src2, dst),
+                    Location::XMM(ones),
+                    loc,
+                    ret,
+                );
+                self.machine.release_temp_xmm(ones);
+            }
+            Operator::V128And => self.emit_v128_binop_avx(Assembler::emit_vpand),
+            Operator::V128Or => self.emit_v128_binop_avx(Assembler::emit_vpor),
+            Operator::V128Xor => self.emit_v128_binop_avx(Assembler::emit_vpxor),
+            Operator::V128AndNot => {
+                // `vpandn` negates its first operand.
+                let I2O1 { loc_a, loc_b, ret } = self.i2o1_prepare(WpType::V128);
+                self.emit_relaxed_avx_v128(
+                    |this, src1, src2, dst| this.assembler.emit_vpandn(src1, src2, dst),
+                    loc_b,
+                    loc_a,
+                    ret,
+                );
+            }
+            Operator::V128Bitselect => {
+                let mask = self.pop_value_released();
+                let I2O1 { loc_a, loc_b, ret } = self.i2o1_prepare(WpType::V128);
+                let tmp_a = self.machine.acquire_temp_xmm().unwrap();
+                let tmp_mask = self.machine.acquire_temp_xmm().unwrap();
+                self.emit_move_v128(loc_a, Location::XMM(tmp_a));
+                self.emit_move_v128(mask, Location::XMM(tmp_mask));
+                self.assembler
+                    .emit_vpand(tmp_a, XMMOrMemory::XMM(tmp_mask), tmp_a);
+                self.assembler
+                    .emit_vpandn(tmp_mask, v128_operand(loc_b), tmp_mask);
+                self.assembler
+                    .emit_vpor(tmp_a, XMMOrMemory::XMM(tmp_mask), tmp_a);
+                self.emit_move_v128(Location::XMM(tmp_a), ret);
+                self.machine.release_temp_xmm(tmp_mask);
+                self.machine.release_temp_xmm(tmp_a);
+            }
+            Operator::V128AnyTrue => self.emit_v128_test(false, |this, x, _| {
+                this.assembler.emit_ptest(XMMOrMemory::XMM(x), x)
+            }),
+            Operator::I8x16AllTrue => self.emit_v128_test(true, |this, x, tmp| {
+                // Only the zero lanes are set in `tmp`.
+                this.assembler.emit_vpxor(tmp, XMMOrMemory::XMM(tmp), tmp);
+                this.assembler.emit_vpcmpeqb(tmp, XMMOrMemory::XMM(x), tmp);
+                this.assembler.emit_ptest(XMMOrMemory::XMM(tmp), tmp);
+            }),
+            Operator::I8x16Add => self.emit_v128_binop_avx(Assembler::emit_vpaddb),
+            Operator::I16x8Add => self.emit_v128_binop_avx(Assembler::emit_vpaddw),
+            Operator::I32x4Add => self.emit_v128_binop_avx(Assembler::emit_vpaddd),
+            Operator::I64x2Add => self.emit_v128_binop_avx(Assembler::emit_vpaddq),
+            Operator::I8x16Sub => self.emit_v128_binop_avx(Assembler::emit_vpsubb),
+            Operator::I16x8Sub => self.emit_v128_binop_avx(Assembler::emit_vpsubw),
+            Operator::I32x4Sub => self.emit_v128_binop_avx(Assembler::emit_vpsubd),
+            Operator::I64x2Sub => self.emit_v128_binop_avx(Assembler::emit_vpsubq),
+            Operator::I16x8Mul => self.emit_v128_binop_avx(Assembler::emit_vpmullw),
+            Operator::I32x4Mul => self.emit_v128_binop_avx(Assembler::emit_vpmulld),
+            Operator::I64x2Mul => {
+                // There is no 64-bit lane multiplication before AVX-512, so the product is
+                // computed from the 32-bit halves of the lanes:
+                // lo(a) * lo(b) + ((hi(a) * lo(b) + lo(a) * hi(b)) << 32).
+                let I2O1 { loc_a, loc_b, ret } = self.i2o1_prepare(WpType::V128);
+                let a = self.machine.acquire_temp_xmm().unwrap();
+                let cross = self.machine.acquire_temp_xmm().unwrap();
+                let tmp = self.machine.acquire_temp_xmm().unwrap();
+                let b = v128_operand(loc_b);
+                self.emit_move_v128(loc_a, Location::XMM(a));
+                self.emit_move_v128(Location::XMM(a), Location::XMM(cross));
+                self.assembler.emit_psrlq_imm(32, cross);
+                self.assembler.emit_vpmuludq(cross, b, cross);
+                self.emit_move_v128(loc_b, Location::XMM(tmp));
+                self.assembler.emit_psrlq_imm(32, tmp);
+                self.assembler.emit_vpmuludq(tmp, XMMOrMemory::XMM(a), tmp);
+                self.assembler
+                    .emit_vpaddq(cross, XMMOrMemory::XMM(tmp), cross);
+                self.assembler.emit_psllq_imm(32, cross);
+                self.assembler.emit_vpmuludq(a, b, a);
+                self.assembler.emit_vpaddq(a, XMMOrMemory::XMM(cross), a);
+                self.emit_move_v128(Location::XMM(a), ret);
+                self.machine.release_temp_xmm(tmp);
+                self.machine.release_temp_xmm(cross);
+                self.machine.release_temp_xmm(a);
+            }
+            Operator::I8x16Neg => self.emit_v128_neg(Assembler::emit_vpsubb),
+            Operator::I16x8Neg => self.emit_v128_neg(Assembler::emit_vpsubw),
+            Operator::I32x4Neg => self.emit_v128_neg(Assembler::emit_vpsubd),
+            Operator::I64x2Neg => self.emit_v128_neg(Assembler::emit_vpsubq),
             _ => {
                 return Err(CodegenError {
-                    message: format!("not yet implemented: {:?}", op),
+                    message: format!("unsupported operator {}", operator_name(&op)),
                 });
             }
         }
@@ -8380,66 +9736,525 @@ impl<'a> FuncGen<'a> {
     }
 
     #[tracing::instrument(skip_all)]
-    pub(crate) fn finalize(mut self, data: &FunctionBodyData) -> CompiledFunction {
-        // Generate actual code for special labels.
-        self.assembler
-            .emit_label(self.special_labels.integer_division_by_zero);
-        self.emit_trap(TrapCode::IntegerDivisionByZero);
-
-        self.assembler
-            .emit_label(self.special_labels.integer_overflow);
-        self.emit_trap(TrapCode::IntegerOverflow);
-
-        self.assembler
-            .emit_label(self.special_labels.bad_conversion_to_integer);
-        self.emit_trap(TrapCode::BadConversionToInteger);
-
-        self.assembler
-            .emit_label(self.special_labels.heap_access_oob);
-        self.emit_trap(TrapCode::HeapAccessOutOfBounds);
-
-        self.assembler
-            .emit_label(self.special_labels.table_access_oob);
-        self.emit_trap(TrapCode::TableAccessOutOfBounds);
-
-        self.assembler
-            .emit_label(self.special_labels.indirect_call_null);
-        self.emit_trap(TrapCode::IndirectCallToNull);
-
-        self.assembler.emit_label(self.special_labels.bad_signature);
-        self.emit_trap(TrapCode::BadSignature);
+    pub(crate) fn finalize(
+        mut self,
+        data: &FunctionBodyData,
+    ) -> (CompiledFunction, Option<MachineStats>, Option<UnwindFrame>) {
+        // The exceptions unwinding the stack to a landing pad only restore RBP. The pad restores
+        // the rest of the state of the code following its call.
+        let mut landing_pads = vec![];
+        for pad in mem::take(&mut self.landing_pads) {
+            landing_pads.push(LandingPad {
+                return_offset: pad.return_offset as u32,
+                handler_offset: self.assembler.get_offset().0 as u32,
+            });
+            self.assembler.emit_lea(
+                Size::S64,
+                Location::Memory(GPR::RBP, -(pad.rsp as i32)),
+                Location::GPR(GPR::RSP),
+            );
+            self.assembler.emit_mov(
+                Size::S64,
+                self.machine.vmctx_slot().unwrap(),
+                Location::GPR(Machine::get_vmctx_reg()),
+            );
+            self.machine
+                .restore_call_frame(&mut self.assembler, pad.frame);
+            self.assembler.emit_jmp(Condition::None, pad.dispatch);
+        }
 
-        self.assembler
-            .emit_label(self.special_labels.gas_limit_exceeded);
-        self.emit_trap(TrapCode::GasExceeded);
+        // Generate the traps out of line, each with the source location of its instruction.
+        let mut traps = vec![];
+        for stub in mem::take(&mut self.trap_stubs) {
+            let begin = self.assembler.get_offset().0;
+            self.assembler.emit_label(stub.label);
+            if let Some(memory_access) = stub.memory_access {
+                // The trap handler finds the rest of the access in the trap information.
+                let address_size = if self.memory64() {
+                    Size::S64
+                } else {
+                    Size::S32
+                };
+                self.assembler.emit_mov(
+                    address_size,
+                    memory_access.address,
+                    Machine::get_param_location(2, self.calling_convention),
+                );
+                traps.push(TrapInformation {
+                    code_offset: self.assembler.get_offset().0 as u32,
+                    trap_code: TrapCode::HeapAccessOutOfBounds,
+                    memory_access: Some(memory_access.access),
+                });
+            }
+            self.emit_trap(stub.code);
+            self.src_loc = stub.srcloc;
+            self.mark_instruction_address_end(begin);
+        }
 
-        self.assembler
-            .emit_label(self.special_labels.stack_overflow);
+        self.assembler.emit_label(self.stack_overflow);
         self.emit_trap(TrapCode::StackOverflow);
 
         // Notify the assembler backend to generate necessary code at end of function.
         self.assembler.finalize_function();
 
         let body_len = self.assembler.get_offset().0;
-        let instructions_address_map = self.instructions_address_map;
+        // The function is held until the whole module is compiled, without the spare capacity
+        // its vectors grew with.
+        let mut instructions_address_map = self.instructions_address_map;
+        instructions_address_map.shrink_to_fit();
+        traps.shrink_to_fit();
+        self.relocations.shrink_to_fit();
         let address_map = get_function_address_map(instructions_address_map, data, body_len);
-        let body = self.assembler.finalize().unwrap().to_vec();
+        let assembler = self.assembler.into_inner();
+        #[cfg(feature = "debug-asm")]
+        let (assembler, debug_asm) = assembler.into_parts();
+        #[cfg(not(feature = "debug-asm"))]
+        let debug_asm = None;
+        let body = assembler.finalize().unwrap().to_vec();
+        let stats = self.machine.take_stats();
+
+        let unwind_ops = self.machine.take_unwind_ops();
+        let (unwind_info, unwind_frame) = match self.calling_convention {
+            CallingConvention::WindowsFastcall => (
+                create_unwind_info(&unwind_ops).map(CompiledFunctionUnwindInfo::WindowsX64),
+                None,
+            ),
+            _ => (
+                Some(CompiledFunctionUnwindInfo::Dwarf),
+                Some(UnwindFrame::SystemV(unwind_ops)),
+            ),
+        };
 
-        CompiledFunction {
-            body: FunctionBody {
-                body,
-                unwind_info: None,
-            },
+        let function = CompiledFunction {
+            body: FunctionBody { body, unwind_info },
             relocations: self.relocations,
             jt_offsets: SecondaryMap::new(),
             frame_info: CompiledFunctionFrameInfo {
-                traps: vec![],
+                traps,
                 address_map,
+                frame_layout: self.frame_layout,
+                landing_pads,
             },
-        }
+            debug_asm,
+        };
+        (function, stats, unwind_frame)
     }
 }
 
+/// Whether `op` is compiled into a call, into another function or into the runtime.
+///
+/// The operators that only call into the runtime on some paths, such as the `global.set` of an
+/// `externref` global, are left to `Machine::require_frame`.
+fn calls_out(op: &Operator) -> bool {
+    matches!(
+        op,
+        Operator::Call { .. }
+            | Operator::CallIndirect { .. }
+            | Operator::ReturnCall { .. }
+            | Operator::ReturnCallIndirect { .. }
+            | Operator::MemorySize { .. }
+            | Operator::MemoryGrow { .. }
+            | Operator::MemoryInit { .. }
+            | Operator::DataDrop { .. }
+            | Operator::MemoryCopy { .. }
+            | Operator::MemoryFill { .. }
+            | Operator::MemoryAtomicNotify { .. }
+            | Operator::MemoryAtomicWait32 { .. }
+            | Operator::MemoryAtomicWait64 { .. }
+            | Operator::RefFunc { .. }
+            | Operator::TableGet { .. }
+            | Operator::TableSet { .. }
+            | Operator::TableGrow { .. }
+            | Operator::TableCopy { .. }
+            | Operator::TableFill { .. }
+            | Operator::TableInit { .. }
+            | Operator::ElemDrop { .. }
+            | Operator::Catch { .. }
+            | Operator::Throw { .. }
+            | Operator::Rethrow { .. }
+    )
+}
+
+/// Whether the memory cached in registers for the accesses of a basic block must be forgotten
+/// before `op`: at the boundaries of the basic blocks, where paths that may not have loaded it
+/// join, and where the memory grows. The calls forget it in `Machine::prepare_call_frame`.
+fn forgets_memory_cache(op: &Operator) -> bool {
+    matches!(
+        op,
+        Operator::Block { .. }
+            | Operator::Loop { .. }
+            | Operator::If { .. }
+            | Operator::Else
+            | Operator::End
+            | Operator::Br { .. }
+            | Operator::BrIf { .. }
+            | Operator::BrTable { .. }
+            | Operator::Return
+            | Operator::ReturnCall { .. }
+            | Operator::ReturnCallIndirect { .. }
+            | Operator::Unreachable
+            | Operator::MemoryGrow { .. }
+            | Operator::Try { .. }
+            | Operator::Catch { .. }
+            | Operator::CatchAll
+            | Operator::Delegate { .. }
+            | Operator::Throw { .. }
+            | Operator::Rethrow { .. }
+    )
+}
+
+/// Whether `FuncGen::feed_operator` compiles `op`, rather than failing with an unsupported
+/// operator error. This must be kept in sync with `FuncGen::feed_operator`.
+pub(crate) fn is_supported_operator(op: &Operator) -> bool {
+    matches!(
+        op,
+        Operator::Block { .. }
+            | Operator::Loop { .. }
+            | Operator::If { .. }
+            | Operator::End
+            | Operator::Else
+            | Operator::Try { .. }
+            | Operator::Catch { .. }
+            | Operator::CatchAll
+            | Operator::Delegate { .. }
+            | Operator::Throw { .. }
+            | Operator::Rethrow { .. }
+            | Operator::GlobalGet { .. }
+            | Operator::GlobalSet { .. }
+            | Operator::LocalGet { .. }
+            | Operator::LocalSet { .. }
+            | Operator::LocalTee { .. }
+            | Operator::I32Const { .. }
+            | Operator::I32Add
+            | Operator::I32Sub
+            | Operator::I32Mul
+            | Operator::I32DivU
+            | Operator::I32DivS
+            | Operator::I32RemU
+            | Operator::I32RemS
+            | Operator::I32And
+            | Operator::I32Or
+            | Operator::I32Xor
+            | Operator::I32Eq
+            | Operator::I32Ne
+            | Operator::I32Eqz
+            | Operator::I32Clz
+            | Operator::I32Ctz
+            | Operator::I32Popcnt
+            | Operator::I32Shl
+            | Operator::I32ShrU
+            | Operator::I32ShrS
+            | Operator::I32Rotl
+            | Operator::I32Rotr
+            | Operator::I32LtU
+            | Operator::I32LeU
+            | Operator::I32GtU
+            | Operator::I32GeU
+            | Operator::I32LtS
+            | Operator::I32LeS
+            | Operator::I32GtS
+            | Operator::I32GeS
+            | Operator::I64Const { .. }
+            | Operator::I64Add
+            | Operator::I64Sub
+            | Operator::I64Mul
+            | Operator::I64DivU
+            | Operator::I64DivS
+            | Operator::I64RemU
+            | Operator::I64RemS
+            | Operator::I64And
+            | Operator::I64Or
+            | Operator::I64Xor
+            | Operator::I64Eq
+            | Operator::I64Ne
+            | Operator::I64Eqz
+            | Operator::I64Clz
+            | Operator::I64Ctz
+            | Operator::I64Popcnt
+            | Operator::I64Shl
+            | Operator::I64ShrU
+            | Operator::I64ShrS
+            | Operator::I64Rotl
+            | Operator::I64Rotr
+            | Operator::I64LtU
+            | Operator::I64LeU
+            | Operator::I64GtU
+            | Operator::I64GeU
+            | Operator::I64LtS
+            | Operator::I64LeS
+            | Operator::I64GtS
+            | Operator::I64GeS
+            | Operator::I64ExtendI32U
+            | Operator::I64ExtendI32S
+            | Operator::I32Extend8S
+            | Operator::I32Extend16S
+            | Operator::I64Extend8S
+            | Operator::I64Extend16S
+            | Operator::I64Extend32S
+            | Operator::I32WrapI64
+            | Operator::F32Const { .. }
+            | Operator::F32Add
+            | Operator::F32Sub
+            | Operator::F32Mul
+            | Operator::F32Div
+            | Operator::F32Max
+            | Operator::F32Min
+            | Operator::F32Eq
+            | Operator::F32Ne
+            | Operator::F32Lt
+            | Operator::F32Le
+            | Operator::F32Gt
+            | Operator::F32Ge
+            | Operator::F32Nearest
+            | Operator::F32Floor
+            | Operator::F32Ceil
+            | Operator::F32Trunc
+            | Operator::F32Sqrt
+            | Operator::F32Copysign
+            | Operator::F32Abs
+            | Operator::F32Neg
+            | Operator::F64Const { .. }
+            | Operator::F64Add
+            | Operator::F64Sub
+            | Operator::F64Mul
+            | Operator::F64Div
+            | Operator::F64Max
+            | Operator::F64Min
+            | Operator::F64Eq
+            | Operator::F64Ne
+            | Operator::F64Lt
+            | Operator::F64Le
+            | Operator::F64Gt
+            | Operator::F64Ge
+            | Operator::F64Nearest
+            | Operator::F64Floor
+            | Operator::F64Ceil
+            | Operator::F64Trunc
+            | Operator::F64Sqrt
+            | Operator::F64Copysign
+            | Operator::F64Abs
+            | Operator::F64Neg
+            | Operator::F64PromoteF32
+            | Operator::F32DemoteF64
+            | Operator::I32ReinterpretF32
+            | Operator::F32ReinterpretI32
+            | Operator::I64ReinterpretF64
+            | Operator::F64ReinterpretI64
+            | Operator::I32TruncF32U
+            | Operator::I32TruncSatF32U
+            | Operator::I32TruncF32S
+            | Operator::I32TruncSatF32S
+            | Operator::I64TruncF32S
+            | Operator::I64TruncSatF32S
+            | Operator::I64TruncF32U
+            | Operator::I64TruncSatF32U
+            | Operator::I32TruncF64U
+            | Operator::I32TruncSatF64U
+            | Operator::I32TruncF64S
+            | Operator::I32TruncSatF64S
+            | Operator::I64TruncF64S
+            | Operator::I64TruncSatF64S
+            | Operator::I64TruncF64U
+            | Operator::I64TruncSatF64U
+            | Operator::F32ConvertI32S
+            | Operator::F32ConvertI32U
+            | Operator::F32ConvertI64S
+            | Operator::F32ConvertI64U
+            | Operator::F64ConvertI32S
+            | Operator::F64ConvertI32U
+            | Operator::F64ConvertI64S
+            | Operator::F64ConvertI64U
+            | Operator::Call { .. }
+            | Operator::CallIndirect { .. }
+            | Operator::ReturnCall { .. }
+            | Operator::ReturnCallIndirect { .. }
+            | Operator::TypedSelect { .. }
+            | Operator::Select
+            | Operator::Nop
+            | Operator::MemorySize { .. }
+            | Operator::MemoryInit { .. }
+            | Operator::DataDrop { .. }
+            | Operator::MemoryCopy { .. }
+            | Operator::MemoryFill { .. }
+            | Operator::MemoryGrow { .. }
+            | Operator::I32Load { .. }
+            | Operator::F32Load { .. }
+            | Operator::I32Load8U { .. }
+            | Operator::I32Load8S { .. }
+            | Operator::I32Load16U { .. }
+            | Operator::I32Load16S { .. }
+            | Operator::I32Store { .. }
+            | Operator::F32Store { .. }
+            | Operator::I32Store8 { .. }
+            | Operator::I32Store16 { .. }
+            | Operator::I64Load { .. }
+            | Operator::F64Load { .. }
+            | Operator::I64Load8U { .. }
+            | Operator::I64Load8S { .. }
+            | Operator::I64Load16U { .. }
+            | Operator::I64Load16S { .. }
+            | Operator::I64Load32U { .. }
+            | Operator::I64Load32S { .. }
+            | Operator::I64Store { .. }
+            | Operator::F64Store { .. }
+            | Operator::I64Store8 { .. }
+            | Operator::I64Store16 { .. }
+            | Operator::I64Store32 { .. }
+            | Operator::Unreachable
+            | Operator::Return
+            | Operator::Br { .. }
+            | Operator::BrIf { .. }
+            | Operator::BrTable { .. }
+            | Operator::Drop
+            | Operator::AtomicFence { .. }
+            | Operator::MemoryAtomicWait32 { .. }
+            | Operator::MemoryAtomicWait64 { .. }
+            | Operator::MemoryAtomicNotify { .. }
+            | Operator::I32AtomicLoad { .. }
+            | Operator::I32AtomicLoad8U { .. }
+            | Operator::I32AtomicLoad16U { .. }
+            | Operator::I32AtomicStore { .. }
+            | Operator::I32AtomicStore8 { .. }
+            | Operator::I32AtomicStore16 { .. }
+            | Operator::I64AtomicLoad { .. }
+            | Operator::I64AtomicLoad8U { .. }
+            | Operator::I64AtomicLoad16U { .. }
+            | Operator::I64AtomicLoad32U { .. }
+            | Operator::I64AtomicStore { .. }
+            | Operator::I64AtomicStore8 { .. }
+            | Operator::I64AtomicStore16 { .. }
+            | Operator::I64AtomicStore32 { .. }
+            | Operator::I32AtomicRmwAdd { .. }
+            | Operator::I64AtomicRmwAdd { .. }
+            | Operator::I32AtomicRmw8AddU { .. }
+            | Operator::I32AtomicRmw16AddU { .. }
+            | Operator::I64AtomicRmw8AddU { .. }
+            | Operator::I64AtomicRmw16AddU { .. }
+            | Operator::I64AtomicRmw32AddU { .. }
+            | Operator::I32AtomicRmwSub { .. }
+            | Operator::I64AtomicRmwSub { .. }
+            | Operator::I32AtomicRmw8SubU { .. }
+            | Operator::I32AtomicRmw16SubU { .. }
+            | Operator::I64AtomicRmw8SubU { .. }
+            | Operator::I64AtomicRmw16SubU { .. }
+            | Operator::I64AtomicRmw32SubU { .. }
+            | Operator::I32AtomicRmwAnd { .. }
+            | Operator::I64AtomicRmwAnd { .. }
+            | Operator::I32AtomicRmw8AndU { .. }
+            | Operator::I32AtomicRmw16AndU { .. }
+            | Operator::I64AtomicRmw8AndU { .. }
+            | Operator::I64AtomicRmw16AndU { .. }
+            | Operator::I64AtomicRmw32AndU { .. }
+            | Operator::I32AtomicRmwOr { .. }
+            | Operator::I64AtomicRmwOr { .. }
+            | Operator::I32AtomicRmw8OrU { .. }
+            | Operator::I32AtomicRmw16OrU { .. }
+            | Operator::I64AtomicRmw8OrU { .. }
+            | Operator::I64AtomicRmw16OrU { .. }
+            | Operator::I64AtomicRmw32OrU { .. }
+            | Operator::I32AtomicRmwXor { .. }
+            | Operator::I64AtomicRmwXor { .. }
+            | Operator::I32AtomicRmw8XorU { .. }
+            | Operator::I32AtomicRmw16XorU { .. }
+            | Operator::I64AtomicRmw8XorU { .. }
+            | Operator::I64AtomicRmw16XorU { .. }
+            | Operator::I64AtomicRmw32XorU { .. }
+            | Operator::I32AtomicRmwXchg { .. }
+            | Operator::I64AtomicRmwXchg { .. }
+            | Operator::I32AtomicRmw8XchgU { .. }
+            | Operator::I32AtomicRmw16XchgU { .. }
+            | Operator::I64AtomicRmw8XchgU { .. }
+            | Operator::I64AtomicRmw16XchgU { .. }
+            | Operator::I64AtomicRmw32XchgU { .. }
+            | Operator::I32AtomicRmwCmpxchg { .. }
+            | Operator::I64AtomicRmwCmpxchg { .. }
+            | Operator::I32AtomicRmw8CmpxchgU { .. }
+            | Operator::I32AtomicRmw16CmpxchgU { .. }
+            | Operator::I64AtomicRmw8CmpxchgU { .. }
+            | Operator::I64AtomicRmw16CmpxchgU { .. }
+            | Operator::I64AtomicRmw32CmpxchgU { .. }
+            | Operator::RefNull { .. }
+            | Operator::RefFunc { .. }
+            | Operator::RefIsNull
+            | Operator::TableSet { .. }
+            | Operator::TableGet { .. }
+            | Operator::TableSize { .. }
+            | Operator::TableGrow { .. }
+            | Operator::TableCopy { .. }
+            | Operator::TableFill { .. }
+            | Operator::TableInit { .. }
+            | Operator::ElemDrop { .. }
+            | Operator::V128Const { .. }
+            | Operator::V128Load { .. }
+            | Operator::V128Store { .. }
+            | Operator::V128Load8x8S { .. }
+            | Operator::V128Load8x8U { .. }
+            | Operator::V128Load16x4S { .. }
+            | Operator::V128Load16x4U { .. }
+            | Operator::V128Load32x2S { .. }
+            | Operator::V128Load32x2U { .. }
+            | Operator::V128Load8Splat { .. }
+            | Operator::V128Load16Splat { .. }
+            | Operator::V128Load32Splat { .. }
+            | Operator::V128Load64Splat { .. }
+            | Operator::V128Load32Zero { .. }
+            | Operator::V128Load64Zero { .. }
+            | Operator::V128Load8Lane { .. }
+            | Operator::V128Load16Lane { .. }
+            | Operator::V128Load32Lane { .. }
+            | Operator::V128Load64Lane { .. }
+            | Operator::V128Store8Lane { .. }
+            | Operator::V128Store16Lane { .. }
+            | Operator::V128Store32Lane { .. }
+            | Operator::V128Store64Lane { .. }
+            | Operator::I8x16Splat
+            | Operator::I16x8Splat
+            | Operator::I32x4Splat
+            | Operator::F32x4Splat
+            | Operator::I64x2Splat
+            | Operator::F64x2Splat
+            | Operator::I8x16ExtractLaneS { .. }
+            | Operator::I8x16ExtractLaneU { .. }
+            | Operator::I16x8ExtractLaneS { .. }
+            | Operator::I16x8ExtractLaneU { .. }
+            | Operator::I32x4ExtractLane { .. }
+            | Operator::I64x2ExtractLane { .. }
+            | Operator::F32x4ExtractLane { .. }
+            | Operator::F64x2ExtractLane { .. }
+            | Operator::I8x16ReplaceLane { .. }
+            | Operator::I16x8ReplaceLane { .. }
+            | Operator::I32x4ReplaceLane { .. }
+            | Operator::I64x2ReplaceLane { .. }
+            | Operator::F32x4ReplaceLane { .. }
+            | Operator::F64x2ReplaceLane { .. }
+            | Operator::I8x16Shuffle { .. }
+            | Operator::I8x16Swizzle
+            | Operator::V128Not
+            | Operator::V128And
+            | Operator::V128Or
+            | Operator::V128Xor
+            | Operator::V128AndNot
+            | Operator::V128Bitselect
+            | Operator::V128AnyTrue
+            | Operator::I8x16AllTrue
+            | Operator::I8x16Add
+            | Operator::I16x8Add
+            | Operator::I32x4Add
+            | Operator::I64x2Add
+            | Operator::I8x16Sub
+            | Operator::I16x8Sub
+            | Operator::I32x4Sub
+            | Operator::I64x2Sub
+            | Operator::I16x8Mul
+            | Operator::I32x4Mul
+            | Operator::I64x2Mul
+            | Operator::I8x16Neg
+            | Operator::I16x8Neg
+            | Operator::I32x4Neg
+            | Operator::I64x2Neg
+    )
+}
+
 fn type_to_wp_type(ty: Type) -> WpType {
     match ty {
         Type::I32 => WpType::I32,
@@ -8452,6 +10267,95 @@ fn type_to_wp_type(ty: Type) -> WpType {
     }
 }
 
+/// The index of the first 64-bit word of each of the params of types `tys`, and the total number
+/// of words.
+///
+/// Calls pass their params as consecutive words, see `Machine::get_param_location`. 16-byte
+/// params take two words.
+fn param_words(tys: &[WpType]) -> (SmallVec<[usize; 8]>, usize) {
+    let mut next = 0;
+    let words = tys
+        .iter()
+        .map(|ty| {
+            let word = next;
+            next += if *ty == WpType::V128 { 2 } else { 1 };
+            word
+        })
+        .collect();
+    (words, next)
+}
+
+/// Like `param_words`, for the results of functions returning several values.
+///
+/// The results are returned as words, see `Machine::get_return_location`. 16-byte results never
+/// go to the two return registers, they are moved past them to the return area instead.
+fn result_words(tys: &[WpType]) -> (SmallVec<[usize; 8]>, usize) {
+    let mut next = 0;
+    let words = tys
+        .iter()
+        .map(|ty| {
+            let word = if *ty == WpType::V128 {
+                max(next, 2)
+            } else {
+                next
+            };
+            next = word + if *ty == WpType::V128 { 2 } else { 1 };
+            word
+        })
+        .collect();
+    (words, next)
+}
+
+/// The types of the results of a call that are returned in the return area.
+fn stack_result_types(return_types: &[WpType]) -> SmallVec<[WpType; 8]> {
+    if return_types.len() < 2 {
+        return smallvec![];
+    }
+    let (words, _) = result_words(return_types);
+    return_types
+        .iter()
+        .zip(words)
+        .filter(|(_, word)| *word >= 2)
+        .map(|(ty, _)| *ty)
+        .collect()
+}
+
+/// Splits the values of types `tys` at `locs` into 64-bit words, the 16-byte ones, which must be
+/// in memory, into two.
+fn value_words(tys: &[WpType], locs: &[Location]) -> SmallVec<[Location; 8]> {
+    let mut words = SmallVec::new();
+    for (ty, loc) in tys.iter().zip(locs) {
+        match (ty, *loc) {
+            (WpType::V128, Location::Memory(base, disp)) => {
+                words.push(Location::Memory(base, disp));
+                words.push(Location::Memory(base, disp + 8));
+            }
+            (WpType::V128, _) => unreachable!("16-byte value in {:?}", loc),
+            _ => words.push(*loc),
+        }
+    }
+    words
+}
+
+/// The number of bytes of a value of size `sz`.
+fn size_in_bytes(sz: Size) -> usize {
+    match sz {
+        Size::S8 => 1,
+        Size::S16 => 2,
+        Size::S32 => 4,
+        Size::S64 => 8,
+    }
+}
+
+/// The operand for a 16-byte value, which is always in an XMM register or in memory.
+fn v128_operand(loc: Location) -> XMMOrMemory {
+    match loc {
+        Location::XMM(x) => XMMOrMemory::XMM(x),
+        Location::Memory(base, disp) => XMMOrMemory::Memory(base, disp),
+        _ => unreachable!("16-byte value in {:?}", loc),
+    }
+}
+
 // FIXME: This implementation seems to be not enough to resolve all kinds of register dependencies
 // at call place.
 fn sort_call_movs(movs: &mut [(Location, GPR)]) {
@@ -8501,30 +10405,77 @@ fn sort_call_movs(movs: &mut [(Location, GPR)]) {
     */
 }
 
+/// The function a tail call jumps to, see `FuncGen::emit_return_call`.
+enum TailCallee {
+    Direct(FunctionIndex),
+    /// The function of the `VMCallerCheckedAnyfunc` RAX points to.
+    Indirect,
+}
+
+/// The bytes the prologue reserves below the frame, as a "red zone" for the platforms without
+/// one. RSP is always this far below the stack offset of the `Machine`.
+const RED_ZONE_SIZE: u32 = 32;
+
+/// The XMM registers the Windows ABI makes callee-saved, and which the compiled functions use
+/// without saving them: XMM6 and XMM7 as temporaries, XMM8-XMM10 as scratch registers. The
+/// functions only save the XMM registers holding their locals.
+const WINDOWS_CLOBBERED_XMMS: &[XMM] = &[XMM::XMM6, XMM::XMM7, XMM::XMM8, XMM::XMM9, XMM::XMM10];
+
 // Standard entry trampoline.
+//
+// The host calls it with the callee vmctx, the function pointer and the `args_rets` array, and
+// it calls the function with the arguments where `Machine::get_param_location` says the
+// function reads them for `calling_convention`, results being written back to the array.
 #[tracing::instrument]
 pub(crate) fn gen_std_trampoline(
     sig: &FunctionType,
     calling_convention: CallingConvention,
 ) -> FunctionBody {
-    let mut a = Assembler::new(0);
+    let mut a = VecAssembler::<X64Relocation>::new(0);
+
+    let param_types: SmallVec<[WpType; 8]> =
+        sig.params().iter().cloned().map(type_to_wp_type).collect();
+    let return_types: SmallVec<[WpType; 8]> =
+        sig.results().iter().cloned().map(type_to_wp_type).collect();
+    let (param_words, n_param_words) = param_words(&param_types);
+    let (result_words, n_result_words) = result_words(&return_types);
 
     // Calculate stack offset.
     let mut stack_offset: u32 = 0;
-    for (i, _param) in sig.params().iter().enumerate() {
+    for i in 0..n_param_words {
         if let Location::Memory(_, _) = Machine::get_param_location(1 + i, calling_convention) {
             stack_offset += 8;
         }
     }
+    // The results not returned in registers overwrite the stack arguments.
+    let stack_results = if return_types.len() > 1 {
+        n_result_words.saturating_sub(2)
+    } else {
+        0
+    };
+    stack_offset = max(stack_offset, 8 * stack_results as u32);
     let stack_padding: u32 = match calling_convention {
         CallingConvention::WindowsFastcall => 32,
         _ => 0,
     };
 
-    // Align to 16 bytes. We push two 8-byte registers below, so here we need to ensure stack_offset % 16 == 8.
-    if stack_offset % 16 != 8 {
+    // The callee-saved XMM registers of the host the compiled code may clobber, saved above the
+    // stack arguments.
+    let saved_xmms: &[XMM] = match calling_convention {
+        CallingConvention::WindowsFastcall => WINDOWS_CLOBBERED_XMMS,
+        _ => &[],
+    };
+
+    // Align to 16 bytes. We push three 8-byte registers below, so here we need to ensure stack_offset % 16 == 0.
+    if stack_offset % 16 != 0 {
         stack_offset += 8;
     }
+    let frame_size = stack_offset + stack_padding + 16 * saved_xmms.len() as u32;
+
+    // The function may return with RSP elsewhere than where the call left it, after a tail call,
+    // so RSP is restored from RBP.
+    a.emit_push(Size::S64, Location::GPR(GPR::RBP));
+    a.emit_mov(Size::S64, Location::GPR(GPR::RSP), Location::GPR(GPR::RBP));
 
     // Used callee-saved registers
     a.emit_push(Size::S64, Location::GPR(GPR::R15));
@@ -8533,9 +10484,18 @@ pub(crate) fn gen_std_trampoline(
     // Prepare stack space.
     a.emit_sub(
         Size::S64,
-        Location::Imm32(stack_offset + stack_padding),
+        Location::Imm32(frame_size),
         Location::GPR(GPR::RSP),
     );
+    for (i, xmm) in saved_xmms.iter().enumerate() {
+        a.emit_movdqu(
+            XMMOrMemory::XMM(*xmm),
+            XMMOrMemory::Memory(
+                GPR::RSP,
+                (stack_offset + stack_padding) as i32 + 16 * i as i32,
+            ),
+        );
+    }
 
     // Arguments
     a.emit_mov(
@@ -8553,9 +10513,17 @@ pub(crate) fn gen_std_trampoline(
     // `callee_vmctx` is already in the first argument register, so no need to move.
     {
         let mut n_stack_args: usize = 0;
-        for (i, _param) in sig.params().iter().enumerate() {
-            let src_loc = Location::Memory(GPR::R14, (i * 16) as _); // args_rets[i]
-            let dst_loc = Machine::get_param_location(1 + i, calling_convention);
+        let words = param_types
+            .iter()
+            .zip(param_words)
+            .enumerate()
+            .flat_map(|(i, (ty, word))| {
+                let n = if *ty == WpType::V128 { 2 } else { 1 };
+                (0..n).map(move |half| (i * 16 + half * 8, word + half))
+            });
+        for (offset, word) in words {
+            let src_loc = Location::Memory(GPR::R14, offset as _); // args_rets[i]
+            let dst_loc = Machine::get_param_location(1 + word, calling_convention);
 
             match dst_loc {
                 Location::GPR(_) => {
@@ -8583,25 +10551,68 @@ pub(crate) fn gen_std_trampoline(
     // Call.
     a.emit_call_location(Location::GPR(GPR::R15));
 
+    // Write return values.
+    if let [WpType::V128] = &return_types[..] {
+        a.emit_movdqu(
+            XMMOrMemory::XMM(XMM::XMM0),
+            XMMOrMemory::Memory(GPR::R14, 0),
+        );
+    } else {
+        let words =
+            return_types
+                .iter()
+                .zip(result_words)
+                .enumerate()
+                .flat_map(|(i, (ty, word))| {
+                    let n = if *ty == WpType::V128 { 2 } else { 1 };
+                    (0..n).map(move |half| (i * 16 + half * 8, word + half))
+                });
+        for (offset, word) in words {
+            let dst_loc = Location::Memory(GPR::R14, offset as _); // args_rets[i]
+            match Machine::get_return_location(word, calling_convention) {
+                Location::GPR(gpr) => {
+                    a.emit_mov(Size::S64, Location::GPR(gpr), dst_loc);
+                }
+                Location::Memory(_, _) => {
+                    // The return location is relative to the callee's frame, recalculate it. RAX
+                    // is free again, as the first word is written before.
+                    a.emit_mov(
+                        Size::S64,
+                        Location::Memory(GPR::RSP, (stack_padding as usize + (word - 2) * 8) as _),
+                        Location::GPR(GPR::RAX),
+                    );
+                    a.emit_mov(Size::S64, Location::GPR(GPR::RAX), dst_loc);
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
     // Restore stack.
-    a.emit_add(
+    a.emit_lea(
         Size::S64,
-        Location::Imm32(stack_offset + stack_padding),
+        Location::Memory(GPR::RBP, -16 - frame_size as i32),
         Location::GPR(GPR::RSP),
     );
-
-    // Write return value.
-    if !sig.results().is_empty() {
-        a.emit_mov(
-            Size::S64,
-            Location::GPR(GPR::RAX),
-            Location::Memory(GPR::R14, 0),
+    for (i, xmm) in saved_xmms.iter().enumerate() {
+        a.emit_movdqu(
+            XMMOrMemory::Memory(
+                GPR::RSP,
+                (stack_offset + stack_padding) as i32 + 16 * i as i32,
+            ),
+            XMMOrMemory::XMM(*xmm),
         );
     }
+    a.emit_add(
+        Size::S64,
+        Location::Imm32(frame_size),
+        Location::GPR(GPR::RSP),
+    );
 
     // Restore callee-saved registers.
     a.emit_pop(Size::S64, Location::GPR(GPR::R14));
     a.emit_pop(Size::S64, Location::GPR(GPR::R15));
+    a.emit_pop(Size::S64, Location::GPR(GPR::RBP));
 
     a.emit_ret();
 
@@ -8618,7 +10629,7 @@ pub(crate) fn gen_std_dynamic_import_trampoline(
     sig: &FunctionType,
     calling_convention: CallingConvention,
 ) -> FunctionBody {
-    let mut a = Assembler::new(0);
+    let mut a = VecAssembler::<X64Relocation>::new(0);
 
     // Allocate argument array.
     let stack_offset: usize = 16 * std::cmp::max(sig.params().len(), sig.results().len()) + 8; // 16 bytes each + 8 bytes sysv call padding
@@ -8707,14 +10718,27 @@ pub(crate) fn gen_std_dynamic_import_trampoline(
     // Call target.
     a.emit_call_location(Location::GPR(GPR::RAX));
 
-    // Fetch return value.
-    if !sig.results().is_empty() {
-        assert_eq!(sig.results().len(), 1);
-        a.emit_mov(
-            Size::S64,
-            Location::Memory(GPR::RSP, stack_padding as i32),
-            Location::GPR(GPR::RAX),
-        );
+    // Fetch return values. The results not returned in registers go to the caller's stack
+    // arguments, before RAX is loaded.
+    for i in (0..sig.results().len()).rev() {
+        let src_loc = Location::Memory(GPR::RSP, (stack_padding + i * 16) as _);
+        match Machine::get_return_location(i, calling_convention) {
+            Location::GPR(gpr) => {
+                a.emit_mov(Size::S64, src_loc, Location::GPR(gpr));
+            }
+            Location::Memory(_, _) => {
+                a.emit_mov(Size::S64, src_loc, Location::GPR(GPR::RAX));
+                a.emit_mov(
+                    Size::S64,
+                    Location::GPR(GPR::RAX),
+                    Location::Memory(
+                        GPR::RSP,
+                        (stack_padding * 2 + stack_offset + 8 + (i - 2) * 8) as _,
+                    ),
+                );
+            }
+            _ => unreachable!(),
+        }
     }
 
     // Release values array.
@@ -8741,7 +10765,7 @@ pub(crate) fn gen_import_call_trampoline(
     sig: &FunctionType,
     calling_convention: CallingConvention,
 ) -> CustomSection {
-    let mut a = Assembler::new(0);
+    let mut a = VecAssembler::<X64Relocation>::new(0);
 
     // TODO: ARM entry trampoline is not emitted.
 
@@ -8770,8 +10794,10 @@ pub(crate) fn gen_import_call_trampoline(
                     };
                     param_locations.push(loc);
                 }
-                // Copy Float arguments to XMM from GPR.
+                // Copy Float arguments to XMM from GPR. The XMM registers are allocated by slot,
+                // so the first parameter, in the slot after the VMContext, goes in XMM1.
                 let mut argalloc = ArgumentRegisterAllocator::default();
+                argalloc.next(Type::I64, calling_convention).unwrap(); // skip VMContext
                 for (i, ty) in sig.params().iter().enumerate() {
                     let prev_loc = param_locations[i];
                     match argalloc.next(*ty, calling_convention) {
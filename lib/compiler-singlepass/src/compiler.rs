@@ -7,13 +7,25 @@ use crate::codegen_x64::{
     CodegenError, FuncGen,
 };
 use crate::config::Singlepass;
+use crate::dwarf::{create_fde, create_systemv_cie, WriterRelocate};
+use crate::feature_check::FeatureCheck;
+use crate::metering;
+use crate::unwind::UnwindFrame;
+use crate::x64_decl::GPR;
+use gimli::write::{EhFrame, FrameTable};
 #[cfg(feature = "rayon")]
 use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use wasmer_compiler::wasmparser::{BinaryReaderError, Operator};
 use wasmer_compiler::{
-    Architecture, CallingConvention, Compilation, CompileError, CompileModuleInfo,
-    CompiledFunction, Compiler, CompilerConfig, CpuFeature, FunctionBody, FunctionBodyData,
-    ModuleTranslationState, OperatingSystem, SectionIndex, Target, TrapInformation,
+    operator_name, validate_module_with, Architecture, CallingConvention, Compilation,
+    CompileError, CompileModuleInfo, CompileProgress, CompileTiming, CompiledFunction, Compiler,
+    CompilerConfig, CpuFeature, CustomSection, Dwarf, Features, FunctionBody, FunctionBodyData,
+    FunctionLocation, FunctionStats, MachineStats, MiddlewareBinaryReader, ModuleMiddleware,
+    ModuleMiddlewareChain, ModuleResources, ModuleTranslationState, OperatingSystem, SectionIndex,
+    Target, TrapInformation, WasmError,
 };
 use wasmer_types::entity::{EntityRef, PrimaryMap};
 use wasmer_types::{
@@ -21,6 +33,12 @@ use wasmer_types::{
 };
 use wasmer_vm::{TrapCode, VMOffsets};
 
+/// Registers that the code generator only ever uses through register allocation.
+///
+/// All other registers are used implicitly by some instruction sequences (e.g. `RAX`/`RDX` for
+/// division, or the argument registers for calls), and so cannot be reserved.
+const RESERVABLE_GPRS: &[GPR] = &[GPR::RBX, GPR::R10, GPR::R11, GPR::R12, GPR::R13, GPR::R14];
+
 /// A compiler that compiles a WebAssembly module with Singlepass.
 /// It does the compilation in one pass
 pub struct SinglepassCompiler {
@@ -39,22 +57,16 @@ impl SinglepassCompiler {
     }
 }
 
-impl Compiler for SinglepassCompiler {
-    /// Compile the module using Singlepass, producing a compilation result with
-    /// associated relocations.
-    #[tracing::instrument(skip_all)]
-    fn compile_module(
-        &self,
-        target: &Target,
-        compile_info: &CompileModuleInfo,
-        module_translation: &ModuleTranslationState,
-        function_body_inputs: PrimaryMap<LocalFunctionIndex, FunctionBodyData<'_>>,
-    ) -> Result<Compilation, CompileError> {
+impl SinglepassCompiler {
+    /// Checks that the target and the configuration can be compiled for, and returns the calling
+    /// convention of the target.
+    fn check_target(&self, target: &Target) -> Result<CallingConvention, CompileError> {
         /*if target.triple().operating_system == OperatingSystem::Windows {
             return Err(CompileError::UnsupportedTarget(
                 OperatingSystem::Windows.to_string(),
             ));
         }*/
+        // The code generator and `Machine` only know about x86-64 registers and instructions.
         if target.triple().architecture != Architecture::X86_64 {
             return Err(CompileError::UnsupportedTarget(
                 target.triple().architecture.to_string(),
@@ -65,26 +77,47 @@ impl Compiler for SinglepassCompiler {
                 "x86_64 without AVX".to_string(),
             ));
         }
-        if compile_info.features.multi_value {
-            return Err(CompileError::UnsupportedFeature("multivalue".to_string()));
+        if let Some(gpr) = self
+            .config
+            .reserved_gprs
+            .iter()
+            .find(|r| !RESERVABLE_GPRS.contains(r))
+        {
+            return Err(CompileError::Codegen(format!(
+                "register {:?} cannot be reserved by the singlepass compiler",
+                gpr
+            )));
         }
-        let calling_convention = match target.triple().default_calling_convention() {
+        Ok(match target.triple().default_calling_convention() {
             Ok(CallingConvention::WindowsFastcall) => CallingConvention::WindowsFastcall,
             Ok(CallingConvention::SystemV) => CallingConvention::SystemV,
             //Ok(CallingConvention::AppleAarch64) => AppleAarch64,
             _ => panic!("Unsupported Calling convention for Singlepass compiler"),
-        };
+        })
+    }
 
-        let table_styles = &compile_info.table_styles;
+    /// Compiles the module, on the current rayon thread pool if the `rayon` feature is enabled.
+    ///
+    /// The functions may be compiled in any order, but they are collected in the order of their
+    /// indices, and everything built from them afterwards is built sequentially, so the result
+    /// doesn't depend on the scheduling of the threads.
+    fn compile_module_in_current_pool(
+        &self,
+        target: &Target,
+        compile_info: &CompileModuleInfo,
+        module_translation: &ModuleTranslationState,
+        function_body_inputs: PrimaryMap<LocalFunctionIndex, FunctionBodyData<'_>>,
+        progress: &CompileProgress,
+    ) -> Result<Compilation, CompileError> {
+        let calling_convention = self.check_target(target)?;
         let module = &compile_info.module;
-        let pointer_width = target
-            .triple()
-            .pointer_width()
-            .map_err(|()| {
-                CompileError::UnsupportedTarget("target with unknown pointer width".into())
-            })?
-            .bytes();
-        let vmoffsets = VMOffsets::new(pointer_width).with_module_info(&module);
+        let vmoffsets = vmoffsets_for(target, module)?;
+        // The operators emitted by the middlewares are validated against the module info
+        // they transformed.
+        let resources = ModuleResources::new(module, module_translation);
+        let total_code_size = AtomicUsize::new(0);
+        let mut timing = CompileTiming::default();
+        let trampolines_start = Instant::now();
         let import_idxs = 0..module.import_counts.functions as usize;
         let import_trampolines: PrimaryMap<SectionIndex, _> =
             tracing::info_span!("import_trampolines", n_imports = import_idxs.len()).in_scope(
@@ -105,51 +138,96 @@ impl Compiler for SinglepassCompiler {
                         .collect()
                 },
             );
+        timing.trampolines = trampolines_start.elapsed();
+        let codegen_start = Instant::now();
         let functions = function_body_inputs
             .iter()
             .collect::<Vec<(LocalFunctionIndex, &FunctionBodyData<'_>)>>()
             .into_par_iter_if_rayon()
             .map(|(i, input)| {
                 tracing::info_span!("function", i = i.index()).in_scope(|| {
-                    let reader =
-                        wasmer_compiler::FunctionReader::new(input.module_offset, input.data);
-                    let mut generator = FuncGen::new(
-                        module,
+                    if progress.is_cancelled() {
+                        return Err(CompileError::Cancelled);
+                    }
+                    let start = Instant::now();
+                    let compiled = self.compile_function_body(
+                        target,
+                        compile_info,
                         module_translation,
-                        &self.config,
                         &vmoffsets,
-                        &table_styles,
-                        i,
+                        &resources,
                         calling_convention,
-                    )
-                    .map_err(to_compile_error)?;
-
-                    let mut local_reader = reader.get_locals_reader()?;
-                    for _ in 0..local_reader.get_count() {
-                        let (count, ty) = local_reader.read()?;
-                        // Overflows feeding a local here have most likely already been caught by the
-                        // validator, but it is possible that the validator hasn't been run at all, or
-                        // that the validator does not impose any limits on the number of locals.
-                        generator.feed_local(count, ty);
-                    }
-
-                    generator.emit_head().map_err(to_compile_error)?;
-
-                    let mut operator_reader =
-                        reader.get_operators_reader()?.into_iter_with_offsets();
-                    while generator.has_control_frames() {
-                        let (op, pos) = tracing::info_span!("parsing-next-operator")
-                            .in_scope(|| operator_reader.next().unwrap())?;
-                        generator.set_srcloc(pos as u32);
-                        generator.feed_operator(op).map_err(to_compile_error)?;
+                        i,
+                        input,
+                    )?;
+                    let size = compiled.0.body.body.len();
+                    if let Some(limit) = self.config.max_total_code_size {
+                        let total = total_code_size.fetch_add(size, Ordering::Relaxed) + size;
+                        if total > limit {
+                            return Err(CompileError::CodeSizeExceeded {
+                                function: i,
+                                size: total,
+                                limit,
+                            });
+                        }
                     }
-
-                    Ok(generator.finalize(&input))
+                    let elapsed = start.elapsed();
+                    progress.function_compiled(i, size, elapsed);
+                    Ok((compiled, elapsed))
                 })
             })
-            .collect::<Result<Vec<CompiledFunction>, CompileError>>()?
+            .collect::<Result<
+                Vec<(
+                    (CompiledFunction, Option<MachineStats>, Option<UnwindFrame>),
+                    Duration,
+                )>,
+                CompileError,
+            >>()?;
+        timing.codegen = codegen_start.elapsed();
+        // The functions compiled in parallel are accounted for once they all are.
+        let functions = functions
+            .into_iter()
+            .map(|(compiled, elapsed)| {
+                timing.add_function(elapsed);
+                compiled
+            })
+            .collect::<Vec<_>>();
+        let trampolines_start = Instant::now();
+        let function_stats = if compile_info.collect_function_stats {
+            Some(
+                functions
+                    .iter()
+                    .map(|&(_, stats, _)| stats.unwrap_or_default())
+                    .collect::<FunctionStats>(),
+            )
+        } else {
+            None
+        };
+        // The frames of System V functions are described by a shared `.eh_frame` section.
+        let mut frame_table = FrameTable::default();
+        let cie_id = frame_table.add_cie(create_systemv_cie());
+        let mut has_fdes = false;
+        let functions = functions
             .into_iter() // TODO: why not just collect to PrimaryMap directly?
+            .enumerate()
+            .map(|(index, (function, _, unwind_frame))| {
+                if let Some(UnwindFrame::SystemV(ops)) = unwind_frame {
+                    let index = LocalFunctionIndex::new(index);
+                    let fde = create_fde(index, &ops, function.body.body.len());
+                    frame_table.add_fde(cie_id, fde);
+                    has_fdes = true;
+                }
+                function
+            })
             .collect::<PrimaryMap<LocalFunctionIndex, CompiledFunction>>();
+        let mut custom_sections = import_trampolines;
+        let debug = if has_fdes {
+            Some(Dwarf::new(
+                custom_sections.push(write_eh_frame(&mut frame_table)?),
+            ))
+        } else {
+            None
+        };
 
         let function_call_trampolines =
             tracing::info_span!("function_call_trampolines").in_scope(|| {
@@ -182,17 +260,352 @@ impl Compiler for SinglepassCompiler {
                     .collect::<PrimaryMap<FunctionIndex, FunctionBody>>()
             });
 
-        Ok(Compilation::new(
+        let compilation = Compilation::new(
             functions,
-            import_trampolines,
+            custom_sections,
             function_call_trampolines,
             dynamic_function_trampolines,
+            debug,
             None,
-            None,
-        ))
+        );
+        timing.trampolines += trampolines_start.elapsed();
+        let compilation = compilation.with_timing(timing);
+        Ok(match function_stats {
+            Some(stats) => compilation.with_function_stats(stats),
+            None => compilation,
+        })
+    }
+
+    /// Compiles the body of the local function `i`, returning it with its register allocation
+    /// statistics if they were asked for, and how to unwind its frame.
+    #[allow(clippy::too_many_arguments)]
+    fn compile_function_body(
+        &self,
+        target: &Target,
+        compile_info: &CompileModuleInfo,
+        module_translation: &ModuleTranslationState,
+        vmoffsets: &VMOffsets,
+        resources: &ModuleResources<'_>,
+        calling_convention: CallingConvention,
+        i: LocalFunctionIndex,
+        input: &FunctionBodyData<'_>,
+    ) -> Result<(CompiledFunction, Option<MachineStats>, Option<UnwindFrame>), CompileError> {
+        let module = &compile_info.module;
+        let reader = wasmer_compiler::FunctionReader::new(input.module_offset, input.data);
+        let new_generator = || -> Result<FuncGen, CompileError> {
+            let mut generator = FuncGen::new(
+                module,
+                module_translation,
+                &self.config,
+                vmoffsets,
+                target,
+                &compile_info.memory_styles,
+                &compile_info.table_styles,
+                i,
+                calling_convention,
+            )?;
+            if compile_info.collect_function_stats {
+                generator.enable_stats();
+            }
+            Ok(generator)
+        };
+        let mut generator = new_generator()?;
+        let mut validator = if self.config.middlewares.is_empty() {
+            None
+        } else {
+            Some(resources.function_validator(i, &compile_info.features, input.module_offset)?)
+        };
+        let mut operator_reader = MiddlewareBinaryReader::new(
+            reader.get_operators_reader()?,
+            self.config
+                .middlewares
+                .generate_function_middleware_chain(i),
+        );
+        let locate =
+            |error: CompileError, offset, op: Option<&Operator<'_>>| CompileError::Function {
+                location: FunctionLocation {
+                    local_index: i,
+                    index: module.func_index(i),
+                    name: module.function_names.get(&module.func_index(i)).cloned(),
+                    offset,
+                    operator: op.map(operator_name),
+                },
+                error: Box::new(error),
+            };
+        let locate_read_error = |error: WasmError| {
+            let offset = match &error {
+                WasmError::InvalidWebAssembly { offset, .. } => *offset,
+                _ => input.module_offset,
+            };
+            locate(error.into(), offset, None)
+        };
+        // Metering, omitting the frame pointer and the exception handlers need all the operators
+        // up-front.
+        let mut buffered = Vec::new();
+        if self.config.metering.is_some()
+            || self.config.omit_leaf_frame_pointers
+            || compile_info.features.exceptions
+        {
+            while !operator_reader.eof() {
+                buffered.push(operator_reader.read_operator().map_err(locate_read_error)?);
+            }
+        }
+        let fuel_costs = self
+            .config
+            .metering
+            .map(|cost_fn| metering::fuel_costs(buffered.iter().map(|(op, _)| op), cost_fn));
+        if let Some(fuel_costs) = &fuel_costs {
+            generator.enable_metering(fuel_costs.clone());
+        }
+
+        let mut locals = Vec::new();
+        let mut local_reader = reader.get_locals_reader()?;
+        for _ in 0..local_reader.get_count() {
+            let offset = local_reader.original_position();
+            let (count, ty) = local_reader.read()?;
+            if let Some(validator) = &mut validator {
+                validator
+                    .define_locals(offset, count, ty)
+                    .map_err(|e| locate(to_validate_error(e), offset, None))?;
+            }
+            // Too many locals have most likely already been caught by the validator,
+            // but it is possible that the validator hasn't been run at all, or that the
+            // validator does not impose any limits on the number of locals.
+            generator.feed_local(count, ty)?;
+            locals.push((count, ty));
+        }
+
+        if compile_info.features.exceptions
+            && buffered
+                .iter()
+                .any(|(op, _)| matches!(op, Operator::Try { .. }))
+        {
+            generator
+                .enable_exception_handlers()
+                .map_err(|e| locate(e, input.module_offset, None))?;
+        }
+        if self.config.omit_leaf_frame_pointers
+            && generator.can_omit_frame_pointer(buffered.iter().map(|(op, _)| op))
+        {
+            generator.omit_frame_pointer();
+        }
+        generator
+            .emit_head()
+            .map_err(|e| locate(to_compile_error(e), input.module_offset, None))?;
+
+        let mut next = 0;
+        while generator.has_control_frames() {
+            let (op, pos) = tracing::info_span!("parsing-next-operator")
+                .in_scope(|| match buffered.get(next) {
+                    Some(buffered) => {
+                        next += 1;
+                        Ok(buffered.clone())
+                    }
+                    None => operator_reader.read_operator(),
+                })
+                .map_err(locate_read_error)?;
+            if let Some(validator) = &mut validator {
+                validator
+                    .op(pos, &op)
+                    .map_err(|e| locate(to_validate_error(e), pos, Some(&op)))?;
+            }
+            generator.set_srcloc(pos as u32);
+            generator
+                .feed_operator(op.clone())
+                .map_err(|e| locate(to_compile_error(e), pos, Some(&op)))?;
+        }
+        if let Some(validator) = &mut validator {
+            let end = input.module_offset + input.data.len();
+            validator
+                .finish(end)
+                .map_err(|e| locate(to_validate_error(e), end, None))?;
+        }
+
+        // A function compiled without a frame pointer that needed one after all is compiled
+        // again with one, from the operators already read and validated.
+        if generator.needs_frame() {
+            generator = new_generator()?;
+            if let Some(fuel_costs) = fuel_costs {
+                generator.enable_metering(fuel_costs);
+            }
+            for &(count, ty) in &locals {
+                generator.feed_local(count, ty)?;
+            }
+            generator
+                .emit_head()
+                .map_err(|e| locate(to_compile_error(e), input.module_offset, None))?;
+            for (op, pos) in &buffered {
+                generator.set_srcloc(*pos as u32);
+                generator
+                    .feed_operator(op.clone())
+                    .map_err(|e| locate(to_compile_error(e), *pos, Some(op)))?;
+            }
+        }
+
+        let compiled = generator.finalize(input);
+        let size = compiled.0.body.body.len();
+        if let Some(limit) = self.config.max_function_code_size {
+            if size > limit {
+                return Err(CompileError::CodeSizeExceeded {
+                    function: i,
+                    size,
+                    limit,
+                });
+            }
+        }
+        Ok(compiled)
+    }
+}
+
+impl Compiler for SinglepassCompiler {
+    fn fingerprint(&self) -> String {
+        let config = &self.config;
+        format!(
+            "singlepass {} (NaN canonicalization: {}, stack check: {}, stack limit checks: {}, \
+             peephole: {}, frameless leaves: {}, max locals: {}, reserved registers: {:?}, \
+             metering: {:?}, epoch interruption: {}, intrinsics: {:?}, middlewares: {:?})",
+            env!("CARGO_PKG_VERSION"),
+            config.enable_nan_canonicalization,
+            config.enable_stack_check,
+            config.enable_stack_limit_checks,
+            config.enable_peephole,
+            config.omit_leaf_frame_pointers,
+            config.max_locals,
+            config.reserved_gprs,
+            config.metering,
+            config.enable_epoch_interruption,
+            config
+                .intrinsics
+                .iter()
+                .map(|intrinsic| &intrinsic.name)
+                .collect::<Vec<_>>(),
+            config.middlewares,
+        )
+    }
+
+    fn validate_module(&self, features: &Features, data: &[u8]) -> Result<(), CompileError> {
+        if !self.config.exhaustive_feature_check {
+            return validate_module_with(features, data, &mut ());
+        }
+        let mut check = FeatureCheck::default();
+        validate_module_with(features, data, &mut check)?;
+        check.finish()
+    }
+
+    /// The code generator only knows about a single memory, and modules of modules can't be
+    /// translated.
+    fn check_features(&self, features: &Features) -> Result<(), CompileError> {
+        let unsupported = [
+            ("module linking", features.module_linking),
+            ("multi-memory", features.multi_memory),
+        ];
+        match unsupported.iter().find(|(_, enabled)| *enabled) {
+            Some((feature, _)) => Err(CompileError::FeatureNotSupportedByCompiler(
+                feature.to_string(),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    fn get_middlewares(&self) -> &[Arc<dyn ModuleMiddleware>] {
+        &self.config.middlewares
+    }
+
+    /// Compile the module using Singlepass, producing a compilation result with
+    /// associated relocations.
+    #[tracing::instrument(skip_all)]
+    fn compile_module(
+        &self,
+        target: &Target,
+        compile_info: &CompileModuleInfo,
+        module_translation: &ModuleTranslationState,
+        function_body_inputs: PrimaryMap<LocalFunctionIndex, FunctionBodyData<'_>>,
+        progress: &CompileProgress,
+    ) -> Result<Compilation, CompileError> {
+        #[cfg(feature = "rayon")]
+        if let Some(num_threads) = self.config.num_threads {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(num_threads)
+                .build()
+                .map_err(|e| {
+                    CompileError::Resource(format!("failed to start compilation threads: {}", e))
+                })?;
+            return pool.install(|| {
+                self.compile_module_in_current_pool(
+                    target,
+                    compile_info,
+                    module_translation,
+                    function_body_inputs,
+                    progress,
+                )
+            });
+        }
+        self.compile_module_in_current_pool(
+            target,
+            compile_info,
+            module_translation,
+            function_body_inputs,
+            progress,
+        )
+    }
+
+    /// Compile a single function using Singlepass, as `compile_module` would, with the
+    /// `.eh_frame` section describing its frame on System V targets.
+    #[tracing::instrument(skip_all, fields(i = index.index()))]
+    fn compile_function(
+        &self,
+        target: &Target,
+        compile_info: &CompileModuleInfo,
+        module_translation: &ModuleTranslationState,
+        index: LocalFunctionIndex,
+        input: &FunctionBodyData<'_>,
+    ) -> Result<(CompiledFunction, Option<CustomSection>), CompileError> {
+        let calling_convention = self.check_target(target)?;
+        let module = &compile_info.module;
+        let vmoffsets = vmoffsets_for(target, module)?;
+        let resources = ModuleResources::new(module, module_translation);
+        let (function, _, unwind_frame) = self.compile_function_body(
+            target,
+            compile_info,
+            module_translation,
+            &vmoffsets,
+            &resources,
+            calling_convention,
+            index,
+            input,
+        )?;
+        let eh_frame = match unwind_frame {
+            Some(UnwindFrame::SystemV(ops)) => {
+                let mut frame_table = FrameTable::default();
+                let cie_id = frame_table.add_cie(create_systemv_cie());
+                frame_table.add_fde(cie_id, create_fde(index, &ops, function.body.body.len()));
+                Some(write_eh_frame(&mut frame_table)?)
+            }
+            _ => None,
+        };
+        Ok((function, eh_frame))
     }
 }
 
+/// The offsets of the `VMContext` of `module` on `target`.
+fn vmoffsets_for(target: &Target, module: &ModuleInfo) -> Result<VMOffsets, CompileError> {
+    let pointer_width = target
+        .triple()
+        .pointer_width()
+        .map_err(|()| CompileError::UnsupportedTarget("target with unknown pointer width".into()))?
+        .bytes();
+    Ok(VMOffsets::new(pointer_width).with_module_info(module))
+}
+
+/// Write the `.eh_frame` section describing the frames of `frame_table`.
+fn write_eh_frame(frame_table: &mut FrameTable) -> Result<CustomSection, CompileError> {
+    let mut eh_frame = EhFrame(WriterRelocate::new());
+    frame_table.write_eh_frame(&mut eh_frame).map_err(|e| {
+        CompileError::Codegen(format!("failed to write the .eh_frame section: {}", e))
+    })?;
+    Ok(eh_frame.0.into_section())
+}
+
 trait ToCompileError {
     fn to_compile_error(self) -> CompileError;
 }
@@ -207,6 +620,11 @@ fn to_compile_error<T: ToCompileError>(x: T) -> CompileError {
     x.to_compile_error()
 }
 
+fn to_validate_error(e: BinaryReaderError) -> CompileError {
+    // The offset of the error is part of the location of the function.
+    CompileError::Validate(e.message().to_string())
+}
+
 trait IntoParIterIfRayon {
     type Output;
     fn into_par_iter_if_rayon(self) -> Self::Output;
@@ -231,9 +649,15 @@ impl<T: IntoIterator> IntoParIterIfRayon for T {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use iced_x86::{Decoder, DecoderOptions, FlowControl, Mnemonic, OpKind, Register};
+    use std::collections::HashMap;
     use std::str::FromStr;
+    use std::sync::Mutex;
     use target_lexicon::triple;
-    use wasmer_compiler::{CpuFeature, Features, Triple};
+    use wasmer_compiler::{
+        AsmLine, CancellationToken, CpuFeature, Features, ModuleEnvironment, Triple,
+    };
+    use wasmer_types::{Pages, SignatureIndex};
     use wasmer_vm::{MemoryStyle, TableStyle};
 
     fn dummy_compilation_ingredients<'a>() -> (
@@ -246,6 +670,7 @@ mod tests {
             module: Arc::new(ModuleInfo::new()),
             memory_styles: PrimaryMap::<MemoryIndex, MemoryStyle>::new(),
             table_styles: PrimaryMap::<TableIndex, TableStyle>::new(),
+            collect_function_stats: false,
         };
         let module_translation = ModuleTranslationState::new();
         let function_body_inputs = PrimaryMap::<LocalFunctionIndex, FunctionBodyData<'_>>::new();
@@ -268,19 +693,806 @@ mod tests {
         // Compile for 32bit Linux
         let linux32 = Target::new(triple!("i686-unknown-linux-gnu"), CpuFeature::for_host());
         let (mut info, translation, inputs) = dummy_compilation_ingredients();
-        let result = compiler.compile_module(&linux32, &mut info, &translation, inputs);
+        let result = compiler.compile_module(
+            &linux32,
+            &mut info,
+            &translation,
+            inputs,
+            &CompileProgress::default(),
+        );
         match result.unwrap_err() {
             CompileError::UnsupportedTarget(name) => assert_eq!(name, "i686"),
             error => panic!("Unexpected error: {:?}", error),
         };
 
+        // Compile for 64bit ARM Linux
+        let aarch64 = Target::new(triple!("aarch64-unknown-linux-gnu"), CpuFeature::for_host());
+        let (mut info, translation, inputs) = dummy_compilation_ingredients();
+        let result = compiler.compile_module(
+            &aarch64,
+            &mut info,
+            &translation,
+            inputs,
+            &CompileProgress::default(),
+        );
+        match result.unwrap_err() {
+            CompileError::UnsupportedTarget(name) => assert_eq!(name, "aarch64"),
+            error => panic!("Unexpected error: {:?}", error),
+        };
+
         // Compile for win32
         let win32 = Target::new(triple!("i686-pc-windows-gnu"), CpuFeature::for_host());
         let (mut info, translation, inputs) = dummy_compilation_ingredients();
-        let result = compiler.compile_module(&win32, &mut info, &translation, inputs);
+        let result = compiler.compile_module(
+            &win32,
+            &mut info,
+            &translation,
+            inputs,
+            &CompileProgress::default(),
+        );
         match result.unwrap_err() {
             CompileError::UnsupportedTarget(name) => assert_eq!(name, "i686"), // Windows should be checked before architecture
             error => panic!("Unexpected error: {:?}", error),
         };
     }
+
+    /// Compiles `wat` with `config` for x86_64 Linux.
+    fn compile_wat(config: Singlepass, wat: &str) -> Compilation {
+        compile_wasm(config, &wat::parse_str(wat).unwrap()).unwrap()
+    }
+
+    /// Compiles `wasm` with `config` for x86_64 Linux, without validating it first.
+    fn compile_wasm(config: Singlepass, wasm: &[u8]) -> Result<Compilation, CompileError> {
+        let target = Target::new(triple!("x86_64-unknown-linux-gnu"), CpuFeature::for_host());
+        compile_wasm_for(&target, config, wasm, &CompileProgress::default())
+    }
+
+    fn compile_wasm_for(
+        target: &Target,
+        config: Singlepass,
+        wasm: &[u8],
+        progress: &CompileProgress,
+    ) -> Result<Compilation, CompileError> {
+        let translation = ModuleEnvironment::new().translate(wasm).unwrap();
+        let memory_styles = translation
+            .module
+            .memories
+            .values()
+            .map(|_| MemoryStyle::Static {
+                bound: Pages(0x1_0000),
+                offset_guard_size: 0x8000_0000,
+            })
+            .collect();
+        let compile_info = CompileModuleInfo {
+            features: Features::new(),
+            module: Arc::new(translation.module),
+            memory_styles,
+            table_styles: PrimaryMap::new(),
+            collect_function_stats: false,
+        };
+        let compiler = SinglepassCompiler::new(config);
+        compiler.compile_module(
+            target,
+            &compile_info,
+            translation.module_translation_state.as_ref().unwrap(),
+            translation.function_body_inputs,
+            progress,
+        )
+    }
+
+    fn count_branches(code: &[u8]) -> usize {
+        Decoder::new(64, code, DecoderOptions::NONE)
+            .iter()
+            .filter(|instruction| {
+                matches!(
+                    instruction.flow_control(),
+                    FlowControl::ConditionalBranch
+                        | FlowControl::UnconditionalBranch
+                        | FlowControl::IndirectBranch
+                )
+            })
+            .count()
+    }
+
+    #[test]
+    fn fp_min_max_do_not_branch() {
+        let mut wat = String::from("(module");
+        for ty in ["f32", "f64"] {
+            for op in ["add", "min", "max"] {
+                wat.push_str(&format!(
+                    "(func (param {ty} {ty}) (result {ty}) ({ty}.{op} (local.get 0) (local.get 1)))
+                     (func (param {ty}) (result {ty}) ({ty}.{op} (local.get 0) ({ty}.const -0)))",
+                    ty = ty,
+                    op = op,
+                ));
+            }
+        }
+        wat.push(')');
+        let branches: Vec<usize> = compile_wat(Singlepass::default(), &wat)
+            .get_function_bodies()
+            .values()
+            .map(|body| count_branches(&body.body))
+            .collect();
+        // The function prologues and epilogues have some branches of their own, so the functions
+        // are compared with the same ones using `add`, which is lowered without any.
+        for (ty, functions) in ["f32", "f64"].iter().zip(branches.chunks(6)) {
+            let (add, min_max) = functions.split_at(2);
+            assert_eq!(min_max, [add, add].concat(), "{}.min and {}.max", ty, ty);
+        }
+    }
+
+    #[cfg(feature = "debug-asm")]
+    #[test]
+    fn debug_asm_lists_instructions_by_operator() {
+        let wat = r#"(module
+            (func (param i32 i32) (result i32) (i32.add (local.get 0) (local.get 1))))"#;
+        assert!(compile_wat(Singlepass::default(), wat)
+            .get_function_asm()
+            .is_none());
+
+        let mut config = Singlepass::default();
+        config.emit_debug_asm(true);
+        let compilation = compile_wat(config, wat);
+        let listing = &compilation.get_function_asm().unwrap()[LocalFunctionIndex::new(0)];
+        let body_len = compilation.get(LocalFunctionIndex::new(0)).body.body.len();
+
+        let operators: Vec<&str> = listing
+            .lines
+            .iter()
+            .filter_map(|line| match line {
+                AsmLine::Operator(operator) => Some(&operator[..]),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            operators,
+            [
+                "LocalGet { local_index: 0 }",
+                "LocalGet { local_index: 1 }",
+                "I32Add",
+                "End"
+            ]
+        );
+        let offsets: Vec<u32> = listing
+            .lines
+            .iter()
+            .filter_map(|line| match line {
+                AsmLine::Instruction { offset, .. } => Some(*offset),
+                _ => None,
+            })
+            .collect();
+        assert!(offsets.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(offsets.iter().all(|&offset| (offset as usize) < body_len));
+
+        let text = listing.to_string();
+        let add = text.find(";; I32Add\n").unwrap();
+        let end = text.find(";; End\n").unwrap();
+        assert!(text[add..end].contains(": add S32, "), "{}", text);
+    }
+
+    /// The module of the Windows trampoline tests: an export taking more parameters than
+    /// there are argument registers, ints and floats interleaved, forwarded to an import.
+    const MIXED_PARAMS_WAT: &str = r#"(module
+        (import "env" "record" (func $record (param i32 f32 i64 f64 i32 f32 i64 f64)))
+        (func (export "mix") (param i32 f32 i64 f64 i32 f32 i64 f64)
+           (call $record (local.get 0) (local.get 1) (local.get 2) (local.get 3)
+                         (local.get 4) (local.get 5) (local.get 6) (local.get 7))))"#;
+
+    fn compile_wat_for_windows(wat: &str) -> Compilation {
+        let target = Target::new(triple!("x86_64-pc-windows-msvc"), CpuFeature::for_host());
+        compile_wasm_for(
+            &target,
+            Singlepass::default(),
+            &wat::parse_str(wat).unwrap(),
+            &CompileProgress::default(),
+        )
+        .unwrap()
+    }
+
+    /// What a register or a stack slot holds in `interpret_std_trampoline`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum TrampolineValue {
+        /// The value at this offset of the `args_rets` array.
+        Arg(i64),
+    }
+
+    /// The state of the machine at a point of a trampoline: RSP relative to its value at the
+    /// entry, where the return address is, and the values of the registers and stack slots
+    /// copied from the `args_rets` array, the slots by their offset from the entry RSP.
+    #[derive(Debug, Default, Clone)]
+    struct TrampolineState {
+        rsp: i64,
+        registers: HashMap<Register, TrampolineValue>,
+        stack: HashMap<i64, TrampolineValue>,
+    }
+
+    /// Follow the entry trampoline `code`, which reads the `args_rets` array through R14, and
+    /// return the state at the call of the function, and where each XMM register saved by
+    /// `movdqu` is stored and then loaded from, relative to the entry RSP.
+    fn interpret_std_trampoline(
+        code: &[u8],
+    ) -> (TrampolineState, HashMap<Register, (i64, Option<i64>)>) {
+        let mut state = TrampolineState::default();
+        let mut at_call = None;
+        let mut saved_xmms = HashMap::new();
+        for instruction in Decoder::new(64, code, DecoderOptions::NONE).iter() {
+            let memory_offset = |state: &TrampolineState| {
+                assert_eq!(
+                    instruction.memory_base(),
+                    Register::RSP,
+                    "{:?}",
+                    instruction
+                );
+                state.rsp + instruction.memory_displacement64() as i64
+            };
+            match (
+                instruction.mnemonic(),
+                instruction.op0_kind(),
+                instruction.op1_kind(),
+            ) {
+                (Mnemonic::Push, ..) => state.rsp -= 8,
+                (Mnemonic::Pop, ..) => state.rsp += 8,
+                (Mnemonic::Sub, OpKind::Register, _)
+                    if instruction.op0_register() == Register::RSP =>
+                {
+                    state.rsp -= instruction.immediate(1) as i64
+                }
+                (Mnemonic::Add, OpKind::Register, _)
+                    if instruction.op0_register() == Register::RSP =>
+                {
+                    state.rsp += instruction.immediate(1) as i64
+                }
+                (Mnemonic::Mov, OpKind::Register, OpKind::Register) => {
+                    match state.registers.get(&instruction.op1_register()).copied() {
+                        Some(value) => state.registers.insert(instruction.op0_register(), value),
+                        None => state.registers.remove(&instruction.op0_register()),
+                    };
+                }
+                (Mnemonic::Mov, OpKind::Register, OpKind::Memory)
+                    if instruction.memory_base() == Register::R14 =>
+                {
+                    let offset = instruction.memory_displacement64() as i64;
+                    state
+                        .registers
+                        .insert(instruction.op0_register(), TrampolineValue::Arg(offset));
+                }
+                (Mnemonic::Mov, OpKind::Memory, OpKind::Register)
+                    if instruction.memory_base() != Register::R14 =>
+                {
+                    let offset = memory_offset(&state);
+                    match state.registers.get(&instruction.op1_register()) {
+                        Some(value) => state.stack.insert(offset, *value),
+                        None => state.stack.remove(&offset),
+                    };
+                }
+                (Mnemonic::Movdqu, OpKind::Memory, OpKind::Register) => {
+                    assert!(at_call.is_none());
+                    saved_xmms.insert(instruction.op1_register(), (memory_offset(&state), None));
+                }
+                (Mnemonic::Movdqu, OpKind::Register, OpKind::Memory) => {
+                    assert!(at_call.is_some());
+                    let saved = saved_xmms.get_mut(&instruction.op0_register()).unwrap();
+                    saved.1 = Some(memory_offset(&state));
+                }
+                (Mnemonic::Call, ..) => at_call = Some(state.clone()),
+                _ => {}
+            }
+        }
+        assert_eq!(state.rsp, 0);
+        (
+            at_call.expect("the trampoline calls the function"),
+            saved_xmms,
+        )
+    }
+
+    #[test]
+    fn windows_std_trampolines_pass_arguments_where_functions_read_them() {
+        let compilation = compile_wat_for_windows(MIXED_PARAMS_WAT);
+        let trampoline = &compilation.get_function_call_trampolines()[SignatureIndex::new(0)];
+        let (at_call, saved_xmms) = interpret_std_trampoline(&trampoline.body);
+
+        // The stack is 16-byte aligned at the call, the entry RSP being 8 bytes off.
+        assert_eq!(at_call.rsp.rem_euclid(16), 8);
+        // The vmctx stays in RCX, the first 3 parameters go in the next argument registers,
+        // whatever their type, and the others on the stack after the 32 bytes of shadow space.
+        for (register, param) in [(Register::RDX, 0), (Register::R8, 1), (Register::R9, 2)] {
+            assert_eq!(
+                at_call.registers.get(&register),
+                Some(&TrampolineValue::Arg(16 * param)),
+                "{:?}",
+                register
+            );
+        }
+        for param in 3..8 {
+            let slot = at_call.rsp + 32 + 8 * (param - 3);
+            assert_eq!(
+                at_call.stack.get(&slot),
+                Some(&TrampolineValue::Arg(16 * param)),
+                "parameter {}",
+                param
+            );
+        }
+
+        // The function reads them from there: its RBP is 16 bytes below the RSP of the call,
+        // past the return address and the saved RBP.
+        let body = &compilation.get_function_bodies()[LocalFunctionIndex::new(0)].body;
+        let reads: Vec<i64> = Decoder::new(64, body, DecoderOptions::NONE)
+            .iter()
+            .filter(|instruction| {
+                instruction.op1_kind() == OpKind::Memory
+                    && instruction.memory_base() == Register::RBP
+            })
+            .map(|instruction| instruction.memory_displacement64() as i64)
+            .collect();
+        for param in 3..8 {
+            let offset = 16 + 32 + 8 * (param - 3);
+            assert!(reads.contains(&offset), "{} not in {:?}", offset, reads);
+        }
+
+        // The XMM registers callee-saved on Windows the function may clobber are restored from
+        // where they were saved, above the arguments.
+        for xmm in [
+            Register::XMM6,
+            Register::XMM7,
+            Register::XMM8,
+            Register::XMM9,
+            Register::XMM10,
+        ] {
+            let (saved, restored) = saved_xmms[&xmm];
+            assert_eq!(restored, Some(saved), "{:?}", xmm);
+            assert!(saved >= at_call.rsp + 32 + 8 * 5, "{:?}", xmm);
+            assert!(saved + 16 <= -16, "{:?}", xmm);
+        }
+    }
+
+    #[test]
+    fn windows_import_trampolines_pass_floats_in_their_slots() {
+        let compilation = compile_wat_for_windows(MIXED_PARAMS_WAT);
+        let sections = compilation.get_custom_sections();
+        let trampoline = sections[SectionIndex::new(0)].bytes.as_slice();
+        // The compiled code passes all the parameters in integer registers. The host expects
+        // the floats in the XMM register of their slot: the first parameter is in the slot
+        // after the vmctx, so the `f32` is in the third one, and the `f64` after it is already
+        // on the stack.
+        let moves: Vec<(Register, Register)> = Decoder::new(64, trampoline, DecoderOptions::NONE)
+            .iter()
+            .filter(|instruction| instruction.mnemonic() == Mnemonic::Movq)
+            .map(|instruction| (instruction.op0_register(), instruction.op1_register()))
+            .collect();
+        assert_eq!(moves, [(Register::XMM2, Register::R8)]);
+    }
+
+    #[test]
+    fn too_many_locals_is_an_error() {
+        // A function declaring 2^20 `i64` locals, which the validator would have rejected.
+        #[rustfmt::skip]
+        let wasm = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+            // The type `[] -> []`.
+            0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+            0x03, 0x02, 0x01, 0x00,
+            // The body: one run of locals and `end`.
+            0x0a, 0x08, 0x01, 0x06, 0x01, 0x80, 0x80, 0x40, 0x7e, 0x0b,
+        ];
+        match compile_wasm(Singlepass::default(), &wasm).unwrap_err() {
+            CompileError::CodegenTooManyLocals { count, max } => {
+                assert_eq!((count, max), (1 << 20, 50_000))
+            }
+            error => panic!("Unexpected error: {:?}", error),
+        }
+
+        let mut config = Singlepass::default();
+        config.max_locals((1 << 20) - 1);
+        match compile_wasm(config, &wasm).unwrap_err() {
+            CompileError::CodegenTooManyLocals { count, .. } => assert_eq!(count, 1 << 20),
+            error => panic!("Unexpected error: {:?}", error),
+        }
+
+        // Params count too.
+        let mut config = Singlepass::default();
+        config.max_locals(1);
+        let wat = "(module (func (param i32 i32)))";
+        match compile_wasm(config, &wat::parse_str(wat).unwrap()).unwrap_err() {
+            CompileError::CodegenTooManyLocals { count, max } => assert_eq!((count, max), (2, 1)),
+            error => panic!("Unexpected error: {:?}", error),
+        }
+
+        let mut config = Singlepass::default();
+        config.max_locals(1 << 20);
+        assert!(compile_wasm(config, &wasm).is_ok());
+    }
+
+    #[test]
+    fn large_frames_are_probed_page_by_page() {
+        let wat = format!("(module (func (local{})))", " i64".repeat(10_000));
+        for &page_size in &[4096, 16384, 65536] {
+            let target = Target::new(triple!("x86_64-unknown-linux-gnu"), CpuFeature::for_host())
+                .with_page_size(page_size);
+            let compilation = compile_wasm_for(
+                &target,
+                Singlepass::default(),
+                &wat::parse_str(&wat).unwrap(),
+                &CompileProgress::default(),
+            )
+            .unwrap();
+            let body = &compilation.get_function_bodies()[LocalFunctionIndex::new(0)].body;
+
+            // Offsets relative to RBP of the stores, up to the zeroing of the locals.
+            let mut frame_size = None;
+            let mut stores = vec![];
+            for instruction in Decoder::new(64, body, DecoderOptions::NONE).iter() {
+                if instruction.mnemonic() == Mnemonic::Sub
+                    && instruction.op0_register() == Register::RSP
+                    && frame_size.is_none()
+                {
+                    frame_size = Some(instruction.immediate(1) as i64);
+                } else if instruction.mnemonic() == Mnemonic::Stosq {
+                    break;
+                } else if instruction.op0_kind() == OpKind::Memory
+                    && instruction.memory_base() == Register::RBP
+                {
+                    stores.push(instruction.memory_displacement64() as i64);
+                }
+            }
+            let frame_size = frame_size.unwrap();
+            assert!(frame_size > 65536);
+
+            // Each page-sized window of the frame is stored into, and a store never lands more
+            // than a page below the lowest one before it.
+            let page_size = page_size as i64;
+            for window in 0..(frame_size + page_size - 1) / page_size {
+                let range = -(window + 1) * page_size..-window * page_size;
+                assert!(stores.iter().any(|offset| range.contains(offset)));
+            }
+            let mut lowest = 0;
+            for &offset in &stores {
+                assert!(offset >= lowest - page_size, "{} below {}", offset, lowest);
+                lowest = std::cmp::min(lowest, offset);
+            }
+            assert_eq!(lowest, -frame_size);
+        }
+    }
+
+    #[test]
+    fn progress_is_reported_per_function() {
+        let target = Target::new(triple!("x86_64-unknown-linux-gnu"), CpuFeature::for_host());
+        let wasm = wat::parse_str(format!("(module {})", "(func)".repeat(100))).unwrap();
+
+        let reported = Arc::new(Mutex::new(vec![]));
+        let progress = CompileProgress::new().on_function_compiled({
+            let reported = reported.clone();
+            move |index, code_size, _| reported.lock().unwrap().push((index, code_size))
+        });
+        let compilation =
+            compile_wasm_for(&target, Singlepass::default(), &wasm, &progress).unwrap();
+        let mut reported = reported.lock().unwrap().clone();
+        reported.sort();
+        let expected = compilation
+            .get_function_bodies()
+            .iter()
+            .map(|(index, body)| (index, body.body.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(reported, expected);
+
+        let token = CancellationToken::new();
+        token.cancel();
+        let progress = CompileProgress::new().cancellation_token(token);
+        match compile_wasm_for(&target, Singlepass::default(), &wasm, &progress).unwrap_err() {
+            CompileError::Cancelled => {}
+            error => panic!("Unexpected error: {:?}", error),
+        }
+    }
+
+    #[test]
+    fn compilation_does_not_depend_on_the_number_of_threads() {
+        let target = Target::new(triple!("x86_64-unknown-linux-gnu"), CpuFeature::for_host());
+        let mut wat = String::from(
+            r#"(module
+                (import "env" "f" (func $imported (param i32) (result i32)))
+                (memory 1)
+                (table 1 funcref)"#,
+        );
+        for i in 0..200 {
+            wat.push_str(&format!(
+                r#"(func (param i32) (result i32)
+                    (i32.store (local.get 0) (i32.const {i}))
+                    (i32.div_s (i32.load (local.get 0)) (local.get 0))
+                    (call $imported)
+                    (call_indirect (param i32) (result i32) (i32.const 0))
+                    (call {}))"#,
+                (i + 1) % 200 + 1,
+                i = i
+            ));
+        }
+        wat.push(')');
+        let wasm = wat::parse_str(&wat).unwrap();
+
+        let compile = |num_threads| {
+            let mut config = Singlepass::default();
+            config.num_threads(Some(num_threads));
+            // Only the time the compilation took differs from one to the next.
+            compile_wasm_for(&target, config, &wasm, &CompileProgress::default())
+                .unwrap()
+                .with_timing(CompileTiming::default())
+        };
+        let sequential = compile(1);
+        for _ in 0..4 {
+            assert_eq!(compile(8), sequential);
+        }
+    }
+
+    #[test]
+    fn taken_functions_are_what_the_getters_copy() {
+        let mut wat = String::from("(module (memory 1)");
+        for i in 0..20 {
+            wat.push_str(&format!(
+                r#"(func (param i32) (result i32)
+                    (i32.store (local.get 0) (i32.const {i}))
+                    (i32.div_u (i32.load (local.get 0)) (local.get 0))
+                    (call {}))"#,
+                (i + 1) % 20,
+                i = i
+            ));
+        }
+        wat.push(')');
+        let mut compilation = compile_wat(Singlepass::default(), &wat);
+        let bodies = compilation.get_function_bodies();
+        let relocations = compilation.get_relocations();
+        let jt_offsets = compilation.get_jt_offsets();
+        let frame_info = compilation.get_frame_info();
+
+        let functions = compilation.take_functions();
+        assert!(compilation.is_empty());
+        assert_eq!(functions.len(), bodies.len());
+        for (index, function) in functions.iter() {
+            assert_eq!(function.body, bodies[index]);
+            assert_eq!(function.relocations, relocations[index]);
+            assert_eq!(function.jt_offsets, jt_offsets[index]);
+            assert_eq!(function.frame_info, frame_info[index]);
+            // The functions are kept until the module is compiled, without spare capacity.
+            assert_eq!(function.relocations.capacity(), function.relocations.len());
+            let traps = &function.frame_info.traps;
+            assert_eq!(traps.capacity(), traps.len());
+            let instructions = &function.frame_info.address_map.instructions;
+            assert_eq!(instructions.capacity(), instructions.len());
+        }
+    }
+
+    #[test]
+    fn leaf_functions_can_omit_the_frame_pointer() {
+        let wat = format!(
+            r#"(module
+                (func $leaf (param i64 i64) (result i64) (local i64 i64 i64)
+                    (local.set 2 (i64.add (local.get 0) (local.get 1)))
+                    (local.set 3 (i64.mul (local.get 2) (local.get 0)))
+                    (local.set 4 (i64.sub (local.get 3) (local.get 1)))
+                    (local.get 4))
+                (func (param i64) (result i64)
+                    (call $leaf (local.get 0) (local.get 0)))
+                (func (param i64) (result i64)
+                    {} {}))"#,
+            "(local.get 0) ".repeat(10),
+            "(i64.add) ".repeat(9),
+        );
+        let sets_up_rbp = |body: &[u8]| {
+            Decoder::new(64, body, DecoderOptions::NONE)
+                .iter()
+                .any(|instruction| {
+                    instruction.mnemonic() == Mnemonic::Mov
+                        && instruction.op0_kind() == OpKind::Register
+                        && instruction.op0_register() == Register::RBP
+                        && instruction.op1_kind() == OpKind::Register
+                        && instruction.op1_register() == Register::RSP
+                })
+        };
+
+        let compilation = compile_wat(Singlepass::default(), &wat);
+        for (index, function) in compilation.get_function_bodies().iter() {
+            assert!(sets_up_rbp(&function.body), "{:?}", index);
+            assert!(compilation.get(index).frame_info.frame_layout.is_some());
+        }
+
+        let mut config = Singlepass::default();
+        config.omit_leaf_frame_pointers(true);
+        let compilation = compile_wat(config, &wat);
+        let bodies = compilation.get_function_bodies();
+        let leaf = LocalFunctionIndex::new(0);
+        assert!(!sets_up_rbp(&bodies[leaf].body));
+        assert!(compilation.get(leaf).frame_info.frame_layout.is_none());
+        // The caller calls, and the last function needs more stack values than there are
+        // registers, so it is compiled again with a frame pointer.
+        for index in [1, 2].iter().map(|&i| LocalFunctionIndex::new(i)) {
+            assert!(sets_up_rbp(&bodies[index].body), "{:?}", index);
+            assert!(compilation.get(index).frame_info.frame_layout.is_some());
+        }
+
+        // The leaf pushes the five registers of its locals, RBP included, and R15, then keeps
+        // RSP 16-byte aligned, the return address leaving it 8 bytes off.
+        let mut pushed = vec![];
+        let mut rsp = -8;
+        for instruction in Decoder::new(64, &bodies[leaf].body, DecoderOptions::NONE).iter() {
+            match instruction.mnemonic() {
+                Mnemonic::Push => {
+                    pushed.push(instruction.op0_register());
+                    rsp -= 8;
+                }
+                Mnemonic::Sub if instruction.op0_register() == Register::RSP => {
+                    rsp -= instruction.immediate(1) as i64;
+                }
+                _ => break,
+            }
+        }
+        assert_eq!(
+            pushed,
+            [
+                Register::R12,
+                Register::R13,
+                Register::R14,
+                Register::RBX,
+                Register::RBP,
+                Register::R15
+            ]
+        );
+        assert_eq!(rsp.rem_euclid(16), 0);
+    }
+
+    #[test]
+    fn memory_base_is_loaded_once_per_basic_block() {
+        let store =
+            |offset: usize| format!("(i32.store offset={} (local.get 0) (i32.const 1))", offset);
+        let stores = |n: usize| (0..n).map(|i| store(4 * i)).collect::<String>();
+        let wat = format!(
+            "(module (memory 1)
+                (func (param i32))
+                (func (param i32) {})
+                (func (param i32) {})
+                (func (param i32) {} (block {}) {}))",
+            stores(1),
+            stores(10),
+            store(0),
+            store(4),
+            store(8),
+        );
+        // The displacements of the loads from the vmctx.
+        let vmctx_loads = |body: &[u8]| {
+            Decoder::new(64, body, DecoderOptions::NONE)
+                .iter()
+                .filter(|instruction| {
+                    instruction.mnemonic() == Mnemonic::Mov
+                        && instruction.op1_kind() == OpKind::Memory
+                        && instruction.memory_base() == Register::R15
+                })
+                .map(|instruction| instruction.memory_displacement64())
+                .collect::<Vec<_>>()
+        };
+        let compilation = compile_wat(Singlepass::default(), &wat);
+        let loads = compilation
+            .get_function_bodies()
+            .values()
+            .map(|body| vmctx_loads(&body.body))
+            .collect::<Vec<_>>();
+
+        // The base and the length of the memory, for the bounds checks, are loaded once.
+        let (empty, one, ten, blocks) = (&loads[0], &loads[1], &loads[2], &loads[3]);
+        assert_eq!(one.len(), empty.len() + 2);
+        let mut distinct = one.clone();
+        distinct.sort_unstable();
+        distinct.dedup();
+        assert_eq!(distinct.len(), one.len());
+        assert_eq!(ten, one);
+        // And once per basic block: before, in and after the nested block.
+        assert_eq!(blocks.len(), empty.len() + 3 * 2);
+    }
+
+    #[test]
+    fn private_globals_are_accessed_in_the_vmctx() {
+        let bump = |global: &str| {
+            format!(
+                "(func (global.set {0} (i64.add (global.get {0}) (i64.const 1))))",
+                global
+            )
+        };
+        let wat = format!(
+            r#"(module
+                (global $private (mut i64) (i64.const 0))
+                (global $exported (export "exported") (mut i64) (i64.const 0))
+                (func)
+                {}
+                {})"#,
+            bump("$private"),
+            bump("$exported"),
+        );
+        // The displacements of the accesses to the vmctx, and the number of accesses through
+        // other registers than the vmctx and the stack pointers.
+        let accesses = |body: &[u8]| {
+            let mut vmctx = Vec::new();
+            let mut others = 0;
+            for instruction in Decoder::new(64, body, DecoderOptions::NONE).iter() {
+                match instruction.memory_base() {
+                    Register::R15 => vmctx.push(instruction.memory_displacement64()),
+                    Register::None | Register::RSP | Register::RBP | Register::RIP => {}
+                    _ => others += 1,
+                }
+            }
+            vmctx.sort_unstable();
+            (vmctx, others)
+        };
+        let compilation = compile_wat(Singlepass::default(), &wat);
+        let bodies = compilation
+            .get_function_bodies()
+            .values()
+            .map(|body| accesses(&body.body))
+            .collect::<Vec<_>>();
+        let (empty, private, exported) = (&bodies[0], &bodies[1], &bodies[2]);
+        let extra = |(vmctx, _): &(Vec<u64>, usize)| {
+            let mut extra = vmctx.clone();
+            for displacement in &empty.0 {
+                let index = extra.iter().position(|x| x == displacement).unwrap();
+                extra.remove(index);
+            }
+            extra
+        };
+
+        // The private global is read and written right in the vmctx...
+        let private_extra = extra(private);
+        assert_eq!(private_extra.len(), 2);
+        assert_eq!(private_extra[0], private_extra[1]);
+        assert_eq!(private.1, empty.1);
+        // ...while the exported one is through the pointer to its definition.
+        assert_eq!(extra(exported).len(), 2);
+        assert_eq!(exported.1, empty.1 + 2);
+    }
+
+    #[test]
+    fn tail_calls_jump_and_grow_the_argument_area() {
+        let wat = r#"(module
+            (func $swap (param i64 i64) (result i64)
+                (return_call $first (local.get 1) (local.get 0)))
+            (func $first (param i64 i64) (result i64)
+                (local.get 0))
+            (func $spread (result i64)
+                (return_call $eight
+                    (i64.const 1) (i64.const 2) (i64.const 3) (i64.const 4)
+                    (i64.const 5) (i64.const 6) (i64.const 7) (i64.const 8)))
+            (func $eight (param i64 i64 i64 i64 i64 i64 i64 i64) (result i64)
+                (local.get 7)))"#;
+        let compilation = compile_wat(Singlepass::default(), wat);
+        let bodies = compilation.get_function_bodies();
+        // The displacements of the stores relative to RBP, and whether the code calls or jumps
+        // through RAX. The traps call the runtime through the vmctx instead.
+        let inspect = |index: usize| {
+            let body = &bodies[LocalFunctionIndex::new(index)].body;
+            let mut stores = Vec::new();
+            let (mut calls, mut jumps) = (false, false);
+            for instruction in Decoder::new(64, body, DecoderOptions::NONE).iter() {
+                match instruction.mnemonic() {
+                    Mnemonic::Call if instruction.op0_kind() == OpKind::Register => {
+                        calls |= instruction.op0_register() == Register::RAX
+                    }
+                    Mnemonic::Jmp if instruction.op0_kind() == OpKind::Register => {
+                        jumps |= instruction.op0_register() == Register::RAX
+                    }
+                    Mnemonic::Mov
+                        if instruction.op0_kind() == OpKind::Memory
+                            && instruction.memory_base() == Register::RBP =>
+                    {
+                        stores.push(instruction.memory_displacement64() as i64)
+                    }
+                    _ => {}
+                }
+            }
+            (stores, calls, jumps)
+        };
+
+        let (_, calls, jumps) = inspect(0);
+        assert!(!calls && jumps);
+        // The 3 stack arguments of `$eight` don't fit in the area `$spread` got, which holds none:
+        // it grows by 4 slots, to stay 16-byte aligned, and the return address moves from
+        // `[rbp + 8]` to `[rbp - 24]`, right below the arguments.
+        let (stores, calls, jumps) = inspect(2);
+        assert!(!calls && jumps);
+        for offset in [-24, -16, -8, 0] {
+            assert!(stores.contains(&offset), "{} not in {:?}", offset, stores);
+        }
+    }
 }
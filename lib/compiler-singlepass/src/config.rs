@@ -3,9 +3,12 @@
 
 use crate::compiler::SinglepassCompiler;
 use crate::emitter_x64::Location;
+use crate::metering::CostFn;
+use crate::x64_decl::GPR;
 use smallvec::SmallVec;
 use std::sync::Arc;
-use wasmer_compiler::{Compiler, CompilerConfig, CpuFeature, Target};
+use wasmer_compiler::wasmparser::Operator;
+use wasmer_compiler::{Compiler, CompilerConfig, CpuFeature, ModuleMiddleware, Target};
 use wasmer_types::{Features, FunctionType, Type};
 
 #[derive(Debug, Clone)]
@@ -24,8 +27,28 @@ pub(crate) struct Intrinsic {
 pub struct Singlepass {
     pub(crate) enable_nan_canonicalization: bool,
     pub(crate) enable_stack_check: bool,
+    pub(crate) enable_stack_limit_checks: bool,
+    pub(crate) enable_peephole: bool,
+    pub(crate) omit_leaf_frame_pointers: bool,
+    pub(crate) max_locals: u32,
+    pub(crate) num_threads: Option<usize>,
+    #[cfg(feature = "debug-asm")]
+    pub(crate) enable_debug_asm: bool,
     /// Compiler intrinsics.
     pub(crate) intrinsics: Vec<Intrinsic>,
+    /// Registers that the generated code must never clobber.
+    pub(crate) reserved_gprs: Vec<GPR>,
+    /// The fuel cost of each operator, when metering is enabled.
+    pub(crate) metering: Option<CostFn>,
+    pub(crate) enable_epoch_interruption: bool,
+    /// The middlewares transforming the modules, in the order they run.
+    pub(crate) middlewares: Vec<Arc<dyn ModuleMiddleware>>,
+    /// The maximum size in bytes of the machine code of a module's functions.
+    pub(crate) max_total_code_size: Option<usize>,
+    /// The maximum size in bytes of the machine code of a function.
+    pub(crate) max_function_code_size: Option<usize>,
+    /// Whether validation checks the whole module for the features singlepass doesn't support.
+    pub(crate) exhaustive_feature_check: bool,
 }
 
 impl Singlepass {
@@ -35,11 +58,25 @@ impl Singlepass {
         Self {
             enable_nan_canonicalization: true,
             enable_stack_check: false,
+            enable_stack_limit_checks: false,
+            enable_peephole: false,
+            omit_leaf_frame_pointers: false,
+            max_locals: 50_000,
+            num_threads: None,
+            #[cfg(feature = "debug-asm")]
+            enable_debug_asm: false,
             intrinsics: vec![Intrinsic {
                 kind: IntrinsicKind::Gas,
                 name: "gas".to_string(),
                 signature: ([Type::I32], []).into(),
             }],
+            reserved_gprs: vec![],
+            metering: None,
+            enable_epoch_interruption: false,
+            middlewares: vec![],
+            max_total_code_size: None,
+            max_function_code_size: None,
+            exhaustive_feature_check: false,
         }
     }
 
@@ -55,10 +92,180 @@ impl Singlepass {
         self
     }
 
+    /// Enable native stack limit checks.
+    ///
+    /// When enabled, each function compares the stack pointer against the
+    /// limit set with `Instance::set_native_stack_limit` once its frame is
+    /// allocated, and traps with `TrapCode::StackOverflow` when it is below
+    /// the limit. This catches stack exhaustion without relying on the guard
+    /// page and a signal handler.
+    pub fn enable_stack_limit_checks(&mut self, enable: bool) -> &mut Self {
+        self.enable_stack_limit_checks = enable;
+        self
+    }
+
+    /// Enable the peephole optimizer.
+    ///
+    /// When enabled, the moves emitted back to back by the code generator
+    /// are scanned for redundant patterns, such as reloading a value from
+    /// where it was just stored.
+    pub fn enable_peephole(&mut self, enable: bool) -> &mut Self {
+        self.enable_peephole = enable;
+        self
+    }
+
+    /// Compile the leaf functions without a frame pointer.
+    ///
+    /// When enabled, the functions that call nothing, not even into the runtime, and whose
+    /// locals and params all fit in registers, are compiled without setting up RBP as a frame
+    /// pointer, which then holds one more local. They only push the callee-saved registers they
+    /// use. A function that turns out to need stack space for its values is compiled again with
+    /// a frame pointer. This only applies to the System V calling convention, and functions
+    /// compiled this way have no `FrameLayout`.
+    pub fn omit_leaf_frame_pointers(&mut self, enable: bool) -> &mut Self {
+        self.omit_leaf_frame_pointers = enable;
+        self
+    }
+
+    /// Set the maximum number of locals of a function, its params included.
+    ///
+    /// Compiling a function with more locals fails with `CompileError::CodegenTooManyLocals`.
+    /// The default is 50,000, the limit enforced by the validator. Modules compiled without
+    /// validation may declare up to `u32::MAX` locals, while the frame must stay within the
+    /// reach of a 32-bit displacement, so compilation also fails for a larger limit when the
+    /// frame doesn't fit.
+    pub fn max_locals(&mut self, max: u32) -> &mut Self {
+        self.max_locals = max;
+        self
+    }
+
+    /// Set the number of threads compiling the functions of a module.
+    ///
+    /// With `None`, the default, the functions are compiled on the global
+    /// rayon thread pool. Otherwise, each module is compiled on a thread pool
+    /// of its own with this many threads. The compiled code is the same
+    /// whatever the number of threads. Without the `rayon` feature, the
+    /// functions are always compiled one after the other on the calling
+    /// thread, and this setting is ignored.
+    pub fn num_threads(&mut self, num_threads: Option<usize>) -> &mut Self {
+        self.num_threads = num_threads;
+        self
+    }
+
+    /// Record a listing of the emitted instructions.
+    ///
+    /// When enabled, the instructions emitted for each function are recorded
+    /// along with the wasm operators they lower, and can be printed with
+    /// `UniversalArtifact::dump_function_asm`. This is meant for debugging
+    /// the code generator.
+    #[cfg(feature = "debug-asm")]
+    pub fn emit_debug_asm(&mut self, enable: bool) -> &mut Self {
+        self.enable_debug_asm = enable;
+        self
+    }
+
+    /// Reserve general purpose registers for the embedder.
+    ///
+    /// The reserved registers are excluded from register allocation, so the
+    /// generated code will preserve their values. Only the callee-saved
+    /// registers that singlepass would otherwise allocate (`RBX`, `R12`,
+    /// `R13` and `R14`) and the scratch registers `R10` and `R11` can be
+    /// reserved; compilation fails for any other register. `R10` and `R11`
+    /// are caller-saved, so the generated code saves them around the calls
+    /// to host functions and builtins, which may clobber them.
+    pub fn reserved_gprs(&mut self, gprs: &[GPR]) -> &mut Self {
+        self.reserved_gprs = gprs.to_vec();
+        self
+    }
+
+    /// Enable fuel metering, with `cost_fn` giving the fuel each operator consumes.
+    ///
+    /// The functions are split into blocks of operators that run one after the other: a
+    /// block ends after each operator that branches, calls, may trap, or starts or ends a
+    /// control frame. The whole cost of a block is taken from the fuel of the instance
+    /// before the block runs, in a single subtraction, so the fuel consumed accounts for
+    /// exactly the operators that ran. When the fuel left is lower than the cost of a block,
+    /// the block doesn't run, the fuel is left as is, and the code traps with
+    /// `TrapCode::OutOfFuel`.
+    ///
+    /// The fuel is set with `Instance::set_fuel`. Host functions can consume it as well,
+    /// through `Instance::fuel`.
+    pub fn enable_metering(&mut self, cost_fn: fn(&Operator) -> u64) -> &mut Self {
+        self.metering = Some(CostFn(cost_fn));
+        self
+    }
+
+    /// Enable epoch interruption.
+    ///
+    /// When enabled, each function on entry and each loop on every iteration
+    /// compare the epoch of the store, advanced with `Store::increment_epoch`,
+    /// with the deadline set with `Instance::set_epoch_deadline`. Once the
+    /// epoch reaches the deadline, the callback set with
+    /// `Instance::set_epoch_deadline_callback` is called to pick a new
+    /// deadline, and the code traps with `TrapCode::EpochDeadlineExceeded`
+    /// when there is none. This lets another thread stop runaway code.
+    pub fn enable_epoch_interruption(&mut self, enable: bool) -> &mut Self {
+        self.enable_epoch_interruption = enable;
+        self
+    }
+
+    /// Set the maximum size in bytes of the machine code of all the functions of a module.
+    ///
+    /// The size of the code of each function is added to a running total as soon as the
+    /// function is compiled, and compilation stops with `CompileError::CodeSizeExceeded`
+    /// once the total exceeds `limit`, before the whole module is compiled. When several
+    /// threads compile the module, the function reported by the error is the one whose
+    /// code happened to push the total past the limit. There is no limit by default.
+    pub fn max_total_code_size(&mut self, limit: usize) -> &mut Self {
+        self.max_total_code_size = Some(limit);
+        self
+    }
+
+    /// Set the maximum size in bytes of the machine code of a function.
+    ///
+    /// Compilation stops with `CompileError::CodeSizeExceeded` as soon as a function is
+    /// compiled into more code than `limit`. There is no limit by default.
+    pub fn max_function_code_size(&mut self, limit: usize) -> &mut Self {
+        self.max_function_code_size = Some(limit);
+        self
+    }
+
+    /// Check the whole module for the features singlepass doesn't support.
+    ///
+    /// When enabled, the validation of a module also looks at all its sections and at each
+    /// operator of its functions as they are validated, and fails with
+    /// `CompileError::UnsupportedFeatures` listing every unsupported operator and feature
+    /// along with the places the module uses it. Otherwise, compilation stops at the first
+    /// unsupported operator. This is disabled by default.
+    pub fn exhaustive_feature_check(&mut self, enable: bool) -> &mut Self {
+        self.exhaustive_feature_check = enable;
+        self
+    }
+
+    /// Push a middleware transforming the modules before they are compiled.
+    ///
+    /// The middlewares run in the order they are pushed: each one transforms the module
+    /// info as left by the previous ones, and is fed the operators of each function as they
+    /// come out of the previous ones. When there are middlewares, the operators they emit
+    /// are validated again before being compiled, against the module info they transformed,
+    /// and compilation fails with a `CompileError::Function` wrapping a `CompileError::Validate`
+    /// if they are invalid.
+    pub fn push_middleware(&mut self, middleware: Arc<dyn ModuleMiddleware>) -> &mut Self {
+        self.middlewares.push(middleware);
+        self
+    }
+
     fn enable_nan_canonicalization(&mut self) {
         self.enable_nan_canonicalization = true;
     }
 
+    /// Enable NaN canonicalization.
+    ///
+    /// When enabled, every NaN produced by a floating point arithmetic
+    /// operation or conversion is replaced with the canonical quiet NaN
+    /// before it can be observed, so that results don't depend on how the
+    /// host CPU propagates NaN payloads. NaNs that are only moved around,
+    /// such as by loads, stores or reinterpretations, keep their bits.
     pub fn canonicalize_nans(&mut self, enable: bool) -> &mut Self {
         self.enable_nan_canonicalization = enable;
         self
@@ -76,10 +283,10 @@ impl CompilerConfig for Singlepass {
         Box::new(SinglepassCompiler::new(*self))
     }
 
-    /// Gets the default features for this compiler in the given target
+    /// The default features, with the tail calls singlepass compiles.
     fn default_features_for_target(&self, _target: &Target) -> Features {
         let mut features = Features::default();
-        features.multi_value(false);
+        features.tail_call(true);
         features
     }
 }
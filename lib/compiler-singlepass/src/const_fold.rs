@@ -0,0 +1,284 @@
+//! Compile-time evaluation of integer operators on constant operands.
+//!
+//! Constants stay on the virtual stack as immediates until an operator consumes them, so an
+//! operator whose operands are all immediates can be replaced by its result without emitting any
+//! code. Operators that may trap are only folded when they do not, and are otherwise left to the
+//! code emitted at runtime.
+
+use crate::emitter_x64::Location;
+use wasmer_compiler::wasmparser::Operator;
+
+/// Evaluates the binary operator `op` on the constants `a` and `b`.
+///
+/// Returns `None` if `op` is not an integer binary operator, if an operand is not a constant of
+/// its type, or if the operation traps.
+pub(crate) fn fold_binop(op: &Operator, a: Location, b: Location) -> Option<Location> {
+    match (a, b) {
+        (Location::Imm32(a), Location::Imm32(b)) => fold_binop_i32(op, a, b).map(Location::Imm32),
+        (Location::Imm64(a), Location::Imm64(b)) => fold_binop_i64(op, a, b).map(Location::Imm64),
+        _ => None,
+    }
+}
+
+fn fold_binop_i32(op: &Operator, a: u32, b: u32) -> Option<u32> {
+    let (sa, sb) = (a as i32, b as i32);
+    Some(match op {
+        Operator::I32Add => a.wrapping_add(b),
+        Operator::I32Sub => a.wrapping_sub(b),
+        Operator::I32Mul => a.wrapping_mul(b),
+        // `checked_div` fails on the same operands as the trapping wasm division.
+        Operator::I32DivU => a.checked_div(b)?,
+        Operator::I32DivS => sa.checked_div(sb)? as u32,
+        Operator::I32RemU => a.checked_rem(b)?,
+        // Unlike `checked_rem`, `i32.rem_s` does not trap on `i32::MIN % -1`.
+        Operator::I32RemS if b != 0 => sa.wrapping_rem(sb) as u32,
+        Operator::I32And => a & b,
+        Operator::I32Or => a | b,
+        Operator::I32Xor => a ^ b,
+        // The wrapping shifts mask the count the same way wasm does.
+        Operator::I32Shl => a.wrapping_shl(b),
+        Operator::I32ShrU => a.wrapping_shr(b),
+        Operator::I32ShrS => sa.wrapping_shr(b) as u32,
+        Operator::I32Rotl => a.rotate_left(b),
+        Operator::I32Rotr => a.rotate_right(b),
+        _ => return None,
+    })
+}
+
+fn fold_binop_i64(op: &Operator, a: u64, b: u64) -> Option<u64> {
+    let (sa, sb) = (a as i64, b as i64);
+    // Only the low 6 bits of shift and rotation counts matter.
+    let count = b as u32;
+    Some(match op {
+        Operator::I64Add => a.wrapping_add(b),
+        Operator::I64Sub => a.wrapping_sub(b),
+        Operator::I64Mul => a.wrapping_mul(b),
+        Operator::I64DivU => a.checked_div(b)?,
+        Operator::I64DivS => sa.checked_div(sb)? as u64,
+        Operator::I64RemU => a.checked_rem(b)?,
+        Operator::I64RemS if b != 0 => sa.wrapping_rem(sb) as u64,
+        Operator::I64And => a & b,
+        Operator::I64Or => a | b,
+        Operator::I64Xor => a ^ b,
+        Operator::I64Shl => a.wrapping_shl(count),
+        Operator::I64ShrU => a.wrapping_shr(count),
+        Operator::I64ShrS => sa.wrapping_shr(count) as u64,
+        Operator::I64Rotl => a.rotate_left(count),
+        Operator::I64Rotr => a.rotate_right(count),
+        _ => return None,
+    })
+}
+
+/// Evaluates the unary operator `op` on the constant `a`.
+///
+/// Returns `None` if `op` is not a sign extension or integer conversion operator, or if `a` is
+/// not a constant of its type.
+pub(crate) fn fold_unop(op: &Operator, a: Location) -> Option<Location> {
+    match (op, a) {
+        (Operator::I32Extend8S, Location::Imm32(a)) => Some(Location::Imm32(a as i8 as u32)),
+        (Operator::I32Extend16S, Location::Imm32(a)) => Some(Location::Imm32(a as i16 as u32)),
+        (Operator::I64Extend8S, Location::Imm64(a)) => Some(Location::Imm64(a as i8 as u64)),
+        (Operator::I64Extend16S, Location::Imm64(a)) => Some(Location::Imm64(a as i16 as u64)),
+        (Operator::I64Extend32S, Location::Imm64(a)) => Some(Location::Imm64(a as i32 as u64)),
+        (Operator::I32WrapI64, Location::Imm64(a)) => Some(Location::Imm32(a as u32)),
+        (Operator::I64ExtendI32U, Location::Imm32(a)) => Some(Location::Imm64(u64::from(a))),
+        (Operator::I64ExtendI32S, Location::Imm32(a)) => Some(Location::Imm64(a as i32 as u64)),
+        _ => None,
+    }
+}
+
+/// The base-2 logarithm of `loc`, if it is a power of two of the size of the `op` operands.
+pub(crate) fn constant_log2(op: &Operator, loc: Location) -> Option<u32> {
+    match (op, loc) {
+        (Operator::I32Mul, Location::Imm32(x))
+        | (Operator::I32DivU, Location::Imm32(x))
+        | (Operator::I32RemU, Location::Imm32(x))
+            if x.is_power_of_two() =>
+        {
+            Some(x.trailing_zeros())
+        }
+        (Operator::I64Mul, Location::Imm64(x))
+        | (Operator::I64DivU, Location::Imm64(x))
+        | (Operator::I64RemU, Location::Imm64(x))
+            if x.is_power_of_two() =>
+        {
+            Some(x.trailing_zeros())
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::x64_decl::GPR;
+
+    fn fold32(op: Operator, a: u32, b: u32) -> Option<u32> {
+        match fold_binop(&op, Location::Imm32(a), Location::Imm32(b)) {
+            Some(Location::Imm32(x)) => Some(x),
+            None => None,
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    fn fold64(op: Operator, a: u64, b: u64) -> Option<u64> {
+        match fold_binop(&op, Location::Imm64(a), Location::Imm64(b)) {
+            Some(Location::Imm64(x)) => Some(x),
+            None => None,
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_fold_wraps() {
+        assert_eq!(fold32(Operator::I32Add, 4, 8), Some(12));
+        assert_eq!(fold32(Operator::I32Add, u32::MAX, 2), Some(1));
+        assert_eq!(fold32(Operator::I32Sub, 0, 1), Some(u32::MAX));
+        assert_eq!(fold64(Operator::I64Mul, 1 << 63, 2), Some(0));
+        assert_eq!(fold32(Operator::I32Shl, 1, 33), Some(2));
+        assert_eq!(fold64(Operator::I64ShrS, 1 << 63, 127), Some(u64::MAX));
+        assert_eq!(fold64(Operator::I64Rotl, 1 << 63, 65), Some(1));
+    }
+
+    #[test]
+    fn test_fold_signedness() {
+        let minus_seven = -7i32 as u32;
+        assert_eq!(fold32(Operator::I32DivU, minus_seven, 2), Some(0x7fff_fffc));
+        assert_eq!(
+            fold32(Operator::I32DivS, minus_seven, 2),
+            Some(-3i32 as u32)
+        );
+        assert_eq!(
+            fold32(Operator::I32RemS, minus_seven, 2),
+            Some(-1i32 as u32)
+        );
+        assert_eq!(fold32(Operator::I32ShrU, minus_seven, 1), Some(0x7fff_fffc));
+        assert_eq!(
+            fold32(Operator::I32ShrS, minus_seven, 1),
+            Some(-4i32 as u32)
+        );
+    }
+
+    #[test]
+    fn test_fold_leaves_traps_to_runtime() {
+        assert_eq!(fold32(Operator::I32DivU, 1, 0), None);
+        assert_eq!(fold32(Operator::I32RemS, 1, 0), None);
+        assert_eq!(fold64(Operator::I64RemU, 1, 0), None);
+        assert_eq!(fold32(Operator::I32DivS, i32::MIN as u32, u32::MAX), None);
+        assert_eq!(fold64(Operator::I64DivS, i64::MIN as u64, u64::MAX), None);
+        // This one does not trap.
+        assert_eq!(
+            fold32(Operator::I32RemS, i32::MIN as u32, u32::MAX),
+            Some(0)
+        );
+        assert_eq!(
+            fold64(Operator::I64RemS, i64::MIN as u64, u64::MAX),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn test_fold_sign_extensions() {
+        let extend32 = |op, a| fold_unop(&op, Location::Imm32(a));
+        let extend64 = |op, a| fold_unop(&op, Location::Imm64(a));
+        for (a, extended) in [
+            (0, 0),
+            (0x7f, 0x7f),
+            (0x80, 0xffff_ff80),
+            (0xff, u32::MAX),
+            (0x100, 0),
+            (0x1234_5680, 0xffff_ff80),
+        ] {
+            assert_eq!(
+                extend32(Operator::I32Extend8S, a),
+                Some(Location::Imm32(extended))
+            );
+        }
+        for (a, extended) in [
+            (0x7fff, 0x7fff),
+            (0x8000, 0xffff_8000),
+            (0xffff, u32::MAX),
+            (0x1_0000, 0),
+        ] {
+            assert_eq!(
+                extend32(Operator::I32Extend16S, a),
+                Some(Location::Imm32(extended))
+            );
+        }
+        for (op, a, extended) in [
+            (Operator::I64Extend8S, 0x7f, 0x7f),
+            (Operator::I64Extend8S, 0x80, u64::MAX << 7),
+            (Operator::I64Extend8S, 0x0123_4567_89ab_cd80, u64::MAX << 7),
+            (Operator::I64Extend16S, 0x7fff, 0x7fff),
+            (Operator::I64Extend16S, 0x8000, u64::MAX << 15),
+            (Operator::I64Extend16S, 0xffff_0000, 0),
+            (Operator::I64Extend32S, 0x7fff_ffff, 0x7fff_ffff),
+            (Operator::I64Extend32S, 0x8000_0000, u64::MAX << 31),
+            (Operator::I64Extend32S, u32::MAX as u64, u64::MAX),
+            (Operator::I64Extend32S, 0x1_0000_0000, 0),
+        ] {
+            assert_eq!(extend64(op, a), Some(Location::Imm64(extended)));
+        }
+    }
+
+    #[test]
+    fn test_fold_checks_operand_types() {
+        // Constant `i64` operands are always `Imm64`.
+        assert_eq!(
+            fold_binop(&Operator::I64Add, Location::Imm32(1), Location::Imm32(2)),
+            None
+        );
+        assert_eq!(
+            fold_binop(
+                &Operator::I32Add,
+                Location::GPR(GPR::RAX),
+                Location::Imm32(2)
+            ),
+            None
+        );
+        assert_eq!(constant_log2(&Operator::I64Mul, Location::Imm32(8)), None);
+        assert_eq!(
+            constant_log2(&Operator::I64Mul, Location::Imm64(8)),
+            Some(3)
+        );
+        assert_eq!(constant_log2(&Operator::I32RemU, Location::Imm32(6)), None);
+        assert_eq!(constant_log2(&Operator::I32RemU, Location::Imm32(0)), None);
+        assert_eq!(
+            fold_unop(&Operator::I64Extend8S, Location::Imm32(0x80)),
+            None
+        );
+        assert_eq!(
+            fold_unop(&Operator::I32Extend8S, Location::GPR(GPR::RAX)),
+            None
+        );
+        assert_eq!(fold_unop(&Operator::I32Eqz, Location::Imm32(0)), None);
+    }
+
+    #[test]
+    fn test_fold_integer_conversions() {
+        for (a, wrapped) in [
+            (0, 0),
+            (0x1_0000_0000, 0),
+            (0xffff_ffff_8000_0000, 0x8000_0000),
+        ] {
+            assert_eq!(
+                fold_unop(&Operator::I32WrapI64, Location::Imm64(a)),
+                Some(Location::Imm32(wrapped))
+            );
+        }
+        for (op, a, extended) in [
+            (Operator::I64ExtendI32U, 0x7fff_ffff, 0x7fff_ffff),
+            (Operator::I64ExtendI32U, 0x8000_0000, 0x8000_0000),
+            (Operator::I64ExtendI32S, 0x7fff_ffff, 0x7fff_ffff),
+            (Operator::I64ExtendI32S, 0x8000_0000, u64::MAX << 31),
+            (Operator::I64ExtendI32S, u32::MAX, u64::MAX),
+        ] {
+            assert_eq!(
+                fold_unop(&op, Location::Imm32(a)),
+                Some(Location::Imm64(extended))
+            );
+        }
+        // Constants of the wrong width are left to the code emitted at runtime.
+        assert_eq!(fold_unop(&Operator::I32WrapI64, Location::Imm32(1)), None);
+    }
+}
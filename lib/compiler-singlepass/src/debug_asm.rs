@@ -0,0 +1,188 @@
+//! A layer over an `Emitter` recording a listing of the emitted instructions, enabled by the
+//! `debug-asm` feature.
+//!
+//! Each instruction is recorded as the `Emitter` method it was emitted with and the arguments it
+//! was called with, at the offset where its code starts. The code generator adds a marker before
+//! the code of each wasm operator.
+
+use crate::emitter_x64::*;
+use dynasmrt::AssemblyOffset;
+use std::fmt::Debug;
+use wasmer_compiler::wasmparser::Operator;
+use wasmer_compiler::{AsmLine, FunctionAsm};
+
+pub(crate) struct DebugAsmEmitter<E: Emitter> {
+    inner: E,
+    listing: Option<FunctionAsm>,
+}
+
+impl<E: Emitter<Offset = AssemblyOffset>> DebugAsmEmitter<E> {
+    /// Wraps `inner`. When `enabled` is false, nothing is recorded.
+    pub(crate) fn new(inner: E, enabled: bool) -> Self {
+        Self {
+            inner,
+            listing: if enabled {
+                Some(FunctionAsm::default())
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Gives access to the underlying emitter. What is emitted through it is not recorded.
+    pub(crate) fn inner_mut(&mut self) -> &mut E {
+        &mut self.inner
+    }
+
+    /// Returns the underlying emitter and the listing, if it was recorded.
+    pub(crate) fn into_parts(self) -> (E, Option<FunctionAsm>) {
+        (self.inner, self.listing)
+    }
+
+    /// Marks the start of the code lowering `op`.
+    pub(crate) fn mark_operator(&mut self, op: &Operator) {
+        if let Some(listing) = &mut self.listing {
+            listing.lines.push(AsmLine::Operator(format!("{:?}", op)));
+        }
+    }
+
+    fn record(&mut self, method: &str, args: &[&dyn Debug]) {
+        if self.listing.is_none() {
+            return;
+        }
+        // Only the methods emitting code are recorded, under the name of the instruction.
+        let mnemonic = match method
+            .strip_prefix("arch_emit_")
+            .or_else(|| method.strip_prefix("emit_"))
+        {
+            Some(mnemonic) => mnemonic,
+            None => return,
+        };
+        let args = args
+            .iter()
+            .map(|arg| format!("{:?}", arg))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let offset = self.inner.get_offset().0 as u32;
+        self.listing
+            .as_mut()
+            .unwrap()
+            .lines
+            .push(AsmLine::Instruction {
+                offset,
+                text: format!("{} {}", mnemonic, args).trim_end().to_string(),
+            });
+    }
+}
+
+/// Implements `Emitter` methods by recording the call, then forwarding it.
+macro_rules! record_and_forward {
+    ($(fn $name:ident(&mut self $(, $arg:ident: $ty:ty)*) $(-> $ret:ty)?;)*) => {
+        $(
+            fn $name(&mut self $(, $arg: $ty)*) $(-> $ret)? {
+                self.record(stringify!($name), &[$(&$arg),*]);
+                self.inner.$name($($arg),*)
+            }
+        )*
+    };
+}
+
+impl<E: Emitter<Offset = AssemblyOffset>> Emitter for DebugAsmEmitter<E>
+where
+    E::Label: Debug,
+{
+    type Label = E::Label;
+    type Offset = E::Offset;
+
+    fn get_label(&mut self) -> Self::Label {
+        self.inner.get_label()
+    }
+
+    fn get_jmp_instr_size(&self) -> u8 {
+        self.inner.get_jmp_instr_size()
+    }
+
+    fn arch_has_itruncf(&self) -> bool {
+        self.inner.arch_has_itruncf()
+    }
+
+    fn arch_has_fconverti(&self) -> bool {
+        self.inner.arch_has_fconverti()
+    }
+
+    fn arch_has_fneg(&self) -> bool {
+        self.inner.arch_has_fneg()
+    }
+
+    fn arch_supports_canonicalize_nan(&self) -> bool {
+        self.inner.arch_supports_canonicalize_nan()
+    }
+
+    fn arch_requires_indirect_call_trampoline(&self) -> bool {
+        self.inner.arch_requires_indirect_call_trampoline()
+    }
+
+    record_and_forward! {
+        fn emit_mov(&mut self, sz: Size, src: Location, dst: Location);
+        fn emit_add(&mut self, sz: Size, src: Location, dst: Location);
+        fn emit_sub(&mut self, sz: Size, src: Location, dst: Location);
+    }
+
+    with_forwarded_emitter_methods!(record_and_forward);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use dynasmrt::{x64::X64Relocation, VecAssembler};
+
+    type Assembler = VecAssembler<X64Relocation>;
+
+    #[test]
+    fn test_records_instructions_and_markers() {
+        let mut a = DebugAsmEmitter::new(Assembler::new(0), true);
+        a.mark_operator(&Operator::I32Add);
+        a.emit_add(Size::S32, Location::GPR(GPR::RCX), Location::GPR(GPR::RAX));
+        let label = a.get_label();
+        a.emit_label(label);
+        a.emit_ret();
+        let offset = a.get_offset().0 as u32;
+        a.emit_ud2();
+
+        let (inner, listing) = a.into_parts();
+        let mut expected = Assembler::new(0);
+        expected.emit_add(Size::S32, Location::GPR(GPR::RCX), Location::GPR(GPR::RAX));
+        expected.emit_ret();
+        expected.emit_ud2();
+        assert_eq!(inner.finalize().unwrap(), expected.finalize().unwrap());
+
+        let lines = listing.unwrap().lines;
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], AsmLine::Operator("I32Add".to_string()));
+        assert_eq!(
+            lines[1],
+            AsmLine::Instruction {
+                offset: 0,
+                text: "add S32, GPR(RCX), GPR(RAX)".to_string(),
+            }
+        );
+        assert!(
+            matches!(&lines[2], AsmLine::Instruction { text, .. } if text.starts_with("label "))
+        );
+        assert_eq!(
+            lines[4],
+            AsmLine::Instruction {
+                offset,
+                text: "ud2".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_records_nothing_when_disabled() {
+        let mut a = DebugAsmEmitter::new(Assembler::new(0), false);
+        a.mark_operator(&Operator::Nop);
+        a.emit_nop();
+        assert!(a.into_parts().1.is_none());
+    }
+}
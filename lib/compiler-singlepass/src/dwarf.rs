@@ -0,0 +1,248 @@
+//! Generation of the `.eh_frame` section describing the frames of System V functions.
+
+use crate::unwind::UnwindOp;
+use crate::x64_decl::{X64Register, GPR};
+use gimli::write::{
+    Address, CallFrameInstruction, CommonInformationEntry, EndianVec, FrameDescriptionEntry,
+    Result, Writer,
+};
+use gimli::{Encoding, Format, Register, RunTimeEndian, SectionId, X86_64};
+use wasmer_compiler::{
+    CustomSection, CustomSectionProtection, Relocation, RelocationKind, RelocationTarget,
+    SectionBody,
+};
+use wasmer_types::entity::EntityRef;
+use wasmer_types::LocalFunctionIndex;
+
+/// The CIE shared by the FDEs of all functions: on entry, the CFA is right above the return
+/// address at RSP.
+pub(crate) fn create_systemv_cie() -> CommonInformationEntry {
+    let mut entry = CommonInformationEntry::new(
+        Encoding {
+            address_size: 8,
+            format: Format::Dwarf32,
+            version: 1,
+        },
+        1,
+        -8,
+        X86_64::RA,
+    );
+    entry.add_instruction(CallFrameInstruction::Cfa(X86_64::RSP, 8));
+    entry.add_instruction(CallFrameInstruction::Offset(X86_64::RA, -8));
+    entry
+}
+
+/// Describes the `len` bytes of code of the function `index` with the `ops` recorded while
+/// emitting them.
+pub(crate) fn create_fde(
+    index: LocalFunctionIndex,
+    ops: &[(usize, UnwindOp)],
+    len: usize,
+) -> FrameDescriptionEntry {
+    let rbp = Register(X64Register::GPR(GPR::RBP).to_dwarf_regnum());
+    let address = Address::Symbol {
+        symbol: index.index(),
+        addend: 0,
+    };
+    let mut fde = FrameDescriptionEntry::new(address, len as u32);
+    // Without a frame pointer, the CFA stays relative to RSP, this far above it.
+    let mut cfa_offset = 8;
+    // The CFA offset of the body, once the epilogue started changing it.
+    let mut body_cfa_offset = None;
+    for &(offset, op) in ops {
+        let offset = offset as u32;
+        match op {
+            UnwindOp::PushFramePointer => {
+                fde.add_instruction(offset, CallFrameInstruction::CfaOffset(16));
+                fde.add_instruction(offset, CallFrameInstruction::Offset(rbp, -16));
+            }
+            UnwindOp::DefineFramePointer => {
+                fde.add_instruction(offset, CallFrameInstruction::CfaRegister(rbp));
+            }
+            UnwindOp::SaveRegister { reg, bp_neg_offset } => {
+                // The CFA is 16 bytes above RBP, past the saved RBP and the return address.
+                fde.add_instruction(
+                    offset,
+                    CallFrameInstruction::Offset(
+                        Register(reg.to_dwarf_regnum()),
+                        -16 - bp_neg_offset as i32,
+                    ),
+                );
+            }
+            UnwindOp::PopFramePointer => {
+                // The other callee-saved registers are still in memory where they were popped
+                // from, so only the CFA and RBP change.
+                fde.add_instruction(offset, CallFrameInstruction::RememberState);
+                fde.add_instruction(offset, CallFrameInstruction::Cfa(X86_64::RSP, 8));
+                fde.add_instruction(offset, CallFrameInstruction::SameValue(rbp));
+            }
+            UnwindOp::PushRegister { reg } => {
+                cfa_offset += 8;
+                fde.add_instruction(offset, CallFrameInstruction::CfaOffset(cfa_offset));
+                fde.add_instruction(
+                    offset,
+                    CallFrameInstruction::Offset(
+                        Register(X64Register::GPR(reg).to_dwarf_regnum()),
+                        -cfa_offset,
+                    ),
+                );
+            }
+            UnwindOp::AllocateStack { size } => {
+                cfa_offset += size as i32;
+                fde.add_instruction(offset, CallFrameInstruction::CfaOffset(cfa_offset));
+            }
+            UnwindOp::FreeStack { size } => {
+                if body_cfa_offset.is_none() {
+                    body_cfa_offset = Some(cfa_offset);
+                    fde.add_instruction(offset, CallFrameInstruction::RememberState);
+                }
+                cfa_offset -= size as i32;
+                fde.add_instruction(offset, CallFrameInstruction::CfaOffset(cfa_offset));
+            }
+            UnwindOp::PopRegister { reg } => {
+                if body_cfa_offset.is_none() {
+                    body_cfa_offset = Some(cfa_offset);
+                    fde.add_instruction(offset, CallFrameInstruction::RememberState);
+                }
+                cfa_offset -= 8;
+                fde.add_instruction(offset, CallFrameInstruction::CfaOffset(cfa_offset));
+                fde.add_instruction(
+                    offset,
+                    CallFrameInstruction::SameValue(Register(
+                        X64Register::GPR(reg).to_dwarf_regnum(),
+                    )),
+                );
+            }
+            UnwindOp::Return => {
+                // The code after `ret` runs with the frame of the body again.
+                fde.add_instruction(offset, CallFrameInstruction::RestoreState);
+                if let Some(body_cfa_offset) = body_cfa_offset.take() {
+                    cfa_offset = body_cfa_offset;
+                }
+            }
+        }
+    }
+    fde
+}
+
+/// A `gimli` writer turning the addresses of functions into relocations.
+///
+/// Symbols are the indices of local functions.
+pub(crate) struct WriterRelocate {
+    relocs: Vec<Relocation>,
+    writer: EndianVec<RunTimeEndian>,
+}
+
+impl WriterRelocate {
+    pub(crate) fn new() -> Self {
+        Self {
+            relocs: Vec::new(),
+            writer: EndianVec::new(RunTimeEndian::Little),
+        }
+    }
+
+    /// Terminates the written `.eh_frame` contents and wraps them in a custom section.
+    pub(crate) fn into_section(mut self) -> CustomSection {
+        // libgcc's `__register_frame` looks for a zero-length entry to find the end of the
+        // section.
+        self.write_u32(0).unwrap();
+        CustomSection {
+            protection: CustomSectionProtection::Read,
+            bytes: SectionBody::new_with_vec(self.writer.into_vec()),
+            relocations: self.relocs,
+        }
+    }
+}
+
+impl Writer for WriterRelocate {
+    type Endian = RunTimeEndian;
+
+    fn endian(&self) -> Self::Endian {
+        self.writer.endian()
+    }
+
+    fn len(&self) -> usize {
+        self.writer.len()
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        self.writer.write(bytes)
+    }
+
+    fn write_at(&mut self, offset: usize, bytes: &[u8]) -> Result<()> {
+        self.writer.write_at(offset, bytes)
+    }
+
+    fn write_address(&mut self, address: Address, size: u8) -> Result<()> {
+        match address {
+            Address::Constant(val) => self.write_udata(val, size),
+            Address::Symbol { symbol, addend } => {
+                assert_eq!(size, 8, "function addresses are 64-bit");
+                let offset = self.len() as u32;
+                self.relocs.push(Relocation {
+                    kind: RelocationKind::Abs8,
+                    reloc_target: RelocationTarget::LocalFunc(LocalFunctionIndex::new(symbol)),
+                    offset,
+                    addend,
+                });
+                self.write_udata(0, size)
+            }
+        }
+    }
+
+    fn write_offset(&mut self, val: usize, _section: SectionId, size: u8) -> Result<()> {
+        self.write_udata(val as u64, size)
+    }
+
+    fn write_offset_at(
+        &mut self,
+        offset: usize,
+        val: usize,
+        _section: SectionId,
+        size: u8,
+    ) -> Result<()> {
+        self.write_udata_at(offset, val as u64, size)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use gimli::write::{EhFrame, FrameTable};
+
+    #[test]
+    fn test_eh_frame_relocates_function_addresses() {
+        let mut table = FrameTable::default();
+        let cie_id = table.add_cie(create_systemv_cie());
+        let ops = [
+            (1, UnwindOp::PushFramePointer),
+            (4, UnwindOp::DefineFramePointer),
+        ];
+        for i in 0..2 {
+            table.add_fde(cie_id, create_fde(LocalFunctionIndex::new(i), &ops, 16));
+        }
+        let mut eh_frame = EhFrame(WriterRelocate::new());
+        table.write_eh_frame(&mut eh_frame).unwrap();
+        let section = eh_frame.0.into_section();
+
+        let targets = section
+            .relocations
+            .iter()
+            .map(|r| (r.kind, r.reloc_target))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            targets,
+            [
+                (
+                    RelocationKind::Abs8,
+                    RelocationTarget::LocalFunc(LocalFunctionIndex::new(0))
+                ),
+                (
+                    RelocationKind::Abs8,
+                    RelocationTarget::LocalFunc(LocalFunctionIndex::new(1))
+                ),
+            ]
+        );
+        assert!(section.bytes.as_slice().ends_with(&[0, 0, 0, 0]));
+    }
+}
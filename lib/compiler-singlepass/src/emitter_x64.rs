@@ -29,7 +29,6 @@ pub(crate) enum Location {
     GPR(GPR),
     XMM(XMM),
     Memory(GPR, i32),
-    MemoryAddTriple(GPR, GPR, i32),
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -48,6 +47,7 @@ pub(crate) enum Condition {
     Signed,
     Carry,
     Overflow,
+    NotOverflow,
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
@@ -72,12 +72,19 @@ pub(crate) enum GPROrMemory {
     Memory(GPR, i32),
 }
 
+/// An x86-64 assembler for the code generator.
+///
+/// `emit_cmp`, `emit_add`, `emit_sub`, `emit_and`, `emit_or` and `emit_xor` accept an `Imm64`
+/// source of any value, and so does `emit_mov` into a register or memory. A value that doesn't
+/// fit in a sign-extended 32-bit immediate is materialized in a scratch GPR, which is saved on the
+/// stack around the instruction, so these never panic on large immediates. `emit_cmp` also
+/// accepts an immediate on its right.
 pub(crate) trait Emitter {
     type Label;
     type Offset;
 
     fn get_label(&mut self) -> Self::Label;
-    fn get_offset(&self) -> Self::Offset;
+    fn get_offset(&mut self) -> Self::Offset;
     fn get_jmp_instr_size(&self) -> u8;
 
     fn finalize_function(&mut self) {}
@@ -96,6 +103,10 @@ pub(crate) trait Emitter {
     fn emit_mov(&mut self, sz: Size, src: Location, dst: Location);
     fn emit_lea(&mut self, sz: Size, src: Location, dst: Location);
     fn emit_lea_label(&mut self, label: Self::Label, dst: Location);
+    /// Emits a `LEA` of an address relative to the end of the instruction into `dst`, always
+    /// encoding the displacement in the last 4 bytes of the instruction for a relocation to patch
+    /// it.
+    fn emit_lea_pc_relative(&mut self, dst: GPR);
     fn emit_cdq(&mut self);
     fn emit_cqo(&mut self);
     fn emit_xor(&mut self, sz: Size, src: Location, dst: Location);
@@ -122,6 +133,10 @@ pub(crate) trait Emitter {
     fn emit_bsr(&mut self, sz: Size, src: Location, dst: Location);
     fn emit_bsf(&mut self, sz: Size, src: Location, dst: Location);
     fn emit_popcnt(&mut self, sz: Size, src: Location, dst: Location);
+    /// Requires LZCNT, which is decoded as `bsr` by older CPUs.
+    fn emit_lzcnt(&mut self, sz: Size, src: Location, dst: Location);
+    /// Requires BMI1, which is decoded as `bsf` by older CPUs.
+    fn emit_tzcnt(&mut self, sz: Size, src: Location, dst: Location);
     fn emit_movzx(&mut self, sz_src: Size, src: Location, sz_dst: Size, dst: Location);
     fn emit_movsx(&mut self, sz_src: Size, src: Location, sz_dst: Size, dst: Location);
     fn emit_xchg(&mut self, sz: Size, src: Location, dst: Location);
@@ -132,13 +147,18 @@ pub(crate) trait Emitter {
     fn emit_btc_gpr_imm8_32(&mut self, src: u8, dst: GPR);
     fn emit_btc_gpr_imm8_64(&mut self, src: u8, dst: GPR);
 
-    fn emit_cmovae_gpr_32(&mut self, src: GPR, dst: GPR);
-    fn emit_cmovae_gpr_64(&mut self, src: GPR, dst: GPR);
+    /// Moves `src`, a GPR or memory, to the GPR `dst` if `condition` holds.
+    fn emit_cmovcc(&mut self, sz: Size, condition: Condition, src: Location, dst: Location);
 
     fn emit_vmovaps(&mut self, src: XMMOrMemory, dst: XMMOrMemory);
     fn emit_vmovapd(&mut self, src: XMMOrMemory, dst: XMMOrMemory);
+    fn emit_movdqu(&mut self, src: XMMOrMemory, dst: XMMOrMemory);
     fn emit_vxorps(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
     fn emit_vxorpd(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+    fn emit_vorps(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+    fn emit_vorpd(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+    fn emit_vandps(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+    fn emit_vandpd(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
 
     fn emit_vaddss(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
     fn emit_vaddsd(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
@@ -208,6 +228,37 @@ pub(crate) trait Emitter {
     fn emit_vblendvps(&mut self, src1: XMM, src2: XMMOrMemory, mask: XMM, dst: XMM);
     fn emit_vblendvpd(&mut self, src1: XMM, src2: XMMOrMemory, mask: XMM, dst: XMM);
 
+    fn emit_vpaddb(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+    fn emit_vpaddw(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+    fn emit_vpaddd(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+    fn emit_vpaddq(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+    fn emit_vpaddusb(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+    fn emit_vpsubb(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+    fn emit_vpsubw(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+    fn emit_vpsubd(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+    fn emit_vpsubq(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+    fn emit_vpmullw(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+    fn emit_vpmulld(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+    fn emit_vpmuludq(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+    fn emit_vpand(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+    fn emit_vpandn(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+    fn emit_vpor(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+    fn emit_vpxor(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+    fn emit_vpcmpeqb(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+    fn emit_vpcmpeqd(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+    fn emit_vpshufb(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+    fn emit_vpunpcklqdq(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+
+    fn emit_pshufd(&mut self, src: XMM, imm: u8, dst: XMM);
+    fn emit_pshuflw(&mut self, src: XMM, imm: u8, dst: XMM);
+    fn emit_psllq_imm(&mut self, imm: u8, dst: XMM);
+    fn emit_psrlq_imm(&mut self, imm: u8, dst: XMM);
+    fn emit_pextr(&mut self, sz: Size, src: XMM, lane: u8, dst: GPR);
+    fn emit_pinsr(&mut self, sz: Size, src: GPR, lane: u8, dst: XMM);
+    fn emit_ptest(&mut self, src: XMMOrMemory, dst: XMM);
+    fn emit_pmovsx(&mut self, sz: Size, src: XMM, dst: XMM);
+    fn emit_pmovzx(&mut self, sz: Size, src: XMM, dst: XMM);
+
     fn emit_test_gpr_64(&mut self, reg: GPR);
 
     fn emit_ud2(&mut self);
@@ -287,16 +338,6 @@ pub(crate) trait Emitter {
         unimplemented!()
     }
 
-    fn arch_has_xzcnt(&self) -> bool {
-        false
-    }
-    fn arch_emit_lzcnt(&mut self, _sz: Size, _src: Location, _dst: Location) {
-        unimplemented!()
-    }
-    fn arch_emit_tzcnt(&mut self, _sz: Size, _src: Location, _dst: Location) {
-        unimplemented!()
-    }
-
     fn arch_supports_canonicalize_nan(&self) -> bool {
         true
     }
@@ -311,13 +352,209 @@ pub(crate) trait Emitter {
 
     // Emits entry trampoline just before the real function.
     fn arch_emit_entry_trampoline(&mut self) {}
+}
 
-    // Byte offset from the beginning of a `mov Imm64, GPR` instruction to the imm64 value.
-    // Required to support emulation on Aarch64.
-    fn arch_mov64_imm_offset(&self) -> usize {
-        unimplemented!()
-    }
+/// Invokes the macro `$forward` with the signatures of the `Emitter` methods that emit code, for
+/// the emitters layered over another one to forward them.
+///
+/// `emit_mov`, `emit_add` and `emit_sub` are left out, as the layers handle them specially, and
+/// so are `get_label`, `get_jmp_instr_size` and the `arch_*` queries about the architecture.
+macro_rules! with_forwarded_emitter_methods {
+    ($forward:ident) => {
+        $forward! {
+            fn get_offset(&mut self) -> Self::Offset;
+            fn finalize_function(&mut self);
+
+            fn emit_u64(&mut self, x: u64);
+            fn emit_bytes(&mut self, bytes: &[u8]);
+
+            fn emit_label(&mut self, label: Self::Label);
+
+            fn emit_nop(&mut self);
+            fn emit_nop_n(&mut self, n: usize);
+
+            fn emit_lea(&mut self, sz: Size, src: Location, dst: Location);
+            fn emit_lea_label(&mut self, label: Self::Label, dst: Location);
+            fn emit_lea_pc_relative(&mut self, dst: GPR);
+            fn emit_cdq(&mut self);
+            fn emit_cqo(&mut self);
+            fn emit_xor(&mut self, sz: Size, src: Location, dst: Location);
+            fn emit_jmp(&mut self, condition: Condition, label: Self::Label);
+            fn emit_jmp_location(&mut self, loc: Location);
+            fn emit_set(&mut self, condition: Condition, dst: GPR);
+            fn emit_push(&mut self, sz: Size, src: Location);
+            fn emit_pop(&mut self, sz: Size, dst: Location);
+            fn emit_cmp(&mut self, sz: Size, left: Location, right: Location);
+            fn emit_neg(&mut self, sz: Size, value: Location);
+            fn emit_imul(&mut self, sz: Size, src: Location, dst: Location);
+            fn emit_imul_imm32_gpr64(&mut self, src: u32, dst: GPR);
+            fn emit_div(&mut self, sz: Size, divisor: Location);
+            fn emit_idiv(&mut self, sz: Size, divisor: Location);
+            fn emit_shl(&mut self, sz: Size, src: Location, dst: Location);
+            fn emit_shr(&mut self, sz: Size, src: Location, dst: Location);
+            fn emit_sar(&mut self, sz: Size, src: Location, dst: Location);
+            fn emit_rol(&mut self, sz: Size, src: Location, dst: Location);
+            fn emit_ror(&mut self, sz: Size, src: Location, dst: Location);
+            fn emit_and(&mut self, sz: Size, src: Location, dst: Location);
+            fn emit_or(&mut self, sz: Size, src: Location, dst: Location);
+            fn emit_bsr(&mut self, sz: Size, src: Location, dst: Location);
+            fn emit_bsf(&mut self, sz: Size, src: Location, dst: Location);
+            fn emit_popcnt(&mut self, sz: Size, src: Location, dst: Location);
+            fn emit_lzcnt(&mut self, sz: Size, src: Location, dst: Location);
+            fn emit_tzcnt(&mut self, sz: Size, src: Location, dst: Location);
+            fn emit_movzx(&mut self, sz_src: Size, src: Location, sz_dst: Size, dst: Location);
+            fn emit_movsx(&mut self, sz_src: Size, src: Location, sz_dst: Size, dst: Location);
+            fn emit_xchg(&mut self, sz: Size, src: Location, dst: Location);
+            fn emit_lock_xadd(&mut self, sz: Size, src: Location, dst: Location);
+            fn emit_lock_cmpxchg(&mut self, sz: Size, src: Location, dst: Location);
+            fn emit_rep_stosq(&mut self);
+
+            fn emit_btc_gpr_imm8_32(&mut self, src: u8, dst: GPR);
+            fn emit_btc_gpr_imm8_64(&mut self, src: u8, dst: GPR);
+
+            fn emit_cmovcc(&mut self, sz: Size, condition: Condition, src: Location, dst: Location);
+
+            fn emit_vmovaps(&mut self, src: XMMOrMemory, dst: XMMOrMemory);
+            fn emit_vmovapd(&mut self, src: XMMOrMemory, dst: XMMOrMemory);
+            fn emit_movdqu(&mut self, src: XMMOrMemory, dst: XMMOrMemory);
+            fn emit_vxorps(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vxorpd(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vorps(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vorpd(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vandps(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vandpd(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+
+            fn emit_vaddss(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vaddsd(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vsubss(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vsubsd(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vmulss(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vmulsd(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vdivss(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vdivsd(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vmaxss(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vmaxsd(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vminss(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vminsd(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+
+            fn emit_vcmpeqss(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vcmpeqsd(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vcmpneqss(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vcmpneqsd(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vcmpltss(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vcmpltsd(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vcmpless(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vcmplesd(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vcmpgtss(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vcmpgtsd(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vcmpgess(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vcmpgesd(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vcmpunordss(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vcmpunordsd(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vcmpordss(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vcmpordsd(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+
+            fn emit_vsqrtss(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vsqrtsd(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+
+            fn emit_vroundss_nearest(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vroundss_floor(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vroundss_ceil(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vroundss_trunc(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vroundsd_nearest(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vroundsd_floor(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vroundsd_ceil(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vroundsd_trunc(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+
+            fn emit_vcvtss2sd(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vcvtsd2ss(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+
+            fn emit_vpaddb(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vpaddw(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vpaddd(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vpaddq(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vpaddusb(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vpsubb(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vpsubw(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vpsubd(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vpsubq(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vpmullw(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vpmulld(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vpmuludq(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vpand(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vpandn(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vpor(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vpxor(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vpcmpeqb(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vpcmpeqd(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vpshufb(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+            fn emit_vpunpcklqdq(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM);
+
+            fn emit_pshufd(&mut self, src: XMM, imm: u8, dst: XMM);
+            fn emit_pshuflw(&mut self, src: XMM, imm: u8, dst: XMM);
+            fn emit_psllq_imm(&mut self, imm: u8, dst: XMM);
+            fn emit_psrlq_imm(&mut self, imm: u8, dst: XMM);
+            fn emit_pextr(&mut self, sz: Size, src: XMM, lane: u8, dst: GPR);
+            fn emit_pinsr(&mut self, sz: Size, src: GPR, lane: u8, dst: XMM);
+            fn emit_ptest(&mut self, src: XMMOrMemory, dst: XMM);
+            fn emit_pmovsx(&mut self, sz: Size, src: XMM, dst: XMM);
+            fn emit_pmovzx(&mut self, sz: Size, src: XMM, dst: XMM);
+
+            fn emit_ucomiss(&mut self, src: XMMOrMemory, dst: XMM);
+            fn emit_ucomisd(&mut self, src: XMMOrMemory, dst: XMM);
+
+            fn emit_cvttss2si_32(&mut self, src: XMMOrMemory, dst: GPR);
+            fn emit_cvttss2si_64(&mut self, src: XMMOrMemory, dst: GPR);
+            fn emit_cvttsd2si_32(&mut self, src: XMMOrMemory, dst: GPR);
+            fn emit_cvttsd2si_64(&mut self, src: XMMOrMemory, dst: GPR);
+
+            fn emit_vcvtsi2ss_32(&mut self, src1: XMM, src2: GPROrMemory, dst: XMM);
+            fn emit_vcvtsi2ss_64(&mut self, src1: XMM, src2: GPROrMemory, dst: XMM);
+            fn emit_vcvtsi2sd_32(&mut self, src1: XMM, src2: GPROrMemory, dst: XMM);
+            fn emit_vcvtsi2sd_64(&mut self, src1: XMM, src2: GPROrMemory, dst: XMM);
+
+            fn emit_vblendvps(&mut self, src1: XMM, src2: XMMOrMemory, mask: XMM, dst: XMM);
+            fn emit_vblendvpd(&mut self, src1: XMM, src2: XMMOrMemory, mask: XMM, dst: XMM);
+
+            fn emit_test_gpr_64(&mut self, reg: GPR);
+
+            fn emit_ud2(&mut self);
+            fn emit_ret(&mut self);
+            fn emit_call_label(&mut self, label: Self::Label);
+            fn emit_call_location(&mut self, loc: Location);
+            fn emit_call_register(&mut self, reg: GPR);
+
+            fn emit_bkpt(&mut self);
+
+            fn emit_host_redirection(&mut self, target: GPR);
+
+            fn arch_emit_i32_trunc_sf32(&mut self, src: XMM, dst: GPR);
+            fn arch_emit_i32_trunc_sf64(&mut self, src: XMM, dst: GPR);
+            fn arch_emit_i32_trunc_uf32(&mut self, src: XMM, dst: GPR);
+            fn arch_emit_i32_trunc_uf64(&mut self, src: XMM, dst: GPR);
+            fn arch_emit_i64_trunc_sf32(&mut self, src: XMM, dst: GPR);
+            fn arch_emit_i64_trunc_sf64(&mut self, src: XMM, dst: GPR);
+            fn arch_emit_i64_trunc_uf32(&mut self, src: XMM, dst: GPR);
+            fn arch_emit_i64_trunc_uf64(&mut self, src: XMM, dst: GPR);
+
+            fn arch_emit_f32_convert_si32(&mut self, src: GPR, dst: XMM);
+            fn arch_emit_f32_convert_si64(&mut self, src: GPR, dst: XMM);
+            fn arch_emit_f32_convert_ui32(&mut self, src: GPR, dst: XMM);
+            fn arch_emit_f32_convert_ui64(&mut self, src: GPR, dst: XMM);
+            fn arch_emit_f64_convert_si32(&mut self, src: GPR, dst: XMM);
+            fn arch_emit_f64_convert_si64(&mut self, src: GPR, dst: XMM);
+            fn arch_emit_f64_convert_ui32(&mut self, src: GPR, dst: XMM);
+            fn arch_emit_f64_convert_ui64(&mut self, src: GPR, dst: XMM);
+
+            fn arch_emit_f32_neg(&mut self, src: XMM, dst: XMM);
+            fn arch_emit_f64_neg(&mut self, src: XMM, dst: XMM);
+
+            fn arch_emit_indirect_call_with_trampoline(&mut self, loc: Location);
+            fn arch_emit_entry_trampoline(&mut self);
+        }
+    };
 }
+pub(crate) use with_forwarded_emitter_methods;
 
 macro_rules! unop_gpr {
     ($ins:ident, $assembler:tt, $sz:expr, $loc:expr, $otherwise:block) => {
@@ -450,6 +687,79 @@ macro_rules! binop_all_nofp {
     };
 }
 
+/// Emits a 64-bit `$emit` with an `Imm64` source that doesn't fit in a sign-extended 32-bit
+/// immediate, by materializing it in a scratch GPR.
+macro_rules! binop_large_imm64 {
+    ($emit:expr, $assembler:tt, $sz:expr, $src:expr, $dst:expr, $otherwise:block) => {
+        match ($sz, $src, $dst) {
+            (Size::S64, Location::Imm64(src), Location::GPR(_))
+            | (Size::S64, Location::Imm64(src), Location::Memory(_, _)) => {
+                let scratch = ScratchGpr::acquire($assembler, &[$dst]);
+                dynasm!($assembler ; mov Rq(scratch.gpr() as u8), QWORD src as i64);
+                $emit($assembler, Size::S64, Location::GPR(scratch.gpr()), scratch.adjust($dst));
+                scratch.release($assembler);
+            },
+            _ => $otherwise
+        }
+    };
+}
+
+/// Narrows an `Imm64` operand of an instruction of size `sz` to an `Imm32`, if the instruction
+/// reads the same value from both.
+fn narrow_imm64(sz: Size, loc: Location) -> Location {
+    match loc {
+        Location::Imm64(x) if sz != Size::S64 || x as i64 == x as i32 as i64 => {
+            Location::Imm32(x as u32)
+        }
+        _ => loc,
+    }
+}
+
+/// A GPR borrowed by the emitter itself, to hold an operand that can't be encoded in the
+/// instruction.
+///
+/// No GPR is free everywhere in the generated code, so the borrowed one is pushed on `acquire`
+/// and popped on `release`. While it is held, `RSP` is 8 bytes lower, so locations relative to
+/// it must go through `adjust`. Neither `push` nor `pop` changes the flags.
+struct ScratchGpr(GPR);
+
+impl ScratchGpr {
+    const CANDIDATES: [GPR; 5] = [GPR::RAX, GPR::RCX, GPR::RDX, GPR::RSI, GPR::RDI];
+
+    /// Saves a GPR not referenced by any of the `avoid` locations.
+    fn acquire(a: &mut Assembler, avoid: &[Location]) -> Self {
+        let gpr = Self::CANDIDATES
+            .iter()
+            .cloned()
+            .find(|r| {
+                !avoid.iter().any(|loc| match *loc {
+                    Location::GPR(x) | Location::Memory(x, _) => x == *r,
+                    _ => false,
+                })
+            })
+            .unwrap();
+        dynasm!(a ; push Rq(gpr as u8));
+        Self(gpr)
+    }
+
+    fn gpr(&self) -> GPR {
+        self.0
+    }
+
+    /// Translates a location computed before `acquire`.
+    fn adjust(&self, loc: Location) -> Location {
+        match loc {
+            Location::Memory(GPR::RSP, disp) => Location::Memory(GPR::RSP, disp + 8),
+            _ => loc,
+        }
+    }
+
+    /// Restores the GPR.
+    fn release(self, a: &mut Assembler) {
+        dynasm!(a ; pop Rq(self.0 as u8));
+    }
+}
+
 macro_rules! binop_shift {
     ($ins:ident, $assembler:tt, $sz:expr, $src:expr, $dst:expr, $otherwise:block) => {
         match ($sz, $src, $dst) {
@@ -482,6 +792,26 @@ macro_rules! binop_shift {
     }
 }
 
+macro_rules! cmov_op {
+    ($ins:ident, $assembler:tt, $sz:expr, $src:expr, $dst:expr, $otherwise:block) => {
+        match ($sz, $src, $dst) {
+            (Size::S32, Location::GPR(src), Location::GPR(dst)) => {
+                dynasm!($assembler ; $ins Rd(dst as u8), Rd(src as u8));
+            },
+            (Size::S32, Location::Memory(src, disp), Location::GPR(dst)) => {
+                dynasm!($assembler ; $ins Rd(dst as u8), [Rq(src as u8) + disp]);
+            },
+            (Size::S64, Location::GPR(src), Location::GPR(dst)) => {
+                dynasm!($assembler ; $ins Rq(dst as u8), Rq(src as u8));
+            },
+            (Size::S64, Location::Memory(src, disp), Location::GPR(dst)) => {
+                dynasm!($assembler ; $ins Rq(dst as u8), [Rq(src as u8) + disp]);
+            },
+            _ => $otherwise
+        }
+    }
+}
+
 macro_rules! jmp_op {
     ($ins:ident, $assembler:tt, $label:ident) => {
         dynasm!($assembler ; $ins =>$label)
@@ -624,12 +954,20 @@ macro_rules! avx_i2f_32_fn {
     }
 }
 
+/// The bit of the immediate of `roundss` and `roundsd` suppressing the precision exception.
+///
+/// The rounding mode always comes from the immediate rather than from MXCSR, and with this bit the
+/// inexact results don't raise the exception either, so the code doesn't trap even if the host
+/// unmasked it in its MXCSR.
+const ROUND_SUPPRESS_PRECISION: u8 = 0b1000;
+
 macro_rules! avx_round_fn {
     ($ins:ident, $name:ident, $mode:expr) => {
         fn $name(&mut self, src1: XMM, src2: XMMOrMemory, dst: XMM) {
+            let imm = $mode | ROUND_SUPPRESS_PRECISION;
             match src2 {
-                XMMOrMemory::XMM(x) => dynasm!(self ; $ins Rx((dst as u8)), Rx((src1 as u8)), Rx((x as u8)), $mode),
-                XMMOrMemory::Memory(base, disp) => dynasm!(self ; $ins Rx((dst as u8)), Rx((src1 as u8)), [Rq((base as u8)) + disp], $mode),
+                XMMOrMemory::XMM(x) => dynasm!(self ; $ins Rx((dst as u8)), Rx((src1 as u8)), Rx((x as u8)), imm as i8),
+                XMMOrMemory::Memory(base, disp) => dynasm!(self ; $ins Rx((dst as u8)), Rx((src1 as u8)), [Rq((base as u8)) + disp], imm as i8),
             }
         }
     }
@@ -643,7 +981,7 @@ impl Emitter for Assembler {
         self.new_dynamic_label()
     }
 
-    fn get_offset(&self) -> AssemblyOffset {
+    fn get_offset(&mut self) -> AssemblyOffset {
         self.offset()
     }
 
@@ -759,6 +1097,13 @@ impl Emitter for Assembler {
                     (Size::S16, Location::Imm64(src), Location::Memory(dst, disp)) => {
                         dynasm!(self ; mov WORD [Rq(dst as u8) + disp], src as i16);
                     }
+                    (Size::S64, Location::Imm64(_), Location::Memory(_, _)) => {
+                        binop_imm32_mem!(mov, self, sz, narrow_imm64(sz, src), dst, {
+                            binop_large_imm64!(Self::emit_mov, self, sz, src, dst, {
+                                unreachable!()
+                            })
+                        });
+                    }
                     (Size::S32, Location::Imm64(src), Location::GPR(dst)) => {
                         dynasm!(self ; mov Rd(dst as u8), src as i32);
                     }
@@ -807,12 +1152,6 @@ impl Emitter for Assembler {
             (Size::S64, Location::Memory(src, disp), Location::GPR(dst)) => {
                 dynasm!(self ; lea Rq(dst as u8), [Rq(src as u8) + disp]);
             }
-            (Size::S32, Location::MemoryAddTriple(src1, src2, disp), Location::GPR(dst)) => {
-                dynasm!(self ; lea Rd(dst as u8), [Rq(src1 as u8) + Rq(src2 as u8) + disp]);
-            }
-            (Size::S64, Location::MemoryAddTriple(src1, src2, disp), Location::GPR(dst)) => {
-                dynasm!(self ; lea Rq(dst as u8), [Rq(src1 as u8) + Rq(src2 as u8) + disp]);
-            }
             _ => panic!("singlepass can't emit LEA {:?} {:?} {:?}", sz, src, dst),
         }
     }
@@ -824,6 +1163,13 @@ impl Emitter for Assembler {
             _ => panic!("singlepass can't emit LEA label={:?} {:?}", label, dst),
         }
     }
+    fn emit_lea_pc_relative(&mut self, dst: GPR) {
+        // `lea dst, [rip + 0]`: REX.W with the high bit of `dst`, the opcode, and a ModRM byte
+        // selecting RIP-relative addressing with a 32-bit displacement.
+        let dst = dst as u8;
+        self.emit_bytes(&[0x48 | (dst >> 3) << 2, 0x8d, 0x05 | (dst & 7) << 3]);
+        self.emit_bytes(&[0; 4]);
+    }
     fn emit_cdq(&mut self) {
         dynasm!(self ; cdq);
     }
@@ -831,8 +1177,11 @@ impl Emitter for Assembler {
         dynasm!(self ; cqo);
     }
     fn emit_xor(&mut self, sz: Size, src: Location, dst: Location) {
+        let src = narrow_imm64(sz, src);
         binop_all_nofp!(xor, self, sz, src, dst, {
-            panic!("singlepass can't emit XOR {:?} {:?} {:?}", sz, src, dst)
+            binop_large_imm64!(Self::emit_xor, self, sz, src, dst, {
+                panic!("singlepass can't emit XOR {:?} {:?} {:?}", sz, src, dst)
+            })
         });
     }
     fn emit_jmp(&mut self, condition: Condition, label: Self::Label) {
@@ -851,6 +1200,7 @@ impl Emitter for Assembler {
             Condition::Signed => jmp_op!(js, self, label),
             Condition::Carry => jmp_op!(jc, self, label),
             Condition::Overflow => jmp_op!(jo, self, label),
+            Condition::NotOverflow => jmp_op!(jno, self, label),
         }
     }
     fn emit_jmp_location(&mut self, loc: Location) {
@@ -875,6 +1225,7 @@ impl Emitter for Assembler {
             Condition::Signed => dynasm!(self ; sets Rb(dst as u8)),
             Condition::Carry => dynasm!(self ; setc Rb(dst as u8)),
             Condition::Overflow => dynasm!(self ; seto Rb(dst as u8)),
+            Condition::NotOverflow => dynasm!(self ; setno Rb(dst as u8)),
             _ => panic!("singlepass can't emit SET {:?} {:?}", condition, dst),
         }
     }
@@ -916,27 +1267,47 @@ impl Emitter for Assembler {
                 Ordering::Equal => dynasm!(self ; cmp DWORD [>const_zero_32], 0),
                 Ordering::Greater => dynasm!(self ; cmp DWORD [>const_pos_one_32], 0),
             },
-            None => binop_all_nofp!(cmp, self, sz, left, right, {
-                panic!("singlepass can't emit CMP {:?} {:?} {:?}", sz, left, right);
-            }),
+            None => {
+                let (left, right) = (narrow_imm64(sz, left), narrow_imm64(sz, right));
+                match right {
+                    // Only the left operand can be an immediate.
+                    Location::Imm32(_) | Location::Imm64(_) => {
+                        let scratch = ScratchGpr::acquire(self, &[left]);
+                        self.emit_mov(sz, right, Location::GPR(scratch.gpr()));
+                        self.emit_cmp(sz, scratch.adjust(left), Location::GPR(scratch.gpr()));
+                        scratch.release(self);
+                    }
+                    _ => binop_all_nofp!(cmp, self, sz, left, right, {
+                        binop_large_imm64!(Self::emit_cmp, self, sz, left, right, {
+                            panic!("singlepass can't emit CMP {:?} {:?} {:?}", sz, left, right);
+                        })
+                    }),
+                }
+            }
         }
     }
     fn emit_add(&mut self, sz: Size, src: Location, dst: Location) {
+        let src = narrow_imm64(sz, src);
         // Fast path
         if let Location::Imm32(0) = src {
             return;
         }
         binop_all_nofp!(add, self, sz, src, dst, {
-            panic!("singlepass can't emit ADD {:?} {:?} {:?}", sz, src, dst)
+            binop_large_imm64!(Self::emit_add, self, sz, src, dst, {
+                panic!("singlepass can't emit ADD {:?} {:?} {:?}", sz, src, dst)
+            })
         });
     }
     fn emit_sub(&mut self, sz: Size, src: Location, dst: Location) {
+        let src = narrow_imm64(sz, src);
         // Fast path
         if let Location::Imm32(0) = src {
             return;
         }
         binop_all_nofp!(sub, self, sz, src, dst, {
-            panic!("singlepass can't emit SUB {:?} {:?} {:?}", sz, src, dst)
+            binop_large_imm64!(Self::emit_sub, self, sz, src, dst, {
+                panic!("singlepass can't emit SUB {:?} {:?} {:?}", sz, src, dst)
+            })
         });
     }
     fn emit_neg(&mut self, sz: Size, value: Location) {
@@ -1006,13 +1377,19 @@ impl Emitter for Assembler {
         });
     }
     fn emit_and(&mut self, sz: Size, src: Location, dst: Location) {
+        let src = narrow_imm64(sz, src);
         binop_all_nofp!(and, self, sz, src, dst, {
-            panic!("singlepass can't emit AND {:?} {:?} {:?}", sz, src, dst)
+            binop_large_imm64!(Self::emit_and, self, sz, src, dst, {
+                panic!("singlepass can't emit AND {:?} {:?} {:?}", sz, src, dst)
+            })
         });
     }
     fn emit_or(&mut self, sz: Size, src: Location, dst: Location) {
+        let src = narrow_imm64(sz, src);
         binop_all_nofp!(or, self, sz, src, dst, {
-            panic!("singlepass can't emit OR {:?} {:?} {:?}", sz, src, dst)
+            binop_large_imm64!(Self::emit_or, self, sz, src, dst, {
+                panic!("singlepass can't emit OR {:?} {:?} {:?}", sz, src, dst)
+            })
         });
     }
     fn emit_bsr(&mut self, sz: Size, src: Location, dst: Location) {
@@ -1036,6 +1413,20 @@ impl Emitter for Assembler {
             })
         });
     }
+    fn emit_lzcnt(&mut self, sz: Size, src: Location, dst: Location) {
+        binop_gpr_gpr!(lzcnt, self, sz, src, dst, {
+            binop_mem_gpr!(lzcnt, self, sz, src, dst, {
+                panic!("singlepass can't emit LZCNT {:?} {:?} {:?}", sz, src, dst)
+            })
+        });
+    }
+    fn emit_tzcnt(&mut self, sz: Size, src: Location, dst: Location) {
+        binop_gpr_gpr!(tzcnt, self, sz, src, dst, {
+            binop_mem_gpr!(tzcnt, self, sz, src, dst, {
+                panic!("singlepass can't emit TZCNT {:?} {:?} {:?}", sz, src, dst)
+            })
+        });
+    }
     fn emit_movzx(&mut self, sz_src: Size, src: Location, sz_dst: Size, dst: Location) {
         match (sz_src, src, sz_dst, dst) {
             (Size::S8, Location::GPR(src), Size::S32, Location::GPR(dst)) => {
@@ -1206,12 +1597,30 @@ impl Emitter for Assembler {
         dynasm!(self ; btc Rq(dst as u8), BYTE src as i8);
     }
 
-    fn emit_cmovae_gpr_32(&mut self, src: GPR, dst: GPR) {
-        dynasm!(self ; cmovae Rd(dst as u8), Rd(src as u8));
-    }
-
-    fn emit_cmovae_gpr_64(&mut self, src: GPR, dst: GPR) {
-        dynasm!(self ; cmovae Rq(dst as u8), Rq(src as u8));
+    fn emit_cmovcc(&mut self, sz: Size, condition: Condition, src: Location, dst: Location) {
+        let unsupported = || {
+            panic!(
+                "singlepass can't emit CMOV {:?} {:?} {:?} {:?}",
+                sz, condition, src, dst
+            )
+        };
+        match condition {
+            Condition::Above => cmov_op!(cmova, self, sz, src, dst, { unsupported() }),
+            Condition::AboveEqual => cmov_op!(cmovae, self, sz, src, dst, { unsupported() }),
+            Condition::Below => cmov_op!(cmovb, self, sz, src, dst, { unsupported() }),
+            Condition::BelowEqual => cmov_op!(cmovbe, self, sz, src, dst, { unsupported() }),
+            Condition::Greater => cmov_op!(cmovg, self, sz, src, dst, { unsupported() }),
+            Condition::GreaterEqual => cmov_op!(cmovge, self, sz, src, dst, { unsupported() }),
+            Condition::Less => cmov_op!(cmovl, self, sz, src, dst, { unsupported() }),
+            Condition::LessEqual => cmov_op!(cmovle, self, sz, src, dst, { unsupported() }),
+            Condition::Equal => cmov_op!(cmove, self, sz, src, dst, { unsupported() }),
+            Condition::NotEqual => cmov_op!(cmovne, self, sz, src, dst, { unsupported() }),
+            Condition::Signed => cmov_op!(cmovs, self, sz, src, dst, { unsupported() }),
+            Condition::Carry => cmov_op!(cmovc, self, sz, src, dst, { unsupported() }),
+            Condition::Overflow => cmov_op!(cmovo, self, sz, src, dst, { unsupported() }),
+            Condition::NotOverflow => cmov_op!(cmovno, self, sz, src, dst, { unsupported() }),
+            Condition::None => unsupported(),
+        }
     }
 
     fn emit_vmovaps(&mut self, src: XMMOrMemory, dst: XMMOrMemory) {
@@ -1244,8 +1653,27 @@ impl Emitter for Assembler {
         };
     }
 
+    fn emit_movdqu(&mut self, src: XMMOrMemory, dst: XMMOrMemory) {
+        match (src, dst) {
+            (XMMOrMemory::XMM(src), XMMOrMemory::XMM(dst)) => {
+                dynasm!(self ; movdqu Rx(dst as u8), Rx(src as u8))
+            }
+            (XMMOrMemory::Memory(base, disp), XMMOrMemory::XMM(dst)) => {
+                dynasm!(self ; movdqu Rx(dst as u8), [Rq(base as u8) + disp])
+            }
+            (XMMOrMemory::XMM(src), XMMOrMemory::Memory(base, disp)) => {
+                dynasm!(self ; movdqu [Rq(base as u8) + disp], Rx(src as u8))
+            }
+            _ => panic!("singlepass can't emit MOVDQU {:?} {:?}", src, dst),
+        };
+    }
+
     avx_fn!(vxorps, emit_vxorps);
     avx_fn!(vxorpd, emit_vxorpd);
+    avx_fn!(vorps, emit_vorps);
+    avx_fn!(vorpd, emit_vorpd);
+    avx_fn!(vandps, emit_vandps);
+    avx_fn!(vandpd, emit_vandpd);
 
     avx_fn!(vaddss, emit_vaddss);
     avx_fn!(vaddsd, emit_vaddsd);
@@ -1309,6 +1737,88 @@ impl Emitter for Assembler {
     avx_i2f_64_fn!(vcvtsi2ss, emit_vcvtsi2ss_64);
     avx_i2f_64_fn!(vcvtsi2sd, emit_vcvtsi2sd_64);
 
+    avx_fn!(vpaddb, emit_vpaddb);
+    avx_fn!(vpaddw, emit_vpaddw);
+    avx_fn!(vpaddd, emit_vpaddd);
+    avx_fn!(vpaddq, emit_vpaddq);
+    avx_fn!(vpaddusb, emit_vpaddusb);
+    avx_fn!(vpsubb, emit_vpsubb);
+    avx_fn!(vpsubw, emit_vpsubw);
+    avx_fn!(vpsubd, emit_vpsubd);
+    avx_fn!(vpsubq, emit_vpsubq);
+    avx_fn!(vpmullw, emit_vpmullw);
+    avx_fn!(vpmulld, emit_vpmulld);
+    avx_fn!(vpmuludq, emit_vpmuludq);
+    avx_fn!(vpand, emit_vpand);
+    avx_fn!(vpandn, emit_vpandn);
+    avx_fn!(vpor, emit_vpor);
+    avx_fn!(vpxor, emit_vpxor);
+    avx_fn!(vpcmpeqb, emit_vpcmpeqb);
+    avx_fn!(vpcmpeqd, emit_vpcmpeqd);
+    avx_fn!(vpshufb, emit_vpshufb);
+    avx_fn!(vpunpcklqdq, emit_vpunpcklqdq);
+
+    fn emit_pshufd(&mut self, src: XMM, imm: u8, dst: XMM) {
+        dynasm!(self ; pshufd Rx(dst as u8), Rx(src as u8), imm as i8);
+    }
+
+    fn emit_pshuflw(&mut self, src: XMM, imm: u8, dst: XMM) {
+        dynasm!(self ; pshuflw Rx(dst as u8), Rx(src as u8), imm as i8);
+    }
+
+    fn emit_psllq_imm(&mut self, imm: u8, dst: XMM) {
+        dynasm!(self ; psllq Rx(dst as u8), imm as i8);
+    }
+
+    fn emit_psrlq_imm(&mut self, imm: u8, dst: XMM) {
+        dynasm!(self ; psrlq Rx(dst as u8), imm as i8);
+    }
+
+    fn emit_pextr(&mut self, sz: Size, src: XMM, lane: u8, dst: GPR) {
+        match sz {
+            Size::S8 => dynasm!(self ; pextrb Rd(dst as u8), Rx(src as u8), lane as i8),
+            Size::S16 => dynasm!(self ; pextrw Rd(dst as u8), Rx(src as u8), lane as i8),
+            Size::S32 => dynasm!(self ; pextrd Rd(dst as u8), Rx(src as u8), lane as i8),
+            Size::S64 => dynasm!(self ; pextrq Rq(dst as u8), Rx(src as u8), lane as i8),
+        }
+    }
+
+    fn emit_pinsr(&mut self, sz: Size, src: GPR, lane: u8, dst: XMM) {
+        match sz {
+            Size::S8 => dynasm!(self ; pinsrb Rx(dst as u8), Rd(src as u8), lane as i8),
+            Size::S16 => dynasm!(self ; pinsrw Rx(dst as u8), Rd(src as u8), lane as i8),
+            Size::S32 => dynasm!(self ; pinsrd Rx(dst as u8), Rd(src as u8), lane as i8),
+            Size::S64 => dynasm!(self ; pinsrq Rx(dst as u8), Rq(src as u8), lane as i8),
+        }
+    }
+
+    fn emit_ptest(&mut self, src: XMMOrMemory, dst: XMM) {
+        match src {
+            XMMOrMemory::XMM(x) => dynasm!(self ; ptest Rx(dst as u8), Rx(x as u8)),
+            XMMOrMemory::Memory(base, disp) => {
+                dynasm!(self ; ptest Rx(dst as u8), [Rq(base as u8) + disp])
+            }
+        }
+    }
+
+    fn emit_pmovsx(&mut self, sz: Size, src: XMM, dst: XMM) {
+        match sz {
+            Size::S8 => dynasm!(self ; pmovsxbw Rx(dst as u8), Rx(src as u8)),
+            Size::S16 => dynasm!(self ; pmovsxwd Rx(dst as u8), Rx(src as u8)),
+            Size::S32 => dynasm!(self ; pmovsxdq Rx(dst as u8), Rx(src as u8)),
+            Size::S64 => unreachable!(),
+        }
+    }
+
+    fn emit_pmovzx(&mut self, sz: Size, src: XMM, dst: XMM) {
+        match sz {
+            Size::S8 => dynasm!(self ; pmovzxbw Rx(dst as u8), Rx(src as u8)),
+            Size::S16 => dynasm!(self ; pmovzxwd Rx(dst as u8), Rx(src as u8)),
+            Size::S32 => dynasm!(self ; pmovzxdq Rx(dst as u8), Rx(src as u8)),
+            Size::S64 => unreachable!(),
+        }
+    }
+
     fn emit_vblendvps(&mut self, src1: XMM, src2: XMMOrMemory, mask: XMM, dst: XMM) {
         match src2 {
             XMMOrMemory::XMM(src2) => {
@@ -1418,8 +1928,135 @@ impl Emitter for Assembler {
     fn emit_host_redirection(&mut self, target: GPR) {
         self.emit_jmp_location(Location::GPR(target));
     }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const BOUNDARIES: [u64; 5] = [
+        i32::MAX as u64 + 1,
+        u32::MAX as u64,
+        i64::MIN as u64,
+        i64::MAX as u64,
+        i32::MIN as i64 as u64 - 1,
+    ];
+
+    fn emit(f: impl FnOnce(&mut Assembler)) -> Vec<u8> {
+        let mut a = Assembler::new(0);
+        f(&mut a);
+        a.finalize().unwrap()
+    }
+
+    #[test]
+    fn test_small_imm64_is_narrowed() {
+        let rax = Location::GPR(GPR::RAX);
+        for &x in &[0, 1, i32::MAX as u64, i32::MIN as i64 as u64, u64::MAX] {
+            assert_eq!(
+                emit(|a| a.emit_and(Size::S64, Location::Imm64(x), rax)),
+                emit(|a| a.emit_and(Size::S64, Location::Imm32(x as u32), rax)),
+                "{:#x}",
+                x
+            );
+        }
+        // 32-bit instructions only read the low half.
+        for &x in &BOUNDARIES {
+            assert_eq!(
+                emit(|a| a.emit_add(Size::S32, Location::Imm64(x), rax)),
+                emit(|a| a.emit_add(Size::S32, Location::Imm32(x as u32), rax)),
+                "{:#x}",
+                x
+            );
+        }
+    }
+
+    #[test]
+    fn test_pc_relative_lea_ends_with_its_displacement() {
+        for &dst in &[GPR::RAX, GPR::RDI, GPR::R8, GPR::R15] {
+            let code = emit(|a| a.emit_lea_pc_relative(dst));
+            assert_eq!(code[code.len() - 4..], [0; 4], "{:?}", dst);
+            // The same instruction as `lea dst, [rip + 0]` assembled by dynasm.
+            let mut a = Assembler::new(0);
+            dynasm::dynasm!(a ; .arch x64 ; lea Rq(dst as u8), [>next] ; next:);
+            assert_eq!(code, a.finalize().unwrap(), "{:?}", dst);
+        }
+    }
 
-    fn arch_mov64_imm_offset(&self) -> usize {
-        2
+    #[test]
+    fn test_large_imm64_is_materialized() {
+        let binops: [fn(&mut Assembler, Size, Location, Location); 7] = [
+            Assembler::emit_mov,
+            Assembler::emit_cmp,
+            Assembler::emit_add,
+            Assembler::emit_sub,
+            Assembler::emit_and,
+            Assembler::emit_or,
+            Assembler::emit_xor,
+        ];
+        let rax = Location::GPR(GPR::RAX);
+        let rcx = Location::GPR(GPR::RCX);
+        let indirect = Location::Memory(GPR::RAX, 8);
+        for &op in &binops {
+            for &x in &BOUNDARIES {
+                let src = Location::Imm64(x);
+                assert_eq!(
+                    emit(|a| op(a, Size::S64, src, indirect)),
+                    emit(|a| {
+                        a.emit_push(Size::S64, rcx);
+                        a.emit_mov(Size::S64, src, rcx);
+                        op(a, Size::S64, rcx, indirect);
+                        a.emit_pop(Size::S64, rcx);
+                    }),
+                    "{:#x}",
+                    x
+                );
+            }
+        }
+        // Except for `mov`, a register destination is materialized too.
+        for &op in &binops[1..] {
+            let src = Location::Imm64(i64::MIN as u64);
+            assert_eq!(
+                emit(|a| op(a, Size::S64, src, rax)),
+                emit(|a| {
+                    a.emit_push(Size::S64, rcx);
+                    a.emit_mov(Size::S64, src, rcx);
+                    op(a, Size::S64, rcx, rax);
+                    a.emit_pop(Size::S64, rcx);
+                }),
+            );
+        }
+    }
+
+    #[test]
+    fn test_scratch_gpr_accounts_for_push() {
+        let src = Location::Imm64(u32::MAX as u64);
+        let rax = Location::GPR(GPR::RAX);
+        assert_eq!(
+            emit(|a| a.emit_add(Size::S64, src, Location::Memory(GPR::RSP, 8))),
+            emit(|a| {
+                a.emit_push(Size::S64, rax);
+                a.emit_mov(Size::S64, src, rax);
+                a.emit_add(Size::S64, rax, Location::Memory(GPR::RSP, 16));
+                a.emit_pop(Size::S64, rax);
+            }),
+        );
+    }
+
+    #[test]
+    fn test_cmp_with_immediate_on_the_right() {
+        let rax = Location::GPR(GPR::RAX);
+        let rcx = Location::GPR(GPR::RCX);
+        let large = Location::Imm64(i64::MIN as u64);
+        for &(right, materialized) in &[(Location::Imm64(1), Location::Imm32(1)), (large, large)] {
+            assert_eq!(
+                emit(|a| a.emit_cmp(Size::S64, rax, right)),
+                emit(|a| {
+                    a.emit_push(Size::S64, rcx);
+                    a.emit_mov(Size::S64, materialized, rcx);
+                    a.emit_cmp(Size::S64, rax, rcx);
+                    a.emit_pop(Size::S64, rcx);
+                }),
+            );
+        }
     }
 }
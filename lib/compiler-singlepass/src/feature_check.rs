@@ -0,0 +1,97 @@
+//! The check of a whole module for the features singlepass doesn't support, done along with
+//! its validation.
+
+use crate::codegen_x64::is_supported_operator;
+use wasmer_compiler::wasmparser::{Import, ImportSectionEntryType, MemoryType, Operator, Payload};
+use wasmer_compiler::{
+    operator_name, CompileError, UnsupportedItem, UnsupportedUse, ValidatedFunction,
+    ValidationVisitor,
+};
+
+/// The features singlepass doesn't support found so far in a module.
+#[derive(Debug, Default)]
+pub(crate) struct FeatureCheck {
+    /// The features found, in the order of their first use.
+    items: Vec<UnsupportedItem>,
+    /// The number of memories declared so far.
+    memories: usize,
+}
+
+impl FeatureCheck {
+    /// The error listing the unsupported features found in the module, if there are any.
+    pub(crate) fn finish(self) -> Result<(), CompileError> {
+        if self.items.is_empty() {
+            Ok(())
+        } else {
+            Err(CompileError::UnsupportedFeatures(self.items))
+        }
+    }
+
+    fn found(&mut self, feature: String, use_: UnsupportedUse) {
+        match self.items.iter_mut().find(|item| item.feature == feature) {
+            Some(item) => item.uses.push(use_),
+            None => self.items.push(UnsupportedItem {
+                feature,
+                uses: vec![use_],
+            }),
+        }
+    }
+
+    fn memory(&mut self, offset: usize, memory: &MemoryType) {
+        self.memories += 1;
+        // The code generator only ever accesses the first memory.
+        if self.memories > 1 {
+            self.found(
+                "multiple memories".to_string(),
+                UnsupportedUse::Section(offset),
+            );
+        }
+        if let MemoryType::M64 { .. } = memory {
+            self.found(
+                "64-bit memories".to_string(),
+                UnsupportedUse::Section(offset),
+            );
+        }
+    }
+}
+
+impl ValidationVisitor for FeatureCheck {
+    fn payload(&mut self, payload: &Payload<'_>) {
+        match payload {
+            Payload::ImportSection(imports) => {
+                let mut imports = imports.clone();
+                for _ in 0..imports.get_count() {
+                    let offset = imports.original_position();
+                    match imports.read() {
+                        Ok(Import {
+                            ty: ImportSectionEntryType::Memory(memory),
+                            ..
+                        }) => self.memory(offset, &memory),
+                        Ok(_) => {}
+                        Err(_) => break,
+                    }
+                }
+            }
+            Payload::MemorySection(memories) => {
+                let mut memories = memories.clone();
+                for _ in 0..memories.get_count() {
+                    let offset = memories.original_position();
+                    match memories.read() {
+                        Ok(memory) => self.memory(offset, &memory),
+                        Err(_) => break,
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn operator(&mut self, function: &ValidatedFunction<'_>, offset: usize, op: &Operator<'_>) {
+        if !is_supported_operator(op) {
+            self.found(
+                operator_name(op),
+                UnsupportedUse::Function(function.location(offset, Some(op))),
+            );
+        }
+    }
+}
@@ -12,9 +12,21 @@ mod address_map;
 mod codegen_x64;
 mod compiler;
 mod config;
+mod const_fold;
+#[cfg(feature = "debug-asm")]
+mod debug_asm;
+mod dwarf;
 mod emitter_x64;
+mod feature_check;
 mod machine;
+#[cfg(feature = "debug-machine-checks")]
+mod machine_checks;
+mod metering;
+mod peephole;
+mod unwind;
+mod unwind_winx64;
 mod x64_decl;
 
 pub use crate::compiler::SinglepassCompiler;
 pub use crate::config::Singlepass;
+pub use crate::x64_decl::GPR;
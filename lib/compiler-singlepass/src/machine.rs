@@ -1,36 +1,327 @@
+use crate::codegen_x64::CodegenError;
 use crate::emitter_x64::*;
+#[cfg(feature = "debug-machine-checks")]
+use crate::machine_checks::{Ledger, Register};
+use crate::unwind::UnwindOp;
+use crate::x64_decl::X64Register;
+use dynasmrt::{AssemblyOffset, DynamicLabel};
 use smallvec::smallvec;
 use smallvec::SmallVec;
 use std::collections::HashSet;
+use std::iter;
+use std::ops::Range;
 use wasmer_compiler::wasmparser::Type as WpType;
-use wasmer_compiler::CallingConvention;
+use wasmer_compiler::{CallingConvention, FrameLayout, MachineStats};
 
-const NATIVE_PAGE_SIZE: usize = 4096;
+/// The interval between stack probes when the target doesn't specify its page size, the smallest
+/// page size of x86_64.
+pub(crate) const DEFAULT_PROBE_STRIDE: usize = 4096;
 
 struct MachineStackOffset(usize);
 
+/// The state of an 8-byte stack slot for values.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum StackSlot {
+    Free,
+    /// Holds a value, or the lower half of a 16-byte value starting in this slot.
+    Used,
+    /// Holds the upper half of the 16-byte value starting in the next slot.
+    UpperHalf,
+}
+
+/// The stack area set up by `Machine::prepare_call_frame` around a native call.
+///
+/// From RSP upwards, it holds the shadow space, the stack arguments, the alignment padding, the
+/// saved XMM registers and the saved GPRs.
+#[derive(Clone)]
+pub(crate) struct CallFrame {
+    saved_gprs: Vec<GPR>,
+    saved_xmms: Vec<XMM>,
+    /// Size of the shadow space, stack arguments and padding.
+    size: usize,
+    shadow_space: usize,
+}
+
+impl CallFrame {
+    /// Where the `idx`-th argument passed on the stack goes, relative to RSP at the call.
+    pub(crate) fn stack_arg_location(&self, idx: usize) -> Location {
+        Location::Memory(GPR::RSP, (self.shadow_space + 8 * idx) as i32)
+    }
+
+    /// The number of bytes the frame takes on the stack, saved registers included.
+    pub(crate) fn depth(&self) -> usize {
+        8 * self.saved_gprs.len() + 16 * self.saved_xmms.len() + self.size
+    }
+}
+
+/// The registers caching the base of the memory for the accesses of a basic block, see
+/// `Machine::acquire_memory_cache`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct MemoryCache {
+    pub(crate) base: GPR,
+    /// Holds the address right past the current length of the memory, if the accesses are
+    /// bounds-checked.
+    pub(crate) end: Option<GPR>,
+}
+
+impl MemoryCache {
+    fn registers(self) -> impl Iterator<Item = GPR> {
+        iter::once(self.base).chain(self.end)
+    }
+}
+
 pub(crate) struct Machine {
     used_gprs: HashSet<GPR>,
     used_xmms: HashSet<XMM>,
+    /// The used XMM registers holding a 16-byte value rather than a float.
+    v128_xmms: HashSet<XMM>,
     stack_offset: MachineStackOffset,
+    /// Memory location at which the stack slots for values begin.
+    ///
+    /// Populated in `init_locals`.
+    stack_base: MachineStackOffset,
+    /// What each 8-byte stack slot above `stack_base` holds, lowest address last.
+    ///
+    /// Free slots at the end are popped as soon as they appear, so the last slot is always live.
+    stack_slots: Vec<StackSlot>,
+    /// The stack offset RSP actually points at.
+    ///
+    /// Releasing stack values only lowers `stack_offset`, and RSP catches up in a single
+    /// adjustment in `flush_stack_adjustment`. Acquiring a slot below RSP first moves RSP down to
+    /// it, so RSP is never above a live value.
+    rsp_offset: MachineStackOffset,
+    /// Values moved out of their register by `acquire_locations_in`, not yet taken by the caller.
+    evictions: SmallVec<[(Location, Location); 1]>,
     save_area_offset: Option<MachineStackOffset>,
     /// Memory location at which local variables begin.
     ///
     /// Populated in `init_locals`.
     locals_offset: MachineStackOffset,
+    /// Register allocation statistics.
+    ///
+    /// `None` unless collection was requested with `enable_stats`.
+    stats: Option<MachineStats>,
+    /// Registers available for locals, stack values and arguments.
+    gprs: SmallVec<[GPR; 6]>,
+    /// Registers available for internal temporary use.
+    temp_gprs: SmallVec<[GPR; 3]>,
+    /// Callee-saved registers that can hold the first few locals.
+    local_gprs: SmallVec<[GPR; 4]>,
+    /// Reserved registers that callees may clobber, which calls save and restore.
+    reserved_caller_saved_gprs: SmallVec<[GPR; 2]>,
+    /// Number of `local_gprs` actually holding a local.
+    ///
+    /// Populated in `init_locals`.
+    local_gprs_used: usize,
+    /// XMM registers holding float locals.
+    ///
+    /// Populated in `init_locals`.
+    local_xmms: SmallVec<[XMM; 4]>,
+    /// Locations of the locals considered for register assignment.
+    ///
+    /// Populated in `init_locals`.
+    local_prefix: SmallVec<[Location; 8]>,
+    /// The runs of 16-byte locals, each with the number of 16-byte locals before it.
+    ///
+    /// These locals always live on the stack, in two slots. Populated in `init_locals`.
+    v128_locals: Vec<(Range<u32>, u32)>,
+    /// Temporary GPRs currently acquired, least recently acquired first.
+    temp_gpr_order: SmallVec<[GPR; 3]>,
+    /// Temporary GPRs whose previous value was spilled by `steal_temp_gpr`.
+    stolen_gprs: SmallVec<[GPR; 3]>,
+    /// Memory location of the spill slots used by `steal_temp_gpr`, one per temporary GPR.
+    ///
+    /// Populated in `init_locals`.
+    steal_area_offset: Option<MachineStackOffset>,
+    /// The vmctx offset of the native stack limit and the label of the stack overflow trap, if
+    /// the prologue checks RSP against the limit.
+    stack_limit_check: Option<(u32, DynamicLabel)>,
+    /// The changes made to the frame by the prologue and epilogue, each with the offset of the
+    /// code it takes effect at.
+    unwind_ops: Vec<(usize, UnwindOp)>,
+    /// The callee-saved registers stored by `init_locals`, each with its offset below RBP, in the
+    /// order they are saved.
+    saved_registers: Vec<(X64Register, u32)>,
+    /// Whether the function has no frame pointer, see `omit_frame_pointer`.
+    frameless: bool,
+    /// The bytes allocated below the pushed registers of a frameless function, to keep RSP
+    /// 16-byte aligned.
+    frameless_padding: u32,
+    /// Whether the code emitted so far needs the frame that the function doesn't have.
+    needs_frame: bool,
+    /// Whether the function has landing pads, see `enable_exception_handlers`.
+    exception_handlers: bool,
+    /// Memory location of the copy of vmctx the landing pads reload R15 from.
+    ///
+    /// Populated in `init_locals`, with exception handlers only.
+    vmctx_slot: Option<MachineStackOffset>,
+    /// The registers caching the memory, if they hold it.
+    memory_cache: Option<MemoryCache>,
+    #[cfg(feature = "debug-machine-checks")]
+    ledger: Ledger,
 }
 
 impl Machine {
-    pub(crate) fn new() -> Self {
+    const GPRS: &'static [GPR] = &[GPR::RSI, GPR::RDI, GPR::R8, GPR::R9, GPR::R10, GPR::R11];
+    const TEMP_GPRS: &'static [GPR] = &[GPR::RAX, GPR::RCX, GPR::RDX];
+    const LOCAL_REGISTERS: &'static [GPR] = &[GPR::R12, GPR::R13, GPR::R14, GPR::RBX];
+    /// The allocatable registers that are caller-saved in both calling conventions.
+    const CALLER_SAVED_GPRS: &'static [GPR] = &[GPR::R10, GPR::R11];
+    const XMMS: &'static [XMM] = &[XMM::XMM3, XMM::XMM4, XMM::XMM5, XMM::XMM6, XMM::XMM7];
+    // XMM8-XMM10 are used as scratch registers by the float min/max lowering.
+    const LOCAL_XMMS: &'static [XMM] = &[XMM::XMM12, XMM::XMM13, XMM::XMM14, XMM::XMM15];
+
+    /// Creates a new machine that never hands out any of the `reserved_gprs`.
+    pub(crate) fn new(reserved_gprs: &[GPR]) -> Self {
+        let available = |r: &&GPR| !reserved_gprs.contains(r);
         Machine {
             used_gprs: HashSet::new(),
             used_xmms: HashSet::new(),
+            v128_xmms: HashSet::new(),
             stack_offset: MachineStackOffset(0),
+            stack_base: MachineStackOffset(0),
+            stack_slots: Vec::new(),
+            rsp_offset: MachineStackOffset(0),
+            evictions: SmallVec::new(),
             save_area_offset: None,
             locals_offset: MachineStackOffset(0),
+            stats: None,
+            gprs: Self::GPRS.iter().filter(available).cloned().collect(),
+            temp_gprs: Self::TEMP_GPRS.iter().filter(available).cloned().collect(),
+            local_gprs: Self::LOCAL_REGISTERS
+                .iter()
+                .filter(available)
+                .cloned()
+                .collect(),
+            reserved_caller_saved_gprs: Self::CALLER_SAVED_GPRS
+                .iter()
+                .filter(|r| reserved_gprs.contains(r))
+                .cloned()
+                .collect(),
+            local_gprs_used: 0,
+            local_xmms: SmallVec::new(),
+            local_prefix: SmallVec::new(),
+            v128_locals: Vec::new(),
+            temp_gpr_order: SmallVec::new(),
+            stolen_gprs: SmallVec::new(),
+            steal_area_offset: None,
+            stack_limit_check: None,
+            unwind_ops: Vec::new(),
+            saved_registers: Vec::new(),
+            frameless: false,
+            frameless_padding: 0,
+            needs_frame: false,
+            exception_handlers: false,
+            vmctx_slot: None,
+            memory_cache: None,
+            #[cfg(feature = "debug-machine-checks")]
+            ledger: Ledger::default(),
+        }
+    }
+
+    /// Lay the function out without a frame pointer, with `init_frameless_locals` and
+    /// `finalize_frameless_locals`, making RBP available for a local.
+    ///
+    /// A frameless function has no stack slots for values, and can't call anything. Acquiring a
+    /// stack slot or preparing a call still returns as usual, but makes `needs_frame` true: the
+    /// code emitted for the function must then be thrown away, and the function compiled again
+    /// with a frame.
+    pub(crate) fn omit_frame_pointer(&mut self) {
+        self.frameless = true;
+        self.local_gprs.push(GPR::RBP);
+    }
+
+    /// Lay the function out for landing pads, which are reached by unwinding the native stack
+    /// from the callees of the function.
+    ///
+    /// The unwinding doesn't restore the callee-saved registers, so no local lives in one, the
+    /// prologue saves all of them for the epilogue to restore anyway, and `init_locals` keeps a
+    /// copy of vmctx in the frame for the landing pads to reload R15 from, see `vmctx_slot`.
+    pub(crate) fn enable_exception_handlers(&mut self) {
+        assert!(!self.frameless);
+        self.exception_handlers = true;
+    }
+
+    /// The copy of vmctx kept in the frame of a function with exception handlers.
+    pub(crate) fn vmctx_slot(&self) -> Option<Location> {
+        self.vmctx_slot
+            .as_ref()
+            .map(|offset| Location::Memory(GPR::RBP, -(offset.0 as i32)))
+    }
+
+    /// The callee-saved registers stored by the prologue, other than R15.
+    fn saved_local_gprs(&self) -> &[GPR] {
+        if self.exception_handlers {
+            &self.local_gprs
+        } else {
+            &self.local_gprs[..self.local_gprs_used]
+        }
+    }
+
+    pub(crate) fn is_frameless(&self) -> bool {
+        self.frameless
+    }
+
+    /// Whether the code emitted so far relies on a frame the function doesn't have.
+    pub(crate) fn needs_frame(&self) -> bool {
+        self.needs_frame
+    }
+
+    /// Records that the code being emitted relies on a frame, see `omit_frame_pointer`.
+    pub(crate) fn require_frame(&mut self) {
+        if self.frameless {
+            self.needs_frame = true;
+        }
+    }
+
+    /// Start collecting register allocation statistics.
+    pub(crate) fn enable_stats(&mut self) {
+        self.stats.get_or_insert_with(MachineStats::default);
+    }
+
+    /// Make `init_locals` jump to `trap` when the allocated frame reaches below the native stack
+    /// limit stored at `limit_offset` in the vmctx.
+    pub(crate) fn enable_stack_limit_check(&mut self, limit_offset: u32, trap: DynamicLabel) {
+        self.stack_limit_check = Some((limit_offset, trap));
+    }
+
+    /// Take the statistics collected so far, if collection is enabled.
+    pub(crate) fn take_stats(&mut self) -> Option<MachineStats> {
+        self.stats.take()
+    }
+
+    #[inline(always)]
+    fn record(&mut self, f: impl FnOnce(&mut MachineStats)) {
+        if let Some(stats) = self.stats.as_mut() {
+            f(stats);
         }
     }
 
+    fn mark_gpr_used(&mut self, gpr: GPR) {
+        #[cfg(feature = "debug-machine-checks")]
+        self.ledger.acquire(Register::GPR(gpr), self.stack_offset.0);
+        self.used_gprs.insert(gpr);
+    }
+
+    fn mark_gpr_free(&mut self, gpr: GPR) {
+        #[cfg(feature = "debug-machine-checks")]
+        self.ledger.release(Register::GPR(gpr), self.stack_offset.0);
+        assert!(self.used_gprs.remove(&gpr));
+    }
+
+    fn mark_xmm_used(&mut self, xmm: XMM) {
+        #[cfg(feature = "debug-machine-checks")]
+        self.ledger.acquire(Register::XMM(xmm), self.stack_offset.0);
+        self.used_xmms.insert(xmm);
+    }
+
+    fn mark_xmm_free(&mut self, xmm: XMM) {
+        #[cfg(feature = "debug-machine-checks")]
+        self.ledger.release(Register::XMM(xmm), self.stack_offset.0);
+        assert!(self.used_xmms.remove(&xmm));
+        self.v128_xmms.remove(&xmm);
+    }
+
     pub(crate) fn get_stack_offset(&self) -> usize {
         self.stack_offset.0
     }
@@ -55,9 +346,7 @@ impl Machine {
     ///
     /// This method does not mark the register as used.
     pub(crate) fn pick_gpr(&self) -> Option<GPR> {
-        use GPR::*;
-        static REGS: &[GPR] = &[RSI, RDI, R8, R9, R10, R11];
-        for r in REGS {
+        for r in &self.gprs {
             if !self.used_gprs.contains(r) {
                 return Some(*r);
             }
@@ -69,9 +358,7 @@ impl Machine {
     ///
     /// This method does not mark the register as used.
     pub(crate) fn pick_temp_gpr(&self) -> Option<GPR> {
-        use GPR::*;
-        static REGS: &[GPR] = &[RAX, RCX, RDX];
-        for r in REGS {
+        for r in &self.temp_gprs {
             if !self.used_gprs.contains(r) {
                 return Some(*r);
             }
@@ -83,30 +370,99 @@ impl Machine {
     pub(crate) fn acquire_temp_gpr(&mut self) -> Option<GPR> {
         let gpr = self.pick_temp_gpr();
         if let Some(x) = gpr {
-            self.used_gprs.insert(x);
+            self.mark_gpr_used(x);
+            self.temp_gpr_order.push(x);
+        } else {
+            self.record(|s| s.temp_acquisition_failures += 1);
         }
         gpr
     }
 
     /// Releases a temporary GPR.
     pub(crate) fn release_temp_gpr(&mut self, gpr: GPR) {
-        assert!(self.used_gprs.remove(&gpr));
+        self.mark_gpr_free(gpr);
+        self.temp_gpr_order.retain(|r| *r != gpr);
     }
 
     /// Specify that a given register is in use.
     pub(crate) fn reserve_unused_temp_gpr(&mut self, gpr: GPR) -> GPR {
         assert!(!self.used_gprs.contains(&gpr));
-        self.used_gprs.insert(gpr);
+        self.mark_gpr_used(gpr);
+        if self.temp_gprs.contains(&gpr) {
+            self.temp_gpr_order.push(gpr);
+        }
+        gpr
+    }
+
+    /// Acquires a temporary GPR, stealing one if all of them are in use.
+    ///
+    /// Stealing spills the least recently acquired temporary GPR that is not already stolen and
+    /// is not referenced by any of the `avoid` locations to its own stack slot. The register must
+    /// later be given back with `restore_stolen_gpr`, which reloads its previous value, before its
+    /// original owner uses it again.
+    ///
+    /// A temporary GPR can only be stolen once at a time, so this panics if all of them are
+    /// already stolen or referenced by `avoid`.
+    pub(crate) fn steal_temp_gpr<E: Emitter>(&mut self, a: &mut E, avoid: &[Location]) -> GPR {
+        if let Some(gpr) = self.acquire_temp_gpr() {
+            return gpr;
+        }
+        let avoided = |r: &GPR| {
+            avoid.iter().any(|loc| match *loc {
+                Location::GPR(x) | Location::Memory(x, _) => x == *r,
+                _ => false,
+            })
+        };
+        let gpr = self
+            .temp_gpr_order
+            .iter()
+            .cloned()
+            .find(|r| !self.stolen_gprs.contains(r) && !avoided(r))
+            .expect("no temporary GPR can be stolen");
+        a.emit_mov(Size::S64, Location::GPR(gpr), self.get_steal_slot(gpr));
+        self.stolen_gprs.push(gpr);
+        // The thief is now the most recent user of the register.
+        self.temp_gpr_order.retain(|r| *r != gpr);
+        self.temp_gpr_order.push(gpr);
+        self.record(|s| s.temp_gpr_steals += 1);
         gpr
     }
 
+    /// Gives back a temporary GPR obtained with `steal_temp_gpr`.
+    ///
+    /// If the register was stolen, its previous value is reloaded for its original owner;
+    /// otherwise it is simply released.
+    pub(crate) fn restore_stolen_gpr<E: Emitter>(&mut self, a: &mut E, gpr: GPR) {
+        if let Some(pos) = self.stolen_gprs.iter().position(|r| *r == gpr) {
+            self.stolen_gprs.remove(pos);
+            a.emit_mov(Size::S64, self.get_steal_slot(gpr), Location::GPR(gpr));
+        } else {
+            self.release_temp_gpr(gpr);
+        }
+    }
+
+    /// Location of the spill slot of a stolen temporary GPR.
+    ///
+    /// The slots of a frameless function are in the red zone of the System V ABI, right below
+    /// RSP, which stays put in its body.
+    fn get_steal_slot(&self, gpr: GPR) -> Location {
+        let index = self.temp_gprs.iter().position(|r| *r == gpr).unwrap();
+        if self.frameless {
+            return Location::Memory(GPR::RSP, -(8 * (index as i32 + 1)));
+        }
+        let base = self
+            .steal_area_offset
+            .as_ref()
+            .expect("temporary GPRs can only be stolen after `init_locals`")
+            .0;
+        Location::Memory(GPR::RBP, -((base + 8 * (index + 1)) as i32))
+    }
+
     /// Picks an unused XMM register.
     ///
     /// This method does not mark the register as used.
     pub(crate) fn pick_xmm(&self) -> Option<XMM> {
-        use XMM::*;
-        static REGS: &[XMM] = &[XMM3, XMM4, XMM5, XMM6, XMM7];
-        for r in REGS {
+        for r in Self::XMMS {
             if !self.used_xmms.contains(r) {
                 return Some(*r);
             }
@@ -132,18 +488,65 @@ impl Machine {
     pub(crate) fn acquire_temp_xmm(&mut self) -> Option<XMM> {
         let xmm = self.pick_temp_xmm();
         if let Some(x) = xmm {
-            self.used_xmms.insert(x);
+            self.mark_xmm_used(x);
+        } else {
+            self.record(|s| s.temp_acquisition_failures += 1);
         }
         xmm
     }
 
     /// Releases a temporary XMM register.
     pub(crate) fn release_temp_xmm(&mut self, xmm: XMM) {
-        assert_eq!(self.used_xmms.remove(&xmm), true);
+        self.mark_xmm_free(xmm);
+    }
+
+    /// The registers caching the memory, if they still hold it.
+    pub(crate) fn memory_cache(&self) -> Option<MemoryCache> {
+        self.memory_cache
+    }
+
+    /// Acquires registers to cache the base of the memory in, and its end if `with_end`, for
+    /// the following accesses. Loading them is left to the caller.
+    ///
+    /// The registers come from the pool of stack values, from its end, and are taken back as
+    /// soon as a value needs a register and no other is free: reloading the memory is cheaper
+    /// than spilling a value. Returns `None` if there aren't enough free registers.
+    pub(crate) fn acquire_memory_cache(&mut self, with_end: bool) -> Option<MemoryCache> {
+        assert!(self.memory_cache.is_none());
+        let mut free = self
+            .gprs
+            .iter()
+            .rev()
+            .filter(|r| !self.used_gprs.contains(r))
+            .cloned();
+        let base = free.next()?;
+        let end = if with_end { Some(free.next()?) } else { None };
+        let cache = MemoryCache { base, end };
+        for gpr in cache.registers() {
+            self.mark_gpr_used(gpr);
+        }
+        self.memory_cache = Some(cache);
+        Some(cache)
+    }
+
+    /// Forgets the memory cached in registers, releasing them. No code is emitted.
+    ///
+    /// This must be called wherever the cache may not be valid anymore: where the memory can
+    /// move or shrink, and where control flow joins, as some of the paths may not have loaded
+    /// it.
+    pub(crate) fn forget_memory_cache(&mut self) {
+        if let Some(cache) = self.memory_cache.take() {
+            for gpr in cache.registers() {
+                self.mark_gpr_free(gpr);
+            }
+        }
     }
 
     /// Acquires locations from the machine state.
     ///
+    /// Values that do not fit in a register reuse a free stack slot if there is one, and only
+    /// grow the stack otherwise.
+    ///
     /// If the returned locations are used for stack value, `release_location` needs to be called on them;
     /// Otherwise, if the returned locations are used for locals, `release_location` does not need to be called on them.
     pub(crate) fn acquire_locations<E: Emitter>(
@@ -153,147 +556,424 @@ impl Machine {
         zeroed: bool,
     ) -> SmallVec<[Location; 1]> {
         let mut ret = smallvec![];
-        let mut delta_stack_offset: usize = 0;
 
         for ty in tys {
             let loc = match *ty {
-                WpType::F32 | WpType::F64 => self.pick_xmm().map(Location::XMM),
-                WpType::I32 | WpType::I64 => self.pick_gpr().map(Location::GPR),
-                WpType::FuncRef | WpType::ExternRef => self.pick_gpr().map(Location::GPR),
+                WpType::F32 | WpType::F64 | WpType::V128 => self.pick_xmm().map(Location::XMM),
+                WpType::I32 | WpType::I64 | WpType::FuncRef | WpType::ExternRef => {
+                    if self.pick_gpr().is_none() {
+                        self.forget_memory_cache();
+                    }
+                    self.pick_gpr().map(Location::GPR)
+                }
                 _ => unreachable!("can't acquire location for type {:?}", ty),
             };
 
-            let loc = if let Some(x) = loc {
-                x
-            } else {
-                self.stack_offset.0 += 8;
-                delta_stack_offset += 8;
-                Location::Memory(GPR::RBP, -(self.stack_offset.0 as i32))
+            let loc = match loc {
+                Some(x) => x,
+                None if *ty == WpType::V128 => self.acquire_wide_stack_slot(),
+                None => self.acquire_stack_slot(),
             };
             if let Location::GPR(x) = loc {
-                self.used_gprs.insert(x);
+                self.mark_gpr_used(x);
+                self.record(|s| s.gpr_picks += 1);
             } else if let Location::XMM(x) = loc {
-                self.used_xmms.insert(x);
+                self.mark_xmm_used(x);
+                if *ty == WpType::V128 {
+                    self.v128_xmms.insert(x);
+                }
+                self.record(|s| s.xmm_picks += 1);
             }
             ret.push(loc);
         }
 
-        if delta_stack_offset != 0 {
-            assembler.emit_sub(
-                Size::S64,
-                Location::Imm32(delta_stack_offset as u32),
-                Location::GPR(GPR::RSP),
-            );
-        }
+        self.grow_stack(assembler);
         if zeroed {
-            for i in 0..tys.len() {
-                assembler.emit_mov(Size::S64, Location::Imm32(0), ret[i]);
+            for (ty, loc) in tys.iter().zip(ret.iter()) {
+                match (ty, *loc) {
+                    (WpType::V128, Location::XMM(x)) => {
+                        assembler.emit_vxorps(x, XMMOrMemory::XMM(x), x)
+                    }
+                    (WpType::V128, Location::Memory(base, disp)) => {
+                        assembler.emit_mov(Size::S64, Location::Imm32(0), *loc);
+                        assembler.emit_mov(
+                            Size::S64,
+                            Location::Imm32(0),
+                            Location::Memory(base, disp + 8),
+                        );
+                    }
+                    _ => assembler.emit_mov(Size::S64, Location::Imm32(0), *loc),
+                }
             }
         }
         ret
     }
 
-    /// Releases locations used for stack value.
-    pub(crate) fn release_locations<E: Emitter>(&mut self, assembler: &mut E, locs: &[Location]) {
-        let mut delta_stack_offset: usize = 0;
+    /// Acquires stack locations for values of types `tys`, even if registers are free.
+    ///
+    /// This is for values that several code paths write to, or that are written by the callee
+    /// of a call. Release them like any other stack value.
+    pub(crate) fn acquire_stack_locations<E: Emitter>(
+        &mut self,
+        assembler: &mut E,
+        tys: &[WpType],
+    ) -> SmallVec<[Location; 8]> {
+        let ret = tys
+            .iter()
+            .map(|ty| match ty {
+                WpType::V128 => self.acquire_wide_stack_slot(),
+                _ => self.acquire_stack_slot(),
+            })
+            .collect();
+        self.grow_stack(assembler);
+        ret
+    }
 
-        for loc in locs.iter().rev() {
-            match *loc {
-                Location::GPR(ref x) => {
-                    assert_eq!(self.used_gprs.remove(x), true);
-                }
-                Location::XMM(ref x) => {
-                    assert_eq!(self.used_xmms.remove(x), true);
+    /// Acquires `n` adjacent 16-byte stack slots, the first one at the lowest address, for
+    /// values passed to the runtime in memory. Release them like any other stack value.
+    ///
+    /// Unlike `acquire_stack_locations`, free slots are never reused, so that the slots are
+    /// contiguous.
+    pub(crate) fn acquire_stack_buffer<E: Emitter>(
+        &mut self,
+        assembler: &mut E,
+        n: usize,
+    ) -> SmallVec<[Location; 8]> {
+        if n == 0 {
+            return smallvec![];
+        }
+        self.require_frame();
+        // As in `acquire_wide_stack_slot`, each value takes the two slots `(slot - 1, slot)`, and
+        // starts at the lower address of `slot`, which is kept 16-byte aligned.
+        let mut first = self.stack_slots.len() + 1;
+        if self.stack_slot_offset(first) % 16 != 0 {
+            first += 1;
+        }
+        while self.stack_slots.len() < first + 2 * n - 1 {
+            self.stack_offset.0 += 8;
+            self.stack_slots.push(StackSlot::Free);
+        }
+        let slots: SmallVec<[usize; 8]> = (0..n).rev().map(|i| first + 2 * i).collect();
+        for &slot in &slots {
+            self.stack_slots[slot - 1] = StackSlot::UpperHalf;
+            self.stack_slots[slot] = StackSlot::Used;
+            self.record_stack_spill();
+        }
+        self.grow_stack(assembler);
+        slots
+            .into_iter()
+            .map(|slot| Location::Memory(GPR::RBP, -(self.stack_slot_offset(slot) as i32)))
+            .collect()
+    }
+
+    /// Acquires a stack slot for a value, reusing a free one if possible.
+    ///
+    /// Moving RSP down to cover the slot is left to the caller, see `grow_stack`.
+    fn acquire_stack_slot(&mut self) -> Location {
+        self.require_frame();
+        let slot = if let Some(slot) = self
+            .stack_slots
+            .iter()
+            .position(|slot| *slot == StackSlot::Free)
+        {
+            self.stack_slots[slot] = StackSlot::Used;
+            slot
+        } else {
+            self.stack_offset.0 += 8;
+            self.stack_slots.push(StackSlot::Used);
+            self.stack_slots.len() - 1
+        };
+        self.record_stack_spill();
+        Location::Memory(GPR::RBP, -(self.stack_slot_offset(slot) as i32))
+    }
+
+    /// Acquires two adjacent stack slots for a 16-byte value, reusing free ones if possible.
+    ///
+    /// The value starts at the lower address of the two, which is kept 16-byte aligned.
+    fn acquire_wide_stack_slot(&mut self) -> Location {
+        self.require_frame();
+        let free = |slot: &StackSlot| *slot == StackSlot::Free;
+        let reusable = (1..self.stack_slots.len()).find(|&slot| {
+            self.stack_slot_offset(slot) % 16 == 0
+                && free(&self.stack_slots[slot - 1])
+                && free(&self.stack_slots[slot])
+        });
+        let slot = match reusable {
+            Some(slot) => slot,
+            None => {
+                // Skip a slot if needed to keep the value aligned. The skipped slot stays free.
+                let mut slot = self.stack_slots.len() + 1;
+                if self.stack_slot_offset(slot) % 16 != 0 {
+                    slot += 1;
                 }
-                Location::Memory(GPR::RBP, x) => {
-                    if x >= 0 {
-                        unreachable!();
-                    }
-                    let offset = (-x) as usize;
-                    if offset != self.stack_offset.0 {
-                        unreachable!();
-                    }
-                    self.stack_offset.0 -= 8;
-                    delta_stack_offset += 8;
+                while self.stack_slots.len() <= slot {
+                    self.stack_offset.0 += 8;
+                    self.stack_slots.push(StackSlot::Free);
                 }
-                _ => {}
+                slot
             }
+        };
+        self.stack_slots[slot - 1] = StackSlot::UpperHalf;
+        self.stack_slots[slot] = StackSlot::Used;
+        self.record_stack_spill();
+        Location::Memory(GPR::RBP, -(self.stack_slot_offset(slot) as i32))
+    }
+
+    /// Distance from RBP down to the stack slot with index `slot`.
+    fn stack_slot_offset(&self, slot: usize) -> usize {
+        self.stack_base.0 + 8 * (slot + 1)
+    }
+
+    fn record_stack_spill(&mut self) {
+        let stack_offset = self.stack_offset.0 as u64;
+        self.record(|s| {
+            s.stack_spills += 1;
+            s.max_stack_offset = std::cmp::max(s.max_stack_offset, stack_offset);
+        });
+    }
+
+    /// Moves RSP down to cover all the stack slots, if the stack grew past it.
+    fn grow_stack<E: Emitter>(&mut self, a: &mut E) {
+        if self.stack_offset.0 > self.rsp_offset.0 {
+            a.emit_sub(
+                Size::S64,
+                Location::Imm32((self.stack_offset.0 - self.rsp_offset.0) as u32),
+                Location::GPR(GPR::RSP),
+            );
+            self.rsp_offset = MachineStackOffset(self.stack_offset.0);
         }
+    }
 
+    /// Gives back the stack space of the values released since the last flush, so that RSP
+    /// points right at the top of the stack again.
+    ///
+    /// This must be called wherever code relies on the exact value of RSP: before calls, and
+    /// before control flow joins, so that all the paths reaching a label agree on RSP. It does
+    /// not touch the flags, so it can be emitted between a comparison and its jump.
+    pub(crate) fn flush_stack_adjustment<E: Emitter>(&mut self, a: &mut E) {
+        let delta_stack_offset = self.pending_stack_adjustment();
         if delta_stack_offset != 0 {
-            assembler.emit_add(
+            a.emit_lea(
                 Size::S64,
-                Location::Imm32(delta_stack_offset as u32),
+                Location::Memory(GPR::RSP, delta_stack_offset as i32),
                 Location::GPR(GPR::RSP),
             );
+            self.rsp_offset = MachineStackOffset(self.stack_offset.0);
         }
     }
 
-    pub(crate) fn release_locations_only_regs(&mut self, locs: &[Location]) {
-        for loc in locs.iter().rev() {
-            match *loc {
-                Location::GPR(ref x) => {
-                    assert_eq!(self.used_gprs.remove(x), true);
+    /// The number of bytes `flush_stack_adjustment` would pop off the stack.
+    fn pending_stack_adjustment(&self) -> usize {
+        self.rsp_offset.0 - self.stack_offset.0
+    }
+
+    /// Acquires exactly the `preferred` register for a value of type `ty`.
+    ///
+    /// If another value currently occupies the register, it is moved to a fresh stack slot, and
+    /// the move is recorded for `take_evictions` so that the caller can update its references to
+    /// the evicted value. Release the stack slot like any other stack value once it is dead.
+    ///
+    /// `preferred` must be one of the registers `acquire_locations` hands out for values of type
+    /// `ty`: temporary, reserved and local registers are never given to values.
+    pub(crate) fn acquire_locations_in<E: Emitter>(
+        &mut self,
+        assembler: &mut E,
+        ty: WpType,
+        preferred: Location,
+    ) -> Location {
+        let occupied = match (ty, preferred) {
+            (WpType::F32 | WpType::F64 | WpType::V128, Location::XMM(x))
+                if Self::XMMS.contains(&x) =>
+            {
+                let occupied = self.used_xmms.contains(&x);
+                if !occupied {
+                    self.mark_xmm_used(x);
+                }
+                occupied
+            }
+            (WpType::I32 | WpType::I64 | WpType::FuncRef | WpType::ExternRef, Location::GPR(x))
+                if self.gprs.contains(&x) =>
+            {
+                if let Some(cache) = self.memory_cache {
+                    if cache.registers().any(|r| r == x) {
+                        self.forget_memory_cache();
+                    }
                 }
-                Location::XMM(ref x) => {
-                    assert_eq!(self.used_xmms.remove(x), true);
+                let occupied = self.used_gprs.contains(&x);
+                if !occupied {
+                    self.mark_gpr_used(x);
                 }
-                _ => {}
+                occupied
+            }
+            _ => unreachable!("can't acquire {:?} for a value of type {:?}", preferred, ty),
+        };
+        if occupied {
+            let slot = match preferred {
+                Location::XMM(x) if self.v128_xmms.contains(&x) => {
+                    let slot = self.acquire_wide_stack_slot();
+                    self.grow_stack(assembler);
+                    match slot {
+                        Location::Memory(base, disp) => assembler
+                            .emit_movdqu(XMMOrMemory::XMM(x), XMMOrMemory::Memory(base, disp)),
+                        _ => unreachable!(),
+                    }
+                    slot
+                }
+                _ => {
+                    let slot = self.acquire_stack_slot();
+                    self.grow_stack(assembler);
+                    assembler.emit_mov(Size::S64, preferred, slot);
+                    slot
+                }
+            };
+            self.evictions.push((preferred, slot));
+            #[cfg(feature = "debug-machine-checks")]
+            self.ledger.transfer(
+                match preferred {
+                    Location::GPR(x) => Register::GPR(x),
+                    Location::XMM(x) => Register::XMM(x),
+                    _ => unreachable!(),
+                },
+                self.stack_offset.0,
+            );
+        }
+        match preferred {
+            Location::GPR(_) => self.record(|s| s.gpr_picks += 1),
+            Location::XMM(x) => {
+                if ty == WpType::V128 {
+                    self.v128_xmms.insert(x);
+                } else {
+                    self.v128_xmms.remove(&x);
+                }
+                self.record(|s| s.xmm_picks += 1);
             }
+            _ => unreachable!(),
         }
+        preferred
     }
 
-    pub(crate) fn release_locations_only_stack<E: Emitter>(
-        &mut self,
-        assembler: &mut E,
-        locs: &[Location],
-    ) {
-        let mut delta_stack_offset: usize = 0;
+    /// Releases the values still live at the end of the function without emitting any code, so
+    /// that `finalize_locals` can check that nothing else was leaked.
+    #[cfg(feature = "debug-machine-checks")]
+    pub(crate) fn forget_locations(&mut self, locs: &[Location]) {
+        self.release_locations_only_regs(locs);
+        self.free_stack_slots(locs);
+    }
+
+    /// Takes the `(register, stack slot)` moves done by `acquire_locations_in` to free up
+    /// registers, in the order they happened.
+    pub(crate) fn take_evictions(&mut self) -> SmallVec<[(Location, Location); 1]> {
+        std::mem::take(&mut self.evictions)
+    }
+
+    /// Index in `stack_slots` of the stack value at `RBP + offset`.
+    fn get_stack_slot(&self, offset: i32) -> usize {
+        if offset >= 0 {
+            unreachable!();
+        }
+        let offset = (-offset) as usize;
+        if offset <= self.stack_base.0 || (offset - self.stack_base.0) % 8 != 0 {
+            unreachable!();
+        }
+        let slot = (offset - self.stack_base.0) / 8 - 1;
+        if self.stack_slots.get(slot) != Some(&StackSlot::Used) {
+            unreachable!();
+        }
+        slot
+    }
 
+    /// The indices of the stack slots holding the stack value at `RBP + offset`.
+    fn get_value_stack_slots(&self, offset: i32) -> Range<usize> {
+        let slot = self.get_stack_slot(offset);
+        if slot > 0 && self.stack_slots[slot - 1] == StackSlot::UpperHalf {
+            slot - 1..slot + 1
+        } else {
+            slot..slot + 1
+        }
+    }
+
+    /// Whether the stack value at `loc` is a 16-byte value.
+    pub(crate) fn is_v128(&self, loc: Location) -> bool {
+        match loc {
+            Location::XMM(x) => self.v128_xmms.contains(&x),
+            Location::Memory(GPR::RBP, offset) => self.get_value_stack_slots(offset).len() == 2,
+            _ => false,
+        }
+    }
+
+    /// Marks the stack slots of the memory locations in `locs` free.
+    ///
+    /// The stack only shrinks by the run of free slots at its top; other free slots are kept
+    /// around for `acquire_locations` to reuse. No code is emitted: RSP stays where it is until
+    /// the next `flush_stack_adjustment`, so that the stack space can be reused without moving
+    /// RSP back and forth.
+    fn free_stack_slots(&mut self, locs: &[Location]) {
         for loc in locs.iter().rev() {
             if let Location::Memory(GPR::RBP, x) = *loc {
-                if x >= 0 {
-                    unreachable!();
-                }
-                let offset = (-x) as usize;
-                if offset != self.stack_offset.0 {
-                    unreachable!();
+                for slot in self.get_value_stack_slots(x) {
+                    self.stack_slots[slot] = StackSlot::Free;
                 }
-                self.stack_offset.0 -= 8;
-                delta_stack_offset += 8;
             }
         }
+        while let Some(StackSlot::Free) = self.stack_slots.last() {
+            self.stack_slots.pop();
+            self.stack_offset.0 -= 8;
+        }
+    }
 
-        if delta_stack_offset != 0 {
-            assembler.emit_add(
-                Size::S64,
-                Location::Imm32(delta_stack_offset as u32),
-                Location::GPR(GPR::RSP),
-            );
+    /// Releases locations used for stack value.
+    ///
+    /// The locations may be released in any order.
+    pub(crate) fn release_locations(&mut self, locs: &[Location]) {
+        for loc in locs.iter().rev() {
+            match *loc {
+                Location::GPR(x) => self.mark_gpr_free(x),
+                Location::XMM(x) => self.mark_xmm_free(x),
+                _ => {}
+            }
+        }
+        self.free_stack_slots(locs);
+    }
+
+    pub(crate) fn release_locations_only_regs(&mut self, locs: &[Location]) {
+        for loc in locs.iter().rev() {
+            match *loc {
+                Location::GPR(x) => self.mark_gpr_free(x),
+                Location::XMM(x) => self.mark_xmm_free(x),
+                _ => {}
+            }
+        }
+    }
+
+    pub(crate) fn release_locations_only_stack(&mut self, locs: &[Location]) {
+        self.free_stack_slots(locs);
+    }
+
+    /// The number of bytes by which `release_locations` would shrink the stack for `locs`.
+    fn released_stack_size(&self, locs: &[Location]) -> usize {
+        let mut released: SmallVec<[usize; 8]> = SmallVec::new();
+        for loc in locs.iter() {
+            if let Location::Memory(GPR::RBP, x) = *loc {
+                released.extend(self.get_value_stack_slots(x));
+            }
         }
+        8 * self
+            .stack_slots
+            .iter()
+            .enumerate()
+            .rev()
+            .take_while(|(slot, state)| **state == StackSlot::Free || released.contains(slot))
+            .count()
     }
 
+    /// Moves RSP to where it would be after releasing `locs` and flushing the stack adjustment,
+    /// without changing the machine state. Used before branching out of a block.
     pub(crate) fn release_locations_keep_state<E: Emitter>(
         &self,
         assembler: &mut E,
         locs: &[Location],
     ) {
-        let mut delta_stack_offset: usize = 0;
-        let mut stack_offset = self.stack_offset.0;
-
-        for loc in locs.iter().rev() {
-            if let Location::Memory(GPR::RBP, x) = *loc {
-                if x >= 0 {
-                    unreachable!();
-                }
-                let offset = (-x) as usize;
-                if offset != stack_offset {
-                    unreachable!();
-                }
-                stack_offset -= 8;
-                delta_stack_offset += 8;
-            }
-        }
+        let delta_stack_offset = self.pending_stack_adjustment() + self.released_stack_size(locs);
 
         if delta_stack_offset != 0 {
             assembler.emit_add(
@@ -304,206 +984,613 @@ impl Machine {
         }
     }
 
-    const LOCAL_REGISTERS: &'static [GPR] = &[GPR::R12, GPR::R13, GPR::R14, GPR::RBX];
+    /// The maximum number of locals that can be assigned to registers.
+    ///
+    /// Only locals with an index below this value are considered for register assignment, so
+    /// `init_locals` needs the types of at most this many locals.
+    pub(crate) fn max_register_locals(&self) -> usize {
+        self.local_gprs.len() + Self::LOCAL_XMMS.len()
+    }
+
+    /// Location of the `slot`-th stack-allocated local.
+    ///
+    /// `init_locals` checks that the slots of the locals it lays out are within the reach of a
+    /// 32-bit displacement, so this panics rather than wrapping for any other slot.
+    fn get_local_stack_slot(&self, slot: u32) -> Location {
+        let offset = (slot as usize)
+            .checked_mul(8)
+            .and_then(|x| x.checked_add(self.locals_offset.0))
+            .filter(|x| *x <= i32::MAX as usize)
+            .expect("local stack slot out of the frame");
+        Location::Memory(GPR::RBP, -(offset as i32))
+    }
 
     pub(crate) fn get_local_location(&self, idx: u32) -> Location {
-        // NB: This calculation cannot reasonably overflow. `self.locals_offset` will typically be
-        // small (< 32), and `idx` is bounded to `51000` due to limits imposed by the wasmparser
-        // validator. We introduce a debug_assert here to ensure that `idx` never really exceeds
-        // some incredibly large value.
-        debug_assert!(
-            idx <= 999_999,
-            "this runtime can't deal with unreasonable number of locals"
-        );
-        Self::LOCAL_REGISTERS
+        self.local_prefix
             .get(idx as usize)
-            .map(|r| Location::GPR(*r))
+            .cloned()
             .unwrap_or_else(|| {
-                let local_offset = idx
-                    .checked_sub(Self::LOCAL_REGISTERS.len() as u32)
-                    .unwrap()
-                    .wrapping_mul(8);
-                Location::Memory(
-                    GPR::RBP,
-                    (local_offset.wrapping_add(self.locals_offset.0 as u32) as i32).wrapping_neg(),
-                )
+                let mut slot = idx
+                    .checked_sub(self.local_gprs_used as u32 + self.local_xmms.len() as u32)
+                    .and_then(|x| x.checked_add(self.v128_locals_before(idx)))
+                    .expect("local index out of the frame");
+                // 16-byte locals start at the lower address of their two slots.
+                if self.is_v128_local(idx) {
+                    slot += 1;
+                }
+                self.get_local_stack_slot(slot)
             })
     }
 
-    pub(crate) fn init_locals<E: Emitter>(
-        &mut self,
+    /// The number of 16-byte locals with an index below `idx`.
+    fn v128_locals_before(&self, idx: u32) -> u32 {
+        let runs = self
+            .v128_locals
+            .partition_point(|(range, _)| range.start < idx);
+        match runs.checked_sub(1).map(|i| &self.v128_locals[i]) {
+            Some((range, before)) => before + std::cmp::min(idx, range.end) - range.start,
+            None => 0,
+        }
+    }
+
+    fn is_v128_local(&self, idx: u32) -> bool {
+        self.v128_locals_before(idx + 1) != self.v128_locals_before(idx)
+    }
+
+    /// Records that the code emitted so far changed the frame as described by `op`.
+    pub(crate) fn record_unwind_op<E: Emitter<Offset = AssemblyOffset>>(
+        &mut self,
+        a: &mut E,
+        op: UnwindOp,
+    ) {
+        let offset = a.get_offset().0;
+        self.unwind_ops.push((offset, op));
+    }
+
+    /// Takes the changes made to the frame so far, in code order.
+    pub(crate) fn take_unwind_ops(&mut self) -> Vec<(usize, UnwindOp)> {
+        std::mem::take(&mut self.unwind_ops)
+    }
+
+    /// Stores the callee-saved `reg` right below the current stack offset.
+    fn save_callee_saved<E: Emitter<Offset = AssemblyOffset>>(
+        &mut self,
+        a: &mut E,
+        reg: X64Register,
+    ) {
+        match reg {
+            X64Register::GPR(gpr) => {
+                self.stack_offset.0 += 8;
+                a.emit_mov(
+                    Size::S64,
+                    Location::GPR(gpr),
+                    Location::Memory(GPR::RBP, -(self.stack_offset.0 as i32)),
+                );
+            }
+            X64Register::XMM(xmm) => {
+                self.stack_offset.0 += 16;
+                a.emit_movdqu(
+                    XMMOrMemory::XMM(xmm),
+                    XMMOrMemory::Memory(GPR::RBP, -(self.stack_offset.0 as i32)),
+                );
+            }
+        }
+        let bp_neg_offset = self.stack_offset.0 as u32;
+        self.saved_registers.push((reg, bp_neg_offset));
+        self.record_unwind_op(a, UnwindOp::SaveRegister { reg, bp_neg_offset });
+    }
+
+    /// Picks the registers of the first `n` locals, `None` for the ones left on the stack.
+    fn assign_local_registers(
+        &mut self,
+        n: u32,
+        local_types: &[WpType],
+    ) -> SmallVec<[Option<Location>; 8]> {
+        let prefix_len = std::cmp::min(n as usize, self.max_register_locals());
+        let local_gprs: &[GPR] = if self.exception_handlers {
+            &[]
+        } else {
+            &self.local_gprs
+        };
+        let mut free_gprs = local_gprs.iter();
+        let mut free_xmms = Self::LOCAL_XMMS.iter();
+        let registers = local_types[..prefix_len]
+            .iter()
+            .map(|ty| match ty {
+                WpType::F32 | WpType::F64 => free_xmms.next().map(|x| Location::XMM(*x)),
+                WpType::V128 => None,
+                _ => free_gprs.next().map(|x| Location::GPR(*x)),
+            })
+            .collect();
+        self.local_gprs_used = local_gprs.len() - free_gprs.len();
+        self.local_xmms = Self::LOCAL_XMMS[..Self::LOCAL_XMMS.len() - free_xmms.len()]
+            .iter()
+            .cloned()
+            .collect();
+        registers
+    }
+
+    /// Whether all the locals of types `local_types` fit in registers, with RBP available for them
+    /// as `omit_frame_pointer` makes it.
+    pub(crate) fn locals_fit_without_frame(&self, local_types: &[WpType]) -> bool {
+        let floats = local_types
+            .iter()
+            .filter(|ty| matches!(ty, WpType::F32 | WpType::F64))
+            .count();
+        let gprs = self.local_gprs.len() + if self.frameless { 0 } else { 1 };
+        !local_types.contains(&WpType::V128)
+            && floats <= Self::LOCAL_XMMS.len()
+            && local_types.len() - floats <= gprs
+    }
+
+    /// Lay out the locals of a function and emit the code initializing them.
+    ///
+    /// `local_types` contains the types of the first locals, and must have at least
+    /// `min(n, self.max_register_locals())` elements. Among those, integer and reference locals
+    /// are assigned to the callee-saved `LOCAL_REGISTERS`, float locals to `LOCAL_XMMS`, in index
+    /// order. All other locals live on the stack, in index order, the 16-byte ones listed in
+    /// `v128_locals` taking two slots.
+    ///
+    /// The params are passed as 64-bit words, two for each 16-byte param.
+    ///
+    /// Fails if the frame would be too large for its slots to be addressed relative to `RBP`.
+    ///
+    /// A frame larger than `probe_stride`, the page size of the target, is probed from the top
+    /// down before anything is stored into it, so that the guard page below the stack is hit
+    /// before the memory beyond it.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn init_locals<E: Emitter<Label = DynamicLabel, Offset = AssemblyOffset>>(
+        &mut self,
         a: &mut E,
         n: u32,
         n_params: u32,
+        local_types: &[WpType],
+        v128_locals: &[Range<u32>],
         calling_convention: CallingConvention,
-    ) {
+        probe_stride: usize,
+    ) -> Result<(), CodegenError> {
+        debug_assert!(probe_stride >= 8 && probe_stride % 8 == 0);
+        let mut before = 0;
+        self.v128_locals = v128_locals
+            .iter()
+            .map(|range| {
+                let run = (range.clone(), before);
+                before += range.end - range.start;
+                run
+            })
+            .collect();
+
+        // Pick the registers for the first few locals. Assigning the stack slots has to wait until
+        // the size of the static area is known.
+        let registers = self.assign_local_registers(n, local_types);
+        let register_locals = self.local_gprs_used + self.local_xmms.len();
+
         // Total size (in bytes) of the pre-allocated "static area" for this function's
         // locals and callee-saved registers.
         let mut static_area_size: usize = 0;
 
         // Space to clobber registers used for locals.
-        static_area_size += 8 * std::cmp::min(Self::LOCAL_REGISTERS.len(), n as usize);
+        static_area_size += 8 * self.saved_local_gprs().len();
 
         // Callee-saved R15 for vmctx.
         static_area_size += 8;
 
-        // For Windows ABI, save RDI and RSI
+        // For Windows ABI, save RDI and RSI, as well as the full 128 bits of the XMM registers
+        // used for locals (XMM6-XMM15 are callee-saved there). The System V ABI has no
+        // callee-saved XMM registers; instead, `emit_call_native` saves them around calls.
         if calling_convention == CallingConvention::WindowsFastcall {
             static_area_size += 8 * 2;
+            static_area_size += 16 * self.local_xmms.len();
+        }
+
+        // Spill slots for `steal_temp_gpr`.
+        static_area_size += 8 * self.temp_gprs.len();
+
+        // The copy of vmctx for the landing pads.
+        if self.exception_handlers {
+            static_area_size += 8;
         }
 
         // The offset pointing at the very first local. Right now `static_area_size` is pointing at
         // the end address of the 0th local, not at the start address, so we add `8` bytes to fix
         // this up.
         self.locals_offset = MachineStackOffset(static_area_size + 8);
-        let locals_size = (n as usize).saturating_sub(Self::LOCAL_REGISTERS.len()) * 8;
+        let stack_locals = n as usize - register_locals + self.v128_locals_before(n) as usize;
+        let locals_size = stack_locals * 8;
+        let frame_size = static_area_size + locals_size;
+        // The last slot ends at the bottom of the frame.
+        if frame_size > i32::MAX as usize {
+            return Err(CodegenError {
+                message: format!("the {} locals of this function don't fit in a frame", n),
+            });
+        }
+
+        let mut slot = 0;
+        self.local_prefix = registers
+            .into_iter()
+            .zip(local_types)
+            .map(|(reg, ty)| {
+                reg.unwrap_or_else(|| {
+                    slot += if *ty == WpType::V128 { 2 } else { 1 };
+                    self.get_local_stack_slot(slot - 1)
+                })
+            })
+            .collect();
 
         // Allocate the stack, without actually writing to it.
         a.emit_sub(
             Size::S64,
-            Location::Imm32((static_area_size + locals_size) as _),
+            Location::Imm32(frame_size as _),
             Location::GPR(GPR::RSP),
         );
 
         // Save callee-saved registers
-        for local_reg in Self::LOCAL_REGISTERS.iter().take(n as usize) {
-            self.stack_offset.0 += 8;
-            a.emit_mov(
-                Size::S64,
-                Location::GPR(*local_reg),
-                Location::Memory(GPR::RBP, -(self.stack_offset.0 as i32)),
-            );
+        for local_reg in self.saved_local_gprs().to_vec() {
+            self.save_callee_saved(a, X64Register::GPR(local_reg));
         }
 
         // Save R15 for vmctx use.
-        self.stack_offset.0 += 8;
-        a.emit_mov(
-            Size::S64,
-            Location::GPR(GPR::R15),
-            Location::Memory(GPR::RBP, -(self.stack_offset.0 as i32)),
-        );
+        self.save_callee_saved(a, X64Register::GPR(GPR::R15));
 
-        if calling_convention == CallingConvention::WindowsFastcall {
-            for reg in [GPR::RDI, GPR::RSI] {
-                self.stack_offset.0 += 8;
+        // Load vmctx, and check the frame against the native stack limit before writing to the
+        // bulk of it.
+        self.load_vmctx(a, calling_convention);
+
+        // Stack probe.
+        //
+        // So far, only the top of the frame was written to, within a page of the return address.
+        // Further down, the callee-saved XMM registers and the locals may skip the stack guard
+        // page, `rep stosq` notably writing from low address to high address. So here we touch
+        // every page of the frame in order, down to its bottom, when there is more than one.
+        if frame_size > probe_stride {
+            let probes = (probe_stride..frame_size)
+                .step_by(probe_stride)
+                .chain(iter::once(frame_size));
+            for offset in probes {
                 a.emit_mov(
                     Size::S64,
-                    Location::GPR(reg),
-                    Location::Memory(GPR::RBP, -(self.stack_offset.0 as i32)),
+                    Location::Imm32(0),
+                    Location::Memory(GPR::RBP, -(offset as i32)),
                 );
             }
         }
 
+        if calling_convention == CallingConvention::WindowsFastcall {
+            for reg in [GPR::RDI, GPR::RSI] {
+                self.save_callee_saved(a, X64Register::GPR(reg));
+            }
+        }
+
         // Save the offset of register save area.
         self.save_area_offset = Some(MachineStackOffset(self.stack_offset.0));
 
+        // The XMM registers are restored with explicit moves rather than pops, so they go below
+        // the save area.
+        if calling_convention == CallingConvention::WindowsFastcall {
+            for reg in self.local_xmms.clone() {
+                self.save_callee_saved(a, X64Register::XMM(reg));
+            }
+        }
+
+        // The spill slots for stolen temporary GPRs are only written when needed.
+        self.steal_area_offset = Some(MachineStackOffset(self.stack_offset.0));
+        self.stack_offset.0 += 8 * self.temp_gprs.len();
+
+        if self.exception_handlers {
+            self.stack_offset.0 += 8;
+            self.vmctx_slot = Some(MachineStackOffset(self.stack_offset.0));
+            a.emit_mov(
+                Size::S64,
+                Location::GPR(GPR::R15),
+                Location::Memory(GPR::RBP, -(self.stack_offset.0 as i32)),
+            );
+        }
+
         // Load in-register parameters into the allocated locations.
         // Locals are allocated on the stack from higher address to lower address,
         // so we won't skip the stack guard page here.
+        self.load_params(a, n_params, calling_convention);
+
+        // The stack slots that are not populated with function argument data.
+        let param_registers = self
+            .local_prefix
+            .iter()
+            .take(n_params as usize)
+            .filter(|loc| !matches!(loc, Location::Memory(_, _)))
+            .count();
+        let first_slot =
+            (n_params as usize - param_registers) as u32 + self.v128_locals_before(n_params);
+        let stack_slots = first_slot..stack_locals as u32;
+
+        // Initialize all remaining locals to zero.
+        //
+        // First: handle the locals that are allocated to registers...
+        self.zero_register_locals(a, n_params);
+        // Second: handle the locals that are allocated to the stack.
+        self.zero_local_stack_slots(a, stack_slots);
+
+        // Add the size of all locals allocated to stack.
+        self.stack_offset.0 += locals_size;
+        self.stack_base = MachineStackOffset(self.stack_offset.0);
+        self.rsp_offset = MachineStackOffset(self.stack_offset.0);
+        let stack_offset = self.stack_offset.0 as u64;
+        self.record(|s| s.max_stack_offset = std::cmp::max(s.max_stack_offset, stack_offset));
+        Ok(())
+    }
+
+    /// Loads vmctx into R15, then checks RSP against the native stack limit. A limit of zero never
+    /// triggers. The trap handler is reached through R15, so the check has to come after loading
+    /// it.
+    fn load_vmctx<E: Emitter<Label = DynamicLabel>>(
+        &mut self,
+        a: &mut E,
+        calling_convention: CallingConvention,
+    ) {
+        a.emit_mov(
+            Size::S64,
+            Self::get_param_location(0, calling_convention),
+            Location::GPR(GPR::R15),
+        );
+        if let Some((limit_offset, trap)) = self.stack_limit_check {
+            a.emit_cmp(
+                Size::S64,
+                Location::Memory(GPR::R15, limit_offset as i32),
+                Location::GPR(GPR::RSP),
+            );
+            a.emit_jmp(Condition::Below, trap);
+        }
+    }
+
+    /// Moves the `n_params` params from where the caller passed them to their locals.
+    fn load_params<E: Emitter>(
+        &mut self,
+        a: &mut E,
+        n_params: u32,
+        calling_convention: CallingConvention,
+    ) {
         for i in 0..n_params {
-            // NB: the 0th parameter is used for passing around the internal VM data (vmctx).
-            let loc = Self::get_param_location((i + 1) as usize, calling_convention);
             let local_loc = self.get_local_location(i);
-            match loc {
-                Location::GPR(_) => {
-                    a.emit_mov(Size::S64, loc, local_loc);
+            let word = i + self.v128_locals_before(i);
+            let words: SmallVec<[(u32, Location); 2]> = match local_loc {
+                Location::Memory(base, disp) if self.is_v128_local(i) => {
+                    smallvec![
+                        (word, local_loc),
+                        (word + 1, Location::Memory(base, disp + 8))
+                    ]
                 }
-                Location::Memory(_, _) => match local_loc {
-                    Location::GPR(_) => {
-                        a.emit_mov(Size::S64, loc, local_loc);
-                    }
-                    Location::Memory(_, _) => {
+                _ => smallvec![(word, local_loc)],
+            };
+            for (word, dst) in words {
+                // NB: the 0th parameter is used for passing around the internal VM data (vmctx).
+                let loc = Self::get_param_location((word + 1) as usize, calling_convention);
+                match (loc, dst) {
+                    (Location::Memory(_, _), Location::Memory(_, _)) => {
                         a.emit_mov(Size::S64, loc, Location::GPR(GPR::RAX));
-                        a.emit_mov(Size::S64, Location::GPR(GPR::RAX), local_loc);
+                        a.emit_mov(Size::S64, Location::GPR(GPR::RAX), dst);
+                    }
+                    (Location::GPR(_) | Location::Memory(_, _), _) => {
+                        a.emit_mov(Size::S64, loc, dst);
                     }
                     _ => unreachable!(),
-                },
-                _ => unreachable!(),
+                }
             }
         }
+    }
 
-        // Load vmctx into R15.
-        a.emit_mov(
-            Size::S64,
-            Self::get_param_location(0, calling_convention),
-            Location::GPR(GPR::R15),
-        );
-
-        // Stack probe.
-        //
-        // `rep stosq` writes data from low address to high address and may skip the stack guard page.
-        // so here we probe it explicitly when needed.
-        for i in (n_params..n).step_by(NATIVE_PAGE_SIZE / 8).skip(1) {
-            a.emit_mov(Size::S64, Location::Imm32(0), self.get_local_location(i));
+    /// Zeroes the locals after the `n_params` params that are allocated to registers.
+    fn zero_register_locals<E: Emitter>(&mut self, a: &mut E, n_params: u32) {
+        for loc in self.local_prefix.iter().skip(n_params as usize) {
+            match *loc {
+                Location::GPR(_) => a.emit_mov(Size::S64, Location::Imm32(0), *loc),
+                Location::XMM(x) => a.emit_vxorps(x, XMMOrMemory::XMM(x), x),
+                _ => {}
+            }
         }
+    }
 
-        // Initialize all remaining locals to zero.
-        //
-        // This is a little tricky, as we want to initialize all stack local slots, except for
-        // those that were already populated with function argument data. The complication is in
-        // the fact that we allocate some registers to the first couple local slots.
-        //
-        // First: handle the locals that are allocated to registers...
-        for local_reg_idx in Self::LOCAL_REGISTERS
-            .iter()
-            .skip(n_params as usize)
-            .take((n_params..n).len())
-        {
-            a.emit_mov(Size::S64, Location::Imm32(0), Location::GPR(*local_reg_idx));
-        }
-        // Second: handle the locals that are allocated to the stack.
-        let stack_loc_idxs = std::cmp::max(Self::LOCAL_REGISTERS.len() as u32, n_params)..n;
-        if stack_loc_idxs.len() > 0 {
-            // Since these assemblies take up to 24 bytes, if more than 2 slots are initialized, then they are smaller.
-            a.emit_mov(
-                Size::S64,
-                Location::Imm64(stack_loc_idxs.len() as u64),
-                Location::GPR(GPR::RCX),
-            );
-            a.emit_xor(Size::S64, Location::GPR(GPR::RAX), Location::GPR(GPR::RAX));
-            a.emit_lea(
-                Size::S64,
-                self.get_local_location(n - 1),
-                Location::GPR(GPR::RDI),
-            );
-            a.emit_rep_stosq();
+    /// Zeroes the stack-allocated locals in `slots`.
+    ///
+    /// The strategy depends on the number of slots, as `rep stosq` has a high startup cost and
+    /// needs RAX, RCX and RDI, but is more compact for many slots. The direct stores cover at most
+    /// 256 bytes, so only `rep stosq` relies on the stack probes emitted by `init_locals`.
+    fn zero_local_stack_slots<E: Emitter>(&mut self, a: &mut E, slots: std::ops::Range<u32>) {
+        match slots.len() {
+            0 => {}
+            1..=4 => {
+                for slot in slots {
+                    a.emit_mov(
+                        Size::S64,
+                        Location::Imm32(0),
+                        self.get_local_stack_slot(slot),
+                    );
+                }
+            }
+            5..=32 => {
+                let zero = self.acquire_temp_xmm().unwrap();
+                a.emit_vxorps(zero, XMMOrMemory::XMM(zero), zero);
+                // Each store covers two slots, starting at the lower address of the two.
+                let pair_offset = |slot: u32| match self.get_local_stack_slot(slot + 1) {
+                    Location::Memory(base, disp) => (base, disp),
+                    _ => unreachable!(),
+                };
+                // RBP is 16-byte aligned, so either all the stores are aligned or none are.
+                let aligned = pair_offset(slots.start).1 % 16 == 0;
+                let mut slot = slots.start;
+                while slot + 1 < slots.end {
+                    let (base, disp) = pair_offset(slot);
+                    if aligned {
+                        a.emit_vmovaps(XMMOrMemory::XMM(zero), XMMOrMemory::Memory(base, disp));
+                    } else {
+                        a.emit_movdqu(XMMOrMemory::XMM(zero), XMMOrMemory::Memory(base, disp));
+                    }
+                    slot += 2;
+                }
+                if slot < slots.end {
+                    a.emit_mov(
+                        Size::S64,
+                        Location::Imm32(0),
+                        self.get_local_stack_slot(slot),
+                    );
+                }
+                self.release_temp_xmm(zero);
+            }
+            n => {
+                a.emit_mov(
+                    Size::S64,
+                    Location::Imm64(n as u64),
+                    Location::GPR(GPR::RCX),
+                );
+                a.emit_xor(Size::S64, Location::GPR(GPR::RAX), Location::GPR(GPR::RAX));
+                a.emit_lea(
+                    Size::S64,
+                    self.get_local_stack_slot(slots.end - 1),
+                    Location::GPR(GPR::RDI),
+                );
+                a.emit_rep_stosq();
+            }
         }
-
-        // Add the size of all locals allocated to stack.
-        self.stack_offset.0 += locals_size;
     }
 
+    /// Emits the epilogue restoring the callee-saved registers, and returns the layout of the
+    /// frame it tears down.
     pub(crate) fn finalize_locals<E: Emitter>(
         &mut self,
         a: &mut E,
         calling_convention: CallingConvention,
-        local_count: u32,
-    ) {
-        // Unwind stack to the "save area".
+    ) -> FrameLayout {
+        let save_area_offset = self.save_area_offset.as_ref().unwrap().0;
+
+        // All values must have been released by now, leaving the stack with only the static area
+        // and the stack-allocated locals below the save area.
+        #[cfg(feature = "debug-machine-checks")]
+        {
+            self.ledger.check_empty();
+            assert!(
+                self.stack_slots.is_empty() && self.stack_offset.0 == self.stack_base.0,
+                "stack offset {} does not match the end of the static area at {} \
+                 (save area at {})",
+                self.stack_offset.0,
+                self.stack_base.0,
+                save_area_offset
+            );
+        }
+
+        // The XMM registers used for locals sit right below the save area, and are restored with
+        // explicit moves.
+        for &(reg, bp_neg_offset) in &self.saved_registers {
+            if let X64Register::XMM(xmm) = reg {
+                a.emit_movdqu(
+                    XMMOrMemory::Memory(GPR::RBP, -(bp_neg_offset as i32)),
+                    XMMOrMemory::XMM(xmm),
+                );
+            }
+        }
+
+        // Unwind stack to the "save area", and pop the GPRs: the callee-saved registers used for
+        // locals, R15 used by vmctx, and RDI and RSI on Windows.
         a.emit_lea(
             Size::S64,
-            Location::Memory(
-                GPR::RBP,
-                -(self.save_area_offset.as_ref().unwrap().0 as i32),
-            ),
+            Location::Memory(GPR::RBP, -(save_area_offset as i32)),
             Location::GPR(GPR::RSP),
         );
+        for &(reg, _) in self.saved_registers.iter().rev() {
+            if let X64Register::GPR(gpr) = reg {
+                a.emit_pop(Size::S64, Location::GPR(gpr));
+            }
+        }
 
-        if calling_convention == CallingConvention::WindowsFastcall {
-            // Restore RSI and RDI
-            a.emit_pop(Size::S64, Location::GPR(GPR::RSI));
-            a.emit_pop(Size::S64, Location::GPR(GPR::RDI));
+        FrameLayout {
+            locals_offset: self.locals_offset.0 as u64,
+            save_area_offset: save_area_offset as u64,
+            register_locals: (self.local_gprs_used + self.local_xmms.len()) as u32,
+            saved_rdi_rsi: calling_convention == CallingConvention::WindowsFastcall,
+        }
+    }
+
+    /// Emits the loads restoring the callee-saved registers saved by `init_locals`, like
+    /// `finalize_locals` does, but leaving RSP where it is.
+    ///
+    /// This is for the tail calls, which overwrite the frame with the arguments of the callee
+    /// after restoring the registers.
+    pub(crate) fn reload_callee_saved<E: Emitter>(&self, a: &mut E) {
+        for &(reg, bp_neg_offset) in &self.saved_registers {
+            let disp = -(bp_neg_offset as i32);
+            match reg {
+                X64Register::GPR(gpr) => a.emit_mov(
+                    Size::S64,
+                    Location::Memory(GPR::RBP, disp),
+                    Location::GPR(gpr),
+                ),
+                X64Register::XMM(xmm) => {
+                    a.emit_movdqu(XMMOrMemory::Memory(GPR::RBP, disp), XMMOrMemory::XMM(xmm))
+                }
+            }
         }
-        // Restore R15 used by vmctx.
-        a.emit_pop(Size::S64, Location::GPR(GPR::R15));
+    }
 
-        // Restore callee-saved registers that we used for locals.
-        for reg in Self::LOCAL_REGISTERS
-            .iter()
-            .take(local_count as usize)
-            .rev()
-        {
-            a.emit_pop(Size::S64, Location::GPR(*reg));
+    /// Lay out the locals of a function without a frame pointer and emit the code initializing
+    /// them, see `omit_frame_pointer`.
+    ///
+    /// This is `init_locals` for the System V functions whose locals all fit in registers, see
+    /// `locals_fit_without_frame`, and whose params are all passed in registers. The callee-saved
+    /// registers are pushed rather than stored below RBP, and RSP is left 16-byte aligned, so
+    /// that the trap stubs can call the trap handler without moving it.
+    pub(crate) fn init_frameless_locals<
+        E: Emitter<Label = DynamicLabel, Offset = AssemblyOffset>,
+    >(
+        &mut self,
+        a: &mut E,
+        n: u32,
+        n_params: u32,
+        local_types: &[WpType],
+        calling_convention: CallingConvention,
+    ) -> Result<(), CodegenError> {
+        assert!(self.frameless && calling_convention != CallingConvention::WindowsFastcall);
+        let registers = self.assign_local_registers(n, local_types);
+        if registers.len() != n as usize || registers.contains(&None) {
+            return Err(CodegenError {
+                message: format!("the {} locals of this function don't fit in registers", n),
+            });
+        }
+        self.local_prefix = registers.into_iter().flatten().collect();
+
+        let mut pushed = self.local_gprs[..self.local_gprs_used].to_vec();
+        pushed.push(GPR::R15);
+        for &reg in &pushed {
+            a.emit_push(Size::S64, Location::GPR(reg));
+            self.record_unwind_op(a, UnwindOp::PushRegister { reg });
+        }
+        // RSP is 16-byte aligned right before the call, i.e. 8 bytes off right after it.
+        if pushed.len() % 2 == 0 {
+            self.frameless_padding = 8;
+            a.emit_sub(Size::S64, Location::Imm32(8), Location::GPR(GPR::RSP));
+            self.record_unwind_op(a, UnwindOp::AllocateStack { size: 8 });
+        }
+
+        self.load_vmctx(a, calling_convention);
+        self.load_params(a, n_params, calling_convention);
+        self.zero_register_locals(a, n_params);
+        Ok(())
+    }
+
+    /// Emits the epilogue of a function laid out by `init_frameless_locals`.
+    pub(crate) fn finalize_frameless_locals<E: Emitter<Offset = AssemblyOffset>>(
+        &mut self,
+        a: &mut E,
+    ) {
+        #[cfg(feature = "debug-machine-checks")]
+        self.ledger.check_empty();
+
+        if self.frameless_padding != 0 {
+            let size = self.frameless_padding;
+            a.emit_add(Size::S64, Location::Imm32(size), Location::GPR(GPR::RSP));
+            self.record_unwind_op(a, UnwindOp::FreeStack { size });
+        }
+        let mut popped = self.local_gprs[..self.local_gprs_used].to_vec();
+        popped.push(GPR::R15);
+        for reg in popped.into_iter().rev() {
+            a.emit_pop(Size::S64, Location::GPR(reg));
+            self.record_unwind_op(a, UnwindOp::PopRegister { reg });
         }
     }
 
@@ -530,6 +1617,127 @@ impl Machine {
             },
         }
     }
+
+    /// Where a function returning several values leaves its `idx`-th result.
+    ///
+    /// The first two results go in RAX and RDX, and the others in the first stack argument
+    /// slots of the caller's frame, relative to RBP in the callee. The callee is done with its
+    /// stack arguments by then, as `init_locals` copies them out, and the caller reserves at
+    /// least as many slots as there are such results. A single result is returned in RAX.
+    pub(crate) fn get_return_location(
+        idx: usize,
+        calling_convention: CallingConvention,
+    ) -> Location {
+        let shadow_space = match calling_convention {
+            CallingConvention::WindowsFastcall => 32,
+            _ => 0,
+        };
+        match idx {
+            0 => Location::GPR(GPR::RAX),
+            1 => Location::GPR(GPR::RDX),
+            _ => Location::Memory(GPR::RBP, (16 + shadow_space + (idx - 2) * 8) as i32),
+        }
+    }
+
+    /// Sets up the stack for a call taking `n_stack_args` arguments on the stack.
+    ///
+    /// This saves the registers in use that the callee may clobber, as well as the reserved ones
+    /// it may clobber, which belong to the embedder, then reserves the stack
+    /// arguments and the 32 bytes of shadow space Windows requires, padded so that RSP is 16-byte
+    /// aligned at the call. The caller fills in the arguments at `CallFrame::stack_arg_location`
+    /// and hands the frame back to `restore_call_frame` after the call.
+    ///
+    /// The stack adjustment must have been flushed beforehand.
+    pub(crate) fn prepare_call_frame<E: Emitter>(
+        &mut self,
+        a: &mut E,
+        n_stack_args: usize,
+        calling_convention: CallingConvention,
+    ) -> CallFrame {
+        self.require_frame();
+        // The callee may grow the memory, and the registers needn't be saved for nothing.
+        self.forget_memory_cache();
+        let mut saved_gprs = self.get_used_gprs();
+        saved_gprs.extend_from_slice(&self.reserved_caller_saved_gprs);
+        for r in saved_gprs.iter() {
+            a.emit_push(Size::S64, Location::GPR(*r));
+        }
+
+        // The System V ABI has no callee-saved XMM registers, so the ones holding locals need to
+        // be saved as well. The whole register is saved, as it may hold a 16-byte value.
+        let mut saved_xmms = self.get_used_xmms();
+        if calling_convention != CallingConvention::WindowsFastcall {
+            saved_xmms.extend_from_slice(&self.local_xmms);
+        }
+        if !saved_xmms.is_empty() {
+            a.emit_sub(
+                Size::S64,
+                Location::Imm32((saved_xmms.len() * 16) as u32),
+                Location::GPR(GPR::RSP),
+            );
+            for (i, r) in saved_xmms.iter().enumerate() {
+                a.emit_movdqu(
+                    XMMOrMemory::XMM(*r),
+                    XMMOrMemory::Memory(GPR::RSP, (i * 16) as i32),
+                );
+            }
+        }
+
+        let shadow_space = match calling_convention {
+            CallingConvention::WindowsFastcall => 32,
+            _ => 0,
+        };
+        // An even number of words, as tail calls resize the area by 16 bytes at a time, see
+        // `FuncGen::emit_return_call`.
+        let mut size = shadow_space + 8 * ((n_stack_args + 1) & !1);
+        let depth = self.stack_offset.0 + 8 * saved_gprs.len() + 16 * saved_xmms.len() + size;
+        if depth % 16 != 0 {
+            size += 8;
+        }
+        if size != 0 {
+            a.emit_sub(
+                Size::S64,
+                Location::Imm32(size as u32),
+                Location::GPR(GPR::RSP),
+            );
+        }
+
+        CallFrame {
+            saved_gprs,
+            saved_xmms,
+            size,
+            shadow_space,
+        }
+    }
+
+    /// Tears down a frame set up by `prepare_call_frame`, restoring the saved registers.
+    pub(crate) fn restore_call_frame<E: Emitter>(&mut self, a: &mut E, frame: CallFrame) {
+        if frame.size != 0 {
+            a.emit_add(
+                Size::S64,
+                Location::Imm32(frame.size as u32),
+                Location::GPR(GPR::RSP),
+            );
+        }
+
+        if !frame.saved_xmms.is_empty() {
+            for (i, r) in frame.saved_xmms.iter().enumerate() {
+                a.emit_movdqu(
+                    XMMOrMemory::Memory(GPR::RSP, (i * 16) as i32),
+                    XMMOrMemory::XMM(*r),
+                );
+            }
+            a.emit_add(
+                Size::S64,
+                Location::Imm32((frame.saved_xmms.len() * 16) as u32),
+                Location::GPR(GPR::RSP),
+            );
+        }
+
+        for r in frame.saved_gprs.iter().rev() {
+            a.emit_pop(Size::S64, Location::GPR(*r));
+        }
+    }
 }
 
 #[cfg(test)]
@@ -541,7 +1749,7 @@ mod test {
 
     #[test]
     fn test_release_locations_keep_state_nopanic() {
-        let mut machine = Machine::new();
+        let mut machine = Machine::new(&[]);
         let mut assembler = Assembler::new(0);
         let locs = machine.acquire_locations(
             &mut assembler,
@@ -551,4 +1759,765 @@ mod test {
 
         machine.release_locations_keep_state(&mut assembler, &locs);
     }
+
+    #[test]
+    fn test_stats_count_spills() {
+        let mut machine = Machine::new(&[]);
+        let mut assembler = Assembler::new(0);
+        machine.acquire_locations(
+            &mut assembler,
+            &(0..10).map(|_| WpType::I32).collect::<Vec<_>>(),
+            false,
+        );
+        assert!(machine.take_stats().is_none());
+
+        let mut machine = Machine::new(&[]);
+        machine.enable_stats();
+        machine.acquire_locations(
+            &mut assembler,
+            &(0..10).map(|_| WpType::I32).collect::<Vec<_>>(),
+            false,
+        );
+        machine.acquire_locations(&mut assembler, &[WpType::F64], false);
+        let stats = machine.take_stats().unwrap();
+        assert_eq!(stats.gpr_picks, 6);
+        assert_eq!(stats.xmm_picks, 1);
+        assert_eq!(stats.stack_spills, 4);
+        assert_eq!(stats.max_stack_offset, 32);
+    }
+
+    #[test]
+    fn test_reserved_gprs_are_never_picked() {
+        let reserved = [GPR::R10, GPR::R14];
+        let mut machine = Machine::new(&reserved);
+        let mut assembler = Assembler::new(0);
+        machine
+            .init_locals(
+                &mut assembler,
+                16,
+                0,
+                &[WpType::I64; 8],
+                &[],
+                CallingConvention::SystemV,
+                DEFAULT_PROBE_STRIDE,
+            )
+            .unwrap();
+        let locs = machine.acquire_locations(
+            &mut assembler,
+            &(0..16).map(|_| WpType::I64).collect::<Vec<_>>(),
+            false,
+        );
+        while let Some(gpr) = machine.acquire_temp_gpr() {
+            assert!(!reserved.contains(&gpr));
+        }
+        for loc in locs.iter().chain(
+            (0..16)
+                .map(|i| machine.get_local_location(i))
+                .collect::<Vec<_>>()
+                .iter(),
+        ) {
+            if let Location::GPR(gpr) = loc {
+                assert!(!reserved.contains(gpr));
+            }
+        }
+        assert_eq!(machine.get_local_location(2), Location::GPR(GPR::RBX));
+        assert_eq!(
+            machine.get_local_location(3),
+            Location::Memory(GPR::RBP, -(machine.locals_offset.0 as i32))
+        );
+    }
+
+    #[test]
+    fn test_v128_stack_values_are_aligned() {
+        let mut machine = Machine::new(&[]);
+        let mut assembler = Assembler::new(0);
+        machine
+            .init_locals(
+                &mut assembler,
+                0,
+                0,
+                &[],
+                &[],
+                CallingConvention::SystemV,
+                DEFAULT_PROBE_STRIDE,
+            )
+            .unwrap();
+
+        let locs = machine
+            .acquire_stack_locations(&mut assembler, &[WpType::I64, WpType::V128, WpType::I64]);
+        match locs[1] {
+            Location::Memory(GPR::RBP, disp) => assert_eq!(disp % 16, 0),
+            loc => panic!("unexpected location {:?}", loc),
+        }
+        assert!(machine.is_v128(locs[1]));
+        assert!(!machine.is_v128(locs[0]) && !machine.is_v128(locs[2]));
+
+        // Both slots of a released value can be reused by another one.
+        machine.release_locations_only_stack(&locs[1..2]);
+        let v128 = machine.acquire_stack_locations(&mut assembler, &[WpType::V128]);
+        assert_eq!(v128[0], locs[1]);
+
+        machine.release_locations_only_stack(&locs[2..]);
+        machine.release_locations_only_stack(&v128);
+        machine.release_locations_only_stack(&locs[..1]);
+        assert_eq!(machine.get_stack_offset(), machine.stack_base.0);
+    }
+
+    #[test]
+    fn test_v128_locals_take_two_slots() {
+        use WpType::*;
+        let mut machine = Machine::new(&[]);
+        let mut assembler = Assembler::new(0);
+        machine
+            .init_locals(
+                &mut assembler,
+                10,
+                0,
+                &[V128; 8],
+                &[0..9],
+                CallingConvention::SystemV,
+                DEFAULT_PROBE_STRIDE,
+            )
+            .unwrap();
+
+        // The locals are on the stack even with registers left, at the lower of their slots.
+        for i in 0..9 {
+            assert_eq!(
+                machine.get_local_location(i),
+                machine.get_local_stack_slot(2 * i + 1)
+            );
+        }
+        assert_eq!(
+            machine.get_local_location(9),
+            machine.get_local_stack_slot(18)
+        );
+    }
+
+    #[test]
+    fn test_float_locals_in_xmm_registers() {
+        use WpType::*;
+        let mut machine = Machine::new(&[]);
+        let mut assembler = Assembler::new(0);
+        let types = [F64, I32, F32, F64, I64, F32, F64, I32];
+        machine
+            .init_locals(
+                &mut assembler,
+                10,
+                2,
+                &types,
+                &[],
+                CallingConvention::SystemV,
+                DEFAULT_PROBE_STRIDE,
+            )
+            .unwrap();
+
+        let locations = (0..10)
+            .map(|i| machine.get_local_location(i))
+            .collect::<Vec<_>>();
+        assert_eq!(locations[0], Location::XMM(XMM::XMM12));
+        assert_eq!(locations[1], Location::GPR(GPR::R12));
+        assert_eq!(locations[2], Location::XMM(XMM::XMM13));
+        assert_eq!(locations[3], Location::XMM(XMM::XMM14));
+        assert_eq!(locations[4], Location::GPR(GPR::R13));
+        assert_eq!(locations[5], Location::XMM(XMM::XMM15));
+        // All XMM registers are taken, so this float local goes to the stack.
+        assert_eq!(locations[6], machine.get_local_stack_slot(0));
+        assert_eq!(locations[7], Location::GPR(GPR::R14));
+        assert_eq!(locations[8], machine.get_local_stack_slot(1));
+        assert_eq!(locations[9], machine.get_local_stack_slot(2));
+        assert_eq!(machine.local_xmms.len(), 4);
+
+        // The mapping only depends on the local types.
+        let mut other = Machine::new(&[]);
+        other
+            .init_locals(
+                &mut assembler,
+                10,
+                2,
+                &types,
+                &[],
+                CallingConvention::SystemV,
+                DEFAULT_PROBE_STRIDE,
+            )
+            .unwrap();
+        assert_eq!(
+            locations,
+            (0..10)
+                .map(|i| other.get_local_location(i))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_windows_saves_float_local_registers() {
+        let mut machine = Machine::new(&[]);
+        let mut assembler = Assembler::new(0);
+        machine
+            .init_locals(
+                &mut assembler,
+                2,
+                0,
+                &[WpType::F64, WpType::I32],
+                &[],
+                CallingConvention::WindowsFastcall,
+                DEFAULT_PROBE_STRIDE,
+            )
+            .unwrap();
+        // R12, R15, RDI, RSI, 16 bytes for XMM12 and the three steal slots.
+        assert_eq!(machine.locals_offset.0, 8 * 4 + 16 + 8 * 3 + 8);
+        assert_eq!(machine.get_stack_offset(), 8 * 4 + 16 + 8 * 3);
+    }
+
+    #[test]
+    fn test_init_locals_records_callee_saved_registers() {
+        let mut machine = Machine::new(&[]);
+        let mut assembler = Assembler::new(0);
+        machine
+            .init_locals(
+                &mut assembler,
+                2,
+                0,
+                &[WpType::F64, WpType::I32],
+                &[],
+                CallingConvention::WindowsFastcall,
+                DEFAULT_PROBE_STRIDE,
+            )
+            .unwrap();
+        let ops = machine.take_unwind_ops();
+        let saves = ops
+            .iter()
+            .map(|&(_, op)| match op {
+                UnwindOp::SaveRegister { reg, bp_neg_offset } => (reg, bp_neg_offset),
+                op => panic!("unexpected {:?}", op),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            saves,
+            [
+                (X64Register::GPR(GPR::R12), 8),
+                (X64Register::GPR(GPR::R15), 16),
+                (X64Register::GPR(GPR::RDI), 24),
+                (X64Register::GPR(GPR::RSI), 32),
+                (X64Register::XMM(XMM::XMM12), 48),
+            ]
+        );
+        // Each save is recorded right after the instruction performing it.
+        assert!(ops.windows(2).all(|w| w[0].0 < w[1].0));
+    }
+
+    #[test]
+    fn test_callee_saved_registers_are_reloaded_from_their_slots() {
+        let mut machine = Machine::new(&[]);
+        let mut assembler = Assembler::new(0);
+        machine
+            .init_locals(
+                &mut assembler,
+                2,
+                0,
+                &[WpType::F64, WpType::I32],
+                &[],
+                CallingConvention::WindowsFastcall,
+                DEFAULT_PROBE_STRIDE,
+            )
+            .unwrap();
+        let mut reloads = Assembler::new(0);
+        machine.reload_callee_saved(&mut reloads);
+
+        let mut expected = Assembler::new(0);
+        for (gpr, offset) in [
+            (GPR::R12, 8),
+            (GPR::R15, 16),
+            (GPR::RDI, 24),
+            (GPR::RSI, 32),
+        ] {
+            expected.emit_mov(
+                Size::S64,
+                Location::Memory(GPR::RBP, -offset),
+                Location::GPR(gpr),
+            );
+        }
+        expected.emit_movdqu(
+            XMMOrMemory::Memory(GPR::RBP, -48),
+            XMMOrMemory::XMM(XMM::XMM12),
+        );
+        assert_eq!(reloads.finalize().unwrap(), expected.finalize().unwrap());
+    }
+
+    #[test]
+    fn test_frameless_locals_are_pushed_and_popped() {
+        let mut machine = Machine::new(&[GPR::RBX]);
+        machine.omit_frame_pointer();
+        let mut assembler = Assembler::new(0);
+        let local_types = [WpType::I32, WpType::F64, WpType::I64];
+        assert!(machine.locals_fit_without_frame(&local_types));
+        machine
+            .init_frameless_locals(
+                &mut assembler,
+                3,
+                1,
+                &local_types,
+                CallingConvention::SystemV,
+            )
+            .unwrap();
+        assert_eq!(machine.get_local_location(0), Location::GPR(GPR::R12));
+        assert_eq!(machine.get_local_location(1), Location::XMM(XMM::XMM12));
+        assert_eq!(machine.get_local_location(2), Location::GPR(GPR::R13));
+        machine.finalize_frameless_locals(&mut assembler);
+
+        let ops = machine
+            .take_unwind_ops()
+            .into_iter()
+            .map(|(_, op)| op)
+            .collect::<Vec<_>>();
+        // Three pushes leave RSP 16-byte aligned, without any padding.
+        assert_eq!(
+            ops,
+            [
+                UnwindOp::PushRegister { reg: GPR::R12 },
+                UnwindOp::PushRegister { reg: GPR::R13 },
+                UnwindOp::PushRegister { reg: GPR::R15 },
+                UnwindOp::PopRegister { reg: GPR::R15 },
+                UnwindOp::PopRegister { reg: GPR::R13 },
+                UnwindOp::PopRegister { reg: GPR::R12 },
+            ]
+        );
+        assert!(!machine.needs_frame());
+    }
+
+    #[test]
+    fn test_frameless_stack_values_need_a_frame() {
+        let mut machine = Machine::new(&[]);
+        machine.omit_frame_pointer();
+        let mut assembler = Assembler::new(0);
+        // Even with every local register pushed, RSP is kept 16-byte aligned.
+        let local_types = [WpType::I64; 5];
+        assert!(machine.locals_fit_without_frame(&local_types));
+        assert!(!machine.locals_fit_without_frame(&[WpType::I64; 6]));
+        machine
+            .init_frameless_locals(
+                &mut assembler,
+                5,
+                0,
+                &local_types,
+                CallingConvention::SystemV,
+            )
+            .unwrap();
+        assert_eq!(machine.get_local_location(4), Location::GPR(GPR::RBP));
+        assert_eq!(
+            machine.take_unwind_ops().last().unwrap().1,
+            UnwindOp::AllocateStack { size: 8 }
+        );
+
+        // Stolen registers go right below RSP.
+        let held = [(); 3].map(|()| machine.steal_temp_gpr(&mut assembler, &[]));
+        let stolen = machine.steal_temp_gpr(&mut assembler, &[]);
+        assert_eq!(
+            machine.get_steal_slot(stolen),
+            Location::Memory(GPR::RSP, -8)
+        );
+        machine.restore_stolen_gpr(&mut assembler, stolen);
+        for &gpr in held.iter().rev() {
+            machine.restore_stolen_gpr(&mut assembler, gpr);
+        }
+        assert!(!machine.needs_frame());
+
+        let values = machine.acquire_locations(&mut assembler, &[WpType::I64; 7], false);
+        assert!(machine.needs_frame());
+        machine.release_locations(&values);
+    }
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack.windows(needle.len()).any(|w| w == needle)
+    }
+
+    #[test]
+    fn test_zero_local_stack_slots() {
+        let rep_stosq = {
+            let mut a = Assembler::new(0);
+            a.emit_rep_stosq();
+            a.finalize().unwrap()
+        };
+        let zero_locals = |n: u32| {
+            let mut machine = Machine::new(&[]);
+            machine.locals_offset = MachineStackOffset(8);
+            let mut assembler = Assembler::new(0);
+            machine.zero_local_stack_slots(&mut assembler, 0..n);
+            assert!(machine.get_used_xmms().is_empty());
+            assert!(machine.acquire_temp_xmm().is_some());
+            (machine, assembler.finalize().unwrap())
+        };
+
+        // A few locals are zeroed one by one.
+        let (machine, code) = zero_locals(3);
+        let mut expected = Assembler::new(0);
+        for slot in 0..3 {
+            expected.emit_mov(
+                Size::S64,
+                Location::Imm32(0),
+                machine.get_local_stack_slot(slot),
+            );
+        }
+        assert_eq!(code, expected.finalize().unwrap());
+
+        // More are zeroed two by two from an XMM register, with the odd one out at the end.
+        let (machine, code) = zero_locals(7);
+        let mut expected = Assembler::new(0);
+        expected.emit_vxorps(XMM::XMM0, XMMOrMemory::XMM(XMM::XMM0), XMM::XMM0);
+        for slot in &[1, 3, 5] {
+            match machine.get_local_stack_slot(*slot) {
+                // The first local is at RBP-8, so the pairs are 16-byte aligned.
+                Location::Memory(base, disp) => expected
+                    .emit_vmovaps(XMMOrMemory::XMM(XMM::XMM0), XMMOrMemory::Memory(base, disp)),
+                _ => unreachable!(),
+            }
+        }
+        expected.emit_mov(
+            Size::S64,
+            Location::Imm32(0),
+            machine.get_local_stack_slot(6),
+        );
+        assert_eq!(code, expected.finalize().unwrap());
+
+        // Many more use `rep stosq`.
+        let (_, code) = zero_locals(33);
+        assert!(contains(&code, &rep_stosq));
+    }
+
+    #[test]
+    fn test_stack_limit_check_follows_vmctx_load() {
+        let prologue = |check: bool| {
+            let mut machine = Machine::new(&[]);
+            let mut assembler = Assembler::new(0);
+            let trap = assembler.get_label();
+            if check {
+                machine.enable_stack_limit_check(0x40, trap);
+            }
+            machine
+                .init_locals(
+                    &mut assembler,
+                    3,
+                    1,
+                    &[WpType::I64; 3],
+                    &[],
+                    CallingConvention::SystemV,
+                    DEFAULT_PROBE_STRIDE,
+                )
+                .unwrap();
+            assembler.emit_label(trap);
+            assembler.finalize().unwrap()
+        };
+        let cmp = {
+            let mut a = Assembler::new(0);
+            a.emit_mov(Size::S64, Location::GPR(GPR::RDI), Location::GPR(GPR::R15));
+            a.emit_cmp(
+                Size::S64,
+                Location::Memory(GPR::R15, 0x40),
+                Location::GPR(GPR::RSP),
+            );
+            a.finalize().unwrap()
+        };
+
+        let unchecked = prologue(false);
+        let checked = prologue(true);
+        assert!(!contains(&unchecked, &cmp));
+        assert!(contains(&checked, &cmp));
+    }
+
+    #[test]
+    fn test_windows_call_frame_reserves_shadow_space() {
+        let mut machine = Machine::new(&[]);
+        // vmctx and the 8 parameters of the callee, all but 4 of which go on the stack.
+        let n_stack_args = (0..9)
+            .filter(|&i| {
+                matches!(
+                    Machine::get_param_location(i, CallingConvention::WindowsFastcall),
+                    Location::Memory(_, _)
+                )
+            })
+            .count();
+        assert_eq!(n_stack_args, 5);
+
+        let mut assembler = Assembler::new(0);
+        let frame = machine.prepare_call_frame(
+            &mut assembler,
+            n_stack_args,
+            CallingConvention::WindowsFastcall,
+        );
+        assert_eq!(frame.stack_arg_location(0), Location::Memory(GPR::RSP, 32));
+        assert_eq!(frame.stack_arg_location(4), Location::Memory(GPR::RSP, 64));
+        // 32 bytes of shadow space, 40 bytes of arguments and 8 bytes of padding, in one go.
+        let mut expected = Assembler::new(0);
+        expected.emit_sub(Size::S64, Location::Imm32(80), Location::GPR(GPR::RSP));
+        assert_eq!(assembler.finalize().unwrap(), expected.finalize().unwrap());
+
+        let mut assembler = Assembler::new(0);
+        machine.restore_call_frame(&mut assembler, frame);
+        let mut expected = Assembler::new(0);
+        expected.emit_add(Size::S64, Location::Imm32(80), Location::GPR(GPR::RSP));
+        assert_eq!(assembler.finalize().unwrap(), expected.finalize().unwrap());
+    }
+
+    #[test]
+    fn test_return_area_overlaps_stack_args() {
+        for &(cc, first_stack_param) in &[
+            (CallingConvention::SystemV, 6),
+            (CallingConvention::WindowsFastcall, 4),
+        ] {
+            assert_eq!(
+                Machine::get_return_location(2, cc),
+                Machine::get_param_location(first_stack_param, cc)
+            );
+            assert_eq!(
+                Machine::get_return_location(4, cc),
+                Machine::get_param_location(first_stack_param + 2, cc)
+            );
+        }
+    }
+
+    #[test]
+    fn test_call_frames_save_the_reserved_caller_saved_registers() {
+        let mut machine = Machine::new(&[GPR::R11, GPR::R12, GPR::R10]);
+        let mut assembler = Assembler::new(0);
+        let locs = machine.acquire_locations(&mut assembler, &[WpType::I64], false);
+        let frame = machine.prepare_call_frame(&mut assembler, 0, CallingConvention::SystemV);
+        // The callee saves R12 itself.
+        assert_eq!(frame.saved_gprs, vec![GPR::RSI, GPR::R10, GPR::R11]);
+        // Padded to keep RSP 16-byte aligned.
+        assert_eq!(frame.depth(), 32);
+        machine.restore_call_frame(&mut assembler, frame);
+        machine.release_locations(&locs);
+    }
+
+    #[test]
+    fn test_call_frame_alignment_accounts_for_saved_registers() {
+        let mut machine = Machine::new(&[]);
+        let mut assembler = Assembler::new(0);
+        let locs = machine.acquire_locations(&mut assembler, &[WpType::I64], false);
+        let frame = machine.prepare_call_frame(&mut assembler, 2, CallingConvention::SystemV);
+        assert_eq!(frame.stack_arg_location(0), Location::Memory(GPR::RSP, 0));
+        // The saved GPR misaligns the stack, so the arguments need padding.
+        assert_eq!(frame.saved_gprs, vec![GPR::RSI]);
+        assert_eq!(frame.size, 24);
+        machine.restore_call_frame(&mut assembler, frame);
+        machine.release_locations(&locs);
+
+        let frame = machine.prepare_call_frame(&mut assembler, 2, CallingConvention::SystemV);
+        assert!(frame.saved_gprs.is_empty());
+        assert_eq!(frame.size, 16);
+
+        // An odd number of stack arguments is rounded up, see `FuncGen::emit_return_call`.
+        machine.restore_call_frame(&mut assembler, frame);
+        let frame = machine.prepare_call_frame(&mut assembler, 3, CallingConvention::SystemV);
+        assert_eq!(frame.size, 32);
+    }
+
+    #[test]
+    fn test_steal_temp_gpr() {
+        let mut machine = Machine::new(&[]);
+        let mut assembler = Assembler::new(0);
+        machine
+            .init_locals(
+                &mut assembler,
+                0,
+                0,
+                &[],
+                &[],
+                CallingConvention::SystemV,
+                DEFAULT_PROBE_STRIDE,
+            )
+            .unwrap();
+        machine.enable_stats();
+
+        let held = [(); 3].map(|()| machine.steal_temp_gpr(&mut assembler, &[]));
+        assert_eq!(held, [GPR::RAX, GPR::RCX, GPR::RDX]);
+        assert!(machine.acquire_temp_gpr().is_none());
+
+        // The least recently acquired register is stolen first, unless it is being used.
+        let first = machine.steal_temp_gpr(&mut assembler, &[]);
+        assert_eq!(first, GPR::RAX);
+        let second = machine.steal_temp_gpr(&mut assembler, &[Location::Memory(GPR::RCX, 0)]);
+        assert_eq!(second, GPR::RDX);
+        assert_ne!(
+            machine.get_steal_slot(first),
+            machine.get_steal_slot(second)
+        );
+
+        machine.restore_stolen_gpr(&mut assembler, second);
+        machine.restore_stolen_gpr(&mut assembler, first);
+        // Restoring gives the registers back to their owners, which still hold them.
+        assert!(machine.acquire_temp_gpr().is_none());
+        for &gpr in held.iter().rev() {
+            machine.restore_stolen_gpr(&mut assembler, gpr);
+        }
+        assert_eq!(machine.acquire_temp_gpr(), Some(GPR::RAX));
+        assert_eq!(machine.take_stats().unwrap().temp_gpr_steals, 2);
+    }
+
+    #[test]
+    fn test_out_of_order_release_reuses_stack_slots() {
+        let mut machine = Machine::new(&[]);
+        let mut assembler = Assembler::new(0);
+        // The registers take the first six values, the rest go to the stack.
+        let locs = machine.acquire_locations(
+            &mut assembler,
+            &(0..10).map(|_| WpType::I64).collect::<Vec<_>>(),
+            false,
+        );
+        assert_eq!(machine.get_stack_offset(), 32);
+
+        // Freeing a slot below the top leaves a hole without shrinking the stack.
+        machine.release_locations(&[locs[7]]);
+        assert_eq!(machine.get_stack_offset(), 32);
+        assert_eq!(machine.released_stack_size(&[locs[9]]), 8);
+        assert_eq!(machine.released_stack_size(&[locs[8], locs[9]]), 24);
+
+        // The hole is reused before the stack grows again.
+        let reused = machine.acquire_locations(&mut assembler, &[WpType::I64], false);
+        assert_eq!(reused[0], locs[7]);
+        assert_eq!(machine.get_stack_offset(), 32);
+        let grown = machine.acquire_locations(&mut assembler, &[WpType::I64], false);
+        assert_eq!(machine.get_stack_offset(), 40);
+
+        // Freeing the top slots shrinks the stack down to the highest live slot.
+        machine.release_locations(&[locs[8]]);
+        machine.release_locations(&[locs[9], grown[0]]);
+        assert_eq!(machine.get_stack_offset(), 16);
+        machine.release_locations(&[locs[6], reused[0]]);
+        assert_eq!(machine.get_stack_offset(), 0);
+        machine.release_locations(&locs[..6]);
+        assert!(machine.get_used_gprs().is_empty());
+    }
+
+    #[test]
+    fn test_stack_adjustment_is_deferred() {
+        let mut machine = Machine::new(&[]);
+        let mut assembler = Assembler::new(0);
+        let locs = machine.acquire_locations(
+            &mut assembler,
+            &(0..10).map(|_| WpType::I64).collect::<Vec<_>>(),
+            false,
+        );
+        let grown = assembler.get_offset().0;
+        assert!(grown > 0);
+
+        // Releasing and reacquiring stack values below RSP does not move it.
+        machine.release_locations(&locs[6..]);
+        assert_eq!(machine.get_stack_offset(), 0);
+        assert_eq!(machine.pending_stack_adjustment(), 32);
+        let reused = machine.acquire_locations(&mut assembler, &[WpType::I64, WpType::I64], false);
+        assert_eq!(&reused[..], &locs[6..8]);
+        assert_eq!(machine.pending_stack_adjustment(), 16);
+        assert_eq!(assembler.get_offset().0, grown);
+
+        // Flushing catches RSP up with the stack, once.
+        machine.flush_stack_adjustment(&mut assembler);
+        let flushed = assembler.get_offset().0;
+        assert!(flushed > grown);
+        assert_eq!(machine.pending_stack_adjustment(), 0);
+        machine.flush_stack_adjustment(&mut assembler);
+        assert_eq!(assembler.get_offset().0, flushed);
+
+        // Growing past RSP moves it again.
+        machine.acquire_locations(&mut assembler, &[WpType::I64], false);
+        assert!(assembler.get_offset().0 > flushed);
+        assert_eq!(machine.pending_stack_adjustment(), 0);
+    }
+
+    #[test]
+    fn test_acquire_locations_in() {
+        let mut machine = Machine::new(&[]);
+        let mut assembler = Assembler::new(0);
+
+        // A free register is handed out as is.
+        let r9 = machine.acquire_locations_in(&mut assembler, WpType::I64, Location::GPR(GPR::R9));
+        assert_eq!(r9, Location::GPR(GPR::R9));
+        assert!(machine.take_evictions().is_empty());
+
+        // An occupied one is freed up by moving its value to the stack.
+        let again =
+            machine.acquire_locations_in(&mut assembler, WpType::I32, Location::GPR(GPR::R9));
+        assert_eq!(again, Location::GPR(GPR::R9));
+        let evictions = machine.take_evictions();
+        assert_eq!(evictions.len(), 1);
+        assert_eq!(evictions[0].0, Location::GPR(GPR::R9));
+        let slot = evictions[0].1;
+        assert!(matches!(slot, Location::Memory(GPR::RBP, _)));
+        assert_eq!(machine.get_stack_offset(), 8);
+        assert_eq!(machine.get_used_gprs(), vec![GPR::R9]);
+
+        let xmm =
+            machine.acquire_locations_in(&mut assembler, WpType::F64, Location::XMM(XMM::XMM5));
+        assert_eq!(xmm, Location::XMM(XMM::XMM5));
+
+        // The freed up registers are skipped by regular acquisitions.
+        let next = machine.acquire_locations(&mut assembler, &[WpType::I64, WpType::F64], false);
+        assert_eq!(
+            next[..],
+            [Location::GPR(GPR::RSI), Location::XMM(XMM::XMM3)]
+        );
+
+        machine.release_locations(&next);
+        machine.release_locations(&[slot, again, xmm]);
+        assert_eq!(machine.get_stack_offset(), 0);
+        assert!(machine.get_used_gprs().is_empty());
+        assert!(machine.get_used_xmms().is_empty());
+    }
+
+    #[test]
+    fn test_memory_cache_gives_way_to_values() {
+        let mut machine = Machine::new(&[]);
+        let mut assembler = Assembler::new(0);
+
+        // The cache takes the last registers of the pool, leaving the first ones to values.
+        let cache = machine.acquire_memory_cache(true).unwrap();
+        assert_eq!(cache.base, GPR::R11);
+        assert_eq!(cache.end, Some(GPR::R10));
+        let values = machine.acquire_locations(
+            &mut assembler,
+            &[WpType::I64, WpType::I64, WpType::I64, WpType::I64],
+            false,
+        );
+        assert_eq!(machine.memory_cache(), Some(cache));
+
+        // A value needing a register takes back those of the cache rather than spill.
+        let value = machine.acquire_locations(&mut assembler, &[WpType::I32], false)[0];
+        assert_eq!(value, Location::GPR(GPR::R10));
+        assert_eq!(machine.memory_cache(), None);
+        assert_eq!(machine.get_stack_offset(), 0);
+        machine.release_locations(&[value]);
+
+        // Without enough free registers, nothing is cached.
+        let value = machine.acquire_locations(&mut assembler, &[WpType::I32], false)[0];
+        assert_eq!(machine.acquire_memory_cache(true), None);
+        let cache = machine.acquire_memory_cache(false).unwrap();
+        assert_eq!(cache.base, GPR::R11);
+        assert_eq!(cache.end, None);
+
+        // A value wanting the register of the cache in particular takes it without eviction.
+        let r11 =
+            machine.acquire_locations_in(&mut assembler, WpType::I64, Location::GPR(GPR::R11));
+        assert_eq!(r11, Location::GPR(GPR::R11));
+        assert!(machine.take_evictions().is_empty());
+        assert_eq!(machine.memory_cache(), None);
+        machine.release_locations(&[r11, value]);
+
+        // Calls forget the cache, without saving its registers.
+        machine.acquire_memory_cache(true).unwrap();
+        let frame = machine.prepare_call_frame(&mut assembler, 0, CallingConvention::SystemV);
+        assert_eq!(frame.saved_gprs, machine.get_used_gprs());
+        assert_eq!(machine.memory_cache(), None);
+        machine.restore_call_frame(&mut assembler, frame);
+        machine.release_locations(&values);
+        assert!(machine.get_used_gprs().is_empty());
+    }
+
+    #[cfg(feature = "debug-machine-checks")]
+    #[test]
+    #[should_panic(expected = "register RAX released while not in use")]
+    fn test_double_release_is_reported() {
+        let mut machine = Machine::new(&[]);
+        let gpr = machine.acquire_temp_gpr().unwrap();
+        machine.release_temp_gpr(gpr);
+        machine.release_temp_gpr(gpr);
+    }
 }
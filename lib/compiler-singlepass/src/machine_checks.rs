@@ -0,0 +1,112 @@
+//! Register bookkeeping checks for `Machine`, enabled by the `debug-machine-checks` feature.
+//!
+//! Every acquisition and release of a register is recorded along with a backtrace, so that when
+//! a register is acquired twice or released while free, the resulting panic can point at the
+//! code responsible for both sides of the conflict.
+
+use crate::emitter_x64::{GPR, XMM};
+use backtrace::Backtrace;
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum Register {
+    GPR(GPR),
+    XMM(XMM),
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::GPR(gpr) => write!(f, "{:?}", gpr),
+            Self::XMM(xmm) => write!(f, "{:?}", xmm),
+        }
+    }
+}
+
+/// A single acquisition or release of a register.
+struct Event {
+    stack_offset: usize,
+    backtrace: Backtrace,
+}
+
+impl Event {
+    fn new(stack_offset: usize) -> Self {
+        Self {
+            stack_offset,
+            // Resolving symbols is slow, and only needed if the event ends up in a report.
+            backtrace: Backtrace::new_unresolved(),
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut backtrace = self.backtrace.clone();
+        backtrace.resolve();
+        writeln!(f, "at stack offset {}:", self.stack_offset)?;
+        write!(f, "{:?}", backtrace)
+    }
+}
+
+/// The acquisitions of all registers currently in use, and the last release of each register.
+#[derive(Default)]
+pub(crate) struct Ledger {
+    live: HashMap<Register, Event>,
+    released: HashMap<Register, Event>,
+}
+
+impl Ledger {
+    pub(crate) fn acquire(&mut self, reg: Register, stack_offset: usize) {
+        let event = Event::new(stack_offset);
+        if let Some(previous) = self.live.get(&reg) {
+            panic!(
+                "register {} acquired while already in use\n\
+                 first acquisition {}\n\
+                 second acquisition {}",
+                reg, previous, event
+            );
+        }
+        self.live.insert(reg, event);
+    }
+
+    pub(crate) fn release(&mut self, reg: Register, stack_offset: usize) {
+        let event = Event::new(stack_offset);
+        if self.live.remove(&reg).is_none() {
+            match self.released.get(&reg) {
+                Some(previous) => panic!(
+                    "register {} released while not in use\n\
+                     previous release {}\n\
+                     second release {}",
+                    reg, previous, event
+                ),
+                None => panic!(
+                    "register {} released without ever being acquired\nrelease {}",
+                    reg, event
+                ),
+            }
+        }
+        self.released.insert(reg, event);
+    }
+
+    /// Hands over a register in use to a new owner.
+    pub(crate) fn transfer(&mut self, reg: Register, stack_offset: usize) {
+        self.release(reg, stack_offset);
+        self.acquire(reg, stack_offset);
+    }
+
+    /// Checks that no register is in use anymore.
+    pub(crate) fn check_empty(&self) {
+        if self.live.is_empty() {
+            return;
+        }
+        let mut report = String::new();
+        for (reg, event) in self.live.iter() {
+            report.push_str(&format!("register {} acquired {}\n", reg, event));
+        }
+        panic!(
+            "registers still in use at the end of the function\n{}",
+            report
+        );
+    }
+}
@@ -0,0 +1,231 @@
+//! Fuel metering: the fuel to charge at the entry of each block of straight-line code.
+
+use std::fmt;
+use wasmer_compiler::wasmparser::{BinaryReader, Operator};
+
+/// The function giving the fuel cost of each operator.
+#[derive(Clone, Copy)]
+pub(crate) struct CostFn(pub(crate) fn(&Operator) -> u64);
+
+impl CostFn {
+    /// A digest of the costs of all the operators, with their immediates set to zero.
+    ///
+    /// Unlike the address of the function, this identifies the costs across processes, so
+    /// that code compiled with other costs isn't loaded. Costs depending on the immediates
+    /// aren't told apart.
+    pub(crate) fn digest(&self) -> u64 {
+        // FNV-1a, whose result doesn't depend on the version of the standard library.
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        let mut feed = |bytes: &[u8]| {
+            for byte in bytes {
+                hash = (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3);
+            }
+        };
+        let prefixed = [0xfc, 0xfd, 0xfe].iter().flat_map(|&prefix| {
+            (0..0x200u32).map(move |opcode| {
+                let mut code = vec![prefix];
+                leb128_u32(opcode, &mut code);
+                code
+            })
+        });
+        let opcodes = (0..=0xffu8)
+            .filter(|opcode| ![0xfc, 0xfd, 0xfe].contains(opcode))
+            .map(|opcode| vec![opcode])
+            .chain(prefixed);
+        for opcode in opcodes {
+            // Enough zeroes for the largest immediate, that of `v128.const`.
+            let mut code = opcode.clone();
+            code.extend_from_slice(&[0; 16]);
+            if let Ok(op) = BinaryReader::new(&code).read_operator() {
+                feed(&opcode);
+                feed(&(self.0)(&op).to_le_bytes());
+            }
+        }
+        hash
+    }
+}
+
+impl fmt::Debug for CostFn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CostFn({:#018x})", self.digest())
+    }
+}
+
+/// Appends the unsigned LEB128 encoding of `value` to `out`.
+fn leb128_u32(mut value: u32, out: &mut Vec<u8>) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Whether the operator following `op` starts a new block of metered code.
+///
+/// This is the case unless `op` is known to neither branch, call, trap nor start or end a
+/// control frame, so that any other operator ends a block.
+fn ends_block(op: &Operator) -> bool {
+    !matches!(
+        op,
+        Operator::Nop
+            | Operator::Drop
+            | Operator::Select
+            | Operator::TypedSelect { .. }
+            | Operator::LocalGet { .. }
+            | Operator::LocalSet { .. }
+            | Operator::LocalTee { .. }
+            | Operator::GlobalGet { .. }
+            | Operator::GlobalSet { .. }
+            | Operator::I32Const { .. }
+            | Operator::I64Const { .. }
+            | Operator::F32Const { .. }
+            | Operator::F64Const { .. }
+            | Operator::RefNull { .. }
+            | Operator::RefIsNull
+            | Operator::RefFunc { .. }
+            | Operator::I32Eqz
+            | Operator::I32Eq
+            | Operator::I32Ne
+            | Operator::I32LtS
+            | Operator::I32LtU
+            | Operator::I32GtS
+            | Operator::I32GtU
+            | Operator::I32LeS
+            | Operator::I32LeU
+            | Operator::I32GeS
+            | Operator::I32GeU
+            | Operator::I64Eqz
+            | Operator::I64Eq
+            | Operator::I64Ne
+            | Operator::I64LtS
+            | Operator::I64LtU
+            | Operator::I64GtS
+            | Operator::I64GtU
+            | Operator::I64LeS
+            | Operator::I64LeU
+            | Operator::I64GeS
+            | Operator::I64GeU
+            | Operator::F32Eq
+            | Operator::F32Ne
+            | Operator::F32Lt
+            | Operator::F32Gt
+            | Operator::F32Le
+            | Operator::F32Ge
+            | Operator::F64Eq
+            | Operator::F64Ne
+            | Operator::F64Lt
+            | Operator::F64Gt
+            | Operator::F64Le
+            | Operator::F64Ge
+            | Operator::I32Clz
+            | Operator::I32Ctz
+            | Operator::I32Popcnt
+            | Operator::I32Add
+            | Operator::I32Sub
+            | Operator::I32Mul
+            | Operator::I32And
+            | Operator::I32Or
+            | Operator::I32Xor
+            | Operator::I32Shl
+            | Operator::I32ShrS
+            | Operator::I32ShrU
+            | Operator::I32Rotl
+            | Operator::I32Rotr
+            | Operator::I64Clz
+            | Operator::I64Ctz
+            | Operator::I64Popcnt
+            | Operator::I64Add
+            | Operator::I64Sub
+            | Operator::I64Mul
+            | Operator::I64And
+            | Operator::I64Or
+            | Operator::I64Xor
+            | Operator::I64Shl
+            | Operator::I64ShrS
+            | Operator::I64ShrU
+            | Operator::I64Rotl
+            | Operator::I64Rotr
+            | Operator::F32Abs
+            | Operator::F32Neg
+            | Operator::F32Ceil
+            | Operator::F32Floor
+            | Operator::F32Trunc
+            | Operator::F32Nearest
+            | Operator::F32Sqrt
+            | Operator::F32Add
+            | Operator::F32Sub
+            | Operator::F32Mul
+            | Operator::F32Div
+            | Operator::F32Min
+            | Operator::F32Max
+            | Operator::F32Copysign
+            | Operator::F64Abs
+            | Operator::F64Neg
+            | Operator::F64Ceil
+            | Operator::F64Floor
+            | Operator::F64Trunc
+            | Operator::F64Nearest
+            | Operator::F64Sqrt
+            | Operator::F64Add
+            | Operator::F64Sub
+            | Operator::F64Mul
+            | Operator::F64Div
+            | Operator::F64Min
+            | Operator::F64Max
+            | Operator::F64Copysign
+            | Operator::I32WrapI64
+            | Operator::I64ExtendI32S
+            | Operator::I64ExtendI32U
+            | Operator::F32ConvertI32S
+            | Operator::F32ConvertI32U
+            | Operator::F32ConvertI64S
+            | Operator::F32ConvertI64U
+            | Operator::F32DemoteF64
+            | Operator::F64ConvertI32S
+            | Operator::F64ConvertI32U
+            | Operator::F64ConvertI64S
+            | Operator::F64ConvertI64U
+            | Operator::F64PromoteF32
+            | Operator::I32ReinterpretF32
+            | Operator::I64ReinterpretF64
+            | Operator::F32ReinterpretI32
+            | Operator::F64ReinterpretI64
+            | Operator::I32Extend8S
+            | Operator::I32Extend16S
+            | Operator::I64Extend8S
+            | Operator::I64Extend16S
+            | Operator::I64Extend32S
+            | Operator::I32TruncSatF32S
+            | Operator::I32TruncSatF32U
+            | Operator::I32TruncSatF64S
+            | Operator::I32TruncSatF64U
+            | Operator::I64TruncSatF32S
+            | Operator::I64TruncSatF32U
+            | Operator::I64TruncSatF64S
+            | Operator::I64TruncSatF64U
+    )
+}
+
+/// Compute the fuel to charge before each of `operators`, the operators of a function body.
+///
+/// The fuel charged before the first operator of a block is the total cost of the block,
+/// the last operator included, and nothing is charged before the others.
+pub(crate) fn fuel_costs<'a, 'b: 'a>(
+    operators: impl IntoIterator<Item = &'a Operator<'b>>,
+    cost_fn: CostFn,
+) -> Vec<u64> {
+    let mut costs: Vec<u64> = vec![];
+    let mut block_start = 0;
+    for op in operators {
+        costs.push(0);
+        costs[block_start] = costs[block_start].saturating_add((cost_fn.0)(op));
+        if ends_block(op) {
+            block_start = costs.len();
+        }
+    }
+    costs
+}
@@ -0,0 +1,312 @@
+//! A peephole optimizer layered over an `Emitter`.
+//!
+//! The code generator moves values between registers and memory one operator at a time, which
+//! leaves patterns behind such as a value reloaded from where it was just stored. `PeepholeEmitter`
+//! holds back the last `mov` it was asked to emit, so that the next instruction can cancel it or be
+//! cancelled by it. Every other instruction flushes the delayed `mov` first, so labels, branches,
+//! calls, traps and offsets observed through `get_offset` always see the code in its final position.
+
+use crate::emitter_x64::*;
+
+/// A `mov` that has not been passed to the underlying emitter yet.
+#[derive(Copy, Clone, Debug)]
+struct DelayedMov {
+    sz: Size,
+    src: Location,
+    dst: Location,
+}
+
+pub(crate) struct PeepholeEmitter<E: Emitter> {
+    inner: E,
+    enabled: bool,
+    delayed: Option<DelayedMov>,
+}
+
+impl<E: Emitter> PeepholeEmitter<E> {
+    /// Wraps `inner`. When `enabled` is false, every instruction is passed through unchanged.
+    pub(crate) fn new(inner: E, enabled: bool) -> Self {
+        Self {
+            inner,
+            enabled,
+            delayed: None,
+        }
+    }
+
+    /// Flushes the delayed instruction and gives access to the underlying emitter.
+    pub(crate) fn flushed(&mut self) -> &mut E {
+        self.flush();
+        &mut self.inner
+    }
+
+    /// Gives access to the underlying emitter, leaving the delayed instruction pending.
+    #[cfg(feature = "debug-asm")]
+    pub(crate) fn inner_mut(&mut self) -> &mut E {
+        &mut self.inner
+    }
+
+    /// Returns the offset of the code emitted so far, leaving the delayed instruction pending.
+    ///
+    /// The delayed `mov`, if it is emitted at all, lands after this offset.
+    pub(crate) fn unflushed_offset(&mut self) -> E::Offset {
+        self.inner.get_offset()
+    }
+
+    /// Flushes the delayed instruction and returns the underlying emitter.
+    pub(crate) fn into_inner(mut self) -> E {
+        self.flush();
+        self.inner
+    }
+
+    fn flush(&mut self) {
+        if let Some(DelayedMov { sz, src, dst }) = self.delayed.take() {
+            self.inner.emit_mov(sz, src, dst);
+        }
+    }
+}
+
+/// Whether computing `loc` reads `reg`.
+fn reads(loc: Location, reg: GPR) -> bool {
+    match loc {
+        Location::GPR(x) | Location::Memory(x, _) => x == reg,
+        _ => false,
+    }
+}
+
+/// Whether a `mov` of size `sz` into `dst` overwrites the whole of `reg`.
+fn overwrites(sz: Size, dst: Location, reg: GPR) -> bool {
+    // 32-bit writes zero the upper half of the register.
+    dst == Location::GPR(reg) && (sz == Size::S32 || sz == Size::S64)
+}
+
+/// Whether moving `dst` back to `src` after a `mov` of size `sz` from `src` to `dst` is a no-op.
+fn is_round_trip(first: &DelayedMov, sz: Size, src: Location, dst: Location) -> bool {
+    let reg = match first.dst {
+        Location::GPR(reg) => reg,
+        _ => return false,
+    };
+    if first.sz != sz || src != first.dst || dst != first.src {
+        return false;
+    }
+    match dst {
+        // A 32-bit move back would clear the upper half of the register.
+        Location::GPR(_) => sz == Size::S64,
+        // The first move must not have changed the address.
+        Location::Memory(..) => !reads(dst, reg),
+        _ => false,
+    }
+}
+
+impl<E: Emitter> PeepholeEmitter<E> {
+    fn peephole_mov(&mut self, sz: Size, src: Location, dst: Location) {
+        // A 64-bit move of a register to itself does nothing.
+        if sz == Size::S64 && src == dst {
+            if let Location::GPR(_) = dst {
+                return;
+            }
+        }
+        if let Some(first) = self.delayed {
+            if is_round_trip(&first, sz, src, dst) {
+                return;
+            }
+            if let Location::GPR(reg) = first.dst {
+                if overwrites(sz, dst, reg) && !reads(src, reg) {
+                    // The delayed move is dead.
+                    self.delayed = None;
+                }
+            }
+        }
+        self.flush();
+        match src {
+            // Relocations point into the immediate, so it must be emitted where its offset was
+            // taken.
+            Location::Imm64(_) => self.inner.emit_mov(sz, src, dst),
+            _ => self.delayed = Some(DelayedMov { sz, src, dst }),
+        }
+    }
+}
+
+/// Implements `Emitter` methods by flushing the delayed instruction, then forwarding the call.
+macro_rules! flush_and_forward {
+    ($(fn $name:ident(&mut self $(, $arg:ident: $ty:ty)*) $(-> $ret:ty)?;)*) => {
+        $(
+            fn $name(&mut self $(, $arg: $ty)*) $(-> $ret)? {
+                self.flush();
+                self.inner.$name($($arg),*)
+            }
+        )*
+    };
+}
+
+impl<E: Emitter> Emitter for PeepholeEmitter<E> {
+    type Label = E::Label;
+    type Offset = E::Offset;
+
+    fn get_label(&mut self) -> Self::Label {
+        self.inner.get_label()
+    }
+
+    fn get_jmp_instr_size(&self) -> u8 {
+        self.inner.get_jmp_instr_size()
+    }
+
+    fn emit_mov(&mut self, sz: Size, src: Location, dst: Location) {
+        if self.enabled {
+            self.peephole_mov(sz, src, dst);
+        } else {
+            self.inner.emit_mov(sz, src, dst);
+        }
+    }
+
+    fn emit_add(&mut self, sz: Size, src: Location, dst: Location) {
+        // Adding zero does nothing either way, so it doesn't break up a pattern.
+        if self.enabled && src == Location::Imm32(0) {
+            return;
+        }
+        self.flush();
+        self.inner.emit_add(sz, src, dst);
+    }
+
+    fn emit_sub(&mut self, sz: Size, src: Location, dst: Location) {
+        if self.enabled && src == Location::Imm32(0) {
+            return;
+        }
+        self.flush();
+        self.inner.emit_sub(sz, src, dst);
+    }
+
+    fn arch_has_itruncf(&self) -> bool {
+        self.inner.arch_has_itruncf()
+    }
+
+    fn arch_has_fconverti(&self) -> bool {
+        self.inner.arch_has_fconverti()
+    }
+
+    fn arch_has_fneg(&self) -> bool {
+        self.inner.arch_has_fneg()
+    }
+
+    fn arch_supports_canonicalize_nan(&self) -> bool {
+        self.inner.arch_supports_canonicalize_nan()
+    }
+
+    fn arch_requires_indirect_call_trampoline(&self) -> bool {
+        self.inner.arch_requires_indirect_call_trampoline()
+    }
+
+    with_forwarded_emitter_methods!(flush_and_forward);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use dynasmrt::{x64::X64Relocation, VecAssembler};
+
+    type Assembler = VecAssembler<X64Relocation>;
+
+    fn emit(f: impl Fn(&mut dyn FnMut(Size, Location, Location))) -> (Vec<u8>, Vec<u8>) {
+        let mut plain = PeepholeEmitter::new(Assembler::new(0), false);
+        let mut optimized = PeepholeEmitter::new(Assembler::new(0), true);
+        f(&mut |sz, src, dst| plain.emit_mov(sz, src, dst));
+        f(&mut |sz, src, dst| optimized.emit_mov(sz, src, dst));
+        (
+            plain.into_inner().finalize().unwrap(),
+            optimized.into_inner().finalize().unwrap(),
+        )
+    }
+
+    fn movs(movs: &[(Size, Location, Location)]) -> Vec<u8> {
+        let mut a = Assembler::new(0);
+        for &(sz, src, dst) in movs {
+            a.emit_mov(sz, src, dst);
+        }
+        a.finalize().unwrap()
+    }
+
+    #[test]
+    fn test_round_trip_is_removed() {
+        let local = Location::Memory(GPR::RBP, -16);
+        let rax = Location::GPR(GPR::RAX);
+        let (plain, optimized) = emit(|mov| {
+            mov(Size::S64, local, rax);
+            mov(Size::S64, rax, local);
+        });
+        assert_eq!(
+            plain,
+            movs(&[(Size::S64, local, rax), (Size::S64, rax, local)])
+        );
+        assert_eq!(optimized, movs(&[(Size::S64, local, rax)]));
+
+        // Not when the load changed the address.
+        let indirect = Location::Memory(GPR::RAX, 8);
+        let (plain, optimized) = emit(|mov| {
+            mov(Size::S64, indirect, rax);
+            mov(Size::S64, rax, indirect);
+        });
+        assert_eq!(plain, optimized);
+
+        // Nor when moving back would clear the upper half of a register.
+        let rcx = Location::GPR(GPR::RCX);
+        let (plain, optimized) = emit(|mov| {
+            mov(Size::S32, rcx, rax);
+            mov(Size::S32, rax, rcx);
+        });
+        assert_eq!(plain, optimized);
+    }
+
+    #[test]
+    fn test_dead_mov_is_removed() {
+        let rax = Location::GPR(GPR::RAX);
+        let (_, optimized) = emit(|mov| {
+            mov(Size::S64, Location::Memory(GPR::RBP, -16), rax);
+            mov(Size::S64, Location::Memory(GPR::RBP, -24), rax);
+        });
+        assert_eq!(
+            optimized,
+            movs(&[(Size::S64, Location::Memory(GPR::RBP, -24), rax)])
+        );
+
+        // Not when the second move reads the register.
+        let (plain, optimized) = emit(|mov| {
+            mov(Size::S64, Location::Memory(GPR::RBP, -16), rax);
+            mov(Size::S64, Location::Memory(GPR::RAX, 0), rax);
+        });
+        assert_eq!(plain, optimized);
+    }
+
+    #[test]
+    fn test_mov_to_self_is_removed() {
+        let (_, optimized) = emit(|mov| {
+            mov(Size::S64, Location::GPR(GPR::RSI), Location::GPR(GPR::RSI));
+        });
+        assert!(optimized.is_empty());
+
+        // A 32-bit move clears the upper half.
+        let (plain, optimized) = emit(|mov| {
+            mov(Size::S32, Location::GPR(GPR::RSI), Location::GPR(GPR::RSI));
+        });
+        assert_eq!(plain, optimized);
+    }
+
+    #[test]
+    fn test_labels_and_offsets_flush_delayed_mov() {
+        let local = Location::Memory(GPR::RBP, -16);
+        let rax = Location::GPR(GPR::RAX);
+        let round_trip = movs(&[(Size::S64, local, rax), (Size::S64, rax, local)]);
+
+        // A branch may land between the two moves.
+        let mut a = PeepholeEmitter::new(Assembler::new(0), true);
+        let label = a.get_label();
+        a.emit_mov(Size::S64, local, rax);
+        a.emit_label(label);
+        a.emit_mov(Size::S64, rax, local);
+        assert_eq!(a.into_inner().finalize().unwrap(), round_trip);
+
+        // The offset is recorded to refer to the code that follows.
+        let mut a = PeepholeEmitter::new(Assembler::new(0), true);
+        a.emit_mov(Size::S64, local, rax);
+        assert_eq!(a.get_offset().0, movs(&[(Size::S64, local, rax)]).len());
+        a.emit_mov(Size::S64, rax, local);
+        assert_eq!(a.into_inner().finalize().unwrap(), round_trip);
+    }
+}
@@ -0,0 +1,47 @@
+//! Unwind information for the functions emitted by singlepass.
+//!
+//! `Machine` records how the prologue and the epilogue change the frame as a list of `UnwindOp`s,
+//! each taking effect at the end of the instruction it follows. They are then turned into a DWARF
+//! FDE for the `.eh_frame` section on System V (see `dwarf`), or into a Windows x64 `UNWIND_INFO`
+//! (see `unwind_winx64`), so that debuggers and profilers can walk the native stack through wasm
+//! frames.
+//!
+//! Most functions address their frame through RBP. The leaf functions compiled without a frame
+//! pointer push the callee-saved registers they use instead, and their frame is only described
+//! relative to RSP, which stays put in their body.
+
+use crate::x64_decl::{X64Register, GPR};
+
+/// A change made to the frame of a function.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum UnwindOp {
+    /// `push rbp`: the caller's RBP is saved right below the return address.
+    PushFramePointer,
+    /// `mov rbp, rsp`: the frame is now addressed through RBP, 16 bytes below the frame's start.
+    DefineFramePointer,
+    /// A callee-saved register was stored at `[rbp - bp_neg_offset]`.
+    SaveRegister {
+        reg: X64Register,
+        bp_neg_offset: u32,
+    },
+    /// `pop rbp` in the epilogue: only the return address is left on the frame.
+    PopFramePointer,
+    /// `push reg` of a callee-saved register, in a function without a frame pointer.
+    PushRegister { reg: GPR },
+    /// `sub rsp, size` in a function without a frame pointer.
+    AllocateStack { size: u32 },
+    /// `add rsp, size` in the epilogue of a function without a frame pointer.
+    FreeStack { size: u32 },
+    /// `pop reg` in the epilogue of a function without a frame pointer: `reg` holds the value
+    /// of the caller again.
+    PopRegister { reg: GPR },
+    /// `ret` from the epilogue: code placed after it still runs in the complete frame.
+    Return,
+}
+
+/// Unwind information that is kept out of the `FunctionBody` of a function.
+pub(crate) enum UnwindFrame {
+    /// The frame changes of a System V function, to be described in the `.eh_frame` section of
+    /// the module.
+    SystemV(Vec<(usize, UnwindOp)>),
+}
@@ -0,0 +1,198 @@
+//! Encoding of the Windows x64 `UNWIND_INFO` of a function.
+//!
+//! See <https://docs.microsoft.com/en-us/cpp/build/exception-handling-x64> for the format.
+
+use crate::unwind::UnwindOp;
+use crate::x64_decl::{X64Register, GPR};
+use std::convert::TryFrom;
+
+const UWOP_PUSH_NONVOL: u8 = 0;
+const UWOP_ALLOC_LARGE: u8 = 1;
+const UWOP_ALLOC_SMALL: u8 = 2;
+const UWOP_SET_FPREG: u8 = 3;
+const UWOP_SAVE_NONVOL: u8 = 4;
+const UWOP_SAVE_XMM128_FAR: u8 = 9;
+
+/// The largest offset from the frame register to the frame pointer that can be encoded.
+const MAX_FRAME_OFFSET: u32 = 15 * 16;
+
+/// An unwind code, along with the slots holding its operand.
+struct UnwindCode {
+    code_offset: u8,
+    op: u8,
+    info: u8,
+    operands: Vec<u16>,
+}
+
+/// Encodes the prologue described by `ops` as an `UNWIND_INFO`, or returns `None` if it does not
+/// fit in one.
+///
+/// The unwinder expects the registers saved with a `mov` to be above the frame pointer, but
+/// singlepass stores them below RBP. The frame pointer is thus declared to be right below the
+/// save area, as if the prologue allocated the save area before setting RBP to the end of it,
+/// which leads the unwinder to the same saved RBP and return address.
+///
+/// The frame of a function without a frame pointer is only made of the registers it pushes and
+/// the stack it allocates, and it has no frame register.
+pub(crate) fn create_unwind_info(ops: &[(usize, UnwindOp)]) -> Option<Vec<u8>> {
+    let save_area_size = ops
+        .iter()
+        .filter_map(|(_, op)| match op {
+            UnwindOp::SaveRegister { bp_neg_offset, .. } => Some(*bp_neg_offset),
+            _ => None,
+        })
+        .max()
+        .unwrap_or(0);
+    let frame_offset = (save_area_size + 15) & !15;
+    if frame_offset > MAX_FRAME_OFFSET {
+        return None;
+    }
+
+    let mut codes = Vec::new();
+    let mut prologue_size = 0;
+    for &(offset, op) in ops {
+        let code_offset = u8::try_from(offset).ok()?;
+        let code = |op, info, operands| UnwindCode {
+            code_offset,
+            op,
+            info,
+            operands,
+        };
+        match op {
+            UnwindOp::PushFramePointer => {
+                codes.push(code(UWOP_PUSH_NONVOL, GPR::RBP as u8, vec![]));
+            }
+            UnwindOp::PushRegister { reg } => {
+                codes.push(code(UWOP_PUSH_NONVOL, reg as u8, vec![]));
+            }
+            UnwindOp::AllocateStack { size } => {
+                codes.push(match size {
+                    8..=128 => code(UWOP_ALLOC_SMALL, (size / 8 - 1) as u8, vec![]),
+                    _ => code(UWOP_ALLOC_LARGE, 0, vec![u16::try_from(size / 8).ok()?]),
+                });
+            }
+            UnwindOp::DefineFramePointer => {
+                if frame_offset > 0 {
+                    codes.push(match frame_offset {
+                        8..=128 => code(UWOP_ALLOC_SMALL, (frame_offset / 8 - 1) as u8, vec![]),
+                        _ => code(UWOP_ALLOC_LARGE, 0, vec![(frame_offset / 8) as u16]),
+                    });
+                }
+                codes.push(code(UWOP_SET_FPREG, 0, vec![]));
+            }
+            UnwindOp::SaveRegister { reg, bp_neg_offset } => {
+                let stack_offset = frame_offset - bp_neg_offset;
+                codes.push(match reg {
+                    X64Register::GPR(gpr) => {
+                        code(UWOP_SAVE_NONVOL, gpr as u8, vec![(stack_offset / 8) as u16])
+                    }
+                    // The save area is only 8-byte aligned, so the offset is stored unscaled.
+                    X64Register::XMM(xmm) => code(
+                        UWOP_SAVE_XMM128_FAR,
+                        xmm as u8,
+                        vec![stack_offset as u16, (stack_offset >> 16) as u16],
+                    ),
+                });
+            }
+            // Epilogues are recognized from the code itself.
+            UnwindOp::PopFramePointer
+            | UnwindOp::FreeStack { .. }
+            | UnwindOp::PopRegister { .. }
+            | UnwindOp::Return => break,
+        }
+        prologue_size = code_offset;
+    }
+
+    let slots = codes.iter().map(|c| 1 + c.operands.len()).sum::<usize>();
+    let slot_count = u8::try_from(slots).ok()?;
+    let mut info = Vec::with_capacity(4 + 2 * (slots + 1));
+    // Version 1, no flags.
+    info.push(1);
+    info.push(prologue_size);
+    info.push(slot_count);
+    let framed = ops
+        .iter()
+        .any(|(_, op)| *op == UnwindOp::DefineFramePointer);
+    info.push(if framed {
+        GPR::RBP as u8 | ((frame_offset / 16) as u8) << 4
+    } else {
+        0
+    });
+    // The unwinder goes through the codes from the end of the prologue to its start.
+    for code in codes.iter().rev() {
+        info.push(code.code_offset);
+        info.push(code.op | code.info << 4);
+        for operand in &code.operands {
+            info.extend_from_slice(&operand.to_le_bytes());
+        }
+    }
+    // The code array always has an even number of slots.
+    if slots % 2 == 1 {
+        info.extend_from_slice(&[0, 0]);
+    }
+    Some(info)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::x64_decl::XMM;
+
+    #[test]
+    fn test_unwind_info_describes_saves_below_rbp() {
+        let ops = [
+            (1, UnwindOp::PushFramePointer),
+            (4, UnwindOp::DefineFramePointer),
+            (
+                15,
+                UnwindOp::SaveRegister {
+                    reg: X64Register::GPR(GPR::R15),
+                    bp_neg_offset: 8,
+                },
+            ),
+            (
+                22,
+                UnwindOp::SaveRegister {
+                    reg: X64Register::XMM(XMM::XMM12),
+                    bp_neg_offset: 24,
+                },
+            ),
+            (90, UnwindOp::PopFramePointer),
+            (91, UnwindOp::Return),
+        ];
+        let info = create_unwind_info(&ops).unwrap();
+        #[rustfmt::skip]
+        assert_eq!(
+            info,
+            [
+                // Version, prologue size, slots, RBP with a frame offset of 32.
+                0x01, 22, 8, 0x25,
+                // XMM12 at 8 above the frame pointer.
+                22, 0xc9, 8, 0, 0, 0,
+                // R15 at 24 above the frame pointer.
+                15, 0xf4, 3, 0,
+                // RBP set 32 bytes above RSP, after allocating those 32 bytes.
+                4, 0x03,
+                4, 0x32,
+                // The caller's RBP.
+                1, 0x50,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unwind_info_rejects_large_save_area() {
+        let ops = [
+            (1, UnwindOp::PushFramePointer),
+            (4, UnwindOp::DefineFramePointer),
+            (
+                12,
+                UnwindOp::SaveRegister {
+                    reg: X64Register::XMM(XMM::XMM15),
+                    bp_neg_offset: MAX_FRAME_OFFSET + 16,
+                },
+            ),
+        ];
+        assert_eq!(create_unwind_info(&ops), None);
+    }
+}
@@ -5,7 +5,7 @@ use wasmer_types::Type;
 /// General-purpose registers.
 #[repr(u8)]
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
-pub(crate) enum GPR {
+pub enum GPR {
     /// RAX register
     RAX,
     /// RCX register
@@ -121,6 +121,25 @@ impl X64Register {
         })
     }
 
+    /// Converts X64Register to a DWARF regnum.
+    pub(crate) fn to_dwarf_regnum(self) -> u16 {
+        match self {
+            X64Register::GPR(gpr) => match gpr {
+                GPR::RAX => 0,
+                GPR::RDX => 1,
+                GPR::RCX => 2,
+                GPR::RBX => 3,
+                GPR::RSI => 4,
+                GPR::RDI => 5,
+                GPR::RBP => 6,
+                GPR::RSP => 7,
+                // R8 to R15 have the same number in DWARF as in instruction encodings.
+                _ => gpr as u16,
+            },
+            X64Register::XMM(xmm) => 17 + xmm as u16,
+        }
+    }
+
     /// Returns the instruction prefix for `movq %this_reg, ?(%rsp)`.
     ///
     /// To build an instruction, append the memory location as a 32-bit
@@ -171,7 +190,7 @@ impl ArgumentRegisterAllocator {
                 static XMM_SEQ: &'static [XMM] = &[XMM::XMM0, XMM::XMM1, XMM::XMM2, XMM::XMM3];
                 let idx = self.n_gprs + self.n_xmms;
                 match ty {
-                    Type::I32 | Type::I64 => {
+                    Type::I32 | Type::I64 | Type::ExternRef | Type::FuncRef => {
                         if idx < 4 {
                             let gpr = GPR_SEQ[idx];
                             self.n_gprs += 1;
@@ -209,7 +228,7 @@ impl ArgumentRegisterAllocator {
                     XMM::XMM7,
                 ];
                 match ty {
-                    Type::I32 | Type::I64 => {
+                    Type::I32 | Type::I64 | Type::ExternRef | Type::FuncRef => {
                         if self.n_gprs < GPR_SEQ.len() {
                             let gpr = GPR_SEQ[self.n_gprs];
                             self.n_gprs += 1;
@@ -2,16 +2,19 @@
 //! compilers will need to implement.
 
 use crate::error::CompileError;
-use crate::function::Compilation;
+use crate::function::{Compilation, CompiledFunction};
 use crate::lib::std::boxed::Box;
+use crate::lib::std::sync::Arc;
 use crate::module::CompileModuleInfo;
+use crate::progress::CompileProgress;
+use crate::section::CustomSection;
 use crate::target::Target;
+use crate::translator::{validate_module_with, ModuleMiddleware};
 use crate::FunctionBodyData;
 use crate::ModuleTranslationState;
 use crate::SectionIndex;
 use wasmer_types::entity::PrimaryMap;
 use wasmer_types::{Features, FunctionIndex, LocalFunctionIndex, SignatureIndex};
-use wasmparser::{Validator, WasmFeatures};
 
 /// The compiler configuration options.
 pub trait CompilerConfig {
@@ -76,34 +79,41 @@ pub trait Compiler: Send {
     /// Validates a module.
     ///
     /// It returns the a succesful Result in case is valid, `CompileError` in case is not.
+    ///
+    /// The errors in the body of a function are [`CompileError::Function`]s locating them.
     fn validate_module<'data>(
         &self,
         features: &Features,
         data: &'data [u8],
     ) -> Result<(), CompileError> {
-        let mut validator = Validator::new();
-        let wasm_features = WasmFeatures {
-            bulk_memory: features.bulk_memory,
-            threads: features.threads,
-            reference_types: features.reference_types,
-            multi_value: features.multi_value,
-            simd: features.simd,
-            tail_call: features.tail_call,
-            module_linking: features.module_linking,
-            multi_memory: features.multi_memory,
-            memory64: features.memory64,
-            exceptions: features.exceptions,
-            deterministic_only: false,
-        };
-        validator.wasm_features(wasm_features);
-        validator
-            .validate_all(data)
-            .map_err(|e| CompileError::Validate(format!("{}", e)))?;
+        validate_module_with(features, data, &mut ())
+    }
+
+    /// Checks that the compiler supports every Wasm feature enabled in `features`.
+    ///
+    /// Engines check their features before validating or compiling a module, so that enabling
+    /// a feature the compiler doesn't support fails with
+    /// [`CompileError::FeatureNotSupportedByCompiler`] even for the modules not using it.
+    fn check_features(&self, _features: &Features) -> Result<(), CompileError> {
         Ok(())
     }
 
+    /// Identifies the compiler, its version and the settings changing the code it generates.
+    ///
+    /// Engines refuse to load executables compiled by a compiler with another fingerprint.
+    fn fingerprint(&self) -> String;
+
+    /// The middlewares transforming the modules before they are compiled, in the order
+    /// they run.
+    fn get_middlewares(&self) -> &[Arc<dyn ModuleMiddleware>] {
+        &[]
+    }
+
     /// Compiles a parsed module.
     ///
+    /// Each compiled function is reported to `progress`, and the compilation stops with
+    /// [`CompileError::Cancelled`] between two functions if it is cancelled.
+    ///
     /// It returns the [`Compilation`] or a [`CompileError`].
     fn compile_module<'data, 'module>(
         &self,
@@ -112,8 +122,32 @@ pub trait Compiler: Send {
         module_translation: &ModuleTranslationState,
         // The list of function bodies
         function_body_inputs: PrimaryMap<LocalFunctionIndex, FunctionBodyData<'data>>,
+        progress: &CompileProgress,
     ) -> Result<Compilation, CompileError>;
 
+    /// Compiles a single function of a parsed module, for the engines compiling the functions
+    /// the first time they are called.
+    ///
+    /// The relocations of the function are those it has in the [`Compilation`] of
+    /// `compile_module`, whose custom sections and trampolines the engine keeps using. The
+    /// function comes with the `.eh_frame` section describing its frame if the target uses one,
+    /// whose relocations refer to the function as `index`.
+    ///
+    /// Compilers that can only compile whole modules return
+    /// [`CompileError::UnsupportedFeature`].
+    fn compile_function(
+        &self,
+        _target: &Target,
+        _module: &CompileModuleInfo,
+        _module_translation: &ModuleTranslationState,
+        _index: LocalFunctionIndex,
+        _input: &FunctionBodyData<'_>,
+    ) -> Result<(CompiledFunction, Option<CustomSection>), CompileError> {
+        Err(CompileError::UnsupportedFeature(
+            "compiling the functions of a module one at a time".to_string(),
+        ))
+    }
+
     /// Compiles a module into a native object file.
     ///
     /// It returns the bytes as a `&[u8]` or a [`CompileError`].
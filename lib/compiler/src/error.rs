@@ -1,6 +1,10 @@
+use crate::lib::std::boxed::Box;
+use crate::lib::std::fmt;
 use crate::lib::std::string::String;
+use crate::lib::std::vec::Vec;
 #[cfg(feature = "std")]
 use thiserror::Error;
+use wasmer_types::{FunctionIndex, LocalFunctionIndex};
 
 // Compilation Errors
 //
@@ -24,14 +28,74 @@ pub enum CompileError {
     #[cfg_attr(feature = "std", error("Compilation error: {0}"))]
     Codegen(String),
 
+    /// A function has more locals, its params included, than the compiler was configured to
+    /// accept.
+    #[cfg_attr(
+        feature = "std",
+        error("Compilation error: function has {count} locals, more than the limit of {max}")
+    )]
+    CodegenTooManyLocals {
+        /// The number of locals of the function, or the number at which counting stopped.
+        count: u64,
+        /// The maximum number of locals.
+        max: u32,
+    },
+
+    /// The machine code generated for the module is larger than the compiler was configured
+    /// to accept.
+    #[cfg_attr(
+        feature = "std",
+        error(
+            "Compilation error: function {} brings the code to {size} bytes, more than the \
+             limit of {limit}",
+            .function.as_u32()
+        )
+    )]
+    CodeSizeExceeded {
+        /// The function whose code exceeded the limit.
+        function: LocalFunctionIndex,
+        /// The size of the code of the function for a limit on the size of each function, or
+        /// the size of the code compiled so far, this function included, for a limit on the
+        /// total size.
+        size: usize,
+        /// The limit that was exceeded.
+        limit: usize,
+    },
+
     /// The module did not pass validation.
     #[cfg_attr(feature = "std", error("Validation error: {0}"))]
     Validate(String),
 
+    /// A function did not pass validation, or one of its operators could not be compiled.
+    #[cfg_attr(feature = "std", error("{location}: {error}"))]
+    Function {
+        /// Where in the module the error happened.
+        location: FunctionLocation,
+        /// The error.
+        #[cfg_attr(feature = "std", source)]
+        error: Box<CompileError>,
+    },
+
     /// The compiler doesn't support a Wasm feature
     #[cfg_attr(feature = "std", error("Feature {0} is not yet supported"))]
     UnsupportedFeature(String),
 
+    /// The compiler doesn't support the Wasm features used by the module, as found by checking
+    /// the whole module.
+    #[cfg_attr(
+        feature = "std",
+        error("Features are not yet supported: {}", display_unsupported_items(.0))
+    )]
+    UnsupportedFeatures(Vec<UnsupportedItem>),
+
+    /// A Wasm feature enabled in the engine is not supported by its compiler, whether the
+    /// module uses it or not.
+    #[cfg_attr(
+        feature = "std",
+        error("Feature {0} is enabled but not supported by the compiler")
+    )]
+    FeatureNotSupportedByCompiler(String),
+
     /// The compiler cannot compile for the given target.
     /// This can refer to the OS, the chipset or any other aspect of the target system.
     #[cfg_attr(feature = "std", error("The target {0} is not yet supported (see https://docs.wasmer.io/ecosystem/wasmer/wasmer-features)"))]
@@ -41,6 +105,10 @@ pub enum CompileError {
     #[cfg_attr(feature = "std", error("Insufficient resources: {0}"))]
     Resource(String),
 
+    /// The compilation was cancelled through a `CancellationToken`.
+    #[cfg_attr(feature = "std", error("Compilation was cancelled"))]
+    Cancelled,
+
     /// Cannot downcast the engine to a specific type.
     #[cfg_attr(
         feature = "std",
@@ -49,6 +117,94 @@ pub enum CompileError {
     EngineDowncast,
 }
 
+impl CompileError {
+    /// The error without the location of the function it happened in, if any.
+    pub fn without_location(&self) -> &Self {
+        match self {
+            Self::Function { error, .. } => error.without_location(),
+            error => error,
+        }
+    }
+
+    /// The location of the function the error happened in, if it is known.
+    pub fn location(&self) -> Option<&FunctionLocation> {
+        match self {
+            Self::Function { location, .. } => Some(location),
+            _ => None,
+        }
+    }
+}
+
+/// Where in a module an error compiling a function happened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionLocation {
+    /// The index of the function among the functions the module defines.
+    pub local_index: LocalFunctionIndex,
+    /// The index of the function in the module, the imported functions included.
+    pub index: FunctionIndex,
+    /// The name the name section of the module gives the function, if it is known.
+    pub name: Option<String>,
+    /// The offset in the module binary of the operator the error is about, or of the body of
+    /// the function when the error is about none of its operators.
+    pub offset: usize,
+    /// The name of the operator the error is about, such as `V128Load`.
+    pub operator: Option<String>,
+}
+
+impl fmt::Display for FunctionLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "function {}", self.index.as_u32())?;
+        if let Some(name) = &self.name {
+            write!(f, " ('{}')", name)?;
+        }
+        write!(f, " at offset {:#x}", self.offset)
+    }
+}
+
+/// A Wasm feature the compiler doesn't support, and the places the module uses it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnsupportedItem {
+    /// The name of the operator, such as `F32x4Add`, or a description of the feature, such as
+    /// `multiple memories`.
+    pub feature: String,
+    /// The places the module uses the feature, in the order of the module.
+    pub uses: Vec<UnsupportedUse>,
+}
+
+/// A place a module uses a feature the compiler doesn't support.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UnsupportedUse {
+    /// An operator of a function.
+    Function(FunctionLocation),
+    /// A declaration of a section, at this offset in the module binary.
+    Section(usize),
+}
+
+impl fmt::Display for UnsupportedItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (", self.feature)?;
+        for (i, use_) in self.uses.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            match use_ {
+                UnsupportedUse::Function(location) => write!(f, "{}", location)?,
+                UnsupportedUse::Section(offset) => write!(f, "at offset {:#x}", offset)?,
+            }
+        }
+        write!(f, ")")
+    }
+}
+
+#[cfg(feature = "std")]
+fn display_unsupported_items(items: &[UnsupportedItem]) -> String {
+    items
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
 impl From<WasmError> for CompileError {
     fn from(original: WasmError) -> Self {
         Self::Wasm(original)
@@ -4,11 +4,14 @@
 //! A `Compilation` contains the compiled function bodies for a WebAssembly
 //! module (`CompiledFunction`).
 
+use crate::lib::std::fmt;
+use crate::lib::std::mem;
+use crate::lib::std::string::String;
 use crate::lib::std::vec::Vec;
 use crate::section::{CustomSection, SectionIndex};
 use crate::trap::TrapInformation;
 use crate::{
-    CompiledFunctionUnwindInfo, CompiledFunctionUnwindInfoRef, FunctionAddressMap,
+    CompileTiming, CompiledFunctionUnwindInfo, CompiledFunctionUnwindInfoRef, FunctionAddressMap,
     JumpTableOffsets, Relocation,
 };
 use wasmer_types::entity::PrimaryMap;
@@ -29,6 +32,43 @@ pub struct CompiledFunctionFrameInfo {
 
     /// The address map.
     pub address_map: FunctionAddressMap,
+
+    /// The layout of the stack frame, for the compilers that describe it and the functions
+    /// that have a frame pointer.
+    pub frame_layout: Option<FrameLayout>,
+
+    /// The landing pads of the calls whose exceptions the function catches.
+    ///
+    /// Return offsets MUST be in ascending order.
+    pub landing_pads: Vec<LandingPad>,
+}
+
+/// Where the exceptions thrown by a call of a function land in it.
+#[derive(rkyv::Serialize, rkyv::Deserialize, rkyv::Archive, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LandingPad {
+    /// The offset of the return address of the call in the function body.
+    pub return_offset: u32,
+    /// The offset of the landing pad in the function body, which expects the exception in RAX
+    /// and the frame pointer of the function in RBP.
+    pub handler_offset: u32,
+}
+
+/// The layout of the stack frame of a compiled function.
+///
+/// This lets embedders walking wasm stacks find the saved callee-saved registers and the locals
+/// of each frame. Offsets are in bytes below the frame pointer.
+#[derive(
+    rkyv::Serialize, rkyv::Deserialize, rkyv::Archive, Debug, Clone, Copy, PartialEq, Eq, Default,
+)]
+pub struct FrameLayout {
+    /// The offset of the first local stored on the stack.
+    pub locals_offset: u64,
+    /// The offset of the end of the area holding the callee-saved registers.
+    pub save_area_offset: u64,
+    /// The number of locals held in registers rather than on the stack.
+    pub register_locals: u32,
+    /// Whether RDI and RSI were saved, as required by the Windows calling convention.
+    pub saved_rdi_rsi: bool,
 }
 
 /// The function body.
@@ -86,11 +126,80 @@ pub struct CompiledFunction {
 
     /// The frame information.
     pub frame_info: CompiledFunctionFrameInfo,
+
+    /// The listing of the emitted instructions, if it was recorded.
+    pub debug_asm: Option<FunctionAsm>,
+}
+
+/// A line of a [`FunctionAsm`] listing.
+#[derive(rkyv::Serialize, rkyv::Deserialize, rkyv::Archive, Debug, Clone, PartialEq, Eq)]
+pub enum AsmLine {
+    /// The start of the code lowering a wasm operator.
+    Operator(String),
+    /// An instruction, with its mnemonic and operands.
+    Instruction {
+        /// The offset of the instruction in the function body.
+        offset: u32,
+        /// The text of the instruction.
+        text: String,
+    },
+}
+
+/// The instructions emitted for a function, interleaved with the wasm operators they lower.
+///
+/// This is only recorded by the compilers that support it, when asked to. It is meant for
+/// debugging the code generator, and its format is not stable.
+#[derive(
+    rkyv::Serialize, rkyv::Deserialize, rkyv::Archive, Debug, Clone, PartialEq, Eq, Default,
+)]
+pub struct FunctionAsm {
+    /// The lines of the listing, in emission order.
+    pub lines: Vec<AsmLine>,
+}
+
+impl fmt::Display for FunctionAsm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in &self.lines {
+            match line {
+                AsmLine::Operator(operator) => writeln!(f, ";; {}", operator)?,
+                AsmLine::Instruction { offset, text } => writeln!(f, "{:#06x}: {}", offset, text)?,
+            }
+        }
+        Ok(())
+    }
 }
 
 /// The compiled functions map (index in the Wasm -> function)
 pub type Functions = PrimaryMap<LocalFunctionIndex, CompiledFunction>;
 
+/// Register allocation statistics collected while compiling a single function.
+///
+/// These are only gathered by the compilers that track them, and only when
+/// [`CompileModuleInfo::collect_function_stats`](crate::CompileModuleInfo) is
+/// set. They are intended for diagnosing code that spills excessively.
+#[derive(
+    rkyv::Serialize, rkyv::Deserialize, rkyv::Archive, Debug, Clone, Copy, PartialEq, Eq, Default,
+)]
+pub struct MachineStats {
+    /// Number of times a general purpose register was picked for a value.
+    pub gpr_picks: u64,
+    /// Number of times an XMM register was picked for a value.
+    pub xmm_picks: u64,
+    /// Number of values that had to be placed in a stack slot because no
+    /// register was available.
+    pub stack_spills: u64,
+    /// The largest stack offset (in bytes) reached by the function.
+    pub max_stack_offset: u64,
+    /// Number of times a temporary register was requested but none was free.
+    pub temp_acquisition_failures: u64,
+    /// Number of times a temporary register in use had to be spilled to
+    /// satisfy another request.
+    pub temp_gpr_steals: u64,
+}
+
+/// The per-function statistics map (index in the Wasm -> stats)
+pub type FunctionStats = PrimaryMap<LocalFunctionIndex, MachineStats>;
+
 /// The custom sections for a Compilation.
 pub type CustomSections = PrimaryMap<SectionIndex, CustomSection>;
 
@@ -185,6 +294,12 @@ pub struct Compilation {
 
     /// Trampolines for the arch that needs it
     trampolines: Option<TrampolinesSection>,
+
+    /// Register allocation statistics, if they were requested.
+    function_stats: Option<FunctionStats>,
+
+    /// The time the compiler spent in each phase.
+    timing: CompileTiming,
 }
 
 impl Compilation {
@@ -204,9 +319,24 @@ impl Compilation {
             dynamic_function_trampolines,
             debug,
             trampolines,
+            function_stats: None,
+            timing: CompileTiming::default(),
         }
     }
 
+    /// Attaches per-function register allocation statistics to this compilation.
+    pub fn with_function_stats(mut self, function_stats: FunctionStats) -> Self {
+        self.function_stats = Some(function_stats);
+        self
+    }
+
+    /// Attaches the time the compiler spent compiling the functions and generating the
+    /// trampolines to this compilation.
+    pub fn with_timing(mut self, timing: CompileTiming) -> Self {
+        self.timing = timing;
+        self
+    }
+
     /// Gets the bytes of a single function
     pub fn get(&self, func: LocalFunctionIndex) -> &CompiledFunction {
         &self.functions[func]
@@ -222,6 +352,14 @@ impl Compilation {
         self.functions.is_empty()
     }
 
+    /// Takes the compiled functions out of the compilation, which is left without any.
+    ///
+    /// Unlike the getters, this moves the code, relocations and frame info of the functions
+    /// rather than copying them.
+    pub fn take_functions(&mut self) -> Functions {
+        mem::take(&mut self.functions)
+    }
+
     /// Gets functions relocations.
     pub fn get_relocations(&self) -> PrimaryMap<LocalFunctionIndex, Vec<Relocation>> {
         self.functions
@@ -286,6 +424,26 @@ impl Compilation {
     pub fn get_trampolines(&self) -> Option<TrampolinesSection> {
         self.trampolines.clone()
     }
+
+    /// Returns the per-function register allocation statistics, if collected.
+    pub fn get_function_stats(&self) -> Option<FunctionStats> {
+        self.function_stats.clone()
+    }
+
+    /// Returns the time the compiler spent in each phase, which is zero for the phases it
+    /// didn't time.
+    pub fn get_timing(&self) -> CompileTiming {
+        self.timing
+    }
+
+    /// Returns the listings of the instructions emitted for the functions, if all of them were
+    /// recorded.
+    pub fn get_function_asm(&self) -> Option<PrimaryMap<LocalFunctionIndex, FunctionAsm>> {
+        self.functions
+            .iter()
+            .map(|(_, func)| func.debug_asm.clone())
+            .collect()
+    }
 }
 
 impl<'a> IntoIterator for &'a Compilation {
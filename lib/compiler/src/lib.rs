@@ -39,13 +39,13 @@ mod lib {
     #[cfg(feature = "core")]
     pub mod std {
         pub use alloc::{borrow, boxed, str, string, sync, vec};
-        pub use core::fmt;
+        pub use core::{fmt, mem};
         pub use hashbrown as collections;
     }
 
     #[cfg(feature = "std")]
     pub mod std {
-        pub use std::{borrow, boxed, collections, fmt, str, string, sync, vec};
+        pub use std::{borrow, boxed, collections, fmt, mem, str, string, sync, vec};
     }
 }
 
@@ -56,6 +56,7 @@ mod error;
 mod function;
 mod jump_table;
 mod module;
+mod progress;
 mod relocation;
 mod target;
 mod trap;
@@ -70,14 +71,17 @@ pub use crate::address_map::{FunctionAddressMap, InstructionAddressMap};
 #[cfg(feature = "translator")]
 pub use crate::compiler::{Compiler, CompilerConfig, Symbol, SymbolRegistry};
 pub use crate::error::{
-    CompileError, MiddlewareError, ParseCpuFeatureError, WasmError, WasmResult,
+    CompileError, FunctionLocation, MiddlewareError, ParseCpuFeatureError, UnsupportedItem,
+    UnsupportedUse, WasmError, WasmResult,
 };
 pub use crate::function::{
-    Compilation, CompiledFunction, CompiledFunctionFrameInfo, CustomSections, Dwarf, FunctionBody,
-    FunctionBodyRef, Functions, TrampolinesSection,
+    AsmLine, Compilation, CompiledFunction, CompiledFunctionFrameInfo, CustomSections, Dwarf,
+    FrameLayout, FunctionAsm, FunctionBody, FunctionBodyRef, FunctionStats, Functions, LandingPad,
+    MachineStats, TrampolinesSection,
 };
 pub use crate::jump_table::{JumpTable, JumpTableOffsets};
 pub use crate::module::CompileModuleInfo;
+pub use crate::progress::{CancellationToken, CompileProgress, CompileTiming};
 pub use crate::relocation::{Relocation, RelocationKind, RelocationTarget, Relocations};
 pub use crate::section::{
     CustomSection, CustomSectionProtection, CustomSectionRef, SectionBody, SectionIndex,
@@ -89,10 +93,12 @@ pub use crate::target::{
 };
 #[cfg(feature = "translator")]
 pub use crate::translator::{
-    translate_module, wptype_to_type, FunctionBodyData, FunctionReader, ModuleEnvironment,
-    ModuleTranslationState,
+    operator_name, translate_module, validate_module_with, wasmparser_features, wptype_to_type,
+    FunctionBodyData, FunctionMiddleware, FunctionReader, MiddlewareBinaryReader,
+    MiddlewareReaderState, ModuleEnvironment, ModuleMiddleware, ModuleMiddlewareChain,
+    ModuleResources, ModuleTranslationState, ValidatedFunction, ValidationVisitor,
 };
-pub use crate::trap::TrapInformation;
+pub use crate::trap::{MemoryAccess, TrapInformation};
 pub use crate::unwind::{CompiledFunctionUnwindInfo, CompiledFunctionUnwindInfoRef};
 
 pub use wasmer_types::Features;
@@ -8,7 +8,7 @@ use wasmer_vm::{MemoryStyle, TableStyle};
 /// This differs from [`ModuleInfo`] because it have extra info only
 /// possible after translation (such as the features used for compiling,
 /// or the `MemoryStyle` and `TableStyle`).
-#[derive(Debug, PartialEq, Eq, rkyv::Serialize, rkyv::Deserialize, rkyv::Archive)]
+#[derive(Debug, Clone, PartialEq, Eq, rkyv::Serialize, rkyv::Deserialize, rkyv::Archive)]
 pub struct CompileModuleInfo {
     /// The features used for compiling the module
     pub features: Features,
@@ -21,4 +21,7 @@ pub struct CompileModuleInfo {
     pub memory_styles: PrimaryMap<MemoryIndex, MemoryStyle>,
     /// The table plans used for compiling.
     pub table_styles: PrimaryMap<TableIndex, TableStyle>,
+    /// Whether the compiler should collect per-function register allocation
+    /// statistics (see [`MachineStats`](crate::MachineStats)).
+    pub collect_function_stats: bool,
 }
@@ -0,0 +1,138 @@
+//! Reporting the progress of the compilation of a module, timing it, and cancelling it.
+
+use crate::lib::std::fmt;
+use crate::lib::std::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+use wasmer_types::LocalFunctionIndex;
+
+type FunctionCompiledCallback = dyn Fn(LocalFunctionIndex, usize, Duration) + Send + Sync;
+
+/// Hooks observing the compilation of a module, function by function.
+///
+/// Compilers may compile several functions in parallel, so the callback can be invoked from
+/// several threads at once, and in any order of the functions.
+#[derive(Clone, Default)]
+pub struct CompileProgress {
+    on_function_compiled: Option<Arc<FunctionCompiledCallback>>,
+    cancellation: Option<CancellationToken>,
+}
+
+impl CompileProgress {
+    /// Creates hooks that observe nothing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Calls `callback` after each function is compiled, with the index of the function, the
+    /// size of its code in bytes and the time it took to compile.
+    pub fn on_function_compiled(
+        mut self,
+        callback: impl Fn(LocalFunctionIndex, usize, Duration) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_function_compiled = Some(Arc::new(callback));
+        self
+    }
+
+    /// Stops the compilation with `CompileError::Cancelled` once `token` is cancelled.
+    ///
+    /// The token is checked between functions.
+    pub fn cancellation_token(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// Whether the compilation should stop.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation
+            .as_ref()
+            .map_or(false, CancellationToken::is_cancelled)
+    }
+
+    /// Reports that a function was compiled.
+    pub fn function_compiled(
+        &self,
+        index: LocalFunctionIndex,
+        code_size: usize,
+        elapsed: Duration,
+    ) {
+        if let Some(callback) = &self.on_function_compiled {
+            callback(index, code_size, elapsed);
+        }
+    }
+}
+
+impl fmt::Debug for CompileProgress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CompileProgress")
+            .field("on_function_compiled", &self.on_function_compiled.is_some())
+            .field("cancellation", &self.cancellation)
+            .finish()
+    }
+}
+
+/// The time spent in each phase of the compilation and loading of a module.
+///
+/// The phases a module didn't go through, like the compilation of a module loaded from an
+/// executable, take no time.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CompileTiming {
+    /// Parsing the binary into the module info and the bodies of its functions.
+    pub parse: Duration,
+    /// Validating the binary.
+    pub validate: Duration,
+    /// Compiling the functions, from the first one starting to the last one finishing.
+    pub codegen: Duration,
+    /// The sum of the times each function took to compile, which exceeds `codegen` when the
+    /// functions are compiled in parallel.
+    pub function_codegen_total: Duration,
+    /// The time the slowest function took to compile.
+    pub function_codegen_max: Duration,
+    /// Generating the trampolines and the unwind information.
+    pub trampolines: Duration,
+    /// Assembling the compiled code and the module info into the executable the engine loads.
+    pub finalize: Duration,
+    /// Allocating the code, applying its relocations and making it executable.
+    pub relocation: Duration,
+}
+
+impl CompileTiming {
+    /// Accounts for a function that took `elapsed` to compile.
+    pub fn add_function(&mut self, elapsed: Duration) {
+        self.function_codegen_total += elapsed;
+        self.function_codegen_max = self.function_codegen_max.max(elapsed);
+    }
+
+    /// The time of the phases, which run one after the other.
+    pub fn total(&self) -> Duration {
+        self.parse
+            + self.validate
+            + self.codegen
+            + self.trampolines
+            + self.finalize
+            + self.relocation
+    }
+}
+
+/// A flag shared with a compilation in progress, to stop it early.
+///
+/// Clones share the same flag.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    /// Creates a token that is not cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels the compilations using this token.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether `cancel` was called on this token or one of its clones.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
@@ -13,8 +13,9 @@ use crate::lib::std::fmt;
 use crate::lib::std::vec::Vec;
 use crate::section::SectionIndex;
 use crate::{Addend, CodeOffset, JumpTable};
+use core::convert::TryFrom;
 use wasmer_types::entity::PrimaryMap;
-use wasmer_types::LocalFunctionIndex;
+use wasmer_types::{LocalFunctionIndex, SignatureIndex};
 use wasmer_vm::libcalls::LibCall;
 
 /// Relocation kinds for every ISA.
@@ -104,6 +105,9 @@ pub enum RelocationTarget {
     JumpTable(LocalFunctionIndex, JumpTable),
     /// Custom sections generated by the compiler
     CustomSection(SectionIndex),
+    /// The engine-wide id of a signature of the module, its `VMSharedSignatureIndex`, rather
+    /// than an address.
+    SignatureId(SignatureIndex),
 }
 
 impl Relocation {
@@ -113,6 +117,15 @@ impl Relocation {
     /// The function returns the relocation address and the delta.
     pub fn for_address(&self, start: usize, target_func_address: u64) -> (usize, u64) {
         match self.kind {
+            RelocationKind::Abs4 => {
+                let reloc_address = start + self.offset as usize;
+                let reloc_addend = self.addend as isize;
+                let reloc_abs = target_func_address
+                    .checked_add(reloc_addend as u64)
+                    .filter(|abs| *abs <= u64::from(u32::MAX))
+                    .unwrap();
+                (reloc_address, reloc_abs)
+            }
             RelocationKind::Abs8
             | RelocationKind::Arm64Movw0
             | RelocationKind::Arm64Movw1
@@ -127,12 +140,12 @@ impl Relocation {
             }
             RelocationKind::X86PCRel4 => {
                 let reloc_address = start + self.offset as usize;
-                let reloc_addend = self.addend as isize;
-                let reloc_delta_u32 = (target_func_address as u32)
-                    .wrapping_sub(reloc_address as u32)
-                    .checked_add(reloc_addend as u32)
-                    .unwrap();
-                (reloc_address, reloc_delta_u32 as u64)
+                let reloc_delta = (target_func_address as i64)
+                    .wrapping_sub(reloc_address as i64)
+                    .wrapping_add(self.addend);
+                let reloc_delta_i32 = i32::try_from(reloc_delta)
+                    .expect("the target of a PC-relative relocation is out of range");
+                (reloc_address, reloc_delta_i32 as u32 as u64)
             }
             RelocationKind::X86PCRel8 => {
                 let reloc_address = start + self.offset as usize;
@@ -164,6 +164,7 @@ impl ToString for CpuFeature {
 pub struct Target {
     triple: Triple,
     cpu_features: EnumSet<CpuFeature>,
+    page_size: Option<usize>,
 }
 
 impl Target {
@@ -172,9 +173,19 @@ impl Target {
         Self {
             triple,
             cpu_features,
+            page_size: None,
         }
     }
 
+    /// Sets the size of the pages of the system the code will run on.
+    ///
+    /// Compilers probe large stack frames at this interval, so that the guard page below the
+    /// stack is always hit before the memory beyond it.
+    pub fn with_page_size(mut self, page_size: usize) -> Self {
+        self.page_size = Some(page_size);
+        self
+    }
+
     /// The triple associated for the target.
     pub fn triple(&self) -> &Triple {
         &self.triple
@@ -184,6 +195,11 @@ impl Target {
     pub fn cpu_features(&self) -> &EnumSet<CpuFeature> {
         &self.cpu_features
     }
+
+    /// The size of the pages of the system the code will run on, if known.
+    pub fn page_size(&self) -> Option<usize> {
+        self.page_size
+    }
 }
 
 /// The default for the Target will use the HOST as the triple
@@ -192,6 +208,7 @@ impl Default for Target {
         Self {
             triple: Triple::host(),
             cpu_features: CpuFeature::for_host(),
+            page_size: None,
         }
     }
 }
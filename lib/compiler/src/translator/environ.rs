@@ -1,20 +1,19 @@
 // This file contains code from external sources.
 // Attributions: https://github.com/wasmerio/wasmer/blob/master/ATTRIBUTIONS.md
 use super::state::ModuleTranslationState;
-use crate::lib::std::borrow::ToOwned;
 use crate::lib::std::string::ToString;
 use crate::lib::std::{boxed::Box, string::String, vec::Vec};
 use crate::translate_module;
-use crate::{WasmError, WasmResult};
+use crate::WasmResult;
 use std::convert::{TryFrom, TryInto};
 use std::sync::Arc;
 use wasmer_types::entity::PrimaryMap;
 use wasmer_types::FunctionType;
 use wasmer_types::{
-    CustomSectionIndex, DataIndex, DataInitializer, DataInitializerLocation, ElemIndex,
+    ConstExpr, CustomSectionIndex, DataIndex, DataInitializer, DataInitializerLocation, ElemIndex,
     ExportIndex, FunctionIndex, GlobalIndex, GlobalInit, GlobalType, ImportIndex,
     LocalFunctionIndex, MemoryIndex, MemoryType, ModuleInfo, OwnedTableInitializer, SignatureIndex,
-    TableIndex, TableType,
+    TableIndex, TableType, TagIndex,
 };
 pub use wasmparser::FunctionBody as FunctionReader;
 
@@ -186,6 +185,27 @@ impl<'data> ModuleEnvironment<'data> {
         Ok(())
     }
 
+    pub(crate) fn declare_tag_import(
+        &mut self,
+        sig_index: SignatureIndex,
+        module: &str,
+        field: &str,
+    ) -> WasmResult<()> {
+        debug_assert_eq!(
+            self.module.tags.len(),
+            self.module.import_counts.tags as usize,
+            "Imported tags must be declared first"
+        );
+        self.declare_import(
+            ImportIndex::Tag(TagIndex::from_u32(self.module.import_counts.tags)),
+            module,
+            field,
+        )?;
+        self.module.tags.push(sig_index);
+        self.module.import_counts.tags += 1;
+        Ok(())
+    }
+
     pub(crate) fn finish_imports(&mut self) -> WasmResult<()> {
         Ok(())
     }
@@ -224,15 +244,22 @@ impl<'data> ModuleEnvironment<'data> {
     }
 
     pub(crate) fn declare_memory(&mut self, memory: MemoryType) -> WasmResult<()> {
-        if memory.shared {
-            return Err(WasmError::Unsupported(
-                "shared memories are not supported yet".to_owned(),
-            ));
-        }
         self.module.memories.push(memory);
         Ok(())
     }
 
+    pub(crate) fn reserve_tags(&mut self, num: u32) -> WasmResult<()> {
+        self.module
+            .tags
+            .reserve_exact(usize::try_from(num).unwrap());
+        Ok(())
+    }
+
+    pub(crate) fn declare_tag(&mut self, sig_index: SignatureIndex) -> WasmResult<()> {
+        self.module.tags.push(sig_index);
+        Ok(())
+    }
+
     pub(crate) fn reserve_globals(&mut self, num: u32) -> WasmResult<()> {
         self.module
             .globals
@@ -287,6 +314,10 @@ impl<'data> ModuleEnvironment<'data> {
         self.declare_export(ExportIndex::Global(global_index), name)
     }
 
+    pub(crate) fn declare_tag_export(&mut self, tag_index: TagIndex, name: &str) -> WasmResult<()> {
+        self.declare_export(ExportIndex::Tag(tag_index), name)
+    }
+
     pub(crate) fn declare_start_function(&mut self, func_index: FunctionIndex) -> WasmResult<()> {
         debug_assert!(self.module.start_function.is_none());
         self.module.start_function = Some(func_index);
@@ -305,12 +336,14 @@ impl<'data> ModuleEnvironment<'data> {
         table_index: TableIndex,
         base: Option<GlobalIndex>,
         offset: usize,
+        extended_offset: Option<ConstExpr>,
         elements: Box<[FunctionIndex]>,
     ) -> WasmResult<()> {
         self.module.table_initializers.push(OwnedTableInitializer {
             table_index,
             base,
             offset,
+            extended_offset,
             elements,
         });
         Ok(())
@@ -354,6 +387,7 @@ impl<'data> ModuleEnvironment<'data> {
         memory_index: MemoryIndex,
         base: Option<GlobalIndex>,
         offset: usize,
+        extended_offset: Option<ConstExpr>,
         data: &'data [u8],
     ) -> WasmResult<()> {
         self.data_initializers.push(DataInitializer {
@@ -361,6 +395,7 @@ impl<'data> ModuleEnvironment<'data> {
                 memory_index,
                 base,
                 offset,
+                extended_offset,
             },
             data,
         });
@@ -401,6 +436,20 @@ impl<'data> ModuleEnvironment<'data> {
         Ok(())
     }
 
+    pub(crate) fn declare_local_name(
+        &mut self,
+        func_index: FunctionIndex,
+        local_index: u32,
+        name: &'data str,
+    ) -> WasmResult<()> {
+        self.module
+            .local_names
+            .entry(func_index)
+            .or_default()
+            .insert(local_index, name.to_string());
+        Ok(())
+    }
+
     /// Provides the number of imports up front. By default this does nothing, but
     /// implementations can use this to preallocate memory if desired.
     pub(crate) fn reserve_imports(&mut self, _num: u32) -> WasmResult<()> {
@@ -419,7 +468,7 @@ impl<'data> ModuleEnvironment<'data> {
         );
         self.module
             .custom_sections
-            .insert(String::from(name), custom_section);
+            .push((String::from(name), custom_section));
         self.module.custom_sections_data.push(Arc::from(data));
         Ok(())
     }
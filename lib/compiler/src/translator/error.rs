@@ -1,5 +1,6 @@
+use crate::lib::std::string::{String, ToString};
 use crate::{CompileError, WasmError};
-use wasmparser::BinaryReaderError;
+use wasmparser::{BinaryReaderError, Operator};
 
 /// Return an `Err(WasmError::Unsupported(msg))` where `msg` the string built by calling `format!`
 /// on the arguments to this macro.
@@ -25,6 +26,15 @@ impl From<BinaryReaderError> for CompileError {
     }
 }
 
+/// The name of `op`, without its immediates, such as `V128Load`.
+pub fn operator_name(op: &Operator) -> String {
+    let name = format!("{:?}", op);
+    match name.find(|c| c == ' ' || c == '{' || c == '(') {
+        Some(end) => name[..end].to_string(),
+        None => name,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -55,4 +65,11 @@ mod tests {
             err => panic!("Unexpected error: {:?}", err),
         }
     }
+
+    #[test]
+    fn operator_names_have_no_immediates() {
+        assert_eq!(operator_name(&Operator::Nop), "Nop");
+        assert_eq!(operator_name(&Operator::I32Const { value: 3 }), "I32Const");
+        assert_eq!(operator_name(&Operator::Call { function_index: 2 }), "Call");
+    }
 }
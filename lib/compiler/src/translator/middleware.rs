@@ -0,0 +1,323 @@
+//! The middlewares: transformations of the module and of the operators of its functions,
+//! applied between the parsing of the module and its compilation.
+
+use crate::error::{MiddlewareError, WasmError, WasmResult};
+use crate::lib::std::boxed::Box;
+use crate::lib::std::fmt::Debug;
+use crate::lib::std::mem;
+use crate::lib::std::vec::Vec;
+use wasmer_types::{LocalFunctionIndex, ModuleInfo};
+use wasmparser::{Operator, OperatorsReader};
+
+/// A middleware transforming a module before it is compiled.
+///
+/// The middlewares of a compiler run in the order they were pushed: the module info is
+/// given to each of them with [`ModuleMiddleware::transform_module_info`] after the module
+/// is parsed, and then each operator of a function goes through the function middlewares
+/// generated for it, the operators emitted by a middleware being fed to the next one.
+pub trait ModuleMiddleware: Debug + Send + Sync {
+    /// Generate the middleware transforming the operators of the function
+    /// `local_function_index`.
+    fn generate_function_middleware(
+        &self,
+        local_function_index: LocalFunctionIndex,
+    ) -> Box<dyn FunctionMiddleware>;
+
+    /// Transform the module info before the functions are compiled, for example to
+    /// declare globals the instrumented functions use, and export them.
+    ///
+    /// The entities the middleware declares must be appended after the ones of the
+    /// module, whose indices the functions refer to.
+    fn transform_module_info(&self, _module_info: &mut ModuleInfo) {}
+}
+
+/// A middleware transforming the operators of a function.
+pub trait FunctionMiddleware: Debug {
+    /// Process `operator`, pushing the operators replacing it to `state`: the operator
+    /// itself to keep it, nothing to drop it, or any other operators.
+    ///
+    /// The operators pushed don't have to be the ones fed so far: a middleware can hold
+    /// operators back and push them along with the ones it is fed later, as long as the
+    /// whole function body is pushed by the time its final `end` is fed.
+    fn feed<'a>(
+        &mut self,
+        operator: Operator<'a>,
+        state: &mut MiddlewareReaderState<'a>,
+    ) -> Result<(), MiddlewareError> {
+        state.push_operator(operator);
+        Ok(())
+    }
+}
+
+/// The operators emitted by the function middlewares, waiting to be fed to the next one.
+#[derive(Debug)]
+pub struct MiddlewareReaderState<'a> {
+    pending_operations: Vec<(Operator<'a>, usize)>,
+    /// The offset of the operator being transformed, which the operators emitted for it
+    /// are reported at.
+    offset: usize,
+}
+
+impl<'a> MiddlewareReaderState<'a> {
+    /// Emit `operator`, as a replacement of the operator being fed.
+    pub fn push_operator(&mut self, operator: Operator<'a>) {
+        self.pending_operations.push((operator, self.offset));
+    }
+}
+
+/// A chain of module middlewares, in the order they run.
+pub trait ModuleMiddlewareChain {
+    /// Generate the chain of function middlewares of the function `local_function_index`.
+    fn generate_function_middleware_chain(
+        &self,
+        local_function_index: LocalFunctionIndex,
+    ) -> Vec<Box<dyn FunctionMiddleware>>;
+
+    /// Transform the module info with each middleware.
+    fn apply_on_module_info(&self, module_info: &mut ModuleInfo);
+}
+
+impl<T: AsRef<dyn ModuleMiddleware>> ModuleMiddlewareChain for [T] {
+    fn generate_function_middleware_chain(
+        &self,
+        local_function_index: LocalFunctionIndex,
+    ) -> Vec<Box<dyn FunctionMiddleware>> {
+        self.iter()
+            .map(|middleware| {
+                middleware
+                    .as_ref()
+                    .generate_function_middleware(local_function_index)
+            })
+            .collect()
+    }
+
+    fn apply_on_module_info(&self, module_info: &mut ModuleInfo) {
+        for middleware in self {
+            middleware.as_ref().transform_module_info(module_info);
+        }
+    }
+}
+
+/// A reader of the operators of a function body, transformed by a chain of function
+/// middlewares.
+pub struct MiddlewareBinaryReader<'a> {
+    operators: OperatorsReader<'a>,
+    chain: Vec<Box<dyn FunctionMiddleware>>,
+    state: MiddlewareReaderState<'a>,
+    /// The operators that came out of the chain and weren't read yet, last first.
+    ready: Vec<(Operator<'a>, usize)>,
+}
+
+impl<'a> MiddlewareBinaryReader<'a> {
+    /// Create a reader of `operators` transformed by `chain`. Without middlewares, the
+    /// operators are read as they are.
+    pub fn new(operators: OperatorsReader<'a>, chain: Vec<Box<dyn FunctionMiddleware>>) -> Self {
+        Self {
+            operators,
+            chain,
+            state: MiddlewareReaderState {
+                pending_operations: Vec::new(),
+                offset: 0,
+            },
+            ready: Vec::new(),
+        }
+    }
+
+    /// Read the next transformed operator, and the offset of the operator of the function
+    /// body it was emitted for.
+    pub fn read_operator(&mut self) -> WasmResult<(Operator<'a>, usize)> {
+        // Feed the operators of the body to the chain until some come out of it.
+        while self.ready.is_empty() {
+            if self.operators.eof() {
+                return Err(WasmError::InvalidWebAssembly {
+                    message: "the middlewares dropped the end of the function body".into(),
+                    offset: self.state.offset,
+                });
+            }
+            let (operator, offset) = self.operators.read_with_offset()?;
+            self.state.offset = offset;
+            self.state.push_operator(operator);
+            for middleware in &mut self.chain {
+                for (operator, _) in mem::take(&mut self.state.pending_operations) {
+                    middleware.feed(operator, &mut self.state)?;
+                }
+            }
+            self.ready = mem::take(&mut self.state.pending_operations);
+            self.ready.reverse();
+        }
+        Ok(self.ready.pop().unwrap())
+    }
+
+    /// Whether all the operators of the function body were read.
+    pub fn eof(&self) -> bool {
+        self.ready.is_empty() && self.operators.eof()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasmparser::FunctionBody;
+
+    /// Emits each operator twice.
+    #[derive(Debug)]
+    struct Duplicate;
+
+    impl FunctionMiddleware for Duplicate {
+        fn feed<'a>(
+            &mut self,
+            operator: Operator<'a>,
+            state: &mut MiddlewareReaderState<'a>,
+        ) -> Result<(), MiddlewareError> {
+            if !matches!(operator, Operator::End) {
+                state.push_operator(operator.clone());
+            }
+            state.push_operator(operator);
+            Ok(())
+        }
+    }
+
+    /// Holds each constant back until the next operator, swapping them.
+    #[derive(Debug, Default)]
+    struct SwapConstants {
+        held: Option<i32>,
+    }
+
+    impl FunctionMiddleware for SwapConstants {
+        fn feed<'a>(
+            &mut self,
+            operator: Operator<'a>,
+            state: &mut MiddlewareReaderState<'a>,
+        ) -> Result<(), MiddlewareError> {
+            match operator {
+                Operator::I32Const { value } if self.held.is_none() => {
+                    self.held = Some(value);
+                }
+                operator => {
+                    state.push_operator(operator);
+                    if let Some(value) = self.held.take() {
+                        state.push_operator(Operator::I32Const { value });
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+
+    /// Drops the `nop`s.
+    #[derive(Debug)]
+    struct DropNops;
+
+    impl FunctionMiddleware for DropNops {
+        fn feed<'a>(
+            &mut self,
+            operator: Operator<'a>,
+            state: &mut MiddlewareReaderState<'a>,
+        ) -> Result<(), MiddlewareError> {
+            if !matches!(operator, Operator::Nop) {
+                state.push_operator(operator);
+            }
+            Ok(())
+        }
+    }
+
+    /// No locals, and `i32.const 1`, `nop`, `i32.const 2`, `drop`, `drop`, `end`.
+    const BODY: &[u8] = &[0x00, 0x41, 0x01, 0x01, 0x41, 0x02, 0x1a, 0x1a, 0x0b];
+
+    fn reader(body: &[u8], chain: Vec<Box<dyn FunctionMiddleware>>) -> MiddlewareBinaryReader<'_> {
+        let operators = FunctionBody::new(100, body).get_operators_reader().unwrap();
+        MiddlewareBinaryReader::new(operators, chain)
+    }
+
+    fn read_all(chain: Vec<Box<dyn FunctionMiddleware>>) -> Vec<(String, usize)> {
+        let mut reader = reader(BODY, chain);
+        let mut read = vec![];
+        loop {
+            let (operator, offset) = reader.read_operator().unwrap();
+            let end = matches!(operator, Operator::End);
+            read.push((format!("{:?}", operator), offset));
+            if end {
+                return read;
+            }
+        }
+    }
+
+    fn ops(expected: &[(&str, usize)]) -> Vec<(String, usize)> {
+        expected
+            .iter()
+            .map(|&(op, offset)| (op.to_string(), offset))
+            .collect()
+    }
+
+    #[test]
+    fn operators_are_read_as_they_are_without_middlewares() {
+        assert_eq!(
+            read_all(vec![]),
+            ops(&[
+                ("I32Const { value: 1 }", 101),
+                ("Nop", 103),
+                ("I32Const { value: 2 }", 104),
+                ("Drop", 106),
+                ("Drop", 107),
+                ("End", 108),
+            ])
+        );
+    }
+
+    #[test]
+    fn middlewares_transform_the_operators_in_order() {
+        // The constants are swapped with the operators following them, and then duplicated,
+        // and the operators emitted are reported at the offset of the one they were fed.
+        let chain: Vec<Box<dyn FunctionMiddleware>> = vec![
+            Box::new(SwapConstants::default()),
+            Box::new(Duplicate),
+            Box::new(DropNops),
+        ];
+        assert_eq!(
+            read_all(chain),
+            ops(&[
+                ("I32Const { value: 1 }", 103),
+                ("I32Const { value: 1 }", 103),
+                ("Drop", 106),
+                ("Drop", 106),
+                ("I32Const { value: 2 }", 106),
+                ("I32Const { value: 2 }", 106),
+                ("Drop", 107),
+                ("Drop", 107),
+                ("End", 108),
+            ])
+        );
+
+        // In the other order, the `nop` is dropped before the constants are held back.
+        let chain: Vec<Box<dyn FunctionMiddleware>> =
+            vec![Box::new(DropNops), Box::new(SwapConstants::default())];
+        assert_eq!(
+            read_all(chain),
+            ops(&[
+                ("I32Const { value: 2 }", 104),
+                ("I32Const { value: 1 }", 104),
+                ("Drop", 106),
+                ("Drop", 107),
+                ("End", 108),
+            ])
+        );
+    }
+
+    #[test]
+    fn the_end_of_the_body_must_be_emitted() {
+        // The last constant is held back past the end of the body.
+        let body = &[0x00, 0x41, 0x01, 0x0b];
+        let chain: Vec<Box<dyn FunctionMiddleware>> = vec![Box::new(SwapConstants::default())];
+        let mut reader = reader(body, chain);
+        assert!(matches!(reader.read_operator(), Ok((Operator::End, 103))));
+        assert!(matches!(
+            reader.read_operator(),
+            Ok((Operator::I32Const { value: 1 }, 103))
+        ));
+        assert!(reader.eof());
+        assert!(matches!(
+            reader.read_operator(),
+            Err(WasmError::InvalidWebAssembly { .. })
+        ));
+    }
+}
@@ -6,13 +6,24 @@
 //!
 //! [cranelift-wasm]: https://crates.io/crates/cranelift-wasm/
 mod environ;
+mod middleware;
 mod module;
 mod state;
 #[macro_use]
 mod error;
 mod sections;
+mod validation;
 
 pub use self::environ::{FunctionBodyData, FunctionReader, ModuleEnvironment};
+pub use self::error::operator_name;
+pub use self::middleware::{
+    FunctionMiddleware, MiddlewareBinaryReader, MiddlewareReaderState, ModuleMiddleware,
+    ModuleMiddlewareChain,
+};
 pub use self::module::translate_module;
 pub use self::sections::wptype_to_type;
 pub use self::state::ModuleTranslationState;
+pub use self::validation::{
+    validate_module_with, wasmparser_features, ModuleResources, ValidatedFunction,
+    ValidationVisitor,
+};
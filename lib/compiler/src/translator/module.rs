@@ -7,7 +7,7 @@ use super::environ::ModuleEnvironment;
 use super::sections::{
     parse_data_section, parse_element_section, parse_export_section, parse_function_section,
     parse_global_section, parse_import_section, parse_memory_section, parse_name_section,
-    parse_start_section, parse_table_section, parse_type_section,
+    parse_start_section, parse_table_section, parse_tag_section, parse_type_section,
 };
 use super::state::ModuleTranslationState;
 use crate::WasmResult;
@@ -46,6 +46,10 @@ pub fn translate_module<'data>(
                 parse_memory_section(memories, environ)?;
             }
 
+            Payload::EventSection(tags) => {
+                parse_tag_section(tags, environ)?;
+            }
+
             Payload::GlobalSection(globals) => {
                 parse_global_section(globals, environ)?;
             }
@@ -59,6 +63,7 @@ pub fn translate_module<'data>(
             }
 
             Payload::ElementSection(elements) => {
+                module_translation_state.element_count = elements.get_count();
                 parse_element_section(elements, environ)?;
             }
 
@@ -79,12 +84,12 @@ pub fn translate_module<'data>(
             }
 
             Payload::DataCountSection { count, .. } => {
+                module_translation_state.data_count = Some(count);
                 environ.reserve_passive_data(count)?;
             }
 
             Payload::InstanceSection(_)
             | Payload::AliasSection(_)
-            | Payload::EventSection(_)
             | Payload::ModuleSectionStart { .. }
             | Payload::ModuleSectionEntry { .. } => {
                 unimplemented!("module linking not implemented yet")
@@ -95,7 +100,12 @@ pub fn translate_module<'data>(
                 data,
                 data_offset,
                 ..
-            } => parse_name_section(NameSectionReader::new(data, data_offset)?, environ)?,
+            } => {
+                // A name section with a malformed header only loses its names.
+                if let Ok(names) = NameSectionReader::new(data, data_offset) {
+                    parse_name_section(names, environ)?;
+                }
+            }
 
             Payload::CustomSection { name, data, .. } => environ.custom_section(name, data)?,
 
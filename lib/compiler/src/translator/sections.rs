@@ -21,15 +21,17 @@ use std::vec::Vec;
 use wasmer_types::entity::packed_option::ReservedValue;
 use wasmer_types::entity::EntityRef;
 use wasmer_types::{
-    DataIndex, ElemIndex, FunctionIndex, FunctionType, GlobalIndex, GlobalInit, GlobalType,
-    MemoryIndex, MemoryType, Mutability, Pages, SignatureIndex, TableIndex, TableType, Type, V128,
+    ConstExpr, ConstOp, DataIndex, ElemIndex, FunctionIndex, FunctionType, GlobalIndex, GlobalInit,
+    GlobalType, MemoryIndex, MemoryType, Mutability, Pages, SignatureIndex, TableIndex, TableType,
+    TagIndex, Type, V128,
 };
 use wasmparser::{
     self, Data, DataKind, DataSectionReader, Element, ElementItem, ElementItems, ElementKind,
-    ElementSectionReader, Export, ExportSectionReader, ExternalKind, FuncType as WPFunctionType,
-    FunctionSectionReader, GlobalSectionReader, GlobalType as WPGlobalType, ImportSectionEntryType,
-    ImportSectionReader, MemorySectionReader, MemoryType as WPMemoryType, NameSectionReader,
-    Naming, NamingReader, Operator, TableSectionReader, TypeDef, TypeSectionReader,
+    ElementSectionReader, EventSectionReader, EventType, Export, ExportSectionReader, ExternalKind,
+    FuncType as WPFunctionType, FunctionLocalReader, FunctionSectionReader, GlobalSectionReader,
+    GlobalType as WPGlobalType, ImportSectionEntryType, ImportSectionReader, InitExpr,
+    MemorySectionReader, MemoryType as WPMemoryType, NameSectionReader, Naming, NamingReader,
+    Operator, TableSectionReader, TypeDef, TypeSectionReader,
 };
 
 /// Helper function translating wasmparser types to Wasm Type.
@@ -49,6 +51,19 @@ pub fn wptype_to_type(ty: wasmparser::Type) -> WasmResult<Type> {
     }
 }
 
+/// Helper function translating Wasm types to wasmparser types.
+pub(crate) fn type_to_wptype(ty: Type) -> wasmparser::Type {
+    match ty {
+        Type::I32 => wasmparser::Type::I32,
+        Type::I64 => wasmparser::Type::I64,
+        Type::F32 => wasmparser::Type::F32,
+        Type::F64 => wasmparser::Type::F64,
+        Type::V128 => wasmparser::Type::V128,
+        Type::ExternRef => wasmparser::Type::ExternRef,
+        Type::FuncRef => wasmparser::Type::FuncRef,
+    }
+}
+
 /// Parses the Type section of the wasm module.
 pub fn parse_type_section(
     types: TypeSectionReader,
@@ -76,7 +91,9 @@ pub fn parse_type_section(
                 .collect();
             let sig = FunctionType::new(sig_params, sig_returns);
             environ.declare_signature(sig)?;
-            module_translation_state.wasm_types.push((params, returns));
+            module_translation_state
+                .wasm_types
+                .push(WPFunctionType { params, returns });
         } else {
             unimplemented!("module linking not implemented yet")
         }
@@ -105,9 +122,14 @@ pub fn parse_import_section<'data>(
                     field_name.unwrap_or_default(),
                 )?;
             }
-            ImportSectionEntryType::Module(_)
-            | ImportSectionEntryType::Instance(_)
-            | ImportSectionEntryType::Event(_) => {
+            ImportSectionEntryType::Event(EventType { type_index }) => {
+                environ.declare_tag_import(
+                    SignatureIndex::from_u32(type_index),
+                    module_name,
+                    field_name.unwrap_or_default(),
+                )?;
+            }
+            ImportSectionEntryType::Module(_) | ImportSectionEntryType::Instance(_) => {
                 unimplemented!("module linking not implemented yet")
             }
             ImportSectionEntryType::Memory(WPMemoryType::M32 {
@@ -119,13 +141,26 @@ pub fn parse_import_section<'data>(
                         minimum: Pages(memlimits.initial),
                         maximum: memlimits.maximum.map(Pages),
                         shared,
+                        memory64: false,
                     },
                     module_name,
                     field_name.unwrap_or_default(),
                 )?;
             }
-            ImportSectionEntryType::Memory(WPMemoryType::M64 { .. }) => {
-                unimplemented!("64bit memory not implemented yet")
+            ImportSectionEntryType::Memory(WPMemoryType::M64 {
+                limits: ref memlimits,
+                shared,
+            }) => {
+                environ.declare_memory_import(
+                    MemoryType {
+                        minimum: memory64_pages(memlimits.initial)?,
+                        maximum: memlimits.maximum.map(memory64_pages).transpose()?,
+                        shared,
+                        memory64: true,
+                    },
+                    module_name,
+                    field_name.unwrap_or_default(),
+                )?;
             }
             ImportSectionEntryType::Global(ref ty) => {
                 environ.declare_global_import(
@@ -214,15 +249,45 @@ pub fn parse_memory_section(
                     minimum: Pages(limits.initial),
                     maximum: limits.maximum.map(Pages),
                     shared,
+                    memory64: false,
+                })?;
+            }
+            WPMemoryType::M64 { limits, shared } => {
+                environ.declare_memory(MemoryType {
+                    minimum: memory64_pages(limits.initial)?,
+                    maximum: limits.maximum.map(memory64_pages).transpose()?,
+                    shared,
+                    memory64: true,
                 })?;
             }
-            WPMemoryType::M64 { .. } => unimplemented!("64bit memory not implemented yet"),
         }
     }
 
     Ok(())
 }
 
+/// The `Pages` of a limit of a 64-bit memory, which can't count more than `u32::MAX` pages.
+fn memory64_pages(pages: u64) -> WasmResult<Pages> {
+    u32::try_from(pages)
+        .map(Pages)
+        .map_err(|_| wasm_unsupported!("64-bit memories of {} pages", pages))
+}
+
+/// Parses the Tag section of the wasm module, which wasmparser calls the event section.
+pub fn parse_tag_section(
+    tags: EventSectionReader,
+    environ: &mut ModuleEnvironment,
+) -> WasmResult<()> {
+    environ.reserve_tags(tags.get_count())?;
+
+    for entry in tags {
+        let EventType { type_index } = entry?;
+        environ.declare_tag(SignatureIndex::from_u32(type_index))?;
+    }
+
+    Ok(())
+}
+
 /// Parses the Global section of the wasm module.
 pub fn parse_global_section(
     globals: GlobalSectionReader,
@@ -238,26 +303,21 @@ pub fn parse_global_section(
             },
             init_expr,
         } = entry?;
-        let mut init_expr_reader = init_expr.get_binary_reader();
-        let initializer = match init_expr_reader.read_operator()? {
-            Operator::I32Const { value } => GlobalInit::I32Const(value),
-            Operator::I64Const { value } => GlobalInit::I64Const(value),
-            Operator::F32Const { value } => GlobalInit::F32Const(f32::from_bits(value.bits())),
-            Operator::F64Const { value } => GlobalInit::F64Const(f64::from_bits(value.bits())),
-            Operator::V128Const { value } => GlobalInit::V128Const(V128::from(*value.bytes())),
-            Operator::RefNull { ty: _ } => GlobalInit::RefNullConst,
-            Operator::RefFunc { function_index } => {
-                GlobalInit::RefFunc(FunctionIndex::from_u32(function_index))
+        let operators = const_expr_operators(&init_expr)?;
+        let initializer = match operators.as_slice() {
+            [Operator::I32Const { value }] => GlobalInit::I32Const(*value),
+            [Operator::I64Const { value }] => GlobalInit::I64Const(*value),
+            [Operator::F32Const { value }] => GlobalInit::F32Const(f32::from_bits(value.bits())),
+            [Operator::F64Const { value }] => GlobalInit::F64Const(f64::from_bits(value.bits())),
+            [Operator::V128Const { value }] => GlobalInit::V128Const(V128::from(*value.bytes())),
+            [Operator::RefNull { ty: _ }] => GlobalInit::RefNullConst,
+            [Operator::RefFunc { function_index }] => {
+                GlobalInit::RefFunc(FunctionIndex::from_u32(*function_index))
             }
-            Operator::GlobalGet { global_index } => {
-                GlobalInit::GetGlobal(GlobalIndex::from_u32(global_index))
-            }
-            ref s => {
-                return Err(wasm_unsupported!(
-                    "unsupported init expr in global section: {:?}",
-                    s
-                ));
+            [Operator::GlobalGet { global_index }] => {
+                GlobalInit::GetGlobal(GlobalIndex::from_u32(*global_index))
             }
+            _ => GlobalInit::Expr(extended_const_expr(&operators, "global")?),
         };
         let global = GlobalType {
             ty: wptype_to_type(content_type).unwrap(),
@@ -273,6 +333,72 @@ pub fn parse_global_section(
     Ok(())
 }
 
+/// The operators of the constant expression `init_expr`, without its `end`.
+fn const_expr_operators<'data>(init_expr: &InitExpr<'data>) -> WasmResult<Vec<Operator<'data>>> {
+    let mut reader = init_expr.get_operators_reader();
+    let mut operators = Vec::new();
+    loop {
+        match reader.read()? {
+            Operator::End => return Ok(operators),
+            op => operators.push(op),
+        }
+    }
+}
+
+/// The extended constant expression of `operators`, in the section `section`.
+fn extended_const_expr(operators: &[Operator<'_>], section: &str) -> WasmResult<ConstExpr> {
+    let ops = operators
+        .iter()
+        .map(|op| {
+            Ok(match *op {
+                Operator::I32Const { value } => ConstOp::I32Const(value),
+                Operator::I64Const { value } => ConstOp::I64Const(value),
+                Operator::GlobalGet { global_index } => {
+                    ConstOp::GlobalGet(GlobalIndex::from_u32(global_index))
+                }
+                Operator::I32Add => ConstOp::I32Add,
+                Operator::I32Sub => ConstOp::I32Sub,
+                Operator::I32Mul => ConstOp::I32Mul,
+                Operator::I64Add => ConstOp::I64Add,
+                Operator::I64Sub => ConstOp::I64Sub,
+                Operator::I64Mul => ConstOp::I64Mul,
+                ref s => {
+                    return Err(wasm_unsupported!(
+                        "unsupported init expr in {} section: {:?}",
+                        section,
+                        s
+                    ))
+                }
+            })
+        })
+        .collect::<WasmResult<Vec<_>>>()?;
+    Ok(ConstExpr { ops: ops.into() })
+}
+
+/// The base, offset and extended constant expression of the offset `init_expr` of a segment
+/// of the section `section`.
+fn segment_offset(
+    init_expr: &InitExpr<'_>,
+    section: &str,
+) -> WasmResult<(Option<GlobalIndex>, usize, Option<ConstExpr>)> {
+    let operators = const_expr_operators(init_expr)?;
+    Ok(match operators.as_slice() {
+        [Operator::I32Const { value }] => (None, *value as u32 as usize, None),
+        [Operator::I64Const { value }] => (None, *value as u64 as usize, None),
+        [Operator::GlobalGet { global_index }] => {
+            (Some(GlobalIndex::from_u32(*global_index)), 0, None)
+        }
+        [op] => {
+            return Err(wasm_unsupported!(
+                "unsupported init expr in {} section: {:?}",
+                section,
+                op
+            ))
+        }
+        _ => (None, 0, Some(extended_const_expr(&operators, section)?)),
+    })
+}
+
 /// Parses the Export section of the wasm module.
 pub fn parse_export_section<'data>(
     exports: ExportSectionReader<'data>,
@@ -302,10 +428,8 @@ pub fn parse_export_section<'data>(
             ExternalKind::Global => {
                 environ.declare_global_export(GlobalIndex::new(index), field)?
             }
-            ExternalKind::Type
-            | ExternalKind::Module
-            | ExternalKind::Instance
-            | ExternalKind::Event => {
+            ExternalKind::Event => environ.declare_tag_export(TagIndex::new(index), field)?,
+            ExternalKind::Type | ExternalKind::Module | ExternalKind::Instance => {
                 unimplemented!("module linking not implemented yet")
             }
         }
@@ -355,23 +479,12 @@ pub fn parse_element_section<'data>(
                 table_index,
                 init_expr,
             } => {
-                let mut init_expr_reader = init_expr.get_binary_reader();
-                let (base, offset) = match init_expr_reader.read_operator()? {
-                    Operator::I32Const { value } => (None, value as u32 as usize),
-                    Operator::GlobalGet { global_index } => {
-                        (Some(GlobalIndex::from_u32(global_index)), 0)
-                    }
-                    ref s => {
-                        return Err(wasm_unsupported!(
-                            "unsupported init expr in element section: {:?}",
-                            s
-                        ));
-                    }
-                };
+                let (base, offset, extended_offset) = segment_offset(&init_expr, "element")?;
                 environ.declare_table_initializers(
                     TableIndex::from_u32(table_index),
                     base,
                     offset,
+                    extended_offset,
                     segments,
                 )?
             }
@@ -399,23 +512,12 @@ pub fn parse_data_section<'data>(
                 memory_index,
                 init_expr,
             } => {
-                let mut init_expr_reader = init_expr.get_binary_reader();
-                let (base, offset) = match init_expr_reader.read_operator()? {
-                    Operator::I32Const { value } => (None, value as u32 as usize),
-                    Operator::GlobalGet { global_index } => {
-                        (Some(GlobalIndex::from_u32(global_index)), 0)
-                    }
-                    ref s => {
-                        return Err(wasm_unsupported!(
-                            "unsupported init expr in data section: {:?}",
-                            s
-                        ))
-                    }
-                };
+                let (base, offset, extended_offset) = segment_offset(&init_expr, "data")?;
                 environ.declare_data_initialization(
                     MemoryIndex::from_u32(memory_index),
                     base,
                     offset,
+                    extended_offset,
                     data,
                 )?;
             }
@@ -430,6 +532,9 @@ pub fn parse_data_section<'data>(
 }
 
 /// Parses the Name section of the wasm module.
+///
+/// The names are only there for debugging, so a malformed subsection loses its names rather
+/// than failing the translation.
 pub fn parse_name_section<'data>(
     mut names: NameSectionReader<'data>,
     environ: &mut ModuleEnvironment<'data>,
@@ -452,13 +557,45 @@ pub fn parse_name_section<'data>(
                     environ.declare_module_name(name)?;
                 }
             }
-            wasmparser::Name::Local(_) => {}
+            wasmparser::Name::Local(local_subsection) => {
+                if let Some(local_names) = local_subsection
+                    .get_function_local_reader()
+                    .ok()
+                    .and_then(parse_local_name_subsection)
+                {
+                    for (func_index, names) in local_names {
+                        for (local_index, name) in names {
+                            environ.declare_local_name(func_index, local_index, name)?;
+                        }
+                    }
+                }
+            }
             wasmparser::Name::Unknown { .. } => {}
         };
     }
     Ok(())
 }
 
+/// The names the name section `names` gives the functions, leaving out the malformed
+/// subsections like [`parse_name_section`] does.
+pub(crate) fn parse_function_names(
+    mut names: NameSectionReader<'_>,
+) -> HashMap<FunctionIndex, &str> {
+    let mut function_names = HashMap::new();
+    while let Ok(subsection) = names.read() {
+        if let wasmparser::Name::Function(function_subsection) = subsection {
+            if let Some(names) = function_subsection
+                .get_map()
+                .ok()
+                .and_then(parse_function_name_subsection)
+            {
+                function_names.extend(names);
+            }
+        }
+    }
+    function_names
+}
+
 fn parse_function_name_subsection(
     mut naming_reader: NamingReader<'_>,
 ) -> Option<HashMap<FunctionIndex, &str>> {
@@ -482,3 +619,27 @@ fn parse_function_name_subsection(
     }
     Some(function_names)
 }
+
+fn parse_local_name_subsection(
+    mut function_reader: FunctionLocalReader<'_>,
+) -> Option<HashMap<FunctionIndex, HashMap<u32, &str>>> {
+    let mut local_names = HashMap::new();
+    for _ in 0..function_reader.get_count() {
+        let function = function_reader.read().ok()?;
+        let mut naming_reader = function.get_map().ok()?;
+        let mut names = HashMap::new();
+        for _ in 0..naming_reader.get_count() {
+            let Naming { index, name } = naming_reader.read().ok()?;
+            if names.insert(index, name).is_some() {
+                return None;
+            }
+        }
+        if local_names
+            .insert(FunctionIndex::from_u32(function.func_index), names)
+            .is_some()
+        {
+            return None;
+        }
+    }
+    Some(local_names)
+}
@@ -2,14 +2,12 @@
 // Attributions: https://github.com/wasmerio/wasmer/blob/master/ATTRIBUTIONS.md
 
 use crate::{wasm_unsupported, WasmResult};
-use std::boxed::Box;
 use std::collections::HashMap;
 use wasmer_types::entity::PrimaryMap;
 use wasmer_types::{FunctionIndex, ImportIndex, ModuleInfo, SignatureIndex};
 
 /// Map of signatures to a function's parameter and return types.
-pub(crate) type WasmTypes =
-    PrimaryMap<SignatureIndex, (Box<[wasmparser::Type]>, Box<[wasmparser::Type]>)>;
+pub(crate) type WasmTypes = PrimaryMap<SignatureIndex, wasmparser::FuncType>;
 
 /// Contains information decoded from the Wasm module that must be referenced
 /// during each Wasm function's translation.
@@ -27,6 +25,12 @@ pub struct ModuleTranslationState {
 
     /// Imported functions names map.
     pub import_map: HashMap<FunctionIndex, String>,
+
+    /// The number of element segments of the module.
+    pub(crate) element_count: u32,
+
+    /// The number of data segments declared by the data count section, if the module has one.
+    pub(crate) data_count: Option<u32>,
 }
 
 impl ModuleTranslationState {
@@ -35,6 +39,8 @@ impl ModuleTranslationState {
         Self {
             wasm_types: PrimaryMap::new(),
             import_map: HashMap::new(),
+            element_count: 0,
+            data_count: None,
         }
     }
 
@@ -73,8 +79,8 @@ impl ModuleTranslationState {
             },
             wasmparser::TypeOrFuncType::FuncType(ty_index) => {
                 let sig_idx = SignatureIndex::from_u32(ty_index);
-                let (ref params, ref results) = self.wasm_types[sig_idx];
-                (&*params, &*results)
+                let ty = &self.wasm_types[sig_idx];
+                (&*ty.params, &*ty.returns)
             }
         })
     }
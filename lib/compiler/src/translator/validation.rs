@@ -0,0 +1,559 @@
+//! The validation of modules, and of function bodies against a translated module for the
+//! compilers validating the operators transformed by their middlewares.
+
+use super::error::operator_name;
+use super::sections::{parse_function_names, type_to_wptype};
+use super::state::ModuleTranslationState;
+use crate::error::{CompileError, FunctionLocation};
+use crate::lib::std::boxed::Box;
+use crate::lib::std::fmt;
+use crate::lib::std::string::{String, ToString};
+use crate::lib::std::vec::Vec;
+use std::collections::HashMap;
+use wasmer_types::entity::EntityRef;
+use wasmer_types::{
+    Features, FunctionIndex, GlobalIndex, LocalFunctionIndex, MemoryIndex, ModuleInfo,
+    SignatureIndex, TableIndex,
+};
+use wasmparser::{
+    BinaryReaderError, DataKind, DataSectionReader, ElementKind, ElementSectionReader, FuncType,
+    FuncValidator, FunctionBody, GlobalSectionReader, GlobalType, Import, ImportSectionEntryType,
+    InitExpr, MemoryType, NameSectionReader, Operator, Parser, Payload, ResizableLimits,
+    ResizableLimits64, SectionReader, TableType, Type, TypeDef, ValidPayload, Validator,
+    WasmFeatures, WasmModuleResources,
+};
+
+/// The wasmparser features matching `features`.
+pub fn wasmparser_features(features: &Features) -> WasmFeatures {
+    WasmFeatures {
+        bulk_memory: features.bulk_memory,
+        threads: features.threads,
+        reference_types: features.reference_types,
+        multi_value: features.multi_value,
+        simd: features.simd,
+        tail_call: features.tail_call,
+        module_linking: features.module_linking,
+        multi_memory: features.multi_memory,
+        memory64: features.memory64,
+        exceptions: features.exceptions,
+        deterministic_only: false,
+    }
+}
+
+/// Sees the sections of a module and the operators of its functions as they are validated.
+///
+/// This lets a compiler check the module for what it doesn't support without decoding the
+/// function bodies once more.
+pub trait ValidationVisitor {
+    /// Sees a payload of the module once it is validated.
+    fn payload(&mut self, _payload: &Payload<'_>) {}
+
+    /// Sees the operator `op` at `offset` in the body of `function` once it is validated.
+    ///
+    /// The bodies are validated once all the payloads of the module are seen.
+    fn operator(&mut self, _function: &ValidatedFunction<'_>, _offset: usize, _op: &Operator<'_>) {}
+}
+
+impl ValidationVisitor for () {}
+
+/// A function of a module being validated.
+#[derive(Debug, Clone, Copy)]
+pub struct ValidatedFunction<'data> {
+    /// The index of the function among the functions the module defines.
+    pub local_index: LocalFunctionIndex,
+    /// The index of the function in the module, the imported functions included.
+    pub index: FunctionIndex,
+    /// The name the name section of the module gives the function, if any.
+    pub name: Option<&'data str>,
+}
+
+impl ValidatedFunction<'_> {
+    /// The location of the operator `op` at `offset` in the function, or of the function
+    /// itself without `op`.
+    pub fn location(&self, offset: usize, op: Option<&Operator<'_>>) -> FunctionLocation {
+        FunctionLocation {
+            local_index: self.local_index,
+            index: self.index,
+            name: self.name.map(ToString::to_string),
+            offset,
+            operator: op.map(operator_name),
+        }
+    }
+}
+
+/// Validates the module `data`, showing its payloads and the operators of its functions to
+/// `visitor` as they are validated.
+///
+/// The errors in the body of a function are [`CompileError::Function`]s locating them.
+pub fn validate_module_with(
+    features: &Features,
+    data: &[u8],
+    visitor: &mut dyn ValidationVisitor,
+) -> Result<(), CompileError> {
+    let mut validator = Validator::new();
+    validator.wasm_features(wasmparser_features(features));
+    let mut imported_functions = 0;
+    let mut imported_globals = Vec::new();
+    let mut function_names = HashMap::new();
+    let mut functions = Vec::new();
+    for payload in Parser::new(0).parse_all(data) {
+        let payload = payload.map_err(|e| CompileError::Validate(e.to_string()))?;
+        let validated = features.extended_const
+            && validate_extended_consts(&mut validator, data, &payload, &imported_globals)?;
+        if !validated {
+            if let ValidPayload::Func(validator, body) = validator
+                .payload(&payload)
+                .map_err(|e| CompileError::Validate(e.to_string()))?
+            {
+                functions.push((validator, body));
+            }
+        }
+        if !features.floats {
+            validate_no_float_types(&payload).map_err(|e| CompileError::Validate(e.to_string()))?;
+        }
+        match &payload {
+            Payload::ImportSection(imports) => {
+                for import in imports.clone() {
+                    match import {
+                        Ok(Import {
+                            ty: ImportSectionEntryType::Function(_),
+                            ..
+                        }) => imported_functions += 1,
+                        Ok(Import {
+                            ty: ImportSectionEntryType::Global(ty),
+                            ..
+                        }) => imported_globals.push(ty),
+                        _ => {}
+                    }
+                }
+            }
+            Payload::CustomSection {
+                name: "name",
+                data,
+                data_offset,
+                ..
+            } => {
+                if let Ok(names) = NameSectionReader::new(data, *data_offset) {
+                    function_names = parse_function_names(names);
+                }
+            }
+            _ => {}
+        }
+        visitor.payload(&payload);
+    }
+    // The bodies are validated last, as the validation of the sections that come after the
+    // code section may fail.
+    for (local_index, (mut validator, body)) in functions.into_iter().enumerate() {
+        let local_index = LocalFunctionIndex::new(local_index);
+        let index = FunctionIndex::new(imported_functions + local_index.index());
+        let function = ValidatedFunction {
+            local_index,
+            index,
+            name: function_names.get(&index).copied(),
+        };
+        validate_function_body(&mut validator, &body, features.floats, |offset, op| {
+            visitor.operator(&function, offset, op)
+        })
+        .map_err(|e| {
+            let mut location = function.location(e.offset, None);
+            location.operator = operator_at(&body, e.offset);
+            CompileError::Function {
+                location,
+                error: Box::new(CompileError::Validate(e.message)),
+            }
+        })?;
+    }
+    Ok(())
+}
+
+/// Validates the section `payload` of the module `data` if it holds extended constant
+/// expressions, returning whether it did.
+///
+/// The validator doesn't support the extended-const proposal, so the expressions are validated
+/// here, and the validator is given the section with constants in their place.
+fn validate_extended_consts(
+    validator: &mut Validator,
+    data: &[u8],
+    payload: &Payload<'_>,
+    imported_globals: &[GlobalType],
+) -> Result<bool, CompileError> {
+    let error = |e: BinaryReaderError| CompileError::Validate(e.to_string());
+    // The expressions of the section, with the type of their value.
+    let mut exprs = Vec::new();
+    let (start, end) = match payload {
+        Payload::GlobalSection(reader) => {
+            let mut reader = reader.clone();
+            let start = reader.range().start;
+            for _ in 0..reader.get_count() {
+                let global = reader.read().map_err(error)?;
+                exprs.push((global.init_expr, global.ty.content_type));
+            }
+            (start, reader.original_position())
+        }
+        Payload::ElementSection(reader) => {
+            let mut reader = reader.clone();
+            let start = reader.range().start;
+            for _ in 0..reader.get_count() {
+                if let ElementKind::Active { init_expr, .. } = reader.read().map_err(error)?.kind {
+                    exprs.push((init_expr, Type::I32));
+                }
+            }
+            (start, reader.original_position())
+        }
+        Payload::DataSection(reader) => {
+            let mut reader = reader.clone();
+            let start = reader.range().start;
+            for _ in 0..reader.get_count() {
+                if let DataKind::Active { init_expr, .. } = reader.read().map_err(error)?.kind {
+                    exprs.push((init_expr, Type::I32));
+                }
+            }
+            (start, reader.original_position())
+        }
+        _ => return Ok(false),
+    };
+    exprs.retain(|(expr, _)| is_extended_const(expr));
+    if exprs.is_empty() {
+        return Ok(false);
+    }
+
+    let mut section = Vec::with_capacity(end - start);
+    let mut copied = start;
+    for (expr, ty) in &exprs {
+        validate_extended_const(expr, *ty, imported_globals)?;
+        let reader = expr.get_binary_reader();
+        let expr_start = reader.original_position();
+        section.extend_from_slice(&data[copied..expr_start]);
+        // A constant of the type of the expression, and the `end` of the expression.
+        match ty {
+            Type::I32 => section.extend_from_slice(&[0x41, 0x00, 0x0b]),
+            _ => section.extend_from_slice(&[0x42, 0x00, 0x0b]),
+        }
+        copied = expr_start + reader.bytes_remaining();
+    }
+    section.extend_from_slice(&data[copied..end]);
+    match payload {
+        Payload::GlobalSection(_) => {
+            validator.global_section(&GlobalSectionReader::new(&section, start).map_err(error)?)
+        }
+        Payload::ElementSection(_) => {
+            validator.element_section(&ElementSectionReader::new(&section, start).map_err(error)?)
+        }
+        _ => validator.data_section(&DataSectionReader::new(&section, start).map_err(error)?),
+    }
+    .map_err(error)?;
+    Ok(true)
+}
+
+/// Whether `expr` is made of more than one operator, which only extended constant expressions
+/// are.
+fn is_extended_const(expr: &InitExpr<'_>) -> bool {
+    let mut operators = expr.get_operators_reader();
+    !matches!(
+        (operators.read(), operators.read()),
+        (Ok(_), Ok(Operator::End))
+    )
+}
+
+/// Validates the extended constant expression `expr`, whose value must be of type `ty`.
+///
+/// The expressions may only read the immutable imported globals, of `imported_globals`.
+fn validate_extended_const(
+    expr: &InitExpr<'_>,
+    ty: Type,
+    imported_globals: &[GlobalType],
+) -> Result<(), CompileError> {
+    let mut operators = expr.get_operators_reader();
+    let mut stack = Vec::new();
+    loop {
+        let (op, offset) = operators
+            .read_with_offset()
+            .map_err(|e| CompileError::Validate(e.to_string()))?;
+        let error =
+            |message: &str| CompileError::Validate(format!("{} (at offset {})", message, offset));
+        // The type of the operands the operator pops two of, if any, and of its result.
+        let (operands, result) = match op {
+            Operator::I32Const { .. } => (None, Type::I32),
+            Operator::I64Const { .. } => (None, Type::I64),
+            Operator::GlobalGet { global_index } => {
+                match imported_globals.get(global_index as usize) {
+                    Some(global) if !global.mutable => (None, global.content_type),
+                    Some(_) => {
+                        return Err(error(
+                            "constant expression required: global.get of mutable global",
+                        ))
+                    }
+                    None => {
+                        return Err(error(
+                            "unknown global: global.get of locally defined global",
+                        ))
+                    }
+                }
+            }
+            Operator::I32Add | Operator::I32Sub | Operator::I32Mul => (Some(Type::I32), Type::I32),
+            Operator::I64Add | Operator::I64Sub | Operator::I64Mul => (Some(Type::I64), Type::I64),
+            Operator::End if stack == [ty] => return Ok(()),
+            Operator::End => return Err(error("type mismatch: invalid constant expression")),
+            _ => return Err(error("constant expression required: non-constant operator")),
+        };
+        if let Some(operand) = operands {
+            if stack.pop() != Some(operand) || stack.pop() != Some(operand) {
+                return Err(error("type mismatch: invalid constant expression"));
+            }
+        }
+        stack.push(result);
+    }
+}
+
+/// Validates `body`, showing each of its operators to `visit` once it is validated.
+fn validate_function_body(
+    validator: &mut FuncValidator<impl WasmModuleResources>,
+    body: &FunctionBody<'_>,
+    floats: bool,
+    mut visit: impl FnMut(usize, &Operator<'_>),
+) -> Result<(), OffsetError> {
+    let mut locals = body.get_locals_reader()?;
+    for _ in 0..locals.get_count() {
+        let offset = locals.original_position();
+        let (count, ty) = locals.read()?;
+        if !floats {
+            check_not_float(ty, offset)?;
+        }
+        validator.define_locals(offset, count, ty)?;
+    }
+    let mut operators = body.get_operators_reader()?;
+    while !operators.eof() {
+        let (op, offset) = operators.read_with_offset()?;
+        validator.op(offset, &op)?;
+        if !floats && uses_floats(&op) {
+            return Err(OffsetError::floats_disabled(offset));
+        }
+        visit(offset, &op);
+    }
+    Ok(validator.finish(operators.original_position())?)
+}
+
+/// An error found at `offset` in the module, like the `BinaryReaderError`s of the validator,
+/// which only it can create.
+struct OffsetError {
+    message: String,
+    offset: usize,
+}
+
+impl OffsetError {
+    /// The error of a module using floats when they are disabled.
+    fn floats_disabled(offset: usize) -> Self {
+        Self {
+            message: "floating-point support is not enabled".to_string(),
+            offset,
+        }
+    }
+}
+
+impl From<BinaryReaderError> for OffsetError {
+    fn from(error: BinaryReaderError) -> Self {
+        Self {
+            message: error.message().to_string(),
+            offset: error.offset(),
+        }
+    }
+}
+
+impl fmt::Display for OffsetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (at offset {})", self.message, self.offset)
+    }
+}
+
+/// Fails if `ty`, found at `offset`, is a floating point type.
+fn check_not_float(ty: Type, offset: usize) -> Result<(), OffsetError> {
+    match ty {
+        Type::F32 | Type::F64 => Err(OffsetError::floats_disabled(offset)),
+        _ => Ok(()),
+    }
+}
+
+/// Whether `op` takes or produces floats, or has a floating point type as its immediate.
+///
+/// The floats of a module all come from its types, locals, globals or operators, so the other
+/// operators, such as `select` or `block`, need no checking once these are.
+fn uses_floats(op: &Operator<'_>) -> bool {
+    match op {
+        Operator::TypedSelect { ty } => matches!(ty, Type::F32 | Type::F64),
+        _ => {
+            let name = operator_name(op);
+            name.contains("F32") || name.contains("F64")
+        }
+    }
+}
+
+/// Fails if the section `payload` declares a function type or a global with floats.
+fn validate_no_float_types(payload: &Payload<'_>) -> Result<(), OffsetError> {
+    match payload {
+        Payload::TypeSection(types) => {
+            let mut types = types.clone();
+            for _ in 0..types.get_count() {
+                let offset = types.original_position();
+                if let TypeDef::Func(ty) = types.read()? {
+                    for ty in ty.params.iter().chain(ty.returns.iter()) {
+                        check_not_float(*ty, offset)?;
+                    }
+                }
+            }
+        }
+        Payload::ImportSection(imports) => {
+            let mut imports = imports.clone();
+            for _ in 0..imports.get_count() {
+                let offset = imports.original_position();
+                if let ImportSectionEntryType::Global(ty) = imports.read()?.ty {
+                    check_not_float(ty.content_type, offset)?;
+                }
+            }
+        }
+        Payload::GlobalSection(globals) => {
+            let mut globals = globals.clone();
+            for _ in 0..globals.get_count() {
+                let offset = globals.original_position();
+                check_not_float(globals.read()?.ty.content_type, offset)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// The name of the operator of `body` at `offset`, if there is one.
+fn operator_at(body: &FunctionBody<'_>, offset: usize) -> Option<String> {
+    for operator in body.get_operators_reader().ok()?.into_iter_with_offsets() {
+        match operator {
+            Ok((op, operator_offset)) if operator_offset == offset => {
+                return Some(operator_name(&op))
+            }
+            Ok(_) => {}
+            Err(_) => return None,
+        }
+    }
+    None
+}
+
+/// The entities of a translated module, as seen by the validator of its functions.
+///
+/// These are the entities of the module info once the middlewares transformed it, so that
+/// the functions can use the entities the middlewares declared.
+#[derive(Debug)]
+pub struct ModuleResources<'a> {
+    module: &'a ModuleInfo,
+    module_translation: &'a ModuleTranslationState,
+}
+
+impl<'a> ModuleResources<'a> {
+    /// The resources of `module`.
+    pub fn new(module: &'a ModuleInfo, module_translation: &'a ModuleTranslationState) -> Self {
+        Self {
+            module,
+            module_translation,
+        }
+    }
+
+    /// Create the validator of the body of the function `index`, whose operators are then
+    /// given to it one by one.
+    pub fn function_validator(
+        &self,
+        index: LocalFunctionIndex,
+        features: &Features,
+        offset: usize,
+    ) -> Result<FuncValidator<&Self>, CompileError> {
+        let signature = self.module.functions[self.module.func_index(index)];
+        FuncValidator::new(
+            signature.as_u32(),
+            offset,
+            self,
+            &wasmparser_features(features),
+        )
+        .map_err(|e| CompileError::Validate(e.to_string()))
+    }
+}
+
+impl WasmModuleResources for ModuleResources<'_> {
+    type FuncType = FuncType;
+
+    fn table_at(&self, at: u32) -> Option<TableType> {
+        let table = self.module.tables.get(TableIndex::from_u32(at))?;
+        Some(TableType {
+            element_type: type_to_wptype(table.ty),
+            limits: ResizableLimits {
+                initial: table.minimum,
+                maximum: table.maximum,
+            },
+        })
+    }
+
+    fn memory_at(&self, at: u32) -> Option<MemoryType> {
+        let memory = self.module.memories.get(MemoryIndex::from_u32(at))?;
+        Some(if memory.memory64 {
+            MemoryType::M64 {
+                limits: ResizableLimits64 {
+                    initial: u64::from(memory.minimum.0),
+                    maximum: memory.maximum.map(|pages| u64::from(pages.0)),
+                },
+                shared: memory.shared,
+            }
+        } else {
+            MemoryType::M32 {
+                limits: ResizableLimits {
+                    initial: memory.minimum.0,
+                    maximum: memory.maximum.map(|pages| pages.0),
+                },
+                shared: memory.shared,
+            }
+        })
+    }
+
+    fn event_at(&self, _at: u32) -> Option<&FuncType> {
+        None
+    }
+
+    fn global_at(&self, at: u32) -> Option<GlobalType> {
+        let global = self.module.globals.get(GlobalIndex::from_u32(at))?;
+        Some(GlobalType {
+            content_type: type_to_wptype(global.ty),
+            mutable: global.mutability.is_mutable(),
+        })
+    }
+
+    fn func_type_at(&self, type_idx: u32) -> Option<&FuncType> {
+        let signature = SignatureIndex::from_u32(type_idx);
+        self.module_translation.wasm_types.get(signature)
+    }
+
+    fn type_of_function(&self, func_idx: u32) -> Option<&FuncType> {
+        let signature = self
+            .module
+            .functions
+            .get(FunctionIndex::from_u32(func_idx))?;
+        self.func_type_at(signature.as_u32())
+    }
+
+    fn element_type_at(&self, at: u32) -> Option<Type> {
+        // The element segments wasmer supports all hold functions.
+        if at < self.element_count() {
+            Some(Type::FuncRef)
+        } else {
+            None
+        }
+    }
+
+    fn element_count(&self) -> u32 {
+        self.module_translation.element_count
+    }
+
+    fn data_count(&self) -> u32 {
+        self.module_translation.data_count.unwrap_or(0)
+    }
+
+    fn is_function_referenced(&self, _idx: u32) -> bool {
+        // The module passed validation already, and a function referenced by a middleware
+        // doesn't have to be declared by an element segment or an export.
+        true
+    }
+}
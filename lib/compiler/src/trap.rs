@@ -1,4 +1,5 @@
 use crate::CodeOffset;
+use wasmer_types::MemoryIndex;
 use wasmer_vm::TrapCode;
 
 /// Information about trap.
@@ -8,4 +9,18 @@ pub struct TrapInformation {
     pub code_offset: CodeOffset,
     /// Code of the trap.
     pub trap_code: TrapCode,
+    /// The memory access out of bounds, for the traps raised with the wasm address of the
+    /// access.
+    pub memory_access: Option<MemoryAccess>,
+}
+
+/// The static part of a memory access.
+#[derive(rkyv::Serialize, rkyv::Deserialize, rkyv::Archive, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryAccess {
+    /// The memory accessed.
+    pub memory_index: MemoryIndex,
+    /// The offset of the access, added to its wasm address.
+    pub offset: u64,
+    /// The number of bytes accessed.
+    pub size: u32,
 }
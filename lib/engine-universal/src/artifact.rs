@@ -1,60 +1,318 @@
 //! Define `UniversalArtifact` to allow compiling and instantiating to be
 //! done as separate steps.
 
+use indexmap::IndexMap;
 use std::collections::BTreeMap;
 use std::convert::TryFrom;
 use std::sync::Arc;
-use wasmer_engine::InstantiationError;
+use wasmer_compiler::{CompileTiming, FrameLayout, FunctionAsm, FunctionStats, MachineStats};
+use wasmer_engine::{Engine, GlobalFrameInfoRegistration, InstantiationError};
 use wasmer_types::entity::{BoxedSlice, EntityRef, PrimaryMap};
 use wasmer_types::{
-    DataIndex, ElemIndex, FunctionIndex, GlobalInit, GlobalType, ImportCounts, LocalFunctionIndex,
-    LocalGlobalIndex, MemoryType, OwnedDataInitializer, OwnedTableInitializer, SignatureIndex,
-    TableType,
+    DataIndex, DataInitializer, DataInitializerLocation, ElemIndex, ExternType, FunctionIndex,
+    GlobalInit, GlobalType, ImportCounts, LocalFunctionIndex, LocalGlobalIndex, LocalTagIndex,
+    MemoryType, OwnedTableInitializer, SignatureIndex, TableType, TagType,
 };
 use wasmer_vm::{
-    Artifact, FunctionBodyPtr, FunctionExtent, InstanceHandle, Instantiatable, MemoryStyle,
-    Resolver, TableStyle, Tunables, VMImport, VMImportType, VMLocalFunction, VMOffsets,
-    VMSharedSignatureIndex,
+    Artifact, ArtifactBytes, FunctionBodyPtr, FunctionExtent, InstanceHandle, Instantiatable,
+    LimitedMemory, LimitedTable, MemoryStyle, Resolver, TableStyle, Tunables, VMImport,
+    VMImportType, VMLocalFunction, VMOffsets, VMSharedSignatureIndex, VMTag,
 };
 
 /// A compiled wasm module, containing everything necessary for instantiation.
 pub struct UniversalArtifact {
+    /// The frame information of the functions, unregistered before the engine can free their
+    /// code.
+    #[allow(dead_code)]
+    pub(crate) frame_info_registration: Option<GlobalFrameInfoRegistration>,
+    /// Why the code can't run on the host, if it was compiled for another target. The code of
+    /// such an artifact is never made executable.
+    pub(crate) host_mismatch: Option<String>,
+    /// The functions, trampolines and executable sections, loaded together.
+    pub(crate) code: FunctionExtent,
+    pub(crate) position_independent: bool,
     // TODO: figure out how to allocate fewer distinct structures onto heap. Maybe have an arena…?
     pub(crate) engine: crate::UniversalEngine,
+    /// The key the engine shares the artifact under with the other modules compiled from the
+    /// same binary, if it does.
+    pub(crate) deduplication_key: Option<crate::deduplication::ArtifactKey>,
+    /// The wasm binary of the module, if the engine compiled the artifact from it.
+    pub(crate) binary: Option<Arc<[u8]>>,
+    /// The BLAKE3 hash of the wasm binary of the module.
+    pub(crate) module_hash: [u8; blake3::OUT_LEN],
+    /// The names from the name section of the module.
+    pub(crate) module_name: Option<String>,
+    pub(crate) function_names: BTreeMap<FunctionIndex, String>,
+    /// The custom sections, with their names, in the order the module has them.
+    pub(crate) custom_sections: Vec<(String, ArtifactBytes)>,
     pub(crate) import_counts: ImportCounts,
     pub(crate) start_function: Option<FunctionIndex>,
     pub(crate) vmoffsets: VMOffsets,
     pub(crate) imports: Vec<VMImport>,
     pub(crate) dynamic_function_trampolines: BoxedSlice<FunctionIndex, FunctionBodyPtr>,
     pub(crate) functions: BoxedSlice<LocalFunctionIndex, VMLocalFunction>,
-    pub(crate) exports: BTreeMap<String, wasmer_types::ExportIndex>,
+    /// The exports, in the order the module declares them.
+    pub(crate) exports: IndexMap<String, wasmer_types::ExportIndex>,
     pub(crate) signatures: BoxedSlice<SignatureIndex, VMSharedSignatureIndex>,
     pub(crate) local_memories: Vec<(MemoryType, MemoryStyle)>,
-    pub(crate) data_segments: Vec<OwnedDataInitializer>,
-    pub(crate) passive_data: BTreeMap<DataIndex, Arc<[u8]>>,
+    pub(crate) data_segments: Vec<(DataInitializerLocation, ArtifactBytes)>,
+    pub(crate) passive_data: BTreeMap<DataIndex, ArtifactBytes>,
     pub(crate) local_tables: Vec<(TableType, TableStyle)>,
     pub(crate) element_segments: Vec<OwnedTableInitializer>,
     // TODO: does this need to be a BTreeMap? Can it be a plain vector?
     pub(crate) passive_elements: BTreeMap<ElemIndex, Box<[FunctionIndex]>>,
     pub(crate) local_globals: Vec<(GlobalType, GlobalInit)>,
+    pub(crate) local_tags: Vec<TagType>,
+    pub(crate) function_stats: Option<FunctionStats>,
+    pub(crate) function_asm: Option<PrimaryMap<LocalFunctionIndex, FunctionAsm>>,
+    pub(crate) frame_layouts: PrimaryMap<LocalFunctionIndex, Option<FrameLayout>>,
+    /// The time each phase of the compilation and loading took.
+    pub(crate) timing: CompileTiming,
+    /// The stubs and the code of the functions, if they are compiled lazily.
+    pub(crate) lazy_functions: Option<Box<crate::lazy::LazyFunctions>>,
+    /// The shared object holding the code, if it was loaded from one. It is the last field, to
+    /// be unloaded after everything referring to its code is dropped.
+    pub(crate) shared_object: Option<crate::shared_object::SharedObject>,
+    /// The file the artifact was loaded from, if it borrows its data from the mapping. It is
+    /// unmapped after the fields borrowing from it are dropped, like the shared object.
+    pub(crate) file: Option<crate::UniversalExecutableFile>,
 }
 
 impl UniversalArtifact {
+    /// Return the BLAKE3 hash of the wasm binary the module was compiled from, which
+    /// identifies it whatever the engine compiling it.
+    pub fn module_hash(&self) -> &[u8; blake3::OUT_LEN] {
+        &self.module_hash
+    }
+
+    /// Return the wasm binary the module was compiled from, if the engine compiled the artifact
+    /// from it with [`UniversalEngine::compile_deduplicated`] rather than loaded it from an
+    /// executable.
+    ///
+    /// [`UniversalEngine::compile_deduplicated`]: crate::UniversalEngine::compile_deduplicated
+    pub fn binary(&self) -> Option<&[u8]> {
+        self.binary.as_deref()
+    }
+
+    /// Return the name the name section of the module gives it, if any.
+    pub fn name(&self) -> Option<&str> {
+        self.module_name.as_deref()
+    }
+
+    /// Return the names the name section of the module gives its functions, imported ones
+    /// included, in the order of their indices.
+    pub fn function_names(&self) -> impl Iterator<Item = (FunctionIndex, &str)> + '_ {
+        self.function_names
+            .iter()
+            .map(|(index, name)| (*index, &**name))
+    }
+
+    /// Return the names of the exports and what they refer to, in the order the module
+    /// declares them.
+    pub fn exports(&self) -> impl Iterator<Item = (&str, wasmer_types::ExportIndex)> + '_ {
+        self.exports
+            .iter()
+            .map(|(name, index)| (&**name, index.clone()))
+    }
+
+    /// Return the imports of the module, in the order the module declares them.
+    pub fn imports(&self) -> &[VMImport] {
+        &self.imports
+    }
+
+    /// Return the types and initializers of the globals the module defines, including those
+    /// the middlewares of the compiler appended, in the order of their indices.
+    pub fn local_globals(&self) -> &[(GlobalType, GlobalInit)] {
+        &self.local_globals
+    }
+
+    /// Return the type of what an export refers to.
+    pub fn export_type(&self, index: &wasmer_types::ExportIndex) -> ExternType {
+        use wasmer_types::ExportIndex;
+        match *index {
+            ExportIndex::Function(index) => ExternType::Function(
+                self.function_signature(index)
+                    .and_then(|sig| self.engine.lookup_signature(sig))
+                    .expect("the signatures of the functions are registered"),
+            ),
+            ExportIndex::Table(index) => match self.import_counts.local_table_index(index) {
+                Ok(local) => ExternType::Table(self.local_tables[local.index()].0),
+                Err(import) => self.imported_type(import.index(), |ty| match ty {
+                    VMImportType::Table(ty) => Some(ExternType::Table(*ty)),
+                    _ => None,
+                }),
+            },
+            ExportIndex::Memory(index) => match self.import_counts.local_memory_index(index) {
+                Ok(local) => ExternType::Memory(self.local_memories[local.index()].0),
+                Err(import) => self.imported_type(import.index(), |ty| match ty {
+                    VMImportType::Memory(ty, _) => Some(ExternType::Memory(*ty)),
+                    _ => None,
+                }),
+            },
+            ExportIndex::Global(index) => match self.import_counts.local_global_index(index) {
+                Ok(local) => ExternType::Global(self.local_globals[local.index()].0),
+                Err(import) => self.imported_type(import.index(), |ty| match ty {
+                    VMImportType::Global(ty) => Some(ExternType::Global(*ty)),
+                    _ => None,
+                }),
+            },
+            ExportIndex::Tag(index) => match self.import_counts.local_tag_index(index) {
+                Ok(local) => ExternType::Tag(self.local_tags[local.index()].clone()),
+                Err(import) => self.imported_type(import.index(), |ty| match ty {
+                    VMImportType::Tag(ty) => Some(ExternType::Tag(ty.clone())),
+                    _ => None,
+                }),
+            },
+        }
+    }
+
+    /// The type of the `nth` import `ty` picks a type for, which are the imports of one kind.
+    fn imported_type(
+        &self,
+        nth: usize,
+        ty: impl Fn(&VMImportType) -> Option<ExternType>,
+    ) -> ExternType {
+        self.imports
+            .iter()
+            .filter_map(|import| ty(&import.ty))
+            .nth(nth)
+            .expect("the exported imports are imported")
+    }
+
+    /// Return the contents of the custom sections of the module with the name `name`, in the
+    /// order the module has them.
+    pub fn custom_sections<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a [u8]> + 'a {
+        self.custom_sections
+            .iter()
+            .filter(move |(section_name, _)| section_name == name)
+            .map(|(_, data)| &**data)
+    }
+
     /// Return the extents of the specified local function.
+    ///
+    /// A function compiled lazily is called through a stub until it is compiled, and this is
+    /// the extent of the stub until then.
     pub fn function_extent(&self, index: LocalFunctionIndex) -> Option<FunctionExtent> {
         let func = self.functions.get(index)?;
+        if let Some(extent) = self
+            .lazy_functions
+            .as_ref()
+            .and_then(|functions| functions.compiled_extent(index))
+        {
+            return Some(extent);
+        }
         Some(FunctionExtent {
             address: func.body,
             length: usize::try_from(func.length).unwrap(),
         })
     }
 
+    /// The linked code of the functions, trampolines and executable sections, which are loaded
+    /// together.
+    pub fn code(&self) -> &[u8] {
+        // SAFETY: the engine keeps the code it loaded until it is dropped, and the artifact holds
+        // a reference to the engine, or the shared object holding the code.
+        unsafe { std::slice::from_raw_parts(*self.code.address as *const u8, self.code.length) }
+    }
+
+    /// Whether the [code](Self::code) is the same wherever it is loaded, so that the pages
+    /// holding it can be shared, by processes mapping them at different addresses for instance.
+    ///
+    /// Such code refers to itself relatively to the program counter, and to the rest of the
+    /// runtime through the vmctx. The absolute addresses the engine still patches in, like
+    /// those of the unwind information, are on the pages of the data sections, which each
+    /// process keeps to itself.
+    pub fn is_position_independent(&self) -> bool {
+        self.position_independent
+    }
+
+    /// The bytes of code of the local functions, or of their stubs if they are compiled
+    /// lazily.
+    pub(crate) fn code_size(&self) -> usize {
+        self.functions
+            .values()
+            .map(|function| usize::try_from(function.length).unwrap())
+            .sum()
+    }
+
+    /// Return how many of the local functions are compiled.
+    ///
+    /// All of them are unless the module was compiled in
+    /// [`ModuleCompileMode::Lazy`](crate::ModuleCompileMode::Lazy), where each function is
+    /// compiled the first time it is called.
+    pub fn compiled_function_count(&self) -> usize {
+        match &self.lazy_functions {
+            Some(functions) => functions.compiled_count(),
+            None => self.functions.len(),
+        }
+    }
+
+    /// The time each phase of the compilation and loading of the artifact took.
+    ///
+    /// The artifacts compiled with
+    /// [`UniversalEngine::compile_deduplicated`](crate::UniversalEngine::compile_deduplicated)
+    /// time every phase, while those loaded from an executable only time its relocation.
+    pub fn compile_timing(&self) -> CompileTiming {
+        self.timing
+    }
+
+    /// Return the register allocation statistics for every local function.
+    ///
+    /// This is `None` unless the module was compiled with
+    /// [`UniversalEngine::set_collect_function_stats`](crate::UniversalEngine::set_collect_function_stats)
+    /// enabled and by a compiler that collects them.
+    pub fn function_stats(&self) -> Option<&FunctionStats> {
+        self.function_stats.as_ref()
+    }
+
+    /// Return the register allocation statistics of the specified local function.
+    pub fn function_stat(&self, index: LocalFunctionIndex) -> Option<&MachineStats> {
+        self.function_stats.as_ref()?.get(index)
+    }
+
+    /// Return the listing of the instructions emitted for the specified function.
+    ///
+    /// Each instruction is shown with its offset in the function body, after a marker for the
+    /// wasm operator it lowers. The listing is empty for imported functions, and unless the
+    /// module was compiled by a compiler asked to record it, such as Singlepass with
+    /// `emit_debug_asm`.
+    pub fn dump_function_asm(&self, index: FunctionIndex) -> String {
+        let index = match self.import_counts.local_function_index(index) {
+            Ok(index) => index,
+            Err(_) => return String::new(),
+        };
+        self.function_asm
+            .as_ref()
+            .and_then(|listings| listings.get(index))
+            .map_or_else(String::new, ToString::to_string)
+    }
+
+    /// Return the stack frame layout of every local function.
+    ///
+    /// The layout of a function is `None` if the compiler that produced it does not describe it.
+    pub fn frame_layouts(&self) -> &PrimaryMap<LocalFunctionIndex, Option<FrameLayout>> {
+        &self.frame_layouts
+    }
+
+    /// Return the stack frame layout of the specified local function.
+    pub fn frame_layout(&self, index: LocalFunctionIndex) -> Option<&FrameLayout> {
+        self.frame_layouts.get(index)?.as_ref()
+    }
+
     /// Return the engine instance this artifact is loaded into.
     pub fn engine(&self) -> &crate::UniversalEngine {
         &self.engine
     }
 }
 
+impl Drop for UniversalArtifact {
+    fn drop(&mut self) {
+        if let Some(key) = &self.deduplication_key {
+            self.engine.deduplication().evict(key, self);
+        }
+    }
+}
+
 impl Instantiatable for UniversalArtifact {
     type Error = InstantiationError;
 
@@ -65,6 +323,9 @@ impl Instantiatable for UniversalArtifact {
         host_state: Box<dyn std::any::Any>,
         config: wasmer_types::InstanceConfig,
     ) -> Result<InstanceHandle, Self::Error> {
+        if let Some(reason) = &self.host_mismatch {
+            return Err(InstantiationError::IncompatibleTarget(reason.clone()));
+        }
         let (imports, import_function_envs) = {
             let mut imports = wasmer_engine::resolve_imports(
                 &self.engine,
@@ -82,6 +343,7 @@ impl Instantiatable for UniversalArtifact {
             (imports, import_function_envs)
         };
 
+        let limiter = tunables.resource_limiter();
         let (allocator, memory_definition_locations, table_definition_locations) =
             wasmer_vm::InstanceAllocator::new(self.vmoffsets.clone());
 
@@ -89,14 +351,19 @@ impl Instantiatable for UniversalArtifact {
         let mut memories: PrimaryMap<wasmer_types::LocalMemoryIndex, _> =
             PrimaryMap::with_capacity(self.local_memories.len());
         for (idx, (ty, style)) in (self.import_counts.memories..).zip(self.local_memories.iter()) {
-            let memory = tunables
+            let memory_error = |e| {
+                InstantiationError::Link(wasmer_engine::LinkError::Resource(format!(
+                    "Failed to create memory: {}",
+                    e
+                )))
+            };
+            let mut memory = tunables
                 .create_vm_memory(&ty, &style, memory_definition_locations[idx as usize])
-                .map_err(|e| {
-                    InstantiationError::Link(wasmer_engine::LinkError::Resource(format!(
-                        "Failed to create memory: {}",
-                        e
-                    )))
-                })?;
+                .map_err(memory_error)?;
+            if let Some(limiter) = &limiter {
+                memory =
+                    Arc::new(LimitedMemory::new(memory, limiter.clone()).map_err(memory_error)?);
+            }
             memories.push(memory);
         }
 
@@ -104,9 +371,14 @@ impl Instantiatable for UniversalArtifact {
         let mut tables: PrimaryMap<wasmer_types::LocalTableIndex, _> =
             PrimaryMap::with_capacity(self.local_tables.len());
         for (idx, (ty, style)) in (self.import_counts.tables..).zip(self.local_tables.iter()) {
-            let table = tunables
+            let mut table = tunables
                 .create_vm_table(ty, style, table_definition_locations[idx as usize])
                 .map_err(|e| InstantiationError::Link(wasmer_engine::LinkError::Resource(e)))?;
+            if let Some(limiter) = &limiter {
+                table = Arc::new(LimitedTable::new(table, limiter.clone()).map_err(|e| {
+                    InstantiationError::Link(wasmer_engine::LinkError::Resource(e))
+                })?);
+            }
             tables.push(table);
         }
 
@@ -117,18 +389,36 @@ impl Instantiatable for UniversalArtifact {
             globals.push(Arc::new(wasmer_vm::Global::new(*ty)));
         }
 
-        let passive_data = self.passive_data.clone();
+        // Tags, which are new for each instance
+        let tags = self
+            .local_tags
+            .iter()
+            .map(|ty| VMTag::new(ty.clone()))
+            .collect::<PrimaryMap<LocalTagIndex, _>>();
+
+        if let Some(limiter) = &limiter {
+            if !limiter.instance_created() {
+                return Err(InstantiationError::Link(
+                    wasmer_engine::LinkError::Resource(
+                        "the resource limiter doesn't allow creating more instances".to_string(),
+                    ),
+                ));
+            }
+        }
+
         Ok(InstanceHandle::new(
             self,
             allocator,
             memories.into_boxed_slice(),
             tables.into_boxed_slice(),
             globals.into_boxed_slice(),
+            tags.into_boxed_slice(),
             imports,
-            passive_data,
             host_state,
             import_function_envs,
             config,
+            tunables.memory_grow_callback(),
+            limiter,
         ))
     }
 }
@@ -154,8 +444,19 @@ impl Artifact for UniversalArtifact {
         &self.element_segments[..]
     }
 
-    fn data_segments(&self) -> &[OwnedDataInitializer] {
-        &self.data_segments[..]
+    fn data_segments(&self) -> Box<dyn Iterator<Item = DataInitializer<'_>> + '_> {
+        Box::new(
+            self.data_segments
+                .iter()
+                .map(|(location, data)| DataInitializer {
+                    location: location.clone(),
+                    data,
+                }),
+        )
+    }
+
+    fn passive_data(&self) -> &BTreeMap<DataIndex, ArtifactBytes> {
+        &self.passive_data
     }
 
     fn globals(&self) -> &[(GlobalType, GlobalInit)] {
@@ -1,12 +1,37 @@
-use crate::UniversalEngine;
+use crate::{ProfilingStrategy, UniversalEngine};
 use wasmer_compiler::{CompilerConfig, Features, Target};
 
+/// When the engine compiles the functions of the modules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModuleCompileMode {
+    /// All the functions of a module are compiled with the module.
+    Eager,
+    /// Compiling a module only translates it, and each of its functions is compiled the first
+    /// time it is called, by any instance of the module.
+    ///
+    /// The modules are still validated when they are created. The errors compiling a function
+    /// are raised as traps of the call to it, and since the module isn't compiled, it can't be
+    /// serialized. The functions are compiled on the stack of the thread calling them, which
+    /// needs room for the compiler, and their code is kept until the engine is dropped, as is
+    /// the code of the modules compiled eagerly.
+    Lazy,
+}
+
+impl Default for ModuleCompileMode {
+    fn default() -> Self {
+        Self::Eager
+    }
+}
+
 /// The Universal builder
 pub struct Universal {
     #[allow(dead_code)]
     compiler_config: Option<Box<dyn CompilerConfig>>,
     target: Option<Target>,
     features: Option<Features>,
+    collect_function_stats: bool,
+    compile_mode: ModuleCompileMode,
+    profiling: Option<ProfilingStrategy>,
 }
 
 impl Universal {
@@ -19,6 +44,9 @@ impl Universal {
             compiler_config: Some(compiler_config.into()),
             target: None,
             features: None,
+            collect_function_stats: false,
+            compile_mode: ModuleCompileMode::Eager,
+            profiling: None,
         }
     }
 
@@ -28,6 +56,9 @@ impl Universal {
             compiler_config: None,
             target: None,
             features: None,
+            collect_function_stats: false,
+            compile_mode: ModuleCompileMode::Eager,
+            profiling: None,
         }
     }
 
@@ -43,6 +74,24 @@ impl Universal {
         self
     }
 
+    /// Collect per-function register allocation statistics when compiling
+    pub fn function_stats(mut self, collect: bool) -> Self {
+        self.collect_function_stats = collect;
+        self
+    }
+
+    /// Set when the functions of the modules are compiled
+    pub fn compile_mode(mut self, mode: ModuleCompileMode) -> Self {
+        self.compile_mode = mode;
+        self
+    }
+
+    /// Describe the published functions to external profilers the way `strategy` says
+    pub fn enable_profiling(mut self, strategy: ProfilingStrategy) -> Self {
+        self.profiling = Some(strategy);
+        self
+    }
+
     /// Build the `UniversalEngine` for this configuration
     #[cfg(feature = "compiler")]
     pub fn engine(self) -> UniversalEngine {
@@ -52,15 +101,23 @@ impl Universal {
                 .features
                 .unwrap_or_else(|| compiler_config.default_features_for_target(&target));
             let compiler = compiler_config.compiler();
-            UniversalEngine::new(compiler, target, features)
+            let engine = UniversalEngine::new(compiler, target, features);
+            engine.set_collect_function_stats(self.collect_function_stats);
+            engine.set_compile_mode(self.compile_mode);
+            engine.set_profiling(self.profiling);
+            engine
         } else {
-            UniversalEngine::headless()
+            let engine = UniversalEngine::headless();
+            engine.set_profiling(self.profiling);
+            engine
         }
     }
 
     /// Build the `UniversalEngine` for this configuration
     #[cfg(not(feature = "compiler"))]
     pub fn engine(self) -> UniversalEngine {
-        UniversalEngine::headless()
+        let engine = UniversalEngine::headless();
+        engine.set_profiling(self.profiling);
+        engine
     }
 }
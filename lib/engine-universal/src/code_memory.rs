@@ -4,6 +4,7 @@
 //! Memory management for executable code.
 use crate::unwind::UnwindRegistry;
 use wasmer_compiler::{CompiledFunctionUnwindInfoRef, CustomSectionRef, FunctionBodyRef};
+use wasmer_engine::CodeMemoryUsage;
 use wasmer_vm::{Mmap, VMFunctionBody};
 
 /// The optimal alignment for functions.
@@ -18,10 +19,17 @@ const ARCH_FUNCTION_ALIGNMENT: usize = 16;
 const DATA_SECTION_ALIGNMENT: usize = 64;
 
 /// Memory manager for executable code.
+///
+/// The memory is writable while the code is copied and linked, and once published, the pages
+/// of the code are readable and executable and those of the data only readable: no page is
+/// ever writable and executable at once. On Apple Silicon, where the code has to be mapped with
+/// `MAP_JIT`, the writes are allowed by thread instead, from the allocation to the publication,
+/// so both must happen on the same thread.
 pub struct CodeMemory {
     unwind_registry: UnwindRegistry,
     mmap: Mmap,
     start_of_nonexecutable_pages: usize,
+    published: bool,
 }
 
 impl CodeMemory {
@@ -31,9 +39,23 @@ impl CodeMemory {
             unwind_registry: UnwindRegistry::new(),
             mmap: Mmap::new(),
             start_of_nonexecutable_pages: 0,
+            published: false,
+        }
+    }
+
+    /// The executable memory this takes.
+    pub fn usage(&self) -> CodeMemoryUsage {
+        CodeMemoryUsage {
+            allocated: round_up(self.start_of_nonexecutable_pages, region::page::size()),
+            used: self.start_of_nonexecutable_pages,
         }
     }
 
+    /// The functions and executable sections, as allocated.
+    pub fn code(&self) -> &[u8] {
+        &self.mmap.as_slice()[..self.start_of_nonexecutable_pages]
+    }
+
     /// Mutably get the UnwindRegistry.
     pub fn unwind_registry_mut(&mut self) -> &mut UnwindRegistry {
         &mut self.unwind_registry
@@ -78,7 +100,10 @@ impl CodeMemory {
 
         // 2. Allocate the pages. Mark them all read-write.
 
-        self.mmap = Mmap::with_at_least(total_len)?;
+        if self.published {
+            return Err("the code memory has already been published".to_string());
+        }
+        self.mmap = Self::map(total_len)?;
 
         // 3. Determine where the pointers to each function, executable section
         // or data section are. Copy the functions. Collect the addresses of each and return them.
@@ -135,20 +160,78 @@ impl CodeMemory {
         ))
     }
 
-    /// Apply the page permissions.
-    pub fn publish(&mut self) {
-        if self.mmap.is_empty() || self.start_of_nonexecutable_pages == 0 {
-            return;
+    /// Allocate the pages for `len` bytes of code and data, and make them writable.
+    #[cfg(not(all(target_os = "macos", target_arch = "aarch64")))]
+    fn map(len: usize) -> Result<Mmap, String> {
+        Mmap::with_at_least(len)
+    }
+
+    /// Allocate the pages for `len` bytes of code and data, and make them writable by this
+    /// thread.
+    #[cfg(all(target_os = "macos", target_arch = "aarch64"))]
+    fn map(len: usize) -> Result<Mmap, String> {
+        let mmap = Mmap::jit_with_at_least(len)?;
+        if !mmap.is_empty() {
+            unsafe { apple::pthread_jit_write_protect_np(0) };
+        }
+        Ok(mmap)
+    }
+
+    /// Apply the page permissions: the code becomes readable and executable, and the data
+    /// sections only readable.
+    ///
+    /// The code must not be written to after this, so it must already be linked.
+    pub fn publish(&mut self) -> Result<(), String> {
+        if self.published {
+            return Err("the code memory has already been published".to_string());
+        }
+        self.published = true;
+        if self.mmap.is_empty() {
+            return Ok(());
         }
         assert!(self.mmap.len() >= self.start_of_nonexecutable_pages);
+        let start_of_data_pages = round_up(self.start_of_nonexecutable_pages, region::page::size());
+        let data_len = self.mmap.len() - start_of_data_pages;
+        let base = self.mmap.as_mut_ptr();
+        self.protect_code(base)?;
+        if data_len != 0 {
+            unsafe {
+                region::protect(
+                    base.add(start_of_data_pages),
+                    data_len,
+                    region::Protection::READ,
+                )
+            }
+            .map_err(|e| format!("unable to make the data sections read-only: {}", e))?;
+        }
+        Ok(())
+    }
+
+    /// Make the pages of the code readable and executable instead of writable.
+    #[cfg(not(all(target_os = "macos", target_arch = "aarch64")))]
+    fn protect_code(&self, base: *mut u8) -> Result<(), String> {
+        if self.start_of_nonexecutable_pages == 0 {
+            return Ok(());
+        }
         unsafe {
             region::protect(
-                self.mmap.as_mut_ptr(),
+                base,
                 self.start_of_nonexecutable_pages,
                 region::Protection::READ_EXECUTE,
             )
         }
-        .expect("unable to make memory readonly and executable");
+        .map_err(|e| format!("unable to make the code read-only and executable: {}", e))
+    }
+
+    /// Stop this thread, the only one allowed to write to the pages mapped with `MAP_JIT`, from
+    /// writing to them, and drop the stale instructions the instruction cache may hold.
+    #[cfg(all(target_os = "macos", target_arch = "aarch64"))]
+    fn protect_code(&self, base: *mut u8) -> Result<(), String> {
+        unsafe {
+            apple::pthread_jit_write_protect_np(1);
+            apple::sys_icache_invalidate(base.cast(), self.start_of_nonexecutable_pages);
+        }
+        Ok(())
     }
 
     /// Calculates the allocation size of the given compiled function.
@@ -208,6 +291,16 @@ impl CodeMemory {
     }
 }
 
+#[cfg(all(target_os = "macos", target_arch = "aarch64"))]
+mod apple {
+    use std::os::raw::{c_int, c_void};
+
+    extern "C" {
+        pub fn pthread_jit_write_protect_np(enabled: c_int);
+        pub fn sys_icache_invalidate(start: *mut c_void, len: usize);
+    }
+}
+
 fn round_up(size: usize, multiple: usize) -> usize {
     debug_assert!(multiple.is_power_of_two());
     (size + (multiple - 1)) & !(multiple - 1)
@@ -0,0 +1,142 @@
+//! Sharing the artifacts an engine loads from the same binaries between their modules, so that
+//! each binary is compiled and its code mapped once however many modules it backs.
+
+use crate::UniversalArtifact;
+#[cfg(feature = "compiler")]
+use crate::{Fingerprint, ModuleCompileMode};
+use std::collections::HashMap;
+#[cfg(feature = "compiler")]
+use std::sync::Arc;
+use std::sync::{Condvar, Mutex, MutexGuard, Weak};
+#[cfg(feature = "compiler")]
+use wasmer_compiler::CompileError;
+use wasmer_engine::CacheStats;
+
+/// What identifies the artifacts an engine loads from a binary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct ArtifactKey([u8; blake3::OUT_LEN]);
+
+impl ArtifactKey {
+    /// The key of the artifact loaded from `wasm` by an engine with `fingerprint`, compiling it
+    /// in `mode` and collecting the statistics of its functions if `collect_function_stats`.
+    #[cfg(feature = "compiler")]
+    pub(crate) fn new(
+        wasm: &[u8],
+        fingerprint: &Fingerprint,
+        mode: ModuleCompileMode,
+        collect_function_stats: bool,
+    ) -> Self {
+        let mut hasher = blake3::Hasher::new();
+        let settings = format!("{:?} {:?} {}", fingerprint, mode, collect_function_stats);
+        hasher.update(settings.as_bytes());
+        // The debug output never contains a null byte, so it can't run into the wasm.
+        hasher.update(&[0]);
+        hasher.update(wasm);
+        Self(*hasher.finalize().as_bytes())
+    }
+}
+
+enum Entry {
+    /// The artifact is being compiled and loaded by another thread.
+    Loading,
+    /// The artifact is loaded, and referenced by the modules holding it.
+    Loaded(Weak<UniversalArtifact>),
+}
+
+#[derive(Default)]
+struct State {
+    artifacts: HashMap<ArtifactKey, Entry>,
+    stats: CacheStats,
+}
+
+/// The artifacts an engine loaded and which are still held.
+#[derive(Default)]
+pub(crate) struct Deduplication {
+    state: Mutex<State>,
+    /// Notified when an artifact is done loading, successfully or not.
+    loaded: Condvar,
+}
+
+impl Deduplication {
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    pub(crate) fn stats(&self) -> CacheStats {
+        self.state().stats
+    }
+
+    /// Return the artifact loaded for `key` if it is still held and fits, or load it with
+    /// `load`. A thread loading the same key meanwhile waits for this one to finish, and
+    /// loads it itself if this fails.
+    #[cfg(feature = "compiler")]
+    pub(crate) fn get_or_load(
+        &self,
+        key: ArtifactKey,
+        fits: impl Fn(&UniversalArtifact) -> bool,
+        load: impl FnOnce() -> Result<UniversalArtifact, CompileError>,
+    ) -> Result<Arc<UniversalArtifact>, CompileError> {
+        let mut state = self.state();
+        loop {
+            let artifact = match state.artifacts.get(&key) {
+                Some(Entry::Loading) => {
+                    state = self.loaded.wait(state).unwrap();
+                    continue;
+                }
+                Some(Entry::Loaded(artifact)) => artifact.upgrade(),
+                None => None,
+            };
+            match artifact {
+                Some(artifact) if fits(&artifact) => {
+                    state.stats.hits += 1;
+                    state.stats.bytes_saved += artifact.code_size() as u64;
+                    return Ok(artifact);
+                }
+                Some(artifact) => {
+                    // Another artifact takes the place of this one, which must be released
+                    // without holding the lock, see `evict`.
+                    state.stats.misses += 1;
+                    drop(state);
+                    drop(artifact);
+                    return load().map(Arc::new);
+                }
+                // The last module holding it is being dropped, or the artifact wasn't loaded.
+                None => break,
+            }
+        }
+        state.artifacts.insert(key, Entry::Loading);
+        state.stats.misses += 1;
+        drop(state);
+
+        /// Removes the entry of the artifact being loaded if loading it fails or panics.
+        struct Loading<'a>(&'a Deduplication, ArtifactKey);
+
+        impl Drop for Loading<'_> {
+            fn drop(&mut self) {
+                self.0.state().artifacts.remove(&self.1);
+                self.0.loaded.notify_all();
+            }
+        }
+
+        let loading = Loading(self, key);
+        let mut artifact = load()?;
+        artifact.deduplication_key = Some(key);
+        let artifact = Arc::new(artifact);
+        std::mem::forget(loading);
+        self.state()
+            .artifacts
+            .insert(key, Entry::Loaded(Arc::downgrade(&artifact)));
+        self.loaded.notify_all();
+        Ok(artifact)
+    }
+
+    /// Forget `artifact`, which is being dropped, if it's the one loaded for `key`.
+    pub(crate) fn evict(&self, key: &ArtifactKey, artifact: *const UniversalArtifact) {
+        let mut state = self.state();
+        if let Some(Entry::Loaded(loaded)) = state.artifacts.get(key) {
+            if loaded.as_ptr() == artifact {
+                state.artifacts.remove(key);
+            }
+        }
+    }
+}
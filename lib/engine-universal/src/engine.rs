@@ -1,28 +1,41 @@
 //! Universal compilation.
 
-use crate::executable::{unrkyv, UniversalExecutableRef};
-use crate::{CodeMemory, UniversalArtifact, UniversalExecutable};
-use rkyv::de::deserializers::SharedDeserializeMap;
+#[cfg(feature = "compiler")]
+use crate::deduplication::ArtifactKey;
+use crate::deduplication::Deduplication;
+use crate::executable::{unrkyv, UniversalExecutableFile, UniversalExecutableRef};
+use crate::lazy::{FunctionSources, LazyFunctions, LazyUniversalExecutable, STUB_SIZE};
+use crate::profiling::{self, PublishedFunction};
+use crate::shared_object::SharedObject;
+use crate::{
+    CodeMemory, Fingerprint, ModuleCompileMode, ProfilingStrategy, UniversalArtifact,
+    UniversalExecutable,
+};
+use indexmap::IndexMap;
 use std::collections::BTreeMap;
 use std::convert::TryFrom;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
-#[cfg(feature = "compiler")]
-use wasmer_compiler::Compiler;
+use std::time::Instant;
 use wasmer_compiler::{
-    CompileError, CustomSectionProtection, CustomSectionRef, FunctionBodyRef, JumpTable,
-    SectionIndex, Target,
+    CompileError, CompileTiming, CustomSectionProtection, CustomSectionRef, FunctionAddressMap,
+    FunctionBodyRef, JumpTable, SectionIndex, Target,
+};
+#[cfg(feature = "compiler")]
+use wasmer_compiler::{CompileProgress, Compiler, ModuleMiddlewareChain};
+use wasmer_engine::{
+    register_frame_info, CacheStats, CodeMemoryUsage, DeserializeError, Engine, EngineId,
 };
-use wasmer_engine::{Engine, EngineId};
 use wasmer_types::entity::{EntityRef, PrimaryMap};
 use wasmer_types::{
-    DataInitializer, ExportIndex, Features, FunctionIndex, FunctionType, FunctionTypeRef,
-    GlobalInit, GlobalType, ImportCounts, ImportIndex, LocalFunctionIndex, LocalGlobalIndex,
-    MemoryIndex, SignatureIndex, TableIndex,
+    CustomSectionIndex, DataInitializer, ExportIndex, Features, FunctionIndex, FunctionType,
+    FunctionTypeRef, GlobalInit, GlobalType, ImportCounts, ImportIndex, LocalFunctionIndex,
+    LocalGlobalIndex, MemoryIndex, SignatureIndex, TableIndex, TagType,
 };
 use wasmer_vm::{
-    FuncDataRegistry, FunctionBodyPtr, SectionBodyPtr, SignatureRegistry, Tunables,
-    VMCallerCheckedAnyfunc, VMFuncRef, VMFunctionBody, VMImportType, VMLocalFunction, VMOffsets,
-    VMSharedSignatureIndex, VMTrampoline,
+    ArtifactBytes, FuncDataRegistry, FunctionBodyPtr, FunctionExtent, SectionBodyPtr,
+    SignatureRegistry, Tunables, VMCallerCheckedAnyfunc, VMFuncRef, VMFunctionBody, VMImportType,
+    VMLocalFunction, VMOffsets, VMSharedSignatureIndex, VMTrampoline,
 };
 
 /// A WebAssembly `Universal` Engine.
@@ -32,6 +45,8 @@ pub struct UniversalEngine {
     /// The target for the compiler
     target: Arc<Target>,
     engine_id: EngineId,
+    /// The artifacts shared by the modules compiled from the same binaries.
+    deduplication: Arc<Deduplication>,
 }
 
 impl UniversalEngine {
@@ -45,9 +60,13 @@ impl UniversalEngine {
                 signatures: SignatureRegistry::new(),
                 func_data: Arc::new(FuncDataRegistry::new()),
                 features,
+                collect_function_stats: false,
+                compile_mode: ModuleCompileMode::Eager,
+                profiling: None,
             })),
             target: Arc::new(target),
             engine_id: EngineId::default(),
+            deduplication: Arc::default(),
         }
     }
 
@@ -73,9 +92,13 @@ impl UniversalEngine {
                 signatures: SignatureRegistry::new(),
                 func_data: Arc::new(FuncDataRegistry::new()),
                 features: Features::default(),
+                collect_function_stats: false,
+                compile_mode: ModuleCompileMode::Eager,
+                profiling: None,
             })),
             target: Arc::new(Target::default()),
             engine_id: EngineId::default(),
+            deduplication: Arc::default(),
         }
     }
 
@@ -87,19 +110,176 @@ impl UniversalEngine {
         self.inner.lock().unwrap()
     }
 
+    pub(crate) fn deduplication(&self) -> &Deduplication {
+        &self.deduplication
+    }
+
+    /// Request per-function register allocation statistics for modules
+    /// compiled by this engine.
+    ///
+    /// The statistics are available through
+    /// [`UniversalArtifact::function_stats`] once the module is loaded.
+    pub fn set_collect_function_stats(&self, collect: bool) {
+        self.inner_mut().collect_function_stats = collect;
+    }
+
+    /// Set when the functions of the modules compiled by this engine are compiled.
+    pub fn set_compile_mode(&self, mode: ModuleCompileMode) {
+        self.inner_mut().compile_mode = mode;
+    }
+
+    /// When the functions of the modules compiled by this engine are compiled.
+    pub fn compile_mode(&self) -> ModuleCompileMode {
+        self.inner().compile_mode
+    }
+
+    /// Set how the functions this engine publishes are described to external profilers, if
+    /// they are.
+    ///
+    /// Only the functions published afterwards are described.
+    pub fn set_profiling(&self, strategy: Option<ProfilingStrategy>) {
+        self.inner_mut().profiling = strategy;
+    }
+
     /// Compile a WebAssembly binary
     #[cfg(feature = "compiler")]
-    #[tracing::instrument(skip_all)]
     pub fn compile_universal(
         &self,
         binary: &[u8],
         tunables: &dyn Tunables,
     ) -> Result<crate::UniversalExecutable, CompileError> {
+        self.compile_universal_with_progress(binary, tunables, &CompileProgress::default())
+    }
+
+    /// Compile a WebAssembly binary, reporting each compiled function to `progress`
+    #[cfg(feature = "compiler")]
+    #[tracing::instrument(skip_all)]
+    pub fn compile_universal_with_progress(
+        &self,
+        binary: &[u8],
+        tunables: &dyn Tunables,
+        progress: &CompileProgress,
+    ) -> Result<crate::UniversalExecutable, CompileError> {
+        self.compile_universal_in_mode(binary, tunables, progress, ModuleCompileMode::Eager)
+            .map(|(executable, _, _)| executable)
+    }
+
+    /// Compile and load a WebAssembly binary, or reuse the artifact loaded from the same binary
+    /// by this engine, in the same compile mode, while something still holds it.
+    ///
+    /// The artifact is only reused if `tunables` give its memories and tables the same styles.
+    /// Threads compiling the same binary at the same time wait for one of them to load it.
+    /// [`Engine::cache_stats`] reports how many artifacts were reused.
+    ///
+    /// The artifact keeps the binary, see [`UniversalArtifact::binary`].
+    #[cfg(feature = "compiler")]
+    #[tracing::instrument(skip_all)]
+    pub fn compile_deduplicated(
+        &self,
+        binary: &[u8],
+        tunables: &dyn Tunables,
+    ) -> Result<Arc<UniversalArtifact>, CompileError> {
+        let (mode, collect_function_stats) = {
+            let inner = self.inner();
+            (inner.compile_mode, inner.collect_function_stats)
+        };
+        let key = ArtifactKey::new(binary, &self.fingerprint(), mode, collect_function_stats);
+        let fits = |artifact: &UniversalArtifact| {
+            artifact
+                .local_memories
+                .iter()
+                .all(|(ty, style)| tunables.memory_style(ty) == *style)
+                && artifact
+                    .local_tables
+                    .iter()
+                    .all(|(ty, style)| tunables.table_style(ty) == *style)
+        };
+        self.deduplication.get_or_load(key, fits, || {
+            let validate_start = Instant::now();
+            tracing::info_span!("validate").in_scope(|| self.validate(binary))?;
+            let validate = validate_start.elapsed();
+            let progress = CompileProgress::default();
+            let (executable, sources, mut timing) =
+                self.compile_universal_in_mode(binary, tunables, &progress, mode)?;
+            let sources = sources.map(Arc::new);
+            let mut artifact =
+                self.load_universal_executable_with(&executable, sources.as_ref())?;
+            timing.validate = validate;
+            timing.relocation = artifact.timing.relocation;
+            artifact.timing = timing;
+            artifact.binary = Some(binary.into());
+            Ok(artifact)
+        })
+    }
+
+    /// Translate a WebAssembly binary, leaving its functions to be compiled the first time
+    /// they are called once it is loaded.
+    ///
+    /// This compiles the module in [`ModuleCompileMode::Lazy`] whatever the mode of the engine.
+    #[cfg(feature = "compiler")]
+    #[tracing::instrument(skip_all)]
+    pub fn compile_lazy_universal(
+        &self,
+        binary: &[u8],
+        tunables: &dyn Tunables,
+    ) -> Result<LazyUniversalExecutable, CompileError> {
+        let progress = CompileProgress::default();
+        let (executable, sources, _) =
+            self.compile_universal_in_mode(binary, tunables, &progress, ModuleCompileMode::Lazy)?;
+        Ok(LazyUniversalExecutable {
+            executable,
+            sources: Arc::new(sources.expect("the functions are compiled lazily")),
+        })
+    }
+
+    /// Compile a WebAssembly binary, only compiling its functions in `ModuleCompileMode::Eager`,
+    /// and otherwise returning what they are to be compiled from, along with the time each
+    /// phase took.
+    #[cfg(feature = "compiler")]
+    fn compile_universal_in_mode(
+        &self,
+        binary: &[u8],
+        tunables: &dyn Tunables,
+        progress: &CompileProgress,
+        mode: ModuleCompileMode,
+    ) -> Result<
+        (
+            crate::UniversalExecutable,
+            Option<FunctionSources>,
+            CompileTiming,
+        ),
+        CompileError,
+    > {
+        // The stubs the lazily compiled functions are called through are x86-64 code, which
+        // compiles the functions for the host when they are first called.
+        if mode == ModuleCompileMode::Lazy {
+            let triple = self.target().triple();
+            if triple.architecture != wasmer_compiler::Architecture::X86_64 {
+                return Err(CompileError::UnsupportedTarget(format!(
+                    "compiling functions lazily on {}",
+                    triple.architecture
+                )));
+            }
+            if *triple != wasmer_compiler::Triple::host() {
+                return Err(CompileError::UnsupportedTarget(format!(
+                    "compiling functions lazily for {}, which is not the host",
+                    triple
+                )));
+            }
+        }
         let inner_engine = self.inner_mut();
         let features = inner_engine.features();
         let compiler = inner_engine.compiler()?;
+        compiler.check_features(features)?;
+        let parse_start = Instant::now();
         let environ = wasmer_compiler::ModuleEnvironment::new();
-        let translation = environ.translate(binary).map_err(CompileError::Wasm)?;
+        let mut translation = tracing::info_span!("parse")
+            .in_scope(|| environ.translate(binary))
+            .map_err(CompileError::Wasm)?;
+        compiler
+            .get_middlewares()
+            .apply_on_module_info(&mut translation.module);
+        let parse = parse_start.elapsed();
 
         let memory_styles: PrimaryMap<wasmer_types::MemoryIndex, _> = translation
             .module
@@ -120,16 +300,43 @@ impl UniversalEngine {
             features: features.clone(),
             memory_styles,
             table_styles,
+            collect_function_stats: inner_engine.collect_function_stats,
         };
-        let compilation = compiler.compile_module(
-            &self.target(),
-            &compile_info,
-            // SAFETY: Calling `unwrap` is correct since
-            // `environ.translate()` above will write some data into
-            // `module_translation_state`.
-            translation.module_translation_state.as_ref().unwrap(),
-            translation.function_body_inputs,
-        )?;
+        // The code runs in this process unless it is compiled for another target.
+        let mut target = self.target().clone();
+        if target.page_size().is_none() && *target.triple() == wasmer_compiler::Triple::host() {
+            target = target.with_page_size(region::page::size());
+        }
+        // SAFETY: Calling `unwrap` is correct since
+        // `environ.translate()` above will write some data into
+        // `module_translation_state`.
+        let module_translation = translation.module_translation_state.take().unwrap();
+        let (function_body_inputs, bodies) = match mode {
+            ModuleCompileMode::Eager => (translation.function_body_inputs, None),
+            ModuleCompileMode::Lazy => {
+                let bodies = translation
+                    .function_body_inputs
+                    .values()
+                    .map(|body| body.module_offset..body.module_offset + body.data.len())
+                    .collect::<PrimaryMap<LocalFunctionIndex, _>>();
+                (PrimaryMap::new(), Some(bodies))
+            }
+        };
+        let mut compilation = tracing::info_span!("codegen").in_scope(|| {
+            compiler.compile_module(
+                &target,
+                &compile_info,
+                &module_translation,
+                function_body_inputs,
+                progress,
+            )
+        })?;
+        let mut timing = CompileTiming {
+            parse,
+            ..compilation.get_timing()
+        };
+        let finalize_start = Instant::now();
+        let finalize_span = tracing::info_span!("finalize").entered();
         let function_call_trampolines = compilation.get_function_call_trampolines();
         let dynamic_function_trampolines = compilation.get_dynamic_function_trampolines();
         let data_initializers = translation
@@ -138,22 +345,135 @@ impl UniversalEngine {
             .map(wasmer_types::OwnedDataInitializer::new)
             .collect();
 
-        let frame_infos = compilation.get_frame_info();
-        Ok(crate::UniversalExecutable {
-            function_bodies: compilation.get_function_bodies(),
-            function_relocations: compilation.get_relocations(),
-            function_jt_offsets: compilation.get_jt_offsets(),
+        // The functions are moved into the executable one at a time, so that their code is
+        // never held twice. Their code isn't laid out in one buffer nor relocated here: the
+        // executable keeps the code of each function apart and unrelocated, and loading it
+        // places and relocates the code.
+        let functions = compilation.take_functions();
+        let mut function_bodies = PrimaryMap::with_capacity(functions.len());
+        let mut function_relocations = PrimaryMap::with_capacity(functions.len());
+        let mut function_jt_offsets = PrimaryMap::with_capacity(functions.len());
+        let mut frame_infos = PrimaryMap::with_capacity(functions.len());
+        let mut function_asm = Some(PrimaryMap::with_capacity(functions.len()));
+        for (_, function) in functions {
+            function_bodies.push(function.body);
+            function_relocations.push(function.relocations);
+            function_jt_offsets.push(function.jt_offsets);
+            frame_infos.push(function.frame_info);
+            function_asm = function_asm
+                .zip(function.debug_asm)
+                .map(|(mut listings, asm)| {
+                    listings.push(asm);
+                    listings
+                });
+        }
+        let custom_sections = compilation.get_custom_sections();
+        let custom_section_relocations = compilation.get_custom_section_relocations();
+        // The executable sections are loaded along with the functions, while the data sections
+        // go on pages of their own, which may still be relocated by each process.
+        let is_code = |section: SectionIndex| {
+            custom_sections[section].protection == CustomSectionProtection::ReadExecute
+        };
+        // The stubs of the lazily compiled functions refer to the engine by address.
+        let position_independent = mode == ModuleCompileMode::Eager
+            && crate::link::is_position_independent(
+                function_relocations.values().flatten().chain(
+                    custom_section_relocations
+                        .iter()
+                        .filter(|(section, _)| is_code(*section))
+                        .flat_map(|(_, relocations)| relocations),
+                ),
+                is_code,
+            );
+        let executable = crate::UniversalExecutable {
+            function_bodies,
+            function_relocations,
+            function_jt_offsets,
             function_frame_info: frame_infos,
             function_call_trampolines,
             dynamic_function_trampolines,
-            custom_sections: compilation.get_custom_sections(),
-            custom_section_relocations: compilation.get_custom_section_relocations(),
+            custom_sections,
+            custom_section_relocations,
             debug: compilation.get_debug(),
             trampolines: compilation.get_trampolines(),
+            function_stats: compilation.get_function_stats(),
+            function_asm,
             compile_info,
             data_initializers,
             cpu_features: self.target().cpu_features().as_u64(),
-        })
+            compiler: compiler.fingerprint(),
+            calling_convention: Fingerprint::calling_convention_of(self.target()),
+            triple: self.target().triple().to_string(),
+            module_hash: *blake3::hash(binary).as_bytes(),
+            position_independent,
+        };
+        finalize_span.exit();
+        timing.finalize = finalize_start.elapsed();
+        let sources = bodies.map(|bodies| FunctionSources {
+            target,
+            compile_info: executable.compile_info.clone(),
+            module_translation,
+            binary: binary.into(),
+            bodies,
+        });
+        Ok((executable, sources, timing))
+    }
+
+    /// The fingerprint of the executables compiled by this engine, which the executables it
+    /// deserializes must match.
+    pub fn fingerprint(&self) -> Fingerprint {
+        let inner = self.inner();
+        #[cfg(feature = "compiler")]
+        let compiler = inner
+            .compiler
+            .as_ref()
+            .map(|compiler| compiler.fingerprint());
+        #[cfg(not(feature = "compiler"))]
+        let compiler = None;
+        Fingerprint {
+            compiler,
+            features: inner.features().clone(),
+            cpu_features: *self.target().cpu_features(),
+            calling_convention: Fingerprint::calling_convention_of(self.target()),
+            triple: self.target().triple().to_string(),
+        }
+    }
+
+    /// Deserialize and load an executable serialized with `Executable::serialize`.
+    ///
+    /// The data is checked against its hash, and the executable must have been compiled with
+    /// the [fingerprint](Self::fingerprint) of this engine.
+    ///
+    /// # Safety
+    ///
+    /// The hash detects corrupted data, but the data must still come from `serialize`: it is not
+    /// validated further.
+    pub unsafe fn deserialize_universal(
+        &self,
+        data: &[u8],
+    ) -> Result<UniversalArtifact, DeserializeError> {
+        let executable = UniversalExecutableRef::deserialize(data)?;
+        self.fingerprint().check(&executable.fingerprint())?;
+        self.load_universal_executable_ref(&executable)
+            .map_err(DeserializeError::Compiler)
+    }
+
+    /// Like [`deserialize_universal`](Self::deserialize_universal), without comparing the
+    /// fingerprint of the executable to the one of this engine. The data is still checked
+    /// against its hash.
+    ///
+    /// # Safety
+    ///
+    /// On top of the requirements of `deserialize_universal`, the code must be able to run in
+    /// this engine: running code that uses CPU features the host doesn't have, or with another
+    /// calling convention, is undefined behaviour.
+    pub unsafe fn deserialize_universal_ignoring_fingerprint(
+        &self,
+        data: &[u8],
+    ) -> Result<UniversalArtifact, DeserializeError> {
+        let executable = UniversalExecutableRef::deserialize(data)?;
+        self.load_universal_executable_ref(&executable)
+            .map_err(DeserializeError::Compiler)
     }
 
     /// Load a [`UniversalExecutable`](crate::UniversalExecutable) with this engine.
@@ -162,6 +482,35 @@ impl UniversalEngine {
         &self,
         executable: &UniversalExecutable,
     ) -> Result<UniversalArtifact, CompileError> {
+        self.load_universal_executable_with(executable, None)
+    }
+
+    /// Load a [`LazyUniversalExecutable`](crate::LazyUniversalExecutable) with this engine.
+    ///
+    /// The functions of the artifact are compiled the first time they are called, by the
+    /// compiler of this engine.
+    #[tracing::instrument(skip_all)]
+    pub fn load_lazy_universal_executable(
+        &self,
+        executable: &LazyUniversalExecutable,
+    ) -> Result<UniversalArtifact, CompileError> {
+        self.load_universal_executable_with(&executable.executable, Some(&executable.sources))
+    }
+
+    /// Load `executable`, whose functions are compiled lazily from `sources` if given.
+    fn load_universal_executable_with(
+        &self,
+        executable: &UniversalExecutable,
+        sources: Option<&Arc<FunctionSources>>,
+    ) -> Result<UniversalArtifact, CompileError> {
+        // The code compiled for another target is loaded to be inspected, not to run.
+        let host_mismatch = executable.fingerprint().check_host().err();
+        if let (Some(reason), Some(_)) = (&host_mismatch, sources) {
+            return Err(CompileError::UnsupportedTarget(format!(
+                "compiling functions lazily: {}",
+                reason
+            )));
+        }
         let info = &executable.compile_info;
         let module = &info.module;
         let local_memories = (module.import_counts.memories as usize..module.memories.len())
@@ -182,10 +531,16 @@ impl UniversalEngine {
             .skip(module.import_counts.globals as usize)
             .enumerate()
             .map(|(idx, (_, t))| {
-                let init = module.global_initializers[LocalGlobalIndex::new(idx)];
+                let init = module.global_initializers[LocalGlobalIndex::new(idx)].clone();
                 (*t, init)
             })
             .collect();
+        let local_tags = module
+            .tags
+            .values()
+            .skip(module.import_counts.tags as usize)
+            .map(|sig| TagType::from(&module.signatures[*sig]))
+            .collect();
         let mut inner_engine = self.inner_mut();
 
         let local_functions = executable.function_bodies.iter().map(|(_, b)| b.into());
@@ -197,6 +552,7 @@ impl UniversalEngine {
             .map(|(_, sig)| inner_engine.signatures.register(sig.into()))
             .collect::<PrimaryMap<SignatureIndex, _>>()
             .into_boxed_slice();
+        let relocation_start = Instant::now();
         let (functions, trampolines, dynamic_trampolines, custom_sections) = inner_engine
             .allocate(
                 local_functions,
@@ -209,6 +565,8 @@ impl UniversalEngine {
                     (sig_idx, signatures[sig_idx])
                 },
             )?;
+        let mut relocation = relocation_start.elapsed();
+        let code = inner_engine.allocated_code();
         let imports = module
             .imports
             .iter()
@@ -216,6 +574,10 @@ impl UniversalEngine {
                 module: String::from(module_name),
                 field: String::from(field),
                 import_no: *idx,
+                function_name: match entity {
+                    ImportIndex::Function(i) => module.function_names.get(i).cloned(),
+                    _ => None,
+                },
                 ty: match entity {
                     ImportIndex::Function(i) => {
                         let sig_idx = module.functions[*i];
@@ -230,40 +592,128 @@ impl UniversalEngine {
                         VMImportType::Memory(ty, info.memory_styles[i].clone())
                     }
                     ImportIndex::Global(i) => VMImportType::Global(module.globals[*i]),
+                    ImportIndex::Tag(i) => {
+                        VMImportType::Tag(TagType::from(&module.signatures[module.tags[*i]]))
+                    }
                 },
             })
             .collect();
+        // The lazily compiled functions are called through their stubs.
+        let (functions, lazy_functions) = match sources {
+            None => (functions, None),
+            Some(sources) => {
+                let lazy_functions = LazyFunctions::new(
+                    self.clone(),
+                    Arc::clone(sources),
+                    custom_sections.clone(),
+                    signatures.clone(),
+                )?;
+                let functions = sources
+                    .bodies
+                    .keys()
+                    .map(|index| {
+                        let sig_idx = module.functions[module.import_counts.function_index(index)];
+                        VMLocalFunction {
+                            body: lazy_functions.stub(index),
+                            length: STUB_SIZE as u32,
+                            signature: signatures[sig_idx],
+                            trampoline: trampolines[sig_idx],
+                        }
+                    })
+                    .collect::<PrimaryMap<LocalFunctionIndex, _>>();
+                (functions, Some(lazy_functions))
+            }
+        };
 
-        let function_relocations = executable.function_relocations.iter();
-        let section_relocations = executable.custom_section_relocations.iter();
-        crate::link_module(
-            &functions,
-            |func_idx, jt_idx| executable.function_jt_offsets[func_idx][jt_idx],
-            function_relocations.map(|(i, rs)| (i, rs.iter().cloned())),
-            &custom_sections,
-            section_relocations.map(|(i, rs)| (i, rs.iter().cloned())),
-            &executable.trampolines,
-        );
-
-        // Make all code loaded executable.
-        inner_engine.publish_compiled_code();
-        if let Some(ref d) = executable.debug {
-            unsafe {
+        if host_mismatch.is_none() {
+            let relocation_start = Instant::now();
+            let _span = tracing::info_span!("relocation").entered();
+            let function_relocations = executable.function_relocations.iter();
+            let section_relocations = executable.custom_section_relocations.iter();
+            crate::link_module(
+                &functions,
+                |func_idx, jt_idx| executable.function_jt_offsets[func_idx][jt_idx],
+                function_relocations.map(|(i, rs)| (i, rs.iter().cloned())),
+                &custom_sections,
+                section_relocations.map(|(i, rs)| (i, rs.iter().cloned())),
+                &signatures,
+                &executable.trampolines,
+            );
+
+            // Make all code loaded executable.
+            inner_engine.publish_compiled_code()?;
+            // Register the unwind information even without an `.eh_frame` section, as Windows
+            // keeps it next to each function.
+            let eh_frame = executable.debug.as_ref().map(|d| unsafe {
                 // TODO: safety comment
-                inner_engine.publish_eh_frame(std::slice::from_raw_parts(
+                std::slice::from_raw_parts(
                     *custom_sections[d.eh_frame],
                     executable.custom_sections[d.eh_frame].bytes.len(),
-                ))?;
-            }
+                )
+            });
+            inner_engine.publish_eh_frame(eh_frame)?;
+            relocation += relocation_start.elapsed();
         }
         let exports = module
             .exports
             .iter()
             .map(|(s, i)| (s.clone(), i.clone()))
-            .collect::<BTreeMap<String, ExportIndex>>();
+            .collect::<IndexMap<String, ExportIndex>>();
+
+        let function_names = module
+            .function_names
+            .iter()
+            .map(|(index, name)| (*index, name.clone()))
+            .collect::<BTreeMap<_, _>>();
+        // The lazily compiled functions are described as they are compiled.
+        let profiling = inner_engine.profiling.filter(|_| host_mismatch.is_none());
+        if let (Some(strategy), None) = (profiling, &lazy_functions) {
+            let address_maps = executable
+                .function_frame_info
+                .values()
+                .map(|info| &info.address_map);
+            describe_functions(
+                strategy,
+                &module.name(),
+                &function_names,
+                module.import_counts,
+                &functions,
+                address_maps,
+            );
+        }
+
+        // The frame information of the lazily compiled functions is registered as they are.
+        let frame_info_registration = match lazy_functions {
+            None if host_mismatch.is_some() => None,
+            None => register_frame_info(
+                module.name(),
+                function_names.clone(),
+                module.import_counts,
+                function_extents(&functions),
+                executable.function_frame_info.clone(),
+            ),
+            Some(_) => None,
+        };
 
         Ok(UniversalArtifact {
+            frame_info_registration,
+            host_mismatch,
+            code,
+            position_independent: executable.position_independent,
             engine: self.clone(),
+            deduplication_key: None,
+            binary: None,
+            module_hash: executable.module_hash,
+            module_name: module.name.clone(),
+            function_names,
+            custom_sections: module
+                .custom_sections
+                .iter()
+                .map(|(name, index)| {
+                    let data = module.custom_sections_data[*index].clone();
+                    (name.clone(), data.into())
+                })
+                .collect(),
             import_counts: module.import_counts,
             start_function: module.start_function,
             vmoffsets: VMOffsets::for_host().with_module_info(&*module),
@@ -273,12 +723,40 @@ impl UniversalEngine {
             exports,
             signatures,
             local_memories,
-            data_segments: executable.data_initializers.clone(),
-            passive_data: module.passive_data.clone(),
+            data_segments: executable
+                .data_initializers
+                .iter()
+                .map(|init| {
+                    (
+                        init.location.clone(),
+                        Arc::<[u8]>::from(&init.data[..]).into(),
+                    )
+                })
+                .collect(),
+            passive_data: module
+                .passive_data
+                .iter()
+                .map(|(index, data)| (*index, data.clone().into()))
+                .collect(),
             local_tables,
             element_segments: module.table_initializers.clone(),
             passive_elements: module.passive_elements.clone(),
             local_globals,
+            local_tags,
+            function_stats: executable.function_stats.clone(),
+            function_asm: executable.function_asm.clone(),
+            frame_layouts: executable
+                .function_frame_info
+                .values()
+                .map(|info| info.frame_layout)
+                .collect(),
+            timing: CompileTiming {
+                relocation,
+                ..CompileTiming::default()
+            },
+            lazy_functions,
+            shared_object: None,
+            file: None,
         })
     }
 
@@ -287,6 +765,72 @@ impl UniversalEngine {
         &self,
         executable: &UniversalExecutableRef,
     ) -> Result<UniversalArtifact, CompileError> {
+        self.load_universal_executable_ref_with(executable, None, false)
+    }
+
+    /// Load the shared object at `path`, linked from the object file written by
+    /// [`UniversalExecutable::write_object`].
+    ///
+    /// The code of the artifact is the code of the shared object, which stays loaded as long as
+    /// the artifact: the engine neither allocates nor relocates it. The executable must have
+    /// been compiled with the [fingerprint](Self::fingerprint) of this engine, for the host.
+    ///
+    /// # Safety
+    ///
+    /// The shared object is loaded in the process, running its initializers, and its code is
+    /// trusted to be the code of its executable: the shared object must have been linked from
+    /// an object file written by `write_object`, and nothing else.
+    pub unsafe fn load_shared_object(
+        &self,
+        path: impl AsRef<Path>,
+    ) -> Result<UniversalArtifact, DeserializeError> {
+        let library = SharedObject::open(path.as_ref())?;
+        let mut artifact = {
+            let executable = UniversalExecutableRef::deserialize(library.metadata()?)?;
+            self.fingerprint().check(&executable.fingerprint())?;
+            executable
+                .fingerprint()
+                .check_host()
+                .map_err(DeserializeError::Incompatible)?;
+            self.load_universal_executable_ref_with(&executable, Some(&library), true)
+                .map_err(DeserializeError::Compiler)?
+        };
+        artifact.shared_object = Some(library);
+        Ok(artifact)
+    }
+
+    /// Load the executable of `file`, keeping the file mapped as long as the artifact.
+    ///
+    /// The code of the executable is copied to memory allocated by the engine, to be relocated
+    /// and made executable, while the rest of the artifact borrows its data from the mapping:
+    /// the data segments and the custom sections aren't copied. The executable must have been
+    /// compiled with the [fingerprint](Self::fingerprint) of this engine.
+    pub fn load_universal_executable_file(
+        &self,
+        file: UniversalExecutableFile,
+    ) -> Result<UniversalArtifact, DeserializeError> {
+        let mut artifact = {
+            let executable = file.executable();
+            self.fingerprint().check(&executable.fingerprint())?;
+            self.load_universal_executable_ref_with(&executable, None, true)
+                .map_err(DeserializeError::Compiler)?
+        };
+        artifact.file = Some(file);
+        Ok(artifact)
+    }
+
+    /// Load `executable`, whose code is in `library` if given, or allocated by the engine.
+    ///
+    /// The data of the artifact is borrowed from `executable` if `borrow` is set, in which
+    /// case the caller must keep its bytes alive as long as the artifact.
+    fn load_universal_executable_ref_with(
+        &self,
+        executable: &UniversalExecutableRef,
+        library: Option<&SharedObject>,
+        borrow: bool,
+    ) -> Result<UniversalArtifact, CompileError> {
+        // The code compiled for another target is loaded to be inspected, not to run.
+        let host_mismatch = executable.fingerprint().check_host().err();
         let info = &executable.compile_info;
         let module = &info.module;
         let import_counts: ImportCounts = unrkyv(&module.import_counts);
@@ -314,13 +858,35 @@ impl UniversalEngine {
                 (*t, init)
             })
             .collect();
+        let local_tags = module
+            .tags
+            .values()
+            .skip(import_counts.tags as usize)
+            .map(|sig| TagType::new(FunctionTypeRef::from(&module.signatures[sig]).params()))
+            .collect();
 
-        let passive_data =
-            rkyv::Deserialize::deserialize(&module.passive_data, &mut SharedDeserializeMap::new())
-                .map_err(|_| CompileError::Validate("could not deserialize passive data".into()))?;
-        let data_segments = executable.data_initializers.iter();
-        let data_segments = data_segments
-            .map(|s| DataInitializer::from(s).into())
+        // The bytes outliving the artifact are borrowed rather than copied.
+        let bytes = |data: &[u8]| match borrow {
+            true => unsafe { ArtifactBytes::borrowed(data) },
+            false => ArtifactBytes::from(Arc::<[u8]>::from(data)),
+        };
+        let passive_data = module
+            .passive_data
+            .iter()
+            .map(|(index, data)| (unrkyv(index), bytes(data)))
+            .collect();
+        let section_names: Vec<(String, CustomSectionIndex)> = unrkyv(&module.custom_sections);
+        let module_custom_sections = section_names
+            .into_iter()
+            .map(|(name, index)| (name, bytes(&module.custom_sections_data[&index])))
+            .collect();
+        let data_segments = executable
+            .data_initializers
+            .iter()
+            .map(|init| {
+                let init = DataInitializer::from(init);
+                (init.location, bytes(init.data))
+            })
             .collect();
         let element_segments = unrkyv(&module.table_initializers);
         let passive_elements: BTreeMap<wasmer_types::ElemIndex, Box<[FunctionIndex]>> =
@@ -338,18 +904,36 @@ impl UniversalEngine {
             .map(|sig| inner_engine.signatures.register(sig.into()))
             .collect::<PrimaryMap<SignatureIndex, _>>()
             .into_boxed_slice();
-        let (functions, trampolines, dynamic_trampolines, custom_sections) = inner_engine
-            .allocate(
+        let call_trampolines = call_trampolines.map(|(_, b)| b.into());
+        let dynamic_trampolines = dynamic_trampolines.map(|(_, b)| b.into());
+        let custom_sections = executable.custom_sections.iter().map(|(_, s)| s.into());
+        let function_signature = |idx: LocalFunctionIndex| {
+            let func_idx = import_counts.function_index(idx);
+            let sig_idx = module.functions[&func_idx];
+            (sig_idx, signatures[sig_idx])
+        };
+        let relocation_start = Instant::now();
+        let (functions, trampolines, dynamic_trampolines, custom_sections) = match library {
+            Some(library) => library.resolve(
                 local_functions,
-                call_trampolines.map(|(_, b)| b.into()),
-                dynamic_trampolines.map(|(_, b)| b.into()),
-                executable.custom_sections.iter().map(|(_, s)| s.into()),
-                |idx: LocalFunctionIndex| {
-                    let func_idx = import_counts.function_index(idx);
-                    let sig_idx = module.functions[&func_idx];
-                    (sig_idx, signatures[sig_idx])
-                },
-            )?;
+                call_trampolines,
+                dynamic_trampolines,
+                custom_sections,
+                function_signature,
+            )?,
+            None => inner_engine.allocate(
+                local_functions,
+                call_trampolines,
+                dynamic_trampolines,
+                custom_sections,
+                function_signature,
+            )?,
+        };
+        let mut relocation = relocation_start.elapsed();
+        let code = match library {
+            Some(library) => library.code()?,
+            None => inner_engine.allocated_code(),
+        };
         let imports = {
             module
                 .imports
@@ -358,6 +942,12 @@ impl UniversalEngine {
                     module: String::from(module_name.as_str()),
                     field: String::from(field.as_str()),
                     import_no: *idx,
+                    function_name: match entity {
+                        ImportIndex::Function(i) => {
+                            module.function_names.get(i).map(|name| name.to_string())
+                        }
+                        _ => None,
+                    },
                     ty: match entity {
                         ImportIndex::Function(i) => {
                             let sig_idx = module.functions[i];
@@ -372,45 +962,107 @@ impl UniversalEngine {
                             VMImportType::Memory(ty, unrkyv(&info.memory_styles[i]))
                         }
                         ImportIndex::Global(i) => VMImportType::Global(unrkyv(&module.globals[i])),
+                        ImportIndex::Tag(i) => {
+                            let sig = &module.signatures[&module.tags[i]];
+                            VMImportType::Tag(TagType::new(FunctionTypeRef::from(sig).params()))
+                        }
                     },
                 })
                 .collect()
         };
 
-        let function_relocations = executable.function_relocations.iter();
-        let section_relocations = executable.custom_section_relocations.iter();
-        crate::link_module(
-            &functions,
-            |func_idx, jt_idx| {
-                let func_idx = rkyv::Archived::<LocalFunctionIndex>::new(func_idx.index());
-                let jt_idx = rkyv::Archived::<JumpTable>::new(jt_idx.index());
-                executable.function_jt_offsets[&func_idx][&jt_idx]
-            },
-            function_relocations.map(|(i, r)| (i, r.iter().map(unrkyv))),
-            &custom_sections,
-            section_relocations.map(|(i, r)| (i, r.iter().map(unrkyv))),
-            &unrkyv(&executable.trampolines),
-        );
-
-        // Make all code compiled thus far executable.
-        inner_engine.publish_compiled_code();
-        if let rkyv::option::ArchivedOption::Some(ref d) = executable.debug {
-            unsafe {
-                // TODO: safety comment
-                let s = CustomSectionRef::from(&executable.custom_sections[&d.eh_frame]);
-                inner_engine.publish_eh_frame(std::slice::from_raw_parts(
-                    *custom_sections[unrkyv(&d.eh_frame)],
-                    s.bytes.len(),
-                ))?;
+        if host_mismatch.is_none() {
+            let relocation_start = Instant::now();
+            let _span = tracing::info_span!("relocation").entered();
+            // The code of shared objects was relocated when they were linked.
+            if library.is_none() {
+                let function_relocations = executable.function_relocations.iter();
+                let section_relocations = executable.custom_section_relocations.iter();
+                crate::link_module(
+                    &functions,
+                    |func_idx, jt_idx| {
+                        let func_idx = rkyv::Archived::<LocalFunctionIndex>::new(func_idx.index());
+                        let jt_idx = rkyv::Archived::<JumpTable>::new(jt_idx.index());
+                        executable.function_jt_offsets[&func_idx][&jt_idx]
+                    },
+                    function_relocations.map(|(i, r)| (i, r.iter().map(unrkyv))),
+                    &custom_sections,
+                    section_relocations.map(|(i, r)| (i, r.iter().map(unrkyv))),
+                    &signatures,
+                    &unrkyv(&executable.trampolines),
+                );
+
+                // Make all code compiled thus far executable.
+                inner_engine.publish_compiled_code()?;
             }
+            let eh_frame = match executable.debug {
+                rkyv::option::ArchivedOption::Some(ref d) => unsafe {
+                    // TODO: safety comment
+                    let s = CustomSectionRef::from(&executable.custom_sections[&d.eh_frame]);
+                    Some(std::slice::from_raw_parts(
+                        *custom_sections[unrkyv(&d.eh_frame)],
+                        s.bytes.len(),
+                    ))
+                },
+                rkyv::option::ArchivedOption::None => None,
+            };
+            match library {
+                Some(library) => library.publish_eh_frame(eh_frame)?,
+                None => inner_engine.publish_eh_frame(eh_frame)?,
+            }
+            relocation += relocation_start.elapsed();
         }
         let exports = module
             .exports
             .iter()
             .map(|(s, i)| (unrkyv(s), unrkyv(i)))
-            .collect::<BTreeMap<String, ExportIndex>>();
+            .collect::<IndexMap<String, ExportIndex>>();
+        let module_name: Option<String> = unrkyv(&module.name);
+        let function_names: BTreeMap<FunctionIndex, String> = unrkyv(&module.function_names);
+        if let Some(strategy) = inner_engine.profiling.filter(|_| host_mismatch.is_none()) {
+            let address_maps = executable
+                .function_frame_info
+                .values()
+                .map(|info| unrkyv(&info.address_map))
+                .collect::<Vec<FunctionAddressMap>>();
+            describe_functions(
+                strategy,
+                module_name.as_deref().unwrap_or("<module>"),
+                &function_names,
+                import_counts,
+                &functions,
+                address_maps.iter(),
+            );
+        }
+        let frame_info_registration = match host_mismatch {
+            Some(_) => None,
+            None => register_frame_info(
+                module_name
+                    .clone()
+                    .unwrap_or_else(|| "<module>".to_string()),
+                function_names.clone(),
+                import_counts,
+                function_extents(&functions),
+                executable
+                    .function_frame_info
+                    .values()
+                    .map(unrkyv)
+                    .collect(),
+            ),
+        };
+
         Ok(UniversalArtifact {
+            frame_info_registration,
+            host_mismatch,
+            code,
+            position_independent: executable.position_independent,
             engine: self.clone(),
+            deduplication_key: None,
+            binary: None,
+            module_hash: executable.module_hash,
+            module_name,
+            function_names,
+            custom_sections: module_custom_sections,
             import_counts,
             start_function: unrkyv(&module.start_function),
             vmoffsets: VMOffsets::for_host().with_archived_module_info(&*module),
@@ -426,6 +1078,21 @@ impl UniversalEngine {
             element_segments,
             passive_elements,
             local_globals,
+            local_tags,
+            function_stats: unrkyv(&executable.function_stats),
+            function_asm: unrkyv(&executable.function_asm),
+            frame_layouts: executable
+                .function_frame_info
+                .values()
+                .map(|info| unrkyv(&info.frame_layout))
+                .collect(),
+            timing: CompileTiming {
+                relocation,
+                ..CompileTiming::default()
+            },
+            lazy_functions: None,
+            shared_object: None,
+            file: None,
         })
     }
 }
@@ -476,8 +1143,26 @@ impl Engine for UniversalEngine {
         binary: &[u8],
         tunables: &dyn Tunables,
     ) -> Result<Box<dyn wasmer_engine::Executable>, CompileError> {
-        self.compile_universal(binary, tunables)
-            .map(|ex| Box::new(ex) as _)
+        self.compile_with_progress(binary, tunables, &CompileProgress::default())
+    }
+
+    /// Compile a WebAssembly binary, reporting each compiled function to `progress`
+    #[cfg(feature = "compiler")]
+    #[tracing::instrument(skip_all)]
+    fn compile_with_progress(
+        &self,
+        binary: &[u8],
+        tunables: &dyn Tunables,
+        progress: &CompileProgress,
+    ) -> Result<Box<dyn wasmer_engine::Executable>, CompileError> {
+        match self.compile_mode() {
+            ModuleCompileMode::Eager => self
+                .compile_universal_with_progress(binary, tunables, progress)
+                .map(|ex| Box::new(ex) as _),
+            ModuleCompileMode::Lazy => self
+                .compile_lazy_universal(binary, tunables)
+                .map(|ex| Box::new(ex) as _),
+        }
     }
 
     #[tracing::instrument(skip_all)]
@@ -495,6 +1180,21 @@ impl Engine for UniversalEngine {
     fn cloned(&self) -> Arc<dyn Engine + Send + Sync> {
         Arc::new(self.clone())
     }
+
+    fn cache_stats(&self) -> CacheStats {
+        self.deduplication.stats()
+    }
+
+    fn code_memory_usage(&self) -> CodeMemoryUsage {
+        let inner = self.inner();
+        inner.code_memory.iter().map(CodeMemory::usage).fold(
+            CodeMemoryUsage::default(),
+            |total, usage| CodeMemoryUsage {
+                allocated: total.allocated + usage.allocated,
+                used: total.used + usage.used,
+            },
+        )
+    }
 }
 
 /// The inner contents of `UniversalEngine`
@@ -514,6 +1214,12 @@ pub struct UniversalEngineInner {
     /// functions with the same `VMCallerCheckedAnyfunc` will have the same `VMFuncRef`.
     /// It also guarantees that the `VMFuncRef`s stay valid until the engine is dropped.
     func_data: Arc<FuncDataRegistry>,
+    /// Whether to collect per-function register allocation statistics.
+    collect_function_stats: bool,
+    /// When the functions of the modules are compiled.
+    compile_mode: ModuleCompileMode,
+    /// How the published functions are described to external profilers, if they are.
+    pub(crate) profiling: Option<ProfilingStrategy>,
 }
 
 impl UniversalEngineInner {
@@ -529,7 +1235,9 @@ impl UniversalEngineInner {
     /// Validate the module
     #[cfg(feature = "compiler")]
     pub fn validate<'data>(&self, data: &'data [u8]) -> Result<(), CompileError> {
-        self.compiler()?.validate_module(self.features(), data)
+        let compiler = self.compiler()?;
+        compiler.check_features(self.features())?;
+        compiler.validate_module(self.features(), data)
     }
 
     /// Validate the module
@@ -660,13 +1368,59 @@ impl UniversalEngineInner {
         ))
     }
 
+    /// Allocate a function compiled on its own into memory, along with the `.eh_frame` section
+    /// describing its frame.
+    pub(crate) fn allocate_function(
+        &mut self,
+        function: FunctionBodyRef<'_>,
+        eh_frame: Option<CustomSectionRef<'_>>,
+    ) -> Result<(FunctionExtent, Option<SectionBodyPtr>), CompileError> {
+        self.code_memory.push(CodeMemory::new());
+        let code_memory = self.code_memory.last_mut().expect("infallible");
+        let data_sections = eh_frame.into_iter().collect::<Vec<_>>();
+        let (functions, _, data_sections) = code_memory
+            .allocate(&[function], &[], &data_sections)
+            .map_err(|message| {
+                CompileError::Resource(format!(
+                    "failed to allocate memory for functions: {}",
+                    message
+                ))
+            })?;
+        let extent = FunctionExtent {
+            address: FunctionBodyPtr(functions[0].as_ptr()),
+            length: functions[0].len(),
+        };
+        let eh_frame = data_sections
+            .first()
+            .map(|section| SectionBodyPtr(section.as_ptr()));
+        Ok((extent, eh_frame))
+    }
+
+    /// The extent of the functions and executable sections allocated last.
+    pub(crate) fn allocated_code(&self) -> FunctionExtent {
+        let code = self
+            .code_memory
+            .last()
+            .expect("no code was allocated")
+            .code();
+        FunctionExtent {
+            address: FunctionBodyPtr(code.as_ptr() as *const VMFunctionBody),
+            length: code.len(),
+        }
+    }
+
     /// Make memory containing compiled code executable.
-    pub(crate) fn publish_compiled_code(&mut self) {
-        self.code_memory.last_mut().unwrap().publish();
+    pub(crate) fn publish_compiled_code(&mut self) -> Result<(), CompileError> {
+        self.code_memory
+            .last_mut()
+            .unwrap()
+            .publish()
+            .map_err(|e| CompileError::Resource(format!("Error while publishing the code: {}", e)))
     }
 
-    /// Register DWARF-type exception handling information associated with the code.
-    pub(crate) fn publish_eh_frame(&mut self, eh_frame: &[u8]) -> Result<(), CompileError> {
+    /// Register the unwind information associated with the code, along with the DWARF-type
+    /// exception handling information if there is any.
+    pub(crate) fn publish_eh_frame(&mut self, eh_frame: Option<&[u8]>) -> Result<(), CompileError> {
         self.code_memory
             .last_mut()
             .unwrap()
@@ -683,3 +1437,41 @@ impl UniversalEngineInner {
         &self.func_data
     }
 }
+
+/// The extents of the code of the local functions of a module.
+fn function_extents(
+    functions: &PrimaryMap<LocalFunctionIndex, VMLocalFunction>,
+) -> impl Iterator<Item = (LocalFunctionIndex, FunctionExtent)> + '_ {
+    functions.iter().map(|(index, function)| {
+        let extent = FunctionExtent {
+            address: function.body,
+            length: usize::try_from(function.length).unwrap(),
+        };
+        (index, extent)
+    })
+}
+
+/// Describe the local `functions` of a module, whose instructions come from `address_maps`,
+/// to the profiler of `strategy`.
+fn describe_functions<'a>(
+    strategy: ProfilingStrategy,
+    module_name: &str,
+    function_names: &BTreeMap<FunctionIndex, String>,
+    import_counts: ImportCounts,
+    functions: &PrimaryMap<LocalFunctionIndex, VMLocalFunction>,
+    address_maps: impl Iterator<Item = &'a FunctionAddressMap>,
+) {
+    let functions =
+        function_extents(functions)
+            .zip(address_maps)
+            .map(|((index, extent), address_map)| {
+                let index = import_counts.function_index(index);
+                let name = function_names.get(&index).map(String::as_str);
+                PublishedFunction {
+                    name: profiling::symbol_name(module_name, name, index),
+                    extent,
+                    address_map,
+                }
+            });
+    profiling::describe(strategy, module_name, functions);
+}
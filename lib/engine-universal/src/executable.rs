@@ -1,3 +1,6 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
 use std::sync::Arc;
 
 use enumset::EnumSet;
@@ -7,7 +10,8 @@ use rkyv::ser::serializers::{
 };
 use wasmer_compiler::{
     CompileError, CompileModuleInfo, CompiledFunctionFrameInfo, CpuFeature, CustomSection, Dwarf,
-    Features, FunctionBody, JumpTableOffsets, Relocation, SectionIndex, TrampolinesSection,
+    Features, FunctionAsm, FunctionBody, FunctionStats, JumpTableOffsets, Relocation, SectionIndex,
+    Target, TrampolinesSection, Triple,
 };
 use wasmer_engine::{DeserializeError, Engine};
 use wasmer_types::entity::PrimaryMap;
@@ -17,12 +21,175 @@ use wasmer_types::{
 };
 use wasmer_vm::Artifact;
 
+/// The byte after the name is the version of the format.
 const MAGIC_HEADER: [u8; 32] = {
-    let value = *b"\0wasmer-universal\xFF\xFF\xFF\xFF\xFF\xFF\xFF\xFF\xFF\xFF\xFF\xFF\xFF\xFF\xFF";
+    let value = *b"\0wasmer-universal\x07\xFF\xFF\xFF\xFF\xFF\xFF\xFF\xFF\xFF\xFF\xFF\xFF\xFF\xFF";
     let _length_must_be_multiple_of_16: bool = [true][value.len() % 16];
     value
 };
 
+/// The magic header, followed by the BLAKE3 hash of the rest of the data.
+const HEADER_LENGTH: usize = {
+    let value = MAGIC_HEADER.len() + blake3::OUT_LEN;
+    let _length_must_be_multiple_of_16: bool = [true][value % 16];
+    value
+};
+
+/// What an executable was compiled with, which decides whether an engine can load it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fingerprint {
+    /// The compiler, as identified by `Compiler::fingerprint`, or `None` for a headless engine
+    /// that doesn't know which compiler produced its executables.
+    pub compiler: Option<String>,
+    /// The WebAssembly features.
+    pub features: Features,
+    /// The CPU features the code may use.
+    pub cpu_features: EnumSet<CpuFeature>,
+    /// The calling convention of the code.
+    pub calling_convention: String,
+    /// The target triple of the code.
+    pub triple: String,
+}
+
+impl Fingerprint {
+    pub(crate) fn calling_convention_of(target: &Target) -> String {
+        match target.triple().default_calling_convention() {
+            Ok(calling_convention) => format!("{:?}", calling_convention),
+            Err(()) => "unknown".to_string(),
+        }
+    }
+
+    /// Checks that an engine expecting this fingerprint can load an executable with the `found`
+    /// fingerprint.
+    ///
+    /// The executable may use fewer CPU features than the engine expects. The compilers, and the
+    /// WebAssembly features, are only compared when both compilers are known.
+    pub fn check(&self, found: &Fingerprint) -> Result<(), DeserializeError> {
+        let mut expected = vec![];
+        let mut actual = vec![];
+        if self.triple != found.triple {
+            expected.push(format!("target {}", self.triple));
+            actual.push(format!("target {}", found.triple));
+        }
+        if let (Some(e), Some(f)) = (&self.compiler, &found.compiler) {
+            if e != f {
+                expected.push(format!("compiler {}", e));
+                actual.push(format!("compiler {}", f));
+            }
+            // Headless engines compile nothing, so their features don't matter either.
+            if self.features != found.features {
+                expected.push(format!("features {:?}", self.features));
+                actual.push(format!("features {:?}", found.features));
+            }
+        }
+        if !self.cpu_features.is_superset(found.cpu_features) {
+            expected.push(format!("CPU features among {:?}", self.cpu_features));
+            actual.push(format!("CPU features {:?}", found.cpu_features));
+        }
+        if self.calling_convention != found.calling_convention {
+            expected.push(format!("calling convention {}", self.calling_convention));
+            actual.push(format!("calling convention {}", found.calling_convention));
+        }
+        if expected.is_empty() {
+            Ok(())
+        } else {
+            Err(DeserializeError::IncompatibleFingerprint {
+                expected: expected.join(", "),
+                found: actual.join(", "),
+            })
+        }
+    }
+
+    /// Checks that the code of an executable with this fingerprint can run on the host, and
+    /// describes why it can't otherwise.
+    pub fn check_host(&self) -> Result<(), String> {
+        let host = Triple::host().to_string();
+        if self.triple != host {
+            return Err(format!(
+                "the module was compiled for {}, but the host is {}",
+                self.triple, host
+            ));
+        }
+        let missing = self.cpu_features - CpuFeature::for_host();
+        if !missing.is_empty() {
+            return Err(format!(
+                "the module was compiled with the CPU features {:?}, which the host is missing",
+                missing
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The start of the files written by [`UniversalExecutable::serialize_to_file`].
+const FILE_MAGIC: [u8; 16] = *b"\0wasmer-univ-map";
+
+/// The offset of the serialized executable in a file.
+///
+/// The file is mapped from a page boundary, so the executable is as aligned in memory as it is
+/// after the header in the output of `serialize`.
+const FILE_PAYLOAD_OFFSET: usize = 4096;
+
+/// FNV-1a, to reject files that were truncated or modified after they were written.
+fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100_0000_01b3)
+    })
+}
+
+/// An executable written by [`UniversalExecutable::serialize_to_file`], mapped in memory.
+///
+/// Loaded with [`UniversalEngine::load_universal_executable_file`](crate::UniversalEngine::load_universal_executable_file),
+/// the file stays mapped as long as the artifact, which borrows its data from the mapping.
+pub struct UniversalExecutableFile {
+    mmap: memmap2::Mmap,
+}
+
+impl UniversalExecutableFile {
+    /// Maps the file at `path` in memory, and checks it holds an executable.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified while it is mapped. The header and the checksum of the
+    /// file are verified here, so files that were modified or truncated before they were opened
+    /// are rejected, but modifying the file afterwards is undefined behaviour.
+    pub unsafe fn open(path: impl AsRef<Path>) -> Result<Self, DeserializeError> {
+        let file = File::open(path)?;
+        let mmap = memmap2::Mmap::map(&file)?;
+        if mmap.len() < FILE_PAYLOAD_OFFSET || mmap[..FILE_MAGIC.len()] != FILE_MAGIC {
+            return Err(DeserializeError::Incompatible(
+                "the file was not written by `serialize_to_file`".to_string(),
+            ));
+        }
+        let mut length = [0u8; 8];
+        length.copy_from_slice(&mmap[16..24]);
+        let mut expected_checksum = [0u8; 8];
+        expected_checksum.copy_from_slice(&mmap[24..32]);
+        let payload = &mmap[FILE_PAYLOAD_OFFSET..];
+        if u64::from_le_bytes(length) != payload.len() as u64 {
+            return Err(DeserializeError::CorruptedBinary(
+                "the file does not have the length it was written with".to_string(),
+            ));
+        }
+        if checksum(payload) != u64::from_le_bytes(expected_checksum) {
+            return Err(DeserializeError::CorruptedBinary(
+                "the file does not match its checksum".to_string(),
+            ));
+        }
+        UniversalExecutableRef::verify_serialized(payload)
+            .map_err(|e| DeserializeError::Incompatible(e.to_string()))?;
+        Ok(Self { mmap })
+    }
+
+    /// The executable held by the file.
+    pub fn executable(&self) -> UniversalExecutableRef<'_> {
+        // SAFETY: the payload was verified when the file was opened, and `open` requires the
+        // file not to change since.
+        unsafe { UniversalExecutableRef::deserialize(&self.mmap[FILE_PAYLOAD_OFFSET..]) }
+            .expect("the file was verified when it was opened")
+    }
+}
+
 /// A 0-copy view of the encoded `UniversalExecutable` payload.
 #[derive(Clone, Copy)]
 pub struct UniversalExecutableRef<'a> {
@@ -39,17 +206,23 @@ impl<'a> std::ops::Deref for UniversalExecutableRef<'a> {
 
 impl<'a> UniversalExecutableRef<'a> {
     /// Verify the buffer for whether it is a valid `UniversalExecutable`.
+    ///
+    /// The hash of the data is checked before any offset within it is used.
     pub fn verify_serialized(data: &[u8]) -> Result<(), &'static str> {
         if !data.starts_with(&MAGIC_HEADER) {
             return Err("the provided bytes are not wasmer-universal");
         }
-        if data.len() < MAGIC_HEADER.len() + 8 {
+        if data.len() < HEADER_LENGTH + 8 {
             return Err("the data buffer is too small to be valid");
         }
-        let (remaining, position) = data.split_at(data.len() - 8);
+        let (header, hashed) = data.split_at(HEADER_LENGTH);
+        if *blake3::hash(hashed).as_bytes() != header[MAGIC_HEADER.len()..] {
+            return Err("the data does not match its hash");
+        }
+        let (payload, position) = hashed.split_at(hashed.len() - 8);
         let mut position_value = [0u8; 8];
         position_value.copy_from_slice(position);
-        if u64::from_le_bytes(position_value) > remaining.len() as u64 {
+        if u64::from_le_bytes(position_value) > payload.len() as u64 {
             return Err("the buffer is malformed");
         }
         // TODO(0-copy): bytecheck too.
@@ -66,11 +239,19 @@ impl<'a> UniversalExecutableRef<'a> {
     pub unsafe fn deserialize(
         data: &'a [u8],
     ) -> Result<UniversalExecutableRef<'a>, DeserializeError> {
-        Self::verify_serialized(data).map_err(|e| DeserializeError::Incompatible(e.to_string()))?;
+        if !data.starts_with(&MAGIC_HEADER) {
+            return Err(DeserializeError::Incompatible(
+                "the provided bytes are not wasmer-universal, or were serialized by another \
+                 version of it"
+                    .to_string(),
+            ));
+        }
+        Self::verify_serialized(data)
+            .map_err(|e| DeserializeError::CorruptedBinary(e.to_string()))?;
         let (archive, position) = data.split_at(data.len() - 8);
         let mut position_value = [0u8; 8];
         position_value.copy_from_slice(position);
-        let (_, data) = archive.split_at(MAGIC_HEADER.len());
+        let (_, data) = archive.split_at(HEADER_LENGTH);
         Ok(UniversalExecutableRef {
             buffer: data,
             archive: rkyv::archived_value::<UniversalExecutable>(
@@ -80,6 +261,17 @@ impl<'a> UniversalExecutableRef<'a> {
         })
     }
 
+    /// What the executable was compiled with.
+    pub fn fingerprint(&self) -> Fingerprint {
+        Fingerprint {
+            compiler: Some(self.archive.compiler.as_str().to_string()),
+            features: unrkyv(&self.archive.compile_info.features),
+            cpu_features: EnumSet::from_u64(unrkyv(&self.archive.cpu_features)),
+            calling_convention: self.archive.calling_convention.as_str().to_string(),
+            triple: self.archive.triple.as_str().to_string(),
+        }
+    }
+
     // TODO(0-copy): this should never fail.
     /// Convert this reference to an owned `UniversalExecutable` value.
     pub fn to_owned(self) -> Result<UniversalExecutable, DeserializeError> {
@@ -108,9 +300,54 @@ pub struct UniversalExecutable {
     pub(crate) debug: Option<Dwarf>,
     // the Trampoline for Arm arch
     pub(crate) trampolines: Option<TrampolinesSection>,
+    // Register allocation statistics, if requested at compile time
+    pub(crate) function_stats: Option<FunctionStats>,
+    // Listings of the emitted instructions, if requested at compile time
+    pub(crate) function_asm: Option<PrimaryMap<LocalFunctionIndex, FunctionAsm>>,
     pub(crate) compile_info: CompileModuleInfo,
     pub(crate) data_initializers: Vec<OwnedDataInitializer>,
     pub(crate) cpu_features: u64,
+    // The fingerprint of the compiler
+    pub(crate) compiler: String,
+    pub(crate) calling_convention: String,
+    pub(crate) triple: String,
+    /// The BLAKE3 hash of the wasm binary the executable was compiled from.
+    pub(crate) module_hash: [u8; blake3::OUT_LEN],
+    /// Whether the linked code is the same wherever it is loaded.
+    pub(crate) position_independent: bool,
+}
+
+impl UniversalExecutable {
+    /// What the executable was compiled with.
+    pub fn fingerprint(&self) -> Fingerprint {
+        Fingerprint {
+            compiler: Some(self.compiler.clone()),
+            features: self.compile_info.features.clone(),
+            cpu_features: EnumSet::from_u64(self.cpu_features),
+            calling_convention: self.calling_convention.clone(),
+            triple: self.triple.clone(),
+        }
+    }
+
+    /// Writes the executable to a file, to be mapped back in memory with
+    /// [`UniversalExecutableFile::open`].
+    ///
+    /// The file holds the output of `serialize`, at a page-aligned offset after a header
+    /// recording its length and checksum.
+    pub fn serialize_to_file(
+        &self,
+        path: impl AsRef<Path>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let payload = wasmer_engine::Executable::serialize(self)?;
+        let mut header = vec![0u8; FILE_PAYLOAD_OFFSET];
+        header[..FILE_MAGIC.len()].copy_from_slice(&FILE_MAGIC);
+        header[16..24].copy_from_slice(&(payload.len() as u64).to_le_bytes());
+        header[24..32].copy_from_slice(&checksum(&payload).to_le_bytes());
+        let mut file = File::create(path)?;
+        file.write_all(&header)?;
+        file.write_all(&payload)?;
+        Ok(())
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -150,17 +387,25 @@ impl wasmer_engine::Executable for UniversalExecutable {
         // The format is as thus:
         //
         // HEADER
+        // BLAKE3 HASH OF THE PAYLOAD AND POSITION
         // RKYV PAYLOAD
         // RKYV POSITION
         //
         // It is expected that any framing for message length is handled by the caller.
+        //
+        // The bytes only depend on the executable: all its maps are ordered by index or by key,
+        // and rkyv zeroes the padding within and between the archived values.
         let mut serializer = AllocSerializer::<1024>::default();
         let pos = rkyv::ser::Serializer::serialize_value(&mut serializer, self)
             .map_err(ExecutableSerializeError::Executable)? as u64;
         let pos_bytes = pos.to_le_bytes();
         let data = serializer.into_serializer().into_inner();
-        let mut out = Vec::with_capacity(MAGIC_HEADER.len() + pos_bytes.len() + data.len());
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(data.as_slice());
+        hasher.update(&pos_bytes);
+        let mut out = Vec::with_capacity(HEADER_LENGTH + pos_bytes.len() + data.len());
         out.extend(&MAGIC_HEADER);
+        out.extend(hasher.finalize().as_bytes());
         out.extend(data.as_slice());
         out.extend(&pos_bytes);
         Ok(out)
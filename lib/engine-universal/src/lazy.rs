@@ -0,0 +1,478 @@
+//! Compilation of the functions of a module the first time they are called.
+//!
+//! Each local function of a lazily compiled module is called through a stub, whose code jumps
+//! to the address in the slot of the function. Until the function is compiled, the slot holds
+//! the entry of the resolver for the function, which compiles it and stores the address of its
+//! code in the slot, so that the stub jumps straight to the compiled code next. The stubs are shared
+//! by all the instances of the module, which thus share the compiled functions.
+
+use crate::link::patch_relocation;
+use crate::profiling::{self, PublishedFunction};
+use crate::{UniversalEngine, UniversalExecutable};
+use enumset::EnumSet;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use wasmer_compiler::{
+    CompileError, CompileModuleInfo, CompiledFunction, CpuFeature, CustomSection, Features,
+    FunctionBodyData, FunctionBodyRef, ModuleTranslationState, Relocation, RelocationKind,
+    RelocationTarget, SectionIndex, Target,
+};
+use wasmer_engine::{
+    register_function_frame_info, Engine, Executable, GlobalFrameInfoRegistration,
+};
+use wasmer_types::entity::{BoxedSlice, EntityRef, PrimaryMap};
+use wasmer_types::{FunctionIndex, LocalFunctionIndex, SignatureIndex};
+use wasmer_vm::{
+    raise_user_trap, resume_panic, Artifact, FunctionBodyPtr, FunctionExtent, Mmap, SectionBodyPtr,
+    VMFunctionBody, VMSharedSignatureIndex,
+};
+
+/// The size of the stub of a function: the jump through its slot, followed by the entry of the
+/// resolver for the function.
+pub(crate) const STUB_SIZE: usize = 16;
+
+/// The size of a thunk appended to a lazily compiled function: a jump through the address
+/// right after it.
+const THUNK_SIZE: usize = 14;
+
+/// A module compiled by a [`UniversalEngine`] in [`ModuleCompileMode::Lazy`], whose functions are
+/// compiled once it is loaded, the first time they are called.
+///
+/// Everything but the functions is compiled: the trampolines and the custom sections are those of
+/// a [`UniversalExecutable`] without any function. The binary of the module is kept to compile
+/// the functions from, and as the functions aren't compiled yet, the executable can't be
+/// serialized.
+///
+/// [`ModuleCompileMode::Lazy`]: crate::ModuleCompileMode::Lazy
+pub struct LazyUniversalExecutable {
+    pub(crate) executable: UniversalExecutable,
+    pub(crate) sources: Arc<FunctionSources>,
+}
+
+/// What the functions of a lazily compiled module are compiled from.
+pub(crate) struct FunctionSources {
+    /// The target the module is compiled for, with its page size.
+    pub(crate) target: Target,
+    pub(crate) compile_info: CompileModuleInfo,
+    pub(crate) module_translation: ModuleTranslationState,
+    pub(crate) binary: Box<[u8]>,
+    /// The range of the body of each local function in `binary`.
+    pub(crate) bodies: PrimaryMap<LocalFunctionIndex, Range<usize>>,
+}
+
+impl Executable for LazyUniversalExecutable {
+    fn load(&self, engine: &(dyn Engine + 'static)) -> Result<Arc<dyn Artifact>, CompileError> {
+        engine
+            .downcast_ref::<UniversalEngine>()
+            .ok_or(CompileError::EngineDowncast)?
+            .load_lazy_universal_executable(self)
+            .map(|a| Arc::new(a) as _)
+    }
+
+    fn features(&self) -> Features {
+        self.executable.features()
+    }
+
+    fn cpu_features(&self) -> EnumSet<CpuFeature> {
+        self.executable.cpu_features()
+    }
+
+    fn serialize(&self) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+        Err("a lazily compiled module can't be serialized before its functions are compiled".into())
+    }
+
+    fn function_name(&self, index: FunctionIndex) -> Option<&str> {
+        self.executable.function_name(index)
+    }
+}
+
+/// The functions of a lazily compiled module loaded in an engine: their stubs, and the code of
+/// those compiled thus far.
+pub(crate) struct LazyFunctions {
+    /// The functions compiled thus far. The lock is held while compiling a function, so that a
+    /// function called by several threads at once is only compiled once.
+    compiled: Mutex<PrimaryMap<LocalFunctionIndex, Option<LazilyCompiled>>>,
+    engine: UniversalEngine,
+    sources: Arc<FunctionSources>,
+    custom_sections: PrimaryMap<SectionIndex, SectionBodyPtr>,
+    signatures: BoxedSlice<SignatureIndex, VMSharedSignatureIndex>,
+    /// The resolver and the stubs, followed by the slots on their own pages, which stay
+    /// writable while the code isn't.
+    stubs: Mmap,
+    /// The offset of the first stub in `stubs`.
+    first_stub: usize,
+    /// The offset of the first slot in `stubs`.
+    first_slot: usize,
+}
+
+// SAFETY: the custom sections are code and data the engine keeps until it is dropped, and which
+// aren't written to after they are published.
+unsafe impl Send for LazyFunctions {}
+unsafe impl Sync for LazyFunctions {}
+
+/// The code of a function compiled lazily.
+struct LazilyCompiled {
+    body: FunctionBodyPtr,
+    length: usize,
+    _frame_info_registration: GlobalFrameInfoRegistration,
+}
+
+impl LazyFunctions {
+    /// Create the stubs of the functions of `sources`, whose code may call the custom sections
+    /// and refer to the signatures of the module as loaded in `engine`.
+    pub(crate) fn new(
+        engine: UniversalEngine,
+        sources: Arc<FunctionSources>,
+        custom_sections: PrimaryMap<SectionIndex, SectionBodyPtr>,
+        signatures: BoxedSlice<SignatureIndex, VMSharedSignatureIndex>,
+    ) -> Result<Box<Self>, CompileError> {
+        let count = sources.bodies.len();
+        let mut functions = Box::new(Self {
+            compiled: Mutex::new(sources.bodies.keys().map(|_| None).collect()),
+            engine,
+            sources,
+            custom_sections,
+            signatures,
+            stubs: Mmap::new(),
+            first_stub: 0,
+            first_slot: 0,
+        });
+        if count == 0 {
+            return Ok(functions);
+        }
+        // The stub of the function pushes an `imm32` index, and jumps to its slot with a 32-bit
+        // displacement.
+        if i32::try_from(count).is_err() {
+            return Err(CompileError::Resource(
+                "too many functions to compile lazily".to_string(),
+            ));
+        }
+
+        let resolver = resolver_code(&*functions);
+        let first_stub = round_up(resolver.len(), STUB_SIZE);
+        let first_slot = round_up(first_stub + count * STUB_SIZE, region::page::size());
+        let mut stubs = Mmap::with_at_least(first_slot + count * std::mem::size_of::<usize>())
+            .map_err(|e| {
+                CompileError::Resource(format!("failed to allocate memory for the stubs: {}", e))
+            })?;
+        let base = stubs.as_ptr() as usize;
+        let code = stubs.as_mut_slice();
+        code[..resolver.len()].copy_from_slice(&resolver);
+        for i in 0..count {
+            let stub = first_stub + i * STUB_SIZE;
+            let slot = first_slot + i * std::mem::size_of::<usize>();
+            // jmp [rip + slot]
+            code[stub..stub + 2].copy_from_slice(&[0xff, 0x25]);
+            code[stub + 2..stub + 6].copy_from_slice(&((slot - (stub + 6)) as i32).to_le_bytes());
+            // push i
+            code[stub + 6] = 0x68;
+            code[stub + 7..stub + 11].copy_from_slice(&(i as i32).to_le_bytes());
+            // jmp resolver
+            code[stub + 11] = 0xe9;
+            code[stub + 12..stub + 16].copy_from_slice(&(-((stub + 16) as i32)).to_le_bytes());
+            // Until the function is compiled, its slot points to the entry of the resolver.
+            let entry = base + stub + 6;
+            code[slot..slot + std::mem::size_of::<usize>()].copy_from_slice(&entry.to_ne_bytes());
+        }
+        unsafe { region::protect(stubs.as_ptr(), first_slot, region::Protection::READ_EXECUTE) }
+            .map_err(|e| {
+                CompileError::Resource(format!("Error while publishing the stubs: {}", e))
+            })?;
+        functions.stubs = stubs;
+        functions.first_stub = first_stub;
+        functions.first_slot = first_slot;
+        Ok(functions)
+    }
+
+    /// The stub the function `index` is called through.
+    pub(crate) fn stub(&self, index: LocalFunctionIndex) -> FunctionBodyPtr {
+        let offset = self.first_stub + index.index() * STUB_SIZE;
+        FunctionBodyPtr(unsafe { self.stubs.as_ptr().add(offset) } as *const VMFunctionBody)
+    }
+
+    /// The slot holding the address the stub of the function `index` jumps to.
+    fn slot(&self, index: LocalFunctionIndex) -> &AtomicUsize {
+        let offset = self.first_slot + index.index() * std::mem::size_of::<usize>();
+        unsafe { &*(self.stubs.as_ptr().add(offset) as *const AtomicUsize) }
+    }
+
+    /// How many of the functions have been compiled.
+    pub(crate) fn compiled_count(&self) -> usize {
+        let compiled = self.compiled.lock().unwrap();
+        compiled.values().filter(|code| code.is_some()).count()
+    }
+
+    /// The extent of the code of the function `index`, if it has been compiled.
+    pub(crate) fn compiled_extent(&self, index: LocalFunctionIndex) -> Option<FunctionExtent> {
+        let compiled = self.compiled.lock().unwrap();
+        let code = compiled.get(index)?.as_ref()?;
+        Some(FunctionExtent {
+            address: code.body,
+            length: code.length,
+        })
+    }
+
+    /// Compile the function `index` unless it already is, and return its code.
+    fn compile(&self, index: LocalFunctionIndex) -> Result<FunctionBodyPtr, CompileError> {
+        let mut compiled = self.compiled.lock().unwrap();
+        // Another thread may have compiled the function while this one went through the stub.
+        if let Some(code) = &compiled[index] {
+            return Ok(code.body);
+        }
+        let sources = &*self.sources;
+        let range = sources.bodies[index].clone();
+        let input = FunctionBodyData {
+            data: &sources.binary[range.clone()],
+            module_offset: range.start,
+        };
+
+        let mut engine = self.engine.inner_mut();
+        let (function, eh_frame) = compile_function(&engine, sources, index, &input)?;
+        // The function gets pages of its own, which may be out of the reach of its PC-relative
+        // calls to the rest of the code, so those go through thunks appended to it.
+        let thunk_count = function
+            .relocations
+            .iter()
+            .filter(|r| needs_thunk(r, index))
+            .count();
+        let mut code = function.body.body.clone();
+        code.resize(code.len() + thunk_count * THUNK_SIZE, 0);
+        let (extent, eh_frame_address) = engine.allocate_function(
+            FunctionBodyRef {
+                body: &code,
+                ..(&function.body).into()
+            },
+            eh_frame.as_ref().map(Into::into),
+        )?;
+        let body = *extent.address as usize;
+        let mut thunk = body + function.body.body.len();
+        for r in &function.relocations {
+            let mut target = self.relocation_target(r.reloc_target, index, body, &function);
+            if needs_thunk(r, index) {
+                // SAFETY: the thunks are in the code allocated for the function, still writable.
+                let bytes = unsafe { std::slice::from_raw_parts_mut(thunk as *mut u8, THUNK_SIZE) };
+                // jmp [rip], followed by the target.
+                bytes[..6].copy_from_slice(&[0xff, 0x25, 0, 0, 0, 0]);
+                bytes[6..].copy_from_slice(&(target as u64).to_le_bytes());
+                target = thunk;
+                thunk += THUNK_SIZE;
+            }
+            patch_relocation(
+                body,
+                r,
+                target,
+                &PrimaryMap::new(),
+                &None,
+                &mut HashMap::new(),
+            );
+        }
+        let eh_frame = match (&eh_frame, eh_frame_address) {
+            (Some(section), Some(address)) => {
+                let address = *address as usize;
+                for r in &section.relocations {
+                    let target = self.relocation_target(r.reloc_target, index, body, &function);
+                    patch_relocation(
+                        address,
+                        r,
+                        target,
+                        &PrimaryMap::new(),
+                        &None,
+                        &mut HashMap::new(),
+                    );
+                }
+                Some(unsafe {
+                    std::slice::from_raw_parts(address as *const u8, section.bytes.len())
+                })
+            }
+            _ => None,
+        };
+        engine.publish_compiled_code()?;
+        engine.publish_eh_frame(eh_frame)?;
+        let profiling = engine.profiling;
+        drop(engine);
+
+        let module = &sources.compile_info.module;
+        let function_index = module.import_counts.function_index(index);
+        if let Some(strategy) = profiling {
+            let module_name = module.name();
+            let name = module.function_names.get(&function_index);
+            let published = PublishedFunction {
+                name: profiling::symbol_name(
+                    &module_name,
+                    name.map(String::as_str),
+                    function_index,
+                ),
+                extent: FunctionExtent {
+                    address: extent.address,
+                    length: extent.length,
+                },
+                address_map: &function.frame_info.address_map,
+            };
+            profiling::describe(strategy, &module_name, Some(published));
+        }
+        let frame_info_registration = register_function_frame_info(
+            module.name(),
+            module.function_names.get(&function_index).cloned(),
+            module.import_counts,
+            index,
+            FunctionExtent {
+                address: extent.address,
+                length: extent.length,
+            },
+            function.frame_info,
+        );
+        self.slot(index).store(body, Ordering::Release);
+        compiled[index] = Some(LazilyCompiled {
+            body: extent.address,
+            length: extent.length,
+            _frame_info_registration: frame_info_registration,
+        });
+        Ok(extent.address)
+    }
+
+    /// The address the relocation of the function `index`, whose code is at `body`, refers to.
+    ///
+    /// The other local functions are called through their stubs.
+    fn relocation_target(
+        &self,
+        target: RelocationTarget,
+        index: LocalFunctionIndex,
+        body: usize,
+        function: &CompiledFunction,
+    ) -> usize {
+        match target {
+            RelocationTarget::LocalFunc(callee) if callee == index => body,
+            RelocationTarget::LocalFunc(callee) => *self.stub(callee) as usize,
+            RelocationTarget::LibCall(libcall) => libcall.function_pointer(),
+            RelocationTarget::CustomSection(section) => *self.custom_sections[section] as usize,
+            RelocationTarget::JumpTable(func_index, jt) => {
+                assert_eq!(func_index, index, "jump table of another function");
+                body + function.jt_offsets[jt] as usize
+            }
+            RelocationTarget::SignatureId(signature) => {
+                self.signatures[signature].as_u32() as usize
+            }
+        }
+    }
+}
+
+/// Whether the relocation `r` of the lazily compiled function `index` is a PC-relative reference
+/// to code out of the function, which is called through a thunk.
+fn needs_thunk(r: &Relocation, index: LocalFunctionIndex) -> bool {
+    let pc_relative = matches!(
+        r.kind,
+        RelocationKind::X86PCRel4 | RelocationKind::X86CallPCRel4
+    );
+    let external = match r.reloc_target {
+        RelocationTarget::LocalFunc(callee) => callee != index,
+        RelocationTarget::JumpTable(..) => false,
+        _ => true,
+    };
+    pc_relative && external
+}
+
+#[cfg(feature = "compiler")]
+fn compile_function(
+    engine: &crate::engine::UniversalEngineInner,
+    sources: &FunctionSources,
+    index: LocalFunctionIndex,
+    input: &FunctionBodyData<'_>,
+) -> Result<(CompiledFunction, Option<CustomSection>), CompileError> {
+    engine.compiler()?.compile_function(
+        &sources.target,
+        &sources.compile_info,
+        &sources.module_translation,
+        index,
+        input,
+    )
+}
+
+#[cfg(not(feature = "compiler"))]
+fn compile_function(
+    _engine: &crate::engine::UniversalEngineInner,
+    _sources: &FunctionSources,
+    _index: LocalFunctionIndex,
+    _input: &FunctionBodyData<'_>,
+) -> Result<(CompiledFunction, Option<CustomSection>), CompileError> {
+    Err(CompileError::Codegen(
+        "The UniversalEngine is not compiled with compiler support, which is required for compiling functions lazily"
+            .to_string(),
+    ))
+}
+
+/// Called by the resolver with the index the stub of a function pushed: compiles the function
+/// and returns its code for the resolver to jump to.
+///
+/// A compilation error is raised as a trap of the call to the function.
+extern "C" fn lazy_compile(functions: *const LazyFunctions, index: usize) -> *const VMFunctionBody {
+    let functions = unsafe { &*functions };
+    let index = LocalFunctionIndex::new(index);
+    match panic::catch_unwind(AssertUnwindSafe(|| functions.compile(index))) {
+        Ok(Ok(body)) => *body,
+        Ok(Err(error)) => unsafe { raise_user_trap(Box::new(error)) },
+        Err(payload) => unsafe { resume_panic(payload) },
+    }
+}
+
+/// The size of the frame the resolver allocates below the registers it saves: on Windows, the
+/// shadow space of the arguments of `lazy_compile`, and the padding aligning the stack.
+#[cfg(target_os = "windows")]
+const RESOLVER_FRAME: u8 = 40;
+#[cfg(not(target_os = "windows"))]
+const RESOLVER_FRAME: u8 = 8;
+
+/// The code all the stubs enter until their function is compiled.
+///
+/// The arguments of a function are all in general purpose registers or on the stack, so it
+/// saves those the calling convention of the host doesn't preserve, calls `lazy_compile` with
+/// the index pushed by the stub, and returns to the compiled code in place of the index, with
+/// the registers and the stack as the caller left them.
+fn resolver_code(functions: *const LazyFunctions) -> Vec<u8> {
+    // The registers saved after the index, which is thus at this offset above them.
+    const SAVED: u8 = 9 * 8;
+    #[cfg(target_os = "windows")]
+    let (mov_first_argument, second_argument_modrm) = (0xb9, 0x54); // rcx, rdx
+    #[cfg(not(target_os = "windows"))]
+    let (mov_first_argument, second_argument_modrm) = (0xbf, 0x74); // rdi, rsi
+
+    let mut code = Vec::new();
+    // push rax; push rcx; push rdx; push rsi; push rdi; push r8; push r9; push r10; push r11
+    code.extend_from_slice(&[0x50, 0x51, 0x52, 0x56, 0x57]);
+    code.extend_from_slice(&[0x41, 0x50, 0x41, 0x51, 0x41, 0x52, 0x41, 0x53]);
+    // sub rsp, RESOLVER_FRAME
+    code.extend_from_slice(&[0x48, 0x83, 0xec, RESOLVER_FRAME]);
+    // mov <first argument>, functions
+    code.extend_from_slice(&[0x48, mov_first_argument]);
+    code.extend_from_slice(&(functions as u64).to_le_bytes());
+    // mov <second argument>, [rsp + RESOLVER_FRAME + SAVED]
+    code.extend_from_slice(&[
+        0x48,
+        0x8b,
+        second_argument_modrm,
+        0x24,
+        RESOLVER_FRAME + SAVED,
+    ]);
+    // mov rax, lazy_compile; call rax
+    code.extend_from_slice(&[0x48, 0xb8]);
+    code.extend_from_slice(&(lazy_compile as usize as u64).to_le_bytes());
+    code.extend_from_slice(&[0xff, 0xd0]);
+    // add rsp, RESOLVER_FRAME
+    code.extend_from_slice(&[0x48, 0x83, 0xc4, RESOLVER_FRAME]);
+    // mov [rsp + SAVED], rax: the compiled code replaces the index, for `ret` to jump to it
+    code.extend_from_slice(&[0x48, 0x89, 0x44, 0x24, SAVED]);
+    // pop r11; pop r10; pop r9; pop r8; pop rdi; pop rsi; pop rdx; pop rcx; pop rax
+    code.extend_from_slice(&[0x41, 0x5b, 0x41, 0x5a, 0x41, 0x59, 0x41, 0x58]);
+    code.extend_from_slice(&[0x5f, 0x5e, 0x5a, 0x59, 0x58]);
+    // ret
+    code.push(0xc3);
+    code
+}
+
+fn round_up(size: usize, multiple: usize) -> usize {
+    debug_assert!(multiple.is_power_of_two());
+    (size + (multiple - 1)) & !(multiple - 1)
+}
@@ -27,17 +27,25 @@
 mod artifact;
 mod builder;
 mod code_memory;
+mod deduplication;
 mod engine;
 mod executable;
+mod lazy;
 mod link;
+mod profiling;
+mod shared_object;
 mod unwind;
 
 pub use crate::artifact::UniversalArtifact;
-pub use crate::builder::Universal;
+pub use crate::builder::{ModuleCompileMode, Universal};
 pub use crate::code_memory::CodeMemory;
 pub use crate::engine::UniversalEngine;
-pub use crate::executable::{UniversalExecutable, UniversalExecutableRef};
+pub use crate::executable::{
+    Fingerprint, UniversalExecutable, UniversalExecutableFile, UniversalExecutableRef,
+};
+pub use crate::lazy::LazyUniversalExecutable;
 pub use crate::link::link_module;
+pub use crate::profiling::ProfilingStrategy;
 
 /// Version number of this crate.
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -5,9 +5,9 @@ use std::ptr::{read_unaligned, write_unaligned};
 use wasmer_compiler::{
     JumpTable, Relocation, RelocationKind, RelocationTarget, SectionIndex, TrampolinesSection,
 };
-use wasmer_types::entity::PrimaryMap;
-use wasmer_types::LocalFunctionIndex;
-use wasmer_vm::{SectionBodyPtr, VMLocalFunction};
+use wasmer_types::entity::{BoxedSlice, PrimaryMap};
+use wasmer_types::{LocalFunctionIndex, SignatureIndex};
+use wasmer_vm::{SectionBodyPtr, VMLocalFunction, VMSharedSignatureIndex};
 
 /// Add a new trampoline address, given the base adress of the Section. Return the address of the jump
 /// The trampoline itself still have to be writen
@@ -73,6 +73,7 @@ fn apply_relocation(
     allocated_functions: &PrimaryMap<LocalFunctionIndex, VMLocalFunction>,
     jt_offsets: impl Fn(LocalFunctionIndex, JumpTable) -> wasmer_compiler::CodeOffset,
     allocated_sections: &PrimaryMap<SectionIndex, SectionBodyPtr>,
+    signatures: &BoxedSlice<SignatureIndex, VMSharedSignatureIndex>,
     trampolines: &Option<TrampolinesSection>,
     trampolines_map: &mut HashMap<usize, usize>,
 ) {
@@ -86,15 +87,37 @@ fn apply_relocation(
             let offset = jt_offsets(func_index, jt);
             *allocated_functions[func_index].body as usize + offset as usize
         }
+        RelocationTarget::SignatureId(index) => signatures[index].as_u32() as usize,
     };
+    patch_relocation(
+        body,
+        r,
+        target_func_address,
+        allocated_sections,
+        trampolines,
+        trampolines_map,
+    );
+}
 
+/// Patch the code at `body` for the relocation `r` to refer to `target_func_address`.
+pub(crate) fn patch_relocation(
+    body: usize,
+    r: &Relocation,
+    target_func_address: usize,
+    allocated_sections: &PrimaryMap<SectionIndex, SectionBodyPtr>,
+    trampolines: &Option<TrampolinesSection>,
+    trampolines_map: &mut HashMap<usize, usize>,
+) {
     match r.kind {
+        RelocationKind::Abs4 => unsafe {
+            let (reloc_address, reloc_abs) = r.for_address(body, target_func_address as u64);
+            write_unaligned(reloc_address as *mut u32, reloc_abs as u32);
+        },
         #[cfg(target_pointer_width = "64")]
         RelocationKind::Abs8 => unsafe {
             let (reloc_address, reloc_delta) = r.for_address(body, target_func_address as u64);
             write_unaligned(reloc_address as *mut u64, reloc_delta);
         },
-        #[cfg(target_pointer_width = "32")]
         RelocationKind::X86PCRel4 => unsafe {
             let (reloc_address, reloc_delta) = r.for_address(body, target_func_address as u64);
             write_unaligned(reloc_address as *mut u32, reloc_delta as _);
@@ -166,8 +189,32 @@ fn apply_relocation(
     }
 }
 
+/// Whether code with the `relocations` can run wherever it is loaded: it only refers to itself,
+/// relatively to the program counter, and to everything else through the vmctx.
+///
+/// `is_code` tells which custom sections are loaded along with the functions.
+pub(crate) fn is_position_independent<'a>(
+    mut relocations: impl Iterator<Item = &'a Relocation>,
+    is_code: impl Fn(SectionIndex) -> bool,
+) -> bool {
+    relocations.all(|r| {
+        let pc_relative = matches!(
+            r.kind,
+            RelocationKind::X86PCRel4 | RelocationKind::X86PCRel8 | RelocationKind::X86CallPCRel4
+        );
+        let to_code = match r.reloc_target {
+            RelocationTarget::LocalFunc(_) | RelocationTarget::JumpTable(..) => true,
+            RelocationTarget::CustomSection(section) => is_code(section),
+            RelocationTarget::LibCall(_) | RelocationTarget::SignatureId(_) => false,
+        };
+        pc_relative && to_code
+    })
+}
+
 /// Links a module, patching the allocated functions with the
 /// required relocations and jump tables.
+///
+/// `signatures` are the ids the signatures of the module were registered with by the engine.
 #[tracing::instrument(skip_all)]
 pub fn link_module(
     allocated_functions: &PrimaryMap<LocalFunctionIndex, VMLocalFunction>,
@@ -175,6 +222,7 @@ pub fn link_module(
     function_relocations: impl Iterator<Item = (LocalFunctionIndex, impl Iterator<Item = Relocation>)>,
     allocated_sections: &PrimaryMap<SectionIndex, SectionBodyPtr>,
     section_relocations: impl Iterator<Item = (SectionIndex, impl Iterator<Item = Relocation>)>,
+    signatures: &BoxedSlice<SignatureIndex, VMSharedSignatureIndex>,
     trampolines: &Option<TrampolinesSection>,
 ) {
     let mut trampolines_map = fill_trampoline_map(allocated_sections, trampolines);
@@ -187,6 +235,7 @@ pub fn link_module(
                 allocated_functions,
                 &jt_offsets,
                 allocated_sections,
+                signatures,
                 trampolines,
                 &mut trampolines_map,
             );
@@ -201,6 +250,7 @@ pub fn link_module(
                 allocated_functions,
                 &jt_offsets,
                 allocated_sections,
+                signatures,
                 trampolines,
                 &mut trampolines_map,
             );
@@ -0,0 +1,268 @@
+//! Describing the code the engine publishes to external profilers, which can't otherwise
+//! attribute their samples to the anonymous memory holding it.
+
+use std::convert::TryFrom;
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write as _};
+use std::sync::Mutex;
+use wasmer_compiler::FunctionAddressMap;
+use wasmer_types::entity::EntityRef;
+use wasmer_types::FunctionIndex;
+use wasmer_vm::FunctionExtent;
+
+/// How the engine describes the functions it publishes to external profilers.
+///
+/// All the engines of a process describing their code the same way share the same file. The
+/// functions are described as their code is published: when their module is loaded, or when
+/// each of them is compiled in [`ModuleCompileMode::Lazy`](crate::ModuleCompileMode::Lazy).
+/// Failing to write the descriptions doesn't fail the compilations, it's only logged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfilingStrategy {
+    /// Append a line with the address, the size and the name of each function to
+    /// `/tmp/perf-<pid>.map`, which `perf report` reads to name the samples of JIT code.
+    PerfMap,
+    /// Write the code and the name of each function to `/tmp/jit-<pid>.dump`, in the format
+    /// `perf inject --jit` reads, along with the offsets in the wasm binary of its
+    /// instructions. The offsets are given as the line numbers of a file named after the
+    /// module.
+    JitDump,
+}
+
+/// A function whose code was just published.
+pub(crate) struct PublishedFunction<'a> {
+    /// The name of the function, see [`symbol_name`].
+    pub(crate) name: String,
+    pub(crate) extent: FunctionExtent,
+    pub(crate) address_map: &'a FunctionAddressMap,
+}
+
+/// The name profilers show for the function `index` of the module `module_name`: the one
+/// from the name section if there is one, or else its index.
+pub(crate) fn symbol_name(
+    module_name: &str,
+    function_name: Option<&str>,
+    index: FunctionIndex,
+) -> String {
+    match function_name {
+        Some(name) => format!("{}::{}", module_name, name),
+        None => format!("{}::function[{}]", module_name, index.index()),
+    }
+}
+
+/// Describe the `functions` of the module `module_name` the way `strategy` says.
+pub(crate) fn describe<'a>(
+    strategy: ProfilingStrategy,
+    module_name: &str,
+    functions: impl IntoIterator<Item = PublishedFunction<'a>>,
+) {
+    let result = match strategy {
+        ProfilingStrategy::PerfMap => write_perf_map(functions),
+        ProfilingStrategy::JitDump => write_jit_dump(module_name, functions),
+    };
+    if let Err(e) = result {
+        tracing::warn!("could not describe the code to the profiler: {}", e);
+    }
+}
+
+lazy_static::lazy_static! {
+    /// The perf map of the process, opened by the first engine describing code to it.
+    static ref PERF_MAP: Mutex<Option<File>> = Mutex::new(None);
+    /// The jitdump of the process, created by the first engine describing code to it.
+    static ref JIT_DUMP: Mutex<Option<JitDump>> = Mutex::new(None);
+}
+
+fn write_perf_map<'a>(
+    functions: impl IntoIterator<Item = PublishedFunction<'a>>,
+) -> io::Result<()> {
+    let mut lines = String::new();
+    for function in functions {
+        let address = *function.extent.address as usize;
+        writeln!(
+            lines,
+            "{:x} {:x} {}",
+            address, function.extent.length, function.name
+        )
+        .expect("writing to a string");
+    }
+    let mut perf_map = PERF_MAP.lock().unwrap();
+    if perf_map.is_none() {
+        // Appending lets other code generators of the process write to the file too.
+        let path = format!("/tmp/perf-{}.map", std::process::id());
+        *perf_map = Some(OpenOptions::new().create(true).append(true).open(path)?);
+    }
+    // A single write keeps the lines whole even if the other writers don't take the lock.
+    perf_map.as_mut().unwrap().write_all(lines.as_bytes())
+}
+
+/// The jitdump of the process, see <https://github.com/torvalds/linux/blob/master/tools/perf/Documentation/jitdump-specification.txt>.
+struct JitDump {
+    file: File,
+    /// The index of the next function described.
+    code_index: u64,
+}
+
+const JITDUMP_MAGIC: u32 = 0x4A69_5444;
+const JITDUMP_VERSION: u32 = 1;
+const JIT_CODE_LOAD: u32 = 0;
+const JIT_CODE_DEBUG_INFO: u32 = 2;
+
+/// The ELF machine of the code, which perf needs to disassemble it.
+const ELF_MACHINE: u32 = if cfg!(target_arch = "x86_64") {
+    62
+} else if cfg!(target_arch = "aarch64") {
+    183
+} else {
+    0
+};
+
+impl JitDump {
+    fn create() -> io::Result<Self> {
+        let path = format!("/tmp/jit-{}.dump", std::process::id());
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .read(true)
+            .write(true)
+            .open(path)?;
+        let mut header = Vec::with_capacity(40);
+        header.extend_from_slice(&JITDUMP_MAGIC.to_ne_bytes());
+        header.extend_from_slice(&JITDUMP_VERSION.to_ne_bytes());
+        header.extend_from_slice(&40u32.to_ne_bytes());
+        header.extend_from_slice(&ELF_MACHINE.to_ne_bytes());
+        header.extend_from_slice(&0u32.to_ne_bytes());
+        header.extend_from_slice(&std::process::id().to_ne_bytes());
+        header.extend_from_slice(&timestamp().to_ne_bytes());
+        header.extend_from_slice(&0u64.to_ne_bytes());
+        file.write_all(&header)?;
+        mark_jit_dump(&file)?;
+        Ok(Self {
+            file,
+            code_index: 0,
+        })
+    }
+
+    /// Write a record of `kind` made of `body`.
+    fn record(&mut self, kind: u32, body: &[u8]) -> io::Result<()> {
+        let mut record = Vec::with_capacity(16 + body.len());
+        record.extend_from_slice(&kind.to_ne_bytes());
+        let size = u32::try_from(16 + body.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "record over 4GiB"))?;
+        record.extend_from_slice(&size.to_ne_bytes());
+        record.extend_from_slice(&timestamp().to_ne_bytes());
+        record.extend_from_slice(body);
+        self.file.write_all(&record)
+    }
+
+    fn describe(&mut self, module_name: &str, function: &PublishedFunction<'_>) -> io::Result<()> {
+        let address = *function.extent.address as usize as u64;
+        // The debug information must precede the code it describes.
+        let lines = function
+            .address_map
+            .instructions
+            .iter()
+            .filter(|instruction| !instruction.srcloc.is_default());
+        let mut body = Vec::new();
+        body.extend_from_slice(&address.to_ne_bytes());
+        body.extend_from_slice(&(lines.clone().count() as u64).to_ne_bytes());
+        for instruction in lines {
+            let line_address = address + instruction.code_offset as u64;
+            body.extend_from_slice(&line_address.to_ne_bytes());
+            body.extend_from_slice(&instruction.srcloc.bits().to_ne_bytes());
+            body.extend_from_slice(&0u32.to_ne_bytes());
+            body.extend_from_slice(module_name.as_bytes());
+            body.push(0);
+        }
+        self.record(JIT_CODE_DEBUG_INFO, &body)?;
+
+        // SAFETY: the code was just published, and the engine keeps it until it is dropped.
+        let code = unsafe {
+            std::slice::from_raw_parts(
+                *function.extent.address as *const u8,
+                function.extent.length,
+            )
+        };
+        let mut body = Vec::with_capacity(40 + function.name.len() + 1 + code.len());
+        body.extend_from_slice(&std::process::id().to_ne_bytes());
+        body.extend_from_slice(&thread_id().to_ne_bytes());
+        body.extend_from_slice(&address.to_ne_bytes());
+        body.extend_from_slice(&address.to_ne_bytes());
+        body.extend_from_slice(&(code.len() as u64).to_ne_bytes());
+        body.extend_from_slice(&self.code_index.to_ne_bytes());
+        body.extend_from_slice(function.name.as_bytes());
+        body.push(0);
+        body.extend_from_slice(code);
+        self.record(JIT_CODE_LOAD, &body)?;
+        self.code_index += 1;
+        Ok(())
+    }
+}
+
+fn write_jit_dump<'a>(
+    module_name: &str,
+    functions: impl IntoIterator<Item = PublishedFunction<'a>>,
+) -> io::Result<()> {
+    let mut jit_dump = JIT_DUMP.lock().unwrap();
+    if jit_dump.is_none() {
+        *jit_dump = Some(JitDump::create()?);
+    }
+    let jit_dump = jit_dump.as_mut().unwrap();
+    for function in functions {
+        jit_dump.describe(module_name, &function)?;
+    }
+    Ok(())
+}
+
+/// Map the jitdump `file` in the process, which is how `perf record` finds it.
+///
+/// The mapping is kept until the process exits.
+#[cfg(unix)]
+fn mark_jit_dump(file: &File) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    let page_size = region::page::size();
+    let address = unsafe {
+        libc::mmap(
+            std::ptr::null_mut(),
+            page_size,
+            libc::PROT_READ | libc::PROT_EXEC,
+            libc::MAP_PRIVATE,
+            file.as_raw_fd(),
+            0,
+        )
+    };
+    if address == libc::MAP_FAILED {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn mark_jit_dump(_file: &File) -> io::Result<()> {
+    Ok(())
+}
+
+/// The time of the records, from the monotonic clock `perf record -k mono` uses.
+#[cfg(unix)]
+fn timestamp() -> u64 {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut time) };
+    time.tv_sec as u64 * 1_000_000_000 + time.tv_nsec as u64
+}
+
+#[cfg(not(unix))]
+fn timestamp() -> u64 {
+    0
+}
+
+#[cfg(target_os = "linux")]
+fn thread_id() -> u32 {
+    unsafe { libc::syscall(libc::SYS_gettid) as u32 }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn thread_id() -> u32 {
+    std::process::id()
+}
@@ -0,0 +1,498 @@
+//! Executables written as relocatable object files, linked into shared objects that the engine
+//! loads without making any memory executable itself.
+//!
+//! The object file defines a symbol for each piece of code or data of the executable:
+//!
+//! - `wasmer_function_<index>` for the local function `index`,
+//! - `wasmer_trampoline_<index>` for the call trampoline of the signature `index`,
+//! - `wasmer_dynamic_trampoline_<index>` for the dynamic trampoline of the function `index`,
+//! - `wasmer_section_<index>` for the custom section `index`,
+//!
+//! along with `wasmer_code`, of `wasmer_code_length` bytes, spanning all the code, and
+//! `wasmer_metadata`, of `wasmer_metadata_length` bytes, holding the serialized executable the
+//! engine loads everything else from. The lengths are little-endian `u64`s.
+
+use crate::UniversalExecutable;
+use object::write::{
+    Object, Relocation as ObjectRelocation, SectionId, StandardSection, Symbol, SymbolSection,
+};
+use object::{RelocationEncoding, SymbolFlags, SymbolKind, SymbolScope};
+use wasmer_compiler::{
+    Architecture, BinaryFormat, CompileError, CustomSectionProtection, Relocation, RelocationKind,
+    RelocationTarget, SectionIndex, Symbol as CompiledSymbol, SymbolRegistry, Triple,
+};
+use wasmer_types::entity::{EntityRef, PrimaryMap};
+use wasmer_types::{FunctionIndex, LocalFunctionIndex, SignatureIndex};
+
+/// The names of the symbols of the object files.
+pub(crate) struct ObjectSymbols;
+
+impl SymbolRegistry for ObjectSymbols {
+    fn symbol_to_name(&self, symbol: CompiledSymbol) -> String {
+        match symbol {
+            CompiledSymbol::LocalFunction(index) => format!("wasmer_function_{}", index.index()),
+            CompiledSymbol::Section(index) => format!("wasmer_section_{}", index.index()),
+            CompiledSymbol::FunctionCallTrampoline(index) => {
+                format!("wasmer_trampoline_{}", index.index())
+            }
+            CompiledSymbol::DynamicFunctionTrampoline(index) => {
+                format!("wasmer_dynamic_trampoline_{}", index.index())
+            }
+        }
+    }
+
+    fn name_to_symbol(&self, name: &str) -> Option<CompiledSymbol> {
+        let index = |prefix: &str| name.strip_prefix(prefix)?.parse::<usize>().ok();
+        if let Some(index) = index("wasmer_function_") {
+            Some(CompiledSymbol::LocalFunction(LocalFunctionIndex::new(
+                index,
+            )))
+        } else if let Some(index) = index("wasmer_section_") {
+            Some(CompiledSymbol::Section(SectionIndex::new(index)))
+        } else if let Some(index) = index("wasmer_trampoline_") {
+            Some(CompiledSymbol::FunctionCallTrampoline(SignatureIndex::new(
+                index,
+            )))
+        } else {
+            index("wasmer_dynamic_trampoline_")
+                .map(|index| CompiledSymbol::DynamicFunctionTrampoline(FunctionIndex::new(index)))
+        }
+    }
+}
+
+/// The name of `symbol` in the object files.
+fn name(symbol: CompiledSymbol) -> String {
+    ObjectSymbols.symbol_to_name(symbol)
+}
+
+const CODE_SYMBOL: &str = "wasmer_code";
+const CODE_LENGTH_SYMBOL: &str = "wasmer_code_length";
+const METADATA_SYMBOL: &str = "wasmer_metadata";
+const METADATA_LENGTH_SYMBOL: &str = "wasmer_metadata_length";
+
+/// The alignment of the pieces of the executable in the sections of the object file, which
+/// keeps the functions as aligned as the engine allocates them, and the metadata as aligned as
+/// its deserialization needs.
+const ALIGNMENT: u64 = 16;
+
+/// Appends `bytes` to `section`, under the exported symbol `name`, and returns their offset.
+fn define(
+    object: &mut Object,
+    section: SectionId,
+    name: String,
+    kind: SymbolKind,
+    bytes: &[u8],
+) -> u64 {
+    let offset = object.append_section_data(section, bytes, ALIGNMENT);
+    object.add_symbol(Symbol {
+        name: name.into_bytes(),
+        value: offset,
+        size: bytes.len() as u64,
+        kind,
+        scope: SymbolScope::Dynamic,
+        weak: false,
+        section: SymbolSection::Section(section),
+        flags: SymbolFlags::None,
+    });
+    offset
+}
+
+impl UniversalExecutable {
+    /// Writes the executable as a relocatable ELF object file, to be linked into a shared
+    /// object loaded with
+    /// [`UniversalEngine::load_shared_object`](crate::UniversalEngine::load_shared_object).
+    ///
+    /// The relocations of the code become relocations of the object file, resolved once and
+    /// for all when it is linked, so the code must be
+    /// [position-independent](crate::UniversalArtifact::is_position_independent). Only x86-64
+    /// ELF targets are supported.
+    pub fn write_object(&self) -> Result<Vec<u8>, CompileError> {
+        let triple: Triple = self
+            .triple
+            .parse()
+            .map_err(|e| CompileError::UnsupportedTarget(format!("{}: {}", self.triple, e)))?;
+        if triple.architecture != Architecture::X86_64 || triple.binary_format != BinaryFormat::Elf
+        {
+            return Err(CompileError::UnsupportedTarget(format!(
+                "object files for {}",
+                triple
+            )));
+        }
+        if !self.position_independent {
+            return Err(CompileError::UnsupportedFeature(
+                "object files of code that is not position-independent".to_string(),
+            ));
+        }
+        let mut object = Object::new(
+            object::BinaryFormat::Elf,
+            object::Architecture::X86_64,
+            object::Endianness::Little,
+        );
+        let text = object.section_id(StandardSection::Text);
+        let data = object.section_id(StandardSection::Data);
+        let rodata = object.section_id(StandardSection::ReadOnlyData);
+        let mut code_length = 0;
+        let mut define_code = |object: &mut Object, name, bytes: &[u8]| {
+            let offset = define(object, text, name, SymbolKind::Text, bytes);
+            code_length = offset + bytes.len() as u64;
+            offset
+        };
+
+        let mut functions = PrimaryMap::<LocalFunctionIndex, u64>::new();
+        for (index, body) in self.function_bodies.iter() {
+            functions.push(define_code(
+                &mut object,
+                name(CompiledSymbol::LocalFunction(index)),
+                &body.body,
+            ));
+        }
+        for (index, body) in self.function_call_trampolines.iter() {
+            define_code(
+                &mut object,
+                name(CompiledSymbol::FunctionCallTrampoline(index)),
+                &body.body,
+            );
+        }
+        for (index, body) in self.dynamic_function_trampolines.iter() {
+            define_code(
+                &mut object,
+                name(CompiledSymbol::DynamicFunctionTrampoline(index)),
+                &body.body,
+            );
+        }
+        // The data sections may be relocated when the shared object is loaded, so they are
+        // writable.
+        let mut sections = PrimaryMap::<SectionIndex, (SectionId, u64)>::new();
+        for (index, section) in self.custom_sections.iter() {
+            let bytes = section.bytes.as_slice();
+            let offset = match section.protection {
+                CustomSectionProtection::ReadExecute => (
+                    text,
+                    define_code(&mut object, name(CompiledSymbol::Section(index)), bytes),
+                ),
+                CustomSectionProtection::Read => (
+                    data,
+                    define(
+                        &mut object,
+                        data,
+                        name(CompiledSymbol::Section(index)),
+                        SymbolKind::Data,
+                        bytes,
+                    ),
+                ),
+            };
+            sections.push(offset);
+        }
+        object.add_symbol(Symbol {
+            name: CODE_SYMBOL.as_bytes().to_vec(),
+            value: 0,
+            size: code_length,
+            kind: SymbolKind::Text,
+            scope: SymbolScope::Dynamic,
+            weak: false,
+            section: SymbolSection::Section(text),
+            flags: SymbolFlags::None,
+        });
+        define(
+            &mut object,
+            rodata,
+            CODE_LENGTH_SYMBOL.to_string(),
+            SymbolKind::Data,
+            &code_length.to_le_bytes(),
+        );
+
+        let function_relocations = self
+            .function_relocations
+            .iter()
+            .map(|(index, relocations)| ((text, functions[index]), relocations));
+        let section_relocations = self
+            .custom_section_relocations
+            .iter()
+            .map(|(index, relocations)| (sections[index], relocations));
+        for ((section, offset), relocations) in function_relocations.chain(section_relocations) {
+            for relocation in relocations {
+                let (target_section, target_offset) = match relocation.reloc_target {
+                    RelocationTarget::LocalFunc(index) => (text, functions[index]),
+                    RelocationTarget::JumpTable(index, table) => (
+                        text,
+                        functions[index] + u64::from(self.function_jt_offsets[index][table]),
+                    ),
+                    RelocationTarget::CustomSection(index) => sections[index],
+                    target => {
+                        return Err(CompileError::UnsupportedFeature(format!(
+                            "object files with relocations to {:?}",
+                            target
+                        )))
+                    }
+                };
+                let symbol = object.section_symbol(target_section);
+                let relocation = object_relocation(relocation, offset, symbol, target_offset)?;
+                object
+                    .add_relocation(section, relocation)
+                    .map_err(|e| CompileError::Codegen(e.to_string()))?;
+            }
+        }
+
+        let metadata = wasmer_engine::Executable::serialize(self)
+            .map_err(|e| CompileError::Codegen(e.to_string()))?;
+        define(
+            &mut object,
+            rodata,
+            METADATA_SYMBOL.to_string(),
+            SymbolKind::Data,
+            &metadata,
+        );
+        define(
+            &mut object,
+            rodata,
+            METADATA_LENGTH_SYMBOL.to_string(),
+            SymbolKind::Data,
+            &(metadata.len() as u64).to_le_bytes(),
+        );
+        object
+            .write()
+            .map_err(|e| CompileError::Codegen(e.to_string()))
+    }
+}
+
+/// The relocation of the object file for `relocation` of the code at `offset` in its section,
+/// whose target is at `target_offset` from `symbol`.
+fn object_relocation(
+    relocation: &Relocation,
+    offset: u64,
+    symbol: object::write::SymbolId,
+    target_offset: u64,
+) -> Result<ObjectRelocation, CompileError> {
+    // Both compute the address of the target plus the addend, minus the address of the
+    // relocation for the PC-relative ones, as the engine does when it links the code.
+    let (kind, size) = match relocation.kind {
+        RelocationKind::Abs4 => (object::RelocationKind::Absolute, 32),
+        RelocationKind::Abs8 => (object::RelocationKind::Absolute, 64),
+        RelocationKind::X86PCRel4 | RelocationKind::X86CallPCRel4 => {
+            (object::RelocationKind::Relative, 32)
+        }
+        RelocationKind::X86PCRel8 => (object::RelocationKind::Relative, 64),
+        kind => {
+            return Err(CompileError::UnsupportedFeature(format!(
+                "object files with {} relocations",
+                kind
+            )))
+        }
+    };
+    Ok(ObjectRelocation {
+        offset: offset + u64::from(relocation.offset),
+        size,
+        kind,
+        encoding: RelocationEncoding::Generic,
+        symbol,
+        addend: target_offset as i64 + relocation.addend,
+    })
+}
+
+pub(crate) use self::loader::SharedObject;
+
+mod loader {
+    use super::*;
+    use crate::unwind::UnwindRegistry;
+    use std::convert::TryFrom;
+    use std::ffi::{c_void, CString};
+    use std::path::Path;
+    use std::sync::Mutex;
+    use wasmer_compiler::{CustomSectionRef, FunctionBodyRef};
+    use wasmer_engine::DeserializeError;
+    use wasmer_vm::{
+        FunctionBodyPtr, FunctionExtent, SectionBodyPtr, VMFunctionBody, VMLocalFunction,
+        VMSharedSignatureIndex, VMTrampoline,
+    };
+
+    /// The last error of the dynamic loader.
+    #[cfg(unix)]
+    unsafe fn dlerror() -> String {
+        let error = libc::dlerror();
+        if error.is_null() {
+            "unknown error".to_string()
+        } else {
+            std::ffi::CStr::from_ptr(error)
+                .to_string_lossy()
+                .into_owned()
+        }
+    }
+
+    /// A shared object linked from the object file of an executable, loaded in the process
+    /// until it is dropped.
+    pub(crate) struct SharedObject {
+        handle: *mut c_void,
+        /// The registration of the `.eh_frame` section of the executable, which is removed
+        /// before the shared object is unloaded.
+        unwind: Mutex<Option<UnwindRegistry>>,
+    }
+
+    // SAFETY: the handles of the dynamic loader can be used from any thread.
+    unsafe impl Send for SharedObject {}
+    unsafe impl Sync for SharedObject {}
+
+    impl SharedObject {
+        /// Loads the shared object at `path`.
+        ///
+        /// # Safety
+        ///
+        /// The initializers of the shared object are run.
+        #[cfg(unix)]
+        pub(crate) unsafe fn open(path: &Path) -> Result<Self, DeserializeError> {
+            use std::os::unix::ffi::OsStrExt;
+            let c_path = CString::new(path.as_os_str().as_bytes())
+                .map_err(|e| DeserializeError::Generic(e.to_string()))?;
+            let handle = libc::dlopen(c_path.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL);
+            if handle.is_null() {
+                return Err(DeserializeError::Generic(format!(
+                    "could not load the shared object {}: {}",
+                    path.display(),
+                    dlerror()
+                )));
+            }
+            Ok(Self {
+                handle,
+                unwind: Mutex::new(None),
+            })
+        }
+
+        /// Shared objects are only loaded on Unix.
+        #[cfg(not(unix))]
+        pub(crate) unsafe fn open(path: &Path) -> Result<Self, DeserializeError> {
+            Err(DeserializeError::Generic(format!(
+                "could not load the shared object {}: not supported on this platform",
+                path.display()
+            )))
+        }
+
+        /// The address of the symbol `name`.
+        fn symbol(&self, name: &str) -> Result<*const u8, CompileError> {
+            let c_name = CString::new(name).expect("the symbols have no nul byte");
+            // SAFETY: the handle is open until the shared object is dropped.
+            #[cfg(unix)]
+            let address = unsafe { libc::dlsym(self.handle, c_name.as_ptr()) };
+            #[cfg(not(unix))]
+            let address: *mut c_void = unreachable!("{:?} of a shared object opened", c_name);
+            if address.is_null() {
+                return Err(CompileError::Resource(format!(
+                    "the shared object has no symbol {}",
+                    name
+                )));
+            }
+            Ok(address as *const u8)
+        }
+
+        /// The bytes of `symbol`, whose length is the `u64` of `length_symbol`.
+        fn bytes(&self, symbol: &str, length_symbol: &str) -> Result<&[u8], CompileError> {
+            let address = self.symbol(symbol)?;
+            // SAFETY: the object file defines both symbols, the length being unaligned data.
+            unsafe {
+                let length = std::ptr::read_unaligned(self.symbol(length_symbol)? as *const u64);
+                Ok(std::slice::from_raw_parts(address, length as usize))
+            }
+        }
+
+        /// The serialized executable the shared object was linked from.
+        pub(crate) fn metadata(&self) -> Result<&[u8], DeserializeError> {
+            self.bytes(METADATA_SYMBOL, METADATA_LENGTH_SYMBOL)
+                .map_err(|e| DeserializeError::Incompatible(e.to_string()))
+        }
+
+        /// The extent of the code of the shared object.
+        pub(crate) fn code(&self) -> Result<FunctionExtent, CompileError> {
+            let code = self.bytes(CODE_SYMBOL, CODE_LENGTH_SYMBOL)?;
+            Ok(FunctionExtent {
+                address: FunctionBodyPtr(code.as_ptr() as *const VMFunctionBody),
+                length: code.len(),
+            })
+        }
+
+        /// Finds the code and the sections of the executable in the shared object, the way
+        /// `UniversalEngineInner::allocate` allocates them.
+        #[allow(clippy::type_complexity)]
+        pub(crate) fn resolve<'a>(
+            &self,
+            local_functions: impl ExactSizeIterator<Item = FunctionBodyRef<'a>>,
+            call_trampolines: impl ExactSizeIterator<Item = FunctionBodyRef<'a>>,
+            dynamic_trampolines: impl ExactSizeIterator<Item = FunctionBodyRef<'a>>,
+            custom_sections: impl ExactSizeIterator<Item = CustomSectionRef<'a>>,
+            function_signature: impl Fn(LocalFunctionIndex) -> (SignatureIndex, VMSharedSignatureIndex),
+        ) -> Result<
+            (
+                PrimaryMap<LocalFunctionIndex, VMLocalFunction>,
+                PrimaryMap<SignatureIndex, VMTrampoline>,
+                PrimaryMap<FunctionIndex, FunctionBodyPtr>,
+                PrimaryMap<SectionIndex, SectionBodyPtr>,
+            ),
+            CompileError,
+        > {
+            let trampolines = (0..call_trampolines.len())
+                .map(|index| {
+                    let address = self.symbol(&name(CompiledSymbol::FunctionCallTrampoline(
+                        SignatureIndex::new(index),
+                    )))?;
+                    // SAFETY: the symbol is the trampoline, compiled with the calling convention
+                    // of trampolines.
+                    Ok(unsafe { std::mem::transmute::<*const u8, VMTrampoline>(address) })
+                })
+                .collect::<Result<PrimaryMap<SignatureIndex, _>, CompileError>>()?;
+            let functions = local_functions
+                .enumerate()
+                .map(|(index, body)| {
+                    let index = LocalFunctionIndex::new(index);
+                    let (sig_idx, sig) = function_signature(index);
+                    Ok(VMLocalFunction {
+                        body: FunctionBodyPtr(
+                            self.symbol(&name(CompiledSymbol::LocalFunction(index)))?
+                                as *const VMFunctionBody,
+                        ),
+                        length: u32::try_from(body.body.len()).map_err(|_| {
+                            CompileError::Codegen("function body length exceeds 4GiB".into())
+                        })?,
+                        signature: sig,
+                        trampoline: trampolines[sig_idx],
+                    })
+                })
+                .collect::<Result<PrimaryMap<LocalFunctionIndex, _>, CompileError>>()?;
+            let dynamic_trampolines = (0..dynamic_trampolines.len())
+                .map(|index| {
+                    let symbol = name(CompiledSymbol::DynamicFunctionTrampoline(
+                        FunctionIndex::new(index),
+                    ));
+                    Ok(FunctionBodyPtr(
+                        self.symbol(&symbol)? as *const VMFunctionBody
+                    ))
+                })
+                .collect::<Result<PrimaryMap<FunctionIndex, _>, CompileError>>()?;
+            let sections = (0..custom_sections.len())
+                .map(|index| {
+                    let symbol = name(CompiledSymbol::Section(SectionIndex::new(index)));
+                    Ok(SectionBodyPtr(self.symbol(&symbol)?))
+                })
+                .collect::<Result<PrimaryMap<SectionIndex, _>, CompileError>>()?;
+            Ok((functions, trampolines, dynamic_trampolines, sections))
+        }
+
+        /// Registers the unwind information of the code.
+        pub(crate) fn publish_eh_frame(&self, eh_frame: Option<&[u8]>) -> Result<(), CompileError> {
+            let mut registry = UnwindRegistry::new();
+            registry.publish(eh_frame).map_err(|e| {
+                CompileError::Resource(format!("Error while publishing the unwind code: {}", e))
+            })?;
+            *self.unwind.lock().unwrap() = Some(registry);
+            Ok(())
+        }
+    }
+
+    impl Drop for SharedObject {
+        fn drop(&mut self) {
+            self.unwind.get_mut().unwrap().take();
+            // SAFETY: the artifact holding the shared object is dropped, and with it every
+            // instance that could run its code.
+            #[cfg(unix)]
+            unsafe {
+                libc::dlclose(self.handle);
+            }
+        }
+    }
+}
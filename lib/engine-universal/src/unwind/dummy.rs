@@ -1,6 +1,6 @@
 //! Module for Dummy unwind registry.
 
-use wasmer_compiler::CompiledFunctionUnwindInfo;
+use wasmer_compiler::CompiledFunctionUnwindInfoRef;
 
 /// Represents a registry of function unwind information when the host system
 /// support any one in specific.
@@ -18,14 +18,14 @@ impl DummyUnwindRegistry {
         _base_address: usize,
         _func_start: u32,
         _func_len: u32,
-        _info: &CompiledFunctionUnwindInfo,
+        _info: CompiledFunctionUnwindInfoRef<'_>,
     ) -> Result<(), String> {
         // Do nothing
         Ok(())
     }
 
     /// Publishes all registered functions.
-    pub fn publish(&mut self, eh_frame: Option<&[u8]>) -> Result<(), String> {
+    pub fn publish(&mut self, _eh_frame: Option<&[u8]>) -> Result<(), String> {
         // Do nothing
         Ok(())
     }
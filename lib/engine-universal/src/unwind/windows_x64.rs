@@ -3,7 +3,7 @@
 
 //! Module for Windows x64 ABI unwind registry.
 use std::collections::HashMap;
-use wasmer_compiler::CompiledFunctionUnwindInfo;
+use wasmer_compiler::CompiledFunctionUnwindInfoRef;
 use winapi::um::winnt;
 
 /// Represents a registry of function unwind information for Windows x64 ABI.
@@ -28,14 +28,14 @@ impl UnwindRegistry {
         base_address: usize,
         func_start: u32,
         func_len: u32,
-        info: &CompiledFunctionUnwindInfo,
+        info: CompiledFunctionUnwindInfoRef<'_>,
     ) -> Result<(), String> {
         if self.published {
             return Err("unwind registry has already been published".to_string());
         }
 
         match info {
-            CompiledFunctionUnwindInfo::WindowsX64(_) => {}
+            CompiledFunctionUnwindInfoRef::WindowsX64(_) => {}
             _ => return Err("unsupported unwind information".to_string()),
         };
 
@@ -2,7 +2,7 @@
 
 use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
 use std::sync::Arc;
-use wasmer_compiler::{CompileError, Target};
+use wasmer_compiler::{CompileError, CompileProgress, Target};
 use wasmer_types::{FunctionType, FunctionTypeRef};
 use wasmer_vm::{Artifact, Tunables, VMCallerCheckedAnyfunc, VMFuncRef, VMSharedSignatureIndex};
 
@@ -10,6 +10,26 @@ mod private {
     pub struct Internal(pub(super) ());
 }
 
+/// The executable memory the code of the modules loaded by an [`Engine`] takes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CodeMemoryUsage {
+    /// The bytes of the pages mapped executable.
+    pub allocated: usize,
+    /// The bytes of those pages holding functions and executable sections, with their padding.
+    pub used: usize,
+}
+
+/// How often an [`Engine`] reused the modules it compiled from the same binaries.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// The modules which reused one loaded earlier.
+    pub hits: u64,
+    /// The modules which were compiled.
+    pub misses: u64,
+    /// The bytes of code the hits didn't load again.
+    pub bytes_saved: u64,
+}
+
 /// A unimplemented Wasmer `Engine`.
 ///
 /// This trait is used by implementors to implement custom engines
@@ -39,6 +59,22 @@ pub trait Engine {
         tunables: &dyn Tunables,
     ) -> Result<Box<dyn crate::Executable>, CompileError>;
 
+    /// Compile a WebAssembly binary, reporting the progress of the compilation to `progress`
+    ///
+    /// Engines that can't observe the compilation of individual functions only check for
+    /// cancellation before compiling.
+    fn compile_with_progress(
+        &self,
+        binary: &[u8],
+        tunables: &dyn Tunables,
+        progress: &CompileProgress,
+    ) -> Result<Box<dyn crate::Executable>, CompileError> {
+        if progress.is_cancelled() {
+            return Err(CompileError::Cancelled);
+        }
+        self.compile(binary, tunables)
+    }
+
     /// Load a compiled executable with this engine.
     fn load(&self, executable: &(dyn crate::Executable))
         -> Result<Arc<dyn Artifact>, CompileError>;
@@ -53,6 +89,20 @@ pub trait Engine {
     /// Clone the engine
     fn cloned(&self) -> Arc<dyn Engine + Send + Sync>;
 
+    /// The executable memory taken by the code of the modules this engine loaded.
+    ///
+    /// Engines which don't map the code themselves report none.
+    fn code_memory_usage(&self) -> CodeMemoryUsage {
+        CodeMemoryUsage::default()
+    }
+
+    /// How often this engine reused the modules it compiled from the same binaries.
+    ///
+    /// Engines which don't reuse modules report none.
+    fn cache_stats(&self) -> CacheStats {
+        CacheStats::default()
+    }
+
     /// Internal: support for downcasting `Engine`s.
     #[doc(hidden)]
     fn type_id(&self, _: private::Internal) -> std::any::TypeId
@@ -1,5 +1,6 @@
 //! The WebAssembly possible errors
 use crate::trap::RuntimeError;
+use std::fmt;
 use std::io;
 use thiserror::Error;
 use wasmer_compiler::CompileError;
@@ -21,6 +22,15 @@ pub enum DeserializeError {
     /// The provided binary is corrupted
     #[error("corrupted binary: {0}")]
     CorruptedBinary(String),
+    /// The binary was compiled with another compiler, features, CPU
+    /// features or calling convention than the engine loading it expects
+    #[error("incompatible binary: expected {expected}, found {found}")]
+    IncompatibleFingerprint {
+        /// The settings this engine expected, among those that differ
+        expected: String,
+        /// The settings the binary was compiled with, among those that differ
+        found: String,
+    },
     /// The binary was valid, but we got an error when
     /// trying to allocate the required resources.
     #[error(transparent)]
@@ -57,6 +67,11 @@ pub enum LinkError {
     #[error("Error while importing {0:?}.{1:?}: {2}")]
     Import(String, String, ImportError),
 
+    /// Some imports were expected but not provided. All of them are listed, in the order
+    /// the module imports them.
+    #[error("unknown imports: {}", display_unknown_imports(.0))]
+    UnknownImports(Vec<UnknownImport>),
+
     /// A trap ocurred during linking.
     #[error("RuntimeError occurred during linking: {0}")]
     Trap(#[source] RuntimeError),
@@ -66,6 +81,43 @@ pub enum LinkError {
     Resource(String),
 }
 
+/// An import that a module expects but that was not provided.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownImport {
+    /// The position of the import in the import section of the module.
+    pub index: u32,
+    /// The module name of the import.
+    pub module: String,
+    /// The field name of the import.
+    pub field: String,
+    /// The name the name section of the module gives the function the import is for.
+    pub function_name: Option<String>,
+    /// The type the module expects the import to have.
+    pub ty: ExternType,
+}
+
+impl fmt::Display for UnknownImport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "#{} {:?}.{:?} ({})",
+            self.index, self.module, self.field, self.ty
+        )?;
+        if let Some(name) = &self.function_name {
+            write!(f, " required by function '{}'", name)?;
+        }
+        Ok(())
+    }
+}
+
+fn display_unknown_imports(imports: &[UnknownImport]) -> String {
+    imports
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
 /// An error while instantiating a module.
 ///
 /// This is not a common WebAssembly error, however
@@ -84,6 +136,10 @@ pub enum InstantiationError {
     #[error("module compiled with CPU feature that is missing from host")]
     CpuFeature(String),
 
+    /// The module was compiled for a target the current host can't run.
+    #[error("module compiled for another target: {0}")]
+    IncompatibleTarget(String),
+
     /// A runtime error occured while invoking the start function
     #[error(transparent)]
     Start(RuntimeError),
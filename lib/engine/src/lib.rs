@@ -26,8 +26,10 @@ mod executable;
 mod resolver;
 mod trap;
 
-pub use crate::engine::{Engine, EngineId};
-pub use crate::error::{DeserializeError, ImportError, InstantiationError, LinkError};
+pub use crate::engine::{CacheStats, CodeMemoryUsage, Engine, EngineId};
+pub use crate::error::{
+    DeserializeError, ImportError, InstantiationError, LinkError, UnknownImport,
+};
 pub use crate::executable::Executable;
 pub use crate::resolver::resolve_imports;
 pub use crate::trap::*;
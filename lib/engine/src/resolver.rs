@@ -1,7 +1,7 @@
 //! Define the `Resolver` trait, allowing custom resolution for external
 //! references.
 
-use crate::{Engine, ImportError, LinkError};
+use crate::{Engine, ImportError, LinkError, UnknownImport};
 use more_asserts::assert_ge;
 use wasmer_types::entity::{BoxedSlice, EntityRef, PrimaryMap};
 use wasmer_types::{ExternType, FunctionIndex, ImportCounts, MemoryType, TableType};
@@ -24,12 +24,15 @@ fn is_compatible_memory(ex: &MemoryType, im: &MemoryType) -> bool {
         && (im.maximum.is_none()
             || (!ex.maximum.is_none() && im.maximum.unwrap() >= ex.maximum.unwrap()))
         && ex.shared == im.shared
+        && ex.memory64 == im.memory64
 }
 
 /// This function allows to match all imports of a `ModuleInfo` with concrete definitions provided by
 /// a `Resolver`.
 ///
 /// If all imports are satisfied returns an `Imports` instance required for a module instantiation.
+/// Otherwise, either the first import of the wrong type or every import that the resolver
+/// doesn't provide is reported.
 pub fn resolve_imports(
     engine: &dyn Engine,
     resolver: &dyn Resolver,
@@ -43,18 +46,22 @@ pub fn resolve_imports(
     let mut table_imports = PrimaryMap::with_capacity(import_counts.tables as _);
     let mut memory_imports = PrimaryMap::with_capacity(import_counts.memories as _);
     let mut global_imports = PrimaryMap::with_capacity(import_counts.globals as _);
+    let mut tag_imports = PrimaryMap::with_capacity(import_counts.tags as _);
+    let mut instances = Vec::new();
+    let mut unknown_imports = Vec::new();
     for VMImport {
         import_no,
         module,
         field,
+        function_name,
         ty,
     } in imports
     {
-        let resolved = resolver.resolve(*import_no, module, field);
-        let import_extern = || match ty {
+        let expected = match ty {
             &VMImportType::Table(t) => ExternType::Table(t),
             &VMImportType::Memory(t, _) => ExternType::Memory(t),
             &VMImportType::Global(t) => ExternType::Global(t),
+            VMImportType::Tag(t) => ExternType::Tag(t.clone()),
             &VMImportType::Function {
                 sig,
                 static_trampoline: _,
@@ -64,16 +71,25 @@ pub fn resolve_imports(
                     .expect("VMSharedSignatureIndex is not valid?"),
             ),
         };
+        let resolved = resolver.resolve(*import_no, module, field, &expected);
         let resolved = match resolved {
             Some(r) => r,
             None => {
-                return Err(LinkError::Import(
-                    module.to_string(),
-                    field.to_string(),
-                    ImportError::UnknownImport(import_extern()),
-                ));
+                unknown_imports.push(UnknownImport {
+                    index: *import_no,
+                    module: module.to_string(),
+                    field: field.to_string(),
+                    function_name: function_name.clone(),
+                    ty: expected,
+                });
+                continue;
             }
         };
+        if !unknown_imports.is_empty() {
+            // Linking fails anyway, only the remaining unknown imports matter now, and
+            // building the import would clone host envs that are never dropped.
+            continue;
+        }
         let export_extern = || match resolved {
             Export::Function(ref f) => ExternType::Function(
                 engine
@@ -87,6 +103,7 @@ pub fn resolve_imports(
                 let global = g.from.ty();
                 ExternType::Global(*global)
             }
+            Export::Tag(ref t) => ExternType::Tag(t.ty().clone()),
         };
         match (&resolved, ty) {
             (
@@ -96,6 +113,17 @@ pub fn resolve_imports(
                     static_trampoline,
                 },
             ) if ex.vm_function.signature == *sig => {
+                // Functions exported by another instance are called directly, with its
+                // `VMContext`, so that instance has to stay alive.
+                if let Some(instance_ref) = &ex.vm_function.instance_ref {
+                    let instance_ref = instance_ref.upgrade().ok_or_else(|| {
+                        LinkError::Resource(format!(
+                            "the instance exporting {:?}.{:?} was dropped",
+                            module, field
+                        ))
+                    })?;
+                    instances.push(instance_ref);
+                }
                 let address = match ex.vm_function.kind {
                     VMFunctionKind::Dynamic => {
                         // If this is a dynamic imported function,
@@ -173,9 +201,12 @@ pub fn resolve_imports(
                     return Err(LinkError::Import(
                         module.to_string(),
                         field.to_string(),
-                        ImportError::IncompatibleType(import_extern(), export_extern()),
+                        ImportError::IncompatibleType(expected, export_extern()),
                     ));
                 }
+                // The importing instance changes the tables, non-shared memories and mutable
+                // globals it imports, so calls into it enter the instances defining them too.
+                instances.extend(ex.instance_ref.as_ref().and_then(|r| r.upgrade()));
                 table_imports.push(VMTableImport {
                     definition: ex.from.vmtable(),
                     from: ex.from.clone(),
@@ -201,6 +232,9 @@ pub fn resolve_imports(
                     export_memory_style.offset_guard_size(),
                     import_memory_style.offset_guard_size()
                 );
+                if !im.shared {
+                    instances.extend(ex.instance_ref.as_ref().and_then(|r| r.upgrade()));
+                }
                 memory_imports.push(VMMemoryImport {
                     definition: ex.from.vmmemory(),
                     from: ex.from.clone(),
@@ -208,25 +242,36 @@ pub fn resolve_imports(
             }
 
             (Export::Global(ex), VMImportType::Global(im)) if ex.from.ty() == im => {
+                if im.mutability.is_mutable() {
+                    instances.extend(ex.instance_ref.as_ref().and_then(|r| r.upgrade()));
+                }
                 global_imports.push(VMGlobalImport {
                     definition: ex.from.vmglobal(),
                     from: ex.from.clone(),
                 });
             }
+            (Export::Tag(ex), VMImportType::Tag(im)) if ex.ty() == im => {
+                tag_imports.push(ex.clone());
+            }
             _ => {
                 return Err(LinkError::Import(
                     module.to_string(),
                     field.to_string(),
-                    ImportError::IncompatibleType(import_extern(), export_extern()),
+                    ImportError::IncompatibleType(expected, export_extern()),
                 ));
             }
         }
     }
+    if !unknown_imports.is_empty() {
+        return Err(LinkError::UnknownImports(unknown_imports));
+    }
     Ok(Imports::new(
         function_imports,
         host_function_env_initializers,
         table_imports,
         memory_imports,
         global_imports,
+        tag_imports,
+        instances,
     ))
 }
@@ -3,7 +3,8 @@ use backtrace::Backtrace;
 use std::error::Error;
 use std::fmt;
 use std::sync::Arc;
-use wasmer_vm::{raise_user_trap, Trap, TrapCode};
+use wasmer_types::MemoryIndex;
+use wasmer_vm::{raise_user_trap, InstanceId, Trap, TrapCode, VMException};
 
 /// A struct representing an aborted instruction execution, with a message
 /// indicating the cause.
@@ -17,8 +18,13 @@ pub struct RuntimeError {
 enum RuntimeErrorSource {
     Generic(String),
     OOM,
+    InstanceClosed,
+    InstanceSuspended,
+    ConcurrentReentry,
     User(Box<dyn Error + Send + Sync>),
     Trap(TrapCode),
+    HostPanic(String),
+    UncaughtException(VMException),
 }
 
 impl fmt::Display for RuntimeErrorSource {
@@ -27,14 +33,98 @@ impl fmt::Display for RuntimeErrorSource {
             Self::Generic(s) => write!(f, "{}", s),
             Self::User(s) => write!(f, "{}", s),
             Self::OOM => write!(f, "Wasmer VM out of memory"),
+            Self::InstanceClosed => write!(f, "the instance was closed"),
+            Self::InstanceSuspended => {
+                write!(f, "the instance is running an async call that is suspended")
+            }
+            Self::ConcurrentReentry => {
+                write!(f, "the instance is running a call on another thread")
+            }
             Self::Trap(s) => write!(f, "{}", s.message()),
+            Self::HostPanic(s) => write!(f, "{}", s),
+            Self::UncaughtException(e) => write!(f, "uncaught exception of tag {}", e.tag().ty()),
         }
     }
 }
 
+/// An out-of-bounds memory access of the generated code, which raised a trap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OobDetails {
+    /// The effective address of the access: its wasm address plus its offset, which may be
+    /// past 4GiB.
+    pub guest_addr: u64,
+    /// The number of bytes accessed.
+    pub access_size: u32,
+    /// The memory accessed.
+    pub memory_index: MemoryIndex,
+}
+
+/// A trap raised while calling into Wasm, as told to the trap hook of a store before the
+/// trap is converted into a [`RuntimeError`].
+#[derive(Debug, Clone)]
+pub struct TrapInfo {
+    /// The code of the trap.
+    pub code: TrapCode,
+    /// The Wasm function and instruction the trap happened at, or for the traps raised by
+    /// the runtime, the Wasm function that called into it. None if the trap happened in
+    /// code with no frame information.
+    pub location: Option<FrameInfo>,
+    /// The instance called from the host, through which the trap happened. None for calls of
+    /// host functions.
+    pub instance: Option<InstanceId>,
+}
+
+impl TrapInfo {
+    /// Describe `trap`, raised by a call into `instance`. Returns None for the traps without
+    /// a trap code: the errors and panics of host functions, uncaught exceptions, and the VM
+    /// running out of memory.
+    pub fn from_trap(trap: &Trap, instance: Option<InstanceId>) -> Option<Self> {
+        let info = FRAME_INFO.read().unwrap();
+        let (code, location) = match trap {
+            Trap::Wasm {
+                pc, signal_trap, ..
+            } => (
+                wasm_trap_code(&info, *pc, *signal_trap),
+                info.lookup_frame_info(*pc),
+            ),
+            Trap::Lib {
+                trap_code,
+                backtrace,
+            } => {
+                // The runtime was called from a Wasm function, whose frame is the first one
+                // of the trace that is a Wasm frame.
+                let location = backtrace
+                    .frames()
+                    .iter()
+                    .map(|frame| frame.ip() as usize)
+                    .filter(|&pc| pc != 0)
+                    .find_map(|pc| info.lookup_frame_info(pc - 1));
+                (*trap_code, location)
+            }
+            _ => return None,
+        };
+        Some(Self {
+            code,
+            location,
+            instance,
+        })
+    }
+}
+
+/// The code of a trap of the generated code at `pc`: the one the compiler recorded, or for
+/// the signals raised out of the recorded places, the one of the signal.
+fn wasm_trap_code(info: &GlobalFrameInfo, pc: usize, signal_trap: Option<TrapCode>) -> TrapCode {
+    info.lookup_trap_info(pc)
+        .map_or(signal_trap.unwrap_or(TrapCode::StackOverflow), |info| {
+            info.trap_code
+        })
+}
+
 struct RuntimeErrorInner {
     /// The source error (this can be a custom user `Error` or a [`TrapCode`])
     source: RuntimeErrorSource,
+    /// The access out of bounds, for the traps of those recorded by the compiler.
+    oob_details: Option<OobDetails>,
     /// The reconstructed Wasm trace (from the native trace and the `GlobalFrameInfo`).
     wasm_trace: Vec<FrameInfo>,
     /// The native backtrace
@@ -90,22 +180,97 @@ impl RuntimeError {
                 pc,
                 signal_trap,
                 backtrace,
+                memory_address,
             } => {
-                let code = info
-                    .lookup_trap_info(pc)
-                    .map_or(signal_trap.unwrap_or(TrapCode::StackOverflow), |info| {
-                        info.trap_code
+                let trap_info = info.lookup_trap_info(pc);
+                let code = wasm_trap_code(&info, pc, signal_trap);
+                // The stub of the access passes its address, and the compiler records the
+                // rest.
+                let access = trap_info.and_then(|info| info.memory_access);
+                let oob_details = memory_address
+                    .zip(access)
+                    .map(|(address, access)| OobDetails {
+                        guest_addr: address.saturating_add(access.offset),
+                        access_size: access.size,
+                        memory_index: access.memory_index,
                     });
-                Self::new_with_trace(&info, Some(pc), RuntimeErrorSource::Trap(code), backtrace)
+                let mut error = Self::new_with_trace(
+                    &info,
+                    Some(pc),
+                    RuntimeErrorSource::Trap(code),
+                    backtrace,
+                );
+                Arc::get_mut(&mut error.inner).unwrap().oob_details = oob_details;
+                error
             }
             // A trap triggered manually from the Wasmer runtime
             Trap::Lib {
                 trap_code,
                 backtrace,
             } => Self::new_with_trace(&info, None, RuntimeErrorSource::Trap(trap_code), backtrace),
+            // A panic of a host function, caught at the boundary with the Wasm code
+            Trap::HostPanic {
+                message, backtrace, ..
+            } => Self::new_with_trace(
+                &info,
+                None,
+                RuntimeErrorSource::HostPanic(message),
+                backtrace,
+            ),
+            // A wasm exception no handler caught
+            Trap::Exception(exception) => Self::uncaught_exception(exception),
         }
     }
 
+    /// Creates the `RuntimeError` of a wasm exception no handler caught.
+    ///
+    /// Host functions returning such an error throw the exception back to the wasm code that
+    /// called them.
+    pub fn uncaught_exception(exception: VMException) -> Self {
+        let info = FRAME_INFO.read().unwrap();
+        Self::new_with_trace(
+            &info,
+            None,
+            RuntimeErrorSource::UncaughtException(exception),
+            Backtrace::new_unresolved(),
+        )
+    }
+
+    /// Creates the `RuntimeError` returned when calling a function of a closed instance.
+    pub fn instance_closed() -> Self {
+        let info = FRAME_INFO.read().unwrap();
+        Self::new_with_trace(
+            &info,
+            None,
+            RuntimeErrorSource::InstanceClosed,
+            Backtrace::new_unresolved(),
+        )
+    }
+
+    /// Creates the `RuntimeError` returned when calling a function of an instance while an
+    /// async call into it is suspended.
+    pub fn instance_suspended() -> Self {
+        let info = FRAME_INFO.read().unwrap();
+        Self::new_with_trace(
+            &info,
+            None,
+            RuntimeErrorSource::InstanceSuspended,
+            Backtrace::new_unresolved(),
+        )
+    }
+
+    /// Creates the `RuntimeError` returned when calling a function of an instance while
+    /// another thread runs a call into it.
+    pub fn concurrent_reentry() -> Self {
+        let info = FRAME_INFO.read().unwrap();
+        Self::new_with_trace(
+            &info,
+            None,
+            RuntimeErrorSource::ConcurrentReentry,
+            Backtrace::new_unresolved(),
+        )
+    }
+
     /// Raises a custom user Error
     pub fn raise(error: Box<dyn Error + Send + Sync>) -> ! {
         unsafe { raise_user_trap(error) }
@@ -150,6 +315,7 @@ impl RuntimeError {
         Self {
             inner: Arc::new(RuntimeErrorInner {
                 source,
+                oob_details: None,
                 wasm_trace,
                 native_trace,
             }),
@@ -191,6 +357,59 @@ impl RuntimeError {
         }
     }
 
+    /// Returns the access of the trap, if it comes from an out-of-bounds memory access of the
+    /// generated code whose address the compiler lets the runtime find.
+    ///
+    /// The accesses of the memory by the runtime, such as those of `memory.copy`, raise traps
+    /// without these details.
+    pub fn oob_details(&self) -> Option<OobDetails> {
+        self.inner.oob_details
+    }
+
+    /// Returns true if the error comes from calling a function of a closed instance.
+    pub fn is_instance_closed(&self) -> bool {
+        matches!(self.inner.source, RuntimeErrorSource::InstanceClosed)
+    }
+
+    /// Returns true if the error comes from calling a function of an instance while an async
+    /// call into it is suspended.
+    pub fn is_instance_suspended(&self) -> bool {
+        matches!(self.inner.source, RuntimeErrorSource::InstanceSuspended)
+    }
+
+    /// Returns true if the error comes from calling a function of an instance while another
+    /// thread runs a call into it.
+    pub fn is_concurrent_reentry(&self) -> bool {
+        matches!(self.inner.source, RuntimeErrorSource::ConcurrentReentry)
+    }
+
+    /// Returns true if the error comes from the panic of a host function, whose message is
+    /// the message of the error.
+    pub fn is_host_panic(&self) -> bool {
+        matches!(self.inner.source, RuntimeErrorSource::HostPanic(_))
+    }
+
+    /// Returns true if the error comes from running past the epoch deadline of the instance.
+    pub fn is_epoch_deadline_exceeded(&self) -> bool {
+        matches!(
+            self.inner.source,
+            RuntimeErrorSource::Trap(TrapCode::EpochDeadlineExceeded)
+        )
+    }
+
+    /// Returns true if the error is a wasm exception no handler caught.
+    pub fn is_uncaught_exception(&self) -> bool {
+        matches!(self.inner.source, RuntimeErrorSource::UncaughtException(_))
+    }
+
+    /// Returns the wasm exception no handler caught, if the error is one.
+    pub fn exception(&self) -> Option<&VMException> {
+        match &self.inner.source {
+            RuntimeErrorSource::UncaughtException(exception) => Some(exception),
+            _ => None,
+        }
+    }
+
     /// Returns true if the `RuntimeError` is the same as T
     pub fn is<T: Error + 'static>(&self) -> bool {
         match &self.inner.source {
@@ -5,17 +5,23 @@
 //!
 //! # Example
 //! ```ignore
-//! use wasmer_vm::{FRAME_INFO};
-//! use wasmer_types::ModuleInfo;
+//! use wasmer_engine::register_frame_info;
 //!
-//! let module: ModuleInfo = ...;
-//! FRAME_INFO.register(module, compiled_functions);
+//! let registration = register_frame_info(
+//!     module.name(),
+//!     module.function_names.clone(),
+//!     module.import_counts,
+//!     function_extents,
+//!     frame_infos,
+//! );
 //! ```
+use std::cmp;
 use std::collections::BTreeMap;
-use std::sync::{Arc, RwLock};
+use std::sync::RwLock;
 use wasmer_compiler::{CompiledFunctionFrameInfo, SourceLoc, TrapInformation};
 use wasmer_types::entity::{EntityRef, PrimaryMap};
-use wasmer_types::{LocalFunctionIndex, ModuleInfo};
+use wasmer_types::{FunctionIndex, ImportCounts, LocalFunctionIndex};
+use wasmer_vm::{register_landing_pads, FunctionExtent, LandingPadRegistration};
 
 lazy_static::lazy_static! {
     /// This is a global cache of backtrace frame information for all active
@@ -46,19 +52,23 @@ pub struct GlobalFrameInfoRegistration {
     /// The key that will be removed from the global `ranges` map when this is
     /// dropped.
     key: usize,
+    /// The landing pads of the functions of the module, unregistered with them.
+    _landing_pads: LandingPadRegistration,
 }
 
 #[derive(Debug)]
 struct ModuleInfoFrameInfo {
     start: usize,
     functions: BTreeMap<usize, FunctionInfo>,
-    module: Arc<ModuleInfo>,
+    module_name: String,
+    function_names: BTreeMap<FunctionIndex, String>,
+    import_counts: ImportCounts,
     frame_infos: PrimaryMap<LocalFunctionIndex, CompiledFunctionFrameInfo>,
 }
 
 impl ModuleInfoFrameInfo {
-    fn function_debug_info(&self, local_index: LocalFunctionIndex) -> &CompiledFunctionFrameInfo {
-        &self.frame_infos.get(local_index).unwrap()
+    fn function_debug_info(&self, func: &FunctionInfo) -> &CompiledFunctionFrameInfo {
+        &self.frame_infos.get(func.frame_info).unwrap()
     }
 
     /// Gets a function given a pc
@@ -76,6 +86,8 @@ impl ModuleInfoFrameInfo {
 struct FunctionInfo {
     start: usize,
     local_index: LocalFunctionIndex,
+    /// The key of the frame information of the function in `frame_infos`.
+    frame_info: LocalFunctionIndex,
 }
 
 impl GlobalFrameInfo {
@@ -91,7 +103,7 @@ impl GlobalFrameInfo {
         // machine instruction that corresponds to `pc`, which then allows us to
         // map that to a wasm original source location.
         let rel_pos = pc - func.start;
-        let instr_map = &module.function_debug_info(func.local_index).address_map;
+        let instr_map = &module.function_debug_info(func).address_map;
         let pos = match instr_map
             .instructions
             .binary_search_by_key(&rel_pos, |map| map.code_offset)
@@ -126,11 +138,11 @@ impl GlobalFrameInfo {
             // start offset of the function.
             None => instr_map.start_srcloc,
         };
-        let func_index = module.module.func_index(func.local_index);
+        let func_index = module.import_counts.function_index(func.local_index);
         Some(FrameInfo {
-            module_name: module.module.name(),
+            module_name: module.module_name.clone(),
             func_index: func_index.index() as u32,
-            function_name: module.module.function_names.get(&func_index).cloned(),
+            function_name: module.function_names.get(&func_index).cloned(),
             instr,
             func_start: instr_map.start_srcloc,
         })
@@ -140,7 +152,7 @@ impl GlobalFrameInfo {
     pub fn lookup_trap_info(&self, pc: usize) -> Option<&TrapInformation> {
         let module = self.module_info(pc)?;
         let func = module.function_info(pc)?;
-        let traps = &module.function_debug_info(func.local_index).traps;
+        let traps = &module.function_debug_info(func).traps;
         let idx = traps
             .binary_search_by_key(&((pc - func.start) as u32), |info| info.code_offset)
             .ok()?;
@@ -158,6 +170,111 @@ impl GlobalFrameInfo {
     }
 }
 
+/// Register the frame information of the functions of a module, so that the traps raised
+/// while they run are given a backtrace.
+///
+/// `functions` gives where the code of each local function was loaded and `frame_infos` how it
+/// maps back to the wasm instructions. Returns `None` when the module has no functions, and
+/// otherwise a registration which unregisters the information when dropped, which must happen
+/// before the code is unloaded.
+pub fn register_frame_info(
+    module_name: String,
+    function_names: BTreeMap<FunctionIndex, String>,
+    import_counts: ImportCounts,
+    functions: impl IntoIterator<Item = (LocalFunctionIndex, FunctionExtent)>,
+    frame_infos: PrimaryMap<LocalFunctionIndex, CompiledFunctionFrameInfo>,
+) -> Option<GlobalFrameInfoRegistration> {
+    let mut min = usize::MAX;
+    let mut function_infos = BTreeMap::new();
+    for (local_index, extent) in functions {
+        let start = *extent.address as usize;
+        let end = start + extent.length;
+        min = cmp::min(min, start);
+        let func = FunctionInfo {
+            start,
+            local_index,
+            frame_info: local_index,
+        };
+        assert!(function_infos.insert(end, func).is_none());
+    }
+    if function_infos.is_empty() {
+        return None;
+    }
+    Some(register(ModuleInfoFrameInfo {
+        start: min,
+        functions: function_infos,
+        module_name,
+        function_names,
+        import_counts,
+        frame_infos,
+    }))
+}
+
+/// Register the frame information of a single function of a module, loaded on its own rather
+/// than with the other functions of the module.
+///
+/// `function_name` is the name of the function, if it has one. The registration unregisters the
+/// information when dropped, which must happen before the code of the function is unloaded.
+pub fn register_function_frame_info(
+    module_name: String,
+    function_name: Option<String>,
+    import_counts: ImportCounts,
+    local_index: LocalFunctionIndex,
+    extent: FunctionExtent,
+    frame_info: CompiledFunctionFrameInfo,
+) -> GlobalFrameInfoRegistration {
+    let start = *extent.address as usize;
+    let mut functions = BTreeMap::new();
+    let mut frame_infos = PrimaryMap::new();
+    let func = FunctionInfo {
+        start,
+        local_index,
+        frame_info: frame_infos.push(frame_info),
+    };
+    functions.insert(start + extent.length, func);
+    let function_names = function_name
+        .map(|name| (import_counts.function_index(local_index), name))
+        .into_iter()
+        .collect();
+    register(ModuleInfoFrameInfo {
+        start,
+        functions,
+        module_name,
+        function_names,
+        import_counts,
+        frame_infos,
+    })
+}
+
+/// Add the frame information of `module` to the global cache.
+fn register(module: ModuleInfoFrameInfo) -> GlobalFrameInfoRegistration {
+    let min = module.start;
+    let max = *module.functions.keys().next_back().unwrap();
+    let landing_pads = register_landing_pads(module.functions.values().flat_map(|func| {
+        let frame_info = module.function_debug_info(func);
+        frame_info.landing_pads.iter().map(move |pad| {
+            (
+                func.start + pad.return_offset as usize,
+                func.start + pad.handler_offset as usize,
+            )
+        })
+    }));
+    let mut info = FRAME_INFO.write().unwrap();
+    // The code of the modules lies in disjoint ranges.
+    if let Some((_, next)) = info.ranges.range(max..).next() {
+        assert!(next.start > max);
+    }
+    if let Some((prev_end, _)) = info.ranges.range(..=min).next_back() {
+        assert!(*prev_end < min);
+    }
+    let prev = info.ranges.insert(max, module);
+    assert!(prev.is_none());
+    GlobalFrameInfoRegistration {
+        key: max,
+        _landing_pads: landing_pads,
+    }
+}
+
 impl Drop for GlobalFrameInfoRegistration {
     fn drop(&mut self) {
         if let Ok(mut info) = FRAME_INFO.write() {
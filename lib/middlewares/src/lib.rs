@@ -0,0 +1,10 @@
+//! Middlewares instrumenting the WebAssembly modules as they are compiled.
+//!
+//! The middlewares are pushed to the compiler configuration with
+//! `Singlepass::push_middleware`, and run in the order they were pushed.
+
+#![deny(missing_docs, unused_extern_crates)]
+
+mod opcode_histogram;
+
+pub use crate::opcode_histogram::OpcodeHistogram;
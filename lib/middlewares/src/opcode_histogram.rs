@@ -0,0 +1,150 @@
+//! Counting the operators run by the instances of a module, in buckets.
+
+use std::fmt;
+use std::sync::Mutex;
+use wasmer::wasmparser::Operator;
+use wasmer::{
+    FunctionMiddleware, Instance, LocalFunctionIndex, MiddlewareError, MiddlewareReaderState,
+    ModuleMiddleware,
+};
+use wasmer_types::{
+    ExportIndex, GlobalIndex, GlobalInit, GlobalType, ModuleInfo, Mutability, Type,
+};
+
+/// The prefix of the names of the globals holding the counts of the buckets.
+const EXPORT_PREFIX: &str = "wasmer_histogram_";
+
+/// A middleware counting the operators run by the instances of a module, in buckets.
+///
+/// Each bucket is counted by a mutable `i64` global the middleware appends to the module,
+/// exported as `wasmer_histogram_<bucket>`, and incremented before each operator of the
+/// bucket runs. The operators of no bucket aren't counted.
+///
+/// The histogram remembers the globals of the module it instrumented, so it must not be
+/// used to compile several modules.
+pub struct OpcodeHistogram {
+    buckets: Vec<String>,
+    classify: fn(&Operator) -> Option<usize>,
+    /// The globals counting the buckets, once the module info was transformed.
+    globals: Mutex<Option<Vec<GlobalIndex>>>,
+}
+
+impl OpcodeHistogram {
+    /// Create a histogram of the buckets named `buckets`, in which `classify` puts each
+    /// operator, by the index of its bucket.
+    ///
+    /// # Panics
+    ///
+    /// When compiling a module, if `classify` returns an index out of `buckets`.
+    pub fn new(buckets: &[&str], classify: fn(&Operator) -> Option<usize>) -> Self {
+        Self {
+            buckets: buckets.iter().map(|bucket| bucket.to_string()).collect(),
+            classify,
+            globals: Mutex::new(None),
+        }
+    }
+
+    /// The name of the export of the global counting `bucket`.
+    pub fn export_name(bucket: &str) -> String {
+        format!("{}{}", EXPORT_PREFIX, bucket)
+    }
+
+    /// The counts of the buckets in `instance`, an instance of the module the histogram
+    /// instrumented.
+    ///
+    /// # Panics
+    ///
+    /// If `instance` doesn't export the globals of the histogram.
+    pub fn get_counts(&self, instance: &Instance) -> Vec<(String, u64)> {
+        self.buckets
+            .iter()
+            .map(|bucket| {
+                let global = instance
+                    .lookup_global(&Self::export_name(bucket))
+                    .expect("the instance doesn't export the counts of the histogram");
+                (bucket.clone(), global.get().unwrap_i64() as u64)
+            })
+            .collect()
+    }
+}
+
+impl fmt::Debug for OpcodeHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // The globals are left out, so that the fingerprint of the compiler doesn't change
+        // once it compiled a module.
+        f.debug_struct("OpcodeHistogram")
+            .field("buckets", &self.buckets)
+            .finish()
+    }
+}
+
+impl ModuleMiddleware for OpcodeHistogram {
+    fn generate_function_middleware(
+        &self,
+        _local_function_index: LocalFunctionIndex,
+    ) -> Box<dyn FunctionMiddleware> {
+        let globals = self.globals.lock().unwrap().clone().expect(
+            "OpcodeHistogram::generate_function_middleware: the module info wasn't transformed",
+        );
+        Box::new(FunctionOpcodeHistogram {
+            classify: self.classify,
+            globals,
+        })
+    }
+
+    fn transform_module_info(&self, module_info: &mut ModuleInfo) {
+        let mut globals = self.globals.lock().unwrap();
+        if globals.is_some() {
+            panic!("OpcodeHistogram::transform_module_info: the histogram is used by two modules");
+        }
+        *globals = Some(
+            self.buckets
+                .iter()
+                .map(|bucket| {
+                    let index = module_info
+                        .globals
+                        .push(GlobalType::new(Type::I64, Mutability::Var));
+                    module_info
+                        .global_initializers
+                        .push(GlobalInit::I64Const(0));
+                    module_info
+                        .exports
+                        .insert(Self::export_name(bucket), ExportIndex::Global(index));
+                    index
+                })
+                .collect(),
+        );
+    }
+}
+
+/// The middleware counting the operators of a function.
+struct FunctionOpcodeHistogram {
+    classify: fn(&Operator) -> Option<usize>,
+    globals: Vec<GlobalIndex>,
+}
+
+impl fmt::Debug for FunctionOpcodeHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FunctionOpcodeHistogram")
+            .field("globals", &self.globals)
+            .finish()
+    }
+}
+
+impl FunctionMiddleware for FunctionOpcodeHistogram {
+    fn feed<'a>(
+        &mut self,
+        operator: Operator<'a>,
+        state: &mut MiddlewareReaderState<'a>,
+    ) -> Result<(), MiddlewareError> {
+        if let Some(bucket) = (self.classify)(&operator) {
+            let global_index = self.globals[bucket].as_u32();
+            state.push_operator(Operator::GlobalGet { global_index });
+            state.push_operator(Operator::I64Const { value: 1 });
+            state.push_operator(Operator::I64Add);
+            state.push_operator(Operator::GlobalSet { global_index });
+        }
+        state.push_operator(operator);
+        Ok(())
+    }
+}
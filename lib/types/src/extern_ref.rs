@@ -225,6 +225,10 @@ pub struct ExternRef {
     inner: VMExternRef,
 }
 
+// The count of references is atomic and the data is `Send + Sync`, like an `Arc`.
+unsafe impl Send for ExternRef {}
+unsafe impl Sync for ExternRef {}
+
 impl Clone for ExternRef {
     fn clone(&self) -> Self {
         Self {
@@ -261,6 +265,14 @@ impl ExternRef {
             inner: VMExternRef::new(value),
         }
     }
+
+    /// Try to downcast to the given value
+    pub fn downcast<T>(&self) -> Option<&T>
+    where
+        T: Any + Send + Sync + 'static + Sized,
+    {
+        self.inner.downcast::<T>()
+    }
 }
 
 impl From<VMExternRef> for ExternRef {
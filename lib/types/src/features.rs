@@ -24,6 +24,10 @@ pub struct Features {
     pub memory64: bool,
     /// Wasm exceptions proposal should be enabled
     pub exceptions: bool,
+    /// Extended constant expressions proposal should be enabled
+    pub extended_const: bool,
+    /// Floating point types and operators should be enabled
+    pub floats: bool,
 }
 
 impl Features {
@@ -44,9 +48,24 @@ impl Features {
             multi_memory: false,
             memory64: false,
             exceptions: false,
+            extended_const: false,
+            // Floats are part of the MVP
+            floats: true,
         }
     }
 
+    /// The features of modules whose execution doesn't depend on the host they run on.
+    ///
+    /// Floating point operators may produce NaNs whose bits depend on the host, and the
+    /// threads and SIMD proposals bring operators that may too, so they are disabled along with
+    /// the proposals that are not enabled by default. Embedders that need floats can enable
+    /// them back and have the compiler canonicalize the NaNs.
+    pub fn deterministic() -> Self {
+        let mut features = Self::new();
+        features.threads(false).simd(false).floats(false);
+        features
+    }
+
     /// Configures whether the WebAssembly threads proposal will be enabled.
     ///
     /// The [WebAssembly threads proposal][threads] is not currently fully
@@ -219,6 +238,56 @@ impl Features {
         self.memory64 = enable;
         self
     }
+
+    /// Configures whether the WebAssembly exception handling proposal will
+    /// be enabled.
+    ///
+    /// The [WebAssembly exception handling proposal][proposal] is not
+    /// currently fully standardized and is undergoing development.
+    /// Support for this feature can be enabled through this method for
+    /// appropriate WebAssembly modules.
+    ///
+    /// This feature adds exception tags, and the `try`, `catch`,
+    /// `catch_all`, `delegate`, `throw` and `rethrow` instructions.
+    ///
+    /// This is `false` by default.
+    ///
+    /// [proposal]: https://github.com/WebAssembly/exception-handling
+    pub fn exceptions(&mut self, enable: bool) -> &mut Self {
+        self.exceptions = enable;
+        self
+    }
+
+    /// Configures whether the WebAssembly extended constant expressions
+    /// proposal will be enabled.
+    ///
+    /// The [WebAssembly extended constant expressions proposal][proposal] is
+    /// not currently fully standardized and is undergoing development.
+    /// Support for this feature can be enabled through this method for
+    /// appropriate WebAssembly modules.
+    ///
+    /// This feature allows the initializers of the globals and the offsets of
+    /// the segments to add, subtract and multiply `i32`s and `i64`s.
+    ///
+    /// This is `false` by default.
+    ///
+    /// [proposal]: https://github.com/WebAssembly/extended-const
+    pub fn extended_const(&mut self, enable: bool) -> &mut Self {
+        self.extended_const = enable;
+        self
+    }
+
+    /// Configures whether floating point types and operators will be enabled.
+    ///
+    /// Floats are part of the WebAssembly MVP. Disabling them rejects the
+    /// modules using the `f32` and `f64` types, or an operator on or
+    /// producing floats, such as `f32.add` or `i32.trunc_f32_s`.
+    ///
+    /// This is `true` by default.
+    pub fn floats(&mut self, enable: bool) -> &mut Self {
+        self.floats = enable;
+        self
+    }
 }
 
 impl Default for Features {
@@ -246,10 +315,22 @@ mod test_features {
                 multi_memory: false,
                 memory64: false,
                 exceptions: false,
+                extended_const: false,
+                floats: true,
             }
         );
     }
 
+    #[test]
+    fn deterministic_features() {
+        let features = Features::deterministic();
+        assert!(!features.threads);
+        assert!(!features.simd);
+        assert!(!features.floats);
+        assert!(features.bulk_memory);
+        assert!(features.multi_value);
+    }
+
     #[test]
     fn enable_threads() {
         let mut features = Features::new();
@@ -266,6 +347,13 @@ mod test_features {
         assert!(features.bulk_memory);
     }
 
+    #[test]
+    fn enable_exceptions() {
+        let mut features = Features::new();
+        features.exceptions(true);
+        assert!(features.exceptions);
+    }
+
     #[test]
     fn enable_simd() {
         let mut features = Features::new();
@@ -325,4 +413,18 @@ mod test_features {
         features.memory64(true);
         assert!(features.memory64);
     }
+
+    #[test]
+    fn enable_extended_const() {
+        let mut features = Features::new();
+        features.extended_const(true);
+        assert!(features.extended_const);
+    }
+
+    #[test]
+    fn disable_floats() {
+        let mut features = Features::new();
+        features.floats(false);
+        assert!(!features.floats);
+    }
 }
@@ -31,6 +31,11 @@ entity_impl!(LocalTableIndex);
 pub struct LocalMemoryIndex(u32);
 entity_impl!(LocalMemoryIndex);
 
+/// Index type of an exception tag defined locally inside the WebAssembly module.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub struct LocalTagIndex(u32);
+entity_impl!(LocalTagIndex);
+
 /// Index type of a global defined locally inside the WebAssembly module.
 #[derive(
     Copy,
@@ -126,6 +131,25 @@ entity_impl!(GlobalIndex);
 pub struct MemoryIndex(u32);
 entity_impl!(MemoryIndex);
 
+/// Index type of an exception tag (imported or local) inside the WebAssembly module.
+#[derive(
+    Copy,
+    Clone,
+    PartialEq,
+    Eq,
+    Hash,
+    PartialOrd,
+    Ord,
+    Debug,
+    rkyv::Serialize,
+    rkyv::Deserialize,
+    rkyv::Archive,
+)]
+#[archive(as = "Self")]
+#[repr(transparent)]
+pub struct TagIndex(u32);
+entity_impl!(TagIndex);
+
 /// Index type of a signature (imported or local) inside the WebAssembly module.
 #[derive(
     Copy,
@@ -226,6 +250,8 @@ pub enum ExportIndex {
     Memory(MemoryIndex),
     /// Global export.
     Global(GlobalIndex),
+    /// Exception tag export.
+    Tag(TagIndex),
 }
 
 /// An entity to import.
@@ -252,4 +278,6 @@ pub enum ImportIndex {
     Memory(MemoryIndex),
     /// Global import.
     Global(GlobalIndex),
+    /// Exception tag import.
+    Tag(TagIndex),
 }
@@ -1,5 +1,83 @@
 use crate::indexes::{FunctionIndex, GlobalIndex, MemoryIndex, TableIndex};
 use crate::lib::std::boxed::Box;
+use crate::lib::std::vec::Vec;
+
+/// An operator of an extended constant expression.
+#[derive(
+    Clone, Copy, Debug, Hash, PartialEq, Eq, rkyv::Serialize, rkyv::Deserialize, rkyv::Archive,
+)]
+#[archive(as = "Self")]
+pub enum ConstOp {
+    /// An `i32.const`.
+    I32Const(i32),
+    /// An `i64.const`.
+    I64Const(i64),
+    /// A `global.get` of an imported global.
+    GlobalGet(GlobalIndex),
+    /// An `i32.add`.
+    I32Add,
+    /// An `i32.sub`.
+    I32Sub,
+    /// An `i32.mul`.
+    I32Mul,
+    /// An `i64.add`.
+    I64Add,
+    /// An `i64.sub`.
+    I64Sub,
+    /// An `i64.mul`.
+    I64Mul,
+}
+
+/// A constant expression of the extended-const proposal, computing an `i32` or an `i64` from
+/// constants and imported globals.
+///
+/// The expressions of a single constant or `global.get` are represented without it.
+#[derive(Clone, Debug, Hash, PartialEq, Eq, rkyv::Serialize, rkyv::Deserialize, rkyv::Archive)]
+pub struct ConstExpr {
+    /// The operators of the expression, without its `end`.
+    pub ops: Box<[ConstOp]>,
+}
+
+impl ConstExpr {
+    /// Evaluates the expression, with `global` reading the value of a global.
+    ///
+    /// The arithmetic wraps around, and an `i32` result is zero-extended. Only the low 32 bits
+    /// of the values `global` returns for `i32` globals are used.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the operators don't leave one value on the stack, which a validated module
+    /// guarantees.
+    pub fn eval(&self, mut global: impl FnMut(GlobalIndex) -> u64) -> u64 {
+        let mut stack = Vec::with_capacity(self.ops.len());
+        for op in self.ops.iter() {
+            let value = match *op {
+                ConstOp::I32Const(value) => u64::from(value as u32),
+                ConstOp::I64Const(value) => value as u64,
+                ConstOp::GlobalGet(index) => global(index),
+                ConstOp::I32Add | ConstOp::I32Sub | ConstOp::I32Mul => {
+                    let (rhs, lhs) = (stack.pop().unwrap() as u32, stack.pop().unwrap() as u32);
+                    u64::from(match *op {
+                        ConstOp::I32Add => lhs.wrapping_add(rhs),
+                        ConstOp::I32Sub => lhs.wrapping_sub(rhs),
+                        _ => lhs.wrapping_mul(rhs),
+                    })
+                }
+                ConstOp::I64Add | ConstOp::I64Sub | ConstOp::I64Mul => {
+                    let (rhs, lhs): (u64, u64) = (stack.pop().unwrap(), stack.pop().unwrap());
+                    match *op {
+                        ConstOp::I64Add => lhs.wrapping_add(rhs),
+                        ConstOp::I64Sub => lhs.wrapping_sub(rhs),
+                        _ => lhs.wrapping_mul(rhs),
+                    }
+                }
+            };
+            stack.push(value);
+        }
+        assert_eq!(stack.len(), 1, "invalid constant expression {:?}", self);
+        stack[0]
+    }
+}
 
 /// A WebAssembly table initializer.
 #[derive(Clone, Debug, Hash, PartialEq, Eq, rkyv::Serialize, rkyv::Deserialize, rkyv::Archive)]
@@ -10,6 +88,9 @@ pub struct OwnedTableInitializer {
     pub base: Option<GlobalIndex>,
     /// The offset to add to the base.
     pub offset: usize,
+    /// The extended constant expression computing the offset instead of `base` and `offset`,
+    /// if the segment has one.
+    pub extended_offset: Option<ConstExpr>,
     /// The values to write into the table elements.
     pub elements: Box<[FunctionIndex]>,
 }
@@ -26,6 +107,10 @@ pub struct DataInitializerLocation {
 
     /// A constant offset to initialize at.
     pub offset: usize,
+
+    /// The extended constant expression computing the offset instead of `base` and `offset`,
+    /// if the segment has one.
+    pub extended_offset: Option<ConstExpr>,
 }
 
 /// A data initializer for linear memory.
@@ -86,3 +171,36 @@ impl<'a> From<DataInitializer<'a>> for OwnedDataInitializer {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn const_exprs_wrap_around() {
+        let globals = [u64::from(u32::MAX), 0xdead_beef_0000_0002, u64::MAX];
+        let eval = |ops: &[ConstOp]| {
+            let expr = ConstExpr { ops: ops.into() };
+            expr.eval(|index| globals[index.as_u32() as usize])
+        };
+        let global = |index| ConstOp::GlobalGet(GlobalIndex::from_u32(index));
+
+        assert_eq!(eval(&[global(0), ConstOp::I32Const(2), ConstOp::I32Add]), 1);
+        // Only the low 32 bits of an `i32` global are used.
+        assert_eq!(eval(&[global(1), global(1), ConstOp::I32Mul]), 4);
+        assert_eq!(
+            eval(&[ConstOp::I32Const(1), ConstOp::I32Const(2), ConstOp::I32Sub]),
+            u64::from(u32::MAX)
+        );
+        assert_eq!(eval(&[global(2), ConstOp::I64Const(2), ConstOp::I64Add]), 1);
+        assert_eq!(
+            eval(&[
+                ConstOp::I64Const(i64::MIN),
+                ConstOp::I64Const(-1),
+                ConstOp::I64Mul
+            ]),
+            i64::MIN as u64
+        );
+        assert_eq!(eval(&[ConstOp::I64Const(0), global(2), ConstOp::I64Sub]), 1);
+    }
+}
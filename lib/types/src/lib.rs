@@ -73,22 +73,24 @@ pub use crate::extern_ref::{ExternRef, VMExternRef};
 pub use crate::features::Features;
 pub use crate::indexes::{
     CustomSectionIndex, DataIndex, ElemIndex, ExportIndex, FunctionIndex, GlobalIndex, ImportIndex,
-    LocalFunctionIndex, LocalGlobalIndex, LocalMemoryIndex, LocalTableIndex, MemoryIndex,
-    SignatureIndex, TableIndex,
+    LocalFunctionIndex, LocalGlobalIndex, LocalMemoryIndex, LocalTableIndex, LocalTagIndex,
+    MemoryIndex, SignatureIndex, TableIndex, TagIndex,
 };
 pub use crate::initializers::{
-    DataInitializer, DataInitializerLocation, OwnedDataInitializer, OwnedTableInitializer,
+    ConstExpr, ConstOp, DataInitializer, DataInitializerLocation, OwnedDataInitializer,
+    OwnedTableInitializer,
 };
 pub use crate::memory_view::{Atomically, MemoryView};
 pub use crate::module::{ImportCounts, ModuleInfo};
 pub use crate::native::{NativeWasmType, ValueType};
 pub use crate::units::{
-    Bytes, PageCountOutOfRange, Pages, WASM_MAX_PAGES, WASM_MIN_PAGES, WASM_PAGE_SIZE,
+    Bytes, PageCountOutOfRange, Pages, WASM64_MAX_PAGES, WASM_MAX_PAGES, WASM_MIN_PAGES,
+    WASM_PAGE_SIZE,
 };
 pub use crate::values::{Value, WasmValueType};
 pub use types::{
     ExportType, ExternType, FastGasCounter, FunctionType, FunctionTypeRef, GlobalInit, GlobalType,
-    Import, InstanceConfig, MemoryType, Mutability, TableType, Type, V128,
+    Import, InstanceConfig, MemoryType, Mutability, TableType, TagType, Type, V128,
 };
 
 pub use archives::ArchivableIndexMap;
@@ -9,8 +9,8 @@ use crate::ArchivableIndexMap;
 use crate::{
     CustomSectionIndex, DataIndex, ElemIndex, ExportIndex, FunctionIndex, FunctionType,
     GlobalIndex, GlobalInit, GlobalType, ImportIndex, LocalFunctionIndex, LocalGlobalIndex,
-    LocalMemoryIndex, LocalTableIndex, MemoryIndex, MemoryType, OwnedTableInitializer,
-    SignatureIndex, TableIndex, TableType,
+    LocalMemoryIndex, LocalTableIndex, LocalTagIndex, MemoryIndex, MemoryType,
+    OwnedTableInitializer, SignatureIndex, TableIndex, TableType, TagIndex,
 };
 use indexmap::IndexMap;
 use rkyv::{
@@ -60,6 +60,9 @@ pub struct ImportCounts {
 
     /// Number of imported globals in the module.
     pub globals: u32,
+
+    /// Number of imported exception tags in the module.
+    pub tags: u32,
 }
 
 impl ImportCounts {
@@ -93,6 +96,11 @@ impl ImportCounts {
         Self::make_local(idx, self.globals)
     }
 
+    /// Convert the `TagIndex` to a `LocalTagIndex`.
+    pub fn local_tag_index(&self, idx: TagIndex) -> Result<LocalTagIndex, TagIndex> {
+        Self::make_local(idx, self.tags)
+    }
+
     fn make_index<R: EntityRef, I: EntityRef>(idx: I, imports: u32) -> R {
         let imports = imports as usize;
         R::new(idx.index() + imports)
@@ -117,6 +125,11 @@ impl ImportCounts {
     pub fn global_index(&self, idx: LocalGlobalIndex) -> GlobalIndex {
         Self::make_index(idx, self.globals)
     }
+
+    /// Convert the `LocalTagIndex` to a `TagIndex`.
+    pub fn tag_index(&self, idx: LocalTagIndex) -> TagIndex {
+        Self::make_index(idx, self.tags)
+    }
 }
 
 /// A translated WebAssembly module, excluding the function bodies and
@@ -164,6 +177,10 @@ pub struct ModuleInfo {
     /// WebAssembly function names.
     pub function_names: HashMap<FunctionIndex, String>,
 
+    /// WebAssembly local names, by function and then by the index of the local, the
+    /// parameters included.
+    pub local_names: HashMap<FunctionIndex, HashMap<u32, String>>,
+
     /// WebAssembly function signatures.
     pub signatures: PrimaryMap<SignatureIndex, FunctionType>,
 
@@ -179,8 +196,14 @@ pub struct ModuleInfo {
     /// WebAssembly global variables (imported and local).
     pub globals: PrimaryMap<GlobalIndex, GlobalType>,
 
-    /// Custom sections in the module.
-    pub custom_sections: IndexMap<String, CustomSectionIndex>,
+    /// WebAssembly exception tags (imported and local), with the signatures whose params are
+    /// the types of the values their exceptions carry.
+    pub tags: PrimaryMap<TagIndex, SignatureIndex>,
+
+    /// Custom sections in the module, with their names, in the order the module has them.
+    ///
+    /// A module can have several custom sections with the same name.
+    pub custom_sections: Vec<(String, CustomSectionIndex)>,
 
     /// The data for each CustomSection in the module.
     pub custom_sections_data: PrimaryMap<CustomSectionIndex, Arc<[u8]>>,
@@ -201,12 +224,14 @@ pub struct ArchivableModuleInfo {
     pub passive_data: BTreeMap<DataIndex, Arc<[u8]>>,
     pub global_initializers: PrimaryMap<LocalGlobalIndex, GlobalInit>,
     pub function_names: BTreeMap<FunctionIndex, String>,
+    pub local_names: BTreeMap<FunctionIndex, BTreeMap<u32, String>>,
     pub signatures: PrimaryMap<SignatureIndex, FunctionType>,
     pub functions: PrimaryMap<FunctionIndex, SignatureIndex>,
     pub tables: PrimaryMap<TableIndex, TableType>,
     pub memories: PrimaryMap<MemoryIndex, MemoryType>,
     pub globals: PrimaryMap<GlobalIndex, GlobalType>,
-    pub custom_sections: ArchivableIndexMap<String, CustomSectionIndex>,
+    pub tags: PrimaryMap<TagIndex, SignatureIndex>,
+    pub custom_sections: Vec<(String, CustomSectionIndex)>,
     pub custom_sections_data: PrimaryMap<CustomSectionIndex, Arc<[u8]>>,
     pub import_counts: ImportCounts,
 }
@@ -223,12 +248,18 @@ impl From<ModuleInfo> for ArchivableModuleInfo {
             passive_data: it.passive_data.into_iter().collect(),
             global_initializers: it.global_initializers,
             function_names: it.function_names.into_iter().collect(),
+            local_names: it
+                .local_names
+                .into_iter()
+                .map(|(index, names)| (index, names.into_iter().collect()))
+                .collect(),
             signatures: it.signatures,
             functions: it.functions,
             tables: it.tables,
             memories: it.memories,
             globals: it.globals,
-            custom_sections: ArchivableIndexMap::from(it.custom_sections),
+            tags: it.tags,
+            custom_sections: it.custom_sections,
             custom_sections_data: it.custom_sections_data,
             import_counts: it.import_counts,
         }
@@ -248,12 +279,18 @@ impl From<ArchivableModuleInfo> for ModuleInfo {
             passive_data: it.passive_data.into_iter().collect(),
             global_initializers: it.global_initializers,
             function_names: it.function_names.into_iter().collect(),
+            local_names: it
+                .local_names
+                .into_iter()
+                .map(|(index, names)| (index, names.into_iter().collect()))
+                .collect(),
             signatures: it.signatures,
             functions: it.functions,
             tables: it.tables,
             memories: it.memories,
             globals: it.globals,
-            custom_sections: it.custom_sections.into(),
+            tags: it.tags,
+            custom_sections: it.custom_sections,
             custom_sections_data: it.custom_sections_data,
             import_counts: it.import_counts,
         }
@@ -305,11 +342,13 @@ impl PartialEq for ModuleInfo {
             && self.passive_data == other.passive_data
             && self.global_initializers == other.global_initializers
             && self.function_names == other.function_names
+            && self.local_names == other.local_names
             && self.signatures == other.signatures
             && self.functions == other.functions
             && self.tables == other.tables
             && self.memories == other.memories
             && self.globals == other.globals
+            && self.tags == other.tags
             && self.custom_sections == other.custom_sections
             && self.custom_sections_data == other.custom_sections_data
             && self.import_counts == other.import_counts
@@ -420,6 +459,17 @@ impl ModuleInfo {
         self.local_global_index(index).is_none()
     }
 
+    /// Convert a `LocalTagIndex` into a `TagIndex`.
+    pub fn tag_index(&self, local_tag: LocalTagIndex) -> TagIndex {
+        self.import_counts.tag_index(local_tag)
+    }
+
+    /// Convert a `TagIndex` into a `LocalTagIndex`. Returns None if the index is an imported
+    /// tag.
+    pub fn local_tag_index(&self, tag: TagIndex) -> Option<LocalTagIndex> {
+        self.import_counts.local_tag_index(tag).ok()
+    }
+
     /// Get the Module name
     pub fn name(&self) -> String {
         match self.name {
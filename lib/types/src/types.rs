@@ -1,12 +1,14 @@
 use crate::indexes::{FunctionIndex, GlobalIndex};
+use crate::initializers::ConstExpr;
 use crate::lib::std::fmt;
 use crate::lib::std::format;
 use crate::lib::std::string::{String, ToString};
 use crate::lib::std::vec::Vec;
-use crate::units::Pages;
+use crate::units::{Pages, WASM64_MAX_PAGES};
 use crate::values::{Value, WasmValueType};
 use std::cell::UnsafeCell;
 use std::rc::Rc;
+use std::sync::atomic::AtomicU64;
 use std::sync::Arc;
 
 // Type Representations
@@ -117,6 +119,8 @@ pub enum ExternType {
     Table(TableType),
     /// This external type is the type of a WebAssembly memory.
     Memory(MemoryType),
+    /// This external type is the type of a WebAssembly exception tag.
+    Tag(TagType),
 }
 
 macro_rules! accessors {
@@ -149,6 +153,19 @@ impl ExternType {
         (Global(GlobalType) global unwrap_global)
         (Table(TableType) table unwrap_table)
         (Memory(MemoryType) memory unwrap_memory)
+        (Tag(TagType) tag unwrap_tag)
+    }
+}
+
+impl fmt::Display for ExternType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Function(ty) => write!(f, "function {}", ty),
+            Self::Global(ty) => write!(f, "global {}", ty),
+            Self::Table(ty) => write!(f, "table {}", ty),
+            Self::Memory(ty) => write!(f, "memory {}", ty),
+            Self::Tag(ty) => write!(f, "tag {}", ty),
+        }
     }
 }
 
@@ -349,8 +366,7 @@ impl fmt::Display for GlobalType {
 }
 
 /// Globals are initialized via the `const` operators or by referring to another import.
-#[derive(Debug, Clone, Copy, PartialEq, rkyv::Serialize, rkyv::Deserialize, rkyv::Archive)]
-#[archive(as = "Self")]
+#[derive(Debug, Clone, PartialEq, rkyv::Serialize, rkyv::Deserialize, rkyv::Archive)]
 pub enum GlobalInit {
     /// An `i32.const`.
     I32Const(i32),
@@ -371,6 +387,8 @@ pub enum GlobalInit {
     RefNullConst,
     /// A `ref.func <index>`.
     RefFunc(FunctionIndex),
+    /// An extended constant expression.
+    Expr(ConstExpr),
 }
 
 impl Eq for GlobalInit {}
@@ -398,6 +416,54 @@ impl GlobalInit {
     }
 }
 
+// Tag Types
+
+/// A descriptor for an exception tag in a WebAssembly module.
+///
+/// The exceptions thrown with a tag carry values of the types of its params.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TagType {
+    /// The types of the values the exceptions carry.
+    params: Arc<[Type]>,
+}
+
+impl TagType {
+    /// Creates a new tag type for exceptions carrying values of types `params`.
+    pub fn new<Params>(params: Params) -> Self
+    where
+        Params: Into<Arc<[Type]>>,
+    {
+        Self {
+            params: params.into(),
+        }
+    }
+
+    /// The types of the values the exceptions carry.
+    pub fn params(&self) -> &[Type] {
+        &self.params
+    }
+}
+
+impl From<&FunctionType> for TagType {
+    fn from(signature: &FunctionType) -> Self {
+        Self {
+            params: signature.params.clone(),
+        }
+    }
+}
+
+impl fmt::Display for TagType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let params = self
+            .params
+            .iter()
+            .map(|p| format!("{:?}", p))
+            .collect::<Vec<_>>()
+            .join(", ");
+        write!(f, "[{}]", params)
+    }
+}
+
 // Table Types
 
 /// A descriptor for a table in a WebAssembly module.
@@ -455,6 +521,8 @@ pub struct MemoryType {
     pub maximum: Option<Pages>,
     /// Whether the memory may be shared between multiple threads.
     pub shared: bool,
+    /// Whether the memory is indexed with 64-bit addresses, as per the memory64 proposal.
+    pub memory64: bool,
 }
 
 impl MemoryType {
@@ -468,6 +536,16 @@ impl MemoryType {
             minimum: minimum.into(),
             maximum: maximum.map(Into::into),
             shared,
+            memory64: false,
+        }
+    }
+
+    /// The limit of the size of the memory in pages, set by the width of its addresses.
+    pub fn max_pages(&self) -> Pages {
+        if self.memory64 {
+            Pages(WASM64_MAX_PAGES)
+        } else {
+            Pages::max_value()
         }
     }
 }
@@ -624,6 +702,10 @@ pub struct InstanceConfig {
     default_gas_counter: Option<Rc<UnsafeCell<FastGasCounter>>>,
     /// Stack limit, in 8-byte slots.
     pub stack_limit: i32,
+    /// The epoch counter checked against the epoch deadline of the instance, if any.
+    epoch_counter: Option<Arc<AtomicU64>>,
+    /// Whether the instance collects metrics.
+    metrics: bool,
 }
 
 // Default stack limit, in 8-byte stack slots.
@@ -641,6 +723,8 @@ impl InstanceConfig {
             gas_counter: result.get(),
             default_gas_counter: Some(result),
             stack_limit: DEFAULT_STACK_LIMIT,
+            epoch_counter: None,
+            metrics: false,
         }
     }
 
@@ -653,6 +737,30 @@ impl InstanceConfig {
         self
     }
 
+    /// Create instance configuration with the epoch counter that code compiled with epoch
+    /// interruption checks against the epoch deadline of the instance.
+    pub fn with_epoch_counter(mut self, epoch_counter: Arc<AtomicU64>) -> Self {
+        self.epoch_counter = Some(epoch_counter);
+        self
+    }
+
+    /// The epoch counter of the instance, if any.
+    pub fn epoch_counter(&self) -> Option<&Arc<AtomicU64>> {
+        self.epoch_counter.as_ref()
+    }
+
+    /// Create instance configuration collecting the metrics of the instance, which the host
+    /// can sample at any time.
+    pub fn with_metrics(mut self) -> Self {
+        self.metrics = true;
+        self
+    }
+
+    /// Whether the instance collects metrics.
+    pub fn metrics(&self) -> bool {
+        self.metrics
+    }
+
     /// Create instance configuration with given stack limit.
     pub unsafe fn with_stack_limit(mut self, stack_limit: i32) -> Self {
         self.stack_limit = stack_limit;
@@ -13,6 +13,9 @@ pub const WASM_PAGE_SIZE: usize = 0x10000;
 /// The number of pages we can have before we run out of byte index space.
 pub const WASM_MAX_PAGES: u32 = 0x10000;
 
+/// The number of pages a 64-bit memory can have, as many as `Pages` counts.
+pub const WASM64_MAX_PAGES: u32 = u32::MAX;
+
 /// The minimum number of pages allowed.
 pub const WASM_MIN_PAGES: u32 = 0x100;
 
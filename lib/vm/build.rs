@@ -1,11 +1,14 @@
-//! Runtime build script compiles C code using setjmp for trap handling.
+//! Runtime build script compiles C code using setjmp for trap handling, and ucontext for
+//! the fibers.
 
 use std::env;
 
 fn main() {
     println!("cargo:rerun-if-changed=src/trap/handlers.c");
+    println!("cargo:rerun-if-changed=src/fiber.c");
 
-    cc::Build::new()
+    let mut build = cc::Build::new();
+    build
         .warnings(true)
         .define(
             &format!(
@@ -14,6 +17,9 @@ fn main() {
             ),
             None,
         )
-        .file("src/trap/handlers.c")
-        .compile("handlers");
+        .file("src/trap/handlers.c");
+    if env::var_os("CARGO_CFG_UNIX").is_some() {
+        build.file("src/fiber.c");
+    }
+    build.compile("handlers");
 }
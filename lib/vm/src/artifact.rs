@@ -1,8 +1,9 @@
 use crate::{InstanceHandle, Resolver, Tunables, VMLocalFunction, VMSharedSignatureIndex};
-use std::{any::Any, collections::BTreeMap, sync::Arc};
+use std::{any::Any, collections::BTreeMap, fmt, ops::Deref, sync::Arc};
 use wasmer_types::{
-    entity::BoxedSlice, ElemIndex, FunctionIndex, GlobalInit, GlobalType, ImportCounts,
-    InstanceConfig, LocalFunctionIndex, OwnedDataInitializer, OwnedTableInitializer,
+    entity::{BoxedSlice, PrimaryMap},
+    DataIndex, DataInitializer, ElemIndex, FunctionIndex, GlobalInit, GlobalType, ImportCounts,
+    InstanceConfig, LocalFunctionIndex, OwnedTableInitializer,
 };
 
 mod private {
@@ -28,6 +29,51 @@ pub trait Instantiatable: Artifact {
     ) -> Result<InstanceHandle, Self::Error>;
 }
 
+/// Bytes of an [`Artifact`], such as the contents of its data segments: owned by the
+/// artifact, or borrowed from the file it was loaded from, which it keeps mapped.
+#[derive(Clone)]
+pub struct ArtifactBytes(ArtifactBytesInner);
+
+#[derive(Clone)]
+enum ArtifactBytesInner {
+    Owned(Arc<[u8]>),
+    Borrowed(&'static [u8]),
+}
+
+impl ArtifactBytes {
+    /// Borrow `bytes`, which the artifact holding them keeps alive.
+    ///
+    /// # Safety
+    ///
+    /// The bytes must outlive the artifact.
+    pub unsafe fn borrowed(bytes: &[u8]) -> Self {
+        Self(ArtifactBytesInner::Borrowed(&*(bytes as *const [u8])))
+    }
+}
+
+impl From<Arc<[u8]>> for ArtifactBytes {
+    fn from(bytes: Arc<[u8]>) -> Self {
+        Self(ArtifactBytesInner::Owned(bytes))
+    }
+}
+
+impl Deref for ArtifactBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.0 {
+            ArtifactBytesInner::Owned(bytes) => bytes,
+            ArtifactBytesInner::Borrowed(bytes) => bytes,
+        }
+    }
+}
+
+impl fmt::Debug for ArtifactBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ArtifactBytes({} bytes)", self.len())
+    }
+}
+
 /// A predecesor of a full module Instance.
 ///
 /// This type represents parts of a compiled WASM module ([`Executable`](crate::Executable)) that
@@ -56,6 +102,22 @@ pub trait Artifact: Send + Sync {
     /// These are published and ready to call.
     fn functions(&self) -> &BoxedSlice<LocalFunctionIndex, VMLocalFunction>;
 
+    /// The size in bytes of the machine code of each locally defined function.
+    fn function_code_sizes(&self) -> PrimaryMap<LocalFunctionIndex, usize> {
+        self.functions()
+            .values()
+            .map(|function| function.length as usize)
+            .collect()
+    }
+
+    /// The total size in bytes of the machine code of the locally defined functions.
+    fn code_size(&self) -> usize {
+        self.functions()
+            .values()
+            .map(|function| function.length as usize)
+            .sum()
+    }
+
     /// Passive table elements.
     fn passive_elements(&self) -> &BTreeMap<ElemIndex, Box<[FunctionIndex]>>;
 
@@ -63,8 +125,10 @@ pub trait Artifact: Send + Sync {
     fn element_segments(&self) -> &[OwnedTableInitializer];
 
     /// Memory initializers.
-    /// TODO: consider making it an iterator of `DataInitializer`s instead?
-    fn data_segments(&self) -> &[OwnedDataInitializer];
+    fn data_segments(&self) -> Box<dyn Iterator<Item = DataInitializer<'_>> + '_>;
+
+    /// The contents of the passive data segments.
+    fn passive_data(&self) -> &BTreeMap<DataIndex, ArtifactBytes>;
 
     /// Passive table elements.
     fn globals(&self) -> &[(GlobalType, GlobalInit)];
@@ -0,0 +1,23 @@
+//! Epoch interruption: stopping the code that runs past the epoch deadline of its instance.
+
+use std::sync::atomic::AtomicU64;
+
+/// What to do when code compiled with epoch interruption reaches the epoch deadline of its
+/// instance.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EpochDeadlineAction {
+    /// Trap with `TrapCode::EpochDeadlineExceeded`.
+    Trap,
+    /// Keep running with a new epoch deadline.
+    Continue(u64),
+}
+
+/// A callback deciding what to do when the epoch deadline is reached, given the current
+/// epoch.
+///
+/// It runs on the thread running the code, which waits for it, so it can yield to the host:
+/// poll other tasks, or wait for a signal to resume.
+pub type EpochDeadlineCallback = dyn Fn(u64) -> EpochDeadlineAction + Send + Sync;
+
+/// The epoch of the instances created without an epoch counter, which never changes.
+pub(crate) static NO_EPOCH: AtomicU64 = AtomicU64::new(0);
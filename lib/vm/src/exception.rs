@@ -0,0 +1,168 @@
+//! Exceptions of the exception handling proposal, and the landing pads catching them.
+//!
+//! A thrown exception unwinds the native stack up to the innermost landing pad found for the
+//! return addresses of the wasm frames, see `raise_exception`. The compiled code registers its
+//! landing pads here, keyed by the return address of the call they catch the exceptions of.
+
+use crate::export::VMTag;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ptr;
+use std::sync::{Arc, Once, RwLock};
+use wasmer_types::{Type, VMExternRef};
+
+/// A wasm exception: a tag and the values it carries.
+///
+/// Exceptions are shared rather than copied, so that `rethrow` throws the very same exception
+/// again. The compiled code refers to them by the pointer of `as_ptr`.
+#[derive(Clone)]
+pub struct VMException {
+    inner: Arc<ExceptionData>,
+}
+
+struct ExceptionData {
+    tag: VMTag,
+    /// The values, one 16-byte slot each, in the layout of `Value::write_value_to`.
+    ///
+    /// The `externref` values hold a count of their reference.
+    payload: Box<[i128]>,
+}
+
+impl Drop for ExceptionData {
+    fn drop(&mut self) {
+        for (ty, value) in self.tag.ty().params().iter().zip(self.payload.iter()) {
+            if *ty == Type::ExternRef {
+                // SAFETY: the slot holds a counted reference, see `VMException::from_values`.
+                unsafe { ptr::read(value as *const i128 as *const VMExternRef).ref_drop() };
+            }
+        }
+    }
+}
+
+impl VMException {
+    /// Creates an exception of `tag` carrying `payload`, whose `externref`s already hold a count
+    /// for the exception.
+    ///
+    /// # Safety
+    ///
+    /// `payload` must hold a valid value of each param type of `tag`, in order.
+    pub unsafe fn new(tag: VMTag, payload: Box<[i128]>) -> Self {
+        debug_assert_eq!(payload.len(), tag.ty().params().len());
+        Self {
+            inner: Arc::new(ExceptionData { tag, payload }),
+        }
+    }
+
+    /// Creates an exception of `tag` carrying the values at `values`, as the compiled code lays
+    /// them out, counting new references to the `externref`s.
+    ///
+    /// # Safety
+    ///
+    /// `values` must point to a valid value of each param type of `tag`, in consecutive 16-byte
+    /// slots.
+    pub(crate) unsafe fn from_values(tag: VMTag, values: *const i128) -> Self {
+        let params = tag.ty().params();
+        let payload: Box<[i128]> = (0..params.len()).map(|i| *values.add(i)).collect();
+        for (ty, value) in params.iter().zip(payload.iter()) {
+            if *ty == Type::ExternRef {
+                ptr::read(value as *const i128 as *const VMExternRef).ref_clone();
+            }
+        }
+        Self::new(tag, payload)
+    }
+
+    /// The tag of the exception.
+    pub fn tag(&self) -> &VMTag {
+        &self.inner.tag
+    }
+
+    /// The values the exception carries, one 16-byte slot each.
+    pub fn payload(&self) -> &[i128] {
+        &self.inner.payload
+    }
+
+    /// The pointer the compiled code refers to the exception with.
+    pub(crate) fn as_ptr(&self) -> *const u8 {
+        Arc::as_ptr(&self.inner) as *const u8
+    }
+
+    /// Gets back the exception of a pointer given by `as_ptr`.
+    ///
+    /// # Safety
+    ///
+    /// The exception must still be alive.
+    pub(crate) unsafe fn from_ptr(ptr: *const u8) -> Self {
+        let ptr = ptr as *const ExceptionData;
+        Arc::increment_strong_count(ptr);
+        Self {
+            inner: Arc::from_raw(ptr),
+        }
+    }
+
+    /// Returns whether or not the two `VMException`s are the same exception.
+    pub fn same(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner)
+    }
+}
+
+impl fmt::Debug for VMException {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("VMException")
+            .field("tag", &self.inner.tag)
+            .field("payload", &self.inner.payload)
+            .finish()
+    }
+}
+
+/// The landing pads of the loaded code, by the return address of the call they catch the
+/// exceptions of.
+type LandingPads = BTreeMap<usize, usize>;
+
+fn landing_pads() -> &'static RwLock<LandingPads> {
+    static INIT: Once = Once::new();
+    static mut PADS: Option<RwLock<LandingPads>> = None;
+    // SAFETY: `PADS` is only written once, before `call_once` returns for the first time.
+    unsafe {
+        INIT.call_once(|| PADS = Some(RwLock::new(BTreeMap::new())));
+        PADS.as_ref().unwrap()
+    }
+}
+
+/// Registers the landing pads of some loaded code, as `(return address, landing pad)` pairs,
+/// until the returned registration is dropped.
+pub fn register_landing_pads(
+    pads: impl IntoIterator<Item = (usize, usize)>,
+) -> LandingPadRegistration {
+    let return_addresses: Vec<usize> = {
+        let mut registry = landing_pads().write().unwrap_or_else(|e| e.into_inner());
+        pads.into_iter()
+            .map(|(return_address, pad)| {
+                let prev = registry.insert(return_address, pad);
+                assert!(prev.is_none(), "landing pad registered twice");
+                return_address
+            })
+            .collect()
+    };
+    LandingPadRegistration { return_addresses }
+}
+
+/// The landing pad of the call returning to `return_address`, if any.
+pub(crate) fn lookup_landing_pad(return_address: usize) -> Option<usize> {
+    let registry = landing_pads().read().unwrap_or_else(|e| e.into_inner());
+    registry.get(&return_address).copied()
+}
+
+/// Landing pads registered with `register_landing_pads`, unregistered on drop.
+#[derive(Debug)]
+pub struct LandingPadRegistration {
+    return_addresses: Vec<usize>,
+}
+
+impl Drop for LandingPadRegistration {
+    fn drop(&mut self) {
+        let mut registry = landing_pads().write().unwrap_or_else(|e| e.into_inner());
+        for return_address in &self.return_addresses {
+            registry.remove(return_address);
+        }
+    }
+}
@@ -2,13 +2,13 @@
 // Attributions: https://github.com/wasmerio/wasmer/blob/master/ATTRIBUTIONS.md
 
 use crate::global::Global;
-use crate::instance::WeakOrStrongInstanceRef;
+use crate::instance::{InstanceEntry, InstanceId, WeakOrStrongInstanceRef};
 use crate::memory::{Memory, MemoryStyle};
 use crate::table::{Table, TableStyle};
 use crate::vmcontext::{VMFunctionBody, VMFunctionEnvironment, VMFunctionKind, VMTrampoline};
 use crate::VMSharedSignatureIndex;
 use std::sync::Arc;
-use wasmer_types::{MemoryType, TableType};
+use wasmer_types::{MemoryType, TableType, TagType};
 
 /// The value of an export passed from one instance to another.
 #[derive(Debug)]
@@ -24,6 +24,9 @@ pub enum VMExtern {
 
     /// A global export value.
     Global(VMGlobal),
+
+    /// An exception tag export value.
+    Tag(VMTag),
 }
 
 /// A function export value.
@@ -55,6 +58,46 @@ pub struct VMFunction {
 }
 
 impl VMFunction {
+    /// Whether the instance defining this function was closed. Always false for host
+    /// functions.
+    pub fn is_instance_closed(&self) -> bool {
+        self.instance_ref
+            .as_ref()
+            .map_or(false, WeakOrStrongInstanceRef::is_closed)
+    }
+
+    /// Whether an async call into the instance defining this function is suspended. Always
+    /// false for host functions.
+    pub fn is_instance_suspended(&self) -> bool {
+        self.instance_ref
+            .as_ref()
+            .map_or(false, WeakOrStrongInstanceRef::is_suspended)
+    }
+
+    /// The identifier of the instance defining this function. None for host functions and
+    /// for the functions of freed instances.
+    pub fn instance_id(&self) -> Option<InstanceId> {
+        self.instance_ref
+            .as_ref()
+            .and_then(WeakOrStrongInstanceRef::id)
+    }
+
+    /// Enter the instance defining this function for a call from the host on the current
+    /// thread, see [`WeakOrStrongInstanceRef::enter`]. Host functions enter nothing.
+    pub fn enter_instance(&self) -> Option<InstanceEntry> {
+        self.instance_ref
+            .as_ref()
+            .map_or(Some(InstanceEntry::none()), WeakOrStrongInstanceRef::enter)
+    }
+
+    /// Record a call of this function from the host in the metrics of the instance defining
+    /// it, if it collects them. Does nothing for host functions.
+    pub fn record_host_call(&self) {
+        if let Some(instance_ref) = &self.instance_ref {
+            instance_ref.record_host_call();
+        }
+    }
+
     /// Converts the stored instance ref into a strong `InstanceRef` if it is weak.
     /// Returns None if it cannot be upgraded.
     pub fn upgrade_instance_ref(&mut self) -> Option<()> {
@@ -63,11 +106,20 @@ impl VMFunction {
         }
         Some(())
     }
+
+    /// Converts the stored instance ref into a weak `InstanceRef`, so that this export does
+    /// not keep the instance alive.
+    pub fn downgrade_instance_ref(&mut self) {
+        if let Some(ref mut ir) = self.instance_ref {
+            *ir = ir.downgrade();
+        }
+    }
 }
 
 /// # Safety
 /// There is no non-threadsafe logic directly in this type. Calling the function
-/// may not be threadsafe.
+/// is not threadsafe, so the calls from the host enter the instance with
+/// [`VMFunction::enter_instance`] first.
 unsafe impl Send for VMFunction {}
 /// # Safety
 /// The members of an VMFunction are immutable after construction.
@@ -118,6 +170,15 @@ impl VMTable {
         Arc::ptr_eq(&self.from, &other.from)
     }
 
+    /// Enter the instance defining this table for a change from the host on the current
+    /// thread, see [`WeakOrStrongInstanceRef::enter`]. Tables created by the host enter
+    /// nothing.
+    pub fn enter_instance(&self) -> Option<InstanceEntry> {
+        self.instance_ref
+            .as_ref()
+            .map_or(Some(InstanceEntry::none()), WeakOrStrongInstanceRef::enter)
+    }
+
     /// Converts the stored instance ref into a strong `InstanceRef` if it is weak.
     /// Returns None if it cannot be upgraded.
     pub fn upgrade_instance_ref(&mut self) -> Option<()> {
@@ -126,6 +187,14 @@ impl VMTable {
         }
         Some(())
     }
+
+    /// Converts the stored instance ref into a weak `InstanceRef`, so that this export does
+    /// not keep the instance alive.
+    pub fn downgrade_instance_ref(&mut self) {
+        if let Some(ref mut ir) = self.instance_ref {
+            *ir = ir.downgrade();
+        }
+    }
 }
 
 impl From<VMTable> for VMExtern {
@@ -173,6 +242,16 @@ impl VMMemory {
         Arc::ptr_eq(&self.from, &other.from)
     }
 
+    /// Enter the instance defining this memory for a change from the host on the current
+    /// thread, see [`WeakOrStrongInstanceRef::enter`]. Shared memories and the memories
+    /// created by the host enter nothing.
+    pub fn enter_instance(&self) -> Option<InstanceEntry> {
+        match &self.instance_ref {
+            Some(instance_ref) if !self.ty().shared => instance_ref.enter(),
+            _ => Some(InstanceEntry::none()),
+        }
+    }
+
     /// Converts the stored instance ref into a strong `InstanceRef` if it is weak.
     /// Returns None if it cannot be upgraded.
     pub fn upgrade_instance_ref(&mut self) -> Option<()> {
@@ -181,6 +260,14 @@ impl VMMemory {
         }
         Some(())
     }
+
+    /// Converts the stored instance ref into a weak `InstanceRef`, so that this export does
+    /// not keep the instance alive.
+    pub fn downgrade_instance_ref(&mut self) {
+        if let Some(ref mut ir) = self.instance_ref {
+            *ir = ir.downgrade();
+        }
+    }
 }
 
 impl From<VMMemory> for VMExtern {
@@ -218,6 +305,15 @@ impl VMGlobal {
         Arc::ptr_eq(&self.from, &other.from)
     }
 
+    /// Enter the instance defining this global for a change from the host on the current
+    /// thread, see [`WeakOrStrongInstanceRef::enter`]. Globals created by the host enter
+    /// nothing.
+    pub fn enter_instance(&self) -> Option<InstanceEntry> {
+        self.instance_ref
+            .as_ref()
+            .map_or(Some(InstanceEntry::none()), WeakOrStrongInstanceRef::enter)
+    }
+
     /// Converts the stored instance ref into a strong `InstanceRef` if it is weak.
     /// Returns None if it cannot be upgraded.
     pub fn upgrade_instance_ref(&mut self) -> Option<()> {
@@ -226,6 +322,14 @@ impl VMGlobal {
         }
         Some(())
     }
+
+    /// Converts the stored instance ref into a weak `InstanceRef`, so that this export does
+    /// not keep the instance alive.
+    pub fn downgrade_instance_ref(&mut self) {
+        if let Some(ref mut ir) = self.instance_ref {
+            *ir = ir.downgrade();
+        }
+    }
 }
 
 impl From<VMGlobal> for VMExtern {
@@ -233,3 +337,36 @@ impl From<VMGlobal> for VMExtern {
         Self::Global(global)
     }
 }
+
+/// An exception tag export value.
+///
+/// Tags are compared by identity: two tags of the same type are still different tags, and only
+/// the handlers of a tag catch the exceptions thrown with it.
+#[derive(Debug, Clone)]
+pub struct VMTag {
+    /// The tag declaration, used for compatibility checking.
+    pub from: Arc<TagType>,
+}
+
+impl VMTag {
+    /// Creates a new tag, different from all the other tags.
+    pub fn new(ty: TagType) -> Self {
+        Self { from: Arc::new(ty) }
+    }
+
+    /// Get the type for this exported tag
+    pub fn ty(&self) -> &TagType {
+        &self.from
+    }
+
+    /// Returns whether or not the two `VMTag`s are the same tag.
+    pub fn same(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.from, &other.from)
+    }
+}
+
+impl From<VMTag> for VMExtern {
+    fn from(tag: VMTag) -> Self {
+        Self::Tag(tag)
+    }
+}
@@ -0,0 +1,62 @@
+// Stack switching with `ucontext`, for the fibers running the async calls
+// into WebAssembly.
+
+// macOS only declares the `ucontext` functions with this defined.
+#define _XOPEN_SOURCE 700
+#include <stdint.h>
+#include <stdlib.h>
+#include <ucontext.h>
+
+struct wasmer_fiber {
+  ucontext_t fiber;
+  ucontext_t caller;
+  void (*entry)(void*);
+  void *payload;
+};
+
+// `makecontext` only passes `int` arguments, so the fiber pointer is split
+// in two halves.
+static void wasmer_fiber_start(unsigned int hi, unsigned int lo) {
+  struct wasmer_fiber *fiber =
+      (struct wasmer_fiber*) (uintptr_t) (((uint64_t) hi << 32) | (uint64_t) lo);
+  fiber->entry(fiber->payload);
+  // Returning resumes `uc_link`, the caller.
+}
+
+void *wasmer_fiber_new(
+    void *stack,
+    size_t stack_size,
+    void (*entry)(void*),
+    void *payload) {
+  struct wasmer_fiber *fiber = calloc(1, sizeof(struct wasmer_fiber));
+  if (fiber == NULL) {
+    return NULL;
+  }
+  if (getcontext(&fiber->fiber) != 0) {
+    free(fiber);
+    return NULL;
+  }
+  fiber->fiber.uc_stack.ss_sp = stack;
+  fiber->fiber.uc_stack.ss_size = stack_size;
+  fiber->fiber.uc_link = &fiber->caller;
+  fiber->entry = entry;
+  fiber->payload = payload;
+  uint64_t ptr = (uint64_t) (uintptr_t) fiber;
+  makecontext(&fiber->fiber, (void (*)(void)) wasmer_fiber_start, 2,
+              (unsigned int) (ptr >> 32), (unsigned int) ptr);
+  return fiber;
+}
+
+void wasmer_fiber_resume(void *fiber) {
+  struct wasmer_fiber *f = (struct wasmer_fiber*) fiber;
+  swapcontext(&f->caller, &f->fiber);
+}
+
+void wasmer_fiber_suspend(void *fiber) {
+  struct wasmer_fiber *f = (struct wasmer_fiber*) fiber;
+  swapcontext(&f->fiber, &f->caller);
+}
+
+void wasmer_fiber_free(void *fiber) {
+  free(fiber);
+}
@@ -0,0 +1,230 @@
+//! Fibers: running code on a stack of its own, which it can leave and come back to.
+//!
+//! The async calls into WebAssembly run on fibers, so that a host function awaiting a
+//! future can suspend the whole call, the WebAssembly frames included, and have it resumed
+//! later on the same stack.
+
+use crate::instance::entry::{new_thread_token, swap_thread_token};
+use crate::mmap::Mmap;
+use crate::trap::traphandlers::{swap_call_thread_state, CallThreadState};
+use std::any::Any;
+use std::cell::Cell;
+use std::ffi::c_void;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+extern "C" {
+    fn wasmer_fiber_new(
+        stack: *mut u8,
+        stack_size: usize,
+        entry: extern "C" fn(*mut c_void),
+        payload: *mut c_void,
+    ) -> *mut c_void;
+    fn wasmer_fiber_resume(fiber: *mut c_void);
+    fn wasmer_fiber_suspend(fiber: *mut c_void);
+    fn wasmer_fiber_free(fiber: *mut c_void);
+}
+
+/// A function running on a stack of its own, one step at a time: each call to
+/// [`Fiber::resume`] runs it until it calls [`Suspend::suspend`] or returns.
+///
+/// A fiber may be resumed on any thread, the trap handling state of its calls and its
+/// identity for entering instances moving along with it. The values living on its stack
+/// move as well, so they must be `Send`, and the code running on it must not keep the
+/// address of a thread-local across a suspension. Dropping a fiber that didn't return
+/// frees its stack without running the destructors of the values living on it.
+pub struct Fiber<'a> {
+    inner: Box<Inner<'a>>,
+    /// The stack, above a guard page.
+    _stack: Mmap,
+}
+
+struct Inner<'a> {
+    raw: *mut c_void,
+    body: Option<Box<dyn FnOnce(&Suspend) + Send + 'a>>,
+    done: bool,
+    panic: Option<Box<dyn Any + Send>>,
+    /// The trap handling state of the side of the switch that isn't running. The fiber
+    /// and the code resuming it have call states of their own, and each trap must find
+    /// the states of the stack it is raised on.
+    tls: Cell<*const CallThreadState>,
+    /// The thread token of the side of the switch that isn't running.
+    thread: Cell<u64>,
+}
+
+// Only the side of the switch that is running uses the fiber, on a single thread at a time.
+unsafe impl Send for Fiber<'_> {}
+
+/// The handle a fiber suspends itself with, given to its function.
+pub struct Suspend {
+    raw: *mut c_void,
+    inner: *const Inner<'static>,
+}
+
+impl Inner<'_> {
+    /// Swap the thread states of both sides of a switch.
+    unsafe fn swap_thread_states(&self) {
+        self.tls.set(swap_call_thread_state(self.tls.get()));
+        self.thread.set(swap_thread_token(self.thread.get()));
+    }
+}
+
+extern "C" fn fiber_entry(payload: *mut c_void) {
+    let inner = payload as *mut Inner<'static>;
+    unsafe {
+        let body = (*inner).body.take().expect("the fiber started twice");
+        let suspend = Suspend {
+            raw: (*inner).raw,
+            inner,
+        };
+        // Unwinding must not cross the C frame below.
+        if let Err(panic) = panic::catch_unwind(AssertUnwindSafe(|| body(&suspend))) {
+            (*inner).panic = Some(panic);
+        }
+        (*inner).done = true;
+        // Returning switches back to the code that resumed the fiber.
+        (*inner).swap_thread_states();
+    }
+}
+
+impl<'a> Fiber<'a> {
+    /// Create a fiber running `body` on a stack of at least `stack_size` bytes. The fiber
+    /// only starts on the first call to [`Fiber::resume`].
+    pub fn new(stack_size: usize, body: impl FnOnce(&Suspend) + Send + 'a) -> Result<Self, String> {
+        let page_size = region::page::size();
+        let stack_size = (stack_size.max(1) + (page_size - 1)) & !(page_size - 1);
+        let mut stack = Mmap::accessible_reserved(0, stack_size + page_size)?;
+        stack.make_accessible(page_size, stack_size)?;
+        let mut inner = Box::new(Inner {
+            raw: ptr::null_mut(),
+            body: Some(Box::new(body)),
+            done: false,
+            panic: None,
+            tls: Cell::new(ptr::null()),
+            thread: Cell::new(new_thread_token()),
+        });
+        let payload = &mut *inner as *mut Inner<'a> as *mut c_void;
+        inner.raw = unsafe {
+            wasmer_fiber_new(
+                stack.as_mut_ptr().add(page_size),
+                stack_size,
+                fiber_entry,
+                payload,
+            )
+        };
+        if inner.raw.is_null() {
+            return Err("failed to create the context of a fiber".to_string());
+        }
+        Ok(Self {
+            inner,
+            _stack: stack,
+        })
+    }
+
+    /// Run the fiber until it suspends itself or returns, and return whether it returned.
+    ///
+    /// A panic of the function of the fiber is resumed here.
+    pub fn resume(&mut self) -> bool {
+        assert!(!self.inner.done, "the fiber already returned");
+        unsafe {
+            self.inner.swap_thread_states();
+            wasmer_fiber_resume(self.inner.raw);
+        }
+        if let Some(panic) = self.inner.panic.take() {
+            panic::resume_unwind(panic);
+        }
+        self.inner.done
+    }
+
+    /// Whether the function of the fiber returned.
+    pub fn is_done(&self) -> bool {
+        self.inner.done
+    }
+}
+
+impl Drop for Fiber<'_> {
+    fn drop(&mut self) {
+        unsafe { wasmer_fiber_free(self.inner.raw) };
+    }
+}
+
+impl Suspend {
+    /// Switch back to the code that resumed the fiber, until it resumes it again.
+    pub fn suspend(&self) {
+        unsafe {
+            (*self.inner).swap_thread_states();
+            wasmer_fiber_suspend(self.raw);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instance::entry::current_thread;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    #[test]
+    fn fibers_run_step_by_step() {
+        let steps = Arc::new(AtomicUsize::new(0));
+        let mut fiber = Fiber::new(64 * 1024, {
+            let steps = steps.clone();
+            move |suspend| {
+                for _ in 0..3 {
+                    steps.fetch_add(1, Ordering::SeqCst);
+                    suspend.suspend();
+                }
+            }
+        })
+        .unwrap();
+        assert_eq!(steps.load(Ordering::SeqCst), 0);
+        for step in 1..=3 {
+            assert!(!fiber.resume());
+            assert_eq!(steps.load(Ordering::SeqCst), step);
+        }
+        assert!(fiber.resume());
+        assert!(fiber.is_done());
+    }
+
+    #[test]
+    fn fibers_move_between_threads() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let mut fiber = Fiber::new(64 * 1024, {
+            let seen = seen.clone();
+            move |suspend| {
+                for _ in 0..2 {
+                    seen.lock()
+                        .unwrap()
+                        .push((thread::current().id(), current_thread()));
+                    suspend.suspend();
+                }
+            }
+        })
+        .unwrap();
+        assert!(!fiber.resume());
+        let mut fiber = thread::spawn(move || {
+            assert!(!fiber.resume());
+            fiber
+        })
+        .join()
+        .unwrap();
+        assert!(fiber.resume());
+
+        let seen = seen.lock().unwrap();
+        // The fiber ran on both threads, with the same token.
+        assert_ne!(seen[0].0, seen[1].0);
+        assert_eq!(seen[0].1, seen[1].1);
+        // The thread resuming the fiber got its own token back.
+        assert_ne!(current_thread(), seen[0].1);
+    }
+
+    #[test]
+    fn panics_are_resumed_by_the_caller() {
+        let mut fiber = Fiber::new(64 * 1024, |_| panic!("in the fiber")).unwrap();
+        let panic = panic::catch_unwind(AssertUnwindSafe(|| fiber.resume())).unwrap_err();
+        assert_eq!(panic.downcast_ref::<&str>(), Some(&"in the fiber"));
+        assert!(fiber.is_done());
+    }
+}
@@ -1,3 +1,4 @@
+use crate::trap::defer_externref_drop;
 use crate::vmcontext::VMGlobalDefinition;
 use std::cell::UnsafeCell;
 use std::ptr::NonNull;
@@ -77,7 +78,8 @@ impl Global {
                 Type::F32 => Value::F32(definition.to_f32()),
                 Type::F64 => Value::F64(definition.to_f64()),
                 Type::V128 => Value::V128(definition.to_u128()),
-                Type::ExternRef => Value::ExternRef(definition.to_externref().into()),
+                // The global keeps its own reference.
+                Type::ExternRef => Value::ExternRef(definition.to_externref().ref_clone().into()),
                 Type::FuncRef => {
                     let p = definition.to_u128() as i128;
                     if p as usize == 0 {
@@ -125,9 +127,9 @@ impl Global {
             Value::F64(f) => *definition.as_f64_mut() = f,
             Value::V128(x) => *definition.as_bytes_mut() = x.to_ne_bytes(),
             Value::ExternRef(r) => {
-                let extern_ref = definition.as_externref_mut();
-                extern_ref.ref_drop();
-                *extern_ref = r.into()
+                // The Wasm code may still use the reference the global held.
+                let old = std::mem::replace(definition.as_externref_mut(), r.into());
+                defer_externref_drop(old.into());
             }
             Value::FuncRef(None) => *definition.as_u128_mut() = 0,
             Value::FuncRef(Some(r)) => {
@@ -137,3 +139,16 @@ impl Global {
         Ok(())
     }
 }
+
+impl Drop for Global {
+    fn drop(&mut self) {
+        if self.ty.ty == Type::ExternRef {
+            unsafe {
+                self.vm_global_definition
+                    .get_mut()
+                    .as_externref_mut()
+                    .ref_drop()
+            }
+        }
+    }
+}
@@ -1,11 +1,11 @@
 // This file contains code from external sources.
 // Attributions: https://github.com/wasmerio/wasmer/blob/master/ATTRIBUTIONS.md
 
-use crate::instance::ImportFunctionEnv;
+use crate::instance::{ImportFunctionEnv, WeakOrStrongInstanceRef};
 use crate::vmcontext::{VMFunctionImport, VMGlobalImport, VMMemoryImport, VMTableImport};
-use crate::{VMSharedSignatureIndex, VMTrampoline};
+use crate::{VMSharedSignatureIndex, VMTag, VMTrampoline};
 use wasmer_types::entity::{BoxedSlice, PrimaryMap};
-use wasmer_types::{FunctionIndex, GlobalIndex, MemoryIndex, TableIndex};
+use wasmer_types::{FunctionIndex, GlobalIndex, MemoryIndex, TableIndex, TagIndex};
 
 /// Type of the import.
 pub enum VMImportType {
@@ -22,6 +22,8 @@ pub enum VMImportType {
     Table(wasmer_types::TableType),
     /// Some memory.
     Memory(wasmer_types::MemoryType, crate::MemoryStyle),
+    /// An exception tag.
+    Tag(wasmer_types::TagType),
 }
 
 /// A module import.
@@ -34,6 +36,8 @@ pub struct VMImport {
     pub module: String,
     /// The field name.
     pub field: String,
+    /// The name the name section of the module gives the imported function, if any.
+    pub function_name: Option<String>,
     /// Type of the import.
     pub ty: VMImportType,
 }
@@ -64,6 +68,15 @@ pub struct Imports {
 
     /// Resolved addresses for imported globals.
     pub globals: BoxedSlice<GlobalIndex, VMGlobalImport>,
+
+    /// Resolved imported tags.
+    pub tags: BoxedSlice<TagIndex, VMTag>,
+
+    /// The instances that the imported functions are defined in, and those defining the
+    /// imported memories, tables and globals that the importing instance may change. The
+    /// functions run with the `VMContext` of these instances, so they must outlive the
+    /// importing instance, and calls into the importing instance enter all of them.
+    pub instances: Vec<WeakOrStrongInstanceRef>,
 }
 
 impl Imports {
@@ -74,6 +87,8 @@ impl Imports {
         table_imports: PrimaryMap<TableIndex, VMTableImport>,
         memory_imports: PrimaryMap<MemoryIndex, VMMemoryImport>,
         global_imports: PrimaryMap<GlobalIndex, VMGlobalImport>,
+        tag_imports: PrimaryMap<TagIndex, VMTag>,
+        instances: Vec<WeakOrStrongInstanceRef>,
     ) -> Self {
         Self {
             functions: function_imports.into_boxed_slice(),
@@ -81,6 +96,8 @@ impl Imports {
             tables: table_imports.into_boxed_slice(),
             memories: memory_imports.into_boxed_slice(),
             globals: global_imports.into_boxed_slice(),
+            tags: tag_imports.into_boxed_slice(),
+            instances,
         }
     }
 
@@ -92,6 +109,8 @@ impl Imports {
             tables: PrimaryMap::new().into_boxed_slice(),
             memories: PrimaryMap::new().into_boxed_slice(),
             globals: PrimaryMap::new().into_boxed_slice(),
+            tags: PrimaryMap::new().into_boxed_slice(),
+            instances: Vec::new(),
         }
     }
 
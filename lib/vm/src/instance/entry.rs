@@ -0,0 +1,172 @@
+//! Detection of concurrent calls into an instance.
+//!
+//! The state of an instance that is not shared (its globals, tables, non-shared memories,
+//! ...) is mutated by the code running in it without synchronization, so a single thread
+//! at a time may run in it. Calls from the host mark the instance as entered by their
+//! thread; the thread may call into the instance again while it runs (from a host
+//! function), but other threads are refused until it returns.
+//!
+//! The code of an instance also runs the functions it imports from other instances and
+//! changes the memories, tables and globals it imports from them, so entering an instance
+//! enters the instances it imports from too.
+//!
+//! A fiber is a thread of its own here: it takes its token along when it is resumed on
+//! another thread, and the thread resuming it gets its token back when it suspends.
+
+use super::{InstanceRef, WeakOrStrongInstanceRef};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// The owner of an [`EntryLock`] that was not entered.
+const NO_THREAD: u64 = 0;
+
+thread_local! {
+    /// The token of the code running on this thread, assigned on first use.
+    static THREAD: Cell<u64> = Cell::new(NO_THREAD);
+}
+
+/// A new token, never [`NO_THREAD`].
+pub(crate) fn new_thread_token() -> u64 {
+    static NEXT_THREAD: AtomicU64 = AtomicU64::new(NO_THREAD + 1);
+    NEXT_THREAD.fetch_add(1, Ordering::Relaxed)
+}
+
+/// A token identifying the current thread, never [`NO_THREAD`].
+// Not inlined, so that the address of the thread-local isn't kept across a fiber switch.
+#[inline(never)]
+pub(crate) fn current_thread() -> u64 {
+    THREAD.with(|thread| {
+        if thread.get() == NO_THREAD {
+            thread.set(new_thread_token());
+        }
+        thread.get()
+    })
+}
+
+/// Replace the token of the current thread with `token`, returning the previous one, for
+/// the fiber switches.
+#[inline(never)]
+pub(crate) fn swap_thread_token(token: u64) -> u64 {
+    let previous = current_thread();
+    THREAD.with(|thread| thread.set(token));
+    previous
+}
+
+/// Which thread runs in an instance, and how many times it entered it.
+#[derive(Debug, Default)]
+pub(crate) struct EntryLock {
+    owner: AtomicU64,
+    /// Only changed by the owner.
+    depth: AtomicUsize,
+}
+
+impl EntryLock {
+    /// Enter the instance from the current thread, returning false if another thread
+    /// runs in it.
+    pub(crate) fn enter(&self) -> bool {
+        let thread = current_thread();
+        match self
+            .owner
+            .compare_exchange(NO_THREAD, thread, Ordering::Acquire, Ordering::Relaxed)
+        {
+            Ok(_) => {}
+            Err(owner) if owner == thread => {}
+            Err(_) => return false,
+        }
+        self.depth.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+
+    /// Leave the instance, after a successful [`EntryLock::enter`].
+    pub(crate) fn exit(&self) {
+        if self.depth.fetch_sub(1, Ordering::Relaxed) == 1 {
+            self.owner.store(NO_THREAD, Ordering::Release);
+        }
+    }
+}
+
+/// A call into an instance from the host, which leaves the instance when dropped. See
+/// [`WeakOrStrongInstanceRef::enter`](super::WeakOrStrongInstanceRef::enter).
+#[must_use]
+#[derive(Debug)]
+pub struct InstanceEntry {
+    /// The instance entered, None for calls of host functions and of freed instances.
+    instance: Option<InstanceRef>,
+    /// The instances that `instance` imports from, entered along with it.
+    imported: Vec<InstanceRef>,
+    /// The stack meter of the instance when it was entered. The frames a trap unwinds don't
+    /// give back the stack they took, so the meter is restored when the call returns.
+    stack_meter: i32,
+}
+
+impl InstanceEntry {
+    /// An entry that holds no instance.
+    pub(crate) fn none() -> Self {
+        Self {
+            instance: None,
+            imported: Vec::new(),
+            stack_meter: 0,
+        }
+    }
+
+    /// Enter `instance`, and the instances it imports from, from the current thread,
+    /// returning None if another thread runs in any of them.
+    pub(crate) fn enter(instance: InstanceRef) -> Option<Self> {
+        if !instance.as_ref().entry_lock().enter() {
+            return None;
+        }
+        let mut imported = Vec::new();
+        if !enter_imported(&instance, &instance, &mut imported) {
+            for entered in imported.iter().chain(Some(&instance)) {
+                entered.as_ref().entry_lock().exit();
+            }
+            return None;
+        }
+        let stack_meter = unsafe { *instance.as_ref().stack_limit_ptr() };
+        Some(Self {
+            instance: Some(instance),
+            imported,
+            stack_meter,
+        })
+    }
+}
+
+/// Enter the instances that `instance` imports from, recursively, except `root` and those
+/// already in `entered`, adding them to `entered`. Returns false if another thread runs in
+/// one of them, leaving those entered so far in `entered`.
+fn enter_imported(
+    root: &InstanceRef,
+    instance: &InstanceRef,
+    entered: &mut Vec<InstanceRef>,
+) -> bool {
+    for import in &instance.as_ref().imports.instances {
+        let import = match import.upgrade() {
+            Some(WeakOrStrongInstanceRef::Strong(import)) => import,
+            _ => continue,
+        };
+        let id = import.as_ref().id;
+        if id == root.as_ref().id || entered.iter().any(|e| e.as_ref().id == id) {
+            continue;
+        }
+        if !import.as_ref().entry_lock().enter() {
+            return false;
+        }
+        entered.push(import.clone());
+        if !enter_imported(root, &import, entered) {
+            return false;
+        }
+    }
+    true
+}
+
+impl Drop for InstanceEntry {
+    fn drop(&mut self) {
+        for imported in &self.imported {
+            imported.as_ref().entry_lock().exit();
+        }
+        if let Some(instance) = &self.instance {
+            unsafe { *instance.as_ref().stack_limit_ptr() = self.stack_meter };
+            instance.as_ref().entry_lock().exit();
+        }
+    }
+}
@@ -0,0 +1,258 @@
+//! Snapshots of the state of instances, from which new instances of the same module start.
+
+use super::{Instance, InstanceHandle};
+use crate::func_data_registry::VMFuncRef;
+use crate::memory_image::MemoryImage;
+use crate::table::TableElement;
+use crate::vmcontext::VMCallerCheckedAnyfunc;
+use std::convert::TryFrom;
+use std::mem;
+use wasmer_types::entity::{EntityRef, PrimaryMap};
+use wasmer_types::{
+    DataIndex, ElemIndex, FunctionIndex, LocalGlobalIndex, LocalMemoryIndex, LocalTableIndex,
+    Pages, Type, WASM_PAGE_SIZE,
+};
+
+/// A snapshot of the state an instance defines, taken once it was initialized: the contents of
+/// its memories, tables and globals, and the passive segments it didn't drop.
+///
+/// The instances of the same module created with the same imports can start from the image
+/// instead of running their initializers, see [`InstanceHandle::finish_instantiation_from_image`].
+/// The references the image holds are to the functions of the instance, by their index, so the
+/// state of an instance holding references to the functions of other instances, or to host
+/// data, can't be captured.
+#[derive(Debug)]
+pub struct InstancePreImage {
+    memories: PrimaryMap<LocalMemoryIndex, MemoryImage>,
+    state: InstanceState,
+}
+
+/// The state of an instance besides the contents of its memories, in an [`InstancePreImage`] or
+/// an [`InstanceSnapshot`](super::InstanceSnapshot).
+#[derive(Debug, Clone, PartialEq, Eq, rkyv::Serialize, rkyv::Deserialize, rkyv::Archive)]
+pub struct InstanceState {
+    /// The elements of the tables, `None` for the null references.
+    tables: PrimaryMap<LocalTableIndex, Vec<Option<FunctionIndex>>>,
+    globals: PrimaryMap<LocalGlobalIndex, GlobalImage>,
+    /// The passive element segments left, the others being dropped.
+    passive_elements: Vec<ElemIndex>,
+    /// The passive data segments left, the others being dropped.
+    passive_data: Vec<DataIndex>,
+}
+
+/// The value of a global in an [`InstanceState`].
+#[derive(Debug, Clone, PartialEq, Eq, rkyv::Serialize, rkyv::Deserialize, rkyv::Archive)]
+pub enum GlobalImage {
+    /// The value of a numeric global, or a null `externref`.
+    Bytes([u8; 16]),
+    /// A `funcref`, `None` for the null reference.
+    FuncRef(Option<FunctionIndex>),
+}
+
+impl InstancePreImage {
+    /// The images of the memories the instance defines.
+    pub fn memories(&self) -> &PrimaryMap<LocalMemoryIndex, MemoryImage> {
+        &self.memories
+    }
+}
+
+impl Instance {
+    /// The index of the function `funcref` refers to, if it's one of this instance's.
+    pub(super) fn funcref_index(
+        &self,
+        funcref: VMFuncRef,
+    ) -> Result<Option<FunctionIndex>, String> {
+        if funcref.is_null() {
+            return Ok(None);
+        }
+        let funcrefs = self.funcrefs.values().as_slice();
+        let offset = (funcref.0 as usize).wrapping_sub(funcrefs.as_ptr() as usize);
+        let index = offset / mem::size_of::<VMCallerCheckedAnyfunc>();
+        if offset % mem::size_of::<VMCallerCheckedAnyfunc>() != 0 || index >= funcrefs.len() {
+            return Err("a reference to a function of another instance".to_string());
+        }
+        Ok(Some(FunctionIndex::new(index)))
+    }
+
+    fn capture_image(&self) -> Result<InstancePreImage, String> {
+        let memories = self
+            .memories
+            .values()
+            .map(|memory| {
+                let mut image = None;
+                memory.with_definition(&mut |definition| {
+                    let data = unsafe {
+                        std::slice::from_raw_parts(definition.base, definition.current_length)
+                    };
+                    let size =
+                        Pages(u32::try_from(definition.current_length / WASM_PAGE_SIZE).unwrap());
+                    image = Some(MemoryImage::new(data, size));
+                });
+                image.unwrap()
+            })
+            .collect();
+        Ok(InstancePreImage {
+            memories,
+            state: self.capture_state()?,
+        })
+    }
+
+    /// Capture the state of the instance besides the contents of its memories.
+    pub(super) fn capture_state(&self) -> Result<InstanceState, String> {
+        let mut tables = PrimaryMap::with_capacity(self.tables.len());
+        for table in self.tables.values() {
+            let elements = (0..table.size())
+                .map(|index| match table.get(index).unwrap() {
+                    TableElement::FuncRef(funcref) => self.funcref_index(funcref),
+                    TableElement::ExternRef(externref) if externref.is_null() => Ok(None),
+                    TableElement::ExternRef(_) => Err("a non-null externref".to_string()),
+                })
+                .collect::<Result<_, _>>()
+                .map_err(|e| format!("a table holds {}", e))?;
+            tables.push(elements);
+        }
+
+        let mut globals = PrimaryMap::with_capacity(self.globals.len());
+        for (index, global) in self.globals.iter() {
+            let definition = unsafe { self.global_ptr(index).as_ref() };
+            globals.push(match global.ty().ty {
+                Type::FuncRef => GlobalImage::FuncRef(
+                    self.funcref_index(definition.to_funcref())
+                        .map_err(|e| format!("a global holds {}", e))?,
+                ),
+                Type::ExternRef if !definition.to_externref().is_null() => {
+                    return Err("a global holds a non-null externref".to_string());
+                }
+                _ => GlobalImage::Bytes(definition.to_bytes()),
+            });
+        }
+
+        Ok(InstanceState {
+            tables,
+            globals,
+            passive_elements: self.passive_elements.borrow().keys().copied().collect(),
+            passive_data: self.passive_data.borrow().iter().copied().collect(),
+        })
+    }
+
+    fn apply_image(&self, image: &InstancePreImage) -> Result<(), String> {
+        assert_eq!(image.memories.len(), self.memories.len());
+        self.apply_state(&image.state)?;
+        for (index, memory_image) in image.memories.iter() {
+            let memory = &self.memories[index];
+            let size = memory.size();
+            if size < memory_image.size() {
+                self.grow_with_callback(&**memory, memory_image.size() - size)
+                    .map_err(|e| e.to_string())?;
+            }
+            memory
+                .apply_image(memory_image)
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// Restore the state of the instance besides the contents of its memories.
+    ///
+    /// The state may not come from an instance of the same module, so it is checked against
+    /// the definitions of the instance first.
+    pub(super) fn apply_state(&self, state: &InstanceState) -> Result<(), String> {
+        if state.tables.len() != self.tables.len() || state.globals.len() != self.globals.len() {
+            return Err(format!(
+                "the state has {} tables and {} globals, the instance {} and {}",
+                state.tables.len(),
+                state.globals.len(),
+                self.tables.len(),
+                self.globals.len()
+            ));
+        }
+        let functions = self.funcrefs.len();
+        let check_function = |function: &Option<FunctionIndex>| match function {
+            Some(function) if function.index() >= functions => Err(format!(
+                "the function {} is out of the {} functions of the instance",
+                function.index(),
+                functions
+            )),
+            _ => Ok(()),
+        };
+        for (index, value) in state.globals.iter() {
+            match (value, self.globals[index].ty().ty) {
+                (GlobalImage::FuncRef(function), Type::FuncRef) => check_function(function)?,
+                (GlobalImage::Bytes(_), ty) if ty != Type::FuncRef => {}
+                (_, ty) => {
+                    return Err(format!(
+                        "the global {} of type {} has a value of another type",
+                        index.index(),
+                        ty
+                    ))
+                }
+            }
+        }
+        for elements in state.tables.values() {
+            elements.iter().try_for_each(&check_function)?;
+        }
+
+        for (index, value) in state.globals.iter() {
+            let definition = unsafe { self.global_ptr(index).as_mut() };
+            match value {
+                GlobalImage::Bytes(bytes) => unsafe { *definition.as_bytes_mut() = *bytes },
+                GlobalImage::FuncRef(function) => unsafe {
+                    *definition.as_funcref_mut() = function
+                        .map_or_else(VMFuncRef::null, |function| self.get_vm_funcref(function));
+                },
+            }
+        }
+
+        for (index, elements) in state.tables.iter() {
+            let table = &self.tables[index];
+            let size = table.size() as usize;
+            if size < elements.len() {
+                let delta = u32::try_from(elements.len() - size).unwrap();
+                table
+                    .grow(delta, TableElement::FuncRef(VMFuncRef::null()))
+                    .ok_or_else(|| format!("the table {} could not grow", index.index()))?;
+            }
+            for (element, function) in elements.iter().enumerate() {
+                if let Some(function) = function {
+                    let funcref = self.get_vm_funcref(*function);
+                    table
+                        .set(element as u32, TableElement::FuncRef(funcref))
+                        .map_err(|_| format!("the table {} could not be set", index.index()))?;
+                }
+            }
+        }
+
+        self.passive_elements
+            .borrow_mut()
+            .retain(|index, _| state.passive_elements.contains(index));
+        self.passive_data
+            .borrow_mut()
+            .retain(|index| state.passive_data.contains(index));
+        Ok(())
+    }
+}
+
+impl InstanceHandle {
+    /// Capture the state of the instance, for instances of the same module to start from it.
+    ///
+    /// Fails if the instance holds references which can't be captured, see
+    /// [`InstancePreImage`].
+    pub fn capture_image(&self) -> Result<InstancePreImage, String> {
+        self.instance().as_ref().capture_image()
+    }
+
+    /// Finishes the instantiation process started by `Instance::new`, restoring the state
+    /// captured in `image` instead of running the initializers of the module and its start
+    /// function.
+    ///
+    /// # Safety
+    ///
+    /// Only safe to call immediately after instantiation, with the image of an instance of the
+    /// same module, created with the same imports.
+    pub unsafe fn finish_instantiation_from_image(
+        &self,
+        image: &InstancePreImage,
+    ) -> Result<(), String> {
+        self.instance().as_ref().apply_image(image)
+    }
+}
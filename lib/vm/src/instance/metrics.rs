@@ -0,0 +1,51 @@
+//! Counters of what an instance did, for the instances created with
+//! [`InstanceConfig::with_metrics`](wasmer_types::InstanceConfig::with_metrics).
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A sample of the metrics of an instance.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct InstanceMetrics {
+    /// The largest total size, in pages, of the memories of the instance, defined and
+    /// imported ones alike, when it was created and after each `memory.grow` it executed.
+    pub peak_memory_pages: u64,
+    /// The number of `memory.grow` the instance executed, the failed ones included.
+    pub memory_grows: u64,
+    /// The number of calls into the instance from the host: the calls of its functions
+    /// through the API, and the call of its start function.
+    pub host_calls: u64,
+}
+
+/// The counters the metrics of an instance are sampled from. They are relaxed atomics, so
+/// that they can be sampled from another thread while the instance runs.
+#[derive(Debug, Default)]
+pub(crate) struct MetricsCounters {
+    peak_memory_pages: AtomicU64,
+    memory_grows: AtomicU64,
+    host_calls: AtomicU64,
+}
+
+impl MetricsCounters {
+    /// Record that the memories of the instance are `pages` in total.
+    pub(crate) fn record_memory_pages(&self, pages: u64) {
+        self.peak_memory_pages.fetch_max(pages, Ordering::Relaxed);
+    }
+
+    /// Record that the instance executed a `memory.grow`.
+    pub(crate) fn record_memory_grow(&self) {
+        self.memory_grows.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a call into the instance from the host.
+    pub(crate) fn record_host_call(&self) {
+        self.host_calls.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn sample(&self) -> InstanceMetrics {
+        InstanceMetrics {
+            peak_memory_pages: self.peak_memory_pages.load(Ordering::Relaxed),
+            memory_grows: self.memory_grows.load(Ordering::Relaxed),
+            host_calls: self.host_calls.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -8,43 +8,57 @@
 //! wrapper around an `InstanceRef`.
 
 mod allocator;
+pub(crate) mod entry;
+mod image;
+mod metrics;
 mod r#ref;
+mod snapshot;
 
 pub use allocator::InstanceAllocator;
+pub use entry::InstanceEntry;
+pub use image::InstancePreImage;
+pub use metrics::InstanceMetrics;
 pub use r#ref::{InstanceRef, WeakInstanceRef, WeakOrStrongInstanceRef};
+pub use snapshot::InstanceSnapshot;
 
+use crate::epoch::{EpochDeadlineAction, EpochDeadlineCallback, NO_EPOCH};
 use crate::func_data_registry::VMFuncRef;
 use crate::global::Global;
 use crate::imports::Imports;
-use crate::memory::{Memory, MemoryError};
+use crate::instance::entry::EntryLock;
+use crate::instance::metrics::MetricsCounters;
+use crate::limiter::ResourceLimiter;
+use crate::memory::{Memory, MemoryError, MemoryGrow, MemoryGrowCallback};
 use crate::sig_registry::VMSharedSignatureIndex;
 use crate::table::{Table, TableElement};
 use crate::trap::traphandlers::get_trap_handler;
-use crate::trap::{catch_traps, Trap, TrapCode};
+use crate::trap::{catch_traps, defer_externref_drop, Trap, TrapCode};
 use crate::vmcontext::{
     VMBuiltinFunctionsArray, VMCallerCheckedAnyfunc, VMContext, VMFunctionBody,
     VMFunctionEnvironment, VMFunctionImport, VMFunctionKind, VMGlobalDefinition, VMGlobalImport,
     VMLocalFunction, VMMemoryDefinition, VMMemoryImport, VMTableDefinition, VMTableImport,
 };
-use crate::{wasmer_call_trampoline, Artifact, VMOffsets, VMTrampoline};
-use crate::{VMExtern, VMFunction, VMGlobal};
+use crate::{wasmer_call_trampoline, Artifact, VMExternRef, VMOffsets, VMTrampoline};
+use crate::{VMExtern, VMFunction, VMGlobal, VMTag};
 use memoffset::offset_of;
 use more_asserts::assert_lt;
 use std::any::Any;
 use std::cell::RefCell;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::convert::TryFrom;
 use std::ffi;
 use std::fmt;
 use std::mem;
 use std::ptr::{self, NonNull};
 use std::slice;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use wasmer_types::entity::{packed_option::ReservedValue, BoxedSlice, EntityRef, PrimaryMap};
 use wasmer_types::{
-    DataIndex, DataInitializer, ElemIndex, ExportIndex, FastGasCounter, FunctionIndex, GlobalIndex,
-    GlobalInit, InstanceConfig, LocalGlobalIndex, LocalMemoryIndex, LocalTableIndex, MemoryIndex,
-    OwnedTableInitializer, Pages, TableIndex,
+    ConstExpr, DataIndex, DataInitializer, ElemIndex, ExportIndex, FastGasCounter, FunctionIndex,
+    GlobalIndex, GlobalInit, InstanceConfig, LocalGlobalIndex, LocalMemoryIndex, LocalTableIndex,
+    LocalTagIndex, MemoryIndex, MemoryType, OwnedTableInitializer, Pages, TableIndex, TagIndex,
+    Type,
 };
 
 /// The function pointer to call with data and an [`Instance`] pointer to
@@ -52,6 +66,17 @@ use wasmer_types::{
 pub type ImportInitializerFuncPtr<ResultErr = *mut ffi::c_void> =
     fn(*mut ffi::c_void, *const ffi::c_void) -> Result<(), ResultErr>;
 
+/// A unique identifier of an instance, for telling instances apart in callbacks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct InstanceId(u64);
+
+impl InstanceId {
+    fn next() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        Self(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
 /// A WebAssembly instance.
 ///
 /// The type is dynamically-sized. Indeed, the `vmctx` field can
@@ -62,9 +87,34 @@ pub type ImportInitializerFuncPtr<ResultErr = *mut ffi::c_void> =
 pub(crate) struct Instance {
     pub(crate) artifact: Arc<dyn Artifact>,
 
+    /// The identifier of this instance.
+    id: InstanceId,
+
+    /// Whether the instance was closed, see [`InstanceHandle::close`].
+    closed: AtomicBool,
+
+    /// Whether an async call into the instance is suspended, see
+    /// [`WeakOrStrongInstanceRef::set_suspended`].
+    suspended: AtomicBool,
+
+    /// The thread running in the instance, see [`WeakOrStrongInstanceRef::enter`].
+    entry_lock: EntryLock,
+
     /// External configuration for instance.
     config: InstanceConfig,
 
+    /// The callback to call before the memories grow, if any.
+    memory_grow_callback: Option<Arc<MemoryGrowCallback>>,
+
+    /// The limiter that allowed creating this instance, told when it is dropped.
+    resource_limiter: Option<Arc<dyn ResourceLimiter>>,
+
+    /// The callback to call when the epoch deadline is reached, if any.
+    epoch_deadline_callback: Mutex<Option<Arc<EpochDeadlineCallback>>>,
+
+    /// The counters of the metrics of the instance, if it collects them.
+    metrics: Option<MetricsCounters>,
+
     /// WebAssembly linear memory data.
     memories: BoxedSlice<LocalMemoryIndex, Arc<dyn Memory>>,
 
@@ -74,13 +124,16 @@ pub(crate) struct Instance {
     /// WebAssembly global data.
     globals: BoxedSlice<LocalGlobalIndex, Arc<Global>>,
 
+    /// The exception tags defined by the module, new for each instance.
+    tags: BoxedSlice<LocalTagIndex, VMTag>,
+
     /// Passive elements in this instantiation. As `elem.drop`s happen, these
     /// entries get removed.
     passive_elements: RefCell<BTreeMap<ElemIndex, Box<[VMFuncRef]>>>,
 
-    /// Passive data segments from our module. As `data.drop`s happen, entries
-    /// get removed. A missing entry is considered equivalent to an empty slice.
-    passive_data: RefCell<BTreeMap<DataIndex, Arc<[u8]>>>,
+    /// The passive data segments of the artifact not dropped yet. As `data.drop`s happen,
+    /// entries get removed. A missing entry is considered equivalent to an empty slice.
+    passive_data: RefCell<BTreeSet<DataIndex>>,
 
     /// Mapping of function indices to their func ref backing data. `VMFuncRef`s
     /// will point to elements here for functions defined or imported by this
@@ -97,6 +150,11 @@ pub(crate) struct Instance {
     /// functions from other Wasm modules.
     imported_function_envs: BoxedSlice<FunctionIndex, ImportFunctionEnv>,
 
+    /// The resolved imports. The `VMContext` only holds copies of them, this owns what the
+    /// imports refer to: the imported memories, tables and globals, and the instances of
+    /// the imported functions.
+    imports: Imports,
+
     /// Additional context used by compiled WebAssembly code. This
     /// field is last, and represents a dynamically-sized array that
     /// extends beyond the nominal end of the struct (similar to a
@@ -182,8 +240,32 @@ impl fmt::Debug for Instance {
     }
 }
 
+impl Drop for Instance {
+    fn drop(&mut self) {
+        if let Some(limiter) = &self.resource_limiter {
+            limiter.instance_dropped();
+        }
+    }
+}
+
 #[allow(clippy::cast_ptr_alignment)]
 impl Instance {
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    fn is_suspended(&self) -> bool {
+        self.suspended.load(Ordering::SeqCst)
+    }
+
+    fn set_suspended(&self, suspended: bool) {
+        self.suspended.store(suspended, Ordering::SeqCst)
+    }
+
+    fn entry_lock(&self) -> &EntryLock {
+        &self.entry_lock
+    }
+
     /// Helper function to access various locations offset from our `*mut
     /// VMContext` object.
     unsafe fn vmctx_plus_offset<T>(&self, offset: u32) -> *mut T {
@@ -279,6 +361,14 @@ impl Instance {
         }
     }
 
+    /// Return the type of the indexed memory, local or imported.
+    fn memory_type(&self, index: MemoryIndex) -> MemoryType {
+        match self.artifact.import_counts().local_memory_index(index) {
+            Ok(local) => self.memories[local].ty(),
+            Err(import) => self.imported_memory(import).from.ty(),
+        }
+    }
+
     #[allow(dead_code)]
     /// Set the indexed memory to `VMMemoryDefinition`.
     fn set_memory(&self, index: LocalMemoryIndex, mem: &VMMemoryDefinition) {
@@ -306,6 +396,14 @@ impl Instance {
         }
     }
 
+    /// Return the indexed tag, imported or defined by the module.
+    pub(crate) fn tag_by_index(&self, index: TagIndex) -> Option<VMTag> {
+        match self.artifact.import_counts().local_tag_index(index) {
+            Ok(local) => self.tags.get(local).cloned(),
+            Err(import) => self.imports.tags.get(import).cloned(),
+        }
+    }
+
     /// Set the indexed global to `VMGlobalDefinition`.
     #[allow(dead_code)]
     fn set_global(&self, index: LocalGlobalIndex, global: &VMGlobalDefinition) {
@@ -367,13 +465,51 @@ impl Instance {
         unsafe { self.vmctx_plus_offset(self.offsets().vmctx_stack_limit_begin()) }
     }
 
+    /// Return a pointer to the native stack limit.
+    fn native_stack_limit_ptr(&self) -> *mut usize {
+        unsafe { self.vmctx_plus_offset(self.offsets().vmctx_native_stack_limit()) }
+    }
+
+    /// Return a pointer to the fuel left.
+    fn fuel_ptr(&self) -> *mut u64 {
+        unsafe { self.vmctx_plus_offset(self.offsets().vmctx_fuel()) }
+    }
+
+    /// Return a pointer to the pointer to the epoch counter.
+    fn epoch_pointer_ptr(&self) -> *mut *const AtomicU64 {
+        unsafe { self.vmctx_plus_offset(self.offsets().vmctx_epoch_pointer()) }
+    }
+
+    /// Return a pointer to the epoch deadline.
+    fn epoch_deadline_ptr(&self) -> *mut u64 {
+        unsafe { self.vmctx_plus_offset(self.offsets().vmctx_epoch_deadline()) }
+    }
+
+    /// Called when code compiled with epoch interruption reaches the epoch deadline: let the
+    /// callback set a new deadline, or trap.
+    pub(crate) fn epoch_deadline_reached(&self) -> Result<(), Trap> {
+        let epoch = unsafe { (**self.epoch_pointer_ptr()).load(Ordering::SeqCst) };
+        // Don't hold the lock while the callback runs, it may replace itself.
+        let callback = self.epoch_deadline_callback.lock().unwrap().clone();
+        match callback.map(|callback| callback(epoch)) {
+            Some(EpochDeadlineAction::Continue(deadline)) => {
+                unsafe { *self.epoch_deadline_ptr() = deadline };
+                Ok(())
+            }
+            Some(EpochDeadlineAction::Trap) | None => {
+                Err(Trap::lib(TrapCode::EpochDeadlineExceeded))
+            }
+        }
+    }
+
     /// Invoke the WebAssembly start function of the instance, if one is present.
-    fn invoke_start_function(&self) -> Result<(), Trap> {
+    pub(crate) fn invoke_start_function(&self) -> Result<(), Trap> {
         let start_index = match self.artifact.start_function() {
             Some(idx) => idx,
             None => return Ok(()),
         };
         let start_funcref = self.funcrefs[start_index];
+        self.record_host_call();
         // Make the call.
         self.reset_stack_meter();
         let result = unsafe {
@@ -438,7 +574,7 @@ impl Instance {
             .memories
             .get(memory_index)
             .unwrap_or_else(|| panic!("no memory for index {}", memory_index.index()));
-        mem.grow(delta.into())
+        self.grow_with_callback(&**mem, delta.into())
     }
 
     /// Grow imported memory by the specified amount of pages.
@@ -458,7 +594,57 @@ impl Instance {
         IntoPages: Into<Pages>,
     {
         let import = self.imported_memory(memory_index);
-        import.from.grow(delta.into())
+        self.grow_with_callback(&*import.from, delta.into())
+    }
+
+    /// Grow a memory as this instance, first calling the memory grow callback if there is one.
+    fn grow_with_callback(&self, memory: &dyn Memory, delta: Pages) -> Result<Pages, MemoryError> {
+        let result = self.grow_with_callback_unrecorded(memory, delta);
+        if let Some(metrics) = &self.metrics {
+            metrics.record_memory_grow();
+            self.record_memory_pages();
+        }
+        result
+    }
+
+    fn grow_with_callback_unrecorded(
+        &self,
+        memory: &dyn Memory,
+        delta: Pages,
+    ) -> Result<Pages, MemoryError> {
+        match &self.memory_grow_callback {
+            Some(callback) => memory.grow_checked(delta, &mut |previous, new, result| {
+                callback(&MemoryGrow {
+                    instance: Some(self.id),
+                    previous,
+                    new,
+                    result,
+                })
+            }),
+            None => memory.grow(delta),
+        }
+    }
+
+    /// Record the total size of the memories of the instance in its metrics, if it collects
+    /// them.
+    fn record_memory_pages(&self) {
+        if let Some(metrics) = &self.metrics {
+            let local = self.memories.values().map(|memory| memory.size());
+            let imported = self
+                .imports
+                .memories
+                .values()
+                .map(|import| import.from.size());
+            let pages = local.chain(imported).map(|pages| u64::from(pages.0)).sum();
+            metrics.record_memory_pages(pages);
+        }
+    }
+
+    /// Record a call into the instance from the host in its metrics, if it collects them.
+    pub(crate) fn record_host_call(&self) {
+        if let Some(metrics) = &self.metrics {
+            metrics.record_host_call();
+        }
     }
 
     /// Returns the number of allocated wasm pages.
@@ -549,6 +735,16 @@ impl Instance {
         import.from.get(index)
     }
 
+    /// The `global.set` operation on a global of `externref`s, which holds a count of the
+    /// reference it is set to.
+    pub(crate) fn externref_global_set(&self, global_index: GlobalIndex, value: VMExternRef) {
+        let definition =
+            self.global(global_index) as *const VMGlobalDefinition as *mut VMGlobalDefinition;
+        // The Wasm code may still use the reference the global held.
+        let old = unsafe { mem::replace((*definition).as_externref_mut(), value.ref_clone()) };
+        defer_externref_drop(old.into());
+    }
+
     /// Set table element by index.
     pub(crate) fn table_set(
         &self,
@@ -755,8 +951,11 @@ impl Instance {
         // https://webassembly.github.io/bulk-memory-operations/core/exec/instructions.html#exec-memory-init
 
         let memory = self.memory_definition(memory_index);
-        let passive_data = self.passive_data.borrow();
-        let data = passive_data.get(&data_index).map_or(&[][..], |d| &**d);
+        let data = match self.passive_data.borrow().contains(&data_index) {
+            true => self.artifact.passive_data().get(&data_index),
+            false => None,
+        };
+        let data = data.map_or(&[][..], |d| &**d);
 
         let oob_access = src
             .checked_add(len)
@@ -845,18 +1044,20 @@ impl InstanceHandle {
         finished_memories: BoxedSlice<LocalMemoryIndex, Arc<dyn Memory>>,
         finished_tables: BoxedSlice<LocalTableIndex, Arc<dyn Table>>,
         finished_globals: BoxedSlice<LocalGlobalIndex, Arc<Global>>,
+        finished_tags: BoxedSlice<LocalTagIndex, VMTag>,
         imports: Imports,
-        passive_data: BTreeMap<DataIndex, Arc<[u8]>>,
         host_state: Box<dyn Any>,
         imported_function_envs: BoxedSlice<FunctionIndex, ImportFunctionEnv>,
         instance_config: InstanceConfig,
+        memory_grow_callback: Option<Arc<MemoryGrowCallback>>,
+        resource_limiter: Option<Arc<dyn ResourceLimiter>>,
     ) -> Self {
         let vmctx_globals = finished_globals
             .values()
             .map(|m| m.vmglobal())
             .collect::<PrimaryMap<LocalGlobalIndex, _>>()
             .into_boxed_slice();
-        let passive_data = RefCell::new(passive_data);
+        let passive_data = RefCell::new(artifact.passive_data().keys().copied().collect());
 
         let handle = {
             // use dummy value to create an instance so we can get the vmctx pointer
@@ -864,15 +1065,29 @@ impl InstanceHandle {
             // Create the `Instance`. The unique, the One.
             let instance = Instance {
                 artifact,
+                id: InstanceId::next(),
+                closed: AtomicBool::new(false),
+                suspended: AtomicBool::new(false),
+                entry_lock: EntryLock::default(),
                 config: instance_config.clone(),
+                memory_grow_callback,
+                resource_limiter,
+                epoch_deadline_callback: Mutex::new(None),
+                metrics: if instance_config.metrics() {
+                    Some(MetricsCounters::default())
+                } else {
+                    None
+                },
                 memories: finished_memories,
                 tables: finished_tables,
                 globals: finished_globals,
+                tags: finished_tags,
                 passive_elements: Default::default(),
                 passive_data,
                 host_state,
                 funcrefs,
                 imported_function_envs,
+                imports,
                 vmctx: VMContext {},
             };
 
@@ -883,7 +1098,7 @@ impl InstanceHandle {
                 let instance = instance_ref.as_mut().unwrap();
                 let vmctx_ptr = instance.vmctx_ptr();
                 instance.funcrefs = build_funcrefs(
-                    &imports,
+                    &instance.imports,
                     instance.artifact.functions().iter().map(|(_, f)| f),
                     vmctx_ptr,
                 );
@@ -891,6 +1106,13 @@ impl InstanceHandle {
                 *(instance.gas_counter_ptr()) = instance_config.gas_counter;
                 *(instance.stack_limit_ptr()) = instance_config.stack_limit;
                 *(instance.stack_limit_initial_ptr()) = instance_config.stack_limit;
+                *(instance.native_stack_limit_ptr()) = 0;
+                *(instance.fuel_ptr()) = u64::MAX;
+                *(instance.epoch_pointer_ptr()) = instance_config
+                    .epoch_counter()
+                    .map_or(&NO_EPOCH, |counter| &**counter)
+                    as *const AtomicU64;
+                *(instance.epoch_deadline_ptr()) = u64::MAX;
             }
 
             Self {
@@ -906,24 +1128,24 @@ impl InstanceHandle {
         );
 
         ptr::copy(
-            imports.functions.values().as_slice().as_ptr(),
+            instance.imports.functions.values().as_slice().as_ptr(),
             instance.imported_functions_ptr() as *mut VMFunctionImport,
-            imports.functions.len(),
+            instance.imports.functions.len(),
         );
         ptr::copy(
-            imports.tables.values().as_slice().as_ptr(),
+            instance.imports.tables.values().as_slice().as_ptr(),
             instance.imported_tables_ptr() as *mut VMTableImport,
-            imports.tables.len(),
+            instance.imports.tables.len(),
         );
         ptr::copy(
-            imports.memories.values().as_slice().as_ptr(),
+            instance.imports.memories.values().as_slice().as_ptr(),
             instance.imported_memories_ptr() as *mut VMMemoryImport,
-            imports.memories.len(),
+            instance.imports.memories.len(),
         );
         ptr::copy(
-            imports.globals.values().as_slice().as_ptr(),
+            instance.imports.globals.values().as_slice().as_ptr(),
             instance.imported_globals_ptr() as *mut VMGlobalImport,
-            imports.globals.len(),
+            instance.imports.globals.len(),
         );
         // these should already be set, add asserts here? for:
         // - instance.tables_ptr() as *mut VMTableDefinition
@@ -933,6 +1155,13 @@ impl InstanceHandle {
             instance.globals_ptr() as *mut NonNull<VMGlobalDefinition>,
             vmctx_globals.len(),
         );
+        // The globals only the compiled code can access live in their cell in the `VMContext`
+        // instead of their `Global`, which is left unused.
+        for index in vmctx_globals.keys() {
+            if let Some(offset) = instance.offsets().vmctx_vmglobal_cell(index) {
+                *instance.globals_ptr().add(index.index()) = instance.vmctx_plus_offset(offset);
+            }
+        }
         ptr::write(
             instance.builtin_functions_ptr() as *mut VMBuiltinFunctionsArray,
             VMBuiltinFunctionsArray::initialized(),
@@ -942,6 +1171,19 @@ impl InstanceHandle {
         // initialization is deferred to the `initialize` method.
         initialize_passive_elements(instance);
         initialize_globals(instance);
+        instance.record_memory_pages();
+        // The grows the callback or the metrics must see can't be done by compiled code.
+        if instance.memory_grow_callback.is_some() || instance.metrics.is_some() {
+            let local = instance.memories.values().map(|memory| &**memory);
+            let imported = instance
+                .imports
+                .memories
+                .values()
+                .map(|import| &*import.from);
+            for memory in local.chain(imported) {
+                memory.disable_inline_grows();
+            }
+        }
         handle
     }
 
@@ -950,27 +1192,35 @@ impl InstanceHandle {
         &self.instance
     }
 
-    /// Finishes the instantiation process started by `Instance::new`.
+    /// Finishes the instantiation process started by `Instance::new`, applying the table and
+    /// memory initializers.
+    ///
+    /// The start function is invoked separately, with
+    /// [`InstanceRef::invoke_start_function`], once the host environments of the imports are
+    /// initialized.
     ///
     /// # Safety
     ///
     /// Only safe to call immediately after instantiation.
     pub unsafe fn finish_instantiation(&self) -> Result<(), Trap> {
         let instance = self.instance().as_ref();
-
-        // Apply the initializers.
         initialize_tables(instance)?;
-        initialize_memories(
-            instance,
-            instance.artifact.data_segments().iter().map(Into::into),
-        )?;
-
-        // The WebAssembly spec specifies that the start function is
-        // invoked automatically at instantiation time.
-        instance.invoke_start_function()?;
+        initialize_memories(instance, instance.artifact.data_segments())?;
         Ok(())
     }
 
+    /// Finishes the instantiation process started by `Instance::new` like
+    /// [`finish_instantiation`](Self::finish_instantiation), but only applying the table
+    /// initializers, the contents of the memories being kept as they are.
+    ///
+    /// # Safety
+    ///
+    /// Only safe to call immediately after instantiation, once the memories hold the contents
+    /// the instance starts with.
+    pub unsafe fn finish_instantiation_without_data(&self) -> Result<(), Trap> {
+        initialize_tables(self.instance().as_ref())
+    }
+
     /// See [`traphandlers::wasmer_call_trampoline`].
     pub unsafe fn invoke_function(
         &self,
@@ -1075,7 +1325,11 @@ impl InstanceHandle {
     pub fn global_by_index(&self, index: GlobalIndex) -> Option<VMGlobal> {
         let instance = self.instance.as_ref();
         let from = match instance.artifact.import_counts().local_global_index(index) {
-            Ok(local) => Arc::clone(&instance.globals[local]),
+            Ok(local) => {
+                // The `Global` of a global living in the `VMContext` doesn't hold its value.
+                debug_assert!(instance.offsets().vmctx_vmglobal_cell(local).is_none());
+                Arc::clone(&instance.globals[local])
+            }
             Err(import) => Arc::clone(&instance.imported_global(import).from),
         };
         Some(crate::VMGlobal {
@@ -1084,6 +1338,11 @@ impl InstanceHandle {
         })
     }
 
+    /// Return the tag with the given index, imported or defined by the module.
+    pub fn tag_by_index(&self, index: TagIndex) -> Option<VMTag> {
+        self.instance.as_ref().tag_by_index(index)
+    }
+
     /// Lookup an exported function with the given name.
     pub fn lookup(&self, field: &str) -> Option<VMExtern> {
         let instance = self.instance.as_ref();
@@ -1092,6 +1351,7 @@ impl InstanceHandle {
             ExportIndex::Table(idx) => VMExtern::Table(self.table_by_index(idx)?),
             ExportIndex::Global(idx) => VMExtern::Global(self.global_by_index(idx)?),
             ExportIndex::Memory(idx) => VMExtern::Memory(self.memory_by_index(idx)?),
+            ExportIndex::Tag(idx) => VMExtern::Tag(self.tag_by_index(idx)?),
         })
     }
 
@@ -1100,6 +1360,82 @@ impl InstanceHandle {
         self.instance().as_ref().host_state()
     }
 
+    /// Return the identifier of this instance.
+    pub fn id(&self) -> InstanceId {
+        self.instance().as_ref().id
+    }
+
+    /// Mark the instance as closed, so that the API refuses to call its functions from the
+    /// host. The instance is still only freed once the last strong reference to it is
+    /// dropped.
+    pub fn close(&self) {
+        self.instance()
+            .as_ref()
+            .closed
+            .store(true, Ordering::SeqCst);
+    }
+
+    /// Whether [`InstanceHandle::close`] was called on a handle to this instance.
+    pub fn is_closed(&self) -> bool {
+        self.instance().as_ref().is_closed()
+    }
+
+    /// Set the lowest address the native stack pointer may reach while running code compiled
+    /// with stack limit checks, or `0` to disable the limit.
+    pub fn set_native_stack_limit(&self, limit: usize) {
+        unsafe {
+            *(self.instance().as_ref().native_stack_limit_ptr()) = limit;
+        }
+    }
+
+    /// Return the fuel left to the code compiled with metering.
+    pub fn fuel_remaining(&self) -> u64 {
+        self.instance().fuel_remaining()
+    }
+
+    /// Set the fuel left to the code compiled with metering.
+    pub fn set_fuel(&self, fuel: u64) {
+        self.instance().set_fuel(fuel)
+    }
+
+    /// Return the epoch at which code compiled with epoch interruption stops.
+    pub fn epoch_deadline(&self) -> u64 {
+        unsafe { *self.instance().as_ref().epoch_deadline_ptr() }
+    }
+
+    /// Set the epoch at which code compiled with epoch interruption stops.
+    pub fn set_epoch_deadline(&self, deadline: u64) {
+        unsafe { *self.instance().as_ref().epoch_deadline_ptr() = deadline }
+    }
+
+    /// Set the callback deciding whether the code compiled with epoch interruption keeps
+    /// running when it reaches the epoch deadline, or `None` to trap.
+    pub fn set_epoch_deadline_callback(&self, callback: Option<Arc<EpochDeadlineCallback>>) {
+        *self
+            .instance()
+            .as_ref()
+            .epoch_deadline_callback
+            .lock()
+            .unwrap() = callback;
+    }
+
+    /// Sample the metrics of the instance, if it was created with
+    /// [`InstanceConfig::with_metrics`].
+    pub fn metrics(&self) -> Option<InstanceMetrics> {
+        self.instance()
+            .as_ref()
+            .metrics
+            .as_ref()
+            .map(MetricsCounters::sample)
+    }
+
+    /// Get a weak reference to the instance.
+    pub fn downgrade(&self) -> WeakInstanceRef {
+        WeakOrStrongInstanceRef::Strong(self.instance().clone())
+            .downgrade()
+            .into()
+    }
+
     /// Return the memory index for the given `VMMemoryDefinition` in this instance.
     pub fn memory_index(&self, memory: &VMMemoryDefinition) -> LocalMemoryIndex {
         self.instance().as_ref().memory_index(memory)
@@ -1205,11 +1541,23 @@ pub unsafe fn initialize_host_envs<Err: Sized>(
 }
 
 /// Compute the offset for a memory data initializer.
+///
+/// The offsets of the segments of 64-bit memories are `i64`s.
 fn get_memory_init_start(init: &DataInitializer<'_>, instance: &Instance) -> usize {
+    let memory64 = instance.memory_type(init.location.memory_index).memory64;
+    if let Some(expr) = &init.location.extended_offset {
+        let start = eval_const_expr(expr, instance);
+        return if memory64 { start } else { start as u32 as u64 } as usize;
+    }
     let mut start = init.location.offset;
     if let Some(base) = init.location.base {
-        let val = instance.global(base).to_u32();
-        start += usize::try_from(val).unwrap();
+        let global = instance.global(base);
+        let val = if memory64 {
+            global.to_u64()
+        } else {
+            u64::from(global.to_u32())
+        };
+        start = start.saturating_add(usize::try_from(val).unwrap());
     }
     start
 }
@@ -1226,6 +1574,9 @@ unsafe fn get_memory_slice<'instance>(
 
 /// Compute the offset for a table element initializer.
 fn get_table_init_start(init: &OwnedTableInitializer, instance: &Instance) -> usize {
+    if let Some(expr) = &init.extended_offset {
+        return eval_const_expr(expr, instance) as u32 as usize;
+    }
     let mut start = init.offset;
     if let Some(base) = init.base {
         let val = instance.global(base).to_u32();
@@ -1234,6 +1585,11 @@ fn get_table_init_start(init: &OwnedTableInitializer, instance: &Instance) -> us
     start
 }
 
+/// Evaluate the extended constant expression `expr` with the globals of `instance`.
+fn eval_const_expr(expr: &ConstExpr, instance: &Instance) -> u64 {
+    expr.eval(|index| instance.global(index).to_u64())
+}
+
 /// Initialize the table memory from the provided initializers.
 fn initialize_tables(instance: &Instance) -> Result<(), Trap> {
     for init in instance.artifact.element_segments() {
@@ -1317,7 +1673,7 @@ fn initialize_memories<'a>(
 }
 
 fn initialize_globals(instance: &Instance) {
-    for (index, (_, initializer)) in instance.artifact.globals().iter().enumerate() {
+    for (index, (ty, initializer)) in instance.artifact.globals().iter().enumerate() {
         unsafe {
             let to = instance.global_ptr(LocalGlobalIndex::new(index)).as_ptr();
             match initializer {
@@ -1326,12 +1682,20 @@ fn initialize_globals(instance: &Instance) {
                 GlobalInit::F32Const(x) => *(*to).as_f32_mut() = *x,
                 GlobalInit::F64Const(x) => *(*to).as_f64_mut() = *x,
                 GlobalInit::V128Const(x) => *(*to).as_bytes_mut() = *x.bytes(),
-                GlobalInit::GetGlobal(x) => *to = instance.global(*x).clone(),
+                GlobalInit::GetGlobal(x) => {
+                    *to = instance.global(*x).clone();
+                    // Both globals hold a count of the reference.
+                    if ty.ty == Type::ExternRef {
+                        (*to).to_externref().ref_inc_by(1);
+                    }
+                }
                 GlobalInit::RefNullConst => *(*to).as_funcref_mut() = VMFuncRef::null(),
                 GlobalInit::RefFunc(func_idx) => {
                     let funcref = instance.func_ref(*func_idx).unwrap();
                     *(*to).as_funcref_mut() = funcref;
                 }
+                // An `i32` result is zero-extended.
+                GlobalInit::Expr(expr) => *(*to).as_u64_mut() = eval_const_expr(expr, instance),
             }
         }
     }
@@ -1,4 +1,5 @@
 use super::Instance;
+use crate::trap::{Trap, TrapCode};
 use std::alloc::Layout;
 use std::convert::TryFrom;
 use std::ptr::{self, NonNull};
@@ -144,6 +145,39 @@ impl InstanceRef {
         let ptr: *mut InstanceInner = Arc::as_ptr(&self.0) as *mut _;
         (&mut *ptr).as_mut()
     }
+
+    /// Return the fuel left to the code compiled with metering.
+    pub fn fuel_remaining(&self) -> u64 {
+        unsafe { *self.as_ref().fuel_ptr() }
+    }
+
+    /// Set the fuel left to the code compiled with metering.
+    pub fn set_fuel(&self, fuel: u64) {
+        unsafe { *self.as_ref().fuel_ptr() = fuel }
+    }
+
+    /// Invoke the start function of the instance, if it has one, as the WebAssembly spec
+    /// specifies at the end of instantiation.
+    ///
+    /// # Safety
+    ///
+    /// Only safe to call once, after [`InstanceHandle::finish_instantiation`] succeeded.
+    ///
+    /// [`InstanceHandle::finish_instantiation`]: super::InstanceHandle::finish_instantiation
+    pub unsafe fn invoke_start_function(&self) -> Result<(), Trap> {
+        self.as_ref().invoke_start_function()
+    }
+
+    /// Consume `amount` of fuel, failing without consuming any when less is left.
+    pub fn consume_fuel(&self, amount: u64) -> Result<(), Trap> {
+        let fuel = self.as_ref().fuel_ptr();
+        unsafe {
+            *fuel = (*fuel)
+                .checked_sub(amount)
+                .ok_or_else(|| Trap::lib(TrapCode::OutOfFuel))?;
+        }
+        Ok(())
+    }
 }
 
 /// A weak instance ref. This type does not keep the underlying `Instance` alive
@@ -195,6 +229,75 @@ impl WeakOrStrongInstanceRef {
             Self::Strong(strong) => Self::Weak(WeakInstanceRef(Arc::downgrade(&strong.0))),
         }
     }
+
+    /// Whether the instance was closed, or freed for weak references.
+    pub fn is_closed(&self) -> bool {
+        match self {
+            Self::Weak(weak) => weak
+                .upgrade()
+                .map_or(true, |strong| strong.as_ref().is_closed()),
+            Self::Strong(strong) => strong.as_ref().is_closed(),
+        }
+    }
+
+    /// Whether an async call into the instance is suspended. Always false for weak
+    /// references to freed instances.
+    pub fn is_suspended(&self) -> bool {
+        match self {
+            Self::Weak(weak) => weak
+                .upgrade()
+                .map_or(false, |strong| strong.as_ref().is_suspended()),
+            Self::Strong(strong) => strong.as_ref().is_suspended(),
+        }
+    }
+
+    /// Mark the instance as running an async call that is suspended, so that the API
+    /// refuses to call its functions from the host until the call is resumed.
+    pub fn set_suspended(&self, suspended: bool) {
+        match self {
+            Self::Weak(weak) => {
+                if let Some(strong) = weak.upgrade() {
+                    strong.as_ref().set_suspended(suspended)
+                }
+            }
+            Self::Strong(strong) => strong.as_ref().set_suspended(suspended),
+        }
+    }
+
+    /// Enter the instance for a call from the host on the current thread, returning None
+    /// if another thread runs in it. The current thread may enter it several times, for
+    /// the calls made from the host functions it imports. Weak references to freed
+    /// instances enter nothing.
+    pub fn enter(&self) -> Option<super::InstanceEntry> {
+        match self {
+            Self::Weak(weak) => match weak.upgrade() {
+                Some(strong) => super::InstanceEntry::enter(strong),
+                None => Some(super::InstanceEntry::none()),
+            },
+            Self::Strong(strong) => super::InstanceEntry::enter(strong.clone()),
+        }
+    }
+
+    /// Record a call into the instance from the host in its metrics, if it collects them.
+    /// Does nothing for weak references to freed instances.
+    pub fn record_host_call(&self) {
+        match self {
+            Self::Weak(weak) => {
+                if let Some(strong) = weak.upgrade() {
+                    strong.as_ref().record_host_call()
+                }
+            }
+            Self::Strong(strong) => strong.as_ref().record_host_call(),
+        }
+    }
+
+    /// Get the identifier of the instance, returning None if it was already freed.
+    pub fn id(&self) -> Option<super::InstanceId> {
+        match self {
+            Self::Weak(weak) => weak.upgrade().map(|strong| strong.as_ref().id),
+            Self::Strong(strong) => Some(strong.as_ref().id),
+        }
+    }
 }
 
 impl TryFrom<WeakOrStrongInstanceRef> for InstanceRef {
@@ -0,0 +1,240 @@
+//! Snapshots of the state of instances, which can be persisted and restored into new instances
+//! of the same module.
+
+use super::image::InstanceState;
+use super::{Instance, InstanceHandle};
+use rkyv::ser::serializers::AllocSerializer;
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use wasmer_types::entity::PrimaryMap;
+use wasmer_types::{LocalMemoryIndex, Pages, WASM_PAGE_SIZE};
+
+/// The byte after the name is the version of the format.
+const MAGIC_HEADER: [u8; 16] = *b"\0wasmer-snap\x01\xFF\xFF\xFF";
+
+/// The size of the chunks of the memories left out of the snapshots when they only hold zeros.
+const CHUNK_SIZE: usize = 4096;
+
+/// A snapshot of the state an instance defines: the contents of its memories, tables and
+/// globals, and the passive segments it didn't drop.
+///
+/// Unlike an [`InstancePreImage`](super::InstancePreImage), a snapshot can be serialized, and
+/// restored into an instance of the same module created in another process. As in an image,
+/// the references it holds are to the functions of the instance, by their index, so the state
+/// of an instance holding references to the functions of other instances, including those of
+/// the host, or to host data, can't be captured.
+#[derive(Debug, Clone, PartialEq, Eq, rkyv::Serialize, rkyv::Deserialize, rkyv::Archive)]
+pub struct InstanceSnapshot {
+    /// The hash identifying the module of the instance.
+    module_hash: [u8; 32],
+    memories: PrimaryMap<LocalMemoryIndex, MemorySnapshot>,
+    state: InstanceState,
+}
+
+/// The contents of a memory in an [`InstanceSnapshot`].
+#[derive(Debug, Clone, PartialEq, Eq, rkyv::Serialize, rkyv::Deserialize, rkyv::Archive)]
+pub struct MemorySnapshot {
+    size: Pages,
+    /// The contents, as their offsets and the bytes there. The memory is zeroed elsewhere.
+    chunks: Vec<(u64, Vec<u8>)>,
+}
+
+impl MemorySnapshot {
+    /// Capture `data`, the contents of a memory, leaving out the chunks only holding zeros if
+    /// `elide_zero_pages`.
+    fn new(data: &[u8], elide_zero_pages: bool) -> Self {
+        let size = Pages(u32::try_from(data.len() / WASM_PAGE_SIZE).unwrap());
+        if !elide_zero_pages {
+            return Self {
+                size,
+                chunks: vec![(0, data.to_vec())],
+            };
+        }
+        let mut chunks: Vec<(u64, Vec<u8>)> = vec![];
+        for (index, chunk) in data.chunks(CHUNK_SIZE).enumerate() {
+            if chunk.iter().all(|&byte| byte == 0) {
+                continue;
+            }
+            let offset = (index * CHUNK_SIZE) as u64;
+            match chunks.last_mut() {
+                Some((start, bytes)) if *start + bytes.len() as u64 == offset => {
+                    bytes.extend_from_slice(chunk)
+                }
+                _ => chunks.push((offset, chunk.to_vec())),
+            }
+        }
+        Self { size, chunks }
+    }
+}
+
+impl InstanceSnapshot {
+    /// The hash identifying the module of the instance the snapshot was taken of.
+    pub fn module_hash(&self) -> &[u8; 32] {
+        &self.module_hash
+    }
+
+    /// Serialize the snapshot, to restore it with [`InstanceSnapshot::deserialize`].
+    ///
+    /// The bytes only depend on the snapshot, and the format is the same on all the hosts of
+    /// the same endianness.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut serializer = AllocSerializer::<4096>::default();
+        let position = rkyv::ser::Serializer::serialize_value(&mut serializer, self)
+            .expect("snapshots only hold plain data") as u64;
+        let payload = serializer.into_serializer().into_inner();
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(payload.as_slice());
+        hasher.update(&position.to_le_bytes());
+        let mut bytes =
+            Vec::with_capacity(MAGIC_HEADER.len() + blake3::OUT_LEN + payload.len() + 8);
+        bytes.extend(&MAGIC_HEADER);
+        bytes.extend(hasher.finalize().as_bytes());
+        bytes.extend(payload.as_slice());
+        bytes.extend(&position.to_le_bytes());
+        bytes
+    }
+
+    /// Deserialize a snapshot serialized by [`InstanceSnapshot::serialize`].
+    ///
+    /// # Safety
+    ///
+    /// The bytes are checked against their hash, which detects corrupted data, but they must
+    /// still come from `serialize`: they are not validated otherwise.
+    pub unsafe fn deserialize(bytes: &[u8]) -> Result<Self, String> {
+        let header_length = MAGIC_HEADER.len() + blake3::OUT_LEN;
+        if !bytes.starts_with(&MAGIC_HEADER) {
+            return Err("the bytes are not a snapshot, or one of another version".to_string());
+        }
+        if bytes.len() < header_length + 8 {
+            return Err("the snapshot is truncated".to_string());
+        }
+        let (header, hashed) = bytes.split_at(header_length);
+        if blake3::hash(hashed).as_bytes()[..] != header[MAGIC_HEADER.len()..] {
+            return Err("the snapshot does not match its hash".to_string());
+        }
+        let (payload, position) = hashed.split_at(hashed.len() - 8);
+        let position = u64::from_le_bytes(<[u8; 8]>::try_from(position).unwrap()) as usize;
+        if position > payload.len() {
+            return Err("the snapshot is malformed".to_string());
+        }
+        // rkyv reads the values in place, so they must be aligned.
+        let mut aligned = rkyv::AlignedVec::with_capacity(payload.len());
+        aligned.extend_from_slice(payload);
+        let archive = rkyv::archived_value::<Self>(&aligned, position);
+        Ok(Result::<_, std::convert::Infallible>::unwrap(
+            rkyv::Deserialize::deserialize(archive, &mut rkyv::Infallible),
+        ))
+    }
+}
+
+impl Instance {
+    fn snapshot(
+        &self,
+        module_hash: [u8; 32],
+        elide_zero_pages: bool,
+    ) -> Result<InstanceSnapshot, String> {
+        let memories = self
+            .memories
+            .values()
+            .map(|memory| {
+                let mut snapshot = None;
+                memory.with_definition(&mut |definition| {
+                    let data = unsafe {
+                        std::slice::from_raw_parts(definition.base, definition.current_length)
+                    };
+                    snapshot = Some(MemorySnapshot::new(data, elide_zero_pages));
+                });
+                snapshot.unwrap()
+            })
+            .collect();
+        Ok(InstanceSnapshot {
+            module_hash,
+            memories,
+            state: self.capture_state()?,
+        })
+    }
+
+    fn restore(&self, snapshot: &InstanceSnapshot) -> Result<(), String> {
+        if snapshot.memories.len() != self.memories.len() {
+            return Err(format!(
+                "the snapshot has {} memories, the instance {}",
+                snapshot.memories.len(),
+                self.memories.len()
+            ));
+        }
+        self.apply_state(&snapshot.state)?;
+        for (index, memory_snapshot) in snapshot.memories.iter() {
+            let memory = &self.memories[index];
+            let size = memory.size();
+            match size.cmp(&memory_snapshot.size) {
+                Ordering::Less => {
+                    self.grow_with_callback(&**memory, memory_snapshot.size - size)
+                        .map_err(|e| e.to_string())?;
+                }
+                Ordering::Greater => {
+                    return Err(format!(
+                        "the memory {:?} has {} pages, more than the {} of the snapshot",
+                        index, size.0, memory_snapshot.size.0
+                    ));
+                }
+                Ordering::Equal => {}
+            }
+            let mut result = Ok(());
+            memory.with_definition(&mut |definition| {
+                let data = unsafe {
+                    std::slice::from_raw_parts_mut(definition.base, definition.current_length)
+                };
+                for (offset, bytes) in &memory_snapshot.chunks {
+                    let start = *offset as usize;
+                    match data.get_mut(start..start.saturating_add(bytes.len())) {
+                        Some(contents) => contents.copy_from_slice(bytes),
+                        None => {
+                            result = Err(format!(
+                                "the contents of the memory {:?} are past its end",
+                                index
+                            ));
+                            return;
+                        }
+                    }
+                }
+            });
+            result?;
+        }
+        Ok(())
+    }
+}
+
+impl InstanceHandle {
+    /// Take a snapshot of the instance, identifying its module by `module_hash`, and leaving
+    /// out the chunks of its memories only holding zeros if `elide_zero_pages`.
+    ///
+    /// Fails if the instance holds references which can't be captured, see
+    /// [`InstanceSnapshot`].
+    pub fn snapshot(
+        &self,
+        module_hash: [u8; 32],
+        elide_zero_pages: bool,
+    ) -> Result<InstanceSnapshot, String> {
+        self.instance()
+            .as_ref()
+            .snapshot(module_hash, elide_zero_pages)
+    }
+
+    /// Finishes the instantiation process started by `Instance::new`, restoring the state
+    /// captured in `snapshot` instead of running the initializers of the module and its start
+    /// function.
+    ///
+    /// The snapshot is checked against the definitions of the instance, but the caller must
+    /// check it is a snapshot of an instance of the same module for the state to be what it
+    /// was.
+    ///
+    /// # Safety
+    ///
+    /// Only safe to call immediately after instantiation.
+    pub unsafe fn finish_instantiation_from_snapshot(
+        &self,
+        snapshot: &InstanceSnapshot,
+    ) -> Result<(), String> {
+        self.instance().as_ref().restore(snapshot)
+    }
+}
@@ -22,13 +22,20 @@
 )]
 
 mod artifact;
+mod epoch;
+mod exception;
 mod export;
+#[cfg(unix)]
+mod fiber;
 mod func_data_registry;
 mod global;
 mod imports;
 mod instance;
+mod limiter;
 mod memory;
+mod memory_image;
 mod mmap;
+mod parking;
 mod probestack;
 mod resolver;
 mod sig_registry;
@@ -40,16 +47,25 @@ mod vmoffsets;
 
 pub mod libcalls;
 
-pub use crate::artifact::{Artifact, Instantiatable};
+pub use crate::artifact::{Artifact, ArtifactBytes, Instantiatable};
+pub use crate::epoch::{EpochDeadlineAction, EpochDeadlineCallback};
+pub use crate::exception::{register_landing_pads, LandingPadRegistration, VMException};
 pub use crate::export::*;
+#[cfg(unix)]
+pub use crate::fiber::{Fiber, Suspend};
 pub use crate::func_data_registry::{FuncDataRegistry, VMFuncRef};
 pub use crate::global::*;
 pub use crate::imports::{Imports, VMImport, VMImportType};
 pub use crate::instance::{
     initialize_host_envs, ImportFunctionEnv, ImportInitializerFuncPtr, InstanceAllocator,
-    InstanceHandle, WeakOrStrongInstanceRef,
+    InstanceEntry, InstanceHandle, InstanceId, InstanceMetrics, InstancePreImage, InstanceRef,
+    InstanceSnapshot, WeakInstanceRef, WeakOrStrongInstanceRef,
 };
-pub use crate::memory::{LinearMemory, Memory, MemoryError, MemoryStyle};
+pub use crate::limiter::{LimitedMemory, LimitedTable, ResourceLimiter, StaticLimiter};
+pub use crate::memory::{
+    LinearMemory, Memory, MemoryError, MemoryGrow, MemoryGrowCallback, MemoryStyle,
+};
+pub use crate::memory_image::MemoryImage;
 pub use crate::mmap::Mmap;
 pub use crate::probestack::PROBESTACK;
 pub use crate::resolver::{
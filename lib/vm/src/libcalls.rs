@@ -37,16 +37,23 @@
 
 #![allow(missing_docs)] // For some reason lint fails saying that `LibCall` is not documented, when it actually is
 
+use crate::exception::VMException;
 use crate::func_data_registry::VMFuncRef;
+use crate::parking;
 use crate::probestack::PROBESTACK;
 use crate::table::{RawTableElement, TableElement};
-use crate::trap::{raise_lib_trap, Trap, TrapCode};
+use crate::trap::traphandlers::defer_exception_drop;
+use crate::trap::{defer_externref_drop, raise_exception, raise_lib_trap, Trap, TrapCode};
 use crate::vmcontext::VMContext;
 use crate::VMExternRef;
+use std::convert::TryFrom;
 use std::fmt;
+use std::ptr;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::time::Duration;
 use wasmer_types::{
-    DataIndex, ElemIndex, FunctionIndex, LocalMemoryIndex, LocalTableIndex, MemoryIndex,
-    TableIndex, Type,
+    DataIndex, ElemIndex, FunctionIndex, GlobalIndex, LocalMemoryIndex, LocalTableIndex,
+    MemoryIndex, TableIndex, TagIndex, Type,
 };
 
 /// Implementation of f32.ceil
@@ -208,6 +215,75 @@ pub unsafe extern "C" fn wasmer_vm_imported_memory32_size(
     instance.imported_memory_size(memory_index).0
 }
 
+/// Implementation of memory.grow for locally-defined 64-bit memories.
+///
+/// # Safety
+///
+/// `vmctx` must be dereferenceable.
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_vm_memory64_grow(
+    vmctx: *mut VMContext,
+    delta: u64,
+    memory_index: u32,
+) -> u64 {
+    let instance = (&*vmctx).instance();
+    let memory_index = LocalMemoryIndex::from_u32(memory_index);
+
+    u32::try_from(delta)
+        .ok()
+        .and_then(|delta| instance.memory_grow(memory_index, delta).ok())
+        .map_or(u64::max_value(), |pages| u64::from(pages.0))
+}
+
+/// Implementation of memory.grow for imported 64-bit memories.
+///
+/// # Safety
+///
+/// `vmctx` must be dereferenceable.
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_vm_imported_memory64_grow(
+    vmctx: *mut VMContext,
+    delta: u64,
+    memory_index: u32,
+) -> u64 {
+    let instance = (&*vmctx).instance();
+    let memory_index = MemoryIndex::from_u32(memory_index);
+
+    u32::try_from(delta)
+        .ok()
+        .and_then(|delta| instance.imported_memory_grow(memory_index, delta).ok())
+        .map_or(u64::max_value(), |pages| u64::from(pages.0))
+}
+
+/// Implementation of memory.size for locally-defined 64-bit memories.
+///
+/// # Safety
+///
+/// `vmctx` must be dereferenceable.
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_vm_memory64_size(vmctx: *mut VMContext, memory_index: u32) -> u64 {
+    let instance = (&*vmctx).instance();
+    let memory_index = LocalMemoryIndex::from_u32(memory_index);
+
+    u64::from(instance.memory_size(memory_index).0)
+}
+
+/// Implementation of memory.size for imported 64-bit memories.
+///
+/// # Safety
+///
+/// `vmctx` must be dereferenceable.
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_vm_imported_memory64_size(
+    vmctx: *mut VMContext,
+    memory_index: u32,
+) -> u64 {
+    let instance = (&*vmctx).instance();
+    let memory_index = MemoryIndex::from_u32(memory_index);
+
+    u64::from(instance.imported_memory_size(memory_index).0)
+}
+
 /// Implementation of `table.copy`.
 ///
 /// # Safety
@@ -277,7 +353,7 @@ pub unsafe extern "C" fn wasmer_vm_table_fill(
         let table_index = TableIndex::from_u32(table_index);
         let instance = (&*vmctx).instance();
         let elem = match instance.get_table(table_index).ty().ty {
-            Type::ExternRef => TableElement::ExternRef(item.extern_ref.into()),
+            Type::ExternRef => TableElement::ExternRef(item.extern_ref.ref_clone().into()),
             Type::FuncRef => TableElement::FuncRef(item.func_ref),
             _ => panic!("Unrecognized table type: does not contain references"),
         };
@@ -318,6 +394,19 @@ pub unsafe extern "C" fn wasmer_vm_imported_table_size(
     instance.imported_table_size(table_index)
 }
 
+/// The raw form of `element` for the Wasm code, which keeps its reference alive until the
+/// current call returns without holding a count of it.
+fn borrow_element(element: TableElement) -> RawTableElement {
+    match element {
+        TableElement::ExternRef(extern_ref) => {
+            let raw: VMExternRef = extern_ref.into();
+            defer_externref_drop(raw.into());
+            RawTableElement { extern_ref: raw }
+        }
+        TableElement::FuncRef(func_ref) => RawTableElement { func_ref },
+    }
+}
+
 /// Implementation of `table.get`.
 ///
 /// # Safety
@@ -334,7 +423,7 @@ pub unsafe extern "C" fn wasmer_vm_table_get(
 
     // TODO: type checking, maybe have specialized accessors
     match instance.table_get(table_index, elem_index) {
-        Some(table_ref) => table_ref.into(),
+        Some(table_ref) => borrow_element(table_ref),
         None => raise_lib_trap(Trap::lib(TrapCode::TableAccessOutOfBounds)),
     }
 }
@@ -355,7 +444,7 @@ pub unsafe extern "C" fn wasmer_vm_imported_table_get(
 
     // TODO: type checking, maybe have specialized accessors
     match instance.imported_table_get(table_index, elem_index) {
-        Some(table_ref) => table_ref.into(),
+        Some(table_ref) => borrow_element(table_ref),
         None => raise_lib_trap(Trap::lib(TrapCode::TableAccessOutOfBounds)),
     }
 }
@@ -365,9 +454,6 @@ pub unsafe extern "C" fn wasmer_vm_imported_table_get(
 /// # Safety
 ///
 /// `vmctx` must be dereferenceable.
-///
-/// It is the caller's responsibility to increment the ref count of any ref counted
-/// type before passing it to this function.
 #[no_mangle]
 pub unsafe extern "C" fn wasmer_vm_table_set(
     vmctx: *mut VMContext,
@@ -383,7 +469,7 @@ pub unsafe extern "C" fn wasmer_vm_table_set(
         .local_table_index(table_index)
     {
         let elem = match instance.get_local_table(local_table).ty().ty {
-            Type::ExternRef => TableElement::ExternRef(value.extern_ref.into()),
+            Type::ExternRef => TableElement::ExternRef(value.extern_ref.ref_clone().into()),
             Type::FuncRef => TableElement::FuncRef(value.func_ref),
             _ => panic!("Unrecognized table type: does not contain references"),
         };
@@ -412,7 +498,7 @@ pub unsafe extern "C" fn wasmer_vm_imported_table_set(
     let instance = (&*vmctx).instance();
     let table_index = TableIndex::from_u32(table_index);
     let elem = match instance.get_foreign_table(table_index).ty().ty {
-        Type::ExternRef => TableElement::ExternRef(value.extern_ref.into()),
+        Type::ExternRef => TableElement::ExternRef(value.extern_ref.ref_clone().into()),
         Type::FuncRef => TableElement::FuncRef(value.func_ref),
         _ => panic!("Unrecognized table type: does not contain references"),
     };
@@ -437,7 +523,7 @@ pub unsafe extern "C" fn wasmer_vm_table_grow(
     let instance = (&*vmctx).instance();
     let table_index = LocalTableIndex::from_u32(table_index);
     let init_value = match instance.get_local_table(table_index).ty().ty {
-        Type::ExternRef => TableElement::ExternRef(init_value.extern_ref.into()),
+        Type::ExternRef => TableElement::ExternRef(init_value.extern_ref.ref_clone().into()),
         Type::FuncRef => TableElement::FuncRef(init_value.func_ref),
         _ => panic!("Unrecognized table type: does not contain references"),
     };
@@ -461,7 +547,7 @@ pub unsafe extern "C" fn wasmer_vm_imported_table_grow(
     let instance = (&*vmctx).instance();
     let table_index = TableIndex::from_u32(table_index);
     let init_value = match instance.get_table(table_index).ty().ty {
-        Type::ExternRef => TableElement::ExternRef(init_value.extern_ref.into()),
+        Type::ExternRef => TableElement::ExternRef(init_value.extern_ref.ref_clone().into()),
         Type::FuncRef => TableElement::FuncRef(init_value.func_ref),
         _ => panic!("Unrecognized table type: does not contain references"),
     };
@@ -487,6 +573,21 @@ pub unsafe extern "C" fn wasmer_vm_func_ref(
     instance.func_ref(function_index).unwrap()
 }
 
+/// Implementation of `global.set` for globals of `externref`s.
+///
+/// # Safety
+///
+/// `vmctx` must be dereferenceable.
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_vm_externref_global_set(
+    vmctx: *mut VMContext,
+    global_index: u32,
+    value: VMExternRef,
+) {
+    let instance = (&*vmctx).instance();
+    instance.externref_global_set(GlobalIndex::from_u32(global_index), value);
+}
+
 /// Implementation of externref increment
 ///
 /// # Safety
@@ -616,6 +717,167 @@ pub unsafe extern "C" fn wasmer_vm_imported_memory32_fill(
     }
 }
 
+/// Implementation of `memory.atomic.wait32`.
+///
+/// `addr` is the host address of the value, which the caller has checked to be in bounds and
+/// aligned. A negative `timeout` waits forever.
+///
+/// # Safety
+///
+/// `addr` must be dereferenceable for the lifetime of the call.
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_vm_memory32_atomic_wait32(
+    _vmctx: *mut VMContext,
+    addr: *const u32,
+    expected: u32,
+    timeout: i64,
+) -> u32 {
+    let value = &*(addr as *const AtomicU32);
+    parking::wait(
+        addr as usize,
+        || value.load(Ordering::SeqCst) == expected,
+        wait_timeout(timeout),
+    ) as u32
+}
+
+/// Implementation of `memory.atomic.wait64`.
+///
+/// See `wasmer_vm_memory32_atomic_wait32`.
+///
+/// # Safety
+///
+/// `addr` must be dereferenceable for the lifetime of the call.
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_vm_memory32_atomic_wait64(
+    _vmctx: *mut VMContext,
+    addr: *const u64,
+    expected: u64,
+    timeout: i64,
+) -> u32 {
+    let value = &*(addr as *const AtomicU64);
+    parking::wait(
+        addr as usize,
+        || value.load(Ordering::SeqCst) == expected,
+        wait_timeout(timeout),
+    ) as u32
+}
+
+fn wait_timeout(timeout: i64) -> Option<Duration> {
+    u64::try_from(timeout).ok().map(Duration::from_nanos)
+}
+
+/// Implementation of `memory.atomic.notify`.
+///
+/// `addr` is the host address of the value, which the caller has checked to be in bounds and
+/// aligned.
+#[no_mangle]
+pub extern "C" fn wasmer_vm_memory32_atomic_notify(
+    _vmctx: *mut VMContext,
+    addr: *const u32,
+    count: u32,
+) -> u32 {
+    parking::notify(addr as usize, count)
+}
+
+/// Called by code compiled with epoch interruption when the epoch reaches the epoch deadline
+/// of its instance, returning to keep running or raising a trap.
+///
+/// # Safety
+///
+/// `vmctx` must be dereferenceable.
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_vm_epoch_deadline_reached(vmctx: *mut VMContext) {
+    let result = {
+        let instance = (&*vmctx).instance();
+        instance.epoch_deadline_reached()
+    };
+    if let Err(trap) = result {
+        raise_lib_trap(trap);
+    }
+}
+
+/// Implementation of `throw`, out of the `try` blocks of the function: throws the exception of
+/// the tag `tag_index` carrying the values at `values`, one 16-byte slot each.
+///
+/// # Safety
+///
+/// `vmctx` must be dereferenceable, and `values` must hold the params of the tag.
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_vm_throw(
+    vmctx: *mut VMContext,
+    tag_index: u32,
+    values: *const i128,
+) -> ! {
+    let exception = {
+        let instance = (&*vmctx).instance();
+        let tag = instance
+            .tag_by_index(TagIndex::from_u32(tag_index))
+            .unwrap();
+        VMException::from_values(tag, values)
+    };
+    raise_exception(exception)
+}
+
+/// Creates the exception of the tag `tag_index` carrying the values at `values`, for a `throw`
+/// caught by a handler of the same function, which gets the returned pointer.
+///
+/// The exception is kept alive until the call into wasm returns.
+///
+/// # Safety
+///
+/// `vmctx` must be dereferenceable, and `values` must hold the params of the tag.
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_vm_exception_new(
+    vmctx: *mut VMContext,
+    tag_index: u32,
+    values: *const i128,
+) -> *const u8 {
+    let instance = (&*vmctx).instance();
+    let tag = instance
+        .tag_by_index(TagIndex::from_u32(tag_index))
+        .unwrap();
+    let exception = VMException::from_values(tag, values);
+    let ptr = exception.as_ptr();
+    defer_exception_drop(exception);
+    ptr
+}
+
+/// Implementation of `rethrow`, and of the exceptions no handler of the function catches:
+/// throws the exception `exception` again, to the handlers of the calling functions.
+///
+/// # Safety
+///
+/// `exception` must be the pointer of a live exception.
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_vm_rethrow(_vmctx: *mut VMContext, exception: *const u8) -> ! {
+    raise_exception(VMException::from_ptr(exception))
+}
+
+/// Implementation of `catch`: returns the values of `exception`, one 16-byte slot each, if it
+/// has the tag `tag_index`, and null otherwise.
+///
+/// # Safety
+///
+/// `vmctx` must be dereferenceable, and `exception` must be the pointer of a live exception.
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_vm_exception_catch(
+    vmctx: *mut VMContext,
+    exception: *const u8,
+    tag_index: u32,
+) -> *const i128 {
+    let instance = (&*vmctx).instance();
+    let tag = instance
+        .tag_by_index(TagIndex::from_u32(tag_index))
+        .unwrap();
+    let exception = VMException::from_ptr(exception);
+    if exception.tag().same(&tag) {
+        // The values stay alive with the exception.
+        exception.payload().as_ptr()
+    } else {
+        ptr::null()
+    }
+}
+
 /// Implementation of `memory.init`.
 ///
 /// # Safety
@@ -0,0 +1,307 @@
+//! Limits on the resources that instances can use.
+
+use crate::memory::{Memory, MemoryError, MemoryStyle};
+use crate::memory_image::MemoryImage;
+use crate::table::{Table, TableElement, TableStyle};
+use crate::trap::Trap;
+use crate::vmcontext::{VMMemoryDefinition, VMTableDefinition};
+use std::fmt;
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::Arc;
+use wasmer_types::{MemoryType, Pages, TableType};
+
+/// Decides whether memories and tables can grow and instances can be created.
+///
+/// The memories and tables created with a limiter are accounted for from their creation,
+/// which counts as growing from zero, until they are dropped.
+pub trait ResourceLimiter: Send + Sync {
+    /// Whether a memory can grow from `current` to `desired` pages. `maximum` is the
+    /// maximum of the memory, if any.
+    ///
+    /// When this returns `false`, `memory.grow` returns -1 and creating the memory fails.
+    fn memory_growing(&self, current: Pages, desired: Pages, maximum: Option<Pages>) -> bool;
+
+    /// Whether a table can grow from `current` to `desired` elements. `maximum` is the
+    /// maximum of the table, if any.
+    ///
+    /// When this returns `false`, `table.grow` returns -1 and creating the table fails.
+    fn table_growing(&self, current: u32, desired: u32, maximum: Option<u32>) -> bool;
+
+    /// Whether an instance can be created. When this returns `false`, the instantiation
+    /// fails with a link error.
+    fn instance_created(&self) -> bool;
+
+    /// Called when a memory of `pages` pages is dropped, or when a grow that was allowed
+    /// failed anyway.
+    fn memory_released(&self, _pages: Pages) {}
+
+    /// Called when a table of `elements` elements is dropped, or when a grow that was
+    /// allowed failed anyway.
+    fn table_released(&self, _elements: u32) {}
+
+    /// Called when an instance that was allowed to be created is dropped.
+    fn instance_dropped(&self) {}
+}
+
+/// A [`ResourceLimiter`] capping the totals of the memory pages, table elements and
+/// instances that are alive at once.
+#[derive(Debug)]
+pub struct StaticLimiter {
+    max_total_pages: Pages,
+    max_instances: usize,
+    max_table_elements: u32,
+    total_pages: AtomicU32,
+    instances: AtomicUsize,
+    table_elements: AtomicU32,
+}
+
+impl StaticLimiter {
+    /// Creates a limiter allowing at most `max_total_pages` pages in all the memories,
+    /// `max_instances` instances and `max_table_elements` elements in all the tables.
+    pub fn new(max_total_pages: Pages, max_instances: usize, max_table_elements: u32) -> Self {
+        Self {
+            max_total_pages,
+            max_instances,
+            max_table_elements,
+            total_pages: AtomicU32::new(0),
+            instances: AtomicUsize::new(0),
+            table_elements: AtomicU32::new(0),
+        }
+    }
+
+    /// The number of pages in all the memories.
+    pub fn total_pages(&self) -> Pages {
+        Pages(self.total_pages.load(Ordering::SeqCst))
+    }
+
+    /// The number of instances alive.
+    pub fn instances(&self) -> usize {
+        self.instances.load(Ordering::SeqCst)
+    }
+
+    /// The number of elements in all the tables.
+    pub fn table_elements(&self) -> u32 {
+        self.table_elements.load(Ordering::SeqCst)
+    }
+}
+
+/// Adds `delta` to `counter` unless the result would exceed `max`.
+fn reserve_u32(counter: &AtomicU32, delta: u32, max: u32) -> bool {
+    counter
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |total| {
+            total.checked_add(delta).filter(|&total| total <= max)
+        })
+        .is_ok()
+}
+
+impl ResourceLimiter for StaticLimiter {
+    fn memory_growing(&self, current: Pages, desired: Pages, _maximum: Option<Pages>) -> bool {
+        let delta = desired.0.saturating_sub(current.0);
+        reserve_u32(&self.total_pages, delta, self.max_total_pages.0)
+    }
+
+    fn table_growing(&self, current: u32, desired: u32, _maximum: Option<u32>) -> bool {
+        let delta = desired.saturating_sub(current);
+        reserve_u32(&self.table_elements, delta, self.max_table_elements)
+    }
+
+    fn instance_created(&self) -> bool {
+        self.instances
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |instances| {
+                Some(instances + 1).filter(|&instances| instances <= self.max_instances)
+            })
+            .is_ok()
+    }
+
+    fn memory_released(&self, pages: Pages) {
+        self.total_pages.fetch_sub(pages.0, Ordering::SeqCst);
+    }
+
+    fn table_released(&self, elements: u32) {
+        self.table_elements.fetch_sub(elements, Ordering::SeqCst);
+    }
+
+    fn instance_dropped(&self) {
+        self.instances.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// A memory whose size is accounted for by a [`ResourceLimiter`].
+pub struct LimitedMemory {
+    memory: Arc<dyn Memory>,
+    limiter: Arc<dyn ResourceLimiter>,
+}
+
+impl LimitedMemory {
+    /// Accounts for `memory`, failing if `limiter` doesn't allow creating it.
+    ///
+    /// The grows of `memory` all go through the limiter from then on, compiled code no
+    /// longer growing it by itself.
+    pub fn new(
+        memory: Arc<dyn Memory>,
+        limiter: Arc<dyn ResourceLimiter>,
+    ) -> Result<Self, MemoryError> {
+        let size = memory.size();
+        if !limiter.memory_growing(Pages(0), size, memory.ty().maximum) {
+            return Err(MemoryError::Generic(format!(
+                "the resource limiter doesn't allow a memory of {} pages",
+                size.0
+            )));
+        }
+        memory.disable_inline_grows();
+        Ok(Self { memory, limiter })
+    }
+}
+
+impl fmt::Debug for LimitedMemory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LimitedMemory")
+            .field("memory", &self.memory)
+            .finish()
+    }
+}
+
+impl Memory for LimitedMemory {
+    fn ty(&self) -> MemoryType {
+        self.memory.ty()
+    }
+
+    fn style(&self) -> &MemoryStyle {
+        self.memory.style()
+    }
+
+    fn size(&self) -> Pages {
+        self.memory.size()
+    }
+
+    fn grow(&self, delta: Pages) -> Result<Pages, MemoryError> {
+        self.grow_checked(delta, &mut |_, _, _| Ok(()))
+    }
+
+    fn grow_checked(
+        &self,
+        delta: Pages,
+        check: &mut dyn FnMut(Pages, Pages, Result<(), &MemoryError>) -> Result<(), MemoryError>,
+    ) -> Result<Pages, MemoryError> {
+        let maximum = self.memory.ty().maximum;
+        let mut allowed = None;
+        let result = self
+            .memory
+            .grow_checked(delta, &mut |current, new, result| {
+                check(current, new, result)?;
+                if result.is_ok() {
+                    if !self.limiter.memory_growing(current, new, maximum) {
+                        return Err(MemoryError::Generic(format!(
+                            "the resource limiter doesn't allow growing the memory to {} pages",
+                            new.0
+                        )));
+                    }
+                    allowed = Some(Pages(new.0 - current.0));
+                }
+                Ok(())
+            });
+        if let (Err(_), Some(pages)) = (&result, allowed) {
+            self.limiter.memory_released(pages);
+        }
+        result
+    }
+
+    fn vmmemory(&self) -> NonNull<VMMemoryDefinition> {
+        self.memory.vmmemory()
+    }
+
+    fn disable_inline_grows(&self) {
+        self.memory.disable_inline_grows()
+    }
+
+    fn with_definition(&self, f: &mut dyn FnMut(&VMMemoryDefinition)) {
+        self.memory.with_definition(f)
+    }
+
+    fn apply_image(&self, image: &MemoryImage) -> Result<(), MemoryError> {
+        self.memory.apply_image(image)
+    }
+}
+
+impl Drop for LimitedMemory {
+    fn drop(&mut self) {
+        self.limiter.memory_released(self.memory.size());
+    }
+}
+
+/// A table whose size is accounted for by a [`ResourceLimiter`].
+pub struct LimitedTable {
+    table: Arc<dyn Table>,
+    limiter: Arc<dyn ResourceLimiter>,
+}
+
+impl LimitedTable {
+    /// Accounts for `table`, failing if `limiter` doesn't allow creating it.
+    pub fn new(table: Arc<dyn Table>, limiter: Arc<dyn ResourceLimiter>) -> Result<Self, String> {
+        let size = table.size();
+        if !limiter.table_growing(0, size, table.ty().maximum) {
+            return Err(format!(
+                "the resource limiter doesn't allow a table of {} elements",
+                size
+            ));
+        }
+        Ok(Self { table, limiter })
+    }
+}
+
+impl fmt::Debug for LimitedTable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LimitedTable")
+            .field("table", &self.table)
+            .finish()
+    }
+}
+
+impl Table for LimitedTable {
+    fn style(&self) -> &TableStyle {
+        self.table.style()
+    }
+
+    fn ty(&self) -> &TableType {
+        self.table.ty()
+    }
+
+    fn size(&self) -> u32 {
+        self.table.size()
+    }
+
+    fn grow(&self, delta: u32, init_value: TableElement) -> Option<u32> {
+        let current = self.table.size();
+        let desired = current.checked_add(delta)?;
+        if !self
+            .limiter
+            .table_growing(current, desired, self.table.ty().maximum)
+        {
+            return None;
+        }
+        let result = self.table.grow(delta, init_value);
+        if result.is_none() {
+            self.limiter.table_released(delta);
+        }
+        result
+    }
+
+    fn get(&self, index: u32) -> Option<TableElement> {
+        self.table.get(index)
+    }
+
+    fn set(&self, index: u32, reference: TableElement) -> Result<(), Trap> {
+        self.table.set(index, reference)
+    }
+
+    fn vmtable(&self) -> NonNull<VMTableDefinition> {
+        self.table.vmtable()
+    }
+}
+
+impl Drop for LimitedTable {
+    fn drop(&mut self) {
+        self.limiter.table_released(self.table.size());
+    }
+}
@@ -5,6 +5,8 @@
 //!
 //! `LinearMemory` is to WebAssembly linear memories what `Table` is to WebAssembly tables.
 
+use crate::instance::InstanceId;
+use crate::memory_image::MemoryImage;
 use crate::mmap::Mmap;
 use crate::vmcontext::VMMemoryDefinition;
 use more_asserts::assert_ge;
@@ -13,6 +15,7 @@ use std::cell::UnsafeCell;
 use std::convert::TryInto;
 use std::fmt;
 use std::ptr::NonNull;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Mutex;
 use thiserror::Error;
 use wasmer_types::{Bytes, MemoryType, Pages};
@@ -54,11 +57,34 @@ pub enum MemoryError {
         /// The number of pages requested as the maximum amount of memory.
         max_allowed: Pages,
     },
+    /// Another thread runs a call into the instance defining the memory.
+    #[error("The instance of the memory is running a call on another thread")]
+    ConcurrentReentry,
     /// A user defined error value, used for error cases not listed above.
     #[error("A user-defined error occurred: {0}")]
     Generic(String),
 }
 
+/// A grow of a linear memory, as seen by a [`MemoryGrowCallback`].
+#[derive(Debug)]
+pub struct MemoryGrow<'a> {
+    /// The instance running `memory.grow`, or the instance the memory belongs to when the host
+    /// grows it. `None` for memories created by the host and grown by the host.
+    pub instance: Option<InstanceId>,
+    /// The size of the memory before the grow.
+    pub previous: Pages,
+    /// The size of the memory after the grow.
+    pub new: Pages,
+    /// Whether the memory can grow that much, which it can't past its maximum.
+    pub result: Result<(), &'a MemoryError>,
+}
+
+/// A callback called before every grow of a memory, from the host or from WebAssembly.
+///
+/// Returning an error keeps the memory from growing: the error is returned to the host, and
+/// `memory.grow` returns -1. The return value doesn't matter when the grow already fails.
+pub type MemoryGrowCallback = dyn Fn(&MemoryGrow) -> Result<(), MemoryError> + Send + Sync;
+
 /// Implementation styles for WebAssembly linear memory.
 #[derive(Debug, Clone, PartialEq, Eq, Hash, rkyv::Serialize, rkyv::Deserialize, rkyv::Archive)]
 pub enum MemoryStyle {
@@ -108,10 +134,65 @@ pub trait Memory: fmt::Debug + Send + Sync {
     /// Grow memory by the specified amount of wasm pages.
     fn grow(&self, delta: Pages) -> Result<Pages, MemoryError>;
 
+    /// Grow memory by the specified amount of wasm pages, unless `check` fails.
+    ///
+    /// `check` is called with the current size, the size after the grow and whether the memory
+    /// can grow that much, with the memory kept from growing concurrently until the grow is
+    /// done. The memory only grows if both the grow is possible and `check` succeeds, and the
+    /// error of `check` is only returned when the grow is possible.
+    fn grow_checked(
+        &self,
+        delta: Pages,
+        check: &mut dyn FnMut(Pages, Pages, Result<(), &MemoryError>) -> Result<(), MemoryError>,
+    ) -> Result<Pages, MemoryError>;
+
     /// Return a [`VMMemoryDefinition`] for exposing the memory to compiled wasm code.
     ///
     /// The pointer returned in [`VMMemoryDefinition`] must be valid for the lifetime of this memory.
     fn vmmemory(&self) -> NonNull<VMMemoryDefinition>;
+
+    /// Keep compiled code from growing the memory within its `reserved_length`, so that all
+    /// the grows go through [`Memory::grow_checked`] from now on.
+    ///
+    /// This does nothing by default, for the memories that never reserve anything past their
+    /// end.
+    fn disable_inline_grows(&self) {}
+
+    /// Calls `f` with the current [`VMMemoryDefinition`], keeping the memory from growing until
+    /// `f` returns.
+    ///
+    /// The memory may move when it grows, so this lets the host access it while it's grown from
+    /// another thread. `f` must not grow the memory itself.
+    fn with_definition(&self, f: &mut dyn FnMut(&VMMemoryDefinition));
+
+    /// Overwrite the start of the memory, which must still be zeroed, with the contents of
+    /// `image`.
+    ///
+    /// The contents are copied by default.
+    fn apply_image(&self, image: &MemoryImage) -> Result<(), MemoryError> {
+        let mut result = Ok(());
+        self.with_definition(&mut |definition| {
+            result = if image.len() > definition.current_length {
+                Err(image_too_large(image, definition.current_length))
+            } else {
+                let memory = unsafe {
+                    std::slice::from_raw_parts_mut(definition.base, definition.current_length)
+                };
+                image.copy_to(memory).map_err(MemoryError::Region)
+            };
+        });
+        result
+    }
+}
+
+fn image_too_large(image: &MemoryImage, current_length: usize) -> MemoryError {
+    MemoryError::InvalidMemory {
+        reason: format!(
+            "the image ({} bytes) is larger than the memory ({} bytes)",
+            image.len(),
+            current_length
+        ),
+    }
 }
 
 /// A linear memory instance.
@@ -133,6 +214,10 @@ pub struct LinearMemory {
     // constant offsets.
     offset_guard_size: usize,
 
+    /// Whether compiled code may grow the memory by itself, within pages the memory keeps
+    /// accessible past its end.
+    inline_grows: AtomicBool,
+
     /// The owned memory definition used by the generated code
     vm_memory_definition: VMMemoryDefinitionOwnership,
 }
@@ -166,8 +251,28 @@ unsafe impl Sync for LinearMemory {}
 struct WasmMmap {
     // Our OS allocation of mmap'd memory.
     alloc: Mmap,
-    // The current logical size in wasm pages of this linear memory.
-    size: Pages,
+    // The size in wasm pages of the accessible start of the allocation: the current size of
+    // the linear memory, and the pages it reserves past it.
+    accessible: Pages,
+}
+
+/// The pages a memory reserves past its end when it grows by itself, unless it's already
+/// larger: compiled code can then double its size before calling the runtime again.
+const MIN_GROW_RESERVATION: Pages = Pages(16);
+
+/// The `current_length` and `reserved_length` of `definition`, which compiled code may read
+/// and bump concurrently for shared memories.
+///
+/// # Safety
+/// - `definition` must be valid for `'a`.
+unsafe fn lengths<'a>(
+    definition: NonNull<VMMemoryDefinition>,
+) -> (&'a AtomicUsize, &'a AtomicUsize) {
+    let definition = definition.as_ptr();
+    (
+        &*(std::ptr::addr_of_mut!((*definition).current_length) as *const AtomicUsize),
+        &*(std::ptr::addr_of_mut!((*definition).reserved_length) as *const AtomicUsize),
+    )
 }
 
 impl LinearMemory {
@@ -200,18 +305,19 @@ impl LinearMemory {
         style: &MemoryStyle,
         vm_memory_location: Option<NonNull<VMMemoryDefinition>>,
     ) -> Result<Self, MemoryError> {
-        if memory.minimum > Pages::max_value() {
+        let max_pages = memory.max_pages();
+        if memory.minimum > max_pages {
             return Err(MemoryError::MinimumMemoryTooLarge {
                 min_requested: memory.minimum,
-                max_allowed: Pages::max_value(),
+                max_allowed: max_pages,
             });
         }
-        // `maximum` cannot be set to more than `65536` pages.
+        // `maximum` cannot be set to more than `65536` pages, unless the memory is 64-bit.
         if let Some(max) = memory.maximum {
-            if max > Pages::max_value() {
+            if max > max_pages {
                 return Err(MemoryError::MaximumMemoryTooLarge {
                     max_requested: max,
-                    max_allowed: Pages::max_value(),
+                    max_allowed: max_pages,
                 });
             }
             if max < memory.minimum {
@@ -226,8 +332,9 @@ impl LinearMemory {
 
         let offset_guard_bytes = style.offset_guard_size() as usize;
 
+        let reserved_pages = grow_reservation(memory.minimum, memory, style);
         let minimum_pages = match style {
-            MemoryStyle::Dynamic { .. } => memory.minimum,
+            MemoryStyle::Dynamic { .. } => reserved_pages,
             MemoryStyle::Static { bound, .. } => {
                 assert_ge!(*bound, memory.minimum);
                 *bound
@@ -235,42 +342,77 @@ impl LinearMemory {
         };
         let minimum_bytes = minimum_pages.bytes().0;
         let request_bytes = minimum_bytes.checked_add(offset_guard_bytes).unwrap();
-        let mapped_pages = memory.minimum;
-        let mapped_bytes = mapped_pages.bytes();
+        let mapped_bytes = reserved_pages.bytes();
 
         let mut mmap = WasmMmap {
             alloc: Mmap::accessible_reserved(mapped_bytes.0, request_bytes)
                 .map_err(MemoryError::Region)?,
-            size: memory.minimum,
+            accessible: reserved_pages,
         };
 
         let base_ptr = mmap.alloc.as_mut_ptr();
         let mem_length = memory.minimum.bytes().0;
+        let definition = VMMemoryDefinition {
+            base: base_ptr,
+            current_length: mem_length,
+            reserved_length: mapped_bytes.0,
+        };
         Ok(Self {
             mmap: Mutex::new(mmap),
             maximum: memory.maximum,
             offset_guard_size: offset_guard_bytes,
+            inline_grows: AtomicBool::new(true),
             vm_memory_definition: if let Some(mem_loc) = vm_memory_location {
-                {
-                    let mut ptr = mem_loc;
-                    let md = ptr.as_mut();
-                    md.base = base_ptr;
-                    md.current_length = mem_length;
-                }
+                mem_loc.as_ptr().write(definition);
                 VMMemoryDefinitionOwnership::VMOwned(mem_loc)
             } else {
-                VMMemoryDefinitionOwnership::HostOwned(Box::new(UnsafeCell::new(
-                    VMMemoryDefinition {
-                        base: base_ptr,
-                        current_length: mem_length,
-                    },
-                )))
+                VMMemoryDefinitionOwnership::HostOwned(Box::new(UnsafeCell::new(definition)))
             },
             memory: *memory,
             style: style.clone(),
         })
     }
 
+    /// The size of the memory after growing it from `current` by `delta` pages, unless that
+    /// goes past its maximum or what is indexable.
+    fn checked_new_size(&self, current: Pages, delta: Pages) -> Result<Pages, MemoryError> {
+        let could_not_grow = MemoryError::CouldNotGrow {
+            current,
+            attempted_delta: delta,
+        };
+        let max_pages = self.memory.max_pages();
+        let new_pages = current
+            .0
+            .checked_add(delta.0)
+            .map(Pages)
+            .filter(|new_pages| *new_pages <= max_pages)
+            .ok_or_else(|| could_not_grow.clone())?;
+        if let Some(maximum) = self.maximum {
+            if new_pages > maximum {
+                return Err(could_not_grow);
+            }
+        }
+
+        // Wasm linear memories are never allowed to grow beyond what is
+        // indexable. If the memory has no maximum, enforce the greatest
+        // limit here.
+        if new_pages >= max_pages {
+            // Linear memory size would exceed the index range.
+            return Err(could_not_grow);
+        }
+        Ok(new_pages)
+    }
+
+    /// The size the memory reserves for compiled code to grow it to, now that it's `size`
+    /// pages.
+    fn reservation(&self, size: Pages) -> Pages {
+        if self.inline_grows.load(Ordering::SeqCst) {
+            grow_reservation(size, &self.memory, &self.style)
+        } else {
+            size
+        }
+    }
+
     /// Get the `VMMemoryDefinition`.
     ///
     /// # Safety
@@ -286,6 +428,22 @@ impl LinearMemory {
     }
 }
 
+/// The size a memory of type `memory` and `size` pages reserves for compiled code to grow it
+/// to: twice its size, or `MIN_GROW_RESERVATION` pages past it, without going past its
+/// maximum, what is indexable or, for static memories, their bound.
+fn grow_reservation(size: Pages, memory: &MemoryType, style: &MemoryStyle) -> Pages {
+    // `checked_new_size` keeps the memories from growing to `memory.max_pages()`.
+    let mut limit = Pages(memory.max_pages().0 - 1);
+    if let Some(maximum) = memory.maximum {
+        limit = limit.min(maximum);
+    }
+    if let MemoryStyle::Static { bound, .. } = style {
+        limit = limit.min(*bound);
+    }
+    let headroom = size.0.max(MIN_GROW_RESERVATION.0);
+    Pages(size.0.saturating_add(headroom)).min(limit).max(size)
+}
+
 impl Memory for LinearMemory {
     /// Returns the type for this memory.
     fn ty(&self) -> MemoryType {
@@ -303,12 +461,10 @@ impl Memory for LinearMemory {
 
     /// Returns the number of allocated wasm pages.
     fn size(&self) -> Pages {
-        // TODO: investigate this function for race conditions
-        unsafe {
-            let md_ptr = self.get_vm_memory_definition();
-            let md = md_ptr.as_ref();
-            Bytes::from(md.current_length).try_into().unwrap()
-        }
+        let (current_length, _) = unsafe { lengths(self.get_vm_memory_definition()) };
+        Bytes::from(current_length.load(Ordering::SeqCst))
+            .try_into()
+            .unwrap()
     }
 
     /// Grow memory by the specified amount of wasm pages.
@@ -316,83 +472,90 @@ impl Memory for LinearMemory {
     /// Returns `None` if memory can't be grown by the specified amount
     /// of wasm pages.
     fn grow(&self, delta: Pages) -> Result<Pages, MemoryError> {
+        self.grow_checked(delta, &mut |_, _, _| Ok(()))
+    }
+
+    fn grow_checked(
+        &self,
+        delta: Pages,
+        check: &mut dyn FnMut(Pages, Pages, Result<(), &MemoryError>) -> Result<(), MemoryError>,
+    ) -> Result<Pages, MemoryError> {
         let mut mmap_guard = self.mmap.lock().unwrap();
         let mmap = mmap_guard.borrow_mut();
-        // Optimization of memory.grow 0 calls.
-        if delta.0 == 0 {
-            return Ok(mmap.size);
-        }
-
-        let new_pages = mmap
-            .size
-            .checked_add(delta)
-            .ok_or(MemoryError::CouldNotGrow {
-                current: mmap.size,
-                attempted_delta: delta,
-            })?;
-        let prev_pages = mmap.size;
-
-        if let Some(maximum) = self.maximum {
-            if new_pages > maximum {
-                return Err(MemoryError::CouldNotGrow {
-                    current: mmap.size,
-                    attempted_delta: delta,
-                });
+        let md_ptr = unsafe { self.get_vm_memory_definition() };
+        let (current_length, reserved_length) = unsafe { lengths(md_ptr) };
+        // Compiled code may grow a shared memory within its reservation while it's checked,
+        // in which case the grow is checked again from the new size.
+        loop {
+            let prev_bytes = current_length.load(Ordering::SeqCst);
+            let prev_pages: Pages = Bytes(prev_bytes).try_into().unwrap();
+            let new_pages = self.checked_new_size(prev_pages, delta);
+            let checked = check(
+                prev_pages,
+                Pages(prev_pages.0.saturating_add(delta.0)),
+                new_pages.as_ref().map(|_| ()),
+            );
+            let new_pages = new_pages?;
+            checked?;
+            // Optimization of memory.grow 0 calls.
+            if delta.0 == 0 {
+                return Ok(prev_pages);
             }
-        }
-
-        // Wasm linear memories are never allowed to grow beyond what is
-        // indexable. If the memory has no maximum, enforce the greatest
-        // limit here.
-        if new_pages >= Pages::max_value() {
-            // Linear memory size would exceed the index range.
-            return Err(MemoryError::CouldNotGrow {
-                current: mmap.size,
-                attempted_delta: delta,
-            });
-        }
 
-        let delta_bytes = delta.bytes().0;
-        let prev_bytes = prev_pages.bytes().0;
-        let new_bytes = new_pages.bytes().0;
-
-        if new_bytes > mmap.alloc.len() - self.offset_guard_size {
-            // If the new size is within the declared maximum, but needs more memory than we
-            // have on hand, it's a dynamic heap and it can move.
-            let guard_bytes = self.offset_guard_size;
-            let request_bytes =
-                new_bytes
-                    .checked_add(guard_bytes)
-                    .ok_or_else(|| MemoryError::CouldNotGrow {
+            // The memory also reserves pages past its new end, for compiled code to grow into.
+            let reserved_pages = self.reservation(new_pages);
+            let accessible_bytes = mmap.accessible.bytes().0;
+            let reserved_bytes = reserved_pages.bytes().0;
+
+            if reserved_bytes > mmap.alloc.len() - self.offset_guard_size {
+                // If the new size is within the declared maximum, but needs more memory than we
+                // have on hand, it's a dynamic heap and it can move.
+                let guard_bytes = self.offset_guard_size;
+                let request_bytes = reserved_bytes.checked_add(guard_bytes).ok_or_else(|| {
+                    MemoryError::CouldNotGrow {
                         current: new_pages,
                         attempted_delta: Bytes(guard_bytes).try_into().unwrap(),
-                    })?;
-
-            let mut new_mmap =
-                Mmap::accessible_reserved(new_bytes, request_bytes).map_err(MemoryError::Region)?;
-
-            let copy_len = mmap.alloc.len() - self.offset_guard_size;
-            new_mmap.as_mut_slice()[..copy_len].copy_from_slice(&mmap.alloc.as_slice()[..copy_len]);
-
-            mmap.alloc = new_mmap;
-        } else if delta_bytes > 0 {
-            // Make the newly allocated pages accessible.
-            mmap.alloc
-                .make_accessible(prev_bytes, delta_bytes)
-                .map_err(MemoryError::Region)?;
-        }
-
-        mmap.size = new_pages;
+                    }
+                })?;
+
+                let mut new_mmap = Mmap::accessible_reserved(reserved_bytes, request_bytes)
+                    .map_err(MemoryError::Region)?;
+
+                // The pages past the end of the memory are still zeroed.
+                let copy_len = current_length.load(Ordering::SeqCst);
+                new_mmap.as_mut_slice()[..copy_len]
+                    .copy_from_slice(&mmap.alloc.as_slice()[..copy_len]);
+
+                mmap.alloc = new_mmap;
+                mmap.accessible = reserved_pages;
+            } else if reserved_pages > mmap.accessible {
+                // Make the newly allocated pages accessible.
+                mmap.alloc
+                    .make_accessible(accessible_bytes, reserved_bytes - accessible_bytes)
+                    .map_err(MemoryError::Region)?;
+                mmap.accessible = reserved_pages;
+            }
 
-        // update memory definition
-        unsafe {
-            let mut md_ptr = self.get_vm_memory_definition();
-            let md = md_ptr.as_mut();
-            md.current_length = new_pages.bytes().0;
-            md.base = mmap.alloc.as_mut_ptr() as _;
+            // update memory definition
+            unsafe {
+                (*md_ptr.as_ptr()).base = mmap.alloc.as_mut_ptr() as _;
+            }
+            let new_bytes = new_pages.bytes().0;
+            if current_length
+                .compare_exchange(prev_bytes, new_bytes, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+            {
+                reserved_length.store(reserved_bytes, Ordering::SeqCst);
+                return Ok(prev_pages);
+            }
         }
+    }
 
-        Ok(prev_pages)
+    fn disable_inline_grows(&self) {
+        let _mmap_guard = self.mmap.lock().unwrap();
+        self.inline_grows.store(false, Ordering::SeqCst);
+        let (current_length, reserved_length) = unsafe { lengths(self.get_vm_memory_definition()) };
+        reserved_length.store(current_length.load(Ordering::SeqCst), Ordering::SeqCst);
     }
 
     /// Return a `VMMemoryDefinition` for exposing the memory to compiled wasm code.
@@ -400,4 +563,22 @@ impl Memory for LinearMemory {
         let _mmap_guard = self.mmap.lock().unwrap();
         unsafe { self.get_vm_memory_definition() }
     }
+
+    fn with_definition(&self, f: &mut dyn FnMut(&VMMemoryDefinition)) {
+        // `grow` holds the lock until the definition is updated.
+        let _mmap_guard = self.mmap.lock().unwrap();
+        f(unsafe { self.get_vm_memory_definition().as_ref() })
+    }
+
+    /// Map the contents of `image` copy-on-write over the start of the memory.
+    fn apply_image(&self, image: &MemoryImage) -> Result<(), MemoryError> {
+        let mut mmap_guard = self.mmap.lock().unwrap();
+        let mmap = mmap_guard.borrow_mut();
+        let current_length = self.size().bytes().0;
+        if image.len() > current_length {
+            return Err(image_too_large(image, current_length));
+        }
+        // The memory owns its anonymous mapping, whose accessible pages hold its contents.
+        unsafe { image.map_at(mmap.alloc.as_mut_ptr()) }.map_err(MemoryError::Region)
+    }
 }
@@ -0,0 +1,202 @@
+//! Snapshots of the contents of linear memories, mapped copy-on-write into new memories.
+
+use std::ptr;
+use wasmer_types::Pages;
+
+/// A snapshot of the contents of a linear memory, of which new memories can start as a copy.
+///
+/// On Linux, the contents are kept in a sealed memory file, which [`LinearMemory`]s map
+/// privately over their own pages: the pages of the image are only copied once written to,
+/// and the writes of a memory are never seen by the image nor by the other memories. Elsewhere,
+/// or if the file can't be created, the contents are kept in a buffer and copied.
+///
+/// The trailing zero pages aren't part of the image, as the memories start zeroed.
+///
+/// [`LinearMemory`]: crate::LinearMemory
+#[derive(Debug)]
+pub struct MemoryImage {
+    /// The size of the memory when its contents were captured.
+    size: Pages,
+    /// The length in bytes of the contents, a multiple of the host page size.
+    len: usize,
+    data: ImageData,
+}
+
+#[derive(Debug)]
+enum ImageData {
+    #[cfg(target_os = "linux")]
+    File(std::fs::File),
+    Buffer(Box<[u8]>),
+}
+
+impl ImageData {
+    #[cfg(target_os = "linux")]
+    fn new(data: &[u8]) -> Self {
+        if !data.is_empty() {
+            if let Ok(file) = sealed_file(data) {
+                return Self::File(file);
+            }
+        }
+        Self::Buffer(data.into())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn new(data: &[u8]) -> Self {
+        Self::Buffer(data.into())
+    }
+}
+
+impl MemoryImage {
+    /// Capture `data`, the contents of a memory of `size` pages.
+    pub fn new(data: &[u8], size: Pages) -> Self {
+        let page_size = region::page::size();
+        let len = data
+            .iter()
+            .rposition(|&byte| byte != 0)
+            .map_or(0, |last| (last / page_size + 1) * page_size)
+            .min(data.len());
+        Self {
+            size,
+            len,
+            data: ImageData::new(&data[..len]),
+        }
+    }
+
+    /// The size of the memory when its contents were captured, which the memories starting
+    /// from the image must have.
+    pub fn size(&self) -> Pages {
+        self.size
+    }
+
+    /// The length in bytes of the contents of the image, without its trailing zero pages.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the image only holds zeros.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Copy the contents of the image to the start of `memory`.
+    ///
+    /// # Panics
+    ///
+    /// If `memory` is shorter than the contents of the image.
+    pub fn copy_to(&self, memory: &mut [u8]) -> Result<(), String> {
+        let memory = &mut memory[..self.len];
+        match &self.data {
+            #[cfg(target_os = "linux")]
+            ImageData::File(file) => {
+                use std::os::unix::fs::FileExt;
+                file.read_exact_at(memory, 0).map_err(|e| e.to_string())
+            }
+            ImageData::Buffer(buffer) => {
+                memory.copy_from_slice(buffer);
+                Ok(())
+            }
+        }
+    }
+
+    /// Map the contents of the image privately over the `self.len()` bytes at `base`,
+    /// copying them where they can't be mapped.
+    ///
+    /// # Safety
+    ///
+    /// `base` must be page-aligned, and the start of at least `self.len()` accessible bytes
+    /// of an anonymous mapping, which no one else refers to as something else than bytes.
+    pub unsafe fn map_at(&self, base: *mut u8) -> Result<(), String> {
+        if self.len == 0 {
+            return Ok(());
+        }
+        match &self.data {
+            #[cfg(target_os = "linux")]
+            ImageData::File(file) => {
+                use std::os::unix::io::AsRawFd;
+                let ptr = libc::mmap(
+                    base as *mut libc::c_void,
+                    self.len,
+                    libc::PROT_READ | libc::PROT_WRITE,
+                    libc::MAP_PRIVATE | libc::MAP_FIXED,
+                    file.as_raw_fd(),
+                    0,
+                );
+                if ptr as isize == -1_isize {
+                    return Err(std::io::Error::last_os_error().to_string());
+                }
+                debug_assert_eq!(ptr as *mut u8, base);
+                Ok(())
+            }
+            ImageData::Buffer(buffer) => {
+                ptr::copy_nonoverlapping(buffer.as_ptr(), base, self.len);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Create a memory file holding `data`, sealed so that it can't change anymore.
+#[cfg(target_os = "linux")]
+fn sealed_file(data: &[u8]) -> std::io::Result<std::fs::File> {
+    use std::io::{self, Write};
+    use std::os::unix::io::FromRawFd;
+
+    let fd = unsafe {
+        libc::memfd_create(
+            b"wasmer-memory-image\0".as_ptr() as *const libc::c_char,
+            libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING,
+        )
+    };
+    if fd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut file = unsafe { std::fs::File::from_raw_fd(fd) };
+    file.write_all(data)?;
+    let seals = libc::F_SEAL_SEAL | libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_WRITE;
+    if unsafe { libc::fcntl(fd, libc::F_ADD_SEALS, seals) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Mmap;
+
+    #[test]
+    fn trailing_zero_pages_are_left_out() {
+        let page_size = region::page::size();
+        let mut data = vec![0; 4 * page_size];
+        assert!(MemoryImage::new(&data, Pages(1)).is_empty());
+        data[page_size + 1] = 1;
+        let image = MemoryImage::new(&data, Pages(1));
+        assert_eq!(image.len(), 2 * page_size);
+        assert_eq!(image.size(), Pages(1));
+    }
+
+    #[test]
+    fn mapped_images_are_copied_on_write() {
+        let page_size = region::page::size();
+        let mut data = vec![0; 2 * page_size];
+        data[..4].copy_from_slice(&[1, 2, 3, 4]);
+        data[2 * page_size - 1] = 5;
+        let image = MemoryImage::new(&data, Pages(1));
+
+        let mut first = Mmap::with_at_least(4 * page_size).unwrap();
+        let mut second = Mmap::with_at_least(4 * page_size).unwrap();
+        unsafe {
+            image.map_at(first.as_mut_ptr()).unwrap();
+            image.map_at(second.as_mut_ptr()).unwrap();
+        }
+        assert_eq!(&first.as_slice()[..2 * page_size], &data[..]);
+        first.as_mut_slice()[0] = 9;
+        first.as_mut_slice()[3 * page_size] = 9;
+        assert_eq!(&second.as_slice()[..2 * page_size], &data[..]);
+        assert_eq!(second.as_slice()[3 * page_size], 0);
+
+        let mut copy = vec![0; 2 * page_size];
+        image.copy_to(&mut copy).unwrap();
+        assert_eq!(copy, data);
+    }
+}
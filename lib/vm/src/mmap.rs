@@ -5,7 +5,6 @@
 //! of memory.
 
 use more_asserts::assert_le;
-use more_asserts::assert_lt;
 use std::io;
 use std::ptr;
 use std::slice;
@@ -47,6 +46,38 @@ impl Mmap {
         Self::accessible_reserved(rounded_size, rounded_size)
     }
 
+    /// Create a new `Mmap` pointing to at least `size` bytes of page-aligned memory mapped for
+    /// just-in-time compiled code.
+    ///
+    /// The memory is mapped readable, writable and executable with `MAP_JIT`, as Apple Silicon
+    /// requires of the code written at run time, and it's the thread that picks between writing
+    /// to such pages and executing them, with `pthread_jit_write_protect_np`.
+    #[cfg(all(target_os = "macos", target_arch = "aarch64"))]
+    pub fn jit_with_at_least(size: usize) -> Result<Self, String> {
+        let page_size = region::page::size();
+        let rounded_size = round_up_to_page_size(size, page_size);
+        if rounded_size == 0 {
+            return Ok(Self::new());
+        }
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                rounded_size,
+                libc::PROT_READ | libc::PROT_WRITE | libc::PROT_EXEC,
+                libc::MAP_PRIVATE | libc::MAP_ANON | libc::MAP_JIT,
+                -1,
+                0,
+            )
+        };
+        if ptr as isize == -1_isize {
+            return Err(io::Error::last_os_error().to_string());
+        }
+        Ok(Self {
+            ptr: ptr as usize,
+            len: rounded_size,
+        })
+    }
+
     /// Create a new `Mmap` pointing to `accessible_size` bytes of page-aligned accessible memory,
     /// within a reserved mapping of `mapping_size` bytes. `accessible_size` and `mapping_size`
     /// must be native page-size multiples.
@@ -186,8 +217,8 @@ impl Mmap {
         let page_size = region::page::size();
         assert_eq!(start & (page_size - 1), 0);
         assert_eq!(len & (page_size - 1), 0);
-        assert_lt!(len, self.len);
-        assert_lt!(start, self.len - len);
+        assert_le!(len, self.len);
+        assert_le!(start, self.len - len);
 
         // Commit the accessible size.
         let ptr = self.ptr as *const u8;
@@ -206,8 +237,8 @@ impl Mmap {
         let page_size = region::page::size();
         assert_eq!(start & (page_size - 1), 0);
         assert_eq!(len & (page_size - 1), 0);
-        assert_lt!(len, self.len);
-        assert_lt!(start, self.len - len);
+        assert_le!(len, self.len);
+        assert_le!(start, self.len - len);
 
         // Commit the accessible size.
         let ptr = self.ptr as *const u8;
@@ -0,0 +1,152 @@
+//! Parking of the threads blocked in `memory.atomic.wait32` and `memory.atomic.wait64`.
+//!
+//! Waiters are queued by the host address they wait on. Instances sharing a memory see it at
+//! the same address, so they find each other's waiters wherever they run.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, Once};
+use std::time::{Duration, Instant};
+
+/// The outcome of a `wait`, with the values returned by `memory.atomic.wait*`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub(crate) enum WaitResult {
+    /// Woken up by a `notify`.
+    Ok = 0,
+    /// The value did not have the expected value, so the thread did not wait.
+    NotEqual = 1,
+    /// Not notified before the timeout.
+    TimedOut = 2,
+}
+
+#[derive(Default)]
+struct Waiter {
+    /// Only ever accessed with the lot locked.
+    notified: AtomicBool,
+    condvar: Condvar,
+}
+
+type Queues = HashMap<usize, VecDeque<Arc<Waiter>>>;
+
+fn lock_lot() -> MutexGuard<'static, Queues> {
+    static INIT: Once = Once::new();
+    static mut LOT: Option<Mutex<Queues>> = None;
+    // SAFETY: `LOT` is only written once, before `call_once` returns for the first time.
+    let lot = unsafe {
+        INIT.call_once(|| LOT = Some(Mutex::new(HashMap::new())));
+        LOT.as_ref().unwrap()
+    };
+    // The queues are consistent whenever the lock is released, even by a panicking thread.
+    lot.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Blocks the current thread until a `notify` of `addr`, or until `timeout` has passed.
+///
+/// `is_expected` is called first and the thread does not wait if it returns false. It runs
+/// with the lot locked, so a `notify` cannot get in between it and the thread being queued.
+pub(crate) fn wait(
+    addr: usize,
+    is_expected: impl FnOnce() -> bool,
+    timeout: Option<Duration>,
+) -> WaitResult {
+    let mut queues = lock_lot();
+    if !is_expected() {
+        return WaitResult::NotEqual;
+    }
+    let waiter = Arc::new(Waiter::default());
+    queues
+        .entry(addr)
+        .or_default()
+        .push_back(Arc::clone(&waiter));
+
+    // A deadline too far away to be represented is as good as none.
+    let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+    while !waiter.notified.load(Ordering::Relaxed) {
+        queues = match deadline {
+            None => waiter
+                .condvar
+                .wait(queues)
+                .unwrap_or_else(|e| e.into_inner()),
+            Some(deadline) => {
+                let now = Instant::now();
+                if now >= deadline {
+                    let queue = queues.get_mut(&addr).unwrap();
+                    queue.retain(|w| !Arc::ptr_eq(w, &waiter));
+                    if queue.is_empty() {
+                        queues.remove(&addr);
+                    }
+                    return WaitResult::TimedOut;
+                }
+                waiter
+                    .condvar
+                    .wait_timeout(queues, deadline - now)
+                    .unwrap_or_else(|e| e.into_inner())
+                    .0
+            }
+        };
+    }
+    WaitResult::Ok
+}
+
+/// Wakes up to `count` of the threads waiting on `addr`, in the order they started waiting.
+///
+/// Returns the number of threads woken up.
+pub(crate) fn notify(addr: usize, count: u32) -> u32 {
+    let mut queues = lock_lot();
+    let queue = match queues.get_mut(&addr) {
+        Some(queue) => queue,
+        None => return 0,
+    };
+    let mut woken = 0;
+    while woken < count {
+        match queue.pop_front() {
+            Some(waiter) => {
+                waiter.notified.store(true, Ordering::Relaxed);
+                waiter.condvar.notify_one();
+                woken += 1;
+            }
+            None => break,
+        }
+    }
+    if queue.is_empty() {
+        queues.remove(&addr);
+    }
+    woken
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn wait_checks_the_value_first() {
+        assert_eq!(wait(0x1000, || false, None), WaitResult::NotEqual);
+        assert_eq!(notify(0x1000, 1), 0);
+    }
+
+    #[test]
+    fn wait_times_out() {
+        let timeout = Duration::from_millis(10);
+        assert_eq!(wait(0x2000, || true, Some(timeout)), WaitResult::TimedOut);
+        // The timed out waiter is not queued anymore.
+        assert_eq!(notify(0x2000, 1), 0);
+    }
+
+    #[test]
+    fn notify_wakes_waiters_up() {
+        let waiters = (0..2)
+            .map(|_| thread::spawn(|| wait(0x3000, || true, None)))
+            .collect::<Vec<_>>();
+        let mut woken = 0;
+        while woken < 2 {
+            woken += notify(0x3000, 1);
+            thread::yield_now();
+        }
+        for waiter in waiters {
+            assert_eq!(waiter.join().unwrap(), WaitResult::Ok);
+        }
+        assert_eq!(notify(0x3000, u32::MAX), 0);
+    }
+}
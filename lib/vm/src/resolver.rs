@@ -1,6 +1,7 @@
 use std::sync::Arc;
+use wasmer_types::ExternType;
 
-use crate::{ImportInitializerFuncPtr, VMExtern, VMFunction, VMGlobal, VMMemory, VMTable};
+use crate::{ImportInitializerFuncPtr, VMExtern, VMFunction, VMGlobal, VMMemory, VMTable, VMTag};
 
 /// The value of an export passed from one instance to another.
 #[derive(Debug, Clone)]
@@ -16,6 +17,9 @@ pub enum Export {
 
     /// A global export value.
     Global(VMGlobal),
+
+    /// An exception tag export value.
+    Tag(VMTag),
 }
 
 impl From<Export> for VMExtern {
@@ -25,6 +29,7 @@ impl From<Export> for VMExtern {
             Export::Memory(vm_memory) => Self::Memory(vm_memory),
             Export::Table(vm_table) => Self::Table(vm_table),
             Export::Global(vm_global) => Self::Global(vm_global),
+            Export::Tag(vm_tag) => Self::Tag(vm_tag),
         }
     }
 }
@@ -39,6 +44,7 @@ impl From<VMExtern> for Export {
             VMExtern::Memory(vm_memory) => Self::Memory(vm_memory),
             VMExtern::Table(vm_table) => Self::Table(vm_table),
             VMExtern::Global(vm_global) => Self::Global(vm_global),
+            VMExtern::Tag(vm_tag) => Self::Tag(vm_tag),
         }
     }
 }
@@ -166,6 +172,12 @@ impl From<VMGlobal> for Export {
     }
 }
 
+impl From<VMTag> for Export {
+    fn from(tag: VMTag) -> Self {
+        Self::Tag(tag)
+    }
+}
+
 ///
 /// Import resolver connects imports with available exported values.
 pub trait Resolver {
@@ -176,7 +188,13 @@ pub trait Resolver {
     /// listed in the wasm module.
     ///
     /// The `module` and `field` arguments provided are the module/field names
-    /// listed on the import itself.
+    /// listed on the import itself, and `expected` is the type the module
+    /// expects the import to have.
+    ///
+    /// Resolution is lazy: this is called once for each import of the module,
+    /// in the order of the import section, when instantiating it. Resolvers
+    /// can thus create the exports on demand, e.g. a host function of the
+    /// expected type.
     ///
     /// # Notes:
     ///
@@ -188,7 +206,13 @@ pub trait Resolver {
     ///   (import "" "" (func (param i32) (result i32)))
     /// )
     /// ```
-    fn resolve(&self, _index: u32, module: &str, field: &str) -> Option<Export>;
+    fn resolve(
+        &self,
+        _index: u32,
+        module: &str,
+        field: &str,
+        expected: &ExternType,
+    ) -> Option<Export>;
 }
 
 /// Import resolver connects imports with available exported values.
@@ -207,8 +231,14 @@ pub trait NamedResolver {
 // All NamedResolvers should extend `Resolver`.
 impl<T: NamedResolver> Resolver for T {
     /// By default this method will be calling [`NamedResolver::resolve_by_name`],
-    /// dismissing the provided `index`.
-    fn resolve(&self, _index: u32, module: &str, field: &str) -> Option<Export> {
+    /// dismissing the provided `index` and `expected` type.
+    fn resolve(
+        &self,
+        _index: u32,
+        module: &str,
+        field: &str,
+        _expected: &ExternType,
+    ) -> Option<Export> {
         self.resolve_by_name(module, field)
     }
 }
@@ -236,7 +266,13 @@ impl NamedResolver for () {
 pub struct NullResolver {}
 
 impl Resolver for NullResolver {
-    fn resolve(&self, _idx: u32, _module: &str, _field: &str) -> Option<Export> {
+    fn resolve(
+        &self,
+        _idx: u32,
+        _module: &str,
+        _field: &str,
+        _expected: &ExternType,
+    ) -> Option<Export> {
         None
     }
 }
@@ -19,6 +19,11 @@ impl VMSharedSignatureIndex {
     pub fn new(value: u32) -> Self {
         Self(value)
     }
+
+    /// The value of the index, as compared at indirect calls.
+    pub fn as_u32(self) -> u32 {
+        self.0
+    }
 }
 
 /// WebAssembly requires that the caller and callee signatures in an indirect
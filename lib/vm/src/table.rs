@@ -6,7 +6,7 @@
 //! `Table` is to WebAssembly tables what `LinearMemory` is to WebAssembly linear memories.
 
 use crate::func_data_registry::VMFuncRef;
-use crate::trap::{Trap, TrapCode};
+use crate::trap::{defer_externref_drop, Trap, TrapCode};
 use crate::vmcontext::VMTableDefinition;
 use crate::VMExternRef;
 use std::borrow::{Borrow, BorrowMut};
@@ -283,6 +283,16 @@ impl LinearTable {
     }
 }
 
+impl Drop for LinearTable {
+    fn drop(&mut self) {
+        if self.table.ty == ValType::ExternRef {
+            for element in self.vec.get_mut().unwrap().iter_mut() {
+                unsafe { element.extern_ref.ref_drop() }
+            }
+        }
+    }
+}
+
 impl Table for LinearTable {
     /// Returns the type for this Table.
     fn ty(&self) -> &TableType {
@@ -374,12 +384,10 @@ impl Table for LinearTable {
             Some(slot) => {
                 match (self.table.ty, reference) {
                     (ValType::ExternRef, TableElement::ExternRef(extern_ref)) => {
-                        let extern_ref = extern_ref.into();
-                        unsafe {
-                            let elem = &mut *slot;
-                            elem.extern_ref.ref_drop();
-                            elem.extern_ref = extern_ref
-                        }
+                        // The Wasm code may still use the reference the slot held.
+                        let old =
+                            std::mem::replace(unsafe { &mut slot.extern_ref }, extern_ref.into());
+                        defer_externref_drop(old.into());
                     }
                     (ValType::FuncRef, r @ TableElement::FuncRef(_)) => {
                         let element_data = r.into();
@@ -64,3 +64,60 @@ void wasmer_unwind(void *JmpBuf) {
   platform_jmp_buf *buf = (platform_jmp_buf*) JmpBuf;
   platform_longjmp(*buf, 1);
 }
+
+#if defined(__x86_64__) && !defined(CFG_TARGET_OS_WINDOWS)
+#include <stdint.h>
+#include <unwind.h>
+
+struct landing_pad_search {
+  uintptr_t limit;
+  uintptr_t (*lookup)(uintptr_t);
+  uintptr_t pad;
+  uintptr_t frame_pointer;
+};
+
+static _Unwind_Reason_Code search_frame(struct _Unwind_Context *ctx, void *arg) {
+  struct landing_pad_search *search = (struct landing_pad_search*) arg;
+  // The frames past the `wasmer_register_setjmp` of the current call belong to its caller.
+  if (_Unwind_GetCFA(ctx) > search->limit) {
+    return _URC_NORMAL_STOP;
+  }
+  uintptr_t pad = search->lookup(_Unwind_GetIP(ctx));
+  if (pad != 0) {
+    search->pad = pad;
+    // The landing pads find their frame through RBP, DWARF register 6.
+    search->frame_pointer = _Unwind_GetGR(ctx, 6);
+    return _URC_NORMAL_STOP;
+  }
+  return _URC_NO_REASON;
+}
+
+// Walks the stack up to `limit`, the jump buffer of the current call, for the innermost return
+// address `lookup` knows the landing pad of.
+int wasmer_find_landing_pad(
+    void *limit,
+    uintptr_t (*lookup)(uintptr_t),
+    uintptr_t *pad,
+    uintptr_t *frame_pointer) {
+  struct landing_pad_search search = { (uintptr_t) limit, lookup, 0, 0 };
+  _Unwind_Backtrace(search_frame, &search);
+  if (search.pad == 0) {
+    return 0;
+  }
+  *pad = search.pad;
+  *frame_pointer = search.frame_pointer;
+  return 1;
+}
+
+// Jumps to the landing pad `pad` of the frame at `frame_pointer`, with the exception in RAX. The
+// landing pad resets RSP from RBP itself.
+void wasmer_resume_landing_pad(uintptr_t pad, uintptr_t frame_pointer, void *exception) {
+  __asm__ volatile(
+    "mov %%rdx, %%rbp\n\t"
+    "jmp *%%rcx"
+    :
+    : "c"(pad), "d"(frame_pointer), "a"(exception)
+    : "memory");
+  __builtin_unreachable();
+}
+#endif
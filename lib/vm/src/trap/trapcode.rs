@@ -69,6 +69,15 @@ pub enum TrapCode {
 
     /// Hit the gas limit.
     GasExceeded = 12,
+
+    /// A `memory.atomic.wait` was attempted on a memory that is not shared.
+    AtomicWaitNonSharedMemory = 13,
+
+    /// Code compiled with metering ran out of fuel.
+    OutOfFuel = 14,
+
+    /// Code compiled with epoch interruption ran past the epoch deadline of its instance.
+    EpochDeadlineExceeded = 15,
 }
 
 impl TrapCode {
@@ -88,6 +97,9 @@ impl TrapCode {
             Self::UnreachableCodeReached => "unreachable",
             Self::UnalignedAtomic => "unaligned atomic access",
             Self::GasExceeded => "gas limit exceeded",
+            Self::AtomicWaitNonSharedMemory => "atomic wait on non-shared memory",
+            Self::OutOfFuel => "out of fuel",
+            Self::EpochDeadlineExceeded => "epoch deadline exceeded",
         }
     }
 }
@@ -108,6 +120,9 @@ impl Display for TrapCode {
             Self::UnreachableCodeReached => "unreachable",
             Self::UnalignedAtomic => "unalign_atom",
             Self::GasExceeded => "out_of_gas",
+            Self::AtomicWaitNonSharedMemory => "wait_non_shared",
+            Self::OutOfFuel => "out_of_fuel",
+            Self::EpochDeadlineExceeded => "epoch_deadline",
         };
         f.write_str(identifier)
     }
@@ -130,6 +145,9 @@ impl FromStr for TrapCode {
             "bad_toint" => Ok(Self::BadConversionToInteger),
             "unreachable" => Ok(Self::UnreachableCodeReached),
             "unalign_atom" => Ok(Self::UnalignedAtomic),
+            "wait_non_shared" => Ok(Self::AtomicWaitNonSharedMemory),
+            "out_of_fuel" => Ok(Self::OutOfFuel),
+            "epoch_deadline" => Ok(Self::EpochDeadlineExceeded),
             _ => Err(()),
         }
     }
@@ -140,7 +158,7 @@ mod tests {
     use super::*;
 
     // Everything but user-defined codes.
-    const CODES: [TrapCode; 12] = [
+    const CODES: [TrapCode; 15] = [
         TrapCode::StackOverflow,
         TrapCode::HeapAccessOutOfBounds,
         TrapCode::HeapMisaligned,
@@ -153,6 +171,9 @@ mod tests {
         TrapCode::BadConversionToInteger,
         TrapCode::UnreachableCodeReached,
         TrapCode::UnalignedAtomic,
+        TrapCode::AtomicWaitNonSharedMemory,
+        TrapCode::OutOfFuel,
+        TrapCode::EpochDeadlineExceeded,
     ];
 
     #[test]
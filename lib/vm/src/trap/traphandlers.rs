@@ -5,14 +5,17 @@
 //! signalhandling mechanisms.
 
 use super::trapcode::TrapCode;
+use crate::exception::VMException;
 use crate::vmcontext::{VMFunctionBody, VMFunctionEnvironment, VMTrampoline};
 use backtrace::Backtrace;
 use std::any::Any;
-use std::cell::{Cell, UnsafeCell};
+use std::cell::{Cell, RefCell, UnsafeCell};
 use std::error::Error;
+use std::fmt;
 use std::mem::{self, MaybeUninit};
 use std::ptr;
 pub use tls::TlsRestore;
+use wasmer_types::ExternRef;
 
 extern "C" {
     fn wasmer_register_setjmp(
@@ -21,6 +24,15 @@ extern "C" {
         payload: *mut u8,
     ) -> i32;
     fn wasmer_unwind(jmp_buf: *const u8) -> !;
+    #[cfg(all(target_arch = "x86_64", not(target_os = "windows")))]
+    fn wasmer_find_landing_pad(
+        limit: *const u8,
+        lookup: extern "C" fn(usize) -> usize,
+        pad: *mut usize,
+        frame_pointer: *mut usize,
+    ) -> i32;
+    #[cfg(all(target_arch = "x86_64", not(target_os = "windows")))]
+    fn wasmer_resume_landing_pad(pad: usize, frame_pointer: usize, exception: *const u8) -> !;
 }
 
 /// Raises a user-defined trap immediately.
@@ -54,8 +66,63 @@ pub unsafe fn raise_lib_trap(trap: Trap) -> ! {
     tls::with(|info| info.unwrap().unwind_with(UnwindReason::LibTrap(trap)))
 }
 
-/// Carries a Rust panic across wasm code and resumes the panic on the other
-/// side.
+/// Throws a wasm exception immediately.
+///
+/// The stack is unwound to the innermost landing pad of the wasm frames of the current call
+/// into wasm, which gets the exception. Without one, the exception is returned from
+/// `catch_traps` below as a [`Trap::Exception`].
+///
+/// # Safety
+///
+/// Only safe to call when wasm code is on the stack, aka `catch_traps` must
+/// have been previous called and not yet returned.
+/// Additionally no Rust destructors may be on the stack.
+/// They will be skipped and not executed.
+pub unsafe fn raise_exception(exception: VMException) -> ! {
+    tls::with(|info| {
+        let info = info.unwrap();
+        #[cfg(all(target_arch = "x86_64", not(target_os = "windows")))]
+        {
+            let mut pad = 0;
+            let mut frame_pointer = 0;
+            let found = wasmer_find_landing_pad(
+                info.jmp_buf.get(),
+                lookup_landing_pad,
+                &mut pad,
+                &mut frame_pointer,
+            );
+            if found != 0 {
+                let ptr = exception.as_ptr();
+                keep_exception_alive(info, exception);
+                wasmer_resume_landing_pad(pad, frame_pointer, ptr);
+            }
+        }
+        info.unwind_with(UnwindReason::LibTrap(Trap::Exception(exception)))
+    })
+}
+
+#[cfg(all(target_arch = "x86_64", not(target_os = "windows")))]
+extern "C" fn lookup_landing_pad(return_address: usize) -> usize {
+    crate::exception::lookup_landing_pad(return_address).unwrap_or(0)
+}
+
+/// Keep `exception` alive until the current call into wasm returns: the compiled code only
+/// refers to it by its pointer.
+///
+/// # Safety
+///
+/// Only safe to call when wasm code is on the stack, aka `catch_traps` must
+/// have been previous called and not yet returned.
+pub(crate) unsafe fn defer_exception_drop(exception: VMException) {
+    tls::with(|info| keep_exception_alive(info.unwrap(), exception))
+}
+
+fn keep_exception_alive(state: &CallThreadState, exception: VMException) {
+    state.exceptions.borrow_mut().push(exception);
+}
+
+/// Carries a Rust panic across wasm code, to be returned as a [`Trap::HostPanic`] on the
+/// other side.
 ///
 /// # Safety
 ///
@@ -82,6 +149,9 @@ pub enum Trap {
         backtrace: Backtrace,
         /// Optional trapcode associated to the signal that caused the trap
         signal_trap: Option<TrapCode>,
+        /// The wasm address of the access, for the out-of-bounds memory accesses, without
+        /// its offset
+        memory_address: Option<u64>,
     },
 
     /// A trap raised from a wasm libcall
@@ -101,6 +171,40 @@ pub enum Trap {
         /// Native stack backtrace at the time the OOM occurred
         backtrace: Backtrace,
     },
+
+    /// A panic of a host function called from Wasm, caught before it could unwind through the
+    /// Wasm frames.
+    HostPanic {
+        /// The message of the panic, when its payload is a string.
+        message: String,
+        /// The payload of the panic, to resume it with.
+        payload: PanicPayload,
+        /// Native stack backtrace at the time the panic was caught
+        backtrace: Backtrace,
+    },
+
+    /// A wasm exception no handler caught.
+    Exception(VMException),
+}
+
+/// The payload of a panic, which can only be taken out by value.
+pub struct PanicPayload(Box<dyn Any + Send>);
+
+// SAFETY: the payload is never accessed through a shared reference, so sharing a
+// `PanicPayload` between threads shares nothing.
+unsafe impl Sync for PanicPayload {}
+
+impl PanicPayload {
+    /// Return the payload, to resume the panic with `std::panic::resume_unwind`.
+    pub fn into_inner(self) -> Box<dyn Any + Send> {
+        self.0
+    }
+}
+
+impl fmt::Debug for PanicPayload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("PanicPayload").finish()
+    }
 }
 
 impl Trap {
@@ -112,6 +216,7 @@ impl Trap {
             pc,
             backtrace,
             signal_trap,
+            memory_address: None,
         }
     }
 
@@ -126,6 +231,24 @@ impl Trap {
         }
     }
 
+    /// Construct a new host panic trap with the payload of the panic.
+    ///
+    /// Internally saves a backtrace when constructed.
+    pub fn host_panic(payload: Box<dyn Any + Send>) -> Self {
+        let message = if let Some(message) = payload.downcast_ref::<&str>() {
+            message.to_string()
+        } else if let Some(message) = payload.downcast_ref::<String>() {
+            message.clone()
+        } else {
+            "Box<dyn Any>".to_string()
+        };
+        Self::HostPanic {
+            message,
+            payload: PanicPayload(payload),
+            backtrace: Backtrace::new_unresolved(),
+        }
+    }
+
     /// Construct a new OOM trap with the given source location and trap code.
     ///
     /// Internally saves a backtrace when constructed.
@@ -209,12 +332,44 @@ where
     Ok(global_results.assume_init())
 }
 
+/// Release `externref` when the current call into Wasm returns, rather than now: the Wasm code
+/// may still use it without holding a count of it. It is released immediately outside of
+/// calls into Wasm.
+pub fn defer_externref_drop(externref: ExternRef) {
+    if externref.is_null() {
+        return;
+    }
+    tls::with(|state| {
+        if let Some(state) = state {
+            state.externrefs.borrow_mut().push(externref);
+        }
+    })
+}
+
+/// Install `state` as the trap handling state of this thread, and return the previous
+/// one. Fibers use this to swap the states of the stacks they switch between.
+///
+/// # Safety
+///
+/// `state` must be null or the innermost state of a stack executing on this thread.
+pub(crate) unsafe fn swap_call_thread_state(
+    state: *const CallThreadState,
+) -> *const CallThreadState {
+    tls::swap(state)
+}
+
 /// Temporary state stored on the stack which is registered in the `tls` module
 /// below for calls into wasm.
 pub struct CallThreadState {
     unwind: UnsafeCell<MaybeUninit<UnwindReason>>,
     jmp_buf: Cell<*const u8>,
     prev: Cell<tls::Ptr>,
+    /// The activation table of the call: the references the Wasm code may still use without
+    /// holding a count of them, released when the outermost call returns or traps.
+    externrefs: RefCell<Vec<ExternRef>>,
+    /// The exceptions thrown or created during the call, which the Wasm code refers to by
+    /// their pointer, released like `externrefs`.
+    exceptions: RefCell<Vec<VMException>>,
 }
 
 enum UnwindReason {
@@ -229,6 +384,7 @@ enum UnwindReason {
         backtrace: Backtrace,
         pc: usize,
         signal_trap: Option<TrapCode>,
+        memory_address: Option<u64>,
     },
 }
 
@@ -239,12 +395,28 @@ impl<'a> CallThreadState {
             unwind: UnsafeCell::new(MaybeUninit::uninit()),
             jmp_buf: Cell::new(ptr::null()),
             prev: Cell::new(ptr::null()),
+            externrefs: RefCell::new(Vec::new()),
+            exceptions: RefCell::new(Vec::new()),
         }
     }
 
     fn with(self, closure: impl FnOnce(&Self) -> i32) -> Result<(), Trap> {
         let ret = tls::set(&self, || closure(&self))?;
+        // The caller may still read the references the call returned, so they are kept alive
+        // by the enclosing call, if any. They are released with `self` if it traps.
         if ret != 0 {
+            tls::with(|enclosing| {
+                if let Some(enclosing) = enclosing {
+                    enclosing
+                        .externrefs
+                        .borrow_mut()
+                        .append(&mut self.externrefs.borrow_mut());
+                    enclosing
+                        .exceptions
+                        .borrow_mut()
+                        .append(&mut self.exceptions.borrow_mut());
+                }
+            });
             return Ok(());
         }
         // We will only reach this path if ret == 0. And that will
@@ -258,8 +430,14 @@ impl<'a> CallThreadState {
                 backtrace,
                 pc,
                 signal_trap,
-            } => Err(Trap::wasm(pc, backtrace, signal_trap)),
-            UnwindReason::Panic(panic) => std::panic::resume_unwind(panic),
+                memory_address,
+            } => Err(Trap::Wasm {
+                pc,
+                backtrace,
+                signal_trap,
+                memory_address,
+            }),
+            UnwindReason::Panic(panic) => Err(Trap::host_panic(panic)),
         }
     }
 
@@ -394,6 +572,11 @@ mod tls {
         Ok(closure())
     }
 
+    /// Replaces the pointer configured for this thread, returning the previous one.
+    pub fn swap(val: Ptr) -> Ptr {
+        raw::replace(val).expect("tls should be previously initialized")
+    }
+
     /// Returns the last pointer configured with `set` above. Panics if `set`
     /// has not been previously called and not returned.
     pub fn with<R>(closure: impl FnOnce(Option<&CallThreadState>) -> R) -> R {
@@ -402,7 +585,15 @@ mod tls {
     }
 }
 
-extern "C" fn signal_less_trap_handler(pc: *const u8, trap: TrapCode) {
+/// Raise the trap `trap` of the generated code at `pc`.
+///
+/// The stubs of out-of-bounds memory accesses pass the wasm address of the access as
+/// `memory_address`, which the other stubs leave undefined.
+extern "C" fn signal_less_trap_handler(pc: *const u8, trap: TrapCode, memory_address: u64) {
+    let memory_address = match trap {
+        TrapCode::HeapAccessOutOfBounds => Some(memory_address),
+        _ => None,
+    };
     let jmp_buf = tls::with(|info| {
         let backtrace = Backtrace::new_unresolved();
         let info = info.unwrap();
@@ -413,6 +604,7 @@ extern "C" fn signal_less_trap_handler(pc: *const u8, trap: TrapCode) {
                     backtrace,
                     signal_trap: Some(trap),
                     pc: pc as usize,
+                    memory_address,
                 });
             info.jmp_buf.get()
         }
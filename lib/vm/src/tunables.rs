@@ -1,5 +1,5 @@
-use crate::MemoryError;
-use crate::{Memory, Table};
+use crate::{Memory, ResourceLimiter, Table};
+use crate::{MemoryError, MemoryGrowCallback};
 use crate::{MemoryStyle, TableStyle};
 use crate::{VMMemoryDefinition, VMTableDefinition};
 use std::ptr::NonNull;
@@ -50,4 +50,16 @@ pub trait Tunables {
         style: &TableStyle,
         vm_definition_location: NonNull<VMTableDefinition>,
     ) -> Result<Arc<dyn Table>, String>;
+
+    /// The callback to call before every grow of the memories of instances created with these
+    /// tunables, and of memories grown from the host through a store using them.
+    fn memory_grow_callback(&self) -> Option<Arc<MemoryGrowCallback>> {
+        None
+    }
+
+    /// The limiter deciding whether the memories and tables created with these tunables can
+    /// be created and grow, and whether instances can be created.
+    fn resource_limiter(&self) -> Option<Arc<dyn ResourceLimiter>> {
+        None
+    }
 }
@@ -385,8 +385,8 @@ mod test_vmglobal_import {
 }
 
 /// The fields compiled code needs to access to utilize a WebAssembly linear
-/// memory defined within the instance, namely the start address and the
-/// size in bytes.
+/// memory defined within the instance, namely the start address, the
+/// size in bytes and the size it can grow to without calling the runtime.
 #[derive(Debug, Copy, Clone)]
 #[repr(C)]
 pub struct VMMemoryDefinition {
@@ -395,6 +395,14 @@ pub struct VMMemoryDefinition {
 
     /// The current logical size of this linear memory in bytes.
     pub current_length: usize,
+
+    /// The size in bytes compiled code may grow this linear memory to by itself, by bumping
+    /// `current_length`, which is atomically done for shared memories.
+    ///
+    /// The bytes up to it are accessible and zeroed, and within the maximum of the memory.
+    /// It's `current_length` when every grow must go through the runtime, for example to
+    /// be checked by a callback or a limiter.
+    pub reserved_length: usize,
 }
 
 /// # Safety
@@ -498,6 +506,10 @@ mod test_vmmemory_definition {
             offset_of!(VMMemoryDefinition, current_length),
             usize::from(offsets.vmmemory_definition_current_length())
         );
+        assert_eq!(
+            offset_of!(VMMemoryDefinition, reserved_length),
+            usize::from(offsets.vmmemory_definition_reserved_length())
+        );
     }
 }
 
@@ -607,6 +619,10 @@ mod test_vmglobal_definition {
             size_of::<*const VMGlobalDefinition>(),
             usize::from(offsets.size_of_vmglobal_local())
         );
+        assert_eq!(
+            size_of::<VMGlobalDefinition>(),
+            usize::from(offsets.size_of_vmglobal_definition())
+        );
     }
 
     #[test]
@@ -995,9 +1011,63 @@ impl VMBuiltinFunctionIndex {
     pub const fn get_externref_dec_index() -> Self {
         Self(25)
     }
+    /// Returns an index for wasm's `memory.atomic.wait32` instruction.
+    pub const fn get_memory_atomic_wait32_index() -> Self {
+        Self(26)
+    }
+    /// Returns an index for wasm's `memory.atomic.wait64` instruction.
+    pub const fn get_memory_atomic_wait64_index() -> Self {
+        Self(27)
+    }
+    /// Returns an index for wasm's `memory.atomic.notify` instruction.
+    pub const fn get_memory_atomic_notify_index() -> Self {
+        Self(28)
+    }
+    /// Returns an index for the function called when the epoch deadline is reached.
+    pub const fn get_epoch_deadline_reached_index() -> Self {
+        Self(29)
+    }
+    /// Returns an index for wasm's `global.set` instruction on globals of `externref`s.
+    pub const fn get_externref_global_set_index() -> Self {
+        Self(30)
+    }
+    /// Returns an index for wasm's `throw` instruction.
+    pub const fn get_throw_index() -> Self {
+        Self(31)
+    }
+    /// Returns an index for wasm's `rethrow` instruction, and the exceptions no handler of the
+    /// function catches.
+    pub const fn get_rethrow_index() -> Self {
+        Self(32)
+    }
+    /// Returns an index for the function creating the exceptions thrown to a handler of the
+    /// same function.
+    pub const fn get_exception_new_index() -> Self {
+        Self(33)
+    }
+    /// Returns an index for wasm's `catch` clauses.
+    pub const fn get_exception_catch_index() -> Self {
+        Self(34)
+    }
+    /// Returns an index for wasm's `memory.grow` builtin function on 64-bit memories.
+    pub const fn get_memory64_grow_index() -> Self {
+        Self(35)
+    }
+    /// Returns an index for wasm's imported `memory.grow` builtin function on 64-bit memories.
+    pub const fn get_imported_memory64_grow_index() -> Self {
+        Self(36)
+    }
+    /// Returns an index for wasm's `memory.size` builtin function on 64-bit memories.
+    pub const fn get_memory64_size_index() -> Self {
+        Self(37)
+    }
+    /// Returns an index for wasm's imported `memory.size` builtin function on 64-bit memories.
+    pub const fn get_imported_memory64_size_index() -> Self {
+        Self(38)
+    }
     /// Returns the total number of builtin functions.
     pub const fn builtin_functions_total_number() -> u32 {
-        26
+        39
     }
 
     /// Return the index as an u32 number.
@@ -1079,6 +1149,31 @@ impl VMBuiltinFunctionsArray {
             wasmer_vm_externref_inc as usize;
         ptrs[VMBuiltinFunctionIndex::get_externref_dec_index().index() as usize] =
             wasmer_vm_externref_dec as usize;
+        ptrs[VMBuiltinFunctionIndex::get_memory_atomic_wait32_index().index() as usize] =
+            wasmer_vm_memory32_atomic_wait32 as usize;
+        ptrs[VMBuiltinFunctionIndex::get_memory_atomic_wait64_index().index() as usize] =
+            wasmer_vm_memory32_atomic_wait64 as usize;
+        ptrs[VMBuiltinFunctionIndex::get_memory_atomic_notify_index().index() as usize] =
+            wasmer_vm_memory32_atomic_notify as usize;
+        ptrs[VMBuiltinFunctionIndex::get_epoch_deadline_reached_index().index() as usize] =
+            wasmer_vm_epoch_deadline_reached as usize;
+        ptrs[VMBuiltinFunctionIndex::get_externref_global_set_index().index() as usize] =
+            wasmer_vm_externref_global_set as usize;
+        ptrs[VMBuiltinFunctionIndex::get_throw_index().index() as usize] = wasmer_vm_throw as usize;
+        ptrs[VMBuiltinFunctionIndex::get_rethrow_index().index() as usize] =
+            wasmer_vm_rethrow as usize;
+        ptrs[VMBuiltinFunctionIndex::get_exception_new_index().index() as usize] =
+            wasmer_vm_exception_new as usize;
+        ptrs[VMBuiltinFunctionIndex::get_exception_catch_index().index() as usize] =
+            wasmer_vm_exception_catch as usize;
+        ptrs[VMBuiltinFunctionIndex::get_memory64_grow_index().index() as usize] =
+            wasmer_vm_memory64_grow as usize;
+        ptrs[VMBuiltinFunctionIndex::get_imported_memory64_grow_index().index() as usize] =
+            wasmer_vm_imported_memory64_grow as usize;
+        ptrs[VMBuiltinFunctionIndex::get_memory64_size_index().index() as usize] =
+            wasmer_vm_memory64_size as usize;
+        ptrs[VMBuiltinFunctionIndex::get_imported_memory64_size_index().index() as usize] =
+            wasmer_vm_imported_memory64_size as usize;
 
         debug_assert!(ptrs.iter().cloned().all(|p| p != 0));
 
@@ -10,8 +10,8 @@ use crate::VMBuiltinFunctionIndex;
 use more_asserts::assert_lt;
 use std::convert::TryFrom;
 use wasmer_types::{
-    FunctionIndex, GlobalIndex, LocalGlobalIndex, LocalMemoryIndex, LocalTableIndex, MemoryIndex,
-    ModuleInfo, SignatureIndex, TableIndex,
+    ExportIndex, FunctionIndex, GlobalIndex, GlobalType, LocalGlobalIndex, LocalMemoryIndex,
+    LocalTableIndex, MemoryIndex, ModuleInfo, SignatureIndex, TableIndex, Type,
 };
 
 #[cfg(target_pointer_width = "32")]
@@ -52,6 +52,9 @@ pub struct VMOffsets {
     pub num_local_memories: u32,
     /// The number of defined globals in the module.
     pub num_local_globals: u32,
+    /// Whether each defined global lives in its cell in the `VMContext`, see
+    /// `vmctx_vmglobal_cell`. Missing entries are `false`.
+    pub embedded_globals: Vec<bool>,
     /// If the module has trap handler.
     pub has_trap_handlers: bool,
 }
@@ -72,6 +75,7 @@ impl VMOffsets {
             num_local_tables: 0,
             num_local_memories: 0,
             num_local_globals: 0,
+            embedded_globals: Vec::new(),
             has_trap_handlers: false,
         }
     }
@@ -96,6 +100,11 @@ impl VMOffsets {
         self.num_local_tables = cast_to_u32(module.tables.len());
         self.num_local_memories = cast_to_u32(module.memories.len());
         self.num_local_globals = cast_to_u32(module.globals.len());
+        self.embedded_globals = embedded_globals(
+            module.import_counts.globals,
+            module.globals.values(),
+            module.exports.values(),
+        );
         self.has_trap_handlers = true;
         self
     }
@@ -111,11 +120,41 @@ impl VMOffsets {
         self.num_local_tables = cast_to_u32(module.tables.len());
         self.num_local_memories = cast_to_u32(module.memories.len());
         self.num_local_globals = cast_to_u32(module.globals.len());
+        self.embedded_globals = embedded_globals(
+            module.import_counts.globals,
+            module.globals.values(),
+            module.exports.iter().map(|(_, index)| index),
+        );
         self.has_trap_handlers = true;
         self
     }
 }
 
+/// Which of the defined globals live in their cell in the `VMContext`, at a fixed offset from
+/// it, rather than in a `Global` of their own: those that aren't exported, which only the code
+/// of the instance can access, unless they hold an `externref`, whose count their `Global`
+/// drops.
+///
+/// The exported globals outlive the instance once imported by another one, or held by the host.
+fn embedded_globals<'a>(
+    num_imported_globals: u32,
+    globals: impl Iterator<Item = &'a GlobalType>,
+    exports: impl Iterator<Item = &'a ExportIndex>,
+) -> Vec<bool> {
+    let mut embedded = globals
+        .skip(num_imported_globals as usize)
+        .map(|global| global.ty != Type::ExternRef)
+        .collect::<Vec<_>>();
+    for export in exports {
+        if let ExportIndex::Global(index) = export {
+            if let Some(local) = index.as_u32().checked_sub(num_imported_globals) {
+                embedded[local as usize] = false;
+            }
+        }
+    }
+    embedded
+}
+
 /// Offsets for [`VMFunctionImport`].
 ///
 /// [`VMFunctionImport`]: crate::vmcontext::VMFunctionImport
@@ -271,11 +310,16 @@ impl VMOffsets {
         4
     }
 
+    /// The offset of the `reserved_length` field.
+    pub const fn vmmemory_definition_reserved_length(&self) -> u8 {
+        2 * self.pointer_size
+    }
+
     /// Return the size of [`VMMemoryDefinition`].
     ///
     /// [`VMMemoryDefinition`]: crate::vmcontext::VMMemoryDefinition
     pub const fn size_of_vmmemory_definition(&self) -> u8 {
-        2 * self.pointer_size
+        3 * self.pointer_size
     }
 }
 
@@ -317,6 +361,13 @@ impl VMOffsets {
     pub const fn size_of_vmglobal_local(&self) -> u8 {
         self.pointer_size
     }
+
+    /// Return the size of a [`VMGlobalDefinition`] embedded in the `VMContext`.
+    ///
+    /// [`VMGlobalDefinition`]: crate::vmcontext::VMGlobalDefinition
+    pub const fn size_of_vmglobal_definition(&self) -> u8 {
+        16
+    }
 }
 
 /// Offsets for [`VMSharedSignatureIndex`].
@@ -468,12 +519,25 @@ impl VMOffsets {
         align(offset, 16)
     }
 
+    /// The offset of the cells of the globals embedded in the `VMContext`.
+    pub fn vmctx_global_cells_begin(&self) -> u32 {
+        let offset = self
+            .vmctx_globals_begin()
+            .checked_add(
+                self.num_local_globals
+                    .checked_mul(u32::from(self.size_of_vmglobal_local()))
+                    .unwrap(),
+            )
+            .unwrap();
+        align(offset, 16)
+    }
+
     /// The offset of the builtin functions array.
     pub fn vmctx_builtin_functions_begin(&self) -> u32 {
-        self.vmctx_globals_begin()
+        self.vmctx_global_cells_begin()
             .checked_add(
                 self.num_local_globals
-                    .checked_mul(u32::from(self.size_of_vmglobal_local()))
+                    .checked_mul(u32::from(self.size_of_vmglobal_definition()))
                     .unwrap(),
             )
             .unwrap()
@@ -513,13 +577,46 @@ impl VMOffsets {
         self.vmctx_stack_limit_begin().checked_add(4).unwrap()
     }
 
+    /// The offset of the lowest address the native stack pointer may reach.
+    pub fn vmctx_native_stack_limit(&self) -> u32 {
+        align(
+            self.vmctx_stack_limit_initial_begin()
+                .checked_add(4)
+                .unwrap(),
+            u32::from(self.pointer_size),
+        )
+    }
+
+    /// The offset of the fuel left to the code compiled with metering.
+    pub fn vmctx_fuel(&self) -> u32 {
+        align(
+            self.vmctx_native_stack_limit()
+                .checked_add(u32::from(self.pointer_size))
+                .unwrap(),
+            8,
+        )
+    }
+
+    /// The offset of the pointer to the epoch counter.
+    pub fn vmctx_epoch_pointer(&self) -> u32 {
+        self.vmctx_fuel().checked_add(8).unwrap()
+    }
+
+    /// The offset of the epoch deadline.
+    pub fn vmctx_epoch_deadline(&self) -> u32 {
+        align(
+            self.vmctx_epoch_pointer()
+                .checked_add(u32::from(self.pointer_size))
+                .unwrap(),
+            8,
+        )
+    }
+
     /// Return the size of the [`VMContext`] allocation.
     ///
     /// [`VMContext`]: crate::vmcontext::VMContext
     pub fn size_of_vmctx(&self) -> u32 {
-        self.vmctx_stack_limit_initial_begin()
-            .checked_add(4)
-            .unwrap()
+        self.vmctx_epoch_deadline().checked_add(8).unwrap()
     }
 
     /// Return the offset to [`VMSharedSignatureIndex`] index `index`.
@@ -642,6 +739,30 @@ impl VMOffsets {
             .unwrap()
     }
 
+    /// Return the offset to the [`VMGlobalDefinition`] index `index` itself, if it is embedded
+    /// in the `VMContext`.
+    ///
+    /// The pointer at `vmctx_vmglobal_definition` then points there, and code can access the
+    /// global at a fixed offset from the `VMContext` instead.
+    ///
+    /// [`VMGlobalDefinition`]: crate::vmcontext::VMGlobalDefinition
+    pub fn vmctx_vmglobal_cell(&self, index: LocalGlobalIndex) -> Option<u32> {
+        assert_lt!(index.as_u32(), self.num_local_globals);
+        if !self
+            .embedded_globals
+            .get(index.as_u32() as usize)
+            .copied()
+            .unwrap_or(false)
+        {
+            return None;
+        }
+        let offset = index
+            .as_u32()
+            .checked_mul(u32::from(self.size_of_vmglobal_definition()))
+            .unwrap();
+        Some(self.vmctx_global_cells_begin().checked_add(offset).unwrap())
+    }
+
     /// Return the offset to the `body` field in `*const VMFunctionBody` index `index`.
     pub fn vmctx_vmfunction_import_body(&self, index: FunctionIndex) -> u32 {
         self.vmctx_vmfunction_import(index)
@@ -767,6 +888,11 @@ impl TargetSharedSignatureIndex {
 #[cfg(test)]
 mod tests {
     use crate::vmoffsets::align;
+    use crate::VMOffsets;
+    use wasmer_types::{
+        ExportIndex, GlobalIndex, GlobalType, ImportIndex, LocalGlobalIndex, ModuleInfo,
+        Mutability, Type,
+    };
 
     #[test]
     fn alignment() {
@@ -778,4 +904,41 @@ mod tests {
         assert!(is_aligned(align(33, 16)));
         assert!(is_aligned(align(31, 16)));
     }
+
+    #[test]
+    fn only_the_private_globals_are_embedded() {
+        let mut module = ModuleInfo::new();
+        let mut global = |ty| module.globals.push(GlobalType::new(ty, Mutability::Var));
+        let imported = global(Type::I64);
+        let private = global(Type::I64);
+        let exported = global(Type::F64);
+        let externref = global(Type::ExternRef);
+        let v128 = global(Type::V128);
+        module.imports.insert(
+            ("env".to_string(), "global".to_string(), 0),
+            ImportIndex::Global(imported),
+        );
+        module.import_counts.globals = 1;
+        module
+            .exports
+            .insert("exported".to_string(), ExportIndex::Global(exported));
+        let offsets = VMOffsets::new(8).with_module_info(&module);
+        let local = |index: GlobalIndex| LocalGlobalIndex::from_u32(index.as_u32() - 1);
+
+        let cells = offsets.vmctx_global_cells_begin();
+        assert_eq!(cells % 16, 0);
+        let num_cells = offsets.num_local_globals;
+        assert!(cells >= offsets.vmctx_globals_begin() + num_cells * 8);
+        assert_eq!(offsets.vmctx_vmglobal_cell(local(private)), Some(cells));
+        assert_eq!(offsets.vmctx_vmglobal_cell(local(exported)), None);
+        assert_eq!(offsets.vmctx_vmglobal_cell(local(externref)), None);
+        assert_eq!(
+            offsets.vmctx_vmglobal_cell(local(v128)),
+            Some(cells + 3 * 16)
+        );
+        assert_eq!(
+            offsets.vmctx_builtin_functions_begin(),
+            cells + num_cells * 16
+        );
+    }
 }
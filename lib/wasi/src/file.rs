@@ -0,0 +1,112 @@
+//! The files WASI programs read and write.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::sync::{Arc, Mutex};
+
+fn unsupported<T>(operation: &str) -> io::Result<T> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("the file doesn't support {}", operation),
+    ))
+}
+
+/// A file a WASI program can use as one of its standard streams.
+///
+/// The operations the file doesn't support fail with [`io::ErrorKind::Unsupported`], which
+/// is the default.
+pub trait WasiFile: fmt::Debug + Send {
+    /// Read from the file into `buf`, and return how many bytes were read.
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        unsupported("reads")
+    }
+
+    /// Write `buf` to the file, and return how many bytes were written.
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        unsupported("writes")
+    }
+
+    /// Move the cursor of the file to `pos`, and return its offset from the start.
+    fn seek(&mut self, _pos: SeekFrom) -> io::Result<u64> {
+        unsupported("seeks")
+    }
+}
+
+impl WasiFile for fs::File {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        Read::read(self, buf)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Write::write(self, buf)
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        Seek::seek(self, pos)
+    }
+}
+
+impl WasiFile for io::Stdin {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        Read::read(self, buf)
+    }
+}
+
+impl WasiFile for io::Stdout {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut stdout = self.lock();
+        let written = stdout.write(buf)?;
+        stdout.flush()?;
+        Ok(written)
+    }
+}
+
+impl WasiFile for io::Stderr {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Write::write(self, buf)
+    }
+}
+
+/// An in-memory pipe: the bytes written to it are read from it, in order.
+///
+/// The clones of a pipe share its bytes, so that the host can keep a clone of the pipe it
+/// gives to a program, to feed its input or collect its output.
+#[derive(Debug, Clone, Default)]
+pub struct Pipe {
+    buffer: Arc<Mutex<VecDeque<u8>>>,
+}
+
+impl Pipe {
+    /// Create an empty pipe.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append `data` to the bytes of the pipe.
+    pub fn push(&self, data: &[u8]) {
+        self.buffer.lock().unwrap().extend(data);
+    }
+
+    /// Remove all the bytes of the pipe, and return them.
+    pub fn take(&self) -> Vec<u8> {
+        self.buffer.lock().unwrap().drain(..).collect()
+    }
+}
+
+impl WasiFile for Pipe {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut buffer = self.buffer.lock().unwrap();
+        let len = buf.len().min(buffer.len());
+        for (dst, src) in buf.iter_mut().zip(buffer.drain(..len)) {
+            *dst = src;
+        }
+        Ok(len)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.push(buf);
+        Ok(buf.len())
+    }
+}
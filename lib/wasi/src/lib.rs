@@ -0,0 +1,98 @@
+//! Run WebAssembly modules targeting WASI `wasi_snapshot_preview1`, the ones compiled by
+//! `clang --target=wasm32-wasi` or `cargo build --target wasm32-wasi`, with Wasmer.
+//!
+//! The program sees the arguments, environment variables and directories of its
+//! [`WasiState`], which are the only host resources it can access: the paths it opens are
+//! resolved in its preopened directories, and can't go out of them. The paths are resolved
+//! with `openat`, which makes the crate available on Unix hosts only.
+//!
+//! # Example
+//!
+//! ```
+//! use wasmer::{Instance, Module, Store};
+//! use wasmer_wasi::{wasi_import_object, Pipe, WasiExit, WasiState};
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! let wat = r#"
+//!     (import "wasi_snapshot_preview1" "fd_write"
+//!         (func $fd_write (param i32 i32 i32 i32) (result i32)))
+//!     (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+//!     (memory (export "memory") 1)
+//!     (data (i32.const 0) "\08\00\00\00\06\00\00\00hello\n")
+//!     (func (export "_start")
+//!         (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 16)))
+//!         (call $proc_exit (i32.const 3)))
+//! "#;
+//! let store = Store::default();
+//! let module = Module::new(&store, wat)?;
+//! let stdout = Pipe::new();
+//! let state = WasiState::builder("hello").stdout(stdout.clone()).build()?;
+//! let instance = Instance::new(&module, &wasi_import_object(&store, &state, &module))?;
+//!
+//! let start = instance.get_native_function::<(), ()>("_start")?;
+//! let err = start.call().unwrap_err();
+//! assert_eq!(err.downcast::<WasiExit>().ok(), Some(WasiExit(3)));
+//! assert_eq!(stdout.take(), b"hello\n");
+//! # Ok(())
+//! # }
+//! ```
+
+#![deny(missing_docs, unused_extern_crates)]
+
+mod file;
+mod state;
+mod syscalls;
+mod types;
+
+pub use crate::file::{Pipe, WasiFile};
+pub use crate::state::{WasiState, WasiStateBuilder, WasiStateCreationError};
+
+use crate::syscalls::{insert_syscalls, WasiEnv};
+use crate::types::Errno;
+use thiserror::Error;
+use wasmer::{
+    Exports, ExternType, Function, ImportObject, Module, RuntimeError, Store, Type, Value,
+};
+
+/// The namespace of the imports of WASI.
+pub const WASI_MODULE: &str = "wasi_snapshot_preview1";
+
+/// The error a call fails with when the program exits with `proc_exit`, with its status
+/// code.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("the WASI program exited with the status code {0}")]
+pub struct WasiExit(pub u32);
+
+/// Create the imports of `module` from the namespace of WASI, the syscalls using `state`.
+///
+/// The syscalls this crate doesn't implement fail with `ENOSYS` when the module calls them,
+/// rather than preventing its instantiation.
+pub fn wasi_import_object(store: &Store, state: &WasiState, module: &Module) -> ImportObject {
+    let mut namespace = Exports::new();
+    for import in module.imports() {
+        if import.module() != WASI_MODULE {
+            continue;
+        }
+        if let ExternType::Function(ty) = import.ty() {
+            let returns_errno = ty.results() == [Type::I32];
+            let name = import.name().to_string();
+            let unsupported = Function::new(store, ty.clone(), move |_| {
+                if returns_errno {
+                    Ok(vec![Value::I32(i32::from(Errno::NOSYS.0))])
+                } else {
+                    Err(RuntimeError::new(format!(
+                        "the WASI syscall `{}` isn't supported",
+                        name
+                    )))
+                }
+            });
+            namespace.insert(import.name(), unsupported);
+        }
+    }
+    // Inserted last, the syscalls replace the unsupported functions of the same name.
+    insert_syscalls(store, &WasiEnv::new(state.clone()), &mut namespace);
+
+    let mut import_object = ImportObject::new();
+    import_object.register(WASI_MODULE, namespace);
+    import_object
+}
@@ -0,0 +1,502 @@
+//! The state of a WASI program: its arguments, environment and file descriptors.
+
+use crate::file::WasiFile;
+use crate::types::*;
+use std::collections::{BTreeMap, VecDeque};
+use std::ffi::{CStr, CString, OsString};
+use std::fs;
+use std::io;
+use std::os::raw::{c_int, c_uint};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+use thiserror::Error;
+
+/// An error building a [`WasiState`].
+#[derive(Error, Debug)]
+pub enum WasiStateCreationError {
+    /// An argument contains a nul byte.
+    #[error("the argument `{0}` contains a nul byte")]
+    ArgumentContainsNulByte(String),
+    /// An environment variable has an empty key, a key containing `=` or a nul byte.
+    #[error("the environment variable `{0}` isn't of the form `KEY=VALUE` without nul bytes")]
+    EnvironmentVariableFormatError(String),
+    /// A preopened directory can't be opened.
+    #[error("the directory `{}` can't be preopened: {error}", .path.display())]
+    PreopenedDirectoryError {
+        /// The path of the directory on the host.
+        path: PathBuf,
+        /// Why the directory can't be opened.
+        error: io::Error,
+    },
+}
+
+/// The state of a WASI program, shared by the imports created by
+/// [`wasi_import_object`](crate::wasi_import_object).
+///
+/// The clones of a state are handles to the same state, so that several instances given
+/// imports with the same state share their file descriptors.
+#[derive(Clone)]
+pub struct WasiState {
+    inner: Arc<Mutex<WasiInner>>,
+}
+
+impl WasiState {
+    /// Start building the state of the program `program`, its first argument.
+    pub fn builder(program: &str) -> WasiStateBuilder {
+        WasiStateBuilder {
+            args: vec![program.to_string()],
+            envs: vec![],
+            preopens: vec![],
+            stdin: None,
+            stdout: None,
+            stderr: None,
+        }
+    }
+
+    pub(crate) fn lock(&self) -> MutexGuard<'_, WasiInner> {
+        self.inner.lock().unwrap()
+    }
+}
+
+/// The builder of a [`WasiState`], created by [`WasiState::builder`].
+pub struct WasiStateBuilder {
+    args: Vec<String>,
+    envs: Vec<(String, String)>,
+    /// The preopened directories, with the names the program sees them by.
+    preopens: Vec<(String, PathBuf)>,
+    stdin: Option<Box<dyn WasiFile>>,
+    stdout: Option<Box<dyn WasiFile>>,
+    stderr: Option<Box<dyn WasiFile>>,
+}
+
+impl WasiStateBuilder {
+    /// Add an argument, after the program and the arguments added before.
+    pub fn arg(&mut self, arg: &str) -> &mut Self {
+        self.args.push(arg.to_string());
+        self
+    }
+
+    /// Add several arguments, after the program and the arguments added before.
+    pub fn args<I, S>(&mut self, args: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        for arg in args {
+            self.arg(arg.as_ref());
+        }
+        self
+    }
+
+    /// Add the environment variable `key`, whose value is `value`.
+    pub fn env(&mut self, key: &str, value: &str) -> &mut Self {
+        self.envs.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Let the program access the directory `path` of the host, by the same path.
+    pub fn preopen_dir(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        let path = path.as_ref();
+        self.map_dir(&path.to_string_lossy(), path)
+    }
+
+    /// Let the program access the directory `path` of the host, as the directory `alias`.
+    ///
+    /// The paths the program opens in the directory are resolved in it: they can't refer
+    /// to files outside of it, whether with `..` or with symbolic links.
+    pub fn map_dir(&mut self, alias: &str, path: impl AsRef<Path>) -> &mut Self {
+        self.preopens
+            .push((alias.to_string(), path.as_ref().to_path_buf()));
+        self
+    }
+
+    /// Replace the standard input of the program, which is the one of the host by default.
+    pub fn stdin(&mut self, file: impl WasiFile + 'static) -> &mut Self {
+        self.stdin = Some(Box::new(file));
+        self
+    }
+
+    /// Replace the standard output of the program, which is the one of the host by default.
+    pub fn stdout(&mut self, file: impl WasiFile + 'static) -> &mut Self {
+        self.stdout = Some(Box::new(file));
+        self
+    }
+
+    /// Replace the standard error of the program, which is the one of the host by default.
+    pub fn stderr(&mut self, file: impl WasiFile + 'static) -> &mut Self {
+        self.stderr = Some(Box::new(file));
+        self
+    }
+
+    /// Build the state. The standard streams given to the builder are moved to the state,
+    /// and the following states it builds use the ones of the host.
+    pub fn build(&mut self) -> Result<WasiState, WasiStateCreationError> {
+        let mut args = vec![];
+        for arg in &self.args {
+            if arg.contains('\0') {
+                return Err(WasiStateCreationError::ArgumentContainsNulByte(arg.clone()));
+            }
+            args.push(arg.clone().into_bytes());
+        }
+        let mut envs = vec![];
+        for (key, value) in &self.envs {
+            let env = format!("{}={}", key, value);
+            if key.is_empty() || key.contains('=') || env.contains('\0') {
+                return Err(WasiStateCreationError::EnvironmentVariableFormatError(env));
+            }
+            envs.push(env.into_bytes());
+        }
+
+        let mut fds = BTreeMap::new();
+        let stdio: Vec<Box<dyn WasiFile>> = vec![
+            self.stdin.take().unwrap_or_else(|| Box::new(io::stdin())),
+            self.stdout.take().unwrap_or_else(|| Box::new(io::stdout())),
+            self.stderr.take().unwrap_or_else(|| Box::new(io::stderr())),
+        ];
+        for (fd, file) in (0..).zip(stdio) {
+            let filetype = FILETYPE_CHARACTER_DEVICE;
+            fds.insert(fd, Fd::File { file, filetype });
+        }
+        for (fd, (alias, path)) in (3..).zip(&self.preopens) {
+            let error = |error| WasiStateCreationError::PreopenedDirectoryError {
+                path: path.clone(),
+                error,
+            };
+            let root = fs::File::open(path).map_err(error)?;
+            if !root.metadata().map_err(error)?.is_dir() {
+                let not_a_dir = io::Error::new(io::ErrorKind::Other, "not a directory");
+                return Err(error(not_a_dir));
+            }
+            let dir = Dir {
+                root: Arc::new(root),
+                path: PathBuf::new(),
+                preopen: Some(alias.clone()),
+            };
+            fds.insert(fd, Fd::Dir(dir));
+        }
+
+        Ok(WasiState {
+            inner: Arc::new(Mutex::new(WasiInner {
+                args,
+                envs,
+                fds,
+                start: Instant::now(),
+            })),
+        })
+    }
+}
+
+pub(crate) struct WasiInner {
+    /// The arguments, without their nul terminators.
+    pub(crate) args: Vec<Vec<u8>>,
+    /// The environment variables as `KEY=VALUE`, without their nul terminators.
+    pub(crate) envs: Vec<Vec<u8>>,
+    pub(crate) fds: BTreeMap<u32, Fd>,
+    /// The origin of the monotonic clock.
+    pub(crate) start: Instant,
+}
+
+impl WasiInner {
+    /// Add `fd` to the file descriptors, as the lowest free number.
+    pub(crate) fn insert_fd(&mut self, fd: Fd) -> u32 {
+        let number = (0..)
+            .find(|number| !self.fds.contains_key(number))
+            .expect("the program opened too many files");
+        self.fds.insert(number, fd);
+        number
+    }
+
+    /// Get the file open as `fd`.
+    pub(crate) fn file(&mut self, fd: u32) -> Result<&mut Box<dyn WasiFile>, Errno> {
+        match self.fds.get_mut(&fd) {
+            Some(Fd::File { file, .. }) => Ok(file),
+            Some(Fd::Dir(_)) => Err(Errno::ISDIR),
+            None => Err(Errno::BADF),
+        }
+    }
+
+    /// Get the directory open as `fd`.
+    pub(crate) fn dir(&self, fd: u32) -> Result<&Dir, Errno> {
+        match self.fds.get(&fd) {
+            Some(Fd::Dir(dir)) => Ok(dir),
+            Some(Fd::File { .. }) => Err(Errno::NOTDIR),
+            None => Err(Errno::BADF),
+        }
+    }
+}
+
+/// An open file descriptor.
+pub(crate) enum Fd {
+    File {
+        file: Box<dyn WasiFile>,
+        filetype: u8,
+    },
+    Dir(Dir),
+}
+
+/// A directory open in a preopened directory.
+pub(crate) struct Dir {
+    /// The preopened directory it's in, out of which the paths the program opens from it
+    /// can't go.
+    pub(crate) root: Arc<fs::File>,
+    /// The path of the directory in the preopened directory, resolved again from there
+    /// whenever a path is opened from the directory.
+    pub(crate) path: PathBuf,
+    /// The name the program sees the directory by, if it's a preopened directory.
+    pub(crate) preopen: Option<String>,
+}
+
+/// The most symbolic links followed to resolve a path, as in Linux.
+const MAX_SYMLINKS: usize = 40;
+
+impl Dir {
+    /// Resolve `path`, relative to the directory, into a name in a directory open in the
+    /// preopened directory. The file of that name doesn't have to exist.
+    ///
+    /// The path is resolved a component at a time, each directory being opened from the one
+    /// before it without following symbolic links, so that the files being renamed meanwhile
+    /// can't make it go out of the preopened directory. The symbolic links in the path are
+    /// followed by resolving their target in turn, the last component only if `follow` is
+    /// set. A path going out of the preopened directory fails with [`Errno::NOTCAPABLE`]:
+    /// absolute paths, too many `..`, and symbolic links to files outside of it.
+    pub(crate) fn resolve(&self, path: &str, follow: bool) -> Result<Resolved, Errno> {
+        if path.is_empty() {
+            return Err(Errno::NOENT);
+        }
+        let mut pending = VecDeque::new();
+        for name in self.path.iter() {
+            pending.push_back(name.as_bytes().to_vec());
+        }
+        push_components(&mut pending, path.as_bytes())?;
+
+        // The directories opened under the preopened directory, and their names.
+        let mut dirs: Vec<fs::File> = vec![];
+        let mut names: Vec<OsString> = vec![];
+        let mut symlinks = 0;
+        while let Some(name) = pending.pop_front() {
+            match &name[..] {
+                b"" | b"." => continue,
+                b".." => {
+                    if dirs.pop().is_none() {
+                        return Err(Errno::NOTCAPABLE);
+                    }
+                    names.pop();
+                    continue;
+                }
+                _ => {}
+            }
+            let parent = dirs.last().unwrap_or(&self.root);
+            let name = CString::new(name).map_err(|_| Errno::INVAL)?;
+            let last = pending.is_empty();
+            if !last || follow {
+                match read_link(parent, &name) {
+                    Ok(target) => {
+                        symlinks += 1;
+                        if symlinks > MAX_SYMLINKS {
+                            return Err(Errno::LOOP);
+                        }
+                        let mut rest = VecDeque::new();
+                        push_components(&mut rest, &target)?;
+                        rest.append(&mut pending);
+                        pending = rest;
+                        continue;
+                    }
+                    // Not a symbolic link, or a missing file.
+                    Err(error)
+                        if matches!(error.raw_os_error(), Some(libc::EINVAL | libc::ENOENT)) => {}
+                    Err(error) => return Err(error.into()),
+                }
+            }
+            if last {
+                names.push(OsString::from_vec(name.to_bytes().to_vec()));
+                return Ok(Resolved {
+                    root: self.root.clone(),
+                    dir: dirs.pop(),
+                    name,
+                    path: names.iter().collect(),
+                });
+            }
+            let dir = open_at(parent, &name, libc::O_RDONLY | libc::O_DIRECTORY, 0)?;
+            dirs.push(dir);
+            names.push(OsString::from_vec(name.into_bytes()));
+        }
+        // The path ends with the directory it resolved to.
+        Ok(Resolved {
+            root: self.root.clone(),
+            dir: dirs.pop(),
+            name: CString::new(".").unwrap(),
+            path: names.iter().collect(),
+        })
+    }
+}
+
+/// Append the components of `path` to `components`, failing if the path is absolute.
+fn push_components(components: &mut VecDeque<Vec<u8>>, path: &[u8]) -> Result<(), Errno> {
+    if path.starts_with(b"/") {
+        return Err(Errno::NOTCAPABLE);
+    }
+    components.extend(path.split(|byte| *byte == b'/').map(<[u8]>::to_vec));
+    Ok(())
+}
+
+/// Open the file `name` in the directory `dir`, without following symbolic links.
+fn open_at(dir: &fs::File, name: &CStr, flags: c_int, mode: libc::mode_t) -> io::Result<fs::File> {
+    let flags = flags | libc::O_NOFOLLOW | libc::O_CLOEXEC;
+    let fd = unsafe { libc::openat(dir.as_raw_fd(), name.as_ptr(), flags, mode as c_uint) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { fs::File::from_raw_fd(fd) })
+}
+
+/// Read the target of the symbolic link `name` in the directory `dir`.
+fn read_link(dir: &fs::File, name: &CStr) -> io::Result<Vec<u8>> {
+    let mut target = vec![0u8; libc::PATH_MAX as usize];
+    let len = unsafe {
+        libc::readlinkat(
+            dir.as_raw_fd(),
+            name.as_ptr(),
+            target.as_mut_ptr().cast(),
+            target.len(),
+        )
+    };
+    if len < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    target.truncate(len as usize);
+    Ok(target)
+}
+
+/// A path resolved by [`Dir::resolve`].
+pub(crate) struct Resolved {
+    root: Arc<fs::File>,
+    /// The directory the file is in, if it isn't the preopened directory.
+    dir: Option<fs::File>,
+    /// The name of the file in the directory, `.` for the directory itself.
+    name: CString,
+    /// The path of the file in the preopened directory.
+    pub(crate) path: PathBuf,
+}
+
+impl Resolved {
+    /// Open the file with the flags of `open`, without following it if it's a symbolic link.
+    pub(crate) fn open(&self, flags: c_int) -> io::Result<fs::File> {
+        let dir = self.dir.as_ref().unwrap_or(&self.root);
+        open_at(dir, &self.name, flags, 0o666)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    fn preopen(root: &Path) -> Dir {
+        Dir {
+            root: Arc::new(fs::File::open(root).unwrap()),
+            path: PathBuf::new(),
+            preopen: Some("/".to_string()),
+        }
+    }
+
+    /// The path `path` resolves to in `dir`, following the symbolic links.
+    fn resolve(dir: &Dir, path: &str) -> Result<PathBuf, Errno> {
+        dir.resolve(path, true).map(|resolved| resolved.path)
+    }
+
+    #[test]
+    fn paths_are_resolved_in_the_preopened_directory() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir(root.path().join("dir")).unwrap();
+        fs::write(root.path().join("dir/file"), "").unwrap();
+        let dir = preopen(root.path());
+
+        assert_eq!(resolve(&dir, "."), Ok(PathBuf::new()));
+        assert_eq!(resolve(&dir, "dir/file"), Ok(PathBuf::from("dir/file")));
+        assert_eq!(
+            resolve(&dir, "dir/../dir/./file"),
+            Ok(PathBuf::from("dir/file"))
+        );
+        assert_eq!(resolve(&dir, "dir/new"), Ok(PathBuf::from("dir/new")));
+        assert_eq!(resolve(&dir, "dir/"), Ok(PathBuf::from("dir")));
+        assert_eq!(resolve(&dir, "missing/new").err(), Some(Errno::NOENT));
+        assert_eq!(resolve(&dir, "dir/file/new").err(), Some(Errno::NOTDIR));
+        assert_eq!(resolve(&dir, "").err(), Some(Errno::NOENT));
+
+        let subdir = Dir {
+            path: PathBuf::from("dir"),
+            ..preopen(root.path())
+        };
+        assert_eq!(
+            resolve(&subdir, "../dir/file"),
+            Ok(PathBuf::from("dir/file"))
+        );
+        let file = subdir.resolve("file", false).unwrap().open(libc::O_RDONLY);
+        assert!(file.unwrap().metadata().unwrap().is_file());
+    }
+
+    #[test]
+    fn symbolic_links_are_followed_in_the_preopened_directory() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir_all(root.path().join("a/b")).unwrap();
+        fs::write(root.path().join("a/b/file"), "").unwrap();
+        symlink("a/b", root.path().join("link")).unwrap();
+        symlink("b/file", root.path().join("a/file_link")).unwrap();
+        symlink("loop", root.path().join("loop")).unwrap();
+        let dir = preopen(root.path());
+
+        assert_eq!(resolve(&dir, "link/file"), Ok(PathBuf::from("a/b/file")));
+        // `..` goes to the parent of the target of the link.
+        assert_eq!(resolve(&dir, "link/../b"), Ok(PathBuf::from("a/b")));
+        assert_eq!(resolve(&dir, "a/file_link"), Ok(PathBuf::from("a/b/file")));
+        assert_eq!(resolve(&dir, "loop").err(), Some(Errno::LOOP));
+
+        // Without following them, the symbolic links can't be opened.
+        let resolved = dir.resolve("a/file_link", false).unwrap();
+        assert_eq!(resolved.path, PathBuf::from("a/file_link"));
+        let error = resolved.open(libc::O_RDONLY).unwrap_err();
+        assert_eq!(Errno::from(error), Errno::LOOP);
+    }
+
+    #[test]
+    fn paths_cannot_escape_the_preopened_directory() {
+        let parent = tempfile::tempdir().unwrap();
+        let root = parent.path().join("root");
+        fs::create_dir_all(root.join("dir")).unwrap();
+        fs::write(parent.path().join("secret"), "").unwrap();
+        let dir = preopen(&root);
+
+        assert_eq!(resolve(&dir, "..").err(), Some(Errno::NOTCAPABLE));
+        assert_eq!(resolve(&dir, "../secret").err(), Some(Errno::NOTCAPABLE));
+        assert_eq!(
+            resolve(&dir, "dir/../../secret").err(),
+            Some(Errno::NOTCAPABLE)
+        );
+        assert_eq!(
+            resolve(&dir, "../root/../secret").err(),
+            Some(Errno::NOTCAPABLE)
+        );
+        let absolute = parent.path().join("secret");
+        assert_eq!(
+            resolve(&dir, absolute.to_str().unwrap()).err(),
+            Some(Errno::NOTCAPABLE)
+        );
+
+        symlink(parent.path().join("secret"), root.join("link")).unwrap();
+        symlink(parent.path(), root.join("parent")).unwrap();
+        symlink("../secret", root.join("relative")).unwrap();
+        symlink(parent.path().join("missing"), root.join("dangling")).unwrap();
+        assert_eq!(resolve(&dir, "link").err(), Some(Errno::NOTCAPABLE));
+        assert_eq!(resolve(&dir, "parent/new").err(), Some(Errno::NOTCAPABLE));
+        assert_eq!(resolve(&dir, "relative").err(), Some(Errno::NOTCAPABLE));
+        assert_eq!(resolve(&dir, "dangling").err(), Some(Errno::NOTCAPABLE));
+        // Creating a file through a link not followed fails rather than following it.
+        let resolved = dir.resolve("dangling", false).unwrap();
+        let error = resolved.open(libc::O_WRONLY | libc::O_CREAT).unwrap_err();
+        assert_eq!(Errno::from(error), Errno::LOOP);
+        assert!(!parent.path().join("missing").exists());
+    }
+}
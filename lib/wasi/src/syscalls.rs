@@ -0,0 +1,351 @@
+//! The syscalls of `wasi_snapshot_preview1`.
+//!
+//! The syscalls return an [`Errno`], and write their results to the memory of the program at
+//! the addresses it gives them.
+
+use crate::state::{Dir, Fd, WasiState};
+use crate::types::*;
+use crate::WasiExit;
+use std::convert::TryFrom;
+use std::io::{self, SeekFrom};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+use wasmer::{
+    Exportable, Exports, Function, HostEnvInitError, Instance, LazyInit, Memory, Store, WasmerEnv,
+};
+
+/// The environment of the syscalls.
+#[derive(Clone)]
+pub(crate) struct WasiEnv {
+    state: WasiState,
+    memory: LazyInit<Memory>,
+}
+
+impl WasiEnv {
+    pub(crate) fn new(state: WasiState) -> Self {
+        Self {
+            state,
+            memory: LazyInit::new(),
+        }
+    }
+
+    fn memory(&self) -> &Memory {
+        self.memory
+            .get_ref()
+            .expect("the memory of the instance is initialized")
+    }
+}
+
+impl WasmerEnv for WasiEnv {
+    fn init_with_instance(&mut self, instance: &Instance) -> Result<(), HostEnvInitError> {
+        let mut memory = instance.lookup_memory("memory")?;
+        memory.into_weak_instance_ref();
+        self.memory.initialize(memory);
+        Ok(())
+    }
+}
+
+/// Fail with [`Errno::FAULT`] unless the `len` bytes at `buf` are in `memory`, before
+/// allocating a buffer of `len` bytes on the host to copy them.
+fn check_bounds(memory: &Memory, buf: u32, len: u32) -> Result<(), Errno> {
+    if u64::from(buf) + u64::from(len) > memory.data_size() {
+        return Err(Errno::FAULT);
+    }
+    Ok(())
+}
+
+/// Call `f` with the address and length of each buffer of the `iovs_len` iovecs at `iovs`,
+/// until it returns a length shorter than the one of the buffer, and return the sum of the
+/// lengths it returned.
+fn for_each_iovec(
+    memory: &Memory,
+    iovs: u32,
+    iovs_len: u32,
+    mut f: impl FnMut(u32, u32) -> Result<u32, Errno>,
+) -> Result<u32, Errno> {
+    let mut total: u32 = 0;
+    for i in 0..u64::from(iovs_len) {
+        let iov = u64::from(iovs) + i * IOVEC_SIZE;
+        let buf = memory.read_u32(iov)?;
+        let len = memory.read_u32(iov + 4)?;
+        check_bounds(memory, buf, len)?;
+        let done = f(buf, len)?;
+        total = total.saturating_add(done);
+        if done < len {
+            break;
+        }
+    }
+    Ok(total)
+}
+
+/// Write `strings` after one another to `buf`, each with a nul terminator, and their
+/// addresses to the array at `ptrs`.
+fn write_strings(memory: &Memory, strings: &[Vec<u8>], ptrs: u32, buf: u32) -> Result<(), Errno> {
+    let mut offset = u64::from(buf);
+    for (i, string) in strings.iter().enumerate() {
+        memory.write_u32(u64::from(ptrs) + 4 * i as u64, offset as u32)?;
+        memory.write(offset, string)?;
+        memory.write_u8(offset + string.len() as u64, 0)?;
+        offset += string.len() as u64 + 1;
+    }
+    Ok(())
+}
+
+/// Write the number of `strings` to `count`, and the size they take with their nul
+/// terminators to `buf_size`.
+fn write_sizes(
+    memory: &Memory,
+    strings: &[Vec<u8>],
+    count: u32,
+    buf_size: u32,
+) -> Result<(), Errno> {
+    let size: usize = strings.iter().map(|string| string.len() + 1).sum();
+    memory.write_u32(u64::from(count), strings.len() as u32)?;
+    memory.write_u32(u64::from(buf_size), size as u32)?;
+    Ok(())
+}
+
+/// Define the syscalls returning an [`Errno`] from bodies returning `Result<(), Errno>`,
+/// and `insert_syscalls` inserting them in a namespace.
+macro_rules! syscalls {
+    ($(
+        fn $name:ident($env:ident $(, $arg:ident: $ty:ty)* $(,)?) $body:block
+    )*) => {
+        $(
+            #[allow(clippy::too_many_arguments)]
+            fn $name($env: &WasiEnv $(, $arg: $ty)*) -> u32 {
+                #[allow(clippy::too_many_arguments)]
+                fn body($env: &WasiEnv $(, $arg: $ty)*) -> Result<(), Errno> $body
+
+                u32::from(body($env $(, $arg)*).err().unwrap_or(Errno::SUCCESS).0)
+            }
+        )*
+
+        /// Insert the syscalls in `namespace`, all of them sharing `env`.
+        pub(crate) fn insert_syscalls(store: &Store, env: &WasiEnv, namespace: &mut Exports) {
+            $(
+                let function = Function::new_native_with_env(store, env.clone(), $name);
+                namespace.insert(stringify!($name), function);
+            )*
+            namespace.insert("proc_exit", Function::new_native(store, proc_exit));
+        }
+    };
+}
+
+syscalls! {
+    fn args_get(env, argv: u32, argv_buf: u32) {
+        write_strings(env.memory(), &env.state.lock().args, argv, argv_buf)
+    }
+
+    fn args_sizes_get(env, argc: u32, argv_buf_size: u32) {
+        write_sizes(env.memory(), &env.state.lock().args, argc, argv_buf_size)
+    }
+
+    fn environ_get(env, environ: u32, environ_buf: u32) {
+        write_strings(env.memory(), &env.state.lock().envs, environ, environ_buf)
+    }
+
+    fn environ_sizes_get(env, environ_count: u32, environ_buf_size: u32) {
+        write_sizes(env.memory(), &env.state.lock().envs, environ_count, environ_buf_size)
+    }
+
+    fn clock_res_get(env, id: u32, resolution: u32) {
+        match id {
+            CLOCKID_REALTIME | CLOCKID_MONOTONIC | CLOCKID_PROCESS_CPUTIME
+            | CLOCKID_THREAD_CPUTIME => Ok(env.memory().write_u64(u64::from(resolution), 1)?),
+            _ => Err(Errno::INVAL),
+        }
+    }
+
+    fn clock_time_get(env, id: u32, _precision: u64, time: u32) {
+        let nanos = match id {
+            CLOCKID_REALTIME => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_err(|_| Errno::IO)?
+                .as_nanos(),
+            CLOCKID_MONOTONIC | CLOCKID_PROCESS_CPUTIME | CLOCKID_THREAD_CPUTIME => {
+                env.state.lock().start.elapsed().as_nanos()
+            }
+            _ => return Err(Errno::INVAL),
+        };
+        Ok(env.memory().write_u64(u64::from(time), nanos as u64)?)
+    }
+
+    fn fd_close(env, fd: u32) {
+        env.state.lock().fds.remove(&fd).ok_or(Errno::BADF)?;
+        Ok(())
+    }
+
+    fn fd_fdstat_get(env, fd: u32, buf: u32) {
+        let filetype = match env.state.lock().fds.get(&fd) {
+            Some(Fd::File { filetype, .. }) => *filetype,
+            Some(Fd::Dir(_)) => FILETYPE_DIRECTORY,
+            None => return Err(Errno::BADF),
+        };
+        let memory = env.memory();
+        let buf = u64::from(buf);
+        memory.write_u8(buf, filetype)?;
+        memory.write_u16(buf + 2, 0)?;
+        memory.write_u64(buf + 8, RIGHTS_ALL)?;
+        memory.write_u64(buf + 16, RIGHTS_ALL)?;
+        Ok(())
+    }
+
+    fn fd_prestat_get(env, fd: u32, buf: u32) {
+        let state = env.state.lock();
+        let name = match state.fds.get(&fd) {
+            Some(Fd::Dir(Dir { preopen: Some(name), .. })) => name,
+            _ => return Err(Errno::BADF),
+        };
+        let memory = env.memory();
+        memory.write_u8(u64::from(buf), PREOPENTYPE_DIR)?;
+        memory.write_u32(u64::from(buf) + 4, name.len() as u32)?;
+        Ok(())
+    }
+
+    fn fd_prestat_dir_name(env, fd: u32, path: u32, path_len: u32) {
+        let state = env.state.lock();
+        let name = match state.fds.get(&fd) {
+            Some(Fd::Dir(Dir { preopen: Some(name), .. })) => name,
+            _ => return Err(Errno::BADF),
+        };
+        if (path_len as usize) < name.len() {
+            return Err(Errno::NAMETOOLONG);
+        }
+        Ok(env.memory().write(u64::from(path), name.as_bytes())?)
+    }
+
+    fn fd_read(env, fd: u32, iovs: u32, iovs_len: u32, nread: u32) {
+        let memory = env.memory();
+        let mut state = env.state.lock();
+        let file = state.file(fd)?;
+        let total = for_each_iovec(memory, iovs, iovs_len, |buf, len| {
+            let mut data = vec![0; len as usize];
+            let read = file.read(&mut data)?;
+            memory.write(u64::from(buf), &data[..read])?;
+            Ok(read as u32)
+        })?;
+        Ok(memory.write_u32(u64::from(nread), total)?)
+    }
+
+    fn fd_write(env, fd: u32, iovs: u32, iovs_len: u32, nwritten: u32) {
+        let memory = env.memory();
+        let mut state = env.state.lock();
+        let file = state.file(fd)?;
+        let total = for_each_iovec(memory, iovs, iovs_len, |buf, len| {
+            let mut data = vec![0; len as usize];
+            memory.read(u64::from(buf), &mut data)?;
+            Ok(file.write(&data)? as u32)
+        })?;
+        Ok(memory.write_u32(u64::from(nwritten), total)?)
+    }
+
+    fn fd_seek(env, fd: u32, offset: i64, whence: u32, newoffset: u32) {
+        let pos = match whence {
+            WHENCE_SET => SeekFrom::Start(u64::try_from(offset).map_err(|_| Errno::INVAL)?),
+            WHENCE_CUR => SeekFrom::Current(offset),
+            WHENCE_END => SeekFrom::End(offset),
+            _ => return Err(Errno::INVAL),
+        };
+        let offset = match env.state.lock().file(fd)?.seek(pos) {
+            Err(error) if error.kind() == io::ErrorKind::Unsupported => {
+                return Err(Errno::SPIPE)
+            }
+            result => result?,
+        };
+        Ok(env.memory().write_u64(u64::from(newoffset), offset)?)
+    }
+
+    fn path_open(
+        env,
+        dirfd: u32,
+        dirflags: u32,
+        path: u32,
+        path_len: u32,
+        oflags: u32,
+        fs_rights_base: u64,
+        _fs_rights_inheriting: u64,
+        fdflags: u32,
+        opened_fd: u32,
+    ) {
+        let memory = env.memory();
+        check_bounds(memory, path, path_len)?;
+        let mut path_bytes = vec![0; path_len as usize];
+        memory.read(u64::from(path), &mut path_bytes)?;
+        let path = String::from_utf8(path_bytes).map_err(|_| Errno::ILSEQ)?;
+
+        let mut state = env.state.lock();
+        let dir = state.dir(dirfd)?;
+        let resolved = dir.resolve(&path, dirflags & LOOKUPFLAGS_SYMLINK_FOLLOW != 0)?;
+        let root = dir.root.clone();
+        let read = fs_rights_base & RIGHTS_FD_READ != 0;
+        let write = fs_rights_base & RIGHTS_FD_WRITE != 0 || fdflags & FDFLAGS_APPEND != 0;
+        let creates = oflags & (OFLAGS_CREAT | OFLAGS_TRUNC) != 0;
+        let opens_dir = if oflags & OFLAGS_DIRECTORY != 0 {
+            resolved.open(libc::O_RDONLY | libc::O_DIRECTORY)?;
+            true
+        } else if !write && !creates {
+            match resolved.open(libc::O_RDONLY | libc::O_DIRECTORY) {
+                Ok(_) => true,
+                Err(error) if error.raw_os_error() == Some(libc::ENOTDIR) => false,
+                Err(error) => return Err(error.into()),
+            }
+        } else {
+            false
+        };
+        let fd = if opens_dir {
+            Fd::Dir(Dir {
+                root,
+                path: resolved.path,
+                preopen: None,
+            })
+        } else {
+            let mut flags = match (read || !write, write) {
+                (true, true) => libc::O_RDWR,
+                (false, true) => libc::O_WRONLY,
+                _ => libc::O_RDONLY,
+            };
+            for (oflag, flag) in [
+                (OFLAGS_CREAT, libc::O_CREAT),
+                (OFLAGS_EXCL, libc::O_EXCL),
+                (OFLAGS_TRUNC, libc::O_TRUNC),
+            ] {
+                if oflags & oflag != 0 {
+                    flags |= flag;
+                }
+            }
+            if fdflags & FDFLAGS_APPEND != 0 {
+                flags |= libc::O_APPEND;
+            }
+            let file = resolved.open(flags)?;
+            if file.metadata()?.is_dir() {
+                return Err(Errno::ISDIR);
+            }
+            Fd::File {
+                file: Box::new(file),
+                filetype: FILETYPE_REGULAR_FILE,
+            }
+        };
+        let fd = state.insert_fd(fd);
+        Ok(memory.write_u32(u64::from(opened_fd), fd)?)
+    }
+
+    fn random_get(env, buf: u32, buf_len: u32) {
+        let memory = env.memory();
+        check_bounds(memory, buf, buf_len)?;
+        let mut data = vec![0; buf_len as usize];
+        getrandom::getrandom(&mut data).map_err(|_| Errno::IO)?;
+        Ok(memory.write(u64::from(buf), &data)?)
+    }
+
+    fn sched_yield(_env) {
+        thread::yield_now();
+        Ok(())
+    }
+}
+
+/// Exit the program with the status code `code`, unwinding the Wasm frames up to the call
+/// that started it, which fails with [`WasiExit`].
+fn proc_exit(code: u32) -> Result<(), WasiExit> {
+    Err(WasiExit(code))
+}
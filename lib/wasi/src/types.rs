@@ -0,0 +1,102 @@
+//! The types and constants of the `wasi_snapshot_preview1` ABI.
+
+use std::io;
+use wasmer::MemoryAccessError;
+
+/// An error code returned by a syscall.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Errno(pub(crate) u16);
+
+impl Errno {
+    pub(crate) const SUCCESS: Self = Self(0);
+    pub(crate) const ACCES: Self = Self(2);
+    pub(crate) const AGAIN: Self = Self(6);
+    pub(crate) const BADF: Self = Self(8);
+    pub(crate) const EXIST: Self = Self(20);
+    pub(crate) const FAULT: Self = Self(21);
+    pub(crate) const ILSEQ: Self = Self(25);
+    pub(crate) const INTR: Self = Self(27);
+    pub(crate) const INVAL: Self = Self(28);
+    pub(crate) const IO: Self = Self(29);
+    pub(crate) const ISDIR: Self = Self(31);
+    pub(crate) const LOOP: Self = Self(32);
+    pub(crate) const NAMETOOLONG: Self = Self(37);
+    pub(crate) const NOENT: Self = Self(44);
+    pub(crate) const NOSYS: Self = Self(52);
+    pub(crate) const NOTDIR: Self = Self(54);
+    pub(crate) const NOTSUP: Self = Self(58);
+    pub(crate) const PIPE: Self = Self(64);
+    pub(crate) const SPIPE: Self = Self(70);
+    pub(crate) const NOTCAPABLE: Self = Self(76);
+}
+
+impl From<MemoryAccessError> for Errno {
+    fn from(_: MemoryAccessError) -> Self {
+        Self::FAULT
+    }
+}
+
+impl From<io::Error> for Errno {
+    fn from(error: io::Error) -> Self {
+        match error.raw_os_error() {
+            Some(libc::ELOOP) => return Self::LOOP,
+            Some(libc::EISDIR) => return Self::ISDIR,
+            Some(libc::ENOTDIR) => return Self::NOTDIR,
+            Some(libc::ENAMETOOLONG) => return Self::NAMETOOLONG,
+            _ => {}
+        }
+        match error.kind() {
+            io::ErrorKind::NotFound => Self::NOENT,
+            io::ErrorKind::PermissionDenied => Self::ACCES,
+            io::ErrorKind::AlreadyExists => Self::EXIST,
+            io::ErrorKind::WouldBlock => Self::AGAIN,
+            io::ErrorKind::Interrupted => Self::INTR,
+            io::ErrorKind::InvalidInput => Self::INVAL,
+            io::ErrorKind::BrokenPipe => Self::PIPE,
+            io::ErrorKind::Unsupported => Self::NOTSUP,
+            _ => Self::IO,
+        }
+    }
+}
+
+/// The types of the files.
+pub(crate) const FILETYPE_CHARACTER_DEVICE: u8 = 2;
+pub(crate) const FILETYPE_DIRECTORY: u8 = 3;
+pub(crate) const FILETYPE_REGULAR_FILE: u8 = 4;
+
+/// The flag of `path_open` following the file it opens if it's a symbolic link.
+pub(crate) const LOOKUPFLAGS_SYMLINK_FOLLOW: u32 = 1 << 0;
+
+/// The flags of `path_open` creating the file it opens.
+pub(crate) const OFLAGS_CREAT: u32 = 1 << 0;
+pub(crate) const OFLAGS_DIRECTORY: u32 = 1 << 1;
+pub(crate) const OFLAGS_EXCL: u32 = 1 << 2;
+pub(crate) const OFLAGS_TRUNC: u32 = 1 << 3;
+
+/// The flag of a file descriptor appending its writes to the end of the file.
+pub(crate) const FDFLAGS_APPEND: u32 = 1 << 0;
+
+/// The rights of a file descriptor. Opening a file with `path_open` for reading and
+/// writing is asking for these rights, but the file descriptors themselves have all the
+/// rights, the sandbox being the preopened directories.
+pub(crate) const RIGHTS_FD_READ: u64 = 1 << 1;
+pub(crate) const RIGHTS_FD_WRITE: u64 = 1 << 6;
+pub(crate) const RIGHTS_ALL: u64 = (1 << 29) - 1;
+
+/// The only type of preopened file descriptor, a directory.
+pub(crate) const PREOPENTYPE_DIR: u8 = 0;
+
+/// The bases of the offsets of `fd_seek`.
+pub(crate) const WHENCE_SET: u32 = 0;
+pub(crate) const WHENCE_CUR: u32 = 1;
+pub(crate) const WHENCE_END: u32 = 2;
+
+/// The clocks of `clock_time_get`. The CPU time clocks of the process and thread are
+/// approximated by the monotonic clock.
+pub(crate) const CLOCKID_REALTIME: u32 = 0;
+pub(crate) const CLOCKID_MONOTONIC: u32 = 1;
+pub(crate) const CLOCKID_PROCESS_CPUTIME: u32 = 2;
+pub(crate) const CLOCKID_THREAD_CPUTIME: u32 = 3;
+
+/// The size of an `iovec` (or `ciovec`): the address and length of a buffer, as two `u32`.
+pub(crate) const IOVEC_SIZE: u64 = 8;
@@ -0,0 +1,91 @@
+//! Testing the memory the compilation of modules takes, with an allocator counting the bytes
+//! allocated. It is a test target of its own, so that no other test allocates meanwhile.
+
+use anyhow::Result;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use wasmer::*;
+
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+fn allocated(size: usize) {
+    let allocated = ALLOCATED.fetch_add(size, Ordering::SeqCst) + size;
+    PEAK.fetch_max(allocated, Ordering::SeqCst);
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            allocated(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::SeqCst);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            // Both blocks may be held while the old one is copied.
+            allocated(new_size);
+            ALLOCATED.fetch_sub(layout.size(), Ordering::SeqCst);
+        }
+        new_ptr
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// A module of many functions, each with calls to relocate and memory accesses that may trap.
+fn many_functions(count: usize) -> Result<Vec<u8>> {
+    let mut wat = String::from("(module (memory 1)");
+    for i in 0..count {
+        wat.push_str("\n  (func (param i32) (result i32)");
+        for offset in (0..40).step_by(4) {
+            wat.push_str(&format!(
+                "\n    (i32.store offset={} (local.get 0) \
+                 (i32.div_u (i32.load (local.get 0)) (i32.add (local.get 0) (i32.const {}))))",
+                offset, i
+            ));
+        }
+        wat.push_str(&format!(
+            "\n    (i32.add (call {} (local.get 0)) (call {} (local.get 0))))",
+            (i + 1) % count,
+            (i + 7) % count
+        ));
+    }
+    wat.push(')');
+    Ok(wat2wasm(wat.as_bytes())?.into_owned())
+}
+
+#[test]
+fn compiled_functions_are_held_once() -> Result<()> {
+    let engine = Universal::new(Singlepass::default()).engine();
+    let tunables = BaseTunables::for_target(engine.target());
+    // The first compilation starts the threads of the compiler.
+    engine.compile_universal(&many_functions(10)?, &tunables)?;
+
+    let wasm = many_functions(2000)?;
+    let before = ALLOCATED.load(Ordering::SeqCst);
+    PEAK.store(before, Ordering::SeqCst);
+    let _executable = engine.compile_universal(&wasm, &tunables)?;
+    let peak = PEAK.load(Ordering::SeqCst) - before;
+    let retained = ALLOCATED.load(Ordering::SeqCst) - before;
+    // Beyond the executable, the compilation only holds the functions being compiled and some
+    // bookkeeping, where copying the compiled functions into the executable would double it.
+    assert!(
+        peak < retained * 3 / 2,
+        "compiling took up to {} bytes for an executable of {}",
+        peak,
+        retained
+    );
+    Ok(())
+}
@@ -0,0 +1,334 @@
+//! Testing the async calls into Wasm, with host functions awaiting futures.
+
+use anyhow::Result;
+use std::collections::{HashSet, VecDeque};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread;
+use std::time::{Duration, Instant};
+use wasmer::*;
+
+/// The tasks of `run_concurrently` that were woken.
+#[derive(Default)]
+struct Queue {
+    ready: Mutex<VecDeque<usize>>,
+    available: Condvar,
+}
+
+impl Queue {
+    fn push(&self, task: usize) {
+        self.ready.lock().unwrap().push_back(task);
+        self.available.notify_one();
+    }
+
+    fn pop(&self) -> usize {
+        let mut ready = self.ready.lock().unwrap();
+        loop {
+            if let Some(task) = ready.pop_front() {
+                return task;
+            }
+            ready = self.available.wait(ready).unwrap();
+        }
+    }
+}
+
+struct TaskWaker {
+    task: usize,
+    queue: Arc<Queue>,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.queue.push(self.task);
+    }
+}
+
+struct NoopWaker;
+
+impl Wake for NoopWaker {
+    fn wake(self: Arc<Self>) {}
+}
+
+/// Run `futures` to completion on the current thread, polling each one when it is woken.
+fn run_concurrently<F: Future + Unpin>(mut futures: Vec<F>) -> Vec<F::Output> {
+    let queue = Arc::new(Queue::default());
+    let mut outputs: Vec<Option<F::Output>> = futures.iter().map(|_| None).collect();
+    for task in 0..futures.len() {
+        queue.push(task);
+    }
+    let mut left = futures.len();
+    while left > 0 {
+        let task = queue.pop();
+        if outputs[task].is_some() {
+            continue;
+        }
+        let waker = Waker::from(Arc::new(TaskWaker {
+            task,
+            queue: queue.clone(),
+        }));
+        let mut cx = Context::from_waker(&waker);
+        if let Poll::Ready(output) = Pin::new(&mut futures[task]).poll(&mut cx) {
+            outputs[task] = Some(output);
+            left -= 1;
+        }
+    }
+    outputs.into_iter().map(Option::unwrap).collect()
+}
+
+/// Poll `future` once, with a waker doing nothing.
+fn poll_once<F: Future + Unpin>(future: &mut F) -> Poll<F::Output> {
+    let waker = Waker::from(Arc::new(NoopWaker));
+    Pin::new(future).poll(&mut Context::from_waker(&waker))
+}
+
+/// A future ready after some time, woken by a thread of its own.
+struct Sleep {
+    deadline: Instant,
+    waker: Option<Arc<Mutex<Waker>>>,
+}
+
+fn sleep(duration: Duration) -> Sleep {
+    Sleep {
+        deadline: Instant::now() + duration,
+        waker: None,
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if Instant::now() >= self.deadline {
+            return Poll::Ready(());
+        }
+        match &self.waker {
+            Some(waker) => *waker.lock().unwrap() = cx.waker().clone(),
+            None => {
+                let waker = Arc::new(Mutex::new(cx.waker().clone()));
+                let deadline = self.deadline;
+                self.waker = Some(waker.clone());
+                thread::spawn(move || {
+                    thread::sleep(deadline.saturating_duration_since(Instant::now()));
+                    waker.lock().unwrap().wake_by_ref();
+                });
+            }
+        }
+        Poll::Pending
+    }
+}
+
+/// Instantiate a module whose `run` function sleeps twice asynchronously, for 50ms each
+/// time, and returns its argument plus 50.
+fn get_instances(store: &Store, count: usize, sleeping: Arc<AtomicUsize>) -> Result<Vec<Instance>> {
+    let wat = r#"
+        (import "host" "sleep" (func $sleep (param i32) (result i32)))
+        (func (export "run") (param $id i32) (result i32)
+            (drop (call $sleep (i32.const 50)))
+            (i32.add (call $sleep (i32.const 50)) (local.get $id)))
+    "#;
+    let module = Module::new(store, wat)?;
+    let host_sleep = Function::new_async(store, ([Type::I32], [Type::I32]), move |args| {
+        let sleeping = sleeping.clone();
+        async move {
+            let ms = args[0].unwrap_i32();
+            sleeping.fetch_add(1, Ordering::SeqCst);
+            sleep(Duration::from_millis(ms as u64)).await;
+            Ok(vec![Value::I32(ms)])
+        }
+    });
+    let imports = imports! {
+        "host" => {
+            "sleep" => host_sleep,
+        },
+    };
+    let mut instances = vec![];
+    for _ in 0..count {
+        instances.push(Instance::new(&module, &imports)?);
+    }
+    Ok(instances)
+}
+
+#[compiler_test(async_functions)]
+fn concurrent_calls_sleep_asynchronously(config: crate::Config) -> Result<()> {
+    const CALLS: usize = 32;
+    let store = config.store();
+    let sleeping = Arc::new(AtomicUsize::new(0));
+    let instances = get_instances(&store, CALLS, sleeping.clone())?;
+    let mut calls = vec![];
+    for (id, instance) in instances.iter().enumerate() {
+        let run = instance.get_native_function::<i32, i32>("run")?;
+        calls.push(run.call_async(id as i32));
+    }
+
+    let start = Instant::now();
+    let results = run_concurrently(calls);
+    // The calls sleep at the same time rather than one after the other.
+    assert!(start.elapsed() < Duration::from_millis(CALLS as u64 * 100 / 2));
+    assert_eq!(sleeping.load(Ordering::SeqCst), 2 * CALLS);
+    for (id, result) in results.into_iter().enumerate() {
+        assert_eq!(result?, 50 + id as i32);
+    }
+    Ok(())
+}
+
+#[compiler_test(async_functions)]
+fn suspended_instances_reject_calls(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let instance = get_instances(&store, 1, Arc::default())?.remove(0);
+    let run = instance.get_native_function::<i32, i32>("run")?;
+
+    let mut call = run.call_async(1);
+    assert!(poll_once(&mut call).is_pending());
+    let err = run.call(2).unwrap_err();
+    assert!(err.is_instance_suspended());
+    assert_eq!(
+        err.message(),
+        "the instance is running an async call that is suspended"
+    );
+    let err = instance
+        .lookup_function("run")
+        .unwrap()
+        .call(&[Value::I32(2)])
+        .unwrap_err();
+    assert!(err.is_instance_suspended());
+    let mut results = run_concurrently(vec![run.call_async(2)]);
+    assert!(results.pop().unwrap().unwrap_err().is_instance_suspended());
+
+    // The suspended call resumes on its stack, and the instance accepts calls once it
+    // returned.
+    assert_eq!(run_concurrently(vec![call]).pop().unwrap()?, 51);
+    assert_eq!(
+        run_concurrently(vec![run.call_async(3)]).pop().unwrap()?,
+        53
+    );
+    Ok(())
+}
+
+#[compiler_test(async_functions)]
+fn async_functions_need_async_calls(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let instance = get_instances(&store, 1, Arc::default())?.remove(0);
+    let run = instance.get_native_function::<i32, i32>("run")?;
+
+    let err = run.call(1).unwrap_err();
+    assert_eq!(
+        err.message(),
+        "async host functions can only be called from an async call"
+    );
+
+    // Dropping a suspended call cancels it, and unwinds its frames.
+    let mut call = run.call_async(1);
+    assert!(poll_once(&mut call).is_pending());
+    drop(call);
+    assert_eq!(
+        run_concurrently(vec![run.call_async(2)]).pop().unwrap()?,
+        52
+    );
+    Ok(())
+}
+
+#[compiler_test(async_functions)]
+fn calls_are_spawned_on_a_multi_threaded_runtime(config: crate::Config) -> Result<()> {
+    const CALLS: usize = 32;
+    let store = config.store();
+    let wat = r#"
+        (import "host" "sleep" (func $sleep (param i32) (result i32)))
+        (func (export "run") (param $id i32) (result i32)
+            (drop (call $sleep (i32.const 20)))
+            (i32.add (call $sleep (i32.const 20)) (local.get $id)))
+    "#;
+    let module = Module::new(&store, wat)?;
+    let threads = Arc::new(Mutex::new(HashSet::new()));
+    let host_sleep = Function::new_async(&store, ([Type::I32], [Type::I32]), {
+        let threads = threads.clone();
+        move |args| {
+            let threads = threads.clone();
+            async move {
+                let ms = args[0].unwrap_i32();
+                threads.lock().unwrap().insert(thread::current().id());
+                tokio::time::sleep(Duration::from_millis(ms as u64)).await;
+                threads.lock().unwrap().insert(thread::current().id());
+                Ok(vec![Value::I32(ms)])
+            }
+        }
+    });
+    let imports = imports! {
+        "host" => {
+            "sleep" => host_sleep,
+        },
+    };
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(4)
+        .enable_time()
+        .build()?;
+    let mut tasks = vec![];
+    for id in 0..CALLS {
+        let instance = Instance::new(&module, &imports)?;
+        let run = instance.get_native_function::<i32, i32>("run")?;
+        tasks.push(runtime.spawn(async move { run.call_async(id as i32).await }));
+    }
+    for (id, task) in tasks.into_iter().enumerate() {
+        assert_eq!(runtime.block_on(task)??, 20 + id as i32);
+    }
+    assert!(!threads.lock().unwrap().contains(&thread::current().id()));
+    Ok(())
+}
+
+#[compiler_test(async_functions)]
+fn suspended_calls_resume_on_other_threads(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let wat = r#"
+        (import "host" "sleep" (func $sleep (param i32) (result i32)))
+        (import "host" "reenter" (func $reenter (param i32) (result i32)))
+        (func (export "double") (param i32) (result i32)
+            (i32.mul (local.get 0) (i32.const 2)))
+        (func (export "run") (param $id i32) (result i32)
+            (drop (call $sleep (i32.const 10)))
+            (call $reenter (local.get $id)))
+    "#;
+    let module = Module::new(&store, wat)?;
+    let double: Arc<Mutex<Option<NativeFunc<i32, i32>>>> = Arc::default();
+    let reentered_on = Arc::new(Mutex::new(None));
+    let host_sleep = Function::new_async(&store, ([Type::I32], [Type::I32]), |args| async move {
+        sleep(Duration::from_millis(args[0].unwrap_i32() as u64)).await;
+        Ok(vec![Value::I32(0)])
+    });
+    let reenter = Function::new(&store, ([Type::I32], [Type::I32]), {
+        let double = double.clone();
+        let reentered_on = reentered_on.clone();
+        move |args| {
+            *reentered_on.lock().unwrap() = Some(thread::current().id());
+            let double = double.lock().unwrap();
+            let result = double.as_ref().unwrap().call(args[0].unwrap_i32())?;
+            Ok(vec![Value::I32(result)])
+        }
+    });
+    let imports = imports! {
+        "host" => {
+            "sleep" => host_sleep,
+            "reenter" => reenter,
+        },
+    };
+    let instance = Instance::new(&module, &imports)?;
+    *double.lock().unwrap() = Some(instance.get_native_function("double")?);
+    let run = instance.get_native_function::<i32, i32>("run")?;
+
+    let mut call = run.call_async(21);
+    assert!(poll_once(&mut call).is_pending());
+    // The thread resuming the call takes it over: the host functions it calls may call the
+    // instance again from there.
+    let thread = thread::spawn(move || run_concurrently(vec![call]).pop().unwrap());
+    let resumed_on = thread.thread().id();
+    assert_eq!(thread.join().unwrap()?, 42);
+    assert_eq!(*reentered_on.lock().unwrap(), Some(resumed_on));
+    assert_eq!(
+        run.call(0).unwrap_err().message(),
+        "async host functions can only be called from an async call"
+    );
+    double.lock().unwrap().take();
+    Ok(())
+}
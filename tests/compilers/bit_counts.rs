@@ -0,0 +1,61 @@
+//! Tests of `clz`, `ctz` and `popcnt`, with and without the CPU features providing instructions
+//! for them.
+
+use anyhow::Result;
+use wasmer::*;
+
+static BIT_COUNTS_WAT: &str = r#"(module
+    (func (export "i32.clz") (param i32) (result i32) (i32.clz (local.get 0)))
+    (func (export "i32.ctz") (param i32) (result i32) (i32.ctz (local.get 0)))
+    (func (export "i32.popcnt") (param i32) (result i32) (i32.popcnt (local.get 0)))
+    (func (export "i64.clz") (param i64) (result i64) (i64.clz (local.get 0)))
+    (func (export "i64.ctz") (param i64) (result i64) (i64.ctz (local.get 0)))
+    (func (export "i64.popcnt") (param i64) (result i64) (i64.popcnt (local.get 0)))
+)"#;
+
+fn check_bit_counts(config: &crate::Config) -> Result<()> {
+    let store = config.store();
+    let module = Module::new(&store, BIT_COUNTS_WAT)?;
+    let instance = Instance::new(&module, &imports! {})?;
+
+    let mut inputs32 = vec![0, u32::MAX, 0x5555_5555, 0x0f0f_f0f0];
+    inputs32.extend((0..32).map(|i| 1 << i));
+    for (name, expected) in [
+        ("i32.clz", u32::leading_zeros as fn(u32) -> u32),
+        ("i32.ctz", u32::trailing_zeros),
+        ("i32.popcnt", u32::count_ones),
+    ] {
+        let f: NativeFunc<i32, i32> = instance.get_native_function(name)?;
+        for &x in &inputs32 {
+            assert_eq!(f.call(x as i32)? as u32, expected(x), "{}({:#x})", name, x);
+        }
+    }
+
+    let mut inputs64 = vec![0, u64::MAX, 0x5555_5555_5555_5555, 0x00ff_0f0f_f0f0_ff00];
+    inputs64.extend((0..64).map(|i| 1 << i));
+    for (name, expected) in [
+        ("i64.clz", u64::leading_zeros as fn(u64) -> u32),
+        ("i64.ctz", u64::trailing_zeros),
+        ("i64.popcnt", u64::count_ones),
+    ] {
+        let f: NativeFunc<i64, i64> = instance.get_native_function(name)?;
+        for &x in &inputs64 {
+            assert_eq!(f.call(x as i64)?, expected(x) as i64, "{}({:#x})", name, x);
+        }
+    }
+    Ok(())
+}
+
+#[compiler_test(bit_counts)]
+fn bit_counts_for_host(mut config: crate::Config) -> Result<()> {
+    config.set_target(Target::new(Triple::host(), CpuFeature::for_host()));
+    check_bit_counts(&config)
+}
+
+#[compiler_test(bit_counts)]
+fn bit_counts_without_instructions(mut config: crate::Config) -> Result<()> {
+    let features =
+        CpuFeature::for_host() - (CpuFeature::POPCNT | CpuFeature::LZCNT | CpuFeature::BMI1);
+    config.set_target(Target::new(Triple::host(), features));
+    check_bit_counts(&config)
+}
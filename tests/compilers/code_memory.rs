@@ -0,0 +1,129 @@
+//! Testing the memory the compiled code is loaded in.
+
+use anyhow::Result;
+use wasmer::*;
+#[cfg(target_os = "linux")]
+use wasmer_engine_universal::UniversalExecutableRef;
+
+fn get_module(store: &Store) -> Result<Module> {
+    let wat = r#"
+        (func $double (param i32) (result i32)
+            (i32.mul (local.get 0) (i32.const 2)))
+        (func (export "quadruple") (param i32) (result i32)
+            (call $double (call $double (local.get 0))))
+    "#;
+    Ok(Module::new(&store, wat)?)
+}
+
+#[compiler_test(code_memory)]
+fn code_memory_usage_is_reported(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    assert_eq!(
+        store.engine().code_memory_usage(),
+        CodeMemoryUsage::default()
+    );
+
+    let module = get_module(&store)?;
+    let usage = store.engine().code_memory_usage();
+    assert!(usage.used > 0);
+    assert!(usage.allocated >= usage.used);
+
+    // The modules compiled from the same binary share their code.
+    get_module(&store)?;
+    assert_eq!(store.engine().code_memory_usage(), usage);
+
+    // The code of each other module gets its own pages.
+    let _other = Module::new(&store, "(func (export \"empty\"))")?;
+    let second_usage = store.engine().code_memory_usage();
+    assert!(second_usage.used > usage.used);
+    assert!(second_usage.allocated > usage.allocated);
+
+    let instance = Instance::new(&module, &imports! {})?;
+    let quadruple = instance.get_native_function::<i32, i32>("quadruple")?;
+    assert_eq!(quadruple.call(3)?, 12);
+    Ok(())
+}
+
+/// The mappings of the process both writable and executable.
+#[cfg(target_os = "linux")]
+fn writable_and_executable_mappings() -> Result<Vec<String>> {
+    let maps = std::fs::read_to_string("/proc/self/maps")?;
+    Ok(maps
+        .lines()
+        .filter(|line| {
+            let permissions = line.split_whitespace().nth(1).unwrap_or("");
+            permissions.contains('w') && permissions.contains('x')
+        })
+        .map(String::from)
+        .collect())
+}
+
+#[cfg(target_os = "linux")]
+#[compiler_test(code_memory)]
+fn code_is_never_writable_and_executable(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let module = get_module(&store)?;
+    assert_eq!(writable_and_executable_mappings()?, Vec::<String>::new());
+
+    // The code loaded from a serialized executable goes through the same publication.
+    let engine = store.engine();
+    let tunables = BaseTunables::for_target(engine.target());
+    let wasm = wat2wasm(b"(func (export \"empty\"))")?;
+    let serialized = engine.compile(&wasm, &tunables)?.serialize().unwrap();
+    let archived = unsafe { UniversalExecutableRef::deserialize(&serialized) }?;
+    engine.load(&archived)?;
+    assert_eq!(writable_and_executable_mappings()?, Vec::<String>::new());
+
+    let instance = Instance::new(&module, &imports! {})?;
+    let quadruple = instance.get_native_function::<i32, i32>("quadruple")?;
+    assert_eq!(quadruple.call(5)?, 20);
+    assert_eq!(writable_and_executable_mappings()?, Vec::<String>::new());
+    Ok(())
+}
+
+#[test]
+fn position_independent_code_runs_at_any_address() -> Result<()> {
+    let wat = r#"(module
+        (import "host" "offset" (func $offset (result i32)))
+        (type $unary (func (param i32) (result i32)))
+        (table funcref (elem $double))
+        (func $double (param i32) (result i32)
+            (i32.mul (local.get 0) (i32.const 2)))
+        (func (export "run") (param i32) (result i32)
+            (i32.add
+                (call $offset)
+                (call_indirect (type $unary)
+                    (call $double (local.get 0))
+                    (i32.const 0)))))"#;
+    let engine = Universal::new(Singlepass::default()).engine();
+    let tunables = BaseTunables::for_target(engine.target());
+    let executable = engine.compile_universal(&wat2wasm(wat.as_bytes())?, &tunables)?;
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("module.bin");
+    executable.serialize_to_file(&path).unwrap();
+
+    // Each module loaded from the file gets its own copy of the code, at another address.
+    let store = Store::new(&engine);
+    let first = unsafe { Module::deserialize_from_file_mmap(&store, &path) }?;
+    let second = unsafe { Module::deserialize_from_file_mmap(&store, &path) }?;
+    assert!(first.is_position_independent());
+    assert!(second.is_position_independent());
+    assert_ne!(first.code().as_ptr(), second.code().as_ptr());
+    assert_eq!(first.code(), second.code());
+
+    let imports = imports! {
+        "host" => { "offset" => Function::new_native(&store, || 1) },
+    };
+    for module in [&first, &second] {
+        let instance = Instance::new(module, &imports)?;
+        let run = instance.get_native_function::<i32, i32>("run")?;
+        assert_eq!(run.call(5)?, 21);
+    }
+
+    // The stubs of lazily compiled functions hold addresses specific to the engine.
+    let lazy = Universal::new(Singlepass::default())
+        .compile_mode(ModuleCompileMode::Lazy)
+        .engine();
+    assert!(!Module::new(&Store::new(&lazy), wat)?.is_position_independent());
+    Ok(())
+}
@@ -0,0 +1,101 @@
+//! Testing the limits on the size of the code generated by singlepass.
+
+use anyhow::Result;
+use wasmer::*;
+use wasmer_types::entity::EntityRef;
+use wasmer_vm::Artifact;
+
+/// A module with a small function, and a function made of `divisions` signed divisions, each
+/// of which expands to the checks against a zero divisor and an overflow.
+fn get_wasm(divisions: usize) -> Vec<u8> {
+    let body = "(local.set $x (i64.div_s (local.get $x) (local.get $y)))\n".repeat(divisions);
+    let wat = format!(
+        r#"
+        (func (export "small") (result i32)
+            (i32.const 42))
+        (func (export "divide") (param $x i64) (param $y i64) (result i64)
+            {}
+            (local.get $x))
+        "#,
+        body
+    );
+    wat2wasm(wat.as_bytes()).unwrap().into_owned()
+}
+
+fn compile(
+    wasm: &[u8],
+    configure: impl FnOnce(&mut Singlepass),
+) -> Result<UniversalArtifact, CompileError> {
+    let mut compiler = Singlepass::default();
+    configure(&mut compiler);
+    let engine = Universal::new(compiler).engine();
+    let tunables = BaseTunables::for_target(engine.target());
+    let executable = engine.compile_universal(wasm, &tunables)?;
+    engine.load_universal_executable(&executable)
+}
+
+#[test]
+fn code_sizes_are_recorded() -> Result<()> {
+    let artifact = compile(&get_wasm(200), |_| {})?;
+    let sizes = artifact.function_code_sizes();
+    assert_eq!(sizes.len(), 2);
+    let small = sizes[LocalFunctionIndex::new(0)];
+    let divide = sizes[LocalFunctionIndex::new(1)];
+    assert!(small > 0);
+    assert!(divide > 20 * small, "{} bytes for 200 divisions", divide);
+    assert_eq!(artifact.code_size(), small + divide);
+
+    // The code grows with the function.
+    let larger = compile(&get_wasm(201), |_| {})?;
+    assert!(larger.function_code_sizes()[LocalFunctionIndex::new(1)] > divide);
+    Ok(())
+}
+
+#[test]
+fn function_code_size_limit() -> Result<()> {
+    let wasm = get_wasm(200);
+    let size = compile(&wasm, |_| {})?.function_code_sizes()[LocalFunctionIndex::new(1)];
+
+    compile(&wasm, |compiler| {
+        compiler.max_function_code_size(size);
+    })?;
+    match compile(&wasm, |compiler| {
+        compiler.max_function_code_size(size - 1);
+    }) {
+        Err(CompileError::CodeSizeExceeded {
+            function,
+            size: actual,
+            limit,
+        }) => {
+            assert_eq!(function, LocalFunctionIndex::new(1));
+            assert_eq!((actual, limit), (size, size - 1));
+        }
+        other => panic!("unexpected compilation result: {:?}", other.map(|_| ())),
+    }
+    Ok(())
+}
+
+#[test]
+fn total_code_size_limit() -> Result<()> {
+    let wasm = get_wasm(200);
+    let total = compile(&wasm, |_| {})?.code_size();
+
+    let artifact = compile(&wasm, |compiler| {
+        compiler.max_total_code_size(total);
+    })?;
+    assert_eq!(artifact.code_size(), total);
+    match compile(&wasm, |compiler| {
+        compiler.max_total_code_size(total - 1);
+    }) {
+        // Either function can be compiled last, and push the total past the limit.
+        Err(CompileError::CodeSizeExceeded { size, limit, .. }) => {
+            assert_eq!((size, limit), (total, total - 1));
+        }
+        other => panic!("unexpected compilation result: {:?}", other.map(|_| ())),
+    }
+    // The limit on each function doesn't limit the total.
+    compile(&wasm, |compiler| {
+        compiler.max_function_code_size(total - 1);
+    })?;
+    Ok(())
+}
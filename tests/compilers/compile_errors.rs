@@ -0,0 +1,162 @@
+//! Testing the location of the errors compiling a function.
+
+use anyhow::Result;
+use wasmer::*;
+use wasmer_types::entity::EntityRef;
+
+#[compiler_test(compile_errors)]
+fn validation_errors_are_located(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let wasm = wat2wasm(
+        br#"
+        (import "env" "f" (func))
+        (func)
+        (func (result i32)
+            (i64.const 1))
+        "#,
+    )?;
+    match Module::new(&store, &wasm) {
+        Err(CompileError::Function { location, error }) => {
+            assert_eq!(location.local_index, LocalFunctionIndex::new(1));
+            assert_eq!(location.index, FunctionIndex::new(2));
+            // The `end` of the last function, the last byte of the module.
+            assert_eq!(location.offset, wasm.len() - 1);
+            assert_eq!(location.operator.as_deref(), Some("End"));
+            assert!(matches!(*error, CompileError::Validate(_)), "{:?}", error);
+        }
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+    Ok(())
+}
+
+#[compiler_test(compile_errors)]
+fn malformed_operators_are_located(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    #[rustfmt::skip]
+    let wasm = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        // The type `[] -> []`.
+        0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+        0x03, 0x02, 0x01, 0x00,
+        // The body: no locals, an unknown opcode and `end`.
+        0x0a, 0x05, 0x01, 0x03, 0x00, 0xff, 0x0b,
+    ];
+    match Module::new(&store, &wasm[..]) {
+        Err(CompileError::Function { location, .. }) => {
+            assert_eq!(location.index, FunctionIndex::new(0));
+            assert_eq!(location.offset, 23);
+            assert_eq!(location.operator, None);
+        }
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+    Ok(())
+}
+
+#[test]
+fn unsupported_operators_are_located() -> Result<()> {
+    let store = Store::new(&Universal::new(Singlepass::default()).engine());
+    let wasm = wat2wasm(
+        br#"
+        (import "env" "f" (func))
+        (func $mix (local v128 v128)
+            (drop (f32x4.add (local.get 0) (local.get 1))))
+        "#,
+    )?;
+    // The prefix of `f32x4.add`, the only SIMD operator of the module.
+    let offset = wasm.iter().position(|&byte| byte == 0xfd).unwrap();
+    match Module::new(&store, &wasm) {
+        Err(error @ CompileError::Function { .. }) => {
+            let location = error.location().unwrap();
+            assert_eq!(location.local_index, LocalFunctionIndex::new(0));
+            assert_eq!(location.index, FunctionIndex::new(1));
+            assert_eq!(location.name.as_deref(), Some("mix"));
+            assert_eq!(location.offset, offset);
+            assert_eq!(location.operator.as_deref(), Some("F32x4Add"));
+            assert!(
+                matches!(error.without_location(), CompileError::Codegen(_)),
+                "{:?}",
+                error
+            );
+            assert_eq!(
+                error.to_string(),
+                format!(
+                    "function 1 ('mix') at offset {:#x}: Compilation error: unsupported \
+                     operator F32x4Add",
+                    offset
+                )
+            );
+        }
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+    Ok(())
+}
+
+#[test]
+fn exhaustive_feature_check_reports_all_unsupported_features() -> Result<()> {
+    let mut compiler = Singlepass::default();
+    compiler.exhaustive_feature_check(true);
+    let mut features = Features::new();
+    features.threads(true);
+    features.exceptions = true;
+    let store = Store::new(&Universal::new(compiler).features(features).engine());
+
+    let supported = r#"
+        (tag $overflow)
+        (memory 1 1 shared)
+        (func $count (result i32)
+            (i32.atomic.rmw.add (i32.const 0) (i32.const 1)))
+        (func $fail
+            (throw $overflow))
+    "#;
+    Module::new(&store, supported)?;
+
+    let wasm = wat2wasm(
+        br#"
+        (memory 1 1 shared)
+        (func $mix (local v128 v128)
+            (drop (f32x4.add (local.get 0) (local.get 1))))
+        (func $count (result i32)
+            (i32.atomic.rmw.add (i32.const 0) (i32.const 1)))
+        (func $blend (local v128 v128)
+            (drop (f32x4.add (local.get 1) (local.get 0))))
+        "#,
+    )?;
+    let items = match Module::new(&store, &wasm) {
+        Err(CompileError::UnsupportedFeatures(items)) => items,
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    };
+    // The atomic and the exception handling operators are supported, the SIMD ones aren't.
+    let features = items
+        .iter()
+        .map(|item| item.feature.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(features, ["F32x4Add"]);
+
+    // The prefixes of the `f32x4.add`s, the only SIMD operators of the module.
+    let simd_offsets = wasm
+        .iter()
+        .enumerate()
+        .filter(|&(_, &byte)| byte == 0xfd)
+        .map(|(offset, _)| offset);
+    let simd_uses = items[0].uses.iter().map(|use_| match use_ {
+        UnsupportedUse::Function(location) => (location.name.as_deref(), location.offset),
+        use_ => panic!("unexpected use: {:?}", use_),
+    });
+    assert_eq!(
+        simd_uses.collect::<Vec<_>>(),
+        [Some("mix"), Some("blend")]
+            .iter()
+            .copied()
+            .zip(simd_offsets)
+            .collect::<Vec<_>>()
+    );
+    match &items[0].uses[1] {
+        UnsupportedUse::Function(location) => {
+            assert_eq!(location.index, FunctionIndex::new(2));
+            assert_eq!(location.name.as_deref(), Some("blend"));
+            assert_eq!(location.operator.as_deref(), Some("F32x4Add"));
+        }
+        use_ => panic!("unexpected use: {:?}", use_),
+    }
+    Ok(())
+}
@@ -0,0 +1,211 @@
+//! Tests of calls into one instance from several threads, which run one at a time.
+
+use anyhow::Result;
+use std::sync::{Arc, Barrier, Mutex};
+use std::thread;
+use wasmer::*;
+
+static COUNTER_WAT: &str = r#"(module
+    (import "host" "wait" (func $wait))
+    (global $count (mut i32) (i32.const 0))
+    (func (export "block") (call $wait))
+    (func (export "bump") (result i32)
+        (global.set $count (i32.add (global.get $count) (i32.const 1)))
+        (global.get $count))
+    (func (export "count") (result i32) (global.get $count)))"#;
+
+#[compiler_test(concurrency)]
+fn calls_from_another_thread_are_refused(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let entered = Arc::new(Barrier::new(2));
+    let release = Arc::new(Barrier::new(2));
+    let wait = Function::new_native(&store, {
+        let (entered, release) = (entered.clone(), release.clone());
+        move || {
+            entered.wait();
+            release.wait();
+        }
+    });
+    let module = Module::new(&store, COUNTER_WAT)?;
+    let instance = Instance::new(&module, &imports! { "host" => { "wait" => wait } })?;
+    let block: NativeFunc<(), ()> = instance.get_native_function("block")?;
+    let blocked = thread::spawn(move || block.call());
+
+    // The other thread waits in the host function, within its call into the instance.
+    entered.wait();
+    let bump: NativeFunc<(), i32> = instance.get_native_function("bump")?;
+    assert!(bump.call().unwrap_err().is_concurrent_reentry());
+    let error = instance.lookup_function("bump")?.call(&[]).unwrap_err();
+    assert!(error.is_concurrent_reentry(), "{}", error);
+    release.wait();
+    blocked.join().unwrap()?;
+
+    // The refused calls changed nothing.
+    assert_eq!(bump.call()?, 1);
+    Ok(())
+}
+
+#[compiler_test(concurrency)]
+fn host_functions_call_back_into_their_instance(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let inner: Arc<Mutex<Option<NativeFunc<(), i32>>>> = Arc::new(Mutex::new(None));
+    let callback = Function::new_native(&store, {
+        let inner = inner.clone();
+        move || -> Result<i32, RuntimeError> { inner.lock().unwrap().as_ref().unwrap().call() }
+    });
+    let wat = r#"(module
+        (import "host" "callback" (func $callback (result i32)))
+        (func (export "inner") (result i32) (i32.const 7))
+        (func (export "outer") (result i32) (i32.add (call $callback) (i32.const 1))))"#;
+    let module = Module::new(&store, wat)?;
+    let instance = Instance::new(&module, &imports! { "host" => { "callback" => callback } })?;
+    *inner.lock().unwrap() = Some(instance.get_native_function("inner")?);
+    let outer: NativeFunc<(), i32> = instance.get_native_function("outer")?;
+    assert_eq!(outer.call()?, 8);
+    // The instance is left once the outermost call returns.
+    let other = thread::spawn(move || outer.call());
+    assert_eq!(other.join().unwrap()?, 8);
+    *inner.lock().unwrap() = None;
+    Ok(())
+}
+
+#[compiler_test(concurrency)]
+fn threads_hammering_one_instance_do_not_race(config: crate::Config) -> Result<()> {
+    const THREADS: usize = 4;
+    const CALLS: usize = 10_000;
+
+    let store = config.store();
+    let wait = Function::new_native(&store, || {});
+    let module = Module::new(&store, COUNTER_WAT)?;
+    let instance = Instance::new(&module, &imports! { "host" => { "wait" => wait } })?;
+    let start = Arc::new(Barrier::new(THREADS));
+    let threads = (0..THREADS)
+        .map(|_| -> Result<_> {
+            let bump: NativeFunc<(), i32> = instance.get_native_function("bump")?;
+            let start = start.clone();
+            Ok(thread::spawn(move || {
+                start.wait();
+                let mut completed = 0;
+                for _ in 0..CALLS {
+                    match bump.call() {
+                        Ok(_) => completed += 1,
+                        Err(error) => assert!(error.is_concurrent_reentry(), "{}", error),
+                    }
+                }
+                completed
+            }))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let completed: i32 = threads.into_iter().map(|t| t.join().unwrap()).sum();
+
+    // Every call that ran incremented the counter, and no increment was lost to a race.
+    let count: NativeFunc<(), i32> = instance.get_native_function("count")?;
+    assert!(completed > 0);
+    assert_eq!(count.call()?, completed);
+    Ok(())
+}
+
+#[compiler_test(concurrency)]
+fn host_changes_from_another_thread_are_refused(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let entered = Arc::new(Barrier::new(2));
+    let release = Arc::new(Barrier::new(2));
+    let wait = Function::new_native(&store, {
+        let (entered, release) = (entered.clone(), release.clone());
+        move || {
+            entered.wait();
+            release.wait();
+        }
+    });
+    let wat = r#"(module
+        (import "host" "wait" (func $wait))
+        (memory (export "memory") 1)
+        (table (export "table") 1 funcref)
+        (global (export "global") (mut i32) (i32.const 0))
+        (func (export "block") (call $wait)))"#;
+    let module = Module::new(&store, wat)?;
+    let instance = Instance::new(&module, &imports! { "host" => { "wait" => wait } })?;
+    let memory = instance.lookup_memory("memory")?;
+    let table = instance.lookup_table("table")?;
+    let global = instance.lookup_global("global")?;
+    let block: NativeFunc<(), ()> = instance.get_native_function("block")?;
+    let blocked = thread::spawn(move || block.call());
+
+    entered.wait();
+    assert_eq!(memory.grow(Pages(1)), Err(MemoryError::ConcurrentReentry));
+    let null = Val::FuncRef(None);
+    assert!(table
+        .set(0, null.clone())
+        .unwrap_err()
+        .is_concurrent_reentry());
+    assert!(table
+        .grow(1, null.clone())
+        .unwrap_err()
+        .is_concurrent_reentry());
+    assert!(table
+        .fill(0, 1, null.clone())
+        .unwrap_err()
+        .is_concurrent_reentry());
+    assert!(global.set(Val::I32(1)).unwrap_err().is_concurrent_reentry());
+    release.wait();
+    blocked.join().unwrap()?;
+
+    // The refused changes changed nothing, and are accepted once the call returned.
+    assert_eq!(memory.size(), Pages(1));
+    assert_eq!(table.size(), 1);
+    assert_eq!(global.get(), Val::I32(0));
+    assert_eq!(memory.grow(Pages(1))?, Pages(1));
+    assert_eq!(table.grow(1, null)?, 1);
+    global.set(Val::I32(1))?;
+    Ok(())
+}
+
+#[compiler_test(concurrency)]
+fn calls_enter_the_instances_they_import_from(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let entered = Arc::new(Barrier::new(2));
+    let release = Arc::new(Barrier::new(2));
+    let wait = Function::new_native(&store, {
+        let (entered, release) = (entered.clone(), release.clone());
+        move || {
+            entered.wait();
+            release.wait();
+        }
+    });
+    let module = Module::new(&store, COUNTER_WAT)?;
+    let library = Instance::new(&module, &imports! { "host" => { "wait" => wait.clone() } })?;
+    let wat = r#"(module
+        (import "host" "wait" (func $wait))
+        (import "library" "bump" (func $bump (result i32)))
+        (func (export "block") (call $wait))
+        (func (export "bump") (result i32) (call $bump)))"#;
+    let module = Module::new(&store, wat)?;
+    let imports = imports! {
+        "host" => { "wait" => wait },
+        "library" => { "bump" => library.lookup_function("bump")? },
+    };
+    let importer = Instance::new(&module, &imports)?;
+    let library_bump: NativeFunc<(), i32> = library.get_native_function("bump")?;
+    let importer_bump: NativeFunc<(), i32> = importer.get_native_function("bump")?;
+
+    // A thread running in the importing instance may call the library, so the library
+    // refuses other threads meanwhile.
+    let block: NativeFunc<(), ()> = importer.get_native_function("block")?;
+    let blocked = thread::spawn(move || block.call());
+    entered.wait();
+    assert!(library_bump.call().unwrap_err().is_concurrent_reentry());
+    release.wait();
+    blocked.join().unwrap()?;
+
+    // And the importing instance refuses the calls while another thread runs in the library.
+    let block: NativeFunc<(), ()> = library.get_native_function("block")?;
+    let blocked = thread::spawn(move || block.call());
+    entered.wait();
+    assert!(importer_bump.call().unwrap_err().is_concurrent_reentry());
+    release.wait();
+    blocked.join().unwrap()?;
+
+    assert_eq!(importer_bump.call()?, 1);
+    assert_eq!(library_bump.call()?, 2);
+    Ok(())
+}
@@ -1,4 +1,4 @@
-use wasmer::{CompilerConfig, Engine as WasmerEngine, Features, Store};
+use wasmer::{CompilerConfig, Engine as WasmerEngine, Features, Store, Target};
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Compiler {
@@ -16,7 +16,9 @@ pub struct Config {
     pub compiler: Compiler,
     pub engine: Engine,
     pub features: Option<Features>,
+    pub target: Option<Target>,
     pub canonicalize_nans: bool,
+    pub peephole: bool,
 }
 
 impl Config {
@@ -25,7 +27,9 @@ impl Config {
             compiler,
             engine,
             features: None,
+            target: None,
             canonicalize_nans: false,
+            peephole: false,
         }
     }
 
@@ -33,10 +37,18 @@ impl Config {
         self.features = Some(features);
     }
 
+    pub fn set_target(&mut self, target: Target) {
+        self.target = Some(target);
+    }
+
     pub fn set_nan_canonicalization(&mut self, canonicalize_nans: bool) {
         self.canonicalize_nans = canonicalize_nans;
     }
 
+    pub fn set_peephole(&mut self, peephole: bool) {
+        self.peephole = peephole;
+    }
+
     pub fn store(&self) -> Store {
         let compiler_config = self.compiler_config(self.canonicalize_nans);
         let engine = self.engine(compiler_config);
@@ -58,6 +70,9 @@ impl Config {
                 if let Some(ref features) = self.features {
                     engine = engine.features(features.clone())
                 }
+                if let Some(ref target) = self.target {
+                    engine = engine.target(target.clone())
+                }
                 Box::new(engine.engine())
             }
             #[allow(unreachable_patterns)]
@@ -86,6 +101,7 @@ impl Config {
             Compiler::Singlepass => {
                 let mut compiler = wasmer_compiler_singlepass::Singlepass::new();
                 compiler.canonicalize_nans(canonicalize_nans);
+                compiler.enable_peephole(self.peephole);
                 compiler.enable_verifier();
                 Box::new(compiler)
             }
@@ -0,0 +1,103 @@
+//! Tests of the operators evaluated at compile time, or simplified, when operands are constants.
+
+use anyhow::Result;
+use wasmer::*;
+use wasmer_vm::Artifact;
+
+#[compiler_test(const_fold)]
+fn constant_operands(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let wat = r#"(module
+        (func (export "folded") (result i32)
+            (i32.add (i32.const 4) (i32.const 8))
+            (i32.rem_s (i32.const 0x80000000) (i32.const -1))
+            (i32.add)
+            (i32.shr_s (i32.const -64) (i32.const 33))
+            (i32.add))
+        (func (export "folded64") (result i64)
+            (i64.div_u (i64.const -1) (i64.const 2)))
+        (func (export "mul") (param i32) (result i32)
+            (i32.mul (i32.const 8) (local.get 0)))
+        (func (export "div_u") (param i64) (result i64)
+            (i64.div_u (local.get 0) (i64.const 0x100000000)))
+        (func (export "rem_u") (param i64) (result i64)
+            (i64.rem_u (local.get 0) (i64.const 0x100000000)))
+        (func (export "shl") (param i32) (result i32)
+            (i32.shl (local.get 0) (i32.const 35)))
+    )"#;
+    let module = Module::new(&store, wat)?;
+    let instance = Instance::new(&module, &imports! {})?;
+
+    let folded: NativeFunc<(), i32> = instance.get_native_function("folded")?;
+    assert_eq!(folded.call()?, -20);
+    let folded64: NativeFunc<(), i64> = instance.get_native_function("folded64")?;
+    assert_eq!(folded64.call()?, i64::MAX);
+
+    let mul: NativeFunc<i32, i32> = instance.get_native_function("mul")?;
+    assert_eq!(mul.call(-3)?, -24);
+    assert_eq!(mul.call(0x20000000)?, 0);
+    let div_u: NativeFunc<i64, i64> = instance.get_native_function("div_u")?;
+    assert_eq!(div_u.call(-1)?, 0xffffffff);
+    let rem_u: NativeFunc<i64, i64> = instance.get_native_function("rem_u")?;
+    assert_eq!(rem_u.call(-1)?, 0xffffffff);
+    let shl: NativeFunc<i32, i32> = instance.get_native_function("shl")?;
+    assert_eq!(shl.call(3)?, 24);
+    Ok(())
+}
+
+#[compiler_test(const_fold)]
+fn constant_operands_still_trap(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let wat = r#"(module
+        (func (export "div_by_zero") (result i32)
+            (i32.div_u (i32.const 1) (i32.const 0)))
+        (func (export "rem_by_zero") (result i64)
+            (i64.rem_s (i64.const 1) (i64.const 0)))
+        (func (export "overflow") (result i32)
+            (i32.div_s (i32.const 0x80000000) (i32.const -1)))
+    )"#;
+    let module = Module::new(&store, wat)?;
+    let instance = Instance::new(&module, &imports! {})?;
+
+    for (name, message) in [
+        ("div_by_zero", "integer divide by zero"),
+        ("rem_by_zero", "integer divide by zero"),
+        ("overflow", "integer overflow"),
+    ] {
+        let f = instance.lookup_function(name).unwrap();
+        assert_eq!(f.call(&[]).unwrap_err().message(), message);
+    }
+    Ok(())
+}
+
+#[test]
+fn folded_operators_take_no_code() -> Result<()> {
+    let wasm = wat2wasm(
+        br#"(module
+            (func (result i32)
+                (i32.add
+                    (i32.mul (i32.const 6) (i32.const 7))
+                    (i32.shl (i32.const 1) (i32.wrap_i64 (i64.const 4)))))
+            (func (result i32)
+                (i32.const 58))
+            (func (param i32 i32) (result i32)
+                (i32.div_u (local.get 0) (i32.const 16)))
+            (func (param i32 i32) (result i32)
+                (i32.div_u (local.get 0) (local.get 1)))
+        )"#,
+    )?;
+    let engine = Universal::new(Singlepass::default()).engine();
+    let tunables = BaseTunables::for_target(engine.target());
+    let executable = engine.compile_universal(&wasm, &tunables)?;
+    let artifact = engine.load_universal_executable(&executable)?;
+    let sizes = artifact
+        .function_code_sizes()
+        .values()
+        .copied()
+        .collect::<Vec<_>>();
+    // The constant operators compile to the constant they evaluate to.
+    assert_eq!(sizes[0], sizes[1]);
+    // A division by a power of two is a shift, without the checks of the divisor of `div`.
+    assert!(sizes[2] < sizes[3], "{} bytes to divide by 16", sizes[2]);
+    Ok(())
+}
@@ -0,0 +1,464 @@
+//! Tests of every numeric conversion operator, with its operand coming from a parameter, a
+//! stack slot, a memory load and a constant, against a reference implementation.
+
+use anyhow::Result;
+use wasmer::*;
+use wasmer_vm::TrapCode;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Ty {
+    I32,
+    I64,
+    F32,
+    F64,
+}
+
+impl Ty {
+    fn name(self) -> &'static str {
+        match self {
+            Ty::I32 => "i32",
+            Ty::I64 => "i64",
+            Ty::F32 => "f32",
+            Ty::F64 => "f64",
+        }
+    }
+
+    fn value(self, bits: u64) -> Value {
+        match self {
+            Ty::I32 => Value::I32(bits as u32 as i32),
+            Ty::I64 => Value::I64(bits as i64),
+            Ty::F32 => Value::F32(f32::from_bits(bits as u32)),
+            Ty::F64 => Value::F64(f64::from_bits(bits)),
+        }
+    }
+
+    fn bits(self, value: &Value) -> u64 {
+        match (self, value) {
+            (Ty::I32, Value::I32(x)) => *x as u32 as u64,
+            (Ty::I64, Value::I64(x)) => *x as u64,
+            (Ty::F32, Value::F32(x)) => x.to_bits() as u64,
+            (Ty::F64, Value::F64(x)) => x.to_bits(),
+            _ => panic!("{:?} is not a value of type {:?}", value, self),
+        }
+    }
+
+    fn is_nan(self, bits: u64) -> bool {
+        match self {
+            Ty::F32 => f32::from_bits(bits as u32).is_nan(),
+            Ty::F64 => f64::from_bits(bits).is_nan(),
+            _ => false,
+        }
+    }
+
+    /// The constant of this type with the given bits, in the text format. The floats are
+    /// written in hexadecimal, so that they are exact.
+    fn constant(self, bits: u64) -> String {
+        let float = |bits: u64, exponent_bits: u32, mantissa_bits: u32| {
+            let exponent_bias = (1 << (exponent_bits - 1)) - 1;
+            let sign = if bits >> (exponent_bits + mantissa_bits) & 1 == 1 {
+                "-"
+            } else {
+                ""
+            };
+            let exponent = (bits >> mantissa_bits) & ((1 << exponent_bits) - 1);
+            let mantissa = bits & ((1 << mantissa_bits) - 1);
+            // The hexadecimal digits of the mantissa, padded to a whole number of digits.
+            let digits = (mantissa_bits + 3) / 4;
+            let mantissa_hex = mantissa << (digits * 4 - mantissa_bits);
+            let body = if exponent == (1 << exponent_bits) - 1 {
+                if mantissa == 0 {
+                    "inf".to_string()
+                } else {
+                    format!("nan:0x{:x}", mantissa)
+                }
+            } else if exponent == 0 {
+                format!(
+                    "0x0.{:0w$x}p{}",
+                    mantissa_hex,
+                    1 - exponent_bias,
+                    w = digits as usize
+                )
+            } else {
+                format!(
+                    "0x1.{:0w$x}p{}",
+                    mantissa_hex,
+                    exponent as i64 - exponent_bias,
+                    w = digits as usize
+                )
+            };
+            format!("{}{}", sign, body)
+        };
+        match self {
+            Ty::I32 => (bits as u32 as i32).to_string(),
+            Ty::I64 => (bits as i64).to_string(),
+            Ty::F32 => float(bits & u64::from(u32::MAX), 8, 23),
+            Ty::F64 => float(bits, 11, 52),
+        }
+    }
+
+    /// Interesting operands of this type, as bits.
+    fn inputs(self) -> Vec<u64> {
+        match self {
+            Ty::I32 => [
+                0,
+                1,
+                -1,
+                i32::MIN,
+                i32::MAX,
+                i32::MIN + 1,
+                16_777_217,
+                -16_777_217,
+                0x7fff_ffc0,
+            ]
+            .iter()
+            .map(|&x| x as u32 as u64)
+            .collect(),
+            Ty::I64 => [
+                0,
+                1,
+                -1,
+                i64::MIN,
+                i64::MAX,
+                (1 << 53) + 1,
+                -(1 << 53) - 1,
+                0x8000_0000,
+                0x1_0000_0000,
+                -0x8000_0000,
+                -1025,
+                0x7fff_ff80_0000_0001,
+            ]
+            .iter()
+            .map(|&x| x as u64)
+            .collect(),
+            Ty::F32 => {
+                let floats = [
+                    0.0f32,
+                    -0.0,
+                    1.5,
+                    -1.5,
+                    -0.9,
+                    2147483648.0,
+                    -2147483648.0,
+                    2147483520.0,
+                    4294967296.0,
+                    4294967040.0,
+                    9223372036854775808.0,
+                    -9223372036854775808.0,
+                    18446744073709551616.0,
+                    f32::MAX,
+                    f32::MIN_POSITIVE,
+                    f32::INFINITY,
+                    f32::NEG_INFINITY,
+                ];
+                let mut inputs: Vec<u64> = floats.iter().map(|x| x.to_bits() as u64).collect();
+                // Quiet and signaling NaNs, with payloads and both signs, and a subnormal.
+                inputs.extend_from_slice(&[0x7fc0_0000, 0x7fa0_0001, 0xffc0_0123, 1]);
+                inputs
+            }
+            Ty::F64 => {
+                let floats = [
+                    0.0f64,
+                    -0.0,
+                    1.5,
+                    -1.5,
+                    -0.9,
+                    2147483647.9,
+                    -2147483648.9,
+                    2147483648.0,
+                    -2147483649.0,
+                    4294967295.9,
+                    4294967296.0,
+                    9223372036854774784.0,
+                    9223372036854775808.0,
+                    -9223372036854775808.0,
+                    18446744073709549568.0,
+                    18446744073709551616.0,
+                    // Halfway between two floats, rounded to the even one when demoted.
+                    1.000000059604644775390625,
+                    1e300,
+                    f64::MIN_POSITIVE,
+                    f64::INFINITY,
+                    f64::NEG_INFINITY,
+                ];
+                let mut inputs: Vec<u64> = floats.iter().map(|x| x.to_bits()).collect();
+                inputs.extend_from_slice(&[
+                    0x7ff8_0000_0000_0000,
+                    0x7ff4_0000_0000_0001,
+                    0xfff8_0000_dead_beef,
+                    1,
+                ]);
+                inputs
+            }
+        }
+    }
+}
+
+/// A conversion operator, with the reference implementation of its semantics on the bits of
+/// its operand and result.
+struct Conversion {
+    name: &'static str,
+    from: Ty,
+    to: Ty,
+    eval: fn(u64) -> Result<u64, TrapCode>,
+}
+
+fn f32_of(bits: u64) -> f32 {
+    f32::from_bits(bits as u32)
+}
+
+fn f64_of(bits: u64) -> f64 {
+    f64::from_bits(bits)
+}
+
+/// Truncates `x` towards zero, trapping if it is NaN or if the result is out of
+/// `[min, max)`.
+fn trunc(x: f64, (min, max): (f64, f64)) -> Result<f64, TrapCode> {
+    if x.is_nan() {
+        return Err(TrapCode::BadConversionToInteger);
+    }
+    let t = x.trunc();
+    if t >= min && t < max {
+        Ok(t)
+    } else {
+        Err(TrapCode::IntegerOverflow)
+    }
+}
+
+const I32_RANGE: (f64, f64) = (-2147483648.0, 2147483648.0);
+const U32_RANGE: (f64, f64) = (0.0, 4294967296.0);
+const I64_RANGE: (f64, f64) = (-9223372036854775808.0, 9223372036854775808.0);
+const U64_RANGE: (f64, f64) = (0.0, 18446744073709551616.0);
+
+fn conversions() -> Vec<Conversion> {
+    use Ty::*;
+    fn conversion(
+        name: &'static str,
+        from: Ty,
+        to: Ty,
+        eval: fn(u64) -> Result<u64, TrapCode>,
+    ) -> Conversion {
+        Conversion {
+            name,
+            from,
+            to,
+            eval,
+        }
+    }
+    // The `as` casts of Rust round to nearest, ties to even, and saturate like the `_sat`
+    // operators.
+    vec![
+        conversion("i32.wrap_i64", I64, I32, |x| Ok(x as u32 as u64)),
+        conversion("i64.extend_i32_s", I32, I64, |x| {
+            Ok(x as u32 as i32 as i64 as u64)
+        }),
+        conversion("i64.extend_i32_u", I32, I64, |x| Ok(x as u32 as u64)),
+        conversion("i32.trunc_f32_s", F32, I32, |x| {
+            trunc(f32_of(x) as f64, I32_RANGE).map(|t| t as i32 as u32 as u64)
+        }),
+        conversion("i32.trunc_f32_u", F32, I32, |x| {
+            trunc(f32_of(x) as f64, U32_RANGE).map(|t| t as u32 as u64)
+        }),
+        conversion("i32.trunc_f64_s", F64, I32, |x| {
+            trunc(f64_of(x), I32_RANGE).map(|t| t as i32 as u32 as u64)
+        }),
+        conversion("i32.trunc_f64_u", F64, I32, |x| {
+            trunc(f64_of(x), U32_RANGE).map(|t| t as u32 as u64)
+        }),
+        conversion("i64.trunc_f32_s", F32, I64, |x| {
+            trunc(f32_of(x) as f64, I64_RANGE).map(|t| t as i64 as u64)
+        }),
+        conversion("i64.trunc_f32_u", F32, I64, |x| {
+            trunc(f32_of(x) as f64, U64_RANGE).map(|t| t as u64)
+        }),
+        conversion("i64.trunc_f64_s", F64, I64, |x| {
+            trunc(f64_of(x), I64_RANGE).map(|t| t as i64 as u64)
+        }),
+        conversion("i64.trunc_f64_u", F64, I64, |x| {
+            trunc(f64_of(x), U64_RANGE).map(|t| t as u64)
+        }),
+        conversion("i32.trunc_sat_f32_s", F32, I32, |x| {
+            Ok(f32_of(x) as i32 as u32 as u64)
+        }),
+        conversion("i32.trunc_sat_f32_u", F32, I32, |x| {
+            Ok(f32_of(x) as u32 as u64)
+        }),
+        conversion("i32.trunc_sat_f64_s", F64, I32, |x| {
+            Ok(f64_of(x) as i32 as u32 as u64)
+        }),
+        conversion("i32.trunc_sat_f64_u", F64, I32, |x| {
+            Ok(f64_of(x) as u32 as u64)
+        }),
+        conversion("i64.trunc_sat_f32_s", F32, I64, |x| {
+            Ok(f32_of(x) as i64 as u64)
+        }),
+        conversion("i64.trunc_sat_f32_u", F32, I64, |x| Ok(f32_of(x) as u64)),
+        conversion("i64.trunc_sat_f64_s", F64, I64, |x| {
+            Ok(f64_of(x) as i64 as u64)
+        }),
+        conversion("i64.trunc_sat_f64_u", F64, I64, |x| Ok(f64_of(x) as u64)),
+        conversion("f32.convert_i32_s", I32, F32, |x| {
+            Ok((x as u32 as i32 as f32).to_bits() as u64)
+        }),
+        conversion("f32.convert_i32_u", I32, F32, |x| {
+            Ok((x as u32 as f32).to_bits() as u64)
+        }),
+        conversion("f32.convert_i64_s", I64, F32, |x| {
+            Ok((x as i64 as f32).to_bits() as u64)
+        }),
+        conversion("f32.convert_i64_u", I64, F32, |x| {
+            Ok((x as f32).to_bits() as u64)
+        }),
+        conversion("f64.convert_i32_s", I32, F64, |x| {
+            Ok((x as u32 as i32 as f64).to_bits())
+        }),
+        conversion("f64.convert_i32_u", I32, F64, |x| {
+            Ok((x as u32 as f64).to_bits())
+        }),
+        conversion("f64.convert_i64_s", I64, F64, |x| {
+            Ok((x as i64 as f64).to_bits())
+        }),
+        conversion("f64.convert_i64_u", I64, F64, |x| Ok((x as f64).to_bits())),
+        conversion("f32.demote_f64", F64, F32, |x| {
+            Ok((f64_of(x) as f32).to_bits() as u64)
+        }),
+        conversion("f64.promote_f32", F32, F64, |x| {
+            Ok((f32_of(x) as f64).to_bits())
+        }),
+        conversion("i32.reinterpret_f32", F32, I32, Ok),
+        conversion("i64.reinterpret_f64", F64, I64, Ok),
+        conversion("f32.reinterpret_i32", I32, F32, Ok),
+        conversion("f64.reinterpret_i64", I64, F64, Ok),
+    ]
+}
+
+/// The number of live values of each register class that push the operand of `spilled` out of
+/// the registers.
+const FILLERS: usize = 8;
+
+/// A module exporting functions applying `conversion` to an operand taken from a parameter, a
+/// stack slot, a memory load, and each of `inputs` as a constant.
+fn conversion_module(conversion: &Conversion, inputs: &[u64]) -> String {
+    let (from, to, op) = (
+        conversion.from.name(),
+        conversion.to.name(),
+        conversion.name,
+    );
+    let fillers = (0..2 * FILLERS)
+        .map(|i| format!("(local.get {})", i))
+        .collect::<String>();
+    let drops = "(drop)".repeat(2 * FILLERS);
+    let constants = inputs
+        .iter()
+        .enumerate()
+        .map(|(i, &bits)| {
+            format!(
+                r#"(func (export "const{}") (result {}) ({} ({}.const {})))"#,
+                i,
+                to,
+                op,
+                from,
+                conversion.from.constant(bits)
+            )
+        })
+        .collect::<String>();
+    format!(
+        r#"(module
+            (memory 1)
+            (func (export "param") (param $x {from}) (result {to}) ({op} (local.get $x)))
+            (func (export "spilled")
+                (param {ints}) (param {floats}) (param $x {from}) (result {to}) (local $r {to})
+                {fillers}
+                (local.set $r ({op} (local.get $x)))
+                {drops}
+                (local.get $r))
+            (func (export "load") (param $x {from}) (result {to})
+                ({from}.store (i32.const 8) (local.get $x))
+                ({op} ({from}.load (i32.const 8))))
+            {constants})"#,
+        from = from,
+        to = to,
+        op = op,
+        ints = "i64 ".repeat(FILLERS),
+        floats = "f64 ".repeat(FILLERS),
+        fillers = fillers,
+        drops = drops,
+        constants = constants,
+    )
+}
+
+#[compiler_test(conversions)]
+fn conversions_match_the_reference(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    for conversion in conversions() {
+        let inputs = conversion.from.inputs();
+        let module = Module::new(&store, conversion_module(&conversion, &inputs))?;
+        let instance = Instance::new(&module, &imports! {})?;
+        let param = instance.lookup_function("param")?;
+        let spilled = instance.lookup_function("spilled")?;
+        let load = instance.lookup_function("load")?;
+        for (i, &bits) in inputs.iter().enumerate() {
+            let expected = (conversion.eval)(bits);
+            let x = conversion.from.value(bits);
+            let mut spilled_args = vec![Value::I64(0); FILLERS];
+            spilled_args.extend(vec![Value::F64(0.0); FILLERS]);
+            spilled_args.push(x.clone());
+            let constant = instance.lookup_function(&format!("const{}", i))?;
+            let results = [
+                ("param", param.call(&[x.clone()])),
+                ("spilled", spilled.call(&spilled_args)),
+                ("load", load.call(&[x.clone()])),
+                ("const", constant.call(&[])),
+            ];
+            for (source, result) in results.iter() {
+                let context = format!("{} of {:#x} from {}", conversion.name, bits, source);
+                let actual = match result {
+                    Ok(values) => Ok(conversion.to.bits(&values[0])),
+                    Err(error) => Err(error
+                        .clone()
+                        .to_trap()
+                        .unwrap_or_else(|| panic!("{}: unexpected error {}", context, error))),
+                };
+                match (expected, actual) {
+                    // Only the reinterpretations keep the payload of NaNs.
+                    (Ok(expected), Ok(actual))
+                        if conversion.to.is_nan(expected)
+                            && !conversion.name.contains("reinterpret") =>
+                    {
+                        assert!(conversion.to.is_nan(actual), "{}: {:#x}", context, actual)
+                    }
+                    (expected, actual) => assert_eq!(actual, expected, "{}", context),
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[compiler_test(conversions)]
+fn chained_conversions_keep_their_bits(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let wat = r#"(module
+        (func (export "extend_reinterpret") (param f32) (result i64)
+            (i64.extend_i32_u (i32.reinterpret_f32 (local.get 0))))
+        (func (export "reinterpret_wrap") (param f64) (result f32)
+            (f32.reinterpret_i32 (i32.wrap_i64 (i64.reinterpret_f64 (local.get 0)))))
+        (func (export "round_trip") (param i64) (result i64)
+            (i64.reinterpret_f64 (f64.reinterpret_i64 (local.get 0)))))"#;
+    let instance = Instance::new(&Module::new(&store, wat)?, &imports! {})?;
+    let extend_reinterpret: NativeFunc<f32, i64> =
+        instance.get_native_function("extend_reinterpret")?;
+    let reinterpret_wrap: NativeFunc<f64, f32> =
+        instance.get_native_function("reinterpret_wrap")?;
+    let round_trip: NativeFunc<i64, i64> = instance.get_native_function("round_trip")?;
+    for &bits in &[0xffc0_0123u32, 0x7fa0_0001, 0x8000_0000, 0x3fc0_0000] {
+        let result = extend_reinterpret.call(f32::from_bits(bits))?;
+        assert_eq!(result as u64, u64::from(bits));
+        let result =
+            reinterpret_wrap.call(f64::from_bits(0xdead_beef_0000_0000 | u64::from(bits)))?;
+        assert_eq!(result.to_bits(), bits);
+    }
+    for &bits in &[0x7ff4_0000_0000_0001u64, 0xfff8_0000_dead_beef, 1, u64::MAX] {
+        assert_eq!(round_trip.call(bits as i64)? as u64, bits);
+    }
+    Ok(())
+}
@@ -0,0 +1,65 @@
+//! Testing the sharing of the code of the modules compiled from the same binaries.
+
+use anyhow::Result;
+use std::sync::{Arc, Barrier};
+use std::thread;
+use wasmer::*;
+
+static WAT: &str = r#"
+    (func (export "answer") (result i32)
+        (i32.const 42))
+"#;
+
+#[compiler_test(deduplication)]
+fn racing_compilations_of_the_same_binary_compile_it_once(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let wasm = wat2wasm(WAT.as_bytes())?.into_owned();
+    let barrier = Arc::new(Barrier::new(100));
+    let threads = (0..100)
+        .map(|_| {
+            let (store, wasm, barrier) = (store.clone(), wasm.clone(), barrier.clone());
+            thread::spawn(move || {
+                barrier.wait();
+                Module::new(&store, &wasm)
+            })
+        })
+        .collect::<Vec<_>>();
+    let modules = threads
+        .into_iter()
+        .map(|thread| thread.join().unwrap())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let stats = store.engine().cache_stats();
+    assert_eq!(stats.misses, 1);
+    assert_eq!(stats.hits, 99);
+    assert!(stats.bytes_saved > 0);
+    assert_eq!(stats.bytes_saved % 99, 0);
+    for module in &modules {
+        let instance = Instance::new(module, &imports! {})?;
+        let answer: NativeFunc<(), i32> = instance.get_native_function("answer")?;
+        assert_eq!(answer.call()?, 42);
+    }
+    Ok(())
+}
+
+#[compiler_test(deduplication)]
+fn artifacts_are_evicted_with_their_last_module(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let module = Module::new(&store, WAT)?;
+    let clone = module.clone();
+    drop(module);
+    Module::new(&store, WAT)?;
+    let stats = store.engine().cache_stats();
+    assert_eq!((stats.misses, stats.hits), (1, 1));
+
+    // Once no module holds the code, the binary is compiled again.
+    drop(clone);
+    Module::new(&store, WAT)?;
+    assert_eq!(store.engine().cache_stats().misses, 2);
+
+    // As are other binaries.
+    Module::new(&store, "(module)")?;
+    assert_eq!(store.engine().cache_stats().misses, 3);
+    assert_eq!(store.engine().cache_stats().hits, 1);
+    Ok(())
+}
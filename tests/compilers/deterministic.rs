@@ -1,7 +1,8 @@
 use anyhow::Result;
+use std::fs;
 use wasmer::{wat2wasm, BaseTunables, Engine};
 use wasmer_compiler_singlepass::Singlepass;
-use wasmer_engine_universal::Universal;
+use wasmer_engine_universal::{Universal, UniversalEngine};
 
 fn compile_and_compare(wasm: &[u8]) -> Result<()> {
     let compiler = Singlepass::default();
@@ -46,3 +47,101 @@ fn deterministic_table() -> Result<()> {
 
     compile_and_compare(&wasm_bytes)
 }
+
+fn serialize_with(engine: &UniversalEngine, wasm: &[u8]) -> Vec<u8> {
+    let tunables = BaseTunables::for_target(engine.target());
+    engine
+        .compile(wasm, &tunables)
+        .unwrap()
+        .serialize()
+        .unwrap()
+}
+
+fn engine_with_threads(num_threads: Option<usize>) -> UniversalEngine {
+    let mut compiler = Singlepass::default();
+    compiler.num_threads(num_threads);
+    Universal::new(compiler).engine()
+}
+
+#[test]
+fn deterministic_across_many_compilations() -> Result<()> {
+    // Enough functions, imports, exports and names for the compilation to be
+    // spread across threads and for any map to have several entries.
+    let mut wat = String::from(
+        r#"(module
+  (import "env" "imported" (func $imported (param i64) (result i64)))
+  (import "env" "memory" (memory 1))
+  (table 300 funcref)
+  (global $counter (mut i64) (i64.const 0))
+  (data (i32.const 16) "determinism")"#,
+    );
+    for i in 0..300 {
+        wat.push_str(&format!(
+            r#"
+  (func $f{i} (export "f{i}") (param i64) (result i64)
+    (global.set $counter (i64.add (global.get $counter) (i64.const {i})))
+    (i64.store (i32.const {i}) (local.get 0))
+    (call $imported (i64.div_s (i64.load (i32.const {i})) (local.get 0))))
+  (elem (i32.const {i}) $f{i})"#,
+            i = i
+        ));
+    }
+    wat.push(')');
+    let wasm = wat2wasm(wat.as_bytes())?;
+
+    let sequential = engine_with_threads(Some(1));
+    let expected = serialize_with(&sequential, &wasm);
+    for engine in [
+        sequential,
+        engine_with_threads(None),
+        engine_with_threads(Some(8)),
+    ] {
+        for _ in 0..50 {
+            assert!(serialize_with(&engine, &wasm) == expected);
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn deterministic_spec_modules() -> Result<()> {
+    let sequential = engine_with_threads(Some(1));
+    let parallel = engine_with_threads(Some(8));
+    let mut checked = 0;
+    for entry in fs::read_dir("tests/wast/spec")? {
+        let path = entry?.path();
+        if path
+            .extension()
+            .map_or(true, |extension| extension != "wast")
+        {
+            continue;
+        }
+        let contents = fs::read_to_string(&path)?;
+        let buf = wast38::parser::ParseBuffer::new(&contents)?;
+        let ast = wast38::parser::parse::<wast38::Wast>(&buf)?;
+        for directive in ast.directives {
+            let binary = match directive {
+                wast38::WastDirective::Module(mut module) => module.encode()?,
+                _ => continue,
+            };
+            // Modules using features that are not enabled by default are skipped.
+            if sequential.validate(&binary).is_err() {
+                continue;
+            }
+            let expected = serialize_with(&sequential, &binary);
+            assert!(
+                serialize_with(&sequential, &binary) == expected,
+                "{} compiles differently each time",
+                path.display()
+            );
+            assert!(
+                serialize_with(&parallel, &binary) == expected,
+                "{} compiles differently on several threads",
+                path.display()
+            );
+            checked += 1;
+        }
+    }
+    assert!(checked > 0);
+    Ok(())
+}
@@ -0,0 +1,107 @@
+//! Testing the epoch interruption of singlepass.
+
+use anyhow::Result;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use wasmer::*;
+use wasmer_compiler_singlepass::Singlepass;
+use wasmer_engine_universal::Universal;
+use wasmer_vm::TrapCode;
+
+fn get_store() -> Store {
+    let mut compiler = Singlepass::default();
+    compiler.enable_epoch_interruption(true);
+    Store::new(&Universal::new(compiler).engine())
+}
+
+fn get_instance(store: &Store) -> Result<Instance> {
+    let wat = r#"
+        (func (export "spin")
+            (loop $forever (br $forever)))
+        (func (export "count") (param $n i32) (result i32)
+            (local $i i32)
+            (block $done
+                (loop $next
+                    (br_if $done (i32.eq (local.get $i) (local.get $n)))
+                    (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                    (br $next)))
+            (local.get $i))
+    "#;
+    let module = Module::new(&store, &wat)?;
+    Ok(Instance::new(&module, &imports! {})?)
+}
+
+#[test]
+fn another_thread_stops_a_runaway_loop() -> Result<()> {
+    let store = get_store();
+    let instance = get_instance(&store)?;
+    let spin = instance.get_native_function::<(), ()>("spin")?;
+    instance.set_epoch_deadline(store.epoch() + 1);
+
+    let start = Instant::now();
+    let ticker = {
+        let store = store.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            store.increment_epoch();
+        })
+    };
+    let err = spin.call().unwrap_err();
+    ticker.join().unwrap();
+    assert!(start.elapsed() < Duration::from_secs(10));
+    assert!(err.is_epoch_deadline_exceeded());
+    assert_eq!(err.message(), "epoch deadline exceeded");
+    assert_eq!(err.to_trap(), Some(TrapCode::EpochDeadlineExceeded));
+    Ok(())
+}
+
+#[test]
+fn the_callback_decides_when_to_stop() -> Result<()> {
+    let store = get_store();
+    let instance = get_instance(&store)?;
+    let spin = instance.get_native_function::<(), ()>("spin")?;
+    let calls = Arc::new(AtomicUsize::new(0));
+    instance.set_epoch_deadline_callback({
+        let calls = calls.clone();
+        move |epoch| {
+            assert_eq!(epoch, 1);
+            // A deadline in the past is reached again by the next loop iteration.
+            if calls.fetch_add(1, Ordering::SeqCst) < 4 {
+                EpochDeadlineAction::Continue(0)
+            } else {
+                EpochDeadlineAction::Trap
+            }
+        }
+    });
+    instance.set_epoch_deadline(1);
+    store.increment_epoch();
+    assert!(spin.call().unwrap_err().is_epoch_deadline_exceeded());
+    assert_eq!(calls.load(Ordering::SeqCst), 5);
+    Ok(())
+}
+
+#[test]
+fn code_keeps_running_while_the_deadline_is_ahead() -> Result<()> {
+    let store = get_store();
+    let instance = get_instance(&store)?;
+    let count = instance.get_native_function::<i32, i32>("count")?;
+
+    // Without a deadline, the epoch has no effect.
+    store.increment_epoch();
+    assert_eq!(count.call(1000)?, 1000);
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    instance.set_epoch_deadline_callback({
+        let calls = calls.clone();
+        move |_| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            EpochDeadlineAction::Continue(u64::MAX)
+        }
+    });
+    instance.set_epoch_deadline(store.epoch());
+    assert_eq!(count.call(1000)?, 1000);
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    Ok(())
+}
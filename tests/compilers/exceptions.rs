@@ -0,0 +1,114 @@
+//! Tests of the exception handling proposal: exceptions thrown and caught in the guest, across
+//! calls, and between the guest and the host.
+
+use anyhow::Result;
+use wasmer::*;
+
+fn store_with_exceptions(config: &mut crate::Config) -> Store {
+    let mut features = Features::default();
+    features.exceptions(true);
+    config.set_features(features);
+    config.store()
+}
+
+#[compiler_test(exceptions)]
+fn exceptions_are_caught_across_calls(mut config: crate::Config) -> Result<()> {
+    let store = store_with_exceptions(&mut config);
+    let wat = r#"(module
+        (tag $overflow (param i32 f64))
+        (tag $other)
+        (func $check (param $n i32) (result i32)
+            (if (i32.gt_s (local.get $n) (i32.const 100))
+                (then (throw $overflow (local.get $n) (f64.const 1.5))))
+            (local.get $n))
+        (func (export "run") (param $n i32) (result i32)
+            (local $sum i32)
+            (local.set $sum (i32.const 7))
+            (try (result i32)
+                (do
+                    (i32.add (local.get $sum) (call $check (local.get $n))))
+                (catch $other
+                    (i32.const -1))
+                (catch $overflow
+                    (drop)
+                    (i32.sub (local.get $sum)))))
+        (func (export "rethrown") (result i32)
+            (try (result i32)
+                (do
+                    (try
+                        (do (call $check (i32.const 1000)) (drop))
+                        (catch_all (rethrow 0)))
+                    (i32.const 0))
+                (catch $overflow
+                    (i32.add (i32.trunc_f64_s (f64.mul (f64.const 2)))))))
+        (func (export "delegated") (result i32)
+            (try (result i32)
+                (do
+                    (try
+                        (do (call $check (i32.const 1000)) (drop))
+                        (delegate 0))
+                    (i32.const 0))
+                (catch_all (i32.const 1)))))"#;
+    let instance = Instance::new(&Module::new(&store, wat)?, &imports! {})?;
+    let run: NativeFunc<i32, i32> = instance.get_native_function("run")?;
+    assert_eq!(run.call(3)?, 10);
+    assert_eq!(run.call(1000)?, 993);
+    let rethrown: NativeFunc<(), i32> = instance.get_native_function("rethrown")?;
+    assert_eq!(rethrown.call()?, 1003);
+    let delegated: NativeFunc<(), i32> = instance.get_native_function("delegated")?;
+    assert_eq!(delegated.call()?, 1);
+    Ok(())
+}
+
+#[compiler_test(exceptions)]
+fn uncaught_exceptions_carry_their_payload(mut config: crate::Config) -> Result<()> {
+    let store = store_with_exceptions(&mut config);
+    let wat = r#"(module
+        (tag $failure (export "failure") (param i64))
+        (func (export "fail") (param $code i64)
+            (throw $failure (local.get $code))))"#;
+    let instance = Instance::new(&Module::new(&store, wat)?, &imports! {})?;
+    let fail: NativeFunc<i64, ()> = instance.get_native_function("fail")?;
+    let error = fail.call(-42).unwrap_err();
+    assert!(error.is_uncaught_exception());
+    let exception = Exception::from_runtime_error(&store, &error).unwrap();
+    assert!(exception.tag().same(&instance.lookup_tag("failure")?));
+    assert_eq!(exception.payload(), vec![Value::I64(-42)]);
+    Ok(())
+}
+
+#[compiler_test(exceptions)]
+fn host_exceptions_are_caught_by_the_guest(mut config: crate::Config) -> Result<()> {
+    let store = store_with_exceptions(&mut config);
+    let tag = Tag::new(&store, TagType::new(vec![Type::I32]));
+    let host_tag = tag.clone();
+    let raise = Function::new_native(&store, move |code: i32| -> Result<(), RuntimeError> {
+        Err(Exception::new(&host_tag, &[Value::I32(code)])?.into_runtime_error())
+    });
+    let wat = r#"(module
+        (import "host" "error" (tag $error (param i32)))
+        (import "host" "raise" (func $raise (param i32)))
+        (func (export "caught") (param $code i32) (result i32)
+            (try (result i32)
+                (do (call $raise (local.get $code)) (i32.const 0))
+                (catch $error)))
+        (func (export "uncaught") (param $code i32)
+            (call $raise (local.get $code))))"#;
+    let imports = imports! {
+        "host" => {
+            "error" => tag.clone(),
+            "raise" => raise,
+        },
+    };
+    let instance = Instance::new(&Module::new(&store, wat)?, &imports)?;
+    let caught: NativeFunc<i32, i32> = instance.get_native_function("caught")?;
+    assert_eq!(caught.call(17)?, 17);
+
+    // The exception crosses the guest back to the host untouched.
+    let uncaught: NativeFunc<i32, ()> = instance.get_native_function("uncaught")?;
+    let error = uncaught.call(5).unwrap_err();
+    let exception = Exception::from_runtime_error(&store, &error).unwrap();
+    assert!(exception.tag().same(&tag));
+    assert_eq!(exception.payload(), vec![Value::I32(5)]);
+    Ok(())
+}
@@ -0,0 +1,131 @@
+//! Testing the lookup of the exports of an instance.
+
+use anyhow::Result;
+use wasmer::*;
+
+fn get_instance(store: &Store) -> Result<Instance> {
+    // The exports are not declared in the order of their names, nor of their kinds.
+    let wat = r#"
+        (func (export "zero") (result i32) i32.const 0)
+        (memory (export "memory") 1)
+        (global (export "answer") (mut i32) i32.const 42)
+        (table (export "table") 2 funcref)
+        (func (export "extend") (param i32) (result i64)
+            local.get 0
+            i64.extend_i32_s)
+    "#;
+    let module = Module::new(&store, &wat)?;
+    Ok(Instance::new(&module, &imports! {})?)
+}
+
+#[compiler_test(exports)]
+fn exports_are_iterated_in_declaration_order(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let instance = get_instance(&store)?;
+    let exports = instance.exports().collect::<Vec<_>>();
+    assert_eq!(
+        exports,
+        vec![
+            (
+                "zero",
+                ExternType::Function(FunctionType::new(vec![], vec![Type::I32]))
+            ),
+            (
+                "memory",
+                ExternType::Memory(MemoryType::new(Pages(1), None, false))
+            ),
+            (
+                "answer",
+                ExternType::Global(GlobalType::new(Type::I32, Mutability::Var))
+            ),
+            (
+                "table",
+                ExternType::Table(TableType::new(Type::FuncRef, 2, None))
+            ),
+            (
+                "extend",
+                ExternType::Function(FunctionType::new(vec![Type::I32], vec![Type::I64]))
+            ),
+        ]
+    );
+    Ok(())
+}
+
+#[compiler_test(exports)]
+fn typed_lookups(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let instance = get_instance(&store)?;
+    let extend = instance.lookup_function("extend")?.native::<i32, i64>()?;
+    assert_eq!(extend.call(-2)?, -2);
+    assert_eq!(instance.lookup_memory("memory")?.size(), Pages(1));
+    assert_eq!(instance.lookup_global("answer")?.get(), Value::I32(42));
+    assert_eq!(instance.lookup_table("table")?.size(), 2);
+    Ok(())
+}
+
+#[compiler_test(exports)]
+fn typed_lookups_fail_on_missing_names(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let instance = get_instance(&store)?;
+    match instance.lookup_function("missing") {
+        Err(ExportError::Missing(name)) => assert_eq!(name, "missing"),
+        other => panic!("unexpected lookup result: {:?}", other.map(|_| ())),
+    }
+    assert!(matches!(
+        instance.lookup_memory("missing"),
+        Err(ExportError::Missing(_))
+    ));
+    assert!(matches!(
+        instance.get_native_function::<(), i32>("missing"),
+        Err(ExportError::Missing(_))
+    ));
+    Ok(())
+}
+
+#[compiler_test(exports)]
+fn typed_lookups_fail_on_wrong_kinds(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let instance = get_instance(&store)?;
+    match instance.lookup_function("answer") {
+        Err(ExportError::IncompatibleType {
+            name,
+            expected,
+            found,
+        }) => {
+            assert_eq!(name, "answer");
+            assert_eq!(expected, "a function");
+            assert_eq!(
+                found,
+                ExternType::Global(GlobalType::new(Type::I32, Mutability::Var))
+            );
+        }
+        other => panic!("unexpected lookup result: {:?}", other.map(|_| ())),
+    }
+    assert!(matches!(
+        instance.lookup_global("memory"),
+        Err(ExportError::IncompatibleType { .. })
+    ));
+    assert!(matches!(
+        instance.lookup_table("zero"),
+        Err(ExportError::IncompatibleType { .. })
+    ));
+    assert!(matches!(
+        instance.lookup_memory("table"),
+        Err(ExportError::IncompatibleType { .. })
+    ));
+
+    // A function of another type is incompatible with the native function.
+    match instance.get_native_function::<i64, i64>("extend") {
+        Err(ExportError::IncompatibleType {
+            expected, found, ..
+        }) => {
+            assert!(expected.starts_with("a function of type"));
+            assert_eq!(
+                found,
+                ExternType::Function(FunctionType::new(vec![Type::I32], vec![Type::I64]))
+            );
+        }
+        other => panic!("unexpected lookup result: {:?}", other.map(|_| ())),
+    }
+    Ok(())
+}
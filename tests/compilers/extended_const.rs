@@ -0,0 +1,117 @@
+//! Tests of the extended-const proposal, whose constant expressions compute the initial values
+//! of the globals and the offsets of the segments from the imported globals.
+
+use anyhow::Result;
+use wasmer::*;
+
+static EXTENDED_CONST_WAT: &str = r#"(module
+    (import "env" "base" (global $base i32))
+    (import "env" "big" (global $big i64))
+    (memory (export "memory") 1)
+    (table 8 funcref)
+    (global (export "scaled") i64 (i64.mul (global.get $big) (i64.const 3)))
+    (global (export "end") i32 (i32.add (global.get $base) (i32.const 5)))
+    (data (offset (i32.add (global.get $base) (i32.const 32))) "hello")
+    (elem (offset (i32.sub (i32.mul (global.get $base) (i32.const 0)) (i32.const -3))) $answer)
+    (func $answer (result i32) (i32.const 42))
+    (func (export "call") (param i32) (result i32)
+        (call_indirect (result i32) (local.get 0)))
+)"#;
+
+fn store_with_extended_const(config: &mut crate::Config) -> Store {
+    let mut features = Features::default();
+    features.extended_const(true);
+    config.set_features(features);
+    config.store()
+}
+
+fn instantiate(store: &Store, base: i32, big: i64) -> Result<Instance> {
+    let module = Module::new(store, EXTENDED_CONST_WAT)?;
+    let imports = imports! {
+        "env" => {
+            "base" => Global::new(store, Value::I32(base)),
+            "big" => Global::new(store, Value::I64(big)),
+        },
+    };
+    Ok(Instance::new(&module, &imports)?)
+}
+
+#[compiler_test(extended_const)]
+fn segments_land_at_offsets_computed_from_imported_globals(
+    mut config: crate::Config,
+) -> Result<()> {
+    let store = store_with_extended_const(&mut config);
+    let instance = instantiate(&store, 100, 7)?;
+    let memory = instance.lookup_memory("memory")?;
+    let mut hello = [0; 5];
+    memory.read(132, &mut hello)?;
+    assert_eq!(&hello, b"hello");
+    let mut before = [0; 1];
+    memory.read(131, &mut before)?;
+    assert_eq!(before, [0]);
+
+    assert_eq!(instance.lookup_global("scaled")?.get(), Value::I64(21));
+    assert_eq!(instance.lookup_global("end")?.get(), Value::I32(105));
+    let call = instance.get_native_function::<i32, i32>("call")?;
+    assert_eq!(call.call(3)?, 42);
+    assert!(call.call(2).is_err());
+    Ok(())
+}
+
+#[compiler_test(extended_const)]
+fn constant_expressions_wrap_around(mut config: crate::Config) -> Result<()> {
+    let store = store_with_extended_const(&mut config);
+    // The data segment lands at `-16 + 32`, once wrapped around.
+    let instance = instantiate(&store, -16, i64::MAX)?;
+    let memory = instance.lookup_memory("memory")?;
+    let mut hello = [0; 5];
+    memory.read(16, &mut hello)?;
+    assert_eq!(&hello, b"hello");
+
+    assert_eq!(
+        instance.lookup_global("scaled")?.get(),
+        Value::I64(i64::MAX.wrapping_mul(3))
+    );
+    assert_eq!(instance.lookup_global("end")?.get(), Value::I32(-11));
+
+    // The offset of a segment is unsigned, so one wrapping around to a negative `i32` is out
+    // of bounds.
+    assert!(instantiate(&store, -40, 0).is_err());
+    Ok(())
+}
+
+#[compiler_test(extended_const)]
+fn extended_constant_expressions_are_validated(mut config: crate::Config) -> Result<()> {
+    let default_store = config.store();
+    assert!(Module::new(&default_store, EXTENDED_CONST_WAT).is_err());
+
+    let store = store_with_extended_const(&mut config);
+    let invalid = [
+        // A mutable global.
+        r#"(module
+            (import "env" "base" (global $base (mut i32)))
+            (global i32 (i32.add (global.get $base) (i32.const 1))))"#,
+        // A defined global.
+        r#"(module
+            (global $base i32 (i32.const 1))
+            (global i32 (i32.add (global.get $base) (i32.const 1))))"#,
+        // Operands of another type.
+        r#"(module
+            (global i32 (i32.add (i64.const 1) (i64.const 1))))"#,
+        // A result of another type than the global.
+        r#"(module
+            (global i64 (i32.add (i32.const 1) (i32.const 1))))"#,
+        // Operators outside of the proposal.
+        r#"(module
+            (memory 1)
+            (data (offset (i32.div_u (i32.const 4) (i32.const 2))) "a"))"#,
+        // More than one value left.
+        r#"(module
+            (memory 1)
+            (data (offset (i32.const 4) (i32.const 2)) "a"))"#,
+    ];
+    for wat in &invalid {
+        assert!(Module::new(&store, wat).is_err(), "{}", wat);
+    }
+    Ok(())
+}
@@ -0,0 +1,157 @@
+//! Testing the references to host data passed through Wasm.
+
+use anyhow::Result;
+use std::sync::atomic::{AtomicBool, Ordering::SeqCst};
+use std::sync::Arc;
+use wasmer::*;
+
+/// Sets its flag when dropped.
+struct DropFlag(Arc<AtomicBool>);
+
+impl Drop for DropFlag {
+    fn drop(&mut self) {
+        self.0.store(true, SeqCst);
+    }
+}
+
+fn get_instance(store: &Store) -> Result<Instance> {
+    let wat = r#"
+        (import "host" "identity" (func $host_identity (param externref) (result externref)))
+        (import "host" "dynamic_identity" (func $dynamic_identity (param externref) (result externref)))
+        (table $table (export "table") 1 externref)
+        (global $global (export "global") (mut externref) (ref.null extern))
+        (func (export "identity") (param externref) (result externref)
+            (local.get 0))
+        (func (export "through_host") (param externref) (result externref)
+            (call $dynamic_identity (call $host_identity (local.get 0))))
+        (func (export "store") (param externref)
+            (table.set $table (i32.const 0) (local.get 0)))
+        (func (export "load") (result externref)
+            (table.get $table (i32.const 0)))
+        (func (export "set_global") (param externref)
+            (global.set $global (local.get 0)))
+        (func (export "replace_and_return") (param externref) (result externref)
+            (local $old externref)
+            (local.set $old (table.get $table (i32.const 0)))
+            (table.set $table (i32.const 0) (local.get 0))
+            (local.get $old))
+        (func (export "trap") (param externref)
+            (unreachable))
+    "#;
+    let module = Module::new(store, wat)?;
+    let identity_type = FunctionType::new(vec![Type::ExternRef], vec![Type::ExternRef]);
+    let imports = imports! {
+        "host" => {
+            "identity" => Function::new_native(store, |r: Option<ExternRef>| r),
+            "dynamic_identity" => Function::new(store, identity_type, |args| Ok(args.to_vec())),
+        },
+    };
+    Ok(Instance::new(&module, &imports)?)
+}
+
+fn downcast_string(extern_ref: &ExternRef) -> Option<&String> {
+    extern_ref.downcast::<String>()
+}
+
+#[compiler_test(externref)]
+fn host_data_round_trips_through_wasm(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let instance = get_instance(&store)?;
+    let extern_ref = ExternRef::new("host".to_string());
+
+    for name in ["identity", "through_host"] {
+        let typed: NativeFunc<Option<ExternRef>, Option<ExternRef>> =
+            instance.get_native_function(name)?;
+        let returned = typed.call(Some(extern_ref.clone()))?.unwrap();
+        assert_eq!(downcast_string(&returned).unwrap(), "host");
+        assert_eq!(returned, extern_ref);
+        assert_eq!(typed.call(None)?, None);
+
+        let dynamic = instance.lookup_function(name)?;
+        let returned = dynamic.call(&[Value::ExternRef(extern_ref.clone())])?;
+        match &returned[..] {
+            [Value::ExternRef(returned)] => {
+                assert_eq!(downcast_string(returned).unwrap(), "host")
+            }
+            other => panic!("unexpected results {:?}", other),
+        }
+        assert_eq!(downcast_string(&extern_ref).unwrap(), "host");
+    }
+    assert!(extern_ref.downcast::<u32>().is_none());
+    Ok(())
+}
+
+#[compiler_test(externref)]
+fn tables_keep_their_externref_alive(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let instance = get_instance(&store)?;
+    let store_ref: NativeFunc<Option<ExternRef>, ()> = instance.get_native_function("store")?;
+    let load: NativeFunc<(), Option<ExternRef>> = instance.get_native_function("load")?;
+    let dropped = Arc::new(AtomicBool::new(false));
+
+    store_ref.call(Some(ExternRef::new(DropFlag(dropped.clone()))))?;
+    for _ in 0..3 {
+        assert!(load.call()?.unwrap().downcast::<DropFlag>().is_some());
+    }
+    assert!(!dropped.load(SeqCst));
+
+    // Only overwriting the slot releases the reference.
+    store_ref.call(None)?;
+    assert!(dropped.load(SeqCst));
+    assert_eq!(load.call()?, None);
+    Ok(())
+}
+
+#[compiler_test(externref)]
+fn overwritten_externrefs_live_until_the_call_returns(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let instance = get_instance(&store)?;
+    let store_ref: NativeFunc<Option<ExternRef>, ()> = instance.get_native_function("store")?;
+    let replace: NativeFunc<Option<ExternRef>, Option<ExternRef>> =
+        instance.get_native_function("replace_and_return")?;
+    let dropped = Arc::new(AtomicBool::new(false));
+
+    store_ref.call(Some(ExternRef::new(DropFlag(dropped.clone()))))?;
+    // The Wasm code still uses the reference after overwriting the only slot holding it.
+    let old = replace.call(None)?.unwrap();
+    assert!(!dropped.load(SeqCst));
+    assert!(old.downcast::<DropFlag>().is_some());
+    drop(old);
+    assert!(dropped.load(SeqCst));
+    Ok(())
+}
+
+#[compiler_test(externref)]
+fn globals_set_by_wasm_keep_their_externref_alive(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let instance = get_instance(&store)?;
+    let set_global: NativeFunc<Option<ExternRef>, ()> =
+        instance.get_native_function("set_global")?;
+    let global = instance.lookup_global("global")?;
+    let dropped = Arc::new(AtomicBool::new(false));
+
+    set_global.call(Some(ExternRef::new(DropFlag(dropped.clone()))))?;
+    assert!(!dropped.load(SeqCst));
+    match global.get() {
+        Value::ExternRef(extern_ref) => assert!(extern_ref.downcast::<DropFlag>().is_some()),
+        other => panic!("unexpected value {:?}", other),
+    }
+
+    set_global.call(None)?;
+    assert!(dropped.load(SeqCst));
+    Ok(())
+}
+
+#[compiler_test(externref)]
+fn traps_release_the_externrefs_of_the_call(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let instance = get_instance(&store)?;
+    let trap: NativeFunc<Option<ExternRef>, ()> = instance.get_native_function("trap")?;
+    let dropped = Arc::new(AtomicBool::new(false));
+
+    assert!(trap
+        .call(Some(ExternRef::new(DropFlag(dropped.clone()))))
+        .is_err());
+    assert!(dropped.load(SeqCst));
+    Ok(())
+}
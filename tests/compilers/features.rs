@@ -0,0 +1,158 @@
+//! Tests of the Wasm features enabled in engines, checked by the validation of modules.
+
+use anyhow::Result;
+use wasmer::*;
+use wasmer_types::entity::EntityRef;
+
+/// A module using each feature that can be disabled, and the setter of the feature.
+static FEATURE_MODULES: &[(&str, fn(&mut Features, bool) -> &mut Features, &str)] = &[
+    (
+        "threads",
+        Features::threads,
+        r#"(module (memory 1 1 shared))"#,
+    ),
+    (
+        "reference types",
+        Features::reference_types,
+        r#"(module (func (param externref)))"#,
+    ),
+    (
+        "SIMD",
+        Features::simd,
+        r#"(module (func (drop (v128.const i64x2 0 0))))"#,
+    ),
+    (
+        "bulk memory",
+        Features::bulk_memory,
+        r#"(module
+            (memory 1)
+            (func (memory.fill (i32.const 0) (i32.const 0) (i32.const 0))))"#,
+    ),
+    (
+        "multi-value",
+        Features::multi_value,
+        r#"(module (func (result i32 i32) (i32.const 1) (i32.const 2)))"#,
+    ),
+    (
+        "tail calls",
+        Features::tail_call,
+        r#"(module (func $f (return_call $f)))"#,
+    ),
+    (
+        "exceptions",
+        Features::exceptions,
+        r#"(module (tag $e) (func (throw $e)))"#,
+    ),
+    (
+        "extended constants",
+        Features::extended_const,
+        r#"(module (global i32 (i32.add (i32.const 1) (i32.const 2))))"#,
+    ),
+    (
+        "floats",
+        Features::floats,
+        r#"(module (func (result i32) (i32.trunc_f32_s (f32.const 1))))"#,
+    ),
+];
+
+fn store_with(config: &crate::Config, features: Features) -> Store {
+    let mut config = config.clone();
+    config.set_features(features);
+    config.store()
+}
+
+#[compiler_test(features)]
+fn each_feature_gates_the_modules_using_it(config: crate::Config) -> Result<()> {
+    for (name, set, wat) in FEATURE_MODULES {
+        let wasm = wat2wasm(wat.as_bytes())?;
+        let mut features = Features::new();
+        set(&mut features, true);
+        let store = store_with(&config, features.clone());
+        Module::new(&store, &wasm).map_err(|e| anyhow::anyhow!("{}: {}", name, e))?;
+
+        set(&mut features, false);
+        let store = store_with(&config, features);
+        match Module::validate(&store, &wasm) {
+            Err(error @ CompileError::Validate(_)) | Err(error @ CompileError::Function { .. }) => {
+                // The error says where the module uses the feature.
+                assert!(error.to_string().contains("offset"), "{}: {}", name, error)
+            }
+            result => panic!("{}: unexpected result: {:?}", name, result),
+        }
+    }
+    Ok(())
+}
+
+#[compiler_test(features)]
+fn disabled_floats_are_located(config: crate::Config) -> Result<()> {
+    let mut features = Features::new();
+    features.floats(false);
+    let store = store_with(&config, features);
+    let modules = [
+        r#"(module (func (param f64)))"#,
+        r#"(module (global f32 (f32.const 0)))"#,
+        r#"(module (import "env" "g" (global f64)))"#,
+        r#"(module (func (local f32)))"#,
+    ];
+    for wat in modules.iter() {
+        match Module::validate(&store, &wat2wasm(wat.as_bytes())?) {
+            Err(error) => assert!(error.to_string().contains("floating-point"), "{}", error),
+            Ok(()) => panic!("{} should be rejected", wat),
+        }
+    }
+
+    let wasm = wat2wasm(
+        br#"(module
+            (func (result i64) (i64.const 1))
+            (func (result i32) (i32.reinterpret_f32 (f32.const 1))))"#,
+    )?;
+    match Module::validate(&store, &wasm) {
+        Err(CompileError::Function { location, .. }) => {
+            assert_eq!(location.index, FunctionIndex::new(1));
+            assert_eq!(location.operator.as_deref(), Some("F32Const"));
+            assert_eq!(wasm[location.offset], 0x43);
+        }
+        result => panic!("unexpected result: {:?}", result),
+    }
+
+    // Modules without floats are still valid.
+    let wasm = wat2wasm(br#"(module (func (result i64) (i64.extend_i32_s (i32.const -1))))"#)?;
+    Module::new(&store, &wasm)?;
+    Ok(())
+}
+
+#[compiler_test(features)]
+fn deterministic_features_reject_nondeterministic_modules(config: crate::Config) -> Result<()> {
+    let store = store_with(&config, Features::deterministic());
+    for (name, _, wat) in FEATURE_MODULES {
+        let valid = Module::validate(&store, &wat2wasm(wat.as_bytes())?).is_ok();
+        // The proposals not enabled by default stay disabled.
+        let deterministic = matches!(*name, "reference types" | "bulk memory" | "multi-value");
+        assert_eq!(valid, deterministic, "{}", name);
+    }
+    Ok(())
+}
+
+#[compiler_test(features)]
+fn features_the_compiler_lacks_are_refused(config: crate::Config) -> Result<()> {
+    let empty = b"\0asm\x01\0\0\0";
+    let setters: [(&str, fn(&mut Features, bool) -> &mut Features); 2] = [
+        ("module linking", Features::module_linking),
+        ("multi-memory", Features::multi_memory),
+    ];
+    for (name, set) in setters.iter() {
+        let mut features = Features::new();
+        set(&mut features, true);
+        let store = store_with(&config, features);
+        // Even the modules that don't use the feature are refused.
+        match Module::validate(&store, empty) {
+            Err(CompileError::FeatureNotSupportedByCompiler(feature)) => assert_eq!(feature, *name),
+            result => panic!("{}: unexpected result: {:?}", name, result),
+        }
+        assert!(matches!(
+            Module::new(&store, &empty[..]),
+            Err(CompileError::FeatureNotSupportedByCompiler(_))
+        ));
+    }
+    Ok(())
+}
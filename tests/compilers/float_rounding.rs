@@ -0,0 +1,124 @@
+//! Tests of `ceil`, `floor`, `trunc` and `nearest`, on the values where rounding is subtle.
+
+use anyhow::Result;
+use wasmer::*;
+
+static ROUNDING_WAT: &str = r#"(module
+    (func (export "f32.ceil") (param f32) (result f32) (f32.ceil (local.get 0)))
+    (func (export "f32.floor") (param f32) (result f32) (f32.floor (local.get 0)))
+    (func (export "f32.trunc") (param f32) (result f32) (f32.trunc (local.get 0)))
+    (func (export "f32.nearest") (param f32) (result f32) (f32.nearest (local.get 0)))
+    (func (export "f64.ceil") (param f64) (result f64) (f64.ceil (local.get 0)))
+    (func (export "f64.floor") (param f64) (result f64) (f64.floor (local.get 0)))
+    (func (export "f64.trunc") (param f64) (result f64) (f64.trunc (local.get 0)))
+    (func (export "f64.nearest") (param f64) (result f64) (f64.nearest (local.get 0)))
+)"#;
+
+const OPS: [&str; 4] = ["ceil", "floor", "trunc", "nearest"];
+
+/// The inputs, with their ceiling, floor, truncation and nearest integer, ties to even.
+const CASES: [(f64, [f64; 4]); 14] = [
+    (0.5, [1.0, 0.0, 0.0, 0.0]),
+    (1.5, [2.0, 1.0, 1.0, 2.0]),
+    (2.5, [3.0, 2.0, 2.0, 2.0]),
+    (-2.5, [-2.0, -3.0, -2.0, -2.0]),
+    (-3.5, [-3.0, -4.0, -3.0, -4.0]),
+    (2.7, [3.0, 2.0, 2.0, 3.0]),
+    // The results rounded towards zero from a negative value keep their sign.
+    (-0.5, [-0.0, -1.0, -0.0, -0.0]),
+    (-0.7, [-0.0, -1.0, -0.0, -1.0]),
+    (-0.0, [-0.0, -0.0, -0.0, -0.0]),
+    (0.0, [0.0, 0.0, 0.0, 0.0]),
+    // So large that they have no fractional part.
+    (16_777_215.0, [16_777_215.0; 4]),
+    (-1e30, [-1e30; 4]),
+    (f64::INFINITY, [f64::INFINITY; 4]),
+    (f64::NEG_INFINITY, [f64::NEG_INFINITY; 4]),
+];
+
+fn get_instance(config: &crate::Config) -> Result<Instance> {
+    let store = config.store();
+    let module = Module::new(&store, ROUNDING_WAT)?;
+    Ok(Instance::new(&module, &imports! {})?)
+}
+
+/// Rounds `inputs` with each operator, only moving floats around between the calls.
+fn round(instance: &Instance, inputs: &[(f32, f64)]) -> Result<Vec<Vec<(f32, f64)>>> {
+    let mut results = Vec::new();
+    for op in OPS {
+        let f32_op: NativeFunc<f32, f32> = instance.get_native_function(&format!("f32.{}", op))?;
+        let f64_op: NativeFunc<f64, f64> = instance.get_native_function(&format!("f64.{}", op))?;
+        let mut rounded = Vec::with_capacity(inputs.len());
+        for &(x32, x64) in inputs {
+            rounded.push((f32_op.call(x32)?, f64_op.call(x64)?));
+        }
+        results.push(rounded);
+    }
+    Ok(results)
+}
+
+/// Rounds the cases with `round`, and checks the results.
+fn check_rounding(round: impl FnOnce(&[(f32, f64)]) -> Result<Vec<Vec<(f32, f64)>>>) -> Result<()> {
+    let mut inputs = CASES
+        .iter()
+        .map(|&(x, _)| (x as f32, x))
+        .collect::<Vec<_>>();
+    inputs.push((f32::NAN, f64::NAN));
+    let results = round(&inputs)?;
+    for (index, (op, rounded)) in OPS.iter().zip(results).enumerate() {
+        for ((x, expected), &(rounded32, rounded64)) in CASES.iter().zip(&rounded) {
+            let (expected32, expected64) = (expected[index] as f32, expected[index]);
+            assert_eq!(
+                rounded32.to_bits(),
+                expected32.to_bits(),
+                "f32.{}({})",
+                op,
+                x
+            );
+            assert_eq!(
+                rounded64.to_bits(),
+                expected64.to_bits(),
+                "f64.{}({})",
+                op,
+                x
+            );
+        }
+        let &(nan32, nan64) = rounded.last().unwrap();
+        assert!(nan32.is_nan() && nan64.is_nan(), "{}(nan)", op);
+    }
+    Ok(())
+}
+
+#[compiler_test(float_rounding)]
+fn rounding_ties_to_even_and_keeps_the_sign(config: crate::Config) -> Result<()> {
+    let instance = get_instance(&config)?;
+    check_rounding(|inputs| round(&instance, inputs))
+}
+
+/// Replaces the MXCSR of the thread with the result of `f` on it, returning the previous one.
+#[cfg(target_arch = "x86_64")]
+#[allow(deprecated)]
+fn update_mxcsr(f: impl FnOnce(u32) -> u32) -> u32 {
+    use std::arch::x86_64::{_mm_getcsr, _mm_setcsr};
+    unsafe {
+        let previous = _mm_getcsr();
+        _mm_setcsr(f(previous));
+        previous
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[compiler_test(float_rounding)]
+fn rounding_ignores_the_mxcsr_of_the_host(config: crate::Config) -> Result<()> {
+    // The host rounds towards zero, and traps on inexact results.
+    const ROUND_TOWARDS_ZERO: u32 = 0b11 << 13;
+    const PRECISION_MASK: u32 = 1 << 12;
+
+    let instance = get_instance(&config)?;
+    check_rounding(|inputs| {
+        let mxcsr = update_mxcsr(|mxcsr| (mxcsr | ROUND_TOWARDS_ZERO) & !PRECISION_MASK);
+        let results = round(&instance, inputs);
+        update_mxcsr(|_| mxcsr);
+        results
+    })
+}
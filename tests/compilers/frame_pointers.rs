@@ -0,0 +1,122 @@
+//! Testing the leaf functions compiled by singlepass without frame pointers.
+
+use anyhow::Result;
+use wasmer::*;
+use wasmer_compiler_singlepass::Singlepass;
+use wasmer_engine_universal::Universal;
+
+fn get_store(omit_leaf_frame_pointers: bool) -> Store {
+    let mut compiler = Singlepass::default();
+    compiler.omit_leaf_frame_pointers(omit_leaf_frame_pointers);
+    Store::new(&Universal::new(compiler).engine())
+}
+
+/// Leaf functions with their locals in every register, RBP included, one that needs stack
+/// values after all, and a caller keeping its own locals on the stack around the calls.
+const LEAVES_WAT: &str = r#"(module
+    (memory 1)
+    (func $ints (export "ints") (param $a i64) (param $b i64) (result i64)
+        (local $c i64) (local $d i64) (local $e i64)
+        (local.set $c (i64.add (local.get $a) (local.get $b)))
+        (local.set $d (i64.mul (local.get $c) (local.get $a)))
+        (local.set $e (i64.rotl (local.get $d) (local.get $b)))
+        (i64.xor (local.get $e) (i64.div_u (local.get $c) (i64.const 3))))
+    (func $floats (export "floats") (param $x f64) (param $y f64) (result f64)
+        (local $z f64) (local $w f64)
+        (local.set $z (f64.mul (local.get $x) (local.get $y)))
+        (local.set $w (f64.sqrt (f64.abs (local.get $z))))
+        (f64.add (f64.min (local.get $w) (local.get $x)) (f64.nearest (local.get $y))))
+    (func $memory (export "memory") (param $address i32) (param $value i64) (result i64)
+        (i64.store offset=8 (local.get $address) (local.get $value))
+        (i64.add (i64.load offset=8 (local.get $address)) (i64.load (local.get $address))))
+    (func $spills (export "spills") (param $a i64) (result i64)
+        (local.get $a) (local.get $a) (local.get $a) (local.get $a) (local.get $a)
+        (local.get $a) (local.get $a) (local.get $a) (local.get $a) (local.get $a)
+        (i64.mul) (i64.add) (i64.mul) (i64.add) (i64.mul) (i64.add) (i64.mul) (i64.add)
+        (i64.add))
+    (func (export "caller") (param $a i64) (param $b i64) (result i64)
+        (local $c i64) (local $d i64) (local $e i64) (local $f i64) (local $g i64)
+        (local.set $f (i64.const 7))
+        (local.set $g (call $ints (local.get $a) (local.get $b)))
+        (local.set $c (call $spills (local.get $g)))
+        (local.set $d (i64.trunc_f64_s (call $floats (f64.const 1.5) (f64.const -2.5))))
+        (local.set $e (call $memory (i32.const 64) (local.get $c)))
+        (i64.add (i64.add (local.get $c) (local.get $d))
+                 (i64.add (i64.mul (local.get $e) (local.get $f)) (local.get $g))))
+)"#;
+
+fn call_all(store: &Store, a: i64, b: i64) -> Result<(i64, f64, i64, i64, i64)> {
+    let instance = Instance::new(&Module::new(store, LEAVES_WAT)?, &imports! {})?;
+    let ints: NativeFunc<(i64, i64), i64> = instance.get_native_function("ints")?;
+    let floats: NativeFunc<(f64, f64), f64> = instance.get_native_function("floats")?;
+    let memory: NativeFunc<(i32, i64), i64> = instance.get_native_function("memory")?;
+    let spills: NativeFunc<i64, i64> = instance.get_native_function("spills")?;
+    let caller: NativeFunc<(i64, i64), i64> = instance.get_native_function("caller")?;
+    Ok((
+        ints.call(a, b)?,
+        floats.call(a as f64, b as f64)?,
+        memory.call(a as i32 & 0xff, b)?,
+        spills.call(a)?,
+        caller.call(a, b)?,
+    ))
+}
+
+#[test]
+fn frameless_leaves_compute_the_same() -> Result<()> {
+    let (framed, frameless) = (get_store(false), get_store(true));
+    for &(a, b) in &[(0, 0), (1, 2), (-17, 123_456_789), (i64::MAX, i64::MIN)] {
+        let expected = call_all(&framed, a, b)?;
+        let (ints, floats, memory, spills, caller) = call_all(&frameless, a, b)?;
+        assert_eq!(
+            (ints, floats.to_bits(), memory, spills, caller),
+            (
+                expected.0,
+                expected.1.to_bits(),
+                expected.2,
+                expected.3,
+                expected.4
+            ),
+            "({}, {})",
+            a,
+            b
+        );
+    }
+    Ok(())
+}
+
+#[cfg_attr(target_env = "musl", ignore)]
+#[test]
+fn traps_in_frameless_leaves_are_traced_through_their_callers() -> Result<()> {
+    let wat = r#"
+        (module $calc
+            (func $divide (param i32 i32) (result i32)
+                (i32.div_s (local.get 0) (local.get 1)))
+            (func $middle (param i32) (result i32)
+                (call $divide (i32.const 10) (local.get 0)))
+            (func (export "outer") (param i32) (result i32)
+                (call $middle (local.get 0)))
+        )
+    "#;
+    let trace = |store: &Store| -> Result<Vec<(u32, Option<String>, usize)>> {
+        let instance = Instance::new(&Module::new(store, wat)?, &imports! {})?;
+        let outer = instance.get_native_function::<i32, i32>("outer")?;
+        assert_eq!(outer.call(2)?, 5);
+        let error = outer.call(0).unwrap_err();
+        assert_eq!(error.message(), "integer divide by zero");
+        Ok(error
+            .trace()
+            .iter()
+            .map(|frame| {
+                (
+                    frame.func_index(),
+                    frame.function_name().map(str::to_string),
+                    frame.module_offset(),
+                )
+            })
+            .collect())
+    };
+    let frames = trace(&get_store(true))?;
+    assert_eq!(frames, trace(&get_store(false))?);
+    assert_eq!(frames.len(), 3);
+    Ok(())
+}
@@ -0,0 +1,219 @@
+//! Testing globals shared between the host and WebAssembly.
+
+use anyhow::Result;
+use std::sync::atomic::{AtomicBool, Ordering::SeqCst};
+use std::sync::Arc;
+use wasmer::*;
+
+fn get_instance(store: &Store, imports: &ImportObject) -> Result<Instance> {
+    let wat = r#"
+        (global $i32 (import "env" "i32") (mut i32))
+        (global $i64 (import "env" "i64") (mut i64))
+        (global $f32 (import "env" "f32") (mut f32))
+        (global $f64 (import "env" "f64") (mut f64))
+        (global $externref (import "env" "externref") (mut externref))
+        (global $counter (export "counter") (mut i64) (i64.const 0))
+        (func (export "get_i32") (result i32) (global.get $i32))
+        (func (export "set_i32") (param i32) (global.set $i32 (local.get 0)))
+        (func (export "get_i64") (result i64) (global.get $i64))
+        (func (export "set_i64") (param i64) (global.set $i64 (local.get 0)))
+        (func (export "get_f32") (result f32) (global.get $f32))
+        (func (export "set_f32") (param f32) (global.set $f32 (local.get 0)))
+        (func (export "get_f64") (result f64) (global.get $f64))
+        (func (export "set_f64") (param f64) (global.set $f64 (local.get 0)))
+        (func (export "get_externref") (result externref) (global.get $externref))
+        (func (export "set_externref") (param externref) (global.set $externref (local.get 0)))
+        (func (export "bump") (result i64)
+            (global.set $counter (i64.add (global.get $counter) (i64.const 1)))
+            (global.get $counter))
+    "#;
+    let module = Module::new(&store, &wat)?;
+    Ok(Instance::new(&module, imports)?)
+}
+
+#[compiler_test(globals)]
+fn imported_mutable_globals_are_shared(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let externref = ExternRef::new("host");
+    let globals = [
+        ("i32", Value::I32(1), Value::I32(-2)),
+        ("i64", Value::I64(1 << 40), Value::I64(-(1 << 50))),
+        ("f32", Value::F32(1.5), Value::F32(-0.25)),
+        ("f64", Value::F64(1e300), Value::F64(-1e-300)),
+        (
+            "externref",
+            Value::ExternRef(ExternRef::null()),
+            Value::ExternRef(externref),
+        ),
+    ];
+    let mut imports = ImportObject::new();
+    let mut namespace = Exports::new();
+    let mut handles = Vec::new();
+    for (name, initial, _) in &globals {
+        let global = Global::new_mut(&store, initial.clone());
+        namespace.insert(*name, global.clone());
+        handles.push(global);
+    }
+    imports.register("env", namespace);
+    let instance = get_instance(&store, &imports)?;
+
+    for ((name, initial, other), global) in globals.iter().zip(&handles) {
+        let get = instance.lookup_function(&format!("get_{}", name))?;
+        let set = instance.lookup_function(&format!("set_{}", name))?;
+        assert_eq!(&*get.call(&[])?, &[initial.clone()]);
+
+        // The guest sees what the host sets...
+        global.set(other.clone())?;
+        assert_eq!(&*get.call(&[])?, &[other.clone()]);
+
+        // ...and the host sees what the guest sets.
+        set.call(&[initial.clone()])?;
+        assert_eq!(global.get(), *initial);
+    }
+    Ok(())
+}
+
+#[compiler_test(globals)]
+fn exported_mutable_globals_are_set_by_the_host(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let mut imports = ImportObject::new();
+    let mut namespace = Exports::new();
+    namespace.insert("i32", Global::new_mut(&store, Value::I32(0)));
+    namespace.insert("i64", Global::new_mut(&store, Value::I64(0)));
+    namespace.insert("f32", Global::new_mut(&store, Value::F32(0.0)));
+    namespace.insert("f64", Global::new_mut(&store, Value::F64(0.0)));
+    namespace.insert(
+        "externref",
+        Global::new_mut(&store, Value::ExternRef(ExternRef::null())),
+    );
+    imports.register("env", namespace);
+    let instance = get_instance(&store, &imports)?;
+    let counter = instance.lookup_global("counter")?;
+    let bump = instance.get_native_function::<(), i64>("bump")?;
+
+    assert_eq!(bump.call()?, 1);
+    assert_eq!(counter.get(), Value::I64(1));
+    counter.set(Value::I64(i64::MAX - 1))?;
+    assert_eq!(bump.call()?, i64::MAX);
+    assert_eq!(counter.get(), Value::I64(i64::MAX));
+    Ok(())
+}
+
+#[compiler_test(globals)]
+fn globals_enforce_mutability_and_types(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let constant = Global::new(&store, Value::F64(1.0));
+    assert_eq!(
+        constant.ty(),
+        &GlobalType::new(Type::F64, Mutability::Const)
+    );
+    let err = constant.set(Value::F64(2.0)).unwrap_err();
+    assert_eq!(err.message(), "Attempted to set an immutable global");
+    assert_eq!(constant.get(), Value::F64(1.0));
+
+    let variable = Global::new_mut(&store, Value::I32(1));
+    let err = variable.set(Value::I64(2)).unwrap_err();
+    assert_eq!(
+        err.message(),
+        "Attempted to operate on a global of type I32 as a global of type I64"
+    );
+    assert!(variable.set(Value::ExternRef(ExternRef::null())).is_err());
+    assert_eq!(variable.get(), Value::I32(1));
+
+    // Imports must match the mutability of the imported global.
+    let mut imports = ImportObject::new();
+    let mut namespace = Exports::new();
+    namespace.insert("i32", Global::new(&store, Value::I32(0)));
+    namespace.insert("i64", Global::new_mut(&store, Value::I64(0)));
+    namespace.insert("f32", Global::new_mut(&store, Value::F32(0.0)));
+    namespace.insert("f64", Global::new_mut(&store, Value::F64(0.0)));
+    namespace.insert(
+        "externref",
+        Global::new_mut(&store, Value::ExternRef(ExternRef::null())),
+    );
+    imports.register("env", namespace);
+    assert!(get_instance(&store, &imports).is_err());
+    Ok(())
+}
+
+#[compiler_test(globals)]
+fn imported_and_defined_globals_are_told_apart(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    // The defined globals have the local indices of the imported ones, which they shadow in
+    // neither direction: each instance has its own private and exported counters, and shares
+    // the imported one.
+    let wat = r#"
+        (global $shared (import "env" "shared") (mut i64))
+        (global $constant (import "env" "constant") i64)
+        (global $private (mut i64) (i64.const 100))
+        (global $exported (export "exported") (mut i64) (i64.const 200))
+        (func (export "bump")
+            (global.set $shared (i64.add (global.get $shared) (i64.const 1)))
+            (global.set $private (i64.add (global.get $private) (i64.const 10)))
+            (global.set $exported (i64.add (global.get $exported) (i64.const 20))))
+        (func (export "get_shared") (result i64) (global.get $shared))
+        (func (export "get_constant") (result i64) (global.get $constant))
+        (func (export "get_private") (result i64) (global.get $private))
+        (func (export "get_exported") (result i64) (global.get $exported))
+    "#;
+    let module = Module::new(&store, wat)?;
+    let shared = Global::new_mut(&store, Value::I64(0));
+    let imports = imports! {
+        "env" => {
+            "shared" => shared.clone(),
+            "constant" => Global::new(&store, Value::I64(-1)),
+        },
+    };
+    let first = Instance::new(&module, &imports)?;
+    let second = Instance::new(&module, &imports)?;
+    /// Bumps the counters of `instance`, and returns the values it sees.
+    fn bump(instance: &Instance) -> Result<[i64; 4]> {
+        instance.get_native_function::<(), ()>("bump")?.call()?;
+        let mut values = [0; 4];
+        for (value, name) in values
+            .iter_mut()
+            .zip(["shared", "constant", "private", "exported"])
+        {
+            let get = instance.get_native_function::<(), i64>(&format!("get_{}", name))?;
+            *value = get.call()?;
+        }
+        Ok(values)
+    }
+
+    assert_eq!(bump(&first)?, [1, -1, 110, 220]);
+    assert_eq!(bump(&first)?, [2, -1, 120, 240]);
+    assert_eq!(bump(&second)?, [3, -1, 110, 220]);
+    shared.set(Value::I64(1000))?;
+    second.lookup_global("exported")?.set(Value::I64(0))?;
+    assert_eq!(bump(&first)?, [1001, -1, 130, 260]);
+    assert_eq!(bump(&second)?, [1002, -1, 120, 20]);
+    assert_eq!(shared.get(), Value::I64(1002));
+    assert_eq!(first.lookup_global("exported")?.get(), Value::I64(260));
+    Ok(())
+}
+
+/// Sets its flag when dropped.
+struct DropFlag(Arc<AtomicBool>);
+
+impl Drop for DropFlag {
+    fn drop(&mut self) {
+        self.0.store(true, SeqCst);
+    }
+}
+
+#[compiler_test(globals)]
+fn globals_keep_their_externref_alive(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let dropped = Arc::new(AtomicBool::new(false));
+    let global = Global::new_mut(
+        &store,
+        Value::ExternRef(ExternRef::new(DropFlag(dropped.clone()))),
+    );
+    for _ in 0..3 {
+        drop(global.get());
+    }
+    assert!(!dropped.load(SeqCst));
+    global.set(Value::ExternRef(ExternRef::null()))?;
+    assert!(dropped.load(SeqCst));
+    Ok(())
+}
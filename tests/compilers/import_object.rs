@@ -0,0 +1,267 @@
+//! Testing import objects built at runtime, their layering, and the report of the imports
+//! they miss.
+
+use anyhow::Result;
+use wasmer::*;
+
+fn get_module(store: &Store) -> Result<Module> {
+    let wat = r#"
+        (import "env" "one" (func $one (result i32)))
+        (import "env" "two" (func $two (result i32)))
+        (import "math" "ten" (global $ten i32))
+        (func (export "sum") (result i32)
+            (i32.add (i32.add (call $one) (call $two)) (global.get $ten)))
+    "#;
+    Ok(Module::new(&store, &wat)?)
+}
+
+fn one() -> i32 {
+    1
+}
+
+fn two() -> i32 {
+    2
+}
+
+fn twenty() -> i32 {
+    20
+}
+
+#[compiler_test(import_object)]
+fn built_import_objects_match_the_macro(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let module = get_module(&store)?;
+    let from_macro = imports! {
+        "env" => {
+            "one" => Function::new_native(&store, one),
+            "two" => Function::new_native(&store, two),
+        },
+        "math" => {
+            "ten" => Global::new(&store, Value::I32(10)),
+        },
+    };
+
+    let namespaces: Vec<(&str, Vec<(&str, Extern)>)> = vec![
+        (
+            "env",
+            vec![
+                ("one", Function::new_native(&store, one).into()),
+                ("two", Function::new_native(&store, two).into()),
+            ],
+        ),
+        (
+            "math",
+            vec![("ten", Global::new(&store, Value::I32(10)).into())],
+        ),
+    ];
+    let mut built = ImportObject::new();
+    for (name, externs) in namespaces {
+        let mut namespace = Exports::new();
+        for (field, export) in externs {
+            namespace.insert(field, export);
+        }
+        built.register(name, namespace);
+    }
+
+    assert!(built.contains_namespace("env"));
+    assert!(built.get_export("env", "one").is_some());
+    assert!(built.get_export("env", "ten").is_none());
+    assert!(built.get_export("other", "one").is_none());
+    for imports in [from_macro, built] {
+        let instance = Instance::new(&module, &imports)?;
+        let sum = instance.get_native_function::<(), i32>("sum")?;
+        assert_eq!(sum.call()?, 13);
+    }
+    Ok(())
+}
+
+#[compiler_test(import_object)]
+fn chained_import_objects_override_imports(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let module = get_module(&store)?;
+    let base = imports! {
+        "env" => {
+            "one" => Function::new_native(&store, one),
+            "two" => Function::new_native(&store, two),
+        },
+        "math" => {
+            "ten" => Global::new(&store, Value::I32(10)),
+        },
+    };
+    let overrides = imports! {
+        "env" => {
+            "two" => Function::new_native(&store, twenty),
+        },
+    };
+
+    // The resolver in front wins, the other one provides what it lacks.
+    let sum = |resolver: &dyn Resolver| -> Result<i32> {
+        let instance = Instance::new(&module, resolver)?;
+        Ok(instance.get_native_function::<(), i32>("sum")?.call()?)
+    };
+    assert_eq!(sum(&base.clone().chain_front(overrides.clone()))?, 31);
+    assert_eq!(sum(&base.clone().chain_back(overrides.clone()))?, 13);
+    assert_eq!(sum(&overrides.chain_back(base))?, 31);
+    Ok(())
+}
+
+#[compiler_test(import_object)]
+fn all_unknown_imports_are_reported(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let imports = imports! {
+        "env" => {
+            "one" => Function::new_native(&store, one),
+        },
+        "math" => {
+            "one" => Global::new(&store, Value::I32(1)),
+        },
+    };
+    let module = Module::new(
+        &store,
+        r#"
+            (import "env" "one" (func (result i32)))
+            (import "env" "two" (func (result i32)))
+            (import "env" "memory" (memory 1))
+            (import "math" "ten" (global i32))
+        "#,
+    )?;
+
+    let unknown_imports = match Instance::new(&module, &imports) {
+        Err(InstantiationError::Link(LinkError::UnknownImports(unknown_imports))) => {
+            unknown_imports
+        }
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("instantiation should fail"),
+    };
+    let unknown = |index: u32, module: &str, field: &str, ty: ExternType| UnknownImport {
+        index,
+        module: module.to_string(),
+        field: field.to_string(),
+        function_name: None,
+        ty,
+    };
+    assert_eq!(
+        unknown_imports,
+        vec![
+            unknown(
+                1,
+                "env",
+                "two",
+                ExternType::Function(FunctionType::new(vec![], vec![Type::I32])),
+            ),
+            unknown(
+                2,
+                "env",
+                "memory",
+                ExternType::Memory(MemoryType::new(1, None, false)),
+            ),
+            unknown(
+                3,
+                "math",
+                "ten",
+                ExternType::Global(GlobalType::new(Type::I32, Mutability::Const)),
+            ),
+        ]
+    );
+
+    // The message lists them all too.
+    let message = LinkError::UnknownImports(unknown_imports).to_string();
+    assert!(message.contains("unknown imports: "));
+    for field in &[
+        "#1 \"env\".\"two\"",
+        "#2 \"env\".\"memory\"",
+        "#3 \"math\".\"ten\"",
+    ] {
+        assert!(message.contains(field), "{} misses {}", message, field);
+    }
+    Ok(())
+}
+
+fn get_exporting_instance(store: &Store) -> Result<Instance> {
+    let wat = r#"
+        (memory (export "memory") 1)
+        (global $calls (export "calls") (mut i32) (i32.const 0))
+        (func (export "add_one") (param i32) (result i32)
+            (global.set $calls (i32.add (global.get $calls) (i32.const 1)))
+            (i32.add (local.get 0) (i32.const 1)))
+        (func (export "load") (param i32) (result i32)
+            (i32.load (local.get 0)))
+    "#;
+    let module = Module::new(&store, &wat)?;
+    Ok(Instance::new(&module, &imports! {})?)
+}
+
+fn get_importing_module(store: &Store) -> Result<Module> {
+    let wat = r#"
+        (import "a" "add_one" (func $add_one (param i32) (result i32)))
+        (import "a" "load" (func $load (param i32) (result i32)))
+        (import "a" "memory" (memory 1))
+        (import "a" "calls" (global $calls (mut i32)))
+        (func (export "count") (param $n i32) (result i32)
+            (local $count i32)
+            (block $done
+                (loop $next
+                    (br_if $done (i32.eq (local.get $count) (local.get $n)))
+                    (local.set $count (call $add_one (local.get $count)))
+                    (br $next)))
+            (local.get $count))
+        (func (export "store") (param i32 i32)
+            (i32.store (local.get 0) (local.get 1)))
+        (func (export "load") (param i32) (result i32)
+            (call $load (local.get 0)))
+        (func (export "calls") (result i32)
+            (global.get $calls))
+    "#;
+    Ok(Module::new(&store, &wat)?)
+}
+
+#[compiler_test(import_object)]
+fn instances_call_the_functions_of_other_instances(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let a = get_exporting_instance(&store)?;
+    let imports = ImportObject::from_instance_namespace("a", &a);
+    assert!(imports.get_export("a", "add_one").is_some());
+    assert!(imports.get_export("a", "missing").is_none());
+    let b = Instance::new(&get_importing_module(&store)?, &imports)?;
+
+    let count = b.get_native_function::<i32, i32>("count")?;
+    assert_eq!(count.call(1_000_000)?, 1_000_000);
+    assert_eq!(a.lookup_global("calls")?.get(), Value::I32(1_000_000));
+    Ok(())
+}
+
+#[compiler_test(import_object)]
+fn imports_keep_the_exporting_instance_alive(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let memory_only = Module::new(
+        &store,
+        r#"
+            (import "a" "memory" (memory 1))
+            (func (export "load") (param i32) (result i32)
+                (i32.load (local.get 0)))
+        "#,
+    )?;
+    let (b, c) = {
+        let a = get_exporting_instance(&store)?;
+        let imports = ImportObject::from_instance_namespace("a", &a);
+        (
+            Instance::new(&get_importing_module(&store)?, &imports)?,
+            Instance::new(&memory_only, &imports)?,
+        )
+    };
+
+    // The memory and global are still those of the dropped instance, which its functions
+    // see.
+    let write = b.get_native_function::<(i32, i32), ()>("store")?;
+    let load = b.get_native_function::<i32, i32>("load")?;
+    let count = b.get_native_function::<i32, i32>("count")?;
+    write.call(16, 42)?;
+    assert_eq!(load.call(16)?, 42);
+    assert_eq!(count.call(3)?, 3);
+    assert_eq!(b.get_native_function::<(), i32>("calls")?.call()?, 3);
+
+    drop(b);
+    let load = c.get_native_function::<i32, i32>("load")?;
+    assert_eq!(load.call(16)?, 42);
+    Ok(())
+}
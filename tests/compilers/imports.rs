@@ -7,7 +7,7 @@ use std::convert::Infallible;
 use std::sync::atomic::AtomicBool;
 use std::sync::{
     atomic::{AtomicUsize, Ordering::SeqCst},
-    Arc,
+    Arc, Mutex,
 };
 use wasmer::*;
 
@@ -337,6 +337,7 @@ fn dynamic_function_with_env_wasmer_env_init_works(config: crate::Config) -> Res
                 minimum: 0.into(),
                 maximum: None,
                 shared: false,
+                memory64: false,
             },
         )?,
     };
@@ -393,56 +394,224 @@ fn regression_import_trampolines(config: crate::Config) -> Result<()> {
     Ok(())
 }
 
+const MULTIPLE_RESULTS: &str = r#"
+(module
+  (type $t (func (param i32) (result i32 i64 f32 f64 i32)))
+  (import "host" "multi" (func $multi (type $t)))
+  (table funcref (elem $multi))
+  (func (export "call") (type $t)
+    local.get 0
+    call $multi)
+  (func (export "call_indirect") (type $t)
+    local.get 0
+    i32.const 0
+    call_indirect (type $t)))"#;
+
+#[compiler_test(imports)]
+fn dynamic_function_with_multiple_results(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let module = Module::new(&store, &MULTIPLE_RESULTS)?;
+    let ty = FunctionType::new(
+        vec![ValType::I32],
+        vec![
+            ValType::I32,
+            ValType::I64,
+            ValType::F32,
+            ValType::F64,
+            ValType::I32,
+        ],
+    );
+    let multi = Function::new(&store, ty, |values| {
+        let n = match values[0] {
+            Value::I32(n) => n,
+            ref v => panic!("unexpected argument {:?}", v),
+        };
+        Ok(vec![
+            Value::I32(n + 1),
+            Value::I64(n as i64 * 2),
+            Value::F32(n as f32 / 2.0),
+            Value::F64(n as f64 * 1.5),
+            Value::I32(-n),
+        ])
+    });
+    let imports = imports! {
+        "host" => {
+            "multi" => multi,
+        }
+    };
+    let instance = Instance::new(&module, &imports)?;
+    let expected = vec![
+        Value::I32(8),
+        Value::I64(14),
+        Value::F32(3.5),
+        Value::F64(10.5),
+        Value::I32(-7),
+    ]
+    .into_boxed_slice();
+    for name in &["call", "call_indirect"] {
+        let f = instance.lookup_function(name).unwrap();
+        assert_eq!(f.call(&[Value::I32(7)])?, expected, "{}", name);
+    }
+    Ok(())
+}
+
+#[compiler_test(imports)]
+fn multi_use_host_fn_manages_memory_correctly(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let module = get_module2(&store)?;
+
+    #[derive(Clone)]
+    struct Env {
+        memory: LazyInit<Memory>,
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl WasmerEnv for Env {
+        fn init_with_instance(&mut self, instance: &Instance) -> Result<(), HostEnvInitError> {
+            let mut memory = instance.lookup_memory("memory")?;
+            memory.into_weak_instance_ref();
+            self.memory.initialize(memory);
+            Ok(())
+        }
+    }
+
+    let env: Env = Env {
+        memory: LazyInit::default(),
+        calls: Arc::new(AtomicUsize::new(0)),
+    };
+    fn host_fn(env: &Env) -> Result<(), RuntimeError> {
+        let memory = env
+            .memory
+            .get_ref()
+            .ok_or_else(|| RuntimeError::new("the memory is not initialized"))?;
+        assert_eq!(memory.size(), Pages(1));
+        env.calls.fetch_add(1, SeqCst);
+        Ok(())
+    }
+
+    let imports = imports! {
+        "host" => {
+            "fn" => Function::new_native_with_env(&store, env.clone(), host_fn),
+        },
+    };
+    let instance1 = Instance::new(&module, &imports)?;
+    let instance2 = Instance::new(&module, &imports)?;
+    {
+        let f1: NativeFunc<(), ()> = instance1.get_native_function("main")?;
+        f1.call()?;
+    }
+    drop(instance1);
+    {
+        let f2: NativeFunc<(), ()> = instance2.get_native_function("main")?;
+        f2.call()?;
+        f2.call()?;
+    }
+    drop(instance2);
+    assert_eq!(env.calls.load(SeqCst), 3);
+    Ok(())
+}
+
+/// Creates the host functions `sys.call_<n>` on demand, for any signature with `i32`s only.
+struct Syscalls {
+    store: Store,
+    requests: Mutex<Vec<(u32, String, ExternType)>>,
+}
+
+impl Resolver for Syscalls {
+    fn resolve(
+        &self,
+        index: u32,
+        module: &str,
+        field: &str,
+        expected: &ExternType,
+    ) -> Option<Export> {
+        self.requests
+            .lock()
+            .unwrap()
+            .push((index, field.to_string(), expected.clone()));
+        let number: i32 = field.strip_prefix("call_")?.parse().ok()?;
+        let ty = match expected {
+            ExternType::Function(ty) if module == "sys" => ty.clone(),
+            _ => return None,
+        };
+        let function = Function::new(&self.store, ty, move |args| {
+            let sum = args.iter().map(|arg| arg.unwrap_i32()).sum::<i32>();
+            Ok(vec![Value::I32(number + sum)])
+        });
+        Some(function.to_export())
+    }
+}
+
+#[compiler_test(imports)]
+fn lazily_resolved_imports(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let module = Module::new(
+        &store,
+        r#"
+            (import "sys" "call_7" (func $seven (param i32) (result i32)))
+            (import "sys" "call_300" (func $three_hundred (param i32 i32) (result i32)))
+            (func (export "run") (result i32)
+                (i32.add
+                    (call $seven (i32.const 1))
+                    (call $three_hundred (i32.const 2) (i32.const 3))))
+        "#,
+    )?;
+    let syscalls = Syscalls {
+        store: store.clone(),
+        requests: Mutex::new(Vec::new()),
+    };
+    let instance = Instance::new(&module, &syscalls)?;
+    assert_eq!(instance.get_native_function::<(), i32>("run")?.call()?, 313);
+    // Only the imports of the module were asked for, in order, with their types.
+    assert_eq!(
+        *syscalls.requests.lock().unwrap(),
+        vec![
+            (
+                0,
+                "call_7".to_string(),
+                ExternType::Function(FunctionType::new(vec![Type::I32], vec![Type::I32])),
+            ),
+            (
+                1,
+                "call_300".to_string(),
+                ExternType::Function(FunctionType::new(
+                    vec![Type::I32, Type::I32],
+                    vec![Type::I32]
+                )),
+            ),
+        ]
+    );
+
+    // What the resolver can't make is reported with its position and type.
+    let module = Module::new(
+        &store,
+        r#"
+            (import "sys" "call_1" (func))
+            (import "sys" "call_2" (global i32))
+        "#,
+    )?;
+    match Instance::new(&module, &syscalls) {
+        Err(InstantiationError::Link(LinkError::UnknownImports(unknown))) => {
+            assert_eq!(
+                unknown,
+                vec![UnknownImport {
+                    index: 1,
+                    module: "sys".to_string(),
+                    field: "call_2".to_string(),
+                    function_name: None,
+                    ty: ExternType::Global(GlobalType::new(Type::I32, Mutability::Const)),
+                }]
+            );
+        }
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("instantiation should fail"),
+    }
+    Ok(())
+}
+
 // TODO(0-copy): no longer possible to get references to exported entities other than functions
 //               (we don't need that functionality)
 // #[compiler_test(imports)]
-// fn multi_use_host_fn_manages_memory_correctly(config: crate::Config) -> Result<()> {
-//     let store = config.store();
-//     let module = get_module2(&store)?;
-//
-//     #[allow(dead_code)]
-//     #[derive(Clone)]
-//     struct Env {
-//         memory: LazyInit<Memory>,
-//     }
-//
-//     impl WasmerEnv for Env {
-//         fn init_with_instance(&mut self, instance: &Instance) -> Result<(), HostEnvInitError> {
-//             let memory = instance.exports.get_memory("memory")?.clone();
-//             self.memory.initialize(memory);
-//             Ok(())
-//         }
-//     }
-//
-//     let env: Env = Env {
-//         memory: LazyInit::default(),
-//     };
-//     fn host_fn(env: &Env) {
-//         assert!(env.memory.get_ref().is_some());
-//         println!("Hello, world!");
-//     }
-//
-//     let imports = imports! {
-//         "host" => {
-//             "fn" => Function::new_native_with_env(&store, env.clone(), host_fn),
-//         },
-//     };
-//     let instance1 = Instance::new(&module, &imports)?;
-//     let instance2 = Instance::new(&module, &imports)?;
-//     {
-//         let f1: NativeFunc<(), ()> = instance1.get_native_function("main")?;
-//         f1.call()?;
-//     }
-//     drop(instance1);
-//     {
-//         let f2: NativeFunc<(), ()> = instance2.get_native_function("main")?;
-//         f2.call()?;
-//     }
-//     drop(instance2);
-//     Ok(())
-// }
-//
-// #[compiler_test(imports)]
 // fn instance_local_memory_lifetime(config: crate::Config) -> Result<()> {
 //     let store = config.store();
 //
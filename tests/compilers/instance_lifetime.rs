@@ -0,0 +1,232 @@
+//! Testing the release of the resources of instances, and closing them.
+
+use anyhow::Result;
+use std::sync::Arc;
+use wasmer::*;
+use wasmer_vm::TrapCode;
+
+fn get_module(store: &Store) -> Result<Module> {
+    let wat = r#"
+        (memory (export "memory") 1)
+        (table (export "table") 10 funcref)
+        (global (export "global") (mut i32) (i32.const 0))
+        (func (export "answer") (result i32)
+            (i32.const 42))
+    "#;
+    Ok(Module::new(&store, &wat)?)
+}
+
+#[compiler_test(instance_lifetime)]
+fn closed_instances_refuse_calls(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let module = get_module(&store)?;
+    let instance = Instance::new(&module, &imports! {})?;
+    let other_handle = instance.clone();
+    let answer = instance.lookup_function("answer")?;
+    let native_answer = instance.get_native_function::<(), i32>("answer")?;
+    assert_eq!(native_answer.call()?, 42);
+
+    // Another instance isn't affected.
+    let unrelated = Instance::new(&module, &imports! {})?;
+    instance.close();
+    assert!(answer.call(&[]).unwrap_err().is_instance_closed());
+    assert!(native_answer.call().unwrap_err().is_instance_closed());
+    let err = other_handle
+        .get_native_function::<(), i32>("answer")?
+        .call()
+        .unwrap_err();
+    assert!(err.is_instance_closed());
+    assert_eq!(err.message(), "the instance was closed");
+    assert_eq!(
+        unrelated.get_native_function::<(), i32>("answer")?.call()?,
+        42
+    );
+    Ok(())
+}
+
+/// The size of the virtual address space of the process.
+#[cfg(target_os = "linux")]
+fn virtual_memory_size() -> u64 {
+    let status = std::fs::read_to_string("/proc/self/status").unwrap();
+    let kib = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmSize:"))
+        .and_then(|size| size.trim().strip_suffix("kB"))
+        .and_then(|kib| kib.trim().parse::<u64>().ok())
+        .expect("no VmSize in /proc/self/status");
+    kib * 1024
+}
+
+#[cfg(target_os = "linux")]
+#[compiler_test(instance_lifetime)]
+fn dropped_instances_release_their_memories(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let module = get_module(&store)?;
+    let run = |count: usize| -> Result<()> {
+        for i in 0..count {
+            let instance = Instance::new(&module, &imports! {})?;
+            instance.lookup_memory("memory")?.write_u8(0, 1)?;
+            // Both ways of getting rid of an instance free it.
+            if i % 2 == 0 {
+                drop(instance);
+            } else {
+                instance.close();
+            }
+        }
+        Ok(())
+    };
+    run(100)?;
+    let before = virtual_memory_size();
+    run(10_000)?;
+    let after = virtual_memory_size();
+
+    // The static memory style reserves several GiB per memory, leaking them would use tens
+    // of TiB. Other tests run concurrently, so leave them plenty of room.
+    assert!(
+        after.saturating_sub(before) < 1 << 40,
+        "the address space grew from {} to {} bytes",
+        before,
+        after
+    );
+    Ok(())
+}
+
+/// Make a store whose resources are accounted for by `limiter`.
+fn store_with_limiter(config: &crate::Config, limiter: Arc<StaticLimiter>) -> Store {
+    let engine = config.engine(config.compiler_config(config.canonicalize_nans));
+    let mut tunables = BaseTunables::for_target(engine.target());
+    tunables.resource_limiter = Some(limiter);
+    Store::new_with_tunables(&*engine, tunables)
+}
+
+fn assert_released(limiter: &StaticLimiter) {
+    assert_eq!(limiter.total_pages(), Pages(0));
+    assert_eq!(limiter.table_elements(), 0);
+    assert_eq!(limiter.instances(), 0);
+}
+
+#[compiler_test(instance_lifetime)]
+fn instances_whose_start_function_traps_are_freed(config: crate::Config) -> Result<()> {
+    let limiter = Arc::new(StaticLimiter::new(Pages(10), 10, 100));
+    let store = store_with_limiter(&config, limiter.clone());
+    let module = Module::new(
+        &store,
+        r#"
+        (module $failing
+            (memory 2)
+            (table 10 funcref)
+            (func $start
+                (i32.store (i32.const 0) (i32.const 1))
+                unreachable)
+            (start $start))
+        "#,
+    )?;
+
+    let error = match Instance::new(&module, &imports! {}) {
+        Err(InstantiationError::Start(error)) => error,
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("instantiation should fail"),
+    };
+    assert_eq!(
+        error.clone().to_trap(),
+        Some(TrapCode::UnreachableCodeReached)
+    );
+    let frame = &error.trace()[0];
+    assert_eq!(frame.module_name(), "failing");
+    assert_eq!(frame.function_name(), Some("start"));
+    assert_eq!(frame.func_index(), 0);
+    assert_released(&limiter);
+    Ok(())
+}
+
+#[compiler_test(instance_lifetime)]
+fn host_functions_called_from_the_start_function_can_fail(config: crate::Config) -> Result<()> {
+    #[derive(Clone)]
+    struct Env {
+        memory: LazyInit<Memory>,
+    }
+
+    impl WasmerEnv for Env {
+        fn init_with_instance(&mut self, instance: &Instance) -> Result<(), HostEnvInitError> {
+            let mut memory = instance.lookup_memory("memory")?;
+            memory.into_weak_instance_ref();
+            self.memory.initialize(memory);
+            Ok(())
+        }
+    }
+
+    fn fail(env: &Env) -> Result<(), RuntimeError> {
+        // The environment is initialized before the start function runs.
+        let byte = env.memory.get_ref().unwrap().read_u8(0)?;
+        if byte == 0 {
+            panic!("the memory wasn't written");
+        }
+        Err(RuntimeError::new(format!(
+            "the memory starts with {}",
+            byte
+        )))
+    }
+
+    let limiter = Arc::new(StaticLimiter::new(Pages(10), 10, 100));
+    let mut store = store_with_limiter(&config, limiter.clone());
+    let wat = |byte: u8| {
+        format!(
+            r#"
+            (import "env" "fail" (func $fail))
+            (memory (export "memory") 1)
+            (func $start
+                (i32.store8 (i32.const 0) (i32.const {}))
+                (call $fail))
+            (start $start)
+            "#,
+            byte
+        )
+    };
+    let imports = |store: &Store| {
+        let env = Env {
+            memory: LazyInit::new(),
+        };
+        imports! {
+            "env" => {
+                "fail" => Function::new_native_with_env(store, env, fail),
+            },
+        }
+    };
+
+    let module = Module::new(&store, wat(7))?;
+    match Instance::new(&module, &imports(&store)) {
+        Err(InstantiationError::Start(error)) => {
+            assert_eq!(error.message(), "the memory starts with 7");
+        }
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("instantiation should fail"),
+    }
+    assert_released(&limiter);
+
+    let module = Module::new(&store, wat(0))?;
+    match Instance::new(&module, &imports(&store)) {
+        Err(InstantiationError::Start(error)) => {
+            assert!(error.is_host_panic());
+            assert_eq!(error.message(), "the memory wasn't written");
+        }
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("instantiation should fail"),
+    }
+    assert_released(&limiter);
+
+    // Resumed panics free the instance too.
+    store.resume_host_panics(true);
+    let module = Module::new(&store, wat(0))?;
+    let imports = imports(&store);
+    let panic = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        drop(Instance::new(&module, &imports));
+    }))
+    .unwrap_err();
+    assert_eq!(
+        panic.downcast_ref::<&'static str>(),
+        Some(&"the memory wasn't written")
+    );
+    drop((imports, module));
+    assert_released(&limiter);
+    Ok(())
+}
@@ -0,0 +1,118 @@
+//! Testing the metrics collected by instances.
+
+use anyhow::Result;
+use std::sync::atomic::{AtomicBool, Ordering::SeqCst};
+use std::sync::Arc;
+use wasmer::*;
+use wasmer_types::InstanceConfig;
+
+/// A module whose memory is declared by `memory`, and grown by one page by its start
+/// function.
+fn wat(memory: &str) -> String {
+    format!(
+        r#"
+        {}
+        (export "memory" (memory 0))
+        (func $start
+            (drop (memory.grow (i32.const 1))))
+        (start $start)
+        (func (export "grow") (param $pages i32) (result i32)
+            (memory.grow (local.get $pages)))
+        "#,
+        memory
+    )
+}
+
+static DEFINED: &str = "(memory 1 4)";
+static IMPORTED: &str = r#"(import "env" "memory" (memory 1 4))"#;
+
+fn instantiate(store: &Store, memory: &str, config: InstanceConfig) -> Result<Instance> {
+    let module = Module::new(&store, wat(memory))?;
+    let memory = Memory::new(&store, MemoryType::new(1, Some(4), false))?;
+    let imports = imports! {
+        "env" => {
+            "memory" => memory,
+        },
+    };
+    Ok(Instance::new_with_config(&module, config, &imports)?)
+}
+
+#[compiler_test(instance_metrics)]
+fn grows_and_peak_pages_are_counted(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    for memory in [DEFINED, IMPORTED] {
+        let instance = instantiate(&store, memory, InstanceConfig::default().with_metrics())?;
+        let grow = instance.get_native_function::<i32, i32>("grow")?;
+
+        // The start function is a call from the host.
+        assert_eq!(
+            instance.metrics(),
+            Some(InstanceMetrics {
+                peak_memory_pages: 2,
+                memory_grows: 1,
+                host_calls: 1,
+            })
+        );
+
+        assert_eq!(grow.call(1)?, 2);
+        // Past the maximum, failing grows are counted but don't change the size.
+        assert_eq!(grow.call(2)?, -1);
+        assert_eq!(grow.call(0)?, 3);
+        assert_eq!(grow.call(1)?, 3);
+        assert_eq!(
+            instance.metrics(),
+            Some(InstanceMetrics {
+                peak_memory_pages: 4,
+                memory_grows: 5,
+                host_calls: 5,
+            })
+        );
+
+        // Grows from the host aren't the instance's.
+        instance.lookup_memory("memory")?.grow(Pages(0))?;
+        assert_eq!(instance.metrics().unwrap().memory_grows, 5);
+    }
+    Ok(())
+}
+
+#[compiler_test(instance_metrics)]
+fn metrics_are_opt_in(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let instance = instantiate(&store, DEFINED, InstanceConfig::default())?;
+    instance.get_native_function::<i32, i32>("grow")?.call(1)?;
+    assert_eq!(instance.metrics(), None);
+    Ok(())
+}
+
+#[compiler_test(instance_metrics)]
+fn metrics_are_sampled_while_the_instance_runs(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let instance = instantiate(&store, IMPORTED, InstanceConfig::default().with_metrics())?;
+    let grow = instance.get_native_function::<i32, i32>("grow")?;
+
+    let done = Arc::new(AtomicBool::new(false));
+    let sampler = {
+        let instance = instance.clone();
+        let done = done.clone();
+        std::thread::spawn(move || {
+            let mut last = instance.metrics().unwrap();
+            while !done.load(SeqCst) {
+                let metrics = instance.metrics().unwrap();
+                assert!(metrics.memory_grows >= last.memory_grows);
+                assert!(metrics.host_calls >= last.host_calls);
+                assert!(metrics.peak_memory_pages >= last.peak_memory_pages);
+                last = metrics;
+            }
+        })
+    };
+    for _ in 0..1000 {
+        grow.call(0)?;
+    }
+    done.store(true, SeqCst);
+    sampler.join().unwrap();
+    let metrics = instance.metrics().unwrap();
+    assert_eq!(metrics.memory_grows, 1001);
+    assert_eq!(metrics.host_calls, 1001);
+    assert_eq!(metrics.peak_memory_pages, 2);
+    Ok(())
+}
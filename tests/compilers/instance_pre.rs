@@ -0,0 +1,140 @@
+//! Testing the instances starting from the captured state of a first instance.
+
+use anyhow::Result;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use wasmer::*;
+
+/// A module whose data segment is placed at an imported offset, and whose start function
+/// calls the host and initializes a global, a table element and the memory.
+const WAT: &str = r#"
+    (import "host" "started" (func $started))
+    (import "host" "offset" (global $offset i32))
+    (memory (export "memory") 1)
+    (global $answer (export "answer") (mut i32) (i32.const 0))
+    (table 2 funcref)
+    (elem (i32.const 0) $forty_two)
+    (data (global.get $offset) "data")
+    (func $forty_two (result i32) (i32.const 42))
+    (func $start
+        (call $started)
+        (global.set $answer (call_indirect (result i32) (i32.const 0)))
+        (i32.store (i32.const 0x8000) (i32.const 7))
+        (memory.grow (i32.const 1))
+        (drop))
+    (start $start)
+    (func (export "call") (param $index i32) (result i32)
+        (call_indirect (result i32) (local.get $index)))
+    (func (export "load") (param $address i32) (result i32)
+        (i32.load (local.get $address)))
+    (func (export "store") (param $address i32) (param $value i32)
+        (i32.store (local.get $address) (local.get $value)))
+"#;
+
+/// The imports of the module, counting the calls to `started`.
+fn get_imports(store: &Store, offset: i32, starts: &Arc<AtomicU32>) -> ImportObject {
+    let starts = starts.clone();
+    imports! {
+        "host" => {
+            "started" => Function::new_native(store, move || {
+                starts.fetch_add(1, Ordering::SeqCst);
+            }),
+            "offset" => Global::new(store, Value::I32(offset)),
+        },
+    }
+}
+
+fn load(instance: &Instance, address: i32) -> Result<i32> {
+    Ok(instance
+        .get_native_function::<i32, i32>("load")?
+        .call(address)?)
+}
+
+#[compiler_test(instance_pre)]
+fn instances_start_from_the_captured_state(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let module = Module::new(&store, WAT)?;
+    let starts = Arc::new(AtomicU32::new(0));
+    let pre = InstancePre::new(&module, &get_imports(&store, 16, &starts))?;
+    assert_eq!(starts.load(Ordering::SeqCst), 1);
+
+    for _ in 0..3 {
+        let instance = pre.instantiate()?;
+        assert_eq!(load(&instance, 16)?, i32::from_le_bytes(*b"data"));
+        assert_eq!(load(&instance, 0x8000)?, 7);
+        assert_eq!(instance.lookup_global("answer")?.get(), Value::I32(42));
+        let call = instance.get_native_function::<i32, i32>("call")?;
+        assert_eq!(call.call(0)?, 42);
+        assert!(call.call(1).is_err());
+        // The memory grown by the start function is as large in the new instances.
+        assert_eq!(instance.lookup_memory("memory")?.size(), Pages(2));
+        assert_eq!(load(&instance, 0x1_0000)?, 0);
+        assert!(load(&instance, 0x2_0000 - 3).is_err());
+    }
+    // The start function only ran for the first instance.
+    assert_eq!(starts.load(Ordering::SeqCst), 1);
+    Ok(())
+}
+
+#[compiler_test(instance_pre)]
+fn instances_dont_see_each_others_writes(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let module = Module::new(&store, WAT)?;
+    let starts = Arc::new(AtomicU32::new(0));
+    let pre = InstancePre::new(&module, &get_imports(&store, 16, &starts))?;
+
+    let first = pre.instantiate()?;
+    let second = pre.instantiate()?;
+    let store_i32 = first.get_native_function::<(i32, i32), ()>("store")?;
+    store_i32.call(16, 1)?;
+    store_i32.call(0x8000, 2)?;
+    store_i32.call(0x1_0000, 3)?;
+    first.lookup_global("answer")?.set(Value::I32(0))?;
+    first
+        .lookup_memory("memory")?
+        .write(0x9000, b"host write")?;
+
+    assert_eq!(load(&first, 16)?, 1);
+    for instance in [&second, &pre.instantiate()?] {
+        assert_eq!(load(instance, 16)?, i32::from_le_bytes(*b"data"));
+        assert_eq!(load(instance, 0x8000)?, 7);
+        assert_eq!(load(instance, 0x9000)?, 0);
+        assert_eq!(load(instance, 0x1_0000)?, 0);
+        assert_eq!(instance.lookup_global("answer")?.get(), Value::I32(42));
+    }
+    Ok(())
+}
+
+#[compiler_test(instance_pre)]
+fn different_imports_invalidate_the_image(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let module = Module::new(&store, WAT)?;
+    let starts = Arc::new(AtomicU32::new(0));
+    let imports = get_imports(&store, 16, &starts);
+    let pre = InstancePre::new(&module, &imports)?;
+
+    // The same externs: the image is used.
+    let instance = pre.instantiate_with_imports(&imports)?;
+    assert_eq!(starts.load(Ordering::SeqCst), 1);
+    assert_eq!(load(&instance, 16)?, i32::from_le_bytes(*b"data"));
+
+    // A different offset: the module is initialized from scratch.
+    let instance = pre.instantiate_with_imports(&get_imports(&store, 32, &starts))?;
+    assert_eq!(starts.load(Ordering::SeqCst), 2);
+    assert_eq!(load(&instance, 16)?, 0);
+    assert_eq!(load(&instance, 32)?, i32::from_le_bytes(*b"data"));
+    assert_eq!(instance.lookup_global("answer")?.get(), Value::I32(42));
+    Ok(())
+}
+
+#[compiler_test(instance_pre)]
+fn missing_imports_fail_to_link(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let module = Module::new(&store, WAT)?;
+    match InstancePre::new(&module, &imports! {}) {
+        Err(InstantiationError::Link(LinkError::UnknownImports(..))) => {}
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("the module was instantiated without its imports"),
+    }
+    Ok(())
+}
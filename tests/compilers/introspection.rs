@@ -0,0 +1,169 @@
+//! Testing what can be known of a module without instantiating it.
+
+use anyhow::Result;
+use std::convert::TryFrom;
+use wasmer::*;
+use wasmer_engine::Executable;
+use wasmer_engine_universal::UniversalExecutableRef;
+
+#[compiler_test(introspection)]
+fn validation_errors_give_the_offset(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let wasm = wat2wasm(
+        b"(func (export \"sum\") (param i32 i32) (result i32) local.get 0 local.get 1 i32.add)",
+    )?;
+    Module::validate(&store, &wasm)?;
+
+    // The `i32.add` is missing an operand.
+    let wasm =
+        wat2wasm(b"(func (export \"sum\") (param i32 i32) (result i32) local.get 0 i32.add)")?;
+    let offset = wasm.iter().rposition(|&byte| byte == 0x6a).unwrap();
+    match Module::validate(&store, &wasm) {
+        Err(CompileError::Function { location, error }) => {
+            assert_eq!(location.offset, offset);
+            assert_eq!(location.operator.as_deref(), Some("I32Add"));
+            assert!(matches!(*error, CompileError::Validate(_)), "{:?}", error);
+        }
+        result => panic!("unexpected result: {:?}", result),
+    }
+
+    // A truncated module.
+    assert!(matches!(
+        Module::validate(&store, &wasm[..wasm.len() - 1]),
+        Err(CompileError::Validate(_))
+    ));
+    Ok(())
+}
+
+/// `wasm` with a custom section named `name` holding `data` appended.
+fn with_custom_section(mut wasm: Vec<u8>, name: &str, data: &[u8]) -> Vec<u8> {
+    wasm.push(0);
+    wasm.push(u8::try_from(1 + name.len() + data.len()).unwrap());
+    wasm.push(u8::try_from(name.len()).unwrap());
+    wasm.extend_from_slice(name.as_bytes());
+    wasm.extend_from_slice(data);
+    wasm
+}
+
+fn custom_sections<'a>(sections: impl Iterator<Item = &'a [u8]>) -> Vec<&'a [u8]> {
+    sections.collect()
+}
+
+#[compiler_test(introspection)]
+fn custom_sections_are_exposed(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let wasm = wat2wasm(b"(func (export \"run\"))")?.into_owned();
+    let wasm = with_custom_section(wasm, "build-info", b"commit 1");
+    let wasm = with_custom_section(wasm, "other", b"unrelated");
+    let wasm = with_custom_section(wasm, "build-info", b"commit 2");
+    let module = Module::new(&store, &wasm)?;
+    assert_eq!(
+        custom_sections(module.custom_sections("build-info")),
+        vec![&b"commit 1"[..], b"commit 2"]
+    );
+    assert_eq!(
+        custom_sections(module.custom_sections("other")),
+        vec![&b"unrelated"[..]]
+    );
+    assert_eq!(
+        custom_sections(module.custom_sections("missing")),
+        Vec::<&[u8]>::new()
+    );
+    Ok(())
+}
+
+#[test]
+fn custom_sections_survive_serialization() -> Result<()> {
+    let wasm = wat2wasm(b"(func (export \"run\"))")?.into_owned();
+    let wasm = with_custom_section(wasm, "build-info", b"commit 1");
+    let wasm = with_custom_section(wasm, "build-info", b"commit 2");
+    let engine = Universal::new(Singlepass::default()).engine();
+    let tunables = BaseTunables::for_target(engine.target());
+    let executable = engine.compile_universal(&wasm, &tunables)?;
+    let serialized = executable.serialize().unwrap();
+    let archived = unsafe { UniversalExecutableRef::deserialize(&serialized) }?;
+    let artifact = engine.load_universal_executable_ref(&archived)?;
+    assert_eq!(
+        custom_sections(artifact.custom_sections("build-info")),
+        vec![&b"commit 1"[..], b"commit 2"]
+    );
+    Ok(())
+}
+
+#[compiler_test(introspection)]
+fn exports_and_imports_are_typed(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let module = Module::new(
+        &store,
+        r#"
+        (import "env" "counter" (global $counter (mut i64)))
+        (import "env" "memory" (memory $memory 1))
+        (func (export "add") (param i32 i32) (result i32)
+            (i32.add (local.get 0) (local.get 1)))
+        (table (export "table") 2 10 funcref)
+        (global (export "answer") i32 (i32.const 42))
+        (export "counter" (global $counter))
+        (export "memory" (memory $memory))
+        "#,
+    )?;
+
+    let imports = module
+        .imports()
+        .map(|import| {
+            (
+                import.module().to_string(),
+                import.name().to_string(),
+                import.ty().clone(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        imports,
+        vec![
+            (
+                "env".to_string(),
+                "counter".to_string(),
+                ExternType::Global(GlobalType::new(Type::I64, Mutability::Var)),
+            ),
+            (
+                "env".to_string(),
+                "memory".to_string(),
+                ExternType::Memory(MemoryType::new(1, None, false)),
+            ),
+        ]
+    );
+
+    let exports = module
+        .exports()
+        .map(|export| (export.name().to_string(), export.ty().clone()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        exports,
+        vec![
+            (
+                "add".to_string(),
+                ExternType::Function(FunctionType::new(
+                    vec![Type::I32, Type::I32],
+                    vec![Type::I32]
+                )),
+            ),
+            (
+                "table".to_string(),
+                ExternType::Table(TableType::new(Type::FuncRef, 2, Some(10))),
+            ),
+            (
+                "answer".to_string(),
+                ExternType::Global(GlobalType::new(Type::I32, Mutability::Const)),
+            ),
+            (
+                "counter".to_string(),
+                ExternType::Global(GlobalType::new(Type::I64, Mutability::Var)),
+            ),
+            (
+                "memory".to_string(),
+                ExternType::Memory(MemoryType::new(1, None, false)),
+            ),
+        ]
+    );
+    Ok(())
+}
@@ -110,3 +110,108 @@ fn regression_gpr_exhaustion_for_calls(mut config: crate::Config) -> Result<()>
     let instance = Instance::new(&module, &imports)?;
     Ok(())
 }
+
+/// Stack slots of spilled values must leave the stack balanced across branches and loops.
+///
+/// Note: this one is specific to Singlepass, but we want to test in all
+/// available compilers.
+#[compiler_test(issues)]
+fn spilled_values_across_branches_and_loops(mut config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let wat = r#"
+        (module
+          (func (export "run") (param $n i32) (result i64)
+            (local $i i32) (local $acc i64)
+            i64.const 1
+            i64.const 2
+            i64.const 3
+            i64.const 4
+            i64.const 5
+            i64.const 6
+            i64.const 7
+            i64.const 8
+            (block $done
+              (loop $top
+                (br_if $done (i32.ge_u (local.get $i) (local.get $n)))
+                (local.set $acc
+                  (i64.add
+                    (local.get $acc)
+                    (if (result i64) (i32.and (local.get $i) (i32.const 1))
+                      (then
+                        (i64.add
+                          (i64.extend_i32_u (local.get $i))
+                          (i64.mul (i64.const 3) (i64.const 5))))
+                      (else (i64.const 7)))))
+                (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                (br $top)))
+            i64.add
+            i64.add
+            i64.add
+            i64.add
+            i64.add
+            i64.add
+            i64.add
+            local.get $acc
+            i64.add))
+    "#;
+    let module = Module::new(&store, wat)?;
+    let instance = Instance::new(&module, &imports! {})?;
+    let run = instance.lookup_function("run").unwrap();
+    for n in 0..10u32 {
+        let expected = 36
+            + (0..n as i64)
+                .map(|i| if i & 1 == 1 { i + 15 } else { 7 })
+                .sum::<i64>();
+        assert_eq!(
+            run.call(&[Value::I32(n as i32)])?.to_vec(),
+            vec![Value::I64(expected)]
+        );
+    }
+    Ok(())
+}
+
+/// Values living in the argument registers of a call must survive the call.
+///
+/// Note: this one is specific to Singlepass, but we want to test in all
+/// available compilers.
+#[compiler_test(issues)]
+fn values_in_argument_registers_across_calls(mut config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let wat = r#"
+        (module
+          (func $digits (param i64 i64 i64 i64 i64) (result i64)
+            (i64.add
+              (i64.add
+                (i64.add
+                  (i64.add
+                    (local.get 0)
+                    (i64.mul (local.get 1) (i64.const 10)))
+                  (i64.mul (local.get 2) (i64.const 100)))
+                (i64.mul (local.get 3) (i64.const 1000)))
+              (i64.mul (local.get 4) (i64.const 10000))))
+          (func (export "run") (param $x i64) (result i64)
+            (i64.add (local.get $x) (i64.const 1))
+            (i64.add (local.get $x) (i64.const 2))
+            (i64.add (local.get $x) (i64.const 3))
+            (i64.add (local.get $x) (i64.const 4))
+            (call $digits
+              (i64.const 1)
+              (i64.const 2)
+              (i64.add (local.get $x) (i64.const 3))
+              (i64.const 4)
+              (i64.const 5))
+            (call $digits)))
+    "#;
+    let module = Module::new(&store, wat)?;
+    let instance = Instance::new(&module, &imports! {})?;
+    let run = instance.lookup_function("run").unwrap();
+    for x in 0..3i64 {
+        let inner = 1 + 2 * 10 + (x + 3) * 100 + 4 * 1000 + 5 * 10000;
+        let expected = (x + 1) + (x + 2) * 10 + (x + 3) * 100 + (x + 4) * 1000 + inner * 10000;
+        assert_eq!(
+            run.call(&[Value::I64(x)])?.to_vec(),
+            vec![Value::I64(expected)]
+        );
+    }
+    Ok(())
+}
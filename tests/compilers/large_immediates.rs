@@ -0,0 +1,128 @@
+//! Tests of the `i64` operators with constants that don't fit in a 32-bit immediate, as either
+//! operand.
+
+use anyhow::Result;
+use wasmer::*;
+
+static CONSTANTS: [i64; 9] = [
+    i32::MAX as i64 + 1,
+    u32::MAX as i64,
+    i64::MIN,
+    i64::MAX,
+    i32::MIN as i64 - 1,
+    i32::MAX as i64,
+    i32::MIN as i64,
+    -1,
+    0,
+];
+
+static BINOPS: [(&str, fn(i64, i64) -> i64); 6] = [
+    ("add", i64::wrapping_add),
+    ("sub", i64::wrapping_sub),
+    ("mul", i64::wrapping_mul),
+    ("and", |a, b| a & b),
+    ("or", |a, b| a | b),
+    ("xor", |a, b| a ^ b),
+];
+
+static CMPOPS: [(&str, fn(i64, i64) -> bool); 10] = [
+    ("eq", |a, b| a == b),
+    ("ne", |a, b| a != b),
+    ("lt_s", |a, b| a < b),
+    ("lt_u", |a, b| (a as u64) < (b as u64)),
+    ("gt_s", |a, b| a > b),
+    ("gt_u", |a, b| a as u64 > b as u64),
+    ("le_s", |a, b| a <= b),
+    ("le_u", |a, b| a as u64 <= b as u64),
+    ("ge_s", |a, b| a >= b),
+    ("ge_u", |a, b| a as u64 >= b as u64),
+];
+
+/// A module exporting, for each operator and constant, a function applying the operator to its
+/// parameter and the constant, in both orders.
+fn large_immediates_wat() -> String {
+    let mut funcs = String::new();
+    let ops = BINOPS
+        .iter()
+        .map(|(name, _)| (*name, "i64"))
+        .chain(CMPOPS.iter().map(|(name, _)| (*name, "i32")));
+    for (name, result) in ops {
+        for (i, c) in CONSTANTS.iter().enumerate() {
+            funcs += &format!(
+                r#"(func (export "{name}_{i}") (param i64) (result {result})
+                    (i64.{name} (local.get 0) (i64.const {c})))
+                (func (export "{i}_{name}") (param i64) (result {result})
+                    (i64.{name} (i64.const {c}) (local.get 0)))
+                "#,
+                name = name,
+                i = i,
+                c = c,
+                result = result,
+            );
+        }
+    }
+    for (i, c) in CONSTANTS.iter().enumerate() {
+        funcs += &format!(
+            r#"(func (export "store_{i}") (result i64)
+                (i64.store (i32.const 8) (i64.const {c}))
+                (i64.load (i32.const 8)))
+            "#,
+            i = i,
+            c = c,
+        );
+    }
+    format!("(module (memory 1) {})", funcs)
+}
+
+#[compiler_test(large_immediates)]
+fn large_immediates(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let module = Module::new(&store, large_immediates_wat())?;
+    let instance = Instance::new(&module, &imports! {})?;
+
+    for (name, op) in BINOPS.iter() {
+        for (i, &c) in CONSTANTS.iter().enumerate() {
+            let rhs: NativeFunc<i64, i64> =
+                instance.get_native_function(&format!("{}_{}", name, i))?;
+            let lhs: NativeFunc<i64, i64> =
+                instance.get_native_function(&format!("{}_{}", i, name))?;
+            for &x in &CONSTANTS {
+                assert_eq!(rhs.call(x)?, op(x, c), "{}({:#x}, {:#x})", name, x, c);
+                assert_eq!(lhs.call(x)?, op(c, x), "{}({:#x}, {:#x})", name, c, x);
+            }
+        }
+    }
+
+    for (name, op) in CMPOPS.iter() {
+        for (i, &c) in CONSTANTS.iter().enumerate() {
+            let rhs: NativeFunc<i64, i32> =
+                instance.get_native_function(&format!("{}_{}", name, i))?;
+            let lhs: NativeFunc<i64, i32> =
+                instance.get_native_function(&format!("{}_{}", i, name))?;
+            for &x in &CONSTANTS {
+                assert_eq!(
+                    rhs.call(x)?,
+                    op(x, c) as i32,
+                    "{}({:#x}, {:#x})",
+                    name,
+                    x,
+                    c
+                );
+                assert_eq!(
+                    lhs.call(x)?,
+                    op(c, x) as i32,
+                    "{}({:#x}, {:#x})",
+                    name,
+                    c,
+                    x
+                );
+            }
+        }
+    }
+
+    for (i, &c) in CONSTANTS.iter().enumerate() {
+        let stored: NativeFunc<(), i64> = instance.get_native_function(&format!("store_{}", i))?;
+        assert_eq!(stored.call()?, c);
+    }
+    Ok(())
+}
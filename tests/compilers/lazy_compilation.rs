@@ -0,0 +1,139 @@
+//! Testing the compilation of the functions of a module the first time they are called.
+
+use anyhow::Result;
+use std::sync::Arc;
+use wasmer::*;
+use wasmer_vm::TrapCode;
+
+fn lazy_store(configure: impl FnOnce(&mut Singlepass)) -> Store {
+    let mut compiler = Singlepass::default();
+    configure(&mut compiler);
+    let engine = Universal::new(compiler)
+        .compile_mode(ModuleCompileMode::Lazy)
+        .engine();
+    Store::new(&engine)
+}
+
+static WAT: &str = r#"
+    (type $unary (func (param i32) (result i32)))
+    (table 1 funcref)
+    (elem (i32.const 0) $triple)
+    (func $double (param i32) (result i32)
+        (i32.mul (local.get 0) (i32.const 2)))
+    (func $triple (param i32) (result i32)
+        (i32.mul (local.get 0) (i32.const 3)))
+    (func (export "leaf") (param i32) (result i32)
+        (i32.add (local.get 0) (i32.const 1)))
+    (func (export "quadruple") (param i32) (result i32)
+        (call $double (call $double (local.get 0))))
+    (func (export "indirect") (param i32) (result i32)
+        (call_indirect (type $unary) (local.get 0) (i32.const 0)))
+    (func (export "never_called") (result i32)
+        (i32.const 0))
+    (func $trap (export "trap") (param i32) (result i32)
+        (if (local.get 0) (then unreachable))
+        (local.get 0))
+"#;
+
+#[test]
+fn only_called_functions_are_compiled() -> Result<()> {
+    let store = lazy_store(|_| {});
+    let module = Module::new(&store, WAT)?;
+    assert_eq!(module.compiled_function_count(), 0);
+
+    let instance = Instance::new(&module, &imports! {})?;
+    assert_eq!(module.compiled_function_count(), 0);
+    let leaf = instance.get_native_function::<i32, i32>("leaf")?;
+    assert_eq!(leaf.call(1)?, 2);
+    assert_eq!(module.compiled_function_count(), 1);
+    assert_eq!(leaf.call(2)?, 3);
+    assert_eq!(module.compiled_function_count(), 1);
+
+    // The callees get compiled when their caller first calls them.
+    let quadruple = instance.get_native_function::<i32, i32>("quadruple")?;
+    assert_eq!(quadruple.call(3)?, 12);
+    assert_eq!(module.compiled_function_count(), 3);
+    let indirect = instance.get_native_function::<i32, i32>("indirect")?;
+    assert_eq!(indirect.call(5)?, 15);
+    assert_eq!(module.compiled_function_count(), 5);
+    assert_eq!(indirect.call(6)?, 18);
+    assert_eq!(module.compiled_function_count(), 5);
+    Ok(())
+}
+
+#[test]
+fn instances_share_the_compiled_functions() -> Result<()> {
+    let store = lazy_store(|_| {});
+    let module = Module::new(&store, WAT)?;
+    let first = Instance::new(&module, &imports! {})?;
+    let quadruple = first.get_native_function::<i32, i32>("quadruple")?;
+    assert_eq!(quadruple.call(1)?, 4);
+    assert_eq!(module.compiled_function_count(), 2);
+
+    let second = Instance::new(&module, &imports! {})?;
+    let quadruple = second.get_native_function::<i32, i32>("quadruple")?;
+    assert_eq!(quadruple.call(2)?, 8);
+    assert_eq!(module.compiled_function_count(), 2);
+
+    // Instances racing to call the same functions compile each of them once.
+    let module = Arc::new(module);
+    let threads = (0..4)
+        .map(|i| {
+            let module = module.clone();
+            std::thread::spawn(move || -> Result<i32> {
+                let instance = Instance::new(&module, &imports! {})?;
+                let indirect = instance.get_native_function::<i32, i32>("indirect")?;
+                Ok(indirect.call(i)?)
+            })
+        })
+        .collect::<Vec<_>>();
+    for (i, thread) in threads.into_iter().enumerate() {
+        assert_eq!(thread.join().unwrap()?, 3 * i as i32);
+    }
+    assert_eq!(module.compiled_function_count(), 4);
+    Ok(())
+}
+
+#[test]
+fn traps_are_attributed_to_the_compiled_function() -> Result<()> {
+    let store = lazy_store(|_| {});
+    let module = Module::new(&store, WAT)?;
+    let instance = Instance::new(&module, &imports! {})?;
+    let trap = instance.get_native_function::<i32, i32>("trap")?;
+    assert_eq!(trap.call(0)?, 0);
+
+    let error = trap.call(1).unwrap_err();
+    assert_eq!(error.trace()[0].function_name(), Some("trap"));
+    assert_eq!(error.to_trap(), Some(TrapCode::UnreachableCodeReached));
+    Ok(())
+}
+
+#[test]
+fn compile_errors_are_raised_by_the_call() -> Result<()> {
+    let store = lazy_store(|compiler| {
+        compiler.max_function_code_size(1);
+    });
+    // The module compiles, its functions aren't.
+    let module = Module::new(&store, WAT)?;
+    let instance = Instance::new(&module, &imports! {})?;
+    let leaf = instance.get_native_function::<i32, i32>("leaf")?;
+
+    let error = leaf.call(1).unwrap_err();
+    assert!(
+        error.message().contains("more than the limit of 1"),
+        "unexpected error: {}",
+        error.message()
+    );
+    assert_eq!(module.compiled_function_count(), 0);
+    Ok(())
+}
+
+#[test]
+fn lazily_compiled_executables_cannot_be_serialized() -> Result<()> {
+    let store = lazy_store(|_| {});
+    let engine = store.engine();
+    let tunables = BaseTunables::for_target(engine.target());
+    let executable = engine.compile(&wat2wasm(WAT.as_bytes())?, &tunables)?;
+    assert!(executable.serialize().is_err());
+    Ok(())
+}
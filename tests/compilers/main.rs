@@ -4,18 +4,68 @@
 #[macro_use]
 extern crate compiler_test_derive;
 
+mod async_functions;
+mod bit_counts;
+mod code_memory;
+mod code_size;
+mod compile_errors;
+mod concurrency;
 mod config;
+mod const_fold;
+mod conversions;
+mod deduplication;
 mod deterministic;
+mod epoch_interruption;
+mod exceptions;
+mod exports;
+mod extended_const;
+mod externref;
 mod fast_gas_metering;
+mod features;
+mod float_rounding;
+mod frame_pointers;
+mod globals;
+mod import_object;
 mod imports;
+mod instance_lifetime;
+mod instance_metrics;
+mod instance_pre;
+mod introspection;
 mod issues;
+mod large_immediates;
+mod lazy_compilation;
+mod memory64;
+mod memory_access;
+mod memory_grow;
+mod memory_styles;
+mod metering;
+mod middlewares;
+mod module_cache;
+mod names;
 // mod multi_value_imports;
 mod compilation;
 mod native_functions;
+mod profiling;
+mod reinstantiate;
+mod reserved_registers;
+mod resource_limiter;
+mod select;
 mod serialize;
+mod shared_object;
+mod sign_extension;
+mod snapshots;
 mod stack_limiter;
+mod table;
+mod tail_calls;
+mod targets;
+mod temp_registers;
+mod threads;
+mod timing;
+mod trap_hook;
 mod traps;
+mod wasi;
 mod wast;
+mod wat;
 
 pub use crate::config::{Compiler, Config, Engine};
 pub use crate::wast::run_wast;
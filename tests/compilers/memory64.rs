@@ -0,0 +1,151 @@
+//! Testing the accesses to 64-bit memories, as per the memory64 proposal.
+
+use anyhow::Result;
+use wasmer::*;
+use wasmer_types::entity::EntityRef;
+use wasmer_types::MemoryIndex;
+use wasmer_vm::TrapCode;
+
+fn store_with_memory64(config: &crate::Config) -> Store {
+    let mut config = config.clone();
+    let mut features = Features::default();
+    features.memory64(true);
+    config.set_features(features);
+    config.store()
+}
+
+fn get_instance(store: &Store, limits: &str) -> Result<Instance> {
+    let wat = format!(
+        r#"
+        (memory (export "memory") i64 {})
+        (data (i64.const 16) "\2a")
+        (func (export "load8") (param i64) (result i32)
+            (i32.load8_u (local.get 0)))
+        (func (export "load64") (param i64) (result i64)
+            (i64.load offset=8 (local.get 0)))
+        (func (export "store64") (param i64 i64)
+            (i64.store offset=8 (local.get 0) (local.get 1)))
+        (func (export "load_far") (param i64) (result i32)
+            (i32.load offset=0x80000000 (local.get 0)))
+        (func (export "size") (result i64)
+            (memory.size))
+        (func (export "grow") (param i64) (result i64)
+            (memory.grow (local.get 0)))
+    "#,
+        limits
+    );
+    let module = Module::new(store, &wat)?;
+    Ok(Instance::new(&module, &imports! {})?)
+}
+
+fn oob(guest_addr: u64, access_size: u32) -> Option<OobDetails> {
+    Some(OobDetails {
+        guest_addr,
+        access_size,
+        memory_index: MemoryIndex::new(0),
+    })
+}
+
+#[compiler_test(memory64)]
+fn accesses_use_64_bit_addresses(config: crate::Config) -> Result<()> {
+    let store = store_with_memory64(&config);
+    let instance = get_instance(&store, "1")?;
+    let load8 = instance.get_native_function::<i64, i32>("load8")?;
+    let load64 = instance.get_native_function::<i64, i64>("load64")?;
+    let store64 = instance.get_native_function::<(i64, i64), ()>("store64")?;
+    let load_far = instance.get_native_function::<i64, i32>("load_far")?;
+
+    assert_eq!(load8.call(16)?, 0x2a);
+    store64.call(0x100, 0x0123_4567_89ab_cdef)?;
+    assert_eq!(load64.call(0x100)?, 0x0123_4567_89ab_cdef);
+    assert_eq!(load64.call(0x10000 - 16)?, 0);
+
+    // Truncated to 32 bits, these addresses would be in bounds.
+    for address in [0x1_0000_0000, 0x1_0000_0100, -0x1_0000_0000i64 + 0x100] {
+        let error = load8.call(address).unwrap_err();
+        assert_eq!(error.oob_details(), oob(address as u64, 1));
+        assert_eq!(error.to_trap(), Some(TrapCode::HeapAccessOutOfBounds));
+        let error = store64.call(address, 1).unwrap_err();
+        assert_eq!(error.oob_details(), oob(address as u64 + 8, 8));
+    }
+    assert_eq!(load64.call(0x100)?, 0x0123_4567_89ab_cdef);
+
+    let error = load_far.call(0x100).unwrap_err();
+    assert_eq!(error.oob_details(), oob(0x8000_0100, 4));
+    // The offsets overflow the 64-bit addresses.
+    let error = load64.call(-4).unwrap_err();
+    assert_eq!(error.to_trap(), Some(TrapCode::HeapAccessOutOfBounds));
+    let error = load_far.call(-0x8000_0000).unwrap_err();
+    assert_eq!(error.to_trap(), Some(TrapCode::HeapAccessOutOfBounds));
+    Ok(())
+}
+
+#[compiler_test(memory64)]
+fn size_and_grow_count_64_bit_pages(config: crate::Config) -> Result<()> {
+    let store = store_with_memory64(&config);
+    let instance = get_instance(&store, "1")?;
+    let size = instance.get_native_function::<(), i64>("size")?;
+    let grow = instance.get_native_function::<i64, i64>("grow")?;
+    let load64 = instance.get_native_function::<i64, i64>("load64")?;
+    let memory = instance.lookup_memory("memory")?;
+    assert!(memory.ty().memory64);
+
+    assert_eq!(size.call()?, 1);
+    assert_eq!(grow.call(2)?, 1);
+    assert_eq!(size.call()?, 3);
+    assert_eq!(load64.call(3 * 0x10000 - 16)?, 0);
+    assert!(load64.call(3 * 0x10000 - 15).is_err());
+    // Grows past the pages that can be counted fail rather than wrapping around.
+    assert_eq!(grow.call(1 << 32)?, -1);
+    assert_eq!(grow.call(-1)?, -1);
+    assert_eq!(size.call()?, 3);
+    assert_eq!(memory.size(), Pages(3));
+    Ok(())
+}
+
+#[compiler_test(memory64)]
+fn memory64_needs_its_feature(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let wasm = wat2wasm(br#"(module (memory i64 1))"#)?;
+    assert!(Module::new(&store, &wasm).is_err());
+    Ok(())
+}
+
+#[compiler_test(memory64)]
+fn bulk_memory_on_memory64_is_refused(config: crate::Config) -> Result<()> {
+    let store = store_with_memory64(&config);
+    let wat = r#"
+        (memory i64 1)
+        (func (param i64 i64 i64)
+            (memory.copy (local.get 0) (local.get 1) (local.get 2)))
+    "#;
+    assert!(Module::new(&store, wat).is_err());
+    Ok(())
+}
+
+/// Allocates a memory of more than 4 GiB, without a reservation to grow past it.
+#[compiler_test(memory64)]
+#[cfg_attr(not(feature = "big-memory-tests"), ignore)]
+fn memories_above_4_gib_are_addressed(config: crate::Config) -> Result<()> {
+    let store = store_with_memory64(&config);
+    let instance = get_instance(&store, "0x10001 0x10001")?;
+    let load64 = instance.get_native_function::<i64, i64>("load64")?;
+    let store64 = instance.get_native_function::<(i64, i64), ()>("store64")?;
+    let load_far = instance.get_native_function::<i64, i32>("load_far")?;
+    let size = instance.get_native_function::<(), i64>("size")?;
+    let end: i64 = 0x1_0001_0000;
+
+    assert_eq!(size.call()?, 0x1_0001);
+    store64.call(0x1_0000_0000, 1)?;
+    store64.call(end - 16, 2)?;
+    store64.call(0x100, 3)?;
+    assert_eq!(load64.call(0x1_0000_0000)?, 1);
+    assert_eq!(load64.call(end - 16)?, 2);
+    assert_eq!(load64.call(0x100)?, 3);
+    // An offset past `i32::MAX` isn't sign-extended.
+    assert_eq!(load_far.call(0x8000_0008)?, 1);
+
+    let error = load64.call(end - 15).unwrap_err();
+    assert_eq!(error.oob_details(), oob(end as u64 - 7, 8));
+    Ok(())
+}
@@ -0,0 +1,391 @@
+//! Testing the bounds-checked accesses to memories from the host.
+
+use anyhow::Result;
+use std::sync::atomic::{AtomicBool, Ordering::SeqCst};
+use std::sync::Arc;
+use wasmer::*;
+
+fn get_instance(store: &Store) -> Result<Instance> {
+    let wat = r#"
+        (memory (export "memory") 1)
+        (func (export "write_greeting") (param $at i32) (result i32)
+            (i32.store (local.get $at) (i32.const 0x6c6c6548))
+            (i32.store16 (i32.add (local.get $at) (i32.const 4)) (i32.const 0xa9c3))
+            (i32.const 6))
+        (func (export "grow") (param $pages i32) (result i32)
+            (memory.grow (local.get $pages)))
+    "#;
+    let module = Module::new(&store, &wat)?;
+    Ok(Instance::new(&module, &imports! {})?)
+}
+
+#[compiler_test(memory_access)]
+fn guest_written_strings_are_read(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let instance = get_instance(&store)?;
+    let memory = instance.lookup_memory("memory")?;
+    let write_greeting = instance.get_native_function::<i32, i32>("write_greeting")?;
+
+    let len = write_greeting.call(0x1001)?;
+    let ptr = WasmPtr::<u8, Array>::new(0x1001);
+    assert_eq!(ptr.read_utf8_string(&memory, len as u32)?, "Hellé");
+    assert_eq!(memory.read_u32(0x1001)?, 0x6c6c6548);
+    assert_eq!(WasmPtr::<u32>::new(0x1001).read(&memory)?, 0x6c6c6548);
+
+    // Cutting the last character in half leaves invalid UTF-8.
+    assert_eq!(
+        ptr.read_utf8_string(&memory, len as u32 - 1),
+        Err(MemoryAccessError::NonUtf8String)
+    );
+    Ok(())
+}
+
+#[compiler_test(memory_access)]
+fn out_of_bounds_accesses_fail(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let instance = get_instance(&store)?;
+    let memory = instance.lookup_memory("memory")?;
+    let size = memory.data_size();
+
+    let mut buf = [0; 8];
+    memory.read(size - 8, &mut buf)?;
+    assert_eq!(
+        memory.read(size - 7, &mut buf),
+        Err(MemoryAccessError::HeapOutOfBounds)
+    );
+    assert_eq!(
+        memory.write(size, &[1]),
+        Err(MemoryAccessError::HeapOutOfBounds)
+    );
+    assert_eq!(
+        memory.write_u64(size - 4, 1),
+        Err(MemoryAccessError::HeapOutOfBounds)
+    );
+    // Empty accesses at the very end are in bounds.
+    memory.write(size, &[])?;
+
+    // Nothing is written by a failed access.
+    assert_eq!(memory.read_u32(size - 4)?, 0);
+
+    let ptr = WasmPtr::<u32, Array>::new(size as u32 - 8);
+    let slice = ptr.slice(&memory, 2)?;
+    slice.write_slice(&[1, 2])?;
+    assert_eq!(slice.read_to_vec()?, vec![1, 2]);
+    assert_eq!(slice.read(2), Err(MemoryAccessError::HeapOutOfBounds));
+    assert_eq!(
+        slice.write_slice(&[1, 2, 3]),
+        Err(MemoryAccessError::HeapOutOfBounds)
+    );
+    assert!(matches!(
+        ptr.slice(&memory, 3),
+        Err(MemoryAccessError::HeapOutOfBounds)
+    ));
+    assert_eq!(
+        WasmPtr::<u64>::new(size as u32 - 4).read(&memory),
+        Err(MemoryAccessError::HeapOutOfBounds)
+    );
+    Ok(())
+}
+
+#[compiler_test(memory_access)]
+fn wrapping_accesses_fail(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let instance = get_instance(&store)?;
+    let memory = instance.lookup_memory("memory")?;
+
+    // The end of the access would wrap around to the start of the memory.
+    let mut buf = [0; 2];
+    assert_eq!(
+        memory.read(u64::MAX, &mut buf),
+        Err(MemoryAccessError::Overflow)
+    );
+    assert_eq!(
+        memory.write(u64::MAX, &[0]),
+        Err(MemoryAccessError::Overflow)
+    );
+    assert!(matches!(
+        WasmSlice::<u64>::new(&memory, 8, u64::MAX / 4),
+        Err(MemoryAccessError::Overflow)
+    ));
+
+    // 32-bit offsets don't wrap around either.
+    let ptr = WasmPtr::<u8, Array>::new(u32::MAX - 1);
+    assert!(matches!(
+        ptr.slice(&memory, 4),
+        Err(MemoryAccessError::HeapOutOfBounds)
+    ));
+    assert_eq!(
+        ptr.read_utf8_string(&memory, 4),
+        Err(MemoryAccessError::HeapOutOfBounds)
+    );
+    Ok(())
+}
+
+#[compiler_test(memory_access)]
+fn accesses_see_the_grown_memory(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let instance = get_instance(&store)?;
+    let memory = instance.lookup_memory("memory")?;
+    let grow = instance.get_native_function::<i32, i32>("grow")?;
+
+    let slice = WasmSlice::<u8>::new(&memory, 0x10, 4)?;
+    slice.write_slice(b"wasm")?;
+    let end = memory.data_size();
+    assert_eq!(
+        memory.write_u8(end, 1),
+        Err(MemoryAccessError::HeapOutOfBounds)
+    );
+
+    assert_eq!(grow.call(2)?, 1);
+    // Slices made before the memory grew still refer to the same bytes.
+    assert_eq!(slice.read_to_vec()?, b"wasm");
+    memory.write_u8(end, 1)?;
+    assert_eq!(memory.read_u8(end)?, 1);
+    Ok(())
+}
+
+#[compiler_test(memory_access)]
+fn accesses_are_sound_while_the_guest_grows_the_memory(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let instance = get_instance(&store)?;
+    let memory = instance.lookup_memory("memory")?;
+    let grow = instance.get_native_function::<i32, i32>("grow")?;
+    memory.write(0, b"start")?;
+
+    let done = Arc::new(AtomicBool::new(false));
+    let reader = {
+        let memory = memory.clone();
+        let done = done.clone();
+        std::thread::spawn(move || {
+            let mut buf = [0; 5];
+            while !done.load(SeqCst) {
+                memory.read(0, &mut buf).unwrap();
+                assert_eq!(&buf, b"start");
+                let last = memory.data_size() - 1;
+                memory.read_u8(last).unwrap();
+            }
+        })
+    };
+    for pages in 1..100 {
+        assert_eq!(grow.call(1)?, pages);
+    }
+    done.store(true, SeqCst);
+    reader.join().unwrap();
+    assert_eq!(memory.size(), Pages(100));
+    Ok(())
+}
+
+/// A byte-at-a-time implementation of the bulk copies, to check them against.
+mod reference {
+    use std::ops::Range;
+    use wasmer::{Memory, MemoryAccessError};
+
+    /// The end of the `len` bytes at `offset`, checked to be in `memory`.
+    fn end(memory: &Memory, offset: u64, len: u64) -> Result<u64, MemoryAccessError> {
+        let end = offset.checked_add(len).ok_or(MemoryAccessError::Overflow)?;
+        if end > memory.data_size() {
+            return Err(MemoryAccessError::HeapOutOfBounds);
+        }
+        Ok(end)
+    }
+
+    pub fn copy_to_vec(memory: &Memory, range: Range<u64>) -> Result<Vec<u8>, MemoryAccessError> {
+        if range.end < range.start {
+            return Err(MemoryAccessError::HeapOutOfBounds);
+        }
+        end(memory, range.start, range.end - range.start)?;
+        range.map(|offset| memory.read_u8(offset)).collect()
+    }
+
+    pub fn copy_from_slice(
+        memory: &Memory,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<(), MemoryAccessError> {
+        end(memory, offset, data.len() as u64)?;
+        for (i, &byte) in data.iter().enumerate() {
+            memory.write_u8(offset + i as u64, byte)?;
+        }
+        Ok(())
+    }
+
+    pub fn copy_within(
+        memory: &Memory,
+        src: u64,
+        dst: u64,
+        len: u64,
+    ) -> Result<(), MemoryAccessError> {
+        // Both ranges are checked for overflows before they are checked to be in bounds.
+        if src.checked_add(len).is_none() || dst.checked_add(len).is_none() {
+            return Err(MemoryAccessError::Overflow);
+        }
+        end(memory, src, len)?;
+        end(memory, dst, len)?;
+        let bytes = (0..len)
+            .map(|i| memory.read_u8(src + i))
+            .collect::<Result<Vec<_>, _>>()?;
+        copy_from_slice(memory, dst, &bytes)
+    }
+}
+
+/// A xorshift generator, for reproducible random accesses.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    /// An offset most often near the end of a memory of `size` bytes, sometimes past it.
+    fn offset(&mut self, size: u64) -> u64 {
+        match self.below(8) {
+            0 => self.below(64),
+            1 => u64::MAX - self.below(64),
+            _ => (size + 16).saturating_sub(self.below(128)),
+        }
+    }
+
+    /// A length, zero one time in four.
+    fn len(&mut self) -> u64 {
+        match self.below(4) {
+            0 => 0,
+            _ => self.below(48),
+        }
+    }
+}
+
+/// Runs random bulk copies in `memory` and in `expected`, the same memory copied byte at a
+/// time, checking that they succeed or fail alike and leave the same bytes.
+fn check_bulk_copies(memory: &Memory, expected: &Memory, seed: u64) -> Result<()> {
+    let size = memory.data_size();
+    assert_eq!(size, expected.data_size());
+    let mut rng = Rng(seed);
+    for i in 0..size.min(4096) {
+        let byte = rng.next() as u8;
+        memory.write_u8(size - 1 - i, byte)?;
+        expected.write_u8(size - 1 - i, byte)?;
+    }
+
+    for _ in 0..2000 {
+        match rng.below(3) {
+            0 => {
+                let start = rng.offset(size);
+                let end = start.wrapping_add(rng.len()).wrapping_sub(rng.below(2) * 8);
+                assert_eq!(
+                    memory.copy_to_vec(start..end),
+                    reference::copy_to_vec(expected, start..end),
+                    "copy_to_vec({}..{})",
+                    start,
+                    end
+                );
+            }
+            1 => {
+                let offset = rng.offset(size);
+                let data = (0..rng.len()).map(|_| rng.next() as u8).collect::<Vec<_>>();
+                assert_eq!(
+                    memory.copy_from_slice(offset, &data),
+                    reference::copy_from_slice(expected, offset, &data),
+                    "copy_from_slice({}, {:?})",
+                    offset,
+                    data
+                );
+            }
+            _ => {
+                let src = rng.offset(size);
+                // Often overlapping the source, in either direction.
+                let dst = match rng.below(2) {
+                    0 => src.wrapping_add(rng.below(16)).wrapping_sub(8),
+                    _ => rng.offset(size),
+                };
+                let len = rng.len();
+                assert_eq!(
+                    memory.copy_within(src, dst, len),
+                    reference::copy_within(expected, src, dst, len),
+                    "copy_within({}, {}, {})",
+                    src,
+                    dst,
+                    len
+                );
+            }
+        }
+        let window = size - 256..size;
+        assert_eq!(
+            memory.copy_to_vec(window.clone())?,
+            reference::copy_to_vec(expected, window)?
+        );
+    }
+    assert_eq!(
+        memory.copy_to_vec(0..size)?,
+        reference::copy_to_vec(expected, 0..size)?
+    );
+    Ok(())
+}
+
+#[compiler_test(memory_access)]
+fn bulk_copies_match_byte_copies(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    for seed in 1..=4 {
+        let ty = MemoryType::new(1, None, false);
+        check_bulk_copies(&Memory::new(&store, ty)?, &Memory::new(&store, ty)?, seed)?;
+    }
+    Ok(())
+}
+
+#[compiler_test(memory_access)]
+fn bulk_copies_of_shared_memories_match_byte_copies(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    for seed in 1..=4 {
+        let ty = MemoryType::new(1, Some(1), true);
+        check_bulk_copies(&Memory::new(&store, ty)?, &Memory::new(&store, ty)?, seed)?;
+    }
+    Ok(())
+}
+
+#[compiler_test(memory_access)]
+fn bulk_copies_of_shared_memories_are_sound_while_the_guest_writes(
+    mut config: crate::Config,
+) -> Result<()> {
+    let mut features = Features::default();
+    features.threads(true);
+    config.set_features(features);
+    let store = config.store();
+    let wat = r#"
+        (memory (export "memory") 1 1 shared)
+        (func (export "fill") (param $times i32)
+            (loop $next
+                (memory.fill (i32.const 0) (local.get $times) (i32.const 0x1000))
+                (local.tee $times (i32.sub (local.get $times) (i32.const 1)))
+                (br_if $next)))
+    "#;
+    let module = Module::new(&store, &wat)?;
+    let instance = Instance::new(&module, &imports! {})?;
+    let memory = instance.lookup_memory("memory")?;
+    let fill = instance.get_native_function::<i32, ()>("fill")?;
+
+    let done = Arc::new(AtomicBool::new(false));
+    let copier = {
+        let memory = memory.clone();
+        let done = done.clone();
+        std::thread::spawn(move || {
+            while !done.load(SeqCst) {
+                // Each byte is one the guest wrote, whatever the others are.
+                let bytes = memory.copy_to_vec(0..0x1000).unwrap();
+                assert!(bytes.iter().all(|&byte| byte <= 200), "{:?}", bytes);
+                memory.copy_within(1, 0x2000, 0xfff).unwrap();
+                memory.copy_from_slice(0x3001, &bytes).unwrap();
+            }
+        })
+    };
+    fill.call(200)?;
+    done.store(true, SeqCst);
+    copier.join().unwrap();
+    assert_eq!(memory.copy_to_vec(0..0x1000)?, vec![1; 0x1000]);
+    Ok(())
+}
@@ -0,0 +1,531 @@
+//! Testing the growth of memories from the host, from compiled code and the memory grow callback.
+
+use anyhow::Result;
+use std::sync::{Arc, Barrier, Mutex};
+use std::thread;
+use wasmer::*;
+
+/// The grows seen by a memory grow callback: the instance, the sizes before and after the
+/// grow, and whether the memory could grow.
+type Grows = Arc<Mutex<Vec<(Option<InstanceId>, Pages, Pages, bool)>>>;
+
+/// Make a store whose memory grow callback records the grows, vetoing those past `limit`.
+fn store_with_callback(config: &crate::Config, limit: Pages) -> (Store, Grows) {
+    let grows = Grows::default();
+    let engine = config.engine(config.compiler_config(config.canonicalize_nans));
+    let mut tunables = BaseTunables::for_target(engine.target());
+    let seen = grows.clone();
+    tunables.memory_grow_callback = Some(Arc::new(move |grow: &MemoryGrow| {
+        seen.lock()
+            .unwrap()
+            .push((grow.instance, grow.previous, grow.new, grow.result.is_ok()));
+        if grow.new > limit {
+            return Err(MemoryError::Generic("over the limit".to_string()));
+        }
+        Ok(())
+    }));
+    (Store::new_with_tunables(&*engine, tunables), grows)
+}
+
+fn get_instance(store: &Store) -> Result<Instance> {
+    let wat = r#"
+        (memory (export "memory") 1 4)
+        (func (export "grow") (param $pages i32) (result i32)
+            (memory.grow (local.get $pages)))
+    "#;
+    let module = Module::new(&store, &wat)?;
+    Ok(Instance::new(&module, &imports! {})?)
+}
+
+#[compiler_test(memory_grow)]
+fn host_grows_are_clamped_to_the_maximum(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let instance = get_instance(&store)?;
+    let memory = instance.lookup_memory("memory")?;
+    let grow = instance.get_native_function::<i32, i32>("grow")?;
+
+    assert_eq!(memory.grow(Pages(2))?, Pages(1));
+    assert_eq!(grow.call(0)?, 3);
+    assert_eq!(
+        memory.grow(Pages(2)),
+        Err(MemoryError::CouldNotGrow {
+            current: Pages(3),
+            attempted_delta: Pages(2),
+        })
+    );
+    assert_eq!(memory.grow(Pages(1))?, Pages(3));
+    assert!(memory.grow(Pages(1)).is_err());
+    assert_eq!(grow.call(1)?, -1);
+    assert_eq!(memory.size(), Pages(4));
+
+    let memory = Memory::new(&store, MemoryType::new(1, None, false))?;
+    assert!(memory.grow(Pages(u32::MAX)).is_err());
+    assert_eq!(memory.size(), Pages(1));
+    Ok(())
+}
+
+#[compiler_test(memory_grow)]
+fn the_callback_can_veto_grows(config: crate::Config) -> Result<()> {
+    let (store, grows) = store_with_callback(&config, Pages(2));
+    let instance = get_instance(&store)?;
+    let memory = instance.lookup_memory("memory")?;
+    let grow = instance.get_native_function::<i32, i32>("grow")?;
+    let id = Some(instance.id());
+
+    assert_eq!(grow.call(1)?, 1);
+    assert_eq!(grow.call(1)?, -1);
+    assert_eq!(
+        memory.grow(Pages(1)),
+        Err(MemoryError::Generic("over the limit".to_string()))
+    );
+    // The callback is still told about grows past the maximum, which fail anyway.
+    assert!(matches!(
+        memory.grow(Pages(3)),
+        Err(MemoryError::CouldNotGrow { .. })
+    ));
+    assert_eq!(memory.size(), Pages(2));
+    assert_eq!(
+        *grows.lock().unwrap(),
+        vec![
+            (id, Pages(1), Pages(2), true),
+            (id, Pages(2), Pages(3), true),
+            (id, Pages(2), Pages(3), true),
+            (id, Pages(2), Pages(5), false),
+        ]
+    );
+
+    // Memories created by the host belong to no instance.
+    let memory = Memory::new(&store, MemoryType::new(1, None, false))?;
+    assert_eq!(memory.grow(Pages(1))?, Pages(1));
+    assert_eq!(
+        grows.lock().unwrap().last(),
+        Some(&(None, Pages(1), Pages(2), true))
+    );
+    Ok(())
+}
+
+#[compiler_test(memory_grow)]
+fn the_callback_sees_concurrent_grows_in_order(mut config: crate::Config) -> Result<()> {
+    let mut features = Features::default();
+    features.threads(true);
+    config.set_features(features);
+    let (store, grows) = store_with_callback(&config, Pages(100));
+    let module = Module::new(
+        &store,
+        r#"
+            (import "env" "memory" (memory 1 200 shared))
+            (func (export "grow") (param $pages i32) (result i32)
+                (memory.grow (local.get $pages)))
+        "#,
+    )?;
+    let memory = Memory::new(&store, MemoryType::new(1, Some(200), true))?;
+    let imports = imports! {
+        "env" => {
+            "memory" => memory.clone(),
+        },
+    };
+    let instances = [
+        Instance::new(&module, &imports)?,
+        Instance::new(&module, &imports)?,
+    ];
+    let ids = [Some(instances[0].id()), Some(instances[1].id())];
+    assert_ne!(ids[0], ids[1]);
+
+    let threads = instances
+        .iter()
+        .cloned()
+        .map(|instance| {
+            thread::spawn(move || -> Result<usize> {
+                let grow = instance.get_native_function::<i32, i32>("grow")?;
+                let mut grown = 0;
+                while grow.call(1)? != -1 {
+                    grown += 1;
+                }
+                Ok(grown)
+            })
+        })
+        .collect::<Vec<_>>();
+    let grown = threads
+        .into_iter()
+        .map(|thread| thread.join().unwrap())
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(grown.iter().sum::<usize>(), 99);
+    assert_eq!(memory.size(), Pages(100));
+
+    // Each grow saw the size the previous one left, whichever instance made it, and both
+    // instances got vetoed at the limit.
+    let grows = grows.lock().unwrap();
+    for (size, grow) in (1..100).zip(grows.iter()) {
+        assert!(ids.contains(&grow.0));
+        assert_eq!(
+            (grow.1, grow.2, grow.3),
+            (Pages(size), Pages(size + 1), true)
+        );
+    }
+    assert_eq!(grows.len(), 101);
+    for grow in &grows[99..] {
+        assert_eq!((grow.1, grow.2, grow.3), (Pages(100), Pages(101), true));
+    }
+    assert_ne!(grows[99].0, grows[100].0);
+    Ok(())
+}
+
+/// A store whose memories are all dynamic, so that they move when they grow past their
+/// allocation.
+fn store_with_dynamic_memories(config: &crate::Config) -> Store {
+    let engine = config.engine(config.compiler_config(config.canonicalize_nans));
+    let mut tunables = BaseTunables::for_target(engine.target());
+    let style = tunables.dynamic_memory_style();
+    tunables.memory_style_override = Some(Arc::new(move |_: &MemoryType| Some(style.clone())));
+    Store::new_with_tunables(&*engine, tunables)
+}
+
+/// Grow a memory of 1 page with deltas of 1 to 7 pages up to its maximum of 100, checking
+/// after each grow that the new pages are zeroed and writable, that the previous contents
+/// are kept and that the accesses past the new end trap.
+fn check_grows_up_to_the_maximum(store: &Store) -> Result<()> {
+    let wat = r#"
+        (memory (export "memory") 1 100)
+        (func (export "grow") (param $pages i32) (result i32)
+            (memory.grow (local.get $pages)))
+        (func (export "store") (param $address i32) (param $value i32)
+            (i32.store8 (local.get $address) (local.get $value)))
+        (func (export "load") (param $address i32) (result i32)
+            (i32.load8_u (local.get $address)))
+    "#;
+    let instance = Instance::new(&Module::new(&store, wat)?, &imports! {})?;
+    let grow = instance.get_native_function::<i32, i32>("grow")?;
+    let store8 = instance.get_native_function::<(i32, i32), ()>("store")?;
+    let load8 = instance.get_native_function::<i32, i32>("load")?;
+    let page = 0x1_0000;
+
+    store8.call(0, 42)?;
+    let mut size = 1;
+    for delta in (1..=7).cycle() {
+        if size + delta > 100 {
+            break;
+        }
+        assert_eq!(grow.call(delta)?, size);
+        let end = (size + delta) * page;
+        assert_eq!(load8.call(end - 1)?, 0, "end of {} pages", size + delta);
+        store8.call(end - 1, size)?;
+        assert!(load8.call(end).is_err(), "past {} pages", size + delta);
+        size += delta;
+    }
+    assert_eq!(size, 100);
+    assert_eq!(grow.call(1)?, -1);
+    assert_eq!(grow.call(0)?, 100);
+    assert_eq!(instance.lookup_memory("memory")?.size(), Pages(100));
+    assert!(load8.call(100 * page).is_err());
+
+    // Every page the loop grew into kept what was written at its end.
+    assert_eq!(load8.call(0)?, 42);
+    let mut size = 1;
+    for delta in (1..=7).cycle() {
+        if size + delta > 100 {
+            break;
+        }
+        assert_eq!(load8.call((size + delta) * page - 1)?, size);
+        size += delta;
+    }
+    Ok(())
+}
+
+#[compiler_test(memory_grow)]
+fn grows_of_static_memories_go_past_their_reservations(config: crate::Config) -> Result<()> {
+    check_grows_up_to_the_maximum(&config.store())
+}
+
+#[compiler_test(memory_grow)]
+fn grows_of_dynamic_memories_go_past_their_reservations(config: crate::Config) -> Result<()> {
+    check_grows_up_to_the_maximum(&store_with_dynamic_memories(&config))
+}
+
+/// Access the memory before and after grows in the same basic block, so that the accesses
+/// after each grow can't rely on the base and length loaded for those before.
+fn check_accesses_around_grows(store: &Store) -> Result<()> {
+    let wat = r#"
+        (memory 1 100)
+        (func (export "grow_between") (param $pages i32) (result i32)
+            (local $end i32)
+            (i32.store8 (i32.const 0) (i32.const 1))
+            (local.set $end (i32.mul
+                (i32.add (memory.grow (local.get $pages)) (local.get $pages))
+                (i32.const 0x10000)))
+            (i32.store8 (i32.sub (local.get $end) (i32.const 1)) (i32.const 2))
+            (i32.add
+                (i32.load8_u (i32.const 0))
+                (i32.load8_u (i32.sub (local.get $end) (i32.const 1)))))
+        (func (export "load_past") (param $pages i32) (result i32)
+            (drop (i32.load8_u (i32.const 0)))
+            (drop (memory.grow (local.get $pages)))
+            (i32.load8_u (i32.mul (memory.size) (i32.const 0x10000))))
+    "#;
+    let instance = Instance::new(&Module::new(&store, wat)?, &imports! {})?;
+    let grow_between = instance.get_native_function::<i32, i32>("grow_between")?;
+    let load_past = instance.get_native_function::<i32, i32>("load_past")?;
+    for pages in 1..=7 {
+        assert_eq!(grow_between.call(pages)?, 3, "grow of {} pages", pages);
+        assert!(load_past.call(pages).is_err(), "grow of {} pages", pages);
+    }
+    Ok(())
+}
+
+#[compiler_test(memory_grow)]
+fn accesses_around_grows_of_static_memories(config: crate::Config) -> Result<()> {
+    check_accesses_around_grows(&config.store())
+}
+
+#[compiler_test(memory_grow)]
+fn accesses_around_grows_of_dynamic_memories(config: crate::Config) -> Result<()> {
+    check_accesses_around_grows(&store_with_dynamic_memories(&config))
+}
+
+/// Access an imported memory before and after the host grows it, in the same basic block, so
+/// that the accesses after the call can't rely on the base and length loaded before it.
+fn check_accesses_around_host_grows(store: &Store) -> Result<()> {
+    #[derive(Clone)]
+    struct Env(Memory);
+    impl WasmerEnv for Env {}
+
+    let wat = r#"
+        (import "env" "memory" (memory 1 200))
+        (import "env" "grow" (func $grow (param i32) (result i32)))
+        (func (export "grow_between") (param $pages i32) (result i32)
+            (local $end i32)
+            (i32.store8 (i32.const 0) (i32.const 1))
+            (local.set $end (i32.mul
+                (i32.add (call $grow (local.get $pages)) (local.get $pages))
+                (i32.const 0x10000)))
+            (i32.store8 (i32.sub (local.get $end) (i32.const 1)) (i32.const 2))
+            (i32.add
+                (i32.load8_u (i32.const 0))
+                (i32.load8_u (i32.sub (local.get $end) (i32.const 1)))))
+        (func (export "load_past") (param $pages i32) (result i32)
+            (drop (i32.load8_u (i32.const 0)))
+            (drop (call $grow (local.get $pages)))
+            (i32.load8_u (i32.mul (memory.size) (i32.const 0x10000))))
+    "#;
+    let memory = Memory::new(store, MemoryType::new(1, Some(200), false))?;
+    let grow = Function::new_native_with_env(store, Env(memory.clone()), |env: &Env, pages| {
+        env.0.grow(Pages(pages)).unwrap().0
+    });
+    let imports = imports! {
+        "env" => {
+            "memory" => memory.clone(),
+            "grow" => grow,
+        },
+    };
+    let instance = Instance::new(&Module::new(store, wat)?, &imports)?;
+    let grow_between = instance.get_native_function::<u32, i32>("grow_between")?;
+    let load_past = instance.get_native_function::<u32, i32>("load_past")?;
+    for pages in [1, 2, 3, 20, 30] {
+        assert_eq!(grow_between.call(pages)?, 3, "grow of {} pages", pages);
+        assert!(load_past.call(pages).is_err(), "grow of {} pages", pages);
+    }
+    assert_eq!(memory.size(), Pages(1 + 2 * (1 + 2 + 3 + 20 + 30)));
+    Ok(())
+}
+
+#[compiler_test(memory_grow)]
+fn accesses_around_host_grows_of_static_memories(config: crate::Config) -> Result<()> {
+    check_accesses_around_host_grows(&config.store())
+}
+
+#[compiler_test(memory_grow)]
+fn accesses_around_host_grows_of_dynamic_memories(config: crate::Config) -> Result<()> {
+    check_accesses_around_host_grows(&store_with_dynamic_memories(&config))
+}
+
+#[compiler_test(memory_grow)]
+fn inline_grows_stop_at_the_maximum(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let module = Module::new(
+        &store,
+        r#"
+            (memory (export "memory") 1 3)
+            (func (export "grow") (param $pages i32) (result i32)
+                (memory.grow (local.get $pages)))
+        "#,
+    )?;
+    let instance = Instance::new(&module, &imports! {})?;
+    let grow = instance.get_native_function::<i32, i32>("grow")?;
+    assert_eq!(grow.call(1)?, 1);
+    assert_eq!(grow.call(2)?, -1);
+    assert_eq!(grow.call(1)?, 2);
+    assert_eq!(grow.call(1)?, -1);
+    let memory = instance.lookup_memory("memory")?;
+    assert_eq!(memory.size(), Pages(3));
+    assert!(memory.grow(Pages(1)).is_err());
+    Ok(())
+}
+
+#[compiler_test(memory_grow)]
+fn concurrent_grows_of_a_shared_memory_each_get_their_pages(
+    mut config: crate::Config,
+) -> Result<()> {
+    let mut features = Features::default();
+    features.threads(true);
+    config.set_features(features);
+    let store = config.store();
+    let module = Module::new(
+        &store,
+        r#"
+            (import "env" "memory" (memory 1 200 shared))
+            (func (export "grow") (param $pages i32) (result i32)
+                (memory.grow (local.get $pages)))
+        "#,
+    )?;
+    let memory = Memory::new(&store, MemoryType::new(1, Some(200), true))?;
+    let imports = imports! {
+        "env" => {
+            "memory" => memory.clone(),
+        },
+    };
+    let threads = (0..4)
+        .map(|_| -> Result<_> {
+            let instance = Instance::new(&module, &imports)?;
+            Ok(thread::spawn(move || -> Result<Vec<i32>> {
+                let grow = instance.get_native_function::<i32, i32>("grow")?;
+                let mut previous_sizes = Vec::new();
+                loop {
+                    match grow.call(1)? {
+                        -1 => return Ok(previous_sizes),
+                        previous => previous_sizes.push(previous),
+                    }
+                }
+            }))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut previous_sizes = Vec::new();
+    for thread in threads {
+        previous_sizes.extend(thread.join().unwrap()?);
+    }
+
+    // No two grows saw the same size, whether they were done inline or by the runtime.
+    previous_sizes.sort_unstable();
+    assert_eq!(previous_sizes, (1..200).collect::<Vec<_>>());
+    assert_eq!(memory.size(), Pages(200));
+    Ok(())
+}
+
+#[compiler_test(memory_grow)]
+fn two_threads_grow_a_shared_memory_past_its_reservations(mut config: crate::Config) -> Result<()> {
+    let mut features = Features::default();
+    features.threads(true);
+    config.set_features(features);
+    let store = config.store();
+    let module = Module::new(
+        &store,
+        r#"
+            (import "env" "memory" (memory 1 200 shared))
+            ;; Grow by a page and mark its last byte, returning the previous size.
+            (func (export "grow_and_mark") (param $mark i32) (result i32)
+                (local $previous i32)
+                (local.set $previous (memory.grow (i32.const 1)))
+                (if (i32.ne (local.get $previous) (i32.const -1))
+                    (then (i32.store8
+                        (i32.sub
+                            (i32.mul (i32.add (local.get $previous) (i32.const 1)) (i32.const 0x10000))
+                            (i32.const 1))
+                        (local.get $mark))))
+                (local.get $previous))
+        "#,
+    )?;
+    let memory = Memory::new(&store, MemoryType::new(1, Some(200), true))?;
+    let imports = imports! {
+        "env" => {
+            "memory" => memory.clone(),
+        },
+    };
+    let barrier = Arc::new(Barrier::new(2));
+    let threads = [1, 2]
+        .iter()
+        .map(|&mark| -> Result<_> {
+            let instance = Instance::new(&module, &imports)?;
+            let barrier = barrier.clone();
+            Ok(thread::spawn(move || -> Result<Vec<(i32, u8)>> {
+                let grow_and_mark = instance.get_native_function::<i32, i32>("grow_and_mark")?;
+                let mut grows = Vec::new();
+                barrier.wait();
+                loop {
+                    match grow_and_mark.call(mark)? {
+                        -1 => return Ok(grows),
+                        previous => grows.push((previous, mark as u8)),
+                    }
+                }
+            }))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut grows = Vec::new();
+    for thread in threads {
+        grows.extend(thread.join().unwrap()?);
+    }
+
+    // The 199 grows went through the reservations of 17, 34, 68 and 136 pages, and each
+    // page was grown by a single thread, which could write to it right away.
+    grows.sort_unstable();
+    let previous_sizes = grows
+        .iter()
+        .map(|&(previous, _)| previous)
+        .collect::<Vec<_>>();
+    assert_eq!(previous_sizes, (1..200).collect::<Vec<_>>());
+    assert_eq!(memory.size(), Pages(200));
+    let view = memory.view::<u8>();
+    for (previous, mark) in grows {
+        let end = (previous as usize + 1) * 0x10000;
+        assert_eq!(view[end - 1].get(), mark, "page {}", previous);
+    }
+    Ok(())
+}
+
+#[compiler_test(memory_grow)]
+fn grows_past_the_reservation_move_dynamic_memories(config: crate::Config) -> Result<()> {
+    let store = store_with_dynamic_memories(&config);
+    let wat = r#"
+        (memory (export "memory") 1)
+        (func (export "grow") (param $pages i32) (result i32)
+            (memory.grow (local.get $pages)))
+        (func (export "store") (param $address i32) (param $value i32)
+            (i32.store8 (local.get $address) (local.get $value)))
+        (func (export "load") (param $address i32) (result i32)
+            (i32.load8_u (local.get $address)))
+    "#;
+    let instance = Instance::new(&Module::new(&store, wat)?, &imports! {})?;
+    let memory = instance.lookup_memory("memory")?;
+    let grow = instance.get_native_function::<i32, i32>("grow")?;
+    let store8 = instance.get_native_function::<(i32, i32), ()>("store")?;
+    let load8 = instance.get_native_function::<i32, i32>("load")?;
+    let page = 0x1_0000;
+
+    // A memory of 1 page reserves 16 more, which it grows into without moving.
+    let base = memory.data_ptr();
+    store8.call(0, 1)?;
+    assert_eq!(grow.call(10)?, 1);
+    assert_eq!(grow.call(6)?, 11);
+    assert_eq!(memory.data_ptr(), base);
+    store8.call(17 * page - 1, 2)?;
+    assert!(load8.call(17 * page).is_err());
+
+    // The next page is past the reservation, so the memory moves and reserves as much again
+    // as its new size.
+    assert_eq!(grow.call(1)?, 17);
+    assert_ne!(memory.data_ptr(), base);
+    assert_eq!(load8.call(0)?, 1);
+    assert_eq!(load8.call(17 * page - 1)?, 2);
+    assert_eq!(load8.call(18 * page - 1)?, 0);
+    assert!(load8.call(18 * page).is_err());
+
+    let base = memory.data_ptr();
+    assert_eq!(grow.call(18)?, 18);
+    assert_eq!(memory.data_ptr(), base);
+    assert!(load8.call(36 * page).is_err());
+    // A grow past the reservation at once also moves the memory.
+    assert_eq!(grow.call(37)?, 36);
+    assert_ne!(memory.data_ptr(), base);
+    assert_eq!(load8.call(17 * page - 1)?, 2);
+    assert_eq!(load8.call(73 * page - 1)?, 0);
+    assert!(load8.call(73 * page).is_err());
+    assert_eq!(memory.size(), Pages(73));
+    Ok(())
+}
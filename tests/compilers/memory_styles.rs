@@ -0,0 +1,103 @@
+//! Testing the modules running with static and dynamic memories.
+
+use anyhow::Result;
+use std::sync::Arc;
+use wasmer::vm::MemoryStyle;
+use wasmer::*;
+
+/// Make a store whose memories are all static, or all dynamic.
+fn get_store(config: &crate::Config, dynamic: bool) -> Store {
+    let engine = config.engine(config.compiler_config(config.canonicalize_nans));
+    let mut tunables = BaseTunables::for_target(engine.target());
+    let style = if dynamic {
+        tunables.dynamic_memory_style()
+    } else {
+        tunables.static_memory_style()
+    };
+    tunables.memory_style_override = Some(Arc::new(move |_: &MemoryType| Some(style.clone())));
+    Store::new_with_tunables(&*engine, tunables)
+}
+
+fn get_instance(store: &Store) -> Result<Instance> {
+    let wat = r#"
+        (memory (export "memory") 1)
+        (func (export "store") (param $address i32) (param $value i64)
+            (i64.store (local.get $address) (local.get $value)))
+        (func (export "load") (param $address i32) (result i64)
+            (i64.load (local.get $address)))
+        ;; Store at $address, grow by $pages, store right below the new end of the memory,
+        ;; and add up what both addresses hold after the grow.
+        (func (export "grow_and_reload") (param $address i32) (param $pages i32) (result i64)
+            (local $end i32)
+            (i64.store (local.get $address) (i64.const 40))
+            (drop (memory.grow (local.get $pages)))
+            (local.set $end (i32.mul (memory.size) (i32.const 65536)))
+            (i64.store (i32.sub (local.get $end) (i32.const 8)) (i64.const 2))
+            (i64.add
+                (i64.load (local.get $address))
+                (i64.load (i32.sub (local.get $end) (i32.const 8)))))
+    "#;
+    let module = Module::new(&store, &wat)?;
+    Ok(Instance::new(&module, &imports! {})?)
+}
+
+#[compiler_test(memory_styles)]
+fn same_module_under_both_styles(config: crate::Config) -> Result<()> {
+    for dynamic in [false, true] {
+        let store = get_store(&config, dynamic);
+        let style = store
+            .tunables()
+            .memory_style(&MemoryType::new(1, None, false));
+        assert_eq!(matches!(style, MemoryStyle::Dynamic { .. }), dynamic);
+
+        let instance = get_instance(&store)?;
+        let store_i64 = instance.get_native_function::<(i32, i64), ()>("store")?;
+        let load_i64 = instance.get_native_function::<i32, i64>("load")?;
+
+        store_i64.call(16, 0x0102_0304_0506_0708)?;
+        assert_eq!(load_i64.call(16)?, 0x0102_0304_0506_0708);
+        assert_eq!(load_i64.call(20)?, 0x0102_0304);
+        store_i64.call(65536 - 8, -1)?;
+        assert_eq!(load_i64.call(65536 - 8)?, -1);
+
+        // The accesses crossing the end of the memory trap, whatever its style.
+        assert!(load_i64.call(65536 - 7).is_err());
+        assert!(store_i64.call(65536, 0).is_err());
+        assert!(load_i64.call(-1).is_err());
+    }
+    Ok(())
+}
+
+#[compiler_test(memory_styles)]
+fn dynamic_memories_grow_past_their_allocation(config: crate::Config) -> Result<()> {
+    let store = get_store(&config, true);
+    let instance = get_instance(&store)?;
+    let memory = instance.lookup_memory("memory")?;
+    let grow_and_reload = instance.get_native_function::<(i32, i32), i64>("grow_and_reload")?;
+    let load_i64 = instance.get_native_function::<i32, i64>("load")?;
+
+    memory.write(100, &[7; 8])?;
+    let base = memory.data_ptr();
+    // The memory only reserves a few pages past its first one, so it moves as it grows past
+    // them.
+    assert_eq!(grow_and_reload.call(64, 40)?, 42);
+    assert_eq!(memory.size(), Pages(41));
+    assert_ne!(memory.data_ptr(), base);
+
+    // The memory was copied, and the pointers into it are checked against its new size.
+    let mut bytes = [0; 8];
+    memory.read(100, &mut bytes)?;
+    assert_eq!(bytes, [7; 8]);
+    assert_eq!(load_i64.call(64)?, 40);
+    assert_eq!(load_i64.call(41 * 65536 - 8)?, 2);
+    assert!(load_i64.call(41 * 65536 - 7).is_err());
+
+    // The contents survive another move, past twice the size of the memory.
+    let base = memory.data_ptr();
+    assert_eq!(grow_and_reload.call(41 * 65536 - 8, 50)?, 42);
+    assert_ne!(memory.data_ptr(), base);
+    assert_eq!(load_i64.call(91 * 65536 - 8)?, 2);
+    memory.read(100, &mut bytes)?;
+    assert_eq!(bytes, [7; 8]);
+    Ok(())
+}
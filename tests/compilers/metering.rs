@@ -0,0 +1,111 @@
+//! Testing the fuel metering of singlepass.
+
+use anyhow::Result;
+use wasmer::wasmparser::Operator;
+use wasmer::*;
+use wasmer_compiler_singlepass::Singlepass;
+use wasmer_engine_universal::Universal;
+use wasmer_vm::TrapCode;
+
+fn one_per_operator(_: &Operator) -> u64 {
+    1
+}
+
+fn get_store() -> Store {
+    let mut compiler = Singlepass::default();
+    compiler.enable_metering(one_per_operator);
+    Store::new(&Universal::new(compiler).engine())
+}
+
+#[derive(Clone, Default)]
+struct FuelEnv {
+    fuel: LazyInit<Fuel>,
+}
+
+impl WasmerEnv for FuelEnv {
+    fn init_with_instance(&mut self, instance: &Instance) -> Result<(), HostEnvInitError> {
+        self.fuel.initialize(instance.fuel());
+        Ok(())
+    }
+}
+
+fn consume(env: &FuelEnv, amount: i64) -> Result<(), RuntimeError> {
+    env.fuel.get_ref().unwrap().consume(amount as u64)
+}
+
+fn get_instance(store: &Store) -> Result<Instance> {
+    let wat = r#"
+        (import "host" "consume" (func $consume (param i64)))
+        (func (export "count") (param $n i32) (result i32)
+            (local $i i32)
+            (block $done
+                (loop $next
+                    (br_if $done (i32.eq (local.get $i) (local.get $n)))
+                    (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                    (br $next)))
+            (local.get $i))
+        (func (export "pay") (param i64)
+            (call $consume (local.get 0)))
+    "#;
+    let module = Module::new(&store, &wat)?;
+    let imports = imports! {
+        "host" => {
+            "consume" => Function::new_native_with_env(&store, FuelEnv::default(), consume),
+        },
+    };
+    Ok(Instance::new(&module, &imports)?)
+}
+
+#[test]
+fn loops_consume_the_fuel_of_the_iterations_that_ran() -> Result<()> {
+    let store = get_store();
+    let instance = get_instance(&store)?;
+    let count = instance.get_native_function::<i32, i32>("count")?;
+    assert_eq!(instance.fuel_remaining(), u64::MAX);
+
+    // Entering the block and the loop costs 2, each iteration 9, the last check 4 and the
+    // return 2.
+    instance.set_fuel(1000);
+    assert_eq!(count.call(10)?, 10);
+    assert_eq!(instance.fuel_remaining(), 1000 - (9 * 10 + 8));
+    instance.set_fuel(1000);
+    assert_eq!(count.call(0)?, 0);
+    assert_eq!(instance.fuel_remaining(), 1000 - 8);
+
+    // The sixth iteration can't check its condition with 3 fuel left.
+    instance.set_fuel(50);
+    let err = count.call(10).unwrap_err();
+    assert_eq!(err.message(), "out of fuel");
+    assert_eq!(err.to_trap(), Some(TrapCode::OutOfFuel));
+    assert_eq!(instance.fuel_remaining(), 3);
+
+    // The instance runs again once refueled.
+    instance.set_fuel(1000);
+    assert_eq!(count.call(10)?, 10);
+    assert_eq!(instance.fuel_remaining(), 1000 - (9 * 10 + 8));
+    Ok(())
+}
+
+#[test]
+fn host_functions_consume_fuel() -> Result<()> {
+    let store = get_store();
+    let instance = get_instance(&store)?;
+    let pay = instance.get_native_function::<i64, ()>("pay")?;
+    let fuel = instance.fuel();
+
+    // The call costs 2 before the host function runs and its return 1 after.
+    instance.set_fuel(100);
+    pay.call(40)?;
+    assert_eq!(fuel.remaining(), 100 - 2 - 40 - 1);
+    let err = pay.call(60).unwrap_err();
+    assert_eq!(err.to_trap(), Some(TrapCode::OutOfFuel));
+    assert_eq!(fuel.remaining(), 57 - 2);
+
+    fuel.set(100);
+    assert_eq!(instance.fuel_remaining(), 100);
+    pay.call(60)?;
+    assert_eq!(instance.fuel_remaining(), 100 - 2 - 60 - 1);
+    drop((pay, instance));
+    assert_eq!(fuel.remaining(), 0);
+    Ok(())
+}
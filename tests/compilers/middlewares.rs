@@ -0,0 +1,257 @@
+//! Testing the middlewares transforming the modules compiled by singlepass.
+
+use anyhow::Result;
+use std::sync::Arc;
+use wasmer::wasmparser::Operator;
+use wasmer::*;
+use wasmer_compiler_singlepass::Singlepass;
+use wasmer_engine_universal::Universal;
+use wasmer_middlewares::OpcodeHistogram;
+
+fn get_store(middlewares: Vec<Arc<dyn ModuleMiddleware>>) -> Store {
+    let mut compiler = Singlepass::default();
+    for middleware in middlewares {
+        compiler.push_middleware(middleware);
+    }
+    Store::new(&Universal::new(compiler).engine())
+}
+
+/// A module middleware generating the same function middleware for every function.
+#[derive(Debug)]
+struct Generate(fn() -> Box<dyn FunctionMiddleware>);
+
+impl ModuleMiddleware for Generate {
+    fn generate_function_middleware(&self, _: LocalFunctionIndex) -> Box<dyn FunctionMiddleware> {
+        (self.0)()
+    }
+}
+
+/// Holds each constant back until the next operator, swapping them.
+#[derive(Debug, Default)]
+struct SwapConstants {
+    held: Option<i32>,
+}
+
+impl FunctionMiddleware for SwapConstants {
+    fn feed<'a>(
+        &mut self,
+        operator: Operator<'a>,
+        state: &mut MiddlewareReaderState<'a>,
+    ) -> Result<(), MiddlewareError> {
+        match operator {
+            Operator::I32Const { value } if self.held.is_none() => self.held = Some(value),
+            operator => {
+                state.push_operator(operator);
+                if let Some(value) = self.held.take() {
+                    state.push_operator(Operator::I32Const { value });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Multiplies the first constant of the function by 10.
+#[derive(Debug, Default)]
+struct ScaleFirstConstant {
+    done: bool,
+}
+
+impl FunctionMiddleware for ScaleFirstConstant {
+    fn feed<'a>(
+        &mut self,
+        operator: Operator<'a>,
+        state: &mut MiddlewareReaderState<'a>,
+    ) -> Result<(), MiddlewareError> {
+        match operator {
+            Operator::I32Const { value } if !self.done => {
+                self.done = true;
+                state.push_operator(Operator::I32Const { value: value * 10 });
+            }
+            operator => state.push_operator(operator),
+        }
+        Ok(())
+    }
+}
+
+/// Drops the `drop`s, leaving their operands on the stack.
+#[derive(Debug)]
+struct DropDrops;
+
+impl FunctionMiddleware for DropDrops {
+    fn feed<'a>(
+        &mut self,
+        operator: Operator<'a>,
+        state: &mut MiddlewareReaderState<'a>,
+    ) -> Result<(), MiddlewareError> {
+        if !matches!(operator, Operator::Drop) {
+            state.push_operator(operator);
+        }
+        Ok(())
+    }
+}
+
+fn swap_constants() -> Box<dyn FunctionMiddleware> {
+    Box::new(SwapConstants::default())
+}
+
+fn scale_first_constant() -> Box<dyn FunctionMiddleware> {
+    Box::new(ScaleFirstConstant::default())
+}
+
+fn drop_drops() -> Box<dyn FunctionMiddleware> {
+    Box::new(DropDrops)
+}
+
+fn classify(operator: &Operator) -> Option<usize> {
+    match operator {
+        Operator::Call { .. } => Some(0),
+        Operator::I32Load { .. } => Some(1),
+        Operator::I32Add => Some(2),
+        _ => None,
+    }
+}
+
+#[test]
+fn opcode_histogram_exports_the_counts() -> Result<()> {
+    let histogram = Arc::new(OpcodeHistogram::new(&["calls", "loads", "adds"], classify));
+    let store = get_store(vec![histogram.clone()]);
+    let wat = r#"
+        (import "host" "offset" (global $offset i32))
+        (memory 1)
+        (global $sum (export "sum") (mut i32) (i32.const 7))
+        (func $load (param $address i32) (result i32)
+            (i32.load (i32.add (local.get $address) (global.get $offset))))
+        (func (export "run") (param $n i32)
+            (local $i i32)
+            (loop $loop
+                (global.set $sum (i32.add (global.get $sum) (call $load (i32.const 0))))
+                (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                (br_if $loop (i32.lt_u (local.get $i) (local.get $n)))))
+    "#;
+    let module = Module::new(&store, wat)?;
+    let imports = imports! {
+        "host" => {
+            "offset" => Global::new(&store, Value::I32(0)),
+        },
+    };
+    let instance = Instance::new(&module, &imports)?;
+    assert_eq!(
+        histogram.get_counts(&instance),
+        vec![
+            ("calls".to_string(), 0),
+            ("loads".to_string(), 0),
+            ("adds".to_string(), 0),
+        ]
+    );
+
+    instance.get_native_function::<i32, ()>("run")?.call(5)?;
+    assert_eq!(
+        histogram.get_counts(&instance),
+        vec![
+            ("calls".to_string(), 5),
+            ("loads".to_string(), 5),
+            ("adds".to_string(), 15),
+        ]
+    );
+    // The globals of the module are left as they are.
+    assert_eq!(instance.lookup_global("sum")?.get(), Value::I32(7));
+    assert_eq!(
+        instance
+            .lookup_global(&OpcodeHistogram::export_name("calls"))?
+            .ty(),
+        &GlobalType::new(Type::I64, Mutability::Var)
+    );
+    Ok(())
+}
+
+#[test]
+fn the_globals_of_middlewares_are_rendered_in_the_text_format() -> Result<()> {
+    let histogram = Arc::new(OpcodeHistogram::new(&["calls", "loads", "adds"], classify));
+    let store = get_store(vec![histogram]);
+    let wat = r#"
+        (import "host" "offset" (global $offset i32))
+        (global $sum (export "sum") (mut i32) (i32.const 7))
+        (func $get_sum (export "get_sum") (result i32)
+            (i32.add (global.get $sum) (global.get $offset)))
+    "#;
+    let text = Module::new(&store, wat)?.to_wat()?;
+    assert!(text.contains("(func $get_sum"));
+    assert!(text.contains("(global (;2;) (mut i64) (i64.const 0))"));
+    assert!(text.contains("(global (;4;) (mut i64) (i64.const 0))"));
+    assert!(text.contains("(export \"wasmer_histogram_calls\" (global 2))"));
+    assert!(text.contains("(export \"wasmer_histogram_adds\" (global 4))"));
+
+    // Parsed back without the middleware, the module still has the globals.
+    let store = get_store(vec![]);
+    let module = Module::new(&store, text)?;
+    let imports = imports! {
+        "host" => {
+            "offset" => Global::new(&store, Value::I32(1)),
+        },
+    };
+    let instance = Instance::new(&module, &imports)?;
+    for bucket in ["calls", "loads", "adds"] {
+        let global = instance.lookup_global(&OpcodeHistogram::export_name(bucket))?;
+        assert_eq!(global.get(), Value::I64(0));
+    }
+    let get_sum = instance.get_native_function::<(), i32>("get_sum")?;
+    assert_eq!(get_sum.call()?, 8);
+    Ok(())
+}
+
+#[test]
+fn middlewares_transform_the_operators_in_order() -> Result<()> {
+    let wat = r#"
+        (func (export "sub") (result i32)
+            (i32.sub (i32.const 10) (i32.const 3)))
+    "#;
+    let run = |middlewares: Vec<Arc<dyn ModuleMiddleware>>| -> Result<i32> {
+        let store = get_store(middlewares);
+        let instance = Instance::new(&Module::new(&store, wat)?, &imports! {})?;
+        Ok(instance.get_native_function::<(), i32>("sub")?.call()?)
+    };
+
+    assert_eq!(run(vec![])?, 7);
+    assert_eq!(run(vec![Arc::new(Generate(swap_constants))])?, -7);
+    // The constants are swapped before the first one, 3, is scaled.
+    assert_eq!(
+        run(vec![
+            Arc::new(Generate(swap_constants)),
+            Arc::new(Generate(scale_first_constant)),
+        ])?,
+        20
+    );
+    // 10 is scaled before the constants are swapped.
+    assert_eq!(
+        run(vec![
+            Arc::new(Generate(scale_first_constant)),
+            Arc::new(Generate(swap_constants)),
+        ])?,
+        -97
+    );
+    Ok(())
+}
+
+#[test]
+fn transformed_operators_are_validated() -> Result<()> {
+    let wat = r#"
+        (func (export "f")
+            (drop (i32.const 1)))
+    "#;
+    let store = get_store(vec![]);
+    Module::new(&store, wat)?;
+
+    let store = get_store(vec![Arc::new(Generate(drop_drops))]);
+    match Module::new(&store, wat) {
+        Err(CompileError::Function { location, error }) => {
+            assert_eq!(location.index.as_u32(), 0);
+            match *error {
+                CompileError::Validate(message) => assert!(message.contains("type mismatch")),
+                error => panic!("unexpected error: {:?}", error),
+            }
+        }
+        other => panic!("unexpected compilation result: {:?}", other.map(|_| ())),
+    }
+    Ok(())
+}
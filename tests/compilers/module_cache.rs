@@ -0,0 +1,104 @@
+//! Tests of `Module::new_cached` with a `FileSystemCache`.
+
+use anyhow::Result;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Barrier};
+use wasmer::*;
+
+static MODULE_WAT: &str = r#"(module
+    (func (export "add") (param i32 i32) (result i32) (i32.add (local.get 0) (local.get 1)))
+)"#;
+
+/// A `FileSystemCache` counting the modules stored in it.
+struct CountingCache {
+    inner: FileSystemCache,
+    stores: AtomicUsize,
+}
+
+impl ModuleCache for CountingCache {
+    fn load(&self, key: &ModuleCacheKey) -> Option<Vec<u8>> {
+        self.inner.load(key)
+    }
+
+    fn store(&self, key: &ModuleCacheKey, bytes: &[u8]) -> io::Result<()> {
+        self.stores.fetch_add(1, Ordering::SeqCst);
+        self.inner.store(key, bytes)
+    }
+}
+
+fn check_module(module: &Module) -> Result<()> {
+    let instance = Instance::new(module, &imports! {})?;
+    let add: NativeFunc<(i32, i32), i32> = instance.get_native_function("add")?;
+    assert_eq!(add.call(40, 2)?, 42);
+    Ok(())
+}
+
+fn cached_files(cache: &CountingCache) -> Result<Vec<std::path::PathBuf>> {
+    let mut files = vec![];
+    for entry in std::fs::read_dir(cache.inner.dir())? {
+        files.push(entry?.path());
+    }
+    Ok(files)
+}
+
+#[compiler_test(module_cache)]
+fn concurrent_compilations_share_the_cache(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let dir = tempfile::tempdir()?;
+    let cache = Arc::new(CountingCache {
+        inner: FileSystemCache::new(dir.path(), 1 << 30)?,
+        stores: AtomicUsize::new(0),
+    });
+
+    const THREADS: usize = 8;
+    let barrier = Arc::new(Barrier::new(THREADS));
+    let threads = (0..THREADS)
+        .map(|_| {
+            let (store, cache, barrier) = (store.clone(), cache.clone(), barrier.clone());
+            std::thread::spawn(move || -> Result<()> {
+                barrier.wait();
+                let module = Module::new_cached(&store, MODULE_WAT, &*cache)?;
+                check_module(&module)
+            })
+        })
+        .collect::<Vec<_>>();
+    for thread in threads {
+        thread.join().unwrap()?;
+    }
+
+    // However the threads raced, a single complete entry is left.
+    let stores = cache.stores.load(Ordering::SeqCst);
+    assert!(stores >= 1 && stores <= THREADS);
+    assert_eq!(cached_files(&cache)?.len(), 1);
+    let module = Module::new_cached(&store, MODULE_WAT, &*cache)?;
+    check_module(&module)?;
+    assert_eq!(cache.stores.load(Ordering::SeqCst), stores);
+    Ok(())
+}
+
+#[compiler_test(module_cache)]
+fn corrupted_entries_are_recompiled(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let dir = tempfile::tempdir()?;
+    let cache = CountingCache {
+        inner: FileSystemCache::new(dir.path(), 1 << 30)?,
+        stores: AtomicUsize::new(0),
+    };
+    Module::new_cached(&store, MODULE_WAT, &cache)?;
+    let files = cached_files(&cache)?;
+    assert_eq!(files.len(), 1);
+
+    let mut bytes = std::fs::read(&files[0])?;
+    let middle = bytes.len() / 2;
+    bytes[middle] ^= 0xff;
+    std::fs::write(&files[0], &bytes)?;
+    let module = Module::new_cached(&store, MODULE_WAT, &cache)?;
+    check_module(&module)?;
+    assert_eq!(cache.stores.load(Ordering::SeqCst), 2);
+
+    // The entry was replaced with a valid one.
+    Module::new_cached(&store, MODULE_WAT, &cache)?;
+    assert_eq!(cache.stores.load(Ordering::SeqCst), 2);
+    Ok(())
+}
@@ -0,0 +1,155 @@
+//! Testing the names from the name section of the modules.
+
+use anyhow::Result;
+use std::convert::TryFrom;
+use wasmer::*;
+use wasmer_engine::Executable;
+use wasmer_engine_universal::UniversalExecutableRef;
+use wasmer_types::entity::EntityRef;
+
+static WAT: &str = r#"
+    (module $blocks
+        (import "env" "commit" (func $commit_block (param i32)))
+        (func $process_block (export "process") (param $height i32)
+            (call $commit_block (local.get $height)))
+        (func (export "fail")
+            (call $validate_block))
+        (func $validate_block
+            unreachable))
+"#;
+
+fn function_names(module: &Module) -> Vec<(FunctionIndex, &str)> {
+    module.function_names().collect()
+}
+
+#[compiler_test(names)]
+fn names_are_exposed(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let module = Module::new(&store, WAT)?;
+    assert_eq!(module.name(), Some("blocks"));
+    assert_eq!(
+        function_names(&module),
+        vec![
+            (FunctionIndex::new(0), "commit_block"),
+            (FunctionIndex::new(1), "process_block"),
+            (FunctionIndex::new(3), "validate_block"),
+        ]
+    );
+
+    let module = Module::new(&store, "(module (func (export \"run\")))")?;
+    assert_eq!(module.name(), None);
+    assert_eq!(function_names(&module), vec![]);
+    Ok(())
+}
+
+#[compiler_test(names)]
+fn names_are_used_in_errors(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let module = Module::new(&store, WAT)?;
+    let error = match Instance::new(&module, &imports! {}) {
+        Err(InstantiationError::Link(error @ LinkError::UnknownImports(_))) => error,
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("instantiation should fail"),
+    };
+    let message = error.to_string();
+    assert!(
+        message.contains(
+            "\"env\".\"commit\" (function [I32] -> []) required by function 'commit_block'"
+        ),
+        "{}",
+        message
+    );
+
+    let imports = imports! {
+        "env" => {
+            "commit" => Function::new_native(&store, |_: i32| {}),
+        },
+    };
+    let instance = Instance::new(&module, &imports)?;
+    let error = instance
+        .lookup_function("fail")
+        .unwrap()
+        .call(&[])
+        .unwrap_err();
+    assert_eq!(error.trace()[0].function_name(), Some("validate_block"));
+    assert!(
+        error.to_string().contains("at validate_block (blocks[3]:"),
+        "{}",
+        error
+    );
+    Ok(())
+}
+
+/// A module with a name section made of `names`.
+fn with_name_section(names: &[u8]) -> Vec<u8> {
+    let mut wasm = wat2wasm(b"(module (func (export \"run\")))")
+        .unwrap()
+        .into_owned();
+    // A custom section, and its size: the name of the section and the names.
+    wasm.push(0);
+    wasm.push(u8::try_from(1 + 4 + names.len()).unwrap());
+    wasm.push(4);
+    wasm.extend_from_slice(b"name");
+    wasm.extend_from_slice(names);
+    wasm
+}
+
+#[compiler_test(names)]
+fn malformed_name_sections_are_ignored(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    // A module name longer than its subsection, a subsection longer than the section, a
+    // function name missing from its subsection and an invalid subsection id.
+    for names in [
+        &b"\x00\x05\x04ab"[..],
+        b"\x01\xff\x01",
+        b"\x01\x04\x02\x00\x01a",
+        b"\xff",
+    ] {
+        let module = Module::new(&store, with_name_section(names))?;
+        assert_eq!(module.name(), None);
+        assert_eq!(function_names(&module), vec![]);
+        let instance = Instance::new(&module, &imports! {})?;
+        instance.lookup_function("run").unwrap().call(&[])?;
+    }
+
+    // The names before a malformed subsection are kept.
+    let module = Module::new(
+        &store,
+        with_name_section(b"\x00\x04\x03mod\x01\x04\x01\x00\x09r"),
+    )?;
+    assert_eq!(module.name(), Some("mod"));
+    assert_eq!(function_names(&module), vec![]);
+    Ok(())
+}
+
+#[test]
+fn local_names_are_translated() -> Result<()> {
+    let wasm = wat2wasm(WAT.as_bytes())?;
+    let module = wasmer_compiler::ModuleEnvironment::new()
+        .translate(&wasm)?
+        .module;
+    assert_eq!(module.local_names.len(), 1);
+    assert_eq!(module.local_names[&FunctionIndex::new(1)][&0], "height");
+    Ok(())
+}
+
+#[test]
+fn names_survive_serialization() -> Result<()> {
+    let wasm = wat2wasm(WAT.as_bytes())?;
+    let engine = Universal::new(Singlepass::default()).engine();
+    let tunables = BaseTunables::for_target(engine.target());
+    let executable = engine.compile_universal(&wasm, &tunables)?;
+    let serialized = executable.serialize().unwrap();
+    let archived = unsafe { UniversalExecutableRef::deserialize(&serialized) }?;
+    let artifact = engine.load_universal_executable_ref(&archived)?;
+    assert_eq!(artifact.name(), Some("blocks"));
+    assert_eq!(
+        artifact.function_names().collect::<Vec<_>>(),
+        vec![
+            (FunctionIndex::new(0), "commit_block"),
+            (FunctionIndex::new(1), "process_block"),
+            (FunctionIndex::new(3), "validate_block"),
+        ]
+    );
+    Ok(())
+}
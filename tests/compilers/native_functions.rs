@@ -80,29 +80,218 @@ fn native_function_works_for_wasm(config: crate::Config) -> anyhow::Result<()> {
     Ok(())
 }
 
-#[should_panic(
-    expected = "Closures (functions with captured environments) are currently unsupported with native functions. See: https://github.com/wasmerio/wasmer/issues/1840"
-)]
 #[compiler_test(native_functions)]
-fn native_host_function_closure_panics(config: crate::Config) {
+fn native_signature_mismatch_names_both_signatures(config: crate::Config) -> anyhow::Result<()> {
     let store = config.store();
-    let state = 3;
-    Function::new_native(&store, move |_: i32| {
-        println!("{}", state);
-    });
+    let wat = r#"(module
+        (func (export "add") (param i32 i32) (result i32)
+           (i32.add (local.get 0) (local.get 1))))"#;
+    let module = Module::new(&store, wat)?;
+    let instance = Instance::new(&module, &imports! {})?;
+    let add = instance.lookup_function("add")?;
+
+    let error = add.native::<(i64, i64), i32>().err().unwrap();
+    assert_eq!(
+        error.message(),
+        "the requested signature `[I64, I64] -> [I32]` doesn't match the signature \
+         `[I32, I32] -> [I32]` of the function"
+    );
+    let error = add.native::<(i32, i32), ()>().err().unwrap();
+    assert_eq!(
+        error.message(),
+        "the requested signature `[I32, I32] -> []` doesn't match the signature \
+         `[I32, I32] -> [I32]` of the function"
+    );
+
+    let add: TypedFunction<(i32, i32), i32> = add.native()?;
+    assert_eq!(add.call(1, 2)?, 3);
+    Ok(())
+}
+
+#[compiler_test(native_functions)]
+fn native_functions_pass_extern_refs(config: crate::Config) -> anyhow::Result<()> {
+    let store = config.store();
+    let identity = Function::new_native(&store, |r: Option<ExternRef>| r);
+    let identity = identity.native::<Option<ExternRef>, Option<ExternRef>>()?;
+    assert_eq!(identity.call(None)?, None);
+    let extern_ref = ExternRef::new("extern".to_string());
+    assert_eq!(identity.call(Some(extern_ref.clone()))?, Some(extern_ref));
+    Ok(())
 }
 
-#[should_panic(
-    expected = "Closures (functions with captured environments) are currently unsupported with native functions. See: https://github.com/wasmerio/wasmer/issues/1840"
-)]
 #[compiler_test(native_functions)]
-fn native_with_env_host_function_closure_panics(config: crate::Config) {
+fn float_locals_survive_host_calls(config: crate::Config) -> anyhow::Result<()> {
     let store = config.store();
-    let state = 3;
-    let env = 4;
-    Function::new_native_with_env(&store, env, move |_env: &i32, _: i32| {
-        println!("{}", state);
-    });
+    let wat = r#"(module
+        (func $scramble (import "env" "scramble") (param f64) (result f64))
+        (func (export "run") (param $a f64) (param $b f32) (result f64)
+           (local $c f64) (local $d f64)
+           (local.set $c (f64.mul (local.get $a) (f64.const 3)))
+           (local.set $d (call $scramble (local.get $c)))
+           (f64.add
+                (f64.add (local.get $a) (f64.promote_f32 (local.get $b)))
+                (f64.add (local.get $c) (local.get $d))))
+)"#;
+    let module = Module::new(&store, wat).unwrap();
+
+    let import_object = imports! {
+        "env" => {
+            "scramble" => Function::new_native(&store, |x: f64| {
+                (0..16).fold(x, |acc, _| acc.sqrt() * 1.5 + acc).min(1.0)
+            }),
+        },
+    };
+
+    let instance = Instance::new(&module, &import_object)?;
+    let f: NativeFunc<(f64, f32), f64> = instance.get_native_function("run")?;
+    assert_eq!(f.call(2.0, 0.5)?, 2.0 + 0.5 + 6.0 + 1.0);
+
+    Ok(())
+}
+
+type MixedArgs = (i32, f32, i64, f64, i32, f32, i64, f64);
+
+#[compiler_test(native_functions)]
+fn mixed_arguments_arrive_intact(config: crate::Config) -> anyhow::Result<()> {
+    let store = config.store();
+    // More parameters than the registers of any calling convention, ints and floats
+    // interleaved, both into the wasm function and out of it to the host.
+    let wat = r#"(module
+        (func $record (import "env" "record") (param i32 f32 i64 f64 i32 f32 i64 f64))
+        (func (export "mix") (param i32 f32 i64 f64 i32 f32 i64 f64) (result f64)
+           (call $record (local.get 0) (local.get 1) (local.get 2) (local.get 3)
+                         (local.get 4) (local.get 5) (local.get 6) (local.get 7))
+           (local.get 7))
+)"#;
+    let module = Module::new(&store, wat)?;
+
+    let recorded = Arc::new(Mutex::new(None));
+    let import_object = imports! {
+        "env" => {
+            "record" => Function::new_native(&store, {
+                let recorded = recorded.clone();
+                move |a: i32, b: f32, c: i64, d: f64, e: i32, f: f32, g: i64, h: f64| {
+                    *recorded.lock().unwrap() = Some((a, b, c, d, e, f, g, h));
+                }
+            }),
+        },
+    };
+    let instance = Instance::new(&module, &import_object)?;
+
+    let args: MixedArgs = (
+        -1,
+        1.5,
+        0x1234_5678_9abc_def0,
+        -2.25,
+        0x7fff_0001,
+        3.75,
+        -0x0fed_cba9_8765_4321,
+        1e300,
+    );
+    let mix: NativeFunc<MixedArgs, f64> = instance.get_native_function("mix")?;
+    assert_eq!(
+        mix.call(args.0, args.1, args.2, args.3, args.4, args.5, args.6, args.7)?,
+        args.7
+    );
+    assert_eq!(recorded.lock().unwrap().take(), Some(args));
+
+    let mix = instance.lookup_function("mix").unwrap();
+    let results = mix.call(&[
+        Val::I32(args.0),
+        Val::F32(args.1),
+        Val::I64(args.2),
+        Val::F64(args.3),
+        Val::I32(args.4),
+        Val::F32(args.5),
+        Val::I64(args.6),
+        Val::F64(args.7),
+    ])?;
+    assert_eq!(results.to_vec(), vec![Val::F64(args.7)]);
+    assert_eq!(recorded.lock().unwrap().take(), Some(args));
+    Ok(())
+}
+
+#[compiler_test(native_functions)]
+fn native_host_function_closures_work(config: crate::Config) -> anyhow::Result<()> {
+    let store = config.store();
+    let wat = r#"(module
+        (func $count (import "env" "count") (param i32) (result i32))
+        (func $scale (import "env" "scale") (param i32) (result i32))
+        (func (export "test") (param i32) (result i32)
+           (call $scale (call $count (local.get 0))))
+)"#;
+    let module = Module::new(&store, wat)?;
+
+    let total = Arc::new(Mutex::new(0));
+    let factor = 3;
+    let import_object = imports! {
+        "env" => {
+            "count" => Function::new_native(&store, {
+                let total = total.clone();
+                move |n: i32| -> i32 {
+                    let mut total = total.lock().unwrap();
+                    *total += n;
+                    *total
+                }
+            }),
+            "scale" => Function::new_native_with_env(&store, 2, move |&env: &i32, n: i32| -> i32 {
+                n * env * factor
+            }),
+        },
+    };
+
+    // The instances share the state captured by the closures.
+    for expected in [6, 18] {
+        let instance = Instance::new(&module, &import_object)?;
+        let test: NativeFunc<i32, i32> = instance.get_native_function("test")?;
+        let n = *total.lock().unwrap() + 1;
+        assert_eq!(test.call(n)?, expected);
+    }
+    assert_eq!(*total.lock().unwrap(), 3);
+    Ok(())
+}
+
+#[compiler_test(native_functions)]
+fn native_host_function_closure_envs_are_initialized(config: crate::Config) -> anyhow::Result<()> {
+    let store = config.store();
+    let wat = r#"(module
+        (func $read (import "env" "read") (result i32))
+        (memory (export "memory") 1)
+        (data (i32.const 0) "\2a")
+        (func (export "test") (result i32)
+           (call $read))
+)"#;
+    let module = Module::new(&store, wat)?;
+
+    #[derive(Clone)]
+    struct Env {
+        memory: LazyInit<Memory>,
+    }
+
+    impl WasmerEnv for Env {
+        fn init_with_instance(&mut self, instance: &Instance) -> Result<(), HostEnvInitError> {
+            let mut memory = instance.lookup_memory("memory")?;
+            memory.into_weak_instance_ref();
+            self.memory.initialize(memory);
+            Ok(())
+        }
+    }
+
+    let env = Env {
+        memory: LazyInit::default(),
+    };
+    let offset = 0;
+    let import_object = imports! {
+        "env" => {
+            "read" => Function::new_native_with_env(&store, env, move |env: &Env| -> i32 {
+                env.memory.get_ref().unwrap().view::<u8>()[offset].get() as i32
+            }),
+        },
+    };
+    let instance = Instance::new(&module, &import_object)?;
+    let test: NativeFunc<(), i32> = instance.get_native_function("test")?;
+    assert_eq!(test.call()?, 42);
+    Ok(())
 }
 
 #[compiler_test(native_functions)]
@@ -0,0 +1,165 @@
+//! Testing the descriptions of the compiled code written for external profilers.
+
+use anyhow::Result;
+use std::convert::TryInto;
+use wasmer::*;
+use wasmer_types::entity::EntityRef;
+
+static WAT: &str = r#"
+    (module $profiled
+        (func $answer (export "answer") (result i32)
+            (i32.add (call 1) (call 1)))
+        (func (result i32)
+            (i32.const 21)))
+"#;
+
+/// Load `wat` in a new engine describing its code the way `strategy` says, and return it
+/// with the addresses and the sizes of its functions.
+fn load_profiled(
+    wat: &str,
+    strategy: ProfilingStrategy,
+) -> Result<(UniversalArtifact, Vec<(usize, usize)>)> {
+    let wasm = wat2wasm(wat.as_bytes())?;
+    let engine = Universal::new(Singlepass::default())
+        .enable_profiling(strategy)
+        .engine();
+    let tunables = BaseTunables::for_target(engine.target());
+    let executable = engine.compile_universal(&wasm, &tunables)?;
+    let artifact = engine.load_universal_executable(&executable)?;
+    let extents = (0..2)
+        .map(|index| {
+            let extent = artifact
+                .function_extent(LocalFunctionIndex::new(index))
+                .unwrap();
+            (*extent.address as usize, extent.length)
+        })
+        .collect();
+    Ok((artifact, extents))
+}
+
+/// The functions the perf map of the process describes, with their address and size.
+fn perf_map() -> Vec<(usize, usize, String)> {
+    let path = format!("/tmp/perf-{}.map", std::process::id());
+    // Nothing may have been described yet, and the last line may be partly written by another
+    // test.
+    let map = std::fs::read_to_string(path).unwrap_or_default();
+    map.lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, ' ');
+            let address = usize::from_str_radix(fields.next()?, 16).ok()?;
+            let size = usize::from_str_radix(fields.next()?, 16).ok()?;
+            Some((address, size, fields.next()?.to_string()))
+        })
+        .collect()
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn perf_map_describes_the_functions() -> Result<()> {
+    let (_artifact, extents) = load_profiled(WAT, ProfilingStrategy::PerfMap)?;
+    // Another engine of the process appends to the same file.
+    let other = WAT.replace("$profiled", "$profiled_too");
+    let (_other_artifact, other_extents) = load_profiled(&other, ProfilingStrategy::PerfMap)?;
+
+    let map = perf_map();
+    for (name, (address, size)) in [
+        ("profiled::answer", extents[0]),
+        ("profiled::function[1]", extents[1]),
+        ("profiled_too::answer", other_extents[0]),
+    ] {
+        assert!(
+            map.contains(&(address, size, name.to_string())),
+            "{} at {:#x} is missing from {:?}",
+            name,
+            address,
+            map
+        );
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn perf_map_describes_functions_compiled_lazily() -> Result<()> {
+    let engine = Universal::new(Singlepass::default())
+        .compile_mode(ModuleCompileMode::Lazy)
+        .enable_profiling(ProfilingStrategy::PerfMap)
+        .engine();
+    let store = Store::new(&engine);
+    let wat = WAT.replace("$profiled", "$lazily_profiled");
+    let module = Module::new(&store, wat)?;
+    let instance = Instance::new(&module, &imports! {})?;
+    let described = |name: &str| perf_map().iter().any(|(_, _, other)| other == name);
+    assert!(!described("lazily_profiled::answer"));
+
+    let answer: NativeFunc<(), i32> = instance.get_native_function("answer")?;
+    assert_eq!(answer.call()?, 42);
+    assert!(described("lazily_profiled::answer"));
+    assert!(described("lazily_profiled::function[1]"));
+    Ok(())
+}
+
+/// The records of a jitdump: their kind and their body.
+fn jit_dump_records(dump: &[u8]) -> Vec<(u32, &[u8])> {
+    let u32_at = |offset: usize| u32::from_ne_bytes(dump[offset..offset + 4].try_into().unwrap());
+    assert_eq!(u32_at(0), 0x4A695444);
+    assert_eq!(u32_at(20), std::process::id());
+    let mut records = vec![];
+    let mut offset = u32_at(8) as usize;
+    // The last record may be partly written by another test.
+    while offset + 16 <= dump.len() {
+        let size = u32_at(offset + 4) as usize;
+        if offset + size > dump.len() {
+            break;
+        }
+        records.push((u32_at(offset), &dump[offset + 16..offset + size]));
+        offset += size;
+    }
+    records
+}
+
+fn u64_at(body: &[u8], offset: usize) -> u64 {
+    u64::from_ne_bytes(body[offset..offset + 8].try_into().unwrap())
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn jit_dump_describes_the_functions() -> Result<()> {
+    let wat = WAT.replace("$profiled", "$jit_dumped");
+    let wasm_len = wat2wasm(wat.as_bytes())?.len();
+    let (_artifact, extents) = load_profiled(&wat, ProfilingStrategy::JitDump)?;
+    let (address, size) = extents[0];
+
+    let dump = std::fs::read(format!("/tmp/jit-{}.dump", std::process::id()))?;
+    let records = jit_dump_records(&dump);
+    let load = records
+        .iter()
+        .position(|&(kind, body)| kind == 0 && body[40..].starts_with(b"jit_dumped::answer\0"))
+        .expect("the function is described");
+    let body = records[load].1;
+    assert_eq!(u64_at(body, 8), address as u64);
+    assert_eq!(u64_at(body, 16), address as u64);
+    assert_eq!(u64_at(body, 24), size as u64);
+    // The code follows the name.
+    let code = &body[40 + "jit_dumped::answer\0".len()..];
+    let published = unsafe { std::slice::from_raw_parts(address as *const u8, size) };
+    assert_eq!(code, published);
+
+    // The offsets of the instructions in the binary precede the code.
+    let (kind, debug_info) = records[load - 1];
+    assert_eq!(kind, 2);
+    assert_eq!(u64_at(debug_info, 0), address as u64);
+    let entries = u64_at(debug_info, 8);
+    assert!(entries > 0);
+    let mut offset = 16;
+    for _ in 0..entries {
+        let entry_address = u64_at(debug_info, offset) as usize;
+        assert!((address..address + size).contains(&entry_address));
+        let line = u32::from_ne_bytes(debug_info[offset + 8..offset + 12].try_into().unwrap());
+        assert!((line as usize) < wasm_len, "{}", line);
+        assert!(debug_info[offset + 16..].starts_with(b"jit_dumped\0"));
+        offset += 16 + "jit_dumped\0".len();
+    }
+    assert_eq!(offset, debug_info.len());
+    Ok(())
+}
@@ -0,0 +1,125 @@
+//! Testing the instances of new modules adopting the state of other instances.
+
+use anyhow::Result;
+use wasmer::*;
+use wasmer_types::InstanceConfig;
+
+/// The first version of a module keeping a counter in a global and a log of the counter in
+/// its memory.
+const V1: &str = r#"
+    (memory (export "memory") 1)
+    (global $count (export "count") (mut i32) (i32.const 0))
+    (global (export "version") i32 (i32.const 1))
+    (data (i32.const 0x100) "v1")
+    (func (export "bump") (result i32)
+        (global.set $count (i32.add (global.get $count) (i32.const 1)))
+        (i32.store (i32.mul (global.get $count) (i32.const 4)) (global.get $count))
+        (global.get $count))
+"#;
+
+/// The next version, counting by tens.
+const V2: &str = r#"
+    (memory (export "memory") 1)
+    (global $count (export "count") (mut i32) (i32.const 100))
+    (global (export "version") i32 (i32.const 2))
+    (data (i32.const 0x100) "v2")
+    (func (export "bump") (result i32)
+        (global.set $count (i32.add (global.get $count) (i32.const 10)))
+        (global.get $count))
+"#;
+
+/// Bump the counter of a first version of the module three times and grow its memory.
+fn counted(store: &Store) -> Result<Instance> {
+    let instance = Instance::new(&Module::new(store, V1)?, &imports! {})?;
+    let bump: NativeFunc<(), i32> = instance.get_native_function("bump")?;
+    for _ in 0..3 {
+        bump.call()?;
+    }
+    instance.lookup_memory("memory")?.grow(Pages(1))?;
+    Ok(instance)
+}
+
+#[compiler_test(reinstantiate)]
+fn upgrades_keep_the_state(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let old = counted(&store)?;
+    let old_bump: NativeFunc<(), i32> = old.get_native_function("bump")?;
+    let new = old.reinstantiate_with(&Module::new(&store, V2)?, &imports! {})?;
+
+    assert_eq!(new.lookup_global("count")?.get(), Value::I32(3));
+    assert_eq!(new.lookup_global("version")?.get(), Value::I32(2));
+    let memory = new.lookup_memory("memory")?;
+    assert_eq!(memory.size(), Pages(2));
+    assert_eq!(
+        memory.copy_to_vec(4..16)?,
+        [1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0]
+    );
+    // The data segments of the new module aren't applied.
+    assert_eq!(memory.copy_to_vec(0x100..0x102)?, b"v1");
+
+    let bump: NativeFunc<(), i32> = new.get_native_function("bump")?;
+    assert_eq!(bump.call()?, 13);
+    assert!(old_bump.call().unwrap_err().is_instance_closed());
+    Ok(())
+}
+
+#[compiler_test(reinstantiate)]
+fn upgrades_can_apply_the_data_segments(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let old = counted(&store)?;
+    let new = old.reinstantiate_with_config(
+        &Module::new(&store, V2)?,
+        InstanceConfig::default(),
+        &imports! {},
+        true,
+    )?;
+    let memory = new.lookup_memory("memory")?;
+    assert_eq!(memory.copy_to_vec(0x100..0x102)?, b"v2");
+    assert_eq!(memory.read_u32(12)?, 3);
+    assert_eq!(memory.size(), Pages(2));
+    Ok(())
+}
+
+#[compiler_test(reinstantiate)]
+fn incompatible_upgrades_are_refused(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let old = counted(&store)?;
+    let module = Module::new(
+        &store,
+        r#"
+        (memory (export "memory") 3)
+        (global (export "count") (mut i64) (i64.const 0))
+        (global (export "bump") (mut i32) (i32.const 0))
+        (func (export "version") (result i32)
+            (i32.const 3))
+        "#,
+    )?;
+    let incompatible = match old.reinstantiate_with(&module, &imports! {}) {
+        Err(ReinstantiateError::Incompatible(incompatible)) => incompatible,
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    };
+    let names = incompatible
+        .iter()
+        .map(|export| export.name.as_str())
+        .collect::<Vec<_>>();
+    // The function replacing an immutable global isn't adopted, nor incompatible.
+    assert_eq!(names, ["memory", "count", "bump"]);
+    assert_eq!(
+        incompatible[0].reason,
+        "the new minimum of 3 pages is above the 2 pages of the memory"
+    );
+    assert_eq!(
+        incompatible[1].current,
+        ExternType::Global(GlobalType::new(Type::I32, Mutability::Var))
+    );
+    assert_eq!(
+        incompatible[1].new,
+        ExternType::Global(GlobalType::new(Type::I64, Mutability::Var))
+    );
+    assert_eq!(incompatible[2].reason, "the kinds of the exports differ");
+
+    // The instance is left as it is.
+    let bump: NativeFunc<(), i32> = old.get_native_function("bump")?;
+    assert_eq!(bump.call()?, 4);
+    Ok(())
+}
@@ -0,0 +1,321 @@
+use wasmer::*;
+use wasmer_compiler_singlepass::{Singlepass, GPR};
+use wasmer_engine_universal::Universal;
+
+fn get_store(reserved: &[GPR]) -> Store {
+    let mut compiler = Singlepass::default();
+    compiler.reserved_gprs(reserved);
+    Store::new(&Universal::new(compiler).engine())
+}
+
+/// A function with more live values than there are allocatable registers, so that every
+/// register class is exhausted and values spill to the stack.
+const PRESSURE_WAT: &str = r#"
+    (func $pressure (export "pressure") (param $a i64) (param $b i64) (result i64)
+        (local $c i64) (local $d i64) (local $e i64) (local $f i64)
+        (local.set $c (i64.add (local.get $a) (local.get $b)))
+        (local.set $d (i64.mul (local.get $c) (local.get $a)))
+        (local.set $e (i64.sub (local.get $d) (local.get $b)))
+        (local.set $f (i64.xor (local.get $e) (local.get $c)))
+        (i64.add (local.get $a) (local.get $b))
+        (i64.add (local.get $c) (local.get $d))
+        (i64.add (local.get $e) (local.get $f))
+        (i64.mul (local.get $a) (local.get $f))
+        (i64.div_u (local.get $d) (i64.const 3))
+        (i64.rem_s (local.get $e) (i64.const 7))
+        (i64.shl (local.get $c) (i64.const 5))
+        (i64.rotr (local.get $f) (i64.const 9))
+        (i64.add)
+        (i64.add)
+        (i64.add)
+        (i64.add)
+        (i64.add)
+        (i64.add)
+        (i64.add)
+        (call $callee (local.get $a))
+        (i64.add)
+    )
+    (func $callee (param $x i64) (result i64)
+        (i64.mul (local.get $x) (i64.const 11))
+    )
+"#;
+
+fn run_pressure(reserved: &[GPR], a: i64, b: i64) -> i64 {
+    let store = get_store(reserved);
+    let module = Module::new(&store, PRESSURE_WAT).unwrap();
+    let instance = Instance::new(&module, &imports! {}).unwrap();
+    let pressure = instance
+        .lookup_function("pressure")
+        .expect("expected function pressure");
+    match pressure.call(&[Value::I64(a), Value::I64(b)]).unwrap()[0] {
+        Value::I64(v) => v,
+        ref v => panic!("unexpected result {:?}", v),
+    }
+}
+
+#[test]
+fn reserved_registers_preserve_semantics() {
+    let reservations: &[&[GPR]] = &[
+        &[GPR::R14],
+        &[GPR::R10, GPR::R11],
+        &[GPR::RBX, GPR::R12, GPR::R13, GPR::R14],
+        &[GPR::RBX, GPR::R10, GPR::R11, GPR::R12, GPR::R13, GPR::R14],
+    ];
+    for &(a, b) in &[(0, 0), (1, 2), (-17, 123456789), (i64::MAX, i64::MIN)] {
+        let expected = run_pressure(&[], a, b);
+        for reserved in reservations {
+            assert_eq!(run_pressure(reserved, a, b), expected, "{:?}", reserved);
+        }
+    }
+}
+
+#[test]
+fn unreservable_register_is_rejected() {
+    let store = get_store(&[GPR::RAX]);
+    match Module::new(&store, PRESSURE_WAT) {
+        Err(CompileError::Codegen(message)) => assert!(message.contains("RAX"), "{}", message),
+        Err(e) => panic!("unexpected error {:?}", e),
+        Ok(_) => panic!("reserving RAX should fail"),
+    }
+}
+
+/// The values the reserved registers are seeded with, in the order `RBX`, `R10` to `R14`.
+const SEEDS: [u64; 6] = [
+    0x0bad_cafe_0000_00bb,
+    0x0bad_cafe_0000_0010,
+    0x0bad_cafe_0000_0011,
+    0x0bad_cafe_0000_0012,
+    0x0bad_cafe_0000_0013,
+    0x0bad_cafe_0000_0014,
+];
+
+/// Machine code calling `func(vmctx)` with the reserved registers holding `SEEDS`, and writing
+/// them to `out` once it returns, as `extern "C" fn(vmctx, func, out: *mut [u64; 6])`.
+fn probe_code() -> Vec<u8> {
+    let mut code = vec![
+        0x53, // push rbx
+        0x55, // push rbp
+        0x41, 0x54, // push r12
+        0x41, 0x55, // push r13
+        0x41, 0x56, // push r14
+        0x41, 0x57, // push r15
+        0x52, // push rdx, which also aligns the stack for the call
+    ];
+    // mov rbx/r10/r11/r12/r13/r14, imm64
+    for (prefix, seed) in [
+        ([0x48, 0xbb], SEEDS[0]),
+        ([0x49, 0xba], SEEDS[1]),
+        ([0x49, 0xbb], SEEDS[2]),
+        ([0x49, 0xbc], SEEDS[3]),
+        ([0x49, 0xbd], SEEDS[4]),
+        ([0x49, 0xbe], SEEDS[5]),
+    ] {
+        code.extend_from_slice(&prefix);
+        code.extend_from_slice(&seed.to_le_bytes());
+    }
+    code.extend_from_slice(&[
+        0xff, 0xd6, // call rsi
+        0x5a, // pop rdx
+        0x48, 0x89, 0x1a, // mov [rdx], rbx
+        0x4c, 0x89, 0x52, 0x08, // mov [rdx + 8], r10
+        0x4c, 0x89, 0x5a, 0x10, // mov [rdx + 16], r11
+        0x4c, 0x89, 0x62, 0x18, // mov [rdx + 24], r12
+        0x4c, 0x89, 0x6a, 0x20, // mov [rdx + 32], r13
+        0x4c, 0x89, 0x72, 0x28, // mov [rdx + 40], r14
+        0x41, 0x5f, // pop r15
+        0x41, 0x5e, // pop r14
+        0x41, 0x5d, // pop r13
+        0x41, 0x5c, // pop r12
+        0x5d, // pop rbp
+        0x5b, // pop rbx
+        0xc3, // ret
+    ]);
+    code
+}
+
+type Probe = unsafe extern "C" fn(*mut wasmer_vm::VMContext, *const u8, *mut [u64; 6]);
+
+/// The probe, in executable memory.
+struct ProbeCode {
+    mmap: wasmer_vm::Mmap,
+}
+
+impl ProbeCode {
+    fn new() -> Self {
+        let code = probe_code();
+        let mut mmap = wasmer_vm::Mmap::with_at_least(code.len()).unwrap();
+        mmap.as_mut_slice()[..code.len()].copy_from_slice(&code);
+        unsafe { region::protect(mmap.as_ptr(), mmap.len(), region::Protection::READ_EXECUTE) }
+            .unwrap();
+        Self { mmap }
+    }
+
+    /// Call the exported Wasm function `name`, which takes no arguments, and return the values
+    /// of the reserved registers once it returned.
+    fn call(&self, instance: &Instance, name: &str) -> [u64; 6] {
+        let function = instance.lookup_function(name).unwrap();
+        let vm_function = match function.to_export() {
+            Export::Function(function) => function.vm_function,
+            _ => unreachable!(),
+        };
+        let mut out = [0; 6];
+        unsafe {
+            let probe: Probe = std::mem::transmute(self.mmap.as_ptr());
+            probe(
+                vm_function.vmctx.vmctx,
+                vm_function.address as *const u8,
+                &mut out,
+            );
+        }
+        out
+    }
+}
+
+/// A function for each class of opcodes, with enough live values for all the allocatable
+/// registers to be used. The `builtins` and `imports` ones call into the host, whose code
+/// may clobber the caller-saved `R10` and `R11`.
+const OPCODE_CLASSES_WAT: &str = r#"
+    (import "env" "host" (func $host (param i64) (result i64)))
+    (memory 1 1 shared)
+    (table 3 funcref)
+    (elem (i32.const 0) $callee $callee $host)
+    (global $g (mut i64) (i64.const 5))
+
+    (func $callee (param i64 i64) (result i64)
+        (i64.add (local.get 0) (i64.mul (local.get 1) (i64.const 3))))
+
+    (func (export "integers") (local i64 i64 i64 i64 i64 i64 i32 i32)
+        (local.set 0 (i64.const 0x1234_5678_9abc_def0))
+        (local.set 1 (i64.rotl (local.get 0) (i64.const 13)))
+        (local.set 2 (i64.mul (local.get 0) (local.get 1)))
+        (local.set 3 (i64.div_u (local.get 2) (i64.const 7)))
+        (local.set 4 (i64.rem_s (local.get 3) (i64.const -11)))
+        (local.set 5 (i64.shr_s (local.get 4) (i64.const 3)))
+        (local.set 6 (i32.popcnt (i32.wrap_i64 (local.get 5))))
+        (local.set 7 (i32.div_s (i32.clz (local.get 6)) (i32.ctz (i32.const 8))))
+        (i64.store (i32.const 0)
+            (i64.add
+                (i64.add (i64.add (local.get 0) (local.get 1)) (i64.add (local.get 2) (local.get 3)))
+                (i64.add
+                    (i64.add (local.get 4) (local.get 5))
+                    (i64.extend_i32_u (i32.add (local.get 6) (local.get 7)))))))
+
+    (func (export "floats") (local f64 f64 f32 i64)
+        (local.set 0 (f64.const 1.5))
+        (local.set 1 (f64.sqrt (f64.mul (local.get 0) (f64.const 8.25))))
+        (local.set 2 (f32.demote_f64 (f64.div (local.get 1) (local.get 0))))
+        (local.set 3 (i64.trunc_f64_s (f64.nearest (local.get 1))))
+        (f64.store (i32.const 8)
+            (f64.add
+                (f64.promote_f32 (f32.max (local.get 2) (f32.const -0.0)))
+                (f64.convert_i64_u (i64.add (local.get 3) (i64.trunc_sat_f32_u (local.get 2))))))
+        (i64.store (i32.const 16) (i64.reinterpret_f64 (f64.copysign (local.get 0) (local.get 1)))))
+
+    (func (export "memory") (local i32 i64)
+        (local.set 0 (i32.const 64))
+        (i32.store8 offset=1 (local.get 0) (i32.const 0xff))
+        (i32.store16 offset=2 (local.get 0) (i32.load8_s offset=1 (local.get 0)))
+        (i64.store32 offset=4 (local.get 0) (i64.load16_u offset=2 (local.get 0)))
+        (local.set 1 (i64.load offset=0 (local.get 0)))
+        (i64.store offset=8 (local.get 0) (i64.add (local.get 1) (i64.extend_i32_u (memory.size)))))
+
+    (func (export "control") (local i32 i64)
+        (block $done
+            (loop $next
+                (local.set 1 (i64.add (local.get 1)
+                    (select (i64.const 3) (i64.const 5) (i32.and (local.get 0) (i32.const 1)))))
+                (br_table $next $next $done
+                    (local.tee 0 (i32.add (local.get 0) (i32.const 1))))))
+        (if (i64.gt_u (local.get 1) (i64.const 4))
+            (then (global.set $g (local.get 1)))
+            (else (unreachable)))
+        (i64.store (i32.const 24) (global.get $g)))
+
+    (func (export "calls") (local i64 i64 i64)
+        (local.set 0 (i64.const 7))
+        (local.set 1 (call $callee (local.get 0) (i64.const 2)))
+        (local.set 2 (call_indirect (param i64 i64) (result i64)
+            (local.get 1) (local.get 0) (i32.const 1)))
+        (i64.store (i32.const 32)
+            (i64.add (local.get 0) (i64.add (local.get 1) (local.get 2)))))
+
+    (func (export "atomics") (local i32 i64 i64 i64)
+        (local.set 0 (i32.const 128))
+        (i64.atomic.store (local.get 0) (i64.const 10))
+        (local.set 1 (i64.atomic.rmw.add (local.get 0) (i64.const 5)))
+        (local.set 2 (i64.atomic.rmw.xchg offset=8 (local.get 0) (local.get 1)))
+        (local.set 3 (i64.atomic.rmw.cmpxchg (local.get 0) (local.get 1) (local.get 2)))
+        (drop (i32.atomic.rmw.cmpxchg offset=16 (local.get 0)
+            (i32.wrap_i64 (local.get 3)) (i32.wrap_i64 (local.get 1))))
+        (drop (i64.atomic.rmw8.cmpxchg_u offset=24 (local.get 0) (local.get 2) (local.get 3)))
+        (drop (i32.atomic.rmw16.and_u offset=32 (local.get 0) (i32.const 0xff)))
+        (drop (i64.atomic.rmw32.sub_u offset=40 (local.get 0) (local.get 3)))
+        (atomic.fence)
+        (i64.atomic.store offset=48 (local.get 0)
+            (i64.add (i64.atomic.load (local.get 0)) (i64.add (local.get 1) (local.get 3)))))
+
+    (func (export "builtins") (local i64 i64)
+        (local.set 0 (i64.const 11))
+        (local.set 1 (i64.mul (local.get 0) (i64.const 13)))
+        (memory.fill (i32.const 256) (i32.const 7) (i32.const 64))
+        (memory.copy (i32.const 512) (i32.const 256) (i32.const 64))
+        ;; Past the maximum, so the runtime refuses it.
+        (drop (memory.grow (i32.const 1)))
+        (drop (memory.grow (i32.const 0)))
+        (drop (table.grow (ref.null func) (i32.const 1)))
+        (table.set (i32.const 3) (ref.func $callee))
+        (i64.store (i32.const 40)
+            (i64.add
+                (i64.add (local.get 0) (local.get 1))
+                (i64.extend_i32_u (table.size)))))
+
+    (func (export "imports") (local i64 i64)
+        (local.set 0 (i64.const 17))
+        (local.set 1 (call $host (local.get 0)))
+        (i64.store (i32.const 48)
+            (i64.add
+                (i64.add (local.get 0) (local.get 1))
+                (call_indirect (param i64) (result i64) (local.get 1) (i32.const 2)))))
+"#;
+
+/// The import of `OPCODE_CLASSES_WAT`, which runs enough code for it to use the caller-saved
+/// registers.
+fn host(x: i64) -> i64 {
+    let digits = format!("{:?}", (x, x * 3, x.to_string()));
+    digits.bytes().map(i64::from).sum()
+}
+
+fn get_opcode_classes_store(omit_leaf_frame_pointers: bool) -> Store {
+    let mut compiler = Singlepass::default();
+    compiler.reserved_gprs(&[GPR::RBX, GPR::R10, GPR::R11, GPR::R12, GPR::R13, GPR::R14]);
+    compiler.omit_leaf_frame_pointers(omit_leaf_frame_pointers);
+    let mut features = Features::default();
+    features.threads(true);
+    Store::new(&Universal::new(compiler).features(features).engine())
+}
+
+#[test]
+fn reserved_registers_are_preserved_by_each_opcode_class() {
+    let probe = ProbeCode::new();
+    for omit_leaf_frame_pointers in [false, true] {
+        let store = get_opcode_classes_store(omit_leaf_frame_pointers);
+        let module = Module::new(&store, OPCODE_CLASSES_WAT).unwrap();
+        let imports = imports! {
+            "env" => {
+                "host" => Function::new_native(&store, host),
+            },
+        };
+        let instance = Instance::new(&module, &imports).unwrap();
+        for class in [
+            "integers", "floats", "memory", "control", "calls", "atomics", "builtins", "imports",
+        ] {
+            assert_eq!(
+                probe.call(&instance, class),
+                SEEDS,
+                "{} clobbered a reserved register (leaf frame pointers omitted: {})",
+                class,
+                omit_leaf_frame_pointers
+            );
+        }
+    }
+}
@@ -0,0 +1,101 @@
+//! Testing the limits a resource limiter puts on the memories, tables and instances of a
+//! store.
+
+use anyhow::Result;
+use std::sync::Arc;
+use wasmer::*;
+
+/// Make a store whose resources are limited by `limiter`.
+fn store_with_limiter(config: &crate::Config, limiter: Arc<StaticLimiter>) -> Store {
+    let engine = config.engine(config.compiler_config(config.canonicalize_nans));
+    let mut tunables = BaseTunables::for_target(engine.target());
+    tunables.resource_limiter = Some(limiter);
+    Store::new_with_tunables(&*engine, tunables)
+}
+
+fn get_module(store: &Store) -> Result<Module> {
+    let wat = r#"
+        (memory (export "memory") 1)
+        (table (export "table") 2 funcref)
+        (func (export "grow_memory") (param $pages i32) (result i32)
+            (memory.grow (local.get $pages)))
+        (func (export "grow_table") (param $elements i32) (result i32)
+            (table.grow (ref.null func) (local.get $elements)))
+        (func (export "grow_until_refused") (result i32)
+            (local $grows i32)
+            (block $refused
+                (loop $next
+                    (br_if $refused (i32.eq (memory.grow (i32.const 1)) (i32.const -1)))
+                    (local.set $grows (i32.add (local.get $grows) (i32.const 1)))
+                    (br $next)))
+            (local.get $grows))
+    "#;
+    Ok(Module::new(&store, &wat)?)
+}
+
+#[compiler_test(resource_limiter)]
+fn memory_grows_stop_at_the_limit(config: crate::Config) -> Result<()> {
+    let limiter = Arc::new(StaticLimiter::new(Pages(10), 10, 100));
+    let store = store_with_limiter(&config, limiter.clone());
+    let module = get_module(&store)?;
+    let instance = Instance::new(&module, &imports! {})?;
+    assert_eq!(limiter.total_pages(), Pages(1));
+
+    // The guest is told that the memory can't grow, it doesn't trap.
+    let grow_until_refused = instance.get_native_function::<(), i32>("grow_until_refused")?;
+    assert_eq!(grow_until_refused.call()?, 9);
+    assert_eq!(limiter.total_pages(), Pages(10));
+    let grow_memory = instance.get_native_function::<i32, i32>("grow_memory")?;
+    assert_eq!(grow_memory.call(1)?, -1);
+    let memory = instance.lookup_memory("memory")?;
+    assert!(memory.grow(Pages(1)).is_err());
+    assert_eq!(memory.size(), Pages(10));
+
+    // The limit covers all the memories of the store, host memories included.
+    assert!(Memory::new(&store, MemoryType::new(1, None, false)).is_err());
+    drop((grow_until_refused, grow_memory, memory, instance));
+    assert_eq!(limiter.total_pages(), Pages(0));
+    let memory = Memory::new(&store, MemoryType::new(4, None, false))?;
+    assert_eq!(limiter.total_pages(), Pages(4));
+    drop(memory);
+    assert_eq!(limiter.total_pages(), Pages(0));
+    Ok(())
+}
+
+#[compiler_test(resource_limiter)]
+fn table_grows_stop_at_the_limit(config: crate::Config) -> Result<()> {
+    let limiter = Arc::new(StaticLimiter::new(Pages(10), 10, 5));
+    let store = store_with_limiter(&config, limiter.clone());
+    let module = get_module(&store)?;
+    let instance = Instance::new(&module, &imports! {})?;
+    let grow_table = instance.get_native_function::<i32, i32>("grow_table")?;
+    assert_eq!(grow_table.call(3)?, 2);
+    assert_eq!(limiter.table_elements(), 5);
+    assert_eq!(grow_table.call(1)?, -1);
+    assert_eq!(instance.lookup_table("table")?.size(), 5);
+    Ok(())
+}
+
+#[compiler_test(resource_limiter)]
+fn instantiations_stop_at_the_limit(config: crate::Config) -> Result<()> {
+    let limiter = Arc::new(StaticLimiter::new(Pages(10), 2, 100));
+    let store = store_with_limiter(&config, limiter.clone());
+    let module = get_module(&store)?;
+    let first = Instance::new(&module, &imports! {})?;
+    let _second = Instance::new(&module, &imports! {})?;
+    assert_eq!(limiter.instances(), 2);
+    match Instance::new(&module, &imports! {}) {
+        Err(InstantiationError::Link(LinkError::Resource(_))) => {}
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("instantiation should fail"),
+    }
+
+    // The refused instantiation released the memory and table it created.
+    assert_eq!(limiter.instances(), 2);
+    assert_eq!(limiter.total_pages(), Pages(2));
+    assert_eq!(limiter.table_elements(), 4);
+    drop(first);
+    assert_eq!(limiter.instances(), 1);
+    let _third = Instance::new(&module, &imports! {})?;
+    Ok(())
+}
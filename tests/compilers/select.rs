@@ -0,0 +1,100 @@
+//! Tests of `select` over the different value types, with operands in registers and immediates.
+
+use anyhow::Result;
+use wasmer::*;
+
+static SELECT_WAT: &str = r#"(module
+    (func (export "i32") (param i32 i32 i32) (result i32)
+        (select (local.get 0) (local.get 1) (local.get 2)))
+    (func (export "i64") (param i64 i64 i32) (result i64)
+        (select (local.get 0) (local.get 1) (local.get 2)))
+    (func (export "f32") (param i32 i32 i32) (result i32)
+        (i32.reinterpret_f32
+            (select (f32.reinterpret_i32 (local.get 0))
+                    (f32.reinterpret_i32 (local.get 1))
+                    (local.get 2))))
+    (func (export "f64") (param i64 i64 i32) (result i64)
+        (i64.reinterpret_f64
+            (select (f64.reinterpret_i64 (local.get 0))
+                    (f64.reinterpret_i64 (local.get 1))
+                    (local.get 2))))
+    (func (export "typed_f64") (param f64 f64 i32) (result f64)
+        (select (result f64) (local.get 0) (local.get 1) (local.get 2)))
+    (func (export "constants") (param i32) (result i64)
+        (select (i64.const -1) (i64.const 0) (local.get 0)))
+    (func (export "same_operand") (param i32) (result i32)
+        (select (local.get 0) (local.get 0) (local.get 0)))
+)"#;
+
+#[compiler_test(select)]
+fn select_all_types(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let module = Module::new(&store, SELECT_WAT)?;
+    let instance = Instance::new(&module, &imports! {})?;
+
+    let i32_select: NativeFunc<(i32, i32, i32), i32> = instance.get_native_function("i32")?;
+    assert_eq!(i32_select.call(1, 2, 1)?, 1);
+    assert_eq!(i32_select.call(1, 2, 0)?, 2);
+    // Any non-zero condition picks the first operand.
+    assert_eq!(i32_select.call(1, 2, i32::MIN)?, 1);
+
+    let i64_select: NativeFunc<(i64, i64, i32), i64> = instance.get_native_function("i64")?;
+    assert_eq!(i64_select.call(i64::MIN, i64::MAX, -1)?, i64::MIN);
+    assert_eq!(i64_select.call(i64::MIN, i64::MAX, 0)?, i64::MAX);
+
+    let typed: NativeFunc<(f64, f64, i32), f64> = instance.get_native_function("typed_f64")?;
+    assert_eq!(typed.call(1.5, -0.0, 7)?, 1.5);
+    assert_eq!(typed.call(1.5, -0.0, 0)?.to_bits(), (-0.0f64).to_bits());
+
+    let constants: NativeFunc<i32, i64> = instance.get_native_function("constants")?;
+    assert_eq!(constants.call(1)?, -1);
+    assert_eq!(constants.call(0)?, 0);
+
+    let same_operand: NativeFunc<i32, i32> = instance.get_native_function("same_operand")?;
+    assert_eq!(same_operand.call(0)?, 0);
+    assert_eq!(same_operand.call(42)?, 42);
+    Ok(())
+}
+
+#[compiler_test(select)]
+fn select_preserves_nan_payloads(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let module = Module::new(&store, SELECT_WAT)?;
+    let instance = Instance::new(&module, &imports! {})?;
+
+    // A signaling NaN with a payload, and a negative quiet NaN.
+    let (nan32_a, nan32_b) = (0x7f80_0001u32 as i32, 0xffc0_1234u32 as i32);
+    let f32_select: NativeFunc<(i32, i32, i32), i32> = instance.get_native_function("f32")?;
+    assert_eq!(f32_select.call(nan32_a, nan32_b, 1)?, nan32_a);
+    assert_eq!(f32_select.call(nan32_a, nan32_b, 0)?, nan32_b);
+
+    let (nan64_a, nan64_b) = (
+        0x7ff0_0000_0000_0001u64 as i64,
+        0xfff8_0000_dead_beefu64 as i64,
+    );
+    let f64_select: NativeFunc<(i64, i64, i32), i64> = instance.get_native_function("f64")?;
+    assert_eq!(f64_select.call(nan64_a, nan64_b, 1)?, nan64_a);
+    assert_eq!(f64_select.call(nan64_a, nan64_b, 0)?, nan64_b);
+    Ok(())
+}
+
+#[compiler_test(select)]
+fn select_canonicalizes_nans(mut config: crate::Config) -> Result<()> {
+    config.set_nan_canonicalization(true);
+    let store = config.store();
+    let wat = r#"(module
+        (func (export "select_sum") (param i64 i32) (result i64)
+            (i64.reinterpret_f64
+                (select (f64.add (f64.reinterpret_i64 (local.get 0)) (f64.const 0))
+                        (f64.const 1)
+                        (local.get 1))))
+    )"#;
+    let module = Module::new(&store, wat)?;
+    let instance = Instance::new(&module, &imports! {})?;
+
+    let select_sum: NativeFunc<(i64, i32), i64> = instance.get_native_function("select_sum")?;
+    let nan = 0xfff8_0000_dead_beefu64 as i64;
+    assert_eq!(select_sum.call(nan, 1)?, 0x7ff8_0000_0000_0000);
+    assert_eq!(select_sum.call(nan, 0)?, 1f64.to_bits() as i64);
+    Ok(())
+}
@@ -1,5 +1,8 @@
 use anyhow::Result;
 use wasmer::*;
+use wasmer_engine::Executable;
+use wasmer_engine_universal::UniversalExecutableRef;
+use wasmer_types::entity::EntityRef;
 
 #[compiler_test(serialize)]
 fn test_serialize(config: crate::Config) -> Result<()> {
@@ -22,6 +25,277 @@ fn test_serialize(config: crate::Config) -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn frame_layouts_survive_serialization() {
+    let wasm = wat2wasm(
+        r#"
+        (module
+        (func (export "floats") (param f64) (local i64 i64 i64 i64 i64 i64)
+            (drop (local.get 0)))
+        (func (export "empty"))
+        )
+    "#
+        .as_bytes(),
+    )
+    .unwrap();
+    let engine = Universal::new(Singlepass::default()).engine();
+    let tunables = BaseTunables::for_target(engine.target());
+    let executable = engine.compile_universal(&wasm, &tunables).unwrap();
+    let layouts = engine
+        .load_universal_executable(&executable)
+        .unwrap()
+        .frame_layouts()
+        .clone();
+    assert_eq!(layouts.len(), 2);
+    let floats = layouts[LocalFunctionIndex::new(0)].expect("singlepass describes its frames");
+    assert_eq!(floats.register_locals, 5);
+    assert!(floats.locals_offset > floats.save_area_offset);
+    // Without register locals, only vmctx is saved in the save area.
+    let empty = layouts[LocalFunctionIndex::new(1)].unwrap();
+    assert_eq!(empty.register_locals, 0);
+    assert!(empty.save_area_offset < floats.save_area_offset);
+
+    let serialized = executable.serialize().unwrap();
+    let archived = unsafe { UniversalExecutableRef::deserialize(&serialized) }.unwrap();
+    let artifact = engine.load_universal_executable_ref(&archived).unwrap();
+    assert_eq!(artifact.frame_layouts(), &layouts);
+    let owned = archived.to_owned().unwrap();
+    let artifact = engine.load_universal_executable(&owned).unwrap();
+    assert_eq!(artifact.frame_layouts(), &layouts);
+}
+
+#[test]
+fn modules_load_from_mapped_files() -> Result<()> {
+    let wasm = wat2wasm(
+        r#"
+        (module
+        (memory 1)
+        (data (i32.const 8) "\2a")
+        (func (export "load") (result i32) (i32.load8_u (i32.const 8)))
+        )
+    "#
+        .as_bytes(),
+    )?;
+    let engine = Universal::new(Singlepass::default()).engine();
+    let tunables = BaseTunables::for_target(engine.target());
+    let executable = engine.compile_universal(&wasm, &tunables)?;
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("module.bin");
+    executable.serialize_to_file(&path).unwrap();
+
+    let store = Store::new(&engine);
+    let module = unsafe { Module::deserialize_from_file_mmap(&store, &path) }?;
+    let instance = Instance::new(&module, &imports! {})?;
+    let load: NativeFunc<(), i32> = instance.get_native_function("load")?;
+    assert_eq!(load.call()?, 42);
+    // The executable doesn't hold the binary to render the module from.
+    assert!(matches!(module.to_wat(), Err(ToWatError::NoBinary)));
+    drop((instance, module));
+
+    // Files that changed since they were written are rejected.
+    let mut bytes = std::fs::read(&path)?;
+    let last = bytes.len() - 1;
+    bytes[last] ^= 1;
+    std::fs::write(&path, &bytes)?;
+    match unsafe { Module::deserialize_from_file_mmap(&store, &path) } {
+        Err(DeserializeError::CorruptedBinary(_)) => {}
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+    std::fs::write(&path, &bytes[..last])?;
+    match unsafe { Module::deserialize_from_file_mmap(&store, &path) } {
+        Err(DeserializeError::CorruptedBinary(_)) => {}
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+    std::fs::write(&path, &wasm)?;
+    match unsafe { Module::deserialize_from_file_mmap(&store, &path) } {
+        Err(DeserializeError::Incompatible(_)) => {}
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+    Ok(())
+}
+
+/// Whether the file at `path` is mapped in the process.
+fn is_mapped(path: &std::path::Path) -> Result<bool> {
+    let maps = std::fs::read_to_string("/proc/self/maps")?;
+    let path = path.canonicalize()?;
+    Ok(maps
+        .lines()
+        .any(|line| line.ends_with(&*path.to_string_lossy())))
+}
+
+#[test]
+fn mapped_modules_borrow_their_data_from_the_file() -> Result<()> {
+    let mut wasm = wat2wasm(
+        r#"
+        (module
+        (memory 1)
+        (data (i32.const 8) "\2a")
+        (data "\07")
+        (func (export "load") (param i32) (result i32) (i32.load8_u (local.get 0)))
+        (func (export "init") (memory.init 1 (i32.const 16) (i32.const 0) (i32.const 1)))
+        )
+    "#
+        .as_bytes(),
+    )?
+    .into_owned();
+    // A custom section `extra`, holding `contents`.
+    wasm.extend_from_slice(b"\0\x0e\x05extracontents");
+    let engine = Universal::new(Singlepass::default()).engine();
+    let tunables = BaseTunables::for_target(engine.target());
+    let executable = engine.compile_universal(&wasm, &tunables)?;
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("module.bin");
+    executable.serialize_to_file(&path).unwrap();
+
+    let store = Store::new(&engine);
+    let module = unsafe { Module::deserialize_from_file_mmap(&store, &path) }?;
+    assert!(is_mapped(&path)?);
+    let sections: Vec<&[u8]> = module.custom_sections("extra").collect();
+    assert_eq!(sections, [&b"contents"[..]]);
+    let instance = Instance::new(&module, &imports! {})?;
+    let load: NativeFunc<i32, i32> = instance.get_native_function("load")?;
+    let init: NativeFunc<(), ()> = instance.get_native_function("init")?;
+    init.call()?;
+    assert_eq!(load.call(8)?, 42);
+    assert_eq!(load.call(16)?, 7);
+
+    // The instances keep the file mapped, until they are dropped along with the module.
+    drop(module);
+    assert!(is_mapped(&path)?);
+    drop((load, init, instance));
+    assert!(!is_mapped(&path)?);
+    Ok(())
+}
+
+#[test]
+fn deserialization_checks_hash_and_fingerprint() -> Result<()> {
+    let wasm = wat2wasm(br#"(module (func (export "run") (result i32) (i32.const 7)))"#)?;
+    let engine = Universal::new(Singlepass::default()).engine();
+    let tunables = BaseTunables::for_target(engine.target());
+    let serialized = engine
+        .compile(&wasm, &tunables)
+        .unwrap()
+        .serialize()
+        .unwrap();
+    unsafe { engine.deserialize_universal(&serialized) }?;
+
+    let mut corrupted = serialized.clone();
+    let middle = corrupted.len() / 2;
+    corrupted[middle] ^= 1;
+    match unsafe { engine.deserialize_universal(&corrupted) } {
+        Err(DeserializeError::CorruptedBinary(_)) => {}
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+
+    let mut compiler = Singlepass::default();
+    compiler.enable_peephole(true);
+    let other_compiler = Universal::new(compiler).engine();
+    match unsafe { other_compiler.deserialize_universal(&serialized) } {
+        Err(e @ DeserializeError::IncompatibleFingerprint { .. }) => {
+            let message = e.to_string();
+            assert!(message.contains("peephole: false"), "{}", message);
+            assert!(message.contains("peephole: true"), "{}", message);
+            assert!(!message.contains("features"), "{}", message);
+        }
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+    // The fingerprint can be ignored, but not the hash.
+    unsafe { other_compiler.deserialize_universal_ignoring_fingerprint(&serialized) }?;
+    match unsafe { other_compiler.deserialize_universal_ignoring_fingerprint(&corrupted) } {
+        Err(DeserializeError::CorruptedBinary(_)) => {}
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+
+    let mut features = Features::default();
+    features.bulk_memory(!features.bulk_memory);
+    let other_features = Universal::new(Singlepass::default())
+        .features(features)
+        .engine();
+    match unsafe { other_features.deserialize_universal(&serialized) } {
+        Err(DeserializeError::IncompatibleFingerprint { expected, found }) => {
+            assert!(expected.starts_with("features"), "{}", expected);
+            assert!(found.starts_with("features"), "{}", found);
+        }
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+
+    // Headless engines don't know the compiler, so they only check what they do know.
+    let headless = Universal::headless().engine();
+    unsafe { headless.deserialize_universal(&serialized) }?;
+    Ok(())
+}
+
+/// The fuel costs are part of the fingerprint: code metered with other costs, or not metered,
+/// isn't loaded.
+#[test]
+fn deserialization_checks_fuel_costs() -> Result<()> {
+    fn one_per_operator(_: &wasmparser::Operator) -> u64 {
+        1
+    }
+    fn same_costs(_: &wasmparser::Operator) -> u64 {
+        1
+    }
+    fn expensive_calls(operator: &wasmparser::Operator) -> u64 {
+        match operator {
+            wasmparser::Operator::Call { .. } => 10,
+            _ => 1,
+        }
+    }
+    fn engine(cost_fn: Option<fn(&wasmparser::Operator) -> u64>) -> UniversalEngine {
+        let mut compiler = Singlepass::default();
+        if let Some(cost_fn) = cost_fn {
+            compiler.enable_metering(cost_fn);
+        }
+        Universal::new(compiler).engine()
+    }
+
+    let wasm = wat2wasm(br#"(module (func (export "run") (result i32) (i32.const 7)))"#)?;
+    let engine_metered = engine(Some(one_per_operator));
+    let tunables = BaseTunables::for_target(engine_metered.target());
+    let serialized = engine_metered
+        .compile(&wasm, &tunables)
+        .unwrap()
+        .serialize()
+        .unwrap();
+    unsafe { engine(Some(same_costs)).deserialize_universal(&serialized) }?;
+    let others: [Option<fn(&wasmparser::Operator) -> u64>; 2] = [None, Some(expensive_calls)];
+    for other in others {
+        match unsafe { engine(other).deserialize_universal(&serialized) } {
+            Err(DeserializeError::IncompatibleFingerprint { .. }) => {}
+            result => panic!("unexpected result: {:?}", result.map(|_| ())),
+        }
+    }
+    Ok(())
+}
+
+/// Epoch interruption is part of the fingerprint: code without the deadline checks isn't
+/// loaded by an engine that interrupts code, nor the other way around.
+#[test]
+fn deserialization_checks_epoch_interruption() -> Result<()> {
+    let wasm = wat2wasm(br#"(module (func (export "run") (loop $l (br $l))))"#)?;
+    let mut compiler = Singlepass::default();
+    compiler.enable_epoch_interruption(true);
+    let interruptible = Universal::new(compiler).engine();
+    let tunables = BaseTunables::for_target(interruptible.target());
+    let serialized = interruptible
+        .compile(&wasm, &tunables)
+        .unwrap()
+        .serialize()
+        .unwrap();
+    unsafe { interruptible.deserialize_universal(&serialized) }?;
+
+    let uninterruptible = Universal::new(Singlepass::default()).engine();
+    match unsafe { uninterruptible.deserialize_universal(&serialized) } {
+        Err(e @ DeserializeError::IncompatibleFingerprint { .. }) => {
+            let message = e.to_string();
+            assert!(message.contains("epoch interruption: true"), "{}", message);
+            assert!(message.contains("epoch interruption: false"), "{}", message);
+        }
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+    Ok(())
+}
+
 // #[compiler_test(serialize)]
 // fn test_deserialize(config: crate::Config) -> Result<()> {
 //     let store = config.store();
@@ -0,0 +1,106 @@
+//! Testing the modules loaded from shared objects linked from the object files of executables.
+
+#![cfg(all(target_os = "linux", target_arch = "x86_64"))]
+
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use wasmer::*;
+use wasmer_vm::TrapCode;
+
+/// Compiles `wat` with `engine`, and links its object file into a shared object in `dir`.
+fn link_shared_object(engine: &UniversalEngine, wat: &str, dir: &Path) -> Result<PathBuf> {
+    let tunables = BaseTunables::for_target(engine.target());
+    let executable = engine.compile_universal(&wat2wasm(wat.as_bytes())?, &tunables)?;
+    let object = dir.join("module.o");
+    std::fs::write(&object, executable.write_object()?)?;
+    let library = dir.join("module.so");
+    let status = Command::new("cc")
+        .arg("-shared")
+        .arg("-o")
+        .arg(&library)
+        .arg(&object)
+        .status()?;
+    assert!(status.success(), "cc failed: {}", status);
+    Ok(library)
+}
+
+#[test]
+fn shared_objects_run_their_code() -> Result<()> {
+    let wat = r#"(module
+        (import "host" "offset" (func $offset (result i32)))
+        (type $unary (func (param i32) (result i32)))
+        (table funcref (elem $double))
+        (memory (export "memory") 1)
+        (data (i32.const 16) "\2a")
+        (func $double (param i32) (result i32)
+            (i32.mul (local.get 0) (i32.const 2)))
+        (func (export "run") (param i32) (result i32)
+            (i32.add
+                (i32.add (call $offset) (i32.load8_u (i32.const 16)))
+                (call_indirect (type $unary)
+                    (call $double (local.get 0))
+                    (i32.const 0))))
+        (func (export "branch") (param i32) (result i32)
+            (block (block (block
+                (br_table 0 1 2 (local.get 0)))
+                (return (i32.const 10)))
+                (return (i32.const 20)))
+            (i32.const 30))
+        (func $trap (export "trap") (unreachable)))"#;
+    let engine = Universal::new(Singlepass::default()).engine();
+    let dir = tempfile::tempdir()?;
+    let library = link_shared_object(&engine, wat, dir.path())?;
+
+    let store = Store::new(&engine);
+    let module = unsafe { Module::from_shared_object(&store, &library) }?;
+    assert!(module.is_position_independent());
+    let imports = imports! {
+        "host" => { "offset" => Function::new_native(&store, || 1) },
+    };
+    let instance = Instance::new(&module, &imports)?;
+    let run = instance.get_native_function::<i32, i32>("run")?;
+    assert_eq!(run.call(5)?, 63);
+    let branch = instance.get_native_function::<i32, i32>("branch")?;
+    for (input, output) in [(0, 10), (1, 20), (2, 30), (7, 30)] {
+        assert_eq!(branch.call(input)?, output);
+    }
+    let trap = instance.get_native_function::<(), ()>("trap")?;
+    let error = trap.call().unwrap_err();
+    assert_eq!(
+        error.clone().to_trap(),
+        Some(TrapCode::UnreachableCodeReached)
+    );
+    assert_eq!(error.trace()[0].function_name(), Some("trap"));
+
+    // The shared object stays loaded while the module is.
+    drop(instance);
+    let again = Instance::new(&module, &imports)?;
+    assert_eq!(again.get_native_function::<i32, i32>("run")?.call(1)?, 47);
+    Ok(())
+}
+
+#[test]
+fn shared_objects_are_loaded_by_compatible_engines() -> Result<()> {
+    let engine = Universal::new(Singlepass::default()).engine();
+    let dir = tempfile::tempdir()?;
+    let library = link_shared_object(&engine, r#"(module (func (export "f")))"#, dir.path())?;
+
+    let mut features = Features::default();
+    features.simd(!features.simd);
+    let other = Universal::new(Singlepass::default())
+        .features(features)
+        .engine();
+    match unsafe { Module::from_shared_object(&Store::new(&other), &library) } {
+        Err(DeserializeError::IncompatibleFingerprint { .. }) => {}
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+
+    // Files that aren't shared objects are refused.
+    let object = dir.path().join("module.o");
+    match unsafe { Module::from_shared_object(&Store::new(&engine), &object) } {
+        Err(DeserializeError::Generic(message)) => assert!(message.contains("module.o")),
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+    Ok(())
+}
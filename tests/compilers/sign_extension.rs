@@ -0,0 +1,117 @@
+//! Tests of the sign extension operators, on values in registers, on the stack and constant.
+
+use anyhow::Result;
+use wasmer::*;
+
+/// A module exporting, for each sign extension operator `op` of type `ty`:
+/// - `op`, extending its parameter, which is in a register;
+/// - `op.deep`, extending its parameter below so many values that it is on the stack;
+/// - `op.<index>`, extending the constant `constants[index]`.
+fn sign_extension_wat(ty: &str, ops: &[&str], constants: &[i64]) -> String {
+    let mut wat = "(module".to_string();
+    for op in ops {
+        let deep = format!(
+            "{copies} {ty}.{op} local.set 1 {drops} local.get 1",
+            copies = "local.get 0 ".repeat(32),
+            ty = ty,
+            op = op,
+            drops = "drop ".repeat(31),
+        );
+        wat += &format!(
+            r#"
+            (func (export "{op}") (param {ty}) (result {ty}) ({ty}.{op} (local.get 0)))
+            (func (export "{op}.deep") (param {ty}) (result {ty}) (local {ty}) {deep})"#,
+            ty = ty,
+            op = op,
+            deep = deep,
+        );
+        for (index, constant) in constants.iter().enumerate() {
+            wat += &format!(
+                r#"
+                (func (export "{op}.{index}") (result {ty}) ({ty}.{op} ({ty}.const {constant})))"#,
+                ty = ty,
+                op = op,
+                index = index,
+                constant = constant,
+            );
+        }
+    }
+    wat + ")"
+}
+
+#[compiler_test(sign_extension)]
+fn i32_sign_extensions(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let inputs = [
+        0,
+        0x7f,
+        0x80,
+        0xff,
+        0x100,
+        0x7fff,
+        0x8000,
+        0xffff,
+        0x1_0000,
+        0x1234_5680,
+    ];
+    let constants = inputs.iter().map(|&x| x as i32 as i64).collect::<Vec<_>>();
+    let ops = ["extend8_s", "extend16_s"];
+    let wat = sign_extension_wat("i32", &ops, &constants);
+    let instance = Instance::new(&Module::new(&store, wat)?, &imports! {})?;
+
+    for (op, extend) in [
+        ("extend8_s", (|x: u32| x as i8 as i32) as fn(u32) -> i32),
+        ("extend16_s", |x: u32| x as i16 as i32),
+    ] {
+        let register: NativeFunc<i32, i32> = instance.get_native_function(op)?;
+        let deep: NativeFunc<i32, i32> = instance.get_native_function(&format!("{}.deep", op))?;
+        for (index, &x) in inputs.iter().enumerate() {
+            let constant: NativeFunc<(), i32> =
+                instance.get_native_function(&format!("{}.{}", op, index))?;
+            assert_eq!(register.call(x as i32)?, extend(x), "{}({:#x})", op, x);
+            assert_eq!(deep.call(x as i32)?, extend(x), "{}({:#x}) deep", op, x);
+            assert_eq!(constant.call()?, extend(x), "{}({:#x}) constant", op, x);
+        }
+    }
+    Ok(())
+}
+
+#[compiler_test(sign_extension)]
+fn i64_sign_extensions(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let inputs = [
+        0,
+        0x7f,
+        0x80,
+        0x7fff,
+        0x8000,
+        0xffff,
+        0x7fff_ffff,
+        0x8000_0000,
+        0xffff_ffff,
+        0x1_0000_0000,
+        0x0123_4567_89ab_cd80,
+        u64::MAX,
+    ];
+    let constants = inputs.iter().map(|&x| x as i64).collect::<Vec<_>>();
+    let ops = ["extend8_s", "extend16_s", "extend32_s"];
+    let wat = sign_extension_wat("i64", &ops, &constants);
+    let instance = Instance::new(&Module::new(&store, wat)?, &imports! {})?;
+
+    for (op, extend) in [
+        ("extend8_s", (|x: u64| x as i8 as i64) as fn(u64) -> i64),
+        ("extend16_s", |x: u64| x as i16 as i64),
+        ("extend32_s", |x: u64| x as i32 as i64),
+    ] {
+        let register: NativeFunc<i64, i64> = instance.get_native_function(op)?;
+        let deep: NativeFunc<i64, i64> = instance.get_native_function(&format!("{}.deep", op))?;
+        for (index, &x) in inputs.iter().enumerate() {
+            let constant: NativeFunc<(), i64> =
+                instance.get_native_function(&format!("{}.{}", op, index))?;
+            assert_eq!(register.call(x as i64)?, extend(x), "{}({:#x})", op, x);
+            assert_eq!(deep.call(x as i64)?, extend(x), "{}({:#x}) deep", op, x);
+            assert_eq!(constant.call()?, extend(x), "{}({:#x}) constant", op, x);
+        }
+    }
+    Ok(())
+}
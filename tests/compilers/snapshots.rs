@@ -0,0 +1,105 @@
+//! Testing the snapshots of the state of instances, restored into new instances.
+
+use anyhow::Result;
+use wasmer::*;
+
+/// A module stepping a state kept in a global, calling through a table, and logging each step
+/// in the second page of its memory.
+const WAT: &str = r#"
+    (type $op (func (param i64) (result i64)))
+    (memory (export "memory") 2)
+    (global $state (mut i64) (i64.const 1))
+    (global $steps (export "steps") (mut i32) (i32.const 0))
+    (table 2 funcref)
+    (elem (i32.const 0) $double $triple)
+    (func $double (param i64) (result i64)
+        (i64.mul (local.get 0) (i64.const 2)))
+    (func $triple (param i64) (result i64)
+        (i64.mul (local.get 0) (i64.const 3)))
+    (func (export "step") (result i64)
+        (global.set $state
+            (i64.add
+                (call_indirect (type $op)
+                    (global.get $state)
+                    (i32.wrap_i64 (i64.and (global.get $state) (i64.const 1))))
+                (i64.extend_i32_u (global.get $steps))))
+        (i64.store offset=0x10000
+            (i32.mul (global.get $steps) (i32.const 8))
+            (global.get $state))
+        (global.set $steps (i32.add (global.get $steps) (i32.const 1)))
+        (global.get $state))
+"#;
+
+/// Run `steps` steps of `instance`, returning the states.
+fn run(instance: &Instance, steps: usize) -> Result<Vec<i64>> {
+    let step: NativeFunc<(), i64> = instance.get_native_function("step")?;
+    Ok((0..steps).map(|_| step.call()).collect::<Result<_, _>>()?)
+}
+
+fn memory_contents(instance: &Instance) -> Result<Vec<u8>> {
+    let memory = instance.lookup_memory("memory")?;
+    Ok(memory.copy_to_vec(0..memory.data_size())?)
+}
+
+#[compiler_test(snapshots)]
+fn restored_instances_continue_identically(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let module = Module::new(&store, WAT)?;
+    let instance = Instance::new(&module, &imports! {})?;
+    run(&instance, 5)?;
+    let bytes = instance.snapshot()?.serialize();
+    // Only the chunk of the memory written to is kept.
+    assert!(bytes.len() < 8192, "{}", bytes.len());
+    let dense = instance.snapshot_with_zero_pages()?.serialize();
+    assert!(dense.len() > 2 * 0x10000, "{}", dense.len());
+    let states = run(&instance, 5)?;
+
+    for bytes in [bytes, dense] {
+        let snapshot = unsafe { InstanceSnapshot::deserialize(&bytes) }.unwrap();
+        assert_eq!(snapshot.module_hash(), module.hash());
+        let restored = Instance::restore(&module, &imports! {}, &snapshot)?;
+        assert_eq!(restored.lookup_global("steps")?.get(), Value::I32(5));
+        assert_eq!(run(&restored, 5)?, states);
+        assert_eq!(memory_contents(&restored)?, memory_contents(&instance)?);
+    }
+    Ok(())
+}
+
+#[compiler_test(snapshots)]
+fn snapshots_are_checked(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let module = Module::new(&store, WAT)?;
+    let instance = Instance::new(&module, &imports! {})?;
+    let mut bytes = instance.snapshot()?.serialize();
+
+    let other = Module::new(&store, WAT.replace("(i64.const 3)", "(i64.const 5)"))?;
+    let snapshot = unsafe { InstanceSnapshot::deserialize(&bytes) }.unwrap();
+    assert!(matches!(
+        Instance::restore(&other, &imports! {}, &snapshot),
+        Err(SnapshotError::OtherModule)
+    ));
+
+    let last = bytes.len() - 9;
+    bytes[last] ^= 1;
+    assert!(unsafe { InstanceSnapshot::deserialize(&bytes) }.is_err());
+    assert!(unsafe { InstanceSnapshot::deserialize(b"snapshot") }.is_err());
+    Ok(())
+}
+
+#[compiler_test(snapshots)]
+fn host_references_are_rejected(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let module = Module::new(&store, r#"(table (export "table") 1 funcref)"#)?;
+    let instance = Instance::new(&module, &imports! {})?;
+    instance.snapshot()?;
+
+    let host = Function::new_native(&store, || {});
+    instance
+        .lookup_table("table")?
+        .set(0, Val::FuncRef(Some(host)))?;
+    match instance.snapshot() {
+        Err(SnapshotError::Capture(reason)) => assert!(reason.contains("table"), "{}", reason),
+        result => panic!("unexpected result: {:?}", result),
+    }
+    Ok(())
+}
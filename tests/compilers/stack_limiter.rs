@@ -232,3 +232,70 @@ fn deep_but_sane() {
     let e = main_func.call(&[]);
     assert!(e.is_ok());
 }
+
+const RECURSE_WAT: &str = r#"
+    (func $foo (param $depth i32)
+        (br_if 0 (i32.eqz (local.get $depth)))
+        (call $foo (i32.sub (local.get $depth) (i32.const 1)))
+    )
+    (func (export "main") (param $depth i32)
+        (call $foo (local.get $depth))
+    )
+"#;
+
+fn native_limit_instance(enable_checks: bool) -> Instance {
+    let mut compiler = Singlepass::default();
+    compiler.enable_stack_limit_checks(enable_checks);
+    let store = Store::new(&Universal::new(compiler).engine());
+    let module = Module::new(&store, RECURSE_WAT).unwrap();
+    // Keep the slot-counting stack meter out of the way.
+    let instance = Instance::new_with_config(
+        &module,
+        unsafe { InstanceConfig::default().with_stack_limit(0x7FFF_FFFF) },
+        &imports! {},
+    )
+    .unwrap();
+    // Leave only 64 KiB of native stack below the current frame.
+    let here = 0u8;
+    instance.set_native_stack_limit(&here as *const u8 as usize - 64 * 1024);
+    instance
+}
+
+#[test]
+fn native_stack_limit_hit() {
+    let instance = native_limit_instance(true);
+    let main_func = instance
+        .lookup_function("main")
+        .expect("expected function main");
+    // A few frames fit below the limit.
+    main_func.call(&[Value::I32(10)]).unwrap();
+    // Thousands of frames do not, but still far from the guard page.
+    match main_func.call(&[Value::I32(5000)]) {
+        Err(err) => {
+            let trap = err.to_trap().unwrap();
+            assert_eq!(trap, TrapCode::StackOverflow);
+        }
+        _ => assert!(false),
+    }
+    // The instance remains usable after the trap.
+    main_func.call(&[Value::I32(10)]).unwrap();
+}
+
+#[test]
+fn native_stack_limit_ignored_without_checks() {
+    let instance = native_limit_instance(false);
+    let main_func = instance
+        .lookup_function("main")
+        .expect("expected function main");
+    main_func.call(&[Value::I32(5000)]).unwrap();
+}
+
+#[test]
+fn native_stack_limit_removed() {
+    let instance = native_limit_instance(true);
+    instance.set_native_stack_limit(0);
+    let main_func = instance
+        .lookup_function("main")
+        .expect("expected function main");
+    main_func.call(&[Value::I32(5000)]).unwrap();
+}
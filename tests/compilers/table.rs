@@ -0,0 +1,166 @@
+//! Testing the manipulation of tables from the host.
+
+use anyhow::Result;
+use wasmer::*;
+use wasmer_vm::TrapCode;
+
+fn get_instance(store: &Store) -> Result<Instance> {
+    let wat = r#"
+        (type $binary (func (param i32 i32) (result i32)))
+        (table (export "table") 2 4 funcref)
+        (table (export "externs") 1 externref)
+        (func (export "call") (param $index i32) (param $a i32) (param $b i32) (result i32)
+            (call_indirect (type $binary) (local.get $a) (local.get $b) (local.get $index)))
+        (func (export "sub") (param i32 i32) (result i32)
+            (i32.sub (local.get 0) (local.get 1)))
+    "#;
+    let module = Module::new(&store, &wat)?;
+    Ok(Instance::new(&module, &imports! {})?)
+}
+
+fn mul(a: i32, b: i32) -> i32 {
+    a * b
+}
+
+fn negate(a: i32) -> i32 {
+    -a
+}
+
+#[compiler_test(table)]
+fn host_functions_are_called_indirectly(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let instance = get_instance(&store)?;
+    let table = instance.lookup_table("table")?;
+    let call = instance.get_native_function::<(i32, i32, i32), i32>("call")?;
+
+    table.set(0, Function::new_native(&store, mul).into())?;
+    assert_eq!(call.call(0, 6, 7)?, 42);
+
+    // The function read back from the table is still the host function.
+    match table.get(0) {
+        Some(Value::FuncRef(Some(f))) => {
+            assert_eq!(f.native::<(i32, i32), i32>()?.call(3, 4)?, 12);
+        }
+        other => panic!("unexpected table element: {:?}", other),
+    }
+    table.set(1, instance.lookup_function("sub")?.into())?;
+    assert_eq!(call.call(1, 6, 7)?, -1);
+    Ok(())
+}
+
+#[compiler_test(table)]
+fn call_indirect_checks_host_function_signatures(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let instance = get_instance(&store)?;
+    let table = instance.lookup_table("table")?;
+    let call = instance.get_native_function::<(i32, i32, i32), i32>("call")?;
+
+    table.set(0, Function::new_native(&store, negate).into())?;
+    let err = call.call(0, 6, 7).unwrap_err();
+    assert_eq!(err.to_trap(), Some(TrapCode::BadSignature));
+    let err = call.call(1, 6, 7).unwrap_err();
+    assert_eq!(err.to_trap(), Some(TrapCode::IndirectCallToNull));
+    Ok(())
+}
+
+#[compiler_test(table)]
+fn tables_grow_and_fill(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let instance = get_instance(&store)?;
+    let table = instance.lookup_table("table")?;
+    let call = instance.get_native_function::<(i32, i32, i32), i32>("call")?;
+    let mul = Function::new_native(&store, mul);
+
+    assert_eq!(table.grow(1, mul.clone().into())?, 2);
+    assert_eq!(table.size(), 3);
+    assert_eq!(call.call(2, 6, 7)?, 42);
+    assert!(matches!(table.get(1), Some(Value::FuncRef(None))));
+    assert!(table.grow(2, Value::FuncRef(None)).is_err());
+    assert_eq!(table.size(), 3);
+
+    table.fill(0, 2, mul.into())?;
+    assert_eq!(call.call(0, 2, 3)?, 6);
+    assert_eq!(call.call(1, 2, 3)?, 6);
+    table.fill(1, 2, Value::FuncRef(None))?;
+    assert!(matches!(table.get(2), Some(Value::FuncRef(None))));
+    assert_eq!(call.call(0, 2, 3)?, 6);
+    Ok(())
+}
+
+#[compiler_test(table)]
+fn invalid_table_accesses_fail(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let instance = get_instance(&store)?;
+    let table = instance.lookup_table("table")?;
+    let externs = instance.lookup_table("externs")?;
+    let mul = Function::new_native(&store, mul);
+
+    assert!(table.get(2).is_none());
+    let err = table.set(2, mul.clone().into()).unwrap_err();
+    assert_eq!(err.to_trap(), Some(TrapCode::TableAccessOutOfBounds));
+    let err = table.fill(1, 2, mul.clone().into()).unwrap_err();
+    assert_eq!(err.to_trap(), Some(TrapCode::TableAccessOutOfBounds));
+    assert!(table.fill(1, u32::MAX, Value::FuncRef(None)).is_err());
+    // Nothing is set by a failed fill.
+    assert!(matches!(table.get(1), Some(Value::FuncRef(None))));
+
+    // Values must be of the element type of the table.
+    assert!(table.set(0, Value::I32(1)).is_err());
+    assert!(table.set(0, Value::ExternRef(ExternRef::null())).is_err());
+    assert!(externs.set(0, mul.clone().into()).is_err());
+    assert!(externs.grow(1, Value::FuncRef(None)).is_err());
+    assert_eq!(externs.size(), 1);
+
+    // Dynamic host functions have no address to be called from a table at.
+    let dynamic = Function::new(&store, mul.ty(), |args| Ok(args.to_vec()));
+    assert!(table.set(0, dynamic.into()).is_err());
+    Ok(())
+}
+
+#[compiler_test(table)]
+fn call_indirect_checks_signatures_across_instances(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let instance = get_instance(&store)?;
+    let table = instance.lookup_table("table")?;
+    let call = instance.get_native_function::<(i32, i32, i32), i32>("call")?;
+
+    // The types are declared in another order than in the first module, so the signatures only
+    // compare equal through the ids the engine gave them.
+    let wat = r#"
+        (type $unary (func (param i32) (result i32)))
+        (type $binary (func (param i32 i32) (result i32)))
+        (type $binary_i64 (func (param i64 i64) (result i64)))
+        (import "first" "table" (table 2 4 funcref))
+        (elem (i32.const 0) $negate $add)
+        (func $negate (type $unary)
+            (i32.sub (i32.const 0) (local.get 0)))
+        (func $add (type $binary)
+            (i32.add (local.get 0) (local.get 1)))
+        (func (export "add_i64") (type $binary_i64)
+            (i64.add (local.get 0) (local.get 1)))
+        (func (export "call") (param $index i32) (param $a i32) (result i32)
+            (call_indirect (type $unary) (local.get $a) (local.get $index)))
+    "#;
+    let module = Module::new(&store, &wat)?;
+    let imports = imports! { "first" => { "table" => table.clone() } };
+    let second = Instance::new(&module, &imports)?;
+    let call_unary = second.get_native_function::<(i32, i32), i32>("call")?;
+
+    assert_eq!(call.call(1, 6, 7)?, 13);
+    assert_eq!(call_unary.call(0, 6)?, -6);
+    let err = call.call(0, 6, 7).unwrap_err();
+    assert_eq!(err.to_trap(), Some(TrapCode::BadSignature));
+    let err = call_unary.call(1, 6).unwrap_err();
+    assert_eq!(err.to_trap(), Some(TrapCode::BadSignature));
+
+    // A function of the same arity, but of other types.
+    table.set(1, second.lookup_function("add_i64")?.into())?;
+    let err = call.call(1, 6, 7).unwrap_err();
+    assert_eq!(err.to_trap(), Some(TrapCode::BadSignature));
+
+    // The trap leaves both instances usable.
+    table.set(1, instance.lookup_function("sub")?.into())?;
+    assert_eq!(call.call(1, 6, 7)?, -1);
+    assert_eq!(call_unary.call(0, 1)?, -1);
+    Ok(())
+}
@@ -0,0 +1,85 @@
+//! Tests of `return_call` and `return_call_indirect`, recursing far deeper than the stack
+//! limit would allow regular calls to.
+
+use anyhow::Result;
+use wasmer::*;
+
+#[compiler_test(tail_calls)]
+fn mutual_recursion_runs_in_constant_stack(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let wat = r#"(module
+        (func $ping (export "ping") (param $n i32) (result i32)
+            (if (result i32) (i32.eqz (local.get $n))
+                (then (i32.const 0))
+                (else (return_call $pong (i32.sub (local.get $n) (i32.const 1))))))
+        (func $pong (param $n i32) (result i32)
+            (if (result i32) (i32.eqz (local.get $n))
+                (then (i32.const 1))
+                (else (return_call $ping (i32.sub (local.get $n) (i32.const 1)))))))"#;
+    let instance = Instance::new(&Module::new(&store, wat)?, &imports! {})?;
+    let ping: NativeFunc<i32, i32> = instance.get_native_function("ping")?;
+    assert_eq!(ping.call(10_000_000)?, 0);
+    assert_eq!(ping.call(10_000_001)?, 1);
+    Ok(())
+}
+
+#[compiler_test(tail_calls)]
+fn indirect_mutual_recursion_runs_in_constant_stack(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let wat = r#"(module
+        (type $step (func (param i32) (result i32)))
+        (table funcref (elem $ping $pong))
+        (func $ping (export "ping") (type $step)
+            (if (result i32) (i32.eqz (local.get 0))
+                (then (i32.const 0))
+                (else (return_call_indirect (type $step)
+                    (i32.sub (local.get 0) (i32.const 1)) (i32.const 1)))))
+        (func $pong (type $step)
+            (if (result i32) (i32.eqz (local.get 0))
+                (then (i32.const 1))
+                (else (return_call_indirect (type $step)
+                    (i32.sub (local.get 0) (i32.const 1)) (i32.const 0))))))"#;
+    let instance = Instance::new(&Module::new(&store, wat)?, &imports! {})?;
+    let ping: NativeFunc<i32, i32> = instance.get_native_function("ping")?;
+    assert_eq!(ping.call(10_000_000)?, 0);
+    assert_eq!(ping.call(10_000_001)?, 1);
+    Ok(())
+}
+
+#[compiler_test(tail_calls)]
+fn callees_take_more_stack_arguments(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    // `$many` takes its arguments on the stack, which `$few` has no room for in its frame.
+    let wat = r#"(module
+        (func $few (export "few") (param $n i64) (param $sum i64) (result i64)
+            (if (result i64) (i64.eqz (local.get $n))
+                (then (local.get $sum))
+                (else (return_call $many (local.get $n) (local.get $sum)
+                    (i64.const 1) (i64.const 2) (i64.const 3) (i64.const 4)
+                    (i64.const 5) (i64.const 6) (i64.const 7) (f64.const 8)))))
+        (func $many (param $n i64) (param $sum i64)
+            (param i64 i64 i64 i64 i64 i64 i64) (param f64) (result i64)
+            (return_call $few
+                (i64.sub (local.get $n) (i64.const 1))
+                (i64.add (local.get $sum)
+                    (i64.add (i64.add (i64.add (local.get 2) (local.get 3))
+                                      (i64.add (local.get 4) (local.get 5)))
+                             (i64.add (i64.add (local.get 6) (local.get 7))
+                                      (i64.add (local.get 8)
+                                               (i64.trunc_f64_s (local.get 9)))))))))"#;
+    let instance = Instance::new(&Module::new(&store, wat)?, &imports! {})?;
+    let few: NativeFunc<(i64, i64), i64> = instance.get_native_function("few")?;
+    assert_eq!(few.call(1_000_000, 0)?, 36_000_000);
+    Ok(())
+}
+
+#[compiler_test(tail_calls)]
+fn tail_calls_need_their_feature(mut config: crate::Config) -> Result<()> {
+    let mut features = Features::default();
+    features.tail_call(false);
+    config.set_features(features);
+    let store = config.store();
+    let wat = r#"(module (func $f (return_call $f)))"#;
+    assert!(Module::new(&store, wat).is_err());
+    Ok(())
+}
@@ -0,0 +1,104 @@
+//! Tests of compiling modules for a target other than the host.
+
+use anyhow::Result;
+use wasmer::*;
+use wasmer_engine::Executable;
+use wasmer_engine_universal::UniversalExecutableRef;
+
+static WAT: &str = r#"(module
+    (func (export "popcnt") (param i64) (result i64) (i64.popcnt (local.get 0))))"#;
+
+fn engine_for(target: Target) -> UniversalEngine {
+    Universal::new(Singlepass::default())
+        .target(target)
+        .engine()
+}
+
+fn target(triple: &str, baseline: &[CpuFeature]) -> Target {
+    let cpu_features = baseline.iter().copied().collect();
+    Target::new(triple.parse().unwrap(), cpu_features)
+}
+
+#[test]
+#[cfg(all(target_arch = "x86_64", target_os = "linux", target_env = "gnu"))]
+fn executables_record_the_cpu_baseline_of_their_target() -> Result<()> {
+    let target = target(
+        "x86_64-unknown-linux-gnu",
+        &[CpuFeature::SSE2, CpuFeature::AVX],
+    );
+    let baseline = *target.cpu_features();
+    let engine = engine_for(target);
+    let tunables = BaseTunables::for_target(engine.target());
+    let executable = engine.compile_universal(&wat2wasm(WAT.as_bytes())?, &tunables)?;
+    let fingerprint = executable.fingerprint();
+    assert_eq!(fingerprint.cpu_features, baseline);
+    assert_eq!(fingerprint.triple, "x86_64-unknown-linux-gnu");
+
+    // The fingerprint survives serialization, and an engine with the host's features loads it.
+    let serialized = executable.serialize().unwrap();
+    let archived = unsafe { UniversalExecutableRef::deserialize(&serialized) }?;
+    assert_eq!(archived.fingerprint(), fingerprint);
+    if CpuFeature::for_host().is_superset(baseline) {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("module.bin");
+        executable.serialize_to_file(&path).unwrap();
+        let host = Universal::new(Singlepass::default()).engine();
+        let store = Store::new(&host);
+        let module = unsafe { Module::deserialize_from_file_mmap(&store, &path) }?;
+        let instance = Instance::new(&module, &imports! {})?;
+        let popcnt: NativeFunc<i64, i64> = instance.get_native_function("popcnt")?;
+        assert_eq!(popcnt.call(0x0f0f)?, 8);
+    }
+    Ok(())
+}
+
+#[test]
+fn singlepass_needs_avx() {
+    let engine = engine_for(target("x86_64-unknown-linux-gnu", &[CpuFeature::SSE2]));
+    let tunables = BaseTunables::for_target(engine.target());
+    match engine.compile_universal(&wat2wasm(WAT.as_bytes()).unwrap(), &tunables) {
+        Err(CompileError::UnsupportedTarget(target)) => assert!(target.contains("AVX")),
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+}
+
+#[test]
+#[cfg(target_arch = "x86_64")]
+fn foreign_modules_compile_but_do_not_instantiate() -> Result<()> {
+    let triple = if cfg!(target_os = "freebsd") {
+        "x86_64-unknown-linux-gnu"
+    } else {
+        "x86_64-unknown-freebsd"
+    };
+    let engine = engine_for(target(triple, &[CpuFeature::SSE2, CpuFeature::AVX]));
+    let store = Store::new(&engine);
+    let module = Module::new(&store, WAT)?;
+    assert_eq!(module.exports().count(), 1);
+    match Instance::new(&module, &imports! {}) {
+        Err(InstantiationError::IncompatibleTarget(reason)) => {
+            assert!(reason.contains(triple), "{}", reason)
+        }
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+
+    // Serialized foreign modules only load into engines for their target.
+    let tunables = BaseTunables::for_target(engine.target());
+    let executable = engine.compile_universal(&wat2wasm(WAT.as_bytes())?, &tunables)?;
+    let host = Universal::new(Singlepass::default()).engine();
+    match unsafe { host.deserialize_universal(&executable.serialize().unwrap()) } {
+        Err(DeserializeError::IncompatibleFingerprint { expected, found }) => {
+            assert!(expected.starts_with("target"), "{}", expected);
+            assert!(found.contains(triple), "{}", found);
+        }
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("module.bin");
+    executable.serialize_to_file(&path).unwrap();
+    let module = unsafe { Module::deserialize_from_file_mmap(&store, &path) }?;
+    assert!(matches!(
+        Instance::new(&module, &imports! {}),
+        Err(InstantiationError::IncompatibleTarget(_))
+    ));
+    Ok(())
+}
@@ -0,0 +1,109 @@
+use wasmer::*;
+use wasmer_engine_universal::UniversalEngine;
+use wasmer_types::entity::EntityRef;
+
+fn get_engine() -> UniversalEngine {
+    let mut features = Features::new();
+    features.threads(true);
+    Universal::new(Singlepass::default())
+        .features(features)
+        .function_stats(true)
+        .engine()
+}
+
+/// Atomic read-modify-write operations keep their operand in a temporary register while the
+/// memory access needs more for the effective address and the memory base. With most of the
+/// registers holding the values computed before them, every one of them exhausts the
+/// temporary registers.
+const RMW_OPS: &[(&str, i64)] = &[
+    ("add", 0),
+    ("sub", 1),
+    ("xor", 0xff),
+    ("or", 0x100),
+    ("and", 0xfff),
+    ("xchg", 42),
+];
+
+fn apply(op: &str, memory: i64, operand: i64) -> i64 {
+    match op {
+        "add" => memory.wrapping_add(operand),
+        "sub" => memory.wrapping_sub(operand),
+        "xor" => memory ^ operand,
+        "or" => memory | operand,
+        "and" => memory & operand,
+        "xchg" => operand,
+        _ => unreachable!(),
+    }
+}
+
+/// Generates a function that applies all of `RMW_OPS` to the same memory cell, mixing the
+/// previous values they return into an accumulator. The first operation uses the parameter.
+fn exhausting_wat() -> String {
+    let mut body = String::new();
+    for (i, (op, operand)) in RMW_OPS.iter().enumerate() {
+        let operand = if i == 0 {
+            "(local.get $x)".to_string()
+        } else {
+            format!("(i64.const {})", operand)
+        };
+        // The multiples of `$x` are held in registers while the operation runs, and cancel out.
+        body.push_str(&format!(
+            "(local.set $acc (i64.add (i64.mul (local.get $acc) (i64.const 31)) \
+             (i64.add (i64.mul (local.get $x) (i64.const 2)) \
+             (i64.add (i64.mul (local.get $x) (i64.const 3)) \
+             (i64.add (i64.mul (local.get $x) (i64.const 5)) \
+             (i64.sub (i64.atomic.rmw.{} (local.get $address) {}) \
+             (i64.mul (local.get $x) (i64.const 10))))))))\n",
+            op, operand
+        ));
+    }
+    format!(
+        r#"(module
+            (memory 1)
+            (func (export "run") (param $x i64) (result i64)
+                (local $acc i64) (local $address i32)
+                (local.set $address (i32.const 8))
+                {}
+                (i64.add (local.get $acc) (i64.atomic.load (i32.const 8)))))"#,
+        body
+    )
+}
+
+fn expected(x: i64) -> i64 {
+    let mut memory = 0i64;
+    let mut acc = 0i64;
+    for (i, (op, operand)) in RMW_OPS.iter().enumerate() {
+        let operand = if i == 0 { x } else { *operand };
+        acc = acc.wrapping_mul(31).wrapping_add(memory);
+        memory = apply(op, memory, operand);
+    }
+    acc.wrapping_add(memory)
+}
+
+#[test]
+fn temp_register_exhaustion_is_handled() {
+    let engine = get_engine();
+    let wat = exhausting_wat();
+    let wasm = wat2wasm(wat.as_bytes()).unwrap();
+    let tunables = BaseTunables::for_target(engine.target());
+    let executable = engine.compile_universal(&wasm, &tunables).unwrap();
+    let artifact = engine.load_universal_executable(&executable).unwrap();
+    let stats = artifact
+        .function_stat(LocalFunctionIndex::new(0))
+        .expect("statistics were requested");
+    assert!(stats.temp_gpr_steals > 0, "{:?}", stats);
+
+    let store = Store::new(&engine);
+    let module = Module::new(&store, &wasm).unwrap();
+    for &x in &[0, 1, -5, 0x1234_5678_9abc, i64::MIN] {
+        // Each run starts from a zeroed memory cell.
+        let instance = Instance::new(&module, &imports! {}).unwrap();
+        let run = instance
+            .lookup_function("run")
+            .expect("expected function run");
+        match run.call(&[Value::I64(x)]).unwrap()[0] {
+            Value::I64(v) => assert_eq!(v, expected(x), "{}", x),
+            ref v => panic!("unexpected result {:?}", v),
+        }
+    }
+}
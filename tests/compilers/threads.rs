@@ -0,0 +1,85 @@
+//! Tests of the threads proposal, with instances sharing a memory from different threads.
+
+use anyhow::Result;
+use std::thread;
+use wasmer::*;
+
+static ATOMICS_WAT: &str = r#"(module
+    (import "env" "memory" (memory 1 1 shared))
+    (func (export "wait") (param $addr i32) (param $timeout i64) (result i32)
+        (memory.atomic.wait32 (local.get $addr) (i32.const 0) (local.get $timeout)))
+    (func (export "notify") (param $addr i32) (result i32)
+        (memory.atomic.notify (local.get $addr) (i32.const 1)))
+    (func (export "load") (param $addr i32) (result i64)
+        (i64.atomic.load (local.get $addr)))
+)"#;
+
+fn instantiate(config: &mut crate::Config) -> Result<(Instance, Memory)> {
+    let mut features = Features::default();
+    features.threads(true);
+    config.set_features(features);
+    let store = config.store();
+    let module = Module::new(&store, ATOMICS_WAT)?;
+    let memory = Memory::new(&store, MemoryType::new(1, Some(1), true))?;
+    let instance = Instance::new(
+        &module,
+        &imports! {
+            "env" => {
+                "memory" => memory.clone(),
+            },
+        },
+    )?;
+    Ok((instance, memory))
+}
+
+#[compiler_test(threads)]
+fn wait_is_woken_up_by_notify(mut config: crate::Config) -> Result<()> {
+    let (instance, memory) = instantiate(&mut config)?;
+    let waiter = thread::spawn(move || -> Result<i32> {
+        let wait: NativeFunc<(i32, i64), i32> = instance.get_native_function("wait")?;
+        Ok(wait.call(8, -1)?)
+    });
+
+    // Another instance importing the same memory, running on this thread.
+    let module = Module::new(memory.store(), ATOMICS_WAT)?;
+    let instance = Instance::new(
+        &module,
+        &imports! {
+            "env" => {
+                "memory" => memory,
+            },
+        },
+    )?;
+    let notify: NativeFunc<i32, i32> = instance.get_native_function("notify")?;
+    // The waiter may not be waiting yet.
+    while notify.call(8)? == 0 {
+        thread::yield_now();
+    }
+    assert_eq!(waiter.join().unwrap()?, 0);
+    assert_eq!(notify.call(8)?, 0);
+    Ok(())
+}
+
+#[compiler_test(threads)]
+fn wait_checks_the_value_and_times_out(mut config: crate::Config) -> Result<()> {
+    let (instance, memory) = instantiate(&mut config)?;
+    let wait: NativeFunc<(i32, i64), i32> = instance.get_native_function("wait")?;
+    assert_eq!(wait.call(0, 1_000_000)?, 2);
+    memory.view::<u32>()[0].set(1);
+    assert_eq!(wait.call(0, -1)?, 1);
+    Ok(())
+}
+
+#[compiler_test(threads)]
+fn unaligned_atomics_trap(mut config: crate::Config) -> Result<()> {
+    let (instance, _memory) = instantiate(&mut config)?;
+    let notify: NativeFunc<i32, i32> = instance.get_native_function("notify")?;
+    let load: NativeFunc<i32, i64> = instance.get_native_function("load")?;
+    for err in [notify.call(2).unwrap_err(), load.call(4).unwrap_err()] {
+        assert_eq!(err.message(), "unaligned atomic access");
+    }
+    // Out of bounds accesses are still reported as such.
+    let err = load.call(0x10000).unwrap_err();
+    assert_eq!(err.message(), "out of bounds memory access");
+    Ok(())
+}
@@ -0,0 +1,86 @@
+//! Testing the timing of the phases of the compilation of modules.
+
+use anyhow::Result;
+use std::time::{Duration, Instant};
+use wasmer::*;
+
+fn engine_with_threads(num_threads: Option<usize>) -> UniversalEngine {
+    let mut compiler = Singlepass::default();
+    compiler.num_threads(num_threads);
+    Universal::new(compiler).engine()
+}
+
+/// A module with enough functions, of several signatures, for every phase to take some time.
+fn mid_size_module() -> Result<Vec<u8>> {
+    let mut wat = String::from(
+        r#"(module
+  (import "env" "imported" (func $imported (param i64) (result i64)))
+  (memory 1)
+  (table 200 funcref)"#,
+    );
+    for i in 0..200 {
+        wat.push_str(&format!(
+            r#"
+  (func $f{i} (export "f{i}") (param i64 i32) (result i64)
+    (i64.store (local.get 1) (local.get 0))
+    (if (result i64) (i32.eqz (local.get 1))
+      (then (call $imported (local.get 0)))
+      (else (i64.mul (i64.load (local.get 1)) (i64.const {i})))))
+  (elem (i32.const {i}) $f{i})"#,
+            i = i
+        ));
+    }
+    wat.push(')');
+    Ok(wat2wasm(wat.as_bytes())?.into_owned())
+}
+
+#[test]
+fn phases_add_up_to_the_compilation() -> Result<()> {
+    let wasm = mid_size_module()?;
+    let store = Store::new(&engine_with_threads(Some(1)));
+    let start = Instant::now();
+    let (module, timing) = Module::new_with_timing(&store, &wasm)?;
+    let wall = start.elapsed();
+
+    let phases = [
+        ("parse", timing.parse),
+        ("validate", timing.validate),
+        ("codegen", timing.codegen),
+        ("function codegen", timing.function_codegen_total),
+        ("trampolines", timing.trampolines),
+        ("finalize", timing.finalize),
+        ("relocation", timing.relocation),
+    ];
+    for (phase, duration) in phases {
+        assert!(duration > Duration::ZERO, "{} took no time", phase);
+    }
+    // Compiled one after the other, the functions take at most the time of the codegen.
+    assert!(timing.function_codegen_total <= timing.codegen);
+    assert!(timing.function_codegen_max <= timing.function_codegen_total);
+    assert!(
+        timing.total() <= wall && timing.total() >= wall / 4,
+        "the phases took {:?} out of {:?}: {:?}",
+        timing.total(),
+        wall,
+        timing
+    );
+    assert_eq!(module.compile_timing(), timing);
+
+    // The modules sharing the compiled code of another report its timing.
+    let (_, shared) = Module::new_with_timing(&store, &wasm)?;
+    assert_eq!(shared, timing);
+    Ok(())
+}
+
+#[test]
+fn parallel_function_times_are_aggregated() -> Result<()> {
+    let wasm = mid_size_module()?;
+    let store = Store::new(&engine_with_threads(Some(4)));
+    let (_, timing) = Module::new_with_timing(&store, &wasm)?;
+    // Each function is accounted for once, whichever thread compiled it.
+    assert!(timing.function_codegen_max > Duration::ZERO);
+    assert!(timing.function_codegen_max <= timing.codegen);
+    assert!(timing.function_codegen_max * 200 >= timing.function_codegen_total);
+    assert!(timing.total() >= timing.codegen);
+    Ok(())
+}
@@ -0,0 +1,108 @@
+//! Tests of the trap hook of stores, told about the traps before they become errors.
+
+use anyhow::Result;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use wasmer::*;
+use wasmer_vm::TrapCode;
+
+/// A store whose trap hook records the traps it is told about.
+fn recording_store(config: &crate::Config) -> (Store, Arc<Mutex<Vec<TrapInfo>>>) {
+    let traps = Arc::new(Mutex::new(Vec::new()));
+    let mut store = config.store();
+    store.set_trap_hook(Box::new({
+        let traps = traps.clone();
+        move |info: &TrapInfo| traps.lock().unwrap().push(info.clone())
+    }));
+    (store, traps)
+}
+
+#[compiler_test(trap_hook)]
+fn the_hook_counts_traps_by_code(config: crate::Config) -> Result<()> {
+    let (store, traps) = recording_store(&config);
+    let modules = [
+        (r#"(module (func (export "run") unreachable))"#, 1),
+        (
+            r#"(module
+                (func (export "run") (result i32)
+                    (i32.div_s (i32.const 1) (i32.const 0))))"#,
+            2,
+        ),
+        (
+            r#"(module
+                (memory 1)
+                (func (export "run") (result i32) (i32.load (i32.const 0x10000))))"#,
+            1,
+        ),
+        (
+            r#"(module
+                (memory 1)
+                (func (export "run")
+                    (memory.fill (i32.const 0xff00) (i32.const 0) (i32.const 0x1000))))"#,
+            1,
+        ),
+        (
+            r#"(module
+                (type $t (func))
+                (table 1 funcref)
+                (func (export "run") (call_indirect (type $t) (i32.const 0))))"#,
+            3,
+        ),
+    ];
+    let mut expected = HashMap::new();
+    for (wat, calls) in modules.iter() {
+        let instance = Instance::new(&Module::new(&store, wat)?, &imports! {})?;
+        let run = instance.lookup_function("run")?;
+        for _ in 0..*calls {
+            traps.lock().unwrap().clear();
+            let error = run.call(&[]).unwrap_err();
+            let code = error.to_trap().expect("the call trapped");
+            *expected.entry(code).or_insert(0) += 1;
+
+            let traps = traps.lock().unwrap();
+            assert_eq!(traps.len(), 1);
+            assert_eq!(traps[0].code, code);
+            assert_eq!(traps[0].instance, Some(instance.id()));
+            let location = traps[0].location.as_ref().expect("the trap has a location");
+            assert_eq!(location.func_index(), 0);
+        }
+    }
+    assert_eq!(expected.values().sum::<i32>(), 8);
+    assert_eq!(expected[&TrapCode::UnreachableCodeReached], 1);
+    assert_eq!(expected[&TrapCode::IntegerDivisionByZero], 2);
+    assert_eq!(expected[&TrapCode::IndirectCallToNull], 3);
+    Ok(())
+}
+
+#[compiler_test(trap_hook)]
+fn the_hook_sees_start_functions_but_not_host_errors(config: crate::Config) -> Result<()> {
+    let (store, traps) = recording_store(&config);
+    let start = r#"(module (func $start unreachable) (start $start))"#;
+    match Instance::new(&Module::new(&store, start)?, &imports! {}) {
+        Err(InstantiationError::Start(error)) => {
+            assert_eq!(error.to_trap(), Some(TrapCode::UnreachableCodeReached))
+        }
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+    assert_eq!(traps.lock().unwrap().len(), 1);
+    assert_eq!(
+        traps.lock().unwrap()[0].code,
+        TrapCode::UnreachableCodeReached
+    );
+
+    // The errors of host functions are not traps of the Wasm code.
+    let fail = Function::new_native(&store, || -> Result<(), RuntimeError> {
+        Err(RuntimeError::new("host failure"))
+    });
+    let wat = r#"(module
+        (import "host" "fail" (func $fail))
+        (func (export "run") (call $fail)))"#;
+    let instance = Instance::new(
+        &Module::new(&store, wat)?,
+        &imports! { "host" => { "fail" => fail } },
+    )?;
+    let run: NativeFunc<(), ()> = instance.get_native_function("run")?;
+    assert_eq!(run.call().unwrap_err().message(), "host failure");
+    assert_eq!(traps.lock().unwrap().len(), 1);
+    Ok(())
+}
@@ -1,6 +1,11 @@
 use anyhow::Result;
 use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use wasmer::*;
+use wasmer_types::entity::EntityRef;
+use wasmer_types::MemoryIndex;
+use wasmer_vm::TrapCode;
 
 #[compiler_test(traps)]
 fn test_trap_return(config: crate::Config) -> Result<()> {
@@ -71,6 +76,58 @@ fn test_trap_trace(config: crate::Config) -> Result<()> {
     Ok(())
 }
 
+#[cfg_attr(target_env = "musl", ignore)]
+#[compiler_test(traps)]
+fn test_trap_trace_offsets(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let wat = r#"
+        (module $calc
+            (func $divide (param i32 i32) (result i32)
+                (i32.div_s (local.get 0) (local.get 1)))
+            (func $middle (param i32) (result i32)
+                (call $divide (i32.const 10) (local.get 0)))
+            (func (export "outer") (param i32) (result i32)
+                (call $middle (local.get 0)))
+        )
+    "#;
+
+    let module = Module::new(&store, wat)?;
+    let instance = Instance::new(&module, &imports! {})?;
+    let outer = instance.get_native_function::<i32, i32>("outer")?;
+    assert_eq!(outer.call(2)?, 5);
+
+    let e = outer.call(0).unwrap_err();
+    let frames = e
+        .trace()
+        .iter()
+        .map(|frame| {
+            (
+                frame.module_name().to_string(),
+                frame.func_index(),
+                frame.function_name().map(str::to_string),
+                frame.module_offset(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        frames,
+        [
+            ("calc".to_string(), 0, Some("divide".to_string()), 0x30),
+            ("calc".to_string(), 1, Some("middle".to_string()), 0x38),
+            ("calc".to_string(), 2, None, 0x3f),
+        ]
+    );
+    assert_eq!(
+        e.to_string(),
+        "\
+RuntimeError: integer divide by zero
+    at divide (calc[0]:0x30)
+    at middle (calc[1]:0x38)
+    at <unnamed> (calc[2]:0x3f)"
+    );
+    Ok(())
+}
+
 #[compiler_test(traps)]
 fn test_trap_trace_cb(config: crate::Config) -> Result<()> {
     let store = config.store();
@@ -264,7 +321,8 @@ fn trap_start_function_import(config: crate::Config) -> Result<()> {
 
 #[compiler_test(traps)]
 fn rust_panic_import(config: crate::Config) -> Result<()> {
-    let store = config.store();
+    let mut store = config.store();
+    store.resume_host_panics(true);
     let binary = r#"
         (module $a
             (import "" "foo" (func $foo))
@@ -308,8 +366,74 @@ fn rust_panic_import(config: crate::Config) -> Result<()> {
 }
 
 #[compiler_test(traps)]
-fn rust_panic_start_function(config: crate::Config) -> Result<()> {
+fn host_panics_become_errors(config: crate::Config) -> Result<()> {
+    #[derive(Clone)]
+    struct DropEnv {
+        dropped: Arc<AtomicBool>,
+    }
+    impl WasmerEnv for DropEnv {}
+
+    struct SetOnDrop(Arc<AtomicBool>);
+    impl Drop for SetOnDrop {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    fn host(env: &DropEnv, x: i32) -> i32 {
+        let _guard = SetOnDrop(env.dropped.clone());
+        if x == 0 {
+            panic!("the host can't handle {}", x);
+        }
+        x
+    }
+
     let store = config.store();
+    let wat = r#"
+        (module
+            (import "" "host" (func $host (param i32) (result i32)))
+            (func $inner (param i32) (result i32)
+                (call $host (local.get 0)))
+            (func $middle (param i32) (result i32)
+                (i32.add (call $inner (local.get 0)) (i32.const 1)))
+            (func (export "outer") (param i32) (result i32)
+                (i32.add (call $middle (local.get 0)) (i32.const 1)))
+        )
+    "#;
+    let module = Module::new(&store, wat)?;
+    let dropped = Arc::new(AtomicBool::new(false));
+    let env = DropEnv {
+        dropped: dropped.clone(),
+    };
+    let host = Function::new_native_with_env(&store, env, host);
+    let instance = Instance::new(
+        &module,
+        &imports! {
+            "" => {
+                "host" => host
+            }
+        },
+    )?;
+    let outer = instance.get_native_function::<i32, i32>("outer")?;
+
+    let err = outer.call(0).unwrap_err();
+    assert!(err.is_host_panic());
+    assert_eq!(err.message(), "the host can't handle 0");
+    assert!(dropped.load(Ordering::SeqCst));
+
+    // The instance keeps working.
+    assert_eq!(outer.call(5)?, 7);
+    let outer = instance.lookup_function("outer").unwrap();
+    let err = outer.call(&[Val::I32(0)]).unwrap_err();
+    assert_eq!(err.message(), "the host can't handle 0");
+    assert_eq!(outer.call(&[Val::I32(1)])?.to_vec(), vec![Val::I32(3)]);
+    Ok(())
+}
+
+#[compiler_test(traps)]
+fn rust_panic_start_function(config: crate::Config) -> Result<()> {
+    let mut store = config.store();
+    store.resume_host_panics(true);
     let binary = r#"
         (module $a
             (import "" "" (func $foo))
@@ -434,7 +558,7 @@ fn start_trap_pretty(config: crate::Config) -> Result<()> {
     assert_eq!(
         format!("{}", err),
         "\
-RuntimeError: unreachable
+could not invoke the start function: RuntimeError: unreachable
     at die (m[0]:0x1d)
     at <unnamed> (m[1]:0x21)
     at foo (m[2]:0x26)
@@ -465,3 +589,167 @@ fn present_after_module_drop(config: crate::Config) -> Result<()> {
         // assert_eq!(t.trace()[0].func_index(), 0);
     }
 }
+
+#[compiler_test(traps)]
+fn signed_division_traps_are_distinct(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let wat = r#"(module
+        (func (export "i32.div_s") (param i32 i32) (result i32)
+            (i32.div_s (local.get 0) (local.get 1)))
+        (func (export "i32.rem_s") (param i32 i32) (result i32)
+            (i32.rem_s (local.get 0) (local.get 1)))
+        (func (export "i64.div_s") (param i64 i64) (result i64)
+            (i64.div_s (local.get 0) (local.get 1)))
+        (func (export "i64.rem_s") (param i64 i64) (result i64)
+            (i64.rem_s (local.get 0) (local.get 1)))
+        (func (export "i32.div_s_minus_one") (param i32) (result i32)
+            (i32.div_s (local.get 0) (i32.const -1)))
+        (func (export "i64.div_s_by_zero") (param i64) (result i64)
+            (i64.div_s (local.get 0) (i64.const 0)))
+        (func (export "i64.rem_s_minus_one") (param i64) (result i64)
+            (i64.rem_s (local.get 0) (i64.const -1)))
+    )"#;
+    let module = Module::new(&store, wat)?;
+    let instance = Instance::new(&module, &imports! {})?;
+
+    let i32_div_s: NativeFunc<(i32, i32), i32> = instance.get_native_function("i32.div_s")?;
+    let i32_rem_s: NativeFunc<(i32, i32), i32> = instance.get_native_function("i32.rem_s")?;
+    let i64_div_s: NativeFunc<(i64, i64), i64> = instance.get_native_function("i64.div_s")?;
+    let i64_rem_s: NativeFunc<(i64, i64), i64> = instance.get_native_function("i64.rem_s")?;
+    let division_by_zero = Some(TrapCode::IntegerDivisionByZero);
+    let overflow = Some(TrapCode::IntegerOverflow);
+
+    assert_eq!(
+        i32_div_s.call(7, 0).unwrap_err().to_trap(),
+        division_by_zero
+    );
+    assert_eq!(
+        i32_div_s.call(i32::MIN, 0).unwrap_err().to_trap(),
+        division_by_zero
+    );
+    assert_eq!(
+        i32_div_s.call(i32::MIN, -1).unwrap_err().to_trap(),
+        overflow
+    );
+    assert_eq!(
+        i32_rem_s.call(7, 0).unwrap_err().to_trap(),
+        division_by_zero
+    );
+    assert_eq!(
+        i64_div_s.call(7, 0).unwrap_err().to_trap(),
+        division_by_zero
+    );
+    assert_eq!(
+        i64_div_s.call(i64::MIN, 0).unwrap_err().to_trap(),
+        division_by_zero
+    );
+    assert_eq!(
+        i64_div_s.call(i64::MIN, -1).unwrap_err().to_trap(),
+        overflow
+    );
+    assert_eq!(
+        i64_rem_s.call(7, 0).unwrap_err().to_trap(),
+        division_by_zero
+    );
+
+    // Only the quotient of `MIN / -1` overflows, not its remainder nor other divisions by -1.
+    assert_eq!(i32_rem_s.call(i32::MIN, -1)?, 0);
+    assert_eq!(i64_rem_s.call(i64::MIN, -1)?, 0);
+    assert_eq!(i32_div_s.call(i32::MAX, -1)?, -i32::MAX);
+    assert_eq!(i64_div_s.call(i64::MIN + 1, -1)?, i64::MAX);
+    assert_eq!(i32_div_s.call(i32::MIN, 2)?, i32::MIN / 2);
+    assert_eq!(i32_rem_s.call(-7, 2)?, -1);
+    assert_eq!(i64_rem_s.call(i64::MIN, 3)?, i64::MIN % 3);
+
+    // The same checks against constant divisors.
+    let minus_one: NativeFunc<i32, i32> = instance.get_native_function("i32.div_s_minus_one")?;
+    assert_eq!(minus_one.call(i32::MIN).unwrap_err().to_trap(), overflow);
+    assert_eq!(minus_one.call(5)?, -5);
+    let by_zero: NativeFunc<i64, i64> = instance.get_native_function("i64.div_s_by_zero")?;
+    assert_eq!(by_zero.call(5).unwrap_err().to_trap(), division_by_zero);
+    let minus_one: NativeFunc<i64, i64> = instance.get_native_function("i64.rem_s_minus_one")?;
+    assert_eq!(minus_one.call(i64::MIN)?, 0);
+    Ok(())
+}
+
+#[compiler_test(traps)]
+fn out_of_bounds_accesses_give_their_address(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let wat = r#"(module
+        (memory 1)
+        (func (export "load8") (param i32) (result i32)
+            (i32.load8_u (local.get 0)))
+        (func (export "load16") (param i32) (result i32)
+            (i32.load16_s offset=3 (local.get 0)))
+        (func (export "load32") (param i32) (result i32)
+            (i32.load (local.get 0)))
+        (func (export "load64") (param i32) (result i64)
+            (i64.load offset=0x10000 (local.get 0)))
+        (func (export "store32") (param i32)
+            (i32.store (local.get 0) (i32.const 1)))
+        (func (export "store64") (param i32)
+            (i64.store offset=8 (local.get 0) (i64.const 1)))
+        (func (export "load_constant") (result i32)
+            (i32.load offset=2 (i32.const 0x10002)))
+        (func (export "grow")
+            (drop (memory.grow (i32.const 1))))
+        (func (export "unreachable")
+            unreachable)
+    )"#;
+    let module = Module::new(&store, wat)?;
+    let instance = Instance::new(&module, &imports! {})?;
+    let load8: NativeFunc<i32, i32> = instance.get_native_function("load8")?;
+    let load16: NativeFunc<i32, i32> = instance.get_native_function("load16")?;
+    let load32: NativeFunc<i32, i32> = instance.get_native_function("load32")?;
+    let load64: NativeFunc<i32, i64> = instance.get_native_function("load64")?;
+    let store32: NativeFunc<i32, ()> = instance.get_native_function("store32")?;
+    let store64: NativeFunc<i32, ()> = instance.get_native_function("store64")?;
+    let load_constant: NativeFunc<(), i32> = instance.get_native_function("load_constant")?;
+    let grow: NativeFunc<(), ()> = instance.get_native_function("grow")?;
+    let oob = |guest_addr: u64, access_size: u32| {
+        Some(OobDetails {
+            guest_addr,
+            access_size,
+            memory_index: MemoryIndex::new(0),
+        })
+    };
+
+    let size = 0x10000;
+    for k in [0, 1, 7, 0x1234] {
+        let error = load8.call(size + k).unwrap_err();
+        assert_eq!(error.oob_details(), oob((size + k) as u64, 1));
+        assert_eq!(error.to_trap(), Some(TrapCode::HeapAccessOutOfBounds));
+        let error = store32.call(size + k).unwrap_err();
+        assert_eq!(error.oob_details(), oob((size + k) as u64, 4));
+        let error = load64.call(k).unwrap_err();
+        assert_eq!(error.oob_details(), oob((size + k) as u64, 8));
+        let error = load16.call(size + k - 3).unwrap_err();
+        assert_eq!(error.oob_details(), oob((size + k) as u64, 2));
+    }
+    // The accesses straddling the end of the memory give their first byte.
+    let error = load32.call(size - 3).unwrap_err();
+    assert_eq!(error.oob_details(), oob(size as u64 - 3, 4));
+    assert_eq!(load32.call(size - 4)?, 0);
+    // The wasm address plus the offset can overflow 32 bits.
+    let error = store64.call(-4).unwrap_err();
+    assert_eq!(error.oob_details(), oob(0x1_0000_0004, 8));
+    let error = load64.call(-1).unwrap_err();
+    assert_eq!(error.oob_details(), oob(0x1_0000_ffff, 8));
+    // Constant addresses too.
+    let error = load_constant.call().unwrap_err();
+    assert_eq!(error.oob_details(), oob(size as u64 + 4, 4));
+
+    grow.call()?;
+    assert_eq!(load8.call(size + 7)?, 0);
+    let size = 2 * 0x10000;
+    for k in [0, 3] {
+        let error = load8.call(size + k).unwrap_err();
+        assert_eq!(error.oob_details(), oob((size + k) as u64, 1));
+    }
+
+    // Only the out-of-bounds accesses have details.
+    let unreachable = instance.lookup_function("unreachable").unwrap();
+    assert_eq!(unreachable.call(&[]).unwrap_err().oob_details(), None);
+    assert_eq!(RuntimeError::new("failed").oob_details(), None);
+    Ok(())
+}
@@ -0,0 +1,118 @@
+//! Testing WASI programs, run with the imports of `wasmer-wasi`.
+
+use anyhow::Result;
+use std::fs;
+use wasmer::*;
+use wasmer_wasi::{wasi_import_object, Pipe, WasiExit, WasiState};
+
+/// Run the `_start` function of `module`, and return the status code it exited with.
+fn run(store: &Store, module: &Module, state: &WasiState) -> Result<u32> {
+    let instance = Instance::new(module, &wasi_import_object(store, state, module))?;
+    let start = instance.get_native_function::<(), ()>("_start")?;
+    match start.call() {
+        Ok(()) => Ok(0),
+        Err(err) => Ok(err.downcast::<WasiExit>()?.0),
+    }
+}
+
+/// Run `cat` on `path`, in a preopened directory containing `hello.txt`, `link.txt` linking
+/// to it and `escape.txt` linking out of the directory, and return the status code it
+/// exited with and its output.
+fn cat(config: crate::Config, path: &str) -> Result<(u32, Vec<u8>)> {
+    let store = config.store();
+    let module = Module::new(&store, include_str!("../examples/wasi_cat.wat"))?;
+    let parent = tempfile::tempdir()?;
+    let root = parent.path().join("root");
+    fs::create_dir(&root)?;
+    fs::write(root.join("hello.txt"), "Hello, WASI!\n")?;
+    fs::write(parent.path().join("secret.txt"), "Out of the sandbox\n")?;
+    std::os::unix::fs::symlink("hello.txt", root.join("link.txt"))?;
+    std::os::unix::fs::symlink("../secret.txt", root.join("escape.txt"))?;
+
+    let stdout = Pipe::new();
+    let state = WasiState::builder("cat")
+        .arg(path)
+        .map_dir("/data", &root)
+        .stdout(stdout.clone())
+        .build()?;
+    let code = run(&store, &module, &state)?;
+    Ok((code, stdout.take()))
+}
+
+#[compiler_test(wasi)]
+fn cat_copies_a_preopened_file(config: crate::Config) -> Result<()> {
+    let (code, stdout) = cat(config, "hello.txt")?;
+    assert_eq!(code, 13);
+    assert_eq!(stdout, b"Hello, WASI!\n");
+    Ok(())
+}
+
+#[compiler_test(wasi)]
+fn cat_fails_to_open_missing_files(config: crate::Config) -> Result<()> {
+    // ENOENT
+    assert_eq!(cat(config, "missing.txt")?, (144, vec![]));
+    Ok(())
+}
+
+#[compiler_test(wasi)]
+fn cat_cannot_escape_the_preopened_directory(config: crate::Config) -> Result<()> {
+    // ENOTCAPABLE
+    assert_eq!(cat(config.clone(), "../secret.txt")?, (176, vec![]));
+    assert_eq!(
+        cat(config.clone(), "./../root/../secret.txt")?,
+        (176, vec![])
+    );
+    assert_eq!(cat(config.clone(), "/secret.txt")?, (176, vec![]));
+    assert_eq!(cat(config, "escape.txt")?, (176, vec![]));
+    Ok(())
+}
+
+#[compiler_test(wasi)]
+fn cat_follows_symbolic_links(config: crate::Config) -> Result<()> {
+    let (code, stdout) = cat(config, "link.txt")?;
+    assert_eq!(code, 13);
+    assert_eq!(stdout, b"Hello, WASI!\n");
+    Ok(())
+}
+
+#[compiler_test(wasi)]
+fn programs_see_their_environment(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let wat = r#"
+        (import "wasi_snapshot_preview1" "environ_sizes_get"
+            (func $environ_sizes_get (param i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+        (memory (export "memory") 1)
+        (func (export "_start")
+            (drop (call $environ_sizes_get (i32.const 0) (i32.const 4)))
+            (call $proc_exit
+                (i32.add (i32.mul (i32.load (i32.const 0)) (i32.const 1000))
+                    (i32.load (i32.const 4)))))
+    "#;
+    let module = Module::new(&store, wat)?;
+    let state = WasiState::builder("env")
+        .env("A", "1")
+        .env("KEY", "value")
+        .build()?;
+    // Two variables, `A=1\0KEY=value\0` taking 14 bytes.
+    assert_eq!(run(&store, &module, &state)?, 2014);
+    Ok(())
+}
+
+#[compiler_test(wasi)]
+fn unsupported_syscalls_fail_with_enosys(config: crate::Config) -> Result<()> {
+    let store = config.store();
+    let wat = r#"
+        (import "wasi_snapshot_preview1" "fd_advise"
+            (func $fd_advise (param i32 i64 i64 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+        (memory (export "memory") 1)
+        (func (export "_start")
+            (call $proc_exit
+                (call $fd_advise (i32.const 0) (i64.const 0) (i64.const 0) (i32.const 0))))
+    "#;
+    let module = Module::new(&store, wat)?;
+    let state = WasiState::builder("advise").build()?;
+    assert_eq!(run(&store, &module, &state)?, 52);
+    Ok(())
+}
@@ -1,4 +1,5 @@
 use ::wasmer::Features;
+use anyhow::Context;
 use std::path::Path;
 use wasmer_wast::Wast;
 
@@ -17,19 +18,41 @@ use wasmer_wast::Wast;
 include!(concat!(env!("OUT_DIR"), "/generated_spectests.rs"));
 
 pub fn run_wast(mut config: crate::Config, wast_path: &str) -> anyhow::Result<()> {
+    run_wast_with(config.clone(), wast_path, false)?;
+    // Rendering the modules in the text format must not change the behaviour of any test.
+    run_wast_with(config.clone(), wast_path, true)
+        .context("with the modules parsed back from the text format")?;
+    if config.compiler == crate::Compiler::Singlepass {
+        // The peephole optimizer must not change the behaviour of any test.
+        config.set_peephole(true);
+        run_wast_with(config, wast_path, false).context("with the peephole optimizer enabled")?;
+    }
+    Ok(())
+}
+
+fn run_wast_with(
+    mut config: crate::Config,
+    wast_path: &str,
+    round_trip_wat: bool,
+) -> anyhow::Result<()> {
     println!("Running wast `{}`", wast_path);
     let try_nan_canonicalization = wast_path.contains("nan-canonicalization");
     let mut features = Features::default();
     let is_bulkmemory = wast_path.contains("bulk-memory");
     let is_simd = wast_path.contains("simd");
+    let is_tail_call = wast_path.contains("tail-call");
+    let is_exception_handling = wast_path.contains("exception-handling");
     if is_bulkmemory {
         features.bulk_memory(true);
     }
     if is_simd {
         features.simd(true);
     }
-    if config.compiler == crate::Compiler::Singlepass {
-        features.multi_value(false);
+    if is_tail_call {
+        features.tail_call(true);
+    }
+    if is_exception_handling {
+        features.exceptions(true);
     }
     config.set_features(features);
     config.set_nan_canonicalization(try_nan_canonicalization);
@@ -53,12 +76,8 @@ pub fn run_wast(mut config: crate::Config, wast_path: &str) -> anyhow::Result<()
             "Validation error: Invalid var_u32",
         ]);
     }
-    if config.compiler == crate::Compiler::Singlepass {
-        // We don't support multivalue yet in singlepass
-        wast.allow_instantiation_failures(&[
-            "Validation error: invalid result arity: func type returns multiple values",
-            "Validation error: blocks, loops, and ifs accept no parameters when multi-value is not enabled",
-        ]);
+    if round_trip_wat {
+        wast.round_trip_wat();
     }
     wast.fail_fast = false;
     let path = Path::new(wast_path);
@@ -0,0 +1,102 @@
+//! Testing the conversion of the WebAssembly text format, over the fixtures of `tests/wat`.
+
+use anyhow::Result;
+use std::fs;
+use wasmer::*;
+
+fn store() -> Store {
+    Store::new(&Universal::new(Singlepass::default()).engine())
+}
+
+/// The location a fixture expects its error at, from its `;; error at <line>:<column>` header.
+fn expected_error(text: &str) -> Option<(usize, usize)> {
+    let location = text.lines().next()?.strip_prefix(";; error at ")?;
+    let (line, column) = location.split_once(':')?;
+    Some((line.parse().ok()?, column.parse().ok()?))
+}
+
+#[test]
+fn fixtures_convert_or_fail_where_expected() -> Result<()> {
+    let store = store();
+    let mut checked = 0;
+    for entry in fs::read_dir("tests/wat")? {
+        let path = entry?.path();
+        if path
+            .extension()
+            .map_or(true, |extension| extension != "wat")
+        {
+            continue;
+        }
+        let text = fs::read_to_string(&path)?;
+        match (wat2wasm(text.as_bytes()), expected_error(&text)) {
+            (Ok(binary), None) => {
+                Module::new(&store, &binary)
+                    .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+            }
+            (Err(error), Some((line, column))) => {
+                assert_eq!(
+                    (error.line, error.column),
+                    (line, column),
+                    "{}: {}",
+                    path.display(),
+                    error
+                );
+                // The caret is under the column.
+                let caret = error.snippet.lines().nth(1).unwrap();
+                assert_eq!(caret.chars().count(), column, "{}", error.snippet);
+                assert!(caret.ends_with('^'));
+            }
+            (Ok(_), Some(_)) => panic!("{} should not convert", path.display()),
+            (Err(error), None) => panic!("{}: {}", path.display(), error),
+        }
+        checked += 1;
+    }
+    assert!(checked >= 5);
+    Ok(())
+}
+
+#[test]
+fn custom_annotations_become_custom_sections() -> Result<()> {
+    let text = fs::read_to_string("tests/wat/custom_sections.wat")?;
+    let module = Module::new(&store(), &text)?;
+    let sections = module.custom_sections("wasmer").collect::<Vec<_>>();
+    assert_eq!(sections, [&b"first"[..], &b"second"[..]]);
+    Ok(())
+}
+
+#[test]
+fn text_is_checked_against_the_features() -> Result<()> {
+    let simd = "(module\n  (func (result v128)\n    (v128.const i64x2 0 0)))";
+    wat2wasm_with_features(simd, &Features::default())?;
+    let mut features = Features::default();
+    features.simd(false);
+    let error = wat2wasm_with_features(simd, &features).unwrap_err();
+    assert_eq!(error.message, "SIMD support is not enabled");
+    assert_eq!((error.line, error.column), (2, 17));
+
+    let threads = "(module (memory 1 1 shared))";
+    let mut features = Features::default();
+    features.threads(true);
+    wat2wasm_with_features(threads, &features)?;
+    let error = wat2wasm_with_features(threads, &Features::default()).unwrap_err();
+    assert_eq!(error.message, "threads support is not enabled");
+    assert_eq!((error.line, error.column), (1, 21));
+
+    let floats = "(module (func (result i32) (i32.reinterpret_f32 (f32.const 1))))";
+    let error = wat2wasm_with_features(floats, &Features::deterministic()).unwrap_err();
+    assert_eq!(error.message, "floating-point support is not enabled");
+    assert_eq!(&floats[error.column - 1..][..9], "f32.const");
+
+    // Names and comments are not keywords.
+    let named = "(module (func $v128 (export \"shared\") ;; f32.add\n))";
+    wat2wasm_with_features(named, &Features::deterministic())?;
+    Ok(())
+}
+
+#[test]
+fn invalid_utf8_is_located() {
+    let error = wat2wasm(b"(module\n  (func \xff))").unwrap_err();
+    assert_eq!((error.line, error.column), (2, 9));
+    assert_eq!(error.snippet, "  (func \n        ^");
+    assert!(error.to_string().contains("--> 2:9"));
+}
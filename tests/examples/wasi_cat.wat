@@ -0,0 +1,86 @@
+(module
+  ;; A WASI `cat`: copies the file named by its argument, opened in the preopened
+  ;; directory 3, to its standard output, and exits with the number of bytes copied.
+  ;; A syscall failing with the error code `errno` exits with `100 + errno`.
+  (import "wasi_snapshot_preview1" "args_sizes_get"
+    (func $args_sizes_get (param i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "args_get"
+    (func $args_get (param i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "path_open"
+    (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_read"
+    (func $fd_read (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_write"
+    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_close"
+    (func $fd_close (param i32) (result i32)))
+  (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+
+  ;; 0: argc, 4: the size of the arguments, 8: the opened fd, 12: nread, 16: nwritten,
+  ;; 32: argv, 64: the arguments, 1088: the iovec, 2048: the buffer.
+  (memory (export "memory") 1)
+
+  (func $check (param $errno i32)
+    (if (local.get $errno)
+      (then (call $proc_exit (i32.add (i32.const 100) (local.get $errno))))
+    )
+  )
+
+  (func $strlen (param $str i32) (result i32)
+    (local $end i32)
+    (local.set $end (local.get $str))
+    (block $done
+      (loop $next
+        (br_if $done (i32.eqz (i32.load8_u (local.get $end))))
+        (local.set $end (i32.add (local.get $end) (i32.const 1)))
+        (br $next)
+      )
+    )
+    (i32.sub (local.get $end) (local.get $str))
+  )
+
+  (func (export "_start")
+    (local $path i32)
+    (local $fd i32)
+    (local $read i32)
+    (local $total i32)
+
+    (call $check (call $args_sizes_get (i32.const 0) (i32.const 4)))
+    ;; EINVAL without exactly one argument, ENAMETOOLONG if it doesn't fit.
+    (if (i32.ne (i32.load (i32.const 0)) (i32.const 2))
+      (then (call $check (i32.const 28)))
+    )
+    (if (i32.gt_u (i32.load (i32.const 4)) (i32.const 1024))
+      (then (call $check (i32.const 37)))
+    )
+    (call $check (call $args_get (i32.const 32) (i32.const 64)))
+    (local.set $path (i32.load (i32.const 36)))
+
+    ;; Open the file for reading (the right `fd_read`).
+    (call $check
+      (call $path_open
+        (i32.const 3) (i32.const 1)
+        (local.get $path) (call $strlen (local.get $path))
+        (i32.const 0) (i64.const 2) (i64.const 0) (i32.const 0)
+        (i32.const 8)))
+    (local.set $fd (i32.load (i32.const 8)))
+
+    (block $eof
+      (loop $copy
+        (i32.store (i32.const 1088) (i32.const 2048))
+        (i32.store (i32.const 1092) (i32.const 4096))
+        (call $check
+          (call $fd_read (local.get $fd) (i32.const 1088) (i32.const 1) (i32.const 12)))
+        (local.set $read (i32.load (i32.const 12)))
+        (br_if $eof (i32.eqz (local.get $read)))
+        (i32.store (i32.const 1092) (local.get $read))
+        (call $check
+          (call $fd_write (i32.const 1) (i32.const 1088) (i32.const 1) (i32.const 16)))
+        (local.set $total (i32.add (local.get $total) (local.get $read)))
+        (br $copy)
+      )
+    )
+    (call $check (call $fd_close (local.get $fd)))
+    (call $proc_exit (local.get $total))
+  )
+)
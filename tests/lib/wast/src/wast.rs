@@ -33,6 +33,9 @@ pub struct Wast {
     /// A flag indicating that assert_trap and assert_exhaustion should be skipped.
     /// See https://github.com/wasmerio/wasmer/issues/1550 for more info
     disable_assert_trap_exhaustion: bool,
+    /// A flag indicating that the modules are rendered in the text format and parsed back
+    /// before they are instantiated.
+    round_trip_wat: bool,
 }
 
 impl Wast {
@@ -49,6 +52,7 @@ impl Wast {
             extern_refs: BTreeMap::new(),
             fail_fast: true,
             disable_assert_trap_exhaustion: false,
+            round_trip_wat: false,
         }
     }
 
@@ -71,6 +75,12 @@ impl Wast {
         self.disable_assert_trap_exhaustion = true;
     }
 
+    /// Instantiate the modules parsed back from their rendering in the text format, rather
+    /// than the modules themselves.
+    pub fn round_trip_wat(&mut self) {
+        self.round_trip_wat = true;
+    }
+
     /// Construct a new instance of `Wast` with the spectests imports.
     pub fn new_with_spectest(store: Store) -> Self {
         let import_object = spectest_importobject(&store);
@@ -158,6 +168,16 @@ impl Wast {
         bail!("expected '{}', got '{}'", expected, actual)
     }
 
+    fn assert_exception(&self, result: Result<Vec<Val>>) -> Result<()> {
+        match result {
+            Ok(values) => bail!("expected exception, got {:?}", values),
+            Err(e) => match e.downcast_ref::<RuntimeError>() {
+                Some(error) if error.is_uncaught_exception() => Ok(()),
+                _ => bail!("expected exception, got '{}'", e),
+            },
+        }
+    }
+
     fn run_directive(&mut self, test: &Path, directive: wast::WastDirective) -> Result<()> {
         use wast::WastDirective::*;
 
@@ -229,8 +249,9 @@ impl Wast {
             QuoteModule { .. } => {
                 // Do nothing
             }
-            AssertException { .. } => {
-                // Do nothing for now
+            AssertException { span: _, exec } => {
+                let result = self.perform_execute(exec);
+                self.assert_exception(result)?;
             }
             AssertMalformed {
                 module,
@@ -379,7 +400,10 @@ impl Wast {
     }
 
     fn instantiate(&self, module: &[u8]) -> Result<Instance> {
-        let module = Module::new(&self.store, module)?;
+        let mut module = Module::new(&self.store, module)?;
+        if self.round_trip_wat {
+            module = Module::new(&self.store, module.to_wat()?)?;
+        }
         let instance = Instance::new(&module, &self)?;
         Ok(instance)
     }
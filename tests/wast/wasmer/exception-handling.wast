@@ -0,0 +1,136 @@
+;; Exceptions thrown and caught within a module, across modules, and through
+;; nested, delegating and rethrowing handlers.
+
+(module $thrower
+  (tag $e0 (export "e0"))
+  (tag $e1 (export "e1") (param i32))
+  (func (export "throw-e0") (throw $e0))
+  (func (export "throw-e1") (param i32) (throw $e1 (local.get 0))))
+
+(register "thrower" $thrower)
+
+(module
+  (tag $e0 (import "thrower" "e0"))
+  (tag $e1 (import "thrower" "e1") (param i32))
+  (tag $local (param i64 f32 f64))
+  (func $throw-e0 (import "thrower" "throw-e0"))
+  (func $throw-e1 (import "thrower" "throw-e1") (param i32))
+
+  (func (export "catch-local") (result i64)
+    (local $f f32)
+    (local $d f64)
+    (try (result i64)
+      (do (throw $local (i64.const 7) (f32.const 1.5) (f64.const 2.5)))
+      (catch $local
+        (local.set $d)
+        (local.set $f)
+        (i64.trunc_f64_s (f64.add (local.get $d) (f64.promote_f32 (local.get $f))))
+        (i64.add))))
+
+  (func (export "catch-import") (param i32) (result i32)
+    (try (result i32)
+      (do (call $throw-e1 (local.get 0)) (i32.const -1))
+      (catch $e0 (i32.const -2))
+      (catch $e1)))
+
+  (func (export "catch-all") (result i32)
+    (try (result i32)
+      (do (call $throw-e0) (i32.const 0))
+      (catch_all (i32.const 1))))
+
+  (func (export "no-match") (param i32) (result i32)
+    (try (result i32)
+      (do (call $throw-e1 (local.get 0)) (i32.const 0))
+      (catch $e0 (i32.const 1))))
+
+  (func (export "nested") (param i32) (result i32)
+    (local $r i32)
+    (try
+      (do
+        (try
+          (do (call $throw-e1 (local.get 0)))
+          (catch $e0 (local.set $r (i32.const 100)))))
+      (catch $e1 (local.set $r)))
+    (local.get $r))
+
+  (func (export "in-handler") (result i32)
+    (try (result i32)
+      (do
+        (try (result i32)
+          (do (call $throw-e0) (i32.const 0))
+          (catch $e0 (call $throw-e1 (i32.const 5)) (i32.const 0))))
+      (catch $e1)))
+
+  (func (export "rethrow") (result i32)
+    (try (result i32)
+      (do
+        (try (result i32)
+          (do (call $throw-e1 (i32.const 9)) (i32.const 0))
+          (catch_all (rethrow 0))))
+      (catch $e1 (i32.const 1) (i32.add))))
+
+  (func (export "rethrow-outer") (result i32)
+    (try (result i32)
+      (do
+        (try (result i32)
+          (do (throw $e1 (i32.const 3)))
+          (catch $e1
+            (drop)
+            (try (result i32)
+              (do (throw $e0))
+              (catch $e0 (rethrow 1))))))
+      (catch $e1)))
+
+  (func (export "delegate") (result i32)
+    (try (result i32)
+      (do
+        (try (result i32)
+          (do
+            (try (result i32)
+              (do (call $throw-e1 (i32.const 4)) (i32.const 0))
+              (delegate 1)))
+          (catch $e1 (i32.const 100) (i32.add))))
+      (catch $e1)))
+
+  (func (export "delegate-to-caller")
+    (try
+      (do
+        (try
+          (do (call $throw-e0))
+          (delegate 1)))
+      (catch_all)))
+
+  (func (export "uncaught") (throw $local (i64.const 1) (f32.const 0) (f64.const 0)))
+
+  (func (export "params") (param i32) (result i32)
+    (local.get 0)
+    (try (param i32) (result i32)
+      (do (i32.const 1) (i32.add) (call $throw-e1) (i32.const 0))
+      (catch $e1 (i32.const 2) (i32.mul))))
+
+  (func (export "loop") (param $n i32) (result i32)
+    (local $sum i32)
+    (block $done
+      (loop $next
+        (br_if $done (i32.eqz (local.get $n)))
+        (try
+          (do (call $throw-e1 (local.get $n)))
+          (catch $e1 (local.get $sum) (i32.add) (local.set $sum)))
+        (local.set $n (i32.sub (local.get $n) (i32.const 1)))
+        (br $next)))
+    (local.get $sum)))
+
+(assert_return (invoke "catch-local") (i64.const 11))
+(assert_return (invoke "catch-import" (i32.const 42)) (i32.const 42))
+(assert_return (invoke "catch-all") (i32.const 1))
+(assert_exception (invoke "no-match" (i32.const 3)))
+(assert_return (invoke "nested" (i32.const 12)) (i32.const 12))
+(assert_return (invoke "in-handler") (i32.const 5))
+(assert_return (invoke "rethrow") (i32.const 10))
+(assert_return (invoke "rethrow-outer") (i32.const 3))
+(assert_return (invoke "delegate") (i32.const 4))
+(assert_exception (invoke "delegate-to-caller"))
+(assert_exception (invoke "uncaught"))
+(assert_return (invoke "params" (i32.const 20)) (i32.const 42))
+(assert_return (invoke "loop" (i32.const 10)) (i32.const 55))
+(assert_return (invoke "catch-all") (i32.const 1))
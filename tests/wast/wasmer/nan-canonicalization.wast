@@ -193,6 +193,38 @@
 (assert_return (invoke "nan-canonicalization-f64-func-call-indirect" (i64.const 0x7ff8000000000001)) (i64.const 0x7ff8000000000001))
 (assert_return (invoke "nan-canonicalization-f64-func-call-indirect-cncl" (i64.const 0x7ff8000000000001)) (i64.const 0x7ff8000000000000))
 
+;; Signaling NaNs are quieted by the host with their payload kept, and must be canonicalized too.
+(assert_return (invoke "nan-canonicalization-f32-add" (i32.const 0x7f800001)) (i32.const 0x7fc00000))
+(assert_return (invoke "nan-canonicalization-f32-add" (i32.const 0xff800001)) (i32.const 0x7fc00000))
+(assert_return (invoke "nan-canonicalization-f32-add" (i32.const 0x7fbfffff)) (i32.const 0x7fc00000))
+(assert_return (invoke "nan-canonicalization-f32-mul" (i32.const 0x7f800001)) (i32.const 0x7fc00000))
+(assert_return (invoke "nan-canonicalization-f32-mul" (i32.const 0xff800001)) (i32.const 0x7fc00000))
+(assert_return (invoke "nan-canonicalization-f32-mul" (i32.const 0x7fbfffff)) (i32.const 0x7fc00000))
+(assert_return (invoke "nan-canonicalization-f32-div" (i32.const 0x7f800001)) (i32.const 0x7fc00000))
+(assert_return (invoke "nan-canonicalization-f32-div" (i32.const 0xff800001)) (i32.const 0x7fc00000))
+(assert_return (invoke "nan-canonicalization-f32-div" (i32.const 0x7fbfffff)) (i32.const 0x7fc00000))
+(assert_return (invoke "nan-canonicalization-f32-min" (i32.const 0x7f800001)) (i32.const 0x7fc00000))
+(assert_return (invoke "nan-canonicalization-f32-min" (i32.const 0xff800001)) (i32.const 0x7fc00000))
+(assert_return (invoke "nan-canonicalization-f32-min" (i32.const 0x7fbfffff)) (i32.const 0x7fc00000))
+(assert_return (invoke "nan-canonicalization-f32-max" (i32.const 0x7f800001)) (i32.const 0x7fc00000))
+(assert_return (invoke "nan-canonicalization-f32-max" (i32.const 0xff800001)) (i32.const 0x7fc00000))
+(assert_return (invoke "nan-canonicalization-f32-max" (i32.const 0x7fbfffff)) (i32.const 0x7fc00000))
+(assert_return (invoke "nan-canonicalization-f64-add" (i64.const 0x7ff0000000000001)) (i64.const 0x7ff8000000000000))
+(assert_return (invoke "nan-canonicalization-f64-add" (i64.const 0xfff0000000000001)) (i64.const 0x7ff8000000000000))
+(assert_return (invoke "nan-canonicalization-f64-add" (i64.const 0x7ff7ffffffffffff)) (i64.const 0x7ff8000000000000))
+(assert_return (invoke "nan-canonicalization-f64-mul" (i64.const 0x7ff0000000000001)) (i64.const 0x7ff8000000000000))
+(assert_return (invoke "nan-canonicalization-f64-mul" (i64.const 0xfff0000000000001)) (i64.const 0x7ff8000000000000))
+(assert_return (invoke "nan-canonicalization-f64-mul" (i64.const 0x7ff7ffffffffffff)) (i64.const 0x7ff8000000000000))
+(assert_return (invoke "nan-canonicalization-f64-div" (i64.const 0x7ff0000000000001)) (i64.const 0x7ff8000000000000))
+(assert_return (invoke "nan-canonicalization-f64-div" (i64.const 0xfff0000000000001)) (i64.const 0x7ff8000000000000))
+(assert_return (invoke "nan-canonicalization-f64-div" (i64.const 0x7ff7ffffffffffff)) (i64.const 0x7ff8000000000000))
+(assert_return (invoke "nan-canonicalization-f64-min" (i64.const 0x7ff0000000000001)) (i64.const 0x7ff8000000000000))
+(assert_return (invoke "nan-canonicalization-f64-min" (i64.const 0xfff0000000000001)) (i64.const 0x7ff8000000000000))
+(assert_return (invoke "nan-canonicalization-f64-min" (i64.const 0x7ff7ffffffffffff)) (i64.const 0x7ff8000000000000))
+(assert_return (invoke "nan-canonicalization-f64-max" (i64.const 0x7ff0000000000001)) (i64.const 0x7ff8000000000000))
+(assert_return (invoke "nan-canonicalization-f64-max" (i64.const 0xfff0000000000001)) (i64.const 0x7ff8000000000000))
+(assert_return (invoke "nan-canonicalization-f64-max" (i64.const 0x7ff7ffffffffffff)) (i64.const 0x7ff8000000000000))
+
 ;; Test canonicalization is done before branch in `else` operator.
 (module
   (func (;0;)
@@ -219,3 +251,22 @@
     return
   )
 )
+
+;; Test canonicalization of the NaNs produced by conversions between `f32` and `f64`.
+(module
+  (func (export "nan-canonicalization-f64-promote") (param i32) (result i64)
+    (i64.reinterpret_f64 (f64.promote_f32 (f32.reinterpret_i32 (get_local 0))))
+  )
+  (func (export "nan-canonicalization-f32-demote") (param i64) (result i32)
+    (i32.reinterpret_f32 (f32.demote_f64 (f64.reinterpret_i64 (get_local 0))))
+  )
+)
+
+(assert_return (invoke "nan-canonicalization-f64-promote" (i32.const 0x7fc00001)) (i64.const 0x7ff8000000000000))
+(assert_return (invoke "nan-canonicalization-f64-promote" (i32.const 0x7f800001)) (i64.const 0x7ff8000000000000))
+(assert_return (invoke "nan-canonicalization-f64-promote" (i32.const 0xffc00000)) (i64.const 0x7ff8000000000000))
+(assert_return (invoke "nan-canonicalization-f64-promote" (i32.const 0x3f800000)) (i64.const 0x3ff0000000000000))
+(assert_return (invoke "nan-canonicalization-f32-demote" (i64.const 0x7ff8000000000001)) (i32.const 0x7fc00000))
+(assert_return (invoke "nan-canonicalization-f32-demote" (i64.const 0x7ff0000000000001)) (i32.const 0x7fc00000))
+(assert_return (invoke "nan-canonicalization-f32-demote" (i64.const 0xfff8000000000000)) (i32.const 0x7fc00000))
+(assert_return (invoke "nan-canonicalization-f32-demote" (i64.const 0x3ff0000000000000)) (i32.const 0x3f800000))
@@ -0,0 +1,7 @@
+(module
+  (func $h (import "env" "h") (param i32))
+  (memory (export "memory") (data "\01\02\03"))
+  (table funcref (elem $f))
+  (global (export "counter") (mut i32) (i32.const 0))
+  (func $f (export "f") (param $x i32) (result i32)
+    (i32.add (local.get $x) (i32.const 1))))
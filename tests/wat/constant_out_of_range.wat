@@ -0,0 +1,3 @@
+;; error at 3:33
+(module
+  (func (result i32) (i32.const 4294967296)))
@@ -0,0 +1,4 @@
+(module
+  (@custom "wasmer" (before first) "first")
+  (@custom "wasmer" (after func) "second")
+  (func (export "f")))
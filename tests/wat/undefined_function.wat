@@ -0,0 +1,4 @@
+;; error at 4:11
+(module
+  (func $f
+    (call $missing)))
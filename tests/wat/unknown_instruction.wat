@@ -0,0 +1,3 @@
+;; error at 3:10
+(module
+  (func (i32.bogus)))